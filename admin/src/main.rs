@@ -0,0 +1,251 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+
+use templemeads::admin::{default_socket, AdminRequest, AdminResponse};
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Manage a running OpenPortal agent through its admin socket",
+    long_about = None
+)]
+struct Args {
+    #[arg(
+        long,
+        short = 's',
+        env = "OPENPORTAL_ADMIN_SOCKET",
+        help = "Path to the agent's admin socket"
+    )]
+    socket: Option<PathBuf>,
+
+    #[arg(
+        long,
+        short = 'c',
+        help = "Path to the agent's config file. The admin socket is found next to it"
+    )]
+    config_file: Option<PathBuf>,
+
+    #[arg(long, help = "Print the responses as JSON")]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// List the agent's peers and whether they are connected
+    Peers,
+
+    /// Show the health of the agent and the agents below it
+    Health,
+
+    /// Show the diagnostics report of the agent
+    Diagnostics {
+        #[arg(
+            long,
+            short = 'd',
+            default_value = "",
+            help = "Dot-separated path to another agent to ask, e.g. 'provider.cluster'"
+        )]
+        destination: String,
+    },
+
+    /// Show the jobs on the agent's boards
+    Board {
+        #[arg(
+            long,
+            short = 'd',
+            default_value = "",
+            help = "Dot-separated path to another agent to ask, e.g. 'provider.cluster'"
+        )]
+        destination: String,
+    },
+
+    /// Print an event for every job the agent runs, until interrupted
+    Events,
+
+    /// Reconcile a project's accounts on the agent
+    Reconcile {
+        #[arg(help = "The project to reconcile, e.g. 'project.portal'")]
+        project: String,
+
+        #[arg(long, help = "Repair any missing accounts and associations")]
+        repair: bool,
+    },
+
+    /// Rotate the keys used to connect to a peer
+    RotateKey {
+        #[arg(help = "Name of the peer whose keys should be rotated")]
+        peer: String,
+
+        #[arg(
+            long,
+            short = 'z',
+            help = "The zone of the peer. Only needed if there are several peers with this name"
+        )]
+        zone: Option<String>,
+    },
+
+    /// Restart the agent
+    Restart {
+        #[arg(
+            default_value = "soft",
+            value_parser = ["soft", "reload", "hard", "cancel"],
+            help = "'soft' reconnects to all peers, 'reload' re-reads the config file, 'hard' exits so that the agent is restarted by its supervisor, 'cancel' cancels a scheduled restart"
+        )]
+        restart_type: String,
+
+        #[arg(
+            long,
+            short = 'a',
+            conflicts_with = "now",
+            help = "Local time (HH:MM) at which to drain and restart the agent"
+        )]
+        at: Option<String>,
+
+        #[arg(
+            long,
+            short = 'n',
+            help = "Restart straight away, even outside the agent's maintenance windows"
+        )]
+        now: bool,
+    },
+}
+
+impl Commands {
+    fn request(&self) -> AdminRequest {
+        match self {
+            Commands::Peers => AdminRequest::Peers,
+            Commands::Health => AdminRequest::Health,
+            Commands::Diagnostics { destination } => AdminRequest::Diagnostics {
+                destination: destination.clone(),
+            },
+            Commands::Board { destination } => AdminRequest::Board {
+                destination: destination.clone(),
+            },
+            Commands::Events => AdminRequest::Events,
+            Commands::Reconcile { project, repair } => AdminRequest::Reconcile {
+                project: project.clone(),
+                repair: *repair,
+            },
+            Commands::RotateKey { peer, zone } => AdminRequest::RotateKey {
+                peer: peer.clone(),
+                zone: zone.clone(),
+            },
+            Commands::Restart {
+                restart_type,
+                at,
+                now,
+            } => AdminRequest::Restart {
+                restart_type: match (at, now) {
+                    (Some(at), _) => format!("{}@{}", restart_type, at),
+                    (None, true) => format!("{}@now", restart_type),
+                    (None, false) => restart_type.clone(),
+                },
+            },
+        }
+    }
+}
+
+fn print_response(response: &AdminResponse) {
+    match response {
+        AdminResponse::Peers { peers } => {
+            if peers.is_empty() {
+                println!("No peers");
+            }
+
+            for peer in peers {
+                println!("{}", peer);
+            }
+        }
+        AdminResponse::Health { health } => println!("{}", health.to_pretty_string()),
+        AdminResponse::Diagnostics { report } => println!("{}", report.to_pretty_string()),
+        AdminResponse::Board { dump } => println!("{}", dump.to_pretty_string()),
+        AdminResponse::Event { event } => println!("{}", event),
+        AdminResponse::Reconciled { report } => print!("{}", report),
+        AdminResponse::Handover { boards } => println!("Handed over {} board(s)", boards.len()),
+        AdminResponse::Done { message } => println!("{}", message),
+        AdminResponse::Error { error } => eprintln!("Error: {}", error),
+    }
+}
+
+///
+/// Main function for op-admin
+///
+/// This sends a single request to the admin socket of a running agent
+/// on this host, and prints each response until the agent closes the
+/// connection. Only the user running the agent can use its socket.
+///
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let socket = match (&args.socket, &args.config_file) {
+        (Some(socket), _) => socket.clone(),
+        (None, Some(config_file)) => default_socket(config_file),
+        (None, None) => {
+            return Err(anyhow::anyhow!(
+                "Please specify the agent's admin socket (--socket) or config file (--config-file)"
+            ))
+        }
+    };
+
+    let stream = UnixStream::connect(&socket)
+        .await
+        .with_context(|| format!("Could not connect to admin socket {}", socket.display()))?;
+
+    let (reader, mut writer) = stream.into_split();
+
+    let mut request = serde_json::to_string(&args.command.request())?;
+    request.push('\n');
+    writer.write_all(request.as_bytes()).await?;
+
+    let mut lines = BufReader::new(reader).lines();
+    let mut failed = false;
+    let mut received = false;
+
+    while let Some(line) = lines.next_line().await? {
+        received = true;
+
+        let response: AdminResponse = serde_json::from_str(&line)
+            .with_context(|| format!("Could not parse response from the agent: {}", line))?;
+
+        match args.json {
+            true => println!("{}", serde_json::to_string_pretty(&response)?),
+            false => print_response(&response),
+        }
+
+        // errors while tailing events are only warnings about missed events
+        if matches!(response, AdminResponse::Error { .. })
+            && !matches!(args.command, Commands::Events)
+        {
+            failed = true;
+        }
+    }
+
+    if !received {
+        match args.command {
+            Commands::Restart { .. } => {
+                println!("The agent closed the connection while restarting")
+            }
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "The agent closed the connection without responding"
+                ))
+            }
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
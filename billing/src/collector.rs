@@ -0,0 +1,153 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use templemeads::agent;
+use templemeads::agent::{Peer, Type as AgentType};
+use templemeads::grammar::{Date, DateRange, PortalIdentifier, ProjectIdentifier};
+use templemeads::job::Job;
+use templemeads::usagereport::{Usage, UsageReport};
+use templemeads::Error;
+
+use crate::database;
+
+///
+/// Ask the cluster for the usage reports of all of the portal's
+/// projects over the passed dates
+///
+async fn get_usage_reports(
+    me: &str,
+    cluster: &Peer,
+    portal: &PortalIdentifier,
+    dates: &DateRange,
+) -> Result<UsageReport, Error> {
+    let job = Job::parse(
+        &format!(
+            "{}.{} get_usage_reports {} {}",
+            me,
+            cluster.name(),
+            portal,
+            dates
+        ),
+        false,
+    )?
+    .put(cluster)
+    .await?;
+
+    match job.wait().await?.result::<UsageReport>()? {
+        Some(report) => Ok(report),
+        None => Ok(UsageReport::new(portal)),
+    }
+}
+
+///
+/// Ask the cluster for the limit (allocation) of the project
+///
+async fn get_limit(me: &str, cluster: &Peer, project: &ProjectIdentifier) -> Result<Usage, Error> {
+    let job = Job::parse(
+        &format!("{}.{} get_limit {}", me, cluster.name(), project),
+        false,
+    )?
+    .put(cluster)
+    .await?;
+
+    Ok(job.wait().await?.result::<Usage>()?.unwrap_or_default())
+}
+
+///
+/// Write the usage of the portal's projects on the cluster over the
+/// passed dates, together with each project's allocation
+///
+async fn collect_cluster(
+    me: &str,
+    cluster: &Peer,
+    portal: &PortalIdentifier,
+    dates: &DateRange,
+) -> Result<(), Error> {
+    let report = get_usage_reports(me, cluster, portal, dates).await?;
+
+    let mut written = 0;
+
+    for project in report.projects() {
+        written += database::write_report(cluster.name(), &report.get_report(&project)).await?;
+
+        match get_limit(me, cluster, &project).await {
+            Ok(limit) => database::write_allocation(cluster.name(), &project, &limit).await?,
+            Err(e) => tracing::warn!(
+                "Could not get the allocation of {} on {}: {}",
+                project,
+                cluster.name(),
+                e
+            ),
+        }
+    }
+
+    tracing::info!(
+        "Wrote {} days of usage of {} projects of {} from {}",
+        written,
+        report.projects().len(),
+        portal,
+        cluster.name()
+    );
+
+    Ok(())
+}
+
+///
+/// Write the completed usage of every portal's projects on every
+/// connected cluster over the last `lookback` days
+///
+pub async fn collect(portals: &[PortalIdentifier], lookback: u64) {
+    let me = agent::name().await;
+    let clusters = agent::get_all(&AgentType::Instance).await;
+
+    if clusters.is_empty() {
+        tracing::warn!("No cluster agents are connected, so there is no usage to collect");
+        return;
+    }
+
+    // today is never complete, so collection stops at yesterday
+    let end = Date::yesterday();
+    let start = *end.date() - chrono::Duration::days(lookback.saturating_sub(1) as i64);
+    let dates = DateRange::from_chrono(&start, end.date());
+
+    for cluster in &clusters {
+        for portal in portals {
+            if let Err(e) = collect_cluster(&me, cluster, portal, &dates).await {
+                tracing::error!(
+                    "Failed to collect the usage of {} from {}: {}",
+                    portal,
+                    cluster.name(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+///
+/// Spawn a background task that collects usage every `interval` seconds
+///
+pub fn spawn_collector(interval: u64, portals: Vec<PortalIdentifier>, lookback: u64) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+
+            collect(&portals, lookback).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_collect_without_clusters() {
+        let portal = PortalIdentifier::parse("portal")
+            .unwrap_or_else(|e| unreachable!("Cannot parse portal: {}", e));
+
+        // with no connected clusters there is nothing to collect, so
+        // this returns without touching the database
+        collect(&[portal], 7).await;
+    }
+}
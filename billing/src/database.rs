@@ -0,0 +1,638 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use once_cell::sync::Lazy;
+use secrecy::{ExposeSecret, SecretString};
+use std::collections::HashMap;
+use std::sync::Arc;
+use templemeads::grammar::ProjectIdentifier;
+use templemeads::usagereport::{DailyProjectUsageReport, ProjectUsageReport, Usage};
+use templemeads::Error;
+use tokio::sync::Mutex;
+use tokio_postgres::{Client, GenericClient};
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+/// The component under which each user's main usage is stored, alongside
+/// the named components (e.g. "gpu") of the usage report
+pub const TOTAL: &str = "total";
+
+#[derive(Default)]
+struct Database {
+    url: Option<SecretString>,
+    schema: String,
+    /// The price per hour of each component. Components without a rate
+    /// are not charged.
+    rates: HashMap<String, f64>,
+    client: Option<Client>,
+}
+
+static DATABASE: Lazy<Mutex<Database>> = Lazy::new(|| Mutex::new(Database::default()));
+
+///
+/// Parse a comma-separated list of component rates, e.g.
+/// "total=1.0,gpu=0.25", into the price per hour of each component
+///
+pub fn parse_rates(rates: &str) -> Result<HashMap<String, f64>, Error> {
+    let mut parsed = HashMap::new();
+
+    for rate in rates.split(',').map(|r| r.trim()).filter(|r| !r.is_empty()) {
+        let (component, price) = rate.split_once('=').ok_or_else(|| {
+            Error::Misconfigured(format!(
+                "Invalid rate '{}'. Rates must be given as component=price",
+                rate
+            ))
+        })?;
+
+        let price = price
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| Error::Misconfigured(format!("Invalid price in rate '{}'", rate)))?;
+
+        if !price.is_finite() || price < 0.0 {
+            return Err(Error::Misconfigured(format!(
+                "Invalid price in rate '{}'. Prices cannot be negative",
+                rate
+            )));
+        }
+
+        parsed.insert(component.trim().to_owned(), price);
+    }
+
+    Ok(parsed)
+}
+
+///
+/// Return the schema name if it is safe to interpolate into SQL
+///
+fn check_schema(schema: &str) -> Result<String, Error> {
+    let schema = schema.trim();
+
+    let valid = schema
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && schema
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+
+    match valid {
+        true => Ok(schema.to_owned()),
+        false => Err(Error::Misconfigured(format!(
+            "Invalid schema '{}'. Schemas may only contain lowercase letters, digits and underscores",
+            schema
+        ))),
+    }
+}
+
+fn db_error(e: tokio_postgres::Error) -> Error {
+    Error::Call(format!("Database error: {}", e))
+}
+
+///
+/// Connect to the database. TLS is used if required by the sslmode of
+/// the connection URL, verifying the server against the system roots.
+///
+async fn connect(url: &SecretString) -> Result<Client, Error> {
+    let config = url
+        .expose_secret()
+        .parse::<tokio_postgres::Config>()
+        .map_err(|e| Error::Misconfigured(format!("Invalid database URL: {}", e)))?;
+
+    let mut roots = rustls::RootCertStore::empty();
+
+    for cert in rustls_native_certs::load_native_certs().certs {
+        if let Err(e) = roots.add(cert) {
+            tracing::warn!("Ignoring invalid system root certificate: {}", e);
+        }
+    }
+
+    let tls = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .map_err(|e| Error::Call(format!("Could not configure TLS: {}", e)))?
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+
+    let (client, connection) = config
+        .connect(MakeRustlsConnect::new(tls))
+        .await
+        .map_err(db_error)?;
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            tracing::error!("Database connection closed: {}", e);
+        }
+    });
+
+    Ok(client)
+}
+
+///
+/// Return the connected database, reconnecting if the connection has
+/// been closed
+///
+async fn get_database() -> Result<tokio::sync::MutexGuard<'static, Database>, Error> {
+    let mut database = DATABASE.lock().await;
+
+    if database.client.as_ref().is_none_or(|c| c.is_closed()) {
+        let url = database
+            .url
+            .clone()
+            .ok_or_else(|| Error::Call("Database not initialised".to_owned()))?;
+
+        tracing::info!("Connecting to the billing database");
+        database.client = Some(connect(&url).await?);
+    }
+
+    Ok(database)
+}
+
+///
+/// Connect to the database and create the billing schema, if it does
+/// not already exist
+///
+pub async fn initialise(
+    url: SecretString,
+    schema: &str,
+    rates: HashMap<String, f64>,
+) -> Result<(), Error> {
+    let schema = check_schema(schema)?;
+
+    {
+        let mut database = DATABASE.lock().await;
+        database.url = Some(url);
+        database.schema = schema.clone();
+        database.rates = rates;
+    }
+
+    let database = get_database().await?;
+
+    let client = database
+        .client
+        .as_ref()
+        .ok_or_else(|| Error::Call("Not connected to the database".to_owned()))?;
+
+    client
+        .batch_execute(&create_schema(&schema))
+        .await
+        .map_err(db_error)?;
+
+    tracing::info!("Writing usage to the billing schema '{}'", schema);
+
+    Ok(())
+}
+
+///
+/// Return the SQL that creates the billing schema. Usage is stored in
+/// seconds, and charges are stored with the rate that was used, so that
+/// changing a rate does not change past charges.
+///
+fn create_schema(s: &str) -> String {
+    format!(
+        r#"
+        CREATE SCHEMA IF NOT EXISTS {s};
+
+        CREATE TABLE IF NOT EXISTS {s}.projects (
+            project TEXT PRIMARY KEY,
+            portal TEXT NOT NULL,
+            first_seen TIMESTAMPTZ NOT NULL DEFAULT now(),
+            last_seen TIMESTAMPTZ NOT NULL DEFAULT now()
+        );
+
+        CREATE TABLE IF NOT EXISTS {s}.daily_usage (
+            cluster TEXT NOT NULL,
+            project TEXT NOT NULL REFERENCES {s}.projects (project),
+            day DATE NOT NULL,
+            local_user TEXT NOT NULL,
+            user_identifier TEXT,
+            component TEXT NOT NULL,
+            seconds BIGINT NOT NULL,
+            PRIMARY KEY (cluster, project, day, local_user, component)
+        );
+
+        CREATE TABLE IF NOT EXISTS {s}.loaded_days (
+            cluster TEXT NOT NULL,
+            project TEXT NOT NULL REFERENCES {s}.projects (project),
+            day DATE NOT NULL,
+            loaded_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+            PRIMARY KEY (cluster, project, day)
+        );
+
+        CREATE TABLE IF NOT EXISTS {s}.allocations (
+            cluster TEXT NOT NULL,
+            project TEXT NOT NULL REFERENCES {s}.projects (project),
+            seconds BIGINT NOT NULL,
+            updated TIMESTAMPTZ NOT NULL DEFAULT now(),
+            PRIMARY KEY (cluster, project)
+        );
+
+        CREATE TABLE IF NOT EXISTS {s}.charges (
+            cluster TEXT NOT NULL,
+            project TEXT NOT NULL REFERENCES {s}.projects (project),
+            day DATE NOT NULL,
+            component TEXT NOT NULL,
+            seconds BIGINT NOT NULL,
+            rate DOUBLE PRECISION NOT NULL,
+            amount DOUBLE PRECISION NOT NULL,
+            PRIMARY KEY (cluster, project, day, component)
+        );
+
+        CREATE OR REPLACE VIEW {s}.balances AS
+        SELECT k.cluster,
+               k.project,
+               COALESCE(a.seconds, 0) / 3600.0 AS allocated_hours,
+               COALESCE(u.seconds, 0) / 3600.0 AS used_hours,
+               (COALESCE(a.seconds, 0) - COALESCE(u.seconds, 0)) / 3600.0 AS remaining_hours,
+               COALESCE(c.amount, 0) AS charged
+        FROM (SELECT cluster, project FROM {s}.allocations
+              UNION
+              SELECT cluster, project FROM {s}.loaded_days) k
+        LEFT JOIN {s}.allocations a USING (cluster, project)
+        LEFT JOIN (SELECT cluster, project, SUM(seconds) AS seconds
+                   FROM {s}.daily_usage WHERE component = '{TOTAL}'
+                   GROUP BY cluster, project) u USING (cluster, project)
+        LEFT JOIN (SELECT cluster, project, SUM(amount) AS amount
+                   FROM {s}.charges
+                   GROUP BY cluster, project) c USING (cluster, project);
+        "#
+    )
+}
+
+fn seconds(usage: &Usage) -> i64 {
+    i64::try_from(usage.seconds()).unwrap_or(i64::MAX)
+}
+
+async fn upsert_project<C: GenericClient>(
+    client: &C,
+    schema: &str,
+    project: &ProjectIdentifier,
+) -> Result<(), Error> {
+    client
+        .execute(
+            &*format!(
+                "INSERT INTO {schema}.projects (project, portal) VALUES ($1, $2) \
+                 ON CONFLICT (project) DO UPDATE SET last_seen = now()"
+            ),
+            &[&project.to_string(), &project.portal().to_string()],
+        )
+        .await
+        .map_err(db_error)?;
+
+    Ok(())
+}
+
+///
+/// Return the usage of each local user in each component of the day,
+/// with the main usage under the "total" component
+///
+fn get_usage(daily: &DailyProjectUsageReport) -> Vec<(String, String, Usage)> {
+    let mut usage = Vec::new();
+
+    for user in daily.local_users() {
+        usage.push((TOTAL.to_owned(), user.clone(), daily.usage(&user)));
+    }
+
+    for component in daily.components() {
+        let report = daily.get_component(&component);
+
+        for user in report.local_users() {
+            usage.push((component.clone(), user.clone(), report.usage(&user)));
+        }
+    }
+
+    usage.retain(|(_, _, usage)| !usage.is_zero());
+    usage
+}
+
+///
+/// Write the complete days of the project's usage report that have not
+/// already been written, together with their charges. Returns the
+/// number of days written.
+///
+pub async fn write_report(cluster: &str, report: &ProjectUsageReport) -> Result<usize, Error> {
+    let mut database = get_database().await?;
+    let schema = database.schema.clone();
+    let rates = database.rates.clone();
+
+    let client = database
+        .client
+        .as_mut()
+        .ok_or_else(|| Error::Call("Not connected to the database".to_owned()))?;
+
+    let project = report.project();
+    let users: HashMap<String, String> = report
+        .user_mapping()
+        .into_iter()
+        .map(|(user, local_user)| (local_user, user.to_string()))
+        .collect();
+
+    let mut written = 0;
+
+    for date in report.dates() {
+        let daily = match report
+            .get_report(&date)
+            .daily_reports(false)
+            .into_iter()
+            .next()
+        {
+            Some(daily) => daily,
+            None => continue,
+        };
+
+        // only complete days are billed, as incomplete days will change
+        if !daily.is_complete() {
+            continue;
+        }
+
+        let day = *date.date();
+
+        let transaction = client.transaction().await.map_err(db_error)?;
+
+        upsert_project(&transaction, &schema, &project).await?;
+
+        let loaded = transaction
+            .execute(
+                &*format!(
+                    "INSERT INTO {schema}.loaded_days (cluster, project, day) VALUES ($1, $2, $3) \
+                     ON CONFLICT DO NOTHING"
+                ),
+                &[&cluster, &project.to_string(), &day],
+            )
+            .await
+            .map_err(db_error)?;
+
+        if loaded == 0 {
+            // this day has already been written
+            transaction.rollback().await.map_err(db_error)?;
+            continue;
+        }
+
+        let mut totals: HashMap<String, i64> = HashMap::new();
+
+        for (component, local_user, usage) in get_usage(&daily) {
+            let secs = seconds(&usage);
+
+            transaction
+                .execute(
+                    &*format!(
+                        "INSERT INTO {schema}.daily_usage \
+                         (cluster, project, day, local_user, user_identifier, component, seconds) \
+                         VALUES ($1, $2, $3, $4, $5, $6, $7)"
+                    ),
+                    &[
+                        &cluster,
+                        &project.to_string(),
+                        &day,
+                        &local_user,
+                        &users.get(&local_user),
+                        &component,
+                        &secs,
+                    ],
+                )
+                .await
+                .map_err(db_error)?;
+
+            *totals.entry(component).or_default() += secs;
+        }
+
+        for (component, secs) in totals {
+            let rate = match rates.get(&component) {
+                Some(rate) => *rate,
+                None => continue,
+            };
+
+            transaction
+                .execute(
+                    &*format!(
+                        "INSERT INTO {schema}.charges \
+                         (cluster, project, day, component, seconds, rate, amount) \
+                         VALUES ($1, $2, $3, $4, $5, $6, $7)"
+                    ),
+                    &[
+                        &cluster,
+                        &project.to_string(),
+                        &day,
+                        &component,
+                        &secs,
+                        &rate,
+                        &(secs as f64 / 3600.0 * rate),
+                    ],
+                )
+                .await
+                .map_err(db_error)?;
+        }
+
+        transaction.commit().await.map_err(db_error)?;
+        written += 1;
+
+        tracing::debug!(
+            "Wrote the usage of {} on {} from {}",
+            project,
+            date,
+            cluster
+        );
+    }
+
+    Ok(written)
+}
+
+///
+/// Write the allocation (the scheduler limit) of the project on the
+/// cluster
+///
+pub async fn write_allocation(
+    cluster: &str,
+    project: &ProjectIdentifier,
+    limit: &Usage,
+) -> Result<(), Error> {
+    let database = get_database().await?;
+    let schema = database.schema.clone();
+
+    let client = database
+        .client
+        .as_ref()
+        .ok_or_else(|| Error::Call("Not connected to the database".to_owned()))?;
+
+    upsert_project(client, &schema, project).await?;
+
+    client
+        .execute(
+            &*format!(
+                "INSERT INTO {schema}.allocations (cluster, project, seconds) VALUES ($1, $2, $3) \
+                 ON CONFLICT (cluster, project) DO UPDATE \
+                 SET seconds = EXCLUDED.seconds, updated = now()"
+            ),
+            &[&cluster, &project.to_string(), &seconds(limit)],
+        )
+        .await
+        .map_err(db_error)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use templemeads::grammar::{Date, UserIdentifier, UserMapping};
+
+    #[test]
+    fn test_parse_rates() {
+        let rates = parse_rates(" total = 1.5, gpu=0.25 ,,")
+            .unwrap_or_else(|e| unreachable!("Cannot parse rates: {}", e));
+
+        assert_eq!(rates.len(), 2);
+        assert_eq!(rates.get("total"), Some(&1.5));
+        assert_eq!(rates.get("gpu"), Some(&0.25));
+
+        assert!(parse_rates("").is_ok_and(|rates| rates.is_empty()));
+
+        for invalid in [
+            "total",
+            "total=",
+            "total=abc",
+            "total=-1",
+            "total=inf",
+            "gpu=NaN",
+        ] {
+            assert!(matches!(parse_rates(invalid), Err(Error::Misconfigured(_))));
+        }
+    }
+
+    #[test]
+    fn test_check_schema() {
+        assert!(check_schema("openportal").is_ok_and(|s| s == "openportal"));
+        assert!(check_schema(" _billing_2 ").is_ok_and(|s| s == "_billing_2"));
+
+        for invalid in [
+            "",
+            "2billing",
+            "Billing",
+            "bill-ing",
+            "a.b",
+            "a; DROP TABLE x",
+        ] {
+            assert!(matches!(
+                check_schema(invalid),
+                Err(Error::Misconfigured(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_create_schema() {
+        let sql = create_schema("billing");
+
+        for table in [
+            "projects",
+            "daily_usage",
+            "loaded_days",
+            "allocations",
+            "charges",
+        ] {
+            assert!(sql.contains(&format!("CREATE TABLE IF NOT EXISTS billing.{} (", table)));
+        }
+
+        assert!(sql.contains("CREATE OR REPLACE VIEW billing.balances AS"));
+        assert!(sql.contains("WHERE component = 'total'"));
+    }
+
+    #[test]
+    fn test_seconds() {
+        assert_eq!(seconds(&Usage::new(0)), 0);
+        assert_eq!(seconds(&Usage::new(3600)), 3600);
+        assert_eq!(seconds(&Usage::new(u64::MAX)), i64::MAX);
+    }
+
+    #[test]
+    fn test_get_usage() {
+        let mut daily = DailyProjectUsageReport::default();
+        daily.set_usage("alice", Usage::new(3600));
+        daily.set_usage("bob", Usage::new(0));
+        daily.set_component_usage("gpu", "alice", Usage::new(600));
+        daily.set_component_usage("gpu", "bob", Usage::new(0));
+
+        let mut usage = get_usage(&daily);
+        usage.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+
+        // users without usage are not written
+        assert_eq!(
+            usage,
+            vec![
+                ("gpu".to_owned(), "alice".to_owned(), Usage::new(600)),
+                (TOTAL.to_owned(), "alice".to_owned(), Usage::new(3600)),
+            ]
+        );
+
+        assert!(get_usage(&DailyProjectUsageReport::default()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_database_errors() {
+        let project = ProjectIdentifier::parse("proj.portal")
+            .unwrap_or_else(|e| unreachable!("Cannot parse project: {}", e));
+
+        let mut daily = DailyProjectUsageReport::default();
+        daily.set_usage("alice", Usage::new(3600));
+        daily.set_complete();
+
+        let mut report = ProjectUsageReport::new(&project);
+        report.set_report(&Date::yesterday(), &daily);
+        report
+            .add_mapping(
+                &UserMapping::new(
+                    &UserIdentifier::parse("alice.proj.portal")
+                        .unwrap_or_else(|e| unreachable!("Cannot parse user: {}", e)),
+                    "alice",
+                    "proj",
+                )
+                .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e)),
+            )
+            .unwrap_or_else(|e| unreachable!("Cannot add mapping: {}", e));
+
+        // nothing can be written before the database is initialised
+        assert!(matches!(
+            write_report("cluster", &report).await,
+            Err(Error::Call(_))
+        ));
+
+        assert!(matches!(
+            write_allocation("cluster", &project, &Usage::new(3600)).await,
+            Err(Error::Call(_))
+        ));
+
+        // an invalid schema is rejected before anything is stored
+        assert!(matches!(
+            initialise(
+                SecretString::from("postgres://op@127.0.0.1/billing"),
+                "bad-schema",
+                HashMap::new()
+            )
+            .await,
+            Err(Error::Misconfigured(_))
+        ));
+
+        assert!(DATABASE.lock().await.url.is_none());
+
+        assert!(matches!(
+            initialise(SecretString::from("not a url"), "billing", HashMap::new()).await,
+            Err(Error::Misconfigured(_))
+        ));
+
+        // nothing listens on port 1, so the connection is refused
+        assert!(matches!(
+            initialise(
+                SecretString::from("postgres://op@127.0.0.1:1/billing?sslmode=disable"),
+                "billing",
+                HashMap::new()
+            )
+            .await,
+            Err(Error::Call(_))
+        ));
+
+        assert!(matches!(
+            write_report("cluster", &report).await,
+            Err(Error::Call(_))
+        ));
+    }
+}
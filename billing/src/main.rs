@@ -0,0 +1,133 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+
+use templemeads::agent::monitor::{process_args, run, Defaults};
+use templemeads::agent::Type as AgentType;
+use templemeads::async_runnable;
+use templemeads::grammar::PortalIdentifier;
+use templemeads::job::{Envelope, Job};
+use templemeads::notification::default_notify_runner;
+use templemeads::set_notify_runner;
+use templemeads::Error;
+
+mod collector;
+mod database;
+
+///
+/// Main function for the billing agent
+///
+/// This agent connects to one or more cluster agents, and periodically
+/// collects the usage reports of each portal's projects. The usage of
+/// every completed day is written once into a Postgres billing schema,
+/// together with its charges and each project's allocation, so that
+/// sites can query balances and produce invoices with plain SQL.
+///
+#[tokio::main]
+async fn main() -> Result<()> {
+    // start tracing
+    templemeads::config::initialise_tracing();
+
+    // start system monitoring
+    templemeads::spawn_system_monitor();
+
+    // create the OpenPortal paddington defaults
+    let defaults: Defaults = Defaults::parse(
+        Some("billing".to_owned()),
+        Some(
+            dirs::config_local_dir()
+                .unwrap_or(
+                    ".".parse()
+                        .expect("Could not parse fallback config directory."),
+                )
+                .join("openportal")
+                .join("billing-config.toml"),
+        ),
+        Some("ws://localhost:8053".to_owned()),
+        Some("127.0.0.1".to_owned()),
+        Some(8053),
+        None,
+        None,
+        Some(AgentType::Monitor),
+    );
+
+    // now parse the command line arguments to get the service configuration
+    let config = match process_args(&defaults).await? {
+        Some(config) => config,
+        None => {
+            // Not running the service, so can safely exit
+            return Ok(());
+        }
+    };
+
+    // the database URL normally contains the password, so is a secret
+    let database_url = match config.secret("database-url") {
+        Some(url) => url,
+        None => {
+            return Err(anyhow::anyhow!(
+                "No database URL specified. Please set this in the database-url option."
+            ));
+        }
+    };
+
+    // the portals whose projects are billed
+    let portals = config
+        .option("portals", "")
+        .split(',')
+        .map(|p| p.trim())
+        .filter(|p| !p.is_empty())
+        .map(PortalIdentifier::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if portals.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No portals specified. Please set this in the portals option."
+        ));
+    }
+
+    database::initialise(
+        database_url,
+        &config.option("schema", "openportal"),
+        database::parse_rates(&config.option("rates", "total=1.0"))?,
+    )
+    .await?;
+
+    // get the interval (in seconds) between collections
+    let poll_interval: u64 = config
+        .option("poll-interval", "3600")
+        .parse()
+        .unwrap_or(3600)
+        .max(60);
+
+    // the number of days before today that are collected, so that days
+    // that were incomplete, or missed while an agent was down, are
+    // written once they are complete
+    let lookback_days: u64 = config
+        .option("lookback-days", "7")
+        .parse()
+        .unwrap_or(7)
+        .max(1);
+
+    collector::spawn_collector(poll_interval, portals, lookback_days);
+
+    async_runnable! {
+        ///
+        /// Runnable function that will be called when a job is received
+        /// by the agent
+        ///
+        pub async fn billing_runner(envelope: Envelope) -> Result<Job, templemeads::Error>
+        {
+            let job = envelope.job();
+
+            Err(Error::InvalidInstruction(
+                format!("Invalid instruction: {}. Billing agents do not accept instructions", job.instruction()),
+            ))
+        }
+    }
+
+    set_notify_runner(default_notify_runner).await?;
+    run(config, billing_runner).await?;
+
+    Ok(())
+}
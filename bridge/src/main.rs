@@ -6,17 +6,20 @@ use reqwest::Client;
 use std::time::Duration;
 use tokio::time::sleep;
 use url::Url;
+use uuid::Uuid;
 
 use templemeads::agent;
 use templemeads::agent::bridge::{process_args, run, Defaults};
 use templemeads::async_runnable;
+use templemeads::circuitbreaker::CircuitBreaker;
 use templemeads::destination::{Destination, Destinations};
 use templemeads::diagnostics;
 use templemeads::grammar::Instruction::{
-    CreateProject, GetAward, GetAwards, GetProject, GetProjectMapping, GetProjects,
-    GetStorageReport, GetStorageReports, GetUsageReport, GetUsageReports, GetUsers, RemoveProject,
-    SyncOfferings, UpdateProject,
+    AddUser, CreateProject, GetAward, GetAwards, GetProject, GetProjectMapping, GetProjects,
+    GetStorageReport, GetStorageReports, GetUsageReport, GetUsageReports, GetUsers, IsExistingUser,
+    RemoveProject, RemoveUser, SyncOfferings, UpdateProject,
 };
+use templemeads::health;
 use templemeads::job::{send_queued, Envelope, Job};
 use templemeads::notification::{Notification, NotificationEnvelope};
 use templemeads::server;
@@ -79,10 +82,34 @@ async fn main() -> Result<()> {
 
     let board = server::get_board().await?;
 
-    if let Some(signal_url) = &config.bridge.signal_url {
-        board.write().await.set_signal_url(signal_url.clone());
+    // restore any jobs that were waiting for the web portal when the
+    // bridge was last stopped
+    if let Some(board_file) = &config.bridge.board_file {
+        board.write().await.set_board_file(board_file)?;
     }
 
+    // reject new jobs if the web portal stops fetching them
+    board.write().await.set_high_water_marks(
+        config.bridge.board_warning_mark,
+        config.bridge.board_high_water_mark,
+    );
+
+    board
+        .write()
+        .await
+        .set_signal_policy(config.bridge.signal_policy);
+
+    // signals to the web portal are signed with the bridge API key
+    board
+        .write()
+        .await
+        .set_signal_key(config.bridge.key.clone());
+
+    board
+        .write()
+        .await
+        .set_signal_urls(config.bridge.signal_urls());
+
     if let Some(notification_url) = &config.bridge.notification_url {
         board
             .write()
@@ -135,13 +162,13 @@ async fn main() -> Result<()> {
 
                     let board = server::get_board().await?;
 
-                    let waiter = board.write().await.add(&job)?;
+                    let waiter = board.write().await.add_envelope(&envelope)?;
 
                     // now signal the web-portal connected to the bridge
                     // that this job is ready to be processed
-                    let signal_url = board.read().await.signal_url();
+                    let signal_urls = board.read().await.signal_urls();
 
-                    match signal_web_portal(&signal_url, &job).await {
+                    match signal_web_portal(&signal_urls, &job).await {
                         Ok(_) => {},
                         Err(e) => {
                             // remove the job from the board as it will not be processed
@@ -169,13 +196,13 @@ async fn main() -> Result<()> {
 
                     let board = server::get_board().await?;
 
-                    let waiter = board.write().await.add(&job)?;
+                    let waiter = board.write().await.add_envelope(&envelope)?;
 
                     // now signal the web-portal connected to the bridge
                     // that this job is ready to be processed
-                    let signal_url = board.read().await.signal_url();
+                    let signal_urls = board.read().await.signal_urls();
 
-                    match signal_web_portal(&signal_url, &job).await {
+                    match signal_web_portal(&signal_urls, &job).await {
                         Ok(_) => {},
                         Err(e) => {
                             // remove the job from the board as it will not be processed
@@ -203,13 +230,13 @@ async fn main() -> Result<()> {
 
                     let board = server::get_board().await?;
 
-                    let waiter = board.write().await.add(&job)?;
+                    let waiter = board.write().await.add_envelope(&envelope)?;
 
                     // now signal the web-portal connected to the bridge
                     // that this job is ready to be processed
-                    let signal_url = board.read().await.signal_url();
+                    let signal_urls = board.read().await.signal_urls();
 
-                    match signal_web_portal(&signal_url, &job).await {
+                    match signal_web_portal(&signal_urls, &job).await {
                         Ok(_) => {},
                         Err(e) => {
                             // remove the job from the board as it will not be processed
@@ -237,13 +264,13 @@ async fn main() -> Result<()> {
 
                     let board = server::get_board().await?;
 
-                    let waiter = board.write().await.add(&job)?;
+                    let waiter = board.write().await.add_envelope(&envelope)?;
 
                     // now signal the web-portal connected to the bridge
                     // that this job is ready to be processed
-                    let signal_url = board.read().await.signal_url();
+                    let signal_urls = board.read().await.signal_urls();
 
-                    match signal_web_portal(&signal_url, &job).await {
+                    match signal_web_portal(&signal_urls, &job).await {
                         Ok(_) => {},
                         Err(e) => {
                             // remove the job from the board as it will not be processed
@@ -269,13 +296,13 @@ async fn main() -> Result<()> {
 
                     let board = server::get_board().await?;
 
-                    let waiter = board.write().await.add(&job)?;
+                    let waiter = board.write().await.add_envelope(&envelope)?;
 
                     // now signal the web-portal connected to the bridge
                     // that this job is ready to be processed
-                    let signal_url = board.read().await.signal_url();
+                    let signal_urls = board.read().await.signal_urls();
 
-                    match signal_web_portal(&signal_url, &job).await {
+                    match signal_web_portal(&signal_urls, &job).await {
                         Ok(_) => {},
                         Err(e) => {
                             // remove the job from the board as it will not be processed
@@ -301,13 +328,13 @@ async fn main() -> Result<()> {
 
                     let board = server::get_board().await?;
 
-                    let waiter = board.write().await.add(&job)?;
+                    let waiter = board.write().await.add_envelope(&envelope)?;
 
                     // now signal the web-portal connected to the bridge
                     // that this job is ready to be processed
-                    let signal_url = board.read().await.signal_url();
+                    let signal_urls = board.read().await.signal_urls();
 
-                    match signal_web_portal(&signal_url, &job).await {
+                    match signal_web_portal(&signal_urls, &job).await {
                         Ok(_) => {},
                         Err(e) => {
                             // remove the job from the board as it will not be processed
@@ -333,13 +360,13 @@ async fn main() -> Result<()> {
 
                     let board = server::get_board().await?;
 
-                    let waiter = board.write().await.add(&job)?;
+                    let waiter = board.write().await.add_envelope(&envelope)?;
 
                     // now signal the web-portal connected to the bridge
                     // that this job is ready to be processed
-                    let signal_url = board.read().await.signal_url();
+                    let signal_urls = board.read().await.signal_urls();
 
-                    match signal_web_portal(&signal_url, &job).await {
+                    match signal_web_portal(&signal_urls, &job).await {
                         Ok(_) => {},
                         Err(e) => {
                             // remove the job from the board as it will not be processed
@@ -366,13 +393,13 @@ async fn main() -> Result<()> {
 
                     let board = server::get_board().await?;
 
-                    let waiter = board.write().await.add(&job)?;
+                    let waiter = board.write().await.add_envelope(&envelope)?;
 
                     // now signal the web-portal connected to the bridge
                     // that this job is ready to be processed
-                    let signal_url = board.read().await.signal_url();
+                    let signal_urls = board.read().await.signal_urls();
 
-                    match signal_web_portal(&signal_url, &job).await {
+                    match signal_web_portal(&signal_urls, &job).await {
                         Ok(_) => {},
                         Err(e) => {
                             // remove the job from the board as it will not be processed
@@ -399,13 +426,112 @@ async fn main() -> Result<()> {
 
                     let board = server::get_board().await?;
 
-                    let waiter = board.write().await.add(&job)?;
+                    let waiter = board.write().await.add_envelope(&envelope)?;
 
                     // now signal the web-portal connected to the bridge
                     // that this job is ready to be processed
-                    let signal_url = board.read().await.signal_url();
+                    let signal_urls = board.read().await.signal_urls();
 
-                    match signal_web_portal(&signal_url, &job).await {
+                    match signal_web_portal(&signal_urls, &job).await {
+                        Ok(_) => {},
+                        Err(e) => {
+                            // remove the job from the board as it will not be processed
+                            board.write().await.remove(&job)?;
+                            return job.errored(
+                                &format!("Failed to signal web portal: {}", e),
+                            );
+                        }
+                    }
+
+                    let mut result = waiter.result().await?;
+
+                    while !result.is_finished() {
+                        // get a new waiter to wait for the job to finish
+                        let waiter = board.write().await.get_waiter(&result)?;
+                        result = waiter.result().await?;
+                    }
+
+                    job.copy_result_from(&result)
+                }
+                AddUser(user) => {
+                    // add the user to their project in the web portal
+                    tracing::debug!("Adding user {}", user);
+
+                    let board = server::get_board().await?;
+
+                    let waiter = board.write().await.add_envelope(&envelope)?;
+
+                    // now signal the web-portal connected to the bridge
+                    // that this job is ready to be processed
+                    let signal_urls = board.read().await.signal_urls();
+
+                    match signal_web_portal(&signal_urls, &job).await {
+                        Ok(_) => {},
+                        Err(e) => {
+                            // remove the job from the board as it will not be processed
+                            board.write().await.remove(&job)?;
+                            return job.errored(
+                                &format!("Failed to signal web portal: {}", e),
+                            );
+                        }
+                    }
+
+                    let mut result = waiter.result().await?;
+
+                    while !result.is_finished() {
+                        // get a new waiter to wait for the job to finish
+                        let waiter = board.write().await.get_waiter(&result)?;
+                        result = waiter.result().await?;
+                    }
+
+                    job.copy_result_from(&result)
+                }
+                RemoveUser(user) => {
+                    // remove the user from their project in the web portal
+                    tracing::debug!("Removing user {}", user);
+
+                    let board = server::get_board().await?;
+
+                    let waiter = board.write().await.add_envelope(&envelope)?;
+
+                    // now signal the web-portal connected to the bridge
+                    // that this job is ready to be processed
+                    let signal_urls = board.read().await.signal_urls();
+
+                    match signal_web_portal(&signal_urls, &job).await {
+                        Ok(_) => {},
+                        Err(e) => {
+                            // remove the job from the board as it will not be processed
+                            board.write().await.remove(&job)?;
+                            return job.errored(
+                                &format!("Failed to signal web portal: {}", e),
+                            );
+                        }
+                    }
+
+                    let mut result = waiter.result().await?;
+
+                    while !result.is_finished() {
+                        // get a new waiter to wait for the job to finish
+                        let waiter = board.write().await.get_waiter(&result)?;
+                        result = waiter.result().await?;
+                    }
+
+                    job.copy_result_from(&result)
+                }
+                IsExistingUser(user) => {
+                    // ask the web portal whether the user exists
+                    tracing::debug!("Checking if user {} exists", user);
+
+                    let board = server::get_board().await?;
+
+                    let waiter = board.write().await.add_envelope(&envelope)?;
+
+                    // now signal the web-portal connected to the bridge
+                    // that this job is ready to be processed
+                    let signal_urls = board.read().await.signal_urls();
+
+                    match signal_web_portal(&signal_urls, &job).await {
                         Ok(_) => {},
                         Err(e) => {
                             // remove the job from the board as it will not be processed
@@ -432,13 +558,13 @@ async fn main() -> Result<()> {
 
                     let board = server::get_board().await?;
 
-                    let waiter = board.write().await.add(&job)?;
+                    let waiter = board.write().await.add_envelope(&envelope)?;
 
                     // now signal the web-portal connected to the bridge
                     // that this job is ready to be processed
-                    let signal_url = board.read().await.signal_url();
+                    let signal_urls = board.read().await.signal_urls();
 
-                    match signal_web_portal(&signal_url, &job).await {
+                    match signal_web_portal(&signal_urls, &job).await {
                         Ok(_) => {},
                         Err(e) => {
                             // remove the job from the board as it will not be processed
@@ -465,13 +591,13 @@ async fn main() -> Result<()> {
 
                     let board = server::get_board().await?;
 
-                    let waiter = board.write().await.add(&job)?;
+                    let waiter = board.write().await.add_envelope(&envelope)?;
 
                     // now signal the web-portal connected to the bridge
                     // that this job is ready to be processed
-                    let signal_url = board.read().await.signal_url();
+                    let signal_urls = board.read().await.signal_urls();
 
-                    match signal_web_portal(&signal_url, &job).await {
+                    match signal_web_portal(&signal_urls, &job).await {
                         Ok(_) => {},
                         Err(e) => {
                             // remove the job from the board as it will not be processed
@@ -498,13 +624,13 @@ async fn main() -> Result<()> {
 
                     let board = server::get_board().await?;
 
-                    let waiter = board.write().await.add(&job)?;
+                    let waiter = board.write().await.add_envelope(&envelope)?;
 
                     // now signal the web-portal connected to the bridge
                     // that this job is ready to be processed
-                    let signal_url = board.read().await.signal_url();
+                    let signal_urls = board.read().await.signal_urls();
 
-                    match signal_web_portal(&signal_url, &job).await {
+                    match signal_web_portal(&signal_urls, &job).await {
                         Ok(_) => {},
                         Err(e) => {
                             // remove the job from the board as it will not be processed
@@ -531,13 +657,13 @@ async fn main() -> Result<()> {
 
                     let board = server::get_board().await?;
 
-                    let waiter = board.write().await.add(&job)?;
+                    let waiter = board.write().await.add_envelope(&envelope)?;
 
                     // now signal the web-portal connected to the bridge
                     // that this job is ready to be processed
-                    let signal_url = board.read().await.signal_url();
+                    let signal_urls = board.read().await.signal_urls();
 
-                    match signal_web_portal(&signal_url, &job).await {
+                    match signal_web_portal(&signal_urls, &job).await {
                         Ok(_) => {},
                         Err(e) => {
                             // remove the job from the board as it will not be processed
@@ -579,6 +705,8 @@ async fn main() -> Result<()> {
     // run the Bridge agent
     set_notify_runner(bridge_notify_runner).await?;
     spawn_notification_delivery_task();
+    spawn_expiry_signal_task();
+    spawn_resume_pending_jobs().await?;
     run(config, bridge_runner).await?;
 
     Ok(())
@@ -606,6 +734,195 @@ fn spawn_notification_delivery_task() {
     });
 }
 
+///
+/// Spawn the background task that tells the web portal about jobs
+/// that expired on the bridge board before the web portal answered them
+///
+fn spawn_expiry_signal_task() {
+    tokio::spawn(async move {
+        loop {
+            sleep(Duration::from_secs(10)).await;
+
+            let board = match server::get_board().await {
+                Ok(b) => b,
+                Err(e) => {
+                    tracing::error!("Expiry signals: could not get board: {}", e);
+                    continue;
+                }
+            };
+
+            let expired_jobs = board.write().await.take_expired_jobs();
+
+            if expired_jobs.is_empty() {
+                continue;
+            }
+
+            let signal_urls = board.read().await.signal_urls();
+
+            for job in expired_jobs {
+                if let Err(e) = signal_expired_job(&signal_urls, &job).await {
+                    tracing::error!("{}", e);
+                }
+            }
+        }
+    });
+}
+
+///
+/// Tell the web portal that the passed job expired on the bridge board
+/// without being answered. This calls the signal URLs with the job ID,
+/// an 'event' of 'expired' and the job's 'instruction', all of which
+/// are covered by the signature. Each URL is tried once, stopping at the
+/// first that acknowledges the signal
+///
+async fn signal_expired_job(signal_urls: &[Url], job: &Job) -> Result<(), Error> {
+    if signal_urls.is_empty() {
+        return Ok(());
+    }
+
+    let job_id = job.id().to_string();
+    let instruction = job.instruction().to_string();
+
+    let client = Client::builder()
+        .danger_accept_invalid_certs(should_allow_invalid_certs())
+        .timeout(Duration::from_secs(60))
+        .build()
+        .with_context(|| {
+            format!(
+                "Failed to build HTTP client for signaling expiry of job: {}",
+                job_id
+            )
+        })?;
+
+    // the event and instruction are signed too, so that they cannot
+    // be changed if the signal is replayed
+    let params = [("event", "expired"), ("instruction", instruction.as_str())];
+
+    for url in signal_urls {
+        let (timestamp, signature) = sign_signal(&job.id(), &params).await?;
+
+        match client
+            .get(url.clone())
+            .query(&[("job_id", job_id.as_str())])
+            .query(&params)
+            .query(&[
+                ("timestamp", timestamp.as_str()),
+                ("signature", signature.as_str()),
+            ])
+            .send()
+            .await
+        {
+            Ok(response) if response.status().is_success() => {
+                tracing::info!("Signaled web portal at {} that job {} expired", url, job_id);
+                return Ok(());
+            }
+            Ok(response) => {
+                tracing::warn!(
+                    "Failed to signal web portal at {} that job {} expired. Status: {}",
+                    url,
+                    job_id,
+                    response.status()
+                );
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to signal web portal at {} that job {} expired. Error: {}",
+                    url,
+                    job_id,
+                    e
+                );
+            }
+        }
+    }
+
+    Err(Error::Delivery(format!(
+        "Could not signal any of {} signal URLs that job {} ({}) expired",
+        signal_urls.len(),
+        job_id,
+        instruction
+    )))
+}
+
+///
+/// Spawn a task for each unfinished job that was restored from the
+/// bridge board file. Each task re-signals the web portal and, once the
+/// web portal has sent the result, returns it to the original sender
+/// just as the bridge runner would have done had it not been restarted.
+///
+async fn spawn_resume_pending_jobs() -> Result<(), Error> {
+    let envelopes = server::get_board()
+        .await?
+        .read()
+        .await
+        .unfinished_envelopes();
+
+    for envelope in envelopes {
+        tokio::spawn(async move {
+            let job = envelope.job();
+
+            if let Err(e) = resume_pending_job(&envelope).await {
+                tracing::error!("Failed to resume bridge board job {}: {}", job.id(), e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+async fn resume_pending_job(envelope: &Envelope) -> Result<(), Error> {
+    let job = envelope.job();
+
+    tracing::info!(
+        "Resuming bridge board job {} : {}",
+        job.id(),
+        job.instruction()
+    );
+
+    let board = server::get_board().await?;
+
+    // the waiters were lost on restart, so get a new one
+    let waiter = board.write().await.get_waiter(&job)?;
+
+    let signal_urls = board.read().await.signal_urls();
+
+    let completed = match signal_web_portal(&signal_urls, &job).await {
+        Ok(_) => {
+            let mut result = waiter.result().await?;
+
+            while !result.is_finished() {
+                let waiter = board.write().await.get_waiter(&result)?;
+                result = waiter.result().await?;
+            }
+
+            job.copy_result_from(&result)?
+        }
+        Err(e) => {
+            board.write().await.remove(&job)?;
+            job.errored(&format!("Failed to signal web portal: {}", e))?
+        }
+    };
+
+    // wait until the portal is back, so that the virtual agents
+    // have been re-registered by sync_offerings
+    if agent::portal(PORTAL_WAIT_TIME).await.is_none() {
+        tracing::warn!(
+            "Portal is not connected - result for job {} will be queued",
+            job.id()
+        );
+    }
+
+    let recipient = envelope.recipient();
+    let sender = envelope.sender();
+
+    if agent::is_self(&recipient).await {
+        completed.update(&sender).await?;
+    } else {
+        completed.virtual_update(&recipient, &sender).await?;
+    }
+
+    Ok(())
+}
+
 /// Signal the web portal that a notification is ready to fetch, then serve it
 /// via the authenticated POST /fetch_notification endpoint.
 /// Attempts up to 3 times with a 2-second backoff, then logs and drops.
@@ -639,9 +956,27 @@ async fn deliver_notification(notification_url: &Option<Url>, notification: &Not
     };
 
     for attempt in 1..=3u32 {
+        let (timestamp, signature) = match sign_signal(&notification.id(), &[]).await {
+            Ok(signed) => signed,
+            Err(e) => {
+                tracing::error!(
+                    "Failed to sign signal for notification [{}]: {}",
+                    notification.id(),
+                    e
+                );
+                server::remove_pending_notification(notification.id()).await;
+                diagnostics::increment_notification_failed().await;
+                return;
+            }
+        };
+
         let response = client
             .get(url.clone())
-            .query(&[("notification_id", notification_id.as_str())])
+            .query(&[
+                ("notification_id", notification_id.as_str()),
+                ("timestamp", timestamp.as_str()),
+                ("signature", signature.as_str()),
+            ])
             .send()
             .await;
 
@@ -793,82 +1128,253 @@ pub async fn sync_offerings(offerings: &Destinations) -> Result<Destinations, Er
 }
 
 ///
-/// Call 'get' on the passed signal URL, passing in the job ID
-/// as the 'job_id' query parameter. Do nothing if the signal URL
-/// is not set. Attempt to call this 5 times, then give up
+/// Sign a signal for the passed id and other query parameters with the
+/// bridge key, returning the timestamp and signature to pass as query
+/// parameters
 ///
-pub async fn signal_web_portal(signal_url: &Option<Url>, job: &Job) -> Result<(), Error> {
-    if let Some(url) = signal_url {
-        let job_id = job.id().to_string();
-        let mut attempts = 0;
-
-        let client = Client::builder()
-            .danger_accept_invalid_certs(should_allow_invalid_certs())
-            .timeout(Duration::from_secs(60))
-            .build()
-            .with_context(|| {
-                format!(
-                    "Failed to build HTTP client for signaling web portal for job: {}",
-                    job_id
-                )
-            })?;
+async fn sign_signal(id: &Uuid, params: &[(&str, &str)]) -> Result<(String, String), Error> {
+    let key = match server::get_board().await?.read().await.signal_key() {
+        Some(key) => key,
+        None => {
+            return Err(Error::Misconfigured(
+                "No key has been set to sign signals to the web portal".to_string(),
+            ))
+        }
+    };
 
-        while attempts < 5 {
-            attempts += 1;
+    let timestamp = chrono::Utc::now().timestamp();
+    let signature = server::sign_signal(&key, timestamp, id, params)?;
+
+    Ok((timestamp.to_string(), signature))
+}
+
+///
+/// Call 'get' on each of the passed signal URLs in turn, passing in
+/// the job ID as the 'job_id' query parameter, together with a 'timestamp'
+/// and 'signature' that the web portal can use to verify that the
+/// signal came from this bridge. The first URL to acknowledge the
+/// signal is recorded on the bridge board. Do nothing if no signal URLs
+/// are set. The number of attempts, the interval between them and the
+/// overall deadline are set by the board's signal policy. If the policy
+/// allows background retries then a failed signal is retried in the
+/// background and the job is left on the board, rather than failing
+///
+pub async fn signal_web_portal(signal_urls: &[Url], job: &Job) -> Result<(), Error> {
+    if signal_urls.is_empty() {
+        tracing::warn!(
+            "Signal URL is not set, skipping signaling web portal for job: {}",
+            job.id()
+        );
+        return Ok(());
+    }
+
+    let policy = server::get_board().await?.read().await.signal_policy();
+
+    let error = match try_signal_web_portal(
+        signal_urls,
+        job,
+        policy.attempts,
+        policy.interval_seconds,
+        policy.deadline_seconds,
+    )
+    .await
+    {
+        Ok(_) => return Ok(()),
+        Err(e) => e,
+    };
+
+    if !policy.retries_in_background() {
+        return Err(error);
+    }
+
+    tracing::warn!(
+        "{} - will keep retrying in the background for up to {} seconds",
+        error,
+        policy.background_seconds
+    );
+
+    let signal_urls = signal_urls.to_vec();
+    let job = job.clone();
+
+    tokio::spawn(async move {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(policy.background_seconds);
+
+        while tokio::time::Instant::now() < deadline {
+            sleep(Duration::from_secs(policy.interval_seconds.max(1))).await;
+
+            // stop if the job has been fetched, completed or removed
+            match server::get_board().await {
+                Ok(board) => match board.read().await.get(&job.id()) {
+                    Ok(j) if !j.is_finished() => {}
+                    _ => return,
+                },
+                Err(_) => return,
+            }
+
+            if try_signal_web_portal(&signal_urls, &job, 1, 0, policy.deadline_seconds)
+                .await
+                .is_ok()
+            {
+                return;
+            }
+        }
+
+        tracing::error!(
+            "Gave up signaling web portal in the background for job: {}",
+            job.id()
+        );
+    });
+
+    Ok(())
+}
+
+///
+/// Make up to `attempts` attempts to signal the web portal at each of
+/// the passed signal URLs, waiting `interval_seconds` between attempts,
+/// and giving up once `deadline_seconds` have passed
+///
+async fn try_signal_web_portal(
+    signal_urls: &[Url],
+    job: &Job,
+    attempts: u32,
+    interval_seconds: u64,
+    deadline_seconds: u64,
+) -> Result<(), Error> {
+    let job_id = job.id().to_string();
+    let attempts = attempts.max(1);
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(deadline_seconds);
+
+    let client = Client::builder()
+        .danger_accept_invalid_certs(should_allow_invalid_certs())
+        .timeout(Duration::from_secs(60))
+        .build()
+        .with_context(|| {
+            format!(
+                "Failed to build HTTP client for signaling web portal for job: {}",
+                job_id
+            )
+        })?;
+
+    for attempt in 1..=attempts {
+        let mut unavailable = Vec::new();
+
+        for url in signal_urls {
+            // skip signal URLs that have been failing
+            let breaker = CircuitBreaker::new(&format!("signal_url:{}", url));
+
+            if let Err(e) = breaker.check() {
+                tracing::warn!("Attempt {}: Not signaling {}: {}", attempt, url, e);
+                unavailable.push(e);
+                continue;
+            }
+
+            // sign each attempt separately so that the timestamp is fresh
+            let (timestamp, signature) = sign_signal(&job.id(), &[]).await?;
 
             let response = match client
                 .get(url.clone())
-                .query(&[("job_id", job_id.as_str())])
+                .query(&[
+                    ("job_id", job_id.as_str()),
+                    ("timestamp", timestamp.as_str()),
+                    ("signature", signature.as_str()),
+                ])
                 .send()
                 .await
             {
                 Ok(resp) => resp,
                 Err(e) => {
                     tracing::warn!(
-                        "Attempt {}: Failed to signal web portal for job: {}. Error: {}",
-                        attempts,
+                        "Attempt {}: Failed to signal web portal at {} for job: {}. Error: {}",
+                        attempt,
+                        url,
                         job_id,
                         e
                     );
-                    // Wait before retrying
-                    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+                    breaker.failure(&e);
+                    health::set_dependency(
+                        &format!("signal_url:{}", url),
+                        false,
+                        &format!("could not connect: {}", e),
+                    );
                     continue;
                 }
             };
 
+            // only server errors count against the breaker - anything
+            // else shows that the web portal is up
+            match response.status().is_server_error() {
+                true => breaker.failure(&format!("responded with status {}", response.status())),
+                false => breaker.success(),
+            }
+
             if response.status().is_success() {
-                tracing::info!("Successfully signaled web portal for job: {}", job_id);
+                tracing::info!(
+                    "Successfully signaled web portal at {} for job: {}",
+                    url,
+                    job_id
+                );
+
+                health::set_dependency(
+                    &format!("signal_url:{}", url),
+                    true,
+                    "acknowledged the last signal",
+                );
+
+                server::get_board()
+                    .await?
+                    .write()
+                    .await
+                    .set_acknowledged_by(&job.id(), url);
+
                 return Ok(());
             } else {
                 tracing::warn!(
-                    "Failed to signal web portal for job: {}. Status: {}",
+                    "Attempt {}: Failed to signal web portal at {} for job: {}. Status: {}",
+                    attempt,
+                    url,
                     job_id,
                     response.status()
                 );
+                health::set_dependency(
+                    &format!("signal_url:{}", url),
+                    false,
+                    &format!("responded with status {}", response.status()),
+                );
             }
-
-            // Wait before retrying
-            tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
         }
 
-        tracing::error!(
-            "Failed to signal web portal after 5 re-attempts for job: {}",
-            job_id
-        );
+        // fail fast if every signal URL is unavailable
+        if unavailable.len() == signal_urls.len() {
+            if let Some(error) = unavailable.pop() {
+                return Err(error);
+            }
+        }
 
-        return Err(Error::Unknown(
-            format!(
-                "Failed to signal web portal after 5 re-attempts for job: {}",
+        if tokio::time::Instant::now() >= deadline {
+            tracing::warn!(
+                "Signal deadline of {} seconds passed after {} attempts for job: {}",
+                deadline_seconds,
+                attempt,
                 job_id
-            )
-            .to_string(),
-        ));
-    } else {
-        tracing::warn!(
-            "Signal URL is not set, skipping signaling web portal for job: {}",
-            job.id()
-        );
+            );
+            break;
+        }
+
+        // Wait before retrying
+        if attempt < attempts {
+            sleep(Duration::from_secs(interval_seconds)).await;
+        }
     }
 
-    Ok(())
+    tracing::error!(
+        "Failed to signal web portal at any of {} signal URLs for job: {}",
+        signal_urls.len(),
+        job_id
+    );
+
+    Err(Error::Unknown(format!(
+        "Failed to signal web portal at any of {} signal URLs for job: {}",
+        signal_urls.len(),
+        job_id
+    )))
 }
@@ -0,0 +1,148 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+
+use templemeads::agent::monitor::{process_args, run, Defaults};
+use templemeads::agent::Type as AgentType;
+use templemeads::async_runnable;
+use templemeads::job::{Envelope, Job};
+use templemeads::notification::default_notify_runner;
+use templemeads::set_notify_runner;
+use templemeads::Error;
+
+mod watcher;
+mod webhook;
+
+///
+/// Main function for the chat notification agent
+///
+/// This agent connects to another agent (normally the portal) and
+/// periodically collects the health and diagnostics of every agent in
+/// the network. Job failures, reconciliation drift and health alerts
+/// are posted to Slack, Matrix and/or Microsoft Teams, so that they
+/// reach the team where they already watch for alerts.
+///
+#[tokio::main]
+async fn main() -> Result<()> {
+    // start tracing
+    templemeads::config::initialise_tracing();
+
+    // start system monitoring
+    templemeads::spawn_system_monitor();
+
+    // create the OpenPortal paddington defaults
+    let defaults: Defaults = Defaults::parse(
+        Some("chat".to_owned()),
+        Some(
+            dirs::config_local_dir()
+                .unwrap_or(
+                    ".".parse()
+                        .expect("Could not parse fallback config directory."),
+                )
+                .join("openportal")
+                .join("chat-config.toml"),
+        ),
+        Some("ws://localhost:8050".to_owned()),
+        Some("127.0.0.1".to_owned()),
+        Some(8050),
+        None,
+        None,
+        Some(AgentType::Monitor),
+    );
+
+    // now parse the command line arguments to get the service configuration
+    let config = match process_args(&defaults).await? {
+        Some(config) => config,
+        None => {
+            // Not running the service, so can safely exit
+            return Ok(());
+        }
+    };
+
+    // The channels to post to. Webhook URLs and access tokens contain
+    // credentials, so are stored as secrets
+    let mut channels = Vec::new();
+
+    if let Some(webhook) = config.secret("slack-webhook") {
+        channels.push(webhook::Channel::Slack { webhook });
+    }
+
+    if let Some(webhook) = config.secret("teams-webhook") {
+        channels.push(webhook::Channel::Teams { webhook });
+    }
+
+    let matrix_homeserver = config.option("matrix-homeserver", "");
+    let matrix_room = config.option("matrix-room", "");
+
+    if !matrix_homeserver.is_empty() || !matrix_room.is_empty() {
+        let token = match config.secret("matrix-token") {
+            Some(token) => token,
+            None => {
+                return Err(anyhow::anyhow!(
+                    "No Matrix access token specified. Please set this in the matrix-token option."
+                ));
+            }
+        };
+
+        if matrix_homeserver.is_empty() || matrix_room.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Both matrix-homeserver and matrix-room must be set to post to Matrix."
+            ));
+        }
+
+        channels.push(webhook::Channel::Matrix {
+            homeserver: matrix_homeserver,
+            room: matrix_room,
+            token,
+        });
+    }
+
+    if channels.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No chat channels specified. Please set at least one of the slack-webhook, \
+             teams-webhook or matrix-homeserver/matrix-room options."
+        ));
+    }
+
+    for channel in &channels {
+        tracing::info!("Posting events to {}", channel);
+    }
+
+    webhook::initialise_channels(channels)?;
+
+    // the events to post, from job_failed, reconciliation_drift and
+    // health_alert
+    let events = watcher::Events::parse(
+        &config.option("events", "job_failed,reconciliation_drift,health_alert"),
+    )?;
+
+    // get the interval (in seconds) between checks for new events
+    let poll_interval: u64 = config
+        .option("poll-interval", "60")
+        .parse()
+        .unwrap_or(60)
+        .max(1);
+
+    watcher::spawn_watcher(poll_interval, events);
+
+    async_runnable! {
+        ///
+        /// Runnable function that will be called when a job is received
+        /// by the agent
+        ///
+        pub async fn chat_runner(envelope: Envelope) -> Result<Job, templemeads::Error>
+        {
+            let job = envelope.job();
+
+            Err(Error::InvalidInstruction(
+                format!("Invalid instruction: {}. Chat agents do not accept instructions", job.instruction()),
+            ))
+        }
+    }
+
+    set_notify_runner(default_notify_runner).await?;
+    run(config, chat_runner).await?;
+
+    Ok(())
+}
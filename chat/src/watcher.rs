@@ -0,0 +1,356 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use std::collections::BTreeSet;
+use templemeads::diagnostics;
+use templemeads::health::{self, HealthInfo};
+use templemeads::Error;
+use tokio::sync::RwLock;
+
+use crate::webhook;
+
+/// The most lines posted in a single message. Any more are summarised,
+/// so that an outage does not flood the channel.
+const MAX_LINES: usize = 25;
+
+/// Prefix of the health warnings raised by the cluster agent when a
+/// reconcile finds drift that it did not repair
+const DRIFT_PREFIX: &str = "Reconciliation drift";
+
+///
+/// The kinds of event that are posted to the chat channels
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Events {
+    job_failed: bool,
+    reconciliation_drift: bool,
+    health_alert: bool,
+}
+
+impl Events {
+    ///
+    /// Parse a comma-separated list of events, e.g.
+    /// "job_failed,reconciliation_drift,health_alert"
+    ///
+    pub fn parse(events: &str) -> Result<Self, Error> {
+        let mut parsed = Events {
+            job_failed: false,
+            reconciliation_drift: false,
+            health_alert: false,
+        };
+
+        for event in events
+            .split(',')
+            .map(|e| e.trim())
+            .filter(|e| !e.is_empty())
+        {
+            match event {
+                "job_failed" => parsed.job_failed = true,
+                "reconciliation_drift" => parsed.reconciliation_drift = true,
+                "health_alert" => parsed.health_alert = true,
+                _ => {
+                    return Err(Error::Misconfigured(format!(
+                        "Unknown event '{}'. Events must be one of job_failed, \
+                         reconciliation_drift or health_alert",
+                        event
+                    )))
+                }
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    /// The alerts (drift and health) raised at the last check, which
+    /// are posted again only once they have been resolved and re-raised
+    alerts: BTreeSet<String>,
+    /// Job failures seen after this time have not yet been posted
+    since: DateTime<Utc>,
+}
+
+static STATE: Lazy<RwLock<State>> = Lazy::new(|| {
+    RwLock::new(State {
+        alerts: BTreeSet::new(),
+        since: Utc::now(),
+    })
+});
+
+///
+/// Return the dot-separated path from this agent to each agent in the
+/// health tree, together with that agent's health
+///
+fn flatten(health: &HealthInfo, prefix: &str, agents: &mut Vec<(String, HealthInfo)>) {
+    let mut names = health.keys();
+    names.sort();
+
+    for name in names {
+        if let Some(peer) = health.get(&name) {
+            let path = match prefix.is_empty() {
+                true => name.clone(),
+                false => format!("{}.{}", prefix, name),
+            };
+
+            flatten(&peer, &path, agents);
+            agents.push((path, peer));
+        }
+    }
+}
+
+///
+/// Return the drift and health alerts currently raised by the agents
+///
+fn get_alerts(agents: &[(String, HealthInfo)], events: &Events) -> BTreeSet<String> {
+    let mut alerts = BTreeSet::new();
+
+    for (path, health) in agents {
+        if !health.connected {
+            if events.health_alert {
+                alerts.insert(format!("{} is disconnected", path));
+            }
+
+            continue;
+        }
+
+        for warning in &health.warnings {
+            let enabled = match warning.starts_with(DRIFT_PREFIX) {
+                true => events.reconciliation_drift,
+                false => events.health_alert,
+            };
+
+            if enabled {
+                alerts.insert(format!("{}: {}", path, warning));
+            }
+        }
+    }
+
+    alerts
+}
+
+///
+/// Return a line for each job that failed on the agents since the
+/// passed time
+///
+async fn get_job_failures(agents: &[(String, HealthInfo)], since: &DateTime<Utc>) -> Vec<String> {
+    let mut failures = Vec::new();
+
+    for (path, _) in agents.iter().filter(|(_, health)| health.connected) {
+        match diagnostics::collect_diagnostics(path).await {
+            Ok(report) => {
+                for job in report.failed_jobs.iter().filter(|j| j.last_seen > *since) {
+                    failures.push(format!(
+                        "Job failed on {}: {} ({}): {}{}",
+                        path,
+                        job.instruction,
+                        job.destination,
+                        job.error_message,
+                        match job.count {
+                            1 => String::new(),
+                            n => format!(" [{} times]", n),
+                        }
+                    ));
+                }
+            }
+            Err(e) => {
+                tracing::warn!("Could not get the diagnostics of {}: {}", path, e);
+            }
+        }
+    }
+
+    failures
+}
+
+///
+/// Compose the message to post from the passed lines, summarising any
+/// beyond the maximum
+///
+fn compose(lines: &[String]) -> String {
+    let mut message = lines
+        .iter()
+        .take(MAX_LINES)
+        .map(|line| format!("- {}", line))
+        .collect::<Vec<_>>();
+
+    if lines.len() > MAX_LINES {
+        message.push(format!("- ... and {} more", lines.len() - MAX_LINES));
+    }
+
+    format!("OpenPortal alerts:\n{}", message.join("\n"))
+}
+
+///
+/// Check the health and diagnostics of every agent in the network, and
+/// post any new job failures, new alerts and resolved alerts
+///
+pub async fn check(events: &Events) -> Result<(), Error> {
+    let checked_at = Utc::now();
+
+    let health = health::collect_health("", vec![])
+        .await
+        .map_err(|e| Error::Call(format!("Could not collect health: {}", e)))?;
+
+    let mut agents = Vec::new();
+    flatten(&health, "", &mut agents);
+
+    let alerts = get_alerts(&agents, events);
+
+    let (since, previous) = {
+        let state = STATE.read().await;
+        (state.since, state.alerts.clone())
+    };
+
+    let mut lines: Vec<String> = alerts
+        .difference(&previous)
+        .map(|alert| format!("Alert: {}", alert))
+        .collect();
+
+    lines.extend(
+        previous
+            .difference(&alerts)
+            .map(|alert| format!("Resolved: {}", alert)),
+    );
+
+    if events.job_failed {
+        lines.extend(get_job_failures(&agents, &since).await);
+    }
+
+    if !lines.is_empty() {
+        // the state is only updated once posted, so that nothing is lost
+        // if the chat service is unavailable
+        webhook::post(&compose(&lines)).await?;
+    }
+
+    let mut state = STATE.write().await;
+    state.alerts = alerts;
+    state.since = checked_at;
+
+    Ok(())
+}
+
+///
+/// Spawn a background task that checks for events to post every
+/// `interval` seconds
+///
+pub fn spawn_watcher(interval: u64, events: Events) {
+    // only job failures after the agent started are posted
+    Lazy::force(&STATE);
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+
+            if let Err(e) = check(&events).await {
+                tracing::error!("Failed to post events: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use templemeads::agent::Type as AgentType;
+
+    fn health(name: &str, connected: bool, warnings: &[&str]) -> HealthInfo {
+        let mut health = HealthInfo::new(
+            name,
+            AgentType::Scheduler,
+            connected,
+            Utc::now(),
+            "test",
+            "0.1.0",
+        );
+
+        health.warnings = warnings.iter().map(|w| w.to_string()).collect();
+
+        health
+    }
+
+    #[test]
+    fn test_parse_events() {
+        let all = Events {
+            job_failed: true,
+            reconciliation_drift: true,
+            health_alert: true,
+        };
+
+        assert!(
+            Events::parse("job_failed, reconciliation_drift,health_alert,")
+                .is_ok_and(|events| events == all)
+        );
+
+        assert!(Events::parse("health_alert").is_ok_and(|events| events
+            == Events {
+                job_failed: false,
+                reconciliation_drift: false,
+                health_alert: true,
+            }));
+
+        assert!(Events::parse("").is_ok_and(|events| !events.job_failed
+            && !events.reconciliation_drift
+            && !events.health_alert));
+
+        assert!(matches!(
+            Events::parse("job_failed,disk_full"),
+            Err(Error::Misconfigured(message)) if message.contains("disk_full")
+        ));
+    }
+
+    #[test]
+    fn test_alerts() {
+        let mut cluster = health("cluster", true, &[]);
+        cluster.add_peer_health(health(
+            "slurm",
+            true,
+            &["Reconciliation drift: 2 users missing", "High memory usage"],
+        ));
+        cluster.add_peer_health(health("freeipa", false, &["Ignored"]));
+
+        let mut portal = health("portal", true, &[]);
+        portal.add_peer_health(cluster);
+
+        let mut agents = Vec::new();
+        flatten(&portal, "", &mut agents);
+
+        let paths: Vec<&str> = agents.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, ["cluster.freeipa", "cluster.slurm", "cluster"]);
+
+        let events = |events: &str| {
+            Events::parse(events).unwrap_or_else(|e| unreachable!("Cannot parse events: {}", e))
+        };
+
+        assert_eq!(
+            get_alerts(&agents, &events("reconciliation_drift,health_alert")),
+            BTreeSet::from([
+                "cluster.freeipa is disconnected".to_owned(),
+                "cluster.slurm: High memory usage".to_owned(),
+                "cluster.slurm: Reconciliation drift: 2 users missing".to_owned(),
+            ])
+        );
+
+        assert_eq!(
+            get_alerts(&agents, &events("reconciliation_drift")),
+            BTreeSet::from(["cluster.slurm: Reconciliation drift: 2 users missing".to_owned()])
+        );
+
+        assert!(get_alerts(&agents, &events("job_failed")).is_empty());
+    }
+
+    #[test]
+    fn test_compose() {
+        assert_eq!(
+            compose(&["Alert: one".to_owned(), "Resolved: two".to_owned()]),
+            "OpenPortal alerts:\n- Alert: one\n- Resolved: two"
+        );
+
+        let lines: Vec<String> = (0..MAX_LINES + 5).map(|i| format!("line {}", i)).collect();
+        let message = compose(&lines);
+
+        assert_eq!(message.lines().count(), MAX_LINES + 2);
+        assert!(message.ends_with("- ... and 5 more"));
+    }
+}
@@ -0,0 +1,339 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+use once_cell::sync::OnceCell;
+use secrecy::{ExposeSecret, SecretString};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use templemeads::Error;
+
+static CHANNELS: OnceCell<Vec<Channel>> = OnceCell::new();
+
+/// Counter used to make each Matrix transaction id unique
+static TRANSACTION: AtomicU64 = AtomicU64::new(0);
+
+///
+/// A chat service to which messages are posted
+///
+pub enum Channel {
+    /// A Slack incoming webhook
+    Slack { webhook: SecretString },
+    /// A Microsoft Teams incoming webhook (or Workflows webhook)
+    Teams { webhook: SecretString },
+    /// A Matrix room, posted to using the client-server API
+    Matrix {
+        homeserver: String,
+        room: String,
+        token: SecretString,
+    },
+}
+
+impl std::fmt::Display for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Channel::Slack { .. } => write!(f, "slack"),
+            Channel::Teams { .. } => write!(f, "teams"),
+            Channel::Matrix { room, .. } => write!(f, "matrix ({})", room),
+        }
+    }
+}
+
+pub fn initialise_channels(channels: Vec<Channel>) -> Result<()> {
+    CHANNELS
+        .set(channels)
+        .map_err(|_| anyhow::anyhow!("Channels already initialised"))
+}
+
+fn get_channels() -> Result<&'static Vec<Channel>, Error> {
+    CHANNELS
+        .get()
+        .ok_or_else(|| Error::Call("Channels not initialised".to_owned()))
+}
+
+///
+/// Percent-encode the passed string so that it can be used as a
+/// segment of a URL path, e.g. a Matrix room id like "!abc:example.org"
+///
+fn encode_path_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+///
+/// Post the message to a single channel
+///
+async fn post_to(client: &reqwest::Client, channel: &Channel, message: &str) -> Result<(), Error> {
+    let request = match channel {
+        Channel::Slack { webhook } | Channel::Teams { webhook } => client
+            .post(webhook.expose_secret())
+            .json(&serde_json::json!({ "text": message })),
+        Channel::Matrix {
+            homeserver,
+            room,
+            token,
+        } => {
+            let transaction = format!(
+                "openportal-{}-{}",
+                chrono::Utc::now().timestamp_millis(),
+                TRANSACTION.fetch_add(1, Ordering::Relaxed)
+            );
+
+            client
+                .put(format!(
+                    "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+                    homeserver.trim_end_matches('/'),
+                    encode_path_segment(room),
+                    transaction
+                ))
+                .bearer_auth(token.expose_secret())
+                .json(&serde_json::json!({ "msgtype": "m.text", "body": message }))
+        }
+    };
+
+    let response = request
+        .timeout(Duration::from_secs(30))
+        .send()
+        .await
+        .map_err(|e| Error::Delivery(format!("Could not post to {}: {}", channel, e)))?;
+
+    let status = response.status();
+
+    match status.is_success() {
+        true => Ok(()),
+        false => Err(Error::Delivery(format!(
+            "Could not post to {}: {} {}",
+            channel,
+            status,
+            response.text().await.unwrap_or_default()
+        ))),
+    }
+}
+
+///
+/// Post the message to every configured channel. A failure to post to
+/// one channel does not stop the message being posted to the others.
+///
+pub async fn post(message: &str) -> Result<(), Error> {
+    let channels = get_channels()?;
+    let client = reqwest::Client::new();
+
+    let mut errors = Vec::new();
+
+    for channel in channels {
+        if let Err(e) = post_to(&client, channel, message).await {
+            tracing::warn!("{}", e);
+            errors.push(e.to_string());
+        }
+    }
+
+    match errors.len() == channels.len() && !channels.is_empty() {
+        true => Err(Error::Delivery(format!(
+            "Could not post to any channel: {}",
+            errors.join("; ")
+        ))),
+        false => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    ///
+    /// Start a server that answers a single request with the passed
+    /// status, returning its URL and a handle to the request it received
+    ///
+    async fn serve(status: u16) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot bind: {}", e));
+
+        let url = format!(
+            "http://{}",
+            listener
+                .local_addr()
+                .unwrap_or_else(|e| unreachable!("No address: {}", e))
+        );
+
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener
+                .accept()
+                .await
+                .unwrap_or_else(|e| unreachable!("Cannot accept: {}", e));
+
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 4096];
+
+            // read until the whole body (given by content-length) has arrived
+            loop {
+                let n = stream.read(&mut buffer).await.unwrap_or(0);
+                request.extend_from_slice(&buffer[..n]);
+
+                let text = String::from_utf8_lossy(&request).to_string();
+
+                if let Some((headers, body)) = text.split_once("\r\n\r\n") {
+                    let length = headers
+                        .lines()
+                        .find_map(|l| {
+                            l.to_lowercase()
+                                .strip_prefix("content-length:")
+                                .map(|v| v.trim().to_owned())
+                        })
+                        .and_then(|v| v.parse::<usize>().ok())
+                        .unwrap_or(0);
+
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+
+                if n == 0 {
+                    break;
+                }
+            }
+
+            let _ = stream
+                .write_all(
+                    format!(
+                        "HTTP/1.1 {} Status\r\ncontent-length: 2\r\nconnection: close\r\n\r\nno",
+                        status
+                    )
+                    .as_bytes(),
+                )
+                .await;
+
+            String::from_utf8_lossy(&request).to_string()
+        });
+
+        (url, handle)
+    }
+
+    async fn received(handle: JoinHandle<String>) -> String {
+        handle
+            .await
+            .unwrap_or_else(|e| unreachable!("Server failed: {}", e))
+    }
+
+    #[test]
+    fn test_encode_path_segment() {
+        assert_eq!(encode_path_segment("abc-1_2.3~"), "abc-1_2.3~");
+        assert_eq!(
+            encode_path_segment("!room:example.org"),
+            "%21room%3Aexample.org"
+        );
+        assert_eq!(encode_path_segment("a/b c"), "a%2Fb%20c");
+    }
+
+    #[test]
+    fn test_display() {
+        let secret = || SecretString::from("https://hooks.example.org/secret");
+
+        assert_eq!(Channel::Slack { webhook: secret() }.to_string(), "slack");
+        assert_eq!(Channel::Teams { webhook: secret() }.to_string(), "teams");
+        assert_eq!(
+            Channel::Matrix {
+                homeserver: "https://matrix.example.org".to_owned(),
+                room: "!room:example.org".to_owned(),
+                token: secret(),
+            }
+            .to_string(),
+            "matrix (!room:example.org)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_post_to() {
+        let client = reqwest::Client::new();
+
+        // slack and teams are posted as json to the webhook
+        let (url, handle) = serve(200).await;
+        let slack = Channel::Slack {
+            webhook: SecretString::from(format!("{}/services/abc", url)),
+        };
+
+        post_to(&client, &slack, "hello")
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot post: {}", e));
+
+        let request = received(handle).await;
+        assert!(request.starts_with("POST /services/abc HTTP/1.1"));
+        assert!(request.ends_with(r#"{"text":"hello"}"#));
+
+        // matrix messages are sent to the room with the access token
+        let (url, handle) = serve(200).await;
+        let matrix = Channel::Matrix {
+            homeserver: format!("{}/", url),
+            room: "!room:example.org".to_owned(),
+            token: SecretString::from("secret-token"),
+        };
+
+        post_to(&client, &matrix, "hello")
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot post: {}", e));
+
+        let request = received(handle).await;
+        assert!(request.starts_with(
+            "PUT /_matrix/client/v3/rooms/%21room%3Aexample.org/send/m.room.message/openportal-"
+        ));
+        assert!(request
+            .to_lowercase()
+            .contains("authorization: bearer secret-token"));
+        assert!(request.ends_with(r#"{"body":"hello","msgtype":"m.text"}"#));
+
+        // failures are delivery errors
+        let (url, handle) = serve(500).await;
+        let teams = Channel::Teams {
+            webhook: SecretString::from(url),
+        };
+
+        assert!(matches!(
+            post_to(&client, &teams, "hello").await,
+            Err(Error::Delivery(message)) if message.contains("500")
+        ));
+        received(handle).await;
+    }
+
+    #[tokio::test]
+    async fn test_post() {
+        // nothing can be posted until the channels are set
+        assert!(matches!(post("hello").await, Err(Error::Call(_))));
+
+        let (working, working_handle) = serve(200).await;
+        let (broken, broken_handle) = serve(404).await;
+
+        initialise_channels(vec![
+            Channel::Teams {
+                webhook: SecretString::from(broken),
+            },
+            Channel::Slack {
+                webhook: SecretString::from(working),
+            },
+        ])
+        .unwrap_or_else(|e| unreachable!("Cannot initialise channels: {}", e));
+
+        // a failure to post to one channel does not stop the others
+        assert!(post("hello").await.is_ok());
+        assert!(received(working_handle)
+            .await
+            .ends_with(r#"{"text":"hello"}"#));
+        received(broken_handle).await;
+
+        // but it is an error if nothing could be posted (the servers
+        // have now stopped)
+        assert!(matches!(
+            post("hello").await,
+            Err(Error::Delivery(message)) if message.contains("any channel")
+        ));
+    }
+}
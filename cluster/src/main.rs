@@ -6,21 +6,33 @@ use std::collections::HashMap;
 
 use templemeads::agent;
 use templemeads::agent::instance::{process_args, run, Defaults};
+use templemeads::agent::Peer;
 use templemeads::agent::Type as AgentType;
 use templemeads::async_runnable;
+use templemeads::batch::BatchResult;
 use templemeads::grammar::Instruction::{
-    AddProject, AddUser, BlockProject, BlockUser, ClearProjectQuota, ClearUserQuota, GetHomeDir,
-    GetLimit, GetLocalHomeDir, GetLocalProjectDirs, GetLocalUserDirs, GetProjectDirs,
-    GetProjectMapping, GetProjectQuota, GetProjectQuotas, GetProjects, GetStorageReport,
-    GetStorageReports, GetUsageReport, GetUsageReports, GetUserDirs, GetUserMapping, GetUserQuota,
-    GetUserQuotas, GetUsers, IsBlockedProject, IsBlockedUser, IsProtectedUser, RemoveProject,
-    RemoveUser, SetLimit, SetProjectQuota, SetUserQuota, UnblockProject, UnblockUser,
+    ActivateUser, AddLocalUsers, AddProject, AddUser, AddUserOTPToken, AddUserSSHKey,
+    AddUserToGroup, AddUsers, BlockProject, BlockUser, ClearProjectQuota, ClearUserQuota,
+    GetHomeDir, GetJobQueue, GetLimit, GetLocalHomeDir, GetLocalProjectDirs, GetLocalUserDirs,
+    GetProjectDirs, GetProjectMapping, GetProjectProtection, GetProjectQuota, GetProjectQuotas,
+    GetProjects, GetStorageReport, GetStorageReports, GetUsageReport, GetUsageReports, GetUserDirs,
+    GetUserMapping, GetUserOTPTokens, GetUserProtection, GetUserQuota, GetUserQuotas,
+    GetUserSSHKeys, GetUsers, IsBlockedProject, IsBlockedUser, IsProtectedUser, Reconcile,
+    RemoveLocalUsers, RemoveProject, RemoveUser, RemoveUserFromGroup, RemoveUserOTPToken,
+    RemoveUserSSHKey, RemoveUsers, SetLimit, SetProjectQuota, SetUserQuota, SubmitJob,
+    UnblockProject, UnblockUser, UpdateUser,
 };
 use templemeads::grammar::{
-    DateRange, PortalIdentifier, ProjectIdentifier, ProjectMapping, UserIdentifier, UserMapping,
+    Allocation, DateRange, PortalIdentifier, ProjectIdentifier, ProjectMapping, UserDetails,
+    UserIdentifier, UserMapping,
 };
+use templemeads::health;
 use templemeads::job::{Envelope, Job};
+use templemeads::jobqueue::JobQueue;
 use templemeads::notification::{self, default_notify_runner, NotificationEvent};
+use templemeads::portalquota;
+use templemeads::protection::ProtectionStatus;
+use templemeads::reconcile::ReconciliationReport;
 use templemeads::set_notify_runner;
 use templemeads::storage::{Quota, Volume};
 use templemeads::storagereport::{ProjectStorageReport, StorageReport};
@@ -116,6 +128,16 @@ async fn main() -> Result<()> {
                     // see if the project already exists
                     let project_exists: bool = is_existing_project(me.name(), &project).await?;
 
+                    // new projects must not take the portal over its cap
+                    if !project_exists {
+                        let quota = portalquota::quota(&project.portal());
+
+                        if quota.max_projects().is_some() {
+                            let projects = get_projects(me.name(), &project.portal_identifier()).await?;
+                            quota.check_projects(projects.len() + 1)?;
+                        }
+                    }
+
                     // add the project to the cluster
                     let mapping = match add_project_to_cluster(me.name(), &project).await {
                         Ok(mapping) => mapping,
@@ -180,6 +202,14 @@ async fn main() -> Result<()> {
 
                     if user_exists {
                         tracing::info!("User {} already exists on cluster - re-adding them", user);
+                    } else {
+                        // new users must not take the project over its cap
+                        let quota = portalquota::quota(&user.portal());
+
+                        if quota.max_users().is_some() {
+                            let users = get_accounts(me.name(), &user.project_identifier()).await?;
+                            quota.check_users(&user.project_identifier().to_string(), users.len() + 1)?;
+                        }
                     }
 
                     // add the user to the cluster
@@ -240,6 +270,30 @@ async fn main() -> Result<()> {
                     notification::send(&envelope.job().destination().reverse(), NotificationEvent::UserRemoved(user.clone())).await;
                     job.completed(mapping)
                 }
+                AddUsers(users) => {
+                    assert_agents_connected().await?;
+
+                    let (mut result, skipped) = add_users_to_cluster(me.name(), &users).await?;
+
+                    for mapping in &result.succeeded {
+                        notification::send(&envelope.job().destination().reverse(), NotificationEvent::UserAdded(mapping.user().clone())).await;
+                    }
+
+                    result.succeeded.extend(skipped);
+                    job.completed(result)
+                }
+                RemoveUsers(users) => {
+                    assert_agents_connected().await?;
+
+                    let (mut result, skipped) = remove_users_from_cluster(me.name(), &users).await?;
+
+                    for mapping in &result.succeeded {
+                        notification::send(&envelope.job().destination().reverse(), NotificationEvent::UserRemoved(mapping.user().clone())).await;
+                    }
+
+                    result.succeeded.extend(skipped);
+                    job.completed(result)
+                }
                 BlockUser(user) => {
                     let mapping = block_user_on_cluster(me.name(), &user).await?;
                     notification::send(&envelope.job().destination().reverse(), NotificationEvent::UserBlocked(user.clone())).await;
@@ -250,10 +304,50 @@ async fn main() -> Result<()> {
                     notification::send(&envelope.job().destination().reverse(), NotificationEvent::UserUnblocked(user.clone())).await;
                     job.completed(mapping)
                 }
+                ActivateUser(user) => {
+                    let mapping = activate_user_on_cluster(me.name(), &user).await?;
+                    job.completed(mapping)
+                }
+                UpdateUser(user, details) => {
+                    let mapping = update_user_on_cluster(me.name(), &user, &details).await?;
+                    job.completed(mapping)
+                }
                 IsBlockedUser(user) => {
                     let is_blocked = is_blocked_user(me.name(), &user).await?;
                     job.completed(is_blocked)
                 }
+                AddUserSSHKey(user, key) => {
+                    let keys = run_account_job(me.name(), &format!("add_user_ssh_key {} {}", user, key)).await?;
+                    job.completed(keys)
+                }
+                RemoveUserSSHKey(user, key) => {
+                    let keys = run_account_job(me.name(), &format!("remove_user_ssh_key {} {}", user, key)).await?;
+                    job.completed(keys)
+                }
+                GetUserSSHKeys(user) => {
+                    let keys = run_account_job(me.name(), &format!("get_user_ssh_keys {}", user)).await?;
+                    job.completed(keys)
+                }
+                AddUserOTPToken(user) => {
+                    let uri = add_user_otp_token_on_cluster(me.name(), &user).await?;
+                    job.completed(uri)
+                }
+                RemoveUserOTPToken(user, token) => {
+                    let tokens = run_account_job(me.name(), &format!("remove_user_otp_token {} {}", user, token)).await?;
+                    job.completed(tokens)
+                }
+                GetUserOTPTokens(user) => {
+                    let tokens = run_account_job(me.name(), &format!("get_user_otp_tokens {}", user)).await?;
+                    job.completed(tokens)
+                }
+                AddUserToGroup(user, group) => {
+                    let groups = run_account_job(me.name(), &format!("add_user_to_group {} {}", user, group)).await?;
+                    job.completed(groups)
+                }
+                RemoveUserFromGroup(user, group) => {
+                    let groups = run_account_job(me.name(), &format!("remove_user_from_group {} {}", user, group)).await?;
+                    job.completed(groups)
+                }
                 BlockProject(project) => {
                     let mappings = block_project_on_cluster(me.name(), &project).await?;
                     notification::send(&envelope.job().destination().reverse(), NotificationEvent::ProjectBlocked(project.clone())).await;
@@ -272,6 +366,22 @@ async fn main() -> Result<()> {
                     let is_protected = is_protected_user(me.name(), &user).await?;
                     job.completed(is_protected)
                 }
+                GetUserProtection(user) => {
+                    let status = get_protection_on_cluster(
+                        me.name(),
+                        &format!("get_user_protection {}", user),
+                    )
+                    .await?;
+                    job.completed(status)
+                }
+                GetProjectProtection(project) => {
+                    let status = get_protection_on_cluster(
+                        me.name(),
+                        &format!("get_project_protection {}", project),
+                    )
+                    .await?;
+                    job.completed(status)
+                }
                 GetProjectMapping(project) => {
                     let mapping = get_project_mapping(me.name(), &project).await?;
                     job.completed(mapping)
@@ -301,7 +411,20 @@ async fn main() -> Result<()> {
                     let limit = get_project_limit(me.name(), &project).await?;
                     job.completed(limit)
                 }
+                GetJobQueue(project) => {
+                    let queue = get_job_queue(me.name(), &project).await?;
+                    job.completed(queue)
+                }
+                SubmitJob(user, script) => {
+                    let job_id = submit_job(me.name(), &user, &script).await?;
+                    job.completed(job_id)
+                }
+                Reconcile(project, repair) => {
+                    let report = reconcile(me.name(), &project, repair).await?;
+                    job.completed(report)
+                }
                 SetLimit(project, limit) => {
+                    check_portal_allocation(me.name(), &project, &limit).await?;
                     let limit = set_project_limit(me.name(), &project, limit).await?;
                     job.completed(limit)
                 }
@@ -501,19 +624,40 @@ async fn add_user_to_cluster(me: &str, user: &UserIdentifier) -> Result<UserMapp
 
     let mapping = create_account(me, user).await?;
 
+    // staged users don't exist on the cluster until they are activated,
+    // so they are only provisioned then
+    if !is_existing_user(me, user).await? {
+        tracing::info!("User {} is staged - provisioning on activation", user);
+        return Ok(mapping);
+    }
+
+    provision_user(me, user, &mapping).await?;
+
+    Ok(mapping)
+}
+
+///
+/// Create the home directories of the passed (active) user, and add
+/// them to the job scheduler
+///
+async fn provision_user(
+    me: &str,
+    user: &UserIdentifier,
+    mapping: &UserMapping,
+) -> Result<(), Error> {
     // now create their home directories
-    create_user_directories(me, &mapping).await?;
+    create_user_directories(me, mapping).await?;
 
     // get the home directory path from the filesystem
-    let homedir = get_home_dir(me, &mapping).await?;
+    let homedir = get_home_dir(me, mapping).await?;
 
     // update the home directory in the account
     update_homedir(me, user, &homedir).await?;
 
     // and finally add the user to the job scheduler
-    add_user_to_scheduler(me, user, &mapping).await?;
+    add_user_to_scheduler(me, user, mapping).await?;
 
-    Ok(mapping)
+    Ok(())
 }
 
 async fn remove_user_from_cluster(me: &str, user: &UserIdentifier) -> Result<UserMapping, Error> {
@@ -554,6 +698,355 @@ async fn remove_user_from_cluster(me: &str, user: &UserIdentifier) -> Result<Use
     Ok(mapping)
 }
 
+///
+/// Add a batch of users, who are all in the same project, to the
+/// cluster. Each agent is sent a single job for the whole batch. This
+/// returns the result for each user who was added, and the mappings of
+/// the users who were skipped because they are protected or blocked.
+/// New users who could not be added are removed again from the account
+/// agent, so that no dangling accounts are left
+///
+async fn add_users_to_cluster(
+    me: &str,
+    users: &[UserIdentifier],
+) -> Result<(BatchResult, Vec<UserMapping>), Error> {
+    // the parser guarantees that all users are in the same project
+    let project = match users.first() {
+        Some(user) => user.project_identifier(),
+        None => return Ok((BatchResult::new(), vec![])),
+    };
+
+    tracing::info!("Adding {} users to cluster: {}", users.len(), project);
+
+    let existing = get_accounts(me, &project).await?;
+
+    let mut to_add = Vec::new();
+    let mut new_users = Vec::new();
+    let mut skipped = Vec::new();
+
+    for user in users {
+        let mapping = existing.iter().find(|mapping| mapping.user() == user);
+
+        let is_protected = match is_protected_user(me, user).await {
+            Ok(is_protected) => is_protected,
+            Err(Error::MissingUser(_)) => false,
+            Err(e) => return Err(e),
+        };
+
+        if is_protected {
+            tracing::info!("User {} is protected - not adding", user);
+
+            skipped.push(match mapping {
+                Some(mapping) => mapping.clone(),
+                None => get_user_mapping(me, user).await?,
+            });
+
+            continue;
+        }
+
+        match mapping {
+            // blocked users must not be re-enabled by add_users;
+            // only unblock_user should do that
+            Some(mapping) if is_blocked_user(me, user).await? => {
+                tracing::info!(
+                    "User {} is blocked - not re-adding. Use unblock_user to unblock.",
+                    user
+                );
+                skipped.push(mapping.clone());
+            }
+            Some(_) => {
+                tracing::info!("User {} already exists on cluster - re-adding them", user);
+                to_add.push(user.clone());
+            }
+            None => {
+                new_users.push(user.clone());
+                to_add.push(user.clone());
+            }
+        }
+    }
+
+    // new users must not take the project over its cap
+    if !new_users.is_empty() {
+        let quota = portalquota::quota(&project.portal());
+
+        if quota.max_users().is_some() {
+            quota.check_users(&project.to_string(), existing.len() + new_users.len())?;
+        }
+    }
+
+    if to_add.is_empty() {
+        return Ok((BatchResult::new(), skipped));
+    }
+
+    let result = match add_users_on_agents(me, &to_add).await {
+        Ok(result) => result,
+        Err(e) => {
+            tracing::error!("Error adding users to cluster: {:?}", e);
+            remove_partially_added_users(me, new_users).await;
+            return Err(e);
+        }
+    };
+
+    // only remove the new users who failed (this doesn't remove existing
+    // accounts that failed an update)
+    let failed: Vec<UserIdentifier> = result
+        .failed_users()
+        .into_iter()
+        .filter(|user| new_users.contains(user))
+        .collect();
+
+    if !result.is_complete() {
+        tracing::error!("Error adding some users to cluster: {}", result);
+    }
+
+    remove_partially_added_users(me, failed).await;
+
+    Ok((result, skipped))
+}
+
+///
+/// Remove the passed new users, who could not be fully added, from the
+/// account agent, so that no dangling accounts are left
+///
+async fn remove_partially_added_users(me: &str, users: Vec<UserIdentifier>) {
+    if users.is_empty() {
+        return;
+    }
+
+    tracing::warn!("Removing {} partially added users...", users.len());
+
+    match run_batch_job(
+        me,
+        agent::account(AGENT_WAIT_TIME).await,
+        "account",
+        &RemoveUsers(users).to_string(),
+    )
+    .await
+    {
+        Ok(result) if result.is_complete() => {
+            tracing::info!("Removed partially added users {:?}", result.succeeded)
+        }
+        Ok(result) => tracing::error!("Failed to remove some partially added users: {}", result),
+        Err(e) => tracing::error!("Failed to remove partially added users: {:?}", e),
+    }
+}
+
+///
+/// Add the passed batch of users to the account agent, and then (using
+/// the mappings that returns) to the filesystem and scheduler agents.
+/// The account agent sets the home directory of each new account, so
+/// this does not need to be updated afterwards. Staged users are only
+/// added to the filesystem and scheduler agents when they are activated.
+/// Users who fail at one agent are not sent to the next
+///
+async fn add_users_on_agents(me: &str, users: &[UserIdentifier]) -> Result<BatchResult, Error> {
+    let accounts = run_batch_job(
+        me,
+        agent::account(AGENT_WAIT_TIME).await,
+        "account",
+        &AddUsers(users.to_vec()).to_string(),
+    )
+    .await?;
+
+    let mut result = BatchResult::new();
+    result.failed = accounts.failed;
+
+    // staged users are not listed in the project until they are activated
+    let project = match accounts.succeeded.first() {
+        Some(mapping) => mapping.user().project_identifier(),
+        None => return Ok(result),
+    };
+
+    let listed = get_accounts(me, &project).await?;
+
+    let (active, staged): (Vec<UserMapping>, Vec<UserMapping>) = accounts
+        .succeeded
+        .into_iter()
+        .partition(|mapping| listed.iter().any(|m| m.user() == mapping.user()));
+
+    for mapping in staged {
+        tracing::info!(
+            "User {} is staged - provisioning on activation",
+            mapping.user()
+        );
+        result.add_success(mapping);
+    }
+
+    if active.is_empty() {
+        return Ok(result);
+    }
+
+    let directories = run_batch_job(
+        me,
+        agent::filesystem(AGENT_WAIT_TIME).await,
+        "filesystem",
+        &AddLocalUsers(active).to_string(),
+    )
+    .await?;
+
+    result.failed.extend(directories.failed);
+
+    if directories.succeeded.is_empty() {
+        return Ok(result);
+    }
+
+    let scheduler = run_batch_job(
+        me,
+        agent::scheduler(AGENT_WAIT_TIME).await,
+        "scheduler",
+        &AddLocalUsers(directories.succeeded).to_string(),
+    )
+    .await?;
+
+    result.failed.extend(scheduler.failed);
+    result.succeeded.extend(scheduler.succeeded);
+
+    Ok(result)
+}
+
+///
+/// Remove a batch of users, who are all in the same project, from the
+/// cluster. Each agent is sent a single job for the whole batch. This
+/// returns the result for each user who was removed from the account
+/// agent, and the mappings of the users who were skipped because they
+/// are protected. Users who are not in the project are assumed to have
+/// already been removed
+///
+async fn remove_users_from_cluster(
+    me: &str,
+    users: &[UserIdentifier],
+) -> Result<(BatchResult, Vec<UserMapping>), Error> {
+    // the parser guarantees that all users are in the same project
+    let project = match users.first() {
+        Some(user) => user.project_identifier(),
+        None => return Ok((BatchResult::new(), vec![])),
+    };
+
+    tracing::info!("Removing {} users from cluster: {}", users.len(), project);
+
+    let existing = get_accounts(me, &project).await?;
+
+    let mut to_remove = Vec::new();
+    let mut skipped = Vec::new();
+
+    for user in users {
+        let mapping = match existing.iter().find(|mapping| mapping.user() == user) {
+            Some(mapping) => mapping,
+            None => {
+                tracing::warn!(
+                    "Could not find user {}. Assuming they have already been removed.",
+                    user
+                );
+                continue;
+            }
+        };
+
+        match is_protected_user(me, user).await {
+            Ok(true) => {
+                tracing::info!("User {} is protected - not removing", user);
+                skipped.push(mapping.clone());
+            }
+            Ok(false) | Err(Error::MissingUser(_)) => to_remove.push(user.clone()),
+            Err(e) => return Err(e),
+        }
+    }
+
+    if to_remove.is_empty() {
+        return Ok((BatchResult::new(), skipped));
+    }
+
+    let result = run_batch_job(
+        me,
+        agent::account(AGENT_WAIT_TIME).await,
+        "account",
+        &RemoveUsers(to_remove).to_string(),
+    )
+    .await?;
+
+    if !result.is_complete() {
+        tracing::error!("Error removing some users from cluster: {}", result);
+    }
+
+    if result.succeeded.is_empty() {
+        return Ok((result, skipped));
+    }
+
+    match run_batch_job(
+        me,
+        agent::filesystem(AGENT_WAIT_TIME).await,
+        "filesystem",
+        &RemoveLocalUsers(result.succeeded.clone()).to_string(),
+    )
+    .await
+    {
+        Ok(removed) if removed.is_complete() => {
+            tracing::info!("User directories removed: {:?}", removed.succeeded)
+        }
+        Ok(removed) => tracing::error!("Error removing directories for some users: {}", removed),
+        Err(e) => tracing::error!("Error removing directories for users: {:?}", e),
+    }
+
+    match run_batch_job(
+        me,
+        agent::scheduler(AGENT_WAIT_TIME).await,
+        "scheduler",
+        &RemoveLocalUsers(result.succeeded.clone()).to_string(),
+    )
+    .await
+    {
+        Ok(removed) if removed.is_complete() => {
+            tracing::info!("Users removed from scheduler: {:?}", removed.succeeded)
+        }
+        Ok(removed) => tracing::error!("Error removing some users from scheduler: {}", removed),
+        Err(e) => tracing::error!("Error removing users from scheduler: {:?}", e),
+    }
+
+    Ok((result, skipped))
+}
+
+///
+/// Send the passed batch instruction (e.g. 'add_users ...') to the
+/// passed agent, returning the result for each user in the batch
+///
+async fn run_batch_job(
+    me: &str,
+    agent: Option<Peer>,
+    agent_type: &str,
+    instruction: &str,
+) -> Result<BatchResult, Error> {
+    match agent {
+        Some(agent) => {
+            let job = Job::parse(&format!("{}.{} {}", me, agent.name(), instruction), false)?
+                .put(&agent)
+                .await?;
+
+            // Wait for the batch to complete
+            let result = job.wait().await?.result::<BatchResult>()?;
+
+            match result {
+                Some(result) => {
+                    tracing::info!("Batch run on {} agent: {}", agent_type, result);
+                    Ok(result)
+                }
+                None => {
+                    tracing::error!("Error running batch on {} agent: {:?}", agent_type, job);
+                    Err(Error::Call(format!(
+                        "Error running batch on {} agent: {:?}",
+                        agent_type, job
+                    )))
+                }
+            }
+        }
+        None => {
+            tracing::error!("No {} agent found", agent_type);
+            Err(Error::MissingAgent(format!(
+                "Cannot run the job because there is no {} agent",
+                agent_type
+            )))
+        }
+    }
+}
+
 async fn get_projects(me: &str, portal: &PortalIdentifier) -> Result<Vec<ProjectMapping>, Error> {
     // find the Account agent
     match agent::account(AGENT_WAIT_TIME).await {
@@ -1230,8 +1723,30 @@ async fn get_usage_reports(
 
     let mut report = UsageReport::new(portal);
 
+    // get the reports of all projects in parallel - the scheduler agent
+    // limits how many sacct commands actually run at once
+    let mut tasks = Vec::new();
+
     for project in projects {
-        let project_report = get_usage_report(me, &project, dates).await?;
+        let me = me.to_string();
+        let dates = dates.clone();
+
+        tasks.push(tokio::spawn(async move {
+            get_usage_report(&me, &project, &dates).await
+        }));
+    }
+
+    for task in tasks {
+        let project_report = match task.await {
+            Ok(project_report) => project_report?,
+            Err(e) => {
+                return Err(Error::Call(format!(
+                    "Could not get project usage report: {}",
+                    e
+                )))
+            }
+        };
+
         report.set_report(project_report)?;
     }
 
@@ -1324,6 +1839,176 @@ async fn get_project_limit(me: &str, project: &ProjectIdentifier) -> Result<Usag
     Ok(limit)
 }
 
+async fn get_job_queue(me: &str, project: &ProjectIdentifier) -> Result<JobQueue, Error> {
+    // get the mapping for this project
+    let mapping = get_project_mapping(me, project).await?;
+
+    // find the scheduler agent
+    let scheduler = match agent::scheduler(AGENT_WAIT_TIME).await {
+        Some(scheduler) => scheduler,
+        None => {
+            tracing::error!("No scheduler agent found");
+            return Err(Error::MissingAgent(
+                "Cannot run the job because there is no scheduler agent".to_string(),
+            ));
+        }
+    };
+
+    // ask the scheduler for the project's queued jobs
+    let job = Job::parse(
+        &format!(
+            "{}.{} get_local_job_queue {}",
+            me,
+            scheduler.name(),
+            mapping
+        ),
+        false,
+    )?;
+
+    let job = job.put(&scheduler).await?;
+
+    // Wait for the job to complete... - get the resulting JobQueue
+    match job.wait().await?.result::<JobQueue>()? {
+        Some(queue) => Ok(queue),
+        None => Ok(JobQueue::new(project, Vec::new())),
+    }
+}
+
+async fn reconcile(
+    me: &str,
+    project: &ProjectIdentifier,
+    repair: bool,
+) -> Result<ReconciliationReport, Error> {
+    // get the mapping for this project, and the mappings of its users,
+    // from the account agent
+    let mapping = get_project_mapping(me, project).await?;
+    let users = get_accounts(me, project).await?;
+
+    // find the scheduler agent
+    let scheduler = match agent::scheduler(AGENT_WAIT_TIME).await {
+        Some(scheduler) => scheduler,
+        None => {
+            tracing::error!("No scheduler agent found");
+            return Err(Error::MissingAgent(
+                "Cannot run the job because there is no scheduler agent".to_string(),
+            ));
+        }
+    };
+
+    // ask the scheduler to compare its accounts against these mappings
+    let instruction = templemeads::grammar::Instruction::ReconcileLocal(mapping, repair, users);
+
+    let job = Job::parse(
+        &format!("{}.{} {}", me, scheduler.name(), instruction),
+        false,
+    )?;
+
+    let job = job.put(&scheduler).await?;
+
+    // Wait for the job to complete... - get the resulting report
+    let mut report = match job.wait().await?.result::<ReconciliationReport>()? {
+        Some(report) => report,
+        None => {
+            return Err(Error::Call(format!(
+                "The scheduler did not return a reconciliation report for {}",
+                project
+            )))
+        }
+    };
+
+    // also ask the account agent to check its groups and home directories,
+    // if it supports this (not all account agents do)
+    if let Some(account) = agent::account(AGENT_WAIT_TIME).await {
+        let job = Job::parse(&format!("{}.{} {}", me, account.name(), instruction), false)?
+            .put(&account)
+            .await?;
+
+        match job.wait().await {
+            Ok(job) => match job.result::<ReconciliationReport>() {
+                Ok(Some(account_report)) => report.merge(&account_report),
+                Ok(None) => {}
+                Err(e) => {
+                    tracing::warn!(
+                        "Could not reconcile {} in the account agent: {}",
+                        project,
+                        e
+                    );
+                }
+            },
+            Err(e) => {
+                tracing::warn!(
+                    "Could not reconcile {} in the account agent: {}",
+                    project,
+                    e
+                );
+            }
+        }
+    }
+
+    // raise a health warning for drift that was not repaired, so that it
+    // is reported by health checks until a later reconcile finds it fixed
+    let key = format!("reconcile:{}", project);
+
+    match report.has_drift() && !report.repaired {
+        true => health::set_warning(
+            &key,
+            &format!(
+                "Reconciliation drift in {}: {}",
+                project,
+                report
+                    .to_string()
+                    .lines()
+                    .filter(|l| l.starts_with("  "))
+                    .map(|l| l.trim())
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            ),
+        ),
+        false => health::clear_warning(&key),
+    }
+
+    Ok(report)
+}
+
+async fn submit_job(me: &str, user: &UserIdentifier, script: &str) -> Result<String, Error> {
+    // get the mapping for this user
+    let mapping = get_user_mapping(me, user).await?;
+
+    // find the scheduler agent
+    let scheduler = match agent::scheduler(AGENT_WAIT_TIME).await {
+        Some(scheduler) => scheduler,
+        None => {
+            tracing::error!("No scheduler agent found");
+            return Err(Error::MissingAgent(
+                "Cannot run the job because there is no scheduler agent".to_string(),
+            ));
+        }
+    };
+
+    // ask the scheduler to submit the script as the user
+    let job = Job::parse(
+        &format!(
+            "{}.{} submit_local_job {} {}",
+            me,
+            scheduler.name(),
+            mapping,
+            script
+        ),
+        false,
+    )?;
+
+    let job = job.put(&scheduler).await?;
+
+    // Wait for the job to complete... - get the resulting job id
+    match job.wait().await?.result::<String>()? {
+        Some(job_id) => Ok(job_id),
+        None => Err(Error::Call(format!(
+            "The scheduler did not return a job id for {} submitted by {}",
+            script, user
+        ))),
+    }
+}
+
 pub async fn set_project_limit(
     me: &str,
     project: &ProjectIdentifier,
@@ -1366,6 +2051,35 @@ pub async fn set_project_limit(
     Ok(limit)
 }
 
+///
+/// Return an error if setting the limit of `project` to `limit` would
+/// take the total of the limits of its portal's projects over the
+/// portal's maximum allocation. Limits are in node hours, so the
+/// maximum allocation must be too
+///
+async fn check_portal_allocation(
+    me: &str,
+    project: &ProjectIdentifier,
+    limit: &Usage,
+) -> Result<(), Error> {
+    let quota = portalquota::quota(&project.portal());
+
+    if quota.max_allocation().is_none() {
+        return Ok(());
+    }
+
+    let mut allocations = vec![Allocation::from_size_and_units(limit.hours(), "NHR")?];
+
+    for mapping in get_projects(me, &project.portal_identifier()).await? {
+        if mapping.project() != project {
+            let other = get_project_limit(me, mapping.project()).await?;
+            allocations.push(Allocation::from_size_and_units(other.hours(), "NHR")?);
+        }
+    }
+
+    quota.check_allocations(&allocations)
+}
+
 async fn clear_project_quota(
     me: &str,
     project: &ProjectIdentifier,
@@ -1887,6 +2601,162 @@ async fn block_user_on_cluster(me: &str, user: &UserIdentifier) -> Result<UserMa
     }
 }
 
+///
+/// Activate the passed (staged) user in the account agent, so that
+/// they can log in, and then create their directories and add them
+/// to the job scheduler
+///
+async fn activate_user_on_cluster(me: &str, user: &UserIdentifier) -> Result<UserMapping, Error> {
+    tracing::info!("Activating user on cluster: {}", user);
+
+    match agent::account(AGENT_WAIT_TIME).await {
+        Some(account) => {
+            let job = Job::parse(
+                &format!("{}.{} activate_user {}", me, account.name(), user),
+                false,
+            )?
+            .put(&account)
+            .await?;
+
+            let result = job.wait().await?.result::<UserMapping>()?;
+
+            match result {
+                Some(mapping) => {
+                    tracing::info!("User activated: {:?}", mapping);
+
+                    // the user can only now be found on the cluster, so
+                    // can have their directories and scheduler association
+                    provision_user(me, user, &mapping).await?;
+
+                    Ok(mapping)
+                }
+                None => Err(Error::Call(
+                    format!("Error activating user: {:?}", job).to_string(),
+                )),
+            }
+        }
+        None => Err(Error::MissingAgent(
+            "Cannot run the job because there is no account agent".to_string(),
+        )),
+    }
+}
+
+///
+/// Update the details (e.g. email address and login shell) of the
+/// passed user's account in the account agent
+///
+async fn update_user_on_cluster(
+    me: &str,
+    user: &UserIdentifier,
+    details: &UserDetails,
+) -> Result<UserMapping, Error> {
+    tracing::info!("Updating user on cluster: {}", user);
+
+    match agent::account(AGENT_WAIT_TIME).await {
+        Some(account) => {
+            let job = Job::parse(
+                &format!("{}.{} update_user {} {}", me, account.name(), user, details),
+                false,
+            )?
+            .put(&account)
+            .await?;
+
+            let result = job.wait().await?.result::<UserMapping>()?;
+
+            match result {
+                Some(mapping) => {
+                    tracing::info!("User updated: {:?}", mapping);
+                    Ok(mapping)
+                }
+                None => Err(Error::Call(
+                    format!("Error updating user: {:?}", job).to_string(),
+                )),
+            }
+        }
+        None => Err(Error::MissingAgent(
+            "Cannot run the job because there is no account agent".to_string(),
+        )),
+    }
+}
+
+///
+/// Issue a new OTP token to the passed user via the account agent,
+/// returning the token's `otpauth://` URI
+///
+async fn add_user_otp_token_on_cluster(me: &str, user: &UserIdentifier) -> Result<String, Error> {
+    match agent::account(AGENT_WAIT_TIME).await {
+        Some(account) => {
+            let job = Job::parse(
+                &format!("{}.{} add_user_otp_token {}", me, account.name(), user),
+                false,
+            )?
+            .put(&account)
+            .await?;
+
+            let result = job.wait().await?.result::<String>()?;
+
+            match result {
+                Some(uri) => Ok(uri),
+                None => Err(Error::Call(
+                    format!("Error adding OTP token: {:?}", job).to_string(),
+                )),
+            }
+        }
+        None => Err(Error::MissingAgent(
+            "Cannot run the job because there is no account agent".to_string(),
+        )),
+    }
+}
+
+///
+/// Pass the passed instruction to the account agent, returning the
+/// list of strings (e.g. a user's SSH public keys or secondary groups)
+/// that it returns
+///
+async fn run_account_job(me: &str, instruction: &str) -> Result<Vec<String>, Error> {
+    match agent::account(AGENT_WAIT_TIME).await {
+        Some(account) => {
+            let job = Job::parse(&format!("{}.{} {}", me, account.name(), instruction), false)?
+                .put(&account)
+                .await?;
+
+            let result = job.wait().await?.result::<Vec<String>>()?;
+
+            match result {
+                Some(values) => Ok(values),
+                None => Err(Error::Call(
+                    format!("Error running account job: {:?}", job).to_string(),
+                )),
+            }
+        }
+        None => Err(Error::MissingAgent(
+            "Cannot run the job because there is no account agent".to_string(),
+        )),
+    }
+}
+
+async fn get_protection_on_cluster(me: &str, instruction: &str) -> Result<ProtectionStatus, Error> {
+    match agent::account(AGENT_WAIT_TIME).await {
+        Some(account) => {
+            let job = Job::parse(&format!("{}.{} {}", me, account.name(), instruction), false)?
+                .put(&account)
+                .await?;
+
+            let result = job.wait().await?.result::<ProtectionStatus>()?;
+
+            match result {
+                Some(status) => Ok(status),
+                None => Err(Error::Call(
+                    format!("Error running account job: {:?}", job).to_string(),
+                )),
+            }
+        }
+        None => Err(Error::MissingAgent(
+            "Cannot run the job because there is no account agent".to_string(),
+        )),
+    }
+}
+
 async fn block_project_on_cluster(
     me: &str,
     project: &ProjectIdentifier,
@@ -2159,3 +3029,164 @@ async fn get_user_dirs(me: &str, mapping: &UserMapping) -> Result<Vec<String>, E
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use templemeads::mock::{self, MockAgent};
+
+    // the mocks are shared by the whole process, so only one test
+    // can use them at a time
+    static MOCKS: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    #[tokio::test]
+    async fn test_staged_user_provisioned_on_activation() {
+        let _guard = MOCKS.lock().await;
+
+        mock::host("cluster", &agent::Type::Instance)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot host mocks: {}", e));
+
+        let account = MockAgent::account("freeipa", "test")
+            .unwrap_or_else(|e| unreachable!("Cannot create mock: {}", e))
+            .stage_users()
+            .register()
+            .await;
+
+        let filesystem = MockAgent::filesystem("filesystem", "test")
+            .unwrap_or_else(|e| unreachable!("Cannot create mock: {}", e))
+            .register()
+            .await;
+
+        let scheduler = MockAgent::scheduler("slurm", "test")
+            .unwrap_or_else(|e| unreachable!("Cannot create mock: {}", e))
+            .register()
+            .await;
+
+        let user = UserIdentifier::parse("alice.project.portal")
+            .unwrap_or_else(|e| unreachable!("Cannot parse user: {}", e));
+
+        // a staged user gets an account, but nothing else
+        let mapping = add_user_to_cluster("cluster", &user)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add user: {}", e));
+
+        assert!(!is_existing_user("cluster", &user)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot check user: {}", e)));
+        assert!(mock::calls(&filesystem).await.is_empty());
+        assert!(mock::calls(&scheduler).await.is_empty());
+
+        // as is a staged user added in a batch
+        let bob = UserIdentifier::parse("bob.project.portal")
+            .unwrap_or_else(|e| unreachable!("Cannot parse user: {}", e));
+
+        let (result, skipped) = add_users_to_cluster("cluster", std::slice::from_ref(&bob))
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add users: {}", e));
+
+        assert!(result.is_complete());
+        assert!(skipped.is_empty());
+        assert_eq!(result.succeeded.len(), 1);
+        assert!(mock::calls(&filesystem).await.is_empty());
+        assert!(mock::calls(&scheduler).await.is_empty());
+
+        // activating them creates their directories and adds them
+        // to the scheduler
+        let activated = activate_user_on_cluster("cluster", &user)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot activate user: {}", e));
+
+        assert_eq!(activated, mapping);
+        assert!(is_existing_user("cluster", &user)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot check user: {}", e)));
+        assert_eq!(
+            mock::calls(&filesystem).await,
+            vec![
+                format!("add_local_user {}", mapping),
+                format!("get_local_home_dir {}", mapping),
+            ]
+        );
+        assert_eq!(
+            mock::calls(&scheduler).await,
+            vec![format!("add_local_user {}", mapping)]
+        );
+        assert!(mock::calls(&account)
+            .await
+            .contains(&"update_homedir alice.project.portal /home/alice.project".to_owned()));
+
+        mock::unregister(&account).await;
+        mock::unregister(&filesystem).await;
+        mock::unregister(&scheduler).await;
+    }
+
+    #[tokio::test]
+    async fn test_add_users_with_failure() {
+        let _guard = MOCKS.lock().await;
+
+        mock::host("cluster", &agent::Type::Instance)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot host mocks: {}", e));
+
+        let account = MockAgent::account("freeipa", "test")
+            .unwrap_or_else(|e| unreachable!("Cannot create mock: {}", e))
+            .register()
+            .await;
+
+        // the directories of the first user cannot be created
+        let filesystem = MockAgent::filesystem("filesystem", "test")
+            .unwrap_or_else(|e| unreachable!("Cannot create mock: {}", e))
+            .fail_times("add_local_user", 1, "No space left on device")
+            .register()
+            .await;
+
+        let scheduler = MockAgent::scheduler("slurm", "test")
+            .unwrap_or_else(|e| unreachable!("Cannot create mock: {}", e))
+            .register()
+            .await;
+
+        let users = ["carol.project.portal", "dave.project.portal"]
+            .iter()
+            .map(|user| {
+                UserIdentifier::parse(user)
+                    .unwrap_or_else(|e| unreachable!("Cannot parse user: {}", e))
+            })
+            .collect::<Vec<_>>();
+
+        let (result, skipped) = add_users_to_cluster("cluster", &users)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add users: {}", e));
+
+        assert!(skipped.is_empty());
+        assert_eq!(result.failed_users(), vec![users[0].clone()]);
+        assert_eq!(
+            result
+                .succeeded
+                .iter()
+                .map(|mapping| mapping.user().clone())
+                .collect::<Vec<_>>(),
+            vec![users[1].clone()]
+        );
+
+        // the failed user is not sent to the scheduler, and their new
+        // account is removed again
+        assert_eq!(
+            mock::calls(&scheduler).await,
+            vec![format!("add_local_user {}", result.succeeded[0])]
+        );
+        assert!(mock::calls(&account)
+            .await
+            .contains(&"remove_user carol.project.portal".to_owned()));
+        assert!(!is_existing_user("cluster", &users[0])
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot check user: {}", e)));
+        assert!(is_existing_user("cluster", &users[1])
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot check user: {}", e)));
+
+        mock::unregister(&account).await;
+        mock::unregister(&filesystem).await;
+        mock::unregister(&scheduler).await;
+    }
+}
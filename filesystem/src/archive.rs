@@ -0,0 +1,304 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Archival of removed project directories to S3-compatible object storage.
+//!
+//! If the filesystem config has an `[archive]` section, then every project
+//! volume with `archive_on_removal = true` has each of the project's
+//! directories tarred, checksummed and uploaded before the directories are
+//! recycled. The uploaded objects are returned in the manifest that is the
+//! result of `remove_local_project`.
+//!
+//! The `tar`, `sha256sum` and `aws` commands are configurable so that they
+//! can be prefixed with e.g. `"sudo"`, or replaced by compatible tools.
+//!
+//! # TOML configuration example
+//!
+//! ```toml
+//! [archive]
+//! destination  = "s3://openportal-archive/brics"
+//! endpoint_url = "https://s3.example.ac.uk"
+//! staging_dir  = "/scratch/openportal-archive"
+//! tar          = "sudo tar"
+//! ```
+
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use templemeads::exec;
+use templemeads::grammar::NamedType;
+use templemeads::Error;
+
+/// Tarring, checksumming and uploading a large project can take many
+/// hours, so these commands are given much longer than the default
+const ARCHIVE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+fn default_staging_dir() -> PathBuf {
+    std::env::temp_dir()
+}
+
+fn default_tar_command() -> String {
+    "tar".to_string()
+}
+
+fn default_sha256sum_command() -> String {
+    "sha256sum".to_string()
+}
+
+fn default_aws_command() -> String {
+    "aws".to_string()
+}
+
+/// Configuration for archiving removed project directories.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveConfig {
+    /// The S3 URL under which archives are uploaded, e.g. `s3://bucket/prefix`
+    destination: String,
+
+    /// Optional endpoint for S3-compatible storage that is not AWS
+    endpoint_url: Option<String>,
+
+    /// Local directory in which archives are written before upload
+    /// (default: the system temporary directory)
+    #[serde(default = "default_staging_dir")]
+    staging_dir: PathBuf,
+
+    /// The `tar` command (default: `"tar"`).
+    #[serde(default = "default_tar_command")]
+    tar: String,
+
+    /// The `sha256sum` command (default: `"sha256sum"`).
+    #[serde(default = "default_sha256sum_command")]
+    sha256sum: String,
+
+    /// The `aws` command (default: `"aws"`).
+    #[serde(default = "default_aws_command")]
+    aws: String,
+}
+
+/// A directory that was archived to object storage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedDirectory {
+    /// The directory that was archived
+    pub path: String,
+
+    /// The URL of the uploaded archive
+    pub object: String,
+
+    /// The size of the archive in bytes
+    pub size: u64,
+
+    /// The SHA-256 checksum of the archive
+    pub sha256: String,
+}
+
+/// Manifest of everything preserved when a project was removed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RemovalManifest {
+    /// Snapshots taken before the directories were recycled
+    pub snapshots: Vec<String>,
+
+    /// Archives uploaded before the directories were recycled
+    pub archives: Vec<ArchivedDirectory>,
+}
+
+impl NamedType for RemovalManifest {
+    fn type_name() -> &'static str {
+        "RemovalManifest"
+    }
+}
+
+impl RemovalManifest {
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty() && self.archives.is_empty()
+    }
+}
+
+impl ArchiveConfig {
+    ///
+    /// Validate the configuration
+    ///
+    pub fn validate(&self) -> Result<(), Error> {
+        if !self.destination.starts_with("s3://") {
+            return Err(Error::Misconfigured(format!(
+                "Archive destination must be an s3:// URL, not '{}'",
+                self.destination
+            )));
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Tar, checksum and upload the passed directory, returning the
+    /// details of the uploaded archive. The local copy of the archive
+    /// is always removed afterwards.
+    ///
+    pub async fn archive_dir(
+        &self,
+        path: &Path,
+        project: &str,
+    ) -> Result<ArchivedDirectory, Error> {
+        let (parent, dirname) = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(dirname)) => (parent, dirname.to_string_lossy()),
+            _ => {
+                return Err(Error::InvalidState(format!(
+                    "Cannot archive '{}' as it has no parent directory",
+                    path.to_string_lossy()
+                )))
+            }
+        };
+
+        let filename = archive_name(project, path);
+        let archive = self.staging_dir.join(&filename);
+
+        let result = self.tar_and_upload(&archive, parent, &dirname).await;
+
+        if let Err(e) = tokio::fs::remove_file(&archive).await {
+            tracing::warn!(
+                "Could not remove staged archive '{}': {}",
+                archive.to_string_lossy(),
+                e
+            );
+        }
+
+        let (size, sha256) = result?;
+
+        Ok(ArchivedDirectory {
+            path: path.to_string_lossy().to_string(),
+            object: self.object_url(&filename),
+            size,
+            sha256,
+        })
+    }
+
+    fn object_url(&self, filename: &str) -> String {
+        format!("{}/{}", self.destination.trim_end_matches('/'), filename)
+    }
+
+    async fn tar_and_upload(
+        &self,
+        archive: &Path,
+        parent: &Path,
+        dirname: &str,
+    ) -> Result<(u64, String), Error> {
+        let archive_str = archive.to_string_lossy();
+        let parent_str = parent.to_string_lossy();
+
+        tokio::fs::create_dir_all(&self.staging_dir).await?;
+
+        run_command(
+            &self.tar,
+            &["-czf", &archive_str, "-C", &parent_str, dirname],
+        )
+        .await?;
+
+        let size = tokio::fs::metadata(archive).await?.len();
+
+        let sha256 = run_command(&self.sha256sum, &[&archive_str])
+            .await?
+            .split_whitespace()
+            .next()
+            .map(|sum| sum.to_string())
+            .ok_or_else(|| {
+                Error::Parse(format!("Could not read the checksum of '{}'", archive_str))
+            })?;
+
+        let filename = archive
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let object = self.object_url(&filename);
+        let metadata = format!("sha256={}", sha256);
+
+        let mut args = vec![
+            "s3",
+            "cp",
+            archive_str.as_ref(),
+            &object,
+            "--metadata",
+            &metadata,
+        ];
+
+        if let Some(endpoint_url) = &self.endpoint_url {
+            args.push("--endpoint-url");
+            args.push(endpoint_url);
+        }
+
+        run_command(&self.aws, &args).await?;
+
+        tracing::info!("Archived '{}/{}' to {}", parent_str, dirname, object);
+
+        Ok((size, sha256))
+    }
+}
+
+///
+/// Return the filename of the archive of the passed project directory.
+/// The directory path is included so that the archives of directories
+/// on different roots do not collide.
+///
+fn archive_name(project: &str, path: &Path) -> String {
+    let sanitise = |s: &str| -> String {
+        s.chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    };
+
+    format!(
+        "{}-{}-{}.tar.gz",
+        sanitise(project),
+        sanitise(path.to_string_lossy().trim_matches('/')),
+        Utc::now().format("%Y%m%d%H%M%S")
+    )
+}
+
+/// Run an external command (which may include a multi-token prefix such as
+/// `"sudo"`) with the given extra arguments, returning the captured stdout.
+async fn run_command(program: &str, args: &[&str]) -> Result<String, Error> {
+    if program.trim().is_empty() {
+        return Err(Error::Misconfigured(format!(
+            "Archive command is empty: '{}'",
+            program
+        )));
+    }
+
+    let command = exec::Command::parse(program).args(args);
+    tracing::info!("Archive executing: {}", command);
+
+    command
+        .timeout(ARCHIVE_TIMEOUT)
+        .run()
+        .await
+        .map_err(|failure| Error::Failed(failure.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_name() {
+        let name = archive_name("proj.brics", Path::new("/projects/proj"));
+        assert!(name.starts_with("proj_brics-projects_proj-"));
+        assert!(name.ends_with(".tar.gz"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = toml::from_str::<ArchiveConfig>("destination = \"s3://bucket/prefix\"");
+        assert!(matches!(config, Ok(config) if config.validate().is_ok()
+            && config.object_url("a.tar.gz") == "s3://bucket/prefix/a.tar.gz"));
+
+        let config = toml::from_str::<ArchiveConfig>("destination = \"/archive\"");
+        assert!(matches!(config, Ok(config) if config.validate().is_err()));
+    }
+}
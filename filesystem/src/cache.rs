@@ -3,17 +3,20 @@
 
 use anyhow::Result;
 use once_cell::sync::Lazy;
+use templemeads::grammar::{ProjectMapping, UserMapping};
 use templemeads::storage::QuotaLimit;
 use templemeads::Error;
 use tokio::sync::RwLock;
 
 use crate::volumeconfig::FilesystemConfig;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Default, Debug)]
 struct Database {
     filesystem_config: Option<FilesystemConfig>,
+    projects: HashMap<String, ProjectMapping>,
+    users: HashMap<String, UserMapping>,
 }
 
 impl Database {
@@ -23,6 +26,8 @@ impl Database {
     fn new() -> Self {
         Self {
             filesystem_config: None,
+            projects: HashMap::new(),
+            users: HashMap::new(),
         }
     }
 }
@@ -127,3 +132,59 @@ pub async fn set_filesystem_config(mut config: FilesystemConfig) -> Result<(), E
     cache.filesystem_config = Some(config);
     Ok(())
 }
+
+///
+/// Remember that the passed project has directories managed by this agent,
+/// so that its quotas can be monitored
+///
+pub async fn add_project(mapping: &ProjectMapping) {
+    let mut cache = CACHE.write().await;
+    cache
+        .projects
+        .insert(mapping.project().to_string(), mapping.clone());
+}
+
+///
+/// Forget the passed project, and all of its users
+///
+pub async fn remove_project(mapping: &ProjectMapping) {
+    let mut cache = CACHE.write().await;
+    cache.projects.remove(&mapping.project().to_string());
+    cache
+        .users
+        .retain(|_, user| user.project().project() != mapping.project());
+}
+
+///
+/// Remember that the passed user has directories managed by this agent
+///
+pub async fn add_user(mapping: &UserMapping) {
+    let mut cache = CACHE.write().await;
+    cache
+        .users
+        .insert(mapping.user().to_string(), mapping.clone());
+}
+
+///
+/// Forget the passed user
+///
+pub async fn remove_user(mapping: &UserMapping) {
+    let mut cache = CACHE.write().await;
+    cache.users.remove(&mapping.user().to_string());
+}
+
+///
+/// Return all of the projects with directories managed by this agent
+///
+pub async fn get_projects() -> Vec<ProjectMapping> {
+    let cache = CACHE.read().await;
+    cache.projects.values().cloned().collect()
+}
+
+///
+/// Return all of the users with directories managed by this agent
+///
+pub async fn get_users() -> Vec<UserMapping> {
+    let cache = CACHE.read().await;
+    cache.users.values().cloned().collect()
+}
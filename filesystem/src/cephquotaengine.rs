@@ -0,0 +1,450 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Concrete implementation of the CephFS quota engine.
+//!
+//! CephFS quotas are set per directory tree using the
+//! `ceph.quota.max_bytes` and `ceph.quota.max_files` extended attributes,
+//! and usage is read back from the recursive `ceph.dir.rbytes` attribute.
+//! Quotas are therefore applied to every directory of the user or
+//! project on the volume (one per root), and the reported usage is the
+//! sum across those directories.
+//!
+//! Both commands are configurable so that they can be prefixed with
+//! e.g. `"sudo"` or `"docker exec slurmctld"`.
+//!
+//! # TOML configuration example
+//!
+//! ```toml
+//! [quota_engines.cephquota]
+//! type     = "ceph"
+//! setfattr = "sudo setfattr"
+//! getfattr = "sudo getfattr"
+//! ```
+
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use templemeads::exec;
+use templemeads::grammar::{ProjectMapping, UserMapping};
+use templemeads::job::assert_not_expired;
+use templemeads::storage::{Quota, QuotaLimit, StorageSize, StorageUsage, Volume};
+use templemeads::Error;
+
+use crate::volumeconfig::{PathConfig, ProjectVolumeConfig, UserVolumeConfig};
+
+const MAX_BYTES: &str = "ceph.quota.max_bytes";
+const MAX_FILES: &str = "ceph.quota.max_files";
+const RBYTES: &str = "ceph.dir.rbytes";
+
+fn default_setfattr_command() -> String {
+    "setfattr".to_string()
+}
+
+fn default_getfattr_command() -> String {
+    "getfattr".to_string()
+}
+
+/// Configuration for the CephFS quota engine.
+///
+/// Both `setfattr` and `getfattr` default to the standard system binaries
+/// but can be overridden with e.g. `"sudo setfattr"`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CephQuotaEngineConfig {
+    /// The `setfattr` command (default: `"setfattr"`).
+    #[serde(default = "default_setfattr_command")]
+    setfattr: String,
+
+    /// The `getfattr` command (default: `"getfattr"`).
+    #[serde(default = "default_getfattr_command")]
+    getfattr: String,
+}
+
+/// CephFS quota engine that calls `setfattr` / `getfattr`.
+pub struct CephEngine {
+    config: CephQuotaEngineConfig,
+}
+
+impl CephEngine {
+    pub fn new(config: CephQuotaEngineConfig) -> Result<Self, Error> {
+        if config.setfattr.trim().is_empty() || config.getfattr.trim().is_empty() {
+            return Err(Error::Misconfigured(
+                "CephQuotaEngine requires non-empty 'setfattr' and 'getfattr' settings".to_string(),
+            ));
+        }
+        Ok(Self { config })
+    }
+
+    /// No-op initialisation — CephFS quotas require no engine-level setup.
+    pub async fn initialize(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Check that CephFS can be reached, by reading the recursive
+    /// usage of the passed path
+    pub async fn check(&self, path: &Path, expires: &chrono::DateTime<Utc>) -> Result<(), Error> {
+        let path = path.to_string_lossy();
+        self.run_command(
+            &self.config.getfattr,
+            &["--only-values", "-n", RBYTES, &path],
+            expires,
+        )
+        .await?;
+        Ok(())
+    }
+
+    // -----------------------------------------------------------------------
+    // Internal helpers
+    // -----------------------------------------------------------------------
+
+    /// Run an external command (which may include a multi-token prefix such as
+    /// `"sudo"`) with the given extra arguments.
+    ///
+    /// Returns the captured stdout on success, or an [`Error`] if the process
+    /// exits non-zero.
+    async fn run_command(
+        &self,
+        program: &str,
+        args: &[&str],
+        expires: &chrono::DateTime<Utc>,
+    ) -> Result<String, Error> {
+        assert_not_expired(expires)?;
+
+        if program.trim().is_empty() {
+            return Err(Error::Misconfigured(format!(
+                "Ceph quota command is empty: '{}'",
+                program
+            )));
+        }
+
+        let command = exec::Command::parse(program).args(args);
+        tracing::info!("CephQuotaEngine executing: {}", command);
+
+        command
+            .run()
+            .await
+            .map_err(|failure| Error::Failed(failure.to_string()))
+    }
+
+    /// Convert a [`QuotaLimit`] to the value of `ceph.quota.max_bytes`.
+    ///
+    /// Returns `0` for [`QuotaLimit::Unlimited`], which removes the quota.
+    fn limit_to_bytes(limit: &QuotaLimit) -> u64 {
+        match limit {
+            QuotaLimit::Unlimited => 0,
+            QuotaLimit::Limited(size) => size.as_bytes(),
+        }
+    }
+
+    /// Parse the output of `getfattr --only-values`, which is the bare
+    /// attribute value. A missing quota attribute is reported by CephFS
+    /// as `0`, which means no limit.
+    fn parse_attribute(output: &str, name: &str) -> Result<u64, Error> {
+        let value = output.trim().trim_matches('"');
+
+        value.parse::<u64>().map_err(|e| {
+            Error::Parse(format!(
+                "Could not parse value '{}' of extended attribute {}: {}",
+                value, name, e
+            ))
+        })
+    }
+
+    async fn set_attribute(
+        &self,
+        path: &Path,
+        name: &str,
+        value: u64,
+        expires: &chrono::DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let path = path.to_string_lossy();
+        let value = value.to_string();
+
+        // setfattr -n <name> -v <value> <dir>
+        self.run_command(
+            &self.config.setfattr,
+            &["-n", name, "-v", &value, &path],
+            expires,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_attribute(
+        &self,
+        path: &Path,
+        name: &str,
+        expires: &chrono::DateTime<Utc>,
+    ) -> Result<u64, Error> {
+        let path = path.to_string_lossy();
+
+        // getfattr --only-values -n <name> <dir>
+        let output = self
+            .run_command(
+                &self.config.getfattr,
+                &["--only-values", "-n", name, &path],
+                expires,
+            )
+            .await?;
+
+        Self::parse_attribute(&output, name)
+    }
+
+    /// Set the quota attributes on all of the passed directories
+    async fn set_quota(
+        &self,
+        paths: &[PathBuf],
+        limit: &QuotaLimit,
+        inode_limit: u64,
+        expires: &chrono::DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let bytes = Self::limit_to_bytes(limit);
+
+        for path in paths {
+            self.set_attribute(path, MAX_BYTES, bytes, expires).await?;
+            self.set_attribute(path, MAX_FILES, inode_limit, expires)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Read the quota back from the passed directories. The limit is
+    /// taken from the first directory (all are set to the same value),
+    /// while the usage is summed across all of them.
+    async fn get_quota(
+        &self,
+        paths: &[PathBuf],
+        expires: &chrono::DateTime<Utc>,
+    ) -> Result<Quota, Error> {
+        let Some(first) = paths.first() else {
+            return Err(Error::Misconfigured(
+                "CephQuotaEngine needs at least one directory to read a quota".to_string(),
+            ));
+        };
+
+        let max_bytes = self.get_attribute(first, MAX_BYTES, expires).await?;
+
+        let mut used_bytes: u64 = 0;
+
+        for path in paths {
+            used_bytes =
+                used_bytes.saturating_add(self.get_attribute(path, RBYTES, expires).await?);
+        }
+
+        let limit = if max_bytes == 0 {
+            QuotaLimit::Unlimited
+        } else {
+            QuotaLimit::Limited(StorageSize::from_bytes(max_bytes))
+        };
+
+        Ok(Quota::with_usage(
+            limit,
+            StorageUsage::new(StorageSize::from_bytes(used_bytes)),
+        ))
+    }
+
+    fn user_paths(
+        mapping: &UserMapping,
+        path_configs: &[PathConfig],
+    ) -> Result<Vec<PathBuf>, Error> {
+        path_configs
+            .iter()
+            .map(|path_config| path_config.path(mapping.clone().into()))
+            .collect()
+    }
+
+    fn project_paths(
+        mapping: &ProjectMapping,
+        path_configs: &[PathConfig],
+    ) -> Result<Vec<PathBuf>, Error> {
+        path_configs
+            .iter()
+            .map(|path_config| path_config.project_path(mapping))
+            .collect()
+    }
+
+    // -----------------------------------------------------------------------
+    // User quota methods
+    // -----------------------------------------------------------------------
+
+    pub async fn set_user_quota(
+        &self,
+        mapping: &UserMapping,
+        volume: &Volume,
+        volume_config: &UserVolumeConfig,
+        limit: &QuotaLimit,
+        expires: &chrono::DateTime<Utc>,
+    ) -> Result<Quota, Error> {
+        let user = mapping.local_user();
+
+        // Validate against any configured maximum.
+        if let Some(max_quota) = volume_config.max_quota() {
+            if limit > max_quota {
+                return Err(Error::Failed(format!(
+                    "Requested quota limit ({}) exceeds maximum allowed quota ({}) for user {} on volume {}",
+                    limit, max_quota, user, volume
+                )));
+            }
+        }
+
+        let inode_limit = volume_config.default_inode_limit().unwrap_or(0);
+        let paths = Self::user_paths(mapping, &volume_config.path_configs())?;
+
+        tracing::info!(
+            "CephQuotaEngine::set_user_quota: user={}, volume={}, limit={}, inodes={}",
+            user,
+            volume,
+            limit,
+            inode_limit
+        );
+
+        self.set_quota(&paths, limit, inode_limit, expires).await?;
+
+        self.get_quota(&paths, expires).await
+    }
+
+    pub async fn get_user_quota(
+        &self,
+        mapping: &UserMapping,
+        volume: &Volume,
+        volume_config: &UserVolumeConfig,
+        expires: &chrono::DateTime<Utc>,
+    ) -> Result<Quota, Error> {
+        tracing::info!(
+            "CephQuotaEngine::get_user_quota: user={}, volume={}",
+            mapping.local_user(),
+            volume
+        );
+
+        let paths = Self::user_paths(mapping, &volume_config.path_configs())?;
+
+        self.get_quota(&paths, expires).await
+    }
+
+    pub async fn clear_user_quota(
+        &self,
+        mapping: &UserMapping,
+        volume: &Volume,
+        volume_config: &UserVolumeConfig,
+        expires: &chrono::DateTime<Utc>,
+    ) -> Result<(), Error> {
+        tracing::info!(
+            "CephQuotaEngine::clear_user_quota: user={}, volume={}",
+            mapping.local_user(),
+            volume
+        );
+
+        let paths = Self::user_paths(mapping, &volume_config.path_configs())?;
+
+        // a value of 0 removes the quota
+        self.set_quota(&paths, &QuotaLimit::Unlimited, 0, expires)
+            .await
+    }
+
+    // -----------------------------------------------------------------------
+    // Project quota methods
+    // -----------------------------------------------------------------------
+
+    pub async fn set_project_quota(
+        &self,
+        mapping: &ProjectMapping,
+        volume: &Volume,
+        volume_config: &ProjectVolumeConfig,
+        limit: &QuotaLimit,
+        expires: &chrono::DateTime<Utc>,
+    ) -> Result<Quota, Error> {
+        // Validate against any configured maximum.
+        if let Some(max_quota) = volume_config.max_quota() {
+            if limit > max_quota {
+                return Err(Error::Failed(format!(
+                    "Requested quota limit ({}) exceeds maximum allowed quota ({}) for project {} on volume {}",
+                    limit, max_quota, mapping.project(), volume
+                )));
+            }
+        }
+
+        let inode_limit = volume_config.default_inode_limit().unwrap_or(0);
+        let paths = Self::project_paths(mapping, &volume_config.path_configs())?;
+
+        tracing::info!(
+            "CephQuotaEngine::set_project_quota: project={}, volume={}, limit={}, inodes={}",
+            mapping.project(),
+            volume,
+            limit,
+            inode_limit
+        );
+
+        self.set_quota(&paths, limit, inode_limit, expires).await?;
+
+        self.get_quota(&paths, expires).await
+    }
+
+    pub async fn get_project_quota(
+        &self,
+        mapping: &ProjectMapping,
+        volume: &Volume,
+        volume_config: &ProjectVolumeConfig,
+        expires: &chrono::DateTime<Utc>,
+    ) -> Result<Quota, Error> {
+        tracing::info!(
+            "CephQuotaEngine::get_project_quota: project={}, volume={}",
+            mapping.project(),
+            volume
+        );
+
+        let paths = Self::project_paths(mapping, &volume_config.path_configs())?;
+
+        self.get_quota(&paths, expires).await
+    }
+
+    pub async fn clear_project_quota(
+        &self,
+        mapping: &ProjectMapping,
+        volume: &Volume,
+        volume_config: &ProjectVolumeConfig,
+        expires: &chrono::DateTime<Utc>,
+    ) -> Result<(), Error> {
+        tracing::info!(
+            "CephQuotaEngine::clear_project_quota: project={}, volume={}",
+            mapping.project(),
+            volume
+        );
+
+        let paths = Self::project_paths(mapping, &volume_config.path_configs())?;
+
+        // a value of 0 removes the quota
+        self.set_quota(&paths, &QuotaLimit::Unlimited, 0, expires)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_attribute() {
+        assert_eq!(
+            CephEngine::parse_attribute("1099511627776", MAX_BYTES).unwrap_or_default(),
+            1099511627776
+        );
+        assert_eq!(
+            CephEngine::parse_attribute("\"12345\"\n", RBYTES).unwrap_or_default(),
+            12345
+        );
+        assert!(CephEngine::parse_attribute("", MAX_BYTES).is_err());
+        assert!(CephEngine::parse_attribute("not-a-number", MAX_BYTES).is_err());
+    }
+
+    #[test]
+    fn test_limit_to_bytes() {
+        assert_eq!(CephEngine::limit_to_bytes(&QuotaLimit::Unlimited), 0);
+        assert_eq!(
+            CephEngine::limit_to_bytes(&QuotaLimit::Limited(StorageSize::from_bytes(4096))),
+            4096
+        );
+    }
+}
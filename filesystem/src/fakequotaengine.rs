@@ -26,11 +26,11 @@ use anyhow::Result;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
+use templemeads::exec;
 use templemeads::grammar::{ProjectMapping, UserMapping};
 use templemeads::job::assert_not_expired;
 use templemeads::storage::{Quota, QuotaLimit, StorageSize, StorageUsage, Volume};
 use templemeads::Error;
-use tokio::process::Command;
 
 use crate::volumeconfig::{ProjectVolumeConfig, UserVolumeConfig};
 
@@ -79,6 +79,17 @@ impl FakeEngine {
             })
     }
 
+    /// Check that the quota directory is still present
+    pub async fn check(&self) -> Result<(), Error> {
+        match tokio::fs::metadata(&self.config.quota_dir).await {
+            Ok(metadata) if metadata.is_dir() => Ok(()),
+            _ => Err(Error::Failed(format!(
+                "FakeQuotaEngine: quota_dir '{}' is missing",
+                self.config.quota_dir
+            ))),
+        }
+    }
+
     // -----------------------------------------------------------------------
     // Quota file helpers
     // -----------------------------------------------------------------------
@@ -152,28 +163,26 @@ impl FakeEngine {
     async fn du_bytes(&self, dir: &str, expires: &chrono::DateTime<Utc>) -> Result<u64, Error> {
         assert_not_expired(expires)?;
 
-        let parts: Vec<&str> = self.config.du.split_whitespace().collect();
-        let (prog, prefix_args) = match parts.split_first() {
-            Some(pair) => pair,
-            None => return Ok(0),
-        };
+        if self.config.du.trim().is_empty() {
+            return Ok(0);
+        }
 
         tracing::debug!("FakeQuotaEngine: {} -sk {}", self.config.du, dir);
 
-        let output = Command::new(prog)
-            .args(prefix_args)
+        let output = exec::Command::parse(&self.config.du)
             .args(["-sk", dir])
             .output()
             .await
-            .map_err(|e| Error::Failed(format!("du failed on '{}': {}", dir, e)))?;
+            .map_err(|failure| Error::Failed(format!("du failed on '{}': {}", dir, failure)))?;
 
-        if !output.status.success() {
+        if !output.success() {
             // Treat failures (e.g. directory not yet created) as zero usage.
             return Ok(0);
         }
 
         // `du -sk` output: "<kb>\t<path>"
-        let kb: u64 = String::from_utf8_lossy(&output.stdout)
+        let kb: u64 = output
+            .stdout
             .split_whitespace()
             .next()
             .and_then(|s| s.parse().ok())
@@ -3,8 +3,14 @@
 
 use anyhow::{Context, Result};
 use once_cell::sync::{Lazy, OnceCell};
+use templemeads::dryrun;
+use templemeads::exec;
 use templemeads::Error;
 
+use crate::volumeconfig::AclFormat;
+
+use std::cell::RefCell;
+use std::future::Future;
 use std::os::unix::fs::MetadataExt;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
@@ -46,6 +52,84 @@ fn get_exec_prefix() -> Option<&'static [String]> {
     EXEC_PREFIX.get().and_then(|p| p.as_deref())
 }
 
+///
+/// Whether the agent is in report-only mode. In this mode, every
+/// operation that would change the filesystem or a quota is recorded
+/// instead of being performed, so that new configs can be checked
+/// safely against production filesystems.
+///
+static REPORT_ONLY: OnceCell<bool> = OnceCell::new();
+
+tokio::task_local! {
+    /// The operations recorded by the current job in report-only mode
+    static OPERATIONS: RefCell<Vec<String>>;
+}
+
+///
+/// Configure whether or not the agent is in report-only mode.
+///
+/// Must be called once before any filesystem operations are performed.
+///
+pub fn set_report_only(report_only: bool) -> Result<()> {
+    REPORT_ONLY
+        .set(report_only)
+        .map_err(|_| anyhow::anyhow!("report-only has already been set"))
+}
+
+/// Return whether the agent is in report-only mode
+pub fn is_report_only() -> bool {
+    REPORT_ONLY.get().copied().unwrap_or(false)
+}
+
+///
+/// Return whether operations are being recorded rather than performed,
+/// either because the agent is in report-only mode or because the
+/// current job is a dry run
+///
+pub fn is_recording() -> bool {
+    is_report_only() || dryrun::is_dry_run()
+}
+
+///
+/// Record an operation that would change the filesystem or a quota.
+/// Returns true if the agent is in report-only mode, or the current job
+/// is a dry run, in which case the caller must skip the operation.
+///
+pub fn report_operation(operation: String) -> bool {
+    if dryrun::record(&operation) && !is_report_only() {
+        return true;
+    }
+
+    if !is_report_only() {
+        return false;
+    }
+
+    tracing::info!("Report-only: would {}", operation);
+
+    if OPERATIONS
+        .try_with(|operations| operations.borrow_mut().push(operation))
+        .is_err()
+    {
+        tracing::warn!("Report-only operation recorded outside of a job");
+    }
+
+    true
+}
+
+///
+/// Run the passed future, returning its output together with all of
+/// the operations that it recorded in report-only mode
+///
+pub async fn record_operations<F: Future>(future: F) -> (F::Output, Vec<String>) {
+    OPERATIONS
+        .scope(RefCell::new(Vec::new()), async move {
+            let output = future.await;
+            let operations = OPERATIONS.with(|operations| operations.take());
+            (output, operations)
+        })
+        .await
+}
+
 ///
 /// Run an external command built from a pre-tokenised prefix plus
 /// additional arguments.  Returns (exit_code, stdout, stderr).
@@ -57,22 +141,13 @@ async fn run_remote(prefix: &[String], args: &[&str]) -> Result<(i32, String, St
 
     tracing::debug!("Remote: {} {}", prefix.join(" "), args.join(" "));
 
-    let mut cmd = tokio::process::Command::new(&prefix[0]);
-    for p in &prefix[1..] {
-        cmd.arg(p);
-    }
-    for a in args {
-        cmd.arg(a);
-    }
-
-    let output = cmd
+    let output = exec::Command::from_parts(prefix)
+        .args(args)
         .output()
         .await
-        .map_err(|e| Error::State(format!("Failed to spawn '{}': {}", prefix.join(" "), e)))?;
+        .map_err(|failure| Error::State(failure.to_string()))?;
 
-    let exit_code = output.status.code().unwrap_or(-1);
-    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
-    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    let (exit_code, stdout, stderr) = (output.code, output.stdout, output.stderr);
 
     tracing::debug!(
         "Remote exit_code={}, stdout={:?}, stderr={:?}",
@@ -207,6 +282,20 @@ pub async fn create_dir(
     // convert the permissions into a u32
     let permissions = clean_and_check_permissions(permissions).await?;
 
+    if is_recording() {
+        if !dir_exists(&path).await? {
+            report_operation(format!(
+                "create directory '{}' owned by {}:{} with permissions {:04o}",
+                path.to_string_lossy(),
+                username,
+                groupname,
+                permissions
+            ));
+        }
+
+        return Ok(());
+    }
+
     tracing::info!(
         "Creating directory '{}' for user '{}' and group '{}' with permissions '{}'",
         path.to_string_lossy(),
@@ -423,7 +512,223 @@ async fn create_dir_remote(
     Ok(())
 }
 
+///
+/// Create a file with the passed contents, ownership and permissions.
+/// Nothing is done if the file already exists, so that files that
+/// have been edited are never overwritten.
+///
+pub async fn create_file(
+    path: &Path,
+    username: &str,
+    groupname: &str,
+    permissions: &str,
+    contents: &str,
+) -> Result<(), Error> {
+    let path = clean_and_check_path(path, false).await?;
+    let permissions = clean_and_check_permissions(permissions).await?;
+    let path_str = path.to_string_lossy();
+
+    let (exit_code, _, _) = run_command(&["test", "-e", &path_str]).await?;
+
+    if exit_code == 0 {
+        tracing::info!("File '{}' already exists - not overwriting", path_str);
+        return Ok(());
+    }
+
+    if report_operation(format!(
+        "create file '{}' owned by {}:{} with permissions {:04o}",
+        path_str, username, groupname, permissions
+    )) {
+        return Ok(());
+    }
+
+    tracing::info!(
+        "Creating file '{}' for user '{}' and group '{}' with permissions '{}'",
+        path_str,
+        username,
+        groupname,
+        unix_mode::to_string(permissions)
+    );
+
+    // the contents and path are passed as positional arguments so
+    // that they are never interpreted by the shell
+    let (exit_code, _, stderr) = run_command(&[
+        "sh",
+        "-c",
+        "printf '%s' \"$1\" > \"$2\"",
+        "sh",
+        contents,
+        &path_str,
+    ])
+    .await?;
+
+    if exit_code != 0 {
+        return Err(Error::State(format!(
+            "Writing '{}' failed: exit code {}, stderr: {}",
+            path_str, exit_code, stderr
+        )));
+    }
+
+    let owner = format!("{}:{}", username, groupname);
+    let (exit_code, _, stderr) = run_command(&["chown", &owner, &path_str]).await?;
+    if exit_code != 0 {
+        return Err(Error::State(format!(
+            "chown '{}' '{}' failed: exit code {}, stderr: {}",
+            owner, path_str, exit_code, stderr
+        )));
+    }
+
+    let mode_str = format!("{:04o}", permissions);
+    let (exit_code, _, stderr) = run_command(&["chmod", &mode_str, &path_str]).await?;
+    if exit_code != 0 {
+        return Err(Error::State(format!(
+            "chmod '{}' '{}' failed: exit code {}, stderr: {}",
+            mode_str, path_str, exit_code, stderr
+        )));
+    }
+
+    Ok(())
+}
+
+///
+/// Reset the ownership and permissions of an existing directory to
+/// those it would have been given when it was created. Returns false
+/// if the directory does not exist.
+///
+pub async fn repair_dir(
+    path: &Path,
+    username: &str,
+    groupname: &str,
+    permissions: &str,
+) -> Result<bool, Error> {
+    let path = clean_and_check_path(path, false).await?;
+    let permissions = clean_and_check_permissions(permissions).await?;
+
+    if !dir_exists(&path).await? {
+        return Ok(false);
+    }
+
+    if report_operation(format!(
+        "reset directory '{}' to be owned by {}:{} with permissions {:04o}",
+        path.to_string_lossy(),
+        username,
+        groupname,
+        permissions
+    )) {
+        return Ok(true);
+    }
+
+    tracing::info!(
+        "Repairing directory '{}' for user '{}' and group '{}' with permissions '{}'",
+        path.to_string_lossy(),
+        username,
+        groupname,
+        unix_mode::to_string(permissions)
+    );
+
+    let path_str = path.to_string_lossy();
+    let owner = format!("{}:{}", username, groupname);
+    let mode_str = format!("{:04o}", permissions);
+
+    let (exit_code, _, stderr) = run_command(&["chown", &owner, &path_str]).await?;
+    if exit_code != 0 {
+        return Err(Error::State(format!(
+            "chown '{}' '{}' failed: exit code {}, stderr: {}",
+            owner, path_str, exit_code, stderr
+        )));
+    }
+
+    let (exit_code, _, stderr) = run_command(&["chmod", &mode_str, &path_str]).await?;
+    if exit_code != 0 {
+        return Err(Error::State(format!(
+            "chmod '{}' '{}' failed: exit code {}, stderr: {}",
+            mode_str, path_str, exit_code, stderr
+        )));
+    }
+
+    Ok(true)
+}
+
+///
+/// Set the passed ACL entries on a directory, in addition to any
+/// ACL entries that it already has
+///
+pub async fn set_acls(path: &Path, acls: &[String], format: AclFormat) -> Result<(), Error> {
+    if acls.is_empty() {
+        return Ok(());
+    }
+
+    let path = clean_and_check_path(path, false).await?;
+    let path_str = path.to_string_lossy();
+
+    if report_operation(format!(
+        "set {:?} ACLs on '{}': {}",
+        format,
+        path_str,
+        acls.join(", ")
+    )) {
+        return Ok(());
+    }
+
+    tracing::info!("Setting ACLs on '{}': {}", path_str, acls.join(", "));
+
+    match format {
+        AclFormat::Posix => {
+            let entries = acls.join(",");
+            let (exit_code, _, stderr) =
+                run_command(&["setfacl", "-m", &entries, &path_str]).await?;
+
+            if exit_code != 0 {
+                return Err(Error::State(format!(
+                    "setfacl -m '{}' '{}' failed: exit code {}, stderr: {}",
+                    entries, path_str, exit_code, stderr
+                )));
+            }
+        }
+        AclFormat::Nfs4 => {
+            for acl in acls {
+                let (exit_code, _, stderr) =
+                    run_command(&["nfs4_setfacl", "-a", acl, &path_str]).await?;
+
+                if exit_code != 0 {
+                    return Err(Error::State(format!(
+                        "nfs4_setfacl -a '{}' '{}' failed: exit code {}, stderr: {}",
+                        acl, path_str, exit_code, stderr
+                    )));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Run a command, either remotely via the exec prefix or locally,
+/// returning the exit code, stdout and stderr.
+async fn run_command(args: &[&str]) -> Result<(i32, String, String), Error> {
+    if let Some(prefix) = get_exec_prefix() {
+        return run_remote(prefix, args).await;
+    }
+
+    let output = exec::Command::from_parts(args)
+        .output()
+        .await
+        .map_err(|failure| Error::Failed(failure.to_string()))?;
+
+    Ok((output.code, output.stdout, output.stderr))
+}
+
 pub async fn create_link(path: &Path, link: &Path) -> Result<(), Error> {
+    if is_recording() {
+        let link = clean_and_check_path(link, false).await?;
+        report_operation(format!(
+            "create link '{}' -> '{}'",
+            link.to_string_lossy(),
+            path.to_string_lossy()
+        ));
+        return Ok(());
+    }
+
     match get_exec_prefix() {
         Some(prefix) => {
             // In remote mode skip the local path-existence check; validate
@@ -448,6 +753,10 @@ pub async fn create_link(path: &Path, link: &Path) -> Result<(), Error> {
 pub async fn remove_link(link: &Path) -> Result<(), Error> {
     let link = clean_and_check_path(link, false).await?;
 
+    if report_operation(format!("remove link '{}'", link.to_string_lossy())) {
+        return Ok(());
+    }
+
     match get_exec_prefix() {
         Some(prefix) => {
             if !remote_is_symlink(prefix, &link).await? {
@@ -680,6 +989,85 @@ async fn restore_from_recycle_remote(
     Ok(())
 }
 
+///
+/// Return whether the passed directory exists
+///
+pub async fn dir_exists(path: &Path) -> Result<bool, Error> {
+    let path = clean_and_check_path(path, false).await?;
+
+    match get_exec_prefix() {
+        Some(prefix) => {
+            let path_str = path.to_string_lossy();
+            let (exit_code, _, _) = run_remote(prefix, &["test", "-d", &path_str]).await?;
+            Ok(exit_code == 0)
+        }
+        None => Ok(path.is_dir()),
+    }
+}
+
+///
+/// Return whether a filesystem is mounted at the passed path
+///
+pub async fn is_mount_point(path: &Path) -> Result<bool, Error> {
+    let path = clean_and_check_path(path, false).await?;
+
+    match get_exec_prefix() {
+        Some(prefix) => {
+            let path_str = path.to_string_lossy();
+            let (exit_code, _, _) = run_remote(prefix, &["mountpoint", "-q", &path_str]).await?;
+            Ok(exit_code == 0)
+        }
+        None => {
+            let metadata = match tokio::fs::metadata(&path).await {
+                Ok(metadata) => metadata,
+                Err(_) => return Ok(false),
+            };
+
+            // a mount point is on a different device to its parent,
+            // and the root directory is always a mount point
+            match path.parent() {
+                Some(parent) => {
+                    let parent_metadata = tokio::fs::metadata(parent).await?;
+                    Ok(metadata.dev() != parent_metadata.dev()
+                        || metadata.ino() == parent_metadata.ino())
+                }
+                None => Ok(true),
+            }
+        }
+    }
+}
+
+///
+/// Return whether new files can be written into the passed directory.
+/// This writes and then removes a small probe file, so that read-only
+/// and full filesystems are detected.
+///
+pub async fn is_writable(path: &Path) -> Result<bool, Error> {
+    let path = clean_and_check_path(path, false).await?;
+    let probe = path.join(".openportal-health-check");
+    let probe_str = probe.to_string_lossy();
+
+    match get_exec_prefix() {
+        Some(prefix) => {
+            let (exit_code, _, _) = run_remote(prefix, &["touch", &probe_str]).await?;
+
+            if exit_code != 0 {
+                return Ok(false);
+            }
+
+            let (exit_code, _, _) = run_remote(prefix, &["rm", "-f", &probe_str]).await?;
+            Ok(exit_code == 0)
+        }
+        None => {
+            if tokio::fs::write(&probe, b"").await.is_err() {
+                return Ok(false);
+            }
+
+            Ok(tokio::fs::remove_file(&probe).await.is_ok())
+        }
+    }
+}
+
 ///
 /// Move a directory to the .recycle subdirectory of its parent and update its timestamp.
 /// This is a non-destructive way to "remove" directories - they can be restored later
@@ -688,6 +1076,17 @@ async fn restore_from_recycle_remote(
 pub async fn recycle_dir(path: &Path) -> Result<(), Error> {
     let path = clean_and_check_path(path, false).await?;
 
+    if is_recording() {
+        if dir_exists(&path).await? {
+            report_operation(format!(
+                "move directory '{}' into .recycle",
+                path.to_string_lossy()
+            ));
+        }
+
+        return Ok(());
+    }
+
     match get_exec_prefix() {
         Some(prefix) => recycle_dir_remote(&path, prefix).await,
         None => recycle_dir_native(&path).await,
@@ -882,3 +1281,321 @@ async fn recycle_dir_remote(path: &Path, prefix: &[String]) -> Result<(), Error>
 
     Ok(())
 }
+
+///
+/// Purge entries in the .recycle directories below the passed root that
+/// were recycled more than `retention_days` days ago. The .recycle
+/// directories are searched for in the root and in every directory up to
+/// `depth - 1` levels below it (i.e. every parent of a directory that
+/// could have been recycled). If `dry_run` is true then nothing is deleted.
+/// Returns the paths that were (or, for a dry run, would be) purged.
+///
+pub async fn purge_recycled(
+    root: &Path,
+    depth: usize,
+    retention_days: u64,
+    dry_run: bool,
+) -> Result<Vec<PathBuf>, Error> {
+    let root = clean_and_check_path(root, false).await?;
+
+    match get_exec_prefix() {
+        Some(prefix) => purge_recycled_remote(&root, depth, retention_days, dry_run, prefix).await,
+        None => purge_recycled_native(&root, depth, retention_days, dry_run).await,
+    }
+}
+
+async fn purge_recycled_native(
+    root: &Path,
+    depth: usize,
+    retention_days: u64,
+    dry_run: bool,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut purged = Vec::new();
+
+    if !root.exists() {
+        return Ok(purged);
+    }
+
+    let cutoff = std::time::SystemTime::now()
+        .checked_sub(std::time::Duration::from_secs(retention_days * 24 * 3600))
+        .unwrap_or(std::time::UNIX_EPOCH);
+
+    // find all of the .recycle directories, level by level
+    let mut recycle_dirs = Vec::new();
+    let mut level = vec![root.to_path_buf()];
+
+    for i in 0..depth {
+        let mut next_level = Vec::new();
+
+        for dir in level {
+            let recycle = dir.join(".recycle");
+
+            if recycle.is_dir() {
+                recycle_dirs.push(recycle);
+            }
+
+            if i + 1 == depth {
+                continue;
+            }
+
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    tracing::warn!(
+                        "Could not read directory '{}': {}",
+                        dir.to_string_lossy(),
+                        e
+                    );
+                    continue;
+                }
+            };
+
+            for entry in entries.flatten() {
+                let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+
+                if is_dir && !entry.file_name().to_string_lossy().starts_with('.') {
+                    next_level.push(entry.path());
+                }
+            }
+        }
+
+        level = next_level;
+    }
+
+    for recycle in recycle_dirs {
+        let entries = std::fs::read_dir(&recycle).with_context(|| {
+            format!(
+                "Could not read recycle directory '{}'",
+                recycle.to_string_lossy()
+            )
+        })?;
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            let recycled = match std::fs::symlink_metadata(&path).and_then(|m| m.modified()) {
+                Ok(recycled) => recycled,
+                Err(e) => {
+                    tracing::warn!(
+                        "Could not get the recycle time of '{}': {}",
+                        path.to_string_lossy(),
+                        e
+                    );
+                    continue;
+                }
+            };
+
+            if recycled > cutoff {
+                continue;
+            }
+
+            if dry_run {
+                tracing::info!("Would purge recycled '{}'", path.to_string_lossy());
+            } else {
+                tracing::info!("Purging recycled '{}'", path.to_string_lossy());
+
+                let result = match entry.file_type() {
+                    Ok(t) if t.is_dir() => std::fs::remove_dir_all(&path),
+                    _ => std::fs::remove_file(&path),
+                };
+
+                if let Err(e) = result {
+                    tracing::error!(
+                        "Could not purge recycled '{}': {}",
+                        path.to_string_lossy(),
+                        e
+                    );
+                    continue;
+                }
+            }
+
+            purged.push(path);
+        }
+    }
+
+    Ok(purged)
+}
+
+async fn purge_recycled_remote(
+    root: &Path,
+    depth: usize,
+    retention_days: u64,
+    dry_run: bool,
+    prefix: &[String],
+) -> Result<Vec<PathBuf>, Error> {
+    let mut purged = Vec::new();
+
+    if depth == 0 || !remote_exists(prefix, root).await? {
+        return Ok(purged);
+    }
+
+    let root_str = root.to_string_lossy();
+    let maxdepth = depth.to_string();
+
+    // find all of the .recycle directories
+    let (exit_code, stdout, stderr) = run_remote(
+        prefix,
+        &[
+            "find",
+            &root_str,
+            "-mindepth",
+            "1",
+            "-maxdepth",
+            &maxdepth,
+            "-type",
+            "d",
+            "-name",
+            ".recycle",
+            "-prune",
+        ],
+    )
+    .await?;
+
+    if exit_code != 0 {
+        return Err(Error::State(format!(
+            "find '{}' failed: exit code {}, stderr: {}",
+            root_str, exit_code, stderr
+        )));
+    }
+
+    let mtime = format!("+{}", retention_days.saturating_sub(1));
+
+    for recycle in stdout.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        // find everything recycled before the retention period
+        let (exit_code, stdout, stderr) = run_remote(
+            prefix,
+            &[
+                "find",
+                recycle,
+                "-mindepth",
+                "1",
+                "-maxdepth",
+                "1",
+                "-mtime",
+                &mtime,
+            ],
+        )
+        .await?;
+
+        if exit_code != 0 {
+            tracing::warn!(
+                "find '{}' failed: exit code {}, stderr: {}",
+                recycle,
+                exit_code,
+                stderr
+            );
+            continue;
+        }
+
+        for path in stdout.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            if dry_run {
+                tracing::info!("Would purge recycled (remote) '{}'", path);
+            } else {
+                tracing::info!("Purging recycled (remote) '{}'", path);
+
+                let (exit_code, _, stderr) = run_remote(prefix, &["rm", "-rf", path]).await?;
+
+                if exit_code != 0 {
+                    tracing::error!(
+                        "rm -rf '{}' failed: exit code {}, stderr: {}",
+                        path,
+                        exit_code,
+                        stderr
+                    );
+                    continue;
+                }
+            }
+
+            purged.push(PathBuf::from(path));
+        }
+    }
+
+    Ok(purged)
+}
+
+///
+/// Measure the disk space used by everything below the passed directory,
+/// without following symlinks. Returns 0 if the directory does not exist.
+/// This walks the whole tree, so can be slow for large directories.
+///
+pub async fn disk_usage(path: &Path) -> Result<u64, Error> {
+    let path = clean_and_check_path(path, false).await?;
+
+    match get_exec_prefix() {
+        Some(prefix) => disk_usage_remote(&path, prefix).await,
+        None => disk_usage_native(&path).await,
+    }
+}
+
+async fn disk_usage_native(path: &Path) -> Result<u64, Error> {
+    let path = path.to_path_buf();
+
+    tokio::task::spawn_blocking(move || {
+        let mut total: u64 = 0;
+        let mut stack = vec![path];
+
+        while let Some(dir) = stack.pop() {
+            let metadata = match std::fs::symlink_metadata(&dir) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+
+            // count allocated blocks, as this is what is billed on disk
+            total = total.saturating_add(metadata.blocks() * 512);
+
+            if !metadata.is_dir() {
+                continue;
+            }
+
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    tracing::warn!(
+                        "Could not read directory '{}': {}",
+                        dir.to_string_lossy(),
+                        e
+                    );
+                    continue;
+                }
+            };
+
+            for entry in entries.flatten() {
+                stack.push(entry.path());
+            }
+        }
+
+        total
+    })
+    .await
+    .map_err(|e| Error::State(format!("Disk usage scan failed: {}", e)))
+}
+
+async fn disk_usage_remote(path: &Path, prefix: &[String]) -> Result<u64, Error> {
+    let path_str = path.to_string_lossy();
+
+    if !remote_exists(prefix, path).await? {
+        return Ok(0);
+    }
+
+    let (exit_code, stdout, stderr) = run_remote(prefix, &["du", "-sk", &path_str]).await?;
+
+    if exit_code != 0 {
+        return Err(Error::State(format!(
+            "du -sk '{}' failed: exit code {}, stderr: {}",
+            path_str, exit_code, stderr
+        )));
+    }
+
+    let kb = stdout
+        .split_whitespace()
+        .next()
+        .and_then(|kb| kb.parse::<u64>().ok())
+        .ok_or_else(|| {
+            Error::Parse(format!(
+                "Could not parse output of du -sk '{}': {}",
+                path_str, stdout
+            ))
+        })?;
+
+    Ok(kb.saturating_mul(1024))
+}
@@ -23,11 +23,11 @@
 use anyhow::Result;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use templemeads::exec;
 use templemeads::grammar::{ProjectMapping, UserMapping};
 use templemeads::job::assert_not_expired;
 use templemeads::storage::{Quota, QuotaLimit, StorageSize, StorageUsage, Volume};
 use templemeads::Error;
-use tokio::process::Command;
 
 use crate::volumeconfig::{ProjectVolumeConfig, UserVolumeConfig};
 
@@ -79,6 +79,17 @@ impl LinuxEngine {
         Ok(())
     }
 
+    /// Check that quotas can be read from the configured filesystem
+    pub async fn check(&self, expires: &chrono::DateTime<Utc>) -> Result<(), Error> {
+        self.run_command(
+            &self.config.repquota,
+            &["-u", &self.config.filesystem],
+            expires,
+        )
+        .await?;
+        Ok(())
+    }
+
     // -----------------------------------------------------------------------
     // Internal helpers
     // -----------------------------------------------------------------------
@@ -96,34 +107,20 @@ impl LinuxEngine {
     ) -> Result<String, Error> {
         assert_not_expired(expires)?;
 
-        let parts: Vec<&str> = program.split_whitespace().collect();
-        let (prog, initial_args) = parts.split_first().ok_or_else(|| {
-            Error::Misconfigured(format!("Linux quota command is empty: '{}'", program))
-        })?;
-
-        let cmd_str = format!("{} {}", program, args.join(" "));
-        tracing::info!("LinuxQuotaEngine executing: {}", cmd_str);
-
-        let mut cmd = Command::new(prog);
-        cmd.args(initial_args);
-        cmd.args(args);
-
-        let output = cmd
-            .output()
-            .await
-            .map_err(|e| Error::Failed(format!("Failed to spawn '{}': {}", cmd_str, e)))?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(Error::Failed(format!(
-                "Command '{}' failed (exit {:?}): {}",
-                cmd_str,
-                output.status.code(),
-                stderr.trim()
+        if program.trim().is_empty() {
+            return Err(Error::Misconfigured(format!(
+                "Linux quota command is empty: '{}'",
+                program
             )));
         }
 
-        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        let command = exec::Command::parse(program).args(args);
+        tracing::info!("LinuxQuotaEngine executing: {}", command);
+
+        command
+            .run()
+            .await
+            .map_err(|failure| Error::Failed(failure.to_string()))
     }
 
     /// Convert a [`QuotaLimit`] to kilobytes for `setquota`.
@@ -13,13 +13,12 @@ use std::collections::HashMap;
 use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
+use templemeads::exec;
 use templemeads::grammar::{ProjectMapping, UserMapping};
 use templemeads::job::assert_not_expired;
 use templemeads::storage::{Quota, QuotaLimit, StorageSize, StorageUsage, Volume};
 use templemeads::Error;
-use tokio::process::Command;
 use tokio::sync::Mutex;
-use tokio::time::timeout;
 
 use crate::volumeconfig::{ProjectVolumeConfig, UserVolumeConfig};
 
@@ -517,40 +516,22 @@ impl LockedRunner {
 
         assert_not_expired(expires)?;
 
-        let cmd_parts: Vec<&str> = self.lfs().split_whitespace().collect();
-        let (program, initial_args) = cmd_parts
-            .split_first()
-            .ok_or_else(|| Error::Misconfigured("lfs_command is empty".to_string()))?;
-
-        let command_string = format!("{} {}", program, args.join(" "));
-
-        let mut command = Command::new(program);
-        command.args(initial_args);
-        command.args(args);
-
-        tracing::info!("Executing lfs command: {}", command_string);
+        if self.lfs().trim().is_empty() {
+            return Err(Error::Misconfigured("lfs_command is empty".to_string()));
+        }
 
-        let result = timeout(timeout_duration, command.output())
-            .await
-            .map_err(|_| {
-                Error::Timeout(format!(
-                    "lfs command timed out after {} seconds",
-                    timeout_duration.as_secs()
-                ))
-            })?
-            .map_err(|e| Error::Failed(format!("Failed to execute lfs command: {}", e)))?;
+        let command = exec::Command::parse(self.lfs())
+            .args(args)
+            .timeout(timeout_duration);
 
-        if !result.status.success() {
-            let stdout = String::from_utf8_lossy(&result.stdout);
-            let stderr = String::from_utf8_lossy(&result.stderr);
-            return Err(Error::Failed(format!(
-                "lfs command failed: stdout={} stderr={}",
-                stdout.trim(),
-                stderr.trim()
-            )));
-        }
+        tracing::info!("Executing lfs command: {}", command);
 
-        Ok(String::from_utf8_lossy(&result.stdout).to_string())
+        command.run().await.map_err(|failure| match failure.reason {
+            exec::Reason::Timeout(seconds) => {
+                Error::Timeout(format!("lfs command timed out after {} seconds", seconds))
+            }
+            _ => Error::Failed(format!("lfs command failed: {}", failure)),
+        })
     }
 }
 
@@ -639,36 +620,23 @@ async fn start_queue_worker() {
                         cmd.args.join(" ")
                     );
 
-                    // Build and execute command
-                    let cmd_parts: Vec<&str> = lfs_command.split_whitespace().collect();
-                    let result = if let Some((program, initial_args)) = cmd_parts.split_first() {
-                        let mut command = Command::new(program);
-                        command.args(initial_args);
-                        command.args(&cmd.args);
-
-                        // Use a very long timeout (5 hours)
-                        match timeout(Duration::from_secs(18000), command.output()).await {
-                            Ok(Ok(output)) => {
-                                if output.status.success() {
-                                    Ok(String::from_utf8_lossy(&output.stdout).to_string())
-                                } else {
-                                    let stderr = String::from_utf8_lossy(&output.stderr);
-                                    Err(Error::Failed(format!(
-                                        "Queued lfs command failed: {}",
-                                        stderr.trim()
-                                    )))
-                                }
-                            }
-                            Ok(Err(e)) => Err(Error::Failed(format!(
-                                "Failed to execute queued lfs command: {}",
-                                e
-                            ))),
-                            Err(_) => Err(Error::Timeout(
-                                "Queued lfs command timed out after 5 hours".to_string(),
-                            )),
-                        }
-                    } else {
+                    // Build and execute command, with a very long timeout (5 hours)
+                    let result = if lfs_command.trim().is_empty() {
                         Err(Error::Misconfigured("lfs_command is empty".to_string()))
+                    } else {
+                        exec::Command::parse(&lfs_command)
+                            .args(&cmd.args)
+                            .timeout(Duration::from_secs(18000))
+                            .run()
+                            .await
+                            .map_err(|failure| match failure.reason {
+                                exec::Reason::Timeout(_) => Error::Timeout(
+                                    "Queued lfs command timed out after 5 hours".to_string(),
+                                ),
+                                _ => {
+                                    Error::Failed(format!("Queued lfs command failed: {}", failure))
+                                }
+                            })
                     };
 
                     // Notify all waiters
@@ -766,6 +734,15 @@ impl LustreEngine {
         Ok(())
     }
 
+    /// Check that Lustre can be reached, by running `lfs df` on the
+    /// passed path
+    pub async fn check(&self, path: &Path, expires: &chrono::DateTime<Utc>) -> Result<(), Error> {
+        let path = path.to_string_lossy();
+        self.run_lfs_command(&["df", &path], self.config.command_timeout(), expires)
+            .await?;
+        Ok(())
+    }
+
     fn get_id_strategy(&self, volume: &Volume) -> Result<&LustreIdStrategy, Error> {
         self.config.id_strategies.get(volume).ok_or_else(|| {
             Error::Misconfigured(format!(
@@ -780,9 +757,8 @@ impl LustreEngine {
     /// Tries /usr/bin/id first (standard location), then falls back to PATH.
     async fn get_uid(&self, username: &str) -> Result<u32, Error> {
         // Try /usr/bin/id first (standard location)
-        let result = tokio::process::Command::new("/usr/bin/id")
-            .arg("-u")
-            .arg(username)
+        let result = exec::Command::new("/usr/bin/id")
+            .args(["-u", username])
             .output()
             .await;
 
@@ -790,26 +766,27 @@ impl LustreEngine {
             Ok(output) => output,
             Err(_) => {
                 // Fall back to searching PATH
-                tokio::process::Command::new("id")
-                    .arg("-u")
-                    .arg(username)
+                exec::Command::new("id")
+                    .args(["-u", username])
                     .output()
                     .await
-                    .map_err(|e| {
-                        Error::Failed(format!("Failed to execute 'id -u {}': {}", username, e))
+                    .map_err(|failure| {
+                        Error::Failed(format!(
+                            "Failed to execute 'id -u {}': {}",
+                            username, failure
+                        ))
                     })?
             }
         };
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.success() {
             return Err(Error::Failed(format!(
                 "Failed to get UID for user '{}': {}",
-                username, stderr
+                username, output.stderr
             )));
         }
 
-        let uid_str = String::from_utf8_lossy(&output.stdout);
+        let uid_str = output.stdout;
         let uid = uid_str.trim().parse::<u32>().map_err(|e| {
             Error::Failed(format!(
                 "Failed to parse UID '{}' for user '{}': {}",
@@ -832,24 +809,22 @@ impl LustreEngine {
     /// falls back to reading /etc/group directly (for development on macOS).
     async fn get_gid(&self, groupname: &str) -> Result<u32, Error> {
         // Try /usr/bin/getent first (standard Linux location)
-        let result = tokio::process::Command::new("/usr/bin/getent")
-            .arg("group")
-            .arg(groupname)
+        let result = exec::Command::new("/usr/bin/getent")
+            .args(["group", groupname])
             .output()
             .await;
 
         let output = match result {
-            Ok(output) if output.status.success() => output,
+            Ok(output) if output.success() => output,
             _ => {
                 // Fall back to searching PATH
-                let result2 = tokio::process::Command::new("getent")
-                    .arg("group")
-                    .arg(groupname)
+                let result2 = exec::Command::new("getent")
+                    .args(["group", groupname])
                     .output()
                     .await;
 
                 match result2 {
-                    Ok(output) if output.status.success() => output,
+                    Ok(output) if output.success() => output,
                     _ => {
                         // Final fallback: read /etc/group directly (for macOS development)
                         return self.get_gid_from_file(groupname).await;
@@ -859,7 +834,7 @@ impl LustreEngine {
         };
 
         // getent group returns: groupname:x:gid:members
-        let group_info = String::from_utf8_lossy(&output.stdout);
+        let group_info = output.stdout;
         let parts: Vec<&str> = group_info.trim().split(':').collect();
 
         if parts.len() < 3 {
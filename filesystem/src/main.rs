@@ -3,34 +3,47 @@
 
 use anyhow::Result;
 use chrono::Utc;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 
 use templemeads::agent;
 use templemeads::agent::filesystem::{process_args, run, Defaults};
 use templemeads::agent::Type as AgentType;
 use templemeads::async_runnable;
+use templemeads::batch::{self, BatchResult};
+use templemeads::dryrun;
 use templemeads::grammar::Instruction::{
-    AddLocalProject, AddLocalUser, ClearLocalProjectQuota, ClearLocalUserQuota, GetLocalHomeDir,
-    GetLocalProjectDirs, GetLocalProjectQuota, GetLocalProjectQuotas, GetLocalStorageReport,
-    GetLocalUserDirs, GetLocalUserQuota, GetLocalUserQuotas, RemoveLocalProject, RemoveLocalUser,
-    SetLocalProjectQuota, SetLocalUserQuota,
+    AddLocalProject, AddLocalUser, AddLocalUsers, ClearLocalProjectQuota, ClearLocalUserQuota,
+    GetLocalHomeDir, GetLocalProjectDirs, GetLocalProjectQuota, GetLocalProjectQuotas,
+    GetLocalStorageReport, GetLocalUserDirs, GetLocalUserQuota, GetLocalUserQuotas, PurgeRecycled,
+    RemoveLocalProject, RemoveLocalUser, RemoveLocalUsers, RepairPermissions, SetLocalProjectQuota,
+    SetLocalUserQuota,
 };
-use templemeads::grammar::{Date, ProjectMapping, UserMapping};
+use templemeads::grammar::{Date, Instruction, ProjectMapping, UserMapping, UserOrProjectMapping};
+use templemeads::health;
 use templemeads::job::{Envelope, Job};
 use templemeads::notification::default_notify_runner;
 use templemeads::set_notify_runner;
-use templemeads::storage::Quota;
+use templemeads::storage::{Quota, QuotaLimit, Volume};
 use templemeads::storagereport::ProjectStorageReport;
 use templemeads::Error;
 
+mod archive;
 mod cache;
+mod cephquotaengine;
 mod fakequotaengine;
 mod filesystem;
 mod linuxquotaengine;
 mod lustreengine;
 mod quotaengine;
+mod snapshot;
 mod volumeconfig;
+mod volumehealth;
 
-use volumeconfig::FilesystemConfig;
+use volumeconfig::{FilesystemConfig, SkeletonEntry};
 
 ///
 /// Main function for the filesystem application
@@ -49,7 +62,7 @@ async fn main() -> Result<()> {
     templemeads::spawn_system_monitor();
 
     // create the OpenPortal paddington defaults
-    let defaults: Defaults<FilesystemConfig> = Defaults::parse(
+    let mut defaults: Defaults<FilesystemConfig> = Defaults::parse(
         Some("filesystem".to_owned()),
         Some(
             dirs::config_local_dir()
@@ -68,6 +81,9 @@ async fn main() -> Result<()> {
         Some(AgentType::Filesystem),
     );
 
+    // the volumes and quota engines are all checked by --check-config
+    defaults.set_config_check(|config, check| config.agent_config.check(check));
+
     // now parse the command line arguments to get the service configuration
     let config = match process_args(&defaults).await? {
         Some(config) => config,
@@ -97,6 +113,28 @@ async fn main() -> Result<()> {
     };
     filesystem::set_exec_prefix(exec_prefix)?;
 
+    // Optional report-only mode, in which mutating instructions return
+    // the operations they would perform without changing anything.
+    // Example: report-only = "true"
+    let report_only = config.option("report-only", "false");
+    let report_only = matches!(
+        report_only.trim().to_lowercase().as_str(),
+        "true" | "yes" | "1"
+    );
+
+    if report_only {
+        tracing::warn!("Running in report-only mode - no changes will be made");
+    }
+
+    filesystem::set_report_only(report_only)?;
+
+    // every change is made through report_operation, so dry runs are
+    // recorded in the same way as report-only mode
+    dryrun::set_supported(true);
+
+    // add_local_users and remove_local_users are handled by the runner
+    batch::set_native(true);
+
     async_runnable! {
         ///
         /// Runnable function that will be called when a job is received
@@ -108,136 +146,285 @@ async fn main() -> Result<()> {
             let sender = envelope.sender();
             let job = envelope.job();
 
-            match job.instruction() {
-                GetLocalStorageReport(mapping, dates) => {
-                    let today = Date::today().day();
-                    if dates != today {
-                        return job.errored(&format!(
-                            "Storage reports only support today's date; requested range: {}",
-                            dates
-                        ));
-                    }
-                    let report = get_local_storage_report(
-                        me.name(), &sender, &mapping, job.expires()
-                    ).await?;
-                    job.completed(report)
-                },
-                AddLocalProject(mapping) => {
-                    create_project_dirs_and_links(&mapping, job.expires()).await?;
-                    job.completed_none()
-                },
-                RemoveLocalProject(mapping) => {
-                    remove_project_dirs_and_links(&mapping).await?;
-                    job.completed_none()
-                },
-                AddLocalUser(mapping) => {
-                    create_user_dirs(&mapping, job.expires()).await?;
-                    job.completed_none()
-                },
-                RemoveLocalUser(mapping) => {
-                    remove_user_dirs(&mapping).await?;
-                    job.completed_none()
-                },
-                GetLocalHomeDir(mapping) => {
-                    let config = cache::get_filesystem_config().await?;
-                    let home_dir = config.home_volume()?.home_path(&mapping)?;
-                    job.completed(home_dir.to_string_lossy().to_string())
-                },
-                GetLocalUserDirs(mapping) => {
-                    let config = cache::get_filesystem_config().await?;
-
-                    let mut user_dirs = Vec::new();
+            // in report-only mode, mutating instructions return the
+            // operations that they would have performed
+            let (result, operations) = filesystem::record_operations(async {
+                match job.instruction() {
+                    GetLocalStorageReport(mapping, dates) => {
+                        let today = Date::today().day();
+                        if dates != today {
+                            return job.errored(&format!(
+                                "Storage reports only support today's date; requested range: {}",
+                                dates
+                            ));
+                        }
+                        let report = get_local_storage_report(
+                            me.name(), &sender, &mapping, job.expires()
+                        ).await?;
+                        job.completed(report)
+                    },
+                    AddLocalProject(mapping) => {
+                        let config = cache::get_filesystem_config().await?;
+                        volumehealth::assert_healthy(config.get_project_volumes().keys())?;
+                        create_project_dirs_and_links(&mapping, job.expires()).await?;
+                        if !dryrun::is_dry_run() {
+                            cache::add_project(&mapping).await;
+                        }
+                        job.completed_none()
+                    },
+                    RemoveLocalProject(mapping) => {
+                        let manifest = remove_project_dirs_and_links(&mapping).await?;
+                        if !dryrun::is_dry_run() {
+                            cache::remove_project(&mapping).await;
+                        }
 
-                    for (volume, volume_config) in config.get_user_volumes() {
-                        for path_config in volume_config.path_configs() {
-                            match path_config.path(mapping.clone().into()) {
-                                Ok(path) => {
-                                    user_dirs.push(path.to_string_lossy().to_string());
-                                }
-                                Err(error) => {
-                                    tracing::warn!(
-                                        "Could not get user directory path for volume {}: {}",
-                                        volume,
-                                        error
-                                    );
+                        if manifest.is_empty() {
+                            job.completed_none()
+                        } else {
+                            job.completed(manifest)
+                        }
+                    },
+                    RepairPermissions(mapping) => {
+                        let repaired = repair_permissions(me.name(), &sender, &mapping).await?;
+                        job.completed(repaired)
+                    },
+                    AddLocalUser(mapping) => {
+                        let config = cache::get_filesystem_config().await?;
+                        volumehealth::assert_healthy(config.get_user_volumes().keys())?;
+                        create_user_dirs(&mapping, job.expires()).await?;
+                        if !dryrun::is_dry_run() {
+                            cache::add_user(&mapping).await;
+                        }
+                        job.completed_none()
+                    },
+                    RemoveLocalUser(mapping) => {
+                        remove_user_dirs(&mapping).await?;
+                        if !dryrun::is_dry_run() {
+                            cache::remove_user(&mapping).await;
+                        }
+                        job.completed_none()
+                    },
+                    AddLocalUsers(mappings) => {
+                        let config = cache::get_filesystem_config().await?;
+                        volumehealth::assert_healthy(config.get_user_volumes().keys())?;
+                        let result = create_users_dirs(mappings, job.expires()).await?;
+                        if !dryrun::is_dry_run() {
+                            for mapping in &result.succeeded {
+                                cache::add_user(mapping).await;
+                            }
+                        }
+                        job.completed(result)
+                    },
+                    RemoveLocalUsers(mappings) => {
+                        let result = batch::run_each(mappings, max_concurrent_users().await?, |mapping| async move {
+                            remove_user_dirs(&mapping).await?;
+                            Ok(mapping)
+                        })
+                        .await;
+                        if !dryrun::is_dry_run() {
+                            for mapping in &result.succeeded {
+                                cache::remove_user(mapping).await;
+                            }
+                        }
+                        job.completed(result)
+                    },
+                    GetLocalHomeDir(mapping) => {
+                        let config = cache::get_filesystem_config().await?;
+                        let home_dir = config.home_volume()?.home_path(&mapping)?;
+                        job.completed(home_dir.to_string_lossy().to_string())
+                    },
+                    GetLocalUserDirs(mapping) => {
+                        let config = cache::get_filesystem_config().await?;
+
+                        let mut user_dirs = Vec::new();
+
+                        for (volume, volume_config) in config.get_user_volumes() {
+                            for path_config in volume_config.path_configs() {
+                                match path_config.path(mapping.clone().into()) {
+                                    Ok(path) => {
+                                        user_dirs.push(path.to_string_lossy().to_string());
+                                    }
+                                    Err(error) => {
+                                        tracing::warn!(
+                                            "Could not get user directory path for volume {}: {}",
+                                            volume,
+                                            error
+                                        );
+                                    }
                                 }
                             }
                         }
-                    }
 
-                    job.completed(user_dirs)
-                },
-                GetLocalProjectDirs(mapping) => {
-                    let config = cache::get_filesystem_config().await?;
-
-                    let mut project_dirs = Vec::new();
-
-                    for (volume, volume_config) in config.get_project_volumes() {
-                        for path_config in volume_config.path_configs() {
-                            match path_config.path(mapping.clone().into()) {
-                                Ok(path) => {
-                                    project_dirs.push(path.to_string_lossy().to_string());
-                                }
-                                Err(error) => {
-                                    tracing::warn!(
-                                        "Could not get project directory path for volume {}: {}",
-                                        volume,
-                                        error
-                                    );
+                        job.completed(user_dirs)
+                    },
+                    GetLocalProjectDirs(mapping) => {
+                        let config = cache::get_filesystem_config().await?;
+
+                        let mut project_dirs = Vec::new();
+
+                        for (volume, volume_config) in config.get_project_volumes() {
+                            for path_config in volume_config.path_configs() {
+                                match path_config.path(mapping.clone().into()) {
+                                    Ok(path) => {
+                                        project_dirs.push(path.to_string_lossy().to_string());
+                                    }
+                                    Err(error) => {
+                                        tracing::warn!(
+                                            "Could not get project directory path for volume {}: {}",
+                                            volume,
+                                            error
+                                        );
+                                    }
                                 }
                             }
                         }
-                    }
 
-                    job.completed(project_dirs)
-                },
-                SetLocalProjectQuota(mapping, volume, limit) => {
-                    let quota = set_project_quota(&mapping, &volume, &limit, job.expires()).await?;
-                    job.completed(quota)
-                },
-                GetLocalProjectQuota(mapping, volume) => {
-                    let quota = get_project_quota(&mapping, &volume, job.expires()).await?;
-                    job.completed(quota)
-                },
-                GetLocalProjectQuotas(mapping) => {
-                    let quotas = get_project_quotas(&mapping, job.expires()).await?;
-                    job.completed(quotas)
-                },
-                SetLocalUserQuota(mapping, volume, limit) => {
-                    let quota = set_user_quota(&mapping, &volume, &limit, job.expires()).await?;
-                    job.completed(quota)
-                },
-                GetLocalUserQuota(mapping, volume) => {
-                    let quota = get_user_quota(&mapping, &volume, job.expires()).await?;
-                    job.completed(quota)
-                },
-                GetLocalUserQuotas(mapping) => {
-                    let quotas = get_user_quotas(&mapping, job.expires()).await?;
-                    job.completed(quotas)
-                },
-                ClearLocalProjectQuota(mapping, volume) => {
-                    clear_project_quota(&mapping, &volume, job.expires()).await?;
-                    job.completed_none()
-                },
-                ClearLocalUserQuota(mapping, volume) => {
-                    clear_user_quota(&mapping, &volume, job.expires()).await?;
-                    job.completed_none()
-                },
-                _ => {
-                    Err(Error::InvalidInstruction(
-                        format!("Invalid instruction: {}", job.instruction()),
-                    ))
+                        job.completed(project_dirs)
+                    },
+                    SetLocalProjectQuota(mapping, volume, limit) => {
+                        let quota = set_project_quota(&mapping, &volume, &limit, job.expires()).await?;
+                        job.completed(quota)
+                    },
+                    GetLocalProjectQuota(mapping, volume) => {
+                        let quota = get_project_quota(&mapping, &volume, job.expires()).await?;
+                        job.completed(quota)
+                    },
+                    GetLocalProjectQuotas(mapping) => {
+                        let quotas = get_project_quotas(&mapping, job.expires()).await?;
+                        job.completed(quotas)
+                    },
+                    SetLocalUserQuota(mapping, volume, limit) => {
+                        let quota = set_user_quota(&mapping, &volume, &limit, job.expires()).await?;
+                        job.completed(quota)
+                    },
+                    GetLocalUserQuota(mapping, volume) => {
+                        let quota = get_user_quota(&mapping, &volume, job.expires()).await?;
+                        job.completed(quota)
+                    },
+                    GetLocalUserQuotas(mapping) => {
+                        let quotas = get_user_quotas(&mapping, job.expires()).await?;
+                        job.completed(quotas)
+                    },
+                    ClearLocalProjectQuota(mapping, volume) => {
+                        clear_project_quota(&mapping, &volume, job.expires()).await?;
+                        job.completed_none()
+                    },
+                    ClearLocalUserQuota(mapping, volume) => {
+                        clear_user_quota(&mapping, &volume, job.expires()).await?;
+                        job.completed_none()
+                    },
+                    PurgeRecycled(dry_run) => {
+                        let purged =
+                            purge_recycled_dirs(dry_run || filesystem::is_recording()).await?;
+                        job.completed(purged)
+                    },
+                    _ => {
+                        Err(Error::InvalidInstruction(
+                            format!("Invalid instruction: {}", job.instruction()),
+                        ))
+                    }
                 }
+            })
+            .await;
+
+            if filesystem::is_report_only() && is_mutating(&job.instruction()) {
+                result?;
+                return job.completed(operations);
             }
+
+            result
         }
     }
 
+    spawn_recycle_purger();
+    spawn_quota_monitor();
+    spawn_volume_monitor();
+
     set_notify_runner(default_notify_runner).await?;
     run(config, filesystem_runner).await?;
 
     Ok(())
 }
 
+///
+/// Return whether the passed instruction changes the filesystem or quotas
+///
+fn is_mutating(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        AddLocalProject(_)
+            | RemoveLocalProject(_)
+            | AddLocalUser(_)
+            | RemoveLocalUser(_)
+            | AddLocalUsers(_)
+            | RemoveLocalUsers(_)
+            | SetLocalProjectQuota(_, _, _)
+            | SetLocalUserQuota(_, _, _)
+            | ClearLocalProjectQuota(_, _)
+            | ClearLocalUserQuota(_, _)
+            | PurgeRecycled(_)
+            | RepairPermissions(_)
+    )
+}
+
+/// A filesystem operation on a single path that can be run concurrently
+type PathTask = Pin<Box<dyn Future<Output = Result<(), Error>> + Send>>;
+
+///
+/// Run the passed tasks, at most `max_concurrent` at a time. Every task
+/// is run to completion, and the returned error lists each path whose
+/// task failed.
+///
+async fn run_concurrently(
+    tasks: Vec<(String, PathTask)>,
+    max_concurrent: usize,
+) -> Result<(), Error> {
+    let num_tasks = tasks.len();
+    let mut errors = Vec::new();
+
+    if filesystem::is_recording() {
+        // operations are recorded against the job's own task, so
+        // they must be run one after another within it
+        for (path, task) in tasks {
+            if let Err(e) = task.await {
+                errors.push(format!("{}: {}", path, e));
+            }
+        }
+    } else {
+        let semaphore = Arc::new(Semaphore::new(max_concurrent.max(1)));
+        let mut set = JoinSet::new();
+
+        for (path, task) in tasks {
+            let semaphore = semaphore.clone();
+
+            set.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                (path, task.await)
+            });
+        }
+
+        while let Some(result) = set.join_next().await {
+            match result {
+                Ok((_, Ok(()))) => {}
+                Ok((path, Err(e))) => errors.push(format!("{}: {}", path, e)),
+                Err(e) => errors.push(format!("task panicked: {}", e)),
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        errors.sort();
+        Err(Error::Failed(format!(
+            "{} of {} filesystem operations failed: {}",
+            errors.len(),
+            num_tasks,
+            errors.join("; ")
+        )))
+    }
+}
+
 ///
 /// Create the project directories and links for a given ProjectMapping,
 ///
@@ -246,21 +433,46 @@ async fn create_project_dirs_and_links(
     expires: &chrono::DateTime<Utc>,
 ) -> Result<(), Error> {
     let config = cache::get_filesystem_config().await?;
+    let max_concurrent = config.max_concurrent_operations();
 
     // create all of the project volume directories first
+    let mut tasks: Vec<(String, PathTask)> = Vec::new();
+
     for (volume, volume_config) in config.get_project_volumes() {
         tracing::info!("Creating project volume: {}", volume);
         for path_config in volume_config.path_configs() {
             match path_config.path(mapping.clone().into()) {
                 Ok(path) => {
                     tracing::info!("    - Directory path to create: {}", path.to_string_lossy());
-                    filesystem::create_dir(
-                        &path,
-                        "root",
-                        mapping.local_group(),
-                        path_config.permission(),
-                    )
-                    .await?;
+                    let mapping = mapping.clone();
+                    let skeleton = volume_config.skeleton().to_vec();
+
+                    tasks.push((
+                        path.to_string_lossy().to_string(),
+                        Box::pin(async move {
+                            filesystem::create_dir(
+                                &path,
+                                "root",
+                                mapping.local_group(),
+                                path_config.permission(),
+                            )
+                            .await?;
+                            filesystem::set_acls(
+                                &path,
+                                &path_config.acls(mapping.clone().into()),
+                                path_config.acl_format(),
+                            )
+                            .await?;
+                            create_skeleton(
+                                &path,
+                                &skeleton,
+                                "root",
+                                path_config.permission(),
+                                mapping.into(),
+                            )
+                            .await
+                        }),
+                    ));
                 }
                 Err(error) => {
                     tracing::warn!("Could not get path for creation: {}", error);
@@ -269,19 +481,31 @@ async fn create_project_dirs_and_links(
         }
     }
 
+    run_concurrently(tasks, max_concurrent).await?;
+
     // now create all of the project volume links (as the directories should exist)
+    let mut tasks: Vec<(String, PathTask)> = Vec::new();
+
     for (volume, volume_config) in config.get_project_volumes() {
         tracing::info!("Creating project volume links for: {}", volume);
         for path_config in volume_config.path_configs() {
             if let Ok(Some(link_path)) = path_config.link_path(mapping.clone().into()) {
                 tracing::info!("    - Link path to create: {}", link_path.to_string_lossy());
                 let dir_path = path_config.path(mapping.clone().into())?;
-                filesystem::create_link(&dir_path, &link_path).await?;
+
+                tasks.push((
+                    link_path.to_string_lossy().to_string(),
+                    Box::pin(async move { filesystem::create_link(&dir_path, &link_path).await }),
+                ));
             }
         }
     }
 
+    run_concurrently(tasks, max_concurrent).await?;
+
     // now create the roots of all of the user directories
+    let mut tasks: Vec<(String, PathTask)> = Vec::new();
+
     for (volume, volume_config) in config.get_user_volumes() {
         tracing::info!("Creating user volume: {}", volume);
 
@@ -292,13 +516,15 @@ async fn create_project_dirs_and_links(
                         "    - User directory root to create: {}",
                         path.to_string_lossy()
                     );
-                    filesystem::create_dir(
-                        &path,
-                        "root",
-                        mapping.local_group(),
-                        path_config.permission(),
-                    )
-                    .await?;
+                    let group = mapping.local_group().to_string();
+
+                    tasks.push((
+                        path.to_string_lossy().to_string(),
+                        Box::pin(async move {
+                            filesystem::create_dir(&path, "root", &group, path_config.permission())
+                                .await
+                        }),
+                    ));
                 }
                 Err(error) => {
                     tracing::warn!("Could not get user directory root for creation: {}", error);
@@ -307,6 +533,8 @@ async fn create_project_dirs_and_links(
         }
     }
 
+    run_concurrently(tasks, max_concurrent).await?;
+
     // finally, set any default quotas
     for (volume, volume_config) in config.get_project_volumes() {
         if volume_config.has_quota_engine() {
@@ -342,6 +570,75 @@ async fn create_project_dirs_and_links(
     Ok(())
 }
 
+///
+/// Create the skeleton tree of subdirectories and files inside a newly
+/// created directory. Entries that already exist are left untouched.
+///
+async fn create_skeleton(
+    dir: &std::path::Path,
+    skeleton: &[SkeletonEntry],
+    owner: &str,
+    permission: &str,
+    mapping: UserOrProjectMapping,
+) -> Result<(), Error> {
+    if skeleton.is_empty() {
+        return Ok(());
+    }
+
+    let (project, group, user) = match &mapping {
+        UserOrProjectMapping::User(user_mapping) => (
+            user_mapping.project().project().project(),
+            user_mapping.local_group().to_string(),
+            user_mapping.local_user().to_string(),
+        ),
+        UserOrProjectMapping::Project(project_mapping) => (
+            project_mapping.project().project(),
+            project_mapping.local_group().to_string(),
+            String::new(),
+        ),
+    };
+
+    let expand = |s: &str| {
+        s.replace("{project}", &project)
+            .replace("{group}", &group)
+            .replace("{user}", &user)
+    };
+
+    for entry in skeleton {
+        let path = dir.join(expand(entry.path()));
+
+        let owner = match entry.owner() {
+            Some("{user}") => user.as_str(),
+            Some(owner) => owner,
+            None => owner,
+        };
+
+        match entry.contents() {
+            Some(contents) => {
+                filesystem::create_file(
+                    &path,
+                    owner,
+                    &group,
+                    entry.permissions().unwrap_or("0644"),
+                    &expand(contents),
+                )
+                .await?;
+            }
+            None => {
+                filesystem::create_dir(
+                    &path,
+                    owner,
+                    &group,
+                    entry.permissions().unwrap_or(permission),
+                )
+                .await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
 ///
 /// Create the user directories for a given UserMapping,
 ///
@@ -350,8 +647,63 @@ async fn create_user_dirs(
     expires: &chrono::DateTime<Utc>,
 ) -> Result<(), Error> {
     create_project_dirs_and_links(&mapping.project(), expires).await?;
+    create_user_volume_dirs(mapping, expires).await
+}
+
+///
+/// Create the directories of a batch of users, who are all in the same
+/// project. The project's directories are created once, and then the
+/// directories of the users are created concurrently
+///
+async fn create_users_dirs(
+    mappings: Vec<UserMapping>,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<BatchResult, Error> {
+    let project = match mappings.first() {
+        Some(mapping) => mapping.project(),
+        None => return Ok(BatchResult::new()),
+    };
+
+    create_project_dirs_and_links(&project, expires).await?;
+
+    let result = batch::run_each(
+        mappings,
+        max_concurrent_users().await?,
+        |mapping| async move {
+            create_user_volume_dirs(&mapping, expires).await?;
+            Ok(mapping)
+        },
+    )
+    .await;
+
+    Ok(result)
+}
+
+///
+/// Return the number of users in a batch whose directories can be
+/// created or removed at the same time. Recorded operations are run
+/// one user at a time, so that they are reported in order
+///
+async fn max_concurrent_users() -> Result<usize, Error> {
+    match filesystem::is_recording() {
+        true => Ok(1),
+        false => Ok(cache::get_filesystem_config()
+            .await?
+            .max_concurrent_operations()),
+    }
+}
 
+///
+/// Create the directories of the passed user on each user volume, and
+/// set their default quotas. The project's directories must already
+/// exist
+///
+async fn create_user_volume_dirs(
+    mapping: &UserMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
     let config = cache::get_filesystem_config().await?;
+    let mut tasks: Vec<(String, PathTask)> = Vec::new();
 
     for (volume, volume_config) in config.get_user_volumes() {
         tracing::info!("Creating user volume: {}", volume);
@@ -360,13 +712,35 @@ async fn create_user_dirs(
             match path_config.path(mapping.clone().into()) {
                 Ok(path) => {
                     tracing::info!("    - User directory to create: {}", path.to_string_lossy());
-                    filesystem::create_dir(
-                        &path,
-                        mapping.local_user(),
-                        mapping.local_group(),
-                        path_config.permission(),
-                    )
-                    .await?;
+                    let mapping = mapping.clone();
+                    let skeleton = volume_config.skeleton().to_vec();
+
+                    tasks.push((
+                        path.to_string_lossy().to_string(),
+                        Box::pin(async move {
+                            filesystem::create_dir(
+                                &path,
+                                mapping.local_user(),
+                                mapping.local_group(),
+                                path_config.permission(),
+                            )
+                            .await?;
+                            filesystem::set_acls(
+                                &path,
+                                &path_config.acls(mapping.clone().into()),
+                                path_config.acl_format(),
+                            )
+                            .await?;
+                            create_skeleton(
+                                &path,
+                                &skeleton,
+                                mapping.local_user(),
+                                path_config.permission(),
+                                mapping.clone().into(),
+                            )
+                            .await
+                        }),
+                    ));
                 }
                 Err(error) => {
                     tracing::warn!("Could not get path for creation: {}", error);
@@ -375,6 +749,8 @@ async fn create_user_dirs(
         }
     }
 
+    run_concurrently(tasks, config.max_concurrent_operations()).await?;
+
     // now we have created all of the directories, set any default quotas
     for (volume, volume_config) in config.get_user_volumes() {
         if volume_config.has_quota_engine() {
@@ -409,15 +785,192 @@ async fn create_user_dirs(
     Ok(())
 }
 
+///
+/// Reset the ownership, permissions and ACLs of all of the directories of
+/// the passed project, and of all of its users, to those they were given
+/// when they were created. The sender (cluster agent) is called back with
+/// get_users to find the users. Returns the directories that were repaired.
+///
+async fn repair_permissions(
+    me: &str,
+    sender: &agent::Peer,
+    mapping: &ProjectMapping,
+) -> Result<Vec<String>, Error> {
+    let config = cache::get_filesystem_config().await?;
+    let mut repaired = Vec::new();
+
+    for (_, volume_config) in config.get_project_volumes() {
+        for path_config in volume_config.path_configs() {
+            let path = path_config.path(mapping.clone().into())?;
+
+            if filesystem::repair_dir(
+                &path,
+                "root",
+                mapping.local_group(),
+                path_config.permission(),
+            )
+            .await?
+            {
+                filesystem::set_acls(
+                    &path,
+                    &path_config.acls(mapping.clone().into()),
+                    path_config.acl_format(),
+                )
+                .await?;
+                repaired.push(path.to_string_lossy().to_string());
+            }
+        }
+    }
+
+    for (_, volume_config) in config.get_user_volumes() {
+        for path_config in volume_config.path_configs() {
+            let path = path_config.project_path(mapping)?;
+
+            if filesystem::repair_dir(
+                &path,
+                "root",
+                mapping.local_group(),
+                path_config.permission(),
+            )
+            .await?
+            {
+                repaired.push(path.to_string_lossy().to_string());
+            }
+        }
+    }
+
+    let job = Job::parse(
+        &format!("{}.{} get_users {}", me, sender.name(), mapping.project()),
+        false,
+    )?
+    .put(sender)
+    .await?;
+
+    let user_mappings = job
+        .wait()
+        .await?
+        .result::<Vec<UserMapping>>()?
+        .unwrap_or_default();
+
+    for user_mapping in &user_mappings {
+        for (_, volume_config) in config.get_user_volumes() {
+            for path_config in volume_config.path_configs() {
+                let path = path_config.path(user_mapping.clone().into())?;
+
+                if filesystem::repair_dir(
+                    &path,
+                    user_mapping.local_user(),
+                    user_mapping.local_group(),
+                    path_config.permission(),
+                )
+                .await?
+                {
+                    filesystem::set_acls(
+                        &path,
+                        &path_config.acls(user_mapping.clone().into()),
+                        path_config.acl_format(),
+                    )
+                    .await?;
+                    repaired.push(path.to_string_lossy().to_string());
+                }
+            }
+        }
+    }
+
+    Ok(repaired)
+}
+
 ///
 /// Remove (recycle) the project directories, links, and home roots for a given ProjectMapping.
 /// This is non-destructive - directories are moved to .recycle subdirectories.
 ///
-async fn remove_project_dirs_and_links(mapping: &ProjectMapping) -> Result<(), Error> {
+async fn remove_project_dirs_and_links(
+    mapping: &ProjectMapping,
+) -> Result<archive::RemovalManifest, Error> {
     let config = cache::get_filesystem_config().await?;
+    let snapshot_name = snapshot::snapshot_name(&mapping.project().to_string());
+    let mut manifest = archive::RemovalManifest::default();
 
     for (volume, volume_config) in config.get_project_volumes() {
         tracing::info!("Removing project volume: {}", volume);
+
+        // snapshot the project's directories before anything is moved,
+        // so that the data can be restored if the removal was a mistake
+        if let Some(engine_name) = volume_config.snapshot_engine_name() {
+            let engine = config.get_snapshot_engine(engine_name)?;
+
+            for path_config in volume_config.path_configs() {
+                let path = match path_config.path(mapping.clone().into()) {
+                    Ok(path) => path,
+                    Err(error) => {
+                        tracing::warn!("Could not get path for snapshot: {}", error);
+                        continue;
+                    }
+                };
+
+                if !filesystem::dir_exists(&path).await? {
+                    continue;
+                }
+
+                if filesystem::report_operation(format!(
+                    "take snapshot '{}' of '{}' with snapshot engine '{}'",
+                    snapshot_name,
+                    path.to_string_lossy(),
+                    engine_name
+                )) {
+                    continue;
+                }
+
+                let snapshot = engine.snapshot(&path, &snapshot_name).await?;
+                tracing::info!("    - Snapshot taken: {}", snapshot);
+
+                if !manifest.snapshots.contains(&snapshot) {
+                    manifest.snapshots.push(snapshot);
+                }
+
+                if engine.is_filesystem_wide() {
+                    break;
+                }
+            }
+        }
+
+        // archive the project's directories to object storage before
+        // they are recycled, so they are kept after the recycle is purged
+        if let (true, Some(archive)) = (volume_config.archive_on_removal(), config.archive()) {
+            for path_config in volume_config.path_configs() {
+                let path = match path_config.path(mapping.clone().into()) {
+                    Ok(path) => path,
+                    Err(error) => {
+                        tracing::warn!("Could not get path for archive: {}", error);
+                        continue;
+                    }
+                };
+
+                if !filesystem::dir_exists(&path).await? {
+                    continue;
+                }
+
+                if filesystem::report_operation(format!(
+                    "archive '{}' to object storage",
+                    path.to_string_lossy()
+                )) {
+                    continue;
+                }
+
+                let archived = archive
+                    .archive_dir(&path, &mapping.project().to_string())
+                    .await?;
+
+                tracing::info!(
+                    "    - Archived to {} (sha256 {})",
+                    archived.object,
+                    archived.sha256
+                );
+
+                manifest.archives.push(archived);
+            }
+        }
+
         for path_config in volume_config.path_configs() {
             if let Ok(Some(link_path)) = path_config.link_path(mapping.clone().into()) {
                 tracing::info!("    - Link path to remove: {}", link_path.to_string_lossy());
@@ -452,7 +1005,7 @@ async fn remove_project_dirs_and_links(mapping: &ProjectMapping) -> Result<(), E
         }
     }
 
-    Ok(())
+    Ok(manifest)
 }
 
 ///
@@ -484,6 +1037,208 @@ async fn remove_user_dirs(mapping: &UserMapping) -> Result<(), Error> {
     Ok(())
 }
 
+///
+/// Purge recycled directories on every volume that has a recycle
+/// retention period, returning the paths that were purged (or, if
+/// this is a dry run, the paths that would have been purged)
+///
+async fn purge_recycled_dirs(dry_run: bool) -> Result<Vec<String>, Error> {
+    let config = cache::get_filesystem_config().await?;
+
+    let mut purged = Vec::new();
+
+    for (volume, path_configs, retention_days) in config.get_recycle_retentions() {
+        tracing::info!(
+            "Purging directories recycled more than {} days ago on volume {}{}",
+            retention_days,
+            volume,
+            if dry_run { " (dry run)" } else { "" }
+        );
+
+        for path_config in path_configs {
+            let paths = filesystem::purge_recycled(
+                path_config.root(),
+                path_config.depth(),
+                retention_days,
+                dry_run,
+            )
+            .await?;
+
+            purged.extend(paths.iter().map(|p| p.to_string_lossy().to_string()));
+        }
+    }
+
+    Ok(purged)
+}
+
+///
+/// Spawn the background task that periodically purges expired
+/// recycled directories
+///
+fn spawn_recycle_purger() {
+    tokio::spawn(async move {
+        loop {
+            let (interval, dry_run) = match cache::get_filesystem_config().await {
+                Ok(config) => (
+                    config.recycle_purge_interval(),
+                    config.recycle_purge_dry_run(),
+                ),
+                Err(e) => {
+                    tracing::error!("Recycle purge: could not get config: {}", e);
+                    return;
+                }
+            };
+
+            tokio::time::sleep(interval).await;
+
+            match purge_recycled_dirs(dry_run || filesystem::is_report_only()).await {
+                Ok(purged) => {
+                    if !purged.is_empty() {
+                        tracing::info!(
+                            "{} {} recycled directories",
+                            if dry_run {
+                                "Would have purged"
+                            } else {
+                                "Purged"
+                            },
+                            purged.len()
+                        );
+                    }
+                }
+                Err(e) => tracing::error!("Failed to purge recycled directories: {}", e),
+            }
+        }
+    });
+}
+
+///
+/// Return the health warning for a quota that has crossed one of the
+/// alert thresholds, or None if it is below all of them
+///
+fn quota_alert(owner: &str, volume: &Volume, quota: &Quota, thresholds: &[u8]) -> Option<String> {
+    let percentage = quota.percentage_used()?;
+
+    let threshold = thresholds
+        .iter()
+        .rev()
+        .find(|threshold| percentage >= f64::from(**threshold))?;
+
+    Some(format!(
+        "{} has used {:.0}% of its quota on volume {} ({}), over the {}% threshold",
+        owner, percentage, volume, quota, threshold
+    ))
+}
+
+///
+/// Check the usage of every project and user managed by this agent
+/// against the quota alert thresholds, raising (or clearing) a health
+/// warning for each quota
+///
+async fn check_quota_alerts() -> Result<(), Error> {
+    let config = cache::get_filesystem_config().await?;
+    let thresholds = config.quota_alert_thresholds().to_vec();
+    let expires = Utc::now() + chrono::Duration::minutes(5);
+
+    for mapping in cache::get_projects().await {
+        match get_project_quotas(&mapping, &expires).await {
+            Ok(quotas) => {
+                for (volume, quota) in quotas {
+                    let key = format!("quota:{}:{}", mapping.project(), volume);
+
+                    match quota_alert(&mapping.project().to_string(), &volume, &quota, &thresholds)
+                    {
+                        Some(message) => health::set_warning(&key, &message),
+                        None => health::clear_warning(&key),
+                    }
+                }
+            }
+            Err(e) => {
+                tracing::warn!("Quota monitor: could not get quotas for {}: {}", mapping, e);
+            }
+        }
+    }
+
+    for mapping in cache::get_users().await {
+        match get_user_quotas(&mapping, &expires).await {
+            Ok(quotas) => {
+                for (volume, quota) in quotas {
+                    let key = format!("quota:{}:{}", mapping.user(), volume);
+
+                    match quota_alert(&mapping.user().to_string(), &volume, &quota, &thresholds) {
+                        Some(message) => health::set_warning(&key, &message),
+                        None => health::clear_warning(&key),
+                    }
+                }
+            }
+            Err(e) => {
+                tracing::warn!("Quota monitor: could not get quotas for {}: {}", mapping, e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Spawn a background task that periodically checks quota usage
+/// against the configured alert thresholds
+///
+fn spawn_quota_monitor() {
+    tokio::spawn(async move {
+        loop {
+            let interval = match cache::get_filesystem_config().await {
+                Ok(config) => match config.quota_check_interval() {
+                    Some(interval) => interval,
+                    None => {
+                        tracing::info!("Quota monitoring is disabled");
+                        return;
+                    }
+                },
+                Err(e) => {
+                    tracing::error!("Quota monitor: could not get config: {}", e);
+                    return;
+                }
+            };
+
+            tokio::time::sleep(interval).await;
+
+            if let Err(e) = check_quota_alerts().await {
+                tracing::error!("Failed to check quota alerts: {}", e);
+            }
+        }
+    });
+}
+
+///
+/// Spawn a background task that checks the health of every volume,
+/// first at startup and then periodically
+///
+fn spawn_volume_monitor() {
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = volumehealth::check_volumes().await {
+                tracing::error!("Failed to check volume health: {}", e);
+            }
+
+            let interval = match cache::get_filesystem_config().await {
+                Ok(config) => match config.volume_check_interval() {
+                    Some(interval) => interval,
+                    None => {
+                        tracing::info!("Volume health checks are disabled");
+                        return;
+                    }
+                },
+                Err(e) => {
+                    tracing::error!("Volume monitor: could not get config: {}", e);
+                    return;
+                }
+            };
+
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
+
 ///
 /// Clear the storage quota for a project on a specific volume
 ///
@@ -509,6 +1264,13 @@ pub async fn clear_project_quota(
 
     let engine = config.get_quota_engine(engine_name)?;
 
+    if filesystem::report_operation(format!(
+        "clear the quota of project {} on volume {} with quota engine '{}'",
+        mapping, volume, engine_name
+    )) {
+        return Ok(());
+    }
+
     engine
         .clear_project_quota(mapping, volume, &volume_config, expires)
         .await
@@ -541,6 +1303,16 @@ pub async fn set_project_quota(
 
     let engine = config.get_quota_engine(engine_name)?;
 
+    if filesystem::report_operation(format!(
+        "set the quota of project {} on volume {} to {} with quota engine '{}'",
+        mapping, volume, limit, engine_name
+    )) {
+        return Ok(match limit {
+            QuotaLimit::Limited(size) => Quota::limited(*size),
+            QuotaLimit::Unlimited => Quota::unlimited(),
+        });
+    }
+
     engine
         .set_project_quota(mapping, volume, &volume_config, limit, expires)
         .await
@@ -661,6 +1433,13 @@ pub async fn clear_user_quota(
 
     let engine = config.get_quota_engine(engine_name)?;
 
+    if filesystem::report_operation(format!(
+        "clear the quota of user {} on volume {} with quota engine '{}'",
+        mapping, volume, engine_name
+    )) {
+        return Ok(());
+    }
+
     engine
         .clear_user_quota(mapping, volume, &volume_config, expires)
         .await
@@ -693,6 +1472,16 @@ pub async fn set_user_quota(
 
     let engine = config.get_quota_engine(engine_name)?;
 
+    if filesystem::report_operation(format!(
+        "set the quota of user {} on volume {} to {} with quota engine '{}'",
+        mapping, volume, limit, engine_name
+    )) {
+        return Ok(match limit {
+            QuotaLimit::Limited(size) => Quota::limited(*size),
+            QuotaLimit::Unlimited => Quota::unlimited(),
+        });
+    }
+
     engine
         .set_user_quota(mapping, volume, &volume_config, limit, expires)
         .await
@@ -788,6 +1577,118 @@ pub async fn get_user_quotas(
     Ok(quotas)
 }
 
+///
+/// Measure the usage of a project on every project volume that has
+/// `scan_usage` set, by scanning the project's directories. These volumes
+/// have no quota engine, so the usage is reported against an unlimited quota
+///
+async fn scan_project_usage(
+    mapping: &ProjectMapping,
+) -> std::collections::HashMap<templemeads::storage::Volume, Quota> {
+    let mut usages = std::collections::HashMap::new();
+
+    let config = match cache::get_filesystem_config().await {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::warn!("Could not get filesystem config: {}", e);
+            return usages;
+        }
+    };
+
+    for (volume, volume_config) in config.get_project_volumes() {
+        if !volume_config.scan_usage() {
+            continue;
+        }
+
+        let paths = volume_config
+            .path_configs()
+            .iter()
+            .filter_map(|path_config| path_config.path(mapping.clone().into()).ok())
+            .collect::<Vec<_>>();
+
+        match scan_usage(&paths).await {
+            Ok(usage) => {
+                usages.insert(volume, usage);
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to scan usage for project {} on volume {}: {}",
+                    mapping.project(),
+                    volume,
+                    e
+                );
+            }
+        }
+    }
+
+    usages
+}
+
+///
+/// Measure the usage of a user on every user volume that has
+/// `scan_usage` set, by scanning the user's directories
+///
+async fn scan_user_usage(
+    mapping: &UserMapping,
+) -> std::collections::HashMap<templemeads::storage::Volume, Quota> {
+    let mut usages = std::collections::HashMap::new();
+
+    let config = match cache::get_filesystem_config().await {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::warn!("Could not get filesystem config: {}", e);
+            return usages;
+        }
+    };
+
+    for (volume, volume_config) in config.get_user_volumes() {
+        if !volume_config.scan_usage() {
+            continue;
+        }
+
+        let paths = volume_config
+            .path_configs()
+            .iter()
+            .filter_map(|path_config| path_config.path(mapping.clone().into()).ok())
+            .collect::<Vec<_>>();
+
+        match scan_usage(&paths).await {
+            Ok(usage) => {
+                usages.insert(volume, usage);
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to scan usage for user {} on volume {}: {}",
+                    mapping.local_user(),
+                    volume,
+                    e
+                );
+            }
+        }
+    }
+
+    usages
+}
+
+///
+/// Sum the disk usage of the passed directories, returning it as
+/// the usage of an unlimited quota
+///
+async fn scan_usage(paths: &[std::path::PathBuf]) -> Result<Quota, Error> {
+    let mut bytes: u64 = 0;
+
+    for path in paths {
+        bytes = bytes.saturating_add(filesystem::disk_usage(path).await?);
+    }
+
+    Ok(Quota::with_usage(
+        templemeads::storage::QuotaLimit::Unlimited,
+        templemeads::storage::StorageUsage::new(templemeads::storage::StorageSize::from_bytes(
+            bytes,
+        )),
+    ))
+}
+
 ///
 /// Build a ProjectStorageReport for the given project mapping.
 ///
@@ -804,15 +1705,18 @@ pub async fn get_local_storage_report(
     let project = mapping.project();
     let mut report = ProjectStorageReport::new(project);
 
-    // Fetch project-level quotas locally
-    match get_project_quotas(mapping, expires).await {
-        Ok(quotas) => {
-            report.set_project_quotas(quotas);
-        }
+    // Fetch project-level quotas locally, adding in scanned usage for
+    // volumes that have no quota engine
+    let mut project_quotas = match get_project_quotas(mapping, expires).await {
+        Ok(quotas) => quotas,
         Err(e) => {
             tracing::warn!("Failed to get project quotas for {}: {}", mapping, e);
+            std::collections::HashMap::new()
         }
-    }
+    };
+
+    project_quotas.extend(scan_project_usage(mapping).await);
+    report.set_project_quotas(project_quotas);
 
     // Call back to the sender (cluster agent) to get the users for this project
     let user_mappings: Vec<UserMapping> = {
@@ -834,18 +1738,27 @@ pub async fn get_local_storage_report(
 
     // Fetch per-user quotas locally for each user in the project
     for user_mapping in &user_mappings {
-        match get_user_quotas(user_mapping, expires).await {
-            Ok(quotas) => {
-                report.add_user_quotas(user_mapping.user(), quotas);
-            }
+        let mut user_quotas = match get_user_quotas(user_mapping, expires).await {
+            Ok(quotas) => quotas,
             Err(e) => {
                 tracing::warn!(
                     "Failed to get user quotas for {}: {}",
                     user_mapping.local_user(),
                     e
                 );
+                std::collections::HashMap::new()
             }
-        }
+        };
+
+        user_quotas.extend(scan_user_usage(user_mapping).await);
+        report.add_user_quotas(user_mapping.user(), user_quotas);
+    }
+
+    // Remember the project and its users so that their quotas are monitored
+    cache::add_project(mapping).await;
+
+    for user_mapping in &user_mappings {
+        cache::add_user(user_mapping).await;
     }
 
     // Record portal-user → local-username mappings
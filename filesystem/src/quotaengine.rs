@@ -10,10 +10,12 @@
 use anyhow::Result;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use std::path::Path;
 use templemeads::grammar::{ProjectMapping, UserMapping};
 use templemeads::storage::{Quota, QuotaLimit, Volume};
 use templemeads::Error;
 
+use crate::cephquotaengine::{CephEngine, CephQuotaEngineConfig};
 use crate::fakequotaengine::{FakeEngine, FakeQuotaEngineConfig};
 use crate::linuxquotaengine::{LinuxEngine, LinuxQuotaEngineConfig};
 use crate::lustreengine::{LustreEngine, LustreEngineConfig};
@@ -30,10 +32,11 @@ pub enum QuotaEngineConfig {
     Lustre(LustreEngineConfig),
     #[serde(rename = "linux")]
     Linux(LinuxQuotaEngineConfig),
+    #[serde(rename = "ceph")]
+    Ceph(CephQuotaEngineConfig),
     #[serde(rename = "fake")]
     Fake(FakeQuotaEngineConfig),
     // Future backends can be added here:
-    // Ceph(CephEngineConfig),
     // Vast(VastEngineConfig),
 }
 
@@ -51,6 +54,10 @@ impl QuotaEngineConfig {
                 let engine = LinuxEngine::new(config.clone())?;
                 engine.initialize().await
             }
+            QuotaEngineConfig::Ceph(config) => {
+                let engine = CephEngine::new(config.clone())?;
+                engine.initialize().await
+            }
             QuotaEngineConfig::Fake(config) => {
                 let engine = FakeEngine::new(config.clone())?;
                 engine.initialize().await
@@ -58,6 +65,31 @@ impl QuotaEngineConfig {
         }
     }
 
+    ///
+    /// Check that the engine can reach the filesystem that holds
+    /// the passed path
+    ///
+    pub async fn check(&self, path: &Path, expires: &chrono::DateTime<Utc>) -> Result<(), Error> {
+        match self {
+            QuotaEngineConfig::Lustre(config) => {
+                let engine = LustreEngine::new(config.clone())?;
+                engine.check(path, expires).await
+            }
+            QuotaEngineConfig::Linux(config) => {
+                let engine = LinuxEngine::new(config.clone())?;
+                engine.check(expires).await
+            }
+            QuotaEngineConfig::Ceph(config) => {
+                let engine = CephEngine::new(config.clone())?;
+                engine.check(path, expires).await
+            }
+            QuotaEngineConfig::Fake(config) => {
+                let engine = FakeEngine::new(config.clone())?;
+                engine.check().await
+            }
+        }
+    }
+
     ///
     /// Set a user quota
     ///
@@ -82,6 +114,12 @@ impl QuotaEngineConfig {
                     .set_user_quota(mapping, volume, volume_config, limit, expires)
                     .await
             }
+            QuotaEngineConfig::Ceph(config) => {
+                let engine = CephEngine::new(config.clone())?;
+                engine
+                    .set_user_quota(mapping, volume, volume_config, limit, expires)
+                    .await
+            }
             QuotaEngineConfig::Fake(config) => {
                 let engine = FakeEngine::new(config.clone())?;
                 engine
@@ -115,6 +153,12 @@ impl QuotaEngineConfig {
                     .set_project_quota(mapping, volume, volume_config, limit, expires)
                     .await?)
             }
+            QuotaEngineConfig::Ceph(config) => {
+                let engine = CephEngine::new(config.clone())?;
+                Ok(engine
+                    .set_project_quota(mapping, volume, volume_config, limit, expires)
+                    .await?)
+            }
             QuotaEngineConfig::Fake(config) => {
                 let engine = FakeEngine::new(config.clone())?;
                 Ok(engine
@@ -147,6 +191,12 @@ impl QuotaEngineConfig {
                     .get_user_quota(mapping, volume, volume_config, expires)
                     .await?)
             }
+            QuotaEngineConfig::Ceph(config) => {
+                let engine = CephEngine::new(config.clone())?;
+                Ok(engine
+                    .get_user_quota(mapping, volume, volume_config, expires)
+                    .await?)
+            }
             QuotaEngineConfig::Fake(config) => {
                 let engine = FakeEngine::new(config.clone())?;
                 Ok(engine
@@ -179,6 +229,12 @@ impl QuotaEngineConfig {
                     .get_project_quota(mapping, volume, volume_config, expires)
                     .await?)
             }
+            QuotaEngineConfig::Ceph(config) => {
+                let engine = CephEngine::new(config.clone())?;
+                Ok(engine
+                    .get_project_quota(mapping, volume, volume_config, expires)
+                    .await?)
+            }
             QuotaEngineConfig::Fake(config) => {
                 let engine = FakeEngine::new(config.clone())?;
                 Ok(engine
@@ -211,6 +267,12 @@ impl QuotaEngineConfig {
                     .clear_user_quota(mapping, volume, volume_config, expires)
                     .await
             }
+            QuotaEngineConfig::Ceph(config) => {
+                let engine = CephEngine::new(config.clone())?;
+                engine
+                    .clear_user_quota(mapping, volume, volume_config, expires)
+                    .await
+            }
             QuotaEngineConfig::Fake(config) => {
                 let engine = FakeEngine::new(config.clone())?;
                 engine
@@ -243,6 +305,12 @@ impl QuotaEngineConfig {
                     .clear_project_quota(mapping, volume, volume_config, expires)
                     .await
             }
+            QuotaEngineConfig::Ceph(config) => {
+                let engine = CephEngine::new(config.clone())?;
+                engine
+                    .clear_project_quota(mapping, volume, volume_config, expires)
+                    .await
+            }
             QuotaEngineConfig::Fake(config) => {
                 let engine = FakeEngine::new(config.clone())?;
                 engine
@@ -273,6 +341,11 @@ impl QuotaEngineConfig {
                 // the filesystem path already stored in the engine config.
                 Ok(())
             }
+            QuotaEngineConfig::Ceph(_config) => {
+                // Ceph quotas are set on the volume's directories, so need
+                // no per-volume configuration.
+                Ok(())
+            }
             QuotaEngineConfig::Fake(_config) => {
                 // Fake quota engine requires no per-volume configuration.
                 Ok(())
@@ -0,0 +1,213 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Snapshot engines used to protect project data before it is recycled.
+//!
+//! A project volume can name a snapshot engine (from the `snapshot_engines`
+//! map of the filesystem config). When the project is removed, a snapshot
+//! is taken of each of its directories before they are moved into
+//! `.recycle`, and the snapshot names are returned as the job result so
+//! that the data can be restored if the removal was a mistake.
+//!
+//! Each engine's commands are configurable so that they can be prefixed
+//! with e.g. `"sudo"` or `"docker exec slurmctld"`.
+//!
+//! # TOML configuration example
+//!
+//! ```toml
+//! [snapshot_engines.zfs]
+//! type = "zfs"
+//! zfs  = "sudo zfs"
+//!
+//! [snapshot_engines.cephfs]
+//! type  = "ceph"
+//! mkdir = "sudo mkdir"
+//!
+//! [snapshot_engines.lustre]
+//! type   = "lustre"
+//! lctl   = "sudo lctl"
+//! fsname = "scratch"
+//! ```
+
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use templemeads::exec;
+use templemeads::Error;
+
+fn default_zfs_command() -> String {
+    "zfs".to_string()
+}
+
+fn default_mkdir_command() -> String {
+    "mkdir".to_string()
+}
+
+fn default_lctl_command() -> String {
+    "lctl".to_string()
+}
+
+/// Configuration for ZFS snapshots. The snapshot is taken of the
+/// dataset that contains each directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZfsSnapshotConfig {
+    /// The `zfs` command (default: `"zfs"`).
+    #[serde(default = "default_zfs_command")]
+    zfs: String,
+}
+
+/// Configuration for CephFS snapshots. These are created by making
+/// a directory inside the hidden `.snap` directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CephSnapshotConfig {
+    /// The `mkdir` command (default: `"mkdir"`).
+    #[serde(default = "default_mkdir_command")]
+    mkdir: String,
+}
+
+/// Configuration for Lustre snapshots. These snapshot the whole
+/// filesystem, so only one is taken per removal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LustreSnapshotConfig {
+    /// The `lctl` command (default: `"lctl"`).
+    #[serde(default = "default_lctl_command")]
+    lctl: String,
+
+    /// The name of the Lustre filesystem to snapshot
+    fsname: String,
+}
+
+/// Configuration for creating snapshots, with one variant for
+/// each supported filesystem type.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum SnapshotEngineConfig {
+    #[serde(rename = "zfs")]
+    Zfs(ZfsSnapshotConfig),
+    #[serde(rename = "ceph")]
+    Ceph(CephSnapshotConfig),
+    #[serde(rename = "lustre")]
+    Lustre(LustreSnapshotConfig),
+}
+
+impl SnapshotEngineConfig {
+    ///
+    /// Return whether one snapshot covers every directory, in which
+    /// case only a single snapshot is needed per removal
+    ///
+    pub fn is_filesystem_wide(&self) -> bool {
+        matches!(self, SnapshotEngineConfig::Lustre(_))
+    }
+
+    ///
+    /// Take a snapshot called `name` that covers the passed directory,
+    /// returning the full name of the snapshot that was created
+    ///
+    pub async fn snapshot(&self, path: &Path, name: &str) -> Result<String, Error> {
+        let path_str = path.to_string_lossy();
+
+        match self {
+            SnapshotEngineConfig::Zfs(config) => {
+                let dataset = run_command(
+                    &config.zfs,
+                    &["list", "-H", "-o", "name", path_str.as_ref()],
+                )
+                .await?;
+
+                let dataset = dataset.trim();
+
+                if dataset.is_empty() {
+                    return Err(Error::Failed(format!(
+                        "Could not find the ZFS dataset containing '{}'",
+                        path_str
+                    )));
+                }
+
+                let snapshot = format!("{}@{}", dataset, name);
+                run_command(&config.zfs, &["snapshot", &snapshot]).await?;
+                Ok(snapshot)
+            }
+            SnapshotEngineConfig::Ceph(config) => {
+                let snapshot = path.join(".snap").join(name);
+                run_command(&config.mkdir, &[snapshot.to_string_lossy().as_ref()]).await?;
+                Ok(snapshot.to_string_lossy().to_string())
+            }
+            SnapshotEngineConfig::Lustre(config) => {
+                run_command(
+                    &config.lctl,
+                    &["snapshot_create", "-F", &config.fsname, "-n", name],
+                )
+                .await?;
+                Ok(format!("{}:{}", config.fsname, name))
+            }
+        }
+    }
+}
+
+///
+/// Return the name to use for a snapshot taken before the passed
+/// project is removed
+///
+pub fn snapshot_name(project: &str) -> String {
+    let project: String = project
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    format!(
+        "openportal-{}-{}",
+        project,
+        Utc::now().format("%Y%m%d%H%M%S")
+    )
+}
+
+/// Run an external command (which may include a multi-token prefix such as
+/// `"sudo"`) with the given extra arguments, returning the captured stdout.
+async fn run_command(program: &str, args: &[&str]) -> Result<String, Error> {
+    if program.trim().is_empty() {
+        return Err(Error::Misconfigured(format!(
+            "Snapshot command is empty: '{}'",
+            program
+        )));
+    }
+
+    let command = exec::Command::parse(program).args(args);
+    tracing::info!("Snapshot executing: {}", command);
+
+    command
+        .run()
+        .await
+        .map_err(|failure| Error::Failed(failure.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_name() {
+        let name = snapshot_name("proj.brics");
+        assert!(name.starts_with("openportal-proj_brics-"));
+        assert!(!name.contains('.'));
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config =
+            toml::from_str::<SnapshotEngineConfig>("type = \"lustre\"\nfsname = \"scratch\"");
+        assert!(matches!(config, Ok(config) if config.is_filesystem_wide()));
+
+        let config = toml::from_str::<SnapshotEngineConfig>("type = \"zfs\"");
+        assert!(matches!(config, Ok(config) if !config.is_filesystem_wide()));
+
+        // lustre snapshots need the filesystem name
+        assert!(toml::from_str::<SnapshotEngineConfig>("type = \"lustre\"").is_err());
+    }
+}
@@ -12,11 +12,14 @@ use std::path::{Path, PathBuf};
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use templemeads::configcheck::ConfigCheck;
 use templemeads::grammar::{ProjectMapping, UserMapping, UserOrProjectMapping};
 use templemeads::storage::{QuotaLimit, Volume};
 use templemeads::Error;
 
+use crate::archive::ArchiveConfig;
 use crate::quotaengine::QuotaEngineConfig;
+use crate::snapshot::SnapshotEngineConfig;
 
 use once_cell::sync::Lazy;
 use regex::Regex;
@@ -94,6 +97,31 @@ fn default_project_subpath() -> String {
     "{project}".to_string()
 }
 
+/// Helper function for default recycle purge interval
+fn default_recycle_purge_interval_hours() -> u64 {
+    24
+}
+
+/// Helper function for default quota alert thresholds (percent used)
+fn default_quota_alert_thresholds() -> Vec<u8> {
+    vec![80, 95]
+}
+
+/// Helper function for default quota check interval
+fn default_quota_check_interval_minutes() -> u64 {
+    60
+}
+
+/// Helper function for default volume health check interval
+fn default_volume_check_interval_minutes() -> u64 {
+    5
+}
+
+/// Helper function for default number of concurrent filesystem operations
+fn default_max_concurrent_operations() -> usize {
+    8
+}
+
 /// Helper function for default user permissions
 fn default_user_permissions() -> StringOrVec {
     StringOrVec::Single("0755".to_string())
@@ -104,6 +132,114 @@ fn default_project_permissions() -> StringOrVec {
     StringOrVec::Single("2770".to_string())
 }
 
+/// The type of ACL entries that are set on directories
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AclFormat {
+    /// POSIX ACLs, set with `setfacl -m` (e.g. "g:{group}-managers:rwx")
+    #[default]
+    Posix,
+
+    /// NFSv4 ACLs, set with `nfs4_setfacl -a` (e.g. "A:g:{group}@example.org:rwaDxtTnNcy")
+    Nfs4,
+}
+
+/// Validate that ACL templates only use placeholders that can be expanded
+fn validate_acl_placeholders(acls: &[String], allow_user: bool) -> Result<(), Error> {
+    for acl in acls {
+        if acl.trim().is_empty() {
+            return Err(Error::Misconfigured(
+                "ACL entries cannot be empty".to_string(),
+            ));
+        }
+
+        if !allow_user && acl.contains("{user}") {
+            return Err(Error::Misconfigured(format!(
+                "ACL '{}' cannot use the {{user}} placeholder on a project volume",
+                acl
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// An entry in the skeleton tree that is created inside every new
+/// directory of a volume. This is a file if `contents` is set, and
+/// a directory otherwise.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SkeletonEntry {
+    /// Path relative to the new directory, e.g. "data" or "README.md".
+    /// Placeholders: {project}, {group}, {user}
+    path: String,
+
+    /// Contents of the file. Placeholders: {project}, {group}, {user}
+    contents: Option<String>,
+
+    /// Permissions of the entry
+    /// Default: the permissions of the volume for directories, "0644" for files
+    permissions: Option<String>,
+
+    /// Owner of the entry, either "root" or "{user}"
+    /// Default: the owner of the volume directory
+    owner: Option<String>,
+}
+
+impl SkeletonEntry {
+    /// Return the path of this entry relative to the new directory
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Return the file contents, or None if this is a directory
+    pub fn contents(&self) -> Option<&str> {
+        self.contents.as_deref()
+    }
+
+    /// Return the permissions, if they differ from the default
+    pub fn permissions(&self) -> Option<&str> {
+        self.permissions.as_deref()
+    }
+
+    /// Return the owner, if it differs from the default
+    pub fn owner(&self) -> Option<&str> {
+        self.owner.as_deref()
+    }
+}
+
+/// Validate that the skeleton entries stay inside the new directory
+/// and only use owners that can be resolved
+fn validate_skeleton(skeleton: &[SkeletonEntry], allow_user: bool) -> Result<(), Error> {
+    for entry in skeleton {
+        let path = Path::new(&entry.path);
+
+        if entry.path.trim().is_empty()
+            || path.is_absolute()
+            || path
+                .components()
+                .any(|c| matches!(c, std::path::Component::ParentDir))
+        {
+            return Err(Error::Misconfigured(format!(
+                "Skeleton path '{}' must be a relative path inside the directory",
+                entry.path
+            )));
+        }
+
+        match entry.owner.as_deref() {
+            None | Some("root") => {}
+            Some("{user}") if allow_user => {}
+            Some(owner) => {
+                return Err(Error::Misconfigured(format!(
+                    "Skeleton entry '{}' has unsupported owner '{}'",
+                    entry.path, owner
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
 /// Top-level filesystem configuration.
 ///
 /// This is the main configuration structure that gets deserialized from TOML.
@@ -114,6 +250,14 @@ pub struct FilesystemConfig {
     #[serde(default)]
     quota_engines: HashMap<String, QuotaEngineConfig>,
 
+    /// Named snapshot engine configurations that can be referenced by
+    /// project volumes
+    #[serde(default)]
+    snapshot_engines: HashMap<String, SnapshotEngineConfig>,
+
+    /// Optional object storage to which removed projects are archived
+    archive: Option<ArchiveConfig>,
+
     /// User volume configurations (e.g., home directories)
     #[serde(default)]
     user_volumes: HashMap<Volume, UserVolumeConfig>,
@@ -121,6 +265,39 @@ pub struct FilesystemConfig {
     /// Project volume configurations (e.g., shared project directories)
     #[serde(default)]
     project_volumes: HashMap<Volume, ProjectVolumeConfig>,
+
+    /// How often (in hours) to purge expired recycled directories
+    /// Default: 24
+    #[serde(default = "default_recycle_purge_interval_hours")]
+    recycle_purge_interval_hours: u64,
+
+    /// Whether the background purge only reports what it would delete
+    /// Default: false
+    #[serde(default)]
+    recycle_purge_dry_run: bool,
+
+    /// Percentages of a quota at which a health warning is raised
+    /// Default: [80, 95]
+    #[serde(default = "default_quota_alert_thresholds")]
+    quota_alert_thresholds: Vec<u8>,
+
+    /// How often (in minutes) to check usage against the alert thresholds.
+    /// Set to 0 to disable quota monitoring
+    /// Default: 60
+    #[serde(default = "default_quota_check_interval_minutes")]
+    quota_check_interval_minutes: u64,
+
+    /// How often (in minutes) to check that every volume is mounted,
+    /// writable and reachable by its quota engine. Set to 0 to disable
+    /// volume health checks
+    /// Default: 5
+    #[serde(default = "default_volume_check_interval_minutes")]
+    volume_check_interval_minutes: u64,
+
+    /// Maximum number of directories that are provisioned at the same time
+    /// Default: 8
+    #[serde(default = "default_max_concurrent_operations")]
+    max_concurrent_operations: usize,
 }
 
 impl FilesystemConfig {
@@ -128,8 +305,16 @@ impl FilesystemConfig {
     pub fn new() -> Self {
         Self {
             quota_engines: HashMap::new(),
+            snapshot_engines: HashMap::new(),
+            archive: None,
             user_volumes: HashMap::new(),
             project_volumes: HashMap::new(),
+            recycle_purge_interval_hours: default_recycle_purge_interval_hours(),
+            recycle_purge_dry_run: false,
+            quota_alert_thresholds: default_quota_alert_thresholds(),
+            quota_check_interval_minutes: default_quota_check_interval_minutes(),
+            volume_check_interval_minutes: default_volume_check_interval_minutes(),
+            max_concurrent_operations: default_max_concurrent_operations(),
         }
     }
 
@@ -138,16 +323,48 @@ impl FilesystemConfig {
     /// This performs several checks:
     /// - Ensures at most one user volume has is_home = true
     /// - Auto-sets is_home = true if only one user volume exists
-    /// - Validates that all quota_engine references exist
+    /// - Validates that all quota_engine and snapshot_engine references exist
     /// - Validates that roots and permissions arrays have matching lengths
+    ///
+    /// Every problem is returned, not just the first
     pub fn validate(&mut self) -> Result<(), Error> {
+        let mut check = ConfigCheck::new();
+        self.validate_fields(&mut check);
+        check.into_result()
+    }
+
+    /// Check the configuration without changing it, recording every
+    /// problem against the field (e.g. `user_volumes.home`) that it is in
+    pub fn check(&self, check: &mut ConfigCheck) {
+        self.clone().validate_fields(check);
+    }
+
+    fn validate_fields(&mut self, check: &mut ConfigCheck) {
+        for threshold in self
+            .quota_alert_thresholds
+            .iter()
+            .filter(|t| **t == 0 || **t > 100)
+        {
+            check.error(
+                "quota_alert_thresholds",
+                &format!(
+                    "Quota alert threshold {} must be between 1 and 100",
+                    threshold
+                ),
+            );
+        }
+
+        self.quota_alert_thresholds.sort_unstable();
+        self.quota_alert_thresholds.dedup();
+
         // Check at most one is_home=true across user volumes
         let home_count = self.user_volumes.values().filter(|v| v.is_home()).count();
 
         if home_count > 1 {
-            return Err(Error::Misconfigured(
-                "Multiple user volumes have is_home=true. Only one user volume can be the home directory.".to_string()
-            ));
+            check.error(
+                "user_volumes",
+                "Multiple user volumes have is_home=true. Only one user volume can be the home directory.",
+            );
         }
 
         // Auto-set is_home if only one user volume
@@ -160,58 +377,65 @@ impl FilesystemConfig {
             }
         }
 
-        // Validate quota engine references in user volumes
-        for (name, vol) in &self.user_volumes {
+        // Validate user volumes (sanitize subpaths and check constraints),
+        // and that their quota engines exist and are configured for them
+        for (name, vol) in self.user_volumes.iter_mut() {
+            let field = format!("user_volumes.{}", name);
+
+            check.check(&field, vol.validate());
+
             if let Some(engine_name) = vol.quota_engine_name() {
-                if !self.quota_engines.contains_key(engine_name) {
-                    return Err(Error::Misconfigured(format!(
-                        "User volume '{}' references unknown quota engine: '{}'",
-                        name, engine_name
-                    )));
+                match self.quota_engines.get(engine_name) {
+                    Some(engine_config) => {
+                        check.check(&field, engine_config.verify_volume_config(name));
+                    }
+                    None => check.error(
+                        &field,
+                        &format!("References unknown quota engine: '{}'", engine_name),
+                    ),
                 }
             }
         }
 
-        // Validate user volumes (sanitize subpaths and check constraints)
-        for vol in self.user_volumes.values_mut() {
-            vol.validate()?;
-        }
+        // Validate project volumes in the same way, plus their
+        // snapshot engines and archiving
+        for (name, vol) in self.project_volumes.iter_mut() {
+            let field = format!("project_volumes.{}", name);
+
+            check.check(&field, vol.validate());
 
-        // Validate quota engine references in project volumes
-        for (name, vol) in &self.project_volumes {
             if let Some(engine_name) = vol.quota_engine_name() {
-                if !self.quota_engines.contains_key(engine_name) {
-                    return Err(Error::Misconfigured(format!(
-                        "Project volume '{}' references unknown quota engine: '{}'",
-                        name, engine_name
-                    )));
+                match self.quota_engines.get(engine_name) {
+                    Some(engine_config) => {
+                        check.check(&field, engine_config.verify_volume_config(name));
+                    }
+                    None => check.error(
+                        &field,
+                        &format!("References unknown quota engine: '{}'", engine_name),
+                    ),
                 }
             }
-        }
-
-        // Validate project volumes (sanitize subpaths and check constraints)
-        for vol in self.project_volumes.values_mut() {
-            vol.validate()?;
-        }
 
-        // Validate that quota engines are properly configured for their volumes
-        for (volume, config) in &self.user_volumes {
-            if let Some(engine_name) = config.quota_engine_name() {
-                if let Some(engine_config) = self.quota_engines.get(engine_name) {
-                    engine_config.verify_volume_config(volume)?;
+            if let Some(engine_name) = vol.snapshot_engine_name() {
+                if !self.snapshot_engines.contains_key(engine_name) {
+                    check.error(
+                        &field,
+                        &format!("References unknown snapshot engine: '{}'", engine_name),
+                    );
                 }
             }
-        }
 
-        for (volume, config) in &self.project_volumes {
-            if let Some(engine_name) = config.quota_engine_name() {
-                if let Some(engine_config) = self.quota_engines.get(engine_name) {
-                    engine_config.verify_volume_config(volume)?;
-                }
+            if vol.archive_on_removal() && self.archive.is_none() {
+                check.error(
+                    &field,
+                    "Sets archive_on_removal but there is no [archive] section",
+                );
             }
         }
 
-        Ok(())
+        if let Some(archive) = &self.archive {
+            check.check("archive", archive.validate());
+        }
     }
 
     /// Get the home user volume configuration
@@ -268,6 +492,75 @@ impl FilesystemConfig {
             .ok_or_else(|| Error::NotFound(format!("Quota engine '{}' not found", name)))
     }
 
+    /// Return the named snapshot engine configuration
+    pub fn get_snapshot_engine(&self, name: &str) -> Result<SnapshotEngineConfig, Error> {
+        self.snapshot_engines
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(format!("Snapshot engine '{}' not found", name)))
+    }
+
+    /// Return the archive configuration, if removed projects are archived
+    pub fn archive(&self) -> Option<&ArchiveConfig> {
+        self.archive.as_ref()
+    }
+
+    /// Return the paths and retention period of every volume that
+    /// has a recycle retention period set
+    pub fn get_recycle_retentions(&self) -> Vec<(Volume, Vec<PathConfig>, u64)> {
+        let user_volumes = self.user_volumes.iter().filter_map(|(volume, config)| {
+            config
+                .recycle_retention_days()
+                .map(|days| (volume.clone(), config.path_configs(), days))
+        });
+
+        let project_volumes = self.project_volumes.iter().filter_map(|(volume, config)| {
+            config
+                .recycle_retention_days()
+                .map(|days| (volume.clone(), config.path_configs(), days))
+        });
+
+        user_volumes.chain(project_volumes).collect()
+    }
+
+    /// Return how often expired recycled directories should be purged
+    pub fn recycle_purge_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.recycle_purge_interval_hours.max(1) * 3600)
+    }
+
+    /// Return whether the background purge should only report
+    pub fn recycle_purge_dry_run(&self) -> bool {
+        self.recycle_purge_dry_run
+    }
+
+    /// Return the quota alert thresholds, in ascending order
+    pub fn quota_alert_thresholds(&self) -> &[u8] {
+        &self.quota_alert_thresholds
+    }
+
+    /// Return how often quotas should be checked against the alert
+    /// thresholds, or None if quota monitoring is disabled
+    pub fn quota_check_interval(&self) -> Option<std::time::Duration> {
+        match self.quota_check_interval_minutes {
+            0 => None,
+            minutes => Some(std::time::Duration::from_secs(minutes * 60)),
+        }
+    }
+
+    /// Return how often the health of each volume should be checked,
+    /// or None if volume health checks are disabled
+    pub fn volume_check_interval(&self) -> Option<std::time::Duration> {
+        match self.volume_check_interval_minutes {
+            0 => None,
+            minutes => Some(std::time::Duration::from_secs(minutes * 60)),
+        }
+    }
+
+    /// Return the maximum number of directories to provision at once
+    pub fn max_concurrent_operations(&self) -> usize {
+        self.max_concurrent_operations.max(1)
+    }
+
     /// Alias for get_quota_engine_config() for convenience
     pub fn get_quota_engine(&self, name: &str) -> Result<QuotaEngineConfig, Error> {
         self.get_quota_engine_config(name)
@@ -289,6 +582,10 @@ pub struct PathConfig {
     subpath: String,
     permission: String,
     link: Option<String>,
+    #[serde(default)]
+    acls: Vec<String>,
+    #[serde(default)]
+    acl_format: AclFormat,
 }
 
 impl PathConfig {
@@ -298,13 +595,67 @@ impl PathConfig {
             subpath,
             permission,
             link,
+            acls: Vec::new(),
+            acl_format: AclFormat::default(),
         }
     }
 
+    /// Return this path with the passed ACL templates
+    pub fn with_acls(mut self, acls: Vec<String>, acl_format: AclFormat) -> Self {
+        self.acls = acls;
+        self.acl_format = acl_format;
+        self
+    }
+
     pub fn permission(&self) -> &str {
         &self.permission
     }
 
+    /// Return the type of ACL entries for this path
+    pub fn acl_format(&self) -> AclFormat {
+        self.acl_format
+    }
+
+    /// Return the ACL entries for this path, with the placeholders
+    /// expanded for the passed mapping
+    pub fn acls(&self, mapping: UserOrProjectMapping) -> Vec<String> {
+        let (project, group, user) = match &mapping {
+            UserOrProjectMapping::User(user_mapping) => (
+                user_mapping.project().project().project(),
+                user_mapping.local_group().to_string(),
+                user_mapping.local_user().to_string(),
+            ),
+            UserOrProjectMapping::Project(project_mapping) => (
+                project_mapping.project().project(),
+                project_mapping.local_group().to_string(),
+                String::new(),
+            ),
+        };
+
+        self.acls
+            .iter()
+            .map(|acl| {
+                acl.replace("{project}", &project)
+                    .replace("{group}", &group)
+                    .replace("{user}", &user)
+            })
+            .collect()
+    }
+
+    /// Return the root directory of this path
+    pub fn root(&self) -> &Path {
+        Path::new(&self.root)
+    }
+
+    /// Return the number of directory levels below the root that
+    /// the subpath template expands to (e.g. 2 for "{project}/{user}")
+    pub fn depth(&self) -> usize {
+        self.subpath
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .count()
+    }
+
     pub fn project_path(&self, mapping: &ProjectMapping) -> Result<PathBuf, Error> {
         let project_name = mapping.project().project();
 
@@ -456,6 +807,30 @@ pub struct UserVolumeConfig {
     /// Optional default inode limit for quota (number of files/directories allowed)
     /// If not specified, quota engines may use a large default (e.g., 1000000)
     default_inode_limit: Option<u64>,
+
+    /// Optional number of days to keep recycled directories before they
+    /// are purged. If not specified, recycled directories are never purged
+    recycle_retention_days: Option<u64>,
+
+    /// Whether to measure usage for storage reports by scanning the
+    /// directories, for volumes that have no quota engine to ask
+    /// Default: false
+    #[serde(default)]
+    scan_usage: bool,
+
+    /// ACL entries to set on each directory when it is created or repaired.
+    /// Placeholders: {project}, {group}, {user}
+    #[serde(default)]
+    acls: Vec<String>,
+
+    /// The type of the ACL entries
+    /// Default: "posix"
+    #[serde(default)]
+    acl_format: AclFormat,
+
+    /// Subdirectories and files to create inside each new directory
+    #[serde(default)]
+    skeleton: Vec<SkeletonEntry>,
 }
 
 impl UserVolumeConfig {
@@ -507,6 +882,14 @@ impl UserVolumeConfig {
             ));
         }
 
+        // default quotas are applied by the quota engine, so are
+        // ignored if there isn't one
+        if self.default_quota.is_some() && self.quota_engine.is_none() {
+            tracing::warn!(
+                "User volume has a default quota but no quota engine, so the default quota will not be applied"
+            );
+        }
+
         // make sure that the default quota is not larger than the max quota
         if let (Some(max), Some(default)) = (&self.max_quota, &self.default_quota) {
             match (max, default) {
@@ -528,6 +911,9 @@ impl UserVolumeConfig {
             }
         }
 
+        validate_skeleton(&self.skeleton, true)?;
+        validate_acl_placeholders(&self.acls, true)?;
+
         Ok(())
     }
 
@@ -579,6 +965,21 @@ impl UserVolumeConfig {
         self.default_inode_limit
     }
 
+    /// Get the number of days that recycled directories are kept
+    pub fn recycle_retention_days(&self) -> Option<u64> {
+        self.recycle_retention_days
+    }
+
+    /// Return whether usage should be measured by scanning the directories
+    pub fn scan_usage(&self) -> bool {
+        self.scan_usage && !self.has_quota_engine()
+    }
+
+    /// Return the skeleton tree created inside each new directory
+    pub fn skeleton(&self) -> &[SkeletonEntry] {
+        &self.skeleton
+    }
+
     /// Return all of the paths for this volume
     pub fn path_configs(&self) -> Vec<PathConfig> {
         let num_roots = self.roots.len();
@@ -590,12 +991,15 @@ impl UserVolumeConfig {
                 StringOrVec::Vec(v) => v[i].clone(),
             };
 
-            paths.push(PathConfig::new(
-                self.roots[i].clone(),
-                self.subpath.clone(),
-                permission,
-                None,
-            ));
+            paths.push(
+                PathConfig::new(
+                    self.roots[i].clone(),
+                    self.subpath.clone(),
+                    permission,
+                    None,
+                )
+                .with_acls(self.acls.clone(), self.acl_format),
+            );
         }
 
         paths
@@ -626,6 +1030,16 @@ pub struct ProjectVolumeConfig {
     /// Optional name of quota engine to use (references quota_engines map)
     quota_engine: Option<String>,
 
+    /// Optional name of snapshot engine used to snapshot the project's
+    /// directories before they are recycled (references snapshot_engines map)
+    snapshot_engine: Option<String>,
+
+    /// Whether to archive the project's directories to object storage
+    /// before they are recycled (requires an [archive] section)
+    /// Default: false
+    #[serde(default)]
+    archive_on_removal: bool,
+
     /// Optional maximum size of any quota (defaults to unlimited if a quota
     /// engine is used, or to none if there is no quota engine)
     max_quota: Option<QuotaLimit>,
@@ -642,6 +1056,30 @@ pub struct ProjectVolumeConfig {
     /// If not specified, quota engines may use a large default (e.g., 1000000)
     default_inode_limit: Option<u64>,
 
+    /// Optional number of days to keep recycled directories before they
+    /// are purged. If not specified, recycled directories are never purged
+    recycle_retention_days: Option<u64>,
+
+    /// Whether to measure usage for storage reports by scanning the
+    /// directories, for volumes that have no quota engine to ask
+    /// Default: false
+    #[serde(default)]
+    scan_usage: bool,
+
+    /// ACL entries to set on each directory when it is created or repaired.
+    /// Placeholders: {project}, {group}
+    #[serde(default)]
+    acls: Vec<String>,
+
+    /// The type of the ACL entries
+    /// Default: "posix"
+    #[serde(default)]
+    acl_format: AclFormat,
+
+    /// Subdirectories and files to create inside each new directory
+    #[serde(default)]
+    skeleton: Vec<SkeletonEntry>,
+
     /// Optional symlinks to create (empty string = no link, one per root)
     /// Example: ["", "/fastwork/{project}"] for two roots
     #[serde(default)]
@@ -711,6 +1149,14 @@ impl ProjectVolumeConfig {
             )));
         }
 
+        // default quotas are applied by the quota engine, so are
+        // ignored if there isn't one
+        if self.default_quota.is_some() && self.quota_engine.is_none() {
+            tracing::warn!(
+                "Project volume has a default quota but no quota engine, so the default quota will not be applied"
+            );
+        }
+
         // make sure that the default quota is not larger than the max quota
         if let (Some(max), Some(default)) = (&self.max_quota, &self.default_quota) {
             if default > max {
@@ -721,6 +1167,9 @@ impl ProjectVolumeConfig {
             }
         }
 
+        validate_skeleton(&self.skeleton, false)?;
+        validate_acl_placeholders(&self.acls, false)?;
+
         Ok(())
     }
 
@@ -729,6 +1178,16 @@ impl ProjectVolumeConfig {
         self.quota_engine.as_deref()
     }
 
+    /// Get the snapshot engine name
+    pub fn snapshot_engine_name(&self) -> Option<&str> {
+        self.snapshot_engine.as_deref()
+    }
+
+    /// Return whether the project's directories are archived on removal
+    pub fn archive_on_removal(&self) -> bool {
+        self.archive_on_removal
+    }
+
     /// Return whether or not this volume has an attached quota engine
     pub fn has_quota_engine(&self) -> bool {
         self.quota_engine.is_some()
@@ -754,6 +1213,21 @@ impl ProjectVolumeConfig {
         self.default_inode_limit
     }
 
+    /// Get the number of days that recycled directories are kept
+    pub fn recycle_retention_days(&self) -> Option<u64> {
+        self.recycle_retention_days
+    }
+
+    /// Return whether usage should be measured by scanning the directories
+    pub fn scan_usage(&self) -> bool {
+        self.scan_usage && !self.has_quota_engine()
+    }
+
+    /// Return the skeleton tree created inside each new directory
+    pub fn skeleton(&self) -> &[SkeletonEntry] {
+        &self.skeleton
+    }
+
     /// Return all of the paths for this volume
     pub fn path_configs(&self) -> Vec<PathConfig> {
         let num_roots = self.roots.len();
@@ -776,12 +1250,15 @@ impl ProjectVolumeConfig {
                 None
             };
 
-            paths.push(PathConfig::new(
-                self.roots[i].clone(),
-                self.subpath.clone(),
-                permission,
-                link,
-            ));
+            paths.push(
+                PathConfig::new(
+                    self.roots[i].clone(),
+                    self.subpath.clone(),
+                    permission,
+                    link,
+                )
+                .with_acls(self.acls.clone(), self.acl_format),
+            );
         }
         paths
     }
@@ -871,6 +1348,39 @@ mod tests {
         assert!(validate_subpath_placeholders("{user}", true, true).is_err());
     }
 
+    #[test]
+    fn test_validate_acl_placeholders() {
+        let acls = vec!["g:{group}-managers:rwx".to_string()];
+        assert!(validate_acl_placeholders(&acls, false).is_ok());
+
+        let acls = vec!["u:{user}:rwx".to_string()];
+        assert!(validate_acl_placeholders(&acls, true).is_ok());
+        assert!(validate_acl_placeholders(&acls, false).is_err());
+
+        assert!(validate_acl_placeholders(&[" ".to_string()], true).is_err());
+    }
+
+    #[test]
+    fn test_validate_skeleton() {
+        let entry = |path: &str, owner: Option<&str>| SkeletonEntry {
+            path: path.to_string(),
+            contents: None,
+            permissions: None,
+            owner: owner.map(|o| o.to_string()),
+        };
+
+        assert!(
+            validate_skeleton(&[entry("data", None), entry("shared/docs", None)], false).is_ok()
+        );
+        assert!(validate_skeleton(&[entry("/etc/passwd", None)], false).is_err());
+        assert!(validate_skeleton(&[entry("../other", None)], false).is_err());
+        assert!(validate_skeleton(&[entry("", None)], false).is_err());
+
+        assert!(validate_skeleton(&[entry("data", Some("{user}"))], true).is_ok());
+        assert!(validate_skeleton(&[entry("data", Some("{user}"))], false).is_err());
+        assert!(validate_skeleton(&[entry("data", Some("nobody"))], true).is_err());
+    }
+
     #[test]
     fn test_validate_subpath_placeholders_neither_required() {
         assert!(validate_subpath_placeholders("{project}/{user}", false, false).is_ok());
@@ -0,0 +1,319 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Health checks of the volumes managed by the filesystem agent.
+//!
+//! Each volume is checked periodically to make sure that its mount
+//! point (if configured) is mounted, that every root directory exists
+//! and is writable, and that its quota engine (if any) can be reached.
+//! Problems are raised as warnings in the agent's health. A volume that
+//! is unmounted, missing or read-only is critical, and new projects or
+//! users are not provisioned onto it until it passes a later check.
+
+use anyhow::Result;
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use templemeads::health;
+use templemeads::storage::Volume;
+use templemeads::Error;
+
+use crate::cache;
+use crate::filesystem;
+use crate::quotaengine::QuotaEngineConfig;
+
+/// The critical problems of each unhealthy volume. This uses a std
+/// Mutex so that it can be read without awaiting.
+static CRITICAL: Lazy<std::sync::Mutex<HashMap<Volume, String>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+/// The result of checking a single volume
+#[derive(Debug, Clone, Default)]
+struct VolumeStatus {
+    /// Problems that stop the volume from being provisioned
+    critical: Vec<String>,
+
+    /// Problems that should be investigated, but which do not
+    /// stop provisioning
+    warnings: Vec<String>,
+}
+
+impl VolumeStatus {
+    ///
+    /// Return the health warning to raise for the passed volume,
+    /// or None if the volume is healthy
+    ///
+    fn message(&self, volume: &Volume) -> Option<String> {
+        let problems: Vec<&str> = self
+            .critical
+            .iter()
+            .chain(self.warnings.iter())
+            .map(|p| p.as_str())
+            .collect();
+
+        if problems.is_empty() {
+            return None;
+        }
+
+        let severity = match self.critical.is_empty() {
+            true => "degraded",
+            false => "critical",
+        };
+
+        Some(format!(
+            "Volume {} is {}: {}",
+            volume,
+            severity,
+            problems.join("; ")
+        ))
+    }
+}
+
+///
+/// Check a single volume, made up of the passed root directories
+///
+async fn check_volume(
+    roots: &[PathBuf],
+    mount_point: Option<&str>,
+    engine: Option<(&str, QuotaEngineConfig)>,
+) -> VolumeStatus {
+    let mut status = VolumeStatus::default();
+
+    if let Some(mount_point) = mount_point {
+        match filesystem::is_mount_point(&PathBuf::from(mount_point)).await {
+            Ok(true) => {}
+            Ok(false) => status
+                .critical
+                .push(format!("'{}' is not mounted", mount_point)),
+            Err(e) => status.critical.push(format!(
+                "could not check the mount at '{}': {}",
+                mount_point, e
+            )),
+        }
+    }
+
+    // there is no point probing the roots of an unmounted volume,
+    // as this would only test the directory underneath the mount
+    if status.critical.is_empty() {
+        for root in roots {
+            let root_str = root.to_string_lossy();
+
+            match filesystem::dir_exists(root).await {
+                Ok(true) => match filesystem::is_writable(root).await {
+                    Ok(true) => {}
+                    Ok(false) => status
+                        .critical
+                        .push(format!("'{}' is not writable", root_str)),
+                    Err(e) => status
+                        .critical
+                        .push(format!("could not write to '{}': {}", root_str, e)),
+                },
+                Ok(false) => status
+                    .critical
+                    .push(format!("'{}' does not exist", root_str)),
+                Err(e) => status
+                    .critical
+                    .push(format!("could not check '{}': {}", root_str, e)),
+            }
+        }
+    }
+
+    if let Some((engine_name, engine)) = engine {
+        let path = match mount_point {
+            Some(mount_point) => Some(PathBuf::from(mount_point)),
+            None => roots.first().cloned(),
+        };
+
+        if let Some(path) = path {
+            let expires = Utc::now() + chrono::Duration::minutes(5);
+
+            if let Err(e) = engine.check(&path, &expires).await {
+                status.warnings.push(format!(
+                    "quota engine '{}' is unreachable: {}",
+                    engine_name, e
+                ));
+            }
+        }
+    }
+
+    status
+}
+
+///
+/// Record the result of checking the passed volume, raising or
+/// clearing its health warning
+///
+fn record(volume: &Volume, status: &VolumeStatus) {
+    let key = format!("volume:{}", volume);
+
+    match status.message(volume) {
+        Some(message) => {
+            tracing::warn!("{}", message);
+            health::set_warning(&key, &message);
+            health::set_dependency(&key, false, &message);
+        }
+        None => {
+            health::clear_warning(&key);
+            health::set_dependency(&key, true, "mounted and writable");
+        }
+    }
+
+    match CRITICAL.lock() {
+        Ok(mut critical) => {
+            if status.critical.is_empty() {
+                critical.remove(volume);
+            } else {
+                critical.insert(volume.clone(), status.critical.join("; "));
+            }
+        }
+        Err(e) => {
+            tracing::error!("Could not lock volume health: {}", e);
+        }
+    }
+}
+
+///
+/// Check the health of every project and user volume
+///
+pub async fn check_volumes() -> Result<(), Error> {
+    let config = cache::get_filesystem_config().await?;
+
+    let mut volumes = Vec::new();
+
+    for (volume, volume_config) in config.get_project_volumes() {
+        let roots: Vec<PathBuf> = volume_config
+            .path_configs()
+            .iter()
+            .map(|p| p.root().to_path_buf())
+            .collect();
+
+        volumes.push((
+            volume,
+            roots,
+            volume_config.mount_point().map(|m| m.to_string()),
+            volume_config.quota_engine_name().map(|e| e.to_string()),
+        ));
+    }
+
+    for (volume, volume_config) in config.get_user_volumes() {
+        let roots: Vec<PathBuf> = volume_config
+            .path_configs()
+            .iter()
+            .map(|p| p.root().to_path_buf())
+            .collect();
+
+        volumes.push((
+            volume,
+            roots,
+            volume_config.mount_point().map(|m| m.to_string()),
+            volume_config.quota_engine_name().map(|e| e.to_string()),
+        ));
+    }
+
+    for (volume, mut roots, mount_point, engine_name) in volumes {
+        roots.sort();
+        roots.dedup();
+
+        let engine = match &engine_name {
+            Some(engine_name) => match config.get_quota_engine(engine_name) {
+                Ok(engine) => Some((engine_name.as_str(), engine)),
+                Err(e) => {
+                    tracing::error!("Could not get quota engine '{}': {}", engine_name, e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let status = check_volume(&roots, mount_point.as_deref(), engine).await;
+        record(&volume, &status);
+    }
+
+    Ok(())
+}
+
+///
+/// Return an error if any of the passed volumes failed its last
+/// health check in a way that means it cannot be provisioned
+///
+pub fn assert_healthy<'a>(volumes: impl IntoIterator<Item = &'a Volume>) -> Result<(), Error> {
+    let critical = match CRITICAL.lock() {
+        Ok(critical) => critical,
+        Err(e) => {
+            tracing::error!("Could not lock volume health: {}", e);
+            return Ok(());
+        }
+    };
+
+    let problems: Vec<String> = volumes
+        .into_iter()
+        .filter_map(|volume| {
+            critical
+                .get(volume)
+                .map(|problem| format!("{}: {}", volume, problem))
+        })
+        .collect();
+
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(Error::Unavailable(format!(
+            "Cannot provision onto unhealthy volumes - {}",
+            problems.join(", ")
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message() {
+        let volume = Volume::new("scratch");
+
+        assert!(VolumeStatus::default().message(&volume).is_none());
+
+        let status = VolumeStatus {
+            critical: vec![],
+            warnings: vec!["quota engine 'lustre' is unreachable".to_string()],
+        };
+
+        assert!(matches!(status.message(&volume), Some(m) if m.contains("degraded")));
+
+        let status = VolumeStatus {
+            critical: vec!["'/scratch' is not mounted".to_string()],
+            warnings: status.warnings,
+        };
+
+        assert!(
+            matches!(status.message(&volume), Some(m) if m.contains("critical")
+            && m.contains("not mounted") && m.contains("unreachable"))
+        );
+    }
+
+    #[test]
+    fn test_assert_healthy() {
+        let healthy = Volume::new("test-healthy");
+        let unmounted = Volume::new("test-unmounted");
+
+        record(&healthy, &VolumeStatus::default());
+        record(
+            &unmounted,
+            &VolumeStatus {
+                critical: vec!["'/unmounted' is not mounted".to_string()],
+                warnings: vec![],
+            },
+        );
+
+        assert!(assert_healthy([&healthy]).is_ok());
+        assert!(matches!(
+            assert_healthy([&healthy, &unmounted]),
+            Err(Error::Unavailable(m)) if m.contains("test-unmounted")
+        ));
+
+        // the volume recovers once it passes a later check
+        record(&unmounted, &VolumeStatus::default());
+        assert!(assert_healthy([&healthy, &unmounted]).is_ok());
+    }
+}
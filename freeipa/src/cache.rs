@@ -20,9 +20,15 @@ struct Database {
     users: HashMap<UserIdentifier, IPAUser>,
     groups: HashMap<ProjectIdentifier, IPAGroup>,
     system_groups: Vec<IPAGroup>,
+    secondary_groups: Vec<IPAGroup>,
+    hbac_host_groups: Vec<String>,
+    hbac_services: Vec<String>,
     instance_groups: HashMap<Peer, Vec<IPAGroup>>,
     users_in_group: HashMap<ProjectIdentifier, HashSet<UserIdentifier>>,
     user_mutexes: HashMap<UserIdentifier, Arc<Mutex<()>>>,
+    max_ssh_keys: usize,
+    stage_users: bool,
+    id_ranges: HashMap<String, (u32, u32)>,
 }
 
 static CACHE: Lazy<RwLock<Database>> = Lazy::new(|| RwLock::new(Database::default()));
@@ -253,6 +259,118 @@ pub async fn set_system_groups(groups: &[IPAGroup]) -> Result<(), Error> {
     Ok(())
 }
 
+///
+/// Return the secondary groups that users can be added to and removed
+/// from individually
+///
+pub async fn get_secondary_groups() -> Result<Vec<IPAGroup>, Error> {
+    let cache = CACHE.read().await;
+    Ok(cache.secondary_groups.clone())
+}
+
+///
+/// Set the secondary groups that users can be added to and removed
+/// from individually
+///
+pub async fn set_secondary_groups(groups: &[IPAGroup]) -> Result<(), Error> {
+    let mut cache = CACHE.write().await;
+    cache.secondary_groups = groups.to_vec();
+    tracing::info!("Setting secondary groups to {:?}", cache.secondary_groups);
+    Ok(())
+}
+
+///
+/// Set the host groups that each project's group is given access to via
+/// its HBAC rule, and the HBAC services that the rule allows. No rules
+/// are managed if there are no host groups, and all services are allowed
+/// if there are no services
+///
+pub async fn set_hbac(host_groups: &[String], services: &[String]) -> Result<(), Error> {
+    let mut cache = CACHE.write().await;
+    cache.hbac_host_groups = host_groups.to_vec();
+    cache.hbac_services = services.to_vec();
+    Ok(())
+}
+
+///
+/// Return the host groups that each project's group is given access to
+///
+pub async fn get_hbac_host_groups() -> Result<Vec<String>, Error> {
+    let cache = CACHE.read().await;
+    Ok(cache.hbac_host_groups.clone())
+}
+
+///
+/// Return the HBAC services that each project's HBAC rule allows
+///
+pub async fn get_hbac_services() -> Result<Vec<String>, Error> {
+    let cache = CACHE.read().await;
+    Ok(cache.hbac_services.clone())
+}
+
+///
+/// Set the maximum number of SSH public keys that each user can have
+///
+pub async fn set_max_ssh_keys(max_ssh_keys: usize) -> Result<(), Error> {
+    let mut cache = CACHE.write().await;
+    cache.max_ssh_keys = max_ssh_keys;
+    Ok(())
+}
+
+///
+/// Return the maximum number of SSH public keys that each user can have
+///
+pub async fn get_max_ssh_keys() -> Result<usize, Error> {
+    let cache = CACHE.read().await;
+    Ok(cache.max_ssh_keys)
+}
+
+///
+/// Set whether new users should be added as FreeIPA stage users, which
+/// need to be activated before they can log in
+///
+pub async fn set_stage_users(stage_users: bool) -> Result<(), Error> {
+    let mut cache = CACHE.write().await;
+    cache.stage_users = stage_users;
+    Ok(())
+}
+
+///
+/// Return whether new users should be added as FreeIPA stage users
+///
+pub async fn get_stage_users() -> Result<bool, Error> {
+    let cache = CACHE.read().await;
+    Ok(cache.stage_users)
+}
+
+///
+/// Set the ranges of uids and gids from which the ids of users and
+/// groups created for each portal are allocated
+///
+pub async fn set_id_ranges(id_ranges: &HashMap<String, (u32, u32)>) -> Result<(), Error> {
+    let mut cache = CACHE.write().await;
+    cache.id_ranges = id_ranges.clone();
+    Ok(())
+}
+
+///
+/// Return the range of ids that are allocated to users and groups
+/// created for the passed portal, or None if FreeIPA should
+/// choose the ids itself
+///
+pub async fn get_id_range(portal: &str) -> Result<Option<(u32, u32)>, Error> {
+    let cache = CACHE.read().await;
+    Ok(cache.id_ranges.get(portal).cloned())
+}
+
+///
+/// Return the ranges of ids for all portals
+///
+pub async fn get_id_ranges() -> Result<HashMap<String, (u32, u32)>, Error> {
+    let cache = CACHE.read().await;
+    Ok(cache.id_ranges.clone())
+}
+
 ///
 /// Set the list of all instance groups that should be used for each
 /// instance that connects to this agent. These groups should be added
@@ -4,7 +4,7 @@
 use anyhow::Context;
 use anyhow::Result;
 use chrono::Utc;
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use rand::seq::IteratorRandom;
 use rand::SeedableRng;
 use reqwest::{cookie::CookieStore, cookie::Jar, Client};
@@ -14,13 +14,18 @@ use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::time::Duration;
 use templemeads::grammar::{
-    PortalIdentifier, ProjectIdentifier, ProjectMapping, UserIdentifier, UserMapping,
+    parse_ssh_public_key, PortalIdentifier, ProjectIdentifier, ProjectMapping, UserDetails,
+    UserIdentifier, UserMapping,
 };
+use templemeads::health;
 use templemeads::job::assert_not_expired;
+use templemeads::protection::{ProtectionPolicy, ProtectionStatus};
+use templemeads::reconcile::ReconciliationReport;
 use templemeads::Error;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, RwLock};
 
 use templemeads::agent::Peer;
+use templemeads::circuitbreaker::CircuitBreaker;
 
 use crate::cache;
 
@@ -54,8 +59,23 @@ where
         args,
         kwargs
     );
+
+    // reads can go to replicas, while anything else must go to a
+    // server that accepts writes
+    let read_only = is_read_only(func);
+
+    if !read_only {
+        *LAST_WRITE.write().await = Some(Utc::now());
+    }
+
+    // fail fast if FreeIPA is down
+    let breaker = CircuitBreaker::new(BREAKER);
+    breaker.check()?;
+
     tracing::debug!("Getting a connected server...");
-    let mut lock = get_connected_server(expires).await?;
+    let mut lock = get_connected_server(read_only, expires)
+        .await
+        .inspect_err(|e| breaker.failure(e))?;
     tracing::debug!(
         "Connected server obtained! Took {} ms",
         (Utc::now() - start_time).num_milliseconds()
@@ -76,7 +96,7 @@ where
         time_left
     );
 
-    let url = format!("{}/ipa/session/json", lock.server());
+    let mut url = format!("{}/ipa/session/json", lock.server());
 
     // make id a random integer between 1 and 1000
     let id = rand::random::<u16>() % 1000;
@@ -93,24 +113,63 @@ where
         "id": id,
     });
 
-    // no query should take longer than 60 seconds
-    // Use a timeout to prevent deadlocks from failed servers
-    let client = Client::builder()
-        .cookie_provider(Arc::clone(lock.jar()))
-        .danger_accept_invalid_certs(should_allow_invalid_certs())
-        .timeout(Duration::from_secs(time_left.min(20) as u64))
-        .build()
-        .context("Could not build client")?;
+    let mut num_failovers = 0;
 
-    let mut result = client
-        .post(&url)
-        .header("Referer", format!("{}/ipa", lock.server()))
-        .header("Content-Type", "application/json")
-        .header("Accept", "application/json")
-        .json(&payload)
-        .send()
-        .await
-        .with_context(|| format!("Could not call function: {}", payload))?;
+    // reuse the session's client, so that its connections are kept
+    // alive between calls. Use a timeout to prevent deadlocks from
+    // failed servers
+    let mut result = loop {
+        match lock
+            .client()
+            .post(&url)
+            .timeout(Duration::from_secs(time_left.min(20) as u64))
+            .header("Referer", format!("{}/ipa", lock.server()))
+            .header("Content-Type", "application/json")
+            .header("Accept", "application/json")
+            .json(&payload)
+            .send()
+            .await
+        {
+            Ok(result) => break result,
+            Err(e) => {
+                tracing::error!(
+                    "Could not call function {} on FreeIPA server {}: {}",
+                    func,
+                    lock.server(),
+                    e
+                );
+
+                set_server_healthy(lock.server(), false).await;
+
+                // fail over to another server if the call cannot have
+                // reached this server (or if it only reads)
+                if num_failovers >= MAX_FAILOVERS || !(e.is_connect() || read_only) {
+                    breaker.failure(&e);
+
+                    return Err(Error::Call(format!(
+                        "Could not call function: {}. Error: {}",
+                        payload, e
+                    )));
+                }
+
+                num_failovers += 1;
+
+                drop(lock);
+                assert_not_expired(expires)?;
+
+                lock = get_connected_server(read_only, expires)
+                    .await
+                    .inspect_err(|e| breaker.failure(e))?;
+                url = format!("{}/ipa/session/json", lock.server());
+
+                tracing::warn!(
+                    "Failing over to FreeIPA server {} to call function {}",
+                    lock.server(),
+                    func
+                );
+            }
+        }
+    };
 
     // write a warning if this took a long time
     if (Utc::now() - start_time).num_seconds() > 5 {
@@ -140,7 +199,10 @@ where
         assert_not_expired(expires)?;
 
         tracing::error!("Authorisation (401) error. Reconnecting.");
-        lock = get_connected_server(expires).await?;
+        lock = get_connected_server(read_only, expires)
+            .await
+            .inspect_err(|e| breaker.failure(e))?;
+        url = format!("{}/ipa/session/json", lock.server());
 
         if Utc::now().signed_duration_since(start_time).num_seconds() > 10 {
             tracing::info!(
@@ -159,22 +221,18 @@ where
             ));
         }
 
-        let client = Client::builder()
-            .cookie_provider(Arc::clone(lock.jar()))
-            .danger_accept_invalid_certs(should_allow_invalid_certs())
-            .timeout(Duration::from_secs(time_left.min(20) as u64))
-            .build()
-            .context("Could not build client")?;
-
         // retry the call
-        result = client
+        result = lock
+            .client()
             .post(&url)
+            .timeout(Duration::from_secs(time_left.min(20) as u64))
             .header("Referer", format!("{}/ipa", lock.server()))
             .header("Content-Type", "application/json")
             .header("Accept", "application/json")
             .json(&payload)
             .send()
             .await
+            .inspect_err(|e| breaker.failure(e))
             .with_context(|| format!("Could not call function: {}", payload))?;
 
         if Utc::now().signed_duration_since(start_time).num_seconds() > 10 {
@@ -187,7 +245,21 @@ where
         }
     }
 
+    // only server errors count against the breaker - anything else
+    // shows that FreeIPA is up
+    match result.status().is_server_error() {
+        true => breaker.failure(&format!(
+            "FreeIPA responded with status {}",
+            result.status()
+        )),
+        false => breaker.success(),
+    }
+
     if result.status().is_success() {
+        // the session is kept alive by each successful call
+        lock.set_used();
+        set_server_healthy(lock.server(), true).await;
+
         let result = result
             .json::<FreeResponse>()
             .await
@@ -290,12 +362,27 @@ impl IPAResponse {
     }
 }
 
+///
+/// The time (in minutes) that a FreeIPA session can be idle before it
+/// is assumed to have expired. This is shorter than FreeIPA's default
+/// session lifetime (20 minutes of inactivity), so that we log in again
+/// before the server rejects the session
+///
+const SESSION_IDLE_TIMEOUT: i64 = 15;
+
+///
+/// A single authenticated session with a FreeIPA server. The session
+/// cookie and client (and so its open connections) are reused for all
+/// calls until the session expires
+///
 #[derive(Debug, Clone)]
 struct IPAServer {
     server: String,
     jar: Arc<Jar>,
+    client: Option<Client>,
     user: String,
     password: SecretString,
+    last_used: Option<chrono::DateTime<Utc>>,
     num_failed_reconnects: u32,
     last_failed_reconnect: Option<chrono::DateTime<Utc>>,
 }
@@ -305,14 +392,32 @@ impl IPAServer {
         IPAServer {
             server: server.to_string(),
             jar: Arc::new(Jar::default()),
+            client: None,
             user: user.to_string(),
             password: password.clone(),
+            last_used: None,
             num_failed_reconnects: 0,
             last_failed_reconnect: None,
         }
     }
 
     fn is_logged_in(&self) -> bool {
+        if self.client.is_none() {
+            return false;
+        }
+
+        // idle sessions will have been expired by the server
+        let is_idle = match self.last_used {
+            Some(last_used) => {
+                Utc::now() - last_used >= chrono::Duration::minutes(SESSION_IDLE_TIMEOUT)
+            }
+            None => true,
+        };
+
+        if is_idle {
+            return false;
+        }
+
         // check if the jar has a session cookie for this server
         let url = format!("{}/ipa", &self.server);
 
@@ -332,14 +437,22 @@ impl IPAServer {
         self.num_failed_reconnects += 1;
         self.last_failed_reconnect = Some(Utc::now());
         self.jar = Arc::new(Jar::default());
+        self.client = None;
+        self.last_used = None;
     }
 
-    fn set_login_success(&mut self, jar: Arc<Jar>) {
+    fn set_login_success(&mut self, jar: Arc<Jar>, client: Client) {
         self.jar = jar;
+        self.client = Some(client);
+        self.last_used = Some(Utc::now());
         self.num_failed_reconnects = 0;
         self.last_failed_reconnect = None;
     }
 
+    fn set_used(&mut self) {
+        self.last_used = Some(Utc::now());
+    }
+
     fn should_backoff(&self) -> bool {
         if self.num_failed_reconnects < 3 {
             return false;
@@ -365,43 +478,259 @@ impl LockedIPAServer {
         &self.server.server
     }
 
-    fn jar(&self) -> &Arc<Jar> {
-        &self.server.jar
+    fn client(&self) -> Client {
+        // a server is only locked once it is logged in, so the client
+        // will exist - but fall back to a new client just in case
+        self.server.client.clone().unwrap_or_default()
     }
 
     fn set_login_failed(&mut self) {
         self.server.set_login_failed();
     }
+
+    fn set_used(&mut self) {
+        self.server.set_used();
+    }
+}
+
+type ServerPool = Vec<(String, Arc<Mutex<IPAServer>>)>;
+
+///
+/// The pool of sessions, each with the name of the server it connects to
+///
+static FREEIPA_SERVERS: Lazy<Mutex<ServerPool>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+///
+/// The maximum number of other servers that a single call will fail
+/// over to if the server it is using cannot be reached
+///
+const MAX_FAILOVERS: u32 = 2;
+
+/// The name of the circuit breaker protecting calls to FreeIPA
+const BREAKER: &str = "freeipa";
+
+///
+/// The time (in seconds) after a write during which all reads are sent to
+/// servers that accept writes, so that they are not read from a replica
+/// that has not yet received the change
+///
+const REPLICATION_DELAY: i64 = 30;
+
+///
+/// The role and health of each FreeIPA server (host), shared by all of
+/// the sessions to that server
+///
+#[derive(Debug, Clone)]
+struct ServerStatus {
+    is_replica: bool,
+    is_healthy: bool,
+}
+
+static SERVER_STATUS: Lazy<RwLock<HashMap<String, ServerStatus>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+static LAST_WRITE: Lazy<RwLock<Option<chrono::DateTime<Utc>>>> = Lazy::new(|| RwLock::new(None));
+
+///
+/// Return whether the passed FreeIPA function only reads data, and so
+/// can be sent to a replica
+///
+fn is_read_only(func: &str) -> bool {
+    func == "ping" || func.ends_with("_find") || func.ends_with("_show")
 }
 
-static FREEIPA_SERVERS: Lazy<Mutex<Vec<Arc<Mutex<IPAServer>>>>> =
-    Lazy::new(|| Mutex::new(Vec::new()));
+async fn set_server_healthy(server: &str, is_healthy: bool) {
+    if let Some(status) = SERVER_STATUS.write().await.get_mut(server) {
+        if status.is_healthy != is_healthy {
+            match is_healthy {
+                true => tracing::info!("FreeIPA server {} is healthy again", server),
+                false => tracing::warn!("FreeIPA server {} is unhealthy", server),
+            }
+        }
+
+        status.is_healthy = is_healthy;
+    }
+}
 
+///
+/// Initialise the pool of sessions with the passed FreeIPA servers, plus
+/// the (optional) read-only replicas, which are only used for reads. Up
+/// to `connections` sessions are opened to each server, so that this many
+/// calls can be made to each server at the same time. Each session is
+/// logged in once and then reused until it expires
+///
 pub async fn initialise_servers(
     servers: &[String],
+    replicas: &[String],
     user: &str,
     password: &SecretString,
+    connections: usize,
 ) -> Result<(), Error> {
     let mut freeipa_servers = FREEIPA_SERVERS.lock().await;
+    let mut server_status = SERVER_STATUS.write().await;
 
     // clear any existing servers
     freeipa_servers.clear();
+    server_status.clear();
 
     // now add each server
-    for server in servers {
+    for (server, is_replica) in servers
+        .iter()
+        .map(|s| (s, false))
+        .chain(replicas.iter().map(|s| (s, true)))
+    {
         let server = server.trim();
 
-        if server.is_empty() {
+        if server.is_empty() || server_status.contains_key(server) {
             continue;
         }
 
-        freeipa_servers.push(Arc::new(Mutex::new(IPAServer::new(server, user, password))));
+        server_status.insert(
+            server.to_string(),
+            ServerStatus {
+                is_replica,
+                is_healthy: true,
+            },
+        );
+
+        for _ in 0..connections.max(1) {
+            freeipa_servers.push((
+                server.to_string(),
+                Arc::new(Mutex::new(IPAServer::new(server, user, password))),
+            ));
+        }
+    }
+
+    if !server_status.values().any(|s| !s.is_replica) {
+        return Err(Error::Misconfigured(
+            "At least one FreeIPA server that accepts writes must be specified".to_string(),
+        ));
     }
 
     Ok(())
 }
 
-async fn get_connected_server(expires: &chrono::DateTime<Utc>) -> Result<LockedIPAServer, Error> {
+///
+/// Return the number of sessions in the pool, which is the number of
+/// calls that can be made to FreeIPA at the same time
+///
+pub async fn num_sessions() -> usize {
+    FREEIPA_SERVERS.lock().await.len()
+}
+
+///
+/// Check that the passed server is reachable, logging in if needed,
+/// by calling FreeIPA's `ping` function
+///
+async fn check_server_health(server: &mut IPAServer) -> Result<(), Error> {
+    let expires = Utc::now() + chrono::Duration::seconds(30);
+
+    if !server.is_logged_in() {
+        match login(&server.server, &server.user, &server.password, &expires).await {
+            Ok((jar, client)) => server.set_login_success(jar, client),
+            Err(e) => {
+                server.set_login_failed();
+                return Err(e);
+            }
+        }
+    }
+
+    let client = server.client.clone().unwrap_or_default();
+
+    let payload = serde_json::json!({
+        "method": "ping",
+        "params": [[], {"version": "2.251"}],
+        "id": 0,
+    });
+
+    let result = client
+        .post(format!("{}/ipa/session/json", server.server))
+        .timeout(Duration::from_secs(10))
+        .header("Referer", format!("{}/ipa", server.server))
+        .header("Content-Type", "application/json")
+        .header("Accept", "application/json")
+        .json(&payload)
+        .send()
+        .await
+        .map_err(|e| Error::Call(format!("Could not ping {}: {}", server.server, e)))?;
+
+    match result.status() {
+        status if status.is_success() => {
+            server.set_used();
+            Ok(())
+        }
+        status => {
+            // the session may have expired - log in again next time
+            server.set_login_failed();
+            Err(Error::Call(format!(
+                "Could not ping {}. Status: {}",
+                server.server, status
+            )))
+        }
+    }
+}
+
+///
+/// Spawn the background task that checks the health of every FreeIPA
+/// server every `interval` seconds, so that calls are only sent to
+/// healthy servers, and servers that have recovered are used again
+///
+pub fn spawn_health_check(interval: u64) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+
+            let freeipa_servers = FREEIPA_SERVERS.lock().await.clone();
+            let mut checked = HashSet::new();
+
+            for (server, session) in freeipa_servers {
+                if checked.contains(&server) {
+                    continue;
+                }
+
+                // sessions that are in use are being checked by their calls
+                let mut session = match session.try_lock_owned() {
+                    Ok(session) => session,
+                    Err(_) => continue,
+                };
+
+                checked.insert(server);
+
+                let dependency = format!("freeipa:{}", session.server);
+
+                match check_server_health(&mut session).await {
+                    Ok(_) => {
+                        set_server_healthy(&session.server, true).await;
+                        health::set_dependency(&dependency, true, "responding to ping");
+                        CircuitBreaker::new(BREAKER).probe_succeeded();
+                    }
+                    Err(e) => {
+                        tracing::warn!("Health check of {} failed: {}", session.server, e);
+                        set_server_healthy(&session.server, false).await;
+                        health::set_dependency(&dependency, false, &e.to_string());
+                    }
+                }
+            }
+
+            let expires = Utc::now() + chrono::Duration::seconds(60);
+
+            if let Err(e) = check_id_ranges(&expires).await {
+                tracing::warn!("Could not check the usage of the id ranges: {}", e);
+            }
+        }
+    });
+}
+
+///
+/// Return a logged-in session with one of the FreeIPA servers. Read-only
+/// calls prefer replicas (unless there has been a recent write), while
+/// other calls only use servers that accept writes. Unhealthy servers are
+/// only used if there are no healthy servers
+///
+async fn get_connected_server(
+    read_only: bool,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<LockedIPAServer, Error> {
     // get a copy of the servers, so that we don't hold the lock while we
     // try to connect
     assert_not_expired(expires)?;
@@ -414,16 +743,45 @@ async fn get_connected_server(expires: &chrono::DateTime<Utc>) -> Result<LockedI
         ));
     }
 
+    let use_replicas = read_only
+        && match *LAST_WRITE.read().await {
+            Some(last_write) => {
+                Utc::now() - last_write > chrono::Duration::seconds(REPLICATION_DELAY)
+            }
+            None => true,
+        };
+
     let mut rng = rand::rngs::StdRng::from_os_rng();
 
     loop {
         let mut should_all_backoff: bool = true;
 
-        // randomise the order of the servers for each loop
-        for server in freeipa_servers
+        let server_status = SERVER_STATUS.read().await.clone();
+
+        let status = |server: &str| {
+            server_status.get(server).cloned().unwrap_or(ServerStatus {
+                is_replica: false,
+                is_healthy: true,
+            })
+        };
+
+        let any_healthy = server_status
+            .values()
+            .any(|s| s.is_healthy && (use_replicas || !s.is_replica));
+
+        // randomise the order of the servers for each loop, but try
+        // replicas first for reads
+        let mut ordered = freeipa_servers
             .iter()
-            .choose_multiple(&mut rng, freeipa_servers.len())
-        {
+            .filter(|(server, _)| use_replicas || !status(server).is_replica)
+            .filter(|(server, _)| status(server).is_healthy || !any_healthy)
+            .choose_multiple(&mut rng, freeipa_servers.len());
+
+        if use_replicas {
+            ordered.sort_by_key(|(server, _)| !status(server).is_replica);
+        }
+
+        for (_, server) in ordered {
             assert_not_expired(expires)?;
 
             match server.clone().try_lock_owned() {
@@ -445,10 +803,10 @@ async fn get_connected_server(expires: &chrono::DateTime<Utc>) -> Result<LockedI
 
                     tracing::info!("Logging in to FreeIPA server: {}", server.server);
                     match login(&server.server, &server.user, &server.password, expires).await {
-                        Ok(jar) => {
-                            // update the jar in the server
+                        Ok((jar, client)) => {
+                            // update the session in the server
                             tracing::info!("Login successful to FreeIPA server: {}", server.server);
-                            server.set_login_success(jar);
+                            server.set_login_success(jar, client);
                             return Ok(LockedIPAServer { server });
                         }
                         Err(e) => {
@@ -458,6 +816,7 @@ async fn get_connected_server(expires: &chrono::DateTime<Utc>) -> Result<LockedI
                                 e
                             );
                             server.set_login_failed();
+                            set_server_healthy(&server.server, false).await;
 
                             // release the lock and try the next server
                         }
@@ -496,14 +855,15 @@ fn should_allow_invalid_certs() -> bool {
 ///
 /// Login to the FreeIPA server using the passed username and password.
 /// This returns a cookie jar that will contain the resulting authorisation
-/// cookie, and which can be used for subsequent calls to the server.
+/// cookie, plus the client that uses that jar, which can be used for
+/// subsequent calls to the server.
 ///
 async fn login(
     server: &str,
     user: &str,
     password: &SecretString,
     expires: &chrono::DateTime<Utc>,
-) -> Result<Arc<Jar>, Error> {
+) -> Result<(Arc<Jar>, Client), Error> {
     // how much time is left before we expire?
     let time_left = expires.signed_duration_since(Utc::now()).num_seconds();
 
@@ -515,10 +875,11 @@ async fn login(
 
     let jar = Arc::new(Jar::default());
 
+    // the client is kept for the lifetime of the session, so the timeout
+    // is set for each request rather than for the client
     let client = Client::builder()
         .cookie_provider(Arc::clone(&jar))
         .danger_accept_invalid_certs(should_allow_invalid_certs())
-        .timeout(Duration::from_secs(time_left.min(10) as u64))
         .build()
         .context("Could not build client")?;
 
@@ -526,6 +887,7 @@ async fn login(
 
     let result = client
         .post(&url)
+        .timeout(Duration::from_secs(time_left.min(10) as u64))
         .header("Referer", format!("{}/ipa", server))
         .header("Content-Type", "application/x-www-form-urlencoded")
         .header("Accept", "text/plain")
@@ -539,7 +901,7 @@ async fn login(
         .with_context(|| format!("Could not login calling URL: {}", url))?;
 
     match result.status() {
-        status if status.is_success() => Ok(jar),
+        status if status.is_success() => Ok((jar, client)),
         _ => Err(Error::Login(format!(
             "Could not login to server: {}. Status: {}. Response: {:?}",
             server,
@@ -549,76 +911,380 @@ async fn login(
     }
 }
 
+//
+// Public API
+//
+
+static PROTECTED_USERS: OnceCell<ProtectionPolicy> = OnceCell::new();
+static PROTECTED_GROUPS: OnceCell<ProtectionPolicy> = OnceCell::new();
+
 ///
-/// Public API
+/// Set the policy that decides which users and groups are protected,
+/// in addition to users who are not in the managed group. This can
+/// only be set once, when the agent starts
 ///
+pub fn set_protection_policy(
+    users: ProtectionPolicy,
+    groups: ProtectionPolicy,
+) -> Result<(), Error> {
+    tracing::info!("Protected users: {}", users);
+    tracing::info!("Protected groups: {}", groups);
 
-#[derive(Debug, Clone)]
-pub struct IPAUser {
-    userid: String,
-    cn: UserIdentifier,
-    givenname: String,
-    homedirectory: String,
-    userclass: String,
-    primary_group: String,
-    memberof: Vec<String>,
-    enabled: bool,
+    PROTECTED_USERS.set(users).map_err(|_| {
+        Error::Misconfigured("The protected users have already been set".to_string())
+    })?;
+
+    PROTECTED_GROUPS.set(groups).map_err(|_| {
+        Error::Misconfigured("The protected groups have already been set".to_string())
+    })?;
+
+    Ok(())
 }
 
-// implement display for IPAUser
-impl std::fmt::Display for IPAUser {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}: local_name={}, givenname={}, userclass={}, primary_group={}, memberof={}, home={}, enabled={}",
-            self.identifier(),
-            self.userid(),
-            self.givenname(),
-            self.userclass(),
-            self.primary_group(),
-            self.memberof().join(","),
-            self.home(),
-            self.is_enabled()
-        )
-    }
+///
+/// Return why the user with the passed local username and uid is
+/// protected by the protection policy, or None if they are not
+///
+fn protected_user_reason(userid: &str, uidnumber: Option<u32>) -> Option<String> {
+    PROTECTED_USERS
+        .get()
+        .and_then(|policy| policy.reason(userid, uidnumber))
 }
 
-impl IPAUser {
-    fn construct(
-        result: &serde_json::Value,
-        project: &ProjectIdentifier,
-    ) -> Result<Vec<IPAUser>, Error> {
-        let mut users = Vec::new();
+///
+/// Return why the group with the passed name and gid is protected
+/// by the protection policy, or None if it is not
+///
+fn protected_group_reason(groupid: &str, gidnumber: Option<u32>) -> Option<String> {
+    PROTECTED_GROUPS
+        .get()
+        .and_then(|policy| policy.reason(groupid, gidnumber))
+}
 
-        // convert result into an array if it isn't already
-        let result = match result.as_array() {
-            Some(result) => result.clone(),
-            None => vec![result.clone()],
-        };
+///
+/// Return the first value of the passed numeric attribute (e.g.
+/// uidnumber or gidnumber) of a FreeIPA user or group
+///
+fn get_id_number(value: &serde_json::Value, attribute: &str) -> Option<u32> {
+    value
+        .get(attribute)
+        .and_then(|v| v.as_array())
+        .and_then(|v| v.first())
+        .and_then(|v| match v {
+            serde_json::Value::String(s) => s.parse::<u32>().ok(),
+            serde_json::Value::Number(n) => n.as_u64().and_then(|n| u32::try_from(n).ok()),
+            _ => None,
+        })
+}
 
-        for user in result {
-            let userid = user
-                .get("uid")
-                .and_then(|v| v.as_array())
-                .and_then(|v| v.first())
-                .and_then(|v| v.as_str())
-                .unwrap_or_default()
-                .to_string();
+type UsedIds = HashMap<String, (chrono::DateTime<Utc>, HashSet<u32>)>;
 
-            if userid.is_empty() {
-                tracing::error!("Could not find user id: Skipping user.",);
-                continue;
-            }
+///
+/// The ids in each portal's range that are already in use, plus when
+/// they were last read from FreeIPA. This is locked while an id is
+/// allocated, so that the same id is never given out twice
+///
+static USED_IDS: Lazy<Mutex<UsedIds>> = Lazy::new(|| Mutex::new(HashMap::new()));
 
-            let cn: &str = user
-                .get("cn")
-                .and_then(|v| v.as_array())
-                .and_then(|v| v.first())
-                .and_then(|v| v.as_str())
-                .unwrap_or_default();
+///
+/// How often (in minutes) the ids in use are re-read from FreeIPA, so
+/// that ids used by accounts created outside of OpenPortal are seen
+///
+const USED_IDS_REFRESH: i64 = 10;
 
-            let cn = match UserIdentifier::parse(cn) {
-                Ok(cn) => match cn.project_identifier() == *project {
+///
+/// The percentage of a portal's id range that can be used before a
+/// health warning is raised
+///
+const ID_RANGE_WARNING_PERCENT: u64 = 90;
+
+///
+/// Parse the per-portal id ranges, e.g.
+/// `portal-a:200000-299999,portal-b:300000-399999`. The ranges
+/// must not overlap, so that the accounts of each portal can be
+/// told apart by their ids
+///
+pub fn parse_id_ranges(ranges: &str) -> Result<HashMap<String, (u32, u32)>, Error> {
+    let mut id_ranges: HashMap<String, (u32, u32)> = HashMap::new();
+
+    for range in ranges
+        .split(',')
+        .map(|r| r.trim())
+        .filter(|r| !r.is_empty())
+    {
+        let (portal, ids) = range.split_once(':').ok_or_else(|| {
+            Error::Misconfigured(format!(
+                "Invalid id range '{}'. This should be 'portal:start-end'",
+                range
+            ))
+        })?;
+
+        let portal = PortalIdentifier::parse(portal.trim())?.to_string();
+
+        let (start, end) = ids
+            .split_once('-')
+            .and_then(|(start, end)| {
+                Some((
+                    start.trim().parse::<u32>().ok()?,
+                    end.trim().parse::<u32>().ok()?,
+                ))
+            })
+            .ok_or_else(|| {
+                Error::Misconfigured(format!(
+                    "Invalid id range '{}'. This should be 'portal:start-end'",
+                    range
+                ))
+            })?;
+
+        if start == 0 || start > end {
+            return Err(Error::Misconfigured(format!(
+                "Invalid id range '{}'. The start must be greater than 0 and not after the end",
+                range
+            )));
+        }
+
+        if let Some((other, _)) = id_ranges
+            .iter()
+            .find(|(_, (s, e))| start <= *e && end >= *s)
+        {
+            return Err(Error::Misconfigured(format!(
+                "The id range '{}' overlaps with the range for portal '{}'",
+                range, other
+            )));
+        }
+
+        if id_ranges.insert(portal.clone(), (start, end)).is_some() {
+            return Err(Error::Misconfigured(format!(
+                "More than one id range is given for portal '{}'",
+                portal
+            )));
+        }
+    }
+
+    Ok(id_ranges)
+}
+
+///
+/// Return all of the uids and gids of users, staged users and groups
+/// in FreeIPA that are in the passed (inclusive) range
+///
+async fn get_used_ids(
+    start: u32,
+    end: u32,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<HashSet<u32>, Error> {
+    let mut used = HashSet::new();
+
+    for (func, attributes) in [
+        ("user_find", vec!["uidnumber", "gidnumber"]),
+        ("stageuser_find", vec!["uidnumber", "gidnumber"]),
+        ("group_find", vec!["gidnumber"]),
+    ] {
+        let kwargs = {
+            let mut kwargs = HashMap::new();
+            kwargs.insert("sizelimit".to_string(), "0".to_string());
+            kwargs
+        };
+
+        let result = call_post::<IPAResponse>(func, None, Some(kwargs), expires).await?;
+
+        if result.truncated.unwrap_or(false) {
+            tracing::warn!(
+                "The results of {} were truncated, so some ids in use may be missed",
+                func
+            );
+        }
+
+        let entries = match result.result {
+            Some(serde_json::Value::Array(entries)) => entries,
+            Some(entry) => vec![entry],
+            None => Vec::new(),
+        };
+
+        for entry in entries {
+            for attribute in &attributes {
+                if let Some(id) = get_id_number(&entry, attribute) {
+                    if id >= start && id <= end {
+                        used.insert(id);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(used)
+}
+
+///
+/// Raise (or clear) the health warning for a portal's id range,
+/// depending on how much of that range has been used
+///
+fn update_id_range_warning(portal: &str, start: u32, end: u32, used: usize) {
+    let key = format!("freeipa-id-range:{}", portal);
+    let size = (end - start) as u64 + 1;
+    let used = used as u64;
+
+    if used * 100 >= size * ID_RANGE_WARNING_PERCENT {
+        health::set_warning(
+            &key,
+            &format!(
+                "The id range {}-{} for portal {} is {}% used ({} of {} ids left)",
+                start,
+                end,
+                portal,
+                (used * 100) / size,
+                size.saturating_sub(used),
+                size
+            ),
+        );
+    } else {
+        health::clear_warning(&key);
+    }
+}
+
+///
+/// Allocate a new id from the range for the passed portal, which will
+/// be used as both the uid and gid of a new user (and their private
+/// group), or as the gid of a new group. This returns None if there is
+/// no range for the portal, in which case FreeIPA chooses the id
+///
+async fn allocate_id(portal: &str, expires: &chrono::DateTime<Utc>) -> Result<Option<u32>, Error> {
+    let (start, end) = match cache::get_id_range(portal).await? {
+        Some(range) => range,
+        None => return Ok(None),
+    };
+
+    let mut used_ids = USED_IDS.lock().await;
+
+    let needs_refresh = match used_ids.get(portal) {
+        Some((read_at, _)) => {
+            Utc::now().signed_duration_since(*read_at).num_minutes() >= USED_IDS_REFRESH
+        }
+        None => true,
+    };
+
+    if needs_refresh {
+        let used = get_used_ids(start, end, expires).await?;
+        used_ids.insert(portal.to_string(), (Utc::now(), used));
+    }
+
+    let used = match used_ids.get_mut(portal) {
+        Some((_, used)) => used,
+        None => {
+            return Err(Error::Bug(format!(
+                "The used ids for portal {} were not loaded",
+                portal
+            )))
+        }
+    };
+
+    let id = (start..=end).find(|id| !used.contains(id));
+
+    match id {
+        Some(id) => {
+            used.insert(id);
+            update_id_range_warning(portal, start, end, used.len());
+            tracing::info!("Allocated id {} from the range for portal {}", id, portal);
+            Ok(Some(id))
+        }
+        None => {
+            update_id_range_warning(portal, start, end, used.len());
+            tracing::error!(
+                "The id range {}-{} for portal {} is exhausted",
+                start,
+                end,
+                portal
+            );
+            Err(Error::InvalidState(format!(
+                "The id range {}-{} for portal {} is exhausted",
+                start, end, portal
+            )))
+        }
+    }
+}
+
+///
+/// Re-read the ids in use in every portal's range from FreeIPA, and
+/// update the health warnings for ranges that are nearly exhausted
+///
+pub async fn check_id_ranges(expires: &chrono::DateTime<Utc>) -> Result<(), Error> {
+    for (portal, (start, end)) in cache::get_id_ranges().await? {
+        let used = get_used_ids(start, end, expires).await?;
+        update_id_range_warning(&portal, start, end, used.len());
+        USED_IDS.lock().await.insert(portal, (Utc::now(), used));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct IPAUser {
+    userid: String,
+    uidnumber: Option<u32>,
+    cn: UserIdentifier,
+    givenname: String,
+    homedirectory: String,
+    userclass: String,
+    primary_group: String,
+    memberof: Vec<String>,
+    sshpubkeys: Vec<String>,
+    enabled: bool,
+}
+
+// implement display for IPAUser
+impl std::fmt::Display for IPAUser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: local_name={}, givenname={}, userclass={}, primary_group={}, memberof={}, home={}, enabled={}",
+            self.identifier(),
+            self.userid(),
+            self.givenname(),
+            self.userclass(),
+            self.primary_group(),
+            self.memberof().join(","),
+            self.home(),
+            self.is_enabled()
+        )
+    }
+}
+
+impl IPAUser {
+    fn construct(
+        result: &serde_json::Value,
+        project: &ProjectIdentifier,
+    ) -> Result<Vec<IPAUser>, Error> {
+        let mut users = Vec::new();
+
+        // convert result into an array if it isn't already
+        let result = match result.as_array() {
+            Some(result) => result.clone(),
+            None => vec![result.clone()],
+        };
+
+        for user in result {
+            let userid = user
+                .get("uid")
+                .and_then(|v| v.as_array())
+                .and_then(|v| v.first())
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string();
+
+            if userid.is_empty() {
+                tracing::error!("Could not find user id: Skipping user.",);
+                continue;
+            }
+
+            let cn: &str = user
+                .get("cn")
+                .and_then(|v| v.as_array())
+                .and_then(|v| v.first())
+                .and_then(|v| v.as_str())
+                .unwrap_or_default();
+
+            let cn = match UserIdentifier::parse(cn) {
+                Ok(cn) => match cn.project_identifier() == *project {
                     true => cn,
                     false => {
                         tracing::warn!("Skipping {} as they are not in project {}", cn, project);
@@ -690,6 +1356,17 @@ impl IPAUser {
                 })
                 .unwrap_or_default();
 
+            let sshpubkeys: Vec<String> = user
+                .get("ipasshpubkey")
+                .and_then(|v| v.as_array())
+                .map(|v| {
+                    v.iter()
+                        .filter_map(|v| v.as_str())
+                        .map(|v| v.to_string())
+                        .collect()
+                })
+                .unwrap_or_default();
+
             // try to find the primary group for this user
             let primary_group = get_primary_group(&cn)?.groupid().to_string();
 
@@ -719,14 +1396,18 @@ impl IPAUser {
                 .and_then(|v| v.as_bool())
                 .unwrap_or(false);
 
+            let uidnumber = get_id_number(&user, "uidnumber");
+
             users.push(IPAUser {
                 userid,
+                uidnumber,
                 cn,
                 givenname,
                 userclass,
                 homedirectory,
                 primary_group,
                 memberof,
+                sshpubkeys,
                 enabled: !disabled,
             });
         }
@@ -742,6 +1423,13 @@ impl IPAUser {
         self.local_username()
     }
 
+    ///
+    /// Return the numeric uid of this user, if known
+    ///
+    pub fn uidnumber(&self) -> Option<u32> {
+        self.uidnumber
+    }
+
     ///
     /// Return the givenname for this user (this is the full user.project.portal)
     ///
@@ -778,6 +1466,13 @@ impl IPAUser {
         &self.memberof
     }
 
+    ///
+    /// Return the SSH public keys of this user
+    ///
+    pub fn ssh_keys(&self) -> &Vec<String> {
+        &self.sshpubkeys
+    }
+
     ///
     /// Return the UserIdentifier for this user (user.project.portal)
     ///
@@ -841,11 +1536,36 @@ impl IPAUser {
         groups.iter().all(|group| self.in_group(group))
     }
 
+    ///
+    /// Return why this user is protected, or None if they are managed.
+    /// Users are protected if they match the protection policy, or
+    /// if they are not in the "openportal" group
+    ///
+    pub fn protection_reason(&self) -> Option<String> {
+        if let Some(reason) = protected_user_reason(self.userid(), self.uidnumber()) {
+            return Some(reason);
+        }
+
+        match self.in_managed_group() {
+            true => None,
+            false => Some(format!("'{}' is not managed by OpenPortal", self.userid())),
+        }
+    }
+
     ///
     /// Return whether or not this user is managed - only users
-    /// in the "openportal" group can be managed
+    /// in the "openportal" group who are not protected by the
+    /// protection policy can be managed
     ///
     pub fn is_managed(&self) -> bool {
+        self.protection_reason().is_none()
+    }
+
+    ///
+    /// Return whether or not this user is in the "openportal" group
+    /// (and has the matching userclass, if that is required)
+    ///
+    fn in_managed_group(&self) -> bool {
         let managed_group = match get_managed_group() {
             Ok(group) => group,
             Err(_) => return false,
@@ -912,6 +1632,7 @@ impl IPAUser {
 #[derive(Debug, Clone)]
 pub struct IPAGroup {
     groupid: String,
+    gidnumber: Option<u32>,
     identifier: ProjectIdentifier,
     description: String,
 }
@@ -949,6 +1670,7 @@ impl IPAGroup {
 
         Ok(IPAGroup {
             groupid: groupid.to_string(),
+            gidnumber: None,
             identifier: identifier.clone(),
             description: description.to_string(),
         })
@@ -997,6 +1719,7 @@ impl IPAGroup {
                 .to_string();
 
             groups.push(IPAGroup {
+                gidnumber: get_id_number(&group, "gidnumber"),
                 groupid,
                 identifier: project,
                 description,
@@ -1073,6 +1796,7 @@ impl IPAGroup {
             tracing::info!("Constructing legacy group {} / {}", groupid, identifier);
 
             groups.push(IPAGroup {
+                gidnumber: get_id_number(&group, "gidnumber"),
                 groupid,
                 identifier,
                 description,
@@ -1133,6 +1857,7 @@ impl IPAGroup {
             };
 
             groups.push(IPAGroup {
+                gidnumber: get_id_number(&group, "gidnumber"),
                 groupid,
                 identifier,
                 description,
@@ -1260,6 +1985,18 @@ impl IPAGroup {
         &self.description
     }
 
+    pub fn gidnumber(&self) -> Option<u32> {
+        self.gidnumber
+    }
+
+    ///
+    /// Return why this group is protected by the protection policy,
+    /// or None if it is not protected
+    ///
+    pub fn protection_reason(&self) -> Option<String> {
+        protected_group_reason(self.groupid(), self.gidnumber())
+    }
+
     pub fn is_system_group(&self) -> bool {
         self.identifier.portal() == "system"
     }
@@ -1672,13 +2409,17 @@ async fn get_group_create_if_not_exists(
     // in the description
     let description = format!("{} | {}", group.identifier(), group.description());
 
-    let kwargs = {
+    let mut kwargs = {
         let mut kwargs = HashMap::new();
         kwargs.insert("cn".to_string(), group.groupid().to_string());
         kwargs.insert("description".to_string(), description);
         kwargs
     };
 
+    if let Some(id) = allocate_id(&group.identifier().portal(), expires).await? {
+        kwargs.insert("gidnumber".to_string(), id.to_string());
+    }
+
     match call_post::<IPAResponse>("group_add", None, Some(kwargs), expires).await {
         Ok(_) => {
             tracing::info!("Successfully created group: {}", group);
@@ -1912,75 +2653,271 @@ async fn sync_groups(
 /// Add the project to FreeIPA - this will create the group for the project
 /// if it doesn't already exist. This returns the group
 ///
-pub async fn add_project(
-    project: &ProjectIdentifier,
-    expires: &chrono::DateTime<Utc>,
-) -> Result<IPAGroup, Error> {
-    // ensure that we don't have too many concurrent requests
-    let project_group = get_group_create_if_not_exists(
-        &IPAGroup::new(
-            &identifier_to_projectid(project, false)?,
-            project,
-            "OpenPortal-managed group",
-        )?,
-        expires,
-    )
-    .await?;
-
-    Ok(project_group)
+///
+/// Return the name of the HBAC rule that gives the passed project
+/// group access to the project's hosts
+///
+fn hbac_rule_name(project_group: &IPAGroup) -> String {
+    format!("openportal.{}", project_group.groupid())
 }
 
 ///
-/// Remove the project from FreeIPA - this will remove the group for the project
-/// if it exists, returning the removed group if successful,
-/// or it will return an error if it doesn't exist, or something else
-/// goes wrong
+/// Create (if needed) the HBAC rule that gives the passed project group
+/// access to the configured host groups, using the configured services.
+/// This does nothing if no host groups are configured
 ///
-pub async fn remove_project(
-    project: &ProjectIdentifier,
-    instance: &Peer,
+async fn add_project_hbac_rule(
+    project_group: &IPAGroup,
     expires: &chrono::DateTime<Utc>,
-) -> Result<IPAGroup, Error> {
-    let project_group = match get_group(project, expires).await {
-        Ok(Some(group)) => group,
-        Ok(None) => {
-            tracing::warn!(
-                "Could not find group for project {}. Assuming it has already been removed.",
-                project
-            );
-            return Err(Error::NotFound(format!(
-                "Could not find group for project {}. Assuming it has already been removed.",
-                project
-            )));
-        }
-        Err(e) => {
-            tracing::error!("Could not find group for project {}. Error: {}", project, e);
-            return Err(Error::Call(format!(
-                "Could not find group for project {}. Error: {}",
-                project, e
-            )));
-        }
-    };
+) -> Result<(), Error> {
+    let host_groups = cache::get_hbac_host_groups().await?;
 
-    if !project_group.is_project_group() {
-        return Err(Error::InvalidState(format!(
-            "Cannot remove the group {} associated with project {} because it is not a project group?",
-            project_group, project)));
+    if host_groups.is_empty() {
+        return Ok(());
     }
 
-    assert_not_expired(expires)?;
+    let services = cache::get_hbac_services().await?;
+    let rule = hbac_rule_name(project_group);
 
-    // now get all of the users in this project and remove them as well!
-    let users = force_get_users_in_group(&project_group, expires).await?;
+    let kwargs = {
+        let mut kwargs = HashMap::new();
+        kwargs.insert(
+            "description".to_string(),
+            format!(
+                "OpenPortal-managed access for {}",
+                project_group.identifier()
+            ),
+        );
 
-    tracing::info!(
-        "Removing group {} for project {}. Users to remove: {}",
-        project_group.groupid(),
-        project,
-        users
-            .iter()
-            .map(|u| u.userid())
-            .collect::<Vec<&str>>()
+        if services.is_empty() {
+            kwargs.insert("servicecategory".to_string(), "all".to_string());
+        }
+
+        kwargs
+    };
+
+    match call_post::<IPAResponse>(
+        "hbacrule_add",
+        Some(vec![rule.clone()]),
+        Some(kwargs),
+        expires,
+    )
+    .await
+    {
+        Ok(_) => {
+            tracing::info!("Created HBAC rule {}", rule);
+        }
+        Err(Error::Duplicate(_)) => {
+            tracing::debug!("HBAC rule {} already exists", rule);
+        }
+        Err(e) => {
+            tracing::error!("Could not create HBAC rule {}: {}", rule, e);
+            return Err(e);
+        }
+    }
+
+    // adding members that already belong to the rule is not an error,
+    // so this brings an existing rule up to date
+    let mut members = vec![("group", project_group.groupid().to_string())];
+
+    for host_group in host_groups {
+        members.push(("hostgroup", host_group));
+    }
+
+    for (kind, member) in members {
+        let func = match kind {
+            "group" => "hbacrule_add_user",
+            _ => "hbacrule_add_host",
+        };
+
+        let kwargs = {
+            let mut kwargs = HashMap::new();
+            kwargs.insert(kind.to_string(), member.clone());
+            kwargs
+        };
+
+        if let Err(e) =
+            call_post::<IPAResponse>(func, Some(vec![rule.clone()]), Some(kwargs), expires).await
+        {
+            tracing::error!(
+                "Could not add {} {} to HBAC rule {}: {}",
+                kind,
+                member,
+                rule,
+                e
+            );
+            return Err(e);
+        }
+    }
+
+    for service in services {
+        let kwargs = {
+            let mut kwargs = HashMap::new();
+            kwargs.insert("hbacsvc".to_string(), service.clone());
+            kwargs
+        };
+
+        if let Err(e) = call_post::<IPAResponse>(
+            "hbacrule_add_service",
+            Some(vec![rule.clone()]),
+            Some(kwargs),
+            expires,
+        )
+        .await
+        {
+            tracing::error!(
+                "Could not add service {} to HBAC rule {}: {}",
+                service,
+                rule,
+                e
+            );
+            return Err(e);
+        }
+    }
+
+    tracing::info!(
+        "HBAC rule {} gives group {} access to its hosts",
+        rule,
+        project_group.groupid()
+    );
+
+    Ok(())
+}
+
+///
+/// Remove the HBAC rule that gives the passed project group access to
+/// the project's hosts. This does nothing if no host groups are
+/// configured, or if the rule has already been removed
+///
+async fn remove_project_hbac_rule(
+    project_group: &IPAGroup,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    if cache::get_hbac_host_groups().await?.is_empty() {
+        return Ok(());
+    }
+
+    let rule = hbac_rule_name(project_group);
+
+    match call_post::<IPAResponse>("hbacrule_del", Some(vec![rule.clone()]), None, expires).await {
+        Ok(_) => {
+            tracing::info!("Removed HBAC rule {}", rule);
+            Ok(())
+        }
+        Err(Error::NotFound(_)) => {
+            tracing::debug!("HBAC rule {} has already been removed", rule);
+            Ok(())
+        }
+        Err(e) => {
+            tracing::error!("Could not remove HBAC rule {}: {}", rule, e);
+            Err(e)
+        }
+    }
+}
+
+pub async fn add_project(
+    project: &ProjectIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<IPAGroup, Error> {
+    // ensure that we don't have too many concurrent requests
+    let project_group = get_group_create_if_not_exists(
+        &IPAGroup::new(
+            &identifier_to_projectid(project, false)?,
+            project,
+            "OpenPortal-managed group",
+        )?,
+        expires,
+    )
+    .await?;
+
+    if let Some(reason) = project_group.protection_reason() {
+        tracing::warn!(
+            "Refusing to manage the group {} for project {} as {}",
+            project_group.groupid(),
+            project,
+            reason
+        );
+        return Err(Error::InvalidState(format!(
+            "Refusing to manage the group {} for project {} as {}",
+            project_group.groupid(),
+            project,
+            reason
+        )));
+    }
+
+    add_project_hbac_rule(&project_group, expires).await?;
+
+    Ok(project_group)
+}
+
+///
+/// Return the mapping that the project would have if it was added,
+/// without adding anything to FreeIPA (used for dry runs)
+///
+pub fn get_new_project_mapping(project: &ProjectIdentifier) -> Result<ProjectMapping, Error> {
+    ProjectMapping::new(project, &identifier_to_projectid(project, false)?)
+}
+
+///
+/// Remove the project from FreeIPA - this will remove the group for the project
+/// if it exists, returning the removed group if successful,
+/// or it will return an error if it doesn't exist, or something else
+/// goes wrong
+///
+pub async fn remove_project(
+    project: &ProjectIdentifier,
+    instance: &Peer,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<IPAGroup, Error> {
+    let project_group = match get_group(project, expires).await {
+        Ok(Some(group)) => group,
+        Ok(None) => {
+            tracing::warn!(
+                "Could not find group for project {}. Assuming it has already been removed.",
+                project
+            );
+            return Err(Error::NotFound(format!(
+                "Could not find group for project {}. Assuming it has already been removed.",
+                project
+            )));
+        }
+        Err(e) => {
+            tracing::error!("Could not find group for project {}. Error: {}", project, e);
+            return Err(Error::Call(format!(
+                "Could not find group for project {}. Error: {}",
+                project, e
+            )));
+        }
+    };
+
+    if !project_group.is_project_group() {
+        return Err(Error::InvalidState(format!(
+            "Cannot remove the group {} associated with project {} because it is not a project group?",
+            project_group, project)));
+    }
+
+    if let Some(reason) = project_group.protection_reason() {
+        return Err(Error::InvalidState(format!(
+            "Cannot remove the group {} associated with project {} because {}",
+            project_group.groupid(),
+            project,
+            reason
+        )));
+    }
+
+    assert_not_expired(expires)?;
+
+    // now get all of the users in this project and remove them as well!
+    let users = force_get_users_in_group(&project_group, expires).await?;
+
+    tracing::info!(
+        "Removing group {} for project {}. Users to remove: {}",
+        project_group.groupid(),
+        project,
+        users
+            .iter()
+            .map(|u| u.userid())
+            .collect::<Vec<&str>>()
             .join(", ")
     );
 
@@ -2010,6 +2947,9 @@ pub async fn remove_project(
         };
     }
 
+    // the project's group no longer has access to the project's hosts
+    remove_project_hbac_rule(&project_group, expires).await?;
+
     // DO NOT REMOVE THE GROUP AS WE MAY WANT TO RE-ADD IT LATER, AND
     // WILL NEED TO USE THE SAME GID!
 
@@ -2174,6 +3114,17 @@ pub async fn add_user(
 
     assert_not_expired(expires)?;
 
+    // never create a user whose account name is protected
+    let userid = identifier_to_userid(user).await?;
+
+    if let Some(reason) = protected_user_reason(&userid, None) {
+        tracing::warn!("Refusing to add user {} as {}", user, reason);
+        return Err(Error::UnmanagedUser(format!(
+            "Refusing to add user {} as {}",
+            user, reason
+        )));
+    }
+
     // Get the group that all managed users need to belong to
     let managed_group = get_managed_group()?;
 
@@ -2182,7 +3133,7 @@ pub async fn add_user(
     // The user doesn't exist, so try to add
     let mut kwargs = {
         let mut kwargs = HashMap::new();
-        kwargs.insert("uid".to_string(), identifier_to_userid(user).await?);
+        kwargs.insert("uid".to_string(), userid);
         kwargs.insert("givenname".to_string(), user.username().to_string());
         kwargs.insert("sn".to_string(), user.project().to_string());
         kwargs.insert("userclass".to_string(), managed_group.groupid().to_string());
@@ -2196,6 +3147,12 @@ pub async fn add_user(
         tracing::info!("Adding user {} with home directory: {}", user, homedir);
     }
 
+    // the user and their private group share the id from the portal's range
+    if let Some(id) = allocate_id(&user.portal(), expires).await? {
+        kwargs.insert("uidnumber".to_string(), id.to_string());
+        kwargs.insert("gidnumber".to_string(), id.to_string());
+    }
+
     // we need to let the below go to completion, even if expired, as the
     // user needs to be removed if something goes wrong
     let user = match call_post::<IPAResponse>("user_add", None, Some(kwargs), expires).await {
@@ -2414,18 +3371,44 @@ pub async fn add_user(
 }
 
 ///
-/// Remove the user from FreeIPA - this will return the removed user if
-/// successful, or will return an error if the user doesn't exist, or
-/// something else goes wrong. Note that the user must be managed by
-/// OpenPortal, or an error will be returned
+/// Return whether the passed user is a FreeIPA stage user, i.e. a user
+/// who has been added but who has not yet been activated
 ///
-pub async fn remove_user(
+async fn is_staged_user(
     user: &UserIdentifier,
-    instance: &Peer,
     expires: &chrono::DateTime<Utc>,
-) -> Result<IPAUser, Error> {
-    // get and lock a mutex on this user, as we should only have a single
-    // task adding or removing this user at once
+) -> Result<bool, Error> {
+    let kwargs = {
+        let mut kwargs = HashMap::new();
+        kwargs.insert("uid".to_string(), identifier_to_userid(user).await?);
+        kwargs
+    };
+
+    match call_post::<IPAResponse>("stageuser_show", None, Some(kwargs), expires).await {
+        Ok(_) => Ok(true),
+        Err(Error::NotFound(_)) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+///
+/// Add the passed user as a FreeIPA stage user, if staging of new users
+/// is enabled. Staged users cannot log in until they are activated via
+/// `activate_user`. This returns true if the user is now staged, or
+/// false if staging is disabled or the user already has an active
+/// account (in which case they should be added using `add_user`)
+///
+pub async fn stage_user(
+    user: &UserIdentifier,
+    homedir: &Option<String>,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<bool, Error> {
+    if !cache::get_stage_users().await? || is_internal_portal(&user.portal()) {
+        return Ok(false);
+    }
+
+    // get a lock for this user, as only a single task should be adding
+    // or removing this user at the same time
     let now = chrono::Utc::now();
 
     let _guard = loop {
@@ -2434,17 +3417,16 @@ pub async fn remove_user(
             Err(_) => {
                 if chrono::Utc::now().signed_duration_since(now).num_seconds() > 5 {
                     tracing::warn!(
-                        "Could not get lock to remove user {} - another task is adding or removing.",
+                        "Could not get lock to stage user {} - another task is adding or removing.",
                         user
                     );
 
                     return Err(Error::Locked(format!(
-                        "Could not get lock to remove user {} - another task is adding or removing.",
+                        "Could not get lock to stage user {} - another task is adding or removing.",
                         user
                     )));
                 }
 
-                assert_not_expired(expires)?;
                 tokio::time::sleep(std::time::Duration::from_millis(100)).await;
             }
         };
@@ -2452,54 +3434,263 @@ pub async fn remove_user(
 
     assert_not_expired(expires)?;
 
-    // force get this user, as we need to have up-to-date information from FreeIPA
-    let mut user = match force_get_user(user, expires).await {
-        Ok(Some(user)) => user,
-        Ok(None) => {
-            tracing::warn!(
-                "Could not find user {}. Assuming they have already been removed.",
-                user
-            );
-            return Err(Error::NotFound(format!(
-                "Could not find user {}. Assuming they have already been removed.",
-                user
-            )));
-        }
-        Err(e) => {
-            tracing::error!("Could not find user {}. Error: {}", user, e);
-            return Err(Error::Call(format!(
-                "Could not find user {}. Error: {}",
-                user, e
-            )));
-        }
-    };
+    // existing (including removed or blocked) users are never re-staged
+    if force_get_user(user, expires).await?.is_some() {
+        return Ok(false);
+    }
 
-    if !user.is_managed() {
-        tracing::warn!(
-            "Ignoring request to remove {} as they are not managed by OpenPortal",
-            user.identifier()
-        );
-        return Err(Error::UnmanagedUser(format!(
-            "User {} is not managed by OpenPortal. Either add this user to the managed group, or remove them from FreeIPA.",
-            user.identifier()
-        )));
+    if is_staged_user(user, expires).await? {
+        tracing::info!("User {} is already staged", user);
+        return Ok(true);
     }
 
-    if user.is_disabled() && !user.is_blocked() {
-        // nothing to do - user has already been removed
-        tracing::info!(
-            "User {} is already disabled. No changes needed.",
-            user.identifier()
-        );
-        return Ok(user);
+    assert_not_expired(expires)?;
+
+    let userid = identifier_to_userid(user).await?;
+
+    if let Some(reason) = protected_user_reason(&userid, None) {
+        tracing::warn!("Refusing to stage user {} as {}", user, reason);
+        return Err(Error::UnmanagedUser(format!(
+            "Refusing to stage user {} as {}",
+            user, reason
+        )));
     }
 
-    // get the group used for openportal users of this peer
-    let instance_group = get_op_instance_group(instance)?;
+    let managed_group = get_managed_group()?;
 
-    // maybe don't do anything if the user isn't a member of this group
-    if !user.in_group(&instance_group) {
-        // check that they are in any groups...
+    let mut kwargs = {
+        let mut kwargs = HashMap::new();
+        kwargs.insert("uid".to_string(), userid);
+        kwargs.insert("givenname".to_string(), user.username().to_string());
+        kwargs.insert("sn".to_string(), user.project().to_string());
+        kwargs.insert("userclass".to_string(), managed_group.groupid().to_string());
+        kwargs.insert("cn".to_string(), user.to_string());
+        kwargs
+    };
+
+    if let Some(homedir) = homedir {
+        kwargs.insert("homedirectory".to_string(), homedir.to_string());
+    }
+
+    if let Some(id) = allocate_id(&user.portal(), expires).await? {
+        kwargs.insert("uidnumber".to_string(), id.to_string());
+        kwargs.insert("gidnumber".to_string(), id.to_string());
+    }
+
+    match call_post::<IPAResponse>("stageuser_add", None, Some(kwargs), expires).await {
+        Ok(_) => {
+            tracing::info!("Successfully staged user: {}", user);
+            Ok(true)
+        }
+        Err(Error::Duplicate(_)) => {
+            tracing::warn!("User {} was already staged", user);
+            Ok(true)
+        }
+        Err(e) => {
+            tracing::error!("Could not stage user: {}. Error: {}", user, e);
+            Err(Error::Call(format!(
+                "Could not stage user: {}. Error: {}",
+                user, e
+            )))
+        }
+    }
+}
+
+///
+/// Activate the passed stage user, moving them to the active users so
+/// that they can log in, and then adding them to the managed group and
+/// their project and instance groups. Activating a user who already
+/// has an active account just makes sure that their groups are correct
+///
+pub async fn activate_user(
+    user: &UserIdentifier,
+    instance: &Peer,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<IPAUser, Error> {
+    {
+        // get a lock for this user - this is released before `add_user`
+        // is called below, as that takes the same lock
+        let now = chrono::Utc::now();
+
+        let _guard = loop {
+            match cache::get_user_mutex(user).await?.try_lock_owned() {
+                Ok(guard) => break guard,
+                Err(_) => {
+                    if chrono::Utc::now().signed_duration_since(now).num_seconds() > 5 {
+                        tracing::warn!(
+                            "Could not get lock to activate user {} - another task is adding or removing.",
+                            user
+                        );
+
+                        return Err(Error::Locked(format!(
+                            "Could not get lock to activate user {} - another task is adding or removing.",
+                            user
+                        )));
+                    }
+
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                }
+            };
+        };
+
+        assert_not_expired(expires)?;
+
+        if !is_staged_user(user, expires).await? {
+            if force_get_user(user, expires).await?.is_none() {
+                return Err(Error::NotFound(format!(
+                    "Cannot activate user {} as they have not been added",
+                    user
+                )));
+            }
+
+            tracing::info!("User {} is already active", user);
+        } else {
+            let userid = identifier_to_userid(user).await?;
+
+            let kwargs = {
+                let mut kwargs = HashMap::new();
+                kwargs.insert("uid".to_string(), userid.clone());
+                kwargs
+            };
+
+            // we need to let the below go to completion, even if expired,
+            // as the user must be in the managed group once activated
+            match call_post::<IPAResponse>("stageuser_activate", None, Some(kwargs), expires).await
+            {
+                Ok(_) => {
+                    tracing::info!("Successfully activated user: {}", user);
+                }
+                Err(e) => {
+                    tracing::error!("Could not activate user: {}. Error: {}", user, e);
+                    return Err(Error::Call(format!(
+                        "Could not activate user: {}. Error: {}",
+                        user, e
+                    )));
+                }
+            }
+
+            let managed_group =
+                get_group_create_if_not_exists(&get_managed_group()?, expires).await?;
+
+            let kwargs = {
+                let mut kwargs = HashMap::new();
+                kwargs.insert("cn".to_string(), managed_group.groupid().to_string());
+                kwargs.insert("user".to_string(), userid);
+                kwargs
+            };
+
+            call_post::<IPAResponse>("group_add_member", None, Some(kwargs), expires)
+                .await
+                .map_err(|e| {
+                    tracing::error!(
+                        "Could not add activated user {} to group {}. Error: {}",
+                        user,
+                        managed_group,
+                        e
+                    );
+                    Error::Call(format!(
+                        "Could not add activated user {} to group {}. Error: {}",
+                        user, managed_group, e
+                    ))
+                })?;
+
+            // FreeIPA has changed, so the cached groups are out of date
+            cache::clear().await?;
+        }
+    }
+
+    // the user is now active and managed, so this will synchronise
+    // their project and instance groups
+    add_user(user, instance, &None, expires).await
+}
+
+///
+/// Remove the user from FreeIPA - this will return the removed user if
+/// successful, or will return an error if the user doesn't exist, or
+/// something else goes wrong. Note that the user must be managed by
+/// OpenPortal, or an error will be returned
+///
+pub async fn remove_user(
+    user: &UserIdentifier,
+    instance: &Peer,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<IPAUser, Error> {
+    // get and lock a mutex on this user, as we should only have a single
+    // task adding or removing this user at once
+    let now = chrono::Utc::now();
+
+    let _guard = loop {
+        match cache::get_user_mutex(user).await?.try_lock_owned() {
+            Ok(guard) => break guard,
+            Err(_) => {
+                if chrono::Utc::now().signed_duration_since(now).num_seconds() > 5 {
+                    tracing::warn!(
+                        "Could not get lock to remove user {} - another task is adding or removing.",
+                        user
+                    );
+
+                    return Err(Error::Locked(format!(
+                        "Could not get lock to remove user {} - another task is adding or removing.",
+                        user
+                    )));
+                }
+
+                assert_not_expired(expires)?;
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+        };
+    };
+
+    assert_not_expired(expires)?;
+
+    // force get this user, as we need to have up-to-date information from FreeIPA
+    let mut user = match force_get_user(user, expires).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            tracing::warn!(
+                "Could not find user {}. Assuming they have already been removed.",
+                user
+            );
+            return Err(Error::NotFound(format!(
+                "Could not find user {}. Assuming they have already been removed.",
+                user
+            )));
+        }
+        Err(e) => {
+            tracing::error!("Could not find user {}. Error: {}", user, e);
+            return Err(Error::Call(format!(
+                "Could not find user {}. Error: {}",
+                user, e
+            )));
+        }
+    };
+
+    if !user.is_managed() {
+        tracing::warn!(
+            "Ignoring request to remove {} as they are not managed by OpenPortal",
+            user.identifier()
+        );
+        return Err(Error::UnmanagedUser(format!(
+            "User {} is not managed by OpenPortal. Either add this user to the managed group, or remove them from FreeIPA.",
+            user.identifier()
+        )));
+    }
+
+    if user.is_disabled() && !user.is_blocked() {
+        // nothing to do - user has already been removed
+        tracing::info!(
+            "User {} is already disabled. No changes needed.",
+            user.identifier()
+        );
+        return Ok(user);
+    }
+
+    // get the group used for openportal users of this peer
+    let instance_group = get_op_instance_group(instance)?;
+
+    // maybe don't do anything if the user isn't a member of this group
+    if !user.in_group(&instance_group) {
+        // check that they are in any groups...
         let in_other_instance_groups = match get_groups_for_user(&user, expires).await {
             Ok(groups) => !groups
                 .iter()
@@ -2701,10 +3892,18 @@ pub async fn update_homedir(
     }
 
     // get the user from FreeIPA
-    let user = get_user(user, expires).await?.ok_or(Error::Call(format!(
-        "User {} does not exist in FreeIPA?",
-        user
-    )))?;
+    let user = match get_user(user, expires).await? {
+        Some(user) => user,
+        None => match is_staged_user(user, expires).await? {
+            true => return update_staged_homedir(user, homedir, expires).await,
+            false => {
+                return Err(Error::Call(format!(
+                    "User {} does not exist in FreeIPA?",
+                    user
+                )))
+            }
+        },
+    };
 
     assert_not_expired(expires)?;
 
@@ -2785,70 +3984,234 @@ pub async fn update_homedir(
 }
 
 ///
-/// Return all of the groups that are managed by OpenPortal for the
-/// passed portal
+/// Update the email address, display name and login shell of the passed
+/// user from the passed details. Only the details that are set are
+/// changed. This returns the updated user, or the unchanged user if
+/// they are not managed by OpenPortal
 ///
-pub async fn get_groups(
-    portal: &PortalIdentifier,
-    peer: &Peer,
+pub async fn update_user(
+    user: &UserIdentifier,
+    details: &UserDetails,
     expires: &chrono::DateTime<Utc>,
-) -> Result<Vec<IPAGroup>, Error> {
-    tracing::debug!("Getting managed groups for portal: {}", portal);
-    if is_internal_portal(&portal.portal()) {
-        // return an empty set of groups for internal portals
-        return Ok(Vec::new());
-    }
+) -> Result<IPAUser, Error> {
+    // get a lock for this user, as only a single task should be adding
+    // or removing this user at the same time
+    let now = chrono::Utc::now();
 
-    // Strategy: search for groups by portal name prefix, then filter to those
-    // that have at least one member in the instance group. This avoids enumerating
-    // all users on the system and performing N+1 group lookups per user.
-    let instance_group = get_op_instance_group(peer)?;
+    let _guard = loop {
+        match cache::get_user_mutex(user).await?.try_lock_owned() {
+            Ok(guard) => break guard,
+            Err(_) => {
+                if chrono::Utc::now().signed_duration_since(now).num_seconds() > 5 {
+                    tracing::warn!(
+                        "Could not get lock to update user {} - another task is adding or removing.",
+                        user
+                    );
 
-    // Step 1: get the UIDs of all direct members of the instance group (single API call,
-    // no full user object construction needed)
-    tracing::info!(
-        "Getting members of instance group '{}' for portal {}",
-        instance_group.groupid(),
-        portal
-    );
+                    return Err(Error::Locked(format!(
+                        "Could not get lock to update user {} - another task is adding or removing.",
+                        user
+                    )));
+                }
 
-    let instance_members = get_instance_group_member_uids(&instance_group, expires).await?;
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+        };
+    };
 
     assert_not_expired(expires)?;
 
-    if instance_members.is_empty() {
+    let user = force_get_user(user, expires)
+        .await?
+        .ok_or(Error::NotFound(format!(
+            "User {} does not exist in FreeIPA",
+            user
+        )))?;
+
+    if !user.is_managed() {
         tracing::warn!(
-            "No users in instance group '{}' for portal {}",
-            instance_group.groupid(),
-            portal
+            "Ignoring request to update {} as they are not managed by OpenPortal",
+            user.identifier()
         );
-        return Ok(Vec::new());
+        return Ok(user);
     }
 
-    tracing::info!(
-        "Instance group '{}' has {} members for portal {}",
-        instance_group.groupid(),
-        instance_members.len(),
-        portal
-    );
+    let mut kwargs = HashMap::new();
 
-    // Step 2: find all groups for this portal using the server-side name prefix filter.
-    // Group names are "{portal}.{project}", so passing "{portal}." as the positional
-    // search criteria restricts FreeIPA's substring match to only this portal's groups.
-    // Note: cn must be a positional arg (not a kwarg) for FreeIPA to use substring matching;
-    // passing it as a kwarg triggers exact matching.
-    let portal_prefix = format!("{}.", portal.portal());
-    let kwargs = {
-        let mut kwargs = HashMap::new();
-        kwargs.insert("all".to_string(), "true".to_string());
-        kwargs.insert("sizelimit".to_string(), "4096".to_string());
-        kwargs
-    };
+    if let Some(email) = details.email() {
+        kwargs.insert("mail".to_string(), email);
+    }
 
-    let result = call_post::<IPAResponse>(
-        "group_find",
-        Some(vec![portal_prefix]),
-        Some(kwargs),
+    if let Some(display_name) = details.display_name() {
+        kwargs.insert("displayname".to_string(), display_name);
+    }
+
+    if let Some(shell) = details.shell() {
+        kwargs.insert("loginshell".to_string(), shell);
+    }
+
+    if kwargs.is_empty() {
+        tracing::debug!("No details to update for user {}", user.identifier());
+        return Ok(user);
+    }
+
+    kwargs.insert("uid".to_string(), user.userid().to_string());
+
+    assert_not_expired(expires)?;
+
+    match call_post::<IPAResponse>("user_mod", None, Some(kwargs), expires).await {
+        Ok(_) => {
+            tracing::info!(
+                "Successfully updated details for user: {}",
+                user.identifier()
+            );
+        }
+        // FreeIPA returns an error if nothing was changed
+        Err(Error::Call(e)) if e.contains("EmptyModlist") => {
+            tracing::debug!(
+                "Details for user {} are already up to date",
+                user.identifier()
+            );
+        }
+        Err(Error::NotFound(_)) => {
+            tracing::info!(
+                "User {} not found in FreeIPA. Assuming it has been removed behind our back.",
+                user
+            );
+
+            // clear the cache as FreeIPA has been changed behind our back
+            cache::clear().await?;
+
+            return Err(Error::NotFound(format!(
+                "User {} does not exist in FreeIPA",
+                user.identifier()
+            )));
+        }
+        Err(e) => {
+            tracing::error!(
+                "Could not update details for user {}. Error: {}",
+                user.identifier(),
+                e
+            );
+            return Err(Error::Call(format!(
+                "Could not update details for user {}. Error: {}",
+                user.identifier(),
+                e
+            )));
+        }
+    }
+
+    force_get_user(user.identifier(), expires)
+        .await?
+        .ok_or(Error::NotFound(format!(
+            "User {} does not exist in FreeIPA",
+            user.identifier()
+        )))
+}
+
+///
+/// Update the home directory of the passed stage user, which is set
+/// when the user is activated
+///
+async fn update_staged_homedir(
+    user: &UserIdentifier,
+    homedir: &str,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<String, Error> {
+    let kwargs = {
+        let mut kwargs = HashMap::new();
+        kwargs.insert("uid".to_string(), identifier_to_userid(user).await?);
+        kwargs.insert("homedirectory".to_string(), homedir.to_string());
+        kwargs
+    };
+
+    match call_post::<IPAResponse>("stageuser_mod", None, Some(kwargs), expires).await {
+        Ok(_) => {
+            tracing::info!("Homedir for staged user {} is {}", user, homedir);
+            Ok(homedir.to_string())
+        }
+        // FreeIPA returns an error if nothing was changed
+        Err(Error::Call(e)) if e.contains("EmptyModlist") => Ok(homedir.to_string()),
+        Err(e) => {
+            tracing::error!(
+                "Could not update homedir for staged user {} to {}. Error: {}",
+                user,
+                homedir,
+                e
+            );
+            Err(Error::Call(format!(
+                "Could not update homedir for staged user {} to {}. Error: {}",
+                user, homedir, e
+            )))
+        }
+    }
+}
+
+///
+/// Return all of the groups that are managed by OpenPortal for the
+/// passed portal
+///
+pub async fn get_groups(
+    portal: &PortalIdentifier,
+    peer: &Peer,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Vec<IPAGroup>, Error> {
+    tracing::debug!("Getting managed groups for portal: {}", portal);
+    if is_internal_portal(&portal.portal()) {
+        // return an empty set of groups for internal portals
+        return Ok(Vec::new());
+    }
+
+    // Strategy: search for groups by portal name prefix, then filter to those
+    // that have at least one member in the instance group. This avoids enumerating
+    // all users on the system and performing N+1 group lookups per user.
+    let instance_group = get_op_instance_group(peer)?;
+
+    // Step 1: get the UIDs of all direct members of the instance group (single API call,
+    // no full user object construction needed)
+    tracing::info!(
+        "Getting members of instance group '{}' for portal {}",
+        instance_group.groupid(),
+        portal
+    );
+
+    let instance_members = get_instance_group_member_uids(&instance_group, expires).await?;
+
+    assert_not_expired(expires)?;
+
+    if instance_members.is_empty() {
+        tracing::warn!(
+            "No users in instance group '{}' for portal {}",
+            instance_group.groupid(),
+            portal
+        );
+        return Ok(Vec::new());
+    }
+
+    tracing::info!(
+        "Instance group '{}' has {} members for portal {}",
+        instance_group.groupid(),
+        instance_members.len(),
+        portal
+    );
+
+    // Step 2: find all groups for this portal using the server-side name prefix filter.
+    // Group names are "{portal}.{project}", so passing "{portal}." as the positional
+    // search criteria restricts FreeIPA's substring match to only this portal's groups.
+    // Note: cn must be a positional arg (not a kwarg) for FreeIPA to use substring matching;
+    // passing it as a kwarg triggers exact matching.
+    let portal_prefix = format!("{}.", portal.portal());
+    let kwargs = {
+        let mut kwargs = HashMap::new();
+        kwargs.insert("all".to_string(), "true".to_string());
+        kwargs.insert("sizelimit".to_string(), "4096".to_string());
+        kwargs
+    };
+
+    let result = call_post::<IPAResponse>(
+        "group_find",
+        Some(vec![portal_prefix]),
+        Some(kwargs),
         expires,
     )
     .await?;
@@ -3058,6 +4421,72 @@ pub async fn is_protected_user(
     }
 }
 
+///
+/// Return whether or not the passed user is protected, and why. Users
+/// who don't exist are protected if their account name would be
+///
+pub async fn get_user_protection(
+    user: &UserIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<ProtectionStatus, Error> {
+    let identity = user.to_string();
+
+    match force_get_user(user, expires).await? {
+        Some(user) => match user.protection_reason() {
+            Some(reason) => Ok(ProtectionStatus::protected(
+                &identity,
+                Some(user.userid()),
+                &reason,
+            )),
+            None => Ok(ProtectionStatus::unprotected(
+                &identity,
+                Some(user.userid()),
+            )),
+        },
+        None => {
+            let userid = identifier_to_userid(user).await?;
+
+            match protected_user_reason(&userid, None) {
+                Some(reason) => Ok(ProtectionStatus::protected(&identity, None, &reason)),
+                None => Ok(ProtectionStatus::unprotected(&identity, None)),
+            }
+        }
+    }
+}
+
+///
+/// Return whether or not the group for the passed project is protected,
+/// and why. Groups that don't exist are protected if their name would be
+///
+pub async fn get_project_protection(
+    project: &ProjectIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<ProtectionStatus, Error> {
+    let identity = project.to_string();
+
+    match get_group(project, expires).await? {
+        Some(group) => match group.protection_reason() {
+            Some(reason) => Ok(ProtectionStatus::protected(
+                &identity,
+                Some(group.groupid()),
+                &reason,
+            )),
+            None => Ok(ProtectionStatus::unprotected(
+                &identity,
+                Some(group.groupid()),
+            )),
+        },
+        None => {
+            let groupid = identifier_to_projectid(project, false)?;
+
+            match protected_group_reason(&groupid, None) {
+                Some(reason) => Ok(ProtectionStatus::protected(&identity, None, &reason)),
+                None => Ok(ProtectionStatus::unprotected(&identity, None)),
+            }
+        }
+    }
+}
+
 pub async fn is_existing_user(
     user: &UserIdentifier,
     expires: &chrono::DateTime<Utc>,
@@ -3325,3 +4754,788 @@ pub async fn unblock_user(
 
     Ok(user)
 }
+
+///
+/// Return whether the two passed SSH public keys are the same key.
+/// Only the type and key data are compared, so the comment is ignored
+///
+fn is_same_ssh_key(a: &str, b: &str) -> bool {
+    let a: Vec<&str> = a.split_whitespace().take(2).collect();
+    let b: Vec<&str> = b.split_whitespace().take(2).collect();
+    a.len() == 2 && a == b
+}
+
+///
+/// Return the SSH public keys of the passed user
+///
+pub async fn get_user_ssh_keys(
+    user: &UserIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Vec<String>, Error> {
+    match force_get_user(user, expires).await? {
+        Some(user) => Ok(user.ssh_keys().clone()),
+        None => Err(Error::NotFound(format!(
+            "Could not find user {} to get their SSH keys.",
+            user
+        ))),
+    }
+}
+
+///
+/// Add the passed SSH public key to the user's `ipasshpubkey` attribute,
+/// as long as this would not give the user more than the maximum number
+/// of keys. This returns all of the user's keys
+///
+pub async fn add_user_ssh_key(
+    user: &UserIdentifier,
+    key: &str,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Vec<String>, Error> {
+    let now = chrono::Utc::now();
+
+    let _guard = loop {
+        match cache::get_user_mutex(user).await?.try_lock_owned() {
+            Ok(guard) => break guard,
+            Err(_) => {
+                if chrono::Utc::now().signed_duration_since(now).num_seconds() > 5 {
+                    return Err(Error::Locked(format!(
+                        "Could not get lock to add an SSH key to user {} - another task is adding or removing.",
+                        user
+                    )));
+                }
+                assert_not_expired(expires)?;
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+        };
+    };
+
+    assert_not_expired(expires)?;
+
+    let key = parse_ssh_public_key(key)?;
+
+    let user = match force_get_user(user, expires).await? {
+        Some(user) => user,
+        None => {
+            return Err(Error::NotFound(format!(
+                "Could not find user {} to add an SSH key.",
+                user
+            )));
+        }
+    };
+
+    if !user.is_managed() {
+        return Err(Error::UnmanagedUser(format!(
+            "User {} is not managed by OpenPortal - cannot add an SSH key.",
+            user.identifier()
+        )));
+    }
+
+    if user.ssh_keys().iter().any(|k| is_same_ssh_key(k, &key)) {
+        tracing::info!(
+            "User {} already has this SSH key - nothing to do.",
+            user.identifier()
+        );
+        return Ok(user.ssh_keys().clone());
+    }
+
+    let max_ssh_keys = cache::get_max_ssh_keys().await?;
+
+    if max_ssh_keys > 0 && user.ssh_keys().len() >= max_ssh_keys {
+        return Err(Error::InvalidState(format!(
+            "User {} already has the maximum of {} SSH keys - remove a key before adding another.",
+            user.identifier(),
+            max_ssh_keys
+        )));
+    }
+
+    assert_not_expired(expires)?;
+
+    let kwargs = {
+        let mut kwargs = HashMap::new();
+        kwargs.insert("uid".to_string(), user.userid().to_string());
+        kwargs.insert("addattr".to_string(), format!("ipasshpubkey={}", key));
+        kwargs
+    };
+
+    match call_post::<IPAResponse>("user_mod", None, Some(kwargs), expires).await {
+        Ok(_) => {
+            tracing::info!("Added SSH key to user {}", user.identifier());
+        }
+        Err(e) => {
+            tracing::error!("Could not add SSH key to user {}: {}", user.identifier(), e);
+            return Err(e);
+        }
+    }
+
+    get_user_ssh_keys(user.identifier(), expires).await
+}
+
+///
+/// Remove the passed SSH public key from the user's `ipasshpubkey`
+/// attribute. The key is matched on its type and key data, so its
+/// comment does not need to match. This returns the user's remaining keys
+///
+pub async fn remove_user_ssh_key(
+    user: &UserIdentifier,
+    key: &str,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Vec<String>, Error> {
+    let now = chrono::Utc::now();
+
+    let _guard = loop {
+        match cache::get_user_mutex(user).await?.try_lock_owned() {
+            Ok(guard) => break guard,
+            Err(_) => {
+                if chrono::Utc::now().signed_duration_since(now).num_seconds() > 5 {
+                    return Err(Error::Locked(format!(
+                        "Could not get lock to remove an SSH key from user {} - another task is adding or removing.",
+                        user
+                    )));
+                }
+                assert_not_expired(expires)?;
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+        };
+    };
+
+    assert_not_expired(expires)?;
+
+    let key = parse_ssh_public_key(key)?;
+
+    let user = match force_get_user(user, expires).await? {
+        Some(user) => user,
+        None => {
+            return Err(Error::NotFound(format!(
+                "Could not find user {} to remove an SSH key.",
+                user
+            )));
+        }
+    };
+
+    if !user.is_managed() {
+        return Err(Error::UnmanagedUser(format!(
+            "User {} is not managed by OpenPortal - cannot remove an SSH key.",
+            user.identifier()
+        )));
+    }
+
+    // FreeIPA needs the exact value that is stored
+    let stored_key = match user.ssh_keys().iter().find(|k| is_same_ssh_key(k, &key)) {
+        Some(stored_key) => stored_key.clone(),
+        None => {
+            tracing::info!(
+                "User {} does not have this SSH key - nothing to do.",
+                user.identifier()
+            );
+            return Ok(user.ssh_keys().clone());
+        }
+    };
+
+    assert_not_expired(expires)?;
+
+    let kwargs = {
+        let mut kwargs = HashMap::new();
+        kwargs.insert("uid".to_string(), user.userid().to_string());
+        kwargs.insert(
+            "delattr".to_string(),
+            format!("ipasshpubkey={}", stored_key),
+        );
+        kwargs
+    };
+
+    match call_post::<IPAResponse>("user_mod", None, Some(kwargs), expires).await {
+        Ok(_) => {
+            tracing::info!("Removed SSH key from user {}", user.identifier());
+        }
+        Err(e) => {
+            tracing::error!(
+                "Could not remove SSH key from user {}: {}",
+                user.identifier(),
+                e
+            );
+            return Err(e);
+        }
+    }
+
+    get_user_ssh_keys(user.identifier(), expires).await
+}
+
+///
+/// Return the secondary group with the passed name, as long as it is
+/// one of the groups that users can be added to individually
+///
+async fn get_secondary_group(group: &str) -> Result<IPAGroup, Error> {
+    match cache::get_secondary_groups()
+        .await?
+        .into_iter()
+        .find(|g| g.groupid() == group)
+    {
+        Some(group) => Ok(group),
+        None => Err(Error::InvalidInstruction(format!(
+            "Group {} is not one of the secondary groups that users can be added to.",
+            group
+        ))),
+    }
+}
+
+///
+/// Return the names of the secondary groups that the user is a member of
+///
+async fn get_user_secondary_groups(user: &IPAUser) -> Result<Vec<String>, Error> {
+    Ok(cache::get_secondary_groups()
+        .await?
+        .iter()
+        .filter(|g| user.in_group(g))
+        .map(|g| g.groupid().to_string())
+        .collect())
+}
+
+///
+/// Add the user to the passed secondary group (e.g. a software licence
+/// group), creating the group if it doesn't exist. Only groups listed
+/// in the agent's secondary groups can be used. This returns all of
+/// the secondary groups that the user is a member of
+///
+pub async fn add_user_to_group(
+    user: &UserIdentifier,
+    group: &str,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Vec<String>, Error> {
+    let now = chrono::Utc::now();
+
+    let _guard = loop {
+        match cache::get_user_mutex(user).await?.try_lock_owned() {
+            Ok(guard) => break guard,
+            Err(_) => {
+                if chrono::Utc::now().signed_duration_since(now).num_seconds() > 5 {
+                    return Err(Error::Locked(format!(
+                        "Could not get lock to add user {} to a group - another task is adding or removing.",
+                        user
+                    )));
+                }
+                assert_not_expired(expires)?;
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+        };
+    };
+
+    assert_not_expired(expires)?;
+
+    let group = get_secondary_group(group).await?;
+
+    let user = match force_get_user(user, expires).await? {
+        Some(user) => user,
+        None => {
+            return Err(Error::NotFound(format!(
+                "Could not find user {} to add to group {}.",
+                user,
+                group.groupid()
+            )));
+        }
+    };
+
+    if !user.is_managed() {
+        return Err(Error::UnmanagedUser(format!(
+            "User {} is not managed by OpenPortal - cannot add them to group {}.",
+            user.identifier(),
+            group.groupid()
+        )));
+    }
+
+    if user.in_group(&group) {
+        tracing::info!(
+            "User {} is already in group {} - nothing to do.",
+            user.identifier(),
+            group.groupid()
+        );
+        return get_user_secondary_groups(&user).await;
+    }
+
+    let group = get_group_create_if_not_exists(&group, expires).await?;
+
+    assert_not_expired(expires)?;
+
+    let kwargs = {
+        let mut kwargs = HashMap::new();
+        kwargs.insert("cn".to_string(), group.groupid().to_string());
+        kwargs.insert("user".to_string(), user.userid().to_string());
+        kwargs
+    };
+
+    match call_post::<IPAResponse>("group_add_member", None, Some(kwargs), expires).await {
+        Ok(_) => {
+            tracing::info!(
+                "Added user {} to group {}",
+                user.identifier(),
+                group.groupid()
+            );
+        }
+        Err(e) => {
+            tracing::error!(
+                "Could not add user {} to group {}: {}",
+                user.identifier(),
+                group.groupid(),
+                e
+            );
+            return Err(e);
+        }
+    }
+
+    match force_get_user(user.identifier(), expires).await? {
+        Some(user) => get_user_secondary_groups(&user).await,
+        None => Err(Error::InvalidState(format!(
+            "User {} no longer exists in FreeIPA after being added to group {}.",
+            user.identifier(),
+            group.groupid()
+        ))),
+    }
+}
+
+///
+/// Remove the user from the passed secondary group. Only groups listed
+/// in the agent's secondary groups can be used. This returns the
+/// secondary groups that the user is still a member of
+///
+pub async fn remove_user_from_group(
+    user: &UserIdentifier,
+    group: &str,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Vec<String>, Error> {
+    let now = chrono::Utc::now();
+
+    let _guard = loop {
+        match cache::get_user_mutex(user).await?.try_lock_owned() {
+            Ok(guard) => break guard,
+            Err(_) => {
+                if chrono::Utc::now().signed_duration_since(now).num_seconds() > 5 {
+                    return Err(Error::Locked(format!(
+                        "Could not get lock to remove user {} from a group - another task is adding or removing.",
+                        user
+                    )));
+                }
+                assert_not_expired(expires)?;
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+        };
+    };
+
+    assert_not_expired(expires)?;
+
+    let group = get_secondary_group(group).await?;
+
+    let user = match force_get_user(user, expires).await? {
+        Some(user) => user,
+        None => {
+            return Err(Error::NotFound(format!(
+                "Could not find user {} to remove from group {}.",
+                user,
+                group.groupid()
+            )));
+        }
+    };
+
+    if !user.is_managed() {
+        return Err(Error::UnmanagedUser(format!(
+            "User {} is not managed by OpenPortal - cannot remove them from group {}.",
+            user.identifier(),
+            group.groupid()
+        )));
+    }
+
+    if !user.in_group(&group) {
+        tracing::info!(
+            "User {} is not in group {} - nothing to do.",
+            user.identifier(),
+            group.groupid()
+        );
+        return get_user_secondary_groups(&user).await;
+    }
+
+    assert_not_expired(expires)?;
+
+    let kwargs = {
+        let mut kwargs = HashMap::new();
+        kwargs.insert("cn".to_string(), group.groupid().to_string());
+        kwargs.insert("user".to_string(), user.userid().to_string());
+        kwargs
+    };
+
+    match call_post::<IPAResponse>("group_remove_member", None, Some(kwargs), expires).await {
+        Ok(_) => {
+            tracing::info!(
+                "Removed user {} from group {}",
+                user.identifier(),
+                group.groupid()
+            );
+        }
+        Err(e) => {
+            tracing::error!(
+                "Could not remove user {} from group {}: {}",
+                user.identifier(),
+                group.groupid(),
+                e
+            );
+            return Err(e);
+        }
+    }
+
+    match force_get_user(user.identifier(), expires).await? {
+        Some(user) => get_user_secondary_groups(&user).await,
+        None => Err(Error::InvalidState(format!(
+            "User {} no longer exists in FreeIPA after being removed from group {}.",
+            user.identifier(),
+            group.groupid()
+        ))),
+    }
+}
+
+///
+/// Return the unique IDs of the OTP tokens in the passed result
+/// of `otptoken_find`
+///
+fn otp_token_ids(result: &serde_json::Value) -> Vec<String> {
+    let result = match result.as_array() {
+        Some(result) => result.clone(),
+        None => vec![result.clone()],
+    };
+
+    result
+        .iter()
+        .filter_map(|token| token.get("ipatokenuniqueid"))
+        .filter_map(|id| match id.as_array() {
+            Some(ids) => ids.first().and_then(|id| id.as_str()),
+            None => id.as_str(),
+        })
+        .map(|id| id.to_string())
+        .collect()
+}
+
+///
+/// Return the IDs of all of the OTP tokens owned by the passed user
+///
+pub async fn get_user_otp_tokens(
+    user: &UserIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Vec<String>, Error> {
+    let user = match force_get_user(user, expires).await? {
+        Some(user) => user,
+        None => {
+            return Err(Error::NotFound(format!(
+                "Could not find user {} to get their OTP tokens.",
+                user
+            )));
+        }
+    };
+
+    let kwargs = {
+        let mut kwargs = HashMap::new();
+        kwargs.insert("ipatokenowner".to_string(), user.userid().to_string());
+        kwargs
+    };
+
+    let result = call_post::<IPAResponse>("otptoken_find", None, Some(kwargs), expires).await?;
+
+    Ok(otp_token_ids(&result.result.unwrap_or_default()))
+}
+
+///
+/// Issue a new (TOTP) OTP token to the passed user. This returns the
+/// `otpauth://` URI of the token, which contains the token's secret,
+/// so that the user can enrol it in their authenticator app. The URI
+/// cannot be retrieved again later
+///
+pub async fn add_user_otp_token(
+    user: &UserIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<String, Error> {
+    let now = chrono::Utc::now();
+
+    let _guard = loop {
+        match cache::get_user_mutex(user).await?.try_lock_owned() {
+            Ok(guard) => break guard,
+            Err(_) => {
+                if chrono::Utc::now().signed_duration_since(now).num_seconds() > 5 {
+                    return Err(Error::Locked(format!(
+                        "Could not get lock to add an OTP token to user {} - another task is adding or removing.",
+                        user
+                    )));
+                }
+                assert_not_expired(expires)?;
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+        };
+    };
+
+    assert_not_expired(expires)?;
+
+    let user = match force_get_user(user, expires).await? {
+        Some(user) => user,
+        None => {
+            return Err(Error::NotFound(format!(
+                "Could not find user {} to add an OTP token.",
+                user
+            )));
+        }
+    };
+
+    if !user.is_managed() {
+        return Err(Error::UnmanagedUser(format!(
+            "User {} is not managed by OpenPortal - cannot add an OTP token.",
+            user.identifier()
+        )));
+    }
+
+    assert_not_expired(expires)?;
+
+    let kwargs = {
+        let mut kwargs = HashMap::new();
+        kwargs.insert("ipatokenowner".to_string(), user.userid().to_string());
+        kwargs.insert("type".to_string(), "totp".to_string());
+        kwargs.insert(
+            "description".to_string(),
+            "Issued by OpenPortal".to_string(),
+        );
+        kwargs
+    };
+
+    let result =
+        match call_post::<serde_json::Value>("otptoken_add", None, Some(kwargs), expires).await {
+            Ok(result) => result,
+            Err(e) => {
+                tracing::error!(
+                    "Could not add OTP token to user {}: {}",
+                    user.identifier(),
+                    e
+                );
+                return Err(e);
+            }
+        };
+
+    // the URI is returned alongside the token, rather than as one of
+    // its attributes
+    let uri = result
+        .get("uri")
+        .or_else(|| result.get("result").and_then(|r| r.get("uri")))
+        .and_then(|uri| match uri.as_array() {
+            Some(uris) => uris.first().and_then(|uri| uri.as_str()),
+            None => uri.as_str(),
+        })
+        .ok_or(Error::Call(format!(
+            "FreeIPA did not return the URI of the OTP token added to user {}",
+            user.identifier()
+        )))?;
+
+    tracing::info!("Added OTP token to user {}", user.identifier());
+
+    Ok(uri.to_string())
+}
+
+///
+/// Revoke (delete) the OTP token with the passed ID from the passed user.
+/// This returns the IDs of the user's remaining tokens
+///
+pub async fn remove_user_otp_token(
+    user: &UserIdentifier,
+    token: &str,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Vec<String>, Error> {
+    let now = chrono::Utc::now();
+
+    let _guard = loop {
+        match cache::get_user_mutex(user).await?.try_lock_owned() {
+            Ok(guard) => break guard,
+            Err(_) => {
+                if chrono::Utc::now().signed_duration_since(now).num_seconds() > 5 {
+                    return Err(Error::Locked(format!(
+                        "Could not get lock to remove an OTP token from user {} - another task is adding or removing.",
+                        user
+                    )));
+                }
+                assert_not_expired(expires)?;
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+        };
+    };
+
+    assert_not_expired(expires)?;
+
+    let user = match force_get_user(user, expires).await? {
+        Some(user) => user,
+        None => {
+            return Err(Error::NotFound(format!(
+                "Could not find user {} to remove an OTP token.",
+                user
+            )));
+        }
+    };
+
+    if !user.is_managed() {
+        return Err(Error::UnmanagedUser(format!(
+            "User {} is not managed by OpenPortal - cannot remove an OTP token.",
+            user.identifier()
+        )));
+    }
+
+    // only tokens owned by this user can be removed
+    let tokens = get_user_otp_tokens(user.identifier(), expires).await?;
+
+    if !tokens.iter().any(|t| t == token) {
+        tracing::info!(
+            "User {} does not have OTP token {} - nothing to do.",
+            user.identifier(),
+            token
+        );
+        return Ok(tokens);
+    }
+
+    assert_not_expired(expires)?;
+
+    match call_post::<IPAResponse>("otptoken_del", Some(vec![token.to_string()]), None, expires)
+        .await
+    {
+        Ok(_) => {
+            tracing::info!(
+                "Removed OTP token {} from user {}",
+                token,
+                user.identifier()
+            );
+        }
+        Err(Error::NotFound(_)) => {
+            tracing::info!("OTP token {} has already been removed", token);
+        }
+        Err(e) => {
+            tracing::error!(
+                "Could not remove OTP token {} from user {}: {}",
+                token,
+                user.identifier(),
+                e
+            );
+            return Err(e);
+        }
+    }
+
+    get_user_otp_tokens(user.identifier(), expires).await
+}
+
+///
+/// Compare the passed project's group in FreeIPA against the passed
+/// (expected) user mappings of the project, plus the home directories
+/// that the filesystem expects for those users. Mapped users who do not
+/// exist, or who are missing from the project's group (or the other
+/// groups needed to be managed on `instance`) are reported as missing.
+/// Members of the group who are not mapped are reported as unexpected,
+/// and are never removed. If `repair` is true then the project's group
+/// is added if missing, group memberships are synchronised and home
+/// directories are corrected. Users who are not managed by OpenPortal
+/// are never changed
+///
+pub async fn reconcile(
+    project: &ProjectMapping,
+    users: &[UserMapping],
+    homedirs: &HashMap<UserIdentifier, String>,
+    instance: &Peer,
+    repair: bool,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<ReconciliationReport, Error> {
+    assert_not_expired(expires)?;
+
+    let mut report = ReconciliationReport::new(project.project());
+
+    let group = match get_group(project.project(), expires).await? {
+        Some(group) => Some(group),
+        None => {
+            report.missing_account = true;
+
+            match repair {
+                true => Some(add_project(project.project(), expires).await?),
+                false => None,
+            }
+        }
+    };
+
+    // the cached members may be stale, so always look in FreeIPA
+    let members = match &group {
+        Some(group) => force_get_users_in_group(group, expires).await?,
+        None => Vec::new(),
+    };
+
+    // the groups that users need to belong to to be managed on this instance
+    let mut required_groups = cache::get_system_groups().await?;
+    required_groups.extend(cache::get_instance_groups(instance).await?);
+    required_groups.push(get_managed_group()?);
+
+    let mut expected = Vec::new();
+    let mut out_of_sync = Vec::new();
+    let mut wrong_homedirs = Vec::new();
+    let mut num_absent = 0;
+
+    for mapping in users {
+        assert_not_expired(expires)?;
+
+        let local_user = mapping.local_user().to_string();
+        expected.push(local_user.clone());
+
+        // removed users are disabled, but not blocked
+        let user = match force_get_user(mapping.user(), expires).await? {
+            Some(user) if user.is_enabled() || user.is_blocked() => user,
+            _ => {
+                report.missing_users.push(local_user);
+                num_absent += 1;
+                continue;
+            }
+        };
+
+        if user.is_protected() {
+            continue;
+        }
+
+        // the groups of blocked users cannot be synchronised
+        if user.is_enabled()
+            && (!members.iter().any(|m| m.userid() == user.userid())
+                || !user.in_all_groups(&required_groups))
+        {
+            report.missing_users.push(local_user.clone());
+            out_of_sync.push(user.clone());
+        }
+
+        if let Some(homedir) = homedirs.get(mapping.user()) {
+            if user.home() != homedir {
+                report.wrong_homedirs.push(local_user);
+                wrong_homedirs.push((mapping.user().clone(), homedir.clone()));
+            }
+        }
+    }
+
+    for member in &members {
+        if !expected.iter().any(|e| e == member.userid()) {
+            report.unexpected_users.push(member.userid().to_string());
+        }
+    }
+
+    report.missing_users.sort();
+    report.unexpected_users.sort();
+    report.wrong_homedirs.sort();
+
+    if repair && report.has_drift() {
+        for user in out_of_sync {
+            sync_groups(&user, instance, expires).await?;
+            tracing::info!("Repaired groups of {} in {}", user.userid(), project);
+        }
+
+        for (user, homedir) in wrong_homedirs {
+            update_homedir(&user, &homedir, expires).await?;
+            tracing::info!("Repaired homedir of {} to {}", user, homedir);
+        }
+
+        // users who don't exist cannot be added without their portal,
+        // and unexpected users are not repaired, so the report only
+        // counts as repaired if everything else was
+        report.repaired = num_absent == 0;
+    }
+
+    if report.has_drift() {
+        tracing::warn!("Reconciled {}: {}", project, report);
+    }
+
+    Ok(report)
+}
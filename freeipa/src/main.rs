@@ -11,20 +11,76 @@ use freeipa::IPAGroup;
 
 mod cache;
 
-use templemeads::agent::account::{process_args, run, Defaults};
+use templemeads::agent::account::{process_args, run, Config, Defaults};
 use templemeads::agent::{Peer, Type as AgentType};
 use templemeads::async_runnable;
+use templemeads::batch;
+use templemeads::configcheck::ConfigCheck;
+use templemeads::dryrun;
 use templemeads::grammar::Instruction::{
-    AddProject, AddUser, BlockUser, GetProjectMapping, GetProjects, GetUserMapping, GetUsers,
-    IsBlockedUser, IsExistingProject, IsExistingUser, IsProtectedUser, RemoveProject, RemoveUser,
-    UnblockUser, UpdateHomeDir,
+    ActivateUser, AddProject, AddUser, AddUserOTPToken, AddUserSSHKey, AddUserToGroup, AddUsers,
+    BlockUser, GetProjectMapping, GetProjectProtection, GetProjects, GetUserMapping,
+    GetUserOTPTokens, GetUserProtection, GetUserSSHKeys, GetUsers, IsBlockedUser,
+    IsExistingProject, IsExistingUser, IsProtectedUser, ReconcileLocal, RemoveProject, RemoveUser,
+    RemoveUserFromGroup, RemoveUserOTPToken, RemoveUserSSHKey, RemoveUsers, UnblockUser,
+    UpdateHomeDir, UpdateUser,
 };
-use templemeads::grammar::UserMapping;
+use templemeads::grammar::{UserIdentifier, UserMapping};
 use templemeads::job::{assert_not_expired, Envelope, Job};
 use templemeads::notification::default_notify_runner;
+use templemeads::protection::ProtectionPolicy;
 use templemeads::set_notify_runner;
 use templemeads::Error;
 
+///
+/// Check the options of the freeipa agent when it is run with --check-config
+///
+fn check_freeipa_options(config: &Config, check: &mut ConfigCheck) {
+    if config.option("freeipa-server", "").trim().is_empty() {
+        check.error("freeipa-server", "No FreeIPA server specified");
+    }
+
+    config.check_secret("freeipa-password", check);
+
+    for key in ["system-groups", "secondary-groups"] {
+        check.check(key, IPAGroup::parse_system_groups(&config.option(key, "")));
+    }
+
+    check.check(
+        "instance-groups",
+        IPAGroup::parse_instance_groups(&config.option("instance-groups", "")),
+    );
+
+    check.check(
+        "protected-users",
+        ProtectionPolicy::parse(
+            &config.option("protected-users", "admin"),
+            &config.option("protected-uids", ""),
+        ),
+    );
+    check.check(
+        "protected-groups",
+        ProtectionPolicy::parse(
+            &config.option("protected-groups", "admins,editors,ipausers,trust admins"),
+            &config.option("protected-gids", ""),
+        ),
+    );
+    check.check(
+        "id-ranges",
+        freeipa::parse_id_ranges(&config.option("id-ranges", "")),
+    );
+
+    check.check_number::<u64>(
+        "freeipa-health-interval",
+        &config.option("freeipa-health-interval", ""),
+    );
+    check.check_number::<usize>(
+        "freeipa-connections",
+        &config.option("freeipa-connections", ""),
+    );
+    check.check_number::<usize>("max-ssh-keys", &config.option("max-ssh-keys", ""));
+}
+
 ///
 /// Main function for the freeipa-account application
 ///
@@ -41,7 +97,7 @@ async fn main() -> Result<()> {
     templemeads::spawn_system_monitor();
 
     // create the OpenPortal paddington defaults
-    let defaults: Defaults = Defaults::parse(
+    let mut defaults: Defaults = Defaults::parse(
         Some("freeipa".to_owned()),
         Some(
             dirs::config_local_dir()
@@ -60,6 +116,8 @@ async fn main() -> Result<()> {
         Some(AgentType::Account),
     );
 
+    defaults.set_config_check(check_freeipa_options);
+
     // now parse the command line arguments to get the service configuration
     let config = match process_args(&defaults).await? {
         Some(config) => config,
@@ -76,6 +134,8 @@ async fn main() -> Result<()> {
         IPAGroup::parse_system_groups(&config.option("system-groups", ""))?;
     let instance_groups: HashMap<Peer, Vec<IPAGroup>> =
         IPAGroup::parse_instance_groups(&config.option("instance-groups", ""))?;
+    let secondary_groups: Vec<IPAGroup> =
+        IPAGroup::parse_system_groups(&config.option("secondary-groups", ""))?;
 
     if freeipa_server.is_empty() {
         return Err(anyhow::anyhow!(
@@ -91,6 +151,28 @@ async fn main() -> Result<()> {
         .map(|s| s.trim().to_owned())
         .collect();
 
+    // the (optional) read-only replicas, which are only used for reads
+    let freeipa_replicas: Vec<String> = config
+        .option("freeipa-replicas", "")
+        .split(',')
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    // how often (in seconds) to check the health of each server
+    // (0 means that servers are never checked)
+    let freeipa_health_interval: u64 = config
+        .option("freeipa-health-interval", "60")
+        .parse()
+        .unwrap_or(60);
+
+    // the number of sessions to open to each server, so that this many
+    // calls can be made to each server at the same time
+    let freeipa_connections: usize = config
+        .option("freeipa-connections", "1")
+        .parse()
+        .unwrap_or(1);
+
     // the username and password for all FreeIPA servers must be the same
     let freeipa_password = match config.secret("freeipa-password") {
         Some(password) => password,
@@ -101,13 +183,84 @@ async fn main() -> Result<()> {
         }
     };
 
+    // the maximum number of SSH public keys that each user can have
+    // (0 means that there is no limit)
+    let max_ssh_keys: usize = config.option("max-ssh-keys", "10").parse().unwrap_or(10);
+
+    // whether new users are added as stage users, who cannot log in
+    // until they are activated via activate_user
+    let stage_users = config.option("stage-users", "false");
+    let stage_users = matches!(
+        stage_users.trim().to_lowercase().as_str(),
+        "true" | "yes" | "1"
+    );
+
+    // the users and groups that must never be managed, in addition to
+    // those that are not in the managed group - names (which may
+    // include wildcards) plus ranges of uids and gids
+    freeipa::set_protection_policy(
+        ProtectionPolicy::parse(
+            &config.option("protected-users", "admin"),
+            &config.option("protected-uids", ""),
+        )?,
+        ProtectionPolicy::parse(
+            &config.option("protected-groups", "admins,editors,ipausers,trust admins"),
+            &config.option("protected-gids", ""),
+        )?,
+    )?;
+
+    // the (optional) ranges from which the uids and gids of the users and
+    // groups created for each portal are allocated, e.g.
+    // "portal-a:200000-299999,portal-b:300000-399999"
+    let id_ranges = freeipa::parse_id_ranges(&config.option("id-ranges", ""))?;
+
     cache::set_system_groups(&system_groups).await?;
+    cache::set_max_ssh_keys(max_ssh_keys).await?;
+    cache::set_id_ranges(&id_ranges).await?;
+    cache::set_stage_users(stage_users).await?;
+
+    // the (optional) host groups that each project's group is given
+    // access to via an HBAC rule, plus the services that rule allows
+    let split_list = |value: String| -> Vec<String> {
+        value
+            .split(',')
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect()
+    };
+
+    cache::set_hbac(
+        &split_list(config.option("hbac-host-groups", "")),
+        &split_list(config.option("hbac-services", "")),
+    )
+    .await?;
     cache::set_instance_groups(&instance_groups).await?;
+    cache::set_secondary_groups(&secondary_groups).await?;
 
     // connect the single shared FreeIPA client - this will be used in the
     // async function (we can't bind variables to async functions, or else
     // we would just pass the client with the environment)
-    freeipa::initialise_servers(&freeipa_servers, &freeipa_user, &freeipa_password).await?;
+    freeipa::initialise_servers(
+        &freeipa_servers,
+        &freeipa_replicas,
+        &freeipa_user,
+        &freeipa_password,
+        freeipa_connections,
+    )
+    .await?;
+
+    if freeipa_health_interval > 0 {
+        freeipa::spawn_health_check(freeipa_health_interval);
+    }
+
+    // report straight away if any of the id ranges are nearly exhausted
+    if !id_ranges.is_empty() {
+        let expires = Utc::now() + chrono::Duration::seconds(60);
+
+        if let Err(e) = freeipa::check_id_ranges(&expires).await {
+            tracing::warn!("Could not check the usage of the id ranges: {}", e);
+        }
+    }
 
     // we need to bind the FreeIPA client into the freeipa_runner
     async_runnable! {
@@ -121,6 +274,10 @@ async fn main() -> Result<()> {
             let sender = envelope.sender();
             let me = envelope.recipient();
 
+            if dryrun::is_dry_run() && job.instruction().is_mutating() {
+                return dry_run(&job, me.name(), &sender).await;
+            }
+
             match job.instruction() {
                 GetProjects(portal) => {
                     let groups = freeipa::get_groups(&portal, &sender, job.expires()).await?;
@@ -139,19 +296,39 @@ async fn main() -> Result<()> {
                     job.completed(users.iter().map(|u| u.mapping()).collect::<Result<Vec<_>, _>>()?)
                 },
                 AddUser(user) => {
-                    let local_user = freeipa::identifier_to_userid(&user).await?;
-                    let local_group = freeipa::get_primary_group_name(&user).await?;
-                    let mapping = UserMapping::new(&user, &local_user, &local_group)?;
+                    let mapping = add_user(me.name(), &sender, &user, job.expires()).await?;
+                    job.completed(mapping)
+                },
+                AddUsers(users) => {
+                    // the users are added concurrently, using all of the
+                    // sessions to FreeIPA
+                    let (me, sender, expires) = (me.name(), &sender, job.expires());
 
-                    let homedir = get_home_dir(me.name(), &sender, &mapping, job.expires()).await?;
+                    let result = batch::run_each(users, freeipa::num_sessions().await, |user| async move {
+                        add_user(me, sender, &user, expires).await
+                    })
+                    .await;
 
-                    let user = freeipa::add_user(&user, &sender, &Some(homedir), job.expires()).await?;
+                    job.completed(result)
+                },
+                ActivateUser(user) => {
+                    let user = freeipa::activate_user(&user, &sender, job.expires()).await?;
                     job.completed(user.mapping()?)
                 },
                 RemoveUser(user) => {
                     let user = freeipa::remove_user(&user, &sender, job.expires()).await?;
                     job.completed(user.mapping()?)
                 },
+                RemoveUsers(users) => {
+                    let (sender, expires) = (&sender, job.expires());
+
+                    let result = batch::run_each(users, freeipa::num_sessions().await, |user| async move {
+                        freeipa::remove_user(&user, sender, expires).await?.mapping()
+                    })
+                    .await;
+
+                    job.completed(result)
+                },
                 BlockUser(user) => {
                     let user = freeipa::block_user(&user, job.expires()).await?;
                     job.completed(user.mapping()?)
@@ -164,10 +341,64 @@ async fn main() -> Result<()> {
                     let is_blocked = freeipa::is_blocked_user(&user, job.expires()).await?;
                     job.completed(is_blocked)
                 },
+                UpdateUser(user, details) => {
+                    let user = freeipa::update_user(&user, &details, job.expires()).await?;
+                    job.completed(user.mapping()?)
+                },
                 UpdateHomeDir(user, homedir) => {
                     let _ = freeipa::update_homedir(&user, &homedir, job.expires()).await?;
                     job.completed(homedir)
                 },
+                AddUserSSHKey(user, key) => {
+                    let keys = freeipa::add_user_ssh_key(&user, &key, job.expires()).await?;
+                    job.completed(keys)
+                },
+                RemoveUserSSHKey(user, key) => {
+                    let keys = freeipa::remove_user_ssh_key(&user, &key, job.expires()).await?;
+                    job.completed(keys)
+                },
+                GetUserSSHKeys(user) => {
+                    let keys = freeipa::get_user_ssh_keys(&user, job.expires()).await?;
+                    job.completed(keys)
+                },
+                AddUserOTPToken(user) => {
+                    let uri = freeipa::add_user_otp_token(&user, job.expires()).await?;
+                    job.completed(uri)
+                },
+                RemoveUserOTPToken(user, token) => {
+                    let tokens = freeipa::remove_user_otp_token(&user, &token, job.expires()).await?;
+                    job.completed(tokens)
+                },
+                GetUserOTPTokens(user) => {
+                    let tokens = freeipa::get_user_otp_tokens(&user, job.expires()).await?;
+                    job.completed(tokens)
+                },
+                AddUserToGroup(user, group) => {
+                    let groups = freeipa::add_user_to_group(&user, &group, job.expires()).await?;
+                    job.completed(groups)
+                },
+                RemoveUserFromGroup(user, group) => {
+                    let groups = freeipa::remove_user_from_group(&user, &group, job.expires()).await?;
+                    job.completed(groups)
+                },
+                ReconcileLocal(mapping, repair, users) => {
+                    // find the home directories that the filesystem expects
+                    let mut homedirs = HashMap::new();
+
+                    for user in &users {
+                        match get_home_dir(me.name(), &sender, user, job.expires()).await {
+                            Ok(homedir) => {
+                                homedirs.insert(user.user().clone(), homedir);
+                            }
+                            Err(e) => {
+                                tracing::warn!("Could not get the home directory of {}: {}", user, e);
+                            }
+                        }
+                    }
+
+                    let report = freeipa::reconcile(&mapping, &users, &homedirs, &sender, repair, job.expires()).await?;
+                    job.completed(report)
+                },
                 GetProjectMapping(project) => {
                     let mapping = freeipa::get_project_mapping(&project, job.expires()).await?;
                     job.completed(mapping)
@@ -180,6 +411,14 @@ async fn main() -> Result<()> {
                     let is_protected = freeipa::is_protected_user(&user, job.expires()).await?;
                     job.completed(is_protected)
                 },
+                GetUserProtection(user) => {
+                    let status = freeipa::get_user_protection(&user, job.expires()).await?;
+                    job.completed(status)
+                },
+                GetProjectProtection(project) => {
+                    let status = freeipa::get_project_protection(&project, job.expires()).await?;
+                    job.completed(status)
+                },
                 IsExistingUser(user) => {
                     let exists = freeipa::is_existing_user(&user, job.expires()).await?;
                     job.completed(exists)
@@ -197,12 +436,83 @@ async fn main() -> Result<()> {
         }
     }
 
+    // mutating instructions are handled by dry_run when rehearsed
+    dryrun::set_supported(true);
+
+    // add_users and remove_users are handled by the runner
+    batch::set_native(true);
+
     set_notify_runner(default_notify_runner).await?;
     run(config, freeipa_runner).await?;
 
     Ok(())
 }
 
+///
+/// Describe the change that a dry run of the mutating instruction in the
+/// passed job would make, returning the result that the job would have
+/// had without changing anything in FreeIPA
+///
+async fn dry_run(job: &Job, me: &str, sender: &Peer) -> Result<Job, Error> {
+    match job.instruction() {
+        AddProject(project) => {
+            let mapping = freeipa::get_new_project_mapping(&project)?;
+            dryrun::record(&format!("add project {}", mapping));
+            job.completed(mapping)
+        }
+        RemoveProject(project) => {
+            let mapping = freeipa::get_project_mapping(&project, job.expires()).await?;
+            dryrun::record(&format!("remove project {}", mapping));
+            job.completed(mapping)
+        }
+        AddUser(user) => {
+            let local_user = freeipa::identifier_to_userid(&user).await?;
+            let local_group = freeipa::get_primary_group_name(&user).await?;
+            let mapping = UserMapping::new(&user, &local_user, &local_group)?;
+
+            // this is a read-only request, so is safe to rehearse
+            let homedir = get_home_dir(me, sender, &mapping, job.expires()).await?;
+
+            dryrun::record(&format!("add user {} with home {}", mapping, homedir));
+            job.completed(mapping)
+        }
+        RemoveUser(user) => {
+            let mapping = freeipa::get_user_mapping(&user, job.expires()).await?;
+            dryrun::record(&format!("remove user {}", mapping));
+            job.completed(mapping)
+        }
+        _ => Err(Error::Incompatible(format!(
+            "FreeIPA cannot rehearse {} as a dry run",
+            job.instruction()
+        ))),
+    }
+}
+
+///
+/// Add the passed user, returning their mapping. New users are added as
+/// stage users if staging is enabled
+///
+async fn add_user(
+    me: &str,
+    sender: &Peer,
+    user: &UserIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<UserMapping, Error> {
+    let local_user = freeipa::identifier_to_userid(user).await?;
+    let local_group = freeipa::get_primary_group_name(user).await?;
+    let mapping = UserMapping::new(user, &local_user, &local_group)?;
+
+    let homedir = get_home_dir(me, sender, &mapping, expires).await?;
+
+    // new users are only staged if staging is enabled
+    match freeipa::stage_user(user, &Some(homedir.clone()), expires).await? {
+        true => Ok(mapping),
+        false => freeipa::add_user(user, sender, &Some(homedir), expires)
+            .await?
+            .mapping(),
+    }
+}
+
 async fn get_home_dir(
     me: &str,
     sender: &Peer,
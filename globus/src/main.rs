@@ -0,0 +1,183 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+
+use templemeads::agent;
+use templemeads::agent::filesystem::{process_args, run, Defaults};
+use templemeads::agent::Type as AgentType;
+use templemeads::async_runnable;
+use templemeads::grammar::Instruction::{
+    AddLocalProject, AddLocalUser, GetLocalProjectDirs, RemoveLocalProject, RemoveLocalUser,
+};
+use templemeads::grammar::{Instruction, ProjectMapping};
+use templemeads::job::{Envelope, Job};
+use templemeads::notification::default_notify_runner;
+use templemeads::set_notify_runner;
+use templemeads::Error;
+
+mod globus;
+
+/// Seconds to wait for the filesystem agent to be available
+const AGENT_WAIT_TIME: u64 = 5;
+
+///
+/// Main function for the globus filesystem agent
+///
+/// This agent sits between a cluster and its filesystem agent. It
+/// passes every filesystem instruction on to the filesystem agent, and
+/// shares each project's directory as a Globus guest collection once
+/// the filesystem agent has created it. Members of the project are
+/// given access to the collection when they are added, and lose it when
+/// they are removed, so that data transfer access tracks membership.
+///
+#[tokio::main]
+async fn main() -> Result<()> {
+    // start tracing
+    templemeads::config::initialise_tracing();
+
+    // start system monitoring
+    templemeads::spawn_system_monitor();
+
+    // create the OpenPortal paddington defaults
+    let defaults: Defaults = Defaults::parse(
+        Some("globus".to_owned()),
+        Some(
+            dirs::config_local_dir()
+                .unwrap_or(
+                    ".".parse()
+                        .expect("Could not parse fallback config directory."),
+                )
+                .join("openportal")
+                .join("globus-config.toml"),
+        ),
+        Some("ws://localhost:8051".to_owned()),
+        Some("127.0.0.1".to_owned()),
+        Some(8051),
+        None,
+        None,
+        Some(AgentType::Filesystem),
+    );
+
+    // now parse the command line arguments to get the service configuration
+    let config = match process_args(&defaults).await? {
+        Some(config) => config,
+        None => {
+            // Not running the service, so can safely exit
+            return Ok(());
+        }
+    };
+
+    let client_secret = match config.secret("globus-client-secret") {
+        Some(secret) => secret,
+        None => {
+            return Err(anyhow::anyhow!(
+                "No Globus client secret specified. Please set this in the globus-client-secret option.",
+            ));
+        }
+    };
+
+    globus::initialise(globus::Settings::new(
+        &config.option("globus-client-id", ""),
+        client_secret,
+        &config.option("gcs-endpoint-id", ""),
+        &config.option("gcs-url", ""),
+        &config.option("mapped-collection-id", ""),
+        &config.option("user-credential-id", ""),
+        &config.option("collection-root", ""),
+        &config.option("collection-prefix", "OpenPortal "),
+        &config.option("identity-domain", ""),
+        &config.option("permissions", "rw"),
+    )?)?;
+
+    async_runnable! {
+        ///
+        /// Runnable function that will be called when a job is received
+        /// by the agent
+        ///
+        pub async fn globus_runner(envelope: Envelope) -> Result<Job, templemeads::Error>
+        {
+            let me = envelope.recipient();
+            let job = envelope.job();
+
+            match job.instruction() {
+                AddLocalProject(mapping) => {
+                    let result = forward(me.name(), job.instruction()).await?;
+
+                    if !result.is_error() {
+                        let dirs = get_project_dirs(me.name(), &mapping).await?;
+
+                        match dirs.first() {
+                            Some(dir) => globus::add_collection(&mapping, dir, job.expires()).await?,
+                            None => tracing::warn!("{} has no directory to share", mapping),
+                        }
+                    }
+
+                    job.copy_result_from(&result)
+                },
+                RemoveLocalProject(mapping) => {
+                    // the collection is deleted first, so that no transfers
+                    // are made while the directory is being removed
+                    globus::remove_collection(&mapping, job.expires()).await?;
+                    job.copy_result_from(&forward(me.name(), job.instruction()).await?)
+                },
+                AddLocalUser(mapping) => {
+                    let result = forward(me.name(), job.instruction()).await?;
+
+                    if !result.is_error() {
+                        globus::add_access(&mapping, job.expires()).await?;
+                    }
+
+                    job.copy_result_from(&result)
+                },
+                RemoveLocalUser(mapping) => {
+                    globus::remove_access(&mapping, job.expires()).await?;
+                    job.copy_result_from(&forward(me.name(), job.instruction()).await?)
+                },
+                instruction => {
+                    // everything else is handled by the filesystem agent
+                    job.copy_result_from(&forward(me.name(), instruction).await?)
+                }
+            }
+        }
+    }
+
+    set_notify_runner(default_notify_runner).await?;
+    run(config, globus_runner).await?;
+
+    Ok(())
+}
+
+///
+/// Pass the instruction on to the filesystem agent, returning the
+/// finished job
+///
+async fn forward(me: &str, instruction: Instruction) -> Result<Job, Error> {
+    match agent::filesystem(AGENT_WAIT_TIME).await {
+        Some(filesystem) => {
+            let job = Job::parse(
+                &format!("{}.{} {}", me, filesystem.name(), instruction),
+                false,
+            )?
+            .put(&filesystem)
+            .await?;
+
+            job.wait().await
+        }
+        None => {
+            tracing::error!("No filesystem agent found");
+            Err(Error::MissingAgent(
+                "Cannot run the job because there is no filesystem agent".to_string(),
+            ))
+        }
+    }
+}
+
+///
+/// Return the directories of the project from the filesystem agent
+///
+async fn get_project_dirs(me: &str, mapping: &ProjectMapping) -> Result<Vec<String>, Error> {
+    let result = forward(me, GetLocalProjectDirs(mapping.clone())).await?;
+
+    Ok(result.result::<Vec<String>>()?.unwrap_or_default())
+}
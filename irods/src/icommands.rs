@@ -0,0 +1,570 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+use once_cell::sync::OnceCell;
+use templemeads::exec;
+use templemeads::Error;
+
+static COMMANDS: OnceCell<Commands> = OnceCell::new();
+
+/// The message (and error name) returned by iquest when a query matches nothing
+const NO_ROWS_FOUND: &str = "CAT_NO_ROWS_FOUND";
+
+///
+/// The iRODS icommands used by this agent. Each command is stored as a
+/// pre-split list of tokens so that prefixes like "ssh irods-host iadmin"
+/// work without any shell quoting issues.
+///
+pub struct Commands {
+    iadmin: Vec<String>,
+    imkdir: Vec<String>,
+    ichmod: Vec<String>,
+    iquest: Vec<String>,
+}
+
+impl Commands {
+    fn parse_cmd(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|p| p.to_owned()).collect()
+    }
+
+    pub fn new(iadmin: &str, imkdir: &str, ichmod: &str, iquest: &str) -> Self {
+        Self {
+            iadmin: Self::parse_cmd(iadmin),
+            imkdir: Self::parse_cmd(imkdir),
+            ichmod: Self::parse_cmd(ichmod),
+            iquest: Self::parse_cmd(iquest),
+        }
+    }
+}
+
+pub fn initialise_commands(cmds: Commands) -> Result<()> {
+    COMMANDS
+        .set(cmds)
+        .map_err(|_| anyhow::anyhow!("Commands already initialised"))
+}
+
+fn get_commands() -> Result<&'static Commands, Error> {
+    COMMANDS
+        .get()
+        .ok_or_else(|| Error::Call("Commands not initialised".to_owned()))
+}
+
+///
+/// Run a command built from a pre-tokenised prefix plus additional args.
+/// Returns (exit_code, stdout, stderr).
+///
+async fn run_command(parts: &[String], args: &[&str]) -> Result<(i32, String, String), Error> {
+    let output = exec::Command::from_parts(parts).args(args).output().await?;
+    let (exit_code, stdout, stderr) = (output.code, output.stdout, output.stderr);
+
+    tracing::debug!(
+        "Command exit code: {}, stdout: {}, stderr: {}",
+        exit_code,
+        stdout,
+        stderr
+    );
+
+    Ok((exit_code, stdout, stderr))
+}
+
+///
+/// Run the command, returning its output, or an error if it fails
+///
+async fn run_checked(parts: &[String], args: &[&str]) -> Result<String, Error> {
+    let (code, stdout, stderr) = run_command(parts, args).await?;
+
+    match code {
+        0 => Ok(stdout),
+        _ => Err(Error::Call(format!(
+            "{} {} failed (exit {}): {}",
+            parts.join(" "),
+            args.join(" "),
+            code,
+            match stderr.trim().is_empty() {
+                true => stdout.trim().to_owned(),
+                false => stderr.trim().to_owned(),
+            }
+        ))),
+    }
+}
+
+///
+/// Run an iadmin subcommand, e.g. `iadmin mkgroup <group>`
+///
+async fn iadmin(args: &[&str]) -> Result<(), Error> {
+    run_checked(&get_commands()?.iadmin, args).await?;
+    Ok(())
+}
+
+///
+/// Run the GenQuery, returning one value per matching row. The query
+/// must select a single column.
+///
+async fn iquest(query: &str) -> Result<Vec<String>, Error> {
+    let (code, stdout, stderr) = run_command(&get_commands()?.iquest, &["%s", query]).await?;
+
+    // iquest reports an empty result as an error
+    if stdout.contains(NO_ROWS_FOUND) || stderr.contains(NO_ROWS_FOUND) {
+        return Ok(Vec::new());
+    }
+
+    if code != 0 {
+        return Err(Error::Call(format!(
+            "iquest '{}' failed (exit {}): {}",
+            query,
+            code,
+            stderr.trim()
+        )));
+    }
+
+    Ok(stdout
+        .lines()
+        .map(|line| line.trim().to_owned())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
+
+///
+/// Check that the value can be safely placed inside a quoted GenQuery
+/// condition
+///
+fn assert_quotable(value: &str) -> Result<(), Error> {
+    match value.contains('\'') || value.contains('\n') {
+        true => Err(Error::InvalidState(format!(
+            "Cannot use '{}' in an iRODS query",
+            value
+        ))),
+        false => Ok(()),
+    }
+}
+
+///
+/// Return whether there is a user or group with the passed name
+///
+pub async fn user_exists(name: &str) -> Result<bool, Error> {
+    assert_quotable(name)?;
+
+    Ok(
+        !iquest(&format!("select USER_NAME where USER_NAME = '{}'", name))
+            .await?
+            .is_empty(),
+    )
+}
+
+///
+/// Create the user with the passed type (normally rodsuser). No password
+/// is set, as users authenticate with the zone's own mechanism.
+///
+pub async fn make_user(name: &str, user_type: &str) -> Result<(), Error> {
+    iadmin(&["mkuser", name, user_type]).await
+}
+
+///
+/// Create the group
+///
+pub async fn make_group(name: &str) -> Result<(), Error> {
+    iadmin(&["mkgroup", name]).await
+}
+
+///
+/// Return the members of the group
+///
+pub async fn group_members(group: &str) -> Result<Vec<String>, Error> {
+    assert_quotable(group)?;
+
+    iquest(&format!(
+        "select USER_NAME where USER_GROUP_NAME = '{}' and USER_TYPE <> 'rodsgroup'",
+        group
+    ))
+    .await
+}
+
+///
+/// Add the user to the group
+///
+pub async fn add_to_group(group: &str, user: &str) -> Result<(), Error> {
+    iadmin(&["atg", group, user]).await
+}
+
+///
+/// Remove the user from the group
+///
+pub async fn remove_from_group(group: &str, user: &str) -> Result<(), Error> {
+    iadmin(&["rfg", group, user]).await
+}
+
+///
+/// Create the collection, and any missing parent collections
+///
+pub async fn make_collection(collection: &str) -> Result<(), Error> {
+    run_checked(&get_commands()?.imkdir, &["-p", collection]).await?;
+    Ok(())
+}
+
+///
+/// Return whether the collection exists
+///
+pub async fn collection_exists(collection: &str) -> Result<bool, Error> {
+    assert_quotable(collection)?;
+
+    Ok(!iquest(&format!(
+        "select COLL_NAME where COLL_NAME = '{}'",
+        collection
+    ))
+    .await?
+    .is_empty())
+}
+
+///
+/// Recursively grant the user or group the access (null, read, write or
+/// own) to the collection, as an administrator
+///
+pub async fn set_access(collection: &str, who: &str, access: &str) -> Result<(), Error> {
+    run_checked(
+        &get_commands()?.ichmod,
+        &["-M", "-r", access, who, collection],
+    )
+    .await?;
+    Ok(())
+}
+
+///
+/// Turn on inheritance of the collection's permissions, so that new
+/// data and collections get the same access
+///
+pub async fn set_inherit(collection: &str) -> Result<(), Error> {
+    run_checked(&get_commands()?.ichmod, &["-M", "inherit", collection]).await?;
+    Ok(())
+}
+
+///
+/// Return the total size, in bytes, of the data in the collection and
+/// all of its sub-collections
+///
+pub async fn collection_size(collection: &str) -> Result<u64, Error> {
+    assert_quotable(collection)?;
+
+    let mut size = 0;
+
+    for query in [
+        format!("select sum(DATA_SIZE) where COLL_NAME = '{}'", collection),
+        format!(
+            "select sum(DATA_SIZE) where COLL_NAME like '{}/%'",
+            collection
+        ),
+    ] {
+        for value in iquest(&query).await? {
+            // an empty sum is returned as an empty string
+            size += value.parse::<u64>().unwrap_or(0);
+        }
+    }
+
+    Ok(size)
+}
+
+///
+/// Return the total quota of the user or group, in bytes, or None if
+/// they have no quota
+///
+pub async fn get_quota(name: &str) -> Result<Option<u64>, Error> {
+    assert_quotable(name)?;
+
+    let limits = iquest(&format!(
+        "select QUOTA_LIMIT where QUOTA_USER_NAME = '{}'",
+        name
+    ))
+    .await?;
+
+    Ok(limits
+        .iter()
+        .filter_map(|limit| limit.parse::<i64>().ok())
+        .filter(|limit| *limit > 0)
+        .map(|limit| limit as u64)
+        .min())
+}
+
+///
+/// Set the total quota of the group in bytes, or remove it if this is 0
+///
+pub async fn set_group_quota(group: &str, bytes: u64) -> Result<(), Error> {
+    iadmin(&["sgq", group, "total", &bytes.to_string()]).await
+}
+
+///
+/// Set the total quota of the user in bytes, or remove it if this is 0
+///
+pub async fn set_user_quota(user: &str, bytes: u64) -> Result<(), Error> {
+    iadmin(&["suq", user, "total", &bytes.to_string()]).await
+}
+
+///
+/// Recalculate the usage of every quota, so that quotas are enforced
+/// against up-to-date usage
+///
+pub async fn calculate_usage() -> Result<(), Error> {
+    iadmin(&["cu"]).await
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::Once;
+
+    static FAKE_IRODS: Once = Once::new();
+
+    ///
+    /// Return the directory of fake icommands, used by the tests of all
+    /// modules. Every call is logged to "irods.log" as "<tool> <args>".
+    /// Users, groups, group members, collections and quotas are kept as
+    /// files, collection sizes are read from files in "sizes" (with '/'
+    /// as '%'), and calls for anything "broken" always fail.
+    ///
+    pub(crate) fn fake_irods() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("op-irods-test-{}", std::process::id()));
+
+        FAKE_IRODS.call_once(|| {
+            let _ = std::fs::remove_dir_all(&dir);
+
+            for sub in ["users", "members", "colls", "sizes", "quota"] {
+                let _ = std::fs::create_dir_all(dir.join(sub));
+            }
+
+            let script = format!(
+                r#"dir={dir}
+tool=$1; shift
+echo "$tool $*" >> $dir/irods.log
+case "$*" in *broken*) echo "ERROR: remote error" >&2; exit 3 ;; esac
+enc() {{ echo "$1" | tr '/' '%'; }}
+case "$tool" in
+iadmin)
+  case "$1" in
+    mkuser|mkgroup) touch "$dir/users/$2" ;;
+    atg) echo "$3" >> "$dir/members/$2" ;;
+    rfg) grep -vx "$3" "$dir/members/$2" > "$dir/members/$2.new"; mv "$dir/members/$2.new" "$dir/members/$2" ;;
+    sgq|suq) if [ "$4" = 0 ]; then rm -f "$dir/quota/$2"; else echo "$4" > "$dir/quota/$2"; fi ;;
+  esac ;;
+imkdir) touch "$dir/colls/$(enc "$2")" ;;
+iquest)
+  value=$(echo "$2" | sed "s/^[^']*'\([^']*\)'.*/\1/")
+  case "$2" in
+    *"where USER_NAME ="*) file=$dir/users/$value; out=$value ;;
+    *"where USER_GROUP_NAME ="*) file=$dir/members/$value; out=$(cat "$file" 2>/dev/null) ;;
+    "select COLL_NAME"*) file=$dir/colls/$(enc "$value"); out=$value ;;
+    *"COLL_NAME like"*) file=$dir/sizes/$(enc "${{value%/%}}").sub; out=$(cat "$file" 2>/dev/null) ;;
+    *"sum(DATA_SIZE)"*) file=$dir/sizes/$(enc "$value"); out=$(cat "$file" 2>/dev/null) ;;
+    *QUOTA_LIMIT*) file=$dir/quota/$value; out=$(cat "$file" 2>/dev/null) ;;
+  esac
+  if [ -f "$file" ] && [ -n "$out" ]; then echo "$out"; else echo "CAT_NO_ROWS_FOUND: Nothing was found matching your query"; exit 1; fi ;;
+esac
+"#,
+                dir = dir.display()
+            );
+
+            let _ = std::fs::write(dir.join("irods.sh"), script);
+
+            let command = |tool: &str| format!("sh {} {}", dir.join("irods.sh").display(), tool);
+
+            let _ = initialise_commands(Commands::new(
+                &command("iadmin"),
+                &command("imkdir"),
+                &command("ichmod"),
+                &command("iquest"),
+            ));
+
+            if let Ok(settings) =
+                crate::irods::Settings::new("tempZone", "", "op-", "write", "rodsuser", "irods")
+            {
+                let _ = crate::irods::initialise(settings);
+            }
+        });
+
+        dir
+    }
+
+    ///
+    /// Set the total size of the data directly in the collection, and
+    /// of the data in its sub-collections
+    ///
+    pub(crate) fn set_size(collection: &str, direct: &str, within: &str) {
+        let name = collection.replace('/', "%");
+        let sizes = fake_irods().join("sizes");
+
+        for (file, size) in [(name.clone(), direct), (format!("{}.sub", name), within)] {
+            std::fs::write(sizes.join(file), size)
+                .unwrap_or_else(|e| unreachable!("Cannot write size: {}", e));
+        }
+    }
+
+    ///
+    /// Return the calls of the icommands that contain `text`
+    ///
+    pub(crate) fn logged(text: &str) -> Vec<String> {
+        std::fs::read_to_string(fake_irods().join("irods.log"))
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| line.contains(text))
+            .map(|line| line.to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_cmd() {
+        assert_eq!(
+            Commands::parse_cmd(" ssh  irods-host iadmin "),
+            vec!["ssh", "irods-host", "iadmin"]
+        );
+        assert!(Commands::parse_cmd("").is_empty());
+    }
+
+    #[test]
+    fn test_assert_quotable() {
+        assert!(assert_quotable("/tempZone/projects/op-proj").is_ok());
+
+        for invalid in ["o'brien", "two\nlines"] {
+            assert!(matches!(
+                assert_quotable(invalid),
+                Err(Error::InvalidState(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_users_and_groups() {
+        fake_irods();
+
+        assert!(matches!(user_exists("ic-alice").await, Ok(false)));
+
+        make_user("ic-alice", "rodsuser")
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot make user: {}", e));
+
+        make_group("ic-group")
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot make group: {}", e));
+
+        assert!(matches!(user_exists("ic-alice").await, Ok(true)));
+        assert!(matches!(user_exists("ic-group").await, Ok(true)));
+        assert!(group_members("ic-group")
+            .await
+            .is_ok_and(|members| members.is_empty()));
+
+        add_to_group("ic-group", "ic-alice")
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add to group: {}", e));
+
+        assert!(group_members("ic-group")
+            .await
+            .is_ok_and(|members| members == ["ic-alice"]));
+
+        remove_from_group("ic-group", "ic-alice")
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove from group: {}", e));
+
+        assert!(group_members("ic-group")
+            .await
+            .is_ok_and(|members| members.is_empty()));
+
+        assert_eq!(logged("iadmin mkuser ic-alice rodsuser").len(), 1);
+        assert_eq!(logged("iadmin atg ic-group ic-alice").len(), 1);
+        assert_eq!(logged("iadmin rfg ic-group ic-alice").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_collections_and_quotas() {
+        fake_irods();
+
+        let collection = "/tempZone/home/ic-data";
+
+        assert!(matches!(collection_exists(collection).await, Ok(false)));
+
+        make_collection(collection)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot make collection: {}", e));
+
+        assert!(matches!(collection_exists(collection).await, Ok(true)));
+
+        set_access(collection, "ic-group", "read")
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot set access: {}", e));
+
+        set_inherit(collection)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot set inherit: {}", e));
+
+        assert_eq!(
+            logged("/tempZone/home/ic-data"),
+            [
+                "iquest %s select COLL_NAME where COLL_NAME = '/tempZone/home/ic-data'",
+                "imkdir -p /tempZone/home/ic-data",
+                "iquest %s select COLL_NAME where COLL_NAME = '/tempZone/home/ic-data'",
+                "ichmod -M -r read ic-group /tempZone/home/ic-data",
+                "ichmod -M inherit /tempZone/home/ic-data",
+            ]
+        );
+
+        // the data in the collection and in its sub-collections is summed
+        assert!(matches!(collection_size(collection).await, Ok(0)));
+        set_size(collection, "100", "23\n\n");
+        assert!(matches!(collection_size(collection).await, Ok(123)));
+
+        assert!(matches!(get_quota("ic-quota").await, Ok(None)));
+
+        set_user_quota("ic-quota", 2048)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot set quota: {}", e));
+
+        assert!(matches!(get_quota("ic-quota").await, Ok(Some(2048))));
+
+        set_group_quota("ic-quota", 0)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot clear quota: {}", e));
+
+        assert!(matches!(get_quota("ic-quota").await, Ok(None)));
+
+        assert_eq!(
+            logged("ic-quota total"),
+            [
+                "iadmin suq ic-quota total 2048",
+                "iadmin sgq ic-quota total 0"
+            ]
+        );
+
+        calculate_usage()
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot calculate usage: {}", e));
+
+        assert!(!logged("iadmin cu").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        fake_irods();
+
+        // the error from the icommand is returned
+        assert!(matches!(
+            make_group("ic-broken").await,
+            Err(Error::Call(e)) if e.contains("ERROR: remote error")
+        ));
+
+        assert!(matches!(
+            user_exists("ic-broken").await,
+            Err(Error::Call(_))
+        ));
+
+        // values that would break out of a query are never run
+        assert!(matches!(
+            user_exists("ic-o'brien").await,
+            Err(Error::InvalidState(_))
+        ));
+
+        assert!(matches!(
+            collection_size("/tempZone/it's").await,
+            Err(Error::InvalidState(_))
+        ));
+
+        assert!(logged("brien").is_empty());
+        assert!(logged("it's").is_empty());
+    }
+}
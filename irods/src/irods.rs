@@ -0,0 +1,765 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+use chrono::Utc;
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use templemeads::grammar::{ProjectMapping, UserMapping};
+use templemeads::job::assert_not_expired;
+use templemeads::storage::{Quota, QuotaLimit, StorageSize, StorageUsage, Volume};
+use templemeads::Error;
+
+use crate::icommands;
+
+static SETTINGS: OnceCell<Settings> = OnceCell::new();
+
+/// The access that can be given to a project's group
+const GROUP_ACCESS: [&str; 3] = ["read", "write", "own"];
+
+///
+/// Configuration for how projects and members are provisioned. Each
+/// project gets an iRODS group and a collection under
+/// `projects_collection`, and each member gets an iRODS account and
+/// their own collection within the project's collection.
+///
+pub struct Settings {
+    projects_collection: String,
+    group_prefix: String,
+    group_access: String,
+    user_type: String,
+    volume: Volume,
+}
+
+impl Settings {
+    pub fn new(
+        zone: &str,
+        projects_collection: &str,
+        group_prefix: &str,
+        group_access: &str,
+        user_type: &str,
+        volume: &str,
+    ) -> Result<Self, Error> {
+        let zone = zone.trim();
+
+        if zone.is_empty() {
+            return Err(Error::Misconfigured(
+                "No iRODS zone specified. Please set this in the zone option.".to_owned(),
+            ));
+        }
+
+        let projects_collection = match projects_collection.trim().is_empty() {
+            true => format!("/{}/projects", zone),
+            false => projects_collection.trim().trim_end_matches('/').to_owned(),
+        };
+
+        if !projects_collection.starts_with(&format!("/{}/", zone)) {
+            return Err(Error::Misconfigured(format!(
+                "The projects collection '{}' must be within the zone /{}",
+                projects_collection, zone
+            )));
+        }
+
+        let group_access = group_access.trim().to_lowercase();
+
+        if !GROUP_ACCESS.contains(&group_access.as_str()) {
+            return Err(Error::Misconfigured(format!(
+                "Unknown group access '{}'. This must be one of {}",
+                group_access,
+                GROUP_ACCESS.join(", ")
+            )));
+        }
+
+        Ok(Self {
+            projects_collection,
+            group_prefix: group_prefix.trim().to_owned(),
+            group_access,
+            user_type: user_type.trim().to_owned(),
+            volume: Volume::new(volume.trim()),
+        })
+    }
+}
+
+pub fn initialise(settings: Settings) -> Result<()> {
+    tracing::info!(
+        "Creating project collections in {}",
+        settings.projects_collection
+    );
+
+    SETTINGS
+        .set(settings)
+        .map_err(|_| anyhow::anyhow!("iRODS settings already initialised"))
+}
+
+fn get_settings() -> Result<&'static Settings, Error> {
+    SETTINGS
+        .get()
+        .ok_or_else(|| Error::Call("iRODS settings not initialised".to_owned()))
+}
+
+///
+/// Check that the name is a valid iRODS user or group name, i.e. only
+/// letters, digits, '_', '-' and '.', starting with a letter or digit
+///
+fn assert_valid_name(name: &str) -> Result<(), Error> {
+    let valid = name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+
+    match valid {
+        true => Ok(()),
+        false => Err(Error::InvalidState(format!(
+            "Cannot use '{}' as an iRODS user or group name",
+            name
+        ))),
+    }
+}
+
+///
+/// Return the name of the iRODS group of the passed local project group
+///
+pub fn group_name(local_group: &str) -> Result<String, Error> {
+    let name = format!("{}{}", get_settings()?.group_prefix, local_group);
+    assert_valid_name(&name)?;
+    Ok(name)
+}
+
+///
+/// Return the name of the iRODS account of the passed local user
+///
+fn user_name(local_user: &str) -> Result<String, Error> {
+    assert_valid_name(local_user)?;
+    Ok(local_user.to_owned())
+}
+
+///
+/// Return the collection of the passed local project group
+///
+pub fn project_collection(local_group: &str) -> Result<String, Error> {
+    Ok(format!(
+        "{}/{}",
+        get_settings()?.projects_collection,
+        group_name(local_group)?
+    ))
+}
+
+///
+/// Return the member's own collection within their project's collection
+///
+pub fn user_collection(user: &UserMapping) -> Result<String, Error> {
+    Ok(format!(
+        "{}/{}",
+        project_collection(user.local_group())?,
+        user_name(user.local_user())?
+    ))
+}
+
+///
+/// Create the project's group and collection, and give the group access
+/// to the collection. This also restores the group's access if the
+/// project was previously removed.
+///
+pub async fn add_project(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let settings = get_settings()?;
+    let group = group_name(project.local_group())?;
+    let collection = project_collection(project.local_group())?;
+
+    if !icommands::user_exists(&group).await? {
+        icommands::make_group(&group).await?;
+        tracing::info!("Created iRODS group {} for {}", group, project);
+    }
+
+    if !icommands::collection_exists(&collection).await? {
+        icommands::make_collection(&collection).await?;
+        tracing::info!("Created iRODS collection {} for {}", collection, project);
+    }
+
+    icommands::set_inherit(&collection).await?;
+    icommands::set_access(&collection, &group, &settings.group_access).await?;
+
+    tracing::info!(
+        "Gave {} {} access to {}",
+        group,
+        settings.group_access,
+        collection
+    );
+
+    Ok(())
+}
+
+///
+/// Remove the group's access to the project's collection. The group,
+/// the collection and its data are kept, so that the project can be
+/// restored by adding it again.
+///
+pub async fn remove_project(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let group = group_name(project.local_group())?;
+    let collection = project_collection(project.local_group())?;
+
+    if !icommands::collection_exists(&collection).await? {
+        tracing::warn!(
+            "iRODS collection {} for {} does not exist",
+            collection,
+            project
+        );
+        return Ok(());
+    }
+
+    if icommands::user_exists(&group).await? {
+        icommands::set_access(&collection, &group, "null").await?;
+    }
+
+    tracing::info!("Removed the access of {} to {}", group, collection);
+
+    Ok(())
+}
+
+///
+/// Create the member's iRODS account (if needed), add them to their
+/// project's group, and create their own collection in the project
+///
+pub async fn add_user(user: &UserMapping, expires: &chrono::DateTime<Utc>) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let settings = get_settings()?;
+    let group = group_name(user.local_group())?;
+    let name = user_name(user.local_user())?;
+    let collection = user_collection(user)?;
+
+    if !icommands::user_exists(&group).await? {
+        return Err(Error::InvalidState(format!(
+            "Cannot add {} as the iRODS group {} does not exist",
+            user, group
+        )));
+    }
+
+    if !icommands::user_exists(&name).await? {
+        icommands::make_user(&name, &settings.user_type).await?;
+        tracing::info!("Created iRODS user {} for {}", name, user);
+    }
+
+    if !icommands::group_members(&group).await?.contains(&name) {
+        icommands::add_to_group(&group, &name).await?;
+        tracing::info!("Added {} to iRODS group {}", name, group);
+    }
+
+    if !icommands::collection_exists(&collection).await? {
+        icommands::make_collection(&collection).await?;
+        tracing::info!("Created iRODS collection {} for {}", collection, user);
+    }
+
+    icommands::set_access(&collection, &name, "own").await?;
+
+    Ok(())
+}
+
+///
+/// Remove the member from their project's group, and remove their access
+/// to their own collection. Their account is kept, as they may be a
+/// member of other projects, and their data is kept for the project.
+///
+pub async fn remove_user(user: &UserMapping, expires: &chrono::DateTime<Utc>) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let group = group_name(user.local_group())?;
+    let name = user_name(user.local_user())?;
+    let collection = user_collection(user)?;
+
+    if !icommands::user_exists(&name).await? {
+        tracing::warn!("iRODS user {} for {} does not exist", name, user);
+        return Ok(());
+    }
+
+    if icommands::user_exists(&group).await?
+        && icommands::group_members(&group).await?.contains(&name)
+    {
+        icommands::remove_from_group(&group, &name).await?;
+        tracing::info!("Removed {} from iRODS group {}", name, group);
+    }
+
+    if icommands::collection_exists(&collection).await? {
+        icommands::set_access(&collection, &name, "null").await?;
+        tracing::info!("Removed the access of {} to {}", name, collection);
+    }
+
+    Ok(())
+}
+
+///
+/// Check that the volume is the one served by this agent
+///
+fn assert_volume(volume: &Volume) -> Result<(), Error> {
+    let settings = get_settings()?;
+
+    match *volume == settings.volume {
+        true => Ok(()),
+        false => Err(Error::InvalidInstruction(format!(
+            "Unknown volume {}. This agent only manages the volume {}",
+            volume, settings.volume
+        ))),
+    }
+}
+
+///
+/// Return the quota of the user or group, with the usage of the collection
+///
+async fn get_quota(name: &str, collection: &str) -> Result<Quota, Error> {
+    let limit = match icommands::get_quota(name).await? {
+        Some(bytes) => QuotaLimit::from(StorageSize::from_bytes(bytes)),
+        None => QuotaLimit::Unlimited,
+    };
+
+    let usage = StorageUsage::from(icommands::collection_size(collection).await?);
+
+    Ok(Quota::with_usage(limit, usage))
+}
+
+///
+/// Return the number of bytes of the limit, where 0 removes the quota
+///
+fn limit_bytes(limit: &QuotaLimit) -> u64 {
+    match limit.size() {
+        Some(size) => size.as_bytes().max(1),
+        None => 0,
+    }
+}
+
+///
+/// Return the project's quota on the volume, including the usage of its
+/// collection
+///
+pub async fn get_project_quota(
+    project: &ProjectMapping,
+    volume: &Volume,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Quota, Error> {
+    assert_not_expired(expires)?;
+    assert_volume(volume)?;
+
+    get_quota(
+        &group_name(project.local_group())?,
+        &project_collection(project.local_group())?,
+    )
+    .await
+}
+
+///
+/// Return the project's quotas on every volume served by this agent
+///
+pub async fn get_project_quotas(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<HashMap<Volume, Quota>, Error> {
+    let volume = get_settings()?.volume.clone();
+    let quota = get_project_quota(project, &volume, expires).await?;
+
+    Ok(HashMap::from([(volume, quota)]))
+}
+
+///
+/// Set the quota of the project's group on the volume
+///
+pub async fn set_project_quota(
+    project: &ProjectMapping,
+    volume: &Volume,
+    limit: &QuotaLimit,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Quota, Error> {
+    assert_not_expired(expires)?;
+    assert_volume(volume)?;
+
+    let group = group_name(project.local_group())?;
+
+    icommands::set_group_quota(&group, limit_bytes(limit)).await?;
+
+    tracing::info!("Set the quota of iRODS group {} to {}", group, limit);
+
+    get_project_quota(project, volume, expires).await
+}
+
+///
+/// Remove the quota of the project's group on the volume
+///
+pub async fn clear_project_quota(
+    project: &ProjectMapping,
+    volume: &Volume,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    set_project_quota(project, volume, &QuotaLimit::Unlimited, expires).await?;
+    Ok(())
+}
+
+///
+/// Return the member's quota on the volume, including the usage of
+/// their own collection
+///
+pub async fn get_user_quota(
+    user: &UserMapping,
+    volume: &Volume,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Quota, Error> {
+    assert_not_expired(expires)?;
+    assert_volume(volume)?;
+
+    get_quota(&user_name(user.local_user())?, &user_collection(user)?).await
+}
+
+///
+/// Return the member's quotas on every volume served by this agent
+///
+pub async fn get_user_quotas(
+    user: &UserMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<HashMap<Volume, Quota>, Error> {
+    let volume = get_settings()?.volume.clone();
+    let quota = get_user_quota(user, &volume, expires).await?;
+
+    Ok(HashMap::from([(volume, quota)]))
+}
+
+///
+/// Set the member's quota on the volume. iRODS user quotas apply to all
+/// of the user's data in the zone, not only to this project.
+///
+pub async fn set_user_quota(
+    user: &UserMapping,
+    volume: &Volume,
+    limit: &QuotaLimit,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Quota, Error> {
+    assert_not_expired(expires)?;
+    assert_volume(volume)?;
+
+    let name = user_name(user.local_user())?;
+
+    icommands::set_user_quota(&name, limit_bytes(limit)).await?;
+
+    tracing::info!("Set the quota of iRODS user {} to {}", name, limit);
+
+    get_user_quota(user, volume, expires).await
+}
+
+///
+/// Remove the member's quota on the volume
+///
+pub async fn clear_user_quota(
+    user: &UserMapping,
+    volume: &Volume,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    set_user_quota(user, volume, &QuotaLimit::Unlimited, expires).await?;
+    Ok(())
+}
+
+///
+/// Spawn a background task that recalculates the usage of every quota
+/// every `interval` seconds. iRODS only enforces quotas against the
+/// usage from the last recalculation.
+///
+pub fn spawn_quota_calculator(interval: u64) {
+    tokio::spawn(async move {
+        loop {
+            match icommands::calculate_usage().await {
+                Ok(()) => tracing::debug!("Recalculated the usage of the iRODS quotas"),
+                Err(e) => tracing::error!("Could not recalculate the iRODS quotas: {}", e),
+            }
+
+            tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::icommands::tests::{fake_irods, logged, set_size};
+    use templemeads::grammar::{ProjectIdentifier, UserIdentifier};
+
+    fn project(name: &str) -> ProjectMapping {
+        ProjectMapping::new(
+            &ProjectIdentifier::parse(&format!("{}.portal", name))
+                .unwrap_or_else(|e| unreachable!("Cannot parse project: {}", e)),
+            name,
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e))
+    }
+
+    fn user(name: &str, project: &str) -> UserMapping {
+        UserMapping::new(
+            &UserIdentifier::parse(&format!("{}.{}.portal", name, project))
+                .unwrap_or_else(|e| unreachable!("Cannot parse user: {}", e)),
+            name,
+            project,
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e))
+    }
+
+    fn expires() -> chrono::DateTime<Utc> {
+        Utc::now() + chrono::Duration::seconds(30)
+    }
+
+    #[test]
+    fn test_settings() {
+        let settings = Settings::new(" tempZone ", "", "op-", " Write ", "rodsuser", "irods")
+            .unwrap_or_else(|e| unreachable!("Cannot create settings: {}", e));
+
+        assert_eq!(settings.projects_collection, "/tempZone/projects");
+        assert_eq!(settings.group_access, "write");
+
+        let settings = Settings::new(
+            "tempZone",
+            "/tempZone/data/",
+            "",
+            "own",
+            "rodsuser",
+            "irods",
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create settings: {}", e));
+
+        assert_eq!(settings.projects_collection, "/tempZone/data");
+
+        for (zone, collection, access) in [
+            ("", "", "write"),
+            ("tempZone", "/otherZone/projects", "write"),
+            ("tempZone", "/tempZoneX/projects", "write"),
+            ("tempZone", "", "admin"),
+        ] {
+            assert!(matches!(
+                Settings::new(zone, collection, "op-", access, "rodsuser", "irods"),
+                Err(Error::Misconfigured(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_names() {
+        fake_irods();
+
+        assert!(group_name("proj").is_ok_and(|g| g == "op-proj"));
+        assert!(project_collection("proj").is_ok_and(|c| c == "/tempZone/projects/op-proj"));
+        assert!(user_collection(&user("alice", "proj"))
+            .is_ok_and(|c| c == "/tempZone/projects/op-proj/alice"));
+
+        assert!(user_name("alice.smith_2-b").is_ok());
+
+        for invalid in ["", ".alice", "-alice", "al ice", "al/ice", "al'ice"] {
+            assert!(matches!(user_name(invalid), Err(Error::InvalidState(_))));
+        }
+
+        assert!(matches!(group_name("a b"), Err(Error::InvalidState(_))));
+    }
+
+    #[tokio::test]
+    async fn test_projects() {
+        fake_irods();
+
+        let proj = project("irproj");
+
+        add_project(&proj, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add project: {}", e));
+
+        // adding again does not recreate the group or collection
+        add_project(&proj, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add project: {}", e));
+
+        assert_eq!(logged("iadmin mkgroup op-irproj").len(), 1);
+        assert_eq!(logged("imkdir -p /tempZone/projects/op-irproj").len(), 1);
+        assert_eq!(
+            logged("ichmod -M inherit /tempZone/projects/op-irproj").len(),
+            2
+        );
+        assert_eq!(
+            logged("ichmod -M -r write op-irproj /tempZone/projects/op-irproj").len(),
+            2
+        );
+
+        // removing only removes the group's access
+        remove_project(&proj, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove project: {}", e));
+
+        assert_eq!(
+            logged("ichmod -M -r null op-irproj /tempZone/projects/op-irproj").len(),
+            1
+        );
+        assert!(logged("rmgroup").is_empty());
+
+        // removing a project that was never added does nothing
+        remove_project(&project("irmissing"), &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove project: {}", e));
+
+        assert!(logged("ichmod -M -r null op-irmissing").is_empty());
+
+        assert!(matches!(
+            add_project(&project("irbroken"), &expires()).await,
+            Err(Error::Call(_))
+        ));
+
+        let expired = Utc::now() - chrono::Duration::seconds(1);
+
+        assert!(matches!(
+            add_project(&project("irexpired"), &expired).await,
+            Err(Error::Expired(_))
+        ));
+
+        assert!(logged("irexpired").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_users() {
+        fake_irods();
+
+        let alice = user("iralice", "iruproj");
+
+        // the project's group must exist first
+        assert!(matches!(
+            add_user(&alice, &expires()).await,
+            Err(Error::InvalidState(_))
+        ));
+
+        add_project(&project("iruproj"), &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add project: {}", e));
+
+        add_user(&alice, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add user: {}", e));
+
+        add_user(&alice, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add user: {}", e));
+
+        assert_eq!(logged("iadmin mkuser iralice rodsuser").len(), 1);
+        assert_eq!(logged("iadmin atg op-iruproj iralice").len(), 1);
+        assert_eq!(
+            logged("imkdir -p /tempZone/projects/op-iruproj/iralice").len(),
+            1
+        );
+        assert_eq!(
+            logged("ichmod -M -r own iralice /tempZone/projects/op-iruproj/iralice").len(),
+            2
+        );
+
+        remove_user(&alice, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove user: {}", e));
+
+        assert_eq!(logged("iadmin rfg op-iruproj iralice").len(), 1);
+        assert_eq!(
+            logged("ichmod -M -r null iralice /tempZone/projects/op-iruproj/iralice").len(),
+            1
+        );
+
+        // the account is kept, and removing again does nothing more
+        remove_user(&alice, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove user: {}", e));
+
+        assert!(logged("rmuser").is_empty());
+        assert_eq!(logged("iadmin rfg op-iruproj iralice").len(), 1);
+
+        // removing a user that does not exist does nothing
+        remove_user(&user("irnobody", "iruproj"), &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove user: {}", e));
+
+        assert!(logged("iadmin rfg op-iruproj irnobody").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_quotas() {
+        fake_irods();
+
+        let proj = project("irqproj");
+        let alice = user("irqalice", "irqproj");
+        let volume = Volume::new("irods");
+
+        assert!(matches!(
+            get_project_quota(&proj, &Volume::new("scratch"), &expires()).await,
+            Err(Error::InvalidInstruction(_))
+        ));
+
+        let quota = get_project_quota(&proj, &volume, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot get quota: {}", e));
+
+        assert!(quota.is_unlimited());
+
+        set_size("/tempZone/projects/op-irqproj", "1000", "24");
+
+        let limit = QuotaLimit::from(StorageSize::from_bytes(4096));
+
+        let quota = set_project_quota(&proj, &volume, &limit, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot set quota: {}", e));
+
+        assert_eq!(quota.limit(), &limit);
+        assert_eq!(quota.usage(), Some(StorageUsage::from(1024)));
+
+        let quotas = get_project_quotas(&proj, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot get quotas: {}", e));
+
+        assert_eq!(quotas.len(), 1);
+        assert!(quotas.get(&volume).is_some_and(|q| q.limit() == &limit));
+
+        clear_project_quota(&proj, &volume, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot clear quota: {}", e));
+
+        assert_eq!(
+            logged("iadmin sgq op-irqproj"),
+            [
+                "iadmin sgq op-irqproj total 4096",
+                "iadmin sgq op-irqproj total 0"
+            ]
+        );
+
+        // user quotas are set on the user's account
+        let quota = set_user_quota(&alice, &volume, &limit, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot set quota: {}", e));
+
+        assert_eq!(quota.limit(), &limit);
+        assert!(get_user_quotas(&alice, &expires())
+            .await
+            .is_ok_and(|quotas| quotas.get(&volume).is_some_and(|q| q.limit() == &limit)));
+
+        clear_user_quota(&alice, &volume, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot clear quota: {}", e));
+
+        assert!(get_user_quota(&alice, &volume, &expires())
+            .await
+            .is_ok_and(|quota| quota.is_unlimited()));
+
+        assert_eq!(
+            logged("iadmin suq irqalice"),
+            [
+                "iadmin suq irqalice total 4096",
+                "iadmin suq irqalice total 0"
+            ]
+        );
+
+        // a tiny limit is still a limit, rather than no quota
+        assert_eq!(
+            limit_bytes(&QuotaLimit::from(StorageSize::from_bytes(0))),
+            1
+        );
+        assert_eq!(limit_bytes(&QuotaLimit::Unlimited), 0);
+    }
+}
@@ -0,0 +1,189 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+
+use templemeads::agent::filesystem::{process_args, run, Defaults};
+use templemeads::agent::Type as AgentType;
+use templemeads::async_runnable;
+use templemeads::grammar::Date;
+use templemeads::grammar::Instruction::{
+    AddLocalProject, AddLocalUser, ClearLocalProjectQuota, ClearLocalUserQuota,
+    GetLocalProjectDirs, GetLocalProjectQuota, GetLocalProjectQuotas, GetLocalStorageReport,
+    GetLocalUserDirs, GetLocalUserQuota, GetLocalUserQuotas, RemoveLocalProject, RemoveLocalUser,
+    SetLocalProjectQuota, SetLocalUserQuota,
+};
+use templemeads::job::{Envelope, Job};
+use templemeads::notification::default_notify_runner;
+use templemeads::set_notify_runner;
+use templemeads::storagereport::ProjectStorageReport;
+use templemeads::Error;
+
+mod icommands;
+mod irods;
+
+///
+/// Main function for the iRODS filesystem agent
+///
+/// This agent provisions research data management space for projects in
+/// an iRODS zone. Each project gets an iRODS group and a collection that
+/// the group can access, and each member gets an iRODS account, is added
+/// to the group, and gets their own collection in the project. Project
+/// and member quotas are set as iRODS group and user quotas.
+///
+#[tokio::main]
+async fn main() -> Result<()> {
+    // start tracing
+    templemeads::config::initialise_tracing();
+
+    // start system monitoring
+    templemeads::spawn_system_monitor();
+
+    // create the OpenPortal paddington defaults
+    let defaults: Defaults = Defaults::parse(
+        Some("irods".to_owned()),
+        Some(
+            dirs::config_local_dir()
+                .unwrap_or(
+                    ".".parse()
+                        .expect("Could not parse fallback config directory."),
+                )
+                .join("openportal")
+                .join("irods-config.toml"),
+        ),
+        Some("ws://localhost:8047".to_owned()),
+        Some("127.0.0.1".to_owned()),
+        Some(8047),
+        None,
+        None,
+        Some(AgentType::Filesystem),
+    );
+
+    // now parse the command line arguments to get the service configuration
+    let config = match process_args(&defaults).await? {
+        Some(config) => config,
+        None => {
+            // Not running the service, so can safely exit
+            return Ok(());
+        }
+    };
+
+    icommands::initialise_commands(icommands::Commands::new(
+        &config.option("iadmin-command", "iadmin"),
+        &config.option("imkdir-command", "imkdir"),
+        &config.option("ichmod-command", "ichmod"),
+        &config.option("iquest-command", "iquest"),
+    ))?;
+
+    irods::initialise(irods::Settings::new(
+        &config.option("zone", ""),
+        &config.option("projects-collection", ""),
+        &config.option("group-prefix", ""),
+        &config.option("group-access", "write"),
+        &config.option("user-type", "rodsuser"),
+        &config.option("volume", "irods"),
+    )?)?;
+
+    // get the interval (in seconds) between recalculating the usage of
+    // the quotas, or 0 if this is left to the zone's administrators
+    let quota_interval: u64 = config
+        .option("quota-interval", "600")
+        .parse()
+        .unwrap_or(600);
+
+    if quota_interval > 0 {
+        irods::spawn_quota_calculator(quota_interval.max(60));
+    }
+
+    async_runnable! {
+        ///
+        /// Runnable function that will be called when a job is received
+        /// by the agent
+        ///
+        pub async fn irods_runner(envelope: Envelope) -> Result<Job, templemeads::Error>
+        {
+            let job = envelope.job();
+
+            match job.instruction() {
+                AddLocalProject(mapping) => {
+                    irods::add_project(&mapping, job.expires()).await?;
+                    job.completed_none()
+                },
+                RemoveLocalProject(mapping) => {
+                    // the group and collection are kept, so no data is lost
+                    irods::remove_project(&mapping, job.expires()).await?;
+                    job.completed_none()
+                },
+                AddLocalUser(mapping) => {
+                    irods::add_user(&mapping, job.expires()).await?;
+                    job.completed_none()
+                },
+                RemoveLocalUser(mapping) => {
+                    irods::remove_user(&mapping, job.expires()).await?;
+                    job.completed_none()
+                },
+                GetLocalProjectDirs(mapping) => {
+                    job.completed(vec![irods::project_collection(mapping.local_group())?])
+                },
+                GetLocalUserDirs(mapping) => {
+                    job.completed(vec![irods::user_collection(&mapping)?])
+                },
+                SetLocalProjectQuota(mapping, volume, limit) => {
+                    let quota = irods::set_project_quota(&mapping, &volume, &limit, job.expires()).await?;
+                    job.completed(quota)
+                },
+                GetLocalProjectQuota(mapping, volume) => {
+                    let quota = irods::get_project_quota(&mapping, &volume, job.expires()).await?;
+                    job.completed(quota)
+                },
+                GetLocalProjectQuotas(mapping) => {
+                    let quotas = irods::get_project_quotas(&mapping, job.expires()).await?;
+                    job.completed(quotas)
+                },
+                ClearLocalProjectQuota(mapping, volume) => {
+                    irods::clear_project_quota(&mapping, &volume, job.expires()).await?;
+                    job.completed_none()
+                },
+                SetLocalUserQuota(mapping, volume, limit) => {
+                    let quota = irods::set_user_quota(&mapping, &volume, &limit, job.expires()).await?;
+                    job.completed(quota)
+                },
+                GetLocalUserQuota(mapping, volume) => {
+                    let quota = irods::get_user_quota(&mapping, &volume, job.expires()).await?;
+                    job.completed(quota)
+                },
+                GetLocalUserQuotas(mapping) => {
+                    let quotas = irods::get_user_quotas(&mapping, job.expires()).await?;
+                    job.completed(quotas)
+                },
+                ClearLocalUserQuota(mapping, volume) => {
+                    irods::clear_user_quota(&mapping, &volume, job.expires()).await?;
+                    job.completed_none()
+                },
+                GetLocalStorageReport(mapping, dates) => {
+                    let today = Date::today().day();
+                    if dates != today {
+                        return job.errored(&format!(
+                            "Storage reports only support today's date; requested range: {}",
+                            dates
+                        ));
+                    }
+
+                    let mut report = ProjectStorageReport::new(mapping.project());
+                    report.set_project_quotas(irods::get_project_quotas(&mapping, job.expires()).await?);
+                    job.completed(report)
+                },
+                _ => {
+                    Err(Error::InvalidInstruction(
+                        format!("Invalid instruction: {}. iRODS agents do not support this instruction", job.instruction()),
+                    ))
+                }
+            }
+        }
+    }
+
+    set_notify_runner(default_notify_runner).await?;
+    run(config, irods_runner).await?;
+
+    Ok(())
+}
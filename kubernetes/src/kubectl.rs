@@ -0,0 +1,687 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+use chrono::Utc;
+use once_cell::sync::OnceCell;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use templemeads::exec;
+use templemeads::grammar::{ProjectMapping, UserMapping};
+use templemeads::job::assert_not_expired;
+use templemeads::Error;
+
+static SETTINGS: OnceCell<Settings> = OnceCell::new();
+
+/// Label added to every object created by this agent
+pub const MANAGED_LABEL: &str = "openportal.io/managed";
+
+/// Annotation holding the project identifier of a managed namespace
+const PROJECT_ANNOTATION: &str = "openportal.io/project";
+
+/// Annotation holding the local user of a managed role binding
+const USER_ANNOTATION: &str = "openportal.io/user";
+
+const QUOTA_NAME: &str = "openportal-quota";
+const LIMITS_NAME: &str = "openportal-limits";
+
+///
+/// Configuration for how projects and users are provisioned. Each
+/// project is given its own namespace, and each user of the project
+/// is bound to `user_role` within that namespace.
+///
+pub struct Settings {
+    kubectl: Vec<String>,
+    namespace_prefix: String,
+    user_prefix: String,
+    user_role: String,
+    quota: Map<String, Value>,
+    default_limits: Map<String, Value>,
+    default_requests: Map<String, Value>,
+    delete_namespaces: bool,
+}
+
+impl Settings {
+    // ignore too many arguments warning for this constructor,
+    // since it's more ergonomic to construct the Settings struct directly
+    // from the config file with all fields specified.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        kubectl: &str,
+        namespace_prefix: &str,
+        user_prefix: &str,
+        user_role: &str,
+        quota: &str,
+        default_limits: &str,
+        default_requests: &str,
+        delete_namespaces: bool,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            kubectl: kubectl.split_whitespace().map(|p| p.to_owned()).collect(),
+            namespace_prefix: namespace_prefix.to_owned(),
+            user_prefix: user_prefix.to_owned(),
+            user_role: user_role.to_owned(),
+            quota: parse_resources(quota)?,
+            default_limits: parse_resources(default_limits)?,
+            default_requests: parse_resources(default_requests)?,
+            delete_namespaces,
+        })
+    }
+}
+
+pub fn initialise(settings: Settings) -> Result<()> {
+    tracing::info!(
+        "Namespaces are named '{}<project>', and users are bound to the '{}' role",
+        settings.namespace_prefix,
+        settings.user_role
+    );
+
+    SETTINGS
+        .set(settings)
+        .map_err(|_| anyhow::anyhow!("Kubernetes settings already initialised"))
+}
+
+fn get_settings() -> Result<&'static Settings, Error> {
+    SETTINGS
+        .get()
+        .ok_or_else(|| Error::Call("Kubernetes settings not initialised".to_owned()))
+}
+
+///
+/// Parse a comma-separated list of resource quantities, e.g.
+/// "requests.cpu=64,requests.memory=256Gi,requests.nvidia.com/gpu=4"
+///
+fn parse_resources(resources: &str) -> Result<Map<String, Value>, Error> {
+    let mut map = Map::new();
+
+    for resource in resources
+        .split(',')
+        .map(|r| r.trim())
+        .filter(|r| !r.is_empty())
+    {
+        match resource.split_once('=') {
+            Some((name, quantity)) if !name.trim().is_empty() && !quantity.trim().is_empty() => {
+                map.insert(
+                    name.trim().to_owned(),
+                    Value::String(quantity.trim().to_owned()),
+                );
+            }
+            _ => {
+                return Err(Error::Misconfigured(format!(
+                    "Invalid resource '{}'. This should be 'name=quantity'",
+                    resource
+                )));
+            }
+        }
+    }
+
+    Ok(map)
+}
+
+///
+/// Convert the passed name into a valid Kubernetes object name, i.e.
+/// at most 63 lowercase alphanumeric characters or '-', starting and
+/// ending with an alphanumeric character
+///
+fn sanitise(name: &str) -> Result<String, Error> {
+    let name: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '-',
+        })
+        .collect();
+
+    let name = name.trim_matches('-');
+    let name = name[..name.len().min(63)].trim_end_matches('-').to_owned();
+
+    match name.is_empty() {
+        true => Err(Error::Parse(
+            "Cannot create a Kubernetes name from an empty name".to_owned(),
+        )),
+        false => Ok(name),
+    }
+}
+
+///
+/// Return the namespace used for the passed local project group
+///
+pub fn namespace_name(local_group: &str) -> Result<String, Error> {
+    let settings = get_settings()?;
+    sanitise(&format!("{}{}", settings.namespace_prefix, local_group))
+}
+
+fn role_binding_name(local_user: &str) -> Result<String, Error> {
+    sanitise(&format!("openportal-{}", local_user))
+}
+
+///
+/// Run kubectl with the passed arguments, writing `stdin` (if any) to
+/// its standard input. Returns (exit_code, stdout, stderr).
+///
+async fn run_kubectl(args: &[&str], stdin: Option<&str>) -> Result<(i32, String, String), Error> {
+    let settings = get_settings()?;
+
+    let mut command = exec::Command::from_parts(&settings.kubectl).args(args);
+
+    if let Some(input) = stdin {
+        command = command.stdin(input);
+    }
+
+    let output = command.output().await?;
+    let (exit_code, stdout, stderr) = (output.code, output.stdout, output.stderr);
+
+    tracing::debug!(
+        "Command exit code: {}, stdout: {}, stderr: {}",
+        exit_code,
+        stdout,
+        stderr
+    );
+
+    Ok((exit_code, stdout, stderr))
+}
+
+///
+/// Run kubectl, returning its stdout, or an error if it failed
+///
+async fn kubectl(args: &[&str], stdin: Option<&str>) -> Result<String, Error> {
+    let (code, stdout, stderr) = run_kubectl(args, stdin).await?;
+
+    match code {
+        0 => Ok(stdout),
+        _ => Err(Error::Call(format!(
+            "kubectl {} failed (exit {}): {}",
+            args.join(" "),
+            code,
+            stderr.trim()
+        ))),
+    }
+}
+
+///
+/// Create or update all of the passed objects using `kubectl apply`
+///
+async fn apply(objects: Vec<Value>) -> Result<(), Error> {
+    let list = json!({
+        "apiVersion": "v1",
+        "kind": "List",
+        "items": objects,
+    });
+
+    kubectl(&["apply", "-f", "-"], Some(&list.to_string())).await?;
+
+    Ok(())
+}
+
+///
+/// Run `kubectl get` with the passed arguments and parse the JSON output
+///
+pub async fn get_json(args: &[&str]) -> Result<Value, Error> {
+    let mut args = args.to_vec();
+    args.extend(["-o", "json"]);
+
+    let output = kubectl(&args, None).await?;
+
+    serde_json::from_str(&output)
+        .map_err(|e| Error::Call(format!("Could not parse the kubectl output: {}", e)))
+}
+
+///
+/// Return the names of all namespaces managed by this agent
+///
+pub async fn get_managed_namespaces() -> Result<Vec<String>, Error> {
+    let selector = format!("{}=true", MANAGED_LABEL);
+    let namespaces = get_json(&["get", "namespaces", "-l", &selector]).await?;
+
+    Ok(namespaces["items"]
+        .as_array()
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|item| item["metadata"]["name"].as_str())
+                .map(|name| name.to_owned())
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+///
+/// Create (or update) the namespace for the project, together with
+/// its ResourceQuota and LimitRange
+///
+pub async fn add_project(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let settings = get_settings()?;
+    let namespace = namespace_name(project.local_group())?;
+
+    tracing::info!("Adding namespace {} for {}", namespace, project);
+
+    let mut objects = vec![json!({
+        "apiVersion": "v1",
+        "kind": "Namespace",
+        "metadata": {
+            "name": namespace,
+            "labels": { MANAGED_LABEL: "true" },
+            "annotations": { PROJECT_ANNOTATION: project.project().to_string() },
+        },
+    })];
+
+    if !settings.quota.is_empty() {
+        objects.push(json!({
+            "apiVersion": "v1",
+            "kind": "ResourceQuota",
+            "metadata": {
+                "name": QUOTA_NAME,
+                "namespace": namespace,
+                "labels": { MANAGED_LABEL: "true" },
+            },
+            "spec": { "hard": settings.quota },
+        }));
+    }
+
+    if !settings.default_limits.is_empty() || !settings.default_requests.is_empty() {
+        let mut limit = Map::new();
+        limit.insert("type".to_owned(), Value::String("Container".to_owned()));
+
+        if !settings.default_limits.is_empty() {
+            limit.insert(
+                "default".to_owned(),
+                Value::Object(settings.default_limits.clone()),
+            );
+        }
+
+        if !settings.default_requests.is_empty() {
+            limit.insert(
+                "defaultRequest".to_owned(),
+                Value::Object(settings.default_requests.clone()),
+            );
+        }
+
+        objects.push(json!({
+            "apiVersion": "v1",
+            "kind": "LimitRange",
+            "metadata": {
+                "name": LIMITS_NAME,
+                "namespace": namespace,
+                "labels": { MANAGED_LABEL: "true" },
+            },
+            "spec": { "limits": [limit] },
+        }));
+    }
+
+    apply(objects).await
+}
+
+///
+/// Remove access to the project's namespace. By default the namespace
+/// (and so any data and workloads in it) is kept, and only the role
+/// bindings of its users are removed. The namespace is deleted if
+/// `delete-namespaces` is set.
+///
+pub async fn remove_project(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let settings = get_settings()?;
+    let namespace = namespace_name(project.local_group())?;
+    let selector = format!("{}=true", MANAGED_LABEL);
+
+    if settings.delete_namespaces {
+        tracing::info!("Deleting namespace {} for {}", namespace, project);
+
+        kubectl(
+            &[
+                "delete",
+                "namespace",
+                &namespace,
+                "--ignore-not-found",
+                "--wait=false",
+            ],
+            None,
+        )
+        .await?;
+    } else {
+        tracing::info!("Removing all role bindings from namespace {}", namespace);
+
+        kubectl(
+            &[
+                "delete",
+                "rolebindings",
+                "-n",
+                &namespace,
+                "-l",
+                &selector,
+                "--ignore-not-found",
+            ],
+            None,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+///
+/// Give the user access to their project's namespace by binding them
+/// to the configured role
+///
+pub async fn add_user(user: &UserMapping, expires: &chrono::DateTime<Utc>) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let settings = get_settings()?;
+    let namespace = namespace_name(user.local_group())?;
+    let name = role_binding_name(user.local_user())?;
+
+    tracing::info!(
+        "Binding {} to role {} in {}",
+        user,
+        settings.user_role,
+        namespace
+    );
+
+    apply(vec![json!({
+        "apiVersion": "rbac.authorization.k8s.io/v1",
+        "kind": "RoleBinding",
+        "metadata": {
+            "name": name,
+            "namespace": namespace,
+            "labels": { MANAGED_LABEL: "true" },
+            "annotations": { USER_ANNOTATION: user.local_user() },
+        },
+        "roleRef": {
+            "apiGroup": "rbac.authorization.k8s.io",
+            "kind": "ClusterRole",
+            "name": settings.user_role,
+        },
+        "subjects": [{
+            "apiGroup": "rbac.authorization.k8s.io",
+            "kind": "User",
+            "name": format!("{}{}", settings.user_prefix, user.local_user()),
+        }],
+    })])
+    .await
+}
+
+///
+/// Remove the user's access to their project's namespace
+///
+pub async fn remove_user(user: &UserMapping, expires: &chrono::DateTime<Utc>) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let namespace = namespace_name(user.local_group())?;
+    let name = role_binding_name(user.local_user())?;
+
+    tracing::info!("Removing role binding {} from {}", name, namespace);
+
+    kubectl(
+        &[
+            "delete",
+            "rolebinding",
+            &name,
+            "-n",
+            &namespace,
+            "--ignore-not-found",
+        ],
+        None,
+    )
+    .await?;
+
+    Ok(())
+}
+
+///
+/// Parse a Kubernetes CPU quantity (e.g. "500m", "2" or "1.5") into
+/// a number of cores
+///
+pub fn parse_cpu(quantity: &str) -> Option<f64> {
+    let quantity = quantity.trim();
+
+    match quantity.strip_suffix('m') {
+        Some(millicores) => millicores.parse::<f64>().ok().map(|m| m / 1000.0),
+        None => quantity.parse::<f64>().ok(),
+    }
+}
+
+///
+/// Return the number of cores and GPUs requested by each running pod
+/// in each of the passed namespaces. The request of each container is
+/// used, falling back to its limit if no request is set.
+///
+pub async fn get_namespace_requests(
+    namespaces: &[String],
+    gpu_resource: &str,
+) -> Result<HashMap<String, (f64, f64)>, Error> {
+    let pods = get_json(&[
+        "get",
+        "pods",
+        "--all-namespaces",
+        "--field-selector=status.phase=Running",
+    ])
+    .await?;
+
+    let mut requests: HashMap<String, (f64, f64)> = HashMap::new();
+
+    for pod in pods["items"].as_array().cloned().unwrap_or_default() {
+        let namespace = match pod["metadata"]["namespace"].as_str() {
+            Some(namespace) if namespaces.iter().any(|n| n == namespace) => namespace,
+            _ => continue,
+        };
+
+        let entry = requests.entry(namespace.to_owned()).or_default();
+
+        for container in pod["spec"]["containers"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+        {
+            let resources = &container["resources"];
+
+            let quantity = |resource: &str| {
+                resources["requests"][resource]
+                    .as_str()
+                    .or_else(|| resources["limits"][resource].as_str())
+                    .map(|q| q.to_owned())
+            };
+
+            entry.0 += quantity("cpu").and_then(|q| parse_cpu(&q)).unwrap_or(0.0);
+            entry.1 += quantity(gpu_resource)
+                .and_then(|q| q.trim().parse::<f64>().ok())
+                .unwrap_or(0.0);
+        }
+    }
+
+    Ok(requests)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use templemeads::grammar::{ProjectIdentifier, UserIdentifier};
+
+    ///
+    /// Write a fake kubectl that logs its arguments (and anything written
+    /// to its stdin) to `kubectl.log`, returns a single managed namespace
+    /// for `get`, and fails for anything in the 'portal-broken' namespace
+    ///
+    fn fake_kubectl() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("op-kubectl-test-{}", std::process::id()));
+        let _ = std::fs::create_dir_all(&dir);
+
+        let script = format!(
+            r#"echo "$@" >> {log}
+case "$*" in
+  *portal-broken*) echo 'namespaces "portal-broken" is forbidden' >&2; exit 1 ;;
+  get*) echo '{{"items": [{{"metadata": {{"name": "op-portal-proj"}}}}]}}' ;;
+  apply*) cat >> {log}; echo >> {log} ;;
+esac
+"#,
+            log = dir.join("kubectl.log").display()
+        );
+
+        let _ = std::fs::write(dir.join("kubectl.sh"), script);
+        let _ = std::fs::remove_file(dir.join("kubectl.log"));
+
+        dir
+    }
+
+    #[test]
+    fn test_parse_resources() {
+        let resources = parse_resources("requests.cpu=64, requests.nvidia.com/gpu=4,")
+            .unwrap_or_else(|e| unreachable!("Cannot parse resources: {}", e));
+
+        assert_eq!(resources.len(), 2);
+        assert_eq!(resources["requests.cpu"], "64");
+        assert_eq!(resources["requests.nvidia.com/gpu"], "4");
+
+        assert!(parse_resources("").is_ok_and(|r| r.is_empty()));
+        assert!(parse_resources("requests.cpu").is_err());
+        assert!(parse_resources("=64").is_err());
+        assert!(parse_resources("requests.cpu=").is_err());
+    }
+
+    #[test]
+    fn test_sanitise() {
+        assert!(matches!(
+            sanitise("op-Portal.Proj").as_deref(),
+            Ok("op-portal-proj")
+        ));
+        assert!(matches!(sanitise("..a_b..").as_deref(), Ok("a-b")));
+        assert!(sanitise(&"x".repeat(100)).is_ok_and(|name| name.len() == 63));
+        assert!(sanitise("...").is_err());
+    }
+
+    #[test]
+    fn test_parse_cpu() {
+        assert_eq!(parse_cpu("500m"), Some(0.5));
+        assert_eq!(parse_cpu(" 2 "), Some(2.0));
+        assert_eq!(parse_cpu("1.5"), Some(1.5));
+        assert_eq!(parse_cpu("lots"), None);
+    }
+
+    #[tokio::test]
+    async fn test_kubectl_commands() {
+        let dir = fake_kubectl();
+        let log = dir.join("kubectl.log");
+
+        let project = ProjectMapping::new(
+            &ProjectIdentifier::parse("proj.portal")
+                .unwrap_or_else(|e| unreachable!("Cannot parse project: {}", e)),
+            "portal.proj",
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e));
+
+        let user = UserMapping::new(
+            &UserIdentifier::parse("alice.proj.portal")
+                .unwrap_or_else(|e| unreachable!("Cannot parse user: {}", e)),
+            "alice.proj",
+            "portal.proj",
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e));
+
+        let expires = Utc::now() + chrono::Duration::seconds(30);
+
+        // nothing can be run until the settings are initialised
+        assert!(matches!(
+            add_project(&project, &expires).await,
+            Err(Error::Call(_))
+        ));
+
+        initialise(
+            Settings::new(
+                &format!("sh {}", dir.join("kubectl.sh").display()),
+                "op-",
+                "oidc:",
+                "edit",
+                "requests.cpu=8",
+                "cpu=1",
+                "",
+                false,
+            )
+            .unwrap_or_else(|e| unreachable!("Cannot create settings: {}", e)),
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot initialise: {}", e));
+
+        assert!(matches!(
+            namespace_name("portal.proj").as_deref(),
+            Ok("op-portal-proj")
+        ));
+
+        // projects become namespaces with a quota and limit range
+        add_project(&project, &expires)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add project: {}", e));
+
+        // users are bound to the configured role in that namespace
+        add_user(&user, &expires)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add user: {}", e));
+
+        remove_user(&user, &expires)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove user: {}", e));
+
+        // namespaces are kept unless delete-namespaces is set
+        remove_project(&project, &expires)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove project: {}", e));
+
+        let namespaces = get_managed_namespaces()
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot get namespaces: {}", e));
+        assert_eq!(namespaces, vec!["op-portal-proj".to_owned()]);
+
+        let log = std::fs::read_to_string(&log).unwrap_or_default();
+        let lines: Vec<&str> = log.lines().collect();
+
+        assert_eq!(lines[0], "apply -f -");
+        assert!(lines[1].contains(r#""kind":"Namespace""#));
+        assert!(lines[1].contains(r#""name":"op-portal-proj""#));
+        assert!(lines[1].contains(r#""requests.cpu":"8""#));
+        assert!(lines[1].contains(r#""kind":"LimitRange""#));
+
+        assert_eq!(lines[2], "apply -f -");
+        assert!(lines[3].contains(r#""kind":"RoleBinding""#));
+        assert!(lines[3].contains(r#""name":"openportal-alice-proj""#));
+        assert!(lines[3].contains(r#""name":"oidc:alice.proj""#));
+        assert!(lines[3].contains(r#""name":"edit""#));
+
+        assert_eq!(
+            &lines[4..],
+            [
+                "delete rolebinding openportal-alice-proj -n op-portal-proj --ignore-not-found",
+                "delete rolebindings -n op-portal-proj -l openportal.io/managed=true --ignore-not-found",
+                "get namespaces -l openportal.io/managed=true -o json",
+            ]
+        );
+
+        // failures of kubectl are returned as errors
+        let broken = ProjectMapping::new(
+            &ProjectIdentifier::parse("broken.portal")
+                .unwrap_or_else(|e| unreachable!("Cannot parse project: {}", e)),
+            "portal.broken",
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e));
+
+        assert!(matches!(
+            remove_project(&broken, &expires).await,
+            Err(Error::Call(message)) if message.contains("forbidden")
+        ));
+
+        // and expired jobs are not run
+        let expired = Utc::now() - chrono::Duration::seconds(1);
+        assert!(matches!(
+            add_project(&project, &expired).await,
+            Err(Error::Expired(_))
+        ));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
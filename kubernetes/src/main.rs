@@ -0,0 +1,151 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+
+use templemeads::agent::scheduler::{process_args, run, Defaults};
+use templemeads::agent::Type as AgentType;
+use templemeads::async_runnable;
+use templemeads::grammar::Instruction::{
+    AddLocalProject, AddLocalUser, GetLocalUsageReport, RemoveLocalProject, RemoveLocalUser,
+};
+use templemeads::job::{Envelope, Job};
+use templemeads::notification::default_notify_runner;
+use templemeads::set_notify_runner;
+use templemeads::Error;
+
+mod kubectl;
+mod usage;
+
+///
+/// Main function for the kubernetes scheduler agent.
+///
+/// This agent gives each project its own namespace on a Kubernetes
+/// cluster, with a ResourceQuota and LimitRange, and binds each of the
+/// project's users to a role within that namespace. The resources
+/// requested by the running pods of each namespace are sampled so that
+/// its consumption can be returned as a usage report.
+///
+#[tokio::main]
+async fn main() -> Result<()> {
+    // start tracing
+    templemeads::config::initialise_tracing();
+
+    // start system monitoring
+    templemeads::spawn_system_monitor();
+
+    // create the OpenPortal paddington defaults
+    let defaults: Defaults = Defaults::parse(
+        Some("kubernetes".to_owned()),
+        Some(
+            dirs::config_local_dir()
+                .unwrap_or(
+                    ".".parse()
+                        .expect("Could not parse fallback config directory."),
+                )
+                .join("openportal")
+                .join("kubernetes-config.toml"),
+        ),
+        Some("ws://localhost:8049".to_owned()),
+        Some("127.0.0.1".to_owned()),
+        Some(8049),
+        None,
+        None,
+        Some(AgentType::Scheduler),
+    );
+
+    // now parse the command line arguments to get the service configuration
+    let config = match process_args(&defaults).await? {
+        Some(config) => config,
+        None => {
+            // Not running the service, so can safely exit
+            return Ok(());
+        }
+    };
+
+    // the kubectl command, which can include options such as
+    // "kubectl --kubeconfig /etc/openportal/kubeconfig"
+    let kubectl_command = config.option("kubectl", "kubectl");
+
+    // the ClusterRole that each user is bound to in their project's namespace
+    let user_role = config.option("user-role", "edit");
+
+    // the quota, default limits and default requests of each namespace, as
+    // comma-separated "name=quantity" pairs, e.g. "requests.cpu=64,pods=100"
+    kubectl::initialise(kubectl::Settings::new(
+        &kubectl_command,
+        &config.option("namespace-prefix", ""),
+        &config.option("user-prefix", ""),
+        &user_role,
+        &config.option("namespace-quota", ""),
+        &config.option("default-limits", ""),
+        &config.option("default-requests", ""),
+        config.option("delete-namespaces", "false").to_lowercase() == "true",
+    )?)?;
+
+    // how the consumption of each namespace is measured. CPU usage is
+    // converted to node-seconds using the number of cpus per node
+    let cpus_per_node: f64 = config
+        .option("cpus-per-node", "1")
+        .parse()
+        .map_err(|e| anyhow::anyhow!("Invalid cpus-per-node: {}", e))?;
+
+    usage::initialise(
+        &config.option("usage-dir", ""),
+        &config.option("gpu-resource", "nvidia.com/gpu"),
+        cpus_per_node,
+    )
+    .await?;
+
+    // how often (in seconds) the usage of each namespace is sampled
+    // (0 means that usage is not sampled)
+    let usage_interval: u64 = config.option("usage-interval", "60").parse().unwrap_or(60);
+
+    if usage_interval > 0 {
+        usage::spawn_sampler(usage_interval);
+    }
+
+    async_runnable! {
+        ///
+        /// Runnable function that will be called when a job is received
+        /// by the agent
+        ///
+        pub async fn kubernetes_runner(envelope: Envelope) -> Result<Job, templemeads::Error>
+        {
+            let job = envelope.job();
+
+            match job.instruction() {
+                AddLocalProject(project) => {
+                    kubectl::add_project(&project, job.expires()).await?;
+                    job.completed_none()
+                },
+                RemoveLocalProject(project) => {
+                    kubectl::remove_project(&project, job.expires()).await?;
+                    job.completed_none()
+                },
+                AddLocalUser(user) => {
+                    kubectl::add_user(&user, job.expires()).await?;
+                    job.completed_none()
+                },
+                RemoveLocalUser(mapping) => {
+                    kubectl::remove_user(&mapping, job.expires()).await?;
+                    job.completed_none()
+                },
+                GetLocalUsageReport(mapping, dates) => {
+                    let report = usage::get_usage_report(&mapping, &dates, job.expires()).await?;
+                    job.completed(report)
+                },
+                _ => {
+                    Err(Error::InvalidInstruction(
+                        format!("Invalid instruction: {}. Kubernetes agents do not support this instruction", job.instruction()),
+                    ))
+                }
+            }
+        }
+    }
+
+    set_notify_runner(default_notify_runner).await?;
+    run(config, kubernetes_runner).await?;
+
+    Ok(())
+}
@@ -0,0 +1,254 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use templemeads::grammar::{Date, DateRange, ProjectMapping};
+use templemeads::job::assert_not_expired;
+use templemeads::usagereport::{DailyProjectUsageReport, ProjectUsageReport, Usage};
+use templemeads::Error;
+use tokio::sync::RwLock;
+
+use crate::kubectl;
+
+///
+/// The node-seconds and GPU-seconds consumed by a namespace on a day
+///
+#[derive(Debug, Clone, Copy, Default)]
+struct Consumption {
+    node_seconds: f64,
+    gpu_seconds: f64,
+}
+
+#[derive(Debug, Default)]
+struct Database {
+    consumption: HashMap<String, HashMap<Date, Consumption>>,
+    usage_dir: Option<PathBuf>,
+    gpu_resource: String,
+    cpus_per_node: f64,
+}
+
+static CACHE: Lazy<RwLock<Database>> = Lazy::new(|| RwLock::new(Database::default()));
+
+///
+/// Set how usage is measured and (optionally) the directory in which it
+/// is saved, loading any usage that was saved previously
+///
+pub async fn initialise(
+    usage_dir: &str,
+    gpu_resource: &str,
+    cpus_per_node: f64,
+) -> Result<(), Error> {
+    if cpus_per_node <= 0.0 {
+        return Err(Error::Misconfigured(format!(
+            "Invalid number of cpus per node: {}. This must be greater than zero",
+            cpus_per_node
+        )));
+    }
+
+    let mut cache = CACHE.write().await;
+    cache.gpu_resource = gpu_resource.to_owned();
+    cache.cpus_per_node = cpus_per_node;
+
+    let usage_dir = usage_dir.trim();
+
+    if usage_dir.is_empty() {
+        tracing::warn!("No usage-dir set - namespace usage will be lost when the agent restarts");
+        return Ok(());
+    }
+
+    let usage_dir = PathBuf::from(usage_dir);
+
+    std::fs::create_dir_all(&usage_dir).map_err(|e| {
+        Error::Misconfigured(format!(
+            "Could not create usage directory {}: {}",
+            usage_dir.display(),
+            e
+        ))
+    })?;
+
+    let entries = std::fs::read_dir(&usage_dir).map_err(|e| {
+        Error::Misconfigured(format!(
+            "Could not read usage directory {}: {}",
+            usage_dir.display(),
+            e
+        ))
+    })?;
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        let namespace = match (
+            path.extension().and_then(|e| e.to_str()),
+            path.file_stem().and_then(|s| s.to_str()),
+        ) {
+            (Some("json"), Some(namespace)) => namespace.to_owned(),
+            _ => continue,
+        };
+
+        match load(&path) {
+            Ok(consumption) => {
+                cache.consumption.insert(namespace, consumption);
+            }
+            Err(e) => {
+                tracing::warn!("Could not load usage from {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    cache.usage_dir = Some(usage_dir);
+
+    Ok(())
+}
+
+fn load(path: &PathBuf) -> Result<HashMap<Date, Consumption>, Error> {
+    let contents = std::fs::read_to_string(path)?;
+
+    let value: Value = serde_json::from_str(&contents)
+        .map_err(|e| Error::Parse(format!("Invalid usage file: {}", e)))?;
+
+    let mut consumption = HashMap::new();
+
+    for (day, usage) in value.as_object().cloned().unwrap_or_default() {
+        consumption.insert(
+            Date::parse(&day)?,
+            Consumption {
+                node_seconds: usage["node_seconds"].as_f64().unwrap_or(0.0),
+                gpu_seconds: usage["gpu_seconds"].as_f64().unwrap_or(0.0),
+            },
+        );
+    }
+
+    Ok(consumption)
+}
+
+fn save(path: &PathBuf, consumption: &HashMap<Date, Consumption>) -> Result<(), Error> {
+    let value: serde_json::Map<String, Value> = consumption
+        .iter()
+        .map(|(day, usage)| {
+            (
+                day.to_string(),
+                json!({
+                    "node_seconds": usage.node_seconds,
+                    "gpu_seconds": usage.gpu_seconds,
+                }),
+            )
+        })
+        .collect();
+
+    // write to a temporary file first, so that a crash cannot leave
+    // a partially written file behind
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, Value::Object(value).to_string())?;
+    std::fs::rename(&tmp, path)?;
+
+    Ok(())
+}
+
+///
+/// Sample the resources requested by the running pods of every managed
+/// namespace, and add them to that namespace's usage for today, as if
+/// they had been held for `interval` seconds
+///
+async fn sample(interval: u64) -> Result<(), Error> {
+    let namespaces = kubectl::get_managed_namespaces().await?;
+
+    if namespaces.is_empty() {
+        return Ok(());
+    }
+
+    let gpu_resource = CACHE.read().await.gpu_resource.clone();
+    let requests = kubectl::get_namespace_requests(&namespaces, &gpu_resource).await?;
+
+    let today = Date::today();
+    let mut cache = CACHE.write().await;
+    let cpus_per_node = cache.cpus_per_node;
+    let usage_dir = cache.usage_dir.clone();
+
+    for (namespace, (cpus, gpus)) in requests {
+        let consumption = cache.consumption.entry(namespace.clone()).or_default();
+        let usage = consumption.entry(today.clone()).or_default();
+
+        usage.node_seconds += interval as f64 * cpus / cpus_per_node;
+        usage.gpu_seconds += interval as f64 * gpus;
+
+        if let Some(usage_dir) = &usage_dir {
+            let path = usage_dir.join(format!("{}.json", namespace));
+
+            if let Err(e) = save(&path, consumption) {
+                tracing::warn!("Could not save usage to {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Spawn a background task that samples the usage of every managed
+/// namespace every `interval` seconds
+///
+pub fn spawn_sampler(interval: u64) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(tokio::time::Duration::from_secs(interval));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        // the first tick completes immediately, and there is nothing
+        // to account for before the agent started
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+
+            if let Err(e) = sample(interval).await {
+                tracing::warn!("Could not sample namespace usage: {}", e);
+            }
+        }
+    });
+}
+
+///
+/// Return the usage of the project's namespace on each of the passed
+/// days. Usage is not attributed to individual users, as Kubernetes does
+/// not record who created each pod. CPU usage is in node-seconds, and
+/// GPU usage is in the "gpu" component.
+///
+pub async fn get_usage_report(
+    project: &ProjectMapping,
+    dates: &DateRange,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<ProjectUsageReport, Error> {
+    assert_not_expired(expires)?;
+
+    let namespace = kubectl::namespace_name(project.local_group())?;
+    let today = Date::today();
+
+    let cache = CACHE.read().await;
+    let consumption = cache.consumption.get(&namespace);
+
+    let mut report = ProjectUsageReport::new(project.project());
+
+    // we can't get the usage for days in the future
+    for day in dates.days().into_iter().filter(|day| *day <= today) {
+        let mut daily_report = DailyProjectUsageReport::default();
+
+        if let Some(usage) = consumption.and_then(|c| c.get(&day)) {
+            daily_report.add_unattributed_usage(Usage::new(usage.node_seconds.round() as u64));
+            daily_report.add_unattributed_component_usage(
+                "gpu",
+                Usage::new(usage.gpu_seconds.round() as u64),
+            );
+        }
+
+        if day < today {
+            daily_report.set_complete();
+        }
+
+        report.set_report(&day, &daily_report);
+    }
+
+    Ok(report)
+}
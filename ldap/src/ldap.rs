@@ -0,0 +1,1218 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+use chrono::Utc;
+use ldap3::{
+    dn_escape, ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, Mod, Scope, SearchEntry,
+};
+use once_cell::sync::{Lazy, OnceCell};
+use secrecy::{ExposeSecret, SecretString};
+use std::collections::HashSet;
+use std::time::Duration;
+use templemeads::grammar::{
+    PortalIdentifier, ProjectIdentifier, ProjectMapping, UserIdentifier, UserMapping,
+};
+use templemeads::job::assert_not_expired;
+use templemeads::protection::{ProtectionPolicy, ProtectionStatus};
+use templemeads::Error;
+use tokio::sync::Mutex;
+
+static SETTINGS: OnceCell<Settings> = OnceCell::new();
+
+///
+/// Held while a new uidNumber or gidNumber is chosen and its entry is
+/// added, so that two entries are never given the same id
+///
+static ID_ALLOCATION: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+// LDAP result codes that are handled rather than treated as errors
+const SUCCESS: u32 = 0;
+const NO_SUCH_ATTRIBUTE: u32 = 16;
+const ATTRIBUTE_OR_VALUE_EXISTS: u32 = 20;
+const NO_SUCH_OBJECT: u32 = 32;
+const ENTRY_ALREADY_EXISTS: u32 = 68;
+
+///
+/// Configuration for the LDAP directory managed by this agent. Users are
+/// `posixAccount` entries named `uid=<local user>` under `user_base`, and
+/// projects are `posixGroup` entries named `cn=<local group>` under
+/// `group_base`, with members listed in `memberUid`.
+///
+pub struct Settings {
+    url: String,
+    bind_dn: String,
+    bind_password: SecretString,
+    user_base: String,
+    group_base: String,
+    /// Group that all users managed by this agent are added to, used to
+    /// distinguish managed users from other accounts in the directory.
+    managed_group: String,
+    default_shell: String,
+    /// Inclusive range from which the uidNumber and gidNumber of new
+    /// users and groups are allocated.
+    id_range: (u32, u32),
+    starttls: bool,
+    allow_invalid_certs: bool,
+    protected_users: ProtectionPolicy,
+    protected_groups: ProtectionPolicy,
+}
+
+impl Settings {
+    // ignore too many arguments warning for this constructor,
+    // since it's more ergonomic to construct the Settings struct directly
+    // from the config file with all fields specified.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        url: &str,
+        bind_dn: &str,
+        bind_password: SecretString,
+        user_base: &str,
+        group_base: &str,
+        managed_group: &str,
+        default_shell: &str,
+        id_range: (u32, u32),
+        starttls: bool,
+        allow_invalid_certs: bool,
+        protected_users: ProtectionPolicy,
+        protected_groups: ProtectionPolicy,
+    ) -> Self {
+        Self {
+            url: url.to_owned(),
+            bind_dn: bind_dn.to_owned(),
+            bind_password,
+            user_base: user_base.to_owned(),
+            group_base: group_base.to_owned(),
+            managed_group: managed_group.to_owned(),
+            default_shell: default_shell.to_owned(),
+            id_range,
+            starttls,
+            allow_invalid_certs,
+            protected_users,
+            protected_groups,
+        }
+    }
+}
+
+pub fn initialise(settings: Settings) -> Result<()> {
+    tracing::info!(
+        "Managing users in {} and groups in {} on {}",
+        settings.user_base,
+        settings.group_base,
+        settings.url
+    );
+    tracing::info!("Protected users: {}", settings.protected_users);
+    tracing::info!("Protected groups: {}", settings.protected_groups);
+
+    SETTINGS
+        .set(settings)
+        .map_err(|_| anyhow::anyhow!("LDAP settings already initialised"))
+}
+
+fn get_settings() -> Result<&'static Settings, Error> {
+    SETTINGS
+        .get()
+        .ok_or_else(|| Error::Call("LDAP settings not initialised".to_owned()))
+}
+
+///
+/// Parse an inclusive id range, e.g. "200000-299999"
+///
+pub fn parse_id_range(range: &str) -> Result<(u32, u32), Error> {
+    let (start, end) = range
+        .split_once('-')
+        .and_then(|(start, end)| {
+            Some((
+                start.trim().parse::<u32>().ok()?,
+                end.trim().parse::<u32>().ok()?,
+            ))
+        })
+        .ok_or_else(|| {
+            Error::Misconfigured(format!(
+                "Invalid id range '{}'. This should be 'start-end'",
+                range
+            ))
+        })?;
+
+    if start == 0 || start > end {
+        return Err(Error::Misconfigured(format!(
+            "Invalid id range '{}'. The start must be greater than 0 and not after the end",
+            range
+        )));
+    }
+
+    Ok((start, end))
+}
+
+///
+/// Connect and bind to the LDAP server. A new connection is used for
+/// each request, and is closed when the returned handle is unbound
+/// or dropped.
+///
+async fn connect(expires: &chrono::DateTime<Utc>) -> Result<Ldap, Error> {
+    assert_not_expired(expires)?;
+
+    let settings = get_settings()?;
+
+    let time_left = expires
+        .signed_duration_since(Utc::now())
+        .num_seconds()
+        .clamp(1, 30) as u64;
+
+    let conn_settings = LdapConnSettings::new()
+        .set_conn_timeout(Duration::from_secs(time_left))
+        .set_starttls(settings.starttls)
+        .set_no_tls_verify(settings.allow_invalid_certs);
+
+    let (conn, mut ldap) = LdapConnAsync::with_settings(conn_settings, &settings.url)
+        .await
+        .map_err(|e| Error::Call(format!("Could not connect to {}: {}", settings.url, e)))?;
+
+    ldap3::drive!(conn);
+
+    let result = ldap
+        .simple_bind(&settings.bind_dn, settings.bind_password.expose_secret())
+        .await
+        .map_err(|e| Error::Login(format!("Could not bind to {}: {}", settings.url, e)))?;
+
+    if result.rc != SUCCESS {
+        return Err(Error::Login(format!(
+            "Could not bind to {} as {}: rc={} {}",
+            settings.url, settings.bind_dn, result.rc, result.text
+        )));
+    }
+
+    Ok(ldap)
+}
+
+///
+/// Unbind from the server, logging (but otherwise ignoring) any error,
+/// as the request has already completed
+///
+async fn disconnect(mut ldap: Ldap) {
+    if let Err(e) = ldap.unbind().await {
+        tracing::warn!("Could not unbind from the LDAP server: {}", e);
+    }
+}
+
+///
+/// Search below `base` for entries matching `filter`, returning the
+/// requested attributes. A missing base is treated as no matches.
+///
+async fn search(
+    ldap: &mut Ldap,
+    base: &str,
+    filter: &str,
+    attrs: Vec<&str>,
+) -> Result<Vec<SearchEntry>, Error> {
+    let result = ldap
+        .search(base, Scope::Subtree, filter, attrs)
+        .await
+        .map_err(|e| Error::Call(format!("Could not search {} for {}: {}", base, filter, e)))?;
+
+    match result.1.rc {
+        SUCCESS | NO_SUCH_OBJECT => Ok(result.0.into_iter().map(SearchEntry::construct).collect()),
+        rc => Err(Error::Call(format!(
+            "Could not search {} for {}: rc={} {}",
+            base, filter, rc, result.1.text
+        ))),
+    }
+}
+
+///
+/// Return all values of the named attribute of the entry. Attribute
+/// names are matched case-insensitively, as servers may return them
+/// in a different case from the one requested.
+///
+fn get_attrs(entry: &SearchEntry, name: &str) -> Vec<String> {
+    entry
+        .attrs
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, values)| values.clone())
+        .unwrap_or_default()
+}
+
+fn get_attr(entry: &SearchEntry, name: &str) -> Option<String> {
+    get_attrs(entry, name).into_iter().next()
+}
+
+fn get_id(entry: &SearchEntry, name: &str) -> Option<u32> {
+    get_attr(entry, name).and_then(|id| id.trim().parse::<u32>().ok())
+}
+
+///
+/// Return the local username for a UserIdentifier.
+/// Format: "{username}.{project}"
+///
+pub fn identifier_to_userid(user: &UserIdentifier) -> String {
+    format!("{}.{}", user.username(), user.project())
+}
+
+///
+/// Return the local group name for a ProjectIdentifier.
+/// Format: "{portal}.{project}"
+///
+fn identifier_to_projectid(project: &ProjectIdentifier) -> String {
+    format!("{}.{}", project.portal(), project.project())
+}
+
+///
+/// Return the name of the primary group for a user. This is the
+/// project group: "{portal}.{project}".
+///
+pub fn get_primary_group_name(user: &UserIdentifier) -> String {
+    identifier_to_projectid(&user.project_identifier())
+}
+
+fn user_dn(settings: &Settings, local_user: &str) -> String {
+    format!("uid={},{}", dn_escape(local_user), settings.user_base)
+}
+
+fn group_dn(settings: &Settings, group_name: &str) -> String {
+    format!("cn={},{}", dn_escape(group_name), settings.group_base)
+}
+
+async fn find_user(ldap: &mut Ldap, local_user: &str) -> Result<Option<SearchEntry>, Error> {
+    let settings = get_settings()?;
+
+    Ok(search(
+        ldap,
+        &settings.user_base,
+        &format!(
+            "(&(objectClass=posixAccount)(uid={}))",
+            ldap_escape(local_user)
+        ),
+        vec!["uid", "cn", "uidNumber", "gidNumber", "homeDirectory"],
+    )
+    .await?
+    .into_iter()
+    .next())
+}
+
+async fn find_group(ldap: &mut Ldap, group_name: &str) -> Result<Option<SearchEntry>, Error> {
+    let settings = get_settings()?;
+
+    Ok(search(
+        ldap,
+        &settings.group_base,
+        &format!(
+            "(&(objectClass=posixGroup)(cn={}))",
+            ldap_escape(group_name)
+        ),
+        vec!["cn", "gidNumber", "description", "memberUid"],
+    )
+    .await?
+    .into_iter()
+    .next())
+}
+
+///
+/// Return the lowest id in the configured range that is not used as
+/// the uidNumber or gidNumber of any user, or the gidNumber of any group.
+/// This must be called while holding ID_ALLOCATION.
+///
+async fn next_free_id(ldap: &mut Ldap) -> Result<u32, Error> {
+    let settings = get_settings()?;
+    let (start, end) = settings.id_range;
+
+    let mut used = HashSet::new();
+
+    for entry in search(
+        ldap,
+        &settings.user_base,
+        "(objectClass=posixAccount)",
+        vec!["uidNumber", "gidNumber"],
+    )
+    .await?
+    {
+        used.extend(get_id(&entry, "uidNumber"));
+        used.extend(get_id(&entry, "gidNumber"));
+    }
+
+    for entry in search(
+        ldap,
+        &settings.group_base,
+        "(objectClass=posixGroup)",
+        vec!["gidNumber"],
+    )
+    .await?
+    {
+        used.extend(get_id(&entry, "gidNumber"));
+    }
+
+    (start..=end)
+        .find(|id| !used.contains(id))
+        .ok_or_else(|| Error::InvalidState(format!("The id range {}-{} is exhausted", start, end)))
+}
+
+///
+/// Ensure that the named posixGroup exists, creating it with a new
+/// gidNumber if not, and return its gidNumber.
+///
+async fn ensure_group_exists(
+    ldap: &mut Ldap,
+    group_name: &str,
+    description: &str,
+) -> Result<u32, Error> {
+    if let Some(group) = find_group(ldap, group_name).await? {
+        return get_id(&group, "gidNumber").ok_or_else(|| {
+            Error::InvalidState(format!("Group '{}' has no gidNumber", group_name))
+        });
+    }
+
+    let settings = get_settings()?;
+    let dn = group_dn(settings, group_name);
+
+    let _guard = ID_ALLOCATION.lock().await;
+
+    let gid = next_free_id(ldap).await?.to_string();
+
+    let result = ldap
+        .add(
+            &dn,
+            vec![
+                ("objectClass", HashSet::from(["top", "posixGroup"])),
+                ("cn", HashSet::from([group_name])),
+                ("gidNumber", HashSet::from([gid.as_str()])),
+                ("description", HashSet::from([description])),
+            ],
+        )
+        .await
+        .map_err(|e| Error::Call(format!("Could not add group '{}': {}", dn, e)))?;
+
+    match result.rc {
+        SUCCESS => {
+            tracing::info!("Group created: {} (gid {})", group_name, gid);
+        }
+        ENTRY_ALREADY_EXISTS => {
+            tracing::warn!("Group already exists: {}", group_name);
+        }
+        rc => {
+            return Err(Error::Call(format!(
+                "Could not add group '{}': rc={} {}",
+                dn, rc, result.text
+            )));
+        }
+    }
+
+    match find_group(ldap, group_name).await? {
+        Some(group) => get_id(&group, "gidNumber")
+            .ok_or_else(|| Error::InvalidState(format!("Group '{}' has no gidNumber", group_name))),
+        None => Err(Error::Call(format!(
+            "Failed to add group '{}' to the directory",
+            group_name
+        ))),
+    }
+}
+
+///
+/// Add (or remove) the local user to (or from) the memberUid of the
+/// named group. This is idempotent.
+///
+async fn set_group_member(
+    ldap: &mut Ldap,
+    group_name: &str,
+    local_user: &str,
+    is_member: bool,
+) -> Result<(), Error> {
+    let settings = get_settings()?;
+    let dn = group_dn(settings, group_name);
+
+    let change = match is_member {
+        true => Mod::Add("memberUid", HashSet::from([local_user])),
+        false => Mod::Delete("memberUid", HashSet::from([local_user])),
+    };
+
+    let result = ldap
+        .modify(&dn, vec![change])
+        .await
+        .map_err(|e| Error::Call(format!("Could not modify group '{}': {}", dn, e)))?;
+
+    match result.rc {
+        SUCCESS | ATTRIBUTE_OR_VALUE_EXISTS | NO_SUCH_ATTRIBUTE => Ok(()),
+        NO_SUCH_OBJECT if !is_member => Ok(()),
+        rc => Err(Error::Call(format!(
+            "Could not change the membership of '{}' in group '{}': rc={} {}",
+            local_user, dn, rc, result.text
+        ))),
+    }
+}
+
+///
+/// Return why the user is protected, or None if they are not. Users are
+/// protected if their name or uidNumber matches the protection policy,
+/// or if they exist in the directory but are not members of the managed
+/// group.
+///
+async fn user_protection_reason(
+    ldap: &mut Ldap,
+    local_user: &str,
+) -> Result<Option<String>, Error> {
+    let settings = get_settings()?;
+    let user = find_user(ldap, local_user).await?;
+
+    let uid = user.as_ref().and_then(|user| get_id(user, "uidNumber"));
+
+    if let Some(reason) = settings.protected_users.reason(local_user, uid) {
+        return Ok(Some(reason));
+    }
+
+    if user.is_none() {
+        return Ok(None);
+    }
+
+    let is_managed = match find_group(ldap, &settings.managed_group).await? {
+        Some(group) => get_attrs(&group, "memberUid")
+            .iter()
+            .any(|member| member == local_user),
+        None => false,
+    };
+
+    match is_managed {
+        true => Ok(None),
+        false => Ok(Some(format!(
+            "'{}' is not a member of the managed group '{}'",
+            local_user, settings.managed_group
+        ))),
+    }
+}
+
+///
+/// Return why the group is protected by the protection policy, or
+/// None if it is not.
+///
+async fn group_protection_reason(
+    ldap: &mut Ldap,
+    group_name: &str,
+) -> Result<Option<String>, Error> {
+    let settings = get_settings()?;
+
+    let gid = find_group(ldap, group_name)
+        .await?
+        .and_then(|group| get_id(&group, "gidNumber"));
+
+    Ok(settings.protected_groups.reason(group_name, gid))
+}
+
+///
+/// Add the project (posixGroup) for the given ProjectIdentifier.
+/// Idempotent: succeeds silently if the group already exists.
+///
+pub async fn add_project(
+    project: &ProjectIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<ProjectMapping, Error> {
+    let group_name = identifier_to_projectid(project);
+
+    let mut ldap = connect(expires).await?;
+
+    if let Some(reason) = group_protection_reason(&mut ldap, &group_name).await? {
+        disconnect(ldap).await;
+        return Err(Error::InvalidState(format!(
+            "Refusing to add project group '{}' as {}",
+            group_name, reason
+        )));
+    }
+
+    tracing::info!("Adding project group: {}", group_name);
+
+    let result = ensure_group_exists(
+        &mut ldap,
+        &group_name,
+        &format!("{} | OpenPortal-managed group", project),
+    )
+    .await;
+
+    disconnect(ldap).await;
+    result?;
+
+    ProjectMapping::new(project, &group_name)
+}
+
+///
+/// Remove the project (posixGroup) for the given ProjectIdentifier.
+/// Idempotent: succeeds silently if the group did not exist.
+///
+pub async fn remove_project(
+    project: &ProjectIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<ProjectMapping, Error> {
+    let group_name = identifier_to_projectid(project);
+    let settings = get_settings()?;
+
+    let mut ldap = connect(expires).await?;
+
+    if let Some(reason) = group_protection_reason(&mut ldap, &group_name).await? {
+        disconnect(ldap).await;
+        return Err(Error::InvalidState(format!(
+            "Refusing to remove project group '{}' as {}",
+            group_name, reason
+        )));
+    }
+
+    tracing::info!("Removing project group: {}", group_name);
+
+    let dn = group_dn(settings, &group_name);
+    let result = ldap.delete(&dn).await;
+    disconnect(ldap).await;
+
+    let result = result.map_err(|e| Error::Call(format!("Could not delete '{}': {}", dn, e)))?;
+
+    match result.rc {
+        SUCCESS => {
+            tracing::info!("Project group removed: {}", group_name);
+        }
+        NO_SUCH_OBJECT => {
+            tracing::warn!("Project group did not exist: {}", group_name);
+        }
+        rc => {
+            return Err(Error::Call(format!(
+                "Could not delete '{}': rc={} {}",
+                dn, rc, result.text
+            )));
+        }
+    }
+
+    ProjectMapping::new(project, &group_name)
+}
+
+///
+/// Add a user to the directory. The user's project group and the
+/// managed group are created if they do not yet exist, and the user
+/// is added to both. The supplied homedir is used; if None a default
+/// of /home/{local_user} is used.
+///
+pub async fn add_user(
+    user: &UserIdentifier,
+    homedir: &Option<String>,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<UserMapping, Error> {
+    let mut ldap = connect(expires).await?;
+    let result = add_user_with(&mut ldap, user, homedir).await;
+    disconnect(ldap).await;
+    result
+}
+
+async fn add_user_with(
+    ldap: &mut Ldap,
+    user: &UserIdentifier,
+    homedir: &Option<String>,
+) -> Result<UserMapping, Error> {
+    let settings = get_settings()?;
+    let local_user = identifier_to_userid(user);
+    let local_group = get_primary_group_name(user);
+
+    if let Some(reason) = user_protection_reason(ldap, &local_user).await? {
+        return Err(Error::UnmanagedUser(format!(
+            "Refusing to add user '{}' as {}",
+            local_user, reason
+        )));
+    }
+
+    ensure_group_exists(
+        ldap,
+        &settings.managed_group,
+        "Group for all users managed by OpenPortal",
+    )
+    .await?;
+
+    let gid = ensure_group_exists(
+        ldap,
+        &local_group,
+        &format!("{} | OpenPortal-managed group", user.project_identifier()),
+    )
+    .await?
+    .to_string();
+
+    if find_user(ldap, &local_user).await?.is_none() {
+        let default_home = format!("/home/{}", local_user);
+        let homedir = homedir.as_deref().unwrap_or(&default_home);
+        let dn = user_dn(settings, &local_user);
+        let cn = user.to_string();
+        let username = user.username();
+        let project = user.project();
+
+        tracing::info!("Adding user: {}", local_user);
+
+        let _guard = ID_ALLOCATION.lock().await;
+
+        let uid = next_free_id(ldap).await?.to_string();
+
+        let result = ldap
+            .add(
+                &dn,
+                vec![
+                    (
+                        "objectClass",
+                        HashSet::from([
+                            "top",
+                            "person",
+                            "organizationalPerson",
+                            "inetOrgPerson",
+                            "posixAccount",
+                        ]),
+                    ),
+                    ("uid", HashSet::from([local_user.as_str()])),
+                    ("cn", HashSet::from([cn.as_str()])),
+                    ("givenName", HashSet::from([username.as_str()])),
+                    ("sn", HashSet::from([project.as_str()])),
+                    ("uidNumber", HashSet::from([uid.as_str()])),
+                    ("gidNumber", HashSet::from([gid.as_str()])),
+                    ("homeDirectory", HashSet::from([homedir])),
+                    (
+                        "loginShell",
+                        HashSet::from([settings.default_shell.as_str()]),
+                    ),
+                    ("description", HashSet::from(["OpenPortal-managed user"])),
+                ],
+            )
+            .await
+            .map_err(|e| Error::Call(format!("Could not add user '{}': {}", dn, e)))?;
+
+        match result.rc {
+            SUCCESS => {
+                tracing::info!("User created: {} (uid {})", local_user, uid);
+            }
+            ENTRY_ALREADY_EXISTS => {
+                tracing::warn!("User already exists, will sync groups: {}", local_user);
+            }
+            rc => {
+                return Err(Error::Call(format!(
+                    "Could not add user '{}': rc={} {}",
+                    dn, rc, result.text
+                )));
+            }
+        }
+    }
+
+    set_group_member(ldap, &local_group, &local_user, true).await?;
+    set_group_member(ldap, &settings.managed_group, &local_user, true).await?;
+
+    UserMapping::new(user, &local_user, &local_group)
+}
+
+///
+/// Remove a user from the directory, together with their membership
+/// of all groups. Idempotent: succeeds silently if the user did not exist.
+/// Note: the home directory is intentionally NOT removed here — home
+/// directories are managed separately by the filesystem agent.
+///
+pub async fn remove_user(
+    user: &UserIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<UserMapping, Error> {
+    let mut ldap = connect(expires).await?;
+    let result = remove_user_with(&mut ldap, user).await;
+    disconnect(ldap).await;
+    result
+}
+
+async fn remove_user_with(ldap: &mut Ldap, user: &UserIdentifier) -> Result<UserMapping, Error> {
+    let settings = get_settings()?;
+    let local_user = identifier_to_userid(user);
+    let local_group = get_primary_group_name(user);
+
+    let mapping = UserMapping::new(user, &local_user, &local_group)?;
+
+    if let Some(reason) = user_protection_reason(ldap, &local_user).await? {
+        return Err(Error::UnmanagedUser(format!(
+            "Refusing to remove user '{}' as {}",
+            local_user, reason
+        )));
+    }
+
+    tracing::info!("Removing user: {}", local_user);
+
+    let groups = search(
+        ldap,
+        &settings.group_base,
+        &format!(
+            "(&(objectClass=posixGroup)(memberUid={}))",
+            ldap_escape(&local_user)
+        ),
+        vec!["cn"],
+    )
+    .await?;
+
+    for group in groups {
+        if let Some(group_name) = get_attr(&group, "cn") {
+            set_group_member(ldap, &group_name, &local_user, false).await?;
+        }
+    }
+
+    let dn = user_dn(settings, &local_user);
+
+    let result = ldap
+        .delete(&dn)
+        .await
+        .map_err(|e| Error::Call(format!("Could not delete '{}': {}", dn, e)))?;
+
+    match result.rc {
+        SUCCESS => {
+            tracing::info!("User removed: {}", local_user);
+        }
+        NO_SUCH_OBJECT => {
+            tracing::warn!("User did not exist: {}", local_user);
+        }
+        rc => {
+            return Err(Error::Call(format!(
+                "Could not delete '{}': rc={} {}",
+                dn, rc, result.text
+            )));
+        }
+    }
+
+    Ok(mapping)
+}
+
+///
+/// Update the home directory for a user.
+///
+pub async fn update_homedir(
+    user: &UserIdentifier,
+    homedir: &str,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    let settings = get_settings()?;
+    let local_user = identifier_to_userid(user);
+
+    let mut ldap = connect(expires).await?;
+
+    if let Some(reason) = user_protection_reason(&mut ldap, &local_user).await? {
+        disconnect(ldap).await;
+        return Err(Error::UnmanagedUser(format!(
+            "Refusing to update the home directory of '{}' as {}",
+            local_user, reason
+        )));
+    }
+
+    tracing::info!("Updating home directory for {}: {}", local_user, homedir);
+
+    let dn = user_dn(settings, &local_user);
+
+    let result = ldap
+        .modify(
+            &dn,
+            vec![Mod::Replace("homeDirectory", HashSet::from([homedir]))],
+        )
+        .await;
+
+    disconnect(ldap).await;
+
+    let result = result.map_err(|e| Error::Call(format!("Could not modify '{}': {}", dn, e)))?;
+
+    match result.rc {
+        SUCCESS => Ok(()),
+        NO_SUCH_OBJECT => Err(Error::MissingUser(format!(
+            "User does not exist: {}",
+            local_user
+        ))),
+        rc => Err(Error::Call(format!(
+            "Could not update the home directory of '{}': rc={} {}",
+            dn, rc, result.text
+        ))),
+    }
+}
+
+///
+/// Return the project identifier encoded at the start of the description
+/// of a group created by this agent, e.g. "project.portal | ..."
+///
+fn group_identifier(group: &SearchEntry) -> Option<ProjectIdentifier> {
+    get_attr(group, "description")
+        .and_then(|description| description.split('|').next().map(|s| s.trim().to_string()))
+        .and_then(|identifier| ProjectIdentifier::parse(&identifier).ok())
+}
+
+///
+/// Return all project mappings for the given portal, found from the
+/// identifiers in the descriptions of the groups in the directory.
+///
+pub async fn get_groups(
+    portal: &PortalIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Vec<ProjectMapping>, Error> {
+    let settings = get_settings()?;
+
+    let mut ldap = connect(expires).await?;
+
+    let groups = search(
+        &mut ldap,
+        &settings.group_base,
+        "(&(objectClass=posixGroup)(description=*))",
+        vec!["cn", "description"],
+    )
+    .await;
+
+    disconnect(ldap).await;
+
+    let mut mappings = Vec::new();
+
+    for group in groups? {
+        let (project, group_name) = match (group_identifier(&group), get_attr(&group, "cn")) {
+            (Some(project), Some(group_name)) => (project, group_name),
+            _ => continue,
+        };
+
+        if project.portal_identifier() != *portal {
+            continue;
+        }
+
+        match ProjectMapping::new(&project, &group_name) {
+            Ok(mapping) => mappings.push(mapping),
+            Err(e) => {
+                tracing::warn!("Could not create mapping for group '{}': {}", group_name, e)
+            }
+        }
+    }
+
+    Ok(mappings)
+}
+
+///
+/// Return user mappings for all managed members of the given project's group.
+///
+pub async fn get_users(
+    project: &ProjectIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Vec<UserMapping>, Error> {
+    let settings = get_settings()?;
+    let group_name = identifier_to_projectid(project);
+
+    let mut ldap = connect(expires).await?;
+
+    // the user identifier is stored in the cn of each user
+    let users = search(
+        &mut ldap,
+        &settings.user_base,
+        &format!(
+            "(&(objectClass=posixAccount)(cn=*.{}))",
+            ldap_escape(project.to_string())
+        ),
+        vec!["uid", "cn"],
+    )
+    .await;
+
+    let managed = match find_group(&mut ldap, &settings.managed_group).await {
+        Ok(group) => group
+            .map(|group| get_attrs(&group, "memberUid"))
+            .unwrap_or_default(),
+        Err(e) => {
+            disconnect(ldap).await;
+            return Err(e);
+        }
+    };
+
+    disconnect(ldap).await;
+
+    let mut mappings = Vec::new();
+
+    for user in users? {
+        let (local_user, cn) = match (get_attr(&user, "uid"), get_attr(&user, "cn")) {
+            (Some(local_user), Some(cn)) => (local_user, cn),
+            _ => continue,
+        };
+
+        if !managed.contains(&local_user) {
+            continue;
+        }
+
+        match UserIdentifier::parse(&cn) {
+            Ok(user_id) if user_id.project_identifier() == *project => {
+                match UserMapping::new(&user_id, &local_user, &group_name) {
+                    Ok(mapping) => mappings.push(mapping),
+                    Err(e) => {
+                        tracing::warn!("Could not create user mapping for '{}': {}", local_user, e)
+                    }
+                }
+            }
+            Ok(_) => continue,
+            Err(e) => {
+                tracing::warn!("Could not parse user identifier '{}': {}", cn, e)
+            }
+        }
+    }
+
+    Ok(mappings)
+}
+
+///
+/// Return the ProjectMapping for a project, or an error if it does not exist.
+///
+pub async fn get_project_mapping(
+    project: &ProjectIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<ProjectMapping, Error> {
+    if !is_existing_project(project, expires).await? {
+        return Err(Error::Call(format!("Project does not exist: {}", project)));
+    }
+
+    ProjectMapping::new(project, &identifier_to_projectid(project))
+}
+
+///
+/// Return the UserMapping for a user, or an error if they do not exist.
+///
+pub async fn get_user_mapping(
+    user: &UserIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<UserMapping, Error> {
+    if !is_existing_user(user, expires).await? {
+        return Err(Error::Call(format!("User does not exist: {}", user)));
+    }
+
+    UserMapping::new(
+        user,
+        &identifier_to_userid(user),
+        &get_primary_group_name(user),
+    )
+}
+
+///
+/// Return true if the user for the given identifier exists in the directory.
+///
+pub async fn is_existing_user(
+    user: &UserIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<bool, Error> {
+    let mut ldap = connect(expires).await?;
+    let result = find_user(&mut ldap, &identifier_to_userid(user)).await;
+    disconnect(ldap).await;
+
+    Ok(result?.is_some())
+}
+
+///
+/// Return true if the group for the given project exists in the directory.
+///
+pub async fn is_existing_project(
+    project: &ProjectIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<bool, Error> {
+    let mut ldap = connect(expires).await?;
+    let result = find_group(&mut ldap, &identifier_to_projectid(project)).await;
+    disconnect(ldap).await;
+
+    Ok(result?.is_some())
+}
+
+///
+/// Return true if the user is "protected" — i.e. the user exists in the
+/// directory but is not managed by this agent, or they match the
+/// protection policy.
+///
+pub async fn is_protected_user(
+    user: &UserIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<bool, Error> {
+    let local_user = identifier_to_userid(user);
+
+    let mut ldap = connect(expires).await?;
+
+    let result = match find_user(&mut ldap, &local_user).await {
+        Ok(Some(_)) => user_protection_reason(&mut ldap, &local_user)
+            .await
+            .map(|reason| reason.is_some()),
+        Ok(None) => Ok(false),
+        Err(e) => Err(e),
+    };
+
+    disconnect(ldap).await;
+    result
+}
+
+///
+/// Return whether or not the given user is protected, and why.
+///
+pub async fn get_user_protection(
+    user: &UserIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<ProtectionStatus, Error> {
+    let local_user = identifier_to_userid(user);
+
+    let mut ldap = connect(expires).await?;
+
+    let exists = find_user(&mut ldap, &local_user).await;
+    let reason = user_protection_reason(&mut ldap, &local_user).await;
+
+    disconnect(ldap).await;
+
+    let local_name = match exists?.is_some() {
+        true => Some(local_user.as_str()),
+        false => None,
+    };
+
+    match reason? {
+        Some(reason) => Ok(ProtectionStatus::protected(
+            &user.to_string(),
+            local_name,
+            &reason,
+        )),
+        None => Ok(ProtectionStatus::unprotected(&user.to_string(), local_name)),
+    }
+}
+
+///
+/// Return whether or not the group for the given project is protected,
+/// and why.
+///
+pub async fn get_project_protection(
+    project: &ProjectIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<ProtectionStatus, Error> {
+    let group_name = identifier_to_projectid(project);
+
+    let mut ldap = connect(expires).await?;
+
+    let exists = find_group(&mut ldap, &group_name).await;
+    let reason = group_protection_reason(&mut ldap, &group_name).await;
+
+    disconnect(ldap).await;
+
+    let local_name = match exists?.is_some() {
+        true => Some(group_name.as_str()),
+        false => None,
+    };
+
+    match reason? {
+        Some(reason) => Ok(ProtectionStatus::protected(
+            &project.to_string(),
+            local_name,
+            &reason,
+        )),
+        None => Ok(ProtectionStatus::unprotected(
+            &project.to_string(),
+            local_name,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn settings() -> Settings {
+        Settings::new(
+            "ldap://localhost",
+            "cn=admin,dc=example,dc=org",
+            SecretString::from("password"),
+            "ou=people,dc=example,dc=org",
+            "ou=groups,dc=example,dc=org",
+            "openportal",
+            "/bin/bash",
+            (200000, 299999),
+            false,
+            false,
+            ProtectionPolicy::parse("root", "0-999").unwrap_or_default(),
+            ProtectionPolicy::parse("root,wheel", "0-999").unwrap_or_default(),
+        )
+    }
+
+    fn entry(attrs: &[(&str, &str)]) -> SearchEntry {
+        let mut map: HashMap<String, Vec<String>> = HashMap::new();
+
+        for (key, value) in attrs {
+            map.entry(key.to_string())
+                .or_default()
+                .push(value.to_string());
+        }
+
+        SearchEntry {
+            dn: "cn=test,dc=example,dc=org".to_owned(),
+            attrs: map,
+            bin_attrs: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_parse_id_range() {
+        assert!(matches!(
+            parse_id_range("200000-299999"),
+            Ok((200000, 299999))
+        ));
+        assert!(matches!(parse_id_range(" 5 - 5 "), Ok((5, 5))));
+
+        assert!(parse_id_range("200000").is_err());
+        assert!(parse_id_range("a-b").is_err());
+        assert!(parse_id_range("0-100").is_err());
+        assert!(parse_id_range("300-200").is_err());
+    }
+
+    #[test]
+    fn test_identifier_mapping() {
+        let user = UserIdentifier::parse("alice.proj.portal")
+            .unwrap_or_else(|e| unreachable!("Cannot parse user: {}", e));
+
+        assert_eq!(identifier_to_userid(&user), "alice.proj");
+        assert_eq!(get_primary_group_name(&user), "portal.proj");
+
+        let settings = settings();
+
+        assert_eq!(
+            user_dn(&settings, "alice.proj"),
+            "uid=alice.proj,ou=people,dc=example,dc=org"
+        );
+        assert_eq!(
+            group_dn(&settings, "portal.proj"),
+            "cn=portal.proj,ou=groups,dc=example,dc=org"
+        );
+
+        // special characters must be escaped in DNs
+        assert_eq!(
+            user_dn(&settings, "a,b"),
+            "uid=a\\2cb,ou=people,dc=example,dc=org"
+        );
+    }
+
+    #[test]
+    fn test_attributes() {
+        let group = entry(&[
+            ("CN", "portal.proj"),
+            ("gidNumber", "200001"),
+            ("memberUid", "alice.proj"),
+            ("memberUid", "bob.proj"),
+            ("description", "proj.portal | OpenPortal-managed group"),
+        ]);
+
+        // attribute names are matched case-insensitively
+        assert_eq!(get_attr(&group, "cn"), Some("portal.proj".to_owned()));
+        assert_eq!(get_attrs(&group, "memberuid").len(), 2);
+        assert_eq!(get_id(&group, "gidNumber"), Some(200001));
+        assert_eq!(get_id(&group, "cn"), None);
+        assert!(get_attrs(&group, "missing").is_empty());
+
+        let project = group_identifier(&group)
+            .unwrap_or_else(|| unreachable!("The description holds the project"));
+        assert_eq!(project.to_string(), "proj.portal");
+
+        // groups not created by this agent have no identifier
+        assert!(group_identifier(&entry(&[("description", "Some other group")])).is_none());
+        assert!(group_identifier(&entry(&[("cn", "wheel")])).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let project = ProjectIdentifier::parse("proj.portal")
+            .unwrap_or_else(|e| unreachable!("Cannot parse project: {}", e));
+
+        // expired jobs are refused before connecting
+        let expired = Utc::now() - chrono::Duration::seconds(1);
+        assert!(matches!(
+            add_project(&project, &expired).await,
+            Err(Error::Expired(_))
+        ));
+
+        // nothing can be done until the settings are initialised
+        let expires = Utc::now() + chrono::Duration::seconds(10);
+        assert!(matches!(
+            add_project(&project, &expires).await,
+            Err(Error::Call(_))
+        ));
+        assert!(matches!(
+            get_groups(&project.portal_identifier(), &expires).await,
+            Err(Error::Call(_))
+        ));
+    }
+}
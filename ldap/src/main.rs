@@ -0,0 +1,277 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+use chrono::Utc;
+
+mod ldap;
+
+use templemeads::agent::account::{process_args, run, Defaults};
+use templemeads::agent::{Peer, Type as AgentType};
+use templemeads::async_runnable;
+use templemeads::grammar::Instruction::{
+    AddProject, AddUser, GetProjectMapping, GetProjectProtection, GetProjects, GetUserMapping,
+    GetUserProtection, GetUsers, IsExistingProject, IsExistingUser, IsProtectedUser, RemoveProject,
+    RemoveUser, UpdateHomeDir,
+};
+use templemeads::grammar::UserMapping;
+use templemeads::job::{assert_not_expired, Envelope, Job};
+use templemeads::notification::default_notify_runner;
+use templemeads::protection::ProtectionPolicy;
+use templemeads::set_notify_runner;
+use templemeads::Error;
+
+///
+/// Main function for the ldap agent.
+///
+/// This agent implements the Account agent interface against a plain
+/// LDAP directory (e.g. OpenLDAP or 389-ds), for sites that do not run
+/// FreeIPA. Users are stored as posixAccount entries and projects as
+/// posixGroup entries, with membership held in memberUid.
+///
+#[tokio::main]
+async fn main() -> Result<()> {
+    // start tracing
+    templemeads::config::initialise_tracing();
+
+    // start system monitoring
+    templemeads::spawn_system_monitor();
+
+    // create the OpenPortal paddington defaults
+    let defaults: Defaults = Defaults::parse(
+        Some("ldap".to_owned()),
+        Some(
+            dirs::config_local_dir()
+                .unwrap_or(
+                    ".".parse()
+                        .expect("Could not parse fallback config directory."),
+                )
+                .join("openportal")
+                .join("ldap-config.toml"),
+        ),
+        Some("ws://localhost:8046".to_owned()),
+        Some("127.0.0.1".to_owned()),
+        Some(8046),
+        None,
+        None,
+        Some(AgentType::Account),
+    );
+
+    // now parse the command line arguments to get the service configuration
+    let config = match process_args(&defaults).await? {
+        Some(config) => config,
+        None => {
+            // Not running the service, so can safely exit
+            return Ok(());
+        }
+    };
+
+    // get the details about the LDAP server - this must be set
+    let ldap_url = config.option("ldap-url", "");
+
+    if ldap_url.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No LDAP server specified. Please set this in the ldap-url option."
+        ));
+    }
+
+    let bind_dn = config.option("ldap-bind-dn", "");
+
+    if bind_dn.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No LDAP bind DN specified. Please set this in the ldap-bind-dn option."
+        ));
+    }
+
+    let bind_password = match config.secret("ldap-bind-password") {
+        Some(password) => password,
+        None => {
+            return Err(anyhow::anyhow!(
+                "No LDAP bind password specified. Please set this in the ldap-bind-password option.",
+            ));
+        }
+    };
+
+    // the DNs below which users and groups are created, e.g.
+    // "ou=people,dc=example,dc=org" and "ou=groups,dc=example,dc=org"
+    let user_base = config.option("ldap-user-base", "");
+    let group_base = config.option("ldap-group-base", "");
+
+    if user_base.is_empty() || group_base.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No LDAP user or group base specified. Please set these in the \
+             ldap-user-base and ldap-group-base options."
+        ));
+    }
+
+    // The managed group distinguishes users created by this agent from
+    // pre-existing users in the directory. All managed users are added to it.
+    let managed_group = config.option("managed-group", "openportal");
+
+    let default_shell = config.option("default-shell", "/bin/bash");
+
+    // the inclusive range from which uid and gid numbers are allocated
+    let id_range = ldap::parse_id_range(&config.option("id-range", "200000-999999"))?;
+
+    // whether to upgrade ldap:// connections using StartTLS, and whether to
+    // accept invalid (e.g. self-signed) certificates
+    let starttls = config.option("ldap-starttls", "false").to_lowercase() == "true";
+    let allow_invalid_certs = config
+        .option("ldap-allow-invalid-certs", "false")
+        .to_lowercase()
+        == "true";
+
+    // Users and groups that must never be managed, in addition to users
+    // who are not in the managed group. Names are comma-separated and may
+    // include wildcards, e.g. "root,svc-*", while ids are comma-separated
+    // ranges, e.g. "0-999,65534".
+    let protected_users = ProtectionPolicy::parse(
+        &config.option("protected-users", "root"),
+        &config.option("protected-uids", "0-999"),
+    )?;
+    let protected_groups = ProtectionPolicy::parse(
+        &config.option("protected-groups", "root,wheel,sudo"),
+        &config.option("protected-gids", "0-999"),
+    )?;
+
+    ldap::initialise(ldap::Settings::new(
+        &ldap_url,
+        &bind_dn,
+        bind_password,
+        &user_base,
+        &group_base,
+        &managed_group,
+        &default_shell,
+        id_range,
+        starttls,
+        allow_invalid_certs,
+        protected_users,
+        protected_groups,
+    ))?;
+
+    async_runnable! {
+        ///
+        /// Runnable function that will be called when a job is received
+        /// by the agent
+        ///
+        pub async fn ldap_runner(envelope: Envelope) -> Result<Job, templemeads::Error>
+        {
+            let job = envelope.job();
+            let sender = envelope.sender();
+            let me = envelope.recipient();
+
+            match job.instruction() {
+                GetProjects(portal) => {
+                    let mappings = ldap::get_groups(&portal, job.expires()).await?;
+                    job.completed(mappings)
+                },
+                AddProject(project) => {
+                    let mapping = ldap::add_project(&project, job.expires()).await?;
+                    job.completed(mapping)
+                },
+                RemoveProject(project) => {
+                    let mapping = ldap::remove_project(&project, job.expires()).await?;
+                    job.completed(mapping)
+                },
+                GetUsers(project) => {
+                    let mappings = ldap::get_users(&project, job.expires()).await?;
+                    job.completed(mappings)
+                },
+                AddUser(user) => {
+                    let local_user = ldap::identifier_to_userid(&user);
+                    let local_group = ldap::get_primary_group_name(&user);
+                    let mapping = UserMapping::new(&user, &local_user, &local_group)?;
+                    let homedir = get_home_dir(me.name(), &sender, &mapping, job.expires()).await?;
+                    let mapping = ldap::add_user(&user, &Some(homedir), job.expires()).await?;
+                    job.completed(mapping)
+                },
+                RemoveUser(user) => {
+                    let mapping = ldap::remove_user(&user, job.expires()).await?;
+                    job.completed(mapping)
+                },
+                UpdateHomeDir(user, homedir) => {
+                    ldap::update_homedir(&user, &homedir, job.expires()).await?;
+                    job.completed(homedir)
+                },
+                GetProjectMapping(project) => {
+                    let mapping = ldap::get_project_mapping(&project, job.expires()).await?;
+                    job.completed(mapping)
+                },
+                GetUserMapping(user) => {
+                    let mapping = ldap::get_user_mapping(&user, job.expires()).await?;
+                    job.completed(mapping)
+                },
+                IsProtectedUser(user) => {
+                    let is_protected = ldap::is_protected_user(&user, job.expires()).await?;
+                    job.completed(is_protected)
+                },
+                GetUserProtection(user) => {
+                    let status = ldap::get_user_protection(&user, job.expires()).await?;
+                    job.completed(status)
+                },
+                GetProjectProtection(project) => {
+                    let status = ldap::get_project_protection(&project, job.expires()).await?;
+                    job.completed(status)
+                },
+                IsExistingUser(user) => {
+                    let exists = ldap::is_existing_user(&user, job.expires()).await?;
+                    job.completed(exists)
+                },
+                IsExistingProject(project) => {
+                    let exists = ldap::is_existing_project(&project, job.expires()).await?;
+                    job.completed(exists)
+                },
+                _ => {
+                    Err(Error::InvalidInstruction(
+                        format!("Invalid instruction: {}. LDAP only supports account management instructions", job.instruction()),
+                    ))
+                }
+            }
+        }
+    }
+
+    set_notify_runner(default_notify_runner).await?;
+    run(config, ldap_runner).await?;
+
+    Ok(())
+}
+
+///
+/// Ask the instance agent for the home directory that should be assigned
+/// to this user. This follows the same protocol used by op-freeipa.
+///
+async fn get_home_dir(
+    me: &str,
+    sender: &Peer,
+    mapping: &UserMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<String, Error> {
+    assert_not_expired(expires)?;
+
+    let job = Job::parse(
+        &format!("{}.{} get_local_home_dir {}", me, sender.name(), mapping),
+        false,
+    )?;
+
+    let job = job.put(sender).await?;
+
+    assert_not_expired(expires)?;
+
+    let mut home_dir = job.wait().await?.result::<String>()?;
+
+    assert_not_expired(expires)?;
+
+    while home_dir.is_none() {
+        let job = job.wait().await?;
+        assert_not_expired(expires)?;
+        home_dir = job.result::<String>()?;
+    }
+
+    if let Some(homedir) = home_dir {
+        Ok(homedir)
+    } else {
+        Err(Error::InvalidInstruction(
+            "No home directory found".to_string(),
+        ))
+    }
+}
@@ -0,0 +1,193 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+
+use templemeads::agent;
+use templemeads::agent::scheduler::{process_args, run, Defaults};
+use templemeads::agent::Type as AgentType;
+use templemeads::async_runnable;
+use templemeads::grammar::Instruction;
+use templemeads::grammar::Instruction::{
+    AddLocalUser, GetLocalUsageReport, RemoveLocalProject, RemoveLocalUser,
+};
+use templemeads::job::{Envelope, Job};
+use templemeads::notification::default_notify_runner;
+use templemeads::set_notify_runner;
+use templemeads::usagereport::ProjectUsageReport;
+use templemeads::Error;
+
+mod servers;
+mod usage;
+
+/// Seconds to wait for the scheduler agent to be available
+const AGENT_WAIT_TIME: u64 = 5;
+
+///
+/// Main function for the licenses scheduler agent
+///
+/// This agent sits between a cluster and its scheduler agent. It passes
+/// every scheduler instruction on to the scheduler agent, and regularly
+/// polls the site's FlexLM and RLM license servers to see which users
+/// have checked out which license features. The license-seconds used by
+/// the members of each project are added to the project's usage report
+/// as "license:<feature>" components.
+///
+#[tokio::main]
+async fn main() -> Result<()> {
+    // start tracing
+    templemeads::config::initialise_tracing();
+
+    // start system monitoring
+    templemeads::spawn_system_monitor();
+
+    // create the OpenPortal paddington defaults
+    let defaults: Defaults = Defaults::parse(
+        Some("licenses".to_owned()),
+        Some(
+            dirs::config_local_dir()
+                .unwrap_or(
+                    ".".parse()
+                        .expect("Could not parse fallback config directory."),
+                )
+                .join("openportal")
+                .join("licenses-config.toml"),
+        ),
+        Some("ws://localhost:8056".to_owned()),
+        Some("127.0.0.1".to_owned()),
+        Some(8056),
+        None,
+        None,
+        Some(AgentType::Scheduler),
+    );
+
+    // now parse the command line arguments to get the service configuration
+    let config = match process_args(&defaults).await? {
+        Some(config) => config,
+        None => {
+            // Not running the service, so can safely exit
+            return Ok(());
+        }
+    };
+
+    let servers = servers::parse_servers(&config.option("servers", ""))?;
+
+    if servers.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No license servers specified. Please set this in the servers option."
+        ));
+    }
+
+    usage::initialise(usage::Settings {
+        servers,
+        lmstat: config.option("lmstat-command", "lmutil lmstat"),
+        rlmstat: config.option("rlmstat-command", "rlmutil rlmstat"),
+        features: config.option("features", ""),
+        usage_file: config.option("usage-file", ""),
+        retention_days: config
+            .option("retention-days", "400")
+            .parse()
+            .unwrap_or(400),
+    })
+    .await?;
+
+    // how often (in seconds) the license servers are polled
+    let poll_interval: u64 = config
+        .option("poll-interval", "300")
+        .parse()
+        .unwrap_or(300)
+        .max(30);
+
+    usage::spawn_sampler(poll_interval);
+
+    async_runnable! {
+        ///
+        /// Runnable function that will be called when a job is received
+        /// by the agent
+        ///
+        pub async fn licenses_runner(envelope: Envelope) -> Result<Job, templemeads::Error>
+        {
+            let me = envelope.recipient();
+            let job = envelope.job();
+
+            match job.instruction() {
+                RemoveLocalProject(mapping) => {
+                    let result = forward(me.name(), job.instruction()).await?;
+
+                    if !result.is_error() {
+                        usage::remove_group(&mapping).await;
+                    }
+
+                    job.copy_result_from(&result)
+                },
+                AddLocalUser(mapping) => {
+                    let result = forward(me.name(), job.instruction()).await?;
+
+                    if !result.is_error() {
+                        usage::add_member(&mapping).await;
+                    }
+
+                    job.copy_result_from(&result)
+                },
+                RemoveLocalUser(mapping) => {
+                    let result = forward(me.name(), job.instruction()).await?;
+
+                    if !result.is_error() {
+                        usage::remove_member(&mapping).await;
+                    }
+
+                    job.copy_result_from(&result)
+                },
+                GetLocalUsageReport(mapping, dates) => {
+                    let result = forward(me.name(), job.instruction()).await?;
+
+                    if result.is_error() {
+                        return job.copy_result_from(&result);
+                    }
+
+                    let mut report = result
+                        .result::<ProjectUsageReport>()?
+                        .unwrap_or_else(|| ProjectUsageReport::new(mapping.project()));
+
+                    usage::add_licenses(&mapping, &dates, &mut report).await?;
+
+                    job.completed(report)
+                },
+                instruction => {
+                    // everything else is handled by the scheduler agent
+                    job.copy_result_from(&forward(me.name(), instruction).await?)
+                }
+            }
+        }
+    }
+
+    set_notify_runner(default_notify_runner).await?;
+    run(config, licenses_runner).await?;
+
+    Ok(())
+}
+
+///
+/// Pass the instruction on to the scheduler agent, returning the
+/// finished job
+///
+async fn forward(me: &str, instruction: Instruction) -> Result<Job, Error> {
+    match agent::scheduler(AGENT_WAIT_TIME).await {
+        Some(scheduler) => {
+            let job = Job::parse(
+                &format!("{}.{} {}", me, scheduler.name(), instruction),
+                false,
+            )?
+            .put(&scheduler)
+            .await?;
+
+            job.wait().await
+        }
+        None => {
+            tracing::error!("No scheduler agent found");
+            Err(Error::MissingAgent(
+                "Cannot run the job because there is no scheduler agent".to_string(),
+            ))
+        }
+    }
+}
@@ -0,0 +1,401 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use templemeads::exec;
+use templemeads::Error;
+
+///
+/// The license managers whose servers can be polled
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum Kind {
+    FlexLm,
+    Rlm,
+}
+
+///
+/// A license server, e.g. "27000@licserver" for FlexLM
+///
+#[derive(Debug, Clone)]
+pub struct Server {
+    kind: Kind,
+    address: String,
+}
+
+///
+/// A license feature checked out by a user
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkout {
+    pub feature: String,
+    pub user: String,
+    pub count: u64,
+}
+
+impl std::fmt::Display for Server {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.kind {
+            Kind::FlexLm => write!(f, "flexlm:{}", self.address),
+            Kind::Rlm => write!(f, "rlm:{}", self.address),
+        }
+    }
+}
+
+///
+/// Parse the comma-separated list of license servers, each written as
+/// "flexlm:<port>@<host>" or "rlm:<port>@<host>"
+///
+pub fn parse_servers(servers: &str) -> Result<Vec<Server>, Error> {
+    servers
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            let (kind, address) = s.split_once(':').ok_or_else(|| {
+                Error::Misconfigured(format!(
+                    "Invalid license server '{}'. It should be flexlm:<port>@<host> or rlm:<port>@<host>",
+                    s
+                ))
+            })?;
+
+            let kind = match kind.trim().to_lowercase().as_str() {
+                "flexlm" | "flexnet" | "lmgrd" => Kind::FlexLm,
+                "rlm" => Kind::Rlm,
+                other => {
+                    return Err(Error::Misconfigured(format!(
+                        "Unknown license manager '{}'. This must be flexlm or rlm",
+                        other
+                    )))
+                }
+            };
+
+            let address = address.trim();
+
+            if address.is_empty() || address.contains(char::is_whitespace) {
+                return Err(Error::Misconfigured(format!(
+                    "Invalid license server address '{}'",
+                    address
+                )));
+            }
+
+            Ok(Server {
+                kind,
+                address: address.to_owned(),
+            })
+        })
+        .collect()
+}
+
+///
+/// Parse the output of `lmstat -a`. Each feature starts with a
+/// "Users of <feature>:" line, which is followed by one line per
+/// checkout, e.g.
+///
+///   alice node01 /dev/tty (v41) (licserver/27000 1234), start Mon 10/14 9:00, 2 licenses
+///
+pub fn parse_lmstat(output: &str) -> Vec<Checkout> {
+    let mut checkouts = Vec::new();
+    let mut feature: Option<String> = None;
+
+    for line in output.lines() {
+        let line = line.trim();
+
+        if let Some(rest) = line.strip_prefix("Users of ") {
+            feature = rest
+                .split_once(':')
+                .map(|(f, _)| f.trim().to_owned())
+                .filter(|f| !f.is_empty());
+            continue;
+        }
+
+        let feature = match &feature {
+            Some(feature) => feature,
+            None => continue,
+        };
+
+        if !line.contains(", start ") {
+            continue;
+        }
+
+        let user = match line.split_whitespace().next() {
+            Some(user) => user.to_owned(),
+            None => continue,
+        };
+
+        // the number of licenses is only given if it is more than one
+        let count = line
+            .rsplit_once(", ")
+            .and_then(|(_, last)| last.strip_suffix(" licenses"))
+            .and_then(|n| n.trim().parse::<u64>().ok())
+            .unwrap_or(1);
+
+        checkouts.push(Checkout {
+            feature: feature.clone(),
+            user,
+            count,
+        });
+    }
+
+    checkouts
+}
+
+///
+/// Parse the output of `rlmstat -a`, which has one line per checkout, e.g.
+///
+///   matlab v2024.0: alice@node01 2/0 at 10/14 09:00  (handle: 81)
+///
+pub fn parse_rlmstat(output: &str) -> Vec<Checkout> {
+    let mut checkouts = Vec::new();
+
+    for line in output.lines() {
+        let (product, holder) = match line.trim().split_once(": ") {
+            Some(parts) => parts,
+            None => continue,
+        };
+
+        let mut product = product.split_whitespace();
+        let mut holder = holder.split_whitespace();
+
+        let (feature, version) = match (product.next(), product.next()) {
+            (Some(feature), Some(version)) if version.starts_with('v') => (feature, version),
+            _ => continue,
+        };
+
+        let (user, count) = match (holder.next(), holder.next()) {
+            (Some(user_host), Some(count)) => {
+                match (user_host.split_once('@'), count.split_once('/')) {
+                    (Some((user, _)), Some((count, _))) => (user, count),
+                    _ => continue,
+                }
+            }
+            _ => continue,
+        };
+
+        let count = match count.parse::<u64>() {
+            Ok(count) => count,
+            Err(_) => continue,
+        };
+
+        tracing::trace!("{} {} checked out by {}", feature, version, user);
+
+        checkouts.push(Checkout {
+            feature: feature.to_owned(),
+            user: user.to_owned(),
+            count,
+        });
+    }
+
+    checkouts
+}
+
+impl Server {
+    ///
+    /// Return the licenses currently checked out from this server, using
+    /// the passed lmstat or rlmstat command
+    ///
+    pub async fn get_checkouts(
+        &self,
+        lmstat: &[String],
+        rlmstat: &[String],
+    ) -> Result<Vec<Checkout>, Error> {
+        let command = match self.kind {
+            Kind::FlexLm => lmstat,
+            Kind::Rlm => rlmstat,
+        };
+
+        if command.is_empty() {
+            return Err(Error::Misconfigured(format!(
+                "No command to query license server {}",
+                self
+            )));
+        }
+
+        let output = exec::Command::from_parts(command)
+            .args(["-a", "-c", self.address.as_str()])
+            .run()
+            .await
+            .map_err(|failure| {
+                Error::Call(format!(
+                    "Could not query license server {}: {}",
+                    self, failure
+                ))
+            })?;
+
+        Ok(match self.kind {
+            Kind::FlexLm => parse_lmstat(&output),
+            Kind::Rlm => parse_rlmstat(&output),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) const LMSTAT: &str = "lmstat - Copyright (c) 1989-2023 Flexera. All Rights Reserved.
+Flexible License Manager status on Mon 10/14/2026 09:30
+
+Users of features served by licserver:
+Users of matlab:  (Total of 10 licenses issued;  Total of 3 licenses in use)
+
+  \"matlab\" v41, vendor: MLM, expiry: 31-dec-2026
+  floating license
+
+    alice node01 /dev/tty (v41) (licserver/27000 1234), start Mon 10/14 9:00, 2 licenses
+    bob node02 /dev/tty (v41) (licserver/27000 1235), start Mon 10/14 9:10
+
+Users of simulink:  (Total of 5 licenses issued;  Total of 0 licenses in use)
+
+Users of abaqus:  (Total of 20 licenses issued;  Total of 5 licenses in use)
+
+    carol node03 /dev/tty (v2024) (licserver/27000 1301), start Mon 10/14 8:00, 5 licenses
+";
+
+    pub(crate) const RLMSTAT: &str = "Setting license file path to 5053@rlmserver
+rlmutil v15.1
+License usage status on rlmserver (port 5053)
+
+ISV servers:
+   foundry v15.1: alice@node01 2/0 at 10/14 09:00  (handle: 81)
+   nuke v13.0: bob@node02 1/0 at 10/14 09:05  (handle: 82)
+   broken v1.0: nobody 1/0 at 10/14 09:05
+   other line without a checkout
+";
+
+    #[test]
+    fn test_parse_servers() {
+        let servers = parse_servers(" flexlm:27000@licserver, RLM:5053@rlmserver ,,lmgrd:1@a")
+            .unwrap_or_else(|e| unreachable!("Cannot parse servers: {}", e));
+
+        assert_eq!(servers.len(), 3);
+        assert_eq!(servers[0].kind, Kind::FlexLm);
+        assert_eq!(servers[0].to_string(), "flexlm:27000@licserver");
+        assert_eq!(servers[1].kind, Kind::Rlm);
+        assert_eq!(servers[1].to_string(), "rlm:5053@rlmserver");
+        assert_eq!(servers[2].kind, Kind::FlexLm);
+
+        assert!(parse_servers("").is_ok_and(|s| s.is_empty()));
+
+        for invalid in ["27000@licserver", "dsls:1@a", "flexlm:", "rlm:1@a b"] {
+            assert!(matches!(
+                parse_servers(invalid),
+                Err(Error::Misconfigured(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_parse_lmstat() {
+        assert_eq!(
+            parse_lmstat(LMSTAT),
+            vec![
+                Checkout {
+                    feature: "matlab".to_owned(),
+                    user: "alice".to_owned(),
+                    count: 2
+                },
+                Checkout {
+                    feature: "matlab".to_owned(),
+                    user: "bob".to_owned(),
+                    count: 1
+                },
+                Checkout {
+                    feature: "abaqus".to_owned(),
+                    user: "carol".to_owned(),
+                    count: 5
+                },
+            ]
+        );
+
+        assert!(parse_lmstat("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_rlmstat() {
+        assert_eq!(
+            parse_rlmstat(RLMSTAT),
+            vec![
+                Checkout {
+                    feature: "foundry".to_owned(),
+                    user: "alice".to_owned(),
+                    count: 2
+                },
+                Checkout {
+                    feature: "nuke".to_owned(),
+                    user: "bob".to_owned(),
+                    count: 1
+                },
+            ]
+        );
+
+        assert!(parse_rlmstat("").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_checkouts() {
+        let dir = std::env::temp_dir().join(format!("op-licenses-servers-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)
+            .unwrap_or_else(|e| unreachable!("Cannot create {}: {}", dir.display(), e));
+
+        // fake commands that log their arguments and print the sample
+        // output, failing for servers that are "down"
+        for (name, output) in [("lmstat", LMSTAT), ("rlmstat", RLMSTAT)] {
+            std::fs::write(dir.join(format!("{}.out", name)), output)
+                .unwrap_or_else(|e| unreachable!("Cannot write output: {}", e));
+
+            std::fs::write(
+                dir.join(format!("{}.sh", name)),
+                format!(
+                    "echo \"$*\" >> {dir}/{name}.log\n\
+                     case \"$*\" in *down*) echo \"cannot connect\" >&2; exit 1;; esac\n\
+                     cat {dir}/{name}.out\n",
+                    dir = dir.display(),
+                    name = name
+                ),
+            )
+            .unwrap_or_else(|e| unreachable!("Cannot write command: {}", e));
+        }
+
+        let command = |name: &str| {
+            vec![
+                "sh".to_owned(),
+                dir.join(format!("{}.sh", name)).display().to_string(),
+            ]
+        };
+
+        let servers = parse_servers("flexlm:27000@licserver,rlm:5053@rlmserver,flexlm:1@down")
+            .unwrap_or_else(|e| unreachable!("Cannot parse servers: {}", e));
+
+        assert!(servers[0]
+            .get_checkouts(&command("lmstat"), &command("rlmstat"))
+            .await
+            .is_ok_and(|c| c.len() == 3 && c[0].feature == "matlab"));
+
+        assert!(servers[1]
+            .get_checkouts(&command("lmstat"), &command("rlmstat"))
+            .await
+            .is_ok_and(|c| c.len() == 2 && c[0].feature == "foundry"));
+
+        assert!(matches!(
+            servers[2]
+                .get_checkouts(&command("lmstat"), &command("rlmstat"))
+                .await,
+            Err(Error::Call(_))
+        ));
+
+        // a server cannot be polled without the command of its manager
+        assert!(matches!(
+            servers[1].get_checkouts(&command("lmstat"), &[]).await,
+            Err(Error::Misconfigured(_))
+        ));
+
+        let logged = |name: &str| {
+            std::fs::read_to_string(dir.join(format!("{}.log", name))).unwrap_or_default()
+        };
+
+        assert_eq!(logged("lmstat"), "-a -c 27000@licserver\n-a -c 1@down\n");
+        assert_eq!(logged("rlmstat"), "-a -c 5053@rlmserver\n");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
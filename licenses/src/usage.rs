@@ -0,0 +1,680 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use templemeads::grammar::{Date, DateRange, ProjectMapping, UserMapping};
+use templemeads::usagereport::{license_component, ProjectUsageReport, Usage};
+use templemeads::Error;
+use tokio::sync::RwLock;
+
+use crate::servers::{Checkout, Server};
+
+/// The license-seconds of each feature used by each user in a local group
+type GroupUsage = HashMap<String, HashMap<String, f64>>;
+
+/// The local groups (projects) that each local user is a member of
+type Members = HashMap<String, HashSet<String>>;
+
+/// The license usage of each local group on each day
+type DailyUsage = HashMap<Date, HashMap<String, GroupUsage>>;
+
+#[derive(Debug, Default)]
+struct Database {
+    servers: Vec<Server>,
+    lmstat: Vec<String>,
+    rlmstat: Vec<String>,
+    /// The features to record, or empty to record all features
+    features: HashSet<String>,
+    members: Members,
+    usage: DailyUsage,
+    usage_file: Option<PathBuf>,
+    retention_days: u64,
+}
+
+static CACHE: Lazy<RwLock<Database>> = Lazy::new(|| RwLock::new(Database::default()));
+
+///
+/// Settings for how license usage is measured and kept
+///
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub servers: Vec<Server>,
+    pub lmstat: String,
+    pub rlmstat: String,
+    pub features: String,
+    pub usage_file: String,
+    pub retention_days: u64,
+}
+
+fn parse_cmd(s: &str) -> Vec<String> {
+    s.split_whitespace().map(|p| p.to_owned()).collect()
+}
+
+///
+/// Set how usage is measured and (optionally) the file in which it is
+/// saved, loading any usage that was saved previously
+///
+pub async fn initialise(settings: Settings) -> Result<(), Error> {
+    let mut cache = CACHE.write().await;
+    cache.servers = settings.servers;
+    cache.lmstat = parse_cmd(&settings.lmstat);
+    cache.rlmstat = parse_cmd(&settings.rlmstat);
+    cache.features = settings
+        .features
+        .split(',')
+        .map(|f| f.trim().to_owned())
+        .filter(|f| !f.is_empty())
+        .collect();
+    cache.retention_days = settings.retention_days;
+
+    let usage_file = settings.usage_file.trim();
+
+    if usage_file.is_empty() {
+        tracing::warn!("No usage-file set - license usage will be lost when the agent restarts");
+        return Ok(());
+    }
+
+    let usage_file = PathBuf::from(usage_file);
+
+    if let Some(parent) = usage_file.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| {
+            Error::Misconfigured(format!(
+                "Could not create directory {}: {}",
+                parent.display(),
+                e
+            ))
+        })?;
+    }
+
+    if usage_file.exists() {
+        match load(&usage_file) {
+            Ok((members, usage)) => {
+                cache.members = members;
+                cache.usage = usage;
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Could not load license usage from {}: {}",
+                    usage_file.display(),
+                    e
+                );
+            }
+        }
+    }
+
+    cache.usage_file = Some(usage_file);
+
+    Ok(())
+}
+
+fn load(path: &PathBuf) -> Result<(Members, DailyUsage), Error> {
+    let contents = std::fs::read_to_string(path)?;
+
+    let value: Value = serde_json::from_str(&contents)
+        .map_err(|e| Error::Parse(format!("Invalid usage file: {}", e)))?;
+
+    let mut members = Members::new();
+
+    for (user, groups) in value["members"].as_object().cloned().unwrap_or_default() {
+        members.insert(
+            user,
+            groups
+                .as_array()
+                .cloned()
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|g| g.as_str().map(|g| g.to_owned()))
+                .collect(),
+        );
+    }
+
+    let mut usage = DailyUsage::new();
+
+    for (day, groups) in value["usage"].as_object().cloned().unwrap_or_default() {
+        let groups = groups
+            .as_object()
+            .cloned()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(group, users)| {
+                let users = users
+                    .as_object()
+                    .cloned()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(user, features)| {
+                        let features = features
+                            .as_object()
+                            .cloned()
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(|(feature, seconds)| (feature, seconds.as_f64().unwrap_or(0.0)))
+                            .collect();
+                        (user, features)
+                    })
+                    .collect();
+                (group, users)
+            })
+            .collect();
+
+        usage.insert(Date::parse(&day)?, groups);
+    }
+
+    Ok((members, usage))
+}
+
+fn save(path: &PathBuf, members: &Members, usage: &DailyUsage) -> Result<(), Error> {
+    let members: serde_json::Map<String, Value> = members
+        .iter()
+        .map(|(user, groups)| {
+            let mut groups: Vec<&String> = groups.iter().collect();
+            groups.sort();
+            (user.clone(), json!(groups))
+        })
+        .collect();
+
+    let usage: serde_json::Map<String, Value> = usage
+        .iter()
+        .map(|(day, groups)| (day.to_string(), json!(groups)))
+        .collect();
+
+    // write to a temporary file first, so that a crash cannot leave
+    // a partially written file behind
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(
+        &tmp,
+        json!({
+            "members": members,
+            "usage": usage,
+        })
+        .to_string(),
+    )?;
+    std::fs::rename(&tmp, path)?;
+
+    Ok(())
+}
+
+fn save_cache(cache: &Database) {
+    if let Some(path) = &cache.usage_file {
+        if let Err(e) = save(path, &cache.members, &cache.usage) {
+            tracing::warn!("Could not save license usage to {}: {}", path.display(), e);
+        }
+    }
+}
+
+///
+/// Record that the local user is a member of the local group
+///
+pub async fn add_member(mapping: &UserMapping) {
+    let mut cache = CACHE.write().await;
+
+    let added = cache
+        .members
+        .entry(mapping.local_user().to_owned())
+        .or_default()
+        .insert(mapping.local_group().to_owned());
+
+    if added {
+        save_cache(&cache);
+    }
+}
+
+///
+/// Record that the local user is no longer a member of the local group
+///
+pub async fn remove_member(mapping: &UserMapping) {
+    let mut cache = CACHE.write().await;
+
+    let removed = match cache.members.get_mut(mapping.local_user()) {
+        Some(groups) => {
+            let removed = groups.remove(mapping.local_group());
+
+            if groups.is_empty() {
+                cache.members.remove(mapping.local_user());
+            }
+
+            removed
+        }
+        None => false,
+    };
+
+    if removed {
+        save_cache(&cache);
+    }
+}
+
+///
+/// Record that the local group has been removed, so that no more license
+/// usage is attributed to it
+///
+pub async fn remove_group(mapping: &ProjectMapping) {
+    let mut cache = CACHE.write().await;
+    let mut changed = false;
+
+    cache.members.retain(|_, groups| {
+        changed |= groups.remove(mapping.local_group());
+        !groups.is_empty()
+    });
+
+    if changed {
+        save_cache(&cache);
+    }
+}
+
+///
+/// Add the checkouts, held for `interval` seconds, to today's usage.
+/// A user's checkouts are split equally between the groups that they
+/// are a member of, as license managers do not know which project a
+/// license was used for.
+///
+fn add_checkouts(cache: &mut Database, checkouts: &[Checkout], interval: u64) {
+    let today = Date::today();
+
+    for checkout in checkouts {
+        if !cache.features.is_empty() && !cache.features.contains(&checkout.feature) {
+            continue;
+        }
+
+        let groups: Vec<String> = match cache.members.get(&checkout.user) {
+            Some(groups) if !groups.is_empty() => groups.iter().cloned().collect(),
+            _ => {
+                tracing::debug!(
+                    "Ignoring {} license(s) of {} held by {}, who is not in any project",
+                    checkout.count,
+                    checkout.feature,
+                    checkout.user
+                );
+                continue;
+            }
+        };
+
+        let seconds = (interval * checkout.count) as f64 / groups.len() as f64;
+
+        for group in groups {
+            *cache
+                .usage
+                .entry(today.clone())
+                .or_default()
+                .entry(group)
+                .or_default()
+                .entry(checkout.user.clone())
+                .or_default()
+                .entry(checkout.feature.clone())
+                .or_default() += seconds;
+        }
+    }
+
+    // forget usage that is older than the retention period
+    if cache.retention_days > 0 {
+        let oldest = Date::from_chrono(
+            &(*today.date() - chrono::Duration::days(cache.retention_days as i64)),
+        );
+        cache.usage.retain(|day, _| *day >= oldest);
+    }
+}
+
+///
+/// Poll every license server, and add the licenses that are checked
+/// out to today's usage, as if they had been held for `interval` seconds
+///
+async fn sample(interval: u64) -> Result<(), Error> {
+    let (servers, lmstat, rlmstat) = {
+        let cache = CACHE.read().await;
+        (
+            cache.servers.clone(),
+            cache.lmstat.clone(),
+            cache.rlmstat.clone(),
+        )
+    };
+
+    let mut checkouts = Vec::new();
+
+    for server in servers {
+        match server.get_checkouts(&lmstat, &rlmstat).await {
+            Ok(c) => checkouts.extend(c),
+            Err(e) => {
+                tracing::warn!("Could not poll license server {}: {}", server, e);
+            }
+        }
+    }
+
+    let mut cache = CACHE.write().await;
+    add_checkouts(&mut cache, &checkouts, interval);
+    save_cache(&cache);
+
+    Ok(())
+}
+
+///
+/// Spawn a background task that polls the license servers every
+/// `interval` seconds
+///
+pub fn spawn_sampler(interval: u64) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(tokio::time::Duration::from_secs(interval));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        // the first tick completes immediately, and there is nothing
+        // to account for before the agent started
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+
+            if let Err(e) = sample(interval).await {
+                tracing::warn!("Could not sample license usage: {}", e);
+            }
+        }
+    });
+}
+
+///
+/// Add the license usage of the project to the scheduler's usage report,
+/// as a "license:<feature>" component of each day. The users in the
+/// report are also recorded as members of the project, so that usage is
+/// attributed correctly for projects created before the agent started.
+///
+pub async fn add_licenses(
+    mapping: &ProjectMapping,
+    dates: &DateRange,
+    report: &mut ProjectUsageReport,
+) -> Result<(), Error> {
+    let today = Date::today();
+    let group = mapping.local_group();
+
+    let mut cache = CACHE.write().await;
+    let mut learned = false;
+
+    for day in dates.days().into_iter().filter(|day| *day <= today) {
+        // only days that the scheduler reported are annotated, so that a
+        // missing day is not mistaken for a complete one
+        let mut daily = match report
+            .get_report(&day)
+            .daily_reports(false)
+            .into_iter()
+            .next()
+        {
+            Some(daily) => daily,
+            None => continue,
+        };
+
+        for user in daily.local_users() {
+            learned |= cache
+                .members
+                .entry(user)
+                .or_default()
+                .insert(group.to_owned());
+        }
+
+        if let Some(users) = cache.usage.get(&day).and_then(|groups| groups.get(group)) {
+            for (user, features) in users {
+                for (feature, seconds) in features {
+                    daily.set_component_usage(
+                        &license_component(feature),
+                        user,
+                        Usage::new(seconds.round() as u64),
+                    );
+                }
+            }
+        }
+
+        report.set_report(&day, &daily);
+    }
+
+    if learned {
+        save_cache(&cache);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::servers::parse_servers;
+    use crate::servers::tests::LMSTAT;
+    use templemeads::grammar::{ProjectIdentifier, UserIdentifier};
+    use templemeads::usagereport::DailyProjectUsageReport;
+
+    fn checkout(feature: &str, user: &str, count: u64) -> Checkout {
+        Checkout {
+            feature: feature.to_owned(),
+            user: user.to_owned(),
+            count,
+        }
+    }
+
+    fn project(name: &str) -> ProjectMapping {
+        ProjectMapping::new(
+            &ProjectIdentifier::parse(&format!("{}.portal", name))
+                .unwrap_or_else(|e| unreachable!("Cannot parse project: {}", e)),
+            name,
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e))
+    }
+
+    fn user(name: &str, project: &str) -> UserMapping {
+        UserMapping::new(
+            &UserIdentifier::parse(&format!("{}.{}.portal", name, project))
+                .unwrap_or_else(|e| unreachable!("Cannot parse user: {}", e)),
+            name,
+            project,
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e))
+    }
+
+    fn seconds(usage: &DailyUsage, day: &Date, group: &str, user: &str, feature: &str) -> f64 {
+        usage
+            .get(day)
+            .and_then(|groups| groups.get(group))
+            .and_then(|users| users.get(user))
+            .and_then(|features| features.get(feature))
+            .cloned()
+            .unwrap_or(0.0)
+    }
+
+    #[test]
+    fn test_add_checkouts() {
+        let today = Date::today();
+
+        let mut cache = Database {
+            retention_days: 7,
+            ..Default::default()
+        };
+
+        cache.members.insert(
+            "alice".to_owned(),
+            HashSet::from(["proj".to_owned(), "other".to_owned()]),
+        );
+        cache
+            .members
+            .insert("bob".to_owned(), HashSet::from(["proj".to_owned()]));
+
+        // an old day that is outside the retention period
+        let old = Date::from_chrono(&(*today.date() - chrono::Duration::days(30)));
+        cache.usage.insert(old.clone(), HashMap::new());
+
+        add_checkouts(
+            &mut cache,
+            &[
+                checkout("matlab", "alice", 2),
+                checkout("matlab", "bob", 1),
+                checkout("matlab", "nobody", 4),
+            ],
+            60,
+        );
+
+        // a user's usage is split between their projects
+        assert_eq!(
+            seconds(&cache.usage, &today, "proj", "alice", "matlab"),
+            60.0
+        );
+        assert_eq!(
+            seconds(&cache.usage, &today, "other", "alice", "matlab"),
+            60.0
+        );
+        assert_eq!(seconds(&cache.usage, &today, "proj", "bob", "matlab"), 60.0);
+        assert!(!cache.usage.contains_key(&old));
+
+        let users = cache
+            .usage
+            .get(&today)
+            .map(|groups| groups.values().flat_map(|u| u.keys()).count())
+            .unwrap_or_default();
+        assert_eq!(users, 3);
+
+        // usage accumulates, and only the recorded features are kept
+        cache.features = HashSet::from(["abaqus".to_owned()]);
+
+        add_checkouts(
+            &mut cache,
+            &[checkout("matlab", "bob", 1), checkout("abaqus", "bob", 3)],
+            10,
+        );
+
+        assert_eq!(seconds(&cache.usage, &today, "proj", "bob", "matlab"), 60.0);
+        assert_eq!(seconds(&cache.usage, &today, "proj", "bob", "abaqus"), 30.0);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("op-licenses-save-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)
+            .unwrap_or_else(|e| unreachable!("Cannot create {}: {}", dir.display(), e));
+
+        let path = dir.join("usage.json");
+        let today = Date::today();
+
+        let mut cache = Database::default();
+        cache.members.insert(
+            "alice".to_owned(),
+            HashSet::from(["proj".to_owned(), "other".to_owned()]),
+        );
+
+        add_checkouts(&mut cache, &[checkout("matlab", "alice", 3)], 100);
+
+        save(&path, &cache.members, &cache.usage)
+            .unwrap_or_else(|e| unreachable!("Cannot save: {}", e));
+
+        assert!(!path.with_extension("json.tmp").exists());
+
+        let (members, usage) = load(&path).unwrap_or_else(|e| unreachable!("Cannot load: {}", e));
+
+        assert_eq!(members, cache.members);
+        assert_eq!(seconds(&usage, &today, "proj", "alice", "matlab"), 150.0);
+        assert_eq!(seconds(&usage, &today, "other", "alice", "matlab"), 150.0);
+
+        std::fs::write(&path, "{not json").unwrap_or_else(|e| unreachable!("Cannot write: {}", e));
+        assert!(matches!(load(&path), Err(Error::Parse(_))));
+
+        std::fs::write(&path, r#"{"usage": {"yesterday": {}}}"#)
+            .unwrap_or_else(|e| unreachable!("Cannot write: {}", e));
+        assert!(load(&path).is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_usage() {
+        let dir = std::env::temp_dir().join(format!("op-licenses-usage-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)
+            .unwrap_or_else(|e| unreachable!("Cannot create {}: {}", dir.display(), e));
+
+        std::fs::write(dir.join("lmstat.out"), LMSTAT)
+            .unwrap_or_else(|e| unreachable!("Cannot write output: {}", e));
+
+        std::fs::write(
+            dir.join("lmstat.sh"),
+            format!(
+                "case \"$*\" in *down*) exit 1;; esac\ncat {}\n",
+                dir.join("lmstat.out").display()
+            ),
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot write command: {}", e));
+
+        // bob was a member of the project before the agent restarted
+        let usage_file = dir.join("data").join("usage.json");
+        std::fs::create_dir_all(dir.join("data"))
+            .unwrap_or_else(|e| unreachable!("Cannot create data: {}", e));
+        std::fs::write(
+            &usage_file,
+            r#"{"members": {"bob": ["proj"]}, "usage": {}}"#,
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot write usage: {}", e));
+
+        initialise(Settings {
+            servers: parse_servers("flexlm:27000@licserver,flexlm:1@down")
+                .unwrap_or_else(|e| unreachable!("Cannot parse servers: {}", e)),
+            lmstat: format!("sh {}", dir.join("lmstat.sh").display()),
+            rlmstat: String::new(),
+            features: " matlab , ".to_owned(),
+            usage_file: usage_file.display().to_string(),
+            retention_days: 30,
+        })
+        .await
+        .unwrap_or_else(|e| unreachable!("Cannot initialise: {}", e));
+
+        add_member(&user("alice", "proj")).await;
+        add_member(&user("alice", "other")).await;
+        add_member(&user("carol", "proj")).await;
+        remove_member(&user("carol", "proj")).await;
+        remove_member(&user("nobody", "proj")).await;
+
+        // a failing server does not stop the others being sampled
+        sample(60)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot sample: {}", e));
+
+        let today = Date::today();
+
+        let (members, usage) =
+            load(&usage_file).unwrap_or_else(|e| unreachable!("Cannot load: {}", e));
+
+        assert_eq!(members.get("alice").map(|g| g.len()), Some(2));
+        assert!(!members.contains_key("carol"));
+        assert_eq!(seconds(&usage, &today, "proj", "alice", "matlab"), 60.0);
+        assert_eq!(seconds(&usage, &today, "other", "alice", "matlab"), 60.0);
+        assert_eq!(seconds(&usage, &today, "proj", "bob", "matlab"), 60.0);
+        assert_eq!(seconds(&usage, &today, "proj", "carol", "abaqus"), 0.0);
+
+        // the usage is added to the days that the scheduler reported,
+        // and the users in the report become members of the project
+        let mut daily = DailyProjectUsageReport::default();
+        daily.set_usage("alice", Usage::new(3600));
+        daily.set_usage("dave", Usage::new(60));
+
+        let mut report = ProjectUsageReport::new(project("proj").project());
+        report.set_report(&today, &daily);
+
+        add_licenses(&project("proj"), &today.day(), &mut report)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add licenses: {}", e));
+
+        let reports = report.daily_reports(false);
+        assert_eq!(reports.len(), 1);
+
+        let matlab = reports[0].get_component(&license_component("matlab"));
+        assert_eq!(matlab.usage("alice"), Usage::new(60));
+        assert_eq!(matlab.usage("bob"), Usage::new(60));
+        assert_eq!(reports[0].usage("alice"), Usage::new(3600));
+
+        let (members, _) = load(&usage_file).unwrap_or_else(|e| unreachable!("Cannot load: {}", e));
+        assert!(members.get("dave").is_some_and(|g| g.contains("proj")));
+
+        // removing the project removes it from all of its members
+        remove_group(&project("proj")).await;
+
+        let (members, _) = load(&usage_file).unwrap_or_else(|e| unreachable!("Cannot load: {}", e));
+        assert!(members
+            .get("alice")
+            .is_some_and(|g| g.len() == 1 && g.contains("other")));
+        assert!(!members.contains_key("bob"));
+        assert!(!members.contains_key("dave"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
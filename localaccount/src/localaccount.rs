@@ -6,14 +6,17 @@ use chrono::Utc;
 use once_cell::sync::OnceCell;
 use std::collections::HashMap;
 use templemeads::agent::Peer;
+use templemeads::exec;
 use templemeads::grammar::{
     PortalIdentifier, ProjectIdentifier, ProjectMapping, UserIdentifier, UserMapping,
 };
 use templemeads::job::assert_not_expired;
+use templemeads::protection::{ProtectionPolicy, ProtectionStatus};
 use templemeads::Error;
-use tokio::process::Command;
 
 static COMMANDS: OnceCell<Commands> = OnceCell::new();
+static PROTECTED_USERS: OnceCell<ProtectionPolicy> = OnceCell::new();
+static PROTECTED_GROUPS: OnceCell<ProtectionPolicy> = OnceCell::new();
 
 ///
 /// Configuration for the Unix commands used by this agent. Each command
@@ -81,6 +84,26 @@ pub fn initialise_commands(cmds: Commands) -> Result<()> {
         .map_err(|_| anyhow::anyhow!("Commands already initialised"))
 }
 
+///
+/// Set the policy that decides which users and groups are protected,
+/// in addition to users who are not in the managed group.
+///
+pub fn initialise_protection_policy(
+    users: ProtectionPolicy,
+    groups: ProtectionPolicy,
+) -> Result<()> {
+    tracing::info!("Protected users: {}", users);
+    tracing::info!("Protected groups: {}", groups);
+
+    PROTECTED_USERS
+        .set(users)
+        .map_err(|_| anyhow::anyhow!("Protected users already initialised"))?;
+
+    PROTECTED_GROUPS
+        .set(groups)
+        .map_err(|_| anyhow::anyhow!("Protected groups already initialised"))
+}
+
 fn get_commands() -> Result<&'static Commands, Error> {
     COMMANDS
         .get()
@@ -92,31 +115,8 @@ fn get_commands() -> Result<&'static Commands, Error> {
 /// Returns (exit_code, stdout, stderr).
 ///
 async fn run_command(parts: &[String], args: &[&str]) -> Result<(i32, String, String), Error> {
-    if parts.is_empty() {
-        return Err(Error::Call("Empty command template".to_owned()));
-    }
-
-    tracing::debug!("Running command: {} {}", parts.join(" "), args.join(" "));
-
-    let mut cmd = Command::new(&parts[0]);
-    for part in &parts[1..] {
-        cmd.arg(part);
-    }
-    for arg in args {
-        cmd.arg(arg);
-    }
-
-    let output = cmd.output().await.map_err(|e| {
-        Error::Call(format!(
-            "Failed to spawn command {}: {}",
-            parts.join(" "),
-            e
-        ))
-    })?;
-
-    let exit_code = output.status.code().unwrap_or(-1);
-    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
-    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    let output = exec::Command::from_parts(parts).args(args).output().await?;
+    let (exit_code, stdout, stderr) = (output.code, output.stdout, output.stderr);
 
     tracing::debug!(
         "Command exit code: {}, stdout: {}, stderr: {}",
@@ -281,6 +281,13 @@ pub async fn add_project(
 
     let group_name = identifier_to_projectid(project);
 
+    if let Some(reason) = group_protection_reason(&group_name, expires).await? {
+        return Err(Error::InvalidState(format!(
+            "Refusing to add project group '{}' as {}",
+            group_name, reason
+        )));
+    }
+
     tracing::info!("Adding project group: {}", group_name);
 
     ensure_group_exists(&group_name, expires).await?;
@@ -301,6 +308,13 @@ pub async fn remove_project(
     let group_name = identifier_to_projectid(project);
     let cmds = get_commands()?;
 
+    if let Some(reason) = group_protection_reason(&group_name, expires).await? {
+        return Err(Error::InvalidState(format!(
+            "Refusing to remove project group '{}' as {}",
+            group_name, reason
+        )));
+    }
+
     tracing::info!("Removing project group: {}", group_name);
 
     let (exit_code, _, stderr) = run_command(&cmds.groupdel, &[&group_name]).await?;
@@ -353,6 +367,19 @@ pub async fn add_user(
             .map_err(|e| Error::Call(e.to_string()));
     }
 
+    // never create (or take over) an account whose name or uid is protected
+    let uid = get_id_number("passwd", &local_user, expires).await?;
+
+    if let Some(reason) = PROTECTED_USERS
+        .get()
+        .and_then(|policy| policy.reason(&local_user, uid))
+    {
+        return Err(Error::UnmanagedUser(format!(
+            "Refusing to add user '{}' as {}",
+            local_user, reason
+        )));
+    }
+
     let default_home = format!("/home/{}", local_user);
     let homedir_str = homedir.as_deref().unwrap_or(&default_home);
 
@@ -405,6 +432,13 @@ pub async fn remove_user(
     let mapping = UserMapping::new(user, &local_user, &local_group)
         .map_err(|e| Error::Call(e.to_string()))?;
 
+    if let Some(reason) = user_protection_reason(user, expires).await? {
+        return Err(Error::UnmanagedUser(format!(
+            "Refusing to remove user '{}' as {}",
+            local_user, reason
+        )));
+    }
+
     tracing::info!("Removing user: {}", local_user);
 
     let (exit_code, _, stderr) = run_command(&cmds.userdel, &[&local_user]).await?;
@@ -819,28 +853,70 @@ pub async fn unblock_user(
 }
 
 ///
-/// Return true if the user is "protected" — i.e. the user exists on the
-/// system but was NOT created by this agent. Managed users are identified
-/// by membership of the managed group (default: "openportal").
+/// Return the numeric id of the named entry in the passed `getent`
+/// database ("passwd" for uids, "group" for gids), or None if it
+/// doesn't exist.
 ///
-pub async fn is_protected_user(
-    user: &UserIdentifier,
+async fn get_id_number(
+    database: &str,
+    name: &str,
     expires: &chrono::DateTime<Utc>,
-) -> Result<bool, Error> {
+) -> Result<Option<u32>, Error> {
     assert_not_expired(expires)?;
 
-    if !is_existing_user(user, expires).await? {
-        return Ok(false);
+    let cmds = get_commands()?;
+
+    let (exit_code, stdout, _) = run_command(&cmds.getent, &[database, name]).await?;
+
+    if exit_code != 0 {
+        return Ok(None);
     }
 
+    // Output: name:x:id:...
+    Ok(stdout
+        .trim()
+        .split(':')
+        .nth(2)
+        .and_then(|id| id.parse::<u32>().ok()))
+}
+
+///
+/// Return why the given user is protected, or None if they are not.
+/// Users are protected if their name or uid matches the protection
+/// policy, or if they exist on the system but were NOT created by this
+/// agent. Managed users are identified by membership of the managed
+/// group (default: "openportal").
+///
+async fn user_protection_reason(
+    user: &UserIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Option<String>, Error> {
+    assert_not_expired(expires)?;
+
     let local_user = identifier_to_userid(user);
+    let uid = get_id_number("passwd", &local_user, expires).await?;
+
+    if let Some(reason) = PROTECTED_USERS
+        .get()
+        .and_then(|policy| policy.reason(&local_user, uid))
+    {
+        return Ok(Some(reason));
+    }
+
+    if uid.is_none() {
+        return Ok(None);
+    }
+
     let cmds = get_commands()?;
 
     let (exit_code, stdout, _) = run_command(&cmds.getent, &["group", &cmds.managed_group]).await?;
 
     if exit_code != 0 {
         // Managed group doesn't exist — user must be unmanaged/protected.
-        return Ok(true);
+        return Ok(Some(format!(
+            "the managed group '{}' does not exist",
+            cmds.managed_group
+        )));
     }
 
     // Output: groupname:x:gid:member1,member2,...
@@ -851,5 +927,99 @@ pub async fn is_protected_user(
         .split(',')
         .any(|m| m.trim() == local_user.as_str());
 
-    Ok(!is_managed)
+    match is_managed {
+        true => Ok(None),
+        false => Ok(Some(format!(
+            "'{}' is not a member of the managed group '{}'",
+            local_user, cmds.managed_group
+        ))),
+    }
+}
+
+///
+/// Return why the named group is protected by the protection policy,
+/// or None if it is not.
+///
+async fn group_protection_reason(
+    group_name: &str,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Option<String>, Error> {
+    let policy = match PROTECTED_GROUPS.get() {
+        Some(policy) if !policy.is_empty() => policy,
+        _ => return Ok(None),
+    };
+
+    let gid = get_id_number("group", group_name, expires).await?;
+
+    Ok(policy.reason(group_name, gid))
+}
+
+///
+/// Return true if the user is "protected" — i.e. the user exists on the
+/// system but was NOT created by this agent, or they match the
+/// protection policy.
+///
+pub async fn is_protected_user(
+    user: &UserIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<bool, Error> {
+    assert_not_expired(expires)?;
+
+    if !is_existing_user(user, expires).await? {
+        return Ok(false);
+    }
+
+    Ok(user_protection_reason(user, expires).await?.is_some())
+}
+
+///
+/// Return whether or not the given user is protected, and why.
+///
+pub async fn get_user_protection(
+    user: &UserIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<ProtectionStatus, Error> {
+    let local_user = identifier_to_userid(user);
+
+    let local_name = match is_existing_user(user, expires).await? {
+        true => Some(local_user.as_str()),
+        false => None,
+    };
+
+    match user_protection_reason(user, expires).await? {
+        Some(reason) => Ok(ProtectionStatus::protected(
+            &user.to_string(),
+            local_name,
+            &reason,
+        )),
+        None => Ok(ProtectionStatus::unprotected(&user.to_string(), local_name)),
+    }
+}
+
+///
+/// Return whether or not the group for the given project is protected,
+/// and why.
+///
+pub async fn get_project_protection(
+    project: &ProjectIdentifier,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<ProtectionStatus, Error> {
+    let group_name = identifier_to_projectid(project);
+
+    let local_name = match is_existing_project(project, expires).await? {
+        true => Some(group_name.as_str()),
+        false => None,
+    };
+
+    match group_protection_reason(&group_name, expires).await? {
+        Some(reason) => Ok(ProtectionStatus::protected(
+            &project.to_string(),
+            local_name,
+            &reason,
+        )),
+        None => Ok(ProtectionStatus::unprotected(
+            &project.to_string(),
+            local_name,
+        )),
+    }
 }
@@ -11,13 +11,14 @@ use templemeads::agent::account::{process_args, run, Defaults};
 use templemeads::agent::{Peer, Type as AgentType};
 use templemeads::async_runnable;
 use templemeads::grammar::Instruction::{
-    AddProject, AddUser, BlockUser, GetProjectMapping, GetProjects, GetUserMapping, GetUsers,
-    IsBlockedUser, IsExistingProject, IsExistingUser, IsProtectedUser, RemoveProject, RemoveUser,
-    UnblockUser, UpdateHomeDir,
+    AddProject, AddUser, BlockUser, GetProjectMapping, GetProjectProtection, GetProjects,
+    GetUserMapping, GetUserProtection, GetUsers, IsBlockedUser, IsExistingProject, IsExistingUser,
+    IsProtectedUser, RemoveProject, RemoveUser, UnblockUser, UpdateHomeDir,
 };
 use templemeads::grammar::UserMapping;
 use templemeads::job::{assert_not_expired, Envelope, Job};
 use templemeads::notification::default_notify_runner;
+use templemeads::protection::ProtectionPolicy;
 use templemeads::set_notify_runner;
 use templemeads::Error;
 
@@ -104,6 +105,21 @@ async fn main() -> Result<()> {
         instance_groups,
     ))?;
 
+    // Users and groups that must never be managed, in addition to users
+    // who are not in the managed group. Names are comma-separated and may
+    // include wildcards, e.g. "root,svc-*", while ids are comma-separated
+    // ranges, e.g. "0-999,65534".
+    localaccount::initialise_protection_policy(
+        ProtectionPolicy::parse(
+            &config.option("protected-users", "root"),
+            &config.option("protected-uids", "0-999"),
+        )?,
+        ProtectionPolicy::parse(
+            &config.option("protected-groups", "root,wheel,sudo"),
+            &config.option("protected-gids", "0-999"),
+        )?,
+    )?;
+
     async_runnable! {
         ///
         /// Runnable function that will be called when a job is received
@@ -172,6 +188,14 @@ async fn main() -> Result<()> {
                     let is_protected = localaccount::is_protected_user(&user, job.expires()).await?;
                     job.completed(is_protected)
                 },
+                GetUserProtection(user) => {
+                    let status = localaccount::get_user_protection(&user, job.expires()).await?;
+                    job.completed(status)
+                },
+                GetProjectProtection(project) => {
+                    let status = localaccount::get_project_protection(&project, job.expires()).await?;
+                    job.completed(status)
+                },
                 IsExistingUser(user) => {
                     let exists = localaccount::is_existing_user(&user, job.expires()).await?;
                     job.completed(exists)
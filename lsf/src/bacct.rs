@@ -0,0 +1,351 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use templemeads::grammar::{Date, DateRange, ProjectMapping};
+use templemeads::job::assert_not_expired;
+use templemeads::usagereport::{DailyProjectUsageReport, ProjectUsageReport, Usage};
+use templemeads::Error;
+use tokio::sync::RwLock;
+
+use crate::bconf;
+
+#[derive(Debug)]
+struct Database {
+    slots_per_node: u64,
+    /// The report of each user group on each completed day. Completed
+    /// days never change, so they are only fetched from bacct once.
+    reports: HashMap<(String, Date), DailyProjectUsageReport>,
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self {
+            slots_per_node: 1,
+            reports: HashMap::new(),
+        }
+    }
+}
+
+static CACHE: Lazy<RwLock<Database>> = Lazy::new(|| RwLock::new(Database::default()));
+
+///
+/// Set the number of job slots in a node, which converts slot-seconds
+/// into node-seconds
+///
+pub async fn set_slots_per_node(slots_per_node: u64) -> Result<(), Error> {
+    if slots_per_node == 0 {
+        return Err(Error::Misconfigured(
+            "The number of slots per node must be greater than zero".to_owned(),
+        ));
+    }
+
+    CACHE.write().await.slots_per_node = slots_per_node;
+
+    Ok(())
+}
+
+///
+/// Return the value in angle brackets that follows `key` in the line,
+/// e.g. `field("Job <12>, User <alice>", "User")` is "alice"
+///
+fn field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let start = line.find(&format!("{} <", key))? + key.len() + 2;
+    let end = line[start..].find('>')? + start;
+    Some(&line[start..end])
+}
+
+///
+/// A job parsed from the long-format (`-l -UF`) output of bacct
+///
+#[derive(Debug, Default)]
+struct JobRecord {
+    user: String,
+    slots: u64,
+    wait: u64,
+    run: u64,
+}
+
+///
+/// Parse the long-format, unformatted (`-l -UF`) output of bacct. Each
+/// job is a block separated by a line of dashes, starting with
+/// "Job <id>, User <user>, ...", with a "Dispatched <n> Task(s)" event,
+/// and ending with a table whose columns include WAIT and TURNAROUND.
+///
+fn parse_jobs(output: &str) -> Vec<JobRecord> {
+    let mut jobs = Vec::new();
+
+    for block in output.split("\n---") {
+        let mut job = JobRecord {
+            slots: 1,
+            ..Default::default()
+        };
+
+        let mut header: Option<Vec<&str>> = None;
+
+        for line in block.lines().map(|l| l.trim()) {
+            if line.starts_with("Job <") {
+                job.user = field(line, "User").unwrap_or_default().to_owned();
+            } else if let Some(pos) = line.find("Dispatched ") {
+                if let Some(slots) = line[pos + 11..]
+                    .split_whitespace()
+                    .next()
+                    .and_then(|n| n.parse::<u64>().ok())
+                {
+                    job.slots = slots.max(1);
+                }
+            } else if line.starts_with("CPU_T") {
+                header = Some(line.split_whitespace().collect());
+            } else if let Some(columns) = header.take() {
+                let values: Vec<&str> = line.split_whitespace().collect();
+
+                let value = |name: &str| {
+                    columns
+                        .iter()
+                        .position(|c| *c == name)
+                        .and_then(|i| values.get(i))
+                        .and_then(|v| v.parse::<f64>().ok())
+                        .unwrap_or(0.0) as u64
+                };
+
+                job.wait = value("WAIT");
+                job.run = value("TURNAROUND").saturating_sub(job.wait);
+            }
+        }
+
+        if !job.user.is_empty() {
+            jobs.push(job);
+        }
+    }
+
+    jobs
+}
+
+///
+/// Fetch the usage of the user group (LSF project) from the jobs that
+/// completed on the passed day. Usage is the run time multiplied by the
+/// number of slots, divided by the slots per node, i.e. node-seconds.
+///
+async fn fetch_day(group: &str, day: &Date) -> Result<DailyProjectUsageReport, Error> {
+    let window = format!(
+        "{}/00:00,{}/23:59",
+        day.date().format("%Y/%m/%d"),
+        day.date().format("%Y/%m/%d")
+    );
+
+    let output = bconf::bacct(&["-l", "-UF", "-u", "all", "-P", group, "-C", &window]).await?;
+
+    let slots_per_node = CACHE.read().await.slots_per_node;
+
+    let mut report = DailyProjectUsageReport::default();
+
+    for job in parse_jobs(&output) {
+        report.add_usage(&job.user, Usage::new(job.run * job.slots / slots_per_node));
+        report.add_jobs(&job.user, 1);
+        report.add_wait_seconds(&job.user, job.wait);
+    }
+
+    Ok(report)
+}
+
+///
+/// Return the usage report of the user group on the passed day
+///
+async fn get_daily_report(group: &str, day: &Date) -> Result<DailyProjectUsageReport, Error> {
+    let key = (group.to_owned(), day.clone());
+
+    if let Some(report) = CACHE.read().await.reports.get(&key) {
+        return Ok(report.clone());
+    }
+
+    let mut report = fetch_day(group, day).await?;
+
+    // the usage of days before today is complete, so cache it
+    if *day < Date::today() {
+        report.set_complete();
+        CACHE.write().await.reports.insert(key, report.clone());
+    }
+
+    Ok(report)
+}
+
+///
+/// Return the usage report of the project for each of the passed days
+///
+pub async fn get_usage_report(
+    project: &ProjectMapping,
+    dates: &DateRange,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<ProjectUsageReport, Error> {
+    assert_not_expired(expires)?;
+
+    let group = bconf::group_name(project.local_group())?;
+    let today = Date::today();
+
+    let mut report = ProjectUsageReport::new(project.project());
+
+    // we can't get the usage for days in the future
+    for day in dates.days().into_iter().filter(|day| *day <= today) {
+        assert_not_expired(expires)?;
+
+        let daily_report = match get_daily_report(&group, &day).await {
+            Ok(daily_report) => daily_report,
+            Err(e) => {
+                tracing::warn!("Could not get the usage of {} on {}: {}", group, day, e);
+                DailyProjectUsageReport::default()
+            }
+        };
+
+        report.set_report(&day, &daily_report);
+    }
+
+    Ok(report)
+}
+
+///
+/// Return the total usage of the user group over the passed days
+///
+pub async fn get_total_usage(group: &str, dates: &DateRange) -> Result<Usage, Error> {
+    let today = Date::today();
+    let mut total = Usage::default();
+
+    for day in dates.days().into_iter().filter(|day| *day <= today) {
+        total += get_daily_report(group, &day).await?.total_usage();
+    }
+
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bconf::tests::{fake_lsf, logged, project};
+
+    const BACCT: &str = "Accounting information about jobs that are:
+  - submitted by all users.
+------------------------------------------------------------------------------
+
+Job <101>, User <alice>, Project <acct>, Status <DONE>, Queue <normal>, Command <sleep 3600>
+Tue Apr 14 10:00:00: Submitted from host <login1>, CWD <$HOME>;
+Tue Apr 14 10:01:00: Dispatched 4 Task(s) on Host(s) <4*node1>, Allocated 4 Slot(s);
+Tue Apr 14 11:01:00: Completed <done>.
+
+Accounting information about this job:
+     CPU_T     WAIT     TURNAROUND   STATUS     HOG_FACTOR    MEM    SWAP
+   3600.00       60          3660     done         0.9836     2M     0M
+------------------------------------------------------------------------------
+
+Job <102>, User <bob>, Project <acct>, Status <EXIT>, Queue <normal>, Command <false>
+Tue Apr 14 12:00:00: Submitted from host <login1>, CWD <$HOME>;
+Tue Apr 14 12:00:10: Dispatched 1 Task(s) on Host(s) <node2>, Allocated 1 Slot(s);
+Tue Apr 14 12:30:10: Completed <exit>.
+
+Accounting information about this job:
+     CPU_T     WAIT     TURNAROUND   STATUS     HOG_FACTOR    MEM    SWAP
+      1.00       10          1810     exit         0.0006     1M     0M
+------------------------------------------------------------------------------
+
+SUMMARY:      ( time unit: second )
+ Total number of done jobs:       1      Total number of exited jobs:     1
+";
+
+    #[test]
+    fn test_field() {
+        let line = "Job <101>, User <alice>, Project <acct>";
+
+        assert_eq!(field(line, "Job"), Some("101"));
+        assert_eq!(field(line, "User"), Some("alice"));
+        assert_eq!(field(line, "Queue"), None);
+    }
+
+    #[test]
+    fn test_parse_jobs() {
+        let jobs = parse_jobs(BACCT);
+
+        assert_eq!(jobs.len(), 2);
+
+        assert_eq!(jobs[0].user, "alice");
+        assert_eq!(jobs[0].slots, 4);
+        assert_eq!(jobs[0].wait, 60);
+        assert_eq!(jobs[0].run, 3600);
+
+        assert_eq!(jobs[1].user, "bob");
+        assert_eq!(jobs[1].slots, 1);
+        assert_eq!(jobs[1].wait, 10);
+        assert_eq!(jobs[1].run, 1800);
+
+        assert!(parse_jobs("").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_usage() {
+        let dir = fake_lsf();
+
+        assert!(matches!(
+            set_slots_per_node(0).await,
+            Err(Error::Misconfigured(_))
+        ));
+
+        set_slots_per_node(2)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot set slots per node: {}", e));
+
+        std::fs::write(dir.join("acct.bacct"), BACCT)
+            .unwrap_or_else(|e| unreachable!("Cannot write bacct output: {}", e));
+
+        let yesterday = Date::yesterday();
+
+        let report = get_daily_report("acct", &yesterday)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot get report: {}", e));
+
+        // slot-seconds are converted to node-seconds
+        assert_eq!(report.usage("alice").seconds(), 4 * 3600 / 2);
+        assert_eq!(report.usage("bob").seconds(), 1800 / 2);
+        assert_eq!(report.num_jobs(), 2);
+        assert_eq!(report.wait_seconds_for_user("alice"), 60);
+        assert!(report.is_complete());
+
+        let window = format!(
+            "{}/00:00,{}/23:59",
+            yesterday.date().format("%Y/%m/%d"),
+            yesterday.date().format("%Y/%m/%d")
+        );
+
+        assert_eq!(
+            logged("-P acct"),
+            [format!("bacct -l -UF -u all -P acct -C {}", window)]
+        );
+
+        // completed days are only fetched once
+        let dates = DateRange::from_chrono(&yesterday.to_chrono(), &yesterday.to_chrono());
+
+        assert!(get_total_usage("acct", &dates)
+            .await
+            .is_ok_and(|usage| usage.seconds() == 4 * 3600 / 2 + 1800 / 2));
+
+        assert_eq!(logged("-P acct").len(), 1);
+
+        // failures are reported as an empty day in usage reports...
+        let expires = Utc::now() + chrono::Duration::seconds(30);
+
+        assert!(get_usage_report(&project("broken"), &dates, &expires)
+            .await
+            .is_ok_and(|report| report.total_usage().seconds() == 0));
+
+        // ...but are errors when checking allocations
+        assert!(matches!(
+            get_total_usage("broken", &dates).await,
+            Err(Error::Call(_))
+        ));
+
+        let expired = Utc::now() - chrono::Duration::seconds(1);
+
+        assert!(matches!(
+            get_usage_report(&project("acct"), &dates, &expired).await,
+            Err(Error::Expired(_))
+        ));
+    }
+}
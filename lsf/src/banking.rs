@@ -0,0 +1,454 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Allocation banking - the limit of each project is its allocation,
+//! and the usage of each project is periodically counted against it.
+//! The project's user group is blocked once the allocation is exhausted
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use templemeads::destination::Destination;
+use templemeads::grammar::{Date, DateRange, ProjectMapping};
+use templemeads::job::assert_not_expired;
+use templemeads::notification::{self, NotificationEvent};
+use templemeads::usagereport::Usage;
+use templemeads::Error;
+use tokio::sync::RwLock;
+use tokio::time::sleep;
+
+use crate::bacct;
+use crate::bconf;
+
+///
+/// The allocation of a single project, together with where to send
+/// notifications when the project's user group is blocked or unblocked
+///
+#[derive(Debug, Clone)]
+struct BankAccount {
+    mapping: ProjectMapping,
+    allocation: Usage,
+    since: Date,
+    destination: Destination,
+    /// Whether the user group has been blocked because the
+    /// allocation is exhausted
+    blocked: bool,
+}
+
+#[derive(Debug, Default)]
+struct Database {
+    /// The bank account of each project, keyed by user group
+    accounts: HashMap<String, BankAccount>,
+    limits_file: Option<PathBuf>,
+}
+
+static CACHE: Lazy<RwLock<Database>> = Lazy::new(|| RwLock::new(Database::default()));
+
+///
+/// Set the file in which allocations are saved, and load any
+/// allocations that were saved previously
+///
+pub async fn set_limits_file(limits_file: &str) -> Result<(), Error> {
+    let limits_file = limits_file.trim();
+
+    if limits_file.is_empty() {
+        tracing::warn!("No limits-file set - project limits will be lost when the agent restarts");
+        return Ok(());
+    }
+
+    let limits_file = PathBuf::from(limits_file);
+    let mut cache = CACHE.write().await;
+
+    if limits_file.exists() {
+        let contents = std::fs::read_to_string(&limits_file)?;
+
+        let value: Value = serde_json::from_str(&contents).map_err(|e| {
+            Error::Parse(format!(
+                "Invalid limits file {}: {}",
+                limits_file.display(),
+                e
+            ))
+        })?;
+
+        for (group, account) in value.as_object().cloned().unwrap_or_default() {
+            let field = |name: &str| account[name].as_str().unwrap_or_default().to_owned();
+
+            cache.accounts.insert(
+                group,
+                BankAccount {
+                    mapping: ProjectMapping::parse(&field("mapping"))?,
+                    allocation: Usage::new(account["allocation"].as_u64().unwrap_or(0)),
+                    since: Date::parse(&field("since"))?,
+                    destination: Destination::parse(&field("destination"))?,
+                    blocked: account["blocked"].as_bool().unwrap_or(false),
+                },
+            );
+        }
+
+        tracing::info!(
+            "Loaded the limits of {} projects from {}",
+            cache.accounts.len(),
+            limits_file.display()
+        );
+    }
+
+    cache.limits_file = Some(limits_file);
+
+    Ok(())
+}
+
+fn save(database: &Database) -> Result<(), Error> {
+    let limits_file = match &database.limits_file {
+        Some(limits_file) => limits_file,
+        None => return Ok(()),
+    };
+
+    let value: serde_json::Map<String, Value> = database
+        .accounts
+        .iter()
+        .map(|(group, account)| {
+            (
+                group.clone(),
+                json!({
+                    "mapping": account.mapping.to_string(),
+                    "allocation": account.allocation.seconds(),
+                    "since": account.since.to_string(),
+                    "destination": account.destination.to_string(),
+                    "blocked": account.blocked,
+                }),
+            )
+        })
+        .collect();
+
+    // write to a temporary file first, so that a crash cannot leave
+    // a partially written file behind
+    let tmp = limits_file.with_extension("tmp");
+    std::fs::write(&tmp, Value::Object(value).to_string())?;
+    std::fs::rename(&tmp, limits_file)?;
+
+    Ok(())
+}
+
+///
+/// Return the limit (allocation) of the project, which is zero if
+/// no limit has been set
+///
+pub async fn get_limit(
+    mapping: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Usage, Error> {
+    assert_not_expired(expires)?;
+
+    let group = bconf::group_name(mapping.local_group())?;
+
+    Ok(CACHE
+        .read()
+        .await
+        .accounts
+        .get(&group)
+        .map(|account| account.allocation)
+        .unwrap_or_default())
+}
+
+///
+/// Set the limit (allocation) of the project. Notifications about the
+/// project are sent along `destination`. A zero limit removes the
+/// allocation, so that the project's usage is no longer checked
+///
+pub async fn set_limit(
+    mapping: &ProjectMapping,
+    limit: &Usage,
+    destination: &Destination,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Usage, Error> {
+    assert_not_expired(expires)?;
+
+    let group = bconf::group_name(mapping.local_group())?;
+
+    let was_blocked = {
+        let mut cache = CACHE.write().await;
+
+        let previous = cache.accounts.get(&group).cloned();
+        let was_blocked = previous.as_ref().is_some_and(|account| account.blocked);
+
+        if limit.seconds() == 0 {
+            cache.accounts.remove(&group);
+        } else {
+            // keep counting from the day that the allocation was first set
+            let since = previous
+                .map(|account| account.since)
+                .unwrap_or_else(Date::today);
+
+            cache.accounts.insert(
+                group.clone(),
+                BankAccount {
+                    mapping: mapping.clone(),
+                    allocation: *limit,
+                    since,
+                    destination: destination.clone(),
+                    blocked: was_blocked,
+                },
+            );
+        }
+
+        save(&cache)?;
+
+        was_blocked
+    };
+
+    if limit.seconds() == 0 {
+        // the project can no longer exhaust its allocation
+        if was_blocked {
+            bconf::unblock_group(&group).await?;
+        }
+    } else if let Err(e) = check_group(&group).await {
+        tracing::warn!("Could not check the allocation of {}: {}", mapping, e);
+    }
+
+    Ok(*limit)
+}
+
+///
+/// Remove the allocation of the project, e.g. because it has been removed
+///
+pub async fn remove_limit(mapping: &ProjectMapping) -> Result<(), Error> {
+    let group = bconf::group_name(mapping.local_group())?;
+
+    let mut cache = CACHE.write().await;
+
+    if cache.accounts.remove(&group).is_some() {
+        save(&cache)?;
+    }
+
+    Ok(())
+}
+
+///
+/// Spawn the background task that checks the usage of every project
+/// with a limit every `interval` seconds
+///
+pub fn spawn_banking_task(interval: u64) {
+    tokio::spawn(async move {
+        loop {
+            sleep(Duration::from_secs(interval)).await;
+
+            let groups: Vec<String> = CACHE.read().await.accounts.keys().cloned().collect();
+
+            for group in groups {
+                if let Err(e) = check_group(&group).await {
+                    tracing::error!(
+                        "Banking: could not check the allocation of {}: {}",
+                        group,
+                        e
+                    );
+                }
+            }
+        }
+    });
+}
+
+///
+/// Record whether or not the user group is blocked because its
+/// allocation is exhausted
+///
+async fn set_blocked(group: &str, blocked: bool) -> Result<(), Error> {
+    let mut cache = CACHE.write().await;
+
+    if let Some(account) = cache.accounts.get_mut(group) {
+        account.blocked = blocked;
+        save(&cache)?;
+    }
+
+    Ok(())
+}
+
+///
+/// Count the usage of the project's user group against its allocation,
+/// blocking the group if the allocation is exhausted, or unblocking
+/// it if allocation is available again
+///
+async fn check_group(group: &str) -> Result<(), Error> {
+    let account = match CACHE.read().await.accounts.get(group) {
+        Some(account) => account.clone(),
+        None => return Ok(()),
+    };
+
+    let dates = DateRange::from_chrono(&account.since.to_chrono(), &Date::today().to_chrono());
+    let usage = bacct::get_total_usage(group, &dates).await?;
+    let project = account.mapping.project().clone();
+
+    if usage.seconds() >= account.allocation.seconds() {
+        if account.blocked {
+            // make sure that the group is still blocked, e.g. in case
+            // the project was added again
+            return bconf::block_group(group).await;
+        }
+
+        tracing::warn!(
+            "Project {} has used {} of its allocation of {} - blocking user group {}",
+            project,
+            usage,
+            account.allocation,
+            group
+        );
+
+        bconf::block_group(group).await?;
+        set_blocked(group, true).await?;
+
+        notification::send(
+            &account.destination,
+            NotificationEvent::AllocationExhausted(project),
+        )
+        .await;
+    } else if account.blocked {
+        tracing::info!(
+            "Project {} has used {} of its allocation of {} - unblocked user group {}",
+            project,
+            usage,
+            account.allocation,
+            group
+        );
+
+        bconf::unblock_group(group).await?;
+        set_blocked(group, false).await?;
+
+        notification::send(
+            &account.destination,
+            NotificationEvent::AllocationRestored(project),
+        )
+        .await;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bconf::tests::{fake_lsf, project};
+
+    #[tokio::test]
+    async fn test_banking() {
+        let dir = fake_lsf();
+        let mapping = project("bank");
+        let destination = Destination::parse("portal.cluster.lsf")
+            .unwrap_or_else(|e| unreachable!("Cannot parse destination: {}", e));
+        let expires = Utc::now() + chrono::Duration::seconds(30);
+
+        bacct::set_slots_per_node(2)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot set slots per node: {}", e));
+
+        // the project has used two node-hours today
+        std::fs::write(
+            dir.join("bank.bacct"),
+            "------------------------------------------------------------------------------
+Job <1>, User <alice>, Project <bank>, Status <DONE>
+Tue Apr 14 10:00:00: Dispatched 4 Task(s) on Host(s) <4*node1>;
+     CPU_T     WAIT     TURNAROUND   STATUS
+   3600.00        0          3600     done
+",
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot write bacct output: {}", e));
+
+        let limits_file = dir.join("limits.json");
+
+        set_limits_file(&limits_file.display().to_string())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot set limits file: {}", e));
+
+        bconf::add_project(&mapping, &expires)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add project: {}", e));
+
+        let blocked = || dir.join("op_block_bank.limit").exists();
+
+        let set = |seconds: u64| {
+            let mapping = mapping.clone();
+            let destination = destination.clone();
+
+            async move {
+                set_limit(&mapping, &Usage::new(seconds), &destination, &expires)
+                    .await
+                    .unwrap_or_else(|e| unreachable!("Cannot set limit: {}", e));
+            }
+        };
+
+        // the allocation is not yet used up
+        set(3 * 3600).await;
+        assert!(!blocked());
+        assert!(get_limit(&mapping, &expires)
+            .await
+            .is_ok_and(|limit| limit.seconds() == 3 * 3600));
+
+        // reducing it below the usage blocks the group...
+        set(3600).await;
+        assert!(blocked());
+
+        let saved = std::fs::read_to_string(&limits_file).unwrap_or_default();
+        assert!(saved.contains(r#""allocation":3600"#));
+        assert!(saved.contains(r#""blocked":true"#));
+
+        // ...which is kept blocked even if the project is added again
+        bconf::add_project(&mapping, &expires)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add project: {}", e));
+        set(3600).await;
+        assert!(blocked());
+
+        // increasing it unblocks the group
+        set(4 * 3600).await;
+        assert!(!blocked());
+        assert!(std::fs::read_to_string(&limits_file)
+            .unwrap_or_default()
+            .contains(r#""blocked":false"#));
+
+        // removing the allocation also unblocks the group
+        set(3600).await;
+        assert!(blocked());
+        set(0).await;
+        assert!(!blocked());
+        assert!(get_limit(&mapping, &expires)
+            .await
+            .is_ok_and(|limit| limit.seconds() == 0));
+
+        // blocks that were not made by banking are left alone
+        bconf::block_group("bank")
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot block group: {}", e));
+        set(4 * 3600).await;
+        assert!(blocked());
+        set(0).await;
+        assert!(blocked());
+
+        // saved allocations are loaded when the agent restarts
+        set(4 * 3600).await;
+        CACHE.write().await.accounts.clear();
+
+        set_limits_file(&limits_file.display().to_string())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot set limits file: {}", e));
+
+        assert!(get_limit(&mapping, &expires)
+            .await
+            .is_ok_and(|limit| limit.seconds() == 4 * 3600));
+
+        remove_limit(&mapping)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove limit: {}", e));
+
+        assert!(get_limit(&mapping, &expires)
+            .await
+            .is_ok_and(|limit| limit.seconds() == 0));
+
+        let expired = Utc::now() - chrono::Duration::seconds(1);
+
+        assert!(matches!(
+            set_limit(&mapping, &Usage::new(3600), &destination, &expired).await,
+            Err(Error::Expired(_))
+        ));
+    }
+}
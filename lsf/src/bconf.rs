@@ -0,0 +1,637 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+use chrono::Utc;
+use once_cell::sync::OnceCell;
+use templemeads::exec;
+use templemeads::grammar::{ProjectMapping, UserMapping};
+use templemeads::job::assert_not_expired;
+use templemeads::Error;
+
+static COMMANDS: OnceCell<Commands> = OnceCell::new();
+
+///
+/// The LSF commands used by this agent. Each command is stored as a
+/// pre-split list of tokens so that prefixes like
+/// "ssh lsf-master bconf" work without any shell quoting issues.
+///
+pub struct Commands {
+    bconf: Vec<String>,
+    bugroup: Vec<String>,
+    bkill: Vec<String>,
+    bacct: Vec<String>,
+}
+
+impl Commands {
+    fn parse_cmd(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|p| p.to_owned()).collect()
+    }
+
+    pub fn new(bconf: &str, bugroup: &str, bkill: &str, bacct: &str) -> Self {
+        Self {
+            bconf: Self::parse_cmd(bconf),
+            bugroup: Self::parse_cmd(bugroup),
+            bkill: Self::parse_cmd(bkill),
+            bacct: Self::parse_cmd(bacct),
+        }
+    }
+}
+
+pub fn initialise_commands(cmds: Commands) -> Result<()> {
+    COMMANDS
+        .set(cmds)
+        .map_err(|_| anyhow::anyhow!("Commands already initialised"))
+}
+
+fn get_commands() -> Result<&'static Commands, Error> {
+    COMMANDS
+        .get()
+        .ok_or_else(|| Error::Call("Commands not initialised".to_owned()))
+}
+
+///
+/// Run a command built from a pre-tokenised prefix plus additional args.
+/// Returns (exit_code, stdout, stderr).
+///
+async fn run_command(parts: &[String], args: &[&str]) -> Result<(i32, String, String), Error> {
+    let output = exec::Command::from_parts(parts).args(args).output().await?;
+    let (exit_code, stdout, stderr) = (output.code, output.stdout, output.stderr);
+
+    tracing::debug!(
+        "Command exit code: {}, stdout: {}, stderr: {}",
+        exit_code,
+        stdout,
+        stderr
+    );
+
+    Ok((exit_code, stdout, stderr))
+}
+
+///
+/// Run bconf with the passed arguments, returning an error if it fails
+///
+async fn bconf(args: &[&str]) -> Result<(), Error> {
+    let commands = get_commands()?;
+    let (code, stdout, stderr) = run_command(&commands.bconf, args).await?;
+
+    // bconf reports some failures on stdout with a zero exit code
+    if code != 0 || stdout.to_lowercase().contains("failed") {
+        return Err(Error::Call(format!(
+            "bconf {} failed (exit {}): {} {}",
+            args.join(" "),
+            code,
+            stdout.trim(),
+            stderr.trim()
+        )));
+    }
+
+    Ok(())
+}
+
+///
+/// Run bacct with the passed arguments, returning its output
+///
+pub async fn bacct(args: &[&str]) -> Result<String, Error> {
+    let commands = get_commands()?;
+    let (code, stdout, stderr) = run_command(&commands.bacct, args).await?;
+
+    // bacct exits with an error if no jobs match
+    if code != 0 && !stderr.contains("No matching job found") {
+        return Err(Error::Call(format!(
+            "bacct {} failed (exit {}): {}",
+            args.join(" "),
+            code,
+            stderr.trim()
+        )));
+    }
+
+    Ok(stdout)
+}
+
+///
+/// Return the name of the LSF user group for the passed local project
+/// group, which is also the LSF project that its jobs are charged to.
+/// Any character other than letters, digits, '_', '-' and '.' is
+/// replaced by '_'.
+///
+pub fn group_name(local_group: &str) -> Result<String, Error> {
+    let name: String = local_group
+        .trim()
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.' {
+                true => c,
+                false => '_',
+            },
+        )
+        .collect();
+
+    match name.is_empty() {
+        true => Err(Error::Parse(
+            "Cannot create an LSF user group from an empty name".to_owned(),
+        )),
+        false => Ok(name),
+    }
+}
+
+///
+/// Return the name of the limit used to block the passed user group
+///
+fn block_limit_name(group: &str) -> String {
+    format!("op_block_{}", group)
+}
+
+///
+/// Return the members of the user group, or None if it does not exist
+///
+async fn get_group_members(group: &str) -> Result<Option<Vec<String>>, Error> {
+    let commands = get_commands()?;
+    let (code, stdout, _) = run_command(&commands.bugroup, &["-w", group]).await?;
+
+    if code != 0 {
+        return Ok(None);
+    }
+
+    // output is "GROUP_NAME    USERS\n<group>    user1 user2 ..."
+    Ok(stdout
+        .lines()
+        .skip(1)
+        .find_map(|line| {
+            let mut parts = line.split_whitespace();
+
+            match parts.next() {
+                Some(name) if name == group => Some(
+                    parts
+                        .filter(|user| *user != "all" && *user != "(none)")
+                        .map(|user| user.trim_end_matches('/').to_owned())
+                        .collect(),
+                ),
+                _ => None,
+            }
+        })
+        .or(Some(Vec::new())))
+}
+
+///
+/// Create the LSF user group of the project, if it does not already
+/// exist, and unblock it in case it was previously removed
+///
+pub async fn add_project(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let group = group_name(project.local_group())?;
+
+    if get_group_members(&group).await?.is_none() {
+        tracing::info!("Creating user group {} for {}", group, project);
+        bconf(&["create", &format!("usergroup={}", group), "GROUP_MEMBER=()"]).await?;
+    }
+
+    unblock_group(&group).await?;
+
+    Ok(())
+}
+
+///
+/// Block the project's user group, so that none of its pending jobs
+/// are dispatched, and kill its pending jobs. The group is not deleted,
+/// so that it can be restored by adding the project again.
+///
+pub async fn remove_project(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let group = group_name(project.local_group())?;
+
+    if get_group_members(&group).await?.is_none() {
+        tracing::warn!("User group {} for {} does not exist", group, project);
+        return Ok(());
+    }
+
+    block_group(&group).await?;
+    kill_pending_jobs(&group, None).await?;
+
+    tracing::info!("Blocked user group {} and killed its pending jobs", group);
+
+    Ok(())
+}
+
+///
+/// Add the user to their project's user group
+///
+pub async fn add_user(user: &UserMapping, expires: &chrono::DateTime<Utc>) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let group = group_name(user.local_group())?;
+
+    let members = match get_group_members(&group).await? {
+        Some(members) => members,
+        None => {
+            return Err(Error::InvalidState(format!(
+                "Cannot add {} as the user group {} does not exist",
+                user, group
+            )));
+        }
+    };
+
+    if members.iter().any(|member| member == user.local_user()) {
+        tracing::info!("{} is already in user group {}", user.local_user(), group);
+        return Ok(());
+    }
+
+    bconf(&[
+        "addmember",
+        &format!("usergroup={}", group),
+        &format!("GROUP_MEMBER={}", user.local_user()),
+    ])
+    .await?;
+
+    tracing::info!("Added {} to user group {}", user.local_user(), group);
+
+    Ok(())
+}
+
+///
+/// Remove the user from their project's user group, and kill their
+/// pending jobs in the project
+///
+pub async fn remove_user(user: &UserMapping, expires: &chrono::DateTime<Utc>) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let group = group_name(user.local_group())?;
+
+    let members = match get_group_members(&group).await? {
+        Some(members) => members,
+        None => {
+            tracing::warn!("User group {} for {} does not exist", group, user);
+            return Ok(());
+        }
+    };
+
+    if members.iter().any(|member| member == user.local_user()) {
+        bconf(&[
+            "rmmember",
+            &format!("usergroup={}", group),
+            &format!("GROUP_MEMBER={}", user.local_user()),
+        ])
+        .await?;
+    }
+
+    kill_pending_jobs(&group, Some(user.local_user())).await?;
+
+    tracing::info!(
+        "Removed {} from user group {} and killed their pending jobs",
+        user.local_user(),
+        group
+    );
+
+    Ok(())
+}
+
+///
+/// Block the user group by giving it a limit of zero slots, so that
+/// none of its pending jobs are dispatched. Running jobs are not affected.
+///
+pub async fn block_group(group: &str) -> Result<(), Error> {
+    let limit = block_limit_name(group);
+    let members = format!("USERS={}", group);
+
+    // update the limit if it already exists
+    if bconf(&["update", &format!("limit={}", limit), &members, "SLOTS=0"])
+        .await
+        .is_err()
+    {
+        bconf(&[
+            "create",
+            &format!("limit={}", limit),
+            &format!("{};SLOTS=0", members),
+        ])
+        .await?;
+    }
+
+    Ok(())
+}
+
+///
+/// Remove the limit that blocks the user group. This does nothing if
+/// the group is not blocked.
+///
+pub async fn unblock_group(group: &str) -> Result<(), Error> {
+    let commands = get_commands()?;
+
+    let (code, stdout, stderr) = run_command(
+        &commands.bconf,
+        &["delete", &format!("limit={}", block_limit_name(group))],
+    )
+    .await?;
+
+    if code != 0 {
+        tracing::debug!(
+            "No blocking limit to remove for {}: {} {}",
+            group,
+            stdout.trim(),
+            stderr.trim()
+        );
+    }
+
+    Ok(())
+}
+
+///
+/// Kill all pending jobs charged to the project, optionally only those
+/// of the passed user
+///
+async fn kill_pending_jobs(group: &str, user: Option<&str>) -> Result<(), Error> {
+    let commands = get_commands()?;
+
+    let args = vec![
+        "-u",
+        user.unwrap_or("all"),
+        "-P",
+        group,
+        "-stat",
+        "pend",
+        "0",
+    ];
+
+    let (code, _, stderr) = run_command(&commands.bkill, &args).await?;
+
+    // bkill exits with an error if there were no jobs to kill
+    if code != 0
+        && !stderr.contains("No unfinished job found")
+        && !stderr.contains("No matching job found")
+    {
+        tracing::warn!(
+            "Could not kill all pending jobs in {}: {}",
+            group,
+            stderr.trim()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::Once;
+    use templemeads::grammar::{ProjectIdentifier, UserIdentifier};
+
+    static FAKE_LSF: Once = Once::new();
+
+    ///
+    /// Return the directory of a fake LSF cluster, used by the tests of
+    /// all modules. User groups are stored as "<group>.group" files
+    /// holding their members, limits as "<limit>.limit" files, and the
+    /// bacct output of a group is read from "<group>.bacct". Every
+    /// command is logged to "lsf.log", and any group containing
+    /// "broken" fails.
+    ///
+    pub(crate) fn fake_lsf() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("op-lsf-test-{}", std::process::id()));
+
+        FAKE_LSF.call_once(|| {
+            let _ = std::fs::remove_dir_all(&dir);
+            let _ = std::fs::create_dir_all(&dir);
+
+            let script = format!(
+                r#"dir={dir}
+cmd=$1; shift
+echo "$cmd $*" >> $dir/lsf.log
+case "$*" in *broken*) echo "$cmd: Permission denied" >&2; exit 255 ;; esac
+case "$cmd" in
+  bugroup)
+    [ -f $dir/$2.group ] || {{ echo "$2: No such user/host group" >&2; exit 255; }}
+    echo "GROUP_NAME    USERS"
+    echo "$2    $(cat $dir/$2.group) "
+    ;;
+  bconf)
+    name=${{2#*=}}
+    member=${{3#GROUP_MEMBER=}}
+    case "$1 ${{2%%=*}}" in
+      "create usergroup") touch $dir/$name.group ;;
+      "addmember usergroup") echo "$(cat $dir/$name.group) $member" > $dir/$name.group ;;
+      "rmmember usergroup") sed -e "s/ *\b$member\b//" $dir/$name.group > $dir/$name.tmp; mv $dir/$name.tmp $dir/$name.group ;;
+      "create limit") touch $dir/$name.limit ;;
+      "update limit") [ -f $dir/$name.limit ] || echo "bconf update failed: limit $name does not exist" ;;
+      "delete limit") [ -f $dir/$name.limit ] || exit 255; rm $dir/$name.limit ;;
+    esac
+    ;;
+  bkill) echo "No unfinished job found" >&2; exit 255 ;;
+  bacct)
+    [ -f $dir/$6.bacct ] || {{ echo "No matching job found" >&2; exit 255; }}
+    cat $dir/$6.bacct
+    ;;
+esac
+"#,
+                dir = dir.display()
+            );
+
+            let _ = std::fs::write(dir.join("lsf.sh"), script);
+
+            let command = |name: &str| format!("sh {} {}", dir.join("lsf.sh").display(), name);
+
+            let _ = initialise_commands(Commands::new(
+                &command("bconf"),
+                &command("bugroup"),
+                &command("bkill"),
+                &command("bacct"),
+            ));
+        });
+
+        dir
+    }
+
+    ///
+    /// Return the lines of the fake LSF log that contain `text`
+    ///
+    pub(crate) fn logged(text: &str) -> Vec<String> {
+        std::fs::read_to_string(fake_lsf().join("lsf.log"))
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| line.contains(text))
+            .map(|line| line.to_owned())
+            .collect()
+    }
+
+    pub(crate) fn project(name: &str) -> ProjectMapping {
+        ProjectMapping::new(
+            &ProjectIdentifier::parse(&format!("{}.portal", name))
+                .unwrap_or_else(|e| unreachable!("Cannot parse project: {}", e)),
+            name,
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e))
+    }
+
+    fn user(name: &str, project: &str) -> UserMapping {
+        UserMapping::new(
+            &UserIdentifier::parse(&format!("{}.{}.portal", name, project))
+                .unwrap_or_else(|e| unreachable!("Cannot parse user: {}", e)),
+            name,
+            project,
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e))
+    }
+
+    fn expires() -> chrono::DateTime<Utc> {
+        Utc::now() + chrono::Duration::seconds(30)
+    }
+
+    async fn members(group: &str) -> Option<Vec<String>> {
+        get_group_members(group)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot get members: {}", e))
+    }
+
+    #[test]
+    fn test_group_name() {
+        assert!(matches!(group_name(" proj ").as_deref(), Ok("proj")));
+        assert!(matches!(group_name("a.b c/d").as_deref(), Ok("a.b_c_d")));
+        assert!(matches!(group_name("  "), Err(Error::Parse(_))));
+        assert_eq!(block_limit_name("proj"), "op_block_proj");
+    }
+
+    #[tokio::test]
+    async fn test_projects_and_users() {
+        fake_lsf();
+
+        let project = project("bconf");
+        let alice = user("alice", "bconf");
+        let bob = user("bob", "bconf");
+
+        // users can only be added to projects that exist
+        assert!(matches!(
+            add_user(&alice, &expires()).await,
+            Err(Error::InvalidState(_))
+        ));
+
+        add_project(&project, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add project: {}", e));
+
+        assert_eq!(members("bconf").await, Some(vec![]));
+
+        for user in [&alice, &bob, &alice] {
+            add_user(user, &expires())
+                .await
+                .unwrap_or_else(|e| unreachable!("Cannot add user: {}", e));
+        }
+
+        assert_eq!(
+            members("bconf").await,
+            Some(vec!["alice".to_owned(), "bob".to_owned()])
+        );
+
+        remove_user(&alice, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove user: {}", e));
+
+        assert_eq!(members("bconf").await, Some(vec!["bob".to_owned()]));
+
+        // removing the project blocks it, creating the limit the first time
+        remove_project(&project, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove project: {}", e));
+
+        assert!(fake_lsf().join("op_block_bconf.limit").exists());
+
+        remove_project(&project, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove project: {}", e));
+
+        // adding it again unblocks it, and keeps its members
+        add_project(&project, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add project: {}", e));
+
+        assert!(!fake_lsf().join("op_block_bconf.limit").exists());
+        assert_eq!(members("bconf").await, Some(vec!["bob".to_owned()]));
+
+        assert_eq!(
+            logged("usergroup=bconf"),
+            [
+                "bconf create usergroup=bconf GROUP_MEMBER=()",
+                "bconf addmember usergroup=bconf GROUP_MEMBER=alice",
+                "bconf addmember usergroup=bconf GROUP_MEMBER=bob",
+                "bconf rmmember usergroup=bconf GROUP_MEMBER=alice",
+            ]
+        );
+
+        assert_eq!(
+            logged("limit=op_block_bconf"),
+            [
+                "bconf delete limit=op_block_bconf",
+                "bconf update limit=op_block_bconf USERS=bconf SLOTS=0",
+                "bconf create limit=op_block_bconf USERS=bconf;SLOTS=0",
+                "bconf update limit=op_block_bconf USERS=bconf SLOTS=0",
+                "bconf delete limit=op_block_bconf",
+            ]
+        );
+
+        // pending jobs are killed, even though there were none
+        assert_eq!(
+            logged("-P bconf"),
+            [
+                "bkill -u alice -P bconf -stat pend 0",
+                "bkill -u all -P bconf -stat pend 0",
+                "bkill -u all -P bconf -stat pend 0",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        fake_lsf();
+
+        // the fake bugroup fails for this group, so it looks like it
+        // does not exist...
+        assert_eq!(members("broken").await, None);
+
+        // ...and it cannot be created
+        assert!(matches!(
+            add_project(&project("broken"), &expires()).await,
+            Err(Error::Call(message)) if message.contains("Permission denied")
+        ));
+
+        // bconf failures reported on stdout are also errors
+        assert!(matches!(
+            bconf(&["update", "limit=missing", "USERS=missing", "SLOTS=0"]).await,
+            Err(Error::Call(message)) if message.contains("does not exist")
+        ));
+
+        // bacct is only an error if it fails for a reason other than
+        // there being no jobs
+        assert!(bacct(&["-P", "x", "-u", "all", "-P", "nojobs"])
+            .await
+            .is_ok_and(|output| output.is_empty()));
+        assert!(matches!(
+            bacct(&["-P", "x", "-u", "all", "-P", "broken"]).await,
+            Err(Error::Call(_))
+        ));
+
+        // removing a project or user that doesn't exist is not an error
+        assert!(remove_project(&project("missing"), &expires())
+            .await
+            .is_ok());
+        assert!(remove_user(&user("alice", "missing"), &expires())
+            .await
+            .is_ok());
+
+        let expired = Utc::now() - chrono::Duration::seconds(1);
+
+        assert!(matches!(
+            add_project(&project("bconf"), &expired).await,
+            Err(Error::Expired(_))
+        ));
+        assert!(matches!(
+            add_user(&user("alice", "bconf"), &expired).await,
+            Err(Error::Expired(_))
+        ));
+    }
+}
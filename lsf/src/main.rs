@@ -0,0 +1,182 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+
+use templemeads::agent::scheduler::{process_args, run, Defaults};
+use templemeads::agent::Type as AgentType;
+use templemeads::async_runnable;
+use templemeads::grammar::Instruction::{
+    AddLocalProject, AddLocalUser, GetLocalLimit, GetLocalUsageReport, RemoveLocalProject,
+    RemoveLocalUser, SetLocalLimit,
+};
+use templemeads::job::{Envelope, Job};
+use templemeads::notification::default_notify_runner;
+use templemeads::set_notify_runner;
+use templemeads::Error;
+
+mod bacct;
+mod banking;
+mod bconf;
+
+///
+/// Main function for the lsf scheduler agent
+///
+/// This agent manages projects and users in IBM Spectrum LSF, so that
+/// LSF sites can join an OpenPortal deployment. Each project is an LSF
+/// user group, managed with live reconfiguration (bconf), and jobs are
+/// charged to the LSF project of the same name. Usage is read with bacct,
+/// and each project's limit is enforced by blocking its user group once
+/// the limit has been used.
+///
+#[tokio::main]
+async fn main() -> Result<()> {
+    // start tracing
+    templemeads::config::initialise_tracing();
+
+    // start system monitoring
+    templemeads::spawn_system_monitor();
+
+    // create the OpenPortal paddington defaults
+    let defaults: Defaults = Defaults::parse(
+        Some("lsf".to_owned()),
+        Some(
+            dirs::config_local_dir()
+                .unwrap_or(
+                    ".".parse()
+                        .expect("Could not parse fallback config directory."),
+                )
+                .join("openportal")
+                .join("lsf-config.toml"),
+        ),
+        Some("ws://localhost:8048".to_owned()),
+        Some("127.0.0.1".to_owned()),
+        Some(8048),
+        None,
+        None,
+        Some(AgentType::Scheduler),
+    );
+
+    // now parse the command line arguments to get the service configuration
+    let config = match process_args(&defaults).await? {
+        Some(config) => config,
+        None => {
+            // Not running the service, so can safely exit
+            return Ok(());
+        }
+    };
+
+    // Read the configurable command strings. Each defaults to the standard
+    // LSF binary name, which works when running as an LSF administrator on
+    // an LSF host. To run them remotely, set e.g.:
+    //   bconf = "ssh lsf-master bconf"
+    bconf::initialise_commands(bconf::Commands::new(
+        &config.option("bconf", "bconf"),
+        &config.option("bugroup", "bugroup"),
+        &config.option("bkill", "bkill"),
+        &config.option("bacct", "bacct"),
+    ))?;
+
+    // the number of job slots in a node, used to convert slot-seconds
+    // into node-seconds
+    let slots_per_node: u64 = config
+        .option("slots-per-node", "1")
+        .parse()
+        .map_err(|e| anyhow::anyhow!("Invalid slots-per-node: {}", e))?;
+
+    bacct::set_slots_per_node(slots_per_node).await?;
+
+    // the file in which the limit of each project is saved
+    banking::set_limits_file(
+        &config.option(
+            "limits-file",
+            &dirs::config_local_dir()
+                .unwrap_or(
+                    ".".parse()
+                        .expect("Could not parse fallback config directory."),
+                )
+                .join("openportal")
+                .join("lsf-limits.json")
+                .to_string_lossy(),
+        ),
+    )
+    .await?;
+
+    // get the interval (in seconds) between checks of each project's
+    // usage against its limit. Limits are not enforced if this is zero
+    let banking_interval: u64 = config
+        .option("banking-interval", "3600")
+        .parse()
+        .unwrap_or(3600);
+
+    if banking_interval > 0 {
+        banking::spawn_banking_task(banking_interval);
+    }
+
+    async_runnable! {
+        ///
+        /// Runnable function that will be called when a job is received
+        /// by the agent
+        ///
+        pub async fn lsf_runner(envelope: Envelope) -> Result<Job, templemeads::Error>
+        {
+            let job = envelope.job();
+
+            match job.instruction() {
+                AddLocalProject(project) => {
+                    bconf::add_project(&project, job.expires()).await?;
+                    job.completed_none()
+                },
+                RemoveLocalProject(project) => {
+                    // the user group is blocked rather than deleted, so
+                    // that the project can be restored
+                    bconf::remove_project(&project, job.expires()).await?;
+                    banking::remove_limit(&project).await?;
+                    job.completed_none()
+                },
+                AddLocalUser(user) => {
+                    bconf::add_user(&user, job.expires()).await?;
+                    job.completed_none()
+                },
+                RemoveLocalUser(mapping) => {
+                    bconf::remove_user(&mapping, job.expires()).await?;
+                    job.completed_none()
+                },
+                GetLocalUsageReport(mapping, dates) => {
+                    let report = bacct::get_usage_report(&mapping, &dates, job.expires()).await?;
+                    job.completed(report)
+                },
+                GetLocalLimit(mapping, partition) => {
+                    if partition.is_some() {
+                        return Err(Error::InvalidInstruction(
+                            "LSF agents do not support partition limits".to_owned(),
+                        ));
+                    }
+
+                    let limit = banking::get_limit(&mapping, job.expires()).await?;
+                    job.completed(limit)
+                },
+                SetLocalLimit(mapping, limit, partition) => {
+                    if partition.is_some() {
+                        return Err(Error::InvalidInstruction(
+                            "LSF agents do not support partition limits".to_owned(),
+                        ));
+                    }
+
+                    let limit = banking::set_limit(&mapping, &limit, &job.destination().reverse(), job.expires()).await?;
+                    job.completed(limit)
+                },
+                _ => {
+                    Err(Error::InvalidInstruction(
+                        format!("Invalid instruction: {}. LSF agents do not support this instruction", job.instruction()),
+                    ))
+                }
+            }
+        }
+    }
+
+    set_notify_runner(default_notify_runner).await?;
+    run(config, lsf_runner).await?;
+
+    Ok(())
+}
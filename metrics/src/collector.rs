@@ -0,0 +1,582 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use once_cell::sync::Lazy;
+use std::time::Instant;
+use templemeads::diagnostics;
+use templemeads::health::{self, HealthInfo};
+use templemeads::Error;
+use tokio::sync::RwLock;
+
+use crate::publish;
+
+/// The most recently collected metrics, in the Prometheus text format
+static LATEST: Lazy<RwLock<String>> = Lazy::new(|| RwLock::new(String::new()));
+
+///
+/// A metric and its samples
+///
+struct Family {
+    name: &'static str,
+    help: &'static str,
+    kind: &'static str,
+    samples: Vec<String>,
+}
+
+///
+/// A set of metrics, rendered in the Prometheus text exposition format
+///
+#[derive(Default)]
+struct Metrics {
+    families: Vec<Family>,
+}
+
+///
+/// Escape a label value as required by the text exposition format
+///
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Metrics {
+    fn add(
+        &mut self,
+        name: &'static str,
+        help: &'static str,
+        kind: &'static str,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) {
+        let labels = labels
+            .iter()
+            .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let sample = match labels.is_empty() {
+            true => format!("{} {}", name, value),
+            false => format!("{}{{{}}} {}", name, labels, value),
+        };
+
+        match self.families.iter_mut().find(|f| f.name == name) {
+            Some(family) => family.samples.push(sample),
+            None => self.families.push(Family {
+                name,
+                help,
+                kind,
+                samples: vec![sample],
+            }),
+        }
+    }
+
+    fn gauge(
+        &mut self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) {
+        self.add(name, help, "gauge", labels, value);
+    }
+
+    fn counter(
+        &mut self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) {
+        self.add(name, help, "counter", labels, value);
+    }
+
+    fn render(&self) -> String {
+        let mut text = String::new();
+
+        for family in &self.families {
+            text.push_str(&format!("# HELP {} {}\n", family.name, family.help));
+            text.push_str(&format!("# TYPE {} {}\n", family.name, family.kind));
+
+            for sample in &family.samples {
+                text.push_str(sample);
+                text.push('\n');
+            }
+        }
+
+        text
+    }
+}
+
+///
+/// Return the dot-separated path from this agent to each agent in the
+/// health tree, together with that agent's health
+///
+fn flatten(health: &HealthInfo, prefix: &str, agents: &mut Vec<(String, HealthInfo)>) {
+    let mut names = health.keys();
+    names.sort();
+
+    for name in names {
+        if let Some(peer) = health.get(&name) {
+            let path = match prefix.is_empty() {
+                true => name.clone(),
+                false => format!("{}.{}", prefix, name),
+            };
+
+            flatten(&peer, &path, agents);
+            agents.push((path, peer));
+        }
+    }
+}
+
+///
+/// Add the metrics of the agent's health
+///
+fn add_health(metrics: &mut Metrics, path: &str, health: &HealthInfo) {
+    let agent_type = health.agent_type.to_string();
+    let agent = [("agent", path)];
+
+    metrics.gauge(
+        "openportal_agent_info",
+        "Information about each agent, always 1",
+        &[
+            ("agent", path),
+            ("type", &agent_type),
+            ("engine", &health.engine),
+            ("version", &health.version),
+        ],
+        1.0,
+    );
+
+    metrics.gauge(
+        "openportal_agent_up",
+        "Whether the agent is connected (1) or not (0)",
+        &agent,
+        if health.connected { 1.0 } else { 0.0 },
+    );
+
+    // a disconnected agent only reports its last known details
+    if !health.connected {
+        return;
+    }
+
+    for (state, count) in [
+        ("active", health.active_jobs),
+        ("pending", health.pending_jobs),
+        ("running", health.running_jobs),
+        ("completed", health.completed_jobs),
+        ("duplicate", health.duplicate_jobs),
+        ("successful", health.successful_jobs),
+        ("expired", health.expired_jobs),
+        ("errored", health.errored_jobs),
+        ("inflight", health.inflight_jobs),
+        ("queued", health.queued_jobs),
+    ] {
+        metrics.gauge(
+            "openportal_agent_board_jobs",
+            "Number of jobs on the agent's boards, by state",
+            &[("agent", path), ("state", state)],
+            count as f64,
+        );
+    }
+
+    for (result, count) in [
+        ("completed", health.total_completed),
+        ("failed", health.total_failed),
+        ("expired", health.total_expired),
+        ("slow", health.total_slow),
+    ] {
+        metrics.counter(
+            "openportal_agent_jobs_total",
+            "Number of jobs run by the agent since it started, by result",
+            &[("agent", path), ("result", result)],
+            count as f64,
+        );
+    }
+
+    for (statistic, value) in [
+        ("min", health.job_time_min_ms),
+        ("max", health.job_time_max_ms),
+        ("mean", health.job_time_mean_ms),
+        ("median", health.job_time_median_ms),
+    ] {
+        metrics.gauge(
+            "openportal_agent_job_time_milliseconds",
+            "Job execution time on the agent, by statistic",
+            &[("agent", path), ("statistic", statistic)],
+            value,
+        );
+    }
+
+    metrics.gauge(
+        "openportal_agent_timed_jobs",
+        "Number of jobs whose execution time was measured",
+        &agent,
+        health.job_time_count as f64,
+    );
+
+    metrics.gauge(
+        "openportal_agent_workers",
+        "Number of active worker tasks processing messages",
+        &agent,
+        health.worker_count as f64,
+    );
+
+    metrics.counter(
+        "openportal_agent_replays_rejected_total",
+        "Number of replayed messages rejected since the agent started",
+        &agent,
+        health.replays_rejected as f64,
+    );
+
+    metrics.gauge(
+        "openportal_agent_memory_bytes",
+        "Memory used by the agent process",
+        &agent,
+        health.memory_bytes as f64,
+    );
+
+    metrics.gauge(
+        "openportal_agent_cpu_percent",
+        "CPU used by the agent process, as a percentage",
+        &agent,
+        health.cpu_percent as f64,
+    );
+
+    metrics.gauge(
+        "openportal_agent_system_memory_bytes",
+        "Total memory of the agent's system",
+        &agent,
+        health.system_memory_total as f64,
+    );
+
+    metrics.gauge(
+        "openportal_agent_system_cpus",
+        "Number of CPU cores of the agent's system",
+        &agent,
+        health.system_cpus as f64,
+    );
+
+    metrics.gauge(
+        "openportal_agent_uptime_seconds",
+        "Seconds since the agent started",
+        &agent,
+        health.uptime_seconds as f64,
+    );
+
+    metrics.gauge(
+        "openportal_agent_warnings",
+        "Number of warnings raised in the agent's health",
+        &agent,
+        health.warnings.len() as f64,
+    );
+
+    for breaker in &health.breakers {
+        metrics.gauge(
+            "openportal_agent_circuit_breaker_open",
+            "Whether the circuit breaker for a third-party service is open or half-open (1) or closed (0)",
+            &[("agent", path), ("breaker", &breaker.name)],
+            match breaker.state.as_str() {
+                "closed" => 0.0,
+                _ => 1.0,
+            },
+        );
+
+        metrics.counter(
+            "openportal_agent_circuit_breaker_rejected_total",
+            "Number of calls rejected by the circuit breaker for a third-party service",
+            &[("agent", path), ("breaker", &breaker.name)],
+            breaker.rejected as f64,
+        );
+    }
+}
+
+///
+/// Add the metrics of the agent's diagnostics
+///
+async fn add_diagnostics(metrics: &mut Metrics, path: &str, destination: &str) {
+    let report = match diagnostics::collect_diagnostics(destination).await {
+        Ok(report) => report,
+        Err(e) => {
+            tracing::warn!("Could not get the diagnostics of {}: {}", path, e);
+            return;
+        }
+    };
+
+    let agent = [("agent", path)];
+
+    metrics.gauge(
+        "openportal_agent_failed_jobs",
+        "Number of distinct recent job failures in the agent's diagnostics",
+        &agent,
+        report.failed_jobs.len() as f64,
+    );
+
+    metrics.gauge(
+        "openportal_agent_expired_jobs",
+        "Number of distinct recent job expiries in the agent's diagnostics",
+        &agent,
+        report.expired_jobs.len() as f64,
+    );
+
+    metrics.gauge(
+        "openportal_agent_running_jobs",
+        "Number of jobs running on the agent, from its diagnostics",
+        &agent,
+        report.running_jobs.iter().map(|j| j.count).sum::<usize>() as f64,
+    );
+
+    metrics.gauge(
+        "openportal_agent_diagnostic_warnings",
+        "Number of warnings in the agent's diagnostics",
+        &agent,
+        report.warnings.len() as f64,
+    );
+
+    let statistics = &report.notification_statistics;
+
+    for (result, count) in [
+        ("received", statistics.total_received),
+        ("sent", statistics.total_sent),
+        ("failed", statistics.total_failed),
+    ] {
+        metrics.counter(
+            "openportal_agent_notifications_total",
+            "Number of notifications handled by the agent since it started, by result",
+            &[("agent", path), ("result", result)],
+            count as f64,
+        );
+    }
+}
+
+///
+/// Collect the health, and optionally the diagnostics, of every agent
+/// that can be reached, and publish them as metrics
+///
+pub async fn collect(with_diagnostics: bool) -> Result<(), Error> {
+    let started = Instant::now();
+    let mut metrics = Metrics::default();
+
+    let success = match health::collect_health("", vec![]).await {
+        Ok(health) => {
+            let mut agents = Vec::new();
+            flatten(&health, "", &mut agents);
+
+            // this agent is labelled with its own name, and is the
+            // target of the empty diagnostics path
+            add_health(&mut metrics, &health.name, &health);
+
+            for (path, health) in &agents {
+                add_health(&mut metrics, path, health);
+            }
+
+            if with_diagnostics {
+                add_diagnostics(&mut metrics, &health.name, "").await;
+
+                for (path, _) in agents.iter().filter(|(_, health)| health.connected) {
+                    add_diagnostics(&mut metrics, path, path).await;
+                }
+            }
+
+            true
+        }
+        Err(e) => {
+            tracing::error!("Could not collect health: {}", e);
+            false
+        }
+    };
+
+    metrics.gauge(
+        "openportal_collect_success",
+        "Whether the last collection succeeded (1) or not (0)",
+        &[],
+        if success { 1.0 } else { 0.0 },
+    );
+
+    metrics.gauge(
+        "openportal_collect_duration_seconds",
+        "Seconds taken by the last collection",
+        &[],
+        started.elapsed().as_secs_f64(),
+    );
+
+    metrics.gauge(
+        "openportal_collect_timestamp_seconds",
+        "Time of the last collection, in seconds since the Unix epoch",
+        &[],
+        chrono::Utc::now().timestamp() as f64,
+    );
+
+    let text = metrics.render();
+    *LATEST.write().await = text.clone();
+
+    publish::push(&text).await
+}
+
+///
+/// Return the most recently collected metrics
+///
+pub async fn latest() -> String {
+    LATEST.read().await.clone()
+}
+
+///
+/// Spawn a background task that collects metrics every `interval`
+/// seconds
+///
+pub fn spawn_collector(interval: u64, with_diagnostics: bool) {
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = collect(with_diagnostics).await {
+                tracing::error!("Failed to publish metrics: {}", e);
+            }
+
+            tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use templemeads::agent::Type as AgentType;
+    use templemeads::health::BreakerStatus;
+
+    fn health(name: &str, connected: bool) -> HealthInfo {
+        HealthInfo::new(
+            name,
+            AgentType::Instance,
+            connected,
+            chrono::Utc::now(),
+            "templemeads",
+            "1.0",
+        )
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("plain"), "plain");
+        assert_eq!(escape("a\"b"), "a\\\"b");
+        assert_eq!(escape("a\\b"), "a\\\\b");
+        assert_eq!(escape("a\nb"), "a\\nb");
+    }
+
+    #[test]
+    fn test_render() {
+        let mut metrics = Metrics::default();
+
+        metrics.gauge("up", "Is it up", &[("agent", "a")], 1.0);
+        metrics.counter("jobs_total", "Jobs run", &[], 3.0);
+        metrics.gauge("up", "Is it up", &[("agent", "b\"c")], 0.0);
+
+        // samples of the same metric share a single HELP and TYPE
+        assert_eq!(
+            metrics.render(),
+            "# HELP up Is it up\n\
+             # TYPE up gauge\n\
+             up{agent=\"a\"} 1\n\
+             up{agent=\"b\\\"c\"} 0\n\
+             # HELP jobs_total Jobs run\n\
+             # TYPE jobs_total counter\n\
+             jobs_total 3\n"
+        );
+
+        assert!(Metrics::default().render().is_empty());
+    }
+
+    #[test]
+    fn test_flatten() {
+        let mut cluster = health("cluster", true);
+        cluster.add_peer_health(health("slurm", true));
+        cluster.add_peer_health(health("freeipa", false));
+
+        let mut portal = health("portal", true);
+        portal.add_peer_health(cluster);
+        portal.add_peer_health(health("bridge", true));
+
+        let mut agents = Vec::new();
+        flatten(&portal, "", &mut agents);
+
+        let paths: Vec<&str> = agents.iter().map(|(path, _)| path.as_str()).collect();
+
+        assert_eq!(
+            paths,
+            vec!["bridge", "cluster.freeipa", "cluster.slurm", "cluster"]
+        );
+
+        assert!(agents
+            .iter()
+            .any(|(path, health)| path == "cluster.freeipa" && !health.connected));
+
+        let mut agents = Vec::new();
+        flatten(&health("alone", true), "", &mut agents);
+        assert!(agents.is_empty());
+    }
+
+    #[test]
+    fn test_add_health() {
+        let mut connected = health("cluster", true);
+        connected.running_jobs = 4;
+        connected.total_failed = 2;
+        connected.warnings = vec!["board is backed up".to_owned()];
+        connected.breakers = vec![
+            BreakerStatus {
+                name: "slurmrestd".to_owned(),
+                state: "open".to_owned(),
+                failures: 5,
+                last_error: "timeout".to_owned(),
+                opened_at: Some(chrono::Utc::now()),
+                rejected: 7,
+            },
+            BreakerStatus {
+                name: "freeipa".to_owned(),
+                state: "closed".to_owned(),
+                failures: 0,
+                last_error: String::new(),
+                opened_at: None,
+                rejected: 0,
+            },
+        ];
+
+        let mut metrics = Metrics::default();
+        add_health(&mut metrics, "portal.cluster", &connected);
+        let text = metrics.render();
+
+        assert!(text.contains(
+            "openportal_agent_info{agent=\"portal.cluster\",type=\"instance\",engine=\"templemeads\",version=\"1.0\"} 1\n"
+        ));
+        assert!(text.contains("openportal_agent_up{agent=\"portal.cluster\"} 1\n"));
+        assert!(text.contains(
+            "openportal_agent_board_jobs{agent=\"portal.cluster\",state=\"running\"} 4\n"
+        ));
+        assert!(text.contains(
+            "openportal_agent_jobs_total{agent=\"portal.cluster\",result=\"failed\"} 2\n"
+        ));
+        assert!(text.contains("openportal_agent_warnings{agent=\"portal.cluster\"} 1\n"));
+        assert!(text.contains(
+            "openportal_agent_circuit_breaker_open{agent=\"portal.cluster\",breaker=\"slurmrestd\"} 1\n"
+        ));
+        assert!(text.contains(
+            "openportal_agent_circuit_breaker_open{agent=\"portal.cluster\",breaker=\"freeipa\"} 0\n"
+        ));
+        assert!(text.contains(
+            "openportal_agent_circuit_breaker_rejected_total{agent=\"portal.cluster\",breaker=\"slurmrestd\"} 7\n"
+        ));
+        assert_eq!(
+            text.matches("# TYPE openportal_agent_board_jobs gauge")
+                .count(),
+            1
+        );
+
+        // a disconnected agent only reports its details and that it is down
+        let mut metrics = Metrics::default();
+        add_health(&mut metrics, "portal.slurm", &health("slurm", false));
+        let text = metrics.render();
+
+        assert!(text.contains("openportal_agent_up{agent=\"portal.slurm\"} 0\n"));
+        assert!(text.contains("openportal_agent_info{agent=\"portal.slurm\""));
+        assert!(!text.contains("openportal_agent_board_jobs"));
+        assert!(!text.contains("openportal_agent_memory_bytes"));
+    }
+}
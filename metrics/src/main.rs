@@ -0,0 +1,132 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+use std::net::IpAddr;
+
+use templemeads::agent::monitor::{process_args, run, Defaults};
+use templemeads::agent::Type as AgentType;
+use templemeads::async_runnable;
+use templemeads::job::{Envelope, Job};
+use templemeads::notification::default_notify_runner;
+use templemeads::set_notify_runner;
+use templemeads::Error;
+
+mod collector;
+mod publish;
+
+///
+/// Main function for the metrics exporter agent
+///
+/// This agent connects to another agent (normally the portal) and
+/// periodically collects the health and diagnostics of every agent that
+/// it can reach. These are served as Prometheus metrics, and/or pushed
+/// to a Prometheus Pushgateway, so that the network can be monitored
+/// without scraping the bridge.
+///
+#[tokio::main]
+async fn main() -> Result<()> {
+    // start tracing
+    templemeads::config::initialise_tracing();
+
+    // start system monitoring
+    templemeads::spawn_system_monitor();
+
+    // create the OpenPortal paddington defaults
+    let defaults: Defaults = Defaults::parse(
+        Some("metrics".to_owned()),
+        Some(
+            dirs::config_local_dir()
+                .unwrap_or(
+                    ".".parse()
+                        .expect("Could not parse fallback config directory."),
+                )
+                .join("openportal")
+                .join("metrics-config.toml"),
+        ),
+        Some("ws://localhost:8054".to_owned()),
+        Some("127.0.0.1".to_owned()),
+        Some(8054),
+        None,
+        None,
+        Some(AgentType::Monitor),
+    );
+
+    // now parse the command line arguments to get the service configuration
+    let config = match process_args(&defaults).await? {
+        Some(config) => config,
+        None => {
+            // Not running the service, so can safely exit
+            return Ok(());
+        }
+    };
+
+    // the port of the /metrics endpoint, or 0 to only push metrics
+    let metrics_port: u16 = config
+        .option("metrics-port", "9464")
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid metrics-port"))?;
+
+    let pushgateway_url = config.option("pushgateway-url", "");
+
+    if metrics_port == 0 && pushgateway_url.trim().is_empty() {
+        return Err(anyhow::anyhow!(
+            "Metrics are neither served nor pushed. Please set metrics-port \
+             and/or pushgateway-url."
+        ));
+    }
+
+    let pushgateway = match pushgateway_url.trim().is_empty() {
+        true => None,
+        false => Some(publish::Pushgateway::new(
+            &pushgateway_url,
+            &config.option("pushgateway-job", "openportal"),
+            &config.service().name(),
+            config.secret("pushgateway-token"),
+        )?),
+    };
+
+    publish::initialise_pushgateway(pushgateway)?;
+
+    if metrics_port != 0 {
+        let metrics_ip: IpAddr = config
+            .option("metrics-ip", "127.0.0.1")
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid metrics-ip"))?;
+
+        publish::spawn_server(metrics_ip, metrics_port).await?;
+    }
+
+    // get the interval (in seconds) between collections
+    let poll_interval: u64 = config
+        .option("poll-interval", "60")
+        .parse()
+        .unwrap_or(60)
+        .max(1);
+
+    // diagnostics are larger than health, so can be turned off on big
+    // networks
+    let with_diagnostics = config.option("diagnostics", "true").to_lowercase() == "true";
+
+    collector::spawn_collector(poll_interval, with_diagnostics);
+
+    async_runnable! {
+        ///
+        /// Runnable function that will be called when a job is received
+        /// by the agent
+        ///
+        pub async fn metrics_runner(envelope: Envelope) -> Result<Job, templemeads::Error>
+        {
+            let job = envelope.job();
+
+            Err(Error::InvalidInstruction(
+                format!("Invalid instruction: {}. Metrics agents do not accept instructions", job.instruction()),
+            ))
+        }
+    }
+
+    set_notify_runner(default_notify_runner).await?;
+    run(config, metrics_runner).await?;
+
+    Ok(())
+}
@@ -0,0 +1,306 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use once_cell::sync::OnceCell;
+use secrecy::{ExposeSecret, SecretString};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use templemeads::Error;
+
+use crate::collector;
+
+/// The content type of the Prometheus text exposition format
+const CONTENT_TYPE_TEXT: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+///
+/// A Prometheus Pushgateway to which the metrics are pushed after each
+/// collection
+///
+pub struct Pushgateway {
+    url: String,
+    token: Option<SecretString>,
+    client: reqwest::Client,
+}
+
+static PUSHGATEWAY: OnceCell<Option<Pushgateway>> = OnceCell::new();
+
+///
+/// Percent-encode the passed string so that it can be used as a
+/// segment of a URL path
+///
+fn encode_path_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+impl Pushgateway {
+    ///
+    /// Create the Pushgateway, grouping the metrics under the passed job
+    /// and instance
+    ///
+    pub fn new(
+        url: &str,
+        job: &str,
+        instance: &str,
+        token: Option<SecretString>,
+    ) -> Result<Self, Error> {
+        let url = url.trim().trim_end_matches('/');
+
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(Error::Misconfigured(format!(
+                "Invalid Pushgateway URL '{}'. It must start with http:// or https://",
+                url
+            )));
+        }
+
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()
+            .map_err(|e| Error::Misconfigured(format!("Could not create HTTP client: {}", e)))?;
+
+        Ok(Self {
+            url: format!(
+                "{}/metrics/job/{}/instance/{}",
+                url,
+                encode_path_segment(job),
+                encode_path_segment(instance)
+            ),
+            token,
+            client,
+        })
+    }
+}
+
+pub fn initialise_pushgateway(pushgateway: Option<Pushgateway>) -> Result<()> {
+    if let Some(pushgateway) = &pushgateway {
+        tracing::info!("Pushing metrics to {}", pushgateway.url);
+    }
+
+    PUSHGATEWAY
+        .set(pushgateway)
+        .map_err(|_| anyhow::anyhow!("Pushgateway already initialised"))
+}
+
+///
+/// Push the metrics to the Pushgateway, if there is one. The metrics
+/// replace all of those previously pushed for this agent.
+///
+pub async fn push(metrics: &str) -> Result<(), Error> {
+    let pushgateway = match PUSHGATEWAY.get() {
+        Some(Some(pushgateway)) => pushgateway,
+        _ => return Ok(()),
+    };
+
+    let mut request = pushgateway
+        .client
+        .put(&pushgateway.url)
+        .header(reqwest::header::CONTENT_TYPE, CONTENT_TYPE_TEXT)
+        .body(metrics.to_owned());
+
+    if let Some(token) = &pushgateway.token {
+        request = request.bearer_auth(token.expose_secret());
+    }
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| Error::Call(format!("Could not push metrics: {}", e)))?;
+
+    if !response.status().is_success() {
+        return Err(Error::Call(format!(
+            "Could not push metrics: the Pushgateway returned {}",
+            response.status()
+        )));
+    }
+
+    Ok(())
+}
+
+async fn metrics() -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, CONTENT_TYPE_TEXT)],
+        collector::latest().await,
+    )
+}
+
+///
+/// Spawn the web server from which Prometheus scrapes the metrics, at
+/// /metrics
+///
+pub async fn spawn_server(ip: IpAddr, port: u16) -> Result<(), Error> {
+    let app = Router::new().route("/metrics", get(metrics));
+
+    let listener = tokio::net::TcpListener::bind(&SocketAddr::new(ip, port)).await?;
+
+    tracing::info!("Serving metrics at http://{}:{}/metrics", ip, port);
+
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            tracing::error!("Error running the metrics server: {}", e);
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    ///
+    /// Start a server that answers its requests in turn with the passed
+    /// statuses, returning its URL and a handle to the requests it received
+    ///
+    async fn serve(statuses: Vec<u16>) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot bind: {}", e));
+
+        let url = format!(
+            "http://{}",
+            listener
+                .local_addr()
+                .unwrap_or_else(|e| unreachable!("No address: {}", e))
+        );
+
+        let handle = tokio::spawn(async move {
+            let mut requests = Vec::new();
+
+            for status in statuses {
+                let (mut stream, _) = listener
+                    .accept()
+                    .await
+                    .unwrap_or_else(|e| unreachable!("Cannot accept: {}", e));
+
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 4096];
+
+                // read until the whole body (given by content-length) has arrived
+                loop {
+                    let n = stream.read(&mut buffer).await.unwrap_or(0);
+                    request.extend_from_slice(&buffer[..n]);
+
+                    let text = String::from_utf8_lossy(&request).to_string();
+
+                    if let Some((headers, body)) = text.split_once("\r\n\r\n") {
+                        let length = headers
+                            .lines()
+                            .find_map(|l| {
+                                l.to_lowercase()
+                                    .strip_prefix("content-length:")
+                                    .map(|v| v.trim().to_owned())
+                            })
+                            .and_then(|v| v.parse::<usize>().ok())
+                            .unwrap_or(0);
+
+                        if body.len() >= length {
+                            break;
+                        }
+                    }
+
+                    if n == 0 {
+                        break;
+                    }
+                }
+
+                let _ = stream
+                    .write_all(
+                        format!(
+                            "HTTP/1.1 {} Status\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                            status
+                        )
+                        .as_bytes(),
+                    )
+                    .await;
+
+                requests.push(String::from_utf8_lossy(&request).to_string());
+            }
+
+            requests
+        });
+
+        (url, handle)
+    }
+
+    #[test]
+    fn test_encode_path_segment() {
+        assert_eq!(
+            encode_path_segment("openportal-metrics_1.0~"),
+            "openportal-metrics_1.0~"
+        );
+        assert_eq!(encode_path_segment("a/b c"), "a%2Fb%20c");
+        assert_eq!(encode_path_segment("é"), "%C3%A9");
+        assert_eq!(encode_path_segment(""), "");
+    }
+
+    #[test]
+    fn test_pushgateway() {
+        let pushgateway = Pushgateway::new(" https://push:9091/ ", "openportal", "a/b", None)
+            .unwrap_or_else(|e| unreachable!("Cannot create Pushgateway: {}", e));
+
+        assert_eq!(
+            pushgateway.url,
+            "https://push:9091/metrics/job/openportal/instance/a%2Fb"
+        );
+
+        for invalid in ["", "push:9091", "ftp://push:9091"] {
+            assert!(matches!(
+                Pushgateway::new(invalid, "openportal", "metrics", None),
+                Err(Error::Misconfigured(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_push() {
+        // nothing is pushed if there is no Pushgateway
+        assert!(push("up 1\n").await.is_ok());
+
+        let (url, handle) = serve(vec![200, 500]).await;
+
+        initialise_pushgateway(Some(
+            Pushgateway::new(
+                &url,
+                "openportal",
+                "metrics",
+                Some(SecretString::from("token")),
+            )
+            .unwrap_or_else(|e| unreachable!("Cannot create Pushgateway: {}", e)),
+        ))
+        .unwrap_or_else(|e| unreachable!("Cannot initialise Pushgateway: {}", e));
+
+        assert!(initialise_pushgateway(None).is_err());
+
+        assert!(push("up 1\n").await.is_ok());
+
+        // errors from the Pushgateway are returned
+        assert!(matches!(push("up 0\n").await, Err(Error::Call(_))));
+
+        let requests = handle
+            .await
+            .unwrap_or_else(|e| unreachable!("Server failed: {}", e));
+
+        assert_eq!(requests.len(), 2);
+
+        let request = requests[0].to_lowercase();
+        assert!(request.starts_with("put /metrics/job/openportal/instance/metrics http/1.1"));
+        assert!(request.contains("authorization: bearer token"));
+        assert!(request.contains(&format!("content-type: {}", CONTENT_TYPE_TEXT)));
+        assert!(request.ends_with("\r\n\r\nup 1\n"));
+    }
+}
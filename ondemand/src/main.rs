@@ -0,0 +1,175 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+
+use templemeads::agent;
+use templemeads::agent::scheduler::{process_args, run, Defaults};
+use templemeads::agent::Type as AgentType;
+use templemeads::async_runnable;
+use templemeads::grammar::Instruction;
+use templemeads::grammar::Instruction::{
+    AddLocalProject, AddLocalUser, GetLocalUsageReport, RemoveLocalProject, RemoveLocalUser,
+};
+use templemeads::job::{Envelope, Job};
+use templemeads::notification::default_notify_runner;
+use templemeads::set_notify_runner;
+use templemeads::usagereport::ProjectUsageReport;
+use templemeads::Error;
+
+mod ondemand;
+mod sessions;
+
+/// Seconds to wait for the scheduler agent to be available
+const AGENT_WAIT_TIME: u64 = 5;
+
+///
+/// Main function for the ondemand scheduler agent
+///
+/// This agent sits between a cluster and its scheduler agent. It passes
+/// every scheduler instruction on to the scheduler agent, and keeps the
+/// Open OnDemand configuration of each project and user in step with
+/// it, so that interactive apps only offer the accounts that a user is
+/// a member of. The time spent in interactive sessions is added to each
+/// project's usage report as the "ondemand" component.
+///
+#[tokio::main]
+async fn main() -> Result<()> {
+    // start tracing
+    templemeads::config::initialise_tracing();
+
+    // start system monitoring
+    templemeads::spawn_system_monitor();
+
+    // create the OpenPortal paddington defaults
+    let defaults: Defaults = Defaults::parse(
+        Some("ondemand".to_owned()),
+        Some(
+            dirs::config_local_dir()
+                .unwrap_or(
+                    ".".parse()
+                        .expect("Could not parse fallback config directory."),
+                )
+                .join("openportal")
+                .join("ondemand-config.toml"),
+        ),
+        Some("ws://localhost:8052".to_owned()),
+        Some("127.0.0.1".to_owned()),
+        Some(8052),
+        None,
+        None,
+        Some(AgentType::Scheduler),
+    );
+
+    // now parse the command line arguments to get the service configuration
+    let config = match process_args(&defaults).await? {
+        Some(config) => config,
+        None => {
+            // Not running the service, so can safely exit
+            return Ok(());
+        }
+    };
+
+    ondemand::initialise(ondemand::Settings::new(
+        &config.option("config-dir", "/etc/ood/config/openportal"),
+        &config.option("dataroot", ""),
+    )?)?;
+
+    sessions::initialise(
+        &config.option("sacct", "sacct"),
+        &config.option("session-prefix", "sys/dashboard"),
+    )
+    .await?;
+
+    async_runnable! {
+        ///
+        /// Runnable function that will be called when a job is received
+        /// by the agent
+        ///
+        pub async fn ondemand_runner(envelope: Envelope) -> Result<Job, templemeads::Error>
+        {
+            let me = envelope.recipient();
+            let job = envelope.job();
+
+            match job.instruction() {
+                AddLocalProject(mapping) => {
+                    let result = forward(me.name(), job.instruction()).await?;
+
+                    if !result.is_error() {
+                        ondemand::add_project(&mapping).await?;
+                    }
+
+                    job.copy_result_from(&result)
+                },
+                RemoveLocalProject(mapping) => {
+                    // the project is removed from OnDemand first, so that no
+                    // new sessions are started while it is being removed
+                    ondemand::remove_project(&mapping).await?;
+                    job.copy_result_from(&forward(me.name(), job.instruction()).await?)
+                },
+                AddLocalUser(mapping) => {
+                    let result = forward(me.name(), job.instruction()).await?;
+
+                    if !result.is_error() {
+                        ondemand::add_user(&mapping).await?;
+                    }
+
+                    job.copy_result_from(&result)
+                },
+                RemoveLocalUser(mapping) => {
+                    ondemand::remove_user(&mapping).await?;
+                    job.copy_result_from(&forward(me.name(), job.instruction()).await?)
+                },
+                GetLocalUsageReport(mapping, dates) => {
+                    let result = forward(me.name(), job.instruction()).await?;
+
+                    if result.is_error() {
+                        return job.copy_result_from(&result);
+                    }
+
+                    let mut report = result
+                        .result::<ProjectUsageReport>()?
+                        .unwrap_or_else(|| ProjectUsageReport::new(mapping.project()));
+
+                    sessions::add_sessions(&mapping, &dates, &mut report, job.expires()).await?;
+
+                    job.completed(report)
+                },
+                instruction => {
+                    // everything else is handled by the scheduler agent
+                    job.copy_result_from(&forward(me.name(), instruction).await?)
+                }
+            }
+        }
+    }
+
+    set_notify_runner(default_notify_runner).await?;
+    run(config, ondemand_runner).await?;
+
+    Ok(())
+}
+
+///
+/// Pass the instruction on to the scheduler agent, returning the
+/// finished job
+///
+async fn forward(me: &str, instruction: Instruction) -> Result<Job, Error> {
+    match agent::scheduler(AGENT_WAIT_TIME).await {
+        Some(scheduler) => {
+            let job = Job::parse(
+                &format!("{}.{} {}", me, scheduler.name(), instruction),
+                false,
+            )?
+            .put(&scheduler)
+            .await?;
+
+            job.wait().await
+        }
+        None => {
+            tracing::error!("No scheduler agent found");
+            Err(Error::MissingAgent(
+                "Cannot run the job because there is no scheduler agent".to_string(),
+            ))
+        }
+    }
+}
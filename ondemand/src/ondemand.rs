@@ -0,0 +1,455 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+use once_cell::sync::{Lazy, OnceCell};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use templemeads::grammar::{ProjectMapping, UserMapping};
+use templemeads::Error;
+use tokio::sync::Mutex;
+
+use crate::sessions::account_name;
+
+static SETTINGS: OnceCell<Settings> = OnceCell::new();
+
+/// Serialises changes to the configuration files, so that concurrent
+/// jobs do not overwrite each other's changes
+static LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+///
+/// Where the Open OnDemand configuration of each project and user is
+/// written, and the template of each project's dataroot
+///
+pub struct Settings {
+    config_dir: PathBuf,
+    dataroot: String,
+}
+
+impl Settings {
+    pub fn new(config_dir: &str, dataroot: &str) -> Result<Self, Error> {
+        if config_dir.trim().is_empty() {
+            return Err(Error::Misconfigured(
+                "No config-dir specified. Please set this in the config-dir option.".to_owned(),
+            ));
+        }
+
+        Ok(Self {
+            config_dir: PathBuf::from(config_dir.trim()),
+            dataroot: dataroot.trim().to_owned(),
+        })
+    }
+}
+
+pub fn initialise(settings: Settings) -> Result<()> {
+    tracing::info!(
+        "Writing Open OnDemand configuration to {}",
+        settings.config_dir.display()
+    );
+
+    SETTINGS
+        .set(settings)
+        .map_err(|_| anyhow::anyhow!("OnDemand settings already initialised"))
+}
+
+fn get_settings() -> Result<&'static Settings, Error> {
+    SETTINGS
+        .get()
+        .ok_or_else(|| Error::Call("OnDemand settings not initialised".to_owned()))
+}
+
+fn projects_dir() -> Result<PathBuf, Error> {
+    Ok(get_settings()?.config_dir.join("projects"))
+}
+
+fn users_dir() -> Result<PathBuf, Error> {
+    Ok(get_settings()?.config_dir.join("users"))
+}
+
+///
+/// Return the path of a configuration file, checking that the name
+/// cannot escape the configuration directory
+///
+fn config_file(dir: &Path, name: &str) -> Result<PathBuf, Error> {
+    if name.is_empty() || name.contains('/') || name.starts_with('.') {
+        return Err(Error::InvalidState(format!(
+            "Cannot write the configuration of '{}'",
+            name
+        )));
+    }
+
+    Ok(dir.join(format!("{}.json", name)))
+}
+
+///
+/// Write the passed JSON to the file, via a temporary file so that Open
+/// OnDemand never reads a partially-written file
+///
+async fn write_json(path: &Path, value: &Value) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let tmp = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp, serde_json::to_string_pretty(value)?).await?;
+    tokio::fs::rename(&tmp, path).await?;
+
+    Ok(())
+}
+
+async fn read_json(path: &Path) -> Result<Option<Value>, Error> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn remove_file(path: &Path) -> Result<(), Error> {
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn members(project: &Value) -> BTreeSet<String> {
+    project["members"]
+        .as_array()
+        .map(|members| {
+            members
+                .iter()
+                .filter_map(|m| m.as_str().map(|m| m.to_owned()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+///
+/// Rewrite the configuration of the user, listing the accounts of every
+/// project that they are a member of, or remove it if there are none
+///
+async fn update_user(local_user: &str) -> Result<(), Error> {
+    let path = config_file(&users_dir()?, local_user)?;
+    let mut projects = Vec::new();
+
+    if let Ok(mut entries) = tokio::fs::read_dir(projects_dir()?).await {
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.path().extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+
+            if let Some(project) = read_json(&entry.path()).await? {
+                if members(&project).contains(local_user) {
+                    projects.push(project);
+                }
+            }
+        }
+    }
+
+    if projects.is_empty() {
+        return remove_file(&path).await;
+    }
+
+    projects.sort_by(|a, b| a["account"].as_str().cmp(&b["account"].as_str()));
+
+    write_json(
+        &path,
+        &json!({
+            "accounts": projects.iter().map(|p| p["account"].clone()).collect::<Vec<_>>(),
+            "projects": projects.iter().map(|p| json!({
+                "project": p["project"],
+                "account": p["account"],
+                "dataroot": p["dataroot"],
+            })).collect::<Vec<_>>(),
+        }),
+    )
+    .await
+}
+
+///
+/// Write the configuration of the project, keeping its members if it
+/// already exists
+///
+pub async fn add_project(mapping: &ProjectMapping) -> Result<(), Error> {
+    let _guard = LOCK.lock().await;
+
+    let settings = get_settings()?;
+    let path = config_file(&projects_dir()?, mapping.local_group())?;
+
+    let existing = read_json(&path)
+        .await?
+        .map(|p| members(&p))
+        .unwrap_or_default();
+
+    write_json(
+        &path,
+        &json!({
+            "project": mapping.project().to_string(),
+            "account": account_name(mapping.local_group())?,
+            "dataroot": settings.dataroot.replace("{project}", mapping.local_group()),
+            "members": existing,
+        }),
+    )
+    .await?;
+
+    // the members' accounts may have changed if the project was re-added
+    for member in existing {
+        update_user(&member).await?;
+    }
+
+    tracing::info!("Wrote the OnDemand configuration of {}", mapping);
+
+    Ok(())
+}
+
+///
+/// Remove the configuration of the project, and the project from the
+/// configuration of each of its members
+///
+pub async fn remove_project(mapping: &ProjectMapping) -> Result<(), Error> {
+    let _guard = LOCK.lock().await;
+
+    let path = config_file(&projects_dir()?, mapping.local_group())?;
+
+    let existing = match read_json(&path).await? {
+        Some(project) => members(&project),
+        None => {
+            tracing::warn!("{} has no OnDemand configuration", mapping);
+            return Ok(());
+        }
+    };
+
+    remove_file(&path).await?;
+
+    for member in existing {
+        update_user(&member).await?;
+    }
+
+    tracing::info!("Removed the OnDemand configuration of {}", mapping);
+
+    Ok(())
+}
+
+///
+/// Add the user to the members of their project
+///
+pub async fn add_user(mapping: &UserMapping) -> Result<(), Error> {
+    set_member(mapping, true).await
+}
+
+///
+/// Remove the user from the members of their project
+///
+pub async fn remove_user(mapping: &UserMapping) -> Result<(), Error> {
+    set_member(mapping, false).await
+}
+
+async fn set_member(mapping: &UserMapping, is_member: bool) -> Result<(), Error> {
+    let _guard = LOCK.lock().await;
+
+    let path = config_file(&projects_dir()?, mapping.local_group())?;
+
+    let mut project = match read_json(&path).await? {
+        Some(project) => project,
+        None if is_member => {
+            return Err(Error::InvalidState(format!(
+                "Cannot add {} as their project has no OnDemand configuration",
+                mapping
+            )));
+        }
+        None => {
+            tracing::warn!("The project of {} has no OnDemand configuration", mapping);
+            return update_user(mapping.local_user()).await;
+        }
+    };
+
+    let mut existing = members(&project);
+
+    let changed = match is_member {
+        true => existing.insert(mapping.local_user().to_owned()),
+        false => existing.remove(mapping.local_user()),
+    };
+
+    if changed {
+        project["members"] = json!(existing);
+        write_json(&path, &project).await?;
+    }
+
+    update_user(mapping.local_user()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use templemeads::grammar::{ProjectIdentifier, UserIdentifier};
+
+    ///
+    /// Return the mapping of the project to the passed local group
+    ///
+    fn project(name: &str, local_group: &str) -> ProjectMapping {
+        ProjectMapping::new(
+            &ProjectIdentifier::parse(&format!("{}.portal", name))
+                .unwrap_or_else(|e| unreachable!("Cannot parse project: {}", e)),
+            local_group,
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e))
+    }
+
+    fn user(name: &str, project: &ProjectMapping) -> UserMapping {
+        UserMapping::new(
+            &UserIdentifier::parse(&format!("{}.{}", name, project.project()))
+                .unwrap_or_else(|e| unreachable!("Cannot parse user: {}", e)),
+            name,
+            project.local_group(),
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e))
+    }
+
+    #[test]
+    fn test_config_file() {
+        let dir = Path::new("/etc/ood/openportal/users");
+
+        assert!(config_file(dir, "alice")
+            .is_ok_and(|path| path == Path::new("/etc/ood/openportal/users/alice.json")));
+
+        for name in ["", "../alice", ".hidden", "a/b"] {
+            assert!(matches!(
+                config_file(dir, name),
+                Err(Error::InvalidState(_))
+            ));
+        }
+
+        assert!(matches!(
+            Settings::new(" ", "/data/{project}"),
+            Err(Error::Misconfigured(_))
+        ));
+    }
+
+    #[test]
+    fn test_members() {
+        assert_eq!(
+            members(&json!({"members": ["bob", "alice", 3]})),
+            BTreeSet::from(["alice".to_owned(), "bob".to_owned()])
+        );
+        assert!(members(&json!({})).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_configuration() {
+        let dir = std::env::temp_dir().join(format!("op-ondemand-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let proj = project("proj", "proj");
+        let other = project("other", "group.other");
+
+        // nothing can be written until the settings are initialised
+        assert!(matches!(add_project(&proj).await, Err(Error::Call(_))));
+
+        initialise(
+            Settings::new(&dir.display().to_string(), "/data/{project}")
+                .unwrap_or_else(|e| unreachable!("Cannot create settings: {}", e)),
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot initialise: {}", e));
+
+        let read = |path: PathBuf| -> Option<Value> {
+            std::fs::read_to_string(path)
+                .ok()
+                .and_then(|contents| serde_json::from_str(&contents).ok())
+        };
+
+        let project_file = |name: &str| read(dir.join("projects").join(format!("{}.json", name)));
+        let user_file = |name: &str| read(dir.join("users").join(format!("{}.json", name)));
+
+        // users can only be added to projects that exist
+        assert!(matches!(
+            add_user(&user("alice", &proj)).await,
+            Err(Error::InvalidState(_))
+        ));
+
+        add_project(&proj)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add project: {}", e));
+
+        assert_eq!(
+            project_file("proj"),
+            Some(json!({
+                "project": "proj.portal",
+                "account": "proj",
+                "dataroot": "/data/proj",
+                "members": [],
+            }))
+        );
+
+        add_project(&other)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add project: {}", e));
+
+        for user in [
+            user("alice", &proj),
+            user("bob", &proj),
+            user("alice", &other),
+            user("alice", &proj),
+        ] {
+            add_user(&user)
+                .await
+                .unwrap_or_else(|e| unreachable!("Cannot add user: {}", e));
+        }
+
+        assert_eq!(
+            project_file("proj").map(|p| p["members"].clone()),
+            Some(json!(["alice", "bob"]))
+        );
+
+        // users can use the accounts of all of their projects
+        assert_eq!(
+            user_file("alice"),
+            Some(json!({
+                "accounts": ["other", "proj"],
+                "projects": [
+                    {"project": "other.portal", "account": "other", "dataroot": "/data/group.other"},
+                    {"project": "proj.portal", "account": "proj", "dataroot": "/data/proj"},
+                ],
+            }))
+        );
+
+        assert_eq!(
+            user_file("bob").map(|u| u["accounts"].clone()),
+            Some(json!(["proj"]))
+        );
+
+        // re-adding a project keeps its members
+        add_project(&proj)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add project: {}", e));
+
+        assert_eq!(
+            project_file("proj").map(|p| p["members"].clone()),
+            Some(json!(["alice", "bob"]))
+        );
+
+        remove_user(&user("bob", &proj))
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove user: {}", e));
+
+        // users without any projects have no configuration
+        assert_eq!(user_file("bob"), None);
+
+        remove_project(&proj)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove project: {}", e));
+
+        assert_eq!(project_file("proj"), None);
+        assert_eq!(
+            user_file("alice").map(|u| u["accounts"].clone()),
+            Some(json!(["other"]))
+        );
+
+        // removing things that don't exist is not an error
+        assert!(remove_project(&proj).await.is_ok());
+        assert!(remove_user(&user("carol", &proj)).await.is_ok());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
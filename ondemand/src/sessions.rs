@@ -0,0 +1,362 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use templemeads::exec;
+use templemeads::grammar::{Date, DateRange, ProjectMapping};
+use templemeads::job::assert_not_expired;
+use templemeads::usagereport::{ProjectUsageReport, Usage};
+use templemeads::Error;
+use tokio::sync::RwLock;
+
+/// The usage report component that holds the session time
+pub const COMPONENT: &str = "ondemand";
+
+#[derive(Debug, Default)]
+struct Database {
+    sacct: Vec<String>,
+    job_prefix: String,
+    /// The session seconds of each user in each account on each
+    /// completed day. Completed days never change, so they are only
+    /// fetched from sacct once.
+    sessions: HashMap<(String, Date), HashMap<String, u64>>,
+}
+
+static CACHE: Lazy<RwLock<Database>> = Lazy::new(|| RwLock::new(Database::default()));
+
+///
+/// Set the sacct command, and the prefix of the names of the jobs that
+/// Open OnDemand submits for interactive sessions
+///
+pub async fn initialise(sacct: &str, job_prefix: &str) -> Result<(), Error> {
+    let sacct: Vec<String> = sacct.split_whitespace().map(|p| p.to_owned()).collect();
+
+    if sacct.is_empty() {
+        return Err(Error::Misconfigured("Empty sacct command".to_owned()));
+    }
+
+    let mut cache = CACHE.write().await;
+    cache.sacct = sacct;
+    cache.job_prefix = job_prefix.trim().to_owned();
+
+    Ok(())
+}
+
+///
+/// Return the Slurm account of the passed local project group, named
+/// in the same way as by the Slurm agent
+///
+pub fn account_name(local_group: &str) -> Result<String, Error> {
+    // "group.X" is a legacy name of account "X"
+    let account = match local_group.trim().strip_prefix("group.") {
+        Some(account) => account.split('.').next().unwrap_or(account),
+        None => local_group.trim(),
+    };
+
+    match account.is_empty() {
+        true => Err(Error::Parse("Account name is empty".to_owned())),
+        false => Ok(account.replace(['/', ' '], "_").to_ascii_lowercase()),
+    }
+}
+
+///
+/// Parse the parsable (`-P`) output of sacct with the format
+/// JobName,User,ElapsedRaw,End, returning the seconds of each user's
+/// sessions that ended on the passed day
+///
+fn parse_sessions(output: &str, job_prefix: &str, day: &Date) -> HashMap<String, u64> {
+    let mut sessions = HashMap::new();
+    let day = day.date().format("%Y-%m-%d").to_string();
+
+    for line in output.lines() {
+        let fields: Vec<&str> = line.split('|').collect();
+
+        if fields.len() < 4 || !fields[0].starts_with(job_prefix) {
+            continue;
+        }
+
+        // sessions are counted on the day they ended, so that each is
+        // only counted once
+        if !fields[3].starts_with(&day) {
+            continue;
+        }
+
+        let seconds = fields[2].trim().parse::<u64>().unwrap_or(0);
+
+        *sessions.entry(fields[1].trim().to_owned()).or_default() += seconds;
+    }
+
+    sessions
+}
+
+///
+/// Fetch the seconds of the interactive sessions of each user in the
+/// account that ended on the passed day
+///
+async fn fetch_day(account: &str, day: &Date) -> Result<HashMap<String, u64>, Error> {
+    let (sacct, job_prefix) = {
+        let cache = CACHE.read().await;
+        (cache.sacct.clone(), cache.job_prefix.clone())
+    };
+
+    if sacct.is_empty() {
+        return Err(Error::Call("Sessions not initialised".to_owned()));
+    }
+
+    let start = day.date().format("%Y-%m-%dT00:00:00").to_string();
+    let end = day.date().format("%Y-%m-%dT23:59:59").to_string();
+
+    let args = [
+        "-a",
+        "-X",
+        "-n",
+        "-P",
+        "-A",
+        account,
+        "-S",
+        &start,
+        "-E",
+        &end,
+        "--format=JobName%200,User,ElapsedRaw,End",
+    ];
+
+    let output = exec::Command::from_parts(&sacct)
+        .args(args)
+        .run()
+        .await
+        .map_err(|failure| {
+            Error::Call(format!(
+                "sacct failed for {} on {}: {}",
+                account, day, failure
+            ))
+        })?;
+
+    Ok(parse_sessions(&output, &job_prefix, day))
+}
+
+///
+/// Return the seconds of the interactive sessions of each user in the
+/// account on the passed day
+///
+async fn get_day(account: &str, day: &Date) -> Result<HashMap<String, u64>, Error> {
+    let key = (account.to_owned(), day.clone());
+
+    if let Some(sessions) = CACHE.read().await.sessions.get(&key) {
+        return Ok(sessions.clone());
+    }
+
+    let sessions = fetch_day(account, day).await?;
+
+    // the sessions of days before today are complete, so cache them
+    if *day < Date::today() {
+        CACHE.write().await.sessions.insert(key, sessions.clone());
+    }
+
+    Ok(sessions)
+}
+
+///
+/// Add the time spent in Open OnDemand interactive sessions to the
+/// scheduler's usage report, as the "ondemand" component of each day
+///
+pub async fn add_sessions(
+    mapping: &ProjectMapping,
+    dates: &DateRange,
+    report: &mut ProjectUsageReport,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    let account = account_name(mapping.local_group())?;
+    let today = Date::today();
+
+    for day in dates.days().into_iter().filter(|day| *day <= today) {
+        assert_not_expired(expires)?;
+
+        let sessions = match get_day(&account, &day).await {
+            Ok(sessions) => sessions,
+            Err(e) => {
+                tracing::warn!(
+                    "Could not get the sessions of {} on {}: {}",
+                    account,
+                    day,
+                    e
+                );
+                continue;
+            }
+        };
+
+        // only days that the scheduler reported are annotated, so that a
+        // missing day is not mistaken for a complete one
+        let mut daily = match report
+            .get_report(&day)
+            .daily_reports(false)
+            .into_iter()
+            .next()
+        {
+            Some(daily) => daily,
+            None => continue,
+        };
+
+        for (user, seconds) in sessions {
+            daily.set_component_usage(COMPONENT, &user, Usage::new(seconds));
+        }
+
+        report.set_report(&day, &daily);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use templemeads::grammar::ProjectIdentifier;
+    use templemeads::usagereport::DailyProjectUsageReport;
+
+    #[test]
+    fn test_account_name() {
+        assert!(account_name("proj").is_ok_and(|a| a == "proj"));
+        assert!(account_name(" Big Proj ").is_ok_and(|a| a == "big_proj"));
+        assert!(account_name("a/b").is_ok_and(|a| a == "a_b"));
+        assert!(account_name("group.Proj").is_ok_and(|a| a == "proj"));
+        assert!(account_name("group.proj.extra").is_ok_and(|a| a == "proj"));
+
+        assert!(matches!(account_name(""), Err(Error::Parse(_))));
+        assert!(matches!(account_name("group."), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn test_parse_sessions() {
+        let day = Date::parse("2026-03-02").unwrap_or_else(|e| unreachable!("Bad date: {}", e));
+
+        let output = "ood-desktop|alice|3600|2026-03-02T10:00:00\n\
+                      ood-jupyter|alice|60|2026-03-02T23:59:00\n\
+                      ood-desktop|bob|120|2026-03-02T01:00:00\n\
+                      batch-job|alice|9999|2026-03-02T10:00:00\n\
+                      ood-desktop|carol|100|2026-03-01T23:00:00\n\
+                      ood-desktop|dave|Unknown|2026-03-02T10:00:00\n\
+                      malformed line\n";
+
+        let sessions = parse_sessions(output, "ood-", &day);
+
+        assert_eq!(sessions.get("alice"), Some(&3660));
+        assert_eq!(sessions.get("bob"), Some(&120));
+        assert_eq!(sessions.get("dave"), Some(&0));
+        assert!(!sessions.contains_key("carol"));
+        assert_eq!(sessions.len(), 3);
+
+        assert!(parse_sessions("", "ood-", &day).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_add_sessions() {
+        let yesterday = Date::today().prev();
+        let earlier = yesterday.prev();
+
+        // sacct must be initialised before anything is fetched
+        assert!(matches!(
+            fetch_day("proj", &yesterday).await,
+            Err(Error::Call(_))
+        ));
+
+        assert!(matches!(
+            initialise("  ", "ood-").await,
+            Err(Error::Misconfigured(_))
+        ));
+
+        let dir = std::env::temp_dir().join(format!("op-ondemand-sessions-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)
+            .unwrap_or_else(|e| unreachable!("Cannot create {}: {}", dir.display(), e));
+
+        // a fake sacct that logs its arguments, and prints the sessions
+        // of the account (the sixth argument)
+        let script = format!(
+            "echo \"$*\" >> {dir}/log\n\
+             case \"$6\" in *broken*) echo \"sacct error\" >&2; exit 1;; esac\n\
+             cat {dir}/$6.out\n",
+            dir = dir.display()
+        );
+
+        std::fs::write(dir.join("sacct.sh"), script)
+            .unwrap_or_else(|e| unreachable!("Cannot write sacct: {}", e));
+
+        let day = yesterday.date().format("%Y-%m-%d").to_string();
+
+        std::fs::write(
+            dir.join("proj.out"),
+            format!(
+                "ood-desktop|alice|3600|{day}T10:00:00\n\
+                 ood-desktop|bob|60|{day}T11:00:00\n\
+                 batch|alice|100|{day}T12:00:00\n"
+            ),
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot write sessions: {}", e));
+
+        initialise(&format!("sh {}", dir.join("sacct.sh").display()), " ood- ")
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot initialise: {}", e));
+
+        let project = ProjectIdentifier::parse("proj.portal")
+            .unwrap_or_else(|e| unreachable!("Cannot parse project: {}", e));
+        let mapping = ProjectMapping::new(&project, "group.proj")
+            .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e));
+
+        let dates = DateRange::from_chrono(&earlier.to_chrono(), &yesterday.to_chrono());
+        let expires = Utc::now() + chrono::Duration::minutes(5);
+
+        // only yesterday was reported by the scheduler
+        let mut daily = DailyProjectUsageReport::default();
+        daily.set_usage("alice", Usage::new(7200));
+
+        let mut report = ProjectUsageReport::new(&project);
+        report.set_report(&yesterday, &daily);
+
+        add_sessions(&mapping, &dates, &mut report, &expires)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add sessions: {}", e));
+
+        let reports = report.daily_reports(false);
+        assert_eq!(reports.len(), 1);
+
+        let sessions = reports[0].get_component(COMPONENT);
+        assert_eq!(sessions.usage("alice"), Usage::new(3600));
+        assert_eq!(sessions.usage("bob"), Usage::new(60));
+        assert_eq!(reports[0].usage("alice"), Usage::new(7200));
+
+        let log = std::fs::read_to_string(dir.join("log")).unwrap_or_default();
+        assert!(log.contains(&format!("-A proj -S {day}T00:00:00 -E {day}T23:59:59")));
+
+        // completed days are cached, so sacct is not called again
+        let calls = log.lines().count();
+
+        add_sessions(&mapping, &dates, &mut report, &expires)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add sessions: {}", e));
+
+        let log = std::fs::read_to_string(dir.join("log")).unwrap_or_default();
+        assert_eq!(log.lines().count(), calls);
+
+        // failures of sacct are logged and skipped
+        let broken = ProjectMapping::new(
+            &ProjectIdentifier::parse("broken.portal")
+                .unwrap_or_else(|e| unreachable!("Cannot parse project: {}", e)),
+            "broken",
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e));
+
+        assert!(add_sessions(&broken, &dates, &mut report, &expires)
+            .await
+            .is_ok());
+
+        // expired jobs stop straight away
+        let expired = Utc::now() - chrono::Duration::minutes(5);
+
+        assert!(matches!(
+            add_sessions(&mapping, &dates, &mut report, &expired).await,
+            Err(Error::Expired(_))
+        ));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
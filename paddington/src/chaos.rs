@@ -0,0 +1,183 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Fault injection for resilience testing
+//!
+//! When enabled, messages sent to peers are delayed (so that they may
+//! arrive out of order), dropped or duplicated at random, with the
+//! configured probabilities. This is used to check that the retry,
+//! expiry and deduplication logic of the layers above copes with an
+//! unreliable network before it has to in production. Every
+//! probability is zero (so nothing is injected) unless it is set.
+
+use once_cell::sync::Lazy;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use crate::crypto::random_bytes;
+use crate::error::Error;
+
+///
+/// The probabilities with which faults are injected into the messages
+/// sent to peers
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MessageFaults {
+    /// The probability that a message is delayed
+    pub delay: f64,
+    /// The longest that a message is delayed (each delayed message
+    /// is delayed by a random time up to this)
+    pub max_delay: Duration,
+    /// The probability that a message is dropped
+    pub drop: f64,
+    /// The probability that a message is sent twice
+    pub duplicate: f64,
+}
+
+impl MessageFaults {
+    ///
+    /// Create the faults, checking that each probability is between
+    /// 0 and 1
+    ///
+    pub fn new(delay: f64, max_delay: Duration, drop: f64, duplicate: f64) -> Result<Self, Error> {
+        for probability in [delay, drop, duplicate] {
+            check_probability(probability)?;
+        }
+
+        Ok(MessageFaults {
+            delay,
+            max_delay,
+            drop,
+            duplicate,
+        })
+    }
+
+    ///
+    /// Return whether any faults will be injected
+    ///
+    pub fn is_enabled(&self) -> bool {
+        self.delay > 0.0 || self.drop > 0.0 || self.duplicate > 0.0
+    }
+}
+
+///
+/// A fault injected into a single message
+///
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Fault {
+    Delay(Duration),
+    Drop,
+    Duplicate,
+}
+
+static FAULTS: Lazy<RwLock<MessageFaults>> = Lazy::new(|| RwLock::new(MessageFaults::default()));
+
+///
+/// Return an error if the passed probability is not between 0 and 1
+///
+pub fn check_probability(probability: f64) -> Result<(), Error> {
+    match (0.0..=1.0).contains(&probability) {
+        true => Ok(()),
+        false => Err(Error::Parse(format!(
+            "Invalid probability {}. This must be between 0 and 1",
+            probability
+        ))),
+    }
+}
+
+///
+/// Set the faults injected into every message sent to a peer
+///
+pub fn set_message_faults(faults: MessageFaults) {
+    if faults.is_enabled() {
+        tracing::warn!(
+            "Injecting faults into messages: delay={} (up to {:?}), drop={}, duplicate={}",
+            faults.delay,
+            faults.max_delay,
+            faults.drop,
+            faults.duplicate
+        );
+    }
+
+    match FAULTS.write() {
+        Ok(mut f) => *f = faults,
+        Err(e) => tracing::error!("Could not set the message faults: {}", e),
+    }
+}
+
+///
+/// Return the faults injected into every message sent to a peer
+///
+pub fn message_faults() -> MessageFaults {
+    match FAULTS.read() {
+        Ok(faults) => faults.clone(),
+        Err(e) => {
+            tracing::error!("Could not read the message faults: {}", e);
+            MessageFaults::default()
+        }
+    }
+}
+
+///
+/// Return a random number between 0 (inclusive) and 1 (exclusive)
+///
+fn random_fraction() -> f64 {
+    match random_bytes(8).map(|bytes| <[u8; 8]>::try_from(bytes.as_slice())) {
+        // use the top 53 bits, which is the precision of an f64
+        Ok(Ok(bytes)) => (u64::from_le_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64,
+        _ => {
+            tracing::error!("Could not generate a random number - not injecting a fault");
+            1.0
+        }
+    }
+}
+
+///
+/// Return true with the passed probability
+///
+pub fn chance(probability: f64) -> bool {
+    probability > 0.0 && random_fraction() < probability
+}
+
+///
+/// Return the fault (if any) to inject into the next message
+///
+pub(crate) fn fault() -> Option<Fault> {
+    let faults = message_faults();
+
+    if !faults.is_enabled() {
+        return None;
+    }
+
+    if chance(faults.drop) {
+        Some(Fault::Drop)
+    } else if chance(faults.duplicate) {
+        Some(Fault::Duplicate)
+    } else if chance(faults.delay) {
+        Some(Fault::Delay(faults.max_delay.mul_f64(random_fraction())))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_faults() {
+        assert!(MessageFaults::new(0.1, Duration::from_secs(1), 0.0, 1.0).is_ok());
+        assert!(MessageFaults::new(1.5, Duration::from_secs(1), 0.0, 0.0).is_err());
+        assert!(MessageFaults::new(0.0, Duration::from_secs(1), -0.1, 0.0).is_err());
+
+        assert!(!MessageFaults::default().is_enabled());
+
+        assert!(!chance(0.0));
+        assert!(chance(1.0));
+
+        for _ in 0..100 {
+            let fraction = random_fraction();
+            assert!((0.0..1.0).contains(&fraction));
+        }
+    }
+}
@@ -3,12 +3,16 @@
 
 use crate::crypto::{Key, SecretKey};
 use crate::error::Error;
+use crate::exec;
 use crate::invite::Invite;
 
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use iptools::iprange::IpRange;
+use once_cell::sync::Lazy;
 use secrecy::ExposeSecret;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
 use std::net::IpAddr;
 use std::path;
@@ -193,17 +197,17 @@ fn create_websocket_url(url: &str) -> Result<String, Error> {
 }
 
 impl ServerConfig {
-    pub fn new(name: &str, url: &str, zone: &str) -> Self {
-        ServerConfig {
+    pub fn new(name: &str, url: &str, zone: &str) -> Result<Self, Error> {
+        Ok(ServerConfig {
             name: name.to_string(),
             url: create_websocket_url(url).unwrap_or_else(|e| {
                 tracing::warn!("Could not create websocket URL {}: {:?}", url, e);
                 "".to_string()
             }),
             zone: zone.to_string(),
-            inner_key: Key::generate(),
-            outer_key: Key::generate(),
-        }
+            inner_key: Key::generate()?,
+            outer_key: Key::generate()?,
+        })
     }
 
     pub fn from_invite(invite: &Invite) -> Result<Self, Error> {
@@ -267,6 +271,21 @@ impl ServerConfig {
         self.outer_key.clone()
     }
 
+    ///
+    /// Return this server with the keys that were rotated while the
+    /// service is running, if they have been
+    ///
+    pub(crate) fn with_rotated_keys(&self) -> Self {
+        let mut server = self.clone();
+
+        if let Some(keys) = rotated_keys(&self.name, &self.zone) {
+            server.inner_key = keys.inner_key;
+            server.outer_key = keys.outer_key;
+        }
+
+        server
+    }
+
     pub fn rotate_keys(&mut self, invite: &Invite) -> Result<(), Error> {
         // verify that the name and zone match the invite
         if self.name != invite.name() || self.zone != invite.zone() {
@@ -335,6 +354,22 @@ pub struct ClientConfig {
     zone: String,
     inner_key: SecretKey,
     outer_key: SecretKey,
+
+    /// When the invitation for this client was created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created: Option<DateTime<Utc>>,
+
+    /// When the invitation expires, if it has not been used by then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires: Option<DateTime<Utc>>,
+
+    /// When the invitation was first used to connect
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    used: Option<DateTime<Utc>>,
+
+    /// The agent that first used the invitation to connect
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    used_by: Option<String>,
 }
 
 impl Display for ClientConfig {
@@ -348,14 +383,18 @@ impl Display for ClientConfig {
 }
 
 impl ClientConfig {
-    pub fn new(name: &str, ip: &IpOrRange, zone: &str) -> Self {
-        ClientConfig {
+    pub fn new(name: &str, ip: &IpOrRange, zone: &str) -> Result<Self, Error> {
+        Ok(ClientConfig {
             name: name.to_string(),
             ip: ip.clone(),
             zone: zone.to_string(),
-            inner_key: Key::generate(),
-            outer_key: Key::generate(),
-        }
+            inner_key: Key::generate()?,
+            outer_key: Key::generate()?,
+            created: Some(Utc::now()),
+            expires: None,
+            used: None,
+            used_by: None,
+        })
     }
 
     pub fn create_null() -> Self {
@@ -366,6 +405,10 @@ impl ClientConfig {
             zone: "".to_string(),
             inner_key: Key::null(),
             outer_key: Key::null(),
+            created: None,
+            expires: None,
+            used: None,
+            used_by: None,
         }
     }
 
@@ -405,9 +448,217 @@ impl ClientConfig {
         self.outer_key.clone()
     }
 
-    pub fn rotate_keys(&mut self) {
-        self.inner_key = Key::generate();
-        self.outer_key = Key::generate();
+    pub fn rotate_keys(&mut self) -> Result<(), Error> {
+        // generate both before replacing either, so a failure leaves
+        // the old keys in place
+        let inner_key = Key::generate()?;
+        let outer_key = Key::generate()?;
+
+        self.inner_key = inner_key;
+        self.outer_key = outer_key;
+
+        Ok(())
+    }
+
+    pub fn created(&self) -> Option<DateTime<Utc>> {
+        self.created
+    }
+
+    pub fn expires(&self) -> Option<DateTime<Utc>> {
+        self.expires
+    }
+
+    pub fn used(&self) -> Option<DateTime<Utc>> {
+        self.used
+    }
+
+    pub fn used_by(&self) -> Option<String> {
+        self.used_by.clone()
+    }
+
+    ///
+    /// Return whether the invitation for this client was used to
+    /// connect, either before or since the service was started
+    ///
+    pub fn is_invitation_used(&self) -> bool {
+        self.used.is_some() || invitation_used(&self.name, &self.zone)
+    }
+
+    ///
+    /// Return whether the invitation for this client has expired. An
+    /// invitation only expires if it was not used before its expiry
+    ///
+    pub fn is_invitation_expired(&self) -> bool {
+        match self.expires {
+            Some(expires) => expires < Utc::now() && !self.is_invitation_used(),
+            None => false,
+        }
+    }
+
+    ///
+    /// Return a one-line description of the invitation for this client,
+    /// e.g. for listing invitations
+    ///
+    pub fn invitation_status(&self) -> String {
+        let format = |time: Option<DateTime<Utc>>| match time {
+            Some(time) => time.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+            None => "unknown".to_string(),
+        };
+
+        let status = if let Some(used) = self.used {
+            format!(
+                "used by {} at {}",
+                self.used_by
+                    .clone()
+                    .unwrap_or_else(|| "unknown".to_string()),
+                format(Some(used))
+            )
+        } else if self.is_invitation_used() {
+            "used since the service started".to_string()
+        } else if self.is_invitation_expired() {
+            format!("expired at {}", format(self.expires))
+        } else if let Some(expires) = self.expires {
+            format!("unused, expires at {}", format(Some(expires)))
+        } else {
+            "unused".to_string()
+        };
+
+        format!(
+            "{} (zone {}, ip {}) created {}: {}",
+            self.name,
+            self.zone,
+            self.ip,
+            format(self.created),
+            status
+        )
+    }
+
+    ///
+    /// Return the versions of this client that may authenticate. This
+    /// is the client with the keys that were rotated while the service
+    /// is running, followed by the client with its previous keys if
+    /// the client has not yet confirmed that it has the new ones
+    ///
+    pub(crate) fn with_rotated_keys(&self) -> Vec<Self> {
+        let Some(keys) = rotated_keys(&self.name, &self.zone) else {
+            return vec![self.clone()];
+        };
+
+        let mut clients = vec![ClientConfig {
+            inner_key: keys.inner_key,
+            outer_key: keys.outer_key,
+            ..self.clone()
+        }];
+
+        if let Some((inner_key, outer_key)) = keys.previous {
+            clients.push(ClientConfig {
+                inner_key,
+                outer_key,
+                ..self.clone()
+            });
+        }
+
+        clients
+    }
+}
+
+///
+/// Keys for a peer that were rotated while the service is running,
+/// together with the keys they replaced if those are still accepted
+///
+#[derive(Clone)]
+struct RotatedKeys {
+    inner_key: SecretKey,
+    outer_key: SecretKey,
+    previous: Option<(SecretKey, SecretKey)>,
+}
+
+///
+/// Rotated keys by peer name and zone. Connections use these in place
+/// of the keys in the ServiceConfig that the service was started with
+///
+static ROTATED_KEYS: Lazy<std::sync::RwLock<HashMap<(String, String), RotatedKeys>>> =
+    Lazy::new(|| std::sync::RwLock::new(HashMap::new()));
+
+fn rotated_keys(name: &str, zone: &str) -> Option<RotatedKeys> {
+    match ROTATED_KEYS.read() {
+        Ok(keys) => keys.get(&(name.to_owned(), zone.to_owned())).cloned(),
+        Err(e) => {
+            tracing::error!("Could not read rotated keys: {}", e);
+            None
+        }
+    }
+}
+
+///
+/// Use new keys for all future connections to or from the peer called
+/// `name` in `zone`, without restarting the service. Any `previous`
+/// keys are also accepted from a client until `accept_only_rotated_keys`
+/// is called, so that it can still connect if it misses the new keys
+///
+pub fn set_rotated_keys(
+    name: &str,
+    zone: &str,
+    inner_key: &SecretKey,
+    outer_key: &SecretKey,
+    previous: Option<(SecretKey, SecretKey)>,
+) -> Result<(), Error> {
+    let mut keys = ROTATED_KEYS
+        .write()
+        .map_err(|e| Error::Unavailable(format!("Could not store rotated keys: {}", e)))?;
+
+    keys.insert(
+        (name.to_owned(), zone.to_owned()),
+        RotatedKeys {
+            inner_key: inner_key.clone(),
+            outer_key: outer_key.clone(),
+            previous,
+        },
+    );
+
+    Ok(())
+}
+
+///
+/// Stop accepting the previous keys of the peer called `name` in
+/// `zone`, now that it has confirmed that it has its rotated keys
+///
+pub fn accept_only_rotated_keys(name: &str, zone: &str) -> Result<(), Error> {
+    let mut keys = ROTATED_KEYS
+        .write()
+        .map_err(|e| Error::Unavailable(format!("Could not store rotated keys: {}", e)))?;
+
+    if let Some(keys) = keys.get_mut(&(name.to_owned(), zone.to_owned())) {
+        keys.previous = None;
+    }
+
+    Ok(())
+}
+
+///
+/// Clients (by name and zone) that used their invitation to connect
+/// since the service started, so that their invitation no longer
+/// expires even though the ServiceConfig has not been updated
+///
+static USED_INVITATIONS: Lazy<std::sync::RwLock<HashSet<(String, String)>>> =
+    Lazy::new(|| std::sync::RwLock::new(HashSet::new()));
+
+fn invitation_used(name: &str, zone: &str) -> bool {
+    match USED_INVITATIONS.read() {
+        Ok(used) => used.contains(&(name.to_owned(), zone.to_owned())),
+        Err(e) => {
+            tracing::error!("Could not read used invitations: {}", e);
+            false
+        }
+    }
+}
+
+pub(crate) fn record_invitation_used(name: &str, zone: &str) {
+    match USED_INVITATIONS.write() {
+        Ok(mut used) => {
+            used.insert((name.to_owned(), zone.to_owned()));
+        }
+        Err(e) => tracing::error!("Could not record used invitation: {}", e),
     }
 }
 
@@ -484,14 +735,96 @@ impl PeerConfig {
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum EncryptionScheme {
-    Environment { key: String },
+    Environment {
+        key: String,
+    },
     Simple {},
+    /// The password is the standard output of the specified shell
+    /// command, e.g. a KMS decrypt call or a passphrase prompt
+    Command {
+        command: String,
+    },
     /*Vault {
         url: String,
     }*/
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+///
+/// Parse a duration such as "90s", "10m", "2h" or "1d". A number
+/// without a unit is a number of seconds
+///
+pub fn parse_duration(duration: &str) -> Result<u64, Error> {
+    let duration = duration.trim();
+
+    let (number, unit) = match duration.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => duration.split_at(i),
+        None => (duration, "s"),
+    };
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| Error::Parse(format!("Invalid duration '{}'", duration)))?;
+
+    let scale = match unit.trim() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => {
+            return Err(Error::Parse(format!(
+                "Invalid duration '{}'. The unit must be s, m, h or d",
+                duration
+            )))
+        }
+    };
+
+    Ok(number.saturating_mul(scale))
+}
+
+///
+/// Keys derived from the output of a `Command` encryption scheme,
+/// cached so that the command is only run once per process
+///
+static COMMAND_KEYS: Lazy<std::sync::Mutex<HashMap<String, SecretKey>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+fn key_from_command(command: &str) -> Result<SecretKey, Error> {
+    if let Ok(keys) = COMMAND_KEYS.lock() {
+        if let Some(key) = keys.get(command) {
+            return Ok(key.clone());
+        }
+    }
+
+    // run through exec, so that the command has a timeout and a
+    // scrubbed environment, like every other command run by the agent
+    let password = exec::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .run_blocking()
+        .map_err(|failure| {
+            Error::Unavailable(format!("Encryption password command failed: {}", failure))
+        })?;
+
+    let password = password.trim_end_matches(['\r', '\n']);
+
+    if password.is_empty() {
+        return Err(Error::Null(format!(
+            "Encryption password command '{}' returned an empty password.",
+            command
+        )));
+    }
+
+    let key = Key::from_password(password)?;
+
+    if let Ok(mut keys) = COMMAND_KEYS.lock() {
+        keys.insert(command.to_string(), key.clone());
+    }
+
+    Ok(key)
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(try_from = "ServiceConfigFile")]
 pub struct ServiceConfig {
     name: String,
     url: String,
@@ -503,6 +836,132 @@ pub struct ServiceConfig {
     servers: Vec<ServerConfig>,
     clients: Vec<ClientConfig>,
     encryption: Option<EncryptionScheme>,
+
+    /// Whether the servers and clients (and their keys) are written
+    /// to the config file encrypted, rather than in plain text
+    sealed: bool,
+}
+
+///
+/// The servers and clients that are encrypted together into the
+/// `sealed_peers` field of a sealed config file
+///
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct SealedPeers {
+    servers: Vec<ServerConfig>,
+    clients: Vec<ClientConfig>,
+}
+
+///
+/// The on-disk representation of a ServiceConfig. This is the same
+/// as the in-memory config, except that the servers and clients
+/// may be held encrypted in `sealed_peers`
+///
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct ServiceConfigFile {
+    name: String,
+    url: String,
+    ip: IpAddr,
+    port: u16,
+    heathcheck_port: Option<u16>,
+    proxy_header: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sealed_peers: Option<String>,
+
+    #[serde(default)]
+    servers: Vec<ServerConfig>,
+    #[serde(default)]
+    clients: Vec<ClientConfig>,
+    encryption: Option<EncryptionScheme>,
+}
+
+impl TryFrom<ServiceConfigFile> for ServiceConfig {
+    type Error = Error;
+
+    fn try_from(file: ServiceConfigFile) -> Result<Self, Self::Error> {
+        let mut config = ServiceConfig {
+            name: file.name,
+            url: file.url,
+            ip: file.ip,
+            port: file.port,
+            heathcheck_port: file.heathcheck_port,
+            proxy_header: file.proxy_header,
+            servers: file.servers,
+            clients: file.clients,
+            encryption: file.encryption,
+            sealed: false,
+        };
+
+        if let Some(sealed_peers) = file.sealed_peers {
+            if !config.servers.is_empty() || !config.clients.is_empty() {
+                return Err(Error::Parse(
+                    "A sealed config file cannot also contain plain text servers or clients."
+                        .to_string(),
+                ));
+            }
+
+            let peers: SealedPeers = config
+                .decrypt(&sealed_peers)
+                .map_err(|e| Error::Parse(format!("Could not decrypt the sealed peers: {}", e)))?;
+
+            if let Some(EncryptionScheme::Simple {}) = config.encryption {
+                tracing::warn!(
+                    "The peers of '{}' are sealed with the simple encryption scheme. \
+                     This key is derived from the service name, so does not protect \
+                     the peer keys. Please choose a different encryption scheme.",
+                    config.name
+                );
+            }
+
+            config.servers = peers.servers;
+            config.clients = peers.clients;
+            config.sealed = true;
+        }
+
+        Ok(config)
+    }
+}
+
+impl TryFrom<&ServiceConfig> for ServiceConfigFile {
+    type Error = Error;
+
+    fn try_from(config: &ServiceConfig) -> Result<Self, Self::Error> {
+        let (sealed_peers, servers, clients) = match config.sealed {
+            true => (
+                Some(config.encrypt(&SealedPeers {
+                    servers: config.servers.clone(),
+                    clients: config.clients.clone(),
+                })?),
+                Vec::new(),
+                Vec::new(),
+            ),
+            false => (None, config.servers.clone(), config.clients.clone()),
+        };
+
+        Ok(ServiceConfigFile {
+            name: config.name.clone(),
+            url: config.url.clone(),
+            ip: config.ip,
+            port: config.port,
+            heathcheck_port: config.heathcheck_port,
+            proxy_header: config.proxy_header.clone(),
+            sealed_peers,
+            servers,
+            clients,
+            encryption: config.encryption.clone(),
+        })
+    }
+}
+
+impl Serialize for ServiceConfig {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        ServiceConfigFile::try_from(self)
+            .map_err(serde::ser::Error::custom)?
+            .serialize(serializer)
+    }
 }
 
 impl ServiceConfig {
@@ -543,6 +1002,7 @@ impl ServiceConfig {
             servers: Vec::new(),
             clients: Vec::new(),
             encryption: None,
+            sealed: false,
         })
     }
 
@@ -557,6 +1017,7 @@ impl ServiceConfig {
                 })?)
             }
             Some(EncryptionScheme::Simple {}) => Ok(Key::from_password(&self.name)?),
+            Some(EncryptionScheme::Command { command }) => key_from_command(&command),
             None => Err(Error::Null(
                 "No encryption in use. Please choose a scheme from the options provided."
                     .to_string(),
@@ -572,10 +1033,68 @@ impl ServiceConfig {
     }
 
     pub fn set_simple_encryption(&mut self) -> Result<(), Error> {
+        if self.sealed {
+            return Err(Error::Incompatible(
+                "Cannot use the simple encryption scheme for a sealed config. \
+                 Please unseal the config first."
+                    .to_string(),
+            ));
+        }
+
         self.encryption = Some(EncryptionScheme::Simple {});
         Ok(())
     }
 
+    pub fn set_command_encryption(&mut self, command: &str) -> Result<(), Error> {
+        let command = command.trim();
+
+        if command.is_empty() {
+            return Err(Error::Null(
+                "No encryption password command provided.".to_string(),
+            ));
+        }
+
+        self.encryption = Some(EncryptionScheme::Command {
+            command: command.to_string(),
+        });
+
+        // make sure the command works before it is saved to the config
+        self.get_key()?;
+
+        Ok(())
+    }
+
+    ///
+    /// Set whether the servers and clients, including their keys, are
+    /// encrypted when the config is written to disk. They are decrypted
+    /// in memory when the config is loaded, so this needs an
+    /// encryption scheme to have been chosen. The simple scheme is
+    /// refused, as its key is derived from the service name that is
+    /// stored in the same file.
+    ///
+    pub fn set_sealed(&mut self, sealed: bool) -> Result<(), Error> {
+        if sealed {
+            if let Some(EncryptionScheme::Simple {}) = self.encryption {
+                return Err(Error::Incompatible(
+                    "Cannot seal the config with the simple encryption scheme, as \
+                     its key can be derived from the config file itself. Please \
+                     use the environment or command encryption schemes."
+                        .to_string(),
+                ));
+            }
+
+            // make sure that we can encrypt before we seal
+            self.get_key()?;
+        }
+
+        self.sealed = sealed;
+        Ok(())
+    }
+
+    pub fn is_sealed(&self) -> bool {
+        self.sealed
+    }
+
     pub fn encrypt<T>(&self, data: &T) -> Result<String, Error>
     where
         T: Serialize,
@@ -661,7 +1180,7 @@ impl ServiceConfig {
             }
         }
 
-        let client = ClientConfig::new(name, &ip, &zone);
+        let client = ClientConfig::new(name, &ip, &zone)?;
 
         self.clients.push(client.clone());
 
@@ -743,7 +1262,7 @@ impl ServiceConfig {
             })?;
 
         // rotate the keys
-        client.rotate_keys();
+        client.rotate_keys()?;
 
         // save as a new invite
         Ok(Invite::new(
@@ -755,6 +1274,84 @@ impl ServiceConfig {
         ))
     }
 
+    ///
+    /// Set when the invitation for the client called `name` expires if
+    /// it has not been used, or None if it never expires
+    ///
+    pub fn set_invitation_expiry(
+        &mut self,
+        name: &str,
+        zone: &Option<String>,
+        expires: Option<DateTime<Utc>>,
+    ) -> Result<(), Error> {
+        let client = self.client_mut(name, zone)?;
+        client.expires = expires;
+        Ok(())
+    }
+
+    ///
+    /// Generate new keys for the client called `name`, returning a new
+    /// invitation that replaces its old one. The new invitation is
+    /// unused, and expires at `expires` if given
+    ///
+    pub fn regenerate_invitation(
+        &mut self,
+        name: &str,
+        zone: &Option<String>,
+        expires: Option<DateTime<Utc>>,
+    ) -> Result<Invite, Error> {
+        let invite = self.rotate_client_keys(name, zone)?;
+
+        let client = self.client_mut(name, zone)?;
+        client.created = Some(Utc::now());
+        client.expires = expires;
+        client.used = None;
+        client.used_by = None;
+
+        Ok(invite)
+    }
+
+    ///
+    /// Record that the invitation for the client called `name` in `zone`
+    /// was first used by `used_by`. This returns false if the use of the
+    /// invitation was already recorded
+    ///
+    pub fn record_invitation_use(
+        &mut self,
+        name: &str,
+        zone: &str,
+        used_by: &str,
+    ) -> Result<bool, Error> {
+        let client = self.client_mut(name, &Some(zone.to_string()))?;
+
+        if client.used.is_some() {
+            return Ok(false);
+        }
+
+        client.used = Some(Utc::now());
+        client.used_by = Some(used_by.to_string());
+
+        Ok(true)
+    }
+
+    fn client_mut(
+        &mut self,
+        name: &str,
+        zone: &Option<String>,
+    ) -> Result<&mut ClientConfig, Error> {
+        let zone = self.clean_zone(zone)?;
+
+        self.clients
+            .iter_mut()
+            .find(|c| c.name == name && c.zone == zone)
+            .ok_or_else(|| {
+                Error::Peer(format!(
+                    "Client with name '{}' not found in zone {}.",
+                    name, zone
+                ))
+            })
+    }
+
     pub fn rotate_server_keys(&mut self, invite: &Invite) -> Result<(), Error> {
         // find the server with the given name and zone
         let server = self
@@ -815,6 +1412,10 @@ impl ServiceConfig {
     pub fn name(&self) -> String {
         self.name.clone()
     }
+
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
 }
 
 #[cfg(test)]
@@ -852,7 +1453,9 @@ mod tests {
             unreachable!("Could not create IP address: {:?}", e);
         });
 
-        let client = ClientConfig::new("test", &ip, &default_zone());
+        let client = ClientConfig::new("test", &ip, &default_zone()).unwrap_or_else(|e| {
+            unreachable!("Could not create client config: {:?}", e);
+        });
 
         assert_eq!(client.name, "test".to_string());
         assert_eq!(client.ip, ip);
@@ -908,4 +1511,196 @@ mod tests {
         assert_eq!(primary.clients()[0].name(), "secondary".to_string());
         assert_eq!(secondary.servers()[0].name(), "primary".to_string());
     }
+
+    #[test]
+    fn test_sealed_peers() {
+        let mut config = ServiceConfig::new(
+            "primary",
+            "http://localhost",
+            "127.0.0.1",
+            &5544,
+            &None,
+            &None,
+        )
+        .unwrap_or_else(|e| {
+            unreachable!("Cannot create service config: {}", e);
+        });
+
+        config
+            .add_client("secondary", "127.0.0.1", &None)
+            .unwrap_or_else(|e| {
+                unreachable!("Cannot add client: {}", e);
+            });
+
+        // cannot seal without an encryption scheme
+        assert!(config.set_sealed(true).is_err());
+
+        // or with the simple scheme, whose key is derived from the file
+        config.set_simple_encryption().unwrap_or_else(|e| {
+            unreachable!("Cannot set encryption: {}", e);
+        });
+
+        assert!(matches!(
+            config.set_sealed(true),
+            Err(Error::Incompatible(_))
+        ));
+        assert!(!config.is_sealed());
+
+        config
+            .set_command_encryption("echo test-sealed-peers")
+            .unwrap_or_else(|e| {
+                unreachable!("Cannot set encryption: {}", e);
+            });
+
+        config.set_sealed(true).unwrap_or_else(|e| {
+            unreachable!("Cannot seal config: {}", e);
+        });
+
+        let sealed = toml::to_string(&config).unwrap_or_else(|e| {
+            unreachable!("Cannot serialise sealed config: {}", e);
+        });
+
+        assert!(sealed.contains("sealed_peers"));
+        assert!(!sealed.contains("secondary"));
+
+        // cannot switch to the simple scheme while sealed
+        assert!(matches!(
+            config.set_simple_encryption(),
+            Err(Error::Incompatible(_))
+        ));
+
+        let loaded: ServiceConfig = toml::from_str(&sealed).unwrap_or_else(|e| {
+            unreachable!("Cannot load sealed config: {}", e);
+        });
+
+        assert!(loaded.is_sealed());
+        assert_eq!(loaded.clients().len(), 1);
+        assert_eq!(loaded.clients()[0].name(), "secondary".to_string());
+        assert_eq!(
+            serde_json::to_string(&loaded.clients()[0].inner_key()).unwrap_or_default(),
+            serde_json::to_string(&config.clients()[0].inner_key()).unwrap_or_default()
+        );
+
+        // unsealing writes the peers back in plain text
+        let mut unsealed = loaded.clone();
+        unsealed.set_sealed(false).unwrap_or_else(|e| {
+            unreachable!("Cannot unseal config: {}", e);
+        });
+
+        let unsealed = toml::to_string(&unsealed).unwrap_or_else(|e| {
+            unreachable!("Cannot serialise unsealed config: {}", e);
+        });
+
+        assert!(!unsealed.contains("sealed_peers"));
+        assert!(unsealed.contains("secondary"));
+    }
+
+    #[test]
+    fn test_command_encryption_failures() {
+        let mut config = ServiceConfig::new(
+            "primary",
+            "http://localhost",
+            "127.0.0.1",
+            &5544,
+            &None,
+            &None,
+        )
+        .unwrap_or_else(|e| {
+            unreachable!("Cannot create service config: {}", e);
+        });
+
+        assert!(matches!(
+            config.set_command_encryption("  "),
+            Err(Error::Null(_))
+        ));
+        assert!(matches!(
+            config.set_command_encryption("exit 3"),
+            Err(Error::Unavailable(_))
+        ));
+        assert!(matches!(
+            config.set_command_encryption("true"),
+            Err(Error::Null(_))
+        ));
+        assert!(matches!(
+            config.set_command_encryption("printf '\\n'"),
+            Err(Error::Null(_))
+        ));
+
+        // a failed command is not cached, so cannot be used to seal
+        assert!(config.set_sealed(true).is_err());
+        assert!(!config.is_sealed());
+    }
+
+    #[test]
+    fn test_invitation_lifecycle() {
+        let mut config = ServiceConfig::new(
+            "primary",
+            "http://localhost",
+            "127.0.0.1",
+            &5544,
+            &None,
+            &None,
+        )
+        .unwrap_or_else(|e| {
+            unreachable!("Cannot create service config: {}", e);
+        });
+
+        config
+            .add_client("secondary", "127.0.0.1", &None)
+            .unwrap_or_else(|e| {
+                unreachable!("Cannot add client: {}", e);
+            });
+
+        let client = config.clients()[0].clone();
+        assert!(client.created().is_some());
+        assert!(!client.is_invitation_used());
+        assert!(!client.is_invitation_expired());
+
+        // an unused invitation expires
+        config
+            .set_invitation_expiry(
+                "secondary",
+                &None,
+                Some(Utc::now() - chrono::Duration::hours(1)),
+            )
+            .unwrap_or_else(|e| {
+                unreachable!("Cannot set invitation expiry: {}", e);
+            });
+
+        assert!(config.clients()[0].is_invitation_expired());
+
+        // but not once it has been used
+        let zone = config.clients()[0].zone();
+
+        assert!(config
+            .record_invitation_use("secondary", &zone, "secondary")
+            .unwrap_or_else(|e| {
+                unreachable!("Cannot record invitation use: {}", e);
+            }));
+
+        assert!(!config
+            .record_invitation_use("secondary", &zone, "other")
+            .unwrap_or_else(|e| {
+                unreachable!("Cannot record invitation use: {}", e);
+            }));
+
+        let client = config.clients()[0].clone();
+        assert!(!client.is_invitation_expired());
+        assert_eq!(client.used_by(), Some("secondary".to_string()));
+
+        // regenerating gives new keys and an unused invitation
+        let invite = config
+            .regenerate_invitation("secondary", &None, None)
+            .unwrap_or_else(|e| {
+                unreachable!("Cannot regenerate invitation: {}", e);
+            });
+
+        let client = config.clients()[0].clone();
+        assert!(client.used().is_none());
+        assert!(client.expires().is_none());
+        assert_eq!(
+            serde_json::to_string(&invite.inner_key()).unwrap_or_default(),
+            serde_json::to_string(&client.inner_key()).unwrap_or_default()
+        );
+    }
 }
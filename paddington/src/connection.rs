@@ -25,11 +25,12 @@ use tungstenite::handshake::server::{
 };
 
 use crate::command::Command;
-use crate::config::{ClientConfig, PeerConfig, ServiceConfig};
+use crate::config::{self, ClientConfig, PeerConfig, ServiceConfig};
 use crate::crypto::{random_bytes, Key, Salt, SecretKey, KEY_SIZE};
 use crate::error::Error;
 use crate::exchange;
 use crate::message::Message;
+use crate::replay;
 
 #[derive(Debug, Clone, PartialEq)]
 enum ConnectionStatus {
@@ -224,8 +225,10 @@ where
     T: Serialize,
 {
     // we will now generate per-message keys, using a
-    // random additional info and the salts for this connection
-    let inner_info = random_bytes(KEY_SIZE)?;
+    // random additional info and the salts for this connection.
+    // The inner info starts with the send time and nonce that
+    // are used to reject replayed messages
+    let inner_info = replay::message_info()?;
     let outer_info = random_bytes(KEY_SIZE)?;
 
     let inner_key = inner_key
@@ -280,6 +283,33 @@ where
     )?)
 }
 
+///
+/// Return the nonce of the passed (still enveloped) message, which is
+/// checked by `check_replay` once the message has been de-enveloped
+///
+fn message_nonce(message: &TokioMessage) -> Option<replay::Nonce> {
+    message.to_text().ok().and_then(replay::nonce)
+}
+
+///
+/// Return an error if a message with the passed nonce has already been
+/// received from this peer, or was sent outside the replay window. This
+/// must only be called once the message has been de-enveloped, so that
+/// forged nonces are never recorded, and the message must only be
+/// handled once this has returned, as the nonce is saved first
+///
+async fn check_replay(
+    peer_name: &str,
+    peer_zone: &str,
+    nonce: Option<replay::Nonce>,
+) -> Result<(), Error> {
+    let nonce = nonce.ok_or_else(|| {
+        Error::Incompatible("Message is too short to contain a nonce".to_string())
+    })?;
+
+    replay::check(&format!("{}@{}", peer_name, peer_zone), nonce).await
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Handshake {
     session_key: SecretKey,
@@ -486,6 +516,10 @@ impl Connection {
             }
         };
 
+        // use the server's keys if they were rotated while we are running
+        let server = &server.with_rotated_keys();
+        let peer = &server.to_peer();
+
         // now check that we aren't already handling a connection
         match self.state.lock() {
             Ok(mut state) => {
@@ -559,7 +593,7 @@ impl Connection {
         // the client generates a handshake that contains the new session outer key,
         // the name of its comms engine and version, and sends this to the server
         // using the pre-shared client/server inner and outer keys
-        let outer_key = Key::generate();
+        let outer_key = Key::generate()?;
 
         let handshake = Handshake {
             session_key: outer_key.clone(),
@@ -864,43 +898,61 @@ impl Connection {
         // and now we can start the message handling loop - make sure to
         // handle the sending of messages to others
         let received_from_peer = incoming.try_for_each(|msg| {
-            if msg.is_empty() {
-                // this may happen, e.g. if the connection is closed
-                // This can be safely ignored
-                return future::ok(());
-            }
-
             // we need to deenvelope the message
-            let msg: String = match deenvelope_message(
-                msg,
-                &inner_key,
-                &outer_key,
-                &inner_key_salt,
-                &outer_key_salt,
-            ) {
-                Ok(msg) => msg,
-                Err(e) => {
-                    tracing::warn!("Error de-enveloping message: {:?}", e);
-                    return future::ok(());
-                }
-            };
+            let received = (!msg.is_empty()).then(|| {
+                (
+                    message_nonce(&msg),
+                    deenvelope_message::<String>(
+                        msg,
+                        &inner_key,
+                        &outer_key,
+                        &inner_key_salt,
+                        &outer_key_salt,
+                    ),
+                )
+            });
 
-            exchange::received(Message::received_from(&peer_name, &peer_zone, &msg))
-                .unwrap_or_else(|e| {
-                    tracing::warn!("Error handling message: {:?}", e);
-                });
+            let state = self.state.clone();
+            let peer_name = peer_name.clone();
+            let peer_zone = peer_zone.clone();
+
+            async move {
+                let Some((nonce, msg)) = received else {
+                    // this may happen, e.g. if the connection is closed
+                    // This can be safely ignored
+                    return Ok(());
+                };
+
+                let msg = match msg {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        tracing::warn!("Error de-enveloping message: {:?}", e);
+                        return Ok(());
+                    }
+                };
 
-            // record the last time we successfully received a message
-            match self.state.lock() {
-                Ok(mut state) => {
-                    state.register_activity();
+                // replayed messages are dropped (check_replay logs them)
+                if check_replay(&peer_name, &peer_zone, nonce).await.is_err() {
+                    return Ok(());
                 }
-                Err(e) => {
-                    tracing::warn!("Error registering activity: {:?}", e);
+
+                exchange::received(Message::received_from(&peer_name, &peer_zone, &msg))
+                    .unwrap_or_else(|e| {
+                        tracing::warn!("Error handling message: {:?}", e);
+                    });
+
+                // record the last time we successfully received a message
+                match state.lock() {
+                    Ok(mut state) => {
+                        state.register_activity();
+                    }
+                    Err(e) => {
+                        tracing::warn!("Error registering activity: {:?}", e);
+                    }
                 }
-            }
 
-            future::ok(())
+                Ok(())
+            }
         });
 
         // handle messages that should be sent to the client (received locally
@@ -1067,7 +1119,7 @@ impl Connection {
             .clients()
             .iter()
             .filter(|client| client.matches(client_ip))
-            .cloned()
+            .flat_map(|client| client.with_rotated_keys())
             .collect();
 
         if clients.is_empty() {
@@ -1160,6 +1212,18 @@ impl Connection {
             ));
         }
 
+        if peer.is_invitation_expired() {
+            tracing::warn!(
+                "The invitation for {:?} expired before it was used - closing connection.",
+                peer_name
+            );
+            return Err(Error::InvalidPeer(
+                "The invitation for this peer has expired.".to_string(),
+            ));
+        }
+
+        config::record_invitation_used(&peer_name, &peer_zone);
+
         tracing::info!(
             "Initiating connection: {:?} <=> {:?}",
             service_name,
@@ -1172,6 +1236,8 @@ impl Connection {
 
         // the peer has sent us the new session outer key that should be used,
         // wrapped in the client/server inner and outer keys
+        let nonce = message_nonce(&message);
+
         let handshake = deenvelope_message::<Handshake>(
             message,
             &peer.inner_key(),
@@ -1181,6 +1247,11 @@ impl Connection {
         )
         .with_context(|| "Error de-enveloping message - closing connection.")?;
 
+        // the handshake is made with the pre-shared keys, so could be
+        // replayed in a new connection - its nonce is saved before the
+        // handshake is accepted, so that it is also rejected after a restart
+        check_replay(&peer_name, &peer_zone, nonce).await?;
+
         let outer_key = handshake.session_key.clone();
 
         let peer_engine = handshake.engine;
@@ -1188,7 +1259,7 @@ impl Connection {
 
         // we will create a new session inner key and send it back to the
         // client, wrapped in the client/server inner key and session outer key
-        let inner_key = Key::generate();
+        let inner_key = Key::generate()?;
 
         let handshake = Handshake {
             session_key: inner_key.clone(),
@@ -1437,36 +1508,51 @@ impl Connection {
         // handle the sending of messages to others
         let received_from_peer = incoming.try_for_each(|msg| {
             // we need to deenvelope the message
-            let msg: String = match deenvelope_message(
+            let nonce = message_nonce(&msg);
+
+            let msg = deenvelope_message::<String>(
                 msg,
                 &inner_key,
                 &outer_key,
                 &inner_key_salt,
                 &outer_key_salt,
-            ) {
-                Ok(msg) => msg,
-                Err(e) => {
-                    tracing::warn!("Error de-enveloping message: {:?}", e);
-                    return future::ok(());
-                }
-            };
+            );
 
-            exchange::received(Message::received_from(&peer_name, &peer_zone, &msg))
-                .unwrap_or_else(|e| {
-                    tracing::warn!("Error handling message: {:?}", e);
-                });
+            let state = self.state.clone();
+            let peer_name = peer_name.clone();
+            let peer_zone = peer_zone.clone();
 
-            // record the last time we successfully received a message
-            match self.state.lock() {
-                Ok(mut state) => {
-                    state.register_activity();
+            async move {
+                let msg = match msg {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        tracing::warn!("Error de-enveloping message: {:?}", e);
+                        return Ok(());
+                    }
+                };
+
+                // replayed messages are dropped (check_replay logs them)
+                if check_replay(&peer_name, &peer_zone, nonce).await.is_err() {
+                    return Ok(());
                 }
-                Err(e) => {
-                    tracing::warn!("Error registering activity: {:?}", e);
+
+                exchange::received(Message::received_from(&peer_name, &peer_zone, &msg))
+                    .unwrap_or_else(|e| {
+                        tracing::warn!("Error handling message: {:?}", e);
+                    });
+
+                // record the last time we successfully received a message
+                match state.lock() {
+                    Ok(mut state) => {
+                        state.register_activity();
+                    }
+                    Err(e) => {
+                        tracing::warn!("Error registering activity: {:?}", e);
+                    }
                 }
-            }
 
-            future::ok(())
+                Ok(())
+            }
         });
 
         // handle messages that should be sent to the client (received locally
@@ -1508,8 +1594,10 @@ mod tests {
 
     #[test]
     fn test_enveloping() {
-        let inner_key = Key::generate();
-        let outer_key = Key::generate();
+        #[allow(clippy::unwrap_used)]
+        let inner_key = Key::generate().unwrap();
+        #[allow(clippy::unwrap_used)]
+        let outer_key = Key::generate().unwrap();
         #[allow(clippy::unwrap_used)]
         let inner_key_salt = Salt::generate().unwrap();
         #[allow(clippy::unwrap_used)]
@@ -1541,4 +1629,35 @@ mod tests {
 
         assert_eq!(message, deenvelope);
     }
+
+    #[tokio::test]
+    async fn test_replay() {
+        #[allow(clippy::unwrap_used)]
+        let inner_key = Key::generate().unwrap();
+        #[allow(clippy::unwrap_used)]
+        let outer_key = Key::generate().unwrap();
+        #[allow(clippy::unwrap_used)]
+        let inner_key_salt = Salt::generate().unwrap();
+        #[allow(clippy::unwrap_used)]
+        let outer_key_salt = Salt::generate().unwrap();
+
+        let envelope = envelope_message(
+            "Hello, world!",
+            &inner_key,
+            &outer_key,
+            &inner_key_salt,
+            &outer_key_salt,
+        )
+        .unwrap_or_else(|e| {
+            unreachable!("Error enveloping message: {:?}", e);
+        });
+
+        let nonce = message_nonce(&envelope);
+        assert!(nonce.is_some());
+
+        assert!(check_replay("test_replay", "zone", nonce).await.is_ok());
+        assert!(check_replay("test_replay", "zone", nonce).await.is_err());
+        assert!(check_replay("test_replay", "other", nonce).await.is_ok());
+        assert!(check_replay("test_replay", "zone", None).await.is_err());
+    }
 }
@@ -7,16 +7,134 @@ use secrecy::{zeroize::Zeroize, CloneableSecret, SecretBox, SerializableSecret};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_with::serde_as;
 use std::fmt::Display;
+use std::sync::OnceLock;
 use std::{fmt, str, vec};
 
 use crate::error::Error;
+use crate::fips;
 
 pub const KEY_SIZE: usize = 32;
 pub const SALT_SIZE: usize = KEY_SIZE;
 
-#[derive(Debug, Clone, PartialEq)]
+/// The environment variable used to choose the crypto mode
+pub const CRYPTO_MODE_ENV: &str = "OPENPORTAL_CRYPTO_MODE";
+
+///
+/// The set of algorithms used for encryption, signing, hashing and
+/// key derivation. All agents, bridges and clients that talk to each
+/// other must use the same mode, as the algorithms are not compatible
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CryptoMode {
+    /// XChaCha20-Poly1305, BLAKE2b and Argon2i (via orion), with ring for TLS
+    Standard,
+
+    /// Only FIPS-approved algorithms (AES-256-GCM, HMAC-SHA256, SHA-256,
+    /// HKDF and PBKDF2), from the FIPS 140-3 validated AWS-LC module
+    Fips,
+}
+
+impl Display for CryptoMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CryptoMode::Standard => write!(f, "standard"),
+            CryptoMode::Fips => write!(f, "fips"),
+        }
+    }
+}
+
+impl std::str::FromStr for CryptoMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "standard" => Ok(CryptoMode::Standard),
+            "fips" => Ok(CryptoMode::Fips),
+            _ => Err(Error::Parse(format!(
+                "Invalid crypto mode '{}' - this should be 'standard' or 'fips'",
+                s
+            ))),
+        }
+    }
+}
+
+/// The crypto mode used by this process - this cannot change once set
+static CRYPTO_MODE: OnceLock<CryptoMode> = OnceLock::new();
+
+///
+/// Return whether or not paddington was built with support for FIPS mode
+///
+pub fn fips_available() -> bool {
+    cfg!(feature = "fips")
+}
+
+///
+/// Set the crypto mode used by this process. This must be called before
+/// any keys are used, and returns an error if a different mode has
+/// already been set, or if FIPS mode is requested but is not available
+///
+pub fn set_crypto_mode(mode: CryptoMode) -> Result<CryptoMode, Error> {
+    if mode == CryptoMode::Fips {
+        fips::check()?;
+    }
+
+    let active = *CRYPTO_MODE.get_or_init(|| {
+        tracing::info!("Using the {} crypto mode", mode);
+        mode
+    });
+
+    match active == mode {
+        true => Ok(active),
+        false => Err(Error::Incompatible(format!(
+            "Cannot use the {} crypto mode, as the {} crypto mode is already in use",
+            mode, active
+        ))),
+    }
+}
+
+///
+/// Return the crypto mode used by this process. If this has not been set,
+/// then it is read from the OPENPORTAL_CRYPTO_MODE environment variable,
+/// defaulting to FIPS mode if paddington was built with the `fips`
+/// feature, and standard mode otherwise
+///
+pub fn crypto_mode() -> Result<CryptoMode, Error> {
+    if let Some(mode) = CRYPTO_MODE.get() {
+        return Ok(*mode);
+    }
+
+    let mode = match std::env::var(CRYPTO_MODE_ENV) {
+        Ok(mode) if !mode.trim().is_empty() => mode.parse()?,
+        _ => match fips_available() {
+            true => CryptoMode::Fips,
+            false => CryptoMode::Standard,
+        },
+    };
+
+    set_crypto_mode(mode)
+}
+
+///
+/// Return the rustls crypto provider that matches the crypto mode
+///
+pub fn tls_provider() -> Result<rustls::crypto::CryptoProvider, Error> {
+    match crypto_mode()? {
+        CryptoMode::Standard => Ok(rustls::crypto::ring::default_provider()),
+        CryptoMode::Fips => fips::tls_provider(),
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Signature {
-    sig: orion::auth::Tag,
+    sig: Vec<u8>,
+}
+
+impl PartialEq for Signature {
+    fn eq(&self, other: &Self) -> bool {
+        // constant-time comparison, as signatures authenticate messages
+        orion::util::secure_cmp(&self.sig, &other.sig).is_ok()
+    }
 }
 
 impl Serialize for Signature {
@@ -24,7 +142,7 @@ impl Serialize for Signature {
     where
         S: serde::ser::Serializer,
     {
-        hex::encode(self.sig.unprotected_as_bytes()).serialize(serializer)
+        hex::encode(&self.sig).serialize(serializer)
     }
 }
 
@@ -34,34 +152,61 @@ impl<'de> Deserialize<'de> for Signature {
         D: serde::de::Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        let bytes = hex::decode(s).map_err(serde::de::Error::custom)?;
-        match orion::auth::Tag::from_slice(&bytes) {
-            Ok(sig) => Ok(Signature { sig }),
-            Err(_) => Err(serde::de::Error::custom("Failed to create Signature.")),
-        }
+        Signature::from_string(&s).map_err(serde::de::Error::custom)
     }
 }
 
 impl Display for Signature {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", hex::encode(self.sig.unprotected_as_bytes()))
+        write!(f, "{}", hex::encode(&self.sig))
     }
 }
 
 impl Signature {
     pub fn from_string(s: &str) -> Result<Signature, Error> {
-        let bytes = hex::decode(s).with_context(|| "Failed to decode the signature.")?;
-        Ok(Signature {
-            sig: orion::auth::Tag::from_slice(&bytes)
-                .with_context(|| "Failed to create signature.")?,
-        })
+        let sig = hex::decode(s).with_context(|| "Failed to decode the signature.")?;
+
+        if sig.is_empty() {
+            return Err(anyhow::anyhow!("Failed to create signature - it is empty.").into());
+        }
+
+        Ok(Signature { sig })
     }
 }
 
+///
+/// Return the hex-encoded BLAKE2b-256 (or, in FIPS mode, SHA-256) hash
+/// of the passed data, once it has been serialised to JSON
+///
+pub fn digest<T>(data: T) -> Result<String, Error>
+where
+    T: Serialize,
+{
+    let json_data = serde_json::to_string(&data).with_context(|| {
+        "Failed to serialise the data to JSON. Ensure that the data is serialisable by serde."
+    })?;
+
+    let digest = match crypto_mode()? {
+        CryptoMode::Standard => orion::hash::digest(json_data.as_bytes())
+            .with_context(|| "Failed to hash the data.")?
+            .as_ref()
+            .to_vec(),
+        CryptoMode::Fips => fips::digest(json_data.as_bytes())?,
+    };
+
+    Ok(hex::encode(digest))
+}
+
 pub fn random_bytes(size: usize) -> Result<Vec<u8>, Error> {
-    let mut data: Vec<u8> = vec![0; size];
-    orion::util::secure_rand_bytes(&mut data).context("Failed to generate random bytes.")?;
-    Ok(data)
+    match crypto_mode()? {
+        CryptoMode::Standard => {
+            let mut data: Vec<u8> = vec![0; size];
+            orion::util::secure_rand_bytes(&mut data)
+                .context("Failed to generate random bytes.")?;
+            Ok(data)
+        }
+        CryptoMode::Fips => fips::random_bytes(size),
+    }
 }
 
 #[serde_as]
@@ -79,8 +224,7 @@ impl Display for Salt {
 
 impl Salt {
     pub fn generate() -> Result<Salt, Error> {
-        let mut data: Vec<u8> = [0u8; SALT_SIZE].to_vec();
-        orion::util::secure_rand_bytes(&mut data).context("Failed to generate a salt.")?;
+        let data = random_bytes(SALT_SIZE).context("Failed to generate a salt.")?;
 
         Ok(Salt { data })
     }
@@ -107,12 +251,21 @@ impl std::str::FromStr for Salt {
 }
 
 #[serde_as]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Key {
     #[serde_as(as = "serde_with::hex::Hex")]
     data: vec::Vec<u8>,
 }
 
+/// Never print the key data, even if the key is exposed from its SecretBox
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Key")
+            .field("data", &format_args!("{}", crate::redact::REDACTED))
+            .finish()
+    }
+}
+
 impl Zeroize for Key {
     fn zeroize(&mut self) {
         self.data.zeroize();
@@ -132,20 +285,25 @@ impl Key {
     ///
     /// # Returns
     ///
-    /// The secret key.
+    /// The secret key, or an error if random data could not be
+    /// generated in the current crypto mode.
     ///
     /// # Example
     ///
     /// ```
     /// use paddington::{Key, SecretKey};
     ///
-    /// let key = Key::generate();
+    /// let key = Key::generate().unwrap();
     /// ```
-    pub fn generate() -> SecretKey {
-        Box::new(Key {
-            data: aead::SecretKey::default().unprotected_as_bytes().to_vec(),
-        })
-        .into()
+    pub fn generate() -> Result<SecretKey, Error> {
+        // never fall back to a non-approved generator - the error is
+        // returned so that no key is made from it
+        let data = match crypto_mode()? {
+            CryptoMode::Standard => aead::SecretKey::default().unprotected_as_bytes().to_vec(),
+            CryptoMode::Fips => fips::random_bytes(KEY_SIZE)?,
+        };
+
+        Ok(Box::new(Key { data }).into())
     }
 
     ///
@@ -157,10 +315,17 @@ impl Key {
         salt: &Salt,
         additional_info: Option<&[u8]>,
     ) -> Result<SecretKey, Error> {
-        let mut new_key = self.data.clone();
+        let new_key = match crypto_mode()? {
+            CryptoMode::Standard => {
+                let mut new_key = self.data.clone();
+
+                hkdf::sha512::derive_key(&salt.data, &self.data, additional_info, &mut new_key)
+                    .context("Failed to derive key.")?;
 
-        hkdf::sha512::derive_key(&salt.data, &self.data, additional_info, &mut new_key)
-            .context("Failed to derive key.")?;
+                new_key
+            }
+            CryptoMode::Fips => fips::derive(&salt.data, &self.data, additional_info)?,
+        };
 
         Ok(Box::new(Key { data: new_key }).into())
     }
@@ -195,13 +360,20 @@ impl Key {
             0xf0, 0x01,
         ];
 
+        if crypto_mode()? == CryptoMode::Fips {
+            return Ok(Box::new(Key {
+                data: fips::from_password(password.as_bytes(), &salt, KEY_SIZE)?,
+            })
+            .into());
+        }
+
         let salt =
             kdf::Salt::from_slice(&salt).context("Failed to create a salt from the salt data.")?;
 
         Ok(Box::new(Key {
             data: kdf::derive_key(
                 &kdf::Password::from_slice(password.as_bytes())
-                    .context(format!("Failed to generate a password from {}", password))?,
+                    .context("Failed to generate a password from the supplied password.")?,
                 &salt,
                 3,
                 8,
@@ -252,7 +424,7 @@ impl Key {
     /// use paddington::{Key, SecretKey};
     /// use secrecy::ExposeSecret;
     ///
-    /// let key = Key::generate();
+    /// let key = Key::generate().unwrap();
     ///
     /// let encrypted_data = key.expose_secret().encrypt("Hello, World!".to_string());
     /// ```
@@ -260,14 +432,20 @@ impl Key {
     where
         T: Serialize,
     {
-        let orion_key = aead::SecretKey::from_slice(&self.data)
-            .with_context(|| "Failed to create a secret key from the secret key data.")?;
         let json_data = serde_json::to_string(&data).with_context(|| {
             "Failed to serialise the data to JSON. Ensure that the data is serialisable by serde."
         })?;
 
-        let encrypted_data = aead::seal(&orion_key, json_data.as_bytes())
-            .with_context(|| "Failed to encrypt the data.")?;
+        let encrypted_data = match crypto_mode()? {
+            CryptoMode::Standard => {
+                let orion_key = aead::SecretKey::from_slice(&self.data)
+                    .with_context(|| "Failed to create a secret key from the secret key data.")?;
+
+                aead::seal(&orion_key, json_data.as_bytes())
+                    .with_context(|| "Failed to encrypt the data.")?
+            }
+            CryptoMode::Fips => fips::encrypt(&self.data, json_data.as_bytes())?,
+        };
 
         Ok(hex::encode(encrypted_data))
     }
@@ -290,7 +468,7 @@ impl Key {
     /// use paddington::{Key, SecretKey};
     /// use secrecy::ExposeSecret;
     ///
-    /// let key = Key::generate();
+    /// let key = Key::generate().unwrap();
     ///
     /// let encrypted_data = key.expose_secret().encrypt("Hello, World!").unwrap();
     /// let decrypted_data: String = key.expose_secret().decrypt(&encrypted_data).unwrap();
@@ -301,13 +479,17 @@ impl Key {
     where
         T: DeserializeOwned,
     {
-        let orion_key = aead::SecretKey::from_slice(&self.data)
-            .with_context(|| "Failed to create a secret key from the secret key data.")?;
-
         let data = hex::decode(data).with_context(|| "Failed to decode the hex-encoded data.")?;
 
-        let decrypted_data =
-            aead::open(&orion_key, &data).with_context(|| "Failed to decrypt the data.")?;
+        let decrypted_data = match crypto_mode()? {
+            CryptoMode::Standard => {
+                let orion_key = aead::SecretKey::from_slice(&self.data)
+                    .with_context(|| "Failed to create a secret key from the secret key data.")?;
+
+                aead::open(&orion_key, &data).with_context(|| "Failed to decrypt the data.")?
+            }
+            CryptoMode::Fips => fips::decrypt(&self.data, &data)?,
+        };
 
         let decrypted_string: String = String::from_utf8(decrypted_data)
             .with_context(|| "Failed to convert the decrypted data to a string.")?;
@@ -336,7 +518,7 @@ impl Key {
     /// use paddington::{Key, SecretKey};
     /// use secrecy::ExposeSecret;
     ///
-    /// let key = Key::generate();
+    /// let key = Key::generate().unwrap();
     ///
     /// let signature = key.expose_secret().sign("Hello, World!".to_string());
     /// ```
@@ -345,16 +527,24 @@ impl Key {
     where
         T: Serialize,
     {
-        let orion_key = aead::SecretKey::from_slice(&self.data)
-            .with_context(|| "Failed to create a secret key from the secret key data.")?;
         let json_data = serde_json::to_string(&data).with_context(|| {
             "Failed to serialise the data to JSON. Ensure that the data is serialisable by serde."
         })?;
 
-        let signature = auth::authenticate(&orion_key, json_data.as_bytes())
-            .with_context(|| "Failed to sign the data.")?;
+        let sig = match crypto_mode()? {
+            CryptoMode::Standard => {
+                let orion_key = aead::SecretKey::from_slice(&self.data)
+                    .with_context(|| "Failed to create a secret key from the secret key data.")?;
+
+                auth::authenticate(&orion_key, json_data.as_bytes())
+                    .with_context(|| "Failed to sign the data.")?
+                    .unprotected_as_bytes()
+                    .to_vec()
+            }
+            CryptoMode::Fips => fips::sign(&self.data, json_data.as_bytes())?,
+        };
 
-        Ok(Signature { sig: signature })
+        Ok(Signature { sig })
     }
 
     ///
@@ -376,7 +566,7 @@ impl Key {
     /// use paddington::{Key, SecretKey};
     /// use secrecy::ExposeSecret;
     ///
-    /// let key = Key::generate();
+    /// let key = Key::generate().unwrap();
     ///
     /// let signature = key.expose_secret().sign("Hello, World!".to_string()).unwrap();
     ///
@@ -387,15 +577,23 @@ impl Key {
     where
         T: Serialize,
     {
-        let orion_key = aead::SecretKey::from_slice(&self.data)
-            .with_context(|| "Failed to create a secret key from the secret key data.")?;
-
         let data = serde_json::to_string(&data).with_context(|| {
             "Failed to serialise the data to JSON. Ensure that the data is serialisable by serde."
         })?;
 
-        auth::authenticate_verify(&signature.sig, &orion_key, data.as_bytes())
-            .with_context(|| "Failed to verify the data.")?;
+        match crypto_mode()? {
+            CryptoMode::Standard => {
+                let orion_key = aead::SecretKey::from_slice(&self.data)
+                    .with_context(|| "Failed to create a secret key from the secret key data.")?;
+
+                let tag = auth::Tag::from_slice(&signature.sig)
+                    .with_context(|| "Failed to read the signature.")?;
+
+                auth::authenticate_verify(&tag, &orion_key, data.as_bytes())
+                    .with_context(|| "Failed to verify the data.")?;
+            }
+            CryptoMode::Fips => fips::verify(&self.data, data.as_bytes(), &signature.sig)?,
+        }
 
         Ok(())
     }
@@ -409,7 +607,10 @@ mod tests {
 
     #[test]
     fn test_key_generate() {
-        let key = Key::generate();
+        let key = Key::generate().unwrap_or_else(|err| {
+            unreachable!("Failed to generate key: {}", err);
+        });
+
         assert_eq!(key.expose_secret().data.len(), KEY_SIZE);
     }
 
@@ -430,7 +631,9 @@ mod tests {
 
     #[test]
     fn test_key_encrypt_decrypt() {
-        let key: SecretBox<Key> = Key::generate();
+        let key: SecretBox<Key> = Key::generate().unwrap_or_else(|err| {
+            unreachable!("Failed to generate key: {}", err);
+        });
 
         let encrypted_data: String = key
             .expose_secret()
@@ -451,7 +654,9 @@ mod tests {
 
     #[test]
     fn test_key_sign_verify() {
-        let key: SecretBox<Key> = Key::generate();
+        let key: SecretBox<Key> = Key::generate().unwrap_or_else(|err| {
+            unreachable!("Failed to generate key: {}", err);
+        });
 
         let signature: Signature = key
             .expose_secret()
@@ -466,4 +671,58 @@ mod tests {
                 unreachable!("Failed to verify data: {}", err);
             });
     }
+
+    #[test]
+    fn test_digest() {
+        let hash = digest("Hello, World!".to_string()).unwrap_or_else(|err| {
+            unreachable!("Failed to hash data: {}", err);
+        });
+
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, digest("Hello, World!").unwrap_or_default());
+        assert_ne!(hash, digest("Hello, World").unwrap_or_default());
+    }
+
+    #[test]
+    fn test_crypto_mode() {
+        for mode in [CryptoMode::Standard, CryptoMode::Fips] {
+            assert_eq!(mode.to_string().parse::<CryptoMode>().ok(), Some(mode));
+        }
+
+        assert_eq!(" FIPS ".parse::<CryptoMode>().ok(), Some(CryptoMode::Fips));
+        assert!("chacha".parse::<CryptoMode>().is_err());
+
+        let mode = crypto_mode().unwrap_or_else(|err| {
+            unreachable!("Failed to get the crypto mode: {}", err);
+        });
+
+        assert_eq!(set_crypto_mode(mode).ok(), Some(mode));
+
+        if !fips_available() {
+            assert_eq!(mode, CryptoMode::Standard);
+            assert!(set_crypto_mode(CryptoMode::Fips).is_err());
+        }
+    }
+
+    #[test]
+    fn test_signature_string() {
+        let key: SecretBox<Key> = Key::generate().unwrap_or_else(|err| {
+            unreachable!("Failed to generate key: {}", err);
+        });
+
+        let signature = key
+            .expose_secret()
+            .sign("Hello, World!".to_string())
+            .unwrap_or_else(|err| {
+                unreachable!("Failed to sign data: {}", err);
+            });
+
+        let parsed = Signature::from_string(&signature.to_string()).unwrap_or_else(|err| {
+            unreachable!("Failed to parse signature: {}", err);
+        });
+
+        assert_eq!(parsed, signature);
+        assert!(Signature::from_string("").is_err());
+        assert!(Signature::from_string("not hex").is_err());
+    }
 }
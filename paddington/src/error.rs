@@ -5,68 +5,74 @@ use anyhow::Error as AnyError;
 use std::io::Error as IOError;
 use thiserror::Error;
 
+// all messages are redacted, as errors are often created by formatting
+// config or requests that may contain secrets
+
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
     IO(#[from] IOError),
 
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
     Any(#[from] AnyError),
 
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
     Tungstenite(#[from] tokio_tungstenite::tungstenite::error::Error),
 
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
     TokioTask(#[from] tokio::task::JoinError),
 
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
     BusyLine(String),
 
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
     Derived(String),
 
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
     InvalidPeer(String),
 
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
     ServerIsSecondary(String),
 
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
     ClientIsSecondary(String),
 
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
     PeerIsSecondary(String),
 
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
     NotExists(String),
 
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
     Null(String),
 
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
     Parse(String),
 
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
     Peer(String),
 
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
+    Replay(String),
+
+    #[error("{}", crate::redact::redact(.0))]
     Poison(String),
 
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
     Send(String),
 
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
     TooManyStandbyConnections(String),
 
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
     UnknownPeer(String),
 
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
     UnnamedConnection(String),
 
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
     Incompatible(String),
 
-    #[error("{0}")]
+    #[error("{}", crate::redact::redact(.0))]
     Unavailable(String),
 }
@@ -5,19 +5,24 @@ use anyhow::Result;
 
 use crate::config::ServiceConfig;
 use crate::error::Error;
-use crate::{client, server};
+use crate::{client, replay, server};
 
 pub async fn run(config: ServiceConfig) -> Result<(), Error> {
-    match rustls::crypto::ring::default_provider().install_default() {
+    match crate::crypto::tls_provider()?.install_default() {
         Ok(_) => {}
         Err(e) => {
-            tracing::error!("Could not install default ring provider: {:?}", e);
+            tracing::error!("Could not install default TLS crypto provider: {:?}", e);
             return Err(Error::NotExists(
-                "Could not install default ring provider".to_owned(),
+                "Could not install default TLS crypto provider".to_owned(),
             ));
         }
     }
 
+    // compact the store of the nonces of received messages, which are
+    // saved as they are received so that they cannot be replayed after
+    // a restart
+    let saver = tokio::spawn(replay::save_periodically());
+
     let mut server_handles = vec![];
     let mut client_handles = vec![];
 
@@ -59,6 +64,12 @@ pub async fn run(config: ServiceConfig) -> Result<(), Error> {
         let _ = handle.await?;
     }
 
+    saver.abort();
+
+    if let Err(e) = replay::save_replay_store().await {
+        tracing::error!("Could not save the replay store: {}", e);
+    }
+
     tracing::info!("All handles joined.");
 
     Ok(())
@@ -12,6 +12,7 @@ use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::task::JoinSet;
 
+use crate::chaos::{self, Fault};
 use crate::command::Command;
 use crate::connection::Connection;
 use crate::connection::StandbyStatus;
@@ -694,7 +695,42 @@ pub async fn send(message: Message) -> Result<(), Error> {
     .cloned();
 
     if let Some(connection) = connection {
-        connection.send_message(message.payload()).await?;
+        match chaos::fault() {
+            None => connection.send_message(message.payload()).await?,
+            Some(Fault::Drop) => {
+                tracing::warn!(
+                    "Fault injection: dropping message to {}",
+                    message.recipient()
+                );
+            }
+            Some(Fault::Duplicate) => {
+                tracing::warn!(
+                    "Fault injection: duplicating message to {}",
+                    message.recipient()
+                );
+                connection.send_message(message.payload()).await?;
+                connection.send_message(message.payload()).await?;
+            }
+            Some(Fault::Delay(delay)) => {
+                tracing::warn!(
+                    "Fault injection: delaying message to {} by {:?}",
+                    message.recipient(),
+                    delay
+                );
+
+                // send in the background, so that later messages can overtake it
+                let payload = message.payload().to_owned();
+
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+
+                    if let Err(e) = connection.send_message(&payload).await {
+                        tracing::warn!("Could not send delayed message: {}", e);
+                    }
+                });
+            }
+        }
+
         Ok(())
     } else {
         Err(Error::UnnamedConnection(format!(
@@ -0,0 +1,873 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Running external commands (e.g. sacctmgr, lfs, or the command that
+//! returns the encryption password)
+//!
+//! Every command is run with:
+//!
+//! * a timeout, after which it is killed
+//! * a limit on the size of its output, so that a runaway command
+//!   cannot exhaust the agent's memory
+//! * a scrubbed environment, holding only the variables on the
+//!   allowlist, so that secrets passed to the agent in its environment
+//!   are not leaked to the command
+//!
+//! The defaults can be changed for the whole agent with the
+//! `command-timeout`, `command-max-output` and `command-env` options,
+//! and for a single command with the `Command` builder. Failures are
+//! reported as a `Failure`, which says which command failed and why.
+//! Agents use this through `templemeads::exec`.
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::process::Stdio;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+
+use crate::config::parse_duration;
+use crate::error::Error;
+
+/// The default time (in seconds) that a command can run for
+pub const DEFAULT_TIMEOUT: u64 = 300;
+
+/// The default maximum size (in MB) of each of stdout and stderr
+pub const DEFAULT_MAX_OUTPUT: usize = 64;
+
+/// The environment variables that are always passed to commands.
+/// Names ending with '*' match every variable with that prefix
+const DEFAULT_ENV: [&str; 22] = [
+    "PATH",
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "SHELL",
+    "LANG",
+    "LC_*",
+    "TZ",
+    "TMPDIR",
+    "KRB5*",
+    "SSH_AUTH_SOCK",
+    "SSL_CERT_*",
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "NO_PROXY",
+    "SLURM_*",
+    "PBS_*",
+    "LSF_*",
+    "IRODS_*",
+    "KUBECONFIG",
+    "MC_*",
+    "CEPH_*",
+];
+
+/// The most of a failed command's stderr that is included in its Failure
+const MAX_REPORTED_STDERR: usize = 2048;
+
+///
+/// The limits applied to every command run by this agent
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    pub timeout: Duration,
+    pub max_output: usize,
+    pub env: Vec<String>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT),
+            max_output: DEFAULT_MAX_OUTPUT * 1024 * 1024,
+            env: DEFAULT_ENV.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+impl Policy {
+    ///
+    /// Create the policy from the `command-timeout` (a duration, e.g.
+    /// "5m"), `command-max-output` (in MB) and `command-env` (a
+    /// comma-separated list of variables added to the allowlist)
+    /// options. Empty options keep their defaults
+    ///
+    pub fn from_options(timeout: &str, max_output: &str, env: &str) -> Result<Self, Error> {
+        let mut policy = Policy::default();
+
+        if !timeout.trim().is_empty() {
+            policy.timeout = Duration::from_secs(parse_duration(timeout)?);
+        }
+
+        if !max_output.trim().is_empty() {
+            let max_output = max_output.trim().parse::<usize>().map_err(|_| {
+                Error::Parse(format!(
+                    "Invalid maximum command output '{}' - this should be a number of MB",
+                    max_output
+                ))
+            })?;
+
+            policy.max_output = max_output.max(1) * 1024 * 1024;
+        }
+
+        for name in env.split(',').map(|name| name.trim()) {
+            if !name.is_empty() {
+                policy.allow(name)?;
+            }
+        }
+
+        Ok(policy)
+    }
+
+    ///
+    /// Add the named environment variable to the allowlist, e.g. for
+    /// a variable that only the commands of one agent need. Names
+    /// ending with '*' match every variable with that prefix
+    ///
+    pub fn allow(&mut self, name: &str) -> Result<(), Error> {
+        let name = name.trim();
+
+        if name.is_empty() || name.trim_end_matches('*').contains(['=', '*', ' ']) {
+            return Err(Error::Parse(format!(
+                "Invalid environment variable name '{}'",
+                name
+            )));
+        }
+
+        if !self.env.iter().any(|n| n == name) {
+            self.env.push(name.to_owned());
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Return whether the named environment variable can be passed
+    /// to commands
+    ///
+    pub fn allows(&self, name: &str) -> bool {
+        self.env
+            .iter()
+            .any(|allowed| match allowed.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == allowed,
+            })
+    }
+}
+
+static POLICY: Lazy<RwLock<Policy>> = Lazy::new(|| RwLock::new(Policy::default()));
+
+///
+/// Set the policy applied to every command run by this agent
+///
+pub fn set_policy(policy: Policy) {
+    match POLICY.write() {
+        Ok(mut p) => *p = policy,
+        Err(e) => tracing::error!("Could not set the command policy: {}", e),
+    }
+}
+
+///
+/// Return the policy applied to every command run by this agent
+///
+pub fn policy() -> Policy {
+    match POLICY.read() {
+        Ok(policy) => policy.clone(),
+        Err(e) => {
+            tracing::error!("Could not read the command policy: {}", e);
+            Policy::default()
+        }
+    }
+}
+
+///
+/// Clear the environment of the passed command, and then pass only the
+/// variables allowed by the policy
+///
+fn scrub_env(command: &mut tokio::process::Command) {
+    let policy = policy();
+
+    command.env_clear();
+
+    for (name, value) in std::env::vars_os() {
+        if policy.allows(&name.to_string_lossy()) {
+            command.env(name, value);
+        }
+    }
+}
+
+///
+/// Why a command failed
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    /// There was no command to run
+    Empty,
+    /// The command could not be started, or its pipes failed
+    Io(String),
+    /// The command ran for longer than the timeout (in seconds)
+    Timeout(u64),
+    /// The command wrote more than this many bytes to stdout or stderr
+    OutputTooLarge(usize),
+    /// The command exited with a non-zero exit code (-1 if it was killed
+    /// by a signal)
+    Exit(i32),
+}
+
+///
+/// A command that failed, and why
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Failure {
+    /// The command line, with any secrets redacted
+    pub command: String,
+    pub reason: Reason,
+    /// The start of the command's stderr, with any secrets redacted
+    pub stderr: String,
+    pub duration_ms: u64,
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.reason {
+            Reason::Empty => write!(f, "There is no command to run"),
+            Reason::Io(e) => write!(f, "Could not run '{}': {}", self.command, e),
+            Reason::Timeout(seconds) => {
+                write!(f, "'{}' timed out after {} seconds", self.command, seconds)
+            }
+            Reason::OutputTooLarge(size) => write!(
+                f,
+                "'{}' was stopped as it wrote more than {} bytes of output",
+                self.command, size
+            ),
+            Reason::Exit(-1) => write!(
+                f,
+                "'{}' was killed by a signal: {}",
+                self.command, self.stderr
+            ),
+            Reason::Exit(code) => write!(
+                f,
+                "'{}' failed (exit {}): {}",
+                self.command, code, self.stderr
+            ),
+        }
+    }
+}
+
+///
+/// The output of a command that ran to completion
+///
+#[derive(Debug, Clone)]
+pub struct Output {
+    command: String,
+    pub code: i32,
+    pub stdout: String,
+    pub stderr: String,
+    pub duration: Duration,
+}
+
+impl Output {
+    pub fn success(&self) -> bool {
+        self.code == 0
+    }
+
+    ///
+    /// Return the failure of this command if it exited with a non-zero
+    /// exit code
+    ///
+    pub fn failure(&self) -> Option<Failure> {
+        match self.success() {
+            true => None,
+            false => Some(Failure {
+                command: self.command.clone(),
+                reason: Reason::Exit(self.code),
+                stderr: truncate(&crate::redact::redact_str(self.stderr.trim())),
+                duration_ms: self.duration.as_millis() as u64,
+            }),
+        }
+    }
+
+    ///
+    /// Return stdout if the command succeeded, else its failure
+    ///
+    pub fn check(self) -> Result<String, Failure> {
+        match self.failure() {
+            Some(failure) => Err(failure),
+            None => Ok(self.stdout),
+        }
+    }
+}
+
+///
+/// Return the start of the passed text, cut on a character boundary
+///
+fn truncate(text: &str) -> String {
+    match text.char_indices().nth(MAX_REPORTED_STDERR) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_owned(),
+    }
+}
+
+///
+/// Read all of the passed pipe, failing if there is more than `limit`
+/// bytes of output
+///
+async fn read_limited<R: AsyncRead + Unpin>(
+    pipe: Option<R>,
+    limit: usize,
+) -> Result<Vec<u8>, Reason> {
+    let mut output = Vec::new();
+
+    if let Some(pipe) = pipe {
+        pipe.take(limit as u64 + 1)
+            .read_to_end(&mut output)
+            .await
+            .map_err(|e| Reason::Io(e.to_string()))?;
+    }
+
+    match output.len() > limit {
+        true => Err(Reason::OutputTooLarge(limit)),
+        false => Ok(output),
+    }
+}
+
+///
+/// An external command, together with its arguments, environment and
+/// limits
+///
+#[derive(Debug, Clone, Default)]
+pub struct Command {
+    parts: Vec<String>,
+    env: Vec<(String, String)>,
+    stdin: Option<Vec<u8>>,
+    timeout: Option<Duration>,
+    max_output: Option<usize>,
+}
+
+impl Command {
+    ///
+    /// Create a command to run the passed program
+    ///
+    pub fn new(program: &str) -> Self {
+        Command {
+            parts: vec![program.to_owned()],
+            ..Default::default()
+        }
+    }
+
+    ///
+    /// Create a command from pre-tokenised parts, e.g. ["sudo", "lfs"],
+    /// where the first part is the program
+    ///
+    pub fn from_parts<S: AsRef<str>>(parts: &[S]) -> Self {
+        Command {
+            parts: parts.iter().map(|p| p.as_ref().to_owned()).collect(),
+            ..Default::default()
+        }
+    }
+
+    ///
+    /// Create a command from a command line that is split on whitespace,
+    /// e.g. "sudo lfs"
+    ///
+    pub fn parse(command: &str) -> Self {
+        Command {
+            parts: command.split_whitespace().map(|p| p.to_owned()).collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn arg<S: AsRef<str>>(mut self, arg: S) -> Self {
+        self.parts.push(arg.as_ref().to_owned());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.parts
+            .extend(args.into_iter().map(|a| a.as_ref().to_owned()));
+        self
+    }
+
+    ///
+    /// Set an environment variable for the command. This is passed even
+    /// if it is not on the allowlist
+    ///
+    pub fn env(mut self, name: &str, value: &str) -> Self {
+        self.env.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    ///
+    /// Write the passed input to the command's stdin
+    ///
+    pub fn stdin<S: Into<Vec<u8>>>(mut self, input: S) -> Self {
+        self.stdin = Some(input.into());
+        self
+    }
+
+    ///
+    /// Use this timeout rather than the policy's, e.g. for commands that
+    /// are known to be slow
+    ///
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    ///
+    /// Use this maximum output size (in bytes) rather than the policy's
+    ///
+    pub fn max_output(mut self, max_output: usize) -> Self {
+        self.max_output = Some(max_output);
+        self
+    }
+
+    fn failure(&self, reason: Reason, start: Instant) -> Failure {
+        Failure {
+            command: self.to_string(),
+            reason,
+            stderr: String::new(),
+            duration_ms: start.elapsed().as_millis() as u64,
+        }
+    }
+
+    ///
+    /// Start the command, with its stdout and stderr piped, and a
+    /// scrubbed environment
+    ///
+    fn spawn(
+        &self,
+        program: &str,
+        args: &[String],
+        start: Instant,
+    ) -> Result<tokio::process::Child, Failure> {
+        tracing::debug!("Running command: {}", self);
+
+        let mut command = tokio::process::Command::new(program);
+        command
+            .args(args)
+            .stdin(match self.stdin {
+                Some(_) => Stdio::piped(),
+                None => Stdio::null(),
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        scrub_env(&mut command);
+
+        for (name, value) in &self.env {
+            command.env(name, value);
+        }
+
+        command
+            .spawn()
+            .map_err(|e| self.failure(Reason::Io(e.to_string()), start))
+    }
+
+    ///
+    /// Run the command, returning its output whatever its exit code.
+    /// This fails only if the command could not be run to completion
+    ///
+    pub async fn output(&self) -> Result<Output, Failure> {
+        let start = Instant::now();
+
+        let Some((program, args)) = self.parts.split_first() else {
+            return Err(self.failure(Reason::Empty, start));
+        };
+
+        if program.trim().is_empty() {
+            return Err(self.failure(Reason::Empty, start));
+        }
+
+        let policy = policy();
+        let timeout = self.timeout.unwrap_or(policy.timeout);
+        let max_output = self.max_output.unwrap_or(policy.max_output);
+
+        let mut child = self.spawn(program, args, start)?;
+
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+
+        // the child is moved into the future, so that it is killed if the
+        // command times out, or if it writes too much output
+        let run = async move {
+            let write = async move {
+                if let (Some(input), Some(mut pipe)) = (&self.stdin, stdin) {
+                    pipe.write_all(input)
+                        .await
+                        .map_err(|e| Reason::Io(format!("Could not write to stdin: {}", e)))?;
+                }
+
+                Ok::<(), Reason>(())
+            };
+
+            // stdout and stderr are read at the same time, else the
+            // command could block on a full pipe
+            let (_, stdout, stderr) = tokio::try_join!(
+                write,
+                read_limited(stdout, max_output),
+                read_limited(stderr, max_output)
+            )?;
+
+            let status = child.wait().await.map_err(|e| Reason::Io(e.to_string()))?;
+
+            Ok::<_, Reason>((status, stdout, stderr))
+        };
+
+        let (status, stdout, stderr) = match tokio::time::timeout(timeout, run).await {
+            Ok(Ok(output)) => output,
+            Ok(Err(reason)) => return Err(self.failure(reason, start)),
+            Err(_) => return Err(self.failure(Reason::Timeout(timeout.as_secs()), start)),
+        };
+
+        let output = Output {
+            command: self.to_string(),
+            code: status.code().unwrap_or(-1),
+            stdout: String::from_utf8_lossy(&stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&stderr).into_owned(),
+            duration: start.elapsed(),
+        };
+
+        tracing::debug!(
+            "Command {} exited with code {} after {} ms",
+            self,
+            output.code,
+            output.duration.as_millis()
+        );
+
+        Ok(output)
+    }
+
+    ///
+    /// Run the command, returning its stdout, or its failure if it did
+    /// not exit successfully
+    ///
+    pub async fn run(&self) -> Result<String, Failure> {
+        self.output().await?.check()
+    }
+
+    ///
+    /// Run the command from synchronous code, returning its stdout, or
+    /// its failure if it did not exit successfully. The command is run
+    /// on its own thread and runtime, so this can be called both inside
+    /// and outside of a tokio runtime, but blocks the calling thread
+    ///
+    pub fn run_blocking(&self) -> Result<String, Failure> {
+        let start = Instant::now();
+
+        std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                        .map_err(|e| self.failure(Reason::Io(e.to_string()), start))?
+                        .block_on(self.run())
+                })
+                .join()
+                .unwrap_or_else(|_| {
+                    Err(self.failure(
+                        Reason::Io("The thread running the command panicked".to_owned()),
+                        start,
+                    ))
+                })
+        })
+    }
+
+    ///
+    /// Run the command, sending its stdout to `output` in chunks of up to
+    /// `chunk_size` bytes as it is read, rather than collecting it. This
+    /// is for commands whose output is too large to hold in memory, so
+    /// the output limit only applies to stderr, and the returned Output
+    /// has an empty stdout. The timeout applies as usual. If `output` is
+    /// closed before all of stdout has been sent, then the command is
+    /// killed and None is returned
+    ///
+    pub async fn stream(
+        &self,
+        output: tokio::sync::mpsc::Sender<Vec<u8>>,
+        chunk_size: usize,
+    ) -> Result<Option<Output>, Failure> {
+        let start = Instant::now();
+
+        let Some((program, args)) = self.parts.split_first() else {
+            return Err(self.failure(Reason::Empty, start));
+        };
+
+        if program.trim().is_empty() {
+            return Err(self.failure(Reason::Empty, start));
+        }
+
+        let policy = policy();
+        let timeout = self.timeout.unwrap_or(policy.timeout);
+        let max_output = self.max_output.unwrap_or(policy.max_output);
+
+        let mut child = self.spawn(program, args, start)?;
+
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take();
+
+        // stderr must be drained at the same time as stdout, else the
+        // command could block on a full pipe
+        let stderr = tokio::spawn(read_limited(child.stderr.take(), max_output));
+
+        // the child is moved into the future, so that it is killed if the
+        // command times out
+        let run = async move {
+            let write = async move {
+                if let (Some(input), Some(mut pipe)) = (&self.stdin, stdin) {
+                    pipe.write_all(input)
+                        .await
+                        .map_err(|e| Reason::Io(format!("Could not write to stdin: {}", e)))?;
+                }
+
+                Ok::<(), Reason>(())
+            };
+
+            let read = async move {
+                let Some(mut stdout) = stdout else {
+                    return Ok(true);
+                };
+
+                loop {
+                    let mut chunk = vec![0; chunk_size];
+
+                    let n = stdout
+                        .read(&mut chunk)
+                        .await
+                        .map_err(|e| Reason::Io(e.to_string()))?;
+
+                    if n == 0 {
+                        return Ok::<bool, Reason>(true);
+                    }
+
+                    chunk.truncate(n);
+
+                    if output.send(chunk).await.is_err() {
+                        return Ok(false);
+                    }
+                }
+            };
+
+            let (_, complete) = tokio::try_join!(write, read)?;
+
+            if !complete {
+                // nothing will read the rest of the output
+                let _ = child.start_kill();
+            }
+
+            let status = child.wait().await.map_err(|e| Reason::Io(e.to_string()))?;
+
+            let stderr = match stderr.await {
+                Ok(stderr) => stderr?,
+                Err(e) => return Err(Reason::Io(e.to_string())),
+            };
+
+            Ok::<_, Reason>((complete, status, stderr))
+        };
+
+        let (complete, status, stderr) = match tokio::time::timeout(timeout, run).await {
+            Ok(Ok(output)) => output,
+            Ok(Err(reason)) => return Err(self.failure(reason, start)),
+            Err(_) => return Err(self.failure(Reason::Timeout(timeout.as_secs()), start)),
+        };
+
+        if !complete {
+            tracing::debug!("Command {} was stopped before it finished", self);
+            return Ok(None);
+        }
+
+        let output = Output {
+            command: self.to_string(),
+            code: status.code().unwrap_or(-1),
+            stdout: String::new(),
+            stderr: String::from_utf8_lossy(&stderr).into_owned(),
+            duration: start.elapsed(),
+        };
+
+        tracing::debug!(
+            "Command {} exited with code {} after {} ms",
+            self,
+            output.code,
+            output.duration.as_millis()
+        );
+
+        Ok(Some(output))
+    }
+}
+
+/// The command line, with any secrets redacted
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", crate::redact::redact_str(&self.parts.join(" ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy() {
+        let policy = Policy::from_options("", "", "").unwrap_or_else(|e| {
+            unreachable!("Cannot create default policy: {}", e);
+        });
+
+        assert_eq!(policy, Policy::default());
+        assert!(policy.allows("PATH"));
+        assert!(policy.allows("SLURM_CONF"));
+        assert!(!policy.allows("OPENPORTAL_PASSWORD"));
+        assert!(!policy.allows("AWS_SECRET_ACCESS_KEY"));
+
+        let policy = Policy::from_options("10m", "2", "MY_VAR, SITE_*").unwrap_or_else(|e| {
+            unreachable!("Cannot create policy: {}", e);
+        });
+
+        assert_eq!(policy.timeout, Duration::from_secs(600));
+        assert_eq!(policy.max_output, 2 * 1024 * 1024);
+        assert!(policy.allows("MY_VAR"));
+        assert!(policy.allows("SITE_CONFIG"));
+        assert!(!policy.allows("MY_VAR2"));
+
+        assert!(Policy::from_options("soon", "", "").is_err());
+        assert!(Policy::from_options("", "lots", "").is_err());
+        assert!(Policy::from_options("", "", "A=B").is_err());
+
+        let mut policy = Policy::default();
+
+        policy.allow("AWS_REGION").unwrap_or_else(|e| {
+            unreachable!("Cannot allow variable: {}", e);
+        });
+
+        assert!(policy.allows("AWS_REGION"));
+        assert!(!policy.allows("AWS_SECRET_ACCESS_KEY"));
+        assert!(policy.allow("A*B").is_err());
+        assert!(policy.allow(" ").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_command() {
+        let output = Command::new("echo")
+            .arg("hello")
+            .run()
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot run echo: {}", e));
+
+        assert_eq!(output.trim(), "hello");
+
+        let output = Command::parse("cat")
+            .stdin("from stdin")
+            .run()
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot run cat: {}", e));
+
+        assert_eq!(output, "from stdin");
+
+        let failure = Command::from_parts(&["sh", "-c", "echo oops >&2; exit 3"])
+            .run()
+            .await
+            .err()
+            .unwrap_or_else(|| unreachable!("Command should have failed"));
+
+        assert_eq!(failure.reason, Reason::Exit(3));
+        assert_eq!(failure.stderr, "oops");
+
+        let failure = Command::new("sleep")
+            .arg("5")
+            .timeout(Duration::from_millis(100))
+            .run()
+            .await
+            .err()
+            .unwrap_or_else(|| unreachable!("Command should have timed out"));
+
+        assert!(matches!(failure.reason, Reason::Timeout(_)));
+
+        let failure = Command::new("yes")
+            .max_output(1024)
+            .run()
+            .await
+            .err()
+            .unwrap_or_else(|| unreachable!("Command should have written too much"));
+
+        assert_eq!(failure.reason, Reason::OutputTooLarge(1024));
+
+        let failure = Command::from_parts::<&str>(&[])
+            .run()
+            .await
+            .err()
+            .unwrap_or_else(|| unreachable!("Empty command should fail"));
+
+        assert_eq!(failure.reason, Reason::Empty);
+
+        // streamed output is sent in chunks, and stopping early kills
+        // the command
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+
+        let output = Command::new("echo")
+            .arg("streamed")
+            .stream(tx, 4)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot stream echo: {}", e))
+            .unwrap_or_else(|| unreachable!("Stream should not have stopped"));
+
+        assert!(output.success());
+
+        let mut streamed = Vec::new();
+
+        while let Some(chunk) = rx.recv().await {
+            assert!(chunk.len() <= 4);
+            streamed.extend(chunk);
+        }
+
+        assert_eq!(streamed, b"streamed\n");
+
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        drop(rx);
+
+        let output = Command::new("yes")
+            .stream(tx, 1024)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot stream yes: {}", e));
+
+        assert!(output.is_none());
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(16);
+
+        let failure = Command::new("sleep")
+            .arg("5")
+            .timeout(Duration::from_millis(100))
+            .stream(tx, 1024)
+            .await
+            .err()
+            .unwrap_or_else(|| unreachable!("Streamed command should have timed out"));
+
+        assert!(matches!(failure.reason, Reason::Timeout(_)));
+
+        let output = Command::new("echo")
+            .arg("blocking")
+            .run_blocking()
+            .unwrap_or_else(|e| unreachable!("Cannot run echo: {}", e));
+
+        assert_eq!(output.trim(), "blocking");
+
+        // only allowed variables, and those set explicitly, are passed
+        let output = Command::new("env")
+            .env("OPENPORTAL_TEST_VAR", "set")
+            .run()
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot run env: {}", e));
+
+        assert!(output.contains("OPENPORTAL_TEST_VAR=set"));
+        assert!(output
+            .lines()
+            .filter_map(|line| line.split_once('=').map(|(name, _)| name))
+            .all(|name| policy().allows(name) || name == "OPENPORTAL_TEST_VAR"));
+    }
+}
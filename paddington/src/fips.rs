@@ -0,0 +1,207 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! FIPS-approved implementations of the primitives used by `crypto`
+//!
+//! These use the FIPS 140-3 validated AWS-LC module (via aws-lc-rs), and
+//! are only available if paddington is built with the `fips` feature:
+//!
+//! * random numbers from the module's DRBG
+//! * SHA-256 digests
+//! * HKDF-SHA512 key derivation
+//! * PBKDF2-HMAC-SHA512 password-based key derivation
+//! * AES-256-GCM encryption, with a random 96 bit nonce prepended
+//! * HMAC-SHA256 signatures
+//! * the FIPS rustls provider for TLS
+
+#[cfg(feature = "fips")]
+mod backend {
+    use crate::error::Error;
+
+    use aws_lc_rs::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+    use aws_lc_rs::rand::{SecureRandom, SystemRandom};
+    use aws_lc_rs::{digest, hkdf, hmac, pbkdf2};
+    use std::num::NonZeroU32;
+
+    /// The number of PBKDF2 iterations used to derive a key from a password
+    const PBKDF2_ITERATIONS: u32 = 210_000;
+
+    /// The length of the output of HKDF
+    struct Length(usize);
+
+    impl hkdf::KeyType for Length {
+        fn len(&self) -> usize {
+            self.0
+        }
+    }
+
+    pub fn check() -> Result<(), Error> {
+        aws_lc_rs::try_fips_mode().map_err(|e| {
+            Error::Unavailable(format!(
+                "The AWS-LC module is not running in FIPS mode: {}",
+                e
+            ))
+        })
+    }
+
+    pub fn random_bytes(size: usize) -> Result<Vec<u8>, Error> {
+        let mut data = vec![0; size];
+
+        SystemRandom::new()
+            .fill(&mut data)
+            .map_err(|_| anyhow::anyhow!("Failed to generate random bytes."))?;
+
+        Ok(data)
+    }
+
+    pub fn digest(data: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(digest::digest(&digest::SHA256, data).as_ref().to_vec())
+    }
+
+    pub fn derive(salt: &[u8], key: &[u8], info: Option<&[u8]>) -> Result<Vec<u8>, Error> {
+        let mut new_key = vec![0; key.len()];
+        let info = [info.unwrap_or_default()];
+
+        hkdf::Salt::new(hkdf::HKDF_SHA512, salt)
+            .extract(key)
+            .expand(&info, Length(key.len()))
+            .and_then(|okm| okm.fill(&mut new_key))
+            .map_err(|_| anyhow::anyhow!("Failed to derive key."))?;
+
+        Ok(new_key)
+    }
+
+    pub fn from_password(password: &[u8], salt: &[u8], size: usize) -> Result<Vec<u8>, Error> {
+        let mut key = vec![0; size];
+
+        let iterations = NonZeroU32::new(PBKDF2_ITERATIONS)
+            .ok_or_else(|| Error::Bug("PBKDF2 iterations must be non-zero".to_owned()))?;
+
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA512,
+            iterations,
+            salt,
+            password,
+            &mut key,
+        );
+
+        Ok(key)
+    }
+
+    fn aead_key(key: &[u8]) -> Result<LessSafeKey, Error> {
+        Ok(LessSafeKey::new(
+            UnboundKey::new(&AES_256_GCM, key).map_err(|_| {
+                anyhow::anyhow!("Failed to create a secret key from the secret key data.")
+            })?,
+        ))
+    }
+
+    pub fn encrypt(key: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+        let nonce = random_bytes(NONCE_LEN)?;
+
+        let mut sealed = data.to_vec();
+
+        aead_key(key)?
+            .seal_in_place_append_tag(
+                Nonce::try_assume_unique_for_key(&nonce)
+                    .map_err(|_| anyhow::anyhow!("Failed to create a nonce."))?,
+                Aad::empty(),
+                &mut sealed,
+            )
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt the data."))?;
+
+        Ok([nonce, sealed].concat())
+    }
+
+    pub fn decrypt(key: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+        if data.len() < NONCE_LEN {
+            return Err(anyhow::anyhow!("Failed to decrypt the data - it is too short.").into());
+        }
+
+        let (nonce, sealed) = data.split_at(NONCE_LEN);
+        let mut sealed = sealed.to_vec();
+
+        let opened = aead_key(key)?
+            .open_in_place(
+                Nonce::try_assume_unique_for_key(nonce)
+                    .map_err(|_| anyhow::anyhow!("Failed to read the nonce."))?,
+                Aad::empty(),
+                &mut sealed,
+            )
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt the data."))?;
+
+        Ok(opened.to_vec())
+    }
+
+    pub fn sign(key: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+        Ok(hmac::sign(&key, data).as_ref().to_vec())
+    }
+
+    pub fn verify(key: &[u8], data: &[u8], signature: &[u8]) -> Result<(), Error> {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+
+        hmac::verify(&key, data, signature)
+            .map_err(|_| anyhow::anyhow!("Failed to verify the data."))?;
+
+        Ok(())
+    }
+
+    pub fn tls_provider() -> Result<rustls::crypto::CryptoProvider, Error> {
+        Ok(rustls::crypto::default_fips_provider())
+    }
+}
+
+#[cfg(not(feature = "fips"))]
+mod backend {
+    use crate::error::Error;
+
+    fn unavailable() -> Error {
+        Error::Unavailable(
+            "FIPS mode is not available, as paddington was built without the 'fips' feature"
+                .to_owned(),
+        )
+    }
+
+    pub fn check() -> Result<(), Error> {
+        Err(unavailable())
+    }
+
+    pub fn random_bytes(_size: usize) -> Result<Vec<u8>, Error> {
+        Err(unavailable())
+    }
+
+    pub fn digest(_data: &[u8]) -> Result<Vec<u8>, Error> {
+        Err(unavailable())
+    }
+
+    pub fn derive(_salt: &[u8], _key: &[u8], _info: Option<&[u8]>) -> Result<Vec<u8>, Error> {
+        Err(unavailable())
+    }
+
+    pub fn from_password(_password: &[u8], _salt: &[u8], _size: usize) -> Result<Vec<u8>, Error> {
+        Err(unavailable())
+    }
+
+    pub fn encrypt(_key: &[u8], _data: &[u8]) -> Result<Vec<u8>, Error> {
+        Err(unavailable())
+    }
+
+    pub fn decrypt(_key: &[u8], _data: &[u8]) -> Result<Vec<u8>, Error> {
+        Err(unavailable())
+    }
+
+    pub fn sign(_key: &[u8], _data: &[u8]) -> Result<Vec<u8>, Error> {
+        Err(unavailable())
+    }
+
+    pub fn verify(_key: &[u8], _data: &[u8], _signature: &[u8]) -> Result<(), Error> {
+        Err(unavailable())
+    }
+
+    pub fn tls_provider() -> Result<rustls::crypto::CryptoProvider, Error> {
+        Err(unavailable())
+    }
+}
+
+pub(crate) use backend::*;
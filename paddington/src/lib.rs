@@ -2,19 +2,26 @@
 // SPDX-License-Identifier: MIT
 
 // internal API
+mod chaos;
 mod client;
 mod connection;
 mod crypto;
 mod error;
 mod eventloop;
 mod exchange;
+mod fips;
 mod healthcheck;
+mod replay;
 mod server;
 
 // public API
+pub use chaos::{chance, check_probability, message_faults, set_message_faults, MessageFaults};
 pub mod command;
 pub mod config;
-pub use crypto::{Key, SecretKey, Signature};
+pub use crypto::{
+    crypto_mode, digest, fips_available, set_crypto_mode, tls_provider, CryptoMode, Key, SecretKey,
+    Signature,
+};
 pub use error::Error;
 pub use eventloop::run;
 pub use exchange::disconnect;
@@ -25,5 +32,12 @@ pub use exchange::set_handler;
 pub use exchange::watchdog;
 pub use exchange::worker_count;
 pub use exchange::SoftRestartGuard;
+pub mod exec;
 pub mod invite;
 pub mod message;
+pub mod redact;
+pub use replay::{
+    replays_rejected, save_replay_store, set_replay_store, set_replay_window,
+    DEFAULT_REPLAY_STORE_SIZE, DEFAULT_REPLAY_WINDOW,
+};
+pub use server::{listen, stop_listening};
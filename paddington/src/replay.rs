@@ -0,0 +1,671 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Protection against replayed websocket messages
+//!
+//! Every enveloped message starts with the info used to derive its
+//! per-message keys. This is authenticated by the encryption, so an
+//! attacker cannot change it. The info starts with the time at which the
+//! message was sent, followed by random bytes that act as a nonce.
+//! Messages sent longer ago than the replay window are rejected, and the
+//! nonces of the messages received from each peer within the window are
+//! remembered, so that a message whose nonce has already been seen is
+//! also rejected. Nonces are forgotten once they fall out of the window,
+//! as the message could no longer be accepted anyway. Each nonce is
+//! appended to the store file before the message is handled, so that
+//! messages (in particular, handshakes made with the pre-shared keys)
+//! cannot be replayed after the agent restarts either.
+
+use anyhow::Context;
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::crypto::{random_bytes, KEY_SIZE};
+use crate::error::Error;
+
+/// The default maximum number of nonces remembered for each peer
+pub const DEFAULT_REPLAY_STORE_SIZE: usize = 16384;
+
+/// The default replay window, in seconds
+pub const DEFAULT_REPLAY_WINDOW: u64 = 300;
+
+/// How often (in seconds) the store file is compacted
+const COMPACT_INTERVAL: u64 = 60;
+
+/// The number of bytes of the message info holding the send time
+const TIME_SIZE: usize = 8;
+
+/// The number of bytes of the message info used as the nonce
+const NONCE_SIZE: usize = 16;
+
+///
+/// The nonce of a received message, together with the time (in seconds
+/// since the epoch) at which its sender says it was sent
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Nonce {
+    sent: i64,
+    value: u128,
+}
+
+/// The reasons why a nonce can be rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rejection {
+    /// The nonce has already been seen
+    Seen,
+    /// The message was sent before the start of the replay window
+    Expired,
+    /// The message was sent more than the replay window in the future
+    Future,
+    /// Too many nonces have been seen within the replay window
+    Full,
+}
+
+/// The nonces seen from a single peer, in the order they were received
+#[derive(Debug, Default)]
+struct PeerNonces {
+    order: VecDeque<Nonce>,
+    seen: HashSet<u128>,
+    rejected: u64,
+}
+
+impl PeerNonces {
+    ///
+    /// Forget the nonces of messages sent before the start of the
+    /// window. These are evicted from the front, so a nonce may be kept
+    /// for a little longer than the window, but is never forgotten while
+    /// its message could still be accepted
+    ///
+    fn evict(&mut self, now: i64, window: i64) {
+        while let Some(oldest) = self.order.front() {
+            if oldest.sent.saturating_add(window) >= now {
+                break;
+            }
+
+            self.seen.remove(&oldest.value);
+            self.order.pop_front();
+        }
+    }
+
+    ///
+    /// Record the passed nonce, returning the reason if it must be
+    /// rejected. Nonces outside the window are forgotten first, and
+    /// new nonces are rejected (rather than old ones forgotten) if
+    /// more than `capacity` remain
+    ///
+    fn insert(
+        &mut self,
+        nonce: Nonce,
+        now: i64,
+        window: i64,
+        capacity: usize,
+    ) -> Result<(), Rejection> {
+        if nonce.sent.saturating_add(window) < now {
+            return Err(Rejection::Expired);
+        }
+
+        if nonce.sent > now.saturating_add(window) {
+            return Err(Rejection::Future);
+        }
+
+        self.evict(now, window);
+
+        if self.seen.contains(&nonce.value) {
+            return Err(Rejection::Seen);
+        }
+
+        if self.seen.len() >= capacity {
+            return Err(Rejection::Full);
+        }
+
+        self.seen.insert(nonce.value);
+        self.order.push_back(nonce);
+
+        Ok(())
+    }
+}
+
+/// A line of the store file - the hex-encoded nonce seen from a peer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoreEntry {
+    peer: String,
+    sent: i64,
+    nonce: String,
+}
+
+impl StoreEntry {
+    fn new(peer: &str, nonce: &Nonce) -> Self {
+        StoreEntry {
+            peer: peer.to_owned(),
+            sent: nonce.sent,
+            nonce: format!("{:032x}", nonce.value),
+        }
+    }
+
+    fn nonce(&self) -> Option<Nonce> {
+        u128::from_str_radix(&self.nonce, 16)
+            .ok()
+            .map(|value| Nonce {
+                sent: self.sent,
+                value,
+            })
+    }
+}
+
+#[derive(Debug)]
+struct ReplayStore {
+    peers: HashMap<String, PeerNonces>,
+    file: Option<PathBuf>,
+    capacity: usize,
+    window: i64,
+    changed: bool,
+}
+
+static STORE: Lazy<Mutex<ReplayStore>> = Lazy::new(|| {
+    Mutex::new(ReplayStore {
+        peers: HashMap::new(),
+        file: None,
+        capacity: DEFAULT_REPLAY_STORE_SIZE,
+        window: DEFAULT_REPLAY_WINDOW as i64,
+        changed: false,
+    })
+});
+
+/// Held while the store file is written, so that appends and
+/// compactions cannot interleave
+static SAVING: Lazy<tokio::sync::Mutex<()>> = Lazy::new(|| tokio::sync::Mutex::new(()));
+
+fn poisoned<T>(e: std::sync::PoisonError<T>) -> Error {
+    Error::Poison(format!("Could not lock the replay store: {}", e))
+}
+
+///
+/// Return new info to derive the per-message keys of a message that is
+/// about to be sent. This starts with the current time, followed by
+/// random bytes, the first of which are the message's nonce
+///
+pub(crate) fn message_info() -> Result<Vec<u8>, Error> {
+    let mut info = Utc::now().timestamp().to_be_bytes().to_vec();
+    info.extend(random_bytes(KEY_SIZE - TIME_SIZE)?);
+    Ok(info)
+}
+
+///
+/// Return the nonce of the passed enveloped message (read from the
+/// start of its hex-encoded key derivation info), or None if the
+/// message is too short to have one
+///
+pub(crate) fn nonce(message: &str) -> Option<Nonce> {
+    let sent = message
+        .get(0..(2 * TIME_SIZE))
+        .and_then(|sent| u64::from_str_radix(sent, 16).ok())
+        .and_then(|sent| i64::try_from(sent).ok())?;
+
+    let value = message
+        .get((2 * TIME_SIZE)..(2 * (TIME_SIZE + NONCE_SIZE)))
+        .and_then(|nonce| u128::from_str_radix(nonce, 16).ok())?;
+
+    Some(Nonce { sent, value })
+}
+
+///
+/// Set the replay window, in seconds. Messages sent longer ago than
+/// this (or further in the future, to allow for clock skew) are
+/// rejected, so the clocks of all peers must agree to within the window
+///
+pub fn set_replay_window(window: u64) -> Result<(), Error> {
+    STORE.lock().map_err(poisoned)?.window = i64::try_from(window.max(1)).unwrap_or(i64::MAX);
+    Ok(())
+}
+
+///
+/// Read the entries of the passed store file, skipping (with a warning)
+/// any line that cannot be parsed, e.g. one that was being appended
+/// when the agent crashed
+///
+fn read_store(file: &Path) -> Result<Vec<StoreEntry>, Error> {
+    let input = std::fs::File::open(file)
+        .with_context(|| format!("Could not read replay store {}", file.display()))?;
+
+    let mut entries = Vec::new();
+
+    for line in std::io::BufReader::new(input).lines() {
+        let line =
+            line.with_context(|| format!("Could not read replay store {}", file.display()))?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str::<StoreEntry>(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => tracing::warn!(
+                "Ignoring invalid entry in replay store {}: {}",
+                file.display(),
+                e
+            ),
+        }
+    }
+
+    Ok(entries)
+}
+
+///
+/// Use the passed file to save the nonces seen from each peer, keeping
+/// at most `capacity` nonces per peer. Nonces already in the file that
+/// are still within the replay window are loaded, so that messages seen
+/// before a restart are still rejected. This should be called (after
+/// `set_replay_window`) before any connections are made
+///
+pub fn set_replay_store(file: &Path, capacity: usize) -> Result<(), Error> {
+    let capacity = capacity.max(1);
+
+    let loaded = match file.try_exists()? {
+        true => read_store(file)?,
+        false => Vec::new(),
+    };
+
+    let mut store = STORE.lock().map_err(poisoned)?;
+    let window = store.window;
+    let now = Utc::now().timestamp();
+
+    for entry in loaded {
+        match entry.nonce() {
+            Some(nonce) => {
+                // expired or duplicate nonces are simply not loaded
+                let _ = store
+                    .peers
+                    .entry(entry.peer)
+                    .or_default()
+                    .insert(nonce, now, window, capacity);
+            }
+            None => tracing::warn!("Ignoring invalid nonce in replay store: {}", entry.nonce),
+        }
+    }
+
+    tracing::info!(
+        "Loaded {} nonce(s) for {} peer(s) from replay store {}",
+        store.peers.values().map(|p| p.order.len()).sum::<usize>(),
+        store.peers.len(),
+        file.display()
+    );
+
+    store.file = Some(file.to_path_buf());
+    store.capacity = capacity;
+    store.changed = true;
+
+    Ok(())
+}
+
+///
+/// Append the passed entry to `file`, syncing it to disk before
+/// returning, so that the nonce is remembered even if the agent
+/// crashes straight afterwards
+///
+fn append_store(file: &Path, entry: &StoreEntry) -> Result<(), Error> {
+    let mut line =
+        serde_json::to_string(entry).with_context(|| "Could not serialise a replay nonce")?;
+    line.push('\n');
+
+    let mut out = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)
+        .with_context(|| format!("Could not open replay store {}", file.display()))?;
+
+    out.write_all(line.as_bytes())
+        .and_then(|_| out.sync_data())
+        .with_context(|| format!("Could not append to replay store {}", file.display()))?;
+
+    Ok(())
+}
+
+///
+/// Check that the passed nonce is within the replay window and has not
+/// already been seen from `peer`, recording it if it has not. Returns an
+/// error if the message must be rejected. If a store file is in use then
+/// the nonce is saved to it before this returns, so the message should
+/// only be handled once this has succeeded
+///
+pub(crate) async fn check(peer: &str, nonce: Nonce) -> Result<(), Error> {
+    let (file, entry) = {
+        let mut store = STORE.lock().map_err(poisoned)?;
+        let capacity = store.capacity;
+        let window = store.window;
+        let file = store.file.clone();
+        let peer_nonces = store.peers.entry(peer.to_owned()).or_default();
+
+        match peer_nonces.insert(nonce, Utc::now().timestamp(), window, capacity) {
+            Ok(()) => {}
+            Err(Rejection::Full) => {
+                tracing::warn!(
+                    "Rejecting message from {} - more than {} messages within the replay window",
+                    peer,
+                    capacity
+                );
+
+                return Err(Error::Replay(format!(
+                    "Too many messages from {} within the replay window",
+                    peer
+                )));
+            }
+            Err(Rejection::Future) => {
+                tracing::warn!(
+                    "Rejecting message from {} that was sent at {}, which is more than {}s in \
+                     the future - check that the clocks of the agents agree",
+                    peer,
+                    nonce.sent,
+                    window
+                );
+
+                return Err(Error::Replay(format!(
+                    "Message from {} was sent too far in the future",
+                    peer
+                )));
+            }
+            Err(rejection) => {
+                peer_nonces.rejected += 1;
+
+                tracing::warn!(
+                    "Rejecting replayed message from {} (nonce {:032x}, {}) - {} replay(s) \
+                     rejected",
+                    peer,
+                    nonce.value,
+                    match rejection {
+                        Rejection::Expired => "sent before the replay window",
+                        _ => "already received",
+                    },
+                    peer_nonces.rejected
+                );
+
+                return Err(Error::Replay(format!(
+                    "Message from {} has already been received or is too old",
+                    peer
+                )));
+            }
+        }
+
+        store.changed = true;
+
+        (file, StoreEntry::new(peer, &nonce))
+    };
+
+    let Some(file) = file else {
+        return Ok(());
+    };
+
+    let _saving = SAVING.lock().await;
+
+    match tokio::task::spawn_blocking(move || append_store(&file, &entry)).await {
+        Ok(result) => result,
+        Err(e) => Err(e.into()),
+    }
+}
+
+///
+/// Write the passed entries to `file`. This writes to a temporary file
+/// that is synced and then renamed over `file`, so that a crash while
+/// saving cannot leave a truncated store
+///
+fn write_store(file: &Path, entries: &[StoreEntry]) -> Result<(), Error> {
+    let tmp = file.with_extension("tmp");
+
+    let mut data = String::new();
+
+    for entry in entries {
+        data.push_str(
+            &serde_json::to_string(entry).with_context(|| "Could not serialise a replay nonce")?,
+        );
+        data.push('\n');
+    }
+
+    let mut out = std::fs::File::create(&tmp)
+        .with_context(|| format!("Could not create replay store {}", tmp.display()))?;
+
+    out.write_all(data.as_bytes())
+        .and_then(|_| out.sync_all())
+        .with_context(|| format!("Could not write replay store {}", tmp.display()))?;
+
+    std::fs::rename(&tmp, file)
+        .with_context(|| format!("Could not replace replay store {}", file.display()))?;
+
+    Ok(())
+}
+
+///
+/// Compact the store file, rewriting it with only the nonces that are
+/// still within the replay window. Every nonce has already been
+/// appended to the file as it was received, so this only stops the file
+/// from growing. The file is written on a blocking thread, so that the
+/// runtime is not held up while a large store is saved
+///
+pub async fn save_replay_store() -> Result<(), Error> {
+    let _saving = SAVING.lock().await;
+
+    let (file, entries) = {
+        let mut store = STORE.lock().map_err(poisoned)?;
+
+        let Some(file) = store.file.clone() else {
+            return Ok(());
+        };
+
+        if !store.changed {
+            return Ok(());
+        }
+
+        let now = Utc::now().timestamp();
+        let window = store.window;
+
+        for nonces in store.peers.values_mut() {
+            nonces.evict(now, window);
+        }
+
+        let entries: Vec<StoreEntry> = store
+            .peers
+            .iter()
+            .flat_map(|(peer, nonces)| {
+                nonces
+                    .order
+                    .iter()
+                    .map(move |nonce| StoreEntry::new(peer, nonce))
+            })
+            .collect();
+
+        store.changed = false;
+
+        (file, entries)
+    };
+
+    let result = match tokio::task::spawn_blocking(move || write_store(&file, &entries)).await {
+        Ok(result) => result,
+        Err(e) => Err(e.into()),
+    };
+
+    if result.is_err() {
+        // make sure that the file is compacted again next time
+        STORE.lock().map_err(poisoned)?.changed = true;
+    }
+
+    result
+}
+
+///
+/// Compact the replay store every minute, until the process exits
+///
+pub(crate) async fn save_periodically() {
+    loop {
+        tokio::time::sleep(std::time::Duration::from_secs(COMPACT_INTERVAL)).await;
+
+        if let Err(e) = save_replay_store().await {
+            tracing::error!("Could not compact the replay store: {}", e);
+        }
+    }
+}
+
+///
+/// Return the number of replayed messages that have been rejected from
+/// each peer since this agent started
+///
+pub fn replays_rejected() -> HashMap<String, u64> {
+    match STORE.lock() {
+        Ok(store) => store
+            .peers
+            .iter()
+            .filter(|(_, nonces)| nonces.rejected > 0)
+            .map(|(peer, nonces)| (peer.clone(), nonces.rejected))
+            .collect(),
+        Err(e) => {
+            tracing::error!("Could not lock the replay store: {}", e);
+            HashMap::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nonce(sent: i64, value: u128) -> Nonce {
+        Nonce { sent, value }
+    }
+
+    #[test]
+    fn test_peer_nonces() {
+        let mut nonces = PeerNonces::default();
+
+        assert_eq!(nonces.insert(nonce(100, 1), 100, 10, 3), Ok(()));
+        assert_eq!(
+            nonces.insert(nonce(100, 1), 100, 10, 3),
+            Err(Rejection::Seen)
+        );
+        assert_eq!(nonces.insert(nonce(101, 2), 101, 10, 3), Ok(()));
+        assert_eq!(nonces.insert(nonce(102, 3), 102, 10, 3), Ok(()));
+
+        // the store is full, so new nonces are rejected rather than
+        // old nonces forgotten
+        assert_eq!(
+            nonces.insert(nonce(103, 4), 103, 10, 3),
+            Err(Rejection::Full)
+        );
+        assert!(nonces.seen.contains(&1));
+
+        // messages outside the window are rejected
+        assert_eq!(
+            nonces.insert(nonce(89, 5), 100, 10, 3),
+            Err(Rejection::Expired)
+        );
+        assert_eq!(
+            nonces.insert(nonce(111, 5), 100, 10, 3),
+            Err(Rejection::Future)
+        );
+
+        // nonces are forgotten once they are outside the window,
+        // at which point they are rejected as expired
+        assert_eq!(nonces.insert(nonce(111, 4), 111, 10, 3), Ok(()));
+        assert!(!nonces.seen.contains(&1));
+        assert_eq!(nonces.order.len(), 3);
+        assert_eq!(
+            nonces.insert(nonce(100, 1), 111, 10, 3),
+            Err(Rejection::Expired)
+        );
+    }
+
+    #[test]
+    fn test_nonce() {
+        assert_eq!(super::nonce("short"), None);
+        assert_eq!(super::nonce(&"z".repeat(64)), None);
+        assert_eq!(
+            super::nonce(&format!(
+                "{:016x}{:032x}{}",
+                1234u64,
+                42u128,
+                "ab".repeat(48)
+            )),
+            Some(nonce(1234, 42))
+        );
+
+        let info = message_info().unwrap_or_else(|e| {
+            unreachable!("Cannot create message info: {}", e);
+        });
+
+        assert_eq!(info.len(), KEY_SIZE);
+
+        let sent = super::nonce(&hex::encode(&info)).map(|nonce| nonce.sent);
+        assert!(sent.is_some_and(|sent| (sent - Utc::now().timestamp()).abs() < 5));
+    }
+
+    #[test]
+    fn test_store_file() {
+        let file = std::env::temp_dir().join(format!(
+            "paddington-test-replay-{}.replay.json",
+            std::process::id()
+        ));
+
+        let entries = [
+            StoreEntry::new("a@zone", &nonce(100, 1)),
+            StoreEntry::new("b@zone", &nonce(200, 2)),
+        ];
+
+        write_store(&file, &entries[0..1]).unwrap_or_else(|e| {
+            unreachable!("Cannot write replay store: {}", e);
+        });
+
+        append_store(&file, &entries[1]).unwrap_or_else(|e| {
+            unreachable!("Cannot append to replay store: {}", e);
+        });
+
+        // a partly-written line is skipped
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&file)
+            .and_then(|mut out| out.write_all(b"{\"peer\":\"c@zo"))
+            .unwrap_or_else(|e| {
+                unreachable!("Cannot corrupt replay store: {}", e);
+            });
+
+        let loaded = read_store(&file).unwrap_or_else(|e| {
+            unreachable!("Cannot read replay store: {}", e);
+        });
+
+        let _ = std::fs::remove_file(&file);
+
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].peer, "a@zone");
+        assert_eq!(loaded[0].nonce(), Some(nonce(100, 1)));
+        assert_eq!(loaded[1].peer, "b@zone");
+        assert_eq!(loaded[1].nonce(), Some(nonce(200, 2)));
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let peer = "test_check@replay";
+        let now = Utc::now().timestamp();
+
+        assert!(check(peer, nonce(now, 7)).await.is_ok());
+        assert!(matches!(
+            check(peer, nonce(now, 7)).await,
+            Err(Error::Replay(_))
+        ));
+        assert!(check(peer, nonce(now, 8)).await.is_ok());
+        assert!(check("other@replay", nonce(now, 7)).await.is_ok());
+
+        // too old, which counts as a replay
+        assert!(matches!(
+            check(peer, nonce(now - 2 * DEFAULT_REPLAY_WINDOW as i64, 9)).await,
+            Err(Error::Replay(_))
+        ));
+
+        // too far in the future, which is a clock problem
+        assert!(matches!(
+            check(peer, nonce(now + 2 * DEFAULT_REPLAY_WINDOW as i64, 9)).await,
+            Err(Error::Replay(_))
+        ));
+
+        assert_eq!(replays_rejected().get(peer), Some(&2));
+    }
+}
@@ -1,7 +1,11 @@
 // SPDX-FileCopyrightText: © 2024 Christopher Woods <Christopher.Woods@bristol.ac.uk>
 // SPDX-License-Identifier: MIT
 
-use tokio::net::TcpListener;
+use once_cell::sync::Lazy;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use tokio::net::{TcpListener, TcpSocket};
+use tokio::sync::Notify;
 
 use crate::config::ServiceConfig;
 use crate::connection::Connection;
@@ -9,6 +13,65 @@ use crate::error::Error;
 use crate::exchange;
 use crate::healthcheck;
 
+/// A listener that was bound before the server was run
+static LISTENER: Lazy<Mutex<Option<TcpListener>>> = Lazy::new(|| Mutex::new(None));
+
+/// Notified when the server should stop accepting connections
+static STOP_LISTENING: Lazy<Notify> = Lazy::new(Notify::new);
+
+///
+/// Bind a listener to the passed address. The port is shared
+/// (SO_REUSEPORT) with any other instance of this agent run by the same
+/// user, so that a new instance can start listening before the running
+/// instance hands over to it
+///
+pub(crate) fn bind(addr: SocketAddr) -> Result<TcpListener, Error> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+
+    socket.set_reuseaddr(true)?;
+    socket.set_reuseport(true)?;
+
+    socket.bind(addr)?;
+
+    Ok(socket.listen(1024)?)
+}
+
+///
+/// Start listening for connections now, rather than when the server is
+/// run. This is used by a new instance of an agent that is taking over
+/// from a running instance, so that no connection is refused while
+/// the running instance hands over
+///
+pub fn listen(config: &ServiceConfig) -> Result<(), Error> {
+    let listener = bind(SocketAddr::new(config.ip(), config.port()))?;
+
+    tracing::info!("Listening early on: {}", listener.local_addr()?);
+
+    match LISTENER.lock() {
+        Ok(mut current) => *current = Some(listener),
+        Err(e) => {
+            return Err(Error::Poison(format!(
+                "Could not lock the early listener: {}",
+                e
+            )))
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Stop accepting new connections, e.g. because this agent is handing
+/// over to a new instance that is listening on the same port. Existing
+/// connections are not affected
+///
+pub fn stop_listening() {
+    STOP_LISTENING.notify_one();
+}
+
 ///
 /// Internal function used to handle a single connection to the server.
 /// This will enter an event loop to process messages from the client
@@ -46,27 +109,52 @@ async fn handle_connection(
 pub async fn run_once(config: ServiceConfig) -> Result<(), Error> {
     // Create the event loop and TCP listener we'll accept connections on.
 
-    let addr = format!("{}:{}", config.ip(), config.port());
+    let early = match LISTENER.lock() {
+        Ok(mut early) => early.take(),
+        Err(e) => {
+            tracing::error!("Could not lock the early listener: {}", e);
+            None
+        }
+    };
+
+    let listener = match early {
+        Some(listener) => listener,
+        None => bind(SocketAddr::new(config.ip(), config.port()))?,
+    };
 
-    let listener = TcpListener::bind(addr).await?;
     tracing::info!("Listening on: {}", listener.local_addr()?);
 
     // Let's spawn the handling of each connection in a separate task.
     loop {
-        match listener.accept().await {
-            Ok((stream, addr)) => {
-                tracing::info!("New connection from: {}", addr);
-
-                // spawn a new task to handle the connection, and don't
-                // wait for it to finish - the function will handle all
-                // the processing and errors itself
-                tokio::spawn(handle_connection(stream, config.clone()));
-            }
-            Err(e) => {
-                tracing::error!("Error accepting connection: {:?}", e);
-            }
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, addr)) => {
+                    tracing::info!("New connection from: {}", addr);
+
+                    // spawn a new task to handle the connection, and don't
+                    // wait for it to finish - the function will handle all
+                    // the processing and errors itself
+                    tokio::spawn(handle_connection(stream, config.clone()));
+                }
+                Err(e) => {
+                    tracing::error!("Error accepting connection: {:?}", e);
+                }
+            },
+            _ = STOP_LISTENING.notified() => break,
         }
     }
+
+    tracing::warn!(
+        "No longer accepting connections on {}",
+        listener.local_addr()?
+    );
+    drop(listener);
+
+    // the agent exits once it has handed over, so there is
+    // no need to listen again
+    std::future::pending::<()>().await;
+
+    Ok(())
 }
 
 pub async fn run(config: ServiceConfig) -> Result<(), Error> {
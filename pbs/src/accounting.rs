@@ -0,0 +1,322 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use templemeads::grammar::{Date, DateRange, ProjectMapping};
+use templemeads::job::assert_not_expired;
+use templemeads::usagereport::{DailyProjectUsageReport, ProjectUsageReport, Usage};
+use templemeads::Error;
+use tokio::sync::RwLock;
+
+use crate::qmgr;
+
+#[derive(Debug, Default)]
+struct Database {
+    accounting_dir: PathBuf,
+    /// The reports of each queue on each completed day. Completed days
+    /// never change, so their logs are only read once.
+    reports: HashMap<Date, HashMap<String, DailyProjectUsageReport>>,
+}
+
+static CACHE: Lazy<RwLock<Database>> = Lazy::new(|| RwLock::new(Database::default()));
+
+///
+/// Set the directory containing the PBS accounting logs, which are
+/// named by date, e.g. "20260415"
+///
+pub async fn set_accounting_dir(accounting_dir: &str) -> Result<(), Error> {
+    let accounting_dir = PathBuf::from(accounting_dir.trim());
+
+    if !accounting_dir.is_dir() {
+        tracing::warn!(
+            "The accounting directory {} does not exist - usage will be empty until it does",
+            accounting_dir.display()
+        );
+    }
+
+    CACHE.write().await.accounting_dir = accounting_dir;
+
+    Ok(())
+}
+
+///
+/// Parse a PBS duration ("HH:MM:SS") into seconds
+///
+fn parse_duration(duration: &str) -> Option<u64> {
+    let parts = duration
+        .trim()
+        .split(':')
+        .map(|p| p.parse::<u64>().ok())
+        .collect::<Option<Vec<u64>>>()?;
+
+    match parts.as_slice() {
+        [hours, minutes, seconds] => Some(hours * 3600 + minutes * 60 + seconds),
+        [minutes, seconds] => Some(minutes * 60 + seconds),
+        [seconds] => Some(*seconds),
+        _ => None,
+    }
+}
+
+///
+/// Parse the message of an accounting record, which is a space-separated
+/// list of "key=value" pairs, e.g. "user=alice queue=abc ..."
+///
+fn parse_attributes(message: &str) -> HashMap<&str, &str> {
+    message
+        .split_whitespace()
+        .filter_map(|pair| pair.split_once('='))
+        .collect()
+}
+
+///
+/// Parse the accounting log of the passed day, returning the usage
+/// report of each queue. Each job is counted on the day that it
+/// ended ("E" records), and its usage is its walltime multiplied by
+/// the number of nodes it used, i.e. node-seconds. GPU time is added
+/// as the "gpu" component.
+///
+async fn read_day(day: &Date) -> Result<HashMap<String, DailyProjectUsageReport>, Error> {
+    let path = CACHE
+        .read()
+        .await
+        .accounting_dir
+        .join(day.to_string().replace('-', ""));
+
+    let mut reports: HashMap<String, DailyProjectUsageReport> = HashMap::new();
+
+    let contents = match tokio::fs::read(&path).await {
+        Ok(contents) => String::from_utf8_lossy(&contents).into_owned(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            // no jobs ended on this day
+            return Ok(reports);
+        }
+        Err(e) => {
+            return Err(Error::Call(format!(
+                "Could not read accounting log {}: {}",
+                path.display(),
+                e
+            )));
+        }
+    };
+
+    for line in contents.lines() {
+        // records are "<datetime>;<type>;<job id>;<message>"
+        let mut fields = line.splitn(4, ';');
+
+        let (record_type, message) =
+            match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                (Some(_), Some(record_type), Some(_), Some(message)) => (record_type, message),
+                _ => continue,
+            };
+
+        if record_type != "E" {
+            continue;
+        }
+
+        let attributes = parse_attributes(message);
+
+        let (queue, user) = match (attributes.get("queue"), attributes.get("user")) {
+            (Some(queue), Some(user)) => (queue.to_string(), user.to_string()),
+            _ => continue,
+        };
+
+        let walltime = attributes
+            .get("resources_used.walltime")
+            .and_then(|w| parse_duration(w))
+            .unwrap_or(0);
+
+        let count = |resource: &str| attributes.get(resource).and_then(|n| n.parse::<u64>().ok());
+
+        let nodes = count("Resource_List.nodect").unwrap_or(1);
+        let gpus = count("Resource_List.ngpus").unwrap_or(0);
+
+        let report = reports.entry(queue).or_default();
+
+        report.add_usage(&user, Usage::new(walltime * nodes));
+        report.add_component_usage("gpu", &user, Usage::new(walltime * gpus));
+        report.add_jobs(&user, 1);
+
+        if let (Some(qtime), Some(start)) = (count("qtime"), count("start")) {
+            report.add_wait_seconds(&user, start.saturating_sub(qtime));
+        }
+    }
+
+    Ok(reports)
+}
+
+///
+/// Return the usage report of the queue on the passed day
+///
+async fn get_daily_report(queue: &str, day: &Date) -> Result<DailyProjectUsageReport, Error> {
+    if let Some(reports) = CACHE.read().await.reports.get(day) {
+        return Ok(reports.get(queue).cloned().unwrap_or_default());
+    }
+
+    let mut reports = read_day(day).await?;
+
+    // the logs of days before today are complete, so cache them
+    if *day < Date::today() {
+        for report in reports.values_mut() {
+            report.set_complete();
+        }
+
+        let report = reports.get(queue).cloned();
+
+        CACHE.write().await.reports.insert(day.clone(), reports);
+
+        return Ok(report.unwrap_or_else(|| {
+            let mut report = DailyProjectUsageReport::default();
+            report.set_complete();
+            report
+        }));
+    }
+
+    Ok(reports.remove(queue).unwrap_or_default())
+}
+
+///
+/// Return the usage report of the project for each of the passed days
+///
+pub async fn get_usage_report(
+    project: &ProjectMapping,
+    dates: &DateRange,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<ProjectUsageReport, Error> {
+    assert_not_expired(expires)?;
+
+    let queue = qmgr::queue_name(project.local_group())?;
+    let today = Date::today();
+
+    let mut report = ProjectUsageReport::new(project.project());
+
+    // we can't get the usage for days in the future
+    for day in dates.days().into_iter().filter(|day| *day <= today) {
+        assert_not_expired(expires)?;
+
+        let daily_report = match get_daily_report(&queue, &day).await {
+            Ok(daily_report) => daily_report,
+            Err(e) => {
+                tracing::warn!("Could not get the usage of {} on {}: {}", queue, day, e);
+                DailyProjectUsageReport::default()
+            }
+        };
+
+        report.set_report(&day, &daily_report);
+    }
+
+    Ok(report)
+}
+
+///
+/// Return the total usage of the queue over the passed days
+///
+pub async fn get_total_usage(queue: &str, dates: &DateRange) -> Result<Usage, Error> {
+    let today = Date::today();
+    let mut total = Usage::default();
+
+    for day in dates.days().into_iter().filter(|day| *day <= today) {
+        total += get_daily_report(queue, &day).await?.total_usage();
+    }
+
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qmgr::tests::{fake_pbs, project};
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("01:02:03"), Some(3723));
+        assert_eq!(parse_duration("02:03"), Some(123));
+        assert_eq!(parse_duration(" 45 "), Some(45));
+        assert_eq!(parse_duration("1:2:3:4"), None);
+        assert_eq!(parse_duration("01:xx:03"), None);
+    }
+
+    #[test]
+    fn test_parse_attributes() {
+        let attributes = parse_attributes("user=alice queue=p_abc Resource_List.nodect=2 junk");
+
+        assert_eq!(attributes.len(), 3);
+        assert_eq!(attributes["user"], "alice");
+        assert_eq!(attributes["queue"], "p_abc");
+        assert_eq!(attributes["Resource_List.nodect"], "2");
+    }
+
+    #[tokio::test]
+    async fn test_usage() {
+        let dir = fake_pbs();
+
+        set_accounting_dir(&dir.display().to_string())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot set accounting dir: {}", e));
+
+        let yesterday = Date::yesterday();
+
+        std::fs::write(
+            dir.join(yesterday.to_string().replace('-', "")),
+            [
+                "04/15/2026 09:00:00;S;1.pbs;user=alice queue=p_acct",
+                "04/15/2026 10:00:00;E;1.pbs;user=alice queue=p_acct resources_used.walltime=01:00:00 \
+                 Resource_List.nodect=2 Resource_List.ngpus=1 qtime=1000 start=1600",
+                "04/15/2026 11:00:00;E;2.pbs;user=alice queue=p_acct resources_used.walltime=30:00",
+                "04/15/2026 12:00:00;E;3.pbs;user=bob queue=p_other resources_used.walltime=00:10:00",
+                "04/15/2026 13:00:00;E;4.pbs;queue=p_acct resources_used.walltime=00:10:00",
+                "not a record",
+            ]
+            .join("\n"),
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot write accounting log: {}", e));
+
+        let report = get_daily_report("p_acct", &yesterday)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot get report: {}", e));
+
+        // node-seconds of each job, with GPU time as a component
+        assert_eq!(report.usage("alice").seconds(), 2 * 3600 + 1800);
+        assert_eq!(report.total_usage().seconds(), 2 * 3600 + 1800);
+        assert_eq!(report.get_component("gpu").usage("alice").seconds(), 3600);
+        assert_eq!(report.num_jobs_for_user("alice"), 2);
+        assert_eq!(report.wait_seconds_for_user("alice"), 600);
+        assert!(report.is_complete());
+
+        // completed days are only read once
+        std::fs::write(dir.join(yesterday.to_string().replace('-', "")), "")
+            .unwrap_or_else(|e| unreachable!("Cannot write accounting log: {}", e));
+
+        assert!(get_daily_report("p_other", &yesterday)
+            .await
+            .is_ok_and(|report| report.usage("bob").seconds() == 600));
+
+        let dates = DateRange::from_chrono(&yesterday.to_chrono(), &Date::today().to_chrono());
+
+        assert!(get_total_usage("p_acct", &dates)
+            .await
+            .is_ok_and(|usage| usage.seconds() == 2 * 3600 + 1800));
+
+        let expires = Utc::now() + chrono::Duration::seconds(30);
+
+        assert!(get_usage_report(&project("acct"), &dates, &expires)
+            .await
+            .is_ok_and(|report| report.total_usage().seconds() == 2 * 3600 + 1800));
+
+        // days without a log have no usage
+        let long_ago = yesterday.prev().prev().prev();
+
+        assert!(get_daily_report("p_acct", &long_ago)
+            .await
+            .is_ok_and(|report| report.total_usage().seconds() == 0 && report.is_complete()));
+
+        let expired = Utc::now() - chrono::Duration::seconds(1);
+
+        assert!(matches!(
+            get_usage_report(&project("acct"), &dates, &expired).await,
+            Err(Error::Expired(_))
+        ));
+    }
+}
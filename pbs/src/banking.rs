@@ -0,0 +1,427 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Allocation banking - the limit of each project is its allocation,
+//! and the usage of each project is periodically counted against it.
+//! The project's queue is disabled once the allocation is exhausted
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use templemeads::destination::Destination;
+use templemeads::grammar::{Date, DateRange, ProjectMapping};
+use templemeads::job::assert_not_expired;
+use templemeads::notification::{self, NotificationEvent};
+use templemeads::usagereport::Usage;
+use templemeads::Error;
+use tokio::sync::RwLock;
+use tokio::time::sleep;
+
+use crate::accounting;
+use crate::qmgr;
+
+///
+/// The allocation of a single project, together with where to send
+/// notifications when the project's queue is disabled or re-enabled
+///
+#[derive(Debug, Clone)]
+struct BankAccount {
+    mapping: ProjectMapping,
+    allocation: Usage,
+    since: Date,
+    destination: Destination,
+}
+
+#[derive(Debug, Default)]
+struct Database {
+    /// The bank account of each project, keyed by queue
+    accounts: HashMap<String, BankAccount>,
+    limits_file: Option<PathBuf>,
+}
+
+static CACHE: Lazy<RwLock<Database>> = Lazy::new(|| RwLock::new(Database::default()));
+
+///
+/// Set the file in which allocations are saved, and load any
+/// allocations that were saved previously
+///
+pub async fn set_limits_file(limits_file: &str) -> Result<(), Error> {
+    let limits_file = limits_file.trim();
+
+    if limits_file.is_empty() {
+        tracing::warn!("No limits-file set - project limits will be lost when the agent restarts");
+        return Ok(());
+    }
+
+    let limits_file = PathBuf::from(limits_file);
+    let mut cache = CACHE.write().await;
+
+    if limits_file.exists() {
+        let contents = std::fs::read_to_string(&limits_file)?;
+
+        let value: Value = serde_json::from_str(&contents).map_err(|e| {
+            Error::Parse(format!(
+                "Invalid limits file {}: {}",
+                limits_file.display(),
+                e
+            ))
+        })?;
+
+        for (queue, account) in value.as_object().cloned().unwrap_or_default() {
+            let field = |name: &str| account[name].as_str().unwrap_or_default().to_owned();
+
+            cache.accounts.insert(
+                queue,
+                BankAccount {
+                    mapping: ProjectMapping::parse(&field("mapping"))?,
+                    allocation: Usage::new(account["allocation"].as_u64().unwrap_or(0)),
+                    since: Date::parse(&field("since"))?,
+                    destination: Destination::parse(&field("destination"))?,
+                },
+            );
+        }
+
+        tracing::info!(
+            "Loaded the limits of {} projects from {}",
+            cache.accounts.len(),
+            limits_file.display()
+        );
+    }
+
+    cache.limits_file = Some(limits_file);
+
+    Ok(())
+}
+
+fn save(database: &Database) -> Result<(), Error> {
+    let limits_file = match &database.limits_file {
+        Some(limits_file) => limits_file,
+        None => return Ok(()),
+    };
+
+    let value: serde_json::Map<String, Value> = database
+        .accounts
+        .iter()
+        .map(|(queue, account)| {
+            (
+                queue.clone(),
+                json!({
+                    "mapping": account.mapping.to_string(),
+                    "allocation": account.allocation.seconds(),
+                    "since": account.since.to_string(),
+                    "destination": account.destination.to_string(),
+                }),
+            )
+        })
+        .collect();
+
+    // write to a temporary file first, so that a crash cannot leave
+    // a partially written file behind
+    let tmp = limits_file.with_extension("tmp");
+    std::fs::write(&tmp, Value::Object(value).to_string())?;
+    std::fs::rename(&tmp, limits_file)?;
+
+    Ok(())
+}
+
+///
+/// Return the limit (allocation) of the project, which is zero if
+/// no limit has been set
+///
+pub async fn get_limit(
+    mapping: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Usage, Error> {
+    assert_not_expired(expires)?;
+
+    let queue = qmgr::queue_name(mapping.local_group())?;
+
+    Ok(CACHE
+        .read()
+        .await
+        .accounts
+        .get(&queue)
+        .map(|account| account.allocation)
+        .unwrap_or_default())
+}
+
+///
+/// Set the limit (allocation) of the project. Notifications about the
+/// project are sent along `destination`. A zero limit removes the
+/// allocation, so that the project's usage is no longer checked
+///
+pub async fn set_limit(
+    mapping: &ProjectMapping,
+    limit: &Usage,
+    destination: &Destination,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Usage, Error> {
+    assert_not_expired(expires)?;
+
+    let queue = qmgr::queue_name(mapping.local_group())?;
+
+    {
+        let mut cache = CACHE.write().await;
+
+        if limit.seconds() == 0 {
+            cache.accounts.remove(&queue);
+        } else {
+            // keep counting from the day that the allocation was first set
+            let since = cache
+                .accounts
+                .get(&queue)
+                .map(|account| account.since.clone())
+                .unwrap_or_else(Date::today);
+
+            cache.accounts.insert(
+                queue.clone(),
+                BankAccount {
+                    mapping: mapping.clone(),
+                    allocation: *limit,
+                    since,
+                    destination: destination.clone(),
+                },
+            );
+        }
+
+        save(&cache)?;
+    }
+
+    if limit.seconds() == 0 {
+        // the project can no longer exhaust its allocation
+        if !qmgr::is_queue_enabled(&queue).await? {
+            qmgr::set_queue_enabled(&queue, true).await?;
+        }
+    } else if let Err(e) = check_queue(&queue).await {
+        tracing::warn!("Could not check the allocation of {}: {}", mapping, e);
+    }
+
+    Ok(*limit)
+}
+
+///
+/// Remove the allocation of the project, e.g. because it has been removed
+///
+pub async fn remove_limit(mapping: &ProjectMapping) -> Result<(), Error> {
+    let queue = qmgr::queue_name(mapping.local_group())?;
+
+    let mut cache = CACHE.write().await;
+
+    if cache.accounts.remove(&queue).is_some() {
+        save(&cache)?;
+    }
+
+    Ok(())
+}
+
+///
+/// Spawn the background task that checks the usage of every project
+/// with a limit every `interval` seconds
+///
+pub fn spawn_banking_task(interval: u64) {
+    tokio::spawn(async move {
+        loop {
+            sleep(Duration::from_secs(interval)).await;
+
+            let queues: Vec<String> = CACHE.read().await.accounts.keys().cloned().collect();
+
+            for queue in queues {
+                if let Err(e) = check_queue(&queue).await {
+                    tracing::error!(
+                        "Banking: could not check the allocation of {}: {}",
+                        queue,
+                        e
+                    );
+                }
+            }
+        }
+    });
+}
+
+///
+/// Count the usage of the project's queue against its allocation,
+/// disabling the queue if the allocation is exhausted, or re-enabling
+/// it if allocation is available again
+///
+async fn check_queue(queue: &str) -> Result<(), Error> {
+    let account = match CACHE.read().await.accounts.get(queue) {
+        Some(account) => account.clone(),
+        None => return Ok(()),
+    };
+
+    let dates = DateRange::from_chrono(&account.since.to_chrono(), &Date::today().to_chrono());
+    let usage = accounting::get_total_usage(queue, &dates).await?;
+    let project = account.mapping.project().clone();
+    let is_enabled = qmgr::is_queue_enabled(queue).await?;
+
+    if usage.seconds() >= account.allocation.seconds() {
+        if !is_enabled {
+            return Ok(());
+        }
+
+        tracing::warn!(
+            "Project {} has used {} of its allocation of {} - disabling queue {}",
+            project,
+            usage,
+            account.allocation,
+            queue
+        );
+
+        qmgr::set_queue_enabled(queue, false).await?;
+
+        notification::send(
+            &account.destination,
+            NotificationEvent::AllocationExhausted(project),
+        )
+        .await;
+    } else if !is_enabled {
+        tracing::info!(
+            "Project {} has used {} of its allocation of {} - re-enabled queue {}",
+            project,
+            usage,
+            account.allocation,
+            queue
+        );
+
+        qmgr::set_queue_enabled(queue, true).await?;
+
+        notification::send(
+            &account.destination,
+            NotificationEvent::AllocationRestored(project),
+        )
+        .await;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qmgr::tests::{fake_pbs, logged, project};
+
+    #[tokio::test]
+    async fn test_banking() {
+        let dir = fake_pbs();
+        let mapping = project("bank");
+        let destination = Destination::parse("portal.cluster.pbs")
+            .unwrap_or_else(|e| unreachable!("Cannot parse destination: {}", e));
+        let expires = Utc::now() + chrono::Duration::seconds(30);
+
+        accounting::set_accounting_dir(&dir.display().to_string())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot set accounting dir: {}", e));
+
+        // the project has used two node-hours today
+        std::fs::write(
+            dir.join(Date::today().to_string().replace('-', "")),
+            "04/15/2026 10:00:00;E;1.pbs;user=alice queue=p_bank \
+             resources_used.walltime=01:00:00 Resource_List.nodect=2\n",
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot write accounting log: {}", e));
+
+        // invalid limits files are not loaded
+        let invalid = dir.join("invalid.json");
+        std::fs::write(&invalid, "not json")
+            .unwrap_or_else(|e| unreachable!("Cannot write limits file: {}", e));
+
+        assert!(matches!(
+            set_limits_file(&invalid.display().to_string()).await,
+            Err(Error::Parse(_))
+        ));
+
+        let limits_file = dir.join("limits.json");
+
+        set_limits_file(&limits_file.display().to_string())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot set limits file: {}", e));
+
+        qmgr::add_project(&mapping, &expires)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add project: {}", e));
+
+        assert!(get_limit(&mapping, &expires)
+            .await
+            .is_ok_and(|limit| limit.seconds() == 0));
+
+        let set = |seconds: u64| {
+            let mapping = mapping.clone();
+            let destination = destination.clone();
+
+            async move {
+                set_limit(&mapping, &Usage::new(seconds), &destination, &expires)
+                    .await
+                    .unwrap_or_else(|e| unreachable!("Cannot set limit: {}", e));
+
+                qmgr::is_queue_enabled("p_bank")
+                    .await
+                    .unwrap_or_else(|e| unreachable!("Cannot check queue: {}", e))
+            }
+        };
+
+        // the allocation is not yet used up
+        assert!(set(3 * 3600).await);
+        assert!(get_limit(&mapping, &expires)
+            .await
+            .is_ok_and(|limit| limit.seconds() == 3 * 3600));
+
+        // the allocation is saved
+        let saved = std::fs::read_to_string(&limits_file).unwrap_or_default();
+        assert!(saved.contains(r#""allocation":10800"#));
+        assert!(saved.contains(r#""destination":"portal.cluster.pbs""#));
+
+        // reducing it below the usage disables the queue...
+        assert!(!set(3600).await);
+
+        // ...and increasing it again re-enables it
+        assert!(set(4 * 3600).await);
+
+        // removing the allocation also re-enables the queue
+        assert!(!set(3600).await);
+        assert!(set(0).await);
+        assert!(get_limit(&mapping, &expires)
+            .await
+            .is_ok_and(|limit| limit.seconds() == 0));
+        assert!(!std::fs::read_to_string(&limits_file)
+            .unwrap_or_default()
+            .contains("p_bank"));
+
+        assert_eq!(
+            logged("p_bank")
+                .iter()
+                .filter(|line| *line == "qmgr -c set queue p_bank enabled = false")
+                .count(),
+            2
+        );
+
+        // saved allocations are loaded when the agent restarts
+        assert!(set(3 * 3600).await);
+        CACHE.write().await.accounts.clear();
+
+        set_limits_file(&limits_file.display().to_string())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot set limits file: {}", e));
+
+        assert!(get_limit(&mapping, &expires)
+            .await
+            .is_ok_and(|limit| limit.seconds() == 3 * 3600));
+
+        remove_limit(&mapping)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove limit: {}", e));
+
+        assert!(get_limit(&mapping, &expires)
+            .await
+            .is_ok_and(|limit| limit.seconds() == 0));
+
+        let expired = Utc::now() - chrono::Duration::seconds(1);
+
+        assert!(matches!(
+            get_limit(&mapping, &expired).await,
+            Err(Error::Expired(_))
+        ));
+    }
+}
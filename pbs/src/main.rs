@@ -0,0 +1,178 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+
+use templemeads::agent::scheduler::{process_args, run, Defaults};
+use templemeads::agent::Type as AgentType;
+use templemeads::async_runnable;
+use templemeads::grammar::Instruction::{
+    AddLocalProject, AddLocalUser, GetLocalLimit, GetLocalUsageReport, RemoveLocalProject,
+    RemoveLocalUser, SetLocalLimit,
+};
+use templemeads::job::{Envelope, Job};
+use templemeads::notification::default_notify_runner;
+use templemeads::set_notify_runner;
+use templemeads::Error;
+
+mod accounting;
+mod banking;
+mod qmgr;
+
+///
+/// Main function for the pbs scheduler agent
+///
+/// This agent manages projects and users in OpenPBS or PBS Professional,
+/// for sites that do not run Slurm. Each project is given its own
+/// execution queue, which only the project's users can submit to. Usage
+/// is read from the PBS accounting logs, and each project's limit is
+/// enforced by disabling its queue once the limit has been used.
+///
+#[tokio::main]
+async fn main() -> Result<()> {
+    // start tracing
+    templemeads::config::initialise_tracing();
+
+    // start system monitoring
+    templemeads::spawn_system_monitor();
+
+    // create the OpenPortal paddington defaults
+    let defaults: Defaults = Defaults::parse(
+        Some("pbs".to_owned()),
+        Some(
+            dirs::config_local_dir()
+                .unwrap_or(
+                    ".".parse()
+                        .expect("Could not parse fallback config directory."),
+                )
+                .join("openportal")
+                .join("pbs-config.toml"),
+        ),
+        Some("ws://localhost:8048".to_owned()),
+        Some("127.0.0.1".to_owned()),
+        Some(8048),
+        None,
+        None,
+        Some(AgentType::Scheduler),
+    );
+
+    // now parse the command line arguments to get the service configuration
+    let config = match process_args(&defaults).await? {
+        Some(config) => config,
+        None => {
+            // Not running the service, so can safely exit
+            return Ok(());
+        }
+    };
+
+    // Read the configurable command strings. Each defaults to the standard
+    // PBS binary name, which works when running on the PBS server. To run
+    // them remotely, set e.g.:
+    //   qmgr = "ssh pbs-server qmgr"
+    qmgr::initialise_commands(qmgr::Commands::new(
+        &config.option("qmgr", "qmgr"),
+        &config.option("qselect", "qselect"),
+        &config.option("qdel", "qdel"),
+        &config.option("queue-prefix", ""),
+    ))?;
+
+    // the directory containing the PBS accounting logs
+    accounting::set_accounting_dir(
+        &config.option("accounting-dir", "/var/spool/pbs/server_priv/accounting"),
+    )
+    .await?;
+
+    // the file in which the limit of each project is saved
+    banking::set_limits_file(
+        &config.option(
+            "limits-file",
+            &dirs::config_local_dir()
+                .unwrap_or(
+                    ".".parse()
+                        .expect("Could not parse fallback config directory."),
+                )
+                .join("openportal")
+                .join("pbs-limits.json")
+                .to_string_lossy(),
+        ),
+    )
+    .await?;
+
+    // get the interval (in seconds) between checks of each project's
+    // usage against its limit. Limits are not enforced if this is zero
+    let banking_interval: u64 = config
+        .option("banking-interval", "3600")
+        .parse()
+        .unwrap_or(3600);
+
+    if banking_interval > 0 {
+        banking::spawn_banking_task(banking_interval);
+    }
+
+    async_runnable! {
+        ///
+        /// Runnable function that will be called when a job is received
+        /// by the agent
+        ///
+        pub async fn pbs_runner(envelope: Envelope) -> Result<Job, templemeads::Error>
+        {
+            let job = envelope.job();
+
+            match job.instruction() {
+                AddLocalProject(project) => {
+                    qmgr::add_project(&project, job.expires()).await?;
+                    job.completed_none()
+                },
+                RemoveLocalProject(project) => {
+                    // the queue is disabled rather than deleted, so that
+                    // the project's accounting history is preserved
+                    qmgr::remove_project(&project, job.expires()).await?;
+                    banking::remove_limit(&project).await?;
+                    job.completed_none()
+                },
+                AddLocalUser(user) => {
+                    qmgr::add_user(&user, job.expires()).await?;
+                    job.completed_none()
+                },
+                RemoveLocalUser(mapping) => {
+                    qmgr::remove_user(&mapping, job.expires()).await?;
+                    job.completed_none()
+                },
+                GetLocalUsageReport(mapping, dates) => {
+                    let report = accounting::get_usage_report(&mapping, &dates, job.expires()).await?;
+                    job.completed(report)
+                },
+                GetLocalLimit(mapping, partition) => {
+                    if partition.is_some() {
+                        return Err(Error::InvalidInstruction(
+                            "PBS agents do not support partition limits".to_owned(),
+                        ));
+                    }
+
+                    let limit = banking::get_limit(&mapping, job.expires()).await?;
+                    job.completed(limit)
+                },
+                SetLocalLimit(mapping, limit, partition) => {
+                    if partition.is_some() {
+                        return Err(Error::InvalidInstruction(
+                            "PBS agents do not support partition limits".to_owned(),
+                        ));
+                    }
+
+                    let limit = banking::set_limit(&mapping, &limit, &job.destination().reverse(), job.expires()).await?;
+                    job.completed(limit)
+                },
+                _ => {
+                    Err(Error::InvalidInstruction(
+                        format!("Invalid instruction: {}. PBS agents do not support this instruction", job.instruction()),
+                    ))
+                }
+            }
+        }
+    }
+
+    set_notify_runner(default_notify_runner).await?;
+    run(config, pbs_runner).await?;
+
+    Ok(())
+}
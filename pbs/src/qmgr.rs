@@ -0,0 +1,593 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+use chrono::Utc;
+use once_cell::sync::OnceCell;
+use templemeads::exec;
+use templemeads::grammar::{ProjectMapping, UserMapping};
+use templemeads::job::assert_not_expired;
+use templemeads::Error;
+
+static COMMANDS: OnceCell<Commands> = OnceCell::new();
+
+/// The longest queue name that PBS accepts
+const MAX_QUEUE_NAME: usize = 15;
+
+///
+/// The PBS commands used by this agent. Each command is stored as a
+/// pre-split list of tokens so that prefixes like
+/// "ssh pbs-server qmgr" work without any shell quoting issues.
+///
+pub struct Commands {
+    qmgr: Vec<String>,
+    qselect: Vec<String>,
+    qdel: Vec<String>,
+    queue_prefix: String,
+}
+
+impl Commands {
+    fn parse_cmd(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|p| p.to_owned()).collect()
+    }
+
+    pub fn new(qmgr: &str, qselect: &str, qdel: &str, queue_prefix: &str) -> Self {
+        Self {
+            qmgr: Self::parse_cmd(qmgr),
+            qselect: Self::parse_cmd(qselect),
+            qdel: Self::parse_cmd(qdel),
+            queue_prefix: queue_prefix.to_owned(),
+        }
+    }
+}
+
+pub fn initialise_commands(cmds: Commands) -> Result<()> {
+    COMMANDS
+        .set(cmds)
+        .map_err(|_| anyhow::anyhow!("Commands already initialised"))
+}
+
+fn get_commands() -> Result<&'static Commands, Error> {
+    COMMANDS
+        .get()
+        .ok_or_else(|| Error::Call("Commands not initialised".to_owned()))
+}
+
+///
+/// Run a command built from a pre-tokenised prefix plus additional args.
+/// Returns (exit_code, stdout, stderr).
+///
+async fn run_command(parts: &[String], args: &[&str]) -> Result<(i32, String, String), Error> {
+    let output = exec::Command::from_parts(parts).args(args).output().await?;
+    let (exit_code, stdout, stderr) = (output.code, output.stdout, output.stderr);
+
+    tracing::debug!(
+        "Command exit code: {}, stdout: {}, stderr: {}",
+        exit_code,
+        stdout,
+        stderr
+    );
+
+    Ok((exit_code, stdout, stderr))
+}
+
+///
+/// Run a single qmgr directive, e.g. "set queue abc enabled = true",
+/// returning an error if it fails
+///
+async fn qmgr(directive: &str) -> Result<String, Error> {
+    let commands = get_commands()?;
+    let (code, stdout, stderr) = run_command(&commands.qmgr, &["-c", directive]).await?;
+
+    match code {
+        0 => Ok(stdout),
+        _ => Err(Error::Call(format!(
+            "qmgr -c '{}' failed (exit {}): {}",
+            directive,
+            code,
+            stderr.trim()
+        ))),
+    }
+}
+
+///
+/// Return the name of the PBS queue used for the passed local project
+/// group. PBS queue names must start with a letter, can only contain
+/// letters, digits, '_' and '-', and can be at most 15 characters long.
+///
+pub fn queue_name(local_group: &str) -> Result<String, Error> {
+    let commands = get_commands()?;
+
+    let name: String = format!("{}{}", commands.queue_prefix, local_group)
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                true => c,
+                false => '_',
+            },
+        )
+        .collect();
+
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return Err(Error::InvalidState(format!(
+            "Cannot use '{}' as a PBS queue name, as it does not start with a letter",
+            name
+        )));
+    }
+
+    if name.len() > MAX_QUEUE_NAME {
+        return Err(Error::InvalidState(format!(
+            "Cannot use '{}' as a PBS queue name, as it is longer than {} characters",
+            name, MAX_QUEUE_NAME
+        )));
+    }
+
+    Ok(name)
+}
+
+///
+/// Return the value of the passed attribute of the queue, or None if
+/// the queue or attribute does not exist
+///
+async fn get_queue_attribute(queue: &str, attribute: &str) -> Result<Option<String>, Error> {
+    let commands = get_commands()?;
+
+    let (code, stdout, _) = run_command(
+        &commands.qmgr,
+        &["-c", &format!("list queue {} {}", queue, attribute)],
+    )
+    .await?;
+
+    if code != 0 {
+        return Ok(None);
+    }
+
+    // output is "Queue <name>\n    <attribute> = <value>"
+    Ok(stdout
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(key, _)| key.trim() == attribute)
+        .map(|(_, value)| value.trim().to_owned()))
+}
+
+///
+/// Return whether or not the queue exists
+///
+async fn queue_exists(queue: &str) -> Result<bool, Error> {
+    Ok(get_queue_attribute(queue, "queue_type").await?.is_some())
+}
+
+///
+/// Create (or re-enable) the execution queue of the project. Only the
+/// users in the queue's acl_users can submit to it.
+///
+pub async fn add_project(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let queue = queue_name(project.local_group())?;
+
+    if !queue_exists(&queue).await? {
+        tracing::info!("Creating queue {} for {}", queue, project);
+        qmgr(&format!("create queue {} queue_type = execution", queue)).await?;
+    }
+
+    assert_not_expired(expires)?;
+
+    qmgr(&format!("set queue {} acl_user_enable = true", queue)).await?;
+    qmgr(&format!("set queue {} started = true", queue)).await?;
+    set_queue_enabled(&queue, true).await?;
+
+    Ok(())
+}
+
+///
+/// Disable the project's queue, so that no more jobs can be submitted,
+/// and delete its queued jobs. The queue is not deleted, so that its
+/// accounting history is kept. Adding the project again re-enables it.
+///
+pub async fn remove_project(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let queue = queue_name(project.local_group())?;
+
+    if !queue_exists(&queue).await? {
+        tracing::warn!("Queue {} for {} does not exist", queue, project);
+        return Ok(());
+    }
+
+    set_queue_enabled(&queue, false).await?;
+    delete_queued_jobs(&queue, None).await?;
+
+    tracing::info!("Disabled queue {} and deleted its queued jobs", queue);
+
+    Ok(())
+}
+
+///
+/// Allow the user to submit jobs to their project's queue
+///
+pub async fn add_user(user: &UserMapping, expires: &chrono::DateTime<Utc>) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let queue = queue_name(user.local_group())?;
+
+    if !queue_exists(&queue).await? {
+        return Err(Error::InvalidState(format!(
+            "Cannot add {} as the queue {} does not exist",
+            user, queue
+        )));
+    }
+
+    if get_acl_users(&queue)
+        .await?
+        .iter()
+        .any(|u| u == user.local_user())
+    {
+        tracing::info!("{} can already use queue {}", user.local_user(), queue);
+        return Ok(());
+    }
+
+    qmgr(&format!(
+        "set queue {} acl_users += {}",
+        queue,
+        user.local_user()
+    ))
+    .await?;
+
+    tracing::info!("Added {} to queue {}", user.local_user(), queue);
+
+    Ok(())
+}
+
+///
+/// Stop the user from submitting jobs to their project's queue, and
+/// delete their queued jobs in it
+///
+pub async fn remove_user(user: &UserMapping, expires: &chrono::DateTime<Utc>) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let queue = queue_name(user.local_group())?;
+
+    if !queue_exists(&queue).await? {
+        tracing::warn!("Queue {} for {} does not exist", queue, user);
+        return Ok(());
+    }
+
+    if get_acl_users(&queue)
+        .await?
+        .iter()
+        .any(|u| u == user.local_user())
+    {
+        qmgr(&format!(
+            "set queue {} acl_users -= {}",
+            queue,
+            user.local_user()
+        ))
+        .await?;
+    }
+
+    delete_queued_jobs(&queue, Some(user.local_user())).await?;
+
+    tracing::info!(
+        "Removed {} from queue {} and deleted their queued jobs",
+        user.local_user(),
+        queue
+    );
+
+    Ok(())
+}
+
+async fn get_acl_users(queue: &str) -> Result<Vec<String>, Error> {
+    Ok(get_queue_attribute(queue, "acl_users")
+        .await?
+        .map(|users| {
+            users
+                .split(',')
+                .map(|u| u.trim().to_owned())
+                .filter(|u| !u.is_empty())
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+///
+/// Enable or disable submission to the queue. Jobs that are already
+/// queued or running are not affected.
+///
+pub async fn set_queue_enabled(queue: &str, enabled: bool) -> Result<(), Error> {
+    qmgr(&format!("set queue {} enabled = {}", queue, enabled)).await?;
+    Ok(())
+}
+
+///
+/// Return whether or not submission to the queue is enabled
+///
+pub async fn is_queue_enabled(queue: &str) -> Result<bool, Error> {
+    Ok(get_queue_attribute(queue, "enabled")
+        .await?
+        .map(|enabled| enabled.eq_ignore_ascii_case("true"))
+        .unwrap_or(false))
+}
+
+///
+/// Delete all queued (not running) jobs in the queue, optionally only
+/// those of the passed user
+///
+async fn delete_queued_jobs(queue: &str, user: Option<&str>) -> Result<(), Error> {
+    let commands = get_commands()?;
+
+    let mut args = vec!["-q", queue, "-s", "Q"];
+
+    if let Some(user) = user {
+        args.extend(["-u", user]);
+    }
+
+    let (code, stdout, stderr) = run_command(&commands.qselect, &args).await?;
+
+    if code != 0 {
+        return Err(Error::Call(format!(
+            "qselect {} failed (exit {}): {}",
+            args.join(" "),
+            code,
+            stderr.trim()
+        )));
+    }
+
+    let job_ids: Vec<&str> = stdout
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .collect();
+
+    if job_ids.is_empty() {
+        return Ok(());
+    }
+
+    let (code, _, stderr) = run_command(&commands.qdel, &job_ids).await?;
+
+    if code != 0 {
+        tracing::warn!(
+            "Could not delete all queued jobs in {}: {}",
+            queue,
+            stderr.trim()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::Once;
+    use templemeads::grammar::{ProjectIdentifier, UserIdentifier};
+
+    static FAKE_PBS: Once = Once::new();
+
+    ///
+    /// Return the directory of a fake PBS server, used by the tests of
+    /// all modules. Queue attributes are stored as files called
+    /// "<queue>.<attribute>", and every command is logged to "pbs.log".
+    /// Any directive on a queue containing "broken" fails.
+    ///
+    pub(crate) fn fake_pbs() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("op-pbs-test-{}", std::process::id()));
+
+        FAKE_PBS.call_once(|| {
+            let _ = std::fs::remove_dir_all(&dir);
+            let _ = std::fs::create_dir_all(&dir);
+
+            let script = format!(
+                r#"dir={dir}
+cmd=$1; shift
+echo "$cmd $*" >> $dir/pbs.log
+case "$cmd" in
+  qmgr)
+    set -- $2
+    case "$*" in *broken*) echo "qmgr: Unauthorized Request" >&2; exit 1 ;; esac
+    case "$1" in
+      list)
+        [ -f $dir/$3.queue_type ] || exit 1
+        echo "Queue $3"
+        [ -f $dir/$3.$4 ] && echo "    $4 = $(cat $dir/$3.$4)"
+        ;;
+      create) echo Execution > $dir/$3.queue_type ;;
+      set)
+        case "$5" in
+          "=") echo "$6" > $dir/$3.$4 ;;
+          "+=") old=$(cat $dir/$3.$4 2>/dev/null); echo "${{old:+$old,}}$6" > $dir/$3.$4 ;;
+          "-=") sed -e "s/\(^\|,\)$6\(,\|$\)/\1/; s/,$//" $dir/$3.$4 > $dir/$3.tmp; mv $dir/$3.tmp $dir/$3.$4 ;;
+        esac
+        ;;
+    esac
+    ;;
+  qselect) printf '1.pbs\n2.pbs\n' ;;
+esac
+"#,
+                dir = dir.display()
+            );
+
+            let _ = std::fs::write(dir.join("pbs.sh"), script);
+
+            let command = |name: &str| format!("sh {} {}", dir.join("pbs.sh").display(), name);
+
+            let _ = initialise_commands(Commands::new(
+                &command("qmgr"),
+                &command("qselect"),
+                &command("qdel"),
+                "p_",
+            ));
+        });
+
+        dir
+    }
+
+    ///
+    /// Return the lines of the fake PBS log that mention the queue
+    ///
+    pub(crate) fn logged(queue: &str) -> Vec<String> {
+        std::fs::read_to_string(fake_pbs().join("pbs.log"))
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| line.split_whitespace().any(|word| word == queue))
+            .map(|line| line.to_owned())
+            .collect()
+    }
+
+    pub(crate) fn project(name: &str) -> ProjectMapping {
+        ProjectMapping::new(
+            &ProjectIdentifier::parse(&format!("{}.portal", name))
+                .unwrap_or_else(|e| unreachable!("Cannot parse project: {}", e)),
+            name,
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e))
+    }
+
+    fn user(name: &str, project: &str) -> UserMapping {
+        UserMapping::new(
+            &UserIdentifier::parse(&format!("{}.{}.portal", name, project))
+                .unwrap_or_else(|e| unreachable!("Cannot parse user: {}", e)),
+            name,
+            project,
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e))
+    }
+
+    fn expires() -> chrono::DateTime<Utc> {
+        Utc::now() + chrono::Duration::seconds(30)
+    }
+
+    #[test]
+    fn test_queue_name() {
+        fake_pbs();
+
+        assert!(matches!(queue_name("proj").as_deref(), Ok("p_proj")));
+        assert!(matches!(queue_name("a.b c").as_deref(), Ok("p_a_b_c")));
+        assert!(matches!(
+            queue_name("a_very_long_project"),
+            Err(Error::InvalidState(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_cmd() {
+        assert_eq!(
+            Commands::parse_cmd("ssh  pbs-server qmgr"),
+            vec!["ssh", "pbs-server", "qmgr"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_projects_and_users() {
+        fake_pbs();
+
+        let project = project("qmgr");
+        let alice = user("alice", "qmgr");
+        let bob = user("bob", "qmgr");
+
+        // users can only be added to projects that exist
+        assert!(matches!(
+            add_user(&alice, &expires()).await,
+            Err(Error::InvalidState(_))
+        ));
+
+        add_project(&project, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add project: {}", e));
+
+        assert!(is_queue_enabled("p_qmgr").await.is_ok_and(|e| e));
+
+        for user in [&alice, &bob, &alice] {
+            add_user(user, &expires())
+                .await
+                .unwrap_or_else(|e| unreachable!("Cannot add user: {}", e));
+        }
+
+        assert!(get_acl_users("p_qmgr")
+            .await
+            .is_ok_and(|users| users == ["alice", "bob"]));
+
+        remove_user(&alice, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove user: {}", e));
+
+        assert!(get_acl_users("p_qmgr")
+            .await
+            .is_ok_and(|users| users == ["bob"]));
+
+        remove_project(&project, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove project: {}", e));
+
+        // the queue is kept, but disabled
+        assert!(queue_exists("p_qmgr").await.is_ok_and(|e| e));
+        assert!(is_queue_enabled("p_qmgr").await.is_ok_and(|e| !e));
+
+        let log = logged("p_qmgr");
+        let position = |line: &str| {
+            log.iter()
+                .position(|l| l == line)
+                .unwrap_or_else(|| unreachable!("'{}' was not run: {:?}", line, log))
+        };
+
+        assert!(
+            position("qmgr -c create queue p_qmgr queue_type = execution")
+                < position("qmgr -c set queue p_qmgr acl_user_enable = true")
+        );
+
+        // alice is only added once, and her queued jobs are deleted
+        // when she is removed
+        assert_eq!(
+            log.iter()
+                .filter(|l| *l == "qmgr -c set queue p_qmgr acl_users += alice")
+                .count(),
+            1
+        );
+        position("qmgr -c set queue p_qmgr acl_users -= alice");
+        position("qselect -q p_qmgr -s Q -u alice");
+
+        // all queued jobs are deleted when the project is removed
+        position("qmgr -c set queue p_qmgr enabled = false");
+        position("qselect -q p_qmgr -s Q");
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        fake_pbs();
+
+        let broken = project("broken");
+
+        // the fake qmgr fails every directive on this queue, so it
+        // looks like it does not exist...
+        assert!(matches!(queue_exists("p_broken").await, Ok(false)));
+
+        // ...and cannot be created
+        assert!(matches!(
+            add_project(&broken, &expires()).await,
+            Err(Error::Call(message)) if message.contains("Unauthorized Request")
+        ));
+
+        // removing a project that doesn't exist is not an error
+        assert!(remove_project(&project("missing"), &expires())
+            .await
+            .is_ok());
+
+        let expired = Utc::now() - chrono::Duration::seconds(1);
+
+        assert!(matches!(
+            add_project(&project("qmgr"), &expired).await,
+            Err(Error::Expired(_))
+        ));
+        assert!(matches!(
+            remove_user(&user("alice", "qmgr"), &expired).await,
+            Err(Error::Expired(_))
+        ));
+    }
+}
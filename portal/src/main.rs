@@ -8,7 +8,9 @@ use templemeads::agent::portal::{process_args, run, Defaults};
 use templemeads::agent::Type as AgentType;
 use templemeads::async_runnable;
 use templemeads::command::Command;
+use templemeads::dryrun;
 use templemeads::notification::{self, NotificationEnvelope, NotificationEvent};
+use templemeads::portalquota;
 use templemeads::set_notify_runner;
 
 use templemeads::agent::Type::Bridge;
@@ -19,7 +21,8 @@ use templemeads::grammar::Instruction::{
     RemoveOfferings, RemoveProject, Submit, SyncOfferings, UpdateProject,
 };
 use templemeads::grammar::{
-    DateRange, PortalIdentifier, ProjectDetails, ProjectIdentifier, ProjectMapping, UserMapping,
+    Allocation, DateRange, PortalIdentifier, ProjectDetails, ProjectIdentifier, ProjectMapping,
+    UserMapping,
 };
 use templemeads::job::{send_queued, Envelope, Job};
 use templemeads::storagereport::{ProjectStorageReport, StorageReport};
@@ -87,6 +90,7 @@ async fn main() -> Result<()> {
                 CreateProject(project, details) => {
                     tracing::debug!("Creating project {} with details {}", project, details);
 
+                    check_portal_quota(&me, &resource, &project, &details, &job.destination()).await?;
                     let result = create_project(&me, &resource, &project, &details, &job.destination()).await?;
                     notification::send(&job.destination().reverse(), NotificationEvent::AwardAdded(project.clone())).await;
                     job.completed(result)
@@ -101,6 +105,7 @@ async fn main() -> Result<()> {
                 UpdateProject(project, details) => {
                     tracing::debug!("Updating project {} with details {}", project, details);
 
+                    check_portal_quota(&me, &resource, &project, &details, &job.destination()).await?;
                     let result = update_project(&me, &resource, &project, &details, &job.destination()).await?;
                     notification::send(&job.destination().reverse(), NotificationEvent::AwardChanged(project.clone())).await;
                     job.completed(result)
@@ -299,6 +304,11 @@ async fn main() -> Result<()> {
                             // This is a special instruction that updates the
                             // set of offerings that this portal manages
                             tracing::info!("Syncing offerings to: {:?}", offerings);
+
+                            if dryrun::record(&format!("sync offerings to {}", offerings)) {
+                                return job.completed(offerings);
+                            }
+
                             job.completed(sync_offerings(&offerings).await?)
                         }
                         AddOfferings(offerings) => {
@@ -308,6 +318,10 @@ async fn main() -> Result<()> {
 
                             let existing_offerings = get_offerings().await?;
 
+                            if dryrun::record(&format!("add offerings {}", offerings)) {
+                                return job.completed(existing_offerings.add(offerings));
+                            }
+
                             job.completed(sync_offerings(&existing_offerings.add(offerings)).await?)
                         }
                         RemoveOfferings(offerings) => {
@@ -317,6 +331,10 @@ async fn main() -> Result<()> {
 
                             let existing_offerings = get_offerings().await?;
 
+                            if dryrun::record(&format!("remove offerings {}", offerings)) {
+                                return job.completed(existing_offerings.remove(offerings));
+                            }
+
                             job.completed(sync_offerings(&existing_offerings.remove(offerings)).await?)
                         }
                         _ => {
@@ -657,6 +675,65 @@ pub async fn sync_offerings(offerings: &Destinations) -> Result<Destinations, Er
     }
 }
 
+///
+/// Return an error if creating or updating `project` with `details`
+/// would take its portal over its maximum number of projects, or over
+/// the maximum total allocation that it can be granted
+///
+async fn check_portal_quota(
+    me: &str,
+    resource: &str,
+    project: &ProjectIdentifier,
+    details: &ProjectDetails,
+    forwarded_for: &Destination,
+) -> Result<(), Error> {
+    let quota = portalquota::quota(&project.portal());
+
+    if quota.max_projects().is_none() && quota.max_allocation().is_none() {
+        return Ok(());
+    }
+
+    let portal = project.portal_identifier();
+
+    let projects = get_projects(me, resource, &portal, forwarded_for).await?;
+    let exists = projects.iter().any(|mapping| mapping.project() == project);
+
+    if !exists {
+        quota.check_projects(projects.len() + 1)?;
+    }
+
+    // only a new allocation can take the portal over its maximum
+    let Some(allocation) = details.allocation() else {
+        return Ok(());
+    };
+
+    if quota.max_allocation().is_none() {
+        return Ok(());
+    }
+
+    let mut allocations: Vec<Allocation> = get_awards(me, resource, &portal, forwarded_for)
+        .await?
+        .iter()
+        .filter_map(|award| award.allocation())
+        .collect();
+
+    // the new allocation replaces the existing one for this project
+    if exists {
+        if let Some(existing) = get_award(me, resource, project, forwarded_for)
+            .await?
+            .allocation()
+        {
+            if let Some(index) = allocations.iter().position(|a| *a == existing) {
+                allocations.remove(index);
+            }
+        }
+    }
+
+    allocations.push(allocation);
+
+    quota.check_allocations(&allocations)
+}
+
 ///
 /// Create a new project
 ///
@@ -0,0 +1,287 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! The `openportal.aio` submodule, which provides asyncio-compatible
+//! versions of the functions that call the bridge. Each returns an
+//! awaitable, and the HTTP calls (and the polling while waiting for a
+//! job) are made on a tokio runtime, so that they never block the
+//! Python event loop.
+
+use anyhow::Context;
+use pyo3::exceptions::PyOSError;
+use pyo3::prelude::*;
+use pyo3_stub_gen::derive::*;
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+use templemeads::job;
+use templemeads::Error;
+
+use crate::request::{self, Reply, SignedRequest};
+use crate::{get_config, Health, Job, Uuid};
+
+///
+/// Call `function` on the bridge without blocking, POSTing `body` if
+/// there is one, or else making a GET request. Calls that are rate
+/// limited are retried with exponential backoff
+///
+async fn call<T>(function: &str, body: Option<Vec<u8>>) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    let config = get_config()?;
+    let client = config.async_client()?;
+
+    for attempt in 0..=request::MAX_RETRIES {
+        let response = SignedRequest::new(&config, function, body.as_deref())?
+            .nonblocking(&client)
+            .send()
+            .await
+            .with_context(|| format!("Could not call function: {}", function))?;
+
+        let status = response.status();
+        let reply = response
+            .bytes()
+            .await
+            .with_context(|| format!("Could not read the response to function: {}", function))?;
+
+        match request::decode(function, attempt, status, &reply)? {
+            Reply::Done(value) => return Ok(value),
+            Reply::Retry(backoff) => tokio::time::sleep(backoff).await,
+        }
+    }
+
+    Err(request::retries_exhausted(function))
+}
+
+async fn call_get<T>(function: &str) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    tracing::debug!("Calling get /{} (async)", function);
+
+    call(function, None).await
+}
+
+async fn call_post<T>(function: &str, arguments: serde_json::Value) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    tracing::debug!(
+        "Calling post /{} (async) with arguments: {:?}",
+        function,
+        arguments
+    );
+
+    // Serialize the arguments once to get the exact bytes we'll send
+    let body =
+        serde_json::to_vec(&arguments).with_context(|| "Could not serialize arguments to JSON")?;
+
+    call(function, Some(body)).await
+}
+
+fn to_py_err(e: Error) -> PyErr {
+    PyErr::new::<PyOSError, _>(format!("{:?}", e))
+}
+
+async fn get_job(job_id: String) -> Result<Job, Error> {
+    Ok(
+        call_post::<job::Job>("status", serde_json::json!({"job": job_id}))
+            .await?
+            .into(),
+    )
+}
+
+///
+/// Poll the bridge until the passed job has finished, or until 'max_ms'
+/// milliseconds have passed (forever if 'max_ms' is negative), returning
+/// the latest version of the job. This mirrors `Job.wait`
+///
+async fn wait_for(job: Job, max_ms: i64) -> Result<Job, Error> {
+    let mut job = job;
+
+    if max_ms < 0 {
+        // wait forever...
+        while !job.0.is_finished() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            job = get_job(job.0.id().to_string()).await?;
+        }
+    } else {
+        let max_ms: u64 = max_ms as u64;
+
+        let mut total_waited: u64 = 0;
+
+        // check at least 10 times, with a minimum of 1ms and a maximum of 100ms
+        let delta: u64 = (max_ms / 10).clamp(1, 100);
+
+        while !job.0.is_finished() && total_waited < max_ms {
+            tokio::time::sleep(Duration::from_millis(delta)).await;
+            job = get_job(job.0.id().to_string()).await?;
+            total_waited += delta;
+        }
+    }
+
+    Ok(job)
+}
+
+///
+/// Run the passed command on the OpenPortal system, returning an
+/// awaitable that resolves to the Job. As for `openportal.run`, this
+/// does not wait for the job to finish unless a maximum number of
+/// milliseconds to wait is passed as 'max_ms' (or a negative number
+/// to wait indefinitely). Pass 'dry_run=True' to rehearse the command.
+///
+#[gen_stub_pyfunction(module = "openportal.aio")]
+#[pyfunction]
+#[pyo3(signature = (command, max_ms=0, dry_run=false))]
+#[gen_stub(override_return_type(
+    type_repr = "typing.Awaitable[openportal.Job]",
+    imports = ("typing", "openportal")
+))]
+fn run(py: Python<'_>, command: String, max_ms: i64, dry_run: bool) -> PyResult<Bound<'_, PyAny>> {
+    pyo3_async_runtimes::tokio::future_into_py(py, async move {
+        let job: Job = call_post::<job::Job>(
+            "run",
+            serde_json::json!({"command": command, "dry_run": dry_run}),
+        )
+        .await
+        .map_err(to_py_err)?
+        .into();
+
+        // as for Job.update, only fetch the job if it has not finished
+        let job = match job.0.is_finished() {
+            true => job,
+            false => get_job(job.0.id().to_string()).await.map_err(to_py_err)?,
+        };
+
+        match max_ms {
+            0 => Ok(job),
+            _ => wait_for(job, max_ms).await.map_err(to_py_err),
+        }
+    })
+}
+
+///
+/// Return an awaitable that resolves to the latest version of the
+/// passed job on the OpenPortal system.
+///
+#[gen_stub_pyfunction(module = "openportal.aio")]
+#[pyfunction]
+#[gen_stub(override_return_type(
+    type_repr = "typing.Awaitable[openportal.Job]",
+    imports = ("typing", "openportal")
+))]
+fn status(py: Python<'_>, job: Job) -> PyResult<Bound<'_, PyAny>> {
+    let job_id = job.0.id().to_string();
+
+    pyo3_async_runtimes::tokio::future_into_py(py, async move {
+        get_job(job_id).await.map_err(to_py_err)
+    })
+}
+
+///
+/// Return an awaitable that resolves to the Job with the specified ID.
+/// This raises an error if the job does not exist.
+///
+#[gen_stub_pyfunction(module = "openportal.aio")]
+#[pyfunction]
+#[gen_stub(override_return_type(
+    type_repr = "typing.Awaitable[openportal.Job]",
+    imports = ("typing", "openportal")
+))]
+fn get(py: Python<'_>, job_id: Py<PyAny>) -> PyResult<Bound<'_, PyAny>> {
+    let job_id = match job_id.extract::<Uuid>(py) {
+        Ok(uid) => uid.to_string()?,
+        Err(_) => match job_id.extract::<String>(py) {
+            Ok(uid) => uid,
+            Err(_) => {
+                return Err(PyErr::new::<PyOSError, _>(
+                    "Job ID must be a string or a Uuid",
+                ))
+            }
+        },
+    };
+
+    pyo3_async_runtimes::tokio::future_into_py(py, async move {
+        get_job(job_id).await.map_err(to_py_err)
+    })
+}
+
+///
+/// Return an awaitable that waits for the passed job to finish, for
+/// up to 'max_ms' milliseconds (or forever if this is negative), and
+/// then resolves to the latest version of the job. Check `is_finished`
+/// on the returned job to see if it finished in time.
+///
+#[gen_stub_pyfunction(module = "openportal.aio")]
+#[pyfunction]
+#[pyo3(signature = (job, max_ms=1000))]
+#[gen_stub(override_return_type(
+    type_repr = "typing.Awaitable[openportal.Job]",
+    imports = ("typing", "openportal")
+))]
+fn wait(py: Python<'_>, job: Job, max_ms: i64) -> PyResult<Bound<'_, PyAny>> {
+    pyo3_async_runtimes::tokio::future_into_py(py, async move {
+        wait_for(job, max_ms).await.map_err(to_py_err)
+    })
+}
+
+///
+/// Return an awaitable that resolves to the health of the OpenPortal
+/// system.
+///
+#[gen_stub_pyfunction(module = "openportal.aio")]
+#[pyfunction]
+#[gen_stub(override_return_type(
+    type_repr = "typing.Awaitable[openportal.Health]",
+    imports = ("typing", "openportal")
+))]
+fn health(py: Python<'_>) -> PyResult<Bound<'_, PyAny>> {
+    pyo3_async_runtimes::tokio::future_into_py(py, async move {
+        call_get::<Health>("health").await.map_err(to_py_err)
+    })
+}
+
+///
+/// Send a fire-and-forget notification into the OpenPortal network,
+/// returning an awaitable that resolves once the notification has
+/// been handed off to the bridge.
+///
+#[gen_stub_pyfunction(module = "openportal.aio")]
+#[pyfunction]
+#[gen_stub(override_return_type(
+    type_repr = "typing.Awaitable[None]",
+    imports = ("typing")
+))]
+fn notify(py: Python<'_>, command: String) -> PyResult<Bound<'_, PyAny>> {
+    pyo3_async_runtimes::tokio::future_into_py(py, async move {
+        call_post::<serde_json::Value>("notify", serde_json::json!({"command": command}))
+            .await
+            .map_err(to_py_err)?;
+        Ok(())
+    })
+}
+
+///
+/// Create the `aio` submodule and register it as `openportal.aio`,
+/// so that it can be imported with `from openportal import aio`
+/// or `import openportal.aio`
+///
+pub(crate) fn register(parent: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = parent.py();
+    let m = PyModule::new(py, "aio")?;
+
+    m.add_function(wrap_pyfunction!(get, &m)?)?;
+    m.add_function(wrap_pyfunction!(health, &m)?)?;
+    m.add_function(wrap_pyfunction!(notify, &m)?)?;
+    m.add_function(wrap_pyfunction!(run, &m)?)?;
+    m.add_function(wrap_pyfunction!(status, &m)?)?;
+    m.add_function(wrap_pyfunction!(wait, &m)?)?;
+
+    parent.add_submodule(&m)?;
+
+    py.import("sys")?
+        .getattr("modules")?
+        .set_item("openportal.aio", &m)?;
+
+    Ok(())
+}
@@ -2,7 +2,6 @@
 // SPDX-License-Identifier: MIT
 
 use anyhow::{Context, Result};
-use chrono::Utc;
 use once_cell::sync::Lazy;
 use paddington::SecretKey;
 use pyo3::basic::CompareOp;
@@ -16,28 +15,147 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path;
 use std::sync::RwLock;
+use templemeads::batch;
 use templemeads::destination;
 use templemeads::diagnostics as mod_diagnostics;
 use templemeads::grammar;
 use templemeads::health as mod_health;
 use templemeads::job;
+use templemeads::jobqueue;
 use templemeads::notification as mod_notification;
-use templemeads::server::sign_api_call;
+use templemeads::protection;
+use templemeads::reconcile;
+use templemeads::server;
 use templemeads::storagereport;
 use templemeads::usagereport;
 use templemeads::Error;
 use url::Url;
 
+mod aio;
+mod request;
+
+use request::{Reply, SignedRequest};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BridgeConfig {
     url: Url,
     key: SecretKey,
+
+    // PEM files holding the client certificate and key presented to a
+    // bridge that requires mutual TLS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    client_cert: Option<path::PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    client_key: Option<path::PathBuf>,
+
+    // PEM file holding the CA that signed the bridge's certificate, if
+    // it is not in the system trust store
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ca_cert: Option<path::PathBuf>,
+}
+
+impl BridgeConfig {
+    ///
+    /// Return the client identity (from the client certificate and key)
+    /// and the extra root certificate (from the CA) to use when calling
+    /// the bridge, if these are set
+    ///
+    fn tls(&self) -> Result<(Option<reqwest::Identity>, Option<reqwest::Certificate>), Error> {
+        let identity = match (&self.client_cert, &self.client_key) {
+            (Some(client_cert), Some(client_key)) => {
+                // reqwest reads the certificate and key from a single PEM
+                let mut pem = std::fs::read(client_cert).with_context(|| {
+                    format!("Could not read client certificate: {:?}", client_cert)
+                })?;
+                pem.push(b'\n');
+                pem.extend(
+                    std::fs::read(client_key)
+                        .with_context(|| format!("Could not read client key: {:?}", client_key))?,
+                );
+
+                Some(
+                    reqwest::Identity::from_pem(&pem)
+                        .context("Could not load the client certificate and key")?,
+                )
+            }
+            (None, None) => None,
+            _ => {
+                return Err(Error::InvalidConfig(
+                    "Both client_cert and client_key must be set to use a client certificate"
+                        .to_owned(),
+                ))
+            }
+        };
+
+        let ca = match &self.ca_cert {
+            Some(ca_cert) => {
+                let pem = std::fs::read(ca_cert)
+                    .with_context(|| format!("Could not read CA certificate: {:?}", ca_cert))?;
+
+                Some(
+                    reqwest::Certificate::from_pem(&pem)
+                        .context("Could not load the CA certificate")?,
+                )
+            }
+            None => None,
+        };
+
+        Ok((identity, ca))
+    }
+
+    ///
+    /// Return the HTTP client used to call the bridge, which presents
+    /// the client certificate (if any) and trusts the CA (if any)
+    ///
+    fn client(&self) -> Result<reqwest::blocking::Client, Error> {
+        let mut builder = reqwest::blocking::Client::builder();
+
+        let (identity, ca) = self.tls()?;
+
+        if let Some(identity) = identity {
+            builder = builder.identity(identity);
+        }
+
+        if let Some(ca) = ca {
+            builder = builder.add_root_certificate(ca);
+        }
+
+        Ok(builder
+            .build()
+            .context("Could not create the HTTP client")?)
+    }
+
+    ///
+    /// Return the non-blocking HTTP client used by the `aio` functions
+    /// to call the bridge
+    ///
+    fn async_client(&self) -> Result<reqwest::Client, Error> {
+        let mut builder = reqwest::Client::builder();
+
+        let (identity, ca) = self.tls()?;
+
+        if let Some(identity) = identity {
+            builder = builder.identity(identity);
+        }
+
+        if let Some(ca) = ca {
+            builder = builder.add_root_certificate(ca);
+        }
+
+        Ok(builder
+            .build()
+            .context("Could not create the HTTP client")?)
+    }
 }
 
 ///
 /// Load the client configuration from the passed filename.
 ///
 fn local_load_config(config_file: &path::PathBuf) -> Result<(), Error> {
+    // fail now if the crypto mode is misconfigured, rather than on the
+    // first signed call
+    paddington::crypto_mode()?;
+
     // see if this config_file exists - return an error if it doesn't
     let config_file = path::absolute(config_file)?;
 
@@ -96,143 +214,58 @@ fn get_config() -> Result<BridgeConfig, Error> {
 // this once, and it will be used by all functions
 static SINGLETON_CONFIG: Lazy<RwLock<Option<BridgeConfig>>> = Lazy::new(|| RwLock::new(None));
 
-fn call_get<T>(function: &str) -> Result<T, Error>
+///
+/// Call `function` on the bridge, POSTing `body` if there is one, or
+/// else making a GET request. Calls that are rate limited are retried
+/// with exponential backoff
+///
+fn call<T>(function: &str, body: Option<Vec<u8>>) -> Result<T, Error>
 where
     T: DeserializeOwned,
 {
-    tracing::debug!("Calling get /{}", function);
-
     let config = get_config()?;
+    let client = config.client()?;
 
-    // Retry logic with exponential backoff for rate limiting
-    const MAX_RETRIES: u32 = 5;
-    const INITIAL_BACKOFF_MS: u64 = 100;
-
-    for attempt in 0..=MAX_RETRIES {
-        let date = Utc::now();
-        let url = config.url.join(function).context("Could not join URL")?;
-
-        // Generate a unique nonce for replay attack prevention
-        let nonce = uuid::Uuid::new_v4().to_string();
-        // GET requests have no body, so sign with empty slice
-        let auth_token = sign_api_call(&config.key, &date, "get", function, &[], Some(&nonce))?;
-
-        let result = reqwest::blocking::Client::new()
-            .get(url)
-            .query(&[("openportal-version", "0.1")])
-            .header("Accept", "application/json")
-            .header("Authorization", auth_token)
-            .header("Date", date.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
-            .header("X-Nonce", nonce)
+    for attempt in 0..=request::MAX_RETRIES {
+        let response = SignedRequest::new(&config, function, body.as_deref())?
+            .blocking(&client)
             .send()
             .with_context(|| format!("Could not call function: {}", function))?;
 
-        tracing::debug!("Response: {:?}", result);
-
-        if result.status().is_success() {
-            return Ok(result.json::<T>().context("Could not decode from json")?);
-        } else if result.status() == reqwest::StatusCode::TOO_MANY_REQUESTS && attempt < MAX_RETRIES
-        {
-            // Rate limited - backoff and retry
-            let backoff_ms = INITIAL_BACKOFF_MS * 2_u64.pow(attempt);
-            tracing::warn!(
-                "Rate limited on attempt {} for function: {}. Backing off for {}ms",
-                attempt + 1,
-                function,
-                backoff_ms
-            );
-            std::thread::sleep(std::time::Duration::from_millis(backoff_ms));
-        } else {
-            return Err(Error::Call(format!(
-                "Could not get response for function: {}. Status: {}. Response: {:?}",
-                function,
-                result.status(),
-                result
-            )));
+        let status = response.status();
+        let reply = response
+            .bytes()
+            .with_context(|| format!("Could not read the response to function: {}", function))?;
+
+        match request::decode(function, attempt, status, &reply)? {
+            Reply::Done(value) => return Ok(value),
+            Reply::Retry(backoff) => std::thread::sleep(backoff),
         }
     }
 
-    // If we exhausted all retries
-    Err(Error::Call(format!(
-        "Exceeded maximum retries ({}) for function: {} due to rate limiting",
-        MAX_RETRIES, function
-    )))
+    Err(request::retries_exhausted(function))
 }
 
-fn call_post<T>(function: &str, arguments: serde_json::Value) -> Result<T, Error>
+fn call_get<T>(function: &str) -> Result<T, Error>
 where
     T: DeserializeOwned,
 {
-    tracing::debug!("Calling post /{} with arguments: {:?}", function, arguments);
+    tracing::debug!("Calling get /{}", function);
 
-    let config = get_config()?;
+    call(function, None)
+}
 
-    // Retry logic with exponential backoff for rate limiting
-    const MAX_RETRIES: u32 = 5;
-    const INITIAL_BACKOFF_MS: u64 = 100;
+fn call_post<T>(function: &str, arguments: serde_json::Value) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    tracing::debug!("Calling post /{} with arguments: {:?}", function, arguments);
 
     // Serialize the arguments once to get the exact bytes we'll send
-    let body_bytes =
+    let body =
         serde_json::to_vec(&arguments).with_context(|| "Could not serialize arguments to JSON")?;
 
-    for attempt in 0..=MAX_RETRIES {
-        let date = Utc::now();
-        let url = config.url.join(function).context("Could not join URL")?;
-
-        // Generate a unique nonce for replay attack prevention
-        let nonce = uuid::Uuid::new_v4().to_string();
-        // Sign the exact bytes we're about to send
-        let auth_token = sign_api_call(
-            &config.key,
-            &date,
-            "post",
-            function,
-            &body_bytes,
-            Some(&nonce),
-        )?;
-
-        let result = reqwest::blocking::Client::new()
-            .post(url)
-            .query(&[("openportal-version", "0.1")])
-            .header("Accept", "application/json")
-            .header("Content-Type", "application/json")
-            .header("Authorization", auth_token)
-            .header("Date", date.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
-            .header("X-Nonce", nonce)
-            .body(body_bytes.clone())
-            .send()
-            .with_context(|| format!("Could not call function: {}", function))?;
-
-        tracing::debug!("Response: {:?}", result);
-
-        if result.status().is_success() {
-            return Ok(result.json::<T>().context("Could not decode from json")?);
-        } else if result.status() == reqwest::StatusCode::TOO_MANY_REQUESTS && attempt < MAX_RETRIES
-        {
-            // Rate limited - backoff and retry
-            let backoff_ms = INITIAL_BACKOFF_MS * 2_u64.pow(attempt);
-            tracing::warn!(
-                "Rate limited on attempt {} for function: {}. Backing off for {}ms",
-                attempt + 1,
-                function,
-                backoff_ms
-            );
-            std::thread::sleep(std::time::Duration::from_millis(backoff_ms));
-        } else {
-            return Err(Error::Call(format!(
-                "Could not get response for function: {}. Status: {}. Response: {:?}",
-                function,
-                result.status(),
-                result
-            )));
-        }
-    }
-
-    // If we exhausted all retries
-    Err(Error::Call(format!(
-        "Exceeded maximum retries ({}) for function: {} due to rate limiting",
-        MAX_RETRIES, function
-    )))
+    call(function, Some(body))
 }
 
 ///
@@ -248,6 +281,61 @@ fn load_config(config_file: path::PathBuf) -> PyResult<()> {
     }
 }
 
+///
+/// Return the crypto mode ("standard" or "fips") used to sign calls
+/// to the bridge. This must match the mode used by the bridge
+///
+#[gen_stub_pyfunction]
+#[pyfunction]
+fn crypto_mode() -> PyResult<String> {
+    match paddington::crypto_mode() {
+        Ok(mode) => Ok(mode.to_string()),
+        Err(e) => Err(PyErr::new::<PyOSError, _>(format!("{:?}", e))),
+    }
+}
+
+///
+/// Present the client certificate and key in the passed PEM files to
+/// the bridge, for bridges that require mutual TLS. Optionally also
+/// trust the CA in the passed PEM file when checking the bridge's own
+/// certificate. This overrides any set in the loaded configuration,
+/// so must be called after load_config()
+///
+#[gen_stub_pyfunction]
+#[pyfunction]
+#[pyo3(signature = (client_cert, client_key, ca_cert=None))]
+fn set_client_certificate(
+    client_cert: path::PathBuf,
+    client_key: path::PathBuf,
+    ca_cert: Option<path::PathBuf>,
+) -> PyResult<()> {
+    let mut config = match get_config() {
+        Ok(config) => config,
+        Err(e) => return Err(PyErr::new::<PyOSError, _>(format!("{:?}", e))),
+    };
+
+    config.client_cert = Some(client_cert);
+    config.client_key = Some(client_key);
+
+    if ca_cert.is_some() {
+        config.ca_cert = ca_cert;
+    }
+
+    // check that the certificates can be loaded now, rather than on
+    // the first call
+    if let Err(e) = config.client() {
+        return Err(PyErr::new::<PyOSError, _>(format!("{:?}", e)));
+    }
+
+    match SINGLETON_CONFIG.write() {
+        Ok(mut guard) => {
+            *guard = Some(config);
+            Ok(())
+        }
+        Err(e) => Err(PyErr::new::<PyOSError, _>(format!("{:?}", e))),
+    }
+}
+
 ///
 /// Return whether or not a valid configuration has been loaded
 ///
@@ -588,6 +676,142 @@ impl From<mod_diagnostics::LogEntry> for LogEntry {
     }
 }
 
+///
+/// Record of a soft restart performed automatically by an agent's watchdog
+///
+#[gen_stub_pyclass]
+#[pyclass(module = "openportal")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchdogRestart(mod_diagnostics::WatchdogRestart);
+
+#[gen_stub_pymethods]
+#[pymethods]
+impl WatchdogRestart {
+    #[getter]
+    fn restarted_at<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDateTime>> {
+        PyDateTime::from_timestamp(
+            py,
+            self.0.restarted_at.timestamp() as f64,
+            PyTzInfo::utc(py).ok().as_deref(),
+        )
+    }
+
+    #[getter]
+    fn reason(&self) -> PyResult<String> {
+        Ok(self.0.reason.clone())
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        Ok(format!("{}", self.0))
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        self.__str__()
+    }
+
+    fn __copy__(&self) -> PyResult<WatchdogRestart> {
+        Ok(self.clone())
+    }
+
+    fn __deepcopy__(&self, _memo: Py<PyAny>) -> PyResult<WatchdogRestart> {
+        Ok(self.clone())
+    }
+}
+
+impl From<mod_diagnostics::WatchdogRestart> for WatchdogRestart {
+    fn from(restart: mod_diagnostics::WatchdogRestart) -> Self {
+        WatchdogRestart(restart)
+    }
+}
+
+///
+/// Job and notification counts for one interval of an agent's
+/// diagnostics history
+///
+#[gen_stub_pyclass]
+#[pyclass(module = "openportal")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiagnosticsCounts(mod_diagnostics::DiagnosticsCounts);
+
+#[gen_stub_pymethods]
+#[pymethods]
+impl DiagnosticsCounts {
+    #[getter]
+    fn start<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDateTime>> {
+        PyDateTime::from_timestamp(
+            py,
+            self.0.start.timestamp() as f64,
+            PyTzInfo::utc(py).ok().as_deref(),
+        )
+    }
+
+    #[getter]
+    fn end<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDateTime>> {
+        PyDateTime::from_timestamp(
+            py,
+            self.0.end.timestamp() as f64,
+            PyTzInfo::utc(py).ok().as_deref(),
+        )
+    }
+
+    #[getter]
+    fn completed(&self) -> PyResult<usize> {
+        Ok(self.0.completed)
+    }
+
+    #[getter]
+    fn failed(&self) -> PyResult<usize> {
+        Ok(self.0.failed)
+    }
+
+    #[getter]
+    fn expired(&self) -> PyResult<usize> {
+        Ok(self.0.expired)
+    }
+
+    #[getter]
+    fn slow(&self) -> PyResult<usize> {
+        Ok(self.0.slow)
+    }
+
+    #[getter]
+    fn notifications_received(&self) -> PyResult<usize> {
+        Ok(self.0.notifications_received)
+    }
+
+    #[getter]
+    fn notifications_sent(&self) -> PyResult<usize> {
+        Ok(self.0.notifications_sent)
+    }
+
+    #[getter]
+    fn notifications_failed(&self) -> PyResult<usize> {
+        Ok(self.0.notifications_failed)
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        Ok(format!("{}", self.0))
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        self.__str__()
+    }
+
+    fn __copy__(&self) -> PyResult<DiagnosticsCounts> {
+        Ok(self.clone())
+    }
+
+    fn __deepcopy__(&self, _memo: Py<PyAny>) -> PyResult<DiagnosticsCounts> {
+        Ok(self.clone())
+    }
+}
+
+impl From<mod_diagnostics::DiagnosticsCounts> for DiagnosticsCounts {
+    fn from(counts: mod_diagnostics::DiagnosticsCounts) -> Self {
+        DiagnosticsCounts(counts)
+    }
+}
+
 ///
 /// Notification send/receive/failure totals for a single agent
 ///
@@ -640,214 +864,1001 @@ impl From<mod_diagnostics::NotificationStatistics> for NotificationStatistics {
     }
 }
 
-/// The DiagnosticsReport object returned from diagnostics requests
+/// A single job in a project's scheduler queue
 ///
 #[gen_stub_pyclass]
 #[pyclass(module = "openportal")]
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DiagnosticsReport(mod_diagnostics::DiagnosticsReport);
+pub struct QueuedJob(jobqueue::QueuedJob);
 
 #[gen_stub_pymethods]
 #[pymethods]
-impl DiagnosticsReport {
+impl QueuedJob {
     #[getter]
-    fn agent_name(&self) -> PyResult<String> {
-        Ok(self.0.agent_name.clone())
+    fn job_id(&self) -> PyResult<String> {
+        Ok(self.0.job_id.clone())
     }
 
     #[getter]
-    fn generated_at<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDateTime>> {
-        PyDateTime::from_timestamp(
-            py,
-            self.0.generated_at.timestamp() as f64,
-            PyTzInfo::utc(py).ok().as_deref(),
-        )
+    fn user(&self) -> PyResult<String> {
+        Ok(self.0.user.clone())
     }
 
     #[getter]
-    fn failed_jobs(&self) -> PyResult<Vec<FailedJobEntry>> {
-        Ok(self.0.failed_jobs.iter().cloned().map(Into::into).collect())
+    fn partition(&self) -> PyResult<String> {
+        Ok(self.0.partition.clone())
     }
 
     #[getter]
-    fn slowest_jobs(&self) -> PyResult<Vec<SlowJobEntry>> {
-        Ok(self
-            .0
-            .slowest_jobs
-            .iter()
-            .cloned()
-            .map(Into::into)
-            .collect())
+    fn state(&self) -> PyResult<String> {
+        Ok(self.0.state.clone())
     }
 
     #[getter]
-    fn expired_jobs(&self) -> PyResult<Vec<ExpiredJobEntry>> {
-        Ok(self
-            .0
-            .expired_jobs
-            .iter()
-            .cloned()
-            .map(Into::into)
-            .collect())
+    fn reason(&self) -> PyResult<String> {
+        Ok(self.0.reason.clone())
     }
 
     #[getter]
-    fn running_jobs(&self) -> PyResult<Vec<RunningJobEntry>> {
-        Ok(self
-            .0
-            .running_jobs
-            .iter()
-            .cloned()
-            .map(Into::into)
-            .collect())
+    fn nodes(&self) -> PyResult<u64> {
+        Ok(self.0.nodes)
     }
 
     #[getter]
-    fn warnings(&self) -> PyResult<Vec<String>> {
-        Ok(self.0.warnings.clone())
+    fn cpus(&self) -> PyResult<u64> {
+        Ok(self.0.cpus)
     }
 
     #[getter]
-    fn notification_statistics(&self) -> PyResult<NotificationStatistics> {
-        Ok(self.0.notification_statistics.clone().into())
+    fn gpus(&self) -> PyResult<u64> {
+        Ok(self.0.gpus)
     }
 
-    /// Return log entries in chronological order (oldest first).
-    /// `max=0` returns all. `level` filters by level ("INFO", "WARN+", etc.).
-    /// `search` does a case-insensitive substring match on the message.
-    #[pyo3(signature = (max=0, level=None, search=None))]
-    fn logs(
-        &self,
-        max: usize,
-        level: Option<String>,
-        search: Option<String>,
-    ) -> PyResult<Vec<LogEntry>> {
-        Ok(self
-            .0
-            .logs(max, level.as_deref(), search.as_deref())
-            .into_iter()
-            .map(Into::into)
-            .collect())
+    #[getter]
+    fn submit_time<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDateTime>> {
+        PyDateTime::from_timestamp(
+            py,
+            self.0.submit_time.timestamp() as f64,
+            PyTzInfo::utc(py).ok().as_deref(),
+        )
     }
 
-    fn __str__(&self) -> PyResult<String> {
-        Ok(self.0.to_pretty_string())
+    #[getter]
+    fn start_time<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyDateTime>>> {
+        match self.0.start_time {
+            Some(start_time) => Ok(Some(PyDateTime::from_timestamp(
+                py,
+                start_time.timestamp() as f64,
+                PyTzInfo::utc(py).ok().as_deref(),
+            )?)),
+            None => Ok(None),
+        }
     }
 
-    fn __repr__(&self) -> PyResult<String> {
-        self.__str__()
+    #[getter]
+    fn wait_seconds(&self) -> PyResult<u64> {
+        Ok(self.0.wait_seconds)
     }
 
-    fn __copy__(&self) -> PyResult<DiagnosticsReport> {
-        Ok(self.clone())
+    fn is_pending(&self) -> PyResult<bool> {
+        Ok(self.0.is_pending())
     }
 
-    fn __deepcopy__(&self, _memo: Py<PyAny>) -> PyResult<DiagnosticsReport> {
-        Ok(self.clone())
+    fn is_running(&self) -> PyResult<bool> {
+        Ok(self.0.is_running())
+    }
+
+    fn __copy__(&self) -> PyResult<QueuedJob> {
+        Ok(self.clone())
+    }
+
+    fn __deepcopy__(&self, _memo: Py<PyAny>) -> PyResult<QueuedJob> {
+        Ok(self.clone())
+    }
+}
+
+impl From<jobqueue::QueuedJob> for QueuedJob {
+    fn from(job: jobqueue::QueuedJob) -> Self {
+        QueuedJob(job)
+    }
+}
+
+/// The queued and running jobs of a project, returned from
+/// get_job_queue requests
+///
+#[gen_stub_pyclass]
+#[pyclass(module = "openportal")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobQueue(jobqueue::JobQueue);
+
+#[gen_stub_pymethods]
+#[pymethods]
+impl JobQueue {
+    fn __str__(&self) -> PyResult<String> {
+        Ok(self.0.to_string())
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        self.__str__()
+    }
+
+    #[getter]
+    fn project(&self) -> PyResult<ProjectIdentifier> {
+        Ok(self.0.project.clone().into())
+    }
+
+    #[getter]
+    fn generated_at<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDateTime>> {
+        PyDateTime::from_timestamp(
+            py,
+            self.0.generated_at.timestamp() as f64,
+            PyTzInfo::utc(py).ok().as_deref(),
+        )
+    }
+
+    #[getter]
+    fn jobs(&self) -> PyResult<Vec<QueuedJob>> {
+        Ok(self.0.jobs.iter().cloned().map(Into::into).collect())
+    }
+
+    #[getter]
+    fn num_pending(&self) -> PyResult<usize> {
+        Ok(self.0.num_pending())
+    }
+
+    #[getter]
+    fn num_running(&self) -> PyResult<usize> {
+        Ok(self.0.num_running())
+    }
+
+    fn user_jobs(&self, user: &str) -> PyResult<Vec<QueuedJob>> {
+        Ok(self.0.user_jobs(user).into_iter().map(Into::into).collect())
+    }
+
+    fn __copy__(&self) -> PyResult<JobQueue> {
+        Ok(self.clone())
+    }
+
+    fn __deepcopy__(&self, _memo: Py<PyAny>) -> PyResult<JobQueue> {
+        Ok(self.clone())
+    }
+}
+
+impl From<jobqueue::JobQueue> for JobQueue {
+    fn from(queue: jobqueue::JobQueue) -> Self {
+        JobQueue(queue)
+    }
+}
+
+/// The drift between a scheduler's accounts and the mappings of a
+/// project, returned from reconcile requests
+///
+#[gen_stub_pyclass]
+#[pyclass(module = "openportal")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReconciliationReport(reconcile::ReconciliationReport);
+
+#[gen_stub_pymethods]
+#[pymethods]
+impl ReconciliationReport {
+    fn __str__(&self) -> PyResult<String> {
+        Ok(self.0.to_string())
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        self.__str__()
+    }
+
+    #[getter]
+    fn project(&self) -> PyResult<ProjectIdentifier> {
+        Ok(self.0.project.clone().into())
+    }
+
+    #[getter]
+    fn generated_at<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDateTime>> {
+        PyDateTime::from_timestamp(
+            py,
+            self.0.generated_at.timestamp() as f64,
+            PyTzInfo::utc(py).ok().as_deref(),
+        )
+    }
+
+    #[getter]
+    fn missing_account(&self) -> PyResult<bool> {
+        Ok(self.0.missing_account)
+    }
+
+    #[getter]
+    fn missing_users(&self) -> PyResult<Vec<String>> {
+        Ok(self.0.missing_users.clone())
+    }
+
+    #[getter]
+    fn unexpected_users(&self) -> PyResult<Vec<String>> {
+        Ok(self.0.unexpected_users.clone())
+    }
+
+    #[getter]
+    fn wrong_homedirs(&self) -> PyResult<Vec<String>> {
+        Ok(self.0.wrong_homedirs.clone())
+    }
+
+    #[getter]
+    fn repaired(&self) -> PyResult<bool> {
+        Ok(self.0.repaired)
+    }
+
+    #[getter]
+    fn has_drift(&self) -> PyResult<bool> {
+        Ok(self.0.has_drift())
+    }
+
+    fn __copy__(&self) -> PyResult<ReconciliationReport> {
+        Ok(self.clone())
+    }
+
+    fn __deepcopy__(&self, _memo: Py<PyAny>) -> PyResult<ReconciliationReport> {
+        Ok(self.clone())
+    }
+}
+
+impl From<reconcile::ReconciliationReport> for ReconciliationReport {
+    fn from(report: reconcile::ReconciliationReport) -> Self {
+        ReconciliationReport(report)
+    }
+}
+
+/// The result of a batch instruction (e.g. add_users), with the mapping
+/// of each user for whom it succeeded, and the error of each user for
+/// whom it failed
+///
+#[gen_stub_pyclass]
+#[pyclass(module = "openportal")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchResult(batch::BatchResult);
+
+#[gen_stub_pymethods]
+#[pymethods]
+impl BatchResult {
+    fn __str__(&self) -> PyResult<String> {
+        Ok(self.0.to_string())
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        self.__str__()
+    }
+
+    #[getter]
+    fn succeeded(&self) -> PyResult<Vec<UserMapping>> {
+        Ok(self
+            .0
+            .succeeded
+            .iter()
+            .cloned()
+            .map(UserMapping::from)
+            .collect())
+    }
+
+    #[getter]
+    fn failed(&self) -> PyResult<Vec<(UserIdentifier, String)>> {
+        Ok(self
+            .0
+            .failed
+            .iter()
+            .map(|f| (UserIdentifier::from(f.user.clone()), f.error.clone()))
+            .collect())
+    }
+
+    #[getter]
+    fn is_complete(&self) -> PyResult<bool> {
+        Ok(self.0.is_complete())
+    }
+
+    fn __copy__(&self) -> PyResult<BatchResult> {
+        Ok(self.clone())
+    }
+
+    fn __deepcopy__(&self, _memo: Py<PyAny>) -> PyResult<BatchResult> {
+        Ok(self.clone())
+    }
+}
+
+impl From<batch::BatchResult> for BatchResult {
+    fn from(result: batch::BatchResult) -> Self {
+        BatchResult(result)
+    }
+}
+
+/// Whether or not a user or a project's group is protected in the
+/// account agent, and why, returned from get_user_protection and
+/// get_project_protection requests
+///
+#[gen_stub_pyclass]
+#[pyclass(module = "openportal")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtectionStatus(protection::ProtectionStatus);
+
+#[gen_stub_pymethods]
+#[pymethods]
+impl ProtectionStatus {
+    fn __str__(&self) -> PyResult<String> {
+        Ok(self.0.to_string())
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        self.__str__()
+    }
+
+    #[getter]
+    fn identity(&self) -> PyResult<String> {
+        Ok(self.0.identity.clone())
+    }
+
+    #[getter]
+    fn local_name(&self) -> PyResult<Option<String>> {
+        Ok(self.0.local_name.clone())
+    }
+
+    #[getter]
+    fn protected(&self) -> PyResult<bool> {
+        Ok(self.0.protected)
+    }
+
+    #[getter]
+    fn reason(&self) -> PyResult<Option<String>> {
+        Ok(self.0.reason.clone())
+    }
+
+    fn __copy__(&self) -> PyResult<ProtectionStatus> {
+        Ok(self.clone())
+    }
+
+    fn __deepcopy__(&self, _memo: Py<PyAny>) -> PyResult<ProtectionStatus> {
+        Ok(self.clone())
+    }
+}
+
+impl From<protection::ProtectionStatus> for ProtectionStatus {
+    fn from(status: protection::ProtectionStatus) -> Self {
+        ProtectionStatus(status)
+    }
+}
+
+/// The DiagnosticsReport object returned from diagnostics requests
+///
+#[gen_stub_pyclass]
+#[pyclass(module = "openportal")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiagnosticsReport(mod_diagnostics::DiagnosticsReport);
+
+#[gen_stub_pymethods]
+#[pymethods]
+impl DiagnosticsReport {
+    #[getter]
+    fn agent_name(&self) -> PyResult<String> {
+        Ok(self.0.agent_name.clone())
+    }
+
+    #[getter]
+    fn generated_at<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDateTime>> {
+        PyDateTime::from_timestamp(
+            py,
+            self.0.generated_at.timestamp() as f64,
+            PyTzInfo::utc(py).ok().as_deref(),
+        )
+    }
+
+    #[getter]
+    fn failed_jobs(&self) -> PyResult<Vec<FailedJobEntry>> {
+        Ok(self.0.failed_jobs.iter().cloned().map(Into::into).collect())
+    }
+
+    #[getter]
+    fn slowest_jobs(&self) -> PyResult<Vec<SlowJobEntry>> {
+        Ok(self
+            .0
+            .slowest_jobs
+            .iter()
+            .cloned()
+            .map(Into::into)
+            .collect())
+    }
+
+    #[getter]
+    fn expired_jobs(&self) -> PyResult<Vec<ExpiredJobEntry>> {
+        Ok(self
+            .0
+            .expired_jobs
+            .iter()
+            .cloned()
+            .map(Into::into)
+            .collect())
+    }
+
+    #[getter]
+    fn running_jobs(&self) -> PyResult<Vec<RunningJobEntry>> {
+        Ok(self
+            .0
+            .running_jobs
+            .iter()
+            .cloned()
+            .map(Into::into)
+            .collect())
+    }
+
+    #[getter]
+    fn warnings(&self) -> PyResult<Vec<String>> {
+        Ok(self.0.warnings.clone())
+    }
+
+    #[getter]
+    fn notification_statistics(&self) -> PyResult<NotificationStatistics> {
+        Ok(self.0.notification_statistics.clone().into())
+    }
+
+    #[getter]
+    fn watchdog_restarts(&self) -> PyResult<Vec<WatchdogRestart>> {
+        Ok(self
+            .0
+            .watchdog_restarts
+            .iter()
+            .cloned()
+            .map(Into::into)
+            .collect())
+    }
+
+    /// Return log entries in chronological order (oldest first).
+    /// `max=0` returns all. `level` filters by level ("INFO", "WARN+", etc.).
+    /// `search` does a case-insensitive substring match on the message.
+    #[pyo3(signature = (max=0, level=None, search=None))]
+    fn logs(
+        &self,
+        max: usize,
+        level: Option<String>,
+        search: Option<String>,
+    ) -> PyResult<Vec<LogEntry>> {
+        Ok(self
+            .0
+            .logs(max, level.as_deref(), search.as_deref())
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    /// Return the job and notification counts of each interval of the
+    /// history in chronological order (oldest first). `minutes=0` returns
+    /// the whole retained history.
+    #[pyo3(signature = (minutes=0))]
+    fn history(&self, minutes: u64) -> PyResult<Vec<DiagnosticsCounts>> {
+        Ok(self
+            .0
+            .history(minutes)
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        Ok(self.0.to_pretty_string())
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        self.__str__()
+    }
+
+    fn __copy__(&self) -> PyResult<DiagnosticsReport> {
+        Ok(self.clone())
+    }
+
+    fn __deepcopy__(&self, _memo: Py<PyAny>) -> PyResult<DiagnosticsReport> {
+        Ok(self.clone())
+    }
+}
+
+impl From<mod_diagnostics::DiagnosticsReport> for DiagnosticsReport {
+    fn from(diagnostics_report: mod_diagnostics::DiagnosticsReport) -> Self {
+        DiagnosticsReport(diagnostics_report)
+    }
+}
+
+///
+/// Return type for the diagnostics function
+///
+#[gen_stub_pyclass]
+#[pyclass(module = "openportal")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnostics {
+    pub status: String,
+    #[serde(default)]
+    #[serde(rename = "report")]
+    pub diagnostics: Option<DiagnosticsReport>,
+}
+
+#[gen_stub_pymethods]
+#[pymethods]
+impl Diagnostics {
+    #[getter]
+    fn status(&self) -> PyResult<String> {
+        Ok(self.status.clone())
+    }
+
+    #[getter]
+    fn detail(&self) -> PyResult<Option<DiagnosticsReport>> {
+        Ok(self.diagnostics.clone())
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        let mut s = format!("Diagnostics( status: {}", self.status);
+        if let Some(ref diagnostics) = self.diagnostics {
+            s.push_str(&format!(
+                ", detail:\n{}\n",
+                diagnostics.0.to_pretty_string()
+            ));
+        }
+        s.push_str(" )");
+        Ok(s)
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        self.__str__()
+    }
+
+    fn __copy__(&self) -> PyResult<Diagnostics> {
+        Ok(self.clone())
+    }
+
+    fn __deepcopy__(&self, _memo: Py<PyAny>) -> PyResult<Diagnostics> {
+        Ok(self.clone())
+    }
+
+    fn is_healthy(&self) -> PyResult<bool> {
+        Ok(self.status == "ok")
+    }
+
+    /// Return log entries from the contained report in chronological order (oldest first).
+    /// `max=0` returns all. `level` filters by level ("INFO", "WARN+", etc.).
+    /// `search` does a case-insensitive substring match on the message.
+    #[pyo3(signature = (max=0, level=None, search=None))]
+    fn logs(
+        &self,
+        max: usize,
+        level: Option<String>,
+        search: Option<String>,
+    ) -> PyResult<Vec<LogEntry>> {
+        match &self.diagnostics {
+            Some(report) => Ok(report
+                .0
+                .logs(max, level.as_deref(), search.as_deref())
+                .into_iter()
+                .map(Into::into)
+                .collect()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Return the job and notification counts of each interval of the
+    /// contained report's history in chronological order (oldest first).
+    /// `minutes=0` returns the whole retained history.
+    #[pyo3(signature = (minutes=0))]
+    fn history(&self, minutes: u64) -> PyResult<Vec<DiagnosticsCounts>> {
+        match &self.diagnostics {
+            Some(report) => Ok(report
+                .0
+                .history(minutes)
+                .into_iter()
+                .map(Into::into)
+                .collect()),
+            None => Ok(Vec::new()),
+        }
     }
 }
 
-impl From<mod_diagnostics::DiagnosticsReport> for DiagnosticsReport {
-    fn from(diagnostics_report: mod_diagnostics::DiagnosticsReport) -> Self {
-        DiagnosticsReport(diagnostics_report)
+///
+/// Fetch the diagnostics report from an agent in the OpenPortal system.
+///
+/// Parameters:
+/// - destination: Dot-separated path to the agent (e.g., "brics.aip2.clusters")
+///                Empty string means get the diagnostics from the bridge itself.
+///
+#[gen_stub_pyfunction]
+#[pyfunction]
+fn diagnostics(destination: &str) -> PyResult<Diagnostics> {
+    tracing::debug!("Calling /diagnostics with destination={}", destination);
+
+    let params = serde_json::json!({
+        "destination": destination,
+    });
+
+    match call_post::<Diagnostics>("diagnostics", params) {
+        Ok(response) => Ok(response),
+        Err(e) => Err(PyErr::new::<PyOSError, _>(format!("{:?}", e))),
     }
 }
 
 ///
-/// Return type for the diagnostics function
+/// Snapshot of every job on the board that an agent shares with one peer
 ///
 #[gen_stub_pyclass]
 #[pyclass(module = "openportal")]
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Diagnostics {
-    pub status: String,
+pub struct BoardSnapshot(mod_diagnostics::BoardSnapshot);
+
+#[gen_stub_pymethods]
+#[pymethods]
+impl BoardSnapshot {
+    #[getter]
+    fn peer(&self) -> PyResult<String> {
+        Ok(self.0.peer.clone())
+    }
+
+    #[getter]
+    fn zone(&self) -> PyResult<String> {
+        Ok(self.0.zone.clone())
+    }
+
+    #[getter]
+    fn jobs(&self) -> PyResult<Vec<Job>> {
+        Ok(self.0.jobs.iter().cloned().map(Into::into).collect())
+    }
+
+    #[getter]
+    fn queued(&self) -> PyResult<Vec<Job>> {
+        Ok(self.0.queued.iter().cloned().map(Into::into).collect())
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        Ok(format!(
+            "BoardSnapshot( peer: {}@{}, jobs: {}, queued: {} )",
+            self.0.peer,
+            self.0.zone,
+            self.0.jobs.len(),
+            self.0.queued.len()
+        ))
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        self.__str__()
+    }
+
+    fn __copy__(&self) -> PyResult<BoardSnapshot> {
+        Ok(self.clone())
+    }
+
+    fn __deepcopy__(&self, _memo: Py<PyAny>) -> PyResult<BoardSnapshot> {
+        Ok(self.clone())
+    }
+}
+
+impl From<mod_diagnostics::BoardSnapshot> for BoardSnapshot {
+    fn from(snapshot: mod_diagnostics::BoardSnapshot) -> Self {
+        BoardSnapshot(snapshot)
+    }
+}
+
+///
+/// The full contents of all of an agent's boards, returned from
+/// the dump_board function
+///
+#[gen_stub_pyclass]
+#[pyclass(module = "openportal")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BoardDump(mod_diagnostics::BoardDump);
+
+#[gen_stub_pymethods]
+#[pymethods]
+impl BoardDump {
+    #[getter]
+    fn agent_name(&self) -> PyResult<String> {
+        Ok(self.0.agent_name.clone())
+    }
+
+    #[getter]
+    fn generated_at<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDateTime>> {
+        PyDateTime::from_timestamp(
+            py,
+            self.0.generated_at.timestamp() as f64,
+            PyTzInfo::utc(py).ok().as_deref(),
+        )
+    }
+
+    #[getter]
+    fn boards(&self) -> PyResult<Vec<BoardSnapshot>> {
+        Ok(self.0.boards.iter().cloned().map(Into::into).collect())
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        Ok(self.0.to_pretty_string())
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        self.__str__()
+    }
+
+    fn __copy__(&self) -> PyResult<BoardDump> {
+        Ok(self.clone())
+    }
+
+    fn __deepcopy__(&self, _memo: Py<PyAny>) -> PyResult<BoardDump> {
+        Ok(self.clone())
+    }
+}
+
+impl From<mod_diagnostics::BoardDump> for BoardDump {
+    fn from(dump: mod_diagnostics::BoardDump) -> Self {
+        BoardDump(dump)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct BoardDumpResponse {
+    status: String,
     #[serde(default)]
-    #[serde(rename = "report")]
-    pub diagnostics: Option<DiagnosticsReport>,
+    dump: Option<BoardDump>,
+}
+
+///
+/// Fetch the full contents of the boards of an agent in the OpenPortal
+/// system, i.e. every pending, running and completed job together with
+/// its timestamps. This is useful for debugging stuck pipelines.
+///
+/// Parameters:
+/// - destination: Dot-separated path to the agent (e.g., "brics.aip2.clusters")
+///                Empty string means dump the boards of the bridge itself.
+///
+#[gen_stub_pyfunction]
+#[pyfunction]
+fn dump_board(destination: &str) -> PyResult<BoardDump> {
+    tracing::debug!("Calling /dump_board with destination={}", destination);
+
+    let params = serde_json::json!({
+        "destination": destination,
+    });
+
+    match call_post::<BoardDumpResponse>("dump_board", params) {
+        Ok(response) => match response.dump {
+            Some(dump) => Ok(dump),
+            None => Err(PyErr::new::<PyOSError, _>(format!(
+                "Could not dump the boards of '{}' (status: {})",
+                destination, response.status
+            ))),
+        },
+        Err(e) => Err(PyErr::new::<PyOSError, _>(format!("{:?}", e))),
+    }
+}
+
+///
+/// The status of an external service that an agent depends on
+///
+#[gen_stub_pyclass]
+#[pyclass(module = "openportal")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DependencyStatus(mod_health::DependencyStatus);
+
+#[gen_stub_pymethods]
+#[pymethods]
+impl DependencyStatus {
+    #[getter]
+    fn name(&self) -> PyResult<String> {
+        Ok(self.0.name.clone())
+    }
+
+    #[getter]
+    fn healthy(&self) -> PyResult<bool> {
+        Ok(self.0.healthy)
+    }
+
+    #[getter]
+    fn detail(&self) -> PyResult<String> {
+        Ok(self.0.detail.clone())
+    }
+
+    #[getter]
+    fn last_checked<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDateTime>> {
+        PyDateTime::from_timestamp(
+            py,
+            self.0.last_checked.timestamp() as f64,
+            PyTzInfo::utc(py).ok().as_deref(),
+        )
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        let status = match self.0.healthy {
+            true => "ok",
+            false => "FAILED",
+        };
+
+        Ok(format!("{}: {} ({})", self.0.name, status, self.0.detail))
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        self.__str__()
+    }
+
+    fn __copy__(&self) -> PyResult<DependencyStatus> {
+        Ok(self.clone())
+    }
+
+    fn __deepcopy__(&self, _memo: Py<PyAny>) -> PyResult<DependencyStatus> {
+        Ok(self.clone())
+    }
+}
+
+impl From<mod_health::DependencyStatus> for DependencyStatus {
+    fn from(status: mod_health::DependencyStatus) -> Self {
+        DependencyStatus(status)
+    }
+}
+
+///
+/// The state of a circuit breaker protecting calls to a third-party
+/// service
+///
+#[gen_stub_pyclass]
+#[pyclass(module = "openportal")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BreakerStatus(mod_health::BreakerStatus);
+
+#[gen_stub_pymethods]
+#[pymethods]
+impl BreakerStatus {
+    #[getter]
+    fn name(&self) -> PyResult<String> {
+        Ok(self.0.name.clone())
+    }
+
+    #[getter]
+    fn state(&self) -> PyResult<String> {
+        Ok(self.0.state.clone())
+    }
+
+    #[getter]
+    fn failures(&self) -> PyResult<u32> {
+        Ok(self.0.failures)
+    }
+
+    #[getter]
+    fn last_error(&self) -> PyResult<String> {
+        Ok(self.0.last_error.clone())
+    }
+
+    #[getter]
+    fn opened_at<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyDateTime>>> {
+        match self.0.opened_at {
+            Some(opened_at) => Ok(Some(PyDateTime::from_timestamp(
+                py,
+                opened_at.timestamp() as f64,
+                PyTzInfo::utc(py).ok().as_deref(),
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    #[getter]
+    fn rejected(&self) -> PyResult<u64> {
+        Ok(self.0.rejected)
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        Ok(format!(
+            "{}: {} ({} failures, {} rejected calls)",
+            self.0.name, self.0.state, self.0.failures, self.0.rejected
+        ))
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        self.__str__()
+    }
+
+    fn __copy__(&self) -> PyResult<BreakerStatus> {
+        Ok(self.clone())
+    }
+
+    fn __deepcopy__(&self, _memo: Py<PyAny>) -> PyResult<BreakerStatus> {
+        Ok(self.clone())
+    }
+}
+
+impl From<mod_health::BreakerStatus> for BreakerStatus {
+    fn from(status: mod_health::BreakerStatus) -> Self {
+        BreakerStatus(status)
+    }
 }
 
-#[gen_stub_pymethods]
-#[pymethods]
-impl Diagnostics {
+///
+/// The connection availability of a peer of an agent over rolling
+/// 24 hour, 7 day and 30 day windows
+///
+#[gen_stub_pyclass]
+#[pyclass(module = "openportal")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerAvailability(mod_health::PeerAvailability);
+
+#[gen_stub_pymethods]
+#[pymethods]
+impl PeerAvailability {
+    #[getter]
+    fn peer(&self) -> PyResult<String> {
+        Ok(self.0.peer.clone())
+    }
+
+    #[getter]
+    fn connected(&self) -> PyResult<bool> {
+        Ok(self.0.connected)
+    }
+
+    #[getter]
+    fn tracked_since<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDateTime>> {
+        PyDateTime::from_timestamp(
+            py,
+            self.0.tracked_since.timestamp() as f64,
+            PyTzInfo::utc(py).ok().as_deref(),
+        )
+    }
+
+    #[getter]
+    fn connected_since<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyDateTime>>> {
+        match self.0.connected_since {
+            Some(connected_since) => Ok(Some(PyDateTime::from_timestamp(
+                py,
+                connected_since.timestamp() as f64,
+                PyTzInfo::utc(py).ok().as_deref(),
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    #[getter]
+    fn disconnections_30d(&self) -> PyResult<usize> {
+        Ok(self.0.disconnections_30d)
+    }
+
+    #[getter]
+    fn availability_24h(&self) -> PyResult<f64> {
+        Ok(self.0.availability_24h)
+    }
+
     #[getter]
-    fn status(&self) -> PyResult<String> {
-        Ok(self.status.clone())
+    fn availability_7d(&self) -> PyResult<f64> {
+        Ok(self.0.availability_7d)
     }
 
     #[getter]
-    fn detail(&self) -> PyResult<Option<DiagnosticsReport>> {
-        Ok(self.diagnostics.clone())
+    fn availability_30d(&self) -> PyResult<f64> {
+        Ok(self.0.availability_30d)
     }
 
     fn __str__(&self) -> PyResult<String> {
-        let mut s = format!("Diagnostics( status: {}", self.status);
-        if let Some(ref diagnostics) = self.diagnostics {
-            s.push_str(&format!(
-                ", detail:\n{}\n",
-                diagnostics.0.to_pretty_string()
-            ));
-        }
-        s.push_str(" )");
-        Ok(s)
+        let status = match self.0.connected {
+            true => "up",
+            false => "DOWN",
+        };
+
+        Ok(format!(
+            "{}: {} (24h {:.2}%, 7d {:.2}%, 30d {:.2}%)",
+            self.0.peer,
+            status,
+            self.0.availability_24h,
+            self.0.availability_7d,
+            self.0.availability_30d
+        ))
     }
 
     fn __repr__(&self) -> PyResult<String> {
         self.__str__()
     }
 
-    fn __copy__(&self) -> PyResult<Diagnostics> {
+    fn __copy__(&self) -> PyResult<PeerAvailability> {
         Ok(self.clone())
     }
 
-    fn __deepcopy__(&self, _memo: Py<PyAny>) -> PyResult<Diagnostics> {
+    fn __deepcopy__(&self, _memo: Py<PyAny>) -> PyResult<PeerAvailability> {
         Ok(self.clone())
     }
-
-    fn is_healthy(&self) -> PyResult<bool> {
-        Ok(self.status == "ok")
-    }
-
-    /// Return log entries from the contained report in chronological order (oldest first).
-    /// `max=0` returns all. `level` filters by level ("INFO", "WARN+", etc.).
-    /// `search` does a case-insensitive substring match on the message.
-    #[pyo3(signature = (max=0, level=None, search=None))]
-    fn logs(
-        &self,
-        max: usize,
-        level: Option<String>,
-        search: Option<String>,
-    ) -> PyResult<Vec<LogEntry>> {
-        match &self.diagnostics {
-            Some(report) => Ok(report
-                .0
-                .logs(max, level.as_deref(), search.as_deref())
-                .into_iter()
-                .map(Into::into)
-                .collect()),
-            None => Ok(Vec::new()),
-        }
-    }
 }
 
-///
-/// Fetch the diagnostics report from an agent in the OpenPortal system.
-///
-/// Parameters:
-/// - destination: Dot-separated path to the agent (e.g., "brics.aip2.clusters")
-///                Empty string means get the diagnostics from the bridge itself.
-///
-#[gen_stub_pyfunction]
-#[pyfunction]
-fn diagnostics(destination: &str) -> PyResult<Diagnostics> {
-    tracing::debug!("Calling /diagnostics with destination={}", destination);
-
-    let params = serde_json::json!({
-        "destination": destination,
-    });
-
-    match call_post::<Diagnostics>("diagnostics", params) {
-        Ok(response) => Ok(response),
-        Err(e) => Err(PyErr::new::<PyOSError, _>(format!("{:?}", e))),
+impl From<mod_health::PeerAvailability> for PeerAvailability {
+    fn from(availability: mod_health::PeerAvailability) -> Self {
+        PeerAvailability(availability)
     }
 }
 
@@ -932,6 +1943,11 @@ impl HealthInfo {
         Ok(self.0.worker_count as u64)
     }
 
+    #[getter]
+    fn replays_rejected(&self) -> PyResult<u64> {
+        Ok(self.0.replays_rejected)
+    }
+
     #[getter]
     fn memory_bytes(&self) -> PyResult<u64> {
         Ok(self.0.memory_bytes)
@@ -1030,6 +2046,11 @@ impl HealthInfo {
         Ok(self.0.version.clone())
     }
 
+    #[getter]
+    fn crypto_mode(&self) -> PyResult<String> {
+        Ok(self.0.crypto_mode.clone())
+    }
+
     #[getter]
     fn last_updated<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDateTime>> {
         PyDateTime::from_timestamp(
@@ -1039,6 +2060,38 @@ impl HealthInfo {
         )
     }
 
+    #[getter]
+    fn warnings(&self) -> PyResult<Vec<String>> {
+        Ok(self.0.warnings.clone())
+    }
+
+    #[getter]
+    fn dependencies(&self) -> PyResult<Vec<DependencyStatus>> {
+        Ok(self
+            .0
+            .dependencies
+            .iter()
+            .cloned()
+            .map(Into::into)
+            .collect())
+    }
+
+    #[getter]
+    fn breakers(&self) -> PyResult<Vec<BreakerStatus>> {
+        Ok(self.0.breakers.iter().cloned().map(Into::into).collect())
+    }
+
+    #[getter]
+    fn availability(&self) -> PyResult<Vec<PeerAvailability>> {
+        Ok(self
+            .0
+            .availability
+            .iter()
+            .cloned()
+            .map(Into::into)
+            .collect())
+    }
+
     #[getter]
     fn x(&self) -> PyResult<HealthInfo> {
         // return a copy that has any children removed. This
@@ -1232,7 +2285,10 @@ impl RestartResponse {
 /// Restart an agent in the OpenPortal system.
 ///
 /// Parameters:
-/// - restart_type: Type of restart ("soft", "hard", etc.)
+/// - restart_type: Type of restart ("soft", "hard" or "reload"). Add
+///                 "@HH:MM" to drain and restart at that local time, or
+///                 "@now" to ignore the agent's maintenance windows.
+///                 "cancel" cancels a scheduled restart
 /// - destination: Dot-separated path to the agent (e.g., "brics.aip2.clusters")
 ///                Empty string means restart the bridge itself
 ///
@@ -1328,6 +2384,16 @@ impl Job {
         Ok(self.0.forwarded_for().map(|d| d.into()))
     }
 
+    #[getter]
+    fn is_dry_run(&self) -> PyResult<bool> {
+        Ok(self.0.is_dry_run())
+    }
+
+    #[getter]
+    fn changes(&self) -> PyResult<Vec<String>> {
+        Ok(self.0.changes().to_vec())
+    }
+
     #[getter]
     fn instruction(&self) -> PyResult<Instruction> {
         Ok(self.0.instruction().into())
@@ -1465,6 +2531,9 @@ impl Job {
         try_extract!(ProjectUsageReport, |v: ProjectUsageReport| v.0.clone());
         try_extract!(ProjectStorageReport, |v: ProjectStorageReport| v.0.clone());
         try_extract!(StorageReport, |v: StorageReport| v.0.clone());
+        try_extract!(JobQueue, |v: JobQueue| v.0.clone());
+        try_extract!(ReconciliationReport, |v: ReconciliationReport| v.0.clone());
+        try_extract!(BatchResult, |v: BatchResult| v.0.clone());
         try_extract!(Usage, |v: Usage| v.0);
         try_extract!(DateRange, |v: DateRange| v.0.clone());
         try_extract!(ProjectTemplate, |v: ProjectTemplate| v.0.clone());
@@ -1682,6 +2751,54 @@ impl Job {
                     None => Ok(py.None().into_bound(py)),
                 }
             }
+            "JobQueue" => {
+                let result = match self.0.result::<jobqueue::JobQueue>() {
+                    Ok(result) => result,
+                    Err(e) => return Err(PyErr::new::<PyOSError, _>(format!("{:?}", e))),
+                };
+
+                match result {
+                    Some(result) => Ok(JobQueue::from(result).into_pyobject(py)?.into_any()),
+                    None => Ok(py.None().into_bound(py)),
+                }
+            }
+            "ReconciliationReport" => {
+                let result = match self.0.result::<reconcile::ReconciliationReport>() {
+                    Ok(result) => result,
+                    Err(e) => return Err(PyErr::new::<PyOSError, _>(format!("{:?}", e))),
+                };
+
+                match result {
+                    Some(result) => Ok(ReconciliationReport::from(result)
+                        .into_pyobject(py)?
+                        .into_any()),
+                    None => Ok(py.None().into_bound(py)),
+                }
+            }
+            "BatchResult" => {
+                let result = match self.0.result::<batch::BatchResult>() {
+                    Ok(result) => result,
+                    Err(e) => return Err(PyErr::new::<PyOSError, _>(format!("{:?}", e))),
+                };
+
+                match result {
+                    Some(result) => Ok(BatchResult::from(result).into_pyobject(py)?.into_any()),
+                    None => Ok(py.None().into_bound(py)),
+                }
+            }
+            "ProtectionStatus" => {
+                let result = match self.0.result::<protection::ProtectionStatus>() {
+                    Ok(result) => result,
+                    Err(e) => return Err(PyErr::new::<PyOSError, _>(format!("{:?}", e))),
+                };
+
+                match result {
+                    Some(result) => {
+                        Ok(ProtectionStatus::from(result).into_pyobject(py)?.into_any())
+                    }
+                    None => Ok(py.None().into_bound(py)),
+                }
+            }
             "StorageReport" => {
                 let result = match self.0.result::<storagereport::StorageReport>() {
                     Ok(result) => result,
@@ -1754,6 +2871,23 @@ impl Job {
                     None => Ok(py.None().into_bound(py)),
                 }
             }
+            "Vec<Node>" => {
+                let result = match self.0.result::<Vec<grammar::Node>>() {
+                    Ok(result) => result,
+                    Err(e) => return Err(PyErr::new::<PyOSError, _>(format!("{:?}", e))),
+                };
+
+                match result {
+                    Some(result) => {
+                        let list = PyList::empty(py);
+                        for item in result {
+                            list.append(Node::from(item).into_pyobject(py)?)?;
+                        }
+                        Ok(list.into_any())
+                    }
+                    None => Ok(py.None().into_bound(py)),
+                }
+            }
             "Vec<UserIdentifier>" => {
                 let result = match self.0.result::<Vec<grammar::UserIdentifier>>() {
                     Ok(result) => result,
@@ -2789,6 +3923,15 @@ impl UsageReport {
         Ok(self.0.get_component(component).into())
     }
 
+    #[getter]
+    fn partitions(&self) -> PyResult<Vec<String>> {
+        Ok(self.0.partitions())
+    }
+
+    fn get_partition(&self, partition: &str) -> PyResult<UsageReport> {
+        Ok(self.0.get_partition(partition).into())
+    }
+
     fn remap_portal(&mut self, new_portal: &PortalIdentifier) -> PyResult<()> {
         self.0
             .remap_portal(&new_portal.0)
@@ -2981,6 +4124,41 @@ impl ProjectUsageReport {
         Ok(self.0.total_usage().into())
     }
 
+    #[getter]
+    fn gpu_usage(&self) -> PyResult<Usage> {
+        Ok(self.0.gpu_usage().into())
+    }
+
+    fn user_gpu_usage(&self, user: &UserIdentifier) -> PyResult<Usage> {
+        Ok(self.0.user_gpu_usage(&user.0).into())
+    }
+
+    #[getter]
+    fn licenses(&self) -> PyResult<Vec<String>> {
+        Ok(self.0.licenses())
+    }
+
+    fn license_usage(&self, feature: &str) -> PyResult<Usage> {
+        Ok(self.0.license_usage(feature).into())
+    }
+
+    fn user_license_usage(&self, feature: &str, user: &UserIdentifier) -> PyResult<Usage> {
+        Ok(self.0.user_license_usage(feature, &user.0).into())
+    }
+
+    #[getter]
+    fn energy_kwh(&self) -> PyResult<f64> {
+        Ok(self.0.energy_kwh())
+    }
+
+    fn user_energy_kwh(&self, user: &UserIdentifier) -> PyResult<f64> {
+        Ok(self.0.user_energy_kwh(&user.0))
+    }
+
+    fn carbon_kg(&self, grams_per_kwh: f64) -> PyResult<f64> {
+        Ok(self.0.carbon_kg(grams_per_kwh))
+    }
+
     #[getter]
     fn num_jobs(&self) -> PyResult<u64> {
         Ok(self.0.num_jobs())
@@ -3018,6 +4196,19 @@ impl ProjectUsageReport {
         Ok(self.0.get_component(component).into())
     }
 
+    #[getter]
+    fn partitions(&self) -> PyResult<Vec<String>> {
+        Ok(self.0.partitions())
+    }
+
+    fn partition_usage(&self, partition: &str) -> PyResult<Usage> {
+        Ok(self.0.partition_usage(partition).into())
+    }
+
+    fn get_partition(&self, partition: &str) -> PyResult<ProjectUsageReport> {
+        Ok(self.0.get_partition(partition).into())
+    }
+
     #[staticmethod]
     fn combine(reports: Py<PyAny>, py: Python) -> PyResult<Self> {
         let reports: Vec<ProjectUsageReport> = reports.extract(py)?;
@@ -3627,6 +4818,42 @@ impl DailyProjectUsageReport {
         Ok(self.0.get_component(component).into())
     }
 
+    #[getter]
+    fn partitions(&self) -> PyResult<Vec<String>> {
+        Ok(self.0.partitions())
+    }
+
+    fn partition_usage(&self, partition: &str) -> PyResult<Usage> {
+        Ok(self.0.partition_usage(partition).into())
+    }
+
+    #[getter]
+    fn licenses(&self) -> PyResult<Vec<String>> {
+        Ok(self.0.licenses())
+    }
+
+    fn license_usage(&self, feature: &str) -> PyResult<Usage> {
+        Ok(self.0.license_usage(feature).into())
+    }
+
+    fn user_license_usage(&self, feature: &str, user: &str) -> PyResult<Usage> {
+        Ok(self.0.user_license_usage(feature, user).into())
+    }
+
+    #[getter]
+    fn energy_kwh(&self) -> PyResult<f64> {
+        Ok(self.0.energy_kwh())
+    }
+
+    fn add_energy(&mut self, user: &str, joules: u64) -> PyResult<()> {
+        self.0.add_energy(user, joules);
+        Ok(())
+    }
+
+    fn get_partition(&self, partition: &str) -> PyResult<DailyProjectUsageReport> {
+        Ok(self.0.get_partition(partition).into())
+    }
+
     #[getter]
     fn is_complete(&self) -> PyResult<bool> {
         Ok(self.0.is_complete())
@@ -5194,11 +6421,18 @@ impl From<grammar::ProjectDetails> for AwardDetails {
 /// milliseconds to wait as 'max_ms', or a negative number if you want
 /// to wait indefinitely.
 ///
+/// Pass 'dry_run=True' to rehearse the command. No agent will make
+/// any changes, and the changes that they would have made are
+/// returned in the job's 'changes' once it has finished.
+///
 #[gen_stub_pyfunction]
 #[pyfunction]
-#[pyo3(signature = (command, max_ms=0))]
-fn run(command: String, max_ms: i64) -> PyResult<Job> {
-    let mut job: Job = match call_post::<job::Job>("run", serde_json::json!({"command": command})) {
+#[pyo3(signature = (command, max_ms=0, dry_run=false))]
+fn run(command: String, max_ms: i64, dry_run: bool) -> PyResult<Job> {
+    let mut job: Job = match call_post::<job::Job>(
+        "run",
+        serde_json::json!({"command": command, "dry_run": dry_run}),
+    ) {
         Ok(response) => response.into(),
         Err(e) => return Err(PyErr::new::<PyOSError, _>(format!("{:?}", e))),
     };
@@ -5458,6 +6692,61 @@ fn fetch_notification(py: Python<'_>, notification_id: Py<PyAny>) -> PyResult<No
     }
 }
 
+///
+/// Verify a signal received from the bridge on the `signal_url` or
+/// `notification_url` endpoint.
+///
+/// Pass the `job_id` (or `notification_id`), `timestamp` and `signature`
+/// query parameters exactly as they were received, and any other query
+/// parameters (e.g. `event` and `instruction`) in `params`. Returns `True`
+/// only if the signature was made with the bridge key from the loaded
+/// config over all of these, and the timestamp is within `max_age_seconds`
+/// of now. Signals that fail verification should be ignored.
+///
+#[gen_stub_pyfunction]
+#[pyfunction]
+#[pyo3(signature = (signal_id, timestamp, signature, max_age_seconds=60, params=None))]
+fn verify_signal(
+    signal_id: &str,
+    timestamp: &str,
+    signature: &str,
+    max_age_seconds: i64,
+    params: Option<HashMap<String, String>>,
+) -> PyResult<bool> {
+    let config = get_config().map_err(|e| PyErr::new::<PyOSError, _>(format!("{:?}", e)))?;
+
+    let Ok(uid) = uuid::Uuid::parse_str(signal_id) else {
+        tracing::warn!("Signal id is not a valid Uuid: {}", signal_id);
+        return Ok(false);
+    };
+
+    let Ok(timestamp) = timestamp.parse::<i64>() else {
+        tracing::warn!("Signal timestamp is not valid: {}", timestamp);
+        return Ok(false);
+    };
+
+    let params = params.unwrap_or_default();
+    let params: Vec<(&str, &str)> = params
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+
+    match server::verify_signal(
+        &config.key,
+        timestamp,
+        &uid,
+        &params,
+        signature,
+        max_age_seconds,
+    ) {
+        Ok(()) => Ok(true),
+        Err(e) => {
+            tracing::warn!("Signal verification failed: {}", e);
+            Ok(false)
+        }
+    }
+}
+
 #[gen_stub_pyfunction]
 #[pyfunction]
 fn add_offerings(offerings: Vec<Destination>) -> PyResult<Vec<Destination>> {
@@ -5509,6 +6798,58 @@ fn sync_offerings(offerings: Vec<Destination>) -> PyResult<Vec<Destination>> {
     }
 }
 
+///
+/// Return the IP addresses and CIDR ranges allowed to call the bridge.
+/// An empty list means that every address is allowed
+///
+#[gen_stub_pyfunction]
+#[pyfunction]
+fn get_allowed_ips() -> PyResult<Vec<String>> {
+    match call_get::<Vec<String>>("get_allowed_ips") {
+        Ok(allowed_ips) => Ok(allowed_ips),
+        Err(e) => Err(PyErr::new::<PyOSError, _>(format!("{:?}", e))),
+    }
+}
+
+///
+/// Replace the IP addresses and CIDR ranges allowed to call the bridge,
+/// returning the new list. The list must include the caller
+///
+#[gen_stub_pyfunction]
+#[pyfunction]
+fn sync_allowed_ips(allowed_ips: Vec<String>) -> PyResult<Vec<String>> {
+    match call_post::<Vec<String>>("sync_allowed_ips", serde_json::json!(allowed_ips)) {
+        Ok(allowed_ips) => Ok(allowed_ips),
+        Err(e) => Err(PyErr::new::<PyOSError, _>(format!("{:?}", e))),
+    }
+}
+
+///
+/// Add IP addresses or CIDR ranges to those allowed to call the bridge,
+/// returning the new list
+///
+#[gen_stub_pyfunction]
+#[pyfunction]
+fn add_allowed_ips(allowed_ips: Vec<String>) -> PyResult<Vec<String>> {
+    match call_post::<Vec<String>>("add_allowed_ips", serde_json::json!(allowed_ips)) {
+        Ok(allowed_ips) => Ok(allowed_ips),
+        Err(e) => Err(PyErr::new::<PyOSError, _>(format!("{:?}", e))),
+    }
+}
+
+///
+/// Remove IP addresses or CIDR ranges from those allowed to call the
+/// bridge, returning the new list
+///
+#[gen_stub_pyfunction]
+#[pyfunction]
+fn remove_allowed_ips(allowed_ips: Vec<String>) -> PyResult<Vec<String>> {
+    match call_post::<Vec<String>>("remove_allowed_ips", serde_json::json!(allowed_ips)) {
+        Ok(allowed_ips) => Ok(allowed_ips),
+        Err(e) => Err(PyErr::new::<PyOSError, _>(format!("{:?}", e))),
+    }
+}
+
 // ============================================================================
 // Storage type wrappers
 // ============================================================================
@@ -6011,35 +7352,55 @@ fn send_result(job: Job) -> PyResult<()> {
 
 #[pymodule]
 fn openportal(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(add_allowed_ips, m)?)?;
     m.add_function(wrap_pyfunction!(add_offerings, m)?)?;
     m.add_function(wrap_pyfunction!(load_config, m)?)?;
+    m.add_function(wrap_pyfunction!(crypto_mode, m)?)?;
     m.add_function(wrap_pyfunction!(fetch_job, m)?)?;
     m.add_function(wrap_pyfunction!(fetch_jobs, m)?)?;
     m.add_function(wrap_pyfunction!(fetch_notification, m)?)?;
     m.add_function(wrap_pyfunction!(get, m)?)?;
+    m.add_function(wrap_pyfunction!(get_allowed_ips, m)?)?;
     m.add_function(wrap_pyfunction!(get_offerings, m)?)?;
     m.add_function(wrap_pyfunction!(get_portal, m)?)?;
     m.add_function(wrap_pyfunction!(diagnostics, m)?)?;
+    m.add_function(wrap_pyfunction!(dump_board, m)?)?;
     m.add_function(wrap_pyfunction!(health, m)?)?;
     m.add_function(wrap_pyfunction!(is_config_loaded, m)?)?;
     m.add_function(wrap_pyfunction!(initialize_tracing, m)?)?;
+    m.add_function(wrap_pyfunction!(remove_allowed_ips, m)?)?;
     m.add_function(wrap_pyfunction!(remove_offerings, m)?)?;
     m.add_function(wrap_pyfunction!(restart, m)?)?;
     m.add_function(wrap_pyfunction!(notify, m)?)?;
     m.add_function(wrap_pyfunction!(run, m)?)?;
     m.add_function(wrap_pyfunction!(send_result, m)?)?;
+    m.add_function(wrap_pyfunction!(set_client_certificate, m)?)?;
     m.add_function(wrap_pyfunction!(status, m)?)?;
+    m.add_function(wrap_pyfunction!(sync_allowed_ips, m)?)?;
     m.add_function(wrap_pyfunction!(sync_offerings, m)?)?;
+    m.add_function(wrap_pyfunction!(verify_signal, m)?)?;
 
     m.add_class::<Health>()?;
+    m.add_class::<DependencyStatus>()?;
+    m.add_class::<BreakerStatus>()?;
+    m.add_class::<PeerAvailability>()?;
     m.add_class::<RestartResponse>()?;
     m.add_class::<Diagnostics>()?;
     m.add_class::<DiagnosticsReport>()?;
     m.add_class::<NotificationStatistics>()?;
+    m.add_class::<DiagnosticsCounts>()?;
+    m.add_class::<WatchdogRestart>()?;
+    m.add_class::<BoardDump>()?;
+    m.add_class::<BoardSnapshot>()?;
     m.add_class::<FailedJobEntry>()?;
     m.add_class::<SlowJobEntry>()?;
     m.add_class::<ExpiredJobEntry>()?;
     m.add_class::<RunningJobEntry>()?;
+    m.add_class::<QueuedJob>()?;
+    m.add_class::<JobQueue>()?;
+    m.add_class::<ReconciliationReport>()?;
+    m.add_class::<BatchResult>()?;
+    m.add_class::<ProtectionStatus>()?;
     m.add_class::<Job>()?;
     m.add_class::<Notification>()?;
     m.add_class::<UserIdentifier>()?;
@@ -6073,6 +7434,8 @@ fn openportal(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<Quota>()?;
     m.add_class::<Volume>()?;
 
+    aio::register(m)?;
+
     Ok(())
 }
 
@@ -0,0 +1,184 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Building the signed requests that are made to the bridge, and
+//! decoding its responses. These are shared by the blocking functions
+//! of the `openportal` module and the asyncio-compatible functions of
+//! `openportal.aio`, which only differ in how the requests are sent.
+
+use anyhow::Context;
+use chrono::Utc;
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+use templemeads::server::sign_api_call;
+use templemeads::Error;
+use url::Url;
+
+use crate::BridgeConfig;
+
+// Retry logic with exponential backoff for rate limiting
+pub(crate) const MAX_RETRIES: u32 = 5;
+const INITIAL_BACKOFF_MS: u64 = 100;
+
+///
+/// A request to call a function on the bridge, signed with the bridge
+/// key. This is signed when it is created, so a new request must be
+/// created for every attempt (each has its own date and nonce)
+///
+pub(crate) struct SignedRequest {
+    method: Method,
+    url: Url,
+    headers: HeaderMap,
+    body: Option<Vec<u8>>,
+}
+
+fn header(value: &str) -> Result<HeaderValue, Error> {
+    Ok(HeaderValue::from_str(value).context("Could not create request header")?)
+}
+
+impl SignedRequest {
+    ///
+    /// Create a request to call `function`. This is a POST of `body`
+    /// (the JSON-encoded arguments) if there is one, or else a GET
+    ///
+    pub(crate) fn new(
+        config: &BridgeConfig,
+        function: &str,
+        body: Option<&[u8]>,
+    ) -> Result<Self, Error> {
+        let date = Utc::now();
+
+        let mut url = config.url.join(function).context("Could not join URL")?;
+        url.query_pairs_mut()
+            .append_pair("openportal-version", "0.1");
+
+        let (method, protocol) = match body {
+            Some(_) => (Method::POST, "post"),
+            None => (Method::GET, "get"),
+        };
+
+        // Generate a unique nonce for replay attack prevention
+        let nonce = uuid::Uuid::new_v4().to_string();
+
+        // Sign the exact bytes we're about to send (an empty slice for
+        // GET requests, which have no body)
+        let auth_token = sign_api_call(
+            &config.key,
+            &date,
+            protocol,
+            function,
+            body.unwrap_or_default(),
+            Some(&nonce),
+        )?;
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Accept", header("application/json")?);
+
+        if body.is_some() {
+            headers.insert("Content-Type", header("application/json")?);
+        }
+
+        headers.insert("Authorization", header(&auth_token)?);
+        headers.insert(
+            "Date",
+            header(&date.format("%a, %d %b %Y %H:%M:%S GMT").to_string())?,
+        );
+        headers.insert("X-Nonce", header(&nonce)?);
+
+        Ok(SignedRequest {
+            method,
+            url,
+            headers,
+            body: body.map(|body| body.to_vec()),
+        })
+    }
+
+    ///
+    /// Return the request, ready to be sent by the passed blocking client
+    ///
+    pub(crate) fn blocking(
+        self,
+        client: &reqwest::blocking::Client,
+    ) -> reqwest::blocking::RequestBuilder {
+        let request = client.request(self.method, self.url).headers(self.headers);
+
+        match self.body {
+            Some(body) => request.body(body),
+            None => request,
+        }
+    }
+
+    ///
+    /// Return the request, ready to be sent by the passed async client
+    ///
+    pub(crate) fn nonblocking(self, client: &reqwest::Client) -> reqwest::RequestBuilder {
+        let request = client.request(self.method, self.url).headers(self.headers);
+
+        match self.body {
+            Some(body) => request.body(body),
+            None => request,
+        }
+    }
+}
+
+///
+/// What to do with the response to an attempt to call a function
+///
+pub(crate) enum Reply<T> {
+    /// The function returned this value
+    Done(T),
+    /// The bridge is rate limiting calls, so wait this long and try again
+    Retry(Duration),
+}
+
+///
+/// Decode the response (with status `status` and body `body`) to the
+/// passed attempt to call `function`
+///
+pub(crate) fn decode<T>(
+    function: &str,
+    attempt: u32,
+    status: StatusCode,
+    body: &[u8],
+) -> Result<Reply<T>, Error>
+where
+    T: DeserializeOwned,
+{
+    tracing::debug!("Response: {} ({} bytes)", status, body.len());
+
+    if status.is_success() {
+        Ok(Reply::Done(
+            serde_json::from_slice::<T>(body).context("Could not decode from json")?,
+        ))
+    } else if status == StatusCode::TOO_MANY_REQUESTS && attempt < MAX_RETRIES {
+        // Rate limited - backoff and retry
+        let backoff_ms = INITIAL_BACKOFF_MS * 2_u64.pow(attempt);
+        tracing::warn!(
+            "Rate limited on attempt {} for function: {}. Backing off for {}ms",
+            attempt + 1,
+            function,
+            backoff_ms
+        );
+        Ok(Reply::Retry(Duration::from_millis(backoff_ms)))
+    } else {
+        Err(Error::Call(format!(
+            "Could not get response for function: {}. Status: {}. Response: {}",
+            function,
+            status,
+            String::from_utf8_lossy(body)
+        )))
+    }
+}
+
+///
+/// Return the error for when every attempt to call `function` was
+/// rate limited
+///
+pub(crate) fn retries_exhausted(function: &str) -> Error {
+    Error::Call(format!(
+        "Exceeded maximum retries ({}) for function: {} due to rate limiting",
+        MAX_RETRIES, function
+    ))
+}
@@ -0,0 +1,468 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+use chrono::Utc;
+use once_cell::sync::OnceCell;
+use reqwest::{Client, Method, StatusCode};
+use secrecy::{ExposeSecret, SecretString};
+use std::time::Duration;
+use templemeads::Error;
+
+static SERVER: OnceCell<Server> = OnceCell::new();
+
+///
+/// The Harbor server that is managed by this agent, and the
+/// administrator account used to call its API
+///
+pub struct Server {
+    url: String,
+    user: String,
+    password: SecretString,
+    client: Client,
+}
+
+impl Server {
+    pub fn new(url: &str, user: &str, password: SecretString) -> Result<Self, Error> {
+        let url = url.trim().trim_end_matches('/').to_owned();
+
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return Err(Error::Misconfigured(format!(
+                "Invalid Harbor server '{}'. This should be a URL, e.g. https://harbor.example.org",
+                url
+            )));
+        }
+
+        // the client is kept so that connections stay open between calls
+        let client = Client::builder()
+            .build()
+            .map_err(|e| Error::Misconfigured(format!("Could not build HTTP client: {}", e)))?;
+
+        Ok(Self {
+            url,
+            user: user.trim().to_owned(),
+            password,
+            client,
+        })
+    }
+
+    ///
+    /// Return the host (and port) of the registry, which prefixes the
+    /// name of every image, e.g. "harbor.example.org"
+    ///
+    pub fn host(&self) -> &str {
+        self.url
+            .split_once("://")
+            .map(|(_, host)| host)
+            .unwrap_or(&self.url)
+    }
+}
+
+pub fn initialise(server: Server) -> Result<()> {
+    tracing::info!("Managing the Harbor registry at {}", server.url);
+
+    SERVER
+        .set(server)
+        .map_err(|_| anyhow::anyhow!("Harbor server already initialised"))
+}
+
+pub fn get_server() -> Result<&'static Server, Error> {
+    SERVER
+        .get()
+        .ok_or_else(|| Error::Call("Harbor server not initialised".to_owned()))
+}
+
+///
+/// Percent-encode the passed string so that it can be used as a
+/// segment of a URL path
+///
+pub fn encode_path_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+///
+/// Call the Harbor API with the passed method, path (relative to
+/// /api/v2.0), query and JSON body. Returns the status and the parsed
+/// response, which is Null if the response is empty. Only transport
+/// errors are returned as errors, so that callers can handle e.g. 404.
+///
+pub async fn call(
+    method: Method,
+    path: &str,
+    query: &[(&str, &str)],
+    body: Option<&serde_json::Value>,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(StatusCode, serde_json::Value), Error> {
+    let server = get_server()?;
+
+    // how much time is left before we expire?
+    let time_left = expires.signed_duration_since(Utc::now()).num_seconds();
+
+    if time_left < 5 {
+        return Err(Error::Call(
+            "Not enough time left to call the Harbor server".to_owned(),
+        ));
+    }
+
+    let url = format!("{}/api/v2.0{}", server.url, path);
+
+    tracing::debug!("Calling {} {}", method, url);
+
+    let mut request = server
+        .client
+        .request(method.clone(), &url)
+        .basic_auth(&server.user, Some(server.password.expose_secret()))
+        .header("Accept", "application/json")
+        .header("X-Is-Resource-Name", "true")
+        .query(query)
+        .timeout(Duration::from_secs(time_left.min(60) as u64));
+
+    if let Some(body) = body {
+        request = request.json(body);
+    }
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| Error::Call(format!("Could not call {} {}: {}", method, url, e)))?;
+
+    let status = response.status();
+
+    let text = response
+        .text()
+        .await
+        .map_err(|e| Error::Call(format!("Could not read the response of {}: {}", url, e)))?;
+
+    let value = match text.trim().is_empty() {
+        true => serde_json::Value::Null,
+        false => serde_json::from_str(&text).unwrap_or(serde_json::Value::String(text)),
+    };
+
+    if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        return Err(Error::Call(format!(
+            "Harbor refused {} {} ({}). Check the harbor-user and harbor-password",
+            method, url, status
+        )));
+    }
+
+    Ok((status, value))
+}
+
+///
+/// Call the Harbor API, returning an error unless the call succeeds
+///
+pub async fn call_checked(
+    method: Method,
+    path: &str,
+    query: &[(&str, &str)],
+    body: Option<&serde_json::Value>,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<serde_json::Value, Error> {
+    let (status, value) = call(method.clone(), path, query, body, expires).await?;
+
+    match status.is_success() {
+        true => Ok(value),
+        false => Err(Error::Call(format!(
+            "Harbor {} {} failed ({}): {}",
+            method, path, status, value
+        ))),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::sync::{Mutex, Once};
+
+    static FAKE_HARBOR: Once = Once::new();
+
+    /// The status and body of a response of the fake Harbor server
+    type Response = (u16, String);
+
+    /// The responses of the fake Harbor server, keyed by "<method> <path>",
+    /// optionally followed by "?<query>". Responses are given in turn,
+    /// with the last repeated for all later requests.
+    static RESPONSES: Mutex<Option<HashMap<String, Vec<Response>>>> = Mutex::new(None);
+
+    /// Every request received by the fake Harbor server, as
+    /// "<method> <path>[?<query>] <body>"
+    static REQUESTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    ///
+    /// Read a single request from the stream and answer it
+    ///
+    fn handle(stream: std::net::TcpStream) -> std::io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+
+        let mut length = 0;
+        let mut authorised = false;
+
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header)?;
+
+            let header = header.trim();
+
+            if header.is_empty() {
+                break;
+            }
+
+            let lower = header.to_lowercase();
+
+            if let Some(value) = lower.strip_prefix("content-length:") {
+                length = value.trim().parse().unwrap_or(0);
+            }
+
+            // admin:secret
+            if lower == "authorization: basic ywrtaw46c2vjcmv0" {
+                authorised = true;
+            }
+        }
+
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body)?;
+
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default();
+        let target = parts.next().unwrap_or_default();
+        let path = target.split_once('?').map(|(p, _)| p).unwrap_or(target);
+
+        if let Ok(mut requests) = REQUESTS.lock() {
+            requests.push(format!(
+                "{} {} {}",
+                method,
+                target,
+                String::from_utf8_lossy(&body)
+            ));
+        }
+
+        let (status, response) = match authorised {
+            false => (401, String::new()),
+            true => RESPONSES
+                .lock()
+                .ok()
+                .and_then(|mut responses| {
+                    let responses = responses.as_mut()?;
+
+                    let queue = match responses.contains_key(&format!("{} {}", method, target)) {
+                        true => responses.get_mut(&format!("{} {}", method, target)),
+                        false => responses.get_mut(&format!("{} {}", method, path)),
+                    }?;
+
+                    match queue.len() {
+                        0 | 1 => queue.first().cloned(),
+                        _ => Some(queue.remove(0)),
+                    }
+                })
+                .unwrap_or(match method {
+                    "GET" => (404, String::new()),
+                    _ => (200, String::new()),
+                }),
+        };
+
+        let mut stream = stream;
+
+        write!(
+            stream,
+            "HTTP/1.1 {} Status\r\ncontent-type: application/json\r\n\
+             content-length: {}\r\nconnection: close\r\n\r\n{}",
+            status,
+            response.len(),
+            response
+        )?;
+
+        stream.flush()
+    }
+
+    ///
+    /// Start a fake Harbor server, used by the tests of all modules, and
+    /// initialise the agent to use it. The server runs on its own thread
+    /// so that it outlives the runtime of each test.
+    ///
+    pub(crate) fn fake_harbor() {
+        FAKE_HARBOR.call_once(|| {
+            let listener = match std::net::TcpListener::bind("127.0.0.1:0") {
+                Ok(listener) => listener,
+                Err(e) => unreachable!("Cannot bind: {}", e),
+            };
+
+            let url = match listener.local_addr() {
+                Ok(address) => format!("http://{}/", address),
+                Err(e) => unreachable!("No address: {}", e),
+            };
+
+            std::thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let _ = handle(stream);
+                }
+            });
+
+            let server = Server::new(&url, " admin ", SecretString::from("secret"))
+                .unwrap_or_else(|e| unreachable!("Cannot create server: {}", e));
+
+            let _ = initialise(server);
+
+            let dir = std::env::temp_dir().join(format!("op-registry-test-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+
+            let settings = crate::registry::Settings::new(
+                "op-",
+                "images",
+                "pull, PUSH",
+                &dir.display().to_string(),
+                false,
+            )
+            .unwrap_or_else(|e| unreachable!("Cannot create settings: {}", e));
+
+            let _ = crate::registry::initialise(settings);
+        });
+    }
+
+    ///
+    /// Make the fake server answer requests matching `request` (e.g.
+    /// "GET /api/v2.0/projects/abc") with the passed status and body
+    ///
+    pub(crate) fn respond(request: &str, status: u16, body: &str) {
+        respond_in_turn(request, &[(status, body)]);
+    }
+
+    ///
+    /// Make the fake server answer requests matching `request` with
+    /// each of the passed responses in turn
+    ///
+    pub(crate) fn respond_in_turn(request: &str, responses: &[(u16, &str)]) {
+        if let Ok(mut all) = RESPONSES.lock() {
+            all.get_or_insert_with(HashMap::new).insert(
+                request.to_owned(),
+                responses
+                    .iter()
+                    .map(|(status, body)| (*status, body.to_string()))
+                    .collect(),
+            );
+        }
+    }
+
+    ///
+    /// Return the requests received by the fake server that contain `text`
+    ///
+    pub(crate) fn requests(text: &str) -> Vec<String> {
+        REQUESTS
+            .lock()
+            .map(|requests| {
+                requests
+                    .iter()
+                    .filter(|r| r.contains(text))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn expires() -> chrono::DateTime<Utc> {
+        Utc::now() + chrono::Duration::seconds(30)
+    }
+
+    #[test]
+    fn test_server() {
+        assert!(matches!(
+            Server::new("harbor.example.org", "admin", SecretString::from("secret")),
+            Err(Error::Misconfigured(_))
+        ));
+
+        let server = Server::new(
+            " https://harbor.example.org:8443/ ",
+            "admin",
+            SecretString::from("secret"),
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create server: {}", e));
+
+        assert_eq!(server.url, "https://harbor.example.org:8443");
+        assert_eq!(server.host(), "harbor.example.org:8443");
+    }
+
+    #[test]
+    fn test_encode_path_segment() {
+        assert_eq!(encode_path_segment("op-proj_1.x~"), "op-proj_1.x~");
+        assert_eq!(encode_path_segment("a/b c"), "a%2Fb%20c");
+    }
+
+    #[tokio::test]
+    async fn test_call() {
+        fake_harbor();
+
+        respond("GET /api/v2.0/ping", 200, r#"{"pong": true}"#);
+        respond("GET /api/v2.0/text", 200, "Pong");
+        respond("GET /api/v2.0/broken", 500, r#"{"errors": ["broken"]}"#);
+        respond("GET /api/v2.0/secret", 403, "");
+
+        assert!(
+            call(Method::GET, "/ping", &[("a", "b c")], None, &expires())
+                .await
+                .is_ok_and(|(status, value)| status == StatusCode::OK && value["pong"] == true)
+        );
+
+        assert_eq!(requests("/ping"), ["GET /api/v2.0/ping?a=b+c "]);
+
+        // responses that are not JSON are returned as strings
+        assert!(call_checked(Method::GET, "/text", &[], None, &expires())
+            .await
+            .is_ok_and(|value| value == "Pong"));
+
+        // only transport errors are errors for call...
+        assert!(call(Method::GET, "/broken", &[], None, &expires())
+            .await
+            .is_ok_and(|(status, _)| status == StatusCode::INTERNAL_SERVER_ERROR));
+
+        // ...but any failure is for call_checked
+        assert!(matches!(
+            call_checked(Method::GET, "/broken", &[], None, &expires()).await,
+            Err(Error::Call(message)) if message.contains("500")
+        ));
+
+        // except for authorisation failures
+        assert!(matches!(
+            call(Method::GET, "/secret", &[], None, &expires()).await,
+            Err(Error::Call(message)) if message.contains("harbor-password")
+        ));
+
+        // bodies are sent as JSON
+        assert!(call_checked(
+            Method::POST,
+            "/echo",
+            &[],
+            Some(&serde_json::json!({"a": 1})),
+            &expires()
+        )
+        .await
+        .is_ok_and(|value| value.is_null()));
+
+        assert_eq!(requests("/echo"), [r#"POST /api/v2.0/echo {"a":1}"#]);
+
+        // calls are not made if the job is about to expire
+        assert!(matches!(
+            call(
+                Method::GET,
+                "/ping",
+                &[],
+                None,
+                &(Utc::now() + chrono::Duration::seconds(2))
+            )
+            .await,
+            Err(Error::Call(_))
+        ));
+        assert_eq!(requests("/ping").len(), 1);
+    }
+}
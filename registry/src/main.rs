@@ -0,0 +1,177 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+
+use templemeads::agent::filesystem::{process_args, run, Defaults};
+use templemeads::agent::Type as AgentType;
+use templemeads::async_runnable;
+use templemeads::grammar::Date;
+use templemeads::grammar::Instruction::{
+    AddLocalProject, AddLocalUser, ClearLocalProjectQuota, GetLocalProjectDirs,
+    GetLocalProjectQuota, GetLocalProjectQuotas, GetLocalStorageReport, GetLocalUserDirs,
+    RemoveLocalProject, RemoveLocalUser, SetLocalProjectQuota,
+};
+use templemeads::job::{Envelope, Job};
+use templemeads::notification::default_notify_runner;
+use templemeads::set_notify_runner;
+use templemeads::storagereport::ProjectStorageReport;
+use templemeads::Error;
+
+mod harbor;
+mod registry;
+
+///
+/// Main function for the registry filesystem agent
+///
+/// This agent provisions container registry space for projects on a
+/// Harbor registry. Each project gets a private Harbor project with a
+/// storage quota on the configured volume, and each member gets a robot
+/// account that can pull and push its images. Robot secrets are saved
+/// to the credentials directory so that they can be passed on.
+///
+#[tokio::main]
+async fn main() -> Result<()> {
+    // start tracing
+    templemeads::config::initialise_tracing();
+
+    // start system monitoring
+    templemeads::spawn_system_monitor();
+
+    // create the OpenPortal paddington defaults
+    let defaults: Defaults = Defaults::parse(
+        Some("registry".to_owned()),
+        Some(
+            dirs::config_local_dir()
+                .unwrap_or(
+                    ".".parse()
+                        .expect("Could not parse fallback config directory."),
+                )
+                .join("openportal")
+                .join("registry-config.toml"),
+        ),
+        Some("ws://localhost:8047".to_owned()),
+        Some("127.0.0.1".to_owned()),
+        Some(8047),
+        None,
+        None,
+        Some(AgentType::Filesystem),
+    );
+
+    // now parse the command line arguments to get the service configuration
+    let config = match process_args(&defaults).await? {
+        Some(config) => config,
+        None => {
+            // Not running the service, so can safely exit
+            return Ok(());
+        }
+    };
+
+    let harbor_server = config.option("harbor-server", "");
+
+    if harbor_server.trim().is_empty() {
+        return Err(anyhow::anyhow!(
+            "No Harbor server specified. Please set this in the harbor-server option.",
+        ));
+    }
+
+    let harbor_password = match config.secret("harbor-password") {
+        Some(password) => password,
+        None => {
+            return Err(anyhow::anyhow!(
+                "No Harbor password specified. Please set this in the harbor-password option.",
+            ));
+        }
+    };
+
+    harbor::initialise(harbor::Server::new(
+        &harbor_server,
+        &config.option("harbor-user", "admin"),
+        harbor_password,
+    )?)?;
+
+    registry::initialise(registry::Settings::new(
+        &config.option("project-prefix", ""),
+        &config.option("volume", "registry"),
+        &config.option("robot-access", "pull,push"),
+        &config.option("credentials-dir", ""),
+        config.option("delete-projects", "false").to_lowercase() == "true",
+    )?)?;
+
+    async_runnable! {
+        ///
+        /// Runnable function that will be called when a job is received
+        /// by the agent
+        ///
+        pub async fn registry_runner(envelope: Envelope) -> Result<Job, templemeads::Error>
+        {
+            let job = envelope.job();
+
+            match job.instruction() {
+                AddLocalProject(mapping) => {
+                    registry::add_project(&mapping, job.expires()).await?;
+                    job.completed_none()
+                },
+                RemoveLocalProject(mapping) => {
+                    // the Harbor project is only deleted if delete-projects is set
+                    registry::remove_project(&mapping, job.expires()).await?;
+                    job.completed_none()
+                },
+                AddLocalUser(mapping) => {
+                    registry::add_user(&mapping, job.expires()).await?;
+                    job.completed_none()
+                },
+                RemoveLocalUser(mapping) => {
+                    registry::remove_user(&mapping, job.expires()).await?;
+                    job.completed_none()
+                },
+                GetLocalProjectDirs(mapping) => {
+                    job.completed(registry::get_project_dirs(&mapping)?)
+                },
+                GetLocalUserDirs(_) => {
+                    // members only have robot accounts, not directories
+                    job.completed(Vec::<String>::new())
+                },
+                SetLocalProjectQuota(mapping, volume, limit) => {
+                    let quota = registry::set_project_quota(&mapping, &volume, &limit, job.expires()).await?;
+                    job.completed(quota)
+                },
+                GetLocalProjectQuota(mapping, volume) => {
+                    let quota = registry::get_project_quota(&mapping, &volume, job.expires()).await?;
+                    job.completed(quota)
+                },
+                GetLocalProjectQuotas(mapping) => {
+                    let quotas = registry::get_project_quotas(&mapping, job.expires()).await?;
+                    job.completed(quotas)
+                },
+                ClearLocalProjectQuota(mapping, volume) => {
+                    registry::clear_project_quota(&mapping, &volume, job.expires()).await?;
+                    job.completed_none()
+                },
+                GetLocalStorageReport(mapping, dates) => {
+                    let today = Date::today().day();
+                    if dates != today {
+                        return job.errored(&format!(
+                            "Storage reports only support today's date; requested range: {}",
+                            dates
+                        ));
+                    }
+
+                    let mut report = ProjectStorageReport::new(mapping.project());
+                    report.set_project_quotas(registry::get_project_quotas(&mapping, job.expires()).await?);
+                    job.completed(report)
+                },
+                _ => {
+                    Err(Error::InvalidInstruction(
+                        format!("Invalid instruction: {}. Registry agents do not support this instruction", job.instruction()),
+                    ))
+                }
+            }
+        }
+    }
+
+    set_notify_runner(default_notify_runner).await?;
+    run(config, registry_runner).await?;
+
+    Ok(())
+}
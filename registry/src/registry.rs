@@ -0,0 +1,1113 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+use chrono::Utc;
+use once_cell::sync::OnceCell;
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use templemeads::grammar::{ProjectMapping, UserMapping};
+use templemeads::job::assert_not_expired;
+use templemeads::storage::{Quota, QuotaLimit, StorageSize, StorageUsage, Volume};
+use templemeads::Error;
+
+use crate::harbor::{self, call, call_checked, encode_path_segment};
+
+static SETTINGS: OnceCell<Settings> = OnceCell::new();
+
+/// The most items returned by Harbor in a single page
+const PAGE_SIZE: usize = 100;
+
+/// The actions on repositories that robot accounts can be granted
+const ROBOT_ACTIONS: [&str; 3] = ["pull", "push", "delete"];
+
+///
+/// Configuration for how projects and members are provisioned. Each
+/// project gets its own Harbor project, and each member gets a robot
+/// account with `robot_access` to the repositories of that project.
+///
+pub struct Settings {
+    project_prefix: String,
+    volume: Volume,
+    robot_access: Vec<String>,
+    credentials_dir: Option<PathBuf>,
+    delete_projects: bool,
+}
+
+impl Settings {
+    pub fn new(
+        project_prefix: &str,
+        volume: &str,
+        robot_access: &str,
+        credentials_dir: &str,
+        delete_projects: bool,
+    ) -> Result<Self, Error> {
+        let robot_access: Vec<String> = robot_access
+            .split(',')
+            .map(|a| a.trim().to_lowercase())
+            .filter(|a| !a.is_empty())
+            .collect();
+
+        if let Some(action) = robot_access
+            .iter()
+            .find(|a| !ROBOT_ACTIONS.contains(&a.as_str()))
+        {
+            return Err(Error::Misconfigured(format!(
+                "Unknown robot access '{}'. This must be one of {}",
+                action,
+                ROBOT_ACTIONS.join(", ")
+            )));
+        }
+
+        if robot_access.is_empty() {
+            return Err(Error::Misconfigured(
+                "Robot accounts must be given at least one access, e.g. 'pull,push'".to_owned(),
+            ));
+        }
+
+        Ok(Self {
+            project_prefix: project_prefix.trim().to_owned(),
+            volume: Volume::new(volume.trim()),
+            robot_access,
+            credentials_dir: match credentials_dir.trim().is_empty() {
+                true => None,
+                false => Some(PathBuf::from(credentials_dir.trim())),
+            },
+            delete_projects,
+        })
+    }
+}
+
+pub fn initialise(settings: Settings) -> Result<()> {
+    if settings.credentials_dir.is_none() {
+        tracing::warn!(
+            "No credentials-dir is set, so the secrets of new robot accounts \
+             will not be saved, and cannot be read back from Harbor"
+        );
+    }
+
+    SETTINGS
+        .set(settings)
+        .map_err(|_| anyhow::anyhow!("Registry settings already initialised"))
+}
+
+fn get_settings() -> Result<&'static Settings, Error> {
+    SETTINGS
+        .get()
+        .ok_or_else(|| Error::Call("Registry settings not initialised".to_owned()))
+}
+
+///
+/// Convert the passed name into a valid Harbor project or robot name,
+/// i.e. at most 255 lowercase letters, digits, '-', '_' and '.',
+/// starting and ending with a letter or digit
+///
+fn sanitise(name: &str) -> Result<String, Error> {
+    let name: String = name
+        .to_lowercase()
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                true => c,
+                false => '-',
+            },
+        )
+        .collect();
+
+    let name = name.trim_matches(|c: char| !c.is_ascii_alphanumeric());
+    let name = name[..name.len().min(255)]
+        .trim_end_matches(|c: char| !c.is_ascii_alphanumeric())
+        .to_owned();
+
+    match name.is_empty() {
+        true => Err(Error::Parse(
+            "Cannot create a Harbor name from an empty name".to_owned(),
+        )),
+        false => Ok(name),
+    }
+}
+
+///
+/// Return the name of the Harbor project of the passed local project group
+///
+pub fn project_name(local_group: &str) -> Result<String, Error> {
+    sanitise(&format!(
+        "{}{}",
+        get_settings()?.project_prefix,
+        local_group
+    ))
+}
+
+///
+/// Return the Harbor project, or None if it does not exist
+///
+async fn get_project(
+    project: &str,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Option<Value>, Error> {
+    let (status, value) = call(
+        Method::GET,
+        &format!("/projects/{}", encode_path_segment(project)),
+        &[],
+        None,
+        expires,
+    )
+    .await?;
+
+    match status {
+        StatusCode::NOT_FOUND => Ok(None),
+        s if s.is_success() => Ok(Some(value)),
+        s => Err(Error::Call(format!(
+            "Could not get Harbor project {} ({}): {}",
+            project, s, value
+        ))),
+    }
+}
+
+///
+/// Return the id of the passed Harbor project
+///
+fn project_id(project: &Value) -> Result<i64, Error> {
+    project
+        .get("project_id")
+        .and_then(|id| id.as_i64())
+        .ok_or_else(|| Error::Parse(format!("Could not read the id of project {}", project)))
+}
+
+///
+/// Return the id of the Harbor project, which must exist
+///
+async fn get_project_id(project: &str, expires: &chrono::DateTime<Utc>) -> Result<i64, Error> {
+    match get_project(project, expires).await? {
+        Some(value) => project_id(&value),
+        None => Err(Error::InvalidState(format!(
+            "The Harbor project {} does not exist",
+            project
+        ))),
+    }
+}
+
+///
+/// Return the robot accounts of the Harbor project
+///
+async fn get_robots(project_id: i64, expires: &chrono::DateTime<Utc>) -> Result<Vec<Value>, Error> {
+    let mut robots = Vec::new();
+    let mut page = 1;
+
+    let page_size = PAGE_SIZE.to_string();
+
+    loop {
+        let page_str = page.to_string();
+        let query = format!("Level=project,ProjectID={}", project_id);
+
+        let value = call_checked(
+            Method::GET,
+            "/robots",
+            &[
+                ("q", query.as_str()),
+                ("page", page_str.as_str()),
+                ("page_size", page_size.as_str()),
+            ],
+            None,
+            expires,
+        )
+        .await?;
+
+        let items = value.as_array().cloned().unwrap_or_default();
+        let done = items.len() < PAGE_SIZE;
+
+        robots.extend(items);
+
+        if done {
+            return Ok(robots);
+        }
+
+        page += 1;
+    }
+}
+
+///
+/// Return the robot account of the member, or None if they have none.
+/// Harbor names project robots "<prefix><project>+<name>", where the
+/// prefix (normally "robot$") is set by the Harbor administrator.
+///
+async fn get_robot(
+    project: &str,
+    project_id: i64,
+    robot: &str,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Option<Value>, Error> {
+    let suffix = format!("{}+{}", project, robot);
+
+    Ok(get_robots(project_id, expires)
+        .await?
+        .into_iter()
+        .find(|r| {
+            r.get("name")
+                .and_then(|n| n.as_str())
+                .map(|n| n.ends_with(&suffix))
+                .unwrap_or(false)
+        }))
+}
+
+fn robot_id(robot: &Value) -> Result<i64, Error> {
+    robot
+        .get("id")
+        .and_then(|id| id.as_i64())
+        .ok_or_else(|| Error::Parse(format!("Could not read the id of robot {}", robot)))
+}
+
+///
+/// Enable or disable the robot account. Harbor only updates robots as
+/// a whole, so the robot is read, changed and written back.
+///
+async fn set_robot_disabled(
+    robot: &Value,
+    disabled: bool,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    if robot.get("disable").and_then(|d| d.as_bool()) == Some(disabled) {
+        return Ok(());
+    }
+
+    let path = format!("/robots/{}", robot_id(robot)?);
+
+    let mut robot = call_checked(Method::GET, &path, &[], None, expires).await?;
+    robot["disable"] = Value::Bool(disabled);
+
+    call_checked(Method::PUT, &path, &[], Some(&robot), expires).await?;
+
+    Ok(())
+}
+
+///
+/// Delete every repository (and so every image) in the Harbor project
+///
+async fn delete_repositories(project: &str, expires: &chrono::DateTime<Utc>) -> Result<(), Error> {
+    let path = format!("/projects/{}/repositories", encode_path_segment(project));
+    let page_size = PAGE_SIZE.to_string();
+
+    loop {
+        assert_not_expired(expires)?;
+
+        let repositories = call_checked(
+            Method::GET,
+            &path,
+            &[("page_size", page_size.as_str())],
+            None,
+            expires,
+        )
+        .await?
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+
+        if repositories.is_empty() {
+            return Ok(());
+        }
+
+        for repository in repositories {
+            let name = repository
+                .get("name")
+                .and_then(|n| n.as_str())
+                .unwrap_or_default();
+
+            // repository names include the project, and any '/' in the
+            // rest of the name must be encoded twice
+            let name = name.strip_prefix(&format!("{}/", project)).unwrap_or(name);
+
+            call_checked(
+                Method::DELETE,
+                &format!("{}/{}", path, encode_path_segment(name).replace('%', "%25")),
+                &[],
+                None,
+                expires,
+            )
+            .await?;
+
+            tracing::info!("Deleted repository {}/{}", project, name);
+        }
+    }
+}
+
+///
+/// Create the Harbor project of the project, or re-enable its robot
+/// accounts if the project was previously removed
+///
+pub async fn add_project(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let name = project_name(project.local_group())?;
+
+    match get_project(&name, expires).await? {
+        Some(value) => {
+            for robot in get_robots(project_id(&value)?, expires).await? {
+                set_robot_disabled(&robot, false, expires).await?;
+            }
+
+            tracing::info!("Harbor project {} already exists for {}", name, project);
+        }
+        None => {
+            call_checked(
+                Method::POST,
+                "/projects",
+                &[],
+                Some(&json!({
+                    "project_name": name,
+                    "metadata": {"public": "false"},
+                    "storage_limit": -1,
+                })),
+                expires,
+            )
+            .await?;
+
+            tracing::info!("Created Harbor project {} for {}", name, project);
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Disable the robot accounts of the project, so that they can no
+/// longer pull or push, or delete the Harbor project, its images and
+/// its robots if delete-projects is set
+///
+pub async fn remove_project(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let name = project_name(project.local_group())?;
+
+    let robots = match get_project(&name, expires).await? {
+        Some(value) => get_robots(project_id(&value)?, expires).await?,
+        None => {
+            tracing::warn!("Harbor project {} for {} does not exist", name, project);
+            return Ok(());
+        }
+    };
+
+    match get_settings()?.delete_projects {
+        true => {
+            for robot in robots {
+                call_checked(
+                    Method::DELETE,
+                    &format!("/robots/{}", robot_id(&robot)?),
+                    &[],
+                    None,
+                    expires,
+                )
+                .await?;
+            }
+
+            // Harbor only deletes projects that have no repositories
+            delete_repositories(&name, expires).await?;
+
+            call_checked(
+                Method::DELETE,
+                &format!("/projects/{}", encode_path_segment(&name)),
+                &[],
+                None,
+                expires,
+            )
+            .await?;
+
+            tracing::info!("Deleted Harbor project {} and all of its images", name);
+        }
+        false => {
+            for robot in robots {
+                set_robot_disabled(&robot, true, expires).await?;
+            }
+
+            tracing::info!("Disabled the robot accounts of Harbor project {}", name);
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Return the path of the file holding the member's robot credentials
+///
+fn credentials_path(project: &str, local_user: &str) -> Result<Option<PathBuf>, Error> {
+    Ok(get_settings()?
+        .credentials_dir
+        .as_ref()
+        .map(|dir| dir.join(project).join(format!("{}.json", local_user))))
+}
+
+///
+/// Save the credentials of the member's new robot account to the
+/// credentials directory (if set), so that they can be passed on to the
+/// member. The file is only readable by the agent's user.
+///
+async fn save_credentials(project: &str, local_user: &str, robot: &Value) -> Result<(), Error> {
+    let path = match credentials_path(project, local_user)? {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    let contents = serde_json::to_string_pretty(&json!({
+        "registry": harbor::get_server()?.host(),
+        "project": project,
+        "username": robot.get("name"),
+        "secret": robot.get("secret"),
+    }))?;
+
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    // write to a temporary file with restricted permissions, then
+    // rename, so that a partially-written file is never seen
+    let tmp = path.with_extension("json.tmp");
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options.open(&tmp).await?;
+    tokio::io::AsyncWriteExt::write_all(&mut file, contents.as_bytes()).await?;
+    file.sync_all().await?;
+    tokio::fs::rename(&tmp, &path).await?;
+
+    tracing::info!(
+        "Saved the robot credentials of {} to {}",
+        local_user,
+        path.display()
+    );
+
+    Ok(())
+}
+
+///
+/// Remove the member's saved credentials, if any
+///
+async fn remove_credentials(project: &str, local_user: &str) -> Result<(), Error> {
+    if let Some(path) = credentials_path(project, local_user)? {
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Create a robot account for the member with access to the
+/// repositories of their project, or re-enable it if it already exists
+///
+pub async fn add_user(user: &UserMapping, expires: &chrono::DateTime<Utc>) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let settings = get_settings()?;
+    let project = project_name(user.local_group())?;
+    let robot = sanitise(user.local_user())?;
+
+    let project_id = match get_project(&project, expires).await? {
+        Some(value) => project_id(&value)?,
+        None => {
+            return Err(Error::InvalidState(format!(
+                "Cannot add {} as the Harbor project {} does not exist",
+                user, project
+            )));
+        }
+    };
+
+    if let Some(existing) = get_robot(&project, project_id, &robot, expires).await? {
+        set_robot_disabled(&existing, false, expires).await?;
+        tracing::info!(
+            "{} already has a robot account in {}",
+            user.local_user(),
+            project
+        );
+        return Ok(());
+    }
+
+    let access: Vec<Value> = settings
+        .robot_access
+        .iter()
+        .map(|action| json!({"resource": "repository", "action": action}))
+        .collect();
+
+    let created = call_checked(
+        Method::POST,
+        "/robots",
+        &[],
+        Some(&json!({
+            "name": robot,
+            "description": format!("OpenPortal robot account of {}", user.user()),
+            "duration": -1,
+            "level": "project",
+            "disable": false,
+            "permissions": [{
+                "kind": "project",
+                "namespace": project,
+                "access": access,
+            }],
+        })),
+        expires,
+    )
+    .await?;
+
+    tracing::info!(
+        "Created robot account {} for {}",
+        created
+            .get("name")
+            .and_then(|n| n.as_str())
+            .unwrap_or(&robot),
+        user.local_user()
+    );
+
+    save_credentials(&project, user.local_user(), &created).await
+}
+
+///
+/// Delete the member's robot account
+///
+pub async fn remove_user(user: &UserMapping, expires: &chrono::DateTime<Utc>) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let project = project_name(user.local_group())?;
+    let robot = sanitise(user.local_user())?;
+
+    let project_id = match get_project(&project, expires).await? {
+        Some(value) => project_id(&value)?,
+        None => {
+            tracing::warn!("Harbor project {} for {} does not exist", project, user);
+            return remove_credentials(&project, user.local_user()).await;
+        }
+    };
+
+    match get_robot(&project, project_id, &robot, expires).await? {
+        Some(existing) => {
+            call_checked(
+                Method::DELETE,
+                &format!("/robots/{}", robot_id(&existing)?),
+                &[],
+                None,
+                expires,
+            )
+            .await?;
+
+            tracing::info!(
+                "Deleted the robot account of {} in {}",
+                user.local_user(),
+                project
+            );
+        }
+        None => {
+            tracing::warn!("{} has no robot account in {}", user.local_user(), project);
+        }
+    }
+
+    remove_credentials(&project, user.local_user()).await
+}
+
+///
+/// Check that the volume is the one served by this agent
+///
+fn assert_volume(volume: &Volume) -> Result<(), Error> {
+    let settings = get_settings()?;
+
+    match *volume == settings.volume {
+        true => Ok(()),
+        false => Err(Error::InvalidInstruction(format!(
+            "Unknown volume {}. This agent only manages the volume {}",
+            volume, settings.volume
+        ))),
+    }
+}
+
+///
+/// Return the storage quota record of the Harbor project
+///
+async fn get_quota_record(project: &str, expires: &chrono::DateTime<Utc>) -> Result<Value, Error> {
+    let project_id = get_project_id(project, expires).await?.to_string();
+
+    call_checked(
+        Method::GET,
+        "/quotas",
+        &[
+            ("reference", "project"),
+            ("reference_id", project_id.as_str()),
+        ],
+        None,
+        expires,
+    )
+    .await?
+    .as_array()
+    .and_then(|quotas| quotas.first().cloned())
+    .ok_or_else(|| Error::InvalidState(format!("Harbor project {} has no quota", project)))
+}
+
+///
+/// Return the project's storage quota on the volume, including its usage
+///
+pub async fn get_project_quota(
+    project: &ProjectMapping,
+    volume: &Volume,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Quota, Error> {
+    assert_not_expired(expires)?;
+    assert_volume(volume)?;
+
+    let name = project_name(project.local_group())?;
+    let record = get_quota_record(&name, expires).await?;
+
+    // Harbor uses -1 to mean unlimited
+    let limit = match record["hard"]["storage"].as_i64() {
+        Some(bytes) if bytes >= 0 => QuotaLimit::from(StorageSize::from_bytes(bytes as u64)),
+        _ => QuotaLimit::Unlimited,
+    };
+
+    let usage = StorageUsage::from(record["used"]["storage"].as_u64().unwrap_or(0));
+
+    Ok(Quota::with_usage(limit, usage))
+}
+
+///
+/// Return the project's quotas on every volume served by this agent
+///
+pub async fn get_project_quotas(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<HashMap<Volume, Quota>, Error> {
+    let volume = get_settings()?.volume.clone();
+    let quota = get_project_quota(project, &volume, expires).await?;
+
+    Ok(HashMap::from([(volume, quota)]))
+}
+
+///
+/// Set the project's storage quota on the volume
+///
+pub async fn set_project_quota(
+    project: &ProjectMapping,
+    volume: &Volume,
+    limit: &QuotaLimit,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Quota, Error> {
+    assert_not_expired(expires)?;
+    assert_volume(volume)?;
+
+    let name = project_name(project.local_group())?;
+    let record = get_quota_record(&name, expires).await?;
+
+    let quota_id = record
+        .get("id")
+        .and_then(|id| id.as_i64())
+        .ok_or_else(|| Error::Parse(format!("Could not read the quota id of {}", name)))?;
+
+    let bytes = match limit.size() {
+        Some(size) => size.as_bytes() as i64,
+        None => -1,
+    };
+
+    call_checked(
+        Method::PUT,
+        &format!("/quotas/{}", quota_id),
+        &[],
+        Some(&json!({"hard": {"storage": bytes}})),
+        expires,
+    )
+    .await?;
+
+    tracing::info!(
+        "Set the storage quota of Harbor project {} to {}",
+        name,
+        limit
+    );
+
+    get_project_quota(project, volume, expires).await
+}
+
+///
+/// Remove the project's storage quota on the volume
+///
+pub async fn clear_project_quota(
+    project: &ProjectMapping,
+    volume: &Volume,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    set_project_quota(project, volume, &QuotaLimit::Unlimited, expires).await?;
+    Ok(())
+}
+
+///
+/// Return the prefix of the project's images, e.g.
+/// "harbor.example.org/myproject", which is used as the project's only
+/// "directory"
+///
+pub fn get_project_dirs(project: &ProjectMapping) -> Result<Vec<String>, Error> {
+    Ok(vec![format!(
+        "{}/{}",
+        harbor::get_server()?.host(),
+        project_name(project.local_group())?
+    )])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harbor::tests::{expires, fake_harbor, requests, respond, respond_in_turn};
+    use templemeads::grammar::{ProjectIdentifier, UserIdentifier};
+
+    fn project(name: &str) -> ProjectMapping {
+        ProjectMapping::new(
+            &ProjectIdentifier::parse(&format!("{}.portal", name))
+                .unwrap_or_else(|e| unreachable!("Cannot parse project: {}", e)),
+            name,
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e))
+    }
+
+    fn user(name: &str, project: &str) -> UserMapping {
+        UserMapping::new(
+            &UserIdentifier::parse(&format!("{}.{}.portal", name, project))
+                .unwrap_or_else(|e| unreachable!("Cannot parse user: {}", e)),
+            name,
+            project,
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e))
+    }
+
+    ///
+    /// Make the fake server hold the Harbor project with the passed id
+    /// and robot accounts
+    ///
+    fn harbor_project(name: &str, id: i64, robots: &str) {
+        respond(
+            &format!("GET /api/v2.0/projects/{}", name),
+            200,
+            &format!(r#"{{"project_id": {}, "name": "{}"}}"#, id, name),
+        );
+
+        respond(
+            &format!(
+                "GET /api/v2.0/robots?q=Level%3Dproject%2CProjectID%3D{}&page=1&page_size=100",
+                id
+            ),
+            200,
+            robots,
+        );
+    }
+
+    #[test]
+    fn test_settings() {
+        assert!(Settings::new("", "images", "pull", "", false)
+            .is_ok_and(|s| s.robot_access == ["pull"] && s.credentials_dir.is_none()));
+
+        assert!(matches!(
+            Settings::new("", "images", "pull,admin", "", false),
+            Err(Error::Misconfigured(message)) if message.contains("admin")
+        ));
+
+        assert!(matches!(
+            Settings::new("", "images", " , ", "", false),
+            Err(Error::Misconfigured(_))
+        ));
+    }
+
+    #[test]
+    fn test_sanitise() {
+        assert!(matches!(sanitise("Op_Proj!").as_deref(), Ok("op_proj")));
+        assert!(matches!(sanitise("--a b--").as_deref(), Ok("a-b")));
+        assert!(sanitise(&"x".repeat(300)).is_ok_and(|name| name.len() == 255));
+        assert!(matches!(sanitise("!!!"), Err(Error::Parse(_))));
+    }
+
+    #[tokio::test]
+    async fn test_projects() {
+        fake_harbor();
+
+        assert!(matches!(project_name("Proj").as_deref(), Ok("op-proj")));
+
+        // new projects are private, with no storage limit
+        add_project(&project("new"), &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add project: {}", e));
+
+        assert_eq!(
+            requests("/projects/op-new")
+                .into_iter()
+                .chain(requests(r#""project_name":"op-new""#))
+                .collect::<Vec<_>>(),
+            [
+                "GET /api/v2.0/projects/op-new ",
+                r#"POST /api/v2.0/projects {"metadata":{"public":"false"},"project_name":"op-new","storage_limit":-1}"#,
+            ]
+        );
+
+        // adding an existing project re-enables its disabled robots
+        harbor_project(
+            "op-old",
+            7,
+            r#"[{"id": 70, "name": "robot$op-old+alice", "disable": true},
+                {"id": 71, "name": "robot$op-old+bob", "disable": false}]"#,
+        );
+        respond(
+            "GET /api/v2.0/robots/70",
+            200,
+            r#"{"id": 70, "name": "robot$op-old+alice", "disable": true}"#,
+        );
+        respond(
+            "GET /api/v2.0/robots/71",
+            200,
+            r#"{"id": 71, "name": "robot$op-old+bob", "disable": false}"#,
+        );
+
+        add_project(&project("old"), &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add project: {}", e));
+
+        assert_eq!(
+            requests("PUT /api/v2.0/robots/"),
+            [r#"PUT /api/v2.0/robots/70 {"disable":false,"id":70,"name":"robot$op-old+alice"}"#]
+        );
+
+        // and removing it disables them again
+        remove_project(&project("old"), &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove project: {}", e));
+
+        assert_eq!(
+            requests("PUT /api/v2.0/robots/71"),
+            [r#"PUT /api/v2.0/robots/71 {"disable":true,"id":71,"name":"robot$op-old+bob"}"#]
+        );
+
+        // removing a project that doesn't exist is not an error
+        assert!(remove_project(&project("missing"), &expires())
+            .await
+            .is_ok());
+
+        // deleting repositories continues until there are none left
+        respond_in_turn(
+            "GET /api/v2.0/projects/op-old/repositories",
+            &[
+                (
+                    200,
+                    r#"[{"name": "op-old/app/web"}, {"name": "op-old/db"}]"#,
+                ),
+                (200, "[]"),
+            ],
+        );
+
+        delete_repositories("op-old", &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot delete repositories: {}", e));
+
+        assert_eq!(
+            requests("DELETE /api/v2.0/projects/op-old/repositories"),
+            [
+                "DELETE /api/v2.0/projects/op-old/repositories/app%252Fweb ",
+                "DELETE /api/v2.0/projects/op-old/repositories/db ",
+            ]
+        );
+
+        // unexpected responses from Harbor are errors
+        respond("GET /api/v2.0/projects/op-broken", 500, "oops");
+
+        assert!(matches!(
+            add_project(&project("broken"), &expires()).await,
+            Err(Error::Call(message)) if message.contains("500")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_users() {
+        fake_harbor();
+
+        harbor_project(
+            "op-users",
+            8,
+            r#"[{"id": 80, "name": "robot$op-users+bob", "disable": false}]"#,
+        );
+
+        // bob already has a robot account
+        add_user(&user("bob", "users"), &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add user: {}", e));
+
+        assert!(requests("POST /api/v2.0/robots").is_empty());
+
+        // alice gets a new one, with the configured access
+        respond(
+            "POST /api/v2.0/robots",
+            201,
+            r#"{"id": 81, "name": "robot$op-users+alice", "secret": "s3cret"}"#,
+        );
+
+        add_user(&user("alice", "users"), &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add user: {}", e));
+
+        let created = requests("POST /api/v2.0/robots");
+        assert_eq!(created.len(), 1);
+
+        let body: Value = serde_json::from_str(
+            created[0]
+                .strip_prefix("POST /api/v2.0/robots ")
+                .unwrap_or_default(),
+        )
+        .unwrap_or_else(|e| unreachable!("Invalid robot: {}", e));
+
+        assert_eq!(body["name"], "alice");
+        assert_eq!(body["level"], "project");
+        assert_eq!(body["permissions"][0]["namespace"], "op-users");
+        assert_eq!(
+            body["permissions"][0]["access"],
+            json!([
+                {"resource": "repository", "action": "pull"},
+                {"resource": "repository", "action": "push"},
+            ])
+        );
+
+        // the robot's secret is saved for alice
+        let path = std::env::temp_dir()
+            .join(format!("op-registry-test-{}", std::process::id()))
+            .join("op-users")
+            .join("alice.json");
+
+        let saved: Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap_or_default())
+                .unwrap_or_else(|e| unreachable!("Invalid credentials: {}", e));
+
+        assert!(saved["registry"]
+            .as_str()
+            .is_some_and(|r| r.starts_with("127.0.0.1:")));
+        assert_eq!(saved["project"], "op-users");
+        assert_eq!(saved["username"], "robot$op-users+alice");
+        assert_eq!(saved["secret"], "s3cret");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert!(std::fs::metadata(&path).is_ok_and(|m| m.permissions().mode() & 0o777 == 0o600));
+        }
+
+        // removing a member deletes their robot and saved secret
+        harbor_project(
+            "op-users",
+            8,
+            r#"[{"id": 80, "name": "robot$op-users+bob", "disable": false},
+                {"id": 81, "name": "robot$op-users+alice", "disable": false}]"#,
+        );
+
+        remove_user(&user("alice", "users"), &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove user: {}", e));
+
+        assert_eq!(
+            requests("DELETE /api/v2.0/robots"),
+            ["DELETE /api/v2.0/robots/81 "]
+        );
+        assert!(!path.exists());
+
+        // members without a robot have nothing to remove
+        assert!(remove_user(&user("carol", "users"), &expires())
+            .await
+            .is_ok());
+        assert_eq!(requests("DELETE /api/v2.0/robots").len(), 1);
+
+        // members can only be added to projects that exist
+        assert!(matches!(
+            add_user(&user("alice", "nothere"), &expires()).await,
+            Err(Error::InvalidState(_))
+        ));
+
+        let expired = Utc::now() - chrono::Duration::seconds(1);
+
+        assert!(matches!(
+            add_user(&user("alice", "users"), &expired).await,
+            Err(Error::Expired(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_quotas() {
+        fake_harbor();
+
+        harbor_project("op-quota", 9, "[]");
+        respond(
+            "GET /api/v2.0/quotas?reference=project&reference_id=9",
+            200,
+            r#"[{"id": 90, "hard": {"storage": 1000}, "used": {"storage": 250}}]"#,
+        );
+
+        let project = project("quota");
+        let volume = Volume::new("images");
+
+        let quota = get_project_quota(&project, &volume, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot get quota: {}", e));
+
+        assert_eq!(quota.limit().size().map(|s| s.as_bytes()), Some(1000));
+        assert_eq!(quota.usage().map(|u| u.into_size().as_bytes()), Some(250));
+
+        assert!(get_project_quotas(&project, &expires())
+            .await
+            .is_ok_and(|quotas| quotas.len() == 1 && quotas.contains_key(&volume)));
+
+        set_project_quota(
+            &project,
+            &volume,
+            &QuotaLimit::from(StorageSize::from_bytes(2000)),
+            &expires(),
+        )
+        .await
+        .unwrap_or_else(|e| unreachable!("Cannot set quota: {}", e));
+
+        clear_project_quota(&project, &volume, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot clear quota: {}", e));
+
+        // Harbor uses -1 for no limit
+        assert_eq!(
+            requests("PUT /api/v2.0/quotas"),
+            [
+                r#"PUT /api/v2.0/quotas/90 {"hard":{"storage":2000}}"#,
+                r#"PUT /api/v2.0/quotas/90 {"hard":{"storage":-1}}"#,
+            ]
+        );
+
+        assert!(get_project_dirs(&project).is_ok_and(|dirs| dirs.len() == 1
+            && dirs[0].starts_with("127.0.0.1:")
+            && dirs[0].ends_with("/op-quota")));
+
+        // only the agent's volume can be used
+        assert!(matches!(
+            get_project_quota(&project, &Volume::new("home"), &expires()).await,
+            Err(Error::InvalidInstruction(_))
+        ));
+
+        // projects must exist and have a quota
+        assert!(matches!(
+            get_project_quota(&self::project("nothere"), &volume, &expires()).await,
+            Err(Error::InvalidState(_))
+        ));
+
+        harbor_project("op-noquota", 10, "[]");
+        respond(
+            "GET /api/v2.0/quotas?reference=project&reference_id=10",
+            200,
+            "[]",
+        );
+
+        assert!(matches!(
+            get_project_quota(&self::project("noquota"), &volume, &expires()).await,
+            Err(Error::InvalidState(message)) if message.contains("no quota")
+        ));
+    }
+}
@@ -0,0 +1,174 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+
+use templemeads::agent::filesystem::{process_args, run, Defaults};
+use templemeads::agent::Type as AgentType;
+use templemeads::async_runnable;
+use templemeads::grammar::Date;
+use templemeads::grammar::Instruction::{
+    AddLocalProject, AddLocalUser, ClearLocalProjectQuota, GetLocalProjectDirs,
+    GetLocalProjectQuota, GetLocalProjectQuotas, GetLocalStorageReport, GetLocalUserDirs,
+    RemoveLocalProject, RemoveLocalUser, SetLocalProjectQuota,
+};
+use templemeads::job::{Envelope, Job};
+use templemeads::notification::default_notify_runner;
+use templemeads::set_notify_runner;
+use templemeads::storagereport::ProjectStorageReport;
+use templemeads::Error;
+
+mod minio;
+mod objectstore;
+mod rgw;
+
+///
+/// Main function for the s3 filesystem agent
+///
+/// This agent provisions object storage for projects on an
+/// S3-compatible service (Ceph RADOS Gateway or MinIO). Each project
+/// gets a bucket with a quota on the configured volume, and each member
+/// gets their own access key, which is saved to the credentials
+/// directory so that it can be passed on to them.
+///
+#[tokio::main]
+async fn main() -> Result<()> {
+    // start tracing
+    templemeads::config::initialise_tracing();
+
+    // start system monitoring
+    templemeads::spawn_system_monitor();
+
+    // create the OpenPortal paddington defaults
+    let defaults: Defaults = Defaults::parse(
+        Some("s3".to_owned()),
+        Some(
+            dirs::config_local_dir()
+                .unwrap_or(
+                    ".".parse()
+                        .expect("Could not parse fallback config directory."),
+                )
+                .join("openportal")
+                .join("s3-config.toml"),
+        ),
+        Some("ws://localhost:8047".to_owned()),
+        Some("127.0.0.1".to_owned()),
+        Some(8047),
+        None,
+        None,
+        Some(AgentType::Filesystem),
+    );
+
+    // now parse the command line arguments to get the service configuration
+    let config = match process_args(&defaults).await? {
+        Some(config) => config,
+        None => {
+            // Not running the service, so can safely exit
+            return Ok(());
+        }
+    };
+
+    let backend = objectstore::Backend::parse(&config.option("backend", "rgw"))?;
+
+    // The admin command defaults to the standard binary name of the
+    // backend. To run it remotely, set e.g.:
+    //   radosgw-admin = "ssh ceph-admin radosgw-admin"
+    let command = match backend {
+        objectstore::Backend::Rgw => config.option("radosgw-admin", "radosgw-admin"),
+        objectstore::Backend::Minio => config.option("mc", "mc"),
+    };
+
+    objectstore::initialise(objectstore::Settings::new(
+        backend,
+        &command,
+        &config.option("mc-alias", "openportal"),
+        &config.option("endpoint", ""),
+        &config.option("bucket-prefix", ""),
+        &config.option("volume", "s3"),
+        &config.option("credentials-dir", ""),
+        config.option("delete-buckets", "false").to_lowercase() == "true",
+    )?)?;
+
+    // the admin command may need the AWS credentials and region from the
+    // agent's environment, which are not passed to commands by default
+    objectstore::allow_aws_env()?;
+
+    tracing::info!("Managing object storage using {}", backend);
+
+    async_runnable! {
+        ///
+        /// Runnable function that will be called when a job is received
+        /// by the agent
+        ///
+        pub async fn s3_runner(envelope: Envelope) -> Result<Job, templemeads::Error>
+        {
+            let job = envelope.job();
+
+            match job.instruction() {
+                AddLocalProject(mapping) => {
+                    objectstore::add_project(&mapping, job.expires()).await?;
+                    job.completed_none()
+                },
+                RemoveLocalProject(mapping) => {
+                    // the bucket is only deleted if delete-buckets is set
+                    objectstore::remove_project(&mapping, job.expires()).await?;
+                    job.completed_none()
+                },
+                AddLocalUser(mapping) => {
+                    objectstore::add_user(&mapping, job.expires()).await?;
+                    job.completed_none()
+                },
+                RemoveLocalUser(mapping) => {
+                    objectstore::remove_user(&mapping, job.expires()).await?;
+                    job.completed_none()
+                },
+                GetLocalProjectDirs(mapping) => {
+                    job.completed(objectstore::get_project_dirs(&mapping)?)
+                },
+                GetLocalUserDirs(_) => {
+                    // members only have access keys, not directories
+                    job.completed(Vec::<String>::new())
+                },
+                SetLocalProjectQuota(mapping, volume, limit) => {
+                    let quota = objectstore::set_project_quota(&mapping, &volume, &limit, job.expires()).await?;
+                    job.completed(quota)
+                },
+                GetLocalProjectQuota(mapping, volume) => {
+                    let quota = objectstore::get_project_quota(&mapping, &volume, job.expires()).await?;
+                    job.completed(quota)
+                },
+                GetLocalProjectQuotas(mapping) => {
+                    let quotas = objectstore::get_project_quotas(&mapping, job.expires()).await?;
+                    job.completed(quotas)
+                },
+                ClearLocalProjectQuota(mapping, volume) => {
+                    objectstore::clear_project_quota(&mapping, &volume, job.expires()).await?;
+                    job.completed_none()
+                },
+                GetLocalStorageReport(mapping, dates) => {
+                    let today = Date::today().day();
+                    if dates != today {
+                        return job.errored(&format!(
+                            "Storage reports only support today's date; requested range: {}",
+                            dates
+                        ));
+                    }
+
+                    let mut report = ProjectStorageReport::new(mapping.project());
+                    report.set_project_quotas(objectstore::get_project_quotas(&mapping, job.expires()).await?);
+                    job.completed(report)
+                },
+                _ => {
+                    Err(Error::InvalidInstruction(
+                        format!("Invalid instruction: {}. S3 agents do not support this instruction", job.instruction()),
+                    ))
+                }
+            }
+        }
+    }
+
+    set_notify_runner(default_notify_runner).await?;
+    run(config, s3_runner).await?;
+
+    Ok(())
+}
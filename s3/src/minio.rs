@@ -0,0 +1,339 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use templemeads::Error;
+
+use crate::objectstore::{get_settings, load_access_key, run_checked, run_command, Credentials};
+
+///
+/// Return "<alias>/<bucket>", the path of the bucket used by mc
+///
+fn target(bucket: &str) -> Result<String, Error> {
+    Ok(format!("{}/{}", get_settings()?.alias(), bucket))
+}
+
+///
+/// Return the name of the policy that grants access to the bucket
+///
+fn policy_name(bucket: &str) -> String {
+    format!("op-{}", bucket)
+}
+
+///
+/// Return a random key of the passed length, made from letters and digits
+///
+fn random_key(length: usize) -> String {
+    let mut key = String::new();
+
+    while key.len() < length {
+        key.push_str(&uuid::Uuid::new_v4().simple().to_string());
+    }
+
+    key.truncate(length);
+    key.to_uppercase()
+}
+
+///
+/// Return whether or not the MinIO user exists
+///
+async fn user_exists(alias: &str, user: &str) -> Result<bool, Error> {
+    let (code, _, _) = run_command(&["admin", "user", "info", alias, user]).await?;
+    Ok(code == 0)
+}
+
+///
+/// Create the policy that grants full access to the bucket and its objects
+///
+async fn create_policy(alias: &str, bucket: &str) -> Result<(), Error> {
+    let policy = serde_json::json!({
+        "Version": "2012-10-17",
+        "Statement": [{
+            "Effect": "Allow",
+            "Action": ["s3:*"],
+            "Resource": [
+                format!("arn:aws:s3:::{}", bucket),
+                format!("arn:aws:s3:::{}/*", bucket),
+            ],
+        }],
+    });
+
+    let path = std::env::temp_dir().join(format!("{}.json", policy_name(bucket)));
+    tokio::fs::write(&path, serde_json::to_string_pretty(&policy)?).await?;
+
+    let result = run_checked(&[
+        "admin",
+        "policy",
+        "create",
+        alias,
+        &policy_name(bucket),
+        &path.to_string_lossy(),
+    ])
+    .await;
+
+    let _ = tokio::fs::remove_file(&path).await;
+
+    result.map(|_| ())
+}
+
+///
+/// Create the project's bucket, and a MinIO user that owns the access
+/// keys of its members, or re-enable the user if the project was
+/// previously removed
+///
+pub async fn add_project(bucket: &str) -> Result<(), Error> {
+    let alias = get_settings()?.alias();
+
+    run_checked(&["mb", "--ignore-existing", &target(bucket)?]).await?;
+
+    if user_exists(alias, bucket).await? {
+        run_checked(&["admin", "user", "enable", alias, bucket]).await?;
+        tracing::info!("Re-enabled MinIO user {}", bucket);
+        return Ok(());
+    }
+
+    create_policy(alias, bucket).await?;
+
+    // members only ever use service accounts, so the secret of the
+    // parent user is not needed again
+    run_checked(&["admin", "user", "add", alias, bucket, &random_key(40)]).await?;
+
+    run_checked(&[
+        "admin",
+        "policy",
+        "attach",
+        alias,
+        &policy_name(bucket),
+        "--user",
+        bucket,
+    ])
+    .await?;
+
+    tracing::info!("Created bucket {} and its MinIO user", bucket);
+
+    Ok(())
+}
+
+///
+/// Disable the project's MinIO user, so that none of its members' keys
+/// can be used, or delete the bucket, its data and the user if `delete`
+/// is set
+///
+pub async fn remove_project(bucket: &str, delete: bool) -> Result<(), Error> {
+    let alias = get_settings()?.alias();
+
+    if !user_exists(alias, bucket).await? {
+        tracing::warn!("MinIO user {} does not exist", bucket);
+        return Ok(());
+    }
+
+    match delete {
+        true => {
+            run_checked(&["rb", "--force", &target(bucket)?]).await?;
+            run_checked(&["admin", "user", "rm", alias, bucket]).await?;
+            run_checked(&["admin", "policy", "rm", alias, &policy_name(bucket)]).await?;
+            tracing::info!("Deleted bucket {} and its MinIO user", bucket);
+        }
+        false => {
+            run_checked(&["admin", "user", "disable", alias, bucket]).await?;
+            tracing::info!("Disabled MinIO user {}", bucket);
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Create a service account of the project's MinIO user for the
+/// member. Returns the new credentials, or None if the member already
+/// has a service account.
+///
+pub async fn add_user(bucket: &str, local_user: &str) -> Result<Option<Credentials>, Error> {
+    let alias = get_settings()?.alias();
+
+    if !user_exists(alias, bucket).await? {
+        return Err(Error::InvalidState(format!(
+            "Cannot add {} as the MinIO user {} does not exist",
+            local_user, bucket
+        )));
+    }
+
+    if load_access_key(bucket, local_user).await?.is_some() {
+        tracing::info!("{} already has an access key to {}", local_user, bucket);
+        return Ok(None);
+    }
+
+    let credentials = Credentials {
+        access_key: random_key(20),
+        secret_key: random_key(40),
+    };
+
+    run_checked(&[
+        "admin",
+        "user",
+        "svcacct",
+        "add",
+        alias,
+        bucket,
+        "--access-key",
+        &credentials.access_key,
+        "--secret-key",
+        &credentials.secret_key,
+        "--name",
+        local_user,
+    ])
+    .await?;
+
+    tracing::info!("Created an access key to {} for {}", bucket, local_user);
+
+    Ok(Some(credentials))
+}
+
+///
+/// Delete the member's service account
+///
+pub async fn remove_user(bucket: &str, local_user: &str) -> Result<(), Error> {
+    let alias = get_settings()?.alias();
+
+    let access_key = match load_access_key(bucket, local_user).await? {
+        Some(access_key) => access_key,
+        None => {
+            tracing::warn!("{} has no access key to {}", local_user, bucket);
+            return Ok(());
+        }
+    };
+
+    let (code, _, stderr) =
+        run_command(&["admin", "user", "svcacct", "rm", alias, &access_key]).await?;
+
+    match code {
+        0 => tracing::info!("Removed the access key of {} to {}", local_user, bucket),
+        _ => tracing::warn!(
+            "Could not remove the access key of {} to {}: {}",
+            local_user,
+            bucket,
+            stderr.trim()
+        ),
+    }
+
+    Ok(())
+}
+
+///
+/// Return the hard quota of the bucket in bytes, or None if it has no
+/// quota
+///
+pub async fn get_quota(bucket: &str) -> Result<Option<u64>, Error> {
+    let output = run_checked(&["quota", "info", &target(bucket)?, "--json"]).await?;
+
+    let info: serde_json::Value = serde_json::from_str(&output)?;
+
+    // a quota of zero means no quota
+    Ok(info
+        .get("quota")
+        .and_then(|q| q.as_u64())
+        .filter(|q| *q > 0))
+}
+
+///
+/// Set the hard quota of the bucket, or clear it if `bytes` is None
+///
+pub async fn set_quota(bucket: &str, bytes: Option<u64>) -> Result<(), Error> {
+    let target = target(bucket)?;
+
+    match bytes {
+        Some(bytes) => {
+            run_checked(&["quota", "set", &target, "--size", &bytes.to_string()]).await?;
+        }
+        None => {
+            run_checked(&["quota", "clear", &target]).await?;
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Return the bytes stored in the bucket
+///
+pub async fn get_usage(bucket: &str) -> Result<u64, Error> {
+    let output = run_checked(&["du", &target(bucket)?, "--json"]).await?;
+
+    let usage: serde_json::Value = serde_json::from_str(&output)?;
+
+    usage
+        .get("size")
+        .and_then(|s| s.as_u64())
+        .ok_or_else(|| Error::Parse(format!("Could not read the usage of bucket {}", bucket)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objectstore::tests::{fake_admin, logged, set_output};
+
+    #[test]
+    fn test_names() {
+        assert_eq!(policy_name("op-proj"), "op-op-proj");
+
+        let key = random_key(45);
+        assert_eq!(key.len(), 45);
+        assert!(key
+            .chars()
+            .all(|c| c.is_ascii_digit() || c.is_ascii_uppercase()));
+        assert_ne!(random_key(20), random_key(20));
+    }
+
+    #[tokio::test]
+    async fn test_minio() {
+        fake_admin();
+
+        assert!(matches!(target("bucket").as_deref(), Ok("myminio/bucket")));
+
+        // existing users are re-enabled rather than created again
+        set_output("admin user info myminio op-again", "{}");
+
+        add_project("op-again")
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add project: {}", e));
+
+        assert_eq!(
+            logged("op-again"),
+            [
+                "mb --ignore-existing myminio/op-again",
+                "admin user info myminio op-again",
+                "admin user enable myminio op-again",
+            ]
+        );
+
+        // deleting removes the bucket, user and policy
+        remove_project("op-again", true)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove project: {}", e));
+
+        assert_eq!(
+            &logged("op-again")[4..],
+            [
+                "rb --force myminio/op-again",
+                "admin user rm myminio op-again",
+                "admin policy rm myminio op-op-again",
+            ]
+        );
+
+        // members can only be added to projects that exist
+        assert!(matches!(
+            add_user("op-missing", "alice").await,
+            Err(Error::InvalidState(_))
+        ));
+
+        // a member without a saved key has nothing to remove
+        assert!(remove_user("op-missing", "alice").await.is_ok());
+        assert!(logged("svcacct rm").iter().all(|l| !l.contains("missing")));
+
+        // buckets without a quota have a quota of zero
+        set_output("quota info myminio/op-none --json", r#"{"quota": 0}"#);
+        assert!(matches!(get_quota("op-none").await, Ok(None)));
+
+        set_output("du myminio/op-none --json", r#"{"objects": 0}"#);
+        assert!(matches!(get_usage("op-none").await, Err(Error::Parse(_))));
+    }
+}
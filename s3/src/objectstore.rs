@@ -0,0 +1,842 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+use chrono::Utc;
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use templemeads::exec;
+use templemeads::grammar::{ProjectMapping, UserMapping};
+use templemeads::job::assert_not_expired;
+use templemeads::storage::{Quota, QuotaLimit, StorageSize, StorageUsage, Volume};
+use templemeads::Error;
+
+use crate::{minio, rgw};
+
+static SETTINGS: OnceCell<Settings> = OnceCell::new();
+
+/// The shortest and longest bucket names allowed by S3
+const MIN_BUCKET_NAME: usize = 3;
+const MAX_BUCKET_NAME: usize = 63;
+
+/// The AWS variables that are passed to the admin command, e.g. for its
+/// credentials and region. These are not passed to the commands of
+/// other agents
+const AWS_ENV: [&str; 6] = [
+    "AWS_ACCESS_KEY_ID",
+    "AWS_SECRET_ACCESS_KEY",
+    "AWS_SESSION_TOKEN",
+    "AWS_REGION",
+    "AWS_DEFAULT_REGION",
+    "AWS_CA_BUNDLE",
+];
+
+///
+/// The object storage service that is managed by this agent
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    /// Ceph RADOS Gateway, managed with radosgw-admin
+    Rgw,
+    /// MinIO, managed with the mc client
+    Minio,
+}
+
+impl Backend {
+    pub fn parse(backend: &str) -> Result<Self, Error> {
+        match backend.trim().to_lowercase().as_str() {
+            "rgw" | "ceph" => Ok(Backend::Rgw),
+            "minio" => Ok(Backend::Minio),
+            _ => Err(Error::Misconfigured(format!(
+                "Unknown object storage backend '{}'. Use 'rgw' or 'minio'",
+                backend
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Backend::Rgw => write!(f, "rgw"),
+            Backend::Minio => write!(f, "minio"),
+        }
+    }
+}
+
+///
+/// The settings of this agent. The command is stored as a pre-split
+/// list of tokens so that prefixes like "ssh ceph-admin radosgw-admin"
+/// work without any shell quoting issues.
+///
+pub struct Settings {
+    backend: Backend,
+    command: Vec<String>,
+    alias: String,
+    endpoint: String,
+    bucket_prefix: String,
+    volume: Volume,
+    credentials_dir: Option<PathBuf>,
+    delete_buckets: bool,
+}
+
+impl Settings {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        backend: Backend,
+        command: &str,
+        alias: &str,
+        endpoint: &str,
+        bucket_prefix: &str,
+        volume: &str,
+        credentials_dir: &str,
+        delete_buckets: bool,
+    ) -> Result<Self, Error> {
+        let credentials_dir = match credentials_dir.trim().is_empty() {
+            true => None,
+            false => Some(PathBuf::from(credentials_dir.trim())),
+        };
+
+        // MinIO never shows a secret key again once it has been created,
+        // so the keys of new members must be saved somewhere
+        if backend == Backend::Minio && credentials_dir.is_none() {
+            return Err(Error::Misconfigured(
+                "A credentials-dir must be set when using MinIO, as the secret \
+                 keys of members cannot be read back from MinIO"
+                    .to_owned(),
+            ));
+        }
+
+        if backend == Backend::Minio && alias.trim().is_empty() {
+            return Err(Error::Misconfigured(
+                "An mc alias must be set when using MinIO".to_owned(),
+            ));
+        }
+
+        Ok(Self {
+            backend,
+            command: command.split_whitespace().map(|p| p.to_owned()).collect(),
+            alias: alias.trim().to_owned(),
+            endpoint: endpoint.trim().to_owned(),
+            bucket_prefix: bucket_prefix.trim().to_owned(),
+            volume: Volume::new(volume.trim()),
+            credentials_dir,
+            delete_buckets,
+        })
+    }
+
+    pub fn alias(&self) -> &str {
+        &self.alias
+    }
+}
+
+pub fn initialise(settings: Settings) -> Result<()> {
+    SETTINGS
+        .set(settings)
+        .map_err(|_| anyhow::anyhow!("Settings already initialised"))
+}
+
+///
+/// Add the AWS variables needed by the admin command to the allowlist
+/// of the command policy. This must be called after the agent's
+/// options have been applied, as these set the policy
+///
+pub fn allow_aws_env() -> Result<(), Error> {
+    let mut policy = exec::policy();
+
+    for name in AWS_ENV {
+        policy.allow(name)?;
+    }
+
+    exec::set_policy(policy);
+
+    Ok(())
+}
+
+pub fn get_settings() -> Result<&'static Settings, Error> {
+    SETTINGS
+        .get()
+        .ok_or_else(|| Error::Call("Settings not initialised".to_owned()))
+}
+
+///
+/// Run the admin command (radosgw-admin or mc) with the passed args.
+/// Returns (exit_code, stdout, stderr).
+///
+pub async fn run_command(args: &[&str]) -> Result<(i32, String, String), Error> {
+    let parts = &get_settings()?.command;
+
+    let output = exec::Command::from_parts(parts).args(args).output().await?;
+    let (exit_code, stdout, stderr) = (output.code, output.stdout, output.stderr);
+
+    // output is not logged, as it can contain secret keys
+    tracing::debug!("Command exit code: {}", exit_code);
+
+    Ok((exit_code, stdout, stderr))
+}
+
+///
+/// Run the admin command, returning its output, or an error if it fails
+///
+pub async fn run_checked(args: &[&str]) -> Result<String, Error> {
+    let (code, stdout, stderr) = run_command(args).await?;
+
+    match code {
+        0 => Ok(stdout),
+        _ => Err(Error::Call(format!(
+            "{} failed (exit {}): {}",
+            args.iter().take(3).cloned().collect::<Vec<_>>().join(" "),
+            code,
+            stderr.trim()
+        ))),
+    }
+}
+
+///
+/// Return the name of the bucket (and, for Ceph RGW, the user) of the
+/// passed local project group. Bucket names must be 3-63 characters of
+/// lowercase letters, digits, '-' and '.', starting and ending with a
+/// letter or digit.
+///
+pub fn bucket_name(local_group: &str) -> Result<String, Error> {
+    let settings = get_settings()?;
+
+    let name: String = format!("{}{}", settings.bucket_prefix, local_group)
+        .to_lowercase()
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                true => c,
+                false => '-',
+            },
+        )
+        .collect();
+
+    let name = name.trim_matches(|c| c == '-' || c == '.').to_owned();
+
+    if name.len() < MIN_BUCKET_NAME || name.len() > MAX_BUCKET_NAME {
+        return Err(Error::InvalidState(format!(
+            "Cannot use '{}' as a bucket name, as it must be between {} and {} characters",
+            name, MIN_BUCKET_NAME, MAX_BUCKET_NAME
+        )));
+    }
+
+    Ok(name)
+}
+
+///
+/// The access key of a member of a project
+///
+#[derive(Clone, PartialEq)]
+pub struct Credentials {
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+/// Never print the secret key
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("access_key", &self.access_key)
+            .field("secret_key", &"****")
+            .finish()
+    }
+}
+
+///
+/// Return the path of the file holding the member's credentials
+///
+fn credentials_path(bucket: &str, local_user: &str) -> Result<Option<PathBuf>, Error> {
+    Ok(get_settings()?
+        .credentials_dir
+        .as_ref()
+        .map(|dir| dir.join(bucket).join(format!("{}.json", local_user))))
+}
+
+///
+/// Save the member's new credentials to the credentials directory (if
+/// set), so that they can be passed on to the member. The file is only
+/// readable by the agent's user.
+///
+async fn save_credentials(
+    bucket: &str,
+    local_user: &str,
+    credentials: &Credentials,
+) -> Result<(), Error> {
+    let path = match credentials_path(bucket, local_user)? {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    let settings = get_settings()?;
+
+    let contents = serde_json::to_string_pretty(&serde_json::json!({
+        "endpoint": settings.endpoint,
+        "bucket": bucket,
+        "access_key": credentials.access_key,
+        "secret_key": credentials.secret_key,
+    }))?;
+
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    // write to a temporary file with restricted permissions, then
+    // rename, so that a partially-written file is never seen
+    let tmp = path.with_extension("json.tmp");
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options.open(&tmp).await?;
+    tokio::io::AsyncWriteExt::write_all(&mut file, contents.as_bytes()).await?;
+    file.sync_all().await?;
+    tokio::fs::rename(&tmp, &path).await?;
+
+    tracing::info!(
+        "Saved the credentials of {} to {}",
+        local_user,
+        path.display()
+    );
+
+    Ok(())
+}
+
+///
+/// Return the saved access key of the member, if any
+///
+pub async fn load_access_key(bucket: &str, local_user: &str) -> Result<Option<String>, Error> {
+    let path = match credentials_path(bucket, local_user)? {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+
+    match tokio::fs::read_to_string(&path).await {
+        Ok(contents) => Ok(serde_json::from_str::<serde_json::Value>(&contents)?
+            .get("access_key")
+            .and_then(|key| key.as_str())
+            .map(|key| key.to_owned())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+///
+/// Remove the member's saved credentials, if any
+///
+async fn remove_credentials(bucket: &str, local_user: &str) -> Result<(), Error> {
+    if let Some(path) = credentials_path(bucket, local_user)? {
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Create the bucket of the project (and, for Ceph RGW, the user that
+/// owns it), or restore access if the project was previously removed
+///
+pub async fn add_project(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let bucket = bucket_name(project.local_group())?;
+
+    match get_settings()?.backend {
+        Backend::Rgw => rgw::add_project(&bucket, project).await,
+        Backend::Minio => minio::add_project(&bucket).await,
+    }
+}
+
+///
+/// Remove access to the project's bucket. The bucket and its data are
+/// only deleted if delete-buckets is set.
+///
+pub async fn remove_project(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let settings = get_settings()?;
+    let bucket = bucket_name(project.local_group())?;
+
+    match settings.backend {
+        Backend::Rgw => rgw::remove_project(&bucket, settings.delete_buckets).await,
+        Backend::Minio => minio::remove_project(&bucket, settings.delete_buckets).await,
+    }
+}
+
+///
+/// Create an access key to the project's bucket for the member, if they
+/// do not already have one
+///
+pub async fn add_user(user: &UserMapping, expires: &chrono::DateTime<Utc>) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let bucket = bucket_name(user.local_group())?;
+
+    let credentials = match get_settings()?.backend {
+        Backend::Rgw => rgw::add_user(&bucket, user.local_user()).await?,
+        Backend::Minio => minio::add_user(&bucket, user.local_user()).await?,
+    };
+
+    if let Some(credentials) = credentials {
+        save_credentials(&bucket, user.local_user(), &credentials).await?;
+    }
+
+    Ok(())
+}
+
+///
+/// Delete the member's access key to the project's bucket
+///
+pub async fn remove_user(user: &UserMapping, expires: &chrono::DateTime<Utc>) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let bucket = bucket_name(user.local_group())?;
+
+    match get_settings()?.backend {
+        Backend::Rgw => rgw::remove_user(&bucket, user.local_user()).await?,
+        Backend::Minio => minio::remove_user(&bucket, user.local_user()).await?,
+    }
+
+    remove_credentials(&bucket, user.local_user()).await
+}
+
+///
+/// Check that the volume is the one served by this agent
+///
+fn assert_volume(volume: &Volume) -> Result<(), Error> {
+    let settings = get_settings()?;
+
+    match *volume == settings.volume {
+        true => Ok(()),
+        false => Err(Error::InvalidInstruction(format!(
+            "Unknown volume {}. This agent only manages the volume {}",
+            volume, settings.volume
+        ))),
+    }
+}
+
+///
+/// Return the bytes stored by the project
+///
+async fn get_usage(bucket: &str) -> Result<u64, Error> {
+    match get_settings()?.backend {
+        Backend::Rgw => rgw::get_usage(bucket).await,
+        Backend::Minio => minio::get_usage(bucket).await,
+    }
+}
+
+///
+/// Return the project's quota on the volume, including its usage
+///
+pub async fn get_project_quota(
+    project: &ProjectMapping,
+    volume: &Volume,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Quota, Error> {
+    assert_not_expired(expires)?;
+    assert_volume(volume)?;
+
+    let bucket = bucket_name(project.local_group())?;
+
+    let limit = match get_settings()?.backend {
+        Backend::Rgw => rgw::get_quota(&bucket).await?,
+        Backend::Minio => minio::get_quota(&bucket).await?,
+    };
+
+    let limit = match limit {
+        Some(bytes) => QuotaLimit::from(StorageSize::from_bytes(bytes)),
+        None => QuotaLimit::Unlimited,
+    };
+
+    let usage = StorageUsage::from(get_usage(&bucket).await?);
+
+    Ok(Quota::with_usage(limit, usage))
+}
+
+///
+/// Return the project's quotas on every volume served by this agent
+///
+pub async fn get_project_quotas(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<HashMap<Volume, Quota>, Error> {
+    let volume = get_settings()?.volume.clone();
+    let quota = get_project_quota(project, &volume, expires).await?;
+
+    Ok(HashMap::from([(volume, quota)]))
+}
+
+///
+/// Set the project's quota on the volume, e.g. from the size of its
+/// storage allocation
+///
+pub async fn set_project_quota(
+    project: &ProjectMapping,
+    volume: &Volume,
+    limit: &QuotaLimit,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Quota, Error> {
+    assert_not_expired(expires)?;
+    assert_volume(volume)?;
+
+    let bucket = bucket_name(project.local_group())?;
+    let bytes = limit.size().map(|size| size.as_bytes());
+
+    match get_settings()?.backend {
+        Backend::Rgw => rgw::set_quota(&bucket, bytes).await?,
+        Backend::Minio => minio::set_quota(&bucket, bytes).await?,
+    }
+
+    tracing::info!("Set the quota of {} to {}", bucket, limit);
+
+    get_project_quota(project, volume, expires).await
+}
+
+///
+/// Remove the project's quota on the volume
+///
+pub async fn clear_project_quota(
+    project: &ProjectMapping,
+    volume: &Volume,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    set_project_quota(project, volume, &QuotaLimit::Unlimited, expires).await?;
+    Ok(())
+}
+
+///
+/// Return the URL of the project's bucket, which is used as the
+/// project's only "directory"
+///
+pub fn get_project_dirs(project: &ProjectMapping) -> Result<Vec<String>, Error> {
+    Ok(vec![format!(
+        "s3://{}",
+        bucket_name(project.local_group())?
+    )])
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Once;
+    use templemeads::grammar::{ProjectIdentifier, UserIdentifier};
+
+    static FAKE_ADMIN: Once = Once::new();
+
+    ///
+    /// Return the directory of a fake admin command (mc or radosgw-admin),
+    /// used by the tests of all modules. Every call is logged to
+    /// "admin.log". The output of a call is read from the file in "out"
+    /// named after its arguments (with ' ' as '_' and '/' as '%'), and
+    /// a call fails if that file ends in ".fail". Calls without an
+    /// output file succeed silently, except for "info" calls, which
+    /// fail as if the user or bucket does not exist. Calls for anything
+    /// "broken" always fail.
+    ///
+    pub(crate) fn fake_admin() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("op-s3-test-{}", std::process::id()));
+
+        FAKE_ADMIN.call_once(|| {
+            let _ = std::fs::remove_dir_all(&dir);
+            let _ = std::fs::create_dir_all(dir.join("out"));
+
+            let script = format!(
+                r#"dir={dir}
+echo "$*" >> $dir/admin.log
+case "$*" in *broken*) echo "ERROR: access denied" >&2; exit 13 ;; esac
+out=$dir/out/$(echo "$*" | tr '/ ' '%_')
+if [ -f "$out" ]; then cat "$out"; exit 0; fi
+if [ -f "$out.fail" ]; then cat "$out.fail" >&2; exit 1; fi
+case "$*" in *" info "*) echo "ERROR: does not exist" >&2; exit 22 ;; esac
+"#,
+                dir = dir.display()
+            );
+
+            let _ = std::fs::write(dir.join("admin.sh"), script);
+
+            let settings = Settings::new(
+                Backend::Minio,
+                &format!("sh {}", dir.join("admin.sh").display()),
+                "myminio",
+                "https://s3.example.org",
+                "op-",
+                "s3",
+                &dir.join("credentials").display().to_string(),
+                false,
+            );
+
+            if let Ok(settings) = settings {
+                let _ = initialise(settings);
+            }
+        });
+
+        dir
+    }
+
+    ///
+    /// Set the output of the admin command when called with `args`
+    ///
+    pub(crate) fn set_output(args: &str, output: &str) {
+        let name = args.replace(' ', "_").replace('/', "%");
+
+        std::fs::write(fake_admin().join("out").join(name), output)
+            .unwrap_or_else(|e| unreachable!("Cannot write output: {}", e));
+    }
+
+    ///
+    /// Return the calls of the admin command that contain `text`
+    ///
+    pub(crate) fn logged(text: &str) -> Vec<String> {
+        std::fs::read_to_string(fake_admin().join("admin.log"))
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| line.contains(text))
+            .map(|line| line.to_owned())
+            .collect()
+    }
+
+    fn project(name: &str) -> ProjectMapping {
+        ProjectMapping::new(
+            &ProjectIdentifier::parse(&format!("{}.portal", name))
+                .unwrap_or_else(|e| unreachable!("Cannot parse project: {}", e)),
+            name,
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e))
+    }
+
+    fn user(name: &str, project: &str) -> UserMapping {
+        UserMapping::new(
+            &UserIdentifier::parse(&format!("{}.{}.portal", name, project))
+                .unwrap_or_else(|e| unreachable!("Cannot parse user: {}", e)),
+            name,
+            project,
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e))
+    }
+
+    fn expires() -> chrono::DateTime<Utc> {
+        Utc::now() + chrono::Duration::seconds(30)
+    }
+
+    #[test]
+    fn test_backend() {
+        assert!(matches!(Backend::parse(" Ceph "), Ok(Backend::Rgw)));
+        assert!(matches!(Backend::parse("rgw"), Ok(Backend::Rgw)));
+        assert!(matches!(Backend::parse("MinIO"), Ok(Backend::Minio)));
+        assert!(matches!(
+            Backend::parse("swift"),
+            Err(Error::Misconfigured(_))
+        ));
+        assert_eq!(Backend::Rgw.to_string(), "rgw");
+    }
+
+    #[test]
+    fn test_settings() {
+        let settings = |backend, alias, credentials_dir| {
+            Settings::new(backend, "mc", alias, "", "", "s3", credentials_dir, false)
+        };
+
+        // minio needs somewhere to save secret keys, and an alias
+        assert!(matches!(
+            settings(Backend::Minio, "myminio", " "),
+            Err(Error::Misconfigured(_))
+        ));
+        assert!(matches!(
+            settings(Backend::Minio, "", "/tmp/credentials"),
+            Err(Error::Misconfigured(_))
+        ));
+        assert!(settings(Backend::Minio, "myminio", "/tmp/credentials").is_ok());
+        assert!(settings(Backend::Rgw, "", "").is_ok());
+    }
+
+    #[test]
+    fn test_allow_aws_env() {
+        allow_aws_env().unwrap_or_else(|e| {
+            unreachable!("Cannot allow AWS variables: {}", e);
+        });
+
+        let policy = exec::policy();
+
+        assert!(policy.allows("AWS_ACCESS_KEY_ID"));
+        assert!(policy.allows("AWS_REGION"));
+        assert!(!policy.allows("AWS_SHARED_CREDENTIALS_FILE"));
+    }
+
+    #[test]
+    fn test_credentials_are_not_printed() {
+        let credentials = Credentials {
+            access_key: "AKEY".to_owned(),
+            secret_key: "SECRET".to_owned(),
+        };
+
+        let printed = format!("{:?}", credentials);
+
+        assert!(printed.contains("AKEY"));
+        assert!(!printed.contains("SECRET"));
+    }
+
+    #[tokio::test]
+    async fn test_objectstore() {
+        let dir = fake_admin();
+
+        assert!(matches!(bucket_name("proj").as_deref(), Ok("op-proj")));
+        assert!(matches!(bucket_name("A_B.").as_deref(), Ok("op-a-b")));
+        assert!(matches!(
+            bucket_name(&"x".repeat(64)),
+            Err(Error::InvalidState(_))
+        ));
+
+        let project = project("store");
+        let alice = user("alice", "store");
+
+        assert!(get_project_dirs(&project).is_ok_and(|dirs| dirs == ["s3://op-store"]));
+
+        // a new project gets a bucket, and a user with access to it
+        add_project(&project, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add project: {}", e));
+
+        let calls = logged("op-store");
+        assert_eq!(calls[0], "mb --ignore-existing myminio/op-store");
+        assert_eq!(calls[1], "admin user info myminio op-store");
+        assert!(calls[2].starts_with("admin policy create myminio op-op-store "));
+        assert!(calls[3].starts_with("admin user add myminio op-store "));
+        assert_eq!(
+            calls[4],
+            "admin policy attach myminio op-op-store --user op-store"
+        );
+
+        // the user now exists
+        set_output("admin user info myminio op-store", "{}");
+
+        add_user(&alice, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add user: {}", e));
+
+        // the new key is saved for the member, readable only by the agent
+        let path = dir.join("credentials").join("op-store").join("alice.json");
+        let saved: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap_or_default())
+                .unwrap_or_else(|e| unreachable!("Invalid credentials: {}", e));
+
+        assert_eq!(saved["endpoint"], "https://s3.example.org");
+        assert_eq!(saved["bucket"], "op-store");
+
+        let access_key = saved["access_key"].as_str().unwrap_or_default().to_owned();
+        assert_eq!(access_key.len(), 20);
+        assert_eq!(saved["secret_key"].as_str().map(|k| k.len()), Some(40));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert!(std::fs::metadata(&path).is_ok_and(|m| m.permissions().mode() & 0o777 == 0o600));
+        }
+
+        assert!(load_access_key("op-store", "alice")
+            .await
+            .is_ok_and(|key| key.as_deref() == Some(access_key.as_str())));
+
+        // members only get one key
+        add_user(&alice, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add user: {}", e));
+
+        assert_eq!(logged("svcacct add myminio op-store").len(), 1);
+
+        remove_user(&alice, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove user: {}", e));
+
+        assert_eq!(
+            logged("svcacct rm"),
+            [format!("admin user svcacct rm myminio {}", access_key)]
+        );
+        assert!(!path.exists());
+
+        // quotas are the hard quota of the bucket
+        set_output("quota info myminio/op-store --json", r#"{"quota": 1000}"#);
+        set_output("du myminio/op-store --json", r#"{"size": 250}"#);
+
+        let volume = Volume::new("s3");
+
+        let quota = get_project_quota(&project, &volume, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot get quota: {}", e));
+
+        assert_eq!(quota.limit().size().map(|s| s.as_bytes()), Some(1000));
+        assert_eq!(quota.usage().map(|u| u.into_size().as_bytes()), Some(250));
+
+        assert!(get_project_quotas(&project, &expires())
+            .await
+            .is_ok_and(|quotas| quotas.len() == 1 && quotas.contains_key(&volume)));
+
+        set_project_quota(
+            &project,
+            &volume,
+            &QuotaLimit::from(StorageSize::from_bytes(2000)),
+            &expires(),
+        )
+        .await
+        .unwrap_or_else(|e| unreachable!("Cannot set quota: {}", e));
+
+        clear_project_quota(&project, &volume, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot clear quota: {}", e));
+
+        assert_eq!(
+            logged("quota set myminio/op-store"),
+            ["quota set myminio/op-store --size 2000"]
+        );
+        assert_eq!(
+            logged("quota clear myminio/op-store"),
+            ["quota clear myminio/op-store"]
+        );
+
+        // only the agent's volume can be used
+        assert!(matches!(
+            get_project_quota(&project, &Volume::new("home"), &expires()).await,
+            Err(Error::InvalidInstruction(_))
+        ));
+
+        // removing the project only disables its user
+        remove_project(&project, &expires())
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove project: {}", e));
+
+        assert_eq!(
+            logged("admin user disable"),
+            ["admin user disable myminio op-store"]
+        );
+
+        // failures of the admin command are errors
+        assert!(matches!(
+            add_project(&self::project("broken"), &expires()).await,
+            Err(Error::Call(message)) if message.contains("access denied")
+        ));
+
+        let expired = Utc::now() - chrono::Duration::seconds(1);
+
+        assert!(matches!(
+            add_user(&alice, &expired).await,
+            Err(Error::Expired(_))
+        ));
+        assert!(matches!(
+            get_project_quota(&project, &volume, &expired).await,
+            Err(Error::Expired(_))
+        ));
+    }
+}
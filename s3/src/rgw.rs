@@ -0,0 +1,428 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use templemeads::grammar::ProjectMapping;
+use templemeads::Error;
+
+use crate::objectstore::{run_checked, run_command, Credentials};
+
+///
+/// Return the `user info` JSON of the RGW user, or None if the user
+/// does not exist
+///
+async fn get_user_info(uid: &str) -> Result<Option<serde_json::Value>, Error> {
+    let (code, stdout, stderr) = run_command(&["user", "info", &format!("--uid={}", uid)]).await?;
+
+    match code {
+        0 => Ok(Some(serde_json::from_str(&stdout)?)),
+        _ => {
+            tracing::debug!("RGW user {} does not exist: {}", uid, stderr.trim());
+            Ok(None)
+        }
+    }
+}
+
+///
+/// Return the id of the subuser of the passed member
+///
+fn subuser_name(uid: &str, local_user: &str) -> String {
+    format!("{}:{}", uid, local_user)
+}
+
+///
+/// Create the RGW user that owns the project's buckets, or re-enable it
+/// if the project was previously removed
+///
+pub async fn add_project(uid: &str, project: &ProjectMapping) -> Result<(), Error> {
+    match get_user_info(uid).await? {
+        Some(info) => {
+            if info.get("suspended").and_then(|s| s.as_i64()).unwrap_or(0) != 0 {
+                run_checked(&["user", "enable", &format!("--uid={}", uid)]).await?;
+                tracing::info!("Re-enabled RGW user {} for {}", uid, project);
+            }
+        }
+        None => {
+            run_checked(&[
+                "user",
+                "create",
+                &format!("--uid={}", uid),
+                &format!("--display-name={}", project.project()),
+            ])
+            .await?;
+
+            tracing::info!("Created RGW user {} for {}", uid, project);
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Suspend the project's RGW user, so that none of its keys can be
+/// used, or delete it and all of its buckets if `delete` is set
+///
+pub async fn remove_project(uid: &str, delete: bool) -> Result<(), Error> {
+    if get_user_info(uid).await?.is_none() {
+        tracing::warn!("RGW user {} does not exist", uid);
+        return Ok(());
+    }
+
+    match delete {
+        true => {
+            run_checked(&["user", "rm", &format!("--uid={}", uid), "--purge-data"]).await?;
+            tracing::info!("Deleted RGW user {} and all of its buckets", uid);
+        }
+        false => {
+            run_checked(&["user", "suspend", &format!("--uid={}", uid)]).await?;
+            tracing::info!("Suspended RGW user {}", uid);
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Create a subuser with its own S3 key for the member. Returns the new
+/// credentials, or None if the member already has a subuser.
+///
+pub async fn add_user(uid: &str, local_user: &str) -> Result<Option<Credentials>, Error> {
+    let info = match get_user_info(uid).await? {
+        Some(info) => info,
+        None => {
+            return Err(Error::InvalidState(format!(
+                "Cannot add {} as the RGW user {} does not exist",
+                local_user, uid
+            )));
+        }
+    };
+
+    let subuser = subuser_name(uid, local_user);
+
+    let exists = info
+        .get("subusers")
+        .and_then(|s| s.as_array())
+        .map(|subusers| {
+            subusers
+                .iter()
+                .any(|s| s.get("id").and_then(|id| id.as_str()) == Some(subuser.as_str()))
+        })
+        .unwrap_or(false);
+
+    if exists {
+        tracing::info!("{} already has the subuser {}", local_user, subuser);
+        return Ok(None);
+    }
+
+    let output = run_checked(&[
+        "subuser",
+        "create",
+        &format!("--uid={}", uid),
+        &format!("--subuser={}", subuser),
+        "--access=full",
+        "--key-type=s3",
+        "--gen-access-key",
+        "--gen-secret",
+    ])
+    .await?;
+
+    let info: serde_json::Value = serde_json::from_str(&output)?;
+
+    // the user's "keys" include the S3 keys of all of its subusers
+    let credentials = info
+        .get("keys")
+        .and_then(|k| k.as_array())
+        .and_then(|keys| {
+            keys.iter()
+                .find(|k| k.get("user").and_then(|u| u.as_str()) == Some(subuser.as_str()))
+        })
+        .and_then(|key| {
+            Some(Credentials {
+                access_key: key.get("access_key")?.as_str()?.to_owned(),
+                secret_key: key.get("secret_key")?.as_str()?.to_owned(),
+            })
+        })
+        .ok_or_else(|| {
+            Error::Parse(format!(
+                "Could not find the S3 key of the new subuser {}",
+                subuser
+            ))
+        })?;
+
+    tracing::info!("Created the subuser {} for {}", subuser, local_user);
+
+    Ok(Some(credentials))
+}
+
+///
+/// Delete the member's subuser and its keys
+///
+pub async fn remove_user(uid: &str, local_user: &str) -> Result<(), Error> {
+    let subuser = subuser_name(uid, local_user);
+
+    let (code, _, stderr) = run_command(&[
+        "subuser",
+        "rm",
+        &format!("--uid={}", uid),
+        &format!("--subuser={}", subuser),
+        "--purge-keys",
+    ])
+    .await?;
+
+    match code {
+        0 => tracing::info!("Removed the subuser {}", subuser),
+        _ => tracing::warn!(
+            "Could not remove the subuser {}: {}",
+            subuser,
+            stderr.trim()
+        ),
+    }
+
+    Ok(())
+}
+
+///
+/// Return the user quota of the RGW user in bytes, or None if it has
+/// no quota
+///
+pub async fn get_quota(uid: &str) -> Result<Option<u64>, Error> {
+    let info = get_user_info(uid)
+        .await?
+        .ok_or_else(|| Error::InvalidState(format!("The RGW user {} does not exist", uid)))?;
+
+    let quota = info.get("user_quota");
+
+    let enabled = quota
+        .and_then(|q| q.get("enabled"))
+        .and_then(|e| e.as_bool())
+        .unwrap_or(false);
+
+    // RGW uses a negative max_size to mean unlimited
+    match enabled {
+        true => Ok(quota
+            .and_then(|q| q.get("max_size"))
+            .and_then(|m| m.as_i64())
+            .filter(|m| *m >= 0)
+            .map(|m| m as u64)),
+        false => Ok(None),
+    }
+}
+
+///
+/// Set the user quota of the RGW user, or disable it if `bytes` is None
+///
+pub async fn set_quota(uid: &str, bytes: Option<u64>) -> Result<(), Error> {
+    let uid = format!("--uid={}", uid);
+
+    match bytes {
+        Some(bytes) => {
+            run_checked(&[
+                "quota",
+                "set",
+                "--quota-scope=user",
+                &uid,
+                &format!("--max-size={}", bytes),
+            ])
+            .await?;
+
+            run_checked(&["quota", "enable", "--quota-scope=user", &uid]).await?;
+        }
+        None => {
+            run_checked(&["quota", "disable", "--quota-scope=user", &uid]).await?;
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Return the bytes stored in all of the buckets of the RGW user
+///
+pub async fn get_usage(uid: &str) -> Result<u64, Error> {
+    let output = run_checked(&["user", "stats", &format!("--uid={}", uid), "--sync-stats"]).await?;
+
+    let stats: serde_json::Value = serde_json::from_str(&output)?;
+
+    stats
+        .get("stats")
+        .and_then(|s| s.get("size").or_else(|| s.get("total_bytes")))
+        .and_then(|s| s.as_u64())
+        .ok_or_else(|| Error::Parse(format!("Could not read the usage of RGW user {}", uid)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objectstore::tests::{fake_admin, logged, set_output};
+    use templemeads::grammar::ProjectIdentifier;
+
+    fn project(name: &str) -> ProjectMapping {
+        ProjectMapping::new(
+            &ProjectIdentifier::parse(&format!("{}.portal", name))
+                .unwrap_or_else(|e| unreachable!("Cannot parse project: {}", e)),
+            name,
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create mapping: {}", e))
+    }
+
+    #[test]
+    fn test_subuser_name() {
+        assert_eq!(subuser_name("op-proj", "alice"), "op-proj:alice");
+    }
+
+    #[tokio::test]
+    async fn test_projects() {
+        fake_admin();
+
+        // new projects get a new RGW user
+        add_project("rgw-new", &project("new"))
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add project: {}", e));
+
+        assert_eq!(
+            logged("--uid=rgw-new"),
+            [
+                "user info --uid=rgw-new",
+                "user create --uid=rgw-new --display-name=new.portal",
+            ]
+        );
+
+        // suspended users are re-enabled...
+        set_output("user info --uid=rgw-old", r#"{"suspended": 1}"#);
+
+        add_project("rgw-old", &project("old"))
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot add project: {}", e));
+
+        // ...and removing the project suspends (or deletes) them again
+        remove_project("rgw-old", false)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove project: {}", e));
+
+        remove_project("rgw-old", true)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot remove project: {}", e));
+
+        assert_eq!(
+            logged("--uid=rgw-old"),
+            [
+                "user info --uid=rgw-old",
+                "user enable --uid=rgw-old",
+                "user info --uid=rgw-old",
+                "user suspend --uid=rgw-old",
+                "user info --uid=rgw-old",
+                "user rm --uid=rgw-old --purge-data",
+            ]
+        );
+
+        // removing a project that doesn't exist is not an error
+        assert!(remove_project("rgw-missing", true).await.is_ok());
+        assert!(logged("rm --uid=rgw-missing").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_users() {
+        fake_admin();
+
+        assert!(matches!(
+            add_user("rgw-missing", "alice").await,
+            Err(Error::InvalidState(_))
+        ));
+
+        set_output(
+            "user info --uid=rgw-users",
+            r#"{"subusers": [{"id": "rgw-users:bob"}]}"#,
+        );
+
+        // bob already has a subuser
+        assert!(matches!(add_user("rgw-users", "bob").await, Ok(None)));
+
+        // alice gets a new one, whose key is returned
+        set_output(
+            "subuser create --uid=rgw-users --subuser=rgw-users:alice --access=full \
+             --key-type=s3 --gen-access-key --gen-secret",
+            r#"{"keys": [
+                {"user": "rgw-users:bob", "access_key": "BOB", "secret_key": "bob-secret"},
+                {"user": "rgw-users:alice", "access_key": "ALICE", "secret_key": "alice-secret"}
+            ]}"#,
+        );
+
+        assert!(add_user("rgw-users", "alice").await.is_ok_and(|c| c
+            == Some(Credentials {
+                access_key: "ALICE".to_owned(),
+                secret_key: "alice-secret".to_owned(),
+            })));
+
+        // it is an error if the new key is not returned
+        set_output(
+            "subuser create --uid=rgw-users --subuser=rgw-users:carol --access=full \
+             --key-type=s3 --gen-access-key --gen-secret",
+            r#"{"keys": []}"#,
+        );
+
+        assert!(matches!(
+            add_user("rgw-users", "carol").await,
+            Err(Error::Parse(_))
+        ));
+
+        // failing to remove a subuser is only a warning
+        set_output(
+            "subuser rm --uid=rgw-users --subuser=rgw-users:dave --purge-keys.fail",
+            "no such subuser",
+        );
+
+        assert!(remove_user("rgw-users", "dave").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_quotas() {
+        fake_admin();
+
+        let quota = |info: &str| set_output("user info --uid=rgw-quota", info);
+
+        quota(r#"{"user_quota": {"enabled": true, "max_size": 1024}}"#);
+        assert!(matches!(get_quota("rgw-quota").await, Ok(Some(1024))));
+
+        quota(r#"{"user_quota": {"enabled": true, "max_size": -1}}"#);
+        assert!(matches!(get_quota("rgw-quota").await, Ok(None)));
+
+        quota(r#"{"user_quota": {"enabled": false, "max_size": 1024}}"#);
+        assert!(matches!(get_quota("rgw-quota").await, Ok(None)));
+
+        assert!(matches!(
+            get_quota("rgw-missing").await,
+            Err(Error::InvalidState(_))
+        ));
+
+        set_quota("rgw-quota", Some(2048))
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot set quota: {}", e));
+
+        set_quota("rgw-quota", None)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot clear quota: {}", e));
+
+        assert_eq!(
+            logged("quota-scope=user --uid=rgw-quota"),
+            [
+                "quota set --quota-scope=user --uid=rgw-quota --max-size=2048",
+                "quota enable --quota-scope=user --uid=rgw-quota",
+                "quota disable --quota-scope=user --uid=rgw-quota",
+            ]
+        );
+
+        set_output(
+            "user stats --uid=rgw-quota --sync-stats",
+            r#"{"stats": {"size": 4096}}"#,
+        );
+        assert!(matches!(get_usage("rgw-quota").await, Ok(4096)));
+
+        set_output(
+            "user stats --uid=rgw-quota --sync-stats",
+            r#"{"stats": {"total_bytes": 8192}}"#,
+        );
+        assert!(matches!(get_usage("rgw-quota").await, Ok(8192)));
+
+        set_output("user stats --uid=rgw-quota --sync-stats", "{}");
+        assert!(matches!(get_usage("rgw-quota").await, Err(Error::Parse(_))));
+    }
+}
@@ -0,0 +1,218 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Allocation banking - the usage of each project whose allocation has
+//! been set is periodically counted against that allocation, and the
+//! project's account is blocked once the allocation is exhausted
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use templemeads::destination::Destination;
+use templemeads::grammar::{Date, DateRange, ProjectMapping};
+use templemeads::notification::{self, NotificationEvent};
+use templemeads::usagereport::Usage;
+use templemeads::Error;
+use tokio::time::sleep;
+
+use crate::cache;
+use crate::sacctmgr;
+use crate::slurm::SlurmAccount;
+
+///
+/// The time (in minutes) allowed for each project to be checked,
+/// including fetching its usage report
+///
+const BANKING_TIMEOUT: i64 = 30;
+
+///
+/// The allocation of a single project, together with where to send
+/// notifications when the project's account is blocked or unblocked,
+/// and whether banking blocked the account
+///
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BankAccount {
+    mapping: ProjectMapping,
+    allocation: Usage,
+    since: Date,
+    destination: Destination,
+    #[serde(default)]
+    blocked: bool,
+}
+
+impl BankAccount {
+    pub fn mapping(&self) -> &ProjectMapping {
+        &self.mapping
+    }
+
+    pub fn allocation(&self) -> &Usage {
+        &self.allocation
+    }
+
+    pub fn since(&self) -> &Date {
+        &self.since
+    }
+
+    pub fn destination(&self) -> &Destination {
+        &self.destination
+    }
+
+    ///
+    /// Return whether banking blocked the project's account. Only
+    /// blocks that banking made are lifted by banking
+    ///
+    pub fn is_blocked(&self) -> bool {
+        self.blocked
+    }
+}
+
+///
+/// Record the allocation of the passed project, which is the limit that
+/// was last set on its account. Notifications about the project are sent
+/// along `destination`. Projects with a zero allocation are not banked
+///
+pub async fn set_allocation(
+    mapping: &ProjectMapping,
+    allocation: &Usage,
+    destination: &Destination,
+) -> Result<(), Error> {
+    let account = SlurmAccount::from_mapping(mapping)?;
+
+    if allocation.seconds() == 0 {
+        cache::remove_bank_account(account.name()).await?;
+        return Ok(());
+    }
+
+    // keep counting from the day that the allocation was first set,
+    // and remember whether banking has blocked the account
+    let (since, blocked) = match cache::get_bank_account(account.name()).await? {
+        Some(bank_account) => (bank_account.since().clone(), bank_account.is_blocked()),
+        None => (Date::today(), false),
+    };
+
+    cache::set_bank_account(
+        account.name(),
+        &BankAccount {
+            mapping: mapping.clone(),
+            allocation: *allocation,
+            since,
+            destination: destination.clone(),
+            blocked,
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+///
+/// Spawn the background task that checks the usage of every banked
+/// project every `interval` seconds
+///
+pub fn spawn_banking_task(interval: u64) {
+    tokio::spawn(async move {
+        loop {
+            sleep(Duration::from_secs(interval)).await;
+
+            let bank_accounts = match cache::get_bank_accounts().await {
+                Ok(bank_accounts) => bank_accounts,
+                Err(e) => {
+                    tracing::error!("Banking: could not get bank accounts: {}", e);
+                    continue;
+                }
+            };
+
+            for bank_account in bank_accounts {
+                if let Err(e) = check_account(&bank_account).await {
+                    tracing::error!(
+                        "Banking: could not check the allocation of {}: {}",
+                        bank_account.mapping(),
+                        e
+                    );
+                }
+            }
+        }
+    });
+}
+
+///
+/// Count the usage of the passed project against its allocation, blocking
+/// its account if the allocation is exhausted, or unblocking it if
+/// allocation is available again. Accounts that were blocked by anything
+/// other than banking (e.g. by an administrator) are never unblocked
+///
+async fn check_account(bank_account: &BankAccount) -> Result<(), Error> {
+    let expires = Utc::now() + chrono::Duration::minutes(BANKING_TIMEOUT);
+
+    let since = match cache::get_banking_start_date().await? {
+        Some(start_date) => start_date,
+        None => bank_account.since().clone(),
+    };
+
+    let dates = DateRange::from_chrono(&since.to_chrono(), &Date::today().to_chrono());
+
+    let usage = sacctmgr::get_usage_report(bank_account.mapping(), &dates, &expires)
+        .await?
+        .total_usage();
+
+    let project = bank_account.mapping().project();
+    let account = SlurmAccount::from_mapping(bank_account.mapping())?;
+
+    // the allocation may have changed while the usage was being fetched
+    let mut bank_account = match cache::get_bank_account(account.name()).await? {
+        Some(bank_account) => bank_account,
+        None => return Ok(()),
+    };
+
+    let allocation = *bank_account.allocation();
+
+    if usage.seconds() >= allocation.seconds() {
+        if bank_account.is_blocked()
+            || sacctmgr::is_account_blocked(bank_account.mapping(), &expires).await?
+        {
+            return Ok(());
+        }
+
+        tracing::warn!(
+            "Project {} has used {} of its allocation of {} - blocking its account",
+            project,
+            usage,
+            allocation
+        );
+
+        sacctmgr::block_account(bank_account.mapping(), &expires).await?;
+
+        // record the block before anything else, so that it is lifted
+        // by banking even if the agent restarts
+        bank_account.blocked = true;
+        cache::set_bank_account(account.name(), &bank_account).await?;
+
+        notification::send(
+            bank_account.destination(),
+            NotificationEvent::AllocationExhausted(project.clone()),
+        )
+        .await;
+    } else if bank_account.is_blocked() {
+        let unblocked = sacctmgr::unblock_account(bank_account.mapping(), &expires).await?;
+
+        bank_account.blocked = false;
+        cache::set_bank_account(account.name(), &bank_account).await?;
+
+        if unblocked {
+            tracing::info!(
+                "Project {} has used {} of its allocation of {} - unblocked its account",
+                project,
+                usage,
+                allocation
+            );
+
+            notification::send(
+                bank_account.destination(),
+                NotificationEvent::AllocationRestored(project.clone()),
+            )
+            .await;
+        }
+    }
+
+    Ok(())
+}
@@ -4,12 +4,15 @@
 use anyhow::Result;
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use templemeads::grammar::{Date, Hour, ProjectIdentifier, UserIdentifier};
 use templemeads::usagereport::DailyProjectUsageReport;
 use templemeads::Error;
 use tokio::sync::{Mutex, RwLock};
 
+use crate::banking::BankAccount;
+use crate::qos::SlurmQos;
 use crate::slurm::{SlurmAccount, SlurmJob, SlurmNode, SlurmNodes, SlurmUser};
 
 #[derive(Debug, Clone, Default)]
@@ -26,6 +29,18 @@ struct Database {
     accounts: HashMap<String, SlurmAccount>,
     users: HashMap<String, SlurmUser>,
     nodes: Option<SlurmNodes>,
+    qos_classes: HashMap<String, SlurmQos>,
+    limit_tres: Option<Vec<String>>,
+    fairshare_node_hours: Option<f64>,
+    job_script_dirs: Vec<String>,
+    federated_clusters: Vec<String>,
+    collect_energy: bool,
+    sacct_batch_days: u64,
+    sacct_batch_accounts: u64,
+    usage_cache_dir: Option<PathBuf>,
+    banking_start_date: Option<Date>,
+    banking_file: Option<PathBuf>,
+    bank_accounts: HashMap<String, BankAccount>,
     reports: HashMap<ProjectIdentifier, UsageDatabase>,
     user_mutexes: HashMap<UserIdentifier, Arc<Mutex<()>>>,
     project_mutexes: HashMap<ProjectIdentifier, Arc<Mutex<()>>>,
@@ -71,6 +86,40 @@ pub async fn get_cluster() -> Result<String, Error> {
     }
 }
 
+///
+/// Return all of the clusters managed by this agent. This is the
+/// primary cluster followed by any other clusters in its federation
+///
+pub async fn get_clusters() -> Result<Vec<String>, Error> {
+    let mut clusters = vec![get_cluster().await?];
+
+    let cache = CACHE.read().await;
+
+    for cluster in &cache.federated_clusters {
+        if !clusters.contains(cluster) {
+            clusters.push(cluster.clone());
+        }
+    }
+
+    Ok(clusters)
+}
+
+///
+/// Set the other clusters in the primary cluster's federation. Accounts,
+/// associations and limits are added to these as well as to the primary
+/// cluster, and usage is collected from all of them
+///
+pub async fn set_federated_clusters(clusters: &[String]) -> Result<(), Error> {
+    let mut cache = CACHE.write().await;
+
+    if cache.federated_clusters != clusters {
+        cache.reports.clear();
+    }
+
+    cache.federated_clusters = clusters.to_vec();
+    Ok(())
+}
+
 pub async fn set_cluster(cluster: &str) -> Result<(), Error> {
     let mut cache = CACHE.write().await;
 
@@ -121,6 +170,345 @@ pub async fn set_parent_account(parent_account: &str) -> Result<(), Error> {
     Ok(())
 }
 
+/// The trackable resources that can be limited, in the order that
+/// they are checked
+pub const LIMITABLE_TRES: [&str; 4] = ["cpu", "gpu", "mem", "billing"];
+
+///
+/// Set the trackable resources (from "cpu", "gpu", "mem" and "billing")
+/// that project limits are applied to, as a comma-separated list. Limiting
+/// only "gpu" means that a project's allocation is enforced as a
+/// `gres/gpu` GrpTRESMins limit alone. An empty list limits all of them.
+///
+pub async fn set_limit_tres(limit_tres: &str) -> Result<(), Error> {
+    let mut tres: Vec<String> = Vec::new();
+
+    for t in limit_tres.split(',') {
+        let t = t.trim().to_lowercase();
+
+        if t.is_empty() {
+            continue;
+        }
+
+        let t = match t.as_str() {
+            "gres/gpu" => "gpu".to_string(),
+            _ => t,
+        };
+
+        if !LIMITABLE_TRES.contains(&t.as_str()) {
+            return Err(Error::Misconfigured(format!(
+                "Invalid TRES '{}' in slurm-limit-tres. This should be a comma-separated list of {}",
+                t,
+                LIMITABLE_TRES.join(", ")
+            )));
+        }
+
+        if !tres.contains(&t) {
+            tres.push(t);
+        }
+    }
+
+    let mut cache = CACHE.write().await;
+
+    cache.limit_tres = match tres.is_empty() {
+        true => None,
+        false => Some(tres),
+    };
+
+    Ok(())
+}
+
+///
+/// Return the trackable resources that project limits are applied to
+///
+pub async fn get_limit_tres() -> Result<Vec<String>, Error> {
+    let cache = CACHE.read().await;
+
+    match &cache.limit_tres {
+        Some(tres) => Ok(tres.clone()),
+        None => Ok(LIMITABLE_TRES.iter().map(|t| t.to_string()).collect()),
+    }
+}
+
+///
+/// Set the number of node hours of a project's limit that earn one
+/// share of fairshare. Zero (the default) means that fairshare is not
+/// derived from project limits
+///
+pub async fn set_fairshare_node_hours(node_hours: f64) -> Result<(), Error> {
+    if !node_hours.is_finite() || node_hours < 0.0 {
+        return Err(Error::Misconfigured(format!(
+            "Invalid slurm-fairshare-node-hours '{}'. This should be a non-negative number of node hours per share",
+            node_hours
+        )));
+    }
+
+    let mut cache = CACHE.write().await;
+
+    cache.fairshare_node_hours = match node_hours > 0.0 {
+        true => Some(node_hours),
+        false => None,
+    };
+
+    Ok(())
+}
+
+///
+/// Return the number of node hours of a project's limit that earn one
+/// share of fairshare, or None if fairshare is not derived from limits
+///
+pub async fn get_fairshare_node_hours() -> Result<Option<f64>, Error> {
+    let cache = CACHE.read().await;
+    Ok(cache.fairshare_node_hours)
+}
+
+///
+/// Set whether the energy consumed by jobs is added to usage reports
+///
+pub async fn set_collect_energy(collect_energy: bool) -> Result<(), Error> {
+    let mut cache = CACHE.write().await;
+    cache.collect_energy = collect_energy;
+    Ok(())
+}
+
+///
+/// Return whether the energy consumed by jobs is added to usage reports
+///
+pub async fn get_collect_energy() -> Result<bool, Error> {
+    let cache = CACHE.read().await;
+    Ok(cache.collect_energy)
+}
+
+///
+/// Set the number of consecutive days of usage that are fetched by
+/// a single sacct invocation
+///
+pub async fn set_sacct_batch_days(sacct_batch_days: u64) -> Result<(), Error> {
+    let mut cache = CACHE.write().await;
+    cache.sacct_batch_days = sacct_batch_days.max(1);
+    Ok(())
+}
+
+///
+/// Return the number of consecutive days of usage that are fetched by
+/// a single sacct invocation
+///
+pub async fn get_sacct_batch_days() -> Result<u64, Error> {
+    let cache = CACHE.read().await;
+    Ok(cache.sacct_batch_days.max(1))
+}
+
+///
+/// Set the maximum number of accounts whose usage is fetched by a
+/// single sacct invocation
+///
+pub async fn set_sacct_batch_accounts(sacct_batch_accounts: u64) -> Result<(), Error> {
+    let mut cache = CACHE.write().await;
+    cache.sacct_batch_accounts = sacct_batch_accounts.max(1);
+    Ok(())
+}
+
+///
+/// Return the maximum number of accounts whose usage is fetched by a
+/// single sacct invocation
+///
+pub async fn get_sacct_batch_accounts() -> Result<u64, Error> {
+    let cache = CACHE.read().await;
+    Ok(cache.sacct_batch_accounts.max(1))
+}
+
+///
+/// Set the date from which the usage of every project is counted against
+/// its allocation. If this is not set, usage is counted from the day on
+/// which the project's allocation was first set
+///
+pub async fn set_banking_start_date(start_date: Option<Date>) -> Result<(), Error> {
+    let mut cache = CACHE.write().await;
+    cache.banking_start_date = start_date;
+    Ok(())
+}
+
+///
+/// Return the date from which the usage of every project is counted
+/// against its allocation
+///
+pub async fn get_banking_start_date() -> Result<Option<Date>, Error> {
+    let cache = CACHE.read().await;
+    Ok(cache.banking_start_date.clone())
+}
+
+///
+/// Set the file in which the bank accounts are saved, so that they
+/// survive restarts, and load any bank accounts that were saved by an
+/// earlier run of the agent. Bank accounts are only held in memory if
+/// this is None
+///
+pub async fn set_banking_file(banking_file: Option<PathBuf>) -> Result<(), Error> {
+    let mut bank_accounts = HashMap::new();
+
+    if let Some(file) = &banking_file {
+        match tokio::fs::read_to_string(file).await {
+            Ok(json) => {
+                bank_accounts = serde_json::from_str(&json).map_err(|e| {
+                    Error::Misconfigured(format!(
+                        "Could not parse the banking-file {:?}: {}",
+                        file, e
+                    ))
+                })?;
+
+                tracing::info!(
+                    "Loaded {} bank accounts from {:?}",
+                    bank_accounts.len(),
+                    file
+                );
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(Error::Misconfigured(format!(
+                    "Could not read the banking-file {:?}: {}",
+                    file, e
+                )));
+            }
+        }
+    }
+
+    let mut cache = CACHE.write().await;
+    cache.banking_file = banking_file;
+    cache.bank_accounts = bank_accounts;
+
+    Ok(())
+}
+
+///
+/// Save all of the bank accounts to the banking file, if there is one.
+/// This is called with the cache locked, so that saves cannot overtake
+/// each other
+///
+async fn save_bank_accounts(cache: &Database) -> Result<(), Error> {
+    let Some(file) = &cache.banking_file else {
+        return Ok(());
+    };
+
+    if let Some(dir) = file.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+
+    let json = serde_json::to_string(&cache.bank_accounts)
+        .map_err(|e| Error::Parse(format!("Could not serialise bank accounts: {}", e)))?;
+
+    // write to a temporary file first, so that a crash mid-write
+    // cannot lose every bank account
+    let tmp_file = file.with_extension("tmp");
+    tokio::fs::write(&tmp_file, json).await?;
+    tokio::fs::rename(&tmp_file, file).await?;
+
+    Ok(())
+}
+
+///
+/// Save the passed bank account, replacing any existing bank account
+/// for the same slurm account
+///
+pub async fn set_bank_account(name: &str, bank_account: &BankAccount) -> Result<(), Error> {
+    let mut cache = CACHE.write().await;
+    cache
+        .bank_accounts
+        .insert(name.to_string(), bank_account.clone());
+    save_bank_accounts(&cache).await
+}
+
+///
+/// Return the bank account for the passed slurm account, if it has one
+///
+pub async fn get_bank_account(name: &str) -> Result<Option<BankAccount>, Error> {
+    let cache = CACHE.read().await;
+    Ok(cache.bank_accounts.get(name).cloned())
+}
+
+///
+/// Remove the bank account for the passed slurm account
+///
+pub async fn remove_bank_account(name: &str) -> Result<(), Error> {
+    let mut cache = CACHE.write().await;
+
+    if cache.bank_accounts.remove(name).is_some() {
+        save_bank_accounts(&cache).await?;
+    }
+
+    Ok(())
+}
+
+///
+/// Return all of the bank accounts
+///
+pub async fn get_bank_accounts() -> Result<Vec<BankAccount>, Error> {
+    let cache = CACHE.read().await;
+    Ok(cache.bank_accounts.values().cloned().collect())
+}
+
+///
+/// Set the directories (as a comma-separated list of absolute paths)
+/// that job scripts can be submitted from. Job submission is disabled
+/// if this is empty
+///
+pub async fn set_job_script_dirs(job_script_dirs: &str) -> Result<(), Error> {
+    let mut dirs: Vec<String> = Vec::new();
+
+    for dir in job_script_dirs.split(',') {
+        let dir = dir.trim();
+
+        if dir.is_empty() {
+            continue;
+        }
+
+        if !dir.starts_with('/') || dir.split('/').any(|part| part == "..") {
+            return Err(Error::Misconfigured(format!(
+                "Invalid directory '{}' in job-script-dirs. This should be a comma-separated list of absolute paths",
+                dir
+            )));
+        }
+
+        dirs.push(dir.to_string());
+    }
+
+    let mut cache = CACHE.write().await;
+    cache.job_script_dirs = dirs;
+
+    Ok(())
+}
+
+///
+/// Return the directories that job scripts can be submitted from
+///
+pub async fn get_job_script_dirs() -> Result<Vec<String>, Error> {
+    let cache = CACHE.read().await;
+    Ok(cache.job_script_dirs.clone())
+}
+
+///
+/// Set the QOS that projects of each class should be given
+///
+pub async fn set_qos_classes(qos_classes: HashMap<String, SlurmQos>) -> Result<(), Error> {
+    let mut cache = CACHE.write().await;
+    cache.qos_classes = qos_classes;
+    Ok(())
+}
+
+///
+/// Return the QOS that projects of the passed class should be given
+///
+pub async fn get_qos_class(class: &str) -> Result<SlurmQos, Error> {
+    let cache = CACHE.read().await;
+
+    match cache.qos_classes.get(class) {
+        Some(qos) => Ok(qos.clone()),
+        None => Err(Error::NotFound(format!(
+            "No QOS is defined for project class '{}'. Add it to the slurm-qos option.",
+            class
+        ))),
+    }
+}
+
 ///
 /// Return the name of the parent account
 ///
@@ -190,7 +578,6 @@ pub async fn set_default_node(node: &SlurmNode) -> Result<(), Error> {
     Ok(())
 }
 
-#[allow(dead_code)]
 pub async fn set_node(name: &str, node: &SlurmNode) -> Result<(), Error> {
     let mut cache = CACHE.write().await;
 
@@ -228,20 +615,115 @@ pub async fn get_nodes() -> Result<SlurmNodes, Error> {
     }
 }
 
+///
+/// Set the directory in which completed daily reports are saved, so
+/// that they survive restarts and do not need to be fetched from sacct
+/// again. Reports are only cached in memory if this is empty
+///
+pub async fn set_usage_cache_dir(usage_cache_dir: &str) -> Result<(), Error> {
+    let usage_cache_dir = usage_cache_dir.trim();
+
+    let usage_cache_dir = match usage_cache_dir.is_empty() {
+        true => None,
+        false => {
+            let dir = PathBuf::from(usage_cache_dir);
+
+            tokio::fs::create_dir_all(&dir).await.map_err(|e| {
+                Error::Misconfigured(format!(
+                    "Could not create the usage-cache-dir '{}': {}",
+                    usage_cache_dir, e
+                ))
+            })?;
+
+            Some(dir)
+        }
+    };
+
+    let mut cache = CACHE.write().await;
+    cache.usage_cache_dir = usage_cache_dir;
+
+    Ok(())
+}
+
+///
+/// Return the file in which the completed report of the passed account
+/// on the passed date is saved, or None if reports are not saved. The
+/// clusters are part of the path, as the report depends on them
+///
+async fn report_file(account: &str, date: &Date) -> Result<Option<PathBuf>, Error> {
+    let clusters = get_clusters().await?.join(",");
+
+    let cache = CACHE.read().await;
+
+    Ok(cache.usage_cache_dir.as_ref().map(|dir| {
+        dir.join(clusters)
+            .join(account)
+            .join(format!("{}.json", date))
+    }))
+}
+
+///
+/// Return the completed report of the passed project (whose Slurm
+/// account is `account`) on the passed date, if it has been cached
+///
 pub async fn get_report(
     project: &ProjectIdentifier,
+    account: &str,
     date: &Date,
 ) -> Result<Option<DailyProjectUsageReport>, Error> {
-    let cache = CACHE.read().await;
+    {
+        let cache = CACHE.read().await;
+
+        if let Some(report) = cache
+            .reports
+            .get(project)
+            .and_then(|usage| usage.reports.get(date))
+        {
+            return Ok(Some(report.clone()));
+        }
+    }
 
-    match cache.reports.get(project) {
-        Some(usage) => Ok(usage.reports.get(date).cloned()),
-        None => Ok(None),
+    // completed days never change, so a report saved by an earlier
+    // run of the agent is still valid
+    let Some(file) = report_file(account, date).await? else {
+        return Ok(None);
+    };
+
+    let json = match tokio::fs::read_to_string(&file).await {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            tracing::warn!("Could not read cached report {:?}: {}", file, e);
+            return Ok(None);
+        }
+    };
+
+    let report: DailyProjectUsageReport = match serde_json::from_str(&json) {
+        Ok(report) => report,
+        Err(e) => {
+            tracing::warn!("Could not parse cached report {:?}: {}", file, e);
+            return Ok(None);
+        }
+    };
+
+    if !report.is_complete() {
+        tracing::warn!("Ignoring incomplete cached report {:?}", file);
+        return Ok(None);
     }
+
+    // keep it in memory for next time
+    set_memory_report(project, date, &report).await;
+
+    Ok(Some(report))
 }
 
+///
+/// Cache the completed report of the passed project (whose Slurm
+/// account is `account`) on the passed date
+///
 pub async fn set_report(
     project: &ProjectIdentifier,
+    account: &str,
     date: &Date,
     report: &DailyProjectUsageReport,
 ) -> Result<(), Error> {
@@ -261,6 +743,43 @@ pub async fn set_report(
         )));
     }
 
+    set_memory_report(project, date, report).await;
+
+    // save the report so that it survives restarts. Failing to save
+    // only means that the day will be fetched from sacct again
+    if let Some(file) = report_file(account, date).await? {
+        if let Err(e) = save_report(&file, report).await {
+            tracing::warn!("Could not save report to {:?}: {}", file, e);
+        }
+    }
+
+    Ok(())
+}
+
+async fn save_report(file: &Path, report: &DailyProjectUsageReport) -> Result<(), Error> {
+    if let Some(dir) = file.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+
+    let json = serde_json::to_string(report)
+        .map_err(|e| Error::Parse(format!("Could not serialise report: {}", e)))?;
+
+    // write to a temporary file first, so that a crash mid-write
+    // cannot leave a corrupt report behind
+    let tmp_file = file.with_extension("tmp");
+    tokio::fs::write(&tmp_file, json).await?;
+    tokio::fs::rename(&tmp_file, file).await?;
+
+    Ok(())
+}
+
+async fn set_memory_report(
+    project: &ProjectIdentifier,
+    date: &Date,
+    report: &DailyProjectUsageReport,
+) {
+    let today = Date::today();
+
     let mut cache = CACHE.write().await;
 
     match cache.reports.get_mut(project) {
@@ -291,8 +810,6 @@ pub async fn set_report(
             cache.reports.insert(project.clone(), usage);
         }
     }
-
-    Ok(())
 }
 
 ///
@@ -3,21 +3,91 @@
 
 use anyhow::Result;
 
-use templemeads::agent::scheduler::{process_args, run, Defaults};
+use templemeads::agent::scheduler::{process_args, run, Config, Defaults};
 use templemeads::agent::Type as AgentType;
 use templemeads::async_runnable;
+use templemeads::batch;
+use templemeads::configcheck::ConfigCheck;
+use templemeads::dryrun;
 use templemeads::grammar::Instruction::{
-    AddLocalProject, AddLocalUser, GetLocalLimit, GetLocalUsageReport, RemoveLocalProject,
-    RemoveLocalUser, SetLocalLimit,
+    AddLocalProject, AddLocalUser, AddLocalUsers, CreateLocalReservation, GetLocalJobQueue,
+    GetLocalLimit, GetLocalNodes, GetLocalQos, GetLocalUsageReport, ReconcileLocal,
+    RemoveLocalProject, RemoveLocalReservation, RemoveLocalUser, RemoveLocalUsers, SetLocalLimit,
+    SetLocalQos, SubmitLocalJob,
 };
 use templemeads::job::{Envelope, Job};
 use templemeads::notification::default_notify_runner;
 use templemeads::set_notify_runner;
 use templemeads::Error;
 
+mod banking;
 mod cache;
+mod qos;
 mod sacctmgr;
 mod slurm;
+mod slurmdbd;
+
+///
+/// Check the options of the slurm agent when it is run with --check-config
+///
+fn check_slurm_options(config: &Config, check: &mut ConfigCheck) {
+    match config.option("slurm-default-node", "").trim() {
+        "" => check.error(
+            "slurm-default-node",
+            "No default node provided. This should be a JSON representation of the default node type.",
+        ),
+        node => match serde_json::from_str(node) {
+            Ok(node) => {
+                check.check("slurm-default-node", slurm::SlurmNode::construct(&node));
+            }
+            Err(e) => check.error("slurm-default-node", &format!("Invalid JSON: {}", e)),
+        },
+    }
+
+    let slurm_qos = config.option("slurm-qos", "");
+
+    if !slurm_qos.trim().is_empty() {
+        check.check("slurm-qos", qos::parse_classes(&slurm_qos));
+    }
+
+    check.check_number::<f64>(
+        "slurm-fairshare-node-hours",
+        &config.option("slurm-fairshare-node-hours", ""),
+    );
+
+    for key in [
+        "max-slurm-runners",
+        "sacct-parallelism",
+        "sacct-batch-days",
+        "sacct-batch-accounts",
+        "banking-interval",
+    ] {
+        check.check_number::<u64>(key, &config.option(key, ""));
+    }
+
+    let banking_start_date = config.option("banking-start-date", "");
+
+    if !banking_start_date.trim().is_empty() {
+        check.check(
+            "banking-start-date",
+            templemeads::grammar::Date::parse(&banking_start_date),
+        );
+    }
+
+    let slurm_server = config.option("slurm-server", "");
+
+    if !slurm_server.trim().is_empty() {
+        check.check_url("slurm-server", &slurm_server);
+        check.check_number::<u32>("token-lifespan", &config.option("token-lifespan", ""));
+
+        if config.option("token-command", "").trim().is_empty() {
+            check.error(
+                "token-command",
+                "No token command provided. This is needed to generate a JWT token for slurmrestd.",
+            );
+        }
+    }
+}
 
 ///
 /// Main function for the slurm scheduler application
@@ -36,7 +106,7 @@ async fn main() -> Result<()> {
     templemeads::spawn_system_monitor();
 
     // create the OpenPortal paddington defaults
-    let defaults: Defaults = Defaults::parse(
+    let mut defaults: Defaults = Defaults::parse(
         Some("slurm".to_owned()),
         Some(
             dirs::config_local_dir()
@@ -55,6 +125,8 @@ async fn main() -> Result<()> {
         Some(AgentType::Scheduler),
     );
 
+    defaults.set_config_check(check_slurm_options);
+
     // now parse the command line arguments to get the service configuration
     let config = match process_args(&defaults).await? {
         Some(config) => config,
@@ -86,11 +158,19 @@ async fn main() -> Result<()> {
 
     cache::set_default_node(&slurm::SlurmNode::construct(&slurm_default_node)?).await?;
 
-    // get the (optional) cluster used for this account
+    // get the (optional) cluster used for this account. This can be a
+    // comma-separated list for a federation, with the primary cluster first
     let slurm_cluster = config.option("slurm-cluster", "");
 
-    if !slurm_cluster.is_empty() {
-        cache::set_cluster(&slurm_cluster).await?;
+    let slurm_clusters: Vec<String> = slurm_cluster
+        .split(',')
+        .map(|cluster| cluster.trim().to_string())
+        .filter(|cluster| !cluster.is_empty())
+        .collect();
+
+    if let Some((primary, federated)) = slurm_clusters.split_first() {
+        cache::set_cluster(primary).await?;
+        cache::set_federated_clusters(federated).await?;
     }
 
     // get the (optional) partition used for this account
@@ -104,25 +184,151 @@ async fn main() -> Result<()> {
     let parent_account = config.option("parent-account", "root");
     cache::set_parent_account(&parent_account).await?;
 
+    // get the (optional) QOS that should be given to each class of project
+    let slurm_qos = config.option("slurm-qos", "");
+
+    if !slurm_qos.is_empty() {
+        cache::set_qos_classes(qos::parse_classes(&slurm_qos)?).await?;
+    }
+
+    // get the (optional) list of TRES that project limits are applied to
+    let slurm_limit_tres = config.option("slurm-limit-tres", "");
+    cache::set_limit_tres(&slurm_limit_tres).await?;
+
+    // get the (optional) number of node hours of a project's limit per
+    // share of fairshare
+    let slurm_fairshare_node_hours = config.option("slurm-fairshare-node-hours", "0");
+
+    match slurm_fairshare_node_hours.trim().parse::<f64>() {
+        Ok(node_hours) => cache::set_fairshare_node_hours(node_hours).await?,
+        Err(e) => {
+            return Err(anyhow::anyhow!(format!(
+                "Invalid slurm-fairshare-node-hours '{}'. This should be the number of node hours per share: {}",
+                slurm_fairshare_node_hours, e
+            )));
+        }
+    }
+
+    // should the energy consumed by jobs (from the ConsumedEnergy that
+    // sacct reports as the energy TRES) be added to usage reports?
+    let slurm_collect_energy = config.option("slurm-collect-energy", "false");
+    cache::set_collect_energy(slurm_collect_energy.trim().to_lowercase() == "true").await?;
+
+    // get the (optional) directory in which completed daily usage
+    // reports are saved, so that they are not fetched from sacct again
+    let usage_cache_dir = config.option("usage-cache-dir", "");
+    cache::set_usage_cache_dir(&usage_cache_dir).await?;
+
+    // get the (optional) directories that job scripts can be submitted from
+    let job_script_dirs = config.option("job-script-dirs", "");
+    cache::set_job_script_dirs(&job_script_dirs).await?;
+
     let slurm_server = config.option("slurm-server", "");
 
-    // get the sacct, sacctmgr, scontrol and scancel commands - we may need these even if
+    // get the sacct, sacctmgr, scontrol, scancel, squeue and sbatch commands - we may need these even if
     // we are using the REST API
     let sacct_command = config.option("sacct", "sacct");
     let sacctmgr_command = config.option("sacctmgr", "sacctmgr");
     let scontrol_command = config.option("scontrol", "scontrol");
     let scancel_command = config.option("scancel", "scancel");
+    let squeue_command = config.option("squeue", "squeue");
+    let sbatch_command = config.option("sbatch", "sbatch");
     let max_slurm_runners: u64 = config.option("max-slurm-runners", "5").parse().unwrap_or(5);
 
+    // the number of sacct usage queries that can run at the same time
+    let sacct_parallelism: u64 = config
+        .option("sacct-parallelism", &max_slurm_runners.to_string())
+        .parse()
+        .unwrap_or(max_slurm_runners);
+
+    // how many days, and how many accounts, are fetched by each sacct query
+    let sacct_batch_days: u64 = config.option("sacct-batch-days", "1").parse().unwrap_or(1);
+    cache::set_sacct_batch_days(sacct_batch_days).await?;
+
+    let sacct_batch_accounts: u64 = config
+        .option("sacct-batch-accounts", "1")
+        .parse()
+        .unwrap_or(1);
+    cache::set_sacct_batch_accounts(sacct_batch_accounts).await?;
+
     sacctmgr::set_commands(
         &sacct_command,
         &sacctmgr_command,
         &scontrol_command,
         &scancel_command,
+        &squeue_command,
+        &sbatch_command,
         max_slurm_runners,
+        sacct_parallelism,
     )
     .await;
 
+    // connect to the (optional) slurmdbd database, from which usage
+    // is read directly rather than via sacct
+    let slurmdbd_url = config.option("slurmdbd-url", "");
+
+    if !slurmdbd_url.is_empty() {
+        let slurmdbd_password_file = config.option("slurmdbd-password-file", "");
+        slurmdbd::connect(&slurmdbd_url, &slurmdbd_password_file, sacct_parallelism).await?;
+    }
+
+    // read the hardware of each node, so that the usage of jobs is
+    // calculated from the nodes they ran on. The default node is used
+    // for any node that cannot be read
+    let expires = chrono::Utc::now() + chrono::Duration::minutes(1);
+
+    if let Err(e) = sacctmgr::get_nodes(&expires).await {
+        tracing::warn!(
+            "Could not read the node inventory - using the default node for all jobs: {}",
+            e
+        );
+    }
+
+    // get the (optional) interval (in seconds) between checks of each
+    // project's usage against its allocation. Banking is disabled if
+    // this is zero
+    let banking_interval: u64 = config.option("banking-interval", "0").parse().unwrap_or(0);
+
+    // get the (optional) date from which usage is counted against
+    // each project's allocation
+    let banking_start_date = config.option("banking-start-date", "");
+
+    if !banking_start_date.trim().is_empty() {
+        match templemeads::grammar::Date::parse(&banking_start_date) {
+            Ok(date) => cache::set_banking_start_date(Some(date)).await?,
+            Err(e) => {
+                return Err(anyhow::anyhow!(format!(
+                    "Invalid banking-start-date '{}'. This should be a date in the form YYYY-MM-DD: {}",
+                    banking_start_date, e
+                )));
+            }
+        }
+    }
+
+    // get the (optional) file in which allocations are saved, so that
+    // they are still enforced after a restart. This defaults to the
+    // config file with a `.banking.json` extension, and is disabled
+    // by 'none'
+    let banking_file = match config.option("banking-file", "").trim() {
+        "none" => None,
+        "" => templemeads::config::config_file()
+            .await
+            .map(|file| file.with_extension("banking.json")),
+        file => Some(std::path::PathBuf::from(file)),
+    };
+
+    cache::set_banking_file(banking_file).await?;
+
+    if banking_interval > 0 {
+        banking::spawn_banking_task(banking_interval);
+    }
+
+    // mutating instructions are handled by dry_run when rehearsed
+    dryrun::set_supported(true);
+
+    // add_local_users and remove_local_users are handled by the runners
+    batch::set_native(true);
+
     set_notify_runner(default_notify_runner).await?;
 
     if slurm_server.is_empty() {
@@ -139,6 +345,10 @@ async fn main() -> Result<()> {
             {
                 let job = envelope.job();
 
+                if dryrun::is_dry_run() && job.instruction().is_mutating() {
+                    return dry_run(&job);
+                }
+
                 match job.instruction() {
                     AddLocalProject(project) => {
                         sacctmgr::add_project(&project, job.expires()).await?;
@@ -157,28 +367,75 @@ async fn main() -> Result<()> {
                         sacctmgr::add_user(&user, job.expires()).await?;
                         job.completed_none()
                     },
+                    AddLocalUsers(users) => {
+                        let result = sacctmgr::add_users(users, job.expires()).await;
+                        job.completed(result)
+                    },
+                    RemoveLocalUsers(users) => {
+                        let result = sacctmgr::disable_users(users, job.expires()).await;
+                        job.completed(result)
+                    },
                     RemoveLocalUser(mapping) => {
-                        // we won't remove the user for now, as we want to
-                        // make sure that the statistics are preserved. Will eventually
-                        // disable the user instead. Note that they are already
-                        // disabled in FreeIPA, so cannot submit jobs to this account.
-                        // However, we do want to cancel all pending jobs for this user.
+                        // we don't remove the user, as we want to make sure
+                        // that the statistics are preserved. Instead, the user's
+                        // association is disabled, and all pending jobs for
+                        // this user are cancelled. Adding the user again
+                        // re-enables the association.
+                        sacctmgr::disable_user(&mapping, job.expires()).await?;
                         sacctmgr::cancel_pending_user_jobs(mapping.local_user(), job.expires()).await?;
-                        tracing::info!("Cancelled pending jobs for user {}", mapping);
+                        tracing::info!("Disabled user and cancelled pending jobs for {}", mapping);
                         job.completed_none()
                     },
                     GetLocalUsageReport(mapping, dates) => {
                         let report = sacctmgr::get_usage_report(&mapping, &dates, job.expires()).await?;
                         job.completed(report)
                     }
-                    GetLocalLimit(mapping) => {
-                        let limit = sacctmgr::get_limit(&mapping, job.expires()).await?;
+                    GetLocalLimit(mapping, partition) => {
+                        let limit = sacctmgr::get_limit(&mapping, partition.as_deref(), job.expires()).await?;
                         job.completed(limit)
                     }
-                    SetLocalLimit(mapping, limit) => {
-                        let limit = sacctmgr::set_limit(&mapping, &limit, job.expires()).await?;
+                    SetLocalLimit(mapping, limit, partition) => {
+                        let limit = sacctmgr::set_limit(&mapping, &limit, partition.as_deref(), job.expires()).await?;
+
+                        // the project's limit is its allocation
+                        if partition.is_none() {
+                            banking::set_allocation(&mapping, &limit, &job.destination().reverse()).await?;
+                        }
+
                         job.completed(limit)
                     }
+                    GetLocalQos(mapping) => {
+                        let qos = sacctmgr::get_qos(&mapping, job.expires()).await?;
+                        job.completed(qos)
+                    }
+                    SetLocalQos(mapping, class) => {
+                        let qos = sacctmgr::set_qos(&mapping, &class, job.expires()).await?;
+                        job.completed(qos)
+                    }
+                    CreateLocalReservation(mapping, dates, nodes, window) => {
+                        let reservation = sacctmgr::create_reservation(&mapping, &dates, nodes, &window, job.expires()).await?;
+                        job.completed(reservation)
+                    }
+                    RemoveLocalReservation(mapping, dates) => {
+                        let reservation = sacctmgr::remove_reservation(&mapping, &dates, job.expires()).await?;
+                        job.completed(reservation)
+                    }
+                    GetLocalJobQueue(mapping) => {
+                        let queue = sacctmgr::get_job_queue(&mapping, job.expires()).await?;
+                        job.completed(queue)
+                    }
+                    GetLocalNodes => {
+                        let nodes = sacctmgr::get_nodes(job.expires()).await?;
+                        job.completed(nodes)
+                    }
+                    SubmitLocalJob(mapping, script) => {
+                        let job_id = sacctmgr::submit_job(&mapping, &script, job.expires()).await?;
+                        job.completed(job_id)
+                    }
+                    ReconcileLocal(mapping, repair, users) => {
+                        let report = sacctmgr::reconcile(&mapping, &users, repair, job.expires()).await?;
+                        job.completed(report)
+                    }
                     _ => {
                         Err(Error::InvalidInstruction(
                             format!("Invalid instruction: {}. Slurm agents do not support this instruction", job.instruction()),
@@ -220,6 +477,15 @@ async fn main() -> Result<()> {
             ));
         }
 
+        // slurmrestd only talks to a single cluster
+        if cache::get_clusters().await?.len() > 1 {
+            return Err(anyhow::anyhow!(
+                "Federated clusters are not supported when using slurmrestd. \
+             Use the sacctmgr command line tools to manage a federation."
+                    .to_owned(),
+            ));
+        }
+
         // connect the single shared Slurm client - this will be used in the
         // async function (we can't bind variables to async functions, or else
         // we would just pass the client with the environment)
@@ -234,6 +500,17 @@ async fn main() -> Result<()> {
 
         tracing::info!("Connected to slurm server at {}", slurm_server);
 
+        // how often (in seconds) to check that slurmrestd can be reached
+        // with a valid token (0 means that it is never checked)
+        let slurm_health_interval: u64 = config
+            .option("slurm-health-interval", "60")
+            .parse()
+            .unwrap_or(60);
+
+        if slurm_health_interval > 0 {
+            slurm::spawn_health_check(slurm_health_interval);
+        }
+
         async_runnable! {
             ///
             /// Runnable function that will be called when a job is received
@@ -243,6 +520,10 @@ async fn main() -> Result<()> {
             {
                 let job = envelope.job();
 
+                if dryrun::is_dry_run() && job.instruction().is_mutating() {
+                    return dry_run(&job);
+                }
+
                 match job.instruction() {
                     AddLocalProject(project) => {
                         slurm::add_project(&project, job.expires()).await?;
@@ -261,14 +542,32 @@ async fn main() -> Result<()> {
                         slurm::add_user(&user, job.expires()).await?;
                         job.completed_none()
                     },
+                    AddLocalUsers(users) => {
+                        // the users are added concurrently, using all of
+                        // the connections to slurmrestd
+                        let expires = job.expires();
+
+                        let result = batch::run_each(users, slurm::num_servers().await, |user| async move {
+                            slurm::add_user(&user, expires).await?;
+                            Ok(user)
+                        })
+                        .await;
+
+                        job.completed(result)
+                    },
+                    RemoveLocalUsers(users) => {
+                        let result = sacctmgr::disable_users(users, job.expires()).await;
+                        job.completed(result)
+                    },
                     RemoveLocalUser(mapping) => {
-                        // we won't remove the user for now, as we want to
-                        // make sure that the statistics are preserved. Will eventually
-                        // disable the user instead. Note that they are already
-                        // disabled in FreeIPA, so cannot submit jobs to this account.
-                        // However, we do want to cancel all pending jobs for this user.
+                        // we don't remove the user, as we want to make sure
+                        // that the statistics are preserved. Instead, the user's
+                        // association is disabled, and all pending jobs for
+                        // this user are cancelled. Adding the user again
+                        // re-enables the association.
+                        sacctmgr::disable_user(&mapping, job.expires()).await?;
                         sacctmgr::cancel_pending_user_jobs(mapping.local_user(), job.expires()).await?;
-                        tracing::info!("Cancelled pending jobs for user {}", mapping);
+                        tracing::info!("Disabled user and cancelled pending jobs for {}", mapping);
                         job.completed_none()
                     },
                     GetLocalUsageReport(mapping, dates) => {
@@ -276,14 +575,52 @@ async fn main() -> Result<()> {
                         let report = slurm::get_usage_report(&mapping, &dates, job.expires()).await?;
                         job.completed(report)
                     }
-                    GetLocalLimit(mapping) => {
-                        let limit = slurm::get_limit(&mapping, job.expires()).await?;
+                    GetLocalLimit(mapping, partition) => {
+                        let limit = slurm::get_limit(&mapping, partition.as_deref(), job.expires()).await?;
                         job.completed(limit)
                     }
-                    SetLocalLimit(mapping, limit) => {
-                        let limit = slurm::set_limit(&mapping, &limit, job.expires()).await?;
+                    SetLocalLimit(mapping, limit, partition) => {
+                        let limit = slurm::set_limit(&mapping, &limit, partition.as_deref(), job.expires()).await?;
+
+                        // the project's limit is its allocation
+                        if partition.is_none() {
+                            banking::set_allocation(&mapping, &limit, &job.destination().reverse()).await?;
+                        }
+
                         job.completed(limit)
                     }
+                    GetLocalQos(mapping) => {
+                        let qos = slurm::get_qos(&mapping, job.expires()).await?;
+                        job.completed(qos)
+                    }
+                    SetLocalQos(mapping, class) => {
+                        let qos = slurm::set_qos(&mapping, &class, job.expires()).await?;
+                        job.completed(qos)
+                    }
+                    CreateLocalReservation(mapping, dates, nodes, window) => {
+                        let reservation = slurm::create_reservation(&mapping, &dates, nodes, &window, job.expires()).await?;
+                        job.completed(reservation)
+                    }
+                    RemoveLocalReservation(mapping, dates) => {
+                        let reservation = slurm::remove_reservation(&mapping, &dates, job.expires()).await?;
+                        job.completed(reservation)
+                    }
+                    GetLocalJobQueue(mapping) => {
+                        let queue = slurm::get_job_queue(&mapping, job.expires()).await?;
+                        job.completed(queue)
+                    }
+                    GetLocalNodes => {
+                        let nodes = slurm::get_nodes(job.expires()).await?;
+                        job.completed(nodes)
+                    }
+                    SubmitLocalJob(mapping, script) => {
+                        let job_id = slurm::submit_job(&mapping, &script, job.expires()).await?;
+                        job.completed(job_id)
+                    }
+                    ReconcileLocal(mapping, repair, users) => {
+                        let report = slurm::reconcile(&mapping, &users, repair, job.expires()).await?;
+                        job.completed(report)
+                    }
                     _ => {
                         Err(Error::InvalidInstruction(
                             format!("Invalid instruction: {}. Slurm agents do not support this instruction", job.instruction()),
@@ -298,3 +635,35 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+///
+/// Describe the change that a dry run of the mutating instruction in the
+/// passed job would make, without changing anything in Slurm
+///
+fn dry_run(job: &Job) -> Result<Job, Error> {
+    match job.instruction() {
+        AddLocalProject(project) => {
+            dryrun::record(&format!("add account {}", project));
+            job.completed_none()
+        }
+        RemoveLocalProject(project) => {
+            dryrun::record(&format!("cancel pending jobs of account {}", project));
+            job.completed_none()
+        }
+        AddLocalUser(user) => {
+            dryrun::record(&format!("add user {}", user));
+            job.completed_none()
+        }
+        RemoveLocalUser(mapping) => {
+            dryrun::record(&format!(
+                "disable user {} and cancel their pending jobs",
+                mapping
+            ));
+            job.completed_none()
+        }
+        _ => Err(Error::Incompatible(format!(
+            "Slurm cannot rehearse {} as a dry run",
+            job.instruction()
+        ))),
+    }
+}
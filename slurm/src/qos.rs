@@ -0,0 +1,95 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Slurm QOS definitions for each class of project.
+//!
+//! The `slurm-qos` extra holds a JSON object that maps each project
+//! class (the project template name) to the limits of the QOS that
+//! projects of that class should be given, e.g.
+//!
+//! ```json
+//! {
+//!     "standard": {"priority": 10, "max_wall": "1-00:00:00"},
+//!     "gpu-large": {"priority": 100, "max_tres": "gres/gpu=16", "max_wall": "2-00:00:00"}
+//! }
+//! ```
+//!
+//! Each project is given its own QOS, named after its Slurm account,
+//! so that the limits of one project can later be changed without
+//! affecting any other.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use templemeads::Error;
+
+/// The limits of the QOS given to every project of a class
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SlurmQos {
+    /// The priority of jobs run under this QOS
+    #[serde(default)]
+    priority: Option<u32>,
+
+    /// The maximum trackable resources per job, e.g. "cpu=256,gres/gpu=8"
+    #[serde(default)]
+    max_tres: Option<String>,
+
+    /// The maximum wall time per job, e.g. "2-00:00:00"
+    #[serde(default)]
+    max_wall: Option<String>,
+}
+
+impl SlurmQos {
+    ///
+    /// Return the `Priority`, `MaxTRES` and `MaxWall` settings to pass to
+    /// `sacctmgr modify qos`. Limits that are not set are cleared (-1),
+    /// and the priority reset to 0, so that a project that changes class
+    /// does not keep the limits of its old class
+    ///
+    pub fn sacctmgr_settings(&self) -> Vec<String> {
+        vec![
+            format!("Priority={}", self.priority.unwrap_or_default()),
+            format!(
+                "MaxTRES={}",
+                self.max_tres.clone().unwrap_or_else(|| "-1".to_string())
+            ),
+            format!(
+                "MaxWall={}",
+                self.max_wall.clone().unwrap_or_else(|| "-1".to_string())
+            ),
+        ]
+    }
+}
+
+///
+/// Parse the JSON value of the `slurm-qos` extra into the QOS of
+/// each project class
+///
+pub fn parse_classes(json: &str) -> Result<HashMap<String, SlurmQos>, Error> {
+    let classes: HashMap<String, SlurmQos> = serde_json::from_str(json).map_err(|e| {
+        Error::Misconfigured(format!(
+            "Invalid slurm-qos provided. This should be a JSON object mapping each project class to its QOS limits: {}",
+            e
+        ))
+    })?;
+
+    for (class, qos) in &classes {
+        for value in [&qos.max_tres, &qos.max_wall].into_iter().flatten() {
+            if value.trim().is_empty() || value.contains(char::is_whitespace) {
+                return Err(Error::Misconfigured(format!(
+                    "Invalid QOS limit '{}' for project class '{}'. Limits cannot be empty or contain spaces",
+                    value, class
+                )));
+            }
+        }
+    }
+
+    Ok(classes)
+}
+
+///
+/// Return the name of the QOS of the passed Slurm account
+///
+pub fn qos_name(account: &str) -> String {
+    format!("{}_qos", account)
+}
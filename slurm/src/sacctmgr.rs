@@ -1,25 +1,31 @@
 // SPDX-FileCopyrightText: © 2024 Christopher Woods <Christopher.Woods@bristol.ac.uk>
 // SPDX-License-Identifier: MIT
 
-use anyhow::Context;
 use anyhow::Result;
-use chrono::Utc;
+use chrono::{TimeZone, Utc};
 use once_cell::sync::Lazy;
 use rand::seq::IteratorRandom;
 use rand::SeedableRng;
+use std::collections::HashMap;
 use std::sync::Arc;
-use templemeads::grammar::{DateRange, ProjectMapping, UserMapping};
+use templemeads::batch;
+use templemeads::exec;
+use templemeads::grammar::{DateRange, Node, ProjectMapping, ProjectTemplate, UserMapping};
 use templemeads::job::assert_not_expired;
+use templemeads::jobqueue::{JobQueue, QueuedJob};
+use templemeads::reconcile::ReconciliationReport;
 use templemeads::usagereport::{DailyProjectUsageReport, ProjectUsageReport, Usage};
 use templemeads::Error;
 use tokio::sync::Mutex;
 
 use crate::cache;
+use crate::qos::qos_name;
 use crate::slurm::{
-    clean_account_name, clean_user_name, get_managed_organization, SlurmAccount, SlurmLimit,
-    SlurmUser,
+    clean_account_name, clean_user_name, get_managed_organization, SlurmAccount, SlurmAssociation,
+    SlurmLimit, SlurmUser,
 };
-use crate::slurm::{SlurmJob, SlurmNodes};
+use crate::slurm::{SlurmJob, SlurmNode, SlurmNodes};
+use crate::slurmdbd;
 
 #[derive(Debug, Clone)]
 struct SlurmRunner {
@@ -27,6 +33,8 @@ struct SlurmRunner {
     sacctmgr: String,
     scontrol: String,
     scancel: String,
+    squeue: String,
+    sbatch: String,
 }
 
 impl Default for SlurmRunner {
@@ -36,6 +44,8 @@ impl Default for SlurmRunner {
             sacctmgr: "sacctmgr".to_string(),
             scontrol: "scontrol".to_string(),
             scancel: "scancel".to_string(),
+            squeue: "squeue".to_string(),
+            sbatch: "sbatch".to_string(),
         }
     }
 }
@@ -71,6 +81,14 @@ impl LockedRunner {
         &self.runner.scancel
     }
 
+    pub fn squeue(&self) -> &str {
+        &self.runner.squeue
+    }
+
+    pub fn sbatch(&self) -> &str {
+        &self.runner.sbatch
+    }
+
     /// Build a command safely from a vector of arguments
     /// This is the preferred method to avoid command injection
     ///
@@ -82,9 +100,11 @@ impl LockedRunner {
             "SCONTROL" => self.scontrol(),
             "SACCT" => self.sacct(),
             "SCANCEL" => self.scancel(),
+            "SQUEUE" => self.squeue(),
+            "SBATCH" => self.sbatch(),
             _ => {
                 return Err(Error::Call(format!(
-                    "Unknown command type: {}. Must be SACCTMGR, SCONTROL, SACCT, or SCANCEL",
+                    "Unknown command type: {}. Must be SACCTMGR, SCONTROL, SACCT, SCANCEL, SQUEUE or SBATCH",
                     cmd_type
                 )));
             }
@@ -120,70 +140,27 @@ impl LockedRunner {
             return Err(Error::Call("Empty command vector".to_string()));
         }
 
-        tracing::debug!("Running command: {:?}", cmd);
-
-        let start_time = chrono::Utc::now();
-        let output = tokio::process::Command::new(&cmd[0])
-            .args(&cmd[1..])
-            .kill_on_drop(true)
-            .output();
-
-        // use a tokio timeout to ensure we won't block indefinitely
-        let output = match tokio::time::timeout(timeout, output).await {
-            Ok(output) => output,
-            Err(_) => {
-                tracing::error!(
-                    "Command {:?} timed out after {:?} seconds",
-                    cmd,
-                    timeout.as_secs()
-                );
-                return Err(Error::Timeout("Command timed out".to_string()));
-            }
-        };
-
-        let end_time = chrono::Utc::now();
-
-        let duration_ms = (end_time - start_time).num_milliseconds();
+        let output = exec::Command::from_parts(cmd)
+            .timeout(timeout)
+            .output()
+            .await
+            .map_err(|failure| {
+                tracing::error!("{}", failure);
+                Error::from(failure)
+            })?;
 
-        if duration_ms > 5000 {
+        if output.duration.as_millis() > 5000 {
             tracing::warn!(
                 "Running command {:?} took {} seconds",
                 cmd,
-                duration_ms as f64 / 1000.0
+                output.duration.as_secs_f64()
             );
         }
 
-        let output = match output {
-            Ok(output) => output,
-            Err(e) => {
-                tracing::error!("Could not run command {:?}: {}", cmd, e);
-                return Err(Error::Call("Could not run command".to_string()));
-            }
-        };
-
-        if output.status.success() {
-            let output = match String::from_utf8(output.stdout.clone()) {
-                Ok(output) => output,
-                Err(e) => {
-                    tracing::error!("Could not parse output: {}", e);
-                    tracing::error!("Output: {:?}", output.stdout);
-                    return Err(Error::Call("Could not parse output".to_string()));
-                }
-            };
-
-            Ok(output)
-        } else {
-            tracing::error!(
-                "Command {:?} failed: {}",
-                cmd,
-                String::from_utf8(output.stderr.clone()).context("Could not parse error")?
-            );
-            Err(Error::Call(format!(
-                "Command {:?} failed: {}",
-                cmd,
-                String::from_utf8(output.stderr).context("Could not parse error")?
-            )))
-        }
+        output.check().map_err(|failure| {
+            tracing::error!("{}", failure);
+            Error::from(failure)
+        })
     }
 
     pub async fn run_json(
@@ -215,6 +192,255 @@ impl LockedRunner {
             }
         }
     }
+
+    ///
+    /// Run the passed command, and stream its JSON output through `f`,
+    /// one element at a time of the array held under the top-level
+    /// `key`. The output is parsed as it is read, so only the items
+    /// that `f` returns are held in memory, rather than the whole
+    /// (potentially hundreds of MB) output of the command
+    ///
+    pub async fn run_json_array<T, F>(
+        &self,
+        cmd: &[String],
+        key: &str,
+        timeout: std::time::Duration,
+        f: F,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: Send + 'static,
+        F: FnMut(serde_json::Value) -> Option<T> + Send + 'static,
+    {
+        if cmd.is_empty() {
+            return Err(Error::Call("Empty command vector".to_string()));
+        }
+
+        tracing::debug!("Running command: {:?}", cmd);
+
+        let start_time = chrono::Utc::now();
+
+        // the command is run through exec, so is killed if it takes
+        // longer than the timeout
+        let items = stream_json_array(cmd, key, timeout, f).await?;
+
+        let duration_ms = (chrono::Utc::now() - start_time).num_milliseconds();
+
+        if duration_ms > 5000 {
+            tracing::warn!(
+                "Running and parsing command {:?} took {} seconds",
+                cmd,
+                duration_ms as f64 / 1000.0
+            );
+        }
+
+        Ok(items)
+    }
+}
+
+/// The size of each chunk of output passed from a command to its parser
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The maximum number of chunks waiting to be parsed. This bounds the
+/// memory used to STREAM_CHUNK_SIZE * STREAM_CHUNK_QUEUE bytes
+const STREAM_CHUNK_QUEUE: usize = 16;
+
+async fn stream_json_array<T, F>(
+    cmd: &[String],
+    key: &str,
+    timeout: std::time::Duration,
+    f: F,
+) -> Result<Vec<T>, Error>
+where
+    T: Send + 'static,
+    F: FnMut(serde_json::Value) -> Option<T> + Send + 'static,
+{
+    // serde_json parses from a synchronous reader, so the parser runs on
+    // a blocking thread, fed chunks of output through a bounded channel
+    let (tx, rx) = tokio::sync::mpsc::channel::<Vec<u8>>(STREAM_CHUNK_QUEUE);
+
+    let key = key.to_string();
+    let parser = tokio::task::spawn_blocking(move || {
+        let reader = std::io::BufReader::new(ChunkReader::new(rx));
+        let mut deserializer = serde_json::Deserializer::from_reader(reader);
+        let mut items = Vec::new();
+
+        serde::de::DeserializeSeed::deserialize(
+            JsonArraySeed {
+                key: &key,
+                f,
+                items: &mut items,
+            },
+            &mut deserializer,
+        )?;
+
+        deserializer.end()?;
+
+        Ok::<Vec<T>, serde_json::Error>(items)
+    });
+
+    // if the parser stops early (so has hit an error) then the command
+    // is killed, as nothing will read the rest of its output
+    let output = exec::Command::from_parts(cmd)
+        .timeout(timeout)
+        .stream(tx, STREAM_CHUNK_SIZE)
+        .await
+        .map_err(|failure| {
+            tracing::error!("{}", failure);
+            Error::from(failure)
+        })?;
+
+    if let Some(output) = output {
+        output.check().map_err(|failure| {
+            tracing::error!("{}", failure);
+            Error::from(failure)
+        })?;
+    }
+
+    match parser.await {
+        Ok(Ok(items)) => Ok(items),
+        Ok(Err(e)) => {
+            tracing::error!("Could not parse json output of {:?}: {}", cmd, e);
+            Err(Error::Call("Could not parse json".to_string()))
+        }
+        Err(e) => Err(Error::Call(format!("JSON parser failed: {}", e))),
+    }
+}
+
+/// A synchronous reader over the chunks of output sent by a command
+struct ChunkReader {
+    rx: tokio::sync::mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl ChunkReader {
+    fn new(rx: tokio::sync::mpsc::Receiver<Vec<u8>>) -> Self {
+        Self {
+            rx,
+            chunk: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl std::io::Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos >= self.chunk.len() {
+            match self.rx.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+/// Deserializes a top-level JSON object, passing each element of the
+/// array under `key` to `f` as it is parsed. All other values are
+/// skipped without being stored
+struct JsonArraySeed<'a, T, F> {
+    key: &'a str,
+    f: F,
+    items: &'a mut Vec<T>,
+}
+
+impl<'de, T, F> serde::de::DeserializeSeed<'de> for JsonArraySeed<'_, T, F>
+where
+    F: FnMut(serde_json::Value) -> Option<T>,
+{
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> std::result::Result<(), D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, T, F> serde::de::Visitor<'de> for JsonArraySeed<'_, T, F>
+where
+    F: FnMut(serde_json::Value) -> Option<T>,
+{
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            formatter,
+            "a JSON object containing the array '{}'",
+            self.key
+        )
+    }
+
+    fn visit_map<A>(mut self, mut map: A) -> std::result::Result<(), A::Error>
+    where
+        A: serde::de::MapAccess<'de>,
+    {
+        while let Some(key) = map.next_key::<String>()? {
+            if key == self.key {
+                map.next_value_seed(JsonArrayElements {
+                    f: &mut self.f,
+                    items: self.items,
+                })?;
+            } else {
+                map.next_value::<serde::de::IgnoredAny>()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Passes each element of a JSON array to `f` as it is parsed
+struct JsonArrayElements<'a, T, F> {
+    f: &'a mut F,
+    items: &'a mut Vec<T>,
+}
+
+impl<'de, T, F> serde::de::DeserializeSeed<'de> for JsonArrayElements<'_, T, F>
+where
+    F: FnMut(serde_json::Value) -> Option<T>,
+{
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> std::result::Result<(), D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, T, F> serde::de::Visitor<'de> for JsonArrayElements<'_, T, F>
+where
+    F: FnMut(serde_json::Value) -> Option<T>,
+{
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "a JSON array")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<(), A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        while let Some(item) = seq.next_element::<serde_json::Value>()? {
+            if let Some(item) = (self.f)(item) {
+                self.items.push(item);
+            }
+        }
+
+        Ok(())
+    }
 }
 
 /// The default timeout (30 seconds)
@@ -306,8 +532,9 @@ async fn force_add_slurm_account(
         )));
     }
 
-    // get the cluster name from the cache
-    let cluster = cache::get_cluster().await?;
+    // get the cluster names from the cache - the account is added
+    // to every cluster in the federation
+    let clusters = cache::get_clusters().await?.join(",");
 
     // get the parent account name from the cache
     let parent_account = cache::get_parent_account().await?;
@@ -319,7 +546,7 @@ async fn force_add_slurm_account(
             "add".to_string(),
             "account".to_string(),
             format!("name={}", account.name()),
-            format!("cluster={}", cluster),
+            format!("cluster={}", clusters),
             format!("parent={}", parent_account),
             format!("organization={}", account.organization()),
             format!("description={}", account.description()),
@@ -525,6 +752,32 @@ async fn get_user_from_slurm(
     expires: &chrono::DateTime<Utc>,
 ) -> Result<Option<SlurmUser>, Error> {
     let user = clean_user_name(user)?;
+
+    let slurm_user = get_users_from_slurm(std::slice::from_ref(&user), expires)
+        .await?
+        .into_iter()
+        .find(|u| u.name() == user);
+
+    if slurm_user.is_none() {
+        tracing::warn!("Could not find user '{}' in slurm", user);
+    }
+
+    Ok(slurm_user)
+}
+
+///
+/// Return the passed (cleaned) users, with their associations, using a
+/// single call to sacctmgr. Users who do not exist in slurm are not
+/// returned
+///
+async fn get_users_from_slurm(
+    users: &[String],
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Vec<SlurmUser>, Error> {
+    if users.is_empty() {
+        return Ok(Vec::new());
+    }
+
     let cluster = cache::get_cluster().await?;
 
     let cmd = priority_runner(expires).await?.build_command(
@@ -534,7 +787,7 @@ async fn get_user_from_slurm(
             "list".to_string(),
             "users".to_string(),
             "WithAssoc".to_string(),
-            format!("name={}", user),
+            format!("name={}", users.join(",")),
             format!("cluster={}", cluster),
         ],
     )?;
@@ -544,45 +797,39 @@ async fn get_user_from_slurm(
         .run_json(&cmd, DEFAULT_TIMEOUT)
         .await?;
 
-    // there should be a users list, with a single entry for this user
-    let users = match response.get("users") {
+    // there should be a users list, with an entry for each user
+    let slurm_users = match response.get("users") {
         Some(users) => users,
         None => {
             tracing::warn!("Could not get users from response: {:?}", response);
-            return Ok(None);
+            return Ok(Vec::new());
         }
     };
 
     // this should be an array
-    let users = match users.as_array() {
+    let slurm_users = match slurm_users.as_array() {
         Some(users) => users,
         None => {
-            tracing::warn!("Users is not an array: {:?}", users);
-            return Ok(None);
-        }
-    };
-
-    // there should be an User object in this array with the right name
-    let slurm_user = users.iter().find(|u| {
-        let name = u.get("name").and_then(|n| n.as_str());
-        name == Some(&user)
-    });
-
-    let user = match slurm_user {
-        Some(user) => user,
-        None => {
-            tracing::warn!("Could not find user '{}' in response: {:?}", user, response);
-            return Ok(None);
+            tracing::warn!("Users is not an array: {:?}", slurm_users);
+            return Ok(Vec::new());
         }
     };
 
-    match SlurmUser::construct(user) {
-        Ok(user) => Ok(Some(user)),
-        Err(e) => {
-            tracing::warn!("Could not construct user from response: {}", e);
-            Ok(None)
-        }
-    }
+    Ok(slurm_users
+        .iter()
+        .filter(|u| {
+            u.get("name")
+                .and_then(|n| n.as_str())
+                .is_some_and(|name| users.iter().any(|user| user == name))
+        })
+        .filter_map(|u| match SlurmUser::construct(u) {
+            Ok(user) => Some(user),
+            Err(e) => {
+                tracing::warn!("Could not construct user from response: {}", e);
+                None
+            }
+        })
+        .collect())
 }
 
 async fn get_user(user: &str, expires: &chrono::DateTime<Utc>) -> Result<Option<SlurmUser>, Error> {
@@ -663,8 +910,9 @@ async fn add_account_association(
         )));
     }
 
-    // get the cluster name from the cache
-    let cluster = cache::get_cluster().await?;
+    // get the cluster names from the cache - the association is
+    // added to every cluster in the federation
+    let clusters = cache::get_clusters().await?.join(",");
 
     // get the parent account name from the cache
     let parent_account = cache::get_parent_account().await?;
@@ -676,7 +924,7 @@ async fn add_account_association(
             "add".to_string(),
             "account".to_string(),
             format!("name={}", account.name()),
-            format!("Clusters={}", cluster),
+            format!("Clusters={}", clusters),
             format!("parent={}", parent_account),
             format!("Associations={}", account.name()),
             "Comment=Created by OpenPortal".to_string(),
@@ -707,6 +955,7 @@ async fn add_user_association(
     let mut user = user.clone();
     let mut user_changed = false;
     let cluster = cache::get_cluster().await?;
+    let clusters = cache::get_clusters().await?.join(",");
 
     if user
         .associations()
@@ -732,7 +981,7 @@ async fn add_user_association(
                 "add".to_string(),
                 "user".to_string(),
                 format!("name={}", user.name()),
-                format!("Clusters={}", cluster),
+                format!("Clusters={}", clusters),
                 format!("Accounts={}", account.name()),
                 "Comment=Created by OpenPortal".to_string(),
             ],
@@ -769,7 +1018,7 @@ async fn add_user_association(
                 "add".to_string(),
                 "user".to_string(),
                 format!("name={}", user.name()),
-                format!("Clusters={}", cluster),
+                format!("Clusters={}", clusters),
                 format!("DefaultAccount={}", account.name()),
                 "Comment=Updated by OpenPortal".to_string(),
             ],
@@ -844,7 +1093,8 @@ async fn get_user_create_if_not_exists(
     let username = clean_user_name(user.local_user())?;
     let account = clean_account_name(slurm_account.name())?;
 
-    let cluster = cache::get_cluster().await?;
+    // the user is added to every cluster in the federation
+    let clusters = cache::get_clusters().await?.join(",");
 
     let cmd = priority_runner(expires).await?.build_command(
         "SACCTMGR",
@@ -853,7 +1103,7 @@ async fn get_user_create_if_not_exists(
             "add".to_string(),
             "user".to_string(),
             format!("name={}", username),
-            format!("Clusters={}", cluster),
+            format!("Clusters={}", clusters),
             format!("Accounts={}", account),
             format!("DefaultAccount={}", account),
             "Comment=Created by OpenPortal".to_string(),
@@ -890,34 +1140,46 @@ async fn get_user_create_if_not_exists(
     Ok(slurm_user)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn set_commands(
     sacct: &str,
     sacctmgr: &str,
     scontrol: &str,
     scancel: &str,
+    squeue: &str,
+    sbatch: &str,
     max_slurm_runners: u64,
+    max_sacct_runners: u64,
 ) {
     tracing::debug!(
-        "Using command line slurmd commands: sacctmgr: {}, scontrol: {}, scancel: {}, max_slurm_runners: {}",
+        "Using command line slurmd commands: sacctmgr: {}, scontrol: {}, scancel: {}, squeue: {}, sbatch: {}, max_slurm_runners: {}, max_sacct_runners: {}",
         sacctmgr,
         scontrol,
         scancel,
-        max_slurm_runners
+        squeue,
+        sbatch,
+        max_slurm_runners,
+        max_sacct_runners
     );
 
-    // make sure we have at least one runner
+    // make sure we have at least one runner of each type
     let max_slurm_runners = max_slurm_runners.max(1);
+    let max_sacct_runners = max_sacct_runners.max(1);
 
+    // these runners are only used for the (potentially slow) sacct
+    // queries that generate usage reports
     let mut runners = SLURM_RUNNERS.lock().await;
 
     runners.clear();
 
-    for _ in 0..max_slurm_runners {
+    for _ in 0..max_sacct_runners {
         runners.push(Arc::new(Mutex::new(SlurmRunner {
             sacct: sacct.to_string(),
             sacctmgr: sacctmgr.to_string(),
             scontrol: scontrol.to_string(),
             scancel: scancel.to_string(),
+            squeue: squeue.to_string(),
+            sbatch: sbatch.to_string(),
         })));
     }
 
@@ -933,6 +1195,8 @@ pub async fn set_commands(
             sacctmgr: sacctmgr.to_string(),
             scontrol: scontrol.to_string(),
             scancel: scancel.to_string(),
+            squeue: squeue.to_string(),
+            sbatch: sbatch.to_string(),
         })));
     }
 }
@@ -987,9 +1251,77 @@ pub async fn find_cluster() -> Result<(), Error> {
         cache::set_cluster(&clusters[0]).await?;
     }
 
+    // every other cluster in the federation must also exist
+    for federated_cluster in cache::get_clusters().await?.iter().skip(1) {
+        if !clusters.contains(federated_cluster) {
+            tracing::warn!(
+                "Federated cluster {} not found in list of clusters: {:?}",
+                federated_cluster,
+                clusters
+            );
+            return Err(Error::Login(format!(
+                "Federated cluster {} not found",
+                federated_cluster
+            )));
+        }
+    }
+
     Ok(())
 }
 
+///
+/// Return the distinct types of node managed by slurm, read from
+/// `scontrol show nodes`. The resources of every node are also cached,
+/// so that the usage of each job is calculated from the hardware that
+/// it actually ran on, rather than from the default node
+///
+pub async fn get_nodes(expires: &chrono::DateTime<Utc>) -> Result<Vec<Node>, Error> {
+    assert_not_expired(expires)?;
+
+    let cmd = priority_runner(expires).await?.build_command(
+        "SCONTROL",
+        vec![
+            "--json".to_string(),
+            "show".to_string(),
+            "nodes".to_string(),
+        ],
+    )?;
+
+    let response = priority_runner(expires)
+        .await?
+        .run_json(&cmd, DEFAULT_TIMEOUT)
+        .await?;
+
+    let nodes = match response.get("nodes").and_then(|nodes| nodes.as_array()) {
+        Some(nodes) => nodes,
+        None => {
+            tracing::warn!("Could not get nodes from scontrol: {:?}", response);
+            return Err(Error::Call("Could not get nodes from scontrol".to_string()));
+        }
+    };
+
+    let mut profiles: Vec<Node> = Vec::new();
+
+    for node in nodes {
+        match SlurmNode::from_scontrol(node) {
+            Ok((name, slurm_node, profile)) => {
+                cache::set_node(&name, &slurm_node).await?;
+
+                if !profiles.contains(&profile) {
+                    profiles.push(profile);
+                }
+            }
+            Err(e) => {
+                tracing::warn!("Could not read node {:?}: {}", node, e);
+            }
+        }
+    }
+
+    tracing::info!("Found {} nodes of {} types", nodes.len(), profiles.len());
+
+    Ok(profiles)
+}
+
 pub async fn add_project(
     project: &ProjectMapping,
     expires: &chrono::DateTime<Utc>,
@@ -1008,74 +1340,485 @@ pub async fn add_project(
 pub async fn add_user(user: &UserMapping, expires: &chrono::DateTime<Utc>) -> Result<(), Error> {
     assert_not_expired(expires)?;
 
-    let user: SlurmUser = get_user_create_if_not_exists(user, expires).await?;
+    let slurm_user: SlurmUser = get_user_create_if_not_exists(user, expires).await?;
+
+    // re-enable the user if they had previously been removed
+    enable_user(user, expires).await?;
 
-    tracing::info!("Added user: {}", user);
+    tracing::info!("Added user: {}", slurm_user);
 
     Ok(())
 }
 
-async fn get_hourly_report(
+///
+/// Add a batch of users, who are all in the same project. The users who
+/// are not yet default associated with the project's account are added
+/// with a single call to sacctmgr, and any disabled associations are
+/// re-enabled with another. If this fails, the users are added one at a
+/// time, so that only the users at fault fail
+///
+pub async fn add_users(
+    mappings: Vec<UserMapping>,
     expires: &chrono::DateTime<Utc>,
-    project: &ProjectMapping,
-    day: &templemeads::grammar::Date,
-    account: &SlurmAccount,
-    slurm_nodes: &SlurmNodes,
-    cluster: &str,
-    partition_command: &str,
-) -> Result<DailyProjectUsageReport, Error> {
-    let now = chrono::Utc::now();
-    let mut daily_report = DailyProjectUsageReport::default();
-    let mut total_usage: u64 = 0;
-    let mut num_jobs: u64 = 0;
-    let mut total_wait_seconds: u64 = 0;
+) -> batch::BatchResult {
+    match add_users_together(&mappings, expires).await {
+        Ok(()) => {
+            let mut result = batch::BatchResult::new();
 
-    // we need to get the report hour by hour from slurm, as users may have
-    // run very large numbers of jobs in a day, and sacct may time out
-    for hour in day.hours() {
-        if let Some(hourly_report) = cache::get_hourly_report(project.project(), &hour).await? {
-            // we have this hour in the cache, so use it
-            tracing::debug!(
-                "Using cached hourly report for {}. Number of jobs = {}",
-                hour,
-                hourly_report.len()
+            for mapping in mappings {
+                result.add_success(mapping);
+            }
+
+            result
+        }
+        Err(e) => {
+            tracing::warn!(
+                "Could not add the batch of {} users together: {}. Adding them one at a time.",
+                mappings.len(),
+                e
             );
 
-            let hour_start_time = hour.start_time().and_utc();
+            batch::run_each(mappings, 1, |mapping| async move {
+                add_user(&mapping, expires).await?;
+                Ok(mapping)
+            })
+            .await
+        }
+    }
+}
 
-            for job in hourly_report {
-                total_usage += job.billed_node_seconds();
-                daily_report.add_usage(job.user(), Usage::new(job.billed_node_seconds()));
+async fn add_users_together(
+    mappings: &[UserMapping],
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    assert_not_expired(expires)?;
 
-                if job.original_start_time() >= &hour_start_time {
-                    num_jobs += 1;
-                    total_wait_seconds += job.wait_time().num_seconds() as u64;
-                    daily_report.add_jobs(job.user(), 1);
-                    daily_report.add_wait_seconds(job.user(), job.wait_time().num_seconds() as u64);
-                }
-            }
+    let mapping = match mappings.first() {
+        Some(mapping) => mapping,
+        None => return Ok(()),
+    };
 
-            continue;
-        }
+    // the users are all in the same project, so share its account
+    let slurm_account = get_account_create_if_not_exists(
+        &SlurmAccount::from_mapping(&mapping.clone().into())?,
+        expires,
+    )
+    .await?;
 
-        assert_not_expired(expires)?;
+    let cluster = cache::get_cluster().await?;
 
-        let start_time = hour.start_time().and_utc();
-        let end_time = hour.end_time().and_utc();
+    let users = mappings
+        .iter()
+        .map(|mapping| clean_user_name(mapping.local_user()))
+        .collect::<Result<Vec<String>, Error>>()?;
 
-        if start_time > now {
-            // we can't get the usage for this hour yet as it is in the future
-            continue;
-        }
+    let is_added = |user: &SlurmUser| {
+        *user.default_account() == Some(slurm_account.name().to_string())
+            && user
+                .associations()
+                .iter()
+                .any(|a| a.account() == slurm_account.name() && a.cluster() == cluster)
+    };
 
-        let end_time = match now < end_time {
-            true => now,
-            false => end_time,
-        };
+    let existing = get_users_from_slurm(&users, expires).await?;
 
-        // check that the hour contains <= 3600 seconds
-        if end_time.timestamp() - start_time.timestamp() > 3600 {
-            tracing::warn!(
+    let to_add: Vec<String> = users
+        .iter()
+        .filter(|user| !existing.iter().any(|u| u.name() == *user && is_added(u)))
+        .cloned()
+        .collect();
+
+    if !to_add.is_empty() {
+        // the users are added to every cluster in the federation
+        let clusters = cache::get_clusters().await?.join(",");
+        let account = clean_account_name(slurm_account.name())?;
+
+        let cmd = priority_runner(expires).await?.build_command(
+            "SACCTMGR",
+            vec![
+                "--immediate".to_string(),
+                "add".to_string(),
+                "user".to_string(),
+                format!("name={}", to_add.join(",")),
+                format!("Clusters={}", clusters),
+                format!("Accounts={}", account),
+                format!("DefaultAccount={}", account),
+                "Comment=Created by OpenPortal".to_string(),
+            ],
+        )?;
+
+        priority_runner(expires)
+            .await?
+            .run(&cmd, DEFAULT_TIMEOUT)
+            .await?;
+
+        // check that every user now has their association
+        let added = get_users_from_slurm(&to_add, expires).await?;
+
+        for user in &to_add {
+            match added.iter().find(|u| u.name() == *user && is_added(u)) {
+                Some(slurm_user) => cache::add_user(slurm_user).await?,
+                None => {
+                    return Err(Error::Call(format!(
+                        "User '{}' is not default associated with account '{}' after adding",
+                        user,
+                        slurm_account.name()
+                    )))
+                }
+            }
+        }
+    }
+
+    // re-enable any users who had previously been removed
+    let disabled = get_disabled_users(&users, slurm_account.name(), expires).await?;
+
+    if !disabled.is_empty() {
+        // -1 clears the limits
+        set_users_job_limit(&disabled, slurm_account.name(), "-1", expires).await?;
+
+        tracing::info!(
+            "Re-enabled users {} in account {}",
+            disabled.join(", "),
+            slurm_account.name()
+        );
+    }
+
+    tracing::info!(
+        "Added users {} to account {}",
+        users.join(", "),
+        slurm_account.name()
+    );
+
+    Ok(())
+}
+
+///
+/// Return the jobs of the passed accounts that consumed resources between
+/// `start_time` and `end_time`. These are read using `sacct --json`, with
+/// the output parsed job by job as it is streamed from sacct. If the agent
+/// is connected to the slurmdbd database then the jobs are read from there
+/// instead
+///
+#[allow(clippy::too_many_arguments)]
+async fn get_consumers(
+    expires: &chrono::DateTime<Utc>,
+    accounts: &[String],
+    cluster: &str,
+    partition_command: &str,
+    timeout: std::time::Duration,
+    start_time: &chrono::DateTime<Utc>,
+    end_time: &chrono::DateTime<Utc>,
+    slurm_nodes: &SlurmNodes,
+) -> Result<Vec<SlurmJob>, Error> {
+    if start_time > end_time {
+        return Err(Error::Call(format!(
+            "Start time '{}' is after end time '{}'",
+            start_time, end_time
+        )));
+    }
+
+    if slurmdbd::is_connected().await {
+        let mut jobs = Vec::new();
+
+        for account in accounts {
+            assert_not_expired(expires)?;
+
+            jobs.extend(
+                slurmdbd::get_consumers(account, start_time, end_time, slurm_nodes, timeout)
+                    .await?,
+            );
+        }
+
+        return Ok(jobs);
+    }
+
+    let runner = runner(expires).await?;
+
+    let cmd = runner.build_command(
+        "SACCT",
+        vec![
+            "--noconvert".to_string(),
+            "--allocations".to_string(),
+            "--allusers".to_string(),
+            format!("--starttime={}", start_time.format("%Y-%m-%dT%H:%M:%S")),
+            format!("--endtime={}", end_time.format("%Y-%m-%dT%H:%M:%S")),
+            format!("--account={}", accounts.join(",")),
+            format!("--cluster={}", cluster),
+            partition_command.to_string(),
+            "--json".to_string(),
+        ],
+    )?;
+
+    let start_time = *start_time;
+    let end_time = *end_time;
+    let slurm_nodes = slurm_nodes.clone();
+
+    runner
+        .run_json_array(&cmd, "jobs", timeout, move |job| {
+            SlurmJob::get_consumer(&job, &start_time, &end_time, &slurm_nodes)
+        })
+        .await
+}
+
+/// The jobs of each account in a batch, or the error from fetching them
+type BatchResult = Result<Arc<HashMap<String, Vec<SlurmJob>>>, Arc<Error>>;
+
+/// A batch of accounts whose jobs in the same window will be fetched
+/// by a single sacct invocation
+struct AccountBatch {
+    accounts: Vec<String>,
+    result: tokio::sync::watch::Sender<Option<BatchResult>>,
+}
+
+type BatchWindow = (chrono::DateTime<Utc>, chrono::DateTime<Utc>);
+
+static ACCOUNT_BATCHES: Lazy<Mutex<HashMap<BatchWindow, AccountBatch>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// How long the first account of a batch waits for other accounts to join
+const BATCH_GATHER_TIME: std::time::Duration = std::time::Duration::from_millis(200);
+
+///
+/// Return the jobs of the passed account that consumed resources between
+/// `start_time` and `end_time`. Concurrent requests for the same window
+/// from different accounts are combined, so that up to
+/// `sacct-batch-accounts` accounts are fetched by a single sacct invocation
+///
+#[allow(clippy::too_many_arguments)]
+async fn get_batched_consumers(
+    expires: &chrono::DateTime<Utc>,
+    account: &SlurmAccount,
+    cluster: &str,
+    partition_command: &str,
+    timeout: std::time::Duration,
+    start_time: &chrono::DateTime<Utc>,
+    end_time: &chrono::DateTime<Utc>,
+    slurm_nodes: &SlurmNodes,
+) -> Result<Vec<SlurmJob>, Error> {
+    let batch_accounts = cache::get_sacct_batch_accounts().await?;
+    let account = account.name().to_string();
+
+    if batch_accounts <= 1 || slurmdbd::is_connected().await {
+        return get_consumers(
+            expires,
+            &[account],
+            cluster,
+            partition_command,
+            timeout,
+            start_time,
+            end_time,
+            slurm_nodes,
+        )
+        .await;
+    }
+
+    let window = (*start_time, *end_time);
+
+    enum Role {
+        Leader,
+        Follower(tokio::sync::watch::Receiver<Option<BatchResult>>),
+        Alone,
+    }
+
+    let role = {
+        let mut batches = ACCOUNT_BATCHES.lock().await;
+
+        match batches.get_mut(&window) {
+            Some(batch) => {
+                if batch.accounts.len() < batch_accounts as usize
+                    && !batch.accounts.contains(&account)
+                {
+                    batch.accounts.push(account.clone());
+                    Role::Follower(batch.result.subscribe())
+                } else {
+                    // the batch is full, so fetch this account on its own
+                    Role::Alone
+                }
+            }
+            None => {
+                let (result, _) = tokio::sync::watch::channel(None);
+
+                batches.insert(
+                    window,
+                    AccountBatch {
+                        accounts: vec![account.clone()],
+                        result,
+                    },
+                );
+
+                Role::Leader
+            }
+        }
+    };
+
+    match role {
+        Role::Leader => (),
+        Role::Follower(mut receiver) => {
+            // wait for the first account of the batch to fetch our jobs
+            let wait = receiver.wait_for(|result| result.is_some());
+
+            let result = match tokio::time::timeout(2 * timeout, wait).await {
+                Ok(Ok(result)) => result.clone(),
+                Ok(Err(_)) => {
+                    return Err(Error::Call("Batched sacct query was abandoned".to_string()))
+                }
+                Err(_) => {
+                    return Err(Error::Timeout(
+                        "Timed out waiting for batched sacct query".to_string(),
+                    ))
+                }
+            };
+
+            return match result {
+                Some(Ok(jobs)) => Ok(jobs.get(&account).cloned().unwrap_or_default()),
+                Some(Err(e)) => match e.as_ref() {
+                    Error::Timeout(message) => Err(Error::Timeout(message.clone())),
+                    e => Err(Error::Call(e.to_string())),
+                },
+                None => Err(Error::Bug("Empty batched sacct result".to_string())),
+            };
+        }
+        Role::Alone => {
+            return get_consumers(
+                expires,
+                &[account],
+                cluster,
+                partition_command,
+                timeout,
+                start_time,
+                end_time,
+                slurm_nodes,
+            )
+            .await;
+        }
+    }
+
+    // give other accounts the chance to join the batch
+    tokio::time::sleep(BATCH_GATHER_TIME).await;
+
+    let batch = match ACCOUNT_BATCHES.lock().await.remove(&window) {
+        Some(batch) => batch,
+        None => return Err(Error::Bug("Lost batched sacct query".to_string())),
+    };
+
+    if batch.accounts.len() > 1 {
+        tracing::debug!(
+            "Fetching usage of {} accounts in a single query: {}",
+            batch.accounts.len(),
+            batch.accounts.join(",")
+        );
+    }
+
+    let result = get_consumers(
+        expires,
+        &batch.accounts,
+        cluster,
+        partition_command,
+        timeout,
+        start_time,
+        end_time,
+        slurm_nodes,
+    )
+    .await;
+
+    let (shared, result) = match result {
+        Ok(jobs) => {
+            let mut by_account: HashMap<String, Vec<SlurmJob>> = HashMap::new();
+
+            for job in jobs {
+                by_account
+                    .entry(job.account().to_string())
+                    .or_default()
+                    .push(job);
+            }
+
+            let by_account = Arc::new(by_account);
+            let jobs = by_account.get(&account).cloned().unwrap_or_default();
+
+            (Ok(by_account), Ok(jobs))
+        }
+        Err(e) => {
+            let copy = match &e {
+                Error::Timeout(message) => Error::Timeout(message.clone()),
+                e => Error::Call(e.to_string()),
+            };
+
+            (Err(Arc::new(copy)), Err(e))
+        }
+    };
+
+    // there will be no receivers if no other accounts joined the batch
+    let _ = batch.result.send(Some(shared));
+
+    result
+}
+
+async fn get_hourly_report(
+    expires: &chrono::DateTime<Utc>,
+    project: &ProjectMapping,
+    day: &templemeads::grammar::Date,
+    account: &SlurmAccount,
+    slurm_nodes: &SlurmNodes,
+    cluster: &str,
+    partition_command: &str,
+) -> Result<DailyProjectUsageReport, Error> {
+    let now = chrono::Utc::now();
+    let collect_energy = cache::get_collect_energy().await?;
+    let mut daily_report = DailyProjectUsageReport::default();
+    let mut total_usage: u64 = 0;
+    let mut num_jobs: u64 = 0;
+    let mut total_wait_seconds: u64 = 0;
+
+    // we need to get the report hour by hour from slurm, as users may have
+    // run very large numbers of jobs in a day, and sacct may time out
+    for hour in day.hours() {
+        if let Some(hourly_report) = cache::get_hourly_report(project.project(), &hour).await? {
+            // we have this hour in the cache, so use it
+            tracing::debug!(
+                "Using cached hourly report for {}. Number of jobs = {}",
+                hour,
+                hourly_report.len()
+            );
+
+            let hour_start_time = hour.start_time().and_utc();
+
+            for job in hourly_report {
+                total_usage += job.billed_node_seconds();
+                daily_report.add_usage(job.user(), Usage::new(job.billed_node_seconds()));
+
+                if collect_energy {
+                    daily_report.add_energy(job.user(), job.energy_joules());
+                }
+
+                if job.original_start_time() >= &hour_start_time {
+                    num_jobs += 1;
+                    total_wait_seconds += job.wait_time().num_seconds() as u64;
+                    daily_report.add_jobs(job.user(), 1);
+                    daily_report.add_wait_seconds(job.user(), job.wait_time().num_seconds() as u64);
+                }
+            }
+
+            continue;
+        }
+
+        assert_not_expired(expires)?;
+
+        let start_time = hour.start_time().and_utc();
+        let end_time = hour.end_time().and_utc();
+
+        if start_time > now {
+            // we can't get the usage for this hour yet as it is in the future
+            continue;
+        }
+
+        let end_time = match now < end_time {
+            true => now,
+            false => end_time,
+        };
+
+        // check that the hour contains <= 3600 seconds
+        if end_time.timestamp() - start_time.timestamp() > 3600 {
+            tracing::warn!(
                 "Hour {} contains more than 1 hour - check this! {} : {}",
                 hour,
                 start_time,
@@ -1085,27 +1828,17 @@ async fn get_hourly_report(
 
         // now try to get the report for this hour - we use a much longer
         // timeout here as we may be getting a lot of jobs
-        let cmd = runner(expires).await?.build_command(
-            "SACCT",
-            vec![
-                "--noconvert".to_string(),
-                "--allocations".to_string(),
-                "--allusers".to_string(),
-                format!("--starttime={}", start_time.format("%Y-%m-%dT%H:%M:%S")),
-                format!("--endtime={}", end_time.format("%Y-%m-%dT%H:%M:%S")),
-                format!("--account={}", account.name()),
-                format!("--cluster={}", cluster),
-                partition_command.to_string(),
-                "--json".to_string(),
-            ],
-        )?;
-
-        let response = runner(expires)
-            .await?
-            .run_json(&cmd, std::time::Duration::from_secs(120))
-            .await?;
-
-        let jobs = SlurmJob::get_consumers(&response, &start_time, &end_time, slurm_nodes)?;
+        let jobs = get_consumers(
+            expires,
+            &[account.name().to_string()],
+            cluster,
+            partition_command,
+            std::time::Duration::from_secs(120),
+            &start_time,
+            &end_time,
+            slurm_nodes,
+        )
+        .await?;
 
         tracing::debug!(
             "Got {} jobs for project {} on {}",
@@ -1128,6 +1861,10 @@ async fn get_hourly_report(
             total_usage += job.billed_node_seconds();
             daily_report.add_usage(job.user(), Usage::new(job.billed_node_seconds()));
 
+            if collect_energy {
+                daily_report.add_energy(job.user(), job.energy_joules());
+            }
+
             // only count wait time for jobs that started in this hour
             if job.original_start_time() >= &start_time {
                 num_jobs += 1;
@@ -1176,7 +1913,7 @@ async fn get_hourly_report(
         // we can set this day as completed if it is in the past
         daily_report.set_complete();
 
-        match cache::set_report(project.project(), day, &daily_report).await {
+        match cache::set_report(project.project(), account.name(), day, &daily_report).await {
             Ok(_) => (),
             Err(e) => {
                 tracing::error!("Could not cache report for {}: {}", day, e);
@@ -1187,46 +1924,154 @@ async fn get_hourly_report(
     Ok(daily_report)
 }
 
-async fn get_daily_report(
-    expires: &chrono::DateTime<Utc>,
+///
+/// Build the report of the passed day from the passed jobs, which must
+/// have been trimmed to that day. The report is cached if the day is
+/// complete
+///
+async fn build_daily_report(
     project: &ProjectMapping,
     day: &templemeads::grammar::Date,
     account: &SlurmAccount,
-    slurm_nodes: &SlurmNodes,
     cluster: &str,
-    partition_command: &str,
+    jobs: Vec<SlurmJob>,
 ) -> Result<DailyProjectUsageReport, Error> {
-    // see if we have this report in the cache
-    if let Some(report) = cache::get_report(project.project(), day).await? {
-        return Ok(report);
-    }
-
-    assert_not_expired(expires)?;
-
-    if cache::compute_via_hourly_reports(project.project(), day).await? {
-        return get_hourly_report(
-            expires,
-            project,
-            day,
-            account,
-            slurm_nodes,
-            cluster,
-            partition_command,
-        )
-        .await;
-    }
-
     let now = chrono::Utc::now();
     let start_time = day.day().start_time().and_utc();
-    let end_time = day.day().end_time().and_utc();
 
-    if start_time > now {
-        // we can't get the usage for this day yet as it is in the future
-        return Ok(DailyProjectUsageReport::default());
-    }
+    tracing::debug!(
+        "Got {} jobs for project {} on {}",
+        jobs.len(),
+        project.project(),
+        day
+    );
 
-    let end_time = match now < end_time {
-        true => now,
+    let collect_energy = cache::get_collect_energy().await?;
+
+    let mut daily_report = DailyProjectUsageReport::default();
+    let mut total_usage: u64 = 0;
+    let mut num_jobs_started: u64 = 0;
+    let mut total_wait_seconds: u64 = 0;
+
+    for job in jobs {
+        // build the report of this job, so that it can be added
+        // both to the totals and to the breakdown by partition
+        let mut job_report = DailyProjectUsageReport::default();
+
+        total_usage += job.billed_node_seconds();
+        job_report.add_usage(job.user(), Usage::new(job.billed_node_seconds()));
+
+        // only count jobs and wait time for jobs that started in this day
+        if job.original_start_time() >= &start_time {
+            num_jobs_started += 1;
+            total_wait_seconds += job.wait_time().num_seconds() as u64;
+            job_report.add_jobs(job.user(), 1);
+            job_report.add_wait_seconds(job.user(), job.wait_time().num_seconds() as u64);
+        }
+
+        // also add in all of the components
+        job_report.add_component_usage("cpu", job.user(), Usage::new(job.cpu_seconds()));
+        job_report.add_component_usage("memory", job.user(), Usage::new(job.memory_seconds()));
+        job_report.add_component_usage("gpu", job.user(), Usage::new(job.gpu_seconds()));
+        job_report.add_component_usage("billing", job.user(), Usage::new(job.billing_seconds()));
+
+        if collect_energy {
+            job_report.add_energy(job.user(), job.energy_joules());
+        }
+
+        if !job.partition().is_empty() {
+            // break down federated usage by cluster as well as partition
+            let partition = match cluster.contains(',') {
+                true => format!("{}/{}", job.cluster(), job.partition()),
+                false => job.partition().to_string(),
+            };
+
+            daily_report.add_partition_report(&partition, &job_report);
+        }
+
+        daily_report += job_report;
+    }
+
+    // runtime consistency check
+    if daily_report.num_jobs() != num_jobs_started
+        || daily_report.total_wait_seconds() != total_wait_seconds
+    {
+        tracing::warn!(
+            "Job count/wait time inconsistency for project {} on {}: \
+             local counters ({} jobs, {}s wait) differ from report totals ({} jobs, {}s wait). \
+             This may indicate a bug.",
+            project.project(),
+            day,
+            num_jobs_started,
+            total_wait_seconds,
+            daily_report.num_jobs(),
+            daily_report.total_wait_seconds()
+        );
+    }
+
+    // check that the total usage in the daily report matches the total usage calculated manually
+    if daily_report.total_usage().seconds() != total_usage {
+        // it doesn't - we don't want to mark this as complete or cache it, because
+        // this points to some error when generating the values...
+        tracing::error!(
+            "Total usage in daily report does not match total usage calculated manually: {} != {}",
+            daily_report.total_usage().seconds(),
+            total_usage
+        );
+    } else if day.day().end_time().and_utc() < now {
+        // we can set this day as completed if it is in the past
+        daily_report.set_complete();
+
+        match cache::set_report(project.project(), account.name(), day, &daily_report).await {
+            Ok(_) => (),
+            Err(e) => {
+                tracing::error!("Could not cache report for {}: {}", day, e);
+            }
+        }
+    }
+    Ok(daily_report)
+}
+
+async fn get_daily_report(
+    expires: &chrono::DateTime<Utc>,
+    project: &ProjectMapping,
+    day: &templemeads::grammar::Date,
+    account: &SlurmAccount,
+    slurm_nodes: &SlurmNodes,
+    cluster: &str,
+    partition_command: &str,
+) -> Result<DailyProjectUsageReport, Error> {
+    // see if we have this report in the cache
+    if let Some(report) = cache::get_report(project.project(), account.name(), day).await? {
+        return Ok(report);
+    }
+
+    assert_not_expired(expires)?;
+
+    if cache::compute_via_hourly_reports(project.project(), day).await? {
+        return get_hourly_report(
+            expires,
+            project,
+            day,
+            account,
+            slurm_nodes,
+            cluster,
+            partition_command,
+        )
+        .await;
+    }
+
+    let now = chrono::Utc::now();
+    let start_time = day.day().start_time().and_utc();
+    let end_time = day.day().end_time().and_utc();
+
+    if start_time > now {
+        // we can't get the usage for this day yet as it is in the future
+        return Ok(DailyProjectUsageReport::default());
+    }
+
+    let end_time = match now < end_time {
+        true => now,
         false => end_time,
     };
 
@@ -1242,108 +2087,20 @@ async fn get_daily_report(
 
     // try to get the daily report from slurm - use a shorter 20 second
     // timeout as we will fall back to hourly reports if this fails
-    let cmd = runner(expires).await?.build_command(
-        "SACCT",
-        vec![
-            "--noconvert".to_string(),
-            "--allocations".to_string(),
-            "--allusers".to_string(),
-            format!("--starttime={}", start_time.format("%Y-%m-%dT%H:%M:%S")),
-            format!("--endtime={}", end_time.format("%Y-%m-%dT%H:%M:%S")),
-            format!("--account={}", account.name()),
-            format!("--cluster={}", cluster),
-            partition_command.to_string(),
-            "--json".to_string(),
-        ],
-    )?;
-
-    let response = runner(expires)
-        .await?
-        .run_json(&cmd, std::time::Duration::from_secs(20))
-        .await;
+    let response = get_batched_consumers(
+        expires,
+        account,
+        cluster,
+        partition_command,
+        std::time::Duration::from_secs(20),
+        &start_time,
+        &end_time,
+        slurm_nodes,
+    )
+    .await;
 
     match response {
-        Ok(response) => {
-            let jobs = SlurmJob::get_consumers(&response, &start_time, &end_time, slurm_nodes)?;
-
-            tracing::debug!(
-                "Got {} jobs for project {} on {}",
-                jobs.len(),
-                project.project(),
-                day
-            );
-
-            let mut daily_report = DailyProjectUsageReport::default();
-            let mut total_usage: u64 = 0;
-            let mut num_jobs_started: u64 = 0;
-            let mut total_wait_seconds: u64 = 0;
-
-            for job in jobs {
-                total_usage += job.billed_node_seconds();
-                daily_report.add_usage(job.user(), Usage::new(job.billed_node_seconds()));
-
-                // only count jobs and wait time for jobs that started in this day
-                if job.original_start_time() >= &start_time {
-                    num_jobs_started += 1;
-                    total_wait_seconds += job.wait_time().num_seconds() as u64;
-                    daily_report.add_jobs(job.user(), 1);
-                    daily_report.add_wait_seconds(job.user(), job.wait_time().num_seconds() as u64);
-                }
-
-                // also add in all of the components
-                daily_report.add_component_usage("cpu", job.user(), Usage::new(job.cpu_seconds()));
-                daily_report.add_component_usage(
-                    "memory",
-                    job.user(),
-                    Usage::new(job.memory_seconds()),
-                );
-                daily_report.add_component_usage("gpu", job.user(), Usage::new(job.gpu_seconds()));
-                daily_report.add_component_usage(
-                    "billing",
-                    job.user(),
-                    Usage::new(job.billing_seconds()),
-                );
-            }
-
-            // runtime consistency check
-            if daily_report.num_jobs() != num_jobs_started
-                || daily_report.total_wait_seconds() != total_wait_seconds
-            {
-                tracing::warn!(
-                    "Job count/wait time inconsistency for project {} on {}: \
-                     local counters ({} jobs, {}s wait) differ from report totals ({} jobs, {}s wait). \
-                     This may indicate a bug.",
-                    project.project(),
-                    day,
-                    num_jobs_started,
-                    total_wait_seconds,
-                    daily_report.num_jobs(),
-                    daily_report.total_wait_seconds()
-                );
-            }
-
-            // check that the total usage in the daily report matches the total usage calculated manually
-            if daily_report.total_usage().seconds() != total_usage {
-                // it doesn't - we don't want to mark this as complete or cache it, because
-                // this points to some error when generating the values...
-                tracing::error!(
-                    "Total usage in daily report does not match total usage calculated manually: {} != {}",
-                    daily_report.total_usage().seconds(),
-                    total_usage
-                );
-            } else if day.day().end_time().and_utc() < now {
-                // we can set this day as completed if it is in the past
-                daily_report.set_complete();
-
-                match cache::set_report(project.project(), day, &daily_report).await {
-                    Ok(_) => (),
-                    Err(e) => {
-                        tracing::error!("Could not cache report for {}: {}", day, e);
-                    }
-                }
-            }
-            Ok(daily_report)
-        }
+        Ok(jobs) => build_daily_report(project, day, account, cluster, jobs).await,
         Err(Error::Timeout(_)) => {
             tracing::warn!(
                 "Timed out getting usage for project {} on {}. Switching to hourly reporting.",
@@ -1378,6 +2135,117 @@ async fn get_daily_report(
     }
 }
 
+///
+/// Return the reports of the passed consecutive days, fetching the jobs
+/// of all of the days that are not cached with a single query. Days
+/// that cannot be fetched together fall back to being fetched one by one
+///
+#[allow(clippy::too_many_arguments)]
+async fn get_window_reports(
+    expires: &chrono::DateTime<Utc>,
+    project: &ProjectMapping,
+    days: &[templemeads::grammar::Date],
+    account: &SlurmAccount,
+    slurm_nodes: &SlurmNodes,
+    cluster: &str,
+    partition_command: &str,
+) -> Result<Vec<(templemeads::grammar::Date, DailyProjectUsageReport)>, Error> {
+    let mut reports = Vec::new();
+    let mut to_fetch = Vec::new();
+
+    for day in days {
+        if cache::get_report(project.project(), account.name(), day)
+            .await?
+            .is_some()
+            || cache::compute_via_hourly_reports(project.project(), day).await?
+        {
+            // these are handled day by day
+            reports.push((
+                day.clone(),
+                get_daily_report(
+                    expires,
+                    project,
+                    day,
+                    account,
+                    slurm_nodes,
+                    cluster,
+                    partition_command,
+                )
+                .await?,
+            ));
+        } else {
+            to_fetch.push(day.clone());
+        }
+    }
+
+    let (Some(first), Some(last)) = (to_fetch.first(), to_fetch.last()) else {
+        return Ok(reports);
+    };
+
+    assert_not_expired(expires)?;
+
+    let now = chrono::Utc::now();
+    let start_time = first.day().start_time().and_utc();
+    let end_time = last.day().end_time().and_utc().min(now);
+
+    // allow the same time per day as when fetching a single day
+    let timeout = std::time::Duration::from_secs(20 * to_fetch.len() as u64);
+
+    match get_batched_consumers(
+        expires,
+        account,
+        cluster,
+        partition_command,
+        timeout,
+        &start_time,
+        &end_time,
+        slurm_nodes,
+    )
+    .await
+    {
+        Ok(jobs) => {
+            for day in to_fetch {
+                let day_start = day.day().start_time().and_utc();
+                let day_end = day.day().end_time().and_utc().min(now);
+
+                let day_jobs = jobs
+                    .iter()
+                    .filter_map(|job| job.clamp_to(&day_start, &day_end))
+                    .collect();
+
+                let report = build_daily_report(project, &day, account, cluster, day_jobs).await?;
+                reports.push((day, report));
+            }
+        }
+        Err(e) => {
+            tracing::warn!(
+                "Could not get usage for project {} from {} to {}: {}. Fetching day by day.",
+                project.project(),
+                first,
+                last,
+                e
+            );
+
+            for day in to_fetch {
+                let report = get_daily_report(
+                    expires,
+                    project,
+                    &day,
+                    account,
+                    slurm_nodes,
+                    cluster,
+                    partition_command,
+                )
+                .await?;
+
+                reports.push((day, report));
+            }
+        }
+    }
+
+    Ok(reports)
+}
+
 pub async fn get_usage_report(
     project: &ProjectMapping,
     dates: &DateRange,
@@ -1402,7 +2270,8 @@ pub async fn get_usage_report(
     let mut report = ProjectUsageReport::new(project.project());
     let slurm_nodes = cache::get_nodes().await?;
     let now = chrono::Utc::now();
-    let cluster = cache::get_cluster().await?;
+    // sacct reports the jobs of every cluster in the federation
+    let cluster = cache::get_clusters().await?.join(",");
     let partition = cache::get_partition().await?;
 
     let partition_command = match partition {
@@ -1410,317 +2279,1618 @@ pub async fn get_usage_report(
         None => "".to_string(),
     };
 
-    // we now request the data day by day - do this in parallel
-    let mut tasks = Vec::new();
+    let days: Vec<templemeads::grammar::Date> = dates
+        .days()
+        .into_iter()
+        // we can't get the usage for days in the future
+        .filter(|day| day.day().start_time().and_utc() <= now)
+        .collect();
 
-    for day in dates.days() {
-        if day.day().start_time().and_utc() > now {
-            // we can't get the usage for this day yet as it is in the future
-            continue;
-        }
+    // we now request the data in windows of consecutive days - do this
+    // in parallel
+    let batch_days = cache::get_sacct_batch_days().await?.max(1) as usize;
+
+    let mut tasks = Vec::new();
 
+    for window in days.chunks(batch_days) {
         let expires = *expires;
         let project = project.clone();
         let account = account.clone();
         let slurm_nodes = slurm_nodes.clone();
         let cluster = cluster.clone();
         let partition_command = partition_command.clone();
-        let day = day.clone();
-        let day2 = day.clone();
+        let window = window.to_vec();
+        let window2 = window.clone();
 
         tasks.push((
             tokio::spawn(async move {
-                get_daily_report(
-                    &expires,
-                    &project,
-                    &day,
-                    &account,
-                    &slurm_nodes,
-                    &cluster,
-                    &partition_command,
-                )
-                .await
+                match window.as_slice() {
+                    [day] => Ok(vec![(
+                        day.clone(),
+                        get_daily_report(
+                            &expires,
+                            &project,
+                            day,
+                            &account,
+                            &slurm_nodes,
+                            &cluster,
+                            &partition_command,
+                        )
+                        .await?,
+                    )]),
+                    _ => {
+                        get_window_reports(
+                            &expires,
+                            &project,
+                            &window,
+                            &account,
+                            &slurm_nodes,
+                            &cluster,
+                            &partition_command,
+                        )
+                        .await
+                    }
+                }
             }),
-            day2,
+            window2,
         ));
     }
 
-    for (task, day) in tasks {
-        let daily_report = match task.await {
-            Ok(report) => match report {
-                Ok(report) => report,
+    for (task, window) in tasks {
+        let daily_reports = match task.await {
+            Ok(reports) => match reports {
+                Ok(reports) => reports,
                 Err(e) => {
-                    tracing::warn!("Could not get daily report: {}", e);
-                    // we will return an empty report for this day
-                    DailyProjectUsageReport::default()
+                    tracing::warn!("Could not get daily reports: {}", e);
+                    Vec::new()
                 }
             },
             Err(e) => {
-                tracing::warn!("Could not get daily report: {}", e);
-                // we will return an empty report for this day
-                DailyProjectUsageReport::default()
+                tracing::warn!("Could not get daily reports: {}", e);
+                Vec::new()
             }
         };
 
-        // now save this to the overall report
-        report.set_report(&day, &daily_report);
+        // we will return an empty report for any day that could not
+        // be fetched
+        for day in window {
+            let daily_report = daily_reports
+                .iter()
+                .find(|(d, _)| *d == day)
+                .map(|(_, report)| report.clone())
+                .unwrap_or_default();
+
+            // now save this to the overall report
+            report.set_report(&day, &daily_report);
+        }
     }
 
     Ok(report)
 }
 
-pub async fn get_limit(
-    project: &ProjectMapping,
+pub async fn get_limit(
+    project: &ProjectMapping,
+    partition: Option<&str>,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Usage, Error> {
+    assert_not_expired(expires)?;
+
+    if let Some(partition) = partition {
+        return get_partition_limit(project, partition, expires).await;
+    }
+
+    let account = SlurmAccount::from_mapping(project)?;
+
+    let account = match get_account(account.name(), expires).await? {
+        Some(account) => account,
+        None => {
+            tracing::warn!("Could not get account {}", account.name());
+            return Err(Error::NotFound(account.name().to_string()));
+        }
+    };
+
+    // check that the limits in slurm match up...
+    let cmd = priority_runner(expires).await?.build_command(
+        "SACCTMGR",
+        vec![
+            "--json".to_string(),
+            "show".to_string(),
+            "association".to_string(),
+            "where".to_string(),
+            format!("account={}", account.name()),
+            format!("cluster={}", cache::get_cluster().await?),
+        ],
+    )?;
+
+    let response = priority_runner(expires)
+        .await?
+        .run_json(&cmd, DEFAULT_TIMEOUT)
+        .await?;
+
+    let limits = match response.get("associations") {
+        Some(limits) => match limits.as_array() {
+            Some(limits) => {
+                let mut slurm_limits: Vec<SlurmLimit> = Vec::new();
+
+                for limit in limits {
+                    slurm_limits.push(SlurmLimit::construct(limit)?);
+                }
+
+                slurm_limits
+            }
+            None => {
+                tracing::warn!("Limits is not an array: {:?}", limits);
+                return Err(Error::Call("Limits is not an array".to_string()));
+            }
+        },
+        None => Vec::new(),
+    };
+
+    let cluster = cache::get_cluster().await?;
+
+    let project_limit = account.limit();
+
+    let slurm_limit = match limits.iter().find(|l| {
+        l.account() == account.name() && l.cluster() == cluster && l.partition().is_empty()
+    }) {
+        Some(slurm_limit) => slurm_limit,
+        None => {
+            tracing::warn!("Could not find limit for account {}", account.name());
+            return Err(Error::NotFound(account.name().to_string()));
+        }
+    };
+
+    tracing::debug!(
+        "Found limit for account {}: {}",
+        account.name(),
+        slurm_limit
+    );
+
+    let node = cache::get_default_node().await?;
+    let limit_tres = cache::get_limit_tres().await?;
+
+    let mut actual_slurm_limit: Option<Usage> = None;
+
+    if node.has_cpus() && node.cpus() > 0 && limit_tres.iter().any(|t| t == "cpu") {
+        if let Some(cpu_limit) = slurm_limit.cpu_limit() {
+            let check = node.cpus() * project_limit.seconds();
+            if check != cpu_limit.seconds() {
+                if check != 0 {
+                    tracing::warn!(
+                        "CPU limit for account {} does not match: {} != {}",
+                        account.name(),
+                        check,
+                        cpu_limit.seconds()
+                    );
+                }
+
+                actual_slurm_limit = Some(Usage::new(cpu_limit.seconds() / node.cpus()));
+            }
+        }
+    }
+
+    if node.has_gpus() && node.gpus() > 0 && limit_tres.iter().any(|t| t == "gpu") {
+        if let Some(gpu_limit) = slurm_limit.gpu_limit() {
+            let check = node.gpus() * project_limit.seconds();
+            if check != gpu_limit.seconds() {
+                if check != 0 {
+                    tracing::warn!(
+                        "GPU limit for account {} does not match: {} != {}",
+                        account.name(),
+                        check,
+                        gpu_limit.seconds()
+                    );
+                }
+
+                if actual_slurm_limit.is_none() {
+                    actual_slurm_limit = Some(Usage::new(gpu_limit.seconds() / node.gpus()));
+                }
+            }
+        }
+    }
+
+    if node.has_mem() && node.mem() > 0 && limit_tres.iter().any(|t| t == "mem") {
+        if let Some(mem_limit) = slurm_limit.mem_limit() {
+            let check = node.mem() * project_limit.seconds();
+            if check != mem_limit.seconds() {
+                if check != 0 {
+                    tracing::warn!(
+                        "Memory limit for account {} does not match: {} != {}",
+                        account.name(),
+                        check,
+                        mem_limit.seconds()
+                    );
+                }
+
+                if actual_slurm_limit.is_none() {
+                    actual_slurm_limit = Some(Usage::new(mem_limit.seconds() / node.mem()));
+                }
+            }
+        }
+    }
+
+    if node.has_billing() && node.billing() > 0 && limit_tres.iter().any(|t| t == "billing") {
+        if let Some(billing_limit) = slurm_limit.billing_limit() {
+            let check = node.billing() * project_limit.seconds();
+            if check != billing_limit.seconds() {
+                if check != 0 {
+                    tracing::warn!(
+                        "Billing limit for account {} does not match: {} != {}",
+                        account.name(),
+                        check,
+                        billing_limit.seconds()
+                    );
+                }
+
+                if actual_slurm_limit.is_none() {
+                    actual_slurm_limit = Some(Usage::new(billing_limit.seconds() / node.billing()));
+                }
+            }
+        }
+    }
+
+    if let Some(actual_slurm_limit) = actual_slurm_limit {
+        // we need to set this to the actual slurm limit
+        let mut account = account.clone();
+        account.set_limit(&actual_slurm_limit);
+
+        // now save the account to the cache
+        cache::add_account(&account).await?;
+
+        tracing::info!("Updated account limit to {}", actual_slurm_limit);
+        return Ok(actual_slurm_limit);
+    }
+
+    Ok(*account.limit())
+}
+
+///
+/// Return the limit of the passed project on a single partition. This
+/// is read from the account's association for that partition, and is
+/// converted back into node time using the default node
+///
+async fn get_partition_limit(
+    project: &ProjectMapping,
+    partition: &str,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Usage, Error> {
+    let account = SlurmAccount::from_mapping(project)?;
+    let cluster = cache::get_cluster().await?;
+
+    let cmd = priority_runner(expires).await?.build_command(
+        "SACCTMGR",
+        vec![
+            "--json".to_string(),
+            "show".to_string(),
+            "association".to_string(),
+            "where".to_string(),
+            format!("account={}", account.name()),
+            format!("cluster={}", cluster),
+            format!("partition={}", partition),
+        ],
+    )?;
+
+    let response = priority_runner(expires)
+        .await?
+        .run_json(&cmd, DEFAULT_TIMEOUT)
+        .await?;
+
+    let mut limits: Vec<SlurmLimit> = Vec::new();
+
+    if let Some(associations) = response.get("associations").and_then(|a| a.as_array()) {
+        for association in associations {
+            limits.push(SlurmLimit::construct(association)?);
+        }
+    }
+
+    let slurm_limit = match limits.iter().find(|l| {
+        l.account() == account.name() && l.cluster() == cluster && l.partition() == partition
+    }) {
+        Some(slurm_limit) => slurm_limit,
+        None => {
+            tracing::warn!(
+                "Could not find limit for account {} on partition {}",
+                account.name(),
+                partition
+            );
+            return Err(Error::NotFound(format!("{}:{}", account.name(), partition)));
+        }
+    };
+
+    let node = cache::get_default_node().await?;
+    let limit_tres = cache::get_limit_tres().await?;
+
+    // use the first TRES that is both limited and on the node
+    let candidates = [
+        ("cpu", node.has_cpus(), node.cpus(), slurm_limit.cpu_limit()),
+        ("gpu", node.has_gpus(), node.gpus(), slurm_limit.gpu_limit()),
+        ("mem", node.has_mem(), node.mem(), slurm_limit.mem_limit()),
+        (
+            "billing",
+            node.has_billing(),
+            node.billing(),
+            slurm_limit.billing_limit(),
+        ),
+    ];
+
+    for (name, has_tres, count, tres_limit) in candidates {
+        if has_tres && count > 0 && limit_tres.iter().any(|t| t == name) {
+            if let Some(tres_limit) = tres_limit {
+                return Ok(Usage::new(tres_limit.seconds() / count));
+            }
+        }
+    }
+
+    Ok(Usage::default())
+}
+
+pub async fn set_limit(
+    project: &ProjectMapping,
+    limit: &Usage,
+    partition: Option<&str>,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Usage, Error> {
+    assert_not_expired(expires)?;
+
+    let account = SlurmAccount::from_mapping(project)?;
+
+    match get_account(account.name(), expires).await? {
+        Some(account) => {
+            let mut account = account.clone();
+
+            account.set_limit(limit);
+
+            // each cluster in the federation enforces the limit
+            let clusters = cache::get_clusters().await?.join(",");
+
+            // calculate the GRES limits in terms of CPU, GPU and Memory
+            let node = cache::get_default_node().await?;
+            let limit_tres = cache::get_limit_tres().await?;
+
+            let candidates = [
+                ("cpu", "cpu", node.has_cpus(), node.cpus()),
+                ("gpu", "gres/gpu", node.has_gpus(), node.gpus()),
+                ("mem", "mem", node.has_mem(), node.mem()),
+                ("billing", "billing", node.has_billing(), node.billing()),
+            ];
+
+            let mut tres: Vec<String> = Vec::new();
+
+            for (name, slurm_name, has_tres, count) in candidates {
+                if !has_tres {
+                    continue;
+                }
+
+                // TRES that are not limited are cleared, so that e.g. a
+                // GPU-hour allocation is only enforced on gres/gpu
+                match limit_tres.iter().any(|t| t == name) {
+                    true => tres.push(format!(
+                        "{}={}",
+                        slurm_name,
+                        (count as f64 * limit.minutes()) as u64
+                    )),
+                    false => tres.push(format!("{}=-1", slurm_name)),
+                }
+            }
+
+            if !tres.is_empty() {
+                let mut args = vec![
+                    "--immediate".to_string(),
+                    "modify".to_string(),
+                    "account".to_string(),
+                    account.name().to_string(),
+                    "set".to_string(),
+                    format!("GrpTRESMins={}", tres.join(",")),
+                    "where".to_string(),
+                    format!("cluster={}", clusters),
+                ];
+
+                // a partition limit only updates the account's
+                // association for that partition
+                if let Some(partition) = partition {
+                    args.push(format!("partition={}", partition));
+                }
+
+                let cmd = priority_runner(expires)
+                    .await?
+                    .build_command("SACCTMGR", args)?;
+
+                priority_runner(expires)
+                    .await?
+                    .run(&cmd, DEFAULT_TIMEOUT)
+                    .await?;
+            }
+
+            // partition limits are not cached, as the cached limit
+            // is that of the whole project
+            if let Some(partition) = partition {
+                tracing::info!(
+                    "Set limit of account {} on partition {} to {}",
+                    account.name(),
+                    partition,
+                    limit
+                );
+                return Ok(*limit);
+            }
+
+            // keep the account's fairshare in proportion to its limit
+            if let Some(node_hours) = cache::get_fairshare_node_hours().await? {
+                set_fairshare(&account, limit, node_hours, expires).await?;
+            }
+
+            // now we've made the change, save the account to the cache
+            cache::add_account(&account).await?;
+
+            Ok(*account.limit())
+        }
+        None => {
+            tracing::warn!("Could not get account {}", account.name());
+            Err(Error::NotFound(account.name().to_string()))
+        }
+    }
+}
+
+///
+/// Set the fairshare of the passed account from its limit, giving one
+/// share for every `node_hours` of the limit. Every account gets at
+/// least one share, so that projects with small (or no) allocations
+/// can still run jobs
+///
+async fn set_fairshare(
+    account: &SlurmAccount,
+    limit: &Usage,
+    node_hours: f64,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    let shares = ((limit.hours() / node_hours).round() as u64).max(1);
+
+    let cmd = priority_runner(expires).await?.build_command(
+        "SACCTMGR",
+        vec![
+            "--immediate".to_string(),
+            "modify".to_string(),
+            "account".to_string(),
+            account.name().to_string(),
+            "set".to_string(),
+            format!("fairshare={}", shares),
+            "where".to_string(),
+            format!("cluster={}", cache::get_clusters().await?.join(",")),
+        ],
+    )?;
+
+    priority_runner(expires)
+        .await?
+        .run(&cmd, DEFAULT_TIMEOUT)
+        .await?;
+
+    tracing::info!(
+        "Set fairshare of account {} to {} for a limit of {}",
+        account.name(),
+        shares,
+        limit
+    );
+
+    Ok(())
+}
+
+///
+/// Create (or update) the QOS of the passed project from the QOS
+/// defined for its class, and make it the project's default QOS.
+/// This returns the name of the QOS
+///
+pub async fn set_qos(
+    project: &ProjectMapping,
+    class: &ProjectTemplate,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<String, Error> {
+    assert_not_expired(expires)?;
+
+    let qos = cache::get_qos_class(&class.to_string()).await?;
+
+    let account = SlurmAccount::from_mapping(project)?;
+
+    let account = match get_account(account.name(), expires).await? {
+        Some(account) => account,
+        None => {
+            tracing::warn!("Could not get account {}", account.name());
+            return Err(Error::NotFound(account.name().to_string()));
+        }
+    };
+
+    let name = qos_name(account.name());
+    let clusters = cache::get_clusters().await?.join(",");
+
+    // see if the QOS already exists
+    let cmd = priority_runner(expires).await?.build_command(
+        "SACCTMGR",
+        vec![
+            "--noheader".to_string(),
+            "--parsable2".to_string(),
+            "show".to_string(),
+            "qos".to_string(),
+            format!("name={}", name),
+            "format=name".to_string(),
+        ],
+    )?;
+
+    let existing = priority_runner(expires)
+        .await?
+        .run(&cmd, DEFAULT_TIMEOUT)
+        .await?;
+
+    if !existing.lines().any(|line| line.trim() == name) {
+        let cmd = priority_runner(expires).await?.build_command(
+            "SACCTMGR",
+            vec![
+                "--immediate".to_string(),
+                "add".to_string(),
+                "qos".to_string(),
+                name.clone(),
+            ],
+        )?;
+
+        priority_runner(expires)
+            .await?
+            .run(&cmd, DEFAULT_TIMEOUT)
+            .await?;
+
+        tracing::info!("Created QOS {} for account {}", name, account.name());
+    }
+
+    // apply the limits of the project's class
+    let mut args = vec![
+        "--immediate".to_string(),
+        "modify".to_string(),
+        "qos".to_string(),
+        "where".to_string(),
+        format!("name={}", name),
+        "set".to_string(),
+    ];
+
+    args.extend(qos.sacctmgr_settings());
+
+    let cmd = priority_runner(expires)
+        .await?
+        .build_command("SACCTMGR", args)?;
+
+    priority_runner(expires)
+        .await?
+        .run(&cmd, DEFAULT_TIMEOUT)
+        .await?;
+
+    // finally, allow the account to use the QOS, and make it the default
+    let cmd = priority_runner(expires).await?.build_command(
+        "SACCTMGR",
+        vec![
+            "--immediate".to_string(),
+            "modify".to_string(),
+            "account".to_string(),
+            account.name().to_string(),
+            "set".to_string(),
+            format!("QOS+={}", name),
+            format!("DefaultQOS={}", name),
+            "where".to_string(),
+            format!("cluster={}", clusters),
+        ],
+    )?;
+
+    priority_runner(expires)
+        .await?
+        .run(&cmd, DEFAULT_TIMEOUT)
+        .await?;
+
+    tracing::info!(
+        "Set QOS {} for account {} from project class {}",
+        name,
+        account.name(),
+        class
+    );
+
+    Ok(name)
+}
+
+///
+/// Return the name of the default QOS of the passed project, or an
+/// empty string if the project's account does not have one
+///
+pub async fn get_qos(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<String, Error> {
+    assert_not_expired(expires)?;
+
+    let account = SlurmAccount::from_mapping(project)?;
+
+    let cmd = priority_runner(expires).await?.build_command(
+        "SACCTMGR",
+        vec![
+            "--noheader".to_string(),
+            "--parsable2".to_string(),
+            "show".to_string(),
+            "association".to_string(),
+            "where".to_string(),
+            format!("account={}", account.name()),
+            format!("cluster={}", cache::get_cluster().await?),
+            "format=user,defaultqos".to_string(),
+        ],
+    )?;
+
+    let output = priority_runner(expires)
+        .await?
+        .run(&cmd, DEFAULT_TIMEOUT)
+        .await?;
+
+    // the account's own association is the one without a user
+    for line in output.lines() {
+        if let Some((user, qos)) = line.split_once('|') {
+            if user.trim().is_empty() {
+                return Ok(qos.trim().to_string());
+            }
+        }
+    }
+
+    Err(Error::NotFound(account.name().to_string()))
+}
+
+///
+/// Return the name of the reservation of the passed account that
+/// covers the passed dates
+///
+fn reservation_name(account: &str, dates: &DateRange) -> String {
+    format!("{}_{}_{}", account, dates.start_date(), dates.end_date())
+}
+
+///
+/// Return whether or not the named reservation exists
+///
+async fn reservation_exists(name: &str, expires: &chrono::DateTime<Utc>) -> Result<bool, Error> {
+    let cmd = priority_runner(expires).await?.build_command(
+        "SCONTROL",
+        vec![
+            "--oneliner".to_string(),
+            "show".to_string(),
+            "reservation".to_string(),
+        ],
+    )?;
+
+    let output = priority_runner(expires)
+        .await?
+        .run(&cmd, DEFAULT_TIMEOUT)
+        .await?;
+
+    let key = format!("ReservationName={}", name);
+
+    Ok(output
+        .lines()
+        .any(|line| line.split_whitespace().any(|field| field == key)))
+}
+
+///
+/// Create (or resize) a reservation of `nodes` nodes for the passed
+/// project, covering the passed dates. The reservation is limited to
+/// the project's account, so that every member of the project's group
+/// can run jobs in it. The dates must lie within the project's
+/// allocation `window`, and must not be entirely in the past.
+/// This returns the name of the reservation
+///
+pub async fn create_reservation(
+    project: &ProjectMapping,
+    dates: &DateRange,
+    nodes: u32,
+    window: &DateRange,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<String, Error> {
+    assert_not_expired(expires)?;
+
+    if dates.start_date() < window.start_date() || dates.end_date() > window.end_date() {
+        return Err(Error::InvalidState(format!(
+            "Cannot reserve {} for project {} as this is outside its allocation window {}",
+            dates,
+            project.project(),
+            window
+        )));
+    }
+
+    let now = Utc::now().naive_utc();
+
+    if dates.end_time() <= now {
+        return Err(Error::InvalidState(format!(
+            "Cannot reserve {} for project {} as these dates are in the past",
+            dates,
+            project.project()
+        )));
+    }
+
+    let account = SlurmAccount::from_mapping(project)?;
+
+    let account = match get_account(account.name(), expires).await? {
+        Some(account) => account,
+        None => {
+            tracing::warn!("Could not get account {}", account.name());
+            return Err(Error::NotFound(account.name().to_string()));
+        }
+    };
+
+    let name = reservation_name(account.name(), dates);
+
+    let args = match reservation_exists(&name, expires).await? {
+        true => {
+            // the reservation's dates are part of its name, so only
+            // the number of nodes can change
+            vec![
+                "update".to_string(),
+                format!("ReservationName={}", name),
+                format!("NodeCnt={}", nodes),
+            ]
+        }
+        false => {
+            // a reservation that has already started begins now
+            let start_time = match dates.start_time() > now {
+                true => dates.start_time().format("%Y-%m-%dT%H:%M:%S").to_string(),
+                false => "now".to_string(),
+            };
+
+            let mut args = vec![
+                "create".to_string(),
+                "reservation".to_string(),
+                format!("ReservationName={}", name),
+                format!("StartTime={}", start_time),
+                format!("EndTime={}", dates.end_time().format("%Y-%m-%dT%H:%M:%S")),
+                format!("NodeCnt={}", nodes),
+                format!("Accounts={}", account.name()),
+            ];
+
+            if let Some(partition) = cache::get_partition().await? {
+                args.push(format!("PartitionName={}", partition));
+            }
+
+            args
+        }
+    };
+
+    let cmd = priority_runner(expires)
+        .await?
+        .build_command("SCONTROL", args)?;
+
+    priority_runner(expires)
+        .await?
+        .run(&cmd, DEFAULT_TIMEOUT)
+        .await?;
+
+    tracing::info!(
+        "Reserved {} nodes for account {} from {} in reservation {}",
+        nodes,
+        account.name(),
+        dates,
+        name
+    );
+
+    Ok(name)
+}
+
+///
+/// Remove the reservation of the passed project that covers the passed
+/// dates. This does nothing if the reservation does not exist. This
+/// returns the name of the reservation
+///
+pub async fn remove_reservation(
+    project: &ProjectMapping,
+    dates: &DateRange,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<String, Error> {
+    assert_not_expired(expires)?;
+
+    let account = SlurmAccount::from_mapping(project)?;
+    let name = reservation_name(account.name(), dates);
+
+    if !reservation_exists(&name, expires).await? {
+        tracing::warn!(
+            "Reservation {} does not exist, so does not need removing",
+            name
+        );
+        return Ok(name);
+    }
+
+    let cmd = priority_runner(expires).await?.build_command(
+        "SCONTROL",
+        vec!["delete".to_string(), format!("ReservationName={}", name)],
+    )?;
+
+    priority_runner(expires)
+        .await?
+        .run(&cmd, DEFAULT_TIMEOUT)
+        .await?;
+
+    tracing::info!("Removed reservation {}", name);
+
+    Ok(name)
+}
+
+///
+/// Return the number in the passed field of a squeue job. Newer versions
+/// of slurm wrap numbers as `{"set": true, "number": N}`, while older
+/// versions return the number directly
+///
+fn squeue_number(job: &serde_json::Value, key: &str) -> Option<i64> {
+    match job.get(key) {
+        Some(value) => match value.as_i64() {
+            Some(number) => Some(number),
+            None => match value.get("set").and_then(|set| set.as_bool()) {
+                Some(false) => None,
+                _ => value.get("number").and_then(|number| number.as_i64()),
+            },
+        },
+        None => None,
+    }
+}
+
+///
+/// Return the string in the passed field of a squeue job. Newer versions
+/// of slurm return some fields (e.g. the job state) as an array of strings
+///
+fn squeue_string(job: &serde_json::Value, key: &str) -> String {
+    match job.get(key) {
+        Some(value) => match value.as_str() {
+            Some(value) => value.to_string(),
+            None => value
+                .as_array()
+                .map(|values| {
+                    values
+                        .iter()
+                        .filter_map(|v| v.as_str())
+                        .collect::<Vec<&str>>()
+                        .join(",")
+                })
+                .unwrap_or_default(),
+        },
+        None => String::new(),
+    }
+}
+
+///
+/// Return the time in the passed field of a squeue job, if it is set
+///
+fn squeue_time(job: &serde_json::Value, key: &str) -> Option<chrono::DateTime<Utc>> {
+    match squeue_number(job, key) {
+        // slurm uses 0 for times that are not known
+        Some(timestamp) if timestamp > 0 => Utc.timestamp_opt(timestamp, 0).single(),
+        _ => None,
+    }
+}
+
+///
+/// Return the number of GPUs in a TRES string, e.g.
+/// "cpu=4,mem=16G,node=1,billing=4,gres/gpu:a100=2"
+///
+fn count_gpus(tres: &str) -> u64 {
+    tres.split(',')
+        .filter_map(|t| t.split_once('='))
+        .filter(|(name, _)| *name == "gres/gpu" || name.starts_with("gres/gpu:"))
+        .filter_map(|(_, count)| count.parse::<u64>().ok())
+        .sum()
+}
+
+///
+/// Convert a job returned by `squeue --json` into a QueuedJob
+///
+fn construct_queued_job(job: &serde_json::Value) -> Result<QueuedJob, Error> {
+    let id = match squeue_number(job, "job_id") {
+        Some(id) => id,
+        None => {
+            tracing::warn!("Could not get job_id from queued job: {:?}", job);
+            return Err(Error::Call(
+                "Could not get job_id from queued job".to_string(),
+            ));
+        }
+    };
+
+    // array jobs are shown as <array_job_id>_<array_task_id>
+    let job_id = match (
+        squeue_number(job, "array_job_id"),
+        squeue_number(job, "array_task_id"),
+    ) {
+        (Some(array_job_id), Some(array_task_id)) if array_job_id > 0 => {
+            format!("{}_{}", array_job_id, array_task_id)
+        }
+        _ => id.to_string(),
+    };
+
+    let submit_time = match squeue_time(job, "submit_time") {
+        Some(submit_time) => submit_time,
+        None => {
+            tracing::warn!("Could not get submit_time from queued job: {:?}", job);
+            return Err(Error::Call(
+                "Could not get submit_time from queued job".to_string(),
+            ));
+        }
+    };
+
+    let start_time = squeue_time(job, "start_time");
+    let state = squeue_string(job, "job_state");
+
+    // pending jobs have waited until now, while running jobs waited
+    // until they started
+    let waited_until = match (state.as_str(), start_time) {
+        ("PENDING", _) | (_, None) => Utc::now(),
+        (_, Some(start_time)) => start_time,
+    };
+
+    let tres = match squeue_string(job, "tres_alloc_str") {
+        tres if tres.is_empty() => squeue_string(job, "tres_req_str"),
+        tres => tres,
+    };
+
+    Ok(QueuedJob {
+        job_id,
+        user: squeue_string(job, "user_name"),
+        partition: squeue_string(job, "partition"),
+        state,
+        reason: squeue_string(job, "state_reason"),
+        nodes: squeue_number(job, "node_count").unwrap_or_default().max(0) as u64,
+        cpus: squeue_number(job, "cpus").unwrap_or_default().max(0) as u64,
+        gpus: count_gpus(&tres),
+        submit_time,
+        start_time,
+        wait_seconds: (waited_until - submit_time).num_seconds().max(0) as u64,
+    })
+}
+
+///
+/// Return the jobs that the passed project has queued or running
+///
+pub async fn get_job_queue(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<JobQueue, Error> {
+    assert_not_expired(expires)?;
+
+    let account = SlurmAccount::from_mapping(project)?;
+
+    let cmd = priority_runner(expires).await?.build_command(
+        "SQUEUE",
+        vec![
+            "--json".to_string(),
+            format!("--account={}", account.name()),
+        ],
+    )?;
+
+    let response = priority_runner(expires)
+        .await?
+        .run_json(&cmd, DEFAULT_TIMEOUT)
+        .await?;
+
+    let mut jobs = Vec::new();
+
+    match response.get("jobs").and_then(|jobs| jobs.as_array()) {
+        Some(queued) => {
+            for job in queued {
+                match construct_queued_job(job) {
+                    Ok(job) => jobs.push(job),
+                    Err(e) => {
+                        // skip jobs we can't read, rather than hiding the
+                        // rest of the queue
+                        tracing::warn!("Skipping queued job: {}", e);
+                    }
+                }
+            }
+        }
+        None => {
+            tracing::warn!("Could not get jobs from squeue: {:?}", response);
+            return Err(Error::Call("Could not get jobs from squeue".to_string()));
+        }
+    }
+
+    Ok(JobQueue::new(project.project(), jobs))
+}
+
+///
+/// Return the names of the users associated with the passed account
+/// on the primary cluster
+///
+async fn get_account_users(
+    account: &SlurmAccount,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<Vec<String>, Error> {
+    let cmd = priority_runner(expires).await?.build_command(
+        "SACCTMGR",
+        vec![
+            "--noheader".to_string(),
+            "--parsable2".to_string(),
+            "show".to_string(),
+            "associations".to_string(),
+            "where".to_string(),
+            format!("account={}", account.name()),
+            format!("cluster={}", cache::get_cluster().await?),
+            "format=user".to_string(),
+        ],
+    )?;
+
+    let output = priority_runner(expires)
+        .await?
+        .run(&cmd, DEFAULT_TIMEOUT)
+        .await?;
+
+    // the account's own association has an empty user
+    let mut users: Vec<String> = output
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|user| !user.is_empty())
+        .collect();
+
+    users.sort();
+    users.dedup();
+
+    Ok(users)
+}
+
+///
+/// Compare the account and associations of the passed project against
+/// the passed users that should be in the project. If `repair` is true
+/// then the missing account and associations are added. Users who are
+/// associated but not mapped are only reported, as removing them could
+/// cancel their jobs
+///
+pub async fn reconcile(
+    project: &ProjectMapping,
+    users: &[UserMapping],
+    repair: bool,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<ReconciliationReport, Error> {
+    assert_not_expired(expires)?;
+
+    let mut report = ReconciliationReport::new(project.project());
+
+    let account = SlurmAccount::from_mapping(project)?;
+
+    // the cached account may be stale, so always look in slurm
+    let existing = match get_account_from_slurm(account.name(), expires).await? {
+        Some(existing) => Some(existing),
+        None => {
+            report.missing_account = true;
+
+            match repair {
+                true => Some(get_account_create_if_not_exists(&account, expires).await?),
+                false => None,
+            }
+        }
+    };
+
+    let associated = match &existing {
+        Some(existing) => get_account_users(existing, expires).await?,
+        None => Vec::new(),
+    };
+
+    let mut expected = Vec::new();
+    let mut missing = Vec::new();
+
+    for user in users {
+        let local_user = clean_user_name(user.local_user())?;
+
+        if !associated.contains(&local_user) {
+            report.missing_users.push(local_user.clone());
+            missing.push(user);
+        }
+
+        expected.push(local_user);
+    }
+
+    for local_user in &associated {
+        if !expected.contains(local_user) {
+            report.unexpected_users.push(local_user.clone());
+        }
+    }
+
+    report.missing_users.sort();
+    report.unexpected_users.sort();
+
+    if repair && report.has_drift() {
+        for user in missing {
+            get_user_create_if_not_exists(user, expires).await?;
+            tracing::info!("Repaired association of {} with {}", user, account.name());
+        }
+
+        // unexpected users are not repaired, so the report only
+        // counts as repaired if everything else was
+        report.repaired = true;
+    }
+
+    if report.has_drift() {
+        tracing::warn!("Reconciled {}: {}", project, report);
+    }
+
+    Ok(report)
+}
+
+///
+/// Submit the batch script at the passed path as the passed local user,
+/// charging the job to the account of their project. The script must be
+/// inside one of the directories in the `job-script-dirs` option. This
+/// returns the scheduler's id for the job
+///
+pub async fn submit_job(
+    mapping: &UserMapping,
+    script: &str,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<String, Error> {
+    assert_not_expired(expires)?;
+
+    let script_dirs = cache::get_job_script_dirs().await?;
+
+    if script_dirs.is_empty() {
+        return Err(Error::Misconfigured(
+            "Job submission is disabled. Set the job-script-dirs option to the directories \
+             that job scripts can be submitted from."
+                .to_string(),
+        ));
+    }
+
+    let script_path = std::path::Path::new(script);
+
+    if !script_dirs
+        .iter()
+        .any(|dir| script_path.starts_with(std::path::Path::new(dir)))
+    {
+        return Err(Error::InvalidState(format!(
+            "Cannot submit '{}' as it is not in one of the job script directories: {}",
+            script,
+            script_dirs.join(", ")
+        )));
+    }
+
+    let association = SlurmAssociation::from_mapping(mapping)?;
+
+    // make sure that the user exists and can use the account
+    get_user_create_if_not_exists(mapping, expires).await?;
+
+    // run the job from the directory containing the script, so that
+    // relative paths in the script (and the default output files)
+    // are where the user expects
+    let workdir = match script_path.parent() {
+        Some(workdir) => workdir.to_string_lossy().to_string(),
+        None => "/".to_string(),
+    };
+
+    let mut args = vec![
+        "--parsable".to_string(),
+        format!("--uid={}", association.user()),
+        format!("--gid={}", mapping.local_group()),
+        format!("--account={}", association.account()),
+        format!("--chdir={}", workdir),
+    ];
+
+    if let Some(partition) = cache::get_partition().await? {
+        args.push(format!("--partition={}", partition));
+    }
+
+    args.push(script.to_string());
+
+    let cmd = priority_runner(expires)
+        .await?
+        .build_command("SBATCH", args)?;
+
+    let output = priority_runner(expires)
+        .await?
+        .run(&cmd, DEFAULT_TIMEOUT)
+        .await?;
+
+    // --parsable prints "<job_id>" or "<job_id>;<cluster>"
+    let job_id = output
+        .trim()
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+
+    if job_id.is_empty() || !job_id.chars().all(|c| c.is_ascii_digit()) {
+        return Err(Error::Call(format!(
+            "Could not read the job id from the output of sbatch: '{}'",
+            output.trim()
+        )));
+    }
+
+    tracing::info!(
+        "Submitted {} as job {} for user {} in account {}",
+        script,
+        job_id,
+        association.user(),
+        association.account()
+    );
+
+    Ok(job_id)
+}
+
+///
+/// Disable the user's association with their project's account, so that
+/// they can no longer run or submit jobs. The association is kept (with
+/// job limits of zero) rather than removed, so that the user's accounting
+/// history is preserved. The user is re-enabled by `enable_user`
+///
+pub async fn disable_user(
+    mapping: &UserMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let association = SlurmAssociation::from_mapping(mapping)?;
+
+    if get_user(association.user(), expires).await?.is_none() {
+        tracing::warn!(
+            "Cannot disable user {} as they do not exist in slurm",
+            mapping
+        );
+        return Ok(());
+    }
+
+    set_association_job_limit(&association, "0", expires).await?;
+
+    tracing::info!(
+        "Disabled user {} in account {}",
+        association.user(),
+        association.account()
+    );
+
+    Ok(())
+}
+
+///
+/// Re-enable the user's association with their project's account, if it
+/// had been disabled by `disable_user`. Associations that are not
+/// disabled are left untouched, so that any job limits set by the
+/// site's administrators are kept
+///
+pub async fn enable_user(
+    mapping: &UserMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let association = SlurmAssociation::from_mapping(mapping)?;
+
+    if !is_association_disabled(&association, expires).await? {
+        return Ok(());
+    }
+
+    // -1 clears the limits
+    set_association_job_limit(&association, "-1", expires).await?;
+
+    tracing::info!(
+        "Re-enabled user {} in account {}",
+        association.user(),
+        association.account()
+    );
+
+    Ok(())
+}
+
+///
+/// Disable a batch of users, who are all in the same project, with a
+/// single call to sacctmgr, and then cancel each user's pending jobs. If
+/// the call fails, the users are disabled one at a time, so that only
+/// the users at fault fail
+///
+pub async fn disable_users(
+    mappings: Vec<UserMapping>,
+    expires: &chrono::DateTime<Utc>,
+) -> batch::BatchResult {
+    let result = match disable_users_together(&mappings, expires).await {
+        Ok(()) => {
+            let mut result = batch::BatchResult::new();
+
+            for mapping in mappings {
+                result.add_success(mapping);
+            }
+
+            result
+        }
+        Err(e) => {
+            tracing::warn!(
+                "Could not disable the batch of {} users together: {}. Disabling them one at a time.",
+                mappings.len(),
+                e
+            );
+
+            batch::run_each(mappings, 1, |mapping| async move {
+                disable_user(&mapping, expires).await?;
+                Ok(mapping)
+            })
+            .await
+        }
+    };
+
+    for mapping in &result.succeeded {
+        // this never fails - problems are only logged
+        let _ = cancel_pending_user_jobs(mapping.local_user(), expires).await;
+    }
+
+    result
+}
+
+async fn disable_users_together(
+    mappings: &[UserMapping],
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    assert_not_expired(expires)?;
+
+    let associations = mappings
+        .iter()
+        .map(SlurmAssociation::from_mapping)
+        .collect::<Result<Vec<SlurmAssociation>, Error>>()?;
+
+    let account = match associations.first() {
+        Some(association) => association.account().to_string(),
+        None => return Ok(()),
+    };
+
+    let users: Vec<String> = associations
+        .iter()
+        .map(|association| association.user().to_string())
+        .collect();
+
+    // users who do not exist in slurm are already disabled
+    let existing: Vec<String> = get_users_from_slurm(&users, expires)
+        .await?
+        .iter()
+        .map(|user| user.name().to_string())
+        .collect();
+
+    for user in users.iter().filter(|user| !existing.contains(user)) {
+        tracing::warn!("Cannot disable user {} as they do not exist in slurm", user);
+    }
+
+    if !existing.is_empty() {
+        set_users_job_limit(&existing, &account, "0", expires).await?;
+
+        tracing::info!(
+            "Disabled users {} in account {}",
+            existing.join(", "),
+            account
+        );
+    }
+
+    Ok(())
+}
+
+///
+/// Return those of the passed users whose association with `account`
+/// has been disabled by `disable_user` or `disable_users`
+///
+async fn get_disabled_users(
+    users: &[String],
+    account: &str,
     expires: &chrono::DateTime<Utc>,
-) -> Result<Usage, Error> {
-    assert_not_expired(expires)?;
-
-    let account = SlurmAccount::from_mapping(project)?;
-
-    let account = match get_account(account.name(), expires).await? {
-        Some(account) => account,
-        None => {
-            tracing::warn!("Could not get account {}", account.name());
-            return Err(Error::NotFound(account.name().to_string()));
-        }
-    };
+) -> Result<Vec<String>, Error> {
+    let cluster = cache::get_cluster().await?;
 
-    // check that the limits in slurm match up...
     let cmd = priority_runner(expires).await?.build_command(
         "SACCTMGR",
         vec![
-            "--json".to_string(),
+            "--noheader".to_string(),
+            "--parsable2".to_string(),
             "show".to_string(),
-            "association".to_string(),
+            "associations".to_string(),
             "where".to_string(),
-            format!("account={}", account.name()),
-            format!("cluster={}", cache::get_cluster().await?),
+            format!("user={}", users.join(",")),
+            format!("account={}", account),
+            format!("cluster={}", cluster),
+            "format=User,MaxJobs,MaxSubmitJobs".to_string(),
         ],
     )?;
 
-    let response = priority_runner(expires)
+    let output = priority_runner(expires)
         .await?
-        .run_json(&cmd, DEFAULT_TIMEOUT)
+        .run(&cmd, DEFAULT_TIMEOUT)
         .await?;
 
-    let limits = match response.get("associations") {
-        Some(limits) => match limits.as_array() {
-            Some(limits) => {
-                let mut slurm_limits: Vec<SlurmLimit> = Vec::new();
-
-                for limit in limits {
-                    slurm_limits.push(SlurmLimit::construct(limit)?);
-                }
-
-                slurm_limits
-            }
-            None => {
-                tracing::warn!("Limits is not an array: {:?}", limits);
-                return Err(Error::Call("Limits is not an array".to_string()));
-            }
-        },
-        None => Vec::new(),
-    };
+    // a disabled association has both limits set to zero
+    Ok(output
+        .lines()
+        .filter_map(|line| line.trim().strip_suffix("|0|0"))
+        .filter(|user| users.iter().any(|u| u == user))
+        .map(|user| user.to_string())
+        .collect())
+}
 
+async fn is_association_disabled(
+    association: &SlurmAssociation,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<bool, Error> {
     let cluster = cache::get_cluster().await?;
 
-    let project_limit = account.limit();
-
-    let slurm_limit = match limits
-        .iter()
-        .find(|l| l.account() == account.name() && l.cluster() == cluster)
-    {
-        Some(slurm_limit) => slurm_limit,
-        None => {
-            tracing::warn!("Could not find limit for account {}", account.name());
-            return Err(Error::NotFound(account.name().to_string()));
-        }
-    };
-
-    tracing::debug!(
-        "Found limit for account {}: {}",
-        account.name(),
-        slurm_limit
-    );
+    let cmd = priority_runner(expires).await?.build_command(
+        "SACCTMGR",
+        vec![
+            "--noheader".to_string(),
+            "--parsable2".to_string(),
+            "show".to_string(),
+            "associations".to_string(),
+            "where".to_string(),
+            format!("user={}", association.user()),
+            format!("account={}", association.account()),
+            format!("cluster={}", cluster),
+            "format=MaxJobs,MaxSubmitJobs".to_string(),
+        ],
+    )?;
 
-    let node = cache::get_default_node().await?;
+    let output = priority_runner(expires)
+        .await?
+        .run(&cmd, DEFAULT_TIMEOUT)
+        .await?;
 
-    let mut actual_slurm_limit: Option<Usage> = None;
+    // a disabled association has both limits set to zero
+    Ok(output.lines().any(|line| line.trim() == "0|0"))
+}
 
-    if node.has_cpus() && node.cpus() > 0 {
-        if let Some(cpu_limit) = slurm_limit.cpu_limit() {
-            let check = node.cpus() * project_limit.seconds();
-            if check != cpu_limit.seconds() {
-                if check != 0 {
-                    tracing::warn!(
-                        "CPU limit for account {} does not match: {} != {}",
-                        account.name(),
-                        check,
-                        cpu_limit.seconds()
-                    );
-                }
+async fn set_association_job_limit(
+    association: &SlurmAssociation,
+    limit: &str,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    set_users_job_limit(
+        &[association.user().to_string()],
+        association.account(),
+        limit,
+        expires,
+    )
+    .await
+}
 
-                actual_slurm_limit = Some(Usage::new(cpu_limit.seconds() / node.cpus()));
-            }
-        }
-    }
+///
+/// Set the job limits of the associations of all of the passed users
+/// with `account`, using a single call to sacctmgr
+///
+async fn set_users_job_limit(
+    users: &[String],
+    account: &str,
+    limit: &str,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    // the association exists in every cluster in the federation
+    let clusters = cache::get_clusters().await?.join(",");
 
-    if node.has_gpus() && node.gpus() > 0 {
-        if let Some(gpu_limit) = slurm_limit.gpu_limit() {
-            let check = node.gpus() * project_limit.seconds();
-            if check != gpu_limit.seconds() {
-                if check != 0 {
-                    tracing::warn!(
-                        "GPU limit for account {} does not match: {} != {}",
-                        account.name(),
-                        check,
-                        gpu_limit.seconds()
-                    );
-                }
+    let cmd = priority_runner(expires).await?.build_command(
+        "SACCTMGR",
+        vec![
+            "--immediate".to_string(),
+            "modify".to_string(),
+            "user".to_string(),
+            "where".to_string(),
+            format!("name={}", users.join(",")),
+            format!("account={}", account),
+            format!("cluster={}", clusters),
+            "set".to_string(),
+            format!("MaxJobs={}", limit),
+            format!("MaxSubmitJobs={}", limit),
+        ],
+    )?;
 
-                if actual_slurm_limit.is_none() {
-                    actual_slurm_limit = Some(Usage::new(gpu_limit.seconds() / node.gpus()));
-                }
-            }
-        }
-    }
+    priority_runner(expires)
+        .await?
+        .run(&cmd, DEFAULT_TIMEOUT)
+        .await?;
 
-    if node.has_mem() && node.mem() > 0 {
-        if let Some(mem_limit) = slurm_limit.mem_limit() {
-            let check = node.mem() * project_limit.seconds();
-            if check != mem_limit.seconds() {
-                if check != 0 {
-                    tracing::warn!(
-                        "Memory limit for account {} does not match: {} != {}",
-                        account.name(),
-                        check,
-                        mem_limit.seconds()
-                    );
-                }
+    Ok(())
+}
 
-                if actual_slurm_limit.is_none() {
-                    actual_slurm_limit = Some(Usage::new(mem_limit.seconds() / node.mem()));
-                }
-            }
-        }
-    }
+///
+/// Block the project's account, so that none of its users can run or
+/// submit jobs. This is used when the project has consumed all of its
+/// allocation. Jobs that are already running are left to finish. The
+/// account is unblocked by `unblock_account`
+///
+pub async fn block_account(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    assert_not_expired(expires)?;
 
-    if node.has_billing() && node.billing() > 0 {
-        if let Some(billing_limit) = slurm_limit.billing_limit() {
-            let check = node.billing() * project_limit.seconds();
-            if check != billing_limit.seconds() {
-                if check != 0 {
-                    tracing::warn!(
-                        "Billing limit for account {} does not match: {} != {}",
-                        account.name(),
-                        check,
-                        billing_limit.seconds()
-                    );
-                }
+    let account = SlurmAccount::from_mapping(project)?;
 
-                if actual_slurm_limit.is_none() {
-                    actual_slurm_limit = Some(Usage::new(billing_limit.seconds() / node.billing()));
-                }
-            }
-        }
+    if get_account(account.name(), expires).await?.is_none() {
+        tracing::warn!(
+            "Cannot block account {} as it does not exist in slurm",
+            account.name()
+        );
+        return Ok(());
     }
 
-    if let Some(actual_slurm_limit) = actual_slurm_limit {
-        // we need to set this to the actual slurm limit
-        let mut account = account.clone();
-        account.set_limit(&actual_slurm_limit);
-
-        // now save the account to the cache
-        cache::add_account(&account).await?;
+    set_account_job_limit(&account, "0", expires).await?;
 
-        tracing::info!("Updated account limit to {}", actual_slurm_limit);
-        return Ok(actual_slurm_limit);
-    }
+    tracing::info!("Blocked account {}", account.name());
 
-    Ok(*account.limit())
+    Ok(())
 }
 
-pub async fn set_limit(
+///
+/// Unblock the project's account, if it had been blocked by
+/// `block_account`. This returns whether or not the account was
+/// blocked
+///
+pub async fn unblock_account(
     project: &ProjectMapping,
-    limit: &Usage,
     expires: &chrono::DateTime<Utc>,
-) -> Result<Usage, Error> {
+) -> Result<bool, Error> {
     assert_not_expired(expires)?;
 
     let account = SlurmAccount::from_mapping(project)?;
 
-    match get_account(account.name(), expires).await? {
-        Some(account) => {
-            let mut account = account.clone();
+    if !is_account_blocked(project, expires).await? {
+        return Ok(false);
+    }
 
-            account.set_limit(limit);
+    // -1 clears the limits
+    set_account_job_limit(&account, "-1", expires).await?;
 
-            let cluster = cache::get_cluster().await?;
+    tracing::info!("Unblocked account {}", account.name());
 
-            // calculate the GRES limits in terms of CPU, GPU and Memory
-            let node = cache::get_default_node().await?;
+    Ok(true)
+}
 
-            let mut tres: Vec<String> = Vec::new();
+///
+/// Return whether the project's account has been blocked by
+/// `block_account`
+///
+pub async fn is_account_blocked(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<bool, Error> {
+    assert_not_expired(expires)?;
 
-            if node.has_cpus() {
-                tres.push(format!(
-                    "cpu={}",
-                    (node.cpus() as f64 * limit.minutes()) as u64
-                ));
-            }
+    let account = SlurmAccount::from_mapping(project)?;
+    let cluster = cache::get_cluster().await?;
 
-            if node.has_gpus() {
-                tres.push(format!(
-                    "gres/gpu={}",
-                    (node.gpus() as f64 * limit.minutes()) as u64
-                ));
-            }
+    let cmd = priority_runner(expires).await?.build_command(
+        "SACCTMGR",
+        vec![
+            "--noheader".to_string(),
+            "--parsable2".to_string(),
+            "show".to_string(),
+            "associations".to_string(),
+            "where".to_string(),
+            format!("account={}", account.name()),
+            format!("cluster={}", cluster),
+            "format=User,GrpJobs,GrpSubmitJobs".to_string(),
+        ],
+    )?;
 
-            if node.has_mem() {
-                tres.push(format!(
-                    "mem={}",
-                    (node.mem() as f64 * limit.minutes()) as u64
-                ));
-            }
+    let output = priority_runner(expires)
+        .await?
+        .run(&cmd, DEFAULT_TIMEOUT)
+        .await?;
 
-            if node.has_billing() {
-                tres.push(format!(
-                    "billing={}",
-                    (node.billing() as f64 * limit.minutes()) as u64
-                ));
-            }
+    // the account's own association has no user, and a blocked
+    // account has both limits set to zero
+    Ok(output.lines().any(|line| line.trim() == "|0|0"))
+}
 
-            if !tres.is_empty() {
-                let cmd = priority_runner(expires).await?.build_command(
-                    "SACCTMGR",
-                    vec![
-                        "--immediate".to_string(),
-                        "modify".to_string(),
-                        "account".to_string(),
-                        account.name().to_string(),
-                        "set".to_string(),
-                        format!("GrpTRESMins={}", tres.join(",")),
-                        "where".to_string(),
-                        format!("cluster={}", cluster),
-                    ],
-                )?;
+async fn set_account_job_limit(
+    account: &SlurmAccount,
+    limit: &str,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    // the account exists in every cluster in the federation
+    let clusters = cache::get_clusters().await?.join(",");
 
-                priority_runner(expires)
-                    .await?
-                    .run(&cmd, DEFAULT_TIMEOUT)
-                    .await?;
-            }
+    let cmd = priority_runner(expires).await?.build_command(
+        "SACCTMGR",
+        vec![
+            "--immediate".to_string(),
+            "modify".to_string(),
+            "account".to_string(),
+            account.name().to_string(),
+            "set".to_string(),
+            format!("GrpJobs={}", limit),
+            format!("GrpSubmitJobs={}", limit),
+            "where".to_string(),
+            format!("cluster={}", clusters),
+        ],
+    )?;
 
-            // now we've made the change, save the account to the cache
-            cache::add_account(&account).await?;
+    priority_runner(expires)
+        .await?
+        .run(&cmd, DEFAULT_TIMEOUT)
+        .await?;
 
-            Ok(*account.limit())
-        }
-        None => {
-            tracing::warn!("Could not get account {}", account.name());
-            Err(Error::NotFound(account.name().to_string()))
-        }
-    }
+    Ok(())
 }
 
 pub async fn cancel_pending_user_jobs(
@@ -1730,7 +3900,7 @@ pub async fn cancel_pending_user_jobs(
     assert_not_expired(expires)?;
 
     let user = clean_user_name(user)?;
-    let cluster = cache::get_cluster().await?;
+    let cluster = cache::get_clusters().await?.join(",");
 
     tracing::info!(
         "Cancelling all pending jobs for user {} in cluster {}",
@@ -1774,7 +3944,7 @@ pub async fn cancel_pending_project_jobs(
     assert_not_expired(expires)?;
 
     let account = clean_account_name(account)?;
-    let cluster = cache::get_cluster().await?;
+    let cluster = cache::get_clusters().await?.join(",");
 
     tracing::info!(
         "Cancelling all pending jobs for account {} in cluster {}",
@@ -15,8 +15,13 @@ use std::fmt;
 use std::fmt::Display;
 use std::sync::Arc;
 use std::time::Duration;
-use templemeads::grammar::{DateRange, ProjectMapping, UserMapping};
+use templemeads::circuitbreaker::CircuitBreaker;
+use templemeads::exec;
+use templemeads::grammar::{DateRange, Node, ProjectMapping, ProjectTemplate, UserMapping};
+use templemeads::health;
 use templemeads::job::assert_not_expired;
+use templemeads::jobqueue::JobQueue;
+use templemeads::reconcile::ReconciliationReport;
 use templemeads::usagereport::{ProjectUsageReport, Usage};
 use templemeads::Error;
 use tokio::sync::Mutex;
@@ -125,6 +130,9 @@ impl LockedSlurmServer {
     }
 }
 
+/// The name of the circuit breaker protecting calls to slurmrestd
+const BREAKER: &str = "slurmrestd";
+
 static SLURM_SERVERS: Lazy<Mutex<Vec<Arc<Mutex<SlurmServer>>>>> =
     Lazy::new(|| Mutex::new(Vec::new()));
 
@@ -174,8 +182,9 @@ async fn login(
     // parse 'token_command' into an executable plus arguments
     let token_command = shlex::split(&token_command).context("Could not parse token command")?;
 
-    let token_exe = token_command.first().context("No token command")?;
-    let token_args = token_command.get(1..).unwrap_or(&[]);
+    if token_command.is_empty() {
+        return Err(Error::Login("No token command".to_string()));
+    }
 
     // get the current datetime in seconds since the epoch - we will use this
     // to check the token expiry
@@ -185,19 +194,11 @@ async fn login(
 
     assert_not_expired(expires)?;
 
-    // get the JWT token via a tokio process
-    let jwt = match tokio::process::Command::new(token_exe)
-        .args(token_args)
-        .output()
-        .await
-    {
-        Ok(jwt) => String::from_utf8(jwt.stdout).context("Could not convert JWT to string")?,
-        Err(e) => {
-            tracing::error!(
-                "Could not get JWT token using command '{:?}': {}",
-                token_command,
-                e
-            );
+    // get the JWT token by running the token command
+    let jwt = match exec::Command::from_parts(&token_command).run().await {
+        Ok(jwt) => jwt,
+        Err(failure) => {
+            tracing::error!("Could not get JWT token: {}", failure);
             return Err(Error::Login("Could not get JWT token".to_string()));
         }
     };
@@ -526,6 +527,109 @@ async fn get_connected_server(expires: &chrono::DateTime<Utc>) -> Result<LockedS
     }
 }
 
+///
+/// Check that the JWT token of the passed server is valid, by logging in
+/// again if needed, and then pinging slurmrestd with the token
+///
+async fn check_server_health(server: &mut SlurmServer) -> Result<String, Error> {
+    let expires = Utc::now() + chrono::Duration::minutes(1);
+
+    if !server.is_logged_in() {
+        match login(
+            &server.server,
+            &server.user,
+            &server.token_command,
+            server.token_lifespan,
+            &expires,
+        )
+        .await
+        {
+            Ok(session) => server.set_login_success(session),
+            Err(e) => {
+                server.set_login_failed();
+                return Err(Error::Login(format!("could not get a valid token: {}", e)));
+            }
+        }
+    }
+
+    let client = Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .context("Could not build client")?;
+
+    let url = format!("{}/slurm/v{}/ping", server.server, server.version);
+
+    let result = client
+        .get(&url)
+        .header("Accept", "application/json")
+        .header("X-SLURM-USER-NAME", &server.user)
+        .header("X-SLURM-USER-TOKEN", server.jwt.expose_secret().to_string())
+        .send()
+        .await
+        .map_err(|e| Error::Call(format!("could not ping {}: {}", url, e)))?;
+
+    match result.status() {
+        status if status.is_success() => {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .context("Could not get current time")?
+                .as_secs();
+
+            let remaining =
+                (server.jwt_creation_time + server.token_lifespan as u64).saturating_sub(now);
+
+            Ok(format!(
+                "API v{}, token valid for {} more seconds",
+                server.version, remaining
+            ))
+        }
+        status if status.as_u16() == 401 => {
+            // the token has been rejected - get a new one next time
+            server.set_login_failed();
+            Err(Error::Login("the token was rejected (401)".to_string()))
+        }
+        status => Err(Error::Call(format!("ping returned status {}", status))),
+    }
+}
+
+///
+/// Spawn the background task that checks, every `interval` seconds,
+/// that slurmrestd can be reached with a valid token. The result is
+/// reported as a dependency in the agent's health
+///
+pub fn spawn_health_check(interval: u64) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+
+            let slurm_servers = SLURM_SERVERS.lock().await.clone();
+            let mut checked = HashSet::new();
+
+            for server in slurm_servers {
+                // sessions that are in use are being checked by their calls
+                let mut server = match server.try_lock_owned() {
+                    Ok(server) => server,
+                    Err(_) => continue,
+                };
+
+                if !checked.insert(server.server.clone()) {
+                    continue;
+                }
+
+                let dependency = format!("slurmrestd:{}", server.server);
+
+                match check_server_health(&mut server).await {
+                    Ok(detail) => {
+                        health::set_dependency(&dependency, true, &detail);
+                        CircuitBreaker::new(BREAKER).probe_succeeded();
+                    }
+                    Err(e) => health::set_dependency(&dependency, false, &e.to_string()),
+                }
+            }
+        }
+    });
+}
+
 ///
 /// Call a get URL on the slurmrestd server described in 'auth'.
 ///
@@ -535,10 +639,16 @@ async fn call_get(
     query_params: &Vec<(&str, &str)>,
     expires: &chrono::DateTime<Utc>,
 ) -> Result<serde_json::Value, Error> {
+    // fail fast if slurmrestd is down
+    let breaker = CircuitBreaker::new(BREAKER);
+    breaker.check()?;
+
     // get a connected server
     tracing::debug!("Getting a connected server...");
     let start_time = Utc::now();
-    let mut lock = get_connected_server(expires).await?;
+    let mut lock = get_connected_server(expires)
+        .await
+        .inspect_err(|e| breaker.failure(e))?;
     tracing::debug!(
         "Connected server obtained! Took {} ms",
         (Utc::now() - start_time).num_milliseconds()
@@ -592,6 +702,7 @@ async fn call_get(
         .header("X-SLURM-USER-TOKEN", lock.jwt().expose_secret().to_string())
         .send()
         .await
+        .inspect_err(|e| breaker.failure(e))
         .with_context(|| format!("Could not call function: {}", url))?;
 
     // write a warning if this took a long time
@@ -622,7 +733,9 @@ async fn call_get(
         assert_not_expired(expires)?;
 
         tracing::error!("Authorisation (401) error. Reconnecting.");
-        lock = get_connected_server(expires).await?;
+        lock = get_connected_server(expires)
+            .await
+            .inspect_err(|e| breaker.failure(e))?;
 
         if Utc::now().signed_duration_since(start_time).num_seconds() > 10 {
             tracing::info!(
@@ -656,6 +769,7 @@ async fn call_get(
             .header("X-SLURM-USER-TOKEN", lock.jwt().expose_secret().to_string())
             .send()
             .await
+            .inspect_err(|e| breaker.failure(e))
             .with_context(|| format!("Could not call function: {}", url))?;
 
         if Utc::now().signed_duration_since(start_time).num_seconds() > 10 {
@@ -668,6 +782,16 @@ async fn call_get(
         }
     }
 
+    // only server errors count against the breaker - anything else
+    // shows that slurmrestd is up
+    match result.status().is_server_error() {
+        true => breaker.failure(&format!(
+            "slurmrestd responded with status {}",
+            result.status()
+        )),
+        false => breaker.success(),
+    }
+
     if result.status().as_u16() == 500 {
         tracing::error!(
             "500 error - slurmrestd error when calling {} as user {}.",
@@ -742,10 +866,16 @@ async fn call_post(
     payload: &serde_json::Value,
     expires: &chrono::DateTime<Utc>,
 ) -> Result<(), Error> {
+    // fail fast if slurmrestd is down
+    let breaker = CircuitBreaker::new(BREAKER);
+    breaker.check()?;
+
     // get a connected server
     tracing::debug!("Getting a connected server...");
     let start_time = Utc::now();
-    let mut lock = get_connected_server(expires).await?;
+    let mut lock = get_connected_server(expires)
+        .await
+        .inspect_err(|e| breaker.failure(e))?;
     tracing::debug!(
         "Connected server obtained! Took {} ms",
         (Utc::now() - start_time).num_milliseconds()
@@ -791,6 +921,7 @@ async fn call_post(
         .json(&payload)
         .send()
         .await
+        .inspect_err(|e| breaker.failure(e))
         .with_context(|| format!("Could not call function: {}", url))?;
 
     // write a warning if this took a long time
@@ -821,7 +952,9 @@ async fn call_post(
         assert_not_expired(expires)?;
 
         tracing::error!("Authorisation (401) error. Reconnecting.");
-        lock = get_connected_server(expires).await?;
+        lock = get_connected_server(expires)
+            .await
+            .inspect_err(|e| breaker.failure(e))?;
 
         if Utc::now().signed_duration_since(start_time).num_seconds() > 10 {
             tracing::info!(
@@ -856,6 +989,7 @@ async fn call_post(
             .json(&payload)
             .send()
             .await
+            .inspect_err(|e| breaker.failure(e))
             .with_context(|| format!("Could not call function: {}", url))?;
 
         if Utc::now().signed_duration_since(start_time).num_seconds() > 10 {
@@ -868,6 +1002,16 @@ async fn call_post(
         }
     }
 
+    // only server errors count against the breaker - anything else
+    // shows that slurmrestd is up
+    match result.status().is_server_error() {
+        true => breaker.failure(&format!(
+            "slurmrestd responded with status {}",
+            result.status()
+        )),
+        false => breaker.success(),
+    }
+
     if result.status().as_u16() == 500 {
         tracing::error!(
             "500 error - slurmrestd error when calling {} with payload {} as user {}.",
@@ -1544,6 +1688,14 @@ pub fn get_managed_organization() -> String {
     "openportal".to_string()
 }
 
+///
+/// Return the number of connections to slurmrestd, which is the number
+/// of calls that can be made at the same time
+///
+pub async fn num_servers() -> usize {
+    SLURM_SERVERS.lock().await.len()
+}
+
 pub async fn initialise_servers(
     servers: &[String],
     users: &[String],
@@ -1820,6 +1972,7 @@ impl SlurmAccount {
 pub struct SlurmLimit {
     account: String,
     cluster: String,
+    partition: String,
     cpu_limit: Option<Usage>,
     gpu_limit: Option<Usage>,
     mem_limit: Option<Usage>,
@@ -1830,9 +1983,10 @@ impl Display for SlurmLimit {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "SlurmLimit {{ account: {}, cluster: {}, cpu: {:?}, gpu: {:?}, mem: {:?}, billing: {:?} }}",
+            "SlurmLimit {{ account: {}, cluster: {}, partition: {}, cpu: {:?}, gpu: {:?}, mem: {:?}, billing: {:?} }}",
             self.account(),
             self.cluster(),
+            self.partition(),
             self.cpu_limit(),
             self.gpu_limit(),
             self.mem_limit(),
@@ -1879,6 +2033,13 @@ impl SlurmLimit {
             }
         };
 
+        // associations that are not restricted to a partition have
+        // an empty (or missing) partition
+        let partition = result
+            .get("partition")
+            .and_then(|partition| partition.as_str())
+            .unwrap_or_default();
+
         let limits: &Vec<serde_json::Value> = match result.get("max") {
             Some(max) => match max.get("tres") {
                 Some(tres) => match tres.get("group") {
@@ -1986,6 +2147,7 @@ impl SlurmLimit {
         Ok(SlurmLimit {
             account: clean_account_name(account)?,
             cluster: cluster.to_string(),
+            partition: partition.to_string(),
             cpu_limit,
             gpu_limit,
             mem_limit,
@@ -2001,6 +2163,10 @@ impl SlurmLimit {
         &self.cluster
     }
 
+    pub fn partition(&self) -> &str {
+        &self.partition
+    }
+
     pub fn cpu_limit(&self) -> Option<Usage> {
         self.cpu_limit
     }
@@ -2356,6 +2522,68 @@ impl SlurmNode {
         Ok(SlurmNode::new(cpus, gpus, mem, billing))
     }
 
+    ///
+    /// Construct from a single node of the output of
+    /// `scontrol --json show nodes`, returning the name of the node,
+    /// its resources, and its hardware profile
+    ///
+    pub fn from_scontrol(value: &serde_json::Value) -> Result<(String, Self, Node), Error> {
+        let name = match value.get("name").and_then(|name| name.as_str()) {
+            Some(name) => name.to_string(),
+            None => {
+                tracing::warn!("Could not get name from node: {:?}", value);
+                return Err(Error::Call("Could not get name from node".to_string()));
+            }
+        };
+
+        let get_u64 = |key: &str| value.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+
+        let mut cpus = get_u64("cpus");
+        let mut mem = get_u64("real_memory");
+        let mut gpus = 0;
+        let mut billing = 0;
+
+        // the configured TRES of the node (e.g.
+        // "cpu=72,mem=460000M,billing=72,gres/gpu=4") hold everything
+        // that jobs are charged for
+        let tres = value
+            .get("tres")
+            .and_then(|tres| tres.as_str())
+            .unwrap_or_default();
+
+        for item in tres.split(',') {
+            let Some((key, count)) = item.split_once('=') else {
+                continue;
+            };
+
+            match key.trim() {
+                "cpu" => cpus = count.trim().parse().unwrap_or(cpus),
+                "mem" => mem = parse_memory_mb(count).unwrap_or(mem),
+                "gres/gpu" => gpus = count.trim().parse().unwrap_or(0),
+                "billing" => billing = count.trim().parse().unwrap_or(0),
+                _ => {}
+            }
+        }
+
+        if billing == 0 {
+            // slurm bills by CPU if no billing weights are set
+            billing = cpus;
+        }
+
+        let sockets = get_u64("sockets").max(1);
+        let cores = get_u64("cores");
+
+        let profile = Node::construct(
+            sockets as u32,
+            cores as u32,
+            gpus as u32,
+            mem as u32,
+            billing as u32,
+        );
+
+        Ok((name, SlurmNode::new(cpus, gpus, mem, billing), profile))
+    }
+
     pub fn cpus(&self) -> u64 {
         self.cpus
     }
@@ -2416,10 +2644,75 @@ impl SlurmNodes {
     }
 
     pub fn get(&self, name: &str) -> &SlurmNode {
-        self.nodes.get(name).unwrap_or(&self.default)
+        if let Some(node) = self.nodes.get(name) {
+            return node;
+        }
+
+        // jobs that ran on several nodes report a hostlist (e.g.
+        // "node[001-004,010]") - assume that these nodes all have the
+        // same type as the first
+        match first_host(name) {
+            Some(host) => self.nodes.get(&host).unwrap_or(&self.default),
+            None => &self.default,
+        }
+    }
+}
+
+///
+/// Return the name of the first host in a slurm hostlist, e.g.
+/// "node001" for "node[001-004,010],gpu01"
+///
+fn first_host(hostlist: &str) -> Option<String> {
+    let mut depth = 0;
+
+    let end = hostlist
+        .char_indices()
+        .find(|(_, c)| {
+            match c {
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                _ => {}
+            }
+
+            *c == ',' && depth == 0
+        })
+        .map(|(i, _)| i)
+        .unwrap_or(hostlist.len());
+
+    let first = hostlist[..end].trim();
+
+    match first.split_once('[') {
+        Some((prefix, rest)) => {
+            let (range, suffix) = rest.split_once(']')?;
+            let number = range.split([',', '-']).next()?;
+            Some(format!("{}{}{}", prefix, number, suffix))
+        }
+        None => match first.is_empty() {
+            true => None,
+            false => Some(first.to_string()),
+        },
     }
 }
 
+///
+/// Parse a slurm memory size (e.g. "460000M" or "512G") into MB
+///
+fn parse_memory_mb(memory: &str) -> Option<u64> {
+    let memory = memory.trim();
+
+    let (number, multiplier) = match memory.chars().last()? {
+        'K' => (&memory[..memory.len() - 1], 1.0 / 1024.0),
+        'M' => (&memory[..memory.len() - 1], 1.0),
+        'G' => (&memory[..memory.len() - 1], 1024.0),
+        'T' => (&memory[..memory.len() - 1], 1024.0 * 1024.0),
+        _ => (memory, 1.0),
+    };
+
+    let number: f64 = number.parse().ok()?;
+
+    Some((number * multiplier) as u64)
+}
+
 fn get_fraction(used: u64, total: u64) -> f64 {
     match total {
         0 => 0.0,
@@ -2441,6 +2734,7 @@ pub struct SlurmJob {
     duration: u64,
     state: String,
     qos: String,
+    partition: String,
     nodes: u64,
     cpus: u64,
     gpus: u64,
@@ -2458,7 +2752,7 @@ impl Display for SlurmJob {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "SlurmJob {{ id: {}, user: {}, account: {}, cluster: {}, node_info: {}, start: {}, end: {}, duration: {}s, total_duration: {}s state: {}, qos: {}, nodes: {}, cpus: {}, gpus: {}, memory: {}, requested_nodes: {}, requested_cpus: {}, requested_gpus: {}, requested_memory: {}, energy: {}, billing: {}, requested_billing: {}, node_fraction: {}, billed_node_seconds: {} }}",
+            "SlurmJob {{ id: {}, user: {}, account: {}, cluster: {}, node_info: {}, start: {}, end: {}, duration: {}s, total_duration: {}s state: {}, qos: {}, partition: {}, nodes: {}, cpus: {}, gpus: {}, memory: {}, requested_nodes: {}, requested_cpus: {}, requested_gpus: {}, requested_memory: {}, energy: {}, billing: {}, requested_billing: {}, node_fraction: {}, billed_node_seconds: {} }}",
             self.id(),
             self.user(),
             self.account(),
@@ -2470,6 +2764,7 @@ impl Display for SlurmJob {
             self.total_duration().num_seconds(),
             self.state(),
             self.qos(),
+            self.partition(),
             self.nodes(),
             self.cpus(),
             self.gpus(),
@@ -2734,6 +3029,15 @@ impl SlurmJob {
             }
         };
 
+        // jobs that requested several partitions report them as a
+        // comma-separated list, with the one they ran on first
+        let partition = value
+            .get("partition")
+            .and_then(|partition| partition.as_str())
+            .and_then(|partition| partition.split(',').next())
+            .unwrap_or_default()
+            .to_string();
+
         let tres = match value.get("tres") {
             Some(tres) => tres,
             None => {
@@ -2945,6 +3249,7 @@ impl SlurmJob {
             duration,
             state,
             qos,
+            partition,
             nodes,
             cpus,
             gpus,
@@ -2960,65 +3265,64 @@ impl SlurmJob {
     }
 
     ///
-    /// Construct a list of SlurmJobs from a JSON value
-    /// Note this skips jobs that have not consumed any resource
-    /// (i.e. have a duration of 0). If you want these jobs, you
-    /// should contruct each job individually
+    /// Construct the job from the passed single job from the output of
+    /// `sacct --json`, clamped to the passed time window. This returns
+    /// None if the job could not be constructed, or if it consumed
+    /// nothing within the window
     ///
-    pub fn get_consumers(
-        value: &serde_json::Value,
+    pub fn get_consumer(
+        job: &serde_json::Value,
         start_time: &chrono::DateTime<chrono::Utc>,
         end_time: &chrono::DateTime<chrono::Utc>,
         slurm_nodes: &SlurmNodes,
-    ) -> Result<Vec<SlurmJob>, Error> {
-        if start_time > end_time {
-            return Err(Error::Call(format!(
-                "Start time '{}' is after end time '{}'",
-                start_time, end_time
-            )));
-        }
-
-        let jobs = match value.get("jobs") {
-            Some(jobs) => match jobs.as_array() {
-                Some(jobs) => {
-                    let mut slurm_jobs: Vec<SlurmJob> = Vec::new();
+    ) -> Option<SlurmJob> {
+        match SlurmJob::construct(job, slurm_nodes) {
+            Ok(mut job) => {
+                if job.start_time < *start_time {
+                    job.start_time = *start_time;
+                } else if job.start_time > *end_time {
+                    // job was likely cancelled
+                    job.start_time = *end_time;
+                }
 
-                    for job in jobs {
-                        match SlurmJob::construct(job, slurm_nodes) {
-                            Ok(mut job) => {
-                                if job.start_time < *start_time {
-                                    job.start_time = *start_time;
-                                } else if job.start_time > *end_time {
-                                    // job was likely cancelled
-                                    job.start_time = *end_time;
-                                }
+                if job.end_time > *end_time || job.end_time < *start_time {
+                    job.end_time = *end_time;
+                }
 
-                                if job.end_time > *end_time || job.end_time < *start_time {
-                                    job.end_time = *end_time;
-                                }
+                if job.duration().num_seconds() > 0 {
+                    tracing::debug!("Recording job {}", job);
+                    Some(job)
+                } else {
+                    None
+                }
+            }
+            Err(e) => {
+                tracing::warn!("Could not construct job from {}: {}", job, e);
+                None
+            }
+        }
+    }
 
-                                if job.duration().num_seconds() > 0 {
-                                    tracing::debug!("Recording job {}", job);
-                                    slurm_jobs.push(job)
-                                }
-                            }
-                            Err(e) => {
-                                tracing::warn!("Could not construct job from {}: {}", job, e);
-                            }
-                        }
-                    }
+    ///
+    /// Return a copy of this job trimmed to the part of it that ran
+    /// between `start_time` and `end_time`, or None if it did not run
+    /// in that window. The job must already have been returned by
+    /// `get_consumer`, so that its end time is known
+    ///
+    pub fn clamp_to(
+        &self,
+        start_time: &chrono::DateTime<chrono::Utc>,
+        end_time: &chrono::DateTime<chrono::Utc>,
+    ) -> Option<SlurmJob> {
+        let mut job = self.clone();
 
-                    slurm_jobs
-                }
-                None => {
-                    tracing::warn!("Jobs is not an array: {:?}", jobs);
-                    return Err(Error::Call("Jobs is not an array".to_string()));
-                }
-            },
-            None => Vec::new(),
-        };
+        job.start_time = job.start_time.max(*start_time);
+        job.end_time = job.end_time.min(*end_time);
 
-        Ok(jobs)
+        match job.end_time > job.start_time && job.duration().num_seconds() > 0 {
+            true => Some(job),
+            false => None,
+        }
     }
 
     pub fn id(&self) -> u64 {
@@ -3091,6 +3395,10 @@ impl SlurmJob {
         &self.qos
     }
 
+    pub fn partition(&self) -> &str {
+        &self.partition
+    }
+
     pub fn nodes(&self) -> u64 {
         self.nodes
     }
@@ -3127,6 +3435,23 @@ impl SlurmJob {
         self.energy
     }
 
+    ///
+    /// Return the energy (in joules) consumed by this job during the
+    /// query that generated it. Slurm only reports the energy of the
+    /// whole job, so this is shared out in proportion to the time
+    ///
+    pub fn energy_joules(&self) -> u64 {
+        let total_seconds = self.total_duration().num_seconds();
+
+        if total_seconds <= 0 {
+            return 0;
+        }
+
+        let fraction = self.duration().num_seconds() as f64 / total_seconds as f64;
+
+        (self.energy as f64 * fraction.clamp(0.0, 1.0)).round() as u64
+    }
+
     pub fn billing(&self) -> u64 {
         self.billing
     }
@@ -3325,9 +3650,12 @@ pub async fn add_user(user: &UserMapping, expires: &chrono::DateTime<Utc>) -> Re
         };
     };
 
-    let user: SlurmUser = get_user_create_if_not_exists(user, expires).await?;
+    let slurm_user: SlurmUser = get_user_create_if_not_exists(user, expires).await?;
+
+    // re-enable the user if they had previously been removed
+    sacctmgr::enable_user(user, expires).await?;
 
-    tracing::info!("Added user: {}", user);
+    tracing::info!("Added user: {}", slurm_user);
 
     Ok(())
 }
@@ -3375,6 +3703,13 @@ pub async fn add_project(
     Ok(())
 }
 
+pub async fn get_nodes(expires: &chrono::DateTime<Utc>) -> Result<Vec<Node>, Error> {
+    assert_not_expired(expires)?;
+
+    // Call the sacctmgr version
+    sacctmgr::get_nodes(expires).await
+}
+
 pub async fn get_usage_report(
     project: &ProjectMapping,
     dates: &DateRange,
@@ -3388,21 +3723,104 @@ pub async fn get_usage_report(
 
 pub async fn get_limit(
     project: &ProjectMapping,
+    partition: Option<&str>,
     expires: &chrono::DateTime<Utc>,
 ) -> Result<Usage, Error> {
     assert_not_expired(expires)?;
 
     // Call the sacctmgr version
-    sacctmgr::get_limit(project, expires).await
+    sacctmgr::get_limit(project, partition, expires).await
 }
 
 pub async fn set_limit(
     project: &ProjectMapping,
     limit: &Usage,
+    partition: Option<&str>,
     expires: &chrono::DateTime<Utc>,
 ) -> Result<Usage, Error> {
     assert_not_expired(expires)?;
 
     // Call the sacctmgr version
-    sacctmgr::set_limit(project, limit, expires).await
+    sacctmgr::set_limit(project, limit, partition, expires).await
+}
+
+pub async fn set_qos(
+    project: &ProjectMapping,
+    class: &ProjectTemplate,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<String, Error> {
+    assert_not_expired(expires)?;
+
+    // Call the sacctmgr version, as the QOS endpoints of slurmrestd
+    // differ between API versions
+    sacctmgr::set_qos(project, class, expires).await
+}
+
+pub async fn get_qos(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<String, Error> {
+    assert_not_expired(expires)?;
+
+    // Call the sacctmgr version
+    sacctmgr::get_qos(project, expires).await
+}
+
+pub async fn create_reservation(
+    project: &ProjectMapping,
+    dates: &DateRange,
+    nodes: u32,
+    window: &DateRange,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<String, Error> {
+    assert_not_expired(expires)?;
+
+    // Call the scontrol version, as reservations cannot be created
+    // through all versions of slurmrestd
+    sacctmgr::create_reservation(project, dates, nodes, window, expires).await
+}
+
+pub async fn remove_reservation(
+    project: &ProjectMapping,
+    dates: &DateRange,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<String, Error> {
+    assert_not_expired(expires)?;
+
+    // Call the scontrol version
+    sacctmgr::remove_reservation(project, dates, expires).await
+}
+
+pub async fn get_job_queue(
+    project: &ProjectMapping,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<JobQueue, Error> {
+    assert_not_expired(expires)?;
+
+    // Call the squeue version
+    sacctmgr::get_job_queue(project, expires).await
+}
+
+pub async fn reconcile(
+    project: &ProjectMapping,
+    users: &[UserMapping],
+    repair: bool,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<ReconciliationReport, Error> {
+    assert_not_expired(expires)?;
+
+    // Call the sacctmgr version
+    sacctmgr::reconcile(project, users, repair, expires).await
+}
+
+pub async fn submit_job(
+    mapping: &UserMapping,
+    script: &str,
+    expires: &chrono::DateTime<Utc>,
+) -> Result<String, Error> {
+    assert_not_expired(expires)?;
+
+    // Call the sbatch version, as slurmrestd would need the script
+    // contents rather than its path
+    sacctmgr::submit_job(mapping, script, expires).await
 }
@@ -0,0 +1,334 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Read-only access to the slurmdbd MySQL/MariaDB database, so that usage
+//! reports can be generated from the job tables directly, rather than
+//! by running (and parsing the output of) sacct
+
+use chrono::Utc;
+use mysql_async::prelude::*;
+use mysql_async::{OptsBuilder, Pool, Row};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use templemeads::Error;
+use tokio::sync::RwLock;
+
+use crate::cache;
+use crate::slurm::{SlurmJob, SlurmNodes};
+
+static POOL: Lazy<RwLock<Option<Pool>>> = Lazy::new(|| RwLock::new(None));
+
+///
+/// Connect to the slurmdbd database at the passed URL (e.g.
+/// `mysql://slurm@dbhost:3306/slurm_acct_db`), using the password
+/// read from the passed file (if not empty). At most `max_connections`
+/// queries are run at the same time
+///
+pub async fn connect(url: &str, password_file: &str, max_connections: u64) -> Result<(), Error> {
+    let opts = mysql_async::Opts::from_url(url)
+        .map_err(|e| Error::Misconfigured(format!("Invalid slurmdbd-url '{}': {}", url, e)))?;
+
+    let password = match password_file.trim().is_empty() {
+        true => None,
+        false => Some(
+            tokio::fs::read_to_string(password_file.trim())
+                .await
+                .map_err(|e| {
+                    Error::Misconfigured(format!(
+                        "Could not read slurmdbd-password-file '{}': {}",
+                        password_file, e
+                    ))
+                })?
+                .trim()
+                .to_string(),
+        ),
+    };
+
+    let max_connections = max_connections.max(1) as usize;
+
+    let mut builder =
+        OptsBuilder::from_opts(opts).pool_opts(mysql_async::PoolOpts::default().with_constraints(
+            mysql_async::PoolConstraints::new(1, max_connections).ok_or_else(|| {
+                Error::Misconfigured(format!(
+                    "Invalid number of slurmdbd connections: {}",
+                    max_connections
+                ))
+            })?,
+        ));
+
+    if let Some(password) = password {
+        builder = builder.pass(Some(password));
+    }
+
+    let pool = Pool::new(builder);
+
+    // check that we can connect, and that this looks like slurmdbd
+    let mut conn = pool
+        .get_conn()
+        .await
+        .map_err(|e| Error::Login(format!("Could not connect to slurmdbd database: {}", e)))?;
+
+    conn.query_drop("SELECT id FROM tres_table LIMIT 1")
+        .await
+        .map_err(|e| {
+            Error::Login(format!(
+                "Could not read the tres_table of the slurmdbd database: {}",
+                e
+            ))
+        })?;
+
+    drop(conn);
+
+    tracing::info!("Connected to slurmdbd database");
+
+    *POOL.write().await = Some(pool);
+
+    Ok(())
+}
+
+///
+/// Return whether usage should be read from the slurmdbd database
+///
+pub async fn is_connected() -> bool {
+    POOL.read().await.is_some()
+}
+
+///
+/// Return the jobs of the passed account that consumed resources between
+/// `start_time` and `end_time`, read from the job tables of every cluster
+/// in the federation. Each row is converted to the same form as the
+/// output of `sacct --json`, so that the jobs are interpreted in exactly
+/// the same way as when sacct is used
+///
+pub async fn get_consumers(
+    account: &str,
+    start_time: &chrono::DateTime<Utc>,
+    end_time: &chrono::DateTime<Utc>,
+    slurm_nodes: &SlurmNodes,
+    timeout: std::time::Duration,
+) -> Result<Vec<SlurmJob>, Error> {
+    let pool = match POOL.read().await.as_ref() {
+        Some(pool) => pool.clone(),
+        None => {
+            return Err(Error::Misconfigured(
+                "Not connected to the slurmdbd database".to_string(),
+            ))
+        }
+    };
+
+    let query = query_consumers(&pool, account, start_time, end_time, slurm_nodes);
+
+    match tokio::time::timeout(timeout, query).await {
+        Ok(jobs) => jobs,
+        Err(_) => {
+            tracing::error!(
+                "Query of slurmdbd for account {} timed out after {:?} seconds",
+                account,
+                timeout.as_secs()
+            );
+            Err(Error::Timeout("Query timed out".to_string()))
+        }
+    }
+}
+
+async fn query_consumers(
+    pool: &Pool,
+    account: &str,
+    start_time: &chrono::DateTime<Utc>,
+    end_time: &chrono::DateTime<Utc>,
+    slurm_nodes: &SlurmNodes,
+) -> Result<Vec<SlurmJob>, Error> {
+    let mut conn = pool.get_conn().await.map_err(db_error)?;
+
+    // the TRES in the job table are stored as "id=count" - look up
+    // the type and name of each id
+    let tres: HashMap<u64, (String, String)> = conn
+        .query::<(u64, String, String), _>("SELECT id, type, name FROM tres_table")
+        .await
+        .map_err(db_error)?
+        .into_iter()
+        .map(|(id, tres_type, name)| (id, (tres_type, name)))
+        .collect();
+
+    let partition = cache::get_partition().await?;
+    let now = Utc::now().timestamp();
+
+    let mut jobs = Vec::new();
+
+    for cluster in cache::get_clusters().await? {
+        // the cluster name is part of the table names, so cannot be
+        // passed as a parameter
+        if cluster.is_empty()
+            || !cluster
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(Error::Misconfigured(format!(
+                "Cannot query slurmdbd for invalid cluster name '{}'",
+                cluster
+            )));
+        }
+
+        let mut query = format!(
+            "SELECT j.id_job, a.user, j.account, j.time_eligible, j.time_start, \
+             j.time_end, j.time_suspended, j.state, q.name, j.`partition`, \
+             j.nodelist, j.tres_alloc, j.tres_req \
+             FROM `{cluster}_job_table` j \
+             JOIN `{cluster}_assoc_table` a ON j.id_assoc = a.id_assoc \
+             LEFT JOIN qos_table q ON j.id_qos = q.id \
+             WHERE j.deleted = 0 AND j.account = ? AND j.time_start > 0 \
+             AND j.time_start < ? AND (j.time_end = 0 OR j.time_end > ?)"
+        );
+
+        let mut params: Vec<mysql_async::Value> = vec![
+            account.into(),
+            end_time.timestamp().into(),
+            start_time.timestamp().into(),
+        ];
+
+        if let Some(partition) = &partition {
+            query.push_str(" AND FIND_IN_SET(?, j.`partition`) > 0");
+            params.push(partition.as_str().into());
+        }
+
+        let mut errors = 0;
+
+        // stream the rows, so that only the resulting jobs are held
+        // in memory
+        conn.exec_iter(query, params)
+            .await
+            .map_err(db_error)?
+            .for_each(|row: Row| match to_sacct_json(&row, &cluster, &tres, now) {
+                Ok(job) => {
+                    if let Some(job) =
+                        SlurmJob::get_consumer(&job, start_time, end_time, slurm_nodes)
+                    {
+                        jobs.push(job);
+                    }
+                }
+                Err(e) => {
+                    errors += 1;
+                    tracing::warn!("Could not read job from slurmdbd: {}", e);
+                }
+            })
+            .await
+            .map_err(db_error)?;
+
+        if errors > 0 {
+            tracing::warn!(
+                "Skipped {} unreadable jobs of account {} in cluster {}",
+                errors,
+                account,
+                cluster
+            );
+        }
+    }
+
+    Ok(jobs)
+}
+
+fn db_error(e: mysql_async::Error) -> Error {
+    tracing::error!("slurmdbd database error: {}", e);
+    Error::Call(format!("slurmdbd database error: {}", e))
+}
+
+fn column<T: FromValue>(row: &Row, index: usize, name: &str) -> Result<T, Error> {
+    match row.get_opt::<T, usize>(index) {
+        Some(Ok(value)) => Ok(value),
+        Some(Err(e)) => Err(Error::Parse(format!("Invalid {} in job row: {}", name, e))),
+        None => Err(Error::Parse(format!("Missing {} in job row", name))),
+    }
+}
+
+///
+/// Convert a slurmdbd job state number into the name used by sacct
+///
+fn state_name(state: u32) -> &'static str {
+    // the lower 8 bits are the base state - the rest are flags
+    match state & 0xff {
+        0 => "PENDING",
+        1 => "RUNNING",
+        2 => "SUSPENDED",
+        3 => "COMPLETED",
+        4 => "CANCELLED",
+        5 => "FAILED",
+        6 => "TIMEOUT",
+        7 => "NODE_FAIL",
+        8 => "PREEMPTED",
+        9 => "BOOT_FAIL",
+        10 => "DEADLINE",
+        11 => "OUT_OF_MEMORY",
+        _ => "UNKNOWN",
+    }
+}
+
+///
+/// Convert a slurmdbd TRES string (e.g. "1=4,2=8000,4=1,1001=2") into
+/// the list of TRES objects reported by sacct
+///
+fn tres_json(tres: &str, tres_types: &HashMap<u64, (String, String)>) -> serde_json::Value {
+    tres.split(',')
+        .filter_map(|tres| tres.split_once('='))
+        .filter_map(|(id, count)| {
+            let (tres_type, name) = tres_types.get(&id.trim().parse::<u64>().ok()?)?;
+            let count = count.trim().parse::<i64>().ok()?;
+
+            Some(serde_json::json!({
+                "type": tres_type,
+                "name": name,
+                "count": count,
+            }))
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+fn to_sacct_json(
+    row: &Row,
+    cluster: &str,
+    tres_types: &HashMap<u64, (String, String)>,
+    now: i64,
+) -> Result<serde_json::Value, Error> {
+    let id: u64 = column(row, 0, "id_job")?;
+    let user: String = column(row, 1, "user")?;
+    let account: String = column(row, 2, "account")?;
+    let eligible: i64 = column(row, 3, "time_eligible")?;
+    let start: i64 = column(row, 4, "time_start")?;
+    let end: i64 = column(row, 5, "time_end")?;
+    let suspended: i64 = column(row, 6, "time_suspended")?;
+    let state: u32 = column(row, 7, "state")?;
+    let qos: Option<String> = column(row, 8, "qos")?;
+    let partition: String = column(row, 9, "partition")?;
+    let nodelist: Option<String> = column(row, 10, "nodelist")?;
+    let tres_alloc: Option<String> = column(row, 11, "tres_alloc")?;
+    let tres_req: Option<String> = column(row, 12, "tres_req")?;
+
+    // jobs that are still running have an end time of 0
+    let elapsed = match end > 0 {
+        true => end - start - suspended,
+        false => now - start - suspended,
+    };
+
+    Ok(serde_json::json!({
+        "job_id": id,
+        "user": user,
+        "account": account,
+        "cluster": cluster,
+        "nodes": nodelist.unwrap_or_default(),
+        "time": {
+            "eligible": eligible,
+            "start": start,
+            "end": end,
+            "elapsed": elapsed.max(0),
+        },
+        "state": {
+            "current": [state_name(state)],
+        },
+        "qos": qos.unwrap_or_default(),
+        "partition": partition,
+        "tres": {
+            "allocated": tres_json(&tres_alloc.unwrap_or_default(), tres_types),
+            "requested": tres_json(&tres_req.unwrap_or_default(), tres_types),
+        },
+    }))
+}
@@ -0,0 +1,14 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A user in a batch for whom the instruction failed
+ */
+export type BatchFailure = { 
+/**
+ * The user who failed
+ */
+user: string, 
+/**
+ * Why the instruction failed for this user
+ */
+error: string, };
@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { BatchFailure } from "./BatchFailure";
+
+/**
+ * The result of a batch instruction, for each of its users
+ */
+export type BatchResult = { 
+/**
+ * The mappings of the users for whom the instruction succeeded
+ */
+succeeded: Array<string>, 
+/**
+ * The users for whom the instruction failed
+ */
+failed: Array<BatchFailure>, };
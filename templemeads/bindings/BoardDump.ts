@@ -0,0 +1,20 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { BoardSnapshot } from "./BoardSnapshot";
+
+/**
+ * Full dump of the contents of an agent's boards, used to debug
+ * stuck pipelines
+ */
+export type BoardDump = { 
+/**
+ * Agent name
+ */
+agent_name: string, 
+/**
+ * When this dump was generated
+ */
+generated_at: string, 
+/**
+ * The boards shared with each of the agent's peers
+ */
+boards: Array<BoardSnapshot>, };
@@ -0,0 +1,24 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Job } from "./Job";
+
+/**
+ * Snapshot of every job on the board shared with one peer
+ */
+export type BoardSnapshot = { 
+/**
+ * Name of the peer that shares this board
+ */
+peer: string, 
+/**
+ * Zone of the peer that shares this board
+ */
+zone: string, 
+/**
+ * Every job on the board (oldest first), including its state
+ * and its created, changed and expires timestamps
+ */
+jobs: Array<Job>, 
+/**
+ * Jobs that are queued waiting for the connection to the peer to re-open
+ */
+queued: Array<Job>, };
@@ -0,0 +1,31 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * State of a circuit breaker protecting calls to a third-party service
+ */
+export type BreakerStatus = { 
+/**
+ * Name of the breaker, e.g. "slurmrestd"
+ */
+name: string, 
+/**
+ * "closed" (calls are made), "open" (calls fail fast) or
+ * "half-open" (a probe call is let through)
+ */
+state: string, 
+/**
+ * Number of consecutive failed calls
+ */
+failures: number, 
+/**
+ * The error from the last failed call
+ */
+last_error: string, 
+/**
+ * Time when the breaker last opened, if it is not closed
+ */
+opened_at: string | null, 
+/**
+ * Number of calls rejected because the breaker was open
+ */
+rejected: bigint, };
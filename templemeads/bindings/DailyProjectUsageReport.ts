@@ -2,6 +2,11 @@
 import type { Usage } from "./Usage";
 
 export type DailyProjectUsageReport = { reports: { [key in string]?: Usage }, components: { [key in string]?: { [key in string]?: Usage } }, 
+/**
+ * The usage (and its components) on each partition. Empty when
+ * reading data from older instances.
+ */
+partitions: { [key in string]?: DailyProjectUsageReport }, 
 /**
  * Per-user job counts. Empty when reading data from older instances.
  */
@@ -18,4 +23,9 @@ num_jobs: bigint,
 /**
  * Scalar total — equals sum of user_wait_seconds when populated.
  */
-total_wait_seconds: bigint, is_complete: boolean, };
+total_wait_seconds: bigint, 
+/**
+ * Per-user energy consumed, in joules. Empty when energy is not
+ * collected, or when reading data from older instances.
+ */
+user_energy: { [key in string]?: bigint }, is_complete: boolean, };
@@ -0,0 +1,22 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Status of an external service that an agent depends on
+ */
+export type DependencyStatus = { 
+/**
+ * Name of the dependency, e.g. "slurmrestd:https://slurm:6820"
+ */
+name: string, 
+/**
+ * Whether the dependency passed its last check
+ */
+healthy: boolean, 
+/**
+ * Details of the last check (e.g. why it failed)
+ */
+detail: string, 
+/**
+ * Time when the dependency was last checked
+ */
+last_checked: string, };
@@ -0,0 +1,42 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Job and notification counts for one interval of the diagnostics history
+ */
+export type DiagnosticsCounts = { 
+/**
+ * Start of the interval
+ */
+start: string, 
+/**
+ * End of the interval
+ */
+end: string, 
+/**
+ * Number of jobs that completed successfully
+ */
+completed: number, 
+/**
+ * Number of jobs that failed
+ */
+failed: number, 
+/**
+ * Number of jobs that expired
+ */
+expired: number, 
+/**
+ * Number of slow jobs (>10s duration)
+ */
+slow: number, 
+/**
+ * Number of notifications received
+ */
+notifications_received: number, 
+/**
+ * Number of notifications sent
+ */
+notifications_sent: number, 
+/**
+ * Number of notifications that failed to deliver
+ */
+notifications_failed: number, };
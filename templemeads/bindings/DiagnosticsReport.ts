@@ -1,10 +1,12 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { DiagnosticsCounts } from "./DiagnosticsCounts";
 import type { ExpiredJobEntry } from "./ExpiredJobEntry";
 import type { FailedJobEntry } from "./FailedJobEntry";
 import type { LogEntry } from "./LogEntry";
 import type { NotificationStatistics } from "./NotificationStatistics";
 import type { RunningJobEntry } from "./RunningJobEntry";
 import type { SlowJobEntry } from "./SlowJobEntry";
+import type { WatchdogRestart } from "./WatchdogRestart";
 
 /**
  * Diagnostics report containing troubleshooting information
@@ -45,4 +47,13 @@ recent_logs: Array<LogEntry>,
 /**
  * Notification send/receive/failure totals
  */
-notification_statistics: NotificationStatistics, };
+notification_statistics: NotificationStatistics, 
+/**
+ * Job and notification counts in each interval of the retained
+ * history (oldest first, ending with the current interval)
+ */
+history: Array<DiagnosticsCounts>, 
+/**
+ * Soft restarts performed automatically by the watchdog (most recent first)
+ */
+watchdog_restarts: Array<WatchdogRestart>, };
@@ -1,4 +1,7 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { BreakerStatus } from "./BreakerStatus";
+import type { DependencyStatus } from "./DependencyStatus";
+import type { PeerAvailability } from "./PeerAvailability";
 import type { Type } from "./Type";
 
 /**
@@ -61,6 +64,10 @@ queued_jobs: number,
  * Number of active worker tasks processing messages
  */
 worker_count: number, 
+/**
+ * Number of replayed messages rejected since the agent started
+ */
+replays_rejected: bigint, 
 /**
  * Memory usage of this agent process in bytes
  */
@@ -133,10 +140,33 @@ engine: string,
  * Engine version
  */
 version: string, 
+/**
+ * Crypto mode used by this agent ("standard" or "fips")
+ */
+crypto_mode: string, 
 /**
  * Time when this health response was received/cached
  */
 last_updated: string, 
+/**
+ * Active warnings raised by this agent (e.g. a backed-up bridge board)
+ */
+warnings: Array<string>, 
+/**
+ * Status of the external services that this agent depends on
+ * (e.g. slurmrestd, FreeIPA, volume mounts or the signal URL)
+ */
+dependencies: Array<DependencyStatus>, 
+/**
+ * State of the circuit breakers protecting calls to third-party
+ * services (e.g. slurmrestd, FreeIPA or the signal URL)
+ */
+breakers: Array<BreakerStatus>, 
+/**
+ * Connection availability of each of this agent's peers over
+ * rolling 24 hour, 7 day and 30 day windows
+ */
+availability: Array<PeerAvailability>, 
 /**
  * Nested health information from downstream peers
  */
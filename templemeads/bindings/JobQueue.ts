@@ -0,0 +1,19 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { QueuedJob } from "./QueuedJob";
+
+/**
+ * The queued and running jobs of a single project
+ */
+export type JobQueue = { 
+/**
+ * The project that the jobs belong to
+ */
+project: string, 
+/**
+ * When this snapshot of the queue was taken
+ */
+generated_at: string, 
+/**
+ * The jobs, in the order returned by the scheduler
+ */
+jobs: Array<QueuedJob>, };
@@ -0,0 +1,42 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ *
+ * The connection availability of a peer of this agent over
+ * rolling 24 hour, 7 day and 30 day windows
+ *
+ */
+export type PeerAvailability = { 
+/**
+ * Name and zone of the peer, e.g. "cluster@brics"
+ */
+peer: string, 
+/**
+ * Whether the peer is currently connected
+ */
+connected: boolean, 
+/**
+ * When the peer first connected since this agent started.
+ * Availability is only measured from this point
+ */
+tracked_since: string, 
+/**
+ * When the peer last connected, if it is connected
+ */
+connected_since: string | null, 
+/**
+ * Number of disconnections in the last 30 days
+ */
+disconnections_30d: number, 
+/**
+ * Percentage of the last 24 hours that the peer was connected
+ */
+availability_24h: number, 
+/**
+ * Percentage of the last 7 days that the peer was connected
+ */
+availability_7d: number, 
+/**
+ * Percentage of the last 30 days that the peer was connected
+ */
+availability_30d: number, };
@@ -0,0 +1,22 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Whether or not an identity is protected in an account agent, and why
+ */
+export type ProtectionStatus = { 
+/**
+ * The identity (user or project identifier) that was checked
+ */
+identity: string, 
+/**
+ * The local name of the account in the account agent, if it exists
+ */
+local_name: string | null, 
+/**
+ * Whether or not the identity is protected
+ */
+protected: boolean, 
+/**
+ * Why the identity is protected
+ */
+reason: string | null, };
@@ -0,0 +1,53 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A single job in the scheduler's queue
+ */
+export type QueuedJob = { 
+/**
+ * The scheduler's id for the job (e.g. "1234" or "1234_7" for
+ * an element of an array job)
+ */
+job_id: string, 
+/**
+ * The local username of the user who submitted the job
+ */
+user: string, 
+/**
+ * The partition the job was submitted to
+ */
+partition: string, 
+/**
+ * The state of the job, e.g. "PENDING" or "RUNNING"
+ */
+state: string, 
+/**
+ * The reason the job is in this state, e.g. "Priority" or
+ * "AssocGrpGRESMinutes". This is "None" for running jobs
+ */
+reason: string, 
+/**
+ * The number of nodes requested (or allocated, once running)
+ */
+nodes: bigint, 
+/**
+ * The number of CPUs requested (or allocated, once running)
+ */
+cpus: bigint, 
+/**
+ * The number of GPUs requested (or allocated, once running)
+ */
+gpus: bigint, 
+/**
+ * When the job was submitted
+ */
+submit_time: string, 
+/**
+ * When the job started, or the scheduler's estimate of when
+ * a pending job will start (if it has one)
+ */
+start_time: string | null, 
+/**
+ * How long the job has waited (or did wait) to start, in seconds
+ */
+wait_seconds: bigint, };
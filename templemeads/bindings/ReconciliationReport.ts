@@ -0,0 +1,38 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * The result of reconciling a single project against the scheduler
+ */
+export type ReconciliationReport = { 
+/**
+ * The project that was reconciled
+ */
+project: string, 
+/**
+ * When the project was reconciled
+ */
+generated_at: string, 
+/**
+ * Whether the project's account was missing from the scheduler
+ */
+missing_account: boolean, 
+/**
+ * The local usernames of mapped users who have no association
+ * with the project's account
+ */
+missing_users: Array<string>, 
+/**
+ * The local usernames of users who are associated with the
+ * project's account, but who are not mapped to the project.
+ * These are only reported, and are never removed
+ */
+unexpected_users: Array<string>, 
+/**
+ * The local usernames of mapped users whose home directory in the
+ * account agent differs from the one expected by the filesystem
+ */
+wrong_homedirs: Array<string>, 
+/**
+ * Whether missing accounts and associations were repaired
+ */
+repaired: boolean, };
@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type Type = "Portal" | "Provider" | "Platform" | "Instance" | "Bridge" | "Account" | "Filesystem" | "Scheduler" | "Virtual";
+export type Type = "Portal" | "Provider" | "Platform" | "Instance" | "Bridge" | "Account" | "Filesystem" | "Scheduler" | "Monitor" | "Virtual";
@@ -0,0 +1,22 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Details about a user that are held by a portal, and which are
+ * synchronised to the user's account. As for AwardDetails, all data
+ * is held as "option", so that only the fields that are set are
+ * updated.
+ *
+ */
+export type UserDetails = { 
+/**
+ * The email address of the user
+ */
+email: string | null, 
+/**
+ * The name of the user, as it should be displayed
+ */
+display_name: string | null, 
+/**
+ * The user's preferred login shell (e.g. "/bin/bash")
+ */
+shell: string | null, };
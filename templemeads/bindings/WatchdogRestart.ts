@@ -0,0 +1,14 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Record of a soft restart performed automatically by the watchdog
+ */
+export type WatchdogRestart = { 
+/**
+ * When the restart was performed
+ */
+restarted_at: string, 
+/**
+ * Why the watchdog decided that the agent was wedged
+ */
+reason: string, };
@@ -0,0 +1,511 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! The local admin socket of a running agent, which `op-admin` uses
+//! to list peers, show boards, tail job events, trigger reconciliation
+//! and restart the agent, without going through the Python API
+
+use crate::agent::{self, Type as AgentType};
+use crate::audit;
+use crate::board::Board;
+use crate::diagnostics::{self, BoardDump, DiagnosticsReport};
+use crate::error::Error;
+use crate::handler;
+use crate::handover;
+use crate::health::{self, HealthInfo, PeerAvailability};
+use crate::job::{Envelope, Job};
+use crate::keyrotation;
+use crate::reconcile::ReconciliationReport;
+use crate::restart;
+use crate::systemd;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast;
+
+/// The name that jobs run from the admin socket are sent by
+pub const ADMIN_SENDER: &str = "op-admin";
+
+/// The zone that jobs run from the admin socket are sent from
+const ADMIN_ZONE: &str = "admin";
+
+/// The number of job events buffered for slow `events` clients
+const EVENT_BUFFER: usize = 256;
+
+///
+/// Return the default path of the admin socket for the agent that
+/// uses the passed config file. This is the config file with its
+/// extension changed to `.sock`
+///
+pub fn default_socket(config_file: &Path) -> PathBuf {
+    config_file.with_extension("sock")
+}
+
+///
+/// A request sent to the admin socket. Each connection sends a single
+/// request as a line of JSON
+///
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum AdminRequest {
+    /// List the peers of the agent and whether they are connected
+    Peers,
+    /// Collect the health of the agent (and the agents below it)
+    Health,
+    /// Collect the diagnostics report of the agent, or of the agent
+    /// at the dot-separated destination path
+    Diagnostics { destination: String },
+    /// Dump the boards of the agent, or of the agent at the
+    /// dot-separated destination path
+    Board { destination: String },
+    /// Stream an event for every job the agent runs, until the
+    /// client disconnects
+    Events,
+    /// Reconcile a project on the agent, optionally repairing any drift
+    Reconcile { project: String, repair: bool },
+    /// Restart the agent - "soft", "reload" or "hard"
+    Restart { restart_type: String },
+    /// Rotate the keys used to connect to a peer
+    RotateKey { peer: String, zone: Option<String> },
+    /// Drain, and hand over the boards to a new instance of the agent
+    /// that is taking over, then exit
+    Handover,
+}
+
+///
+/// A response from the admin socket. Each response is written as a
+/// line of JSON. The `Events` request returns one `Event` response
+/// per job event
+///
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AdminResponse {
+    Peers { peers: Vec<PeerStatus> },
+    Health { health: Box<HealthInfo> },
+    Diagnostics { report: Box<DiagnosticsReport> },
+    Board { dump: Box<BoardDump> },
+    Event { event: Box<JobEvent> },
+    Reconciled { report: Box<ReconciliationReport> },
+    Handover { boards: Vec<Board> },
+    Done { message: String },
+    Error { error: String },
+}
+
+/// The status of a single peer of the agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerStatus {
+    /// Name and zone of the peer, e.g. "cluster@brics"
+    pub peer: String,
+    /// The agent type that the peer registered as, if it has registered
+    pub agent_type: Option<AgentType>,
+    /// Connection history of the peer, if it has ever connected
+    pub availability: Option<PeerAvailability>,
+}
+
+impl std::fmt::Display for PeerStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let agent_type = match &self.agent_type {
+            Some(agent_type) => agent_type.to_string(),
+            None => "unregistered".to_string(),
+        };
+
+        match &self.availability {
+            Some(availability) => write!(
+                f,
+                "{} ({}) {} - 24h {:.2}%, 7d {:.2}%, 30d {:.2}%, {} disconnection{}",
+                self.peer,
+                agent_type,
+                match availability.connected {
+                    true => "up",
+                    false => "DOWN",
+                },
+                availability.availability_24h,
+                availability.availability_7d,
+                availability.availability_30d,
+                availability.disconnections_30d,
+                match availability.disconnections_30d {
+                    1 => "",
+                    _ => "s",
+                }
+            ),
+            None => write!(f, "{} ({}) never connected", self.peer, agent_type),
+        }
+    }
+}
+
+/// An event in the life of a job run by the agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobEvent {
+    /// When the event happened
+    pub timestamp: DateTime<Utc>,
+    /// What happened - "started", "completed", "failed" or "expired"
+    pub event: String,
+    /// The job, as it was when the event happened
+    pub job: Job,
+}
+
+impl std::fmt::Display for JobEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {:<9} {} {} {}",
+            self.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            self.event,
+            self.job.id(),
+            self.job.destination(),
+            self.job.instruction()
+        )?;
+
+        if let Some(error) = self.job.error_message() {
+            write!(f, " - {}", error)?;
+        }
+
+        Ok(())
+    }
+}
+
+static EVENTS: Lazy<broadcast::Sender<JobEvent>> = Lazy::new(|| broadcast::channel(EVENT_BUFFER).0);
+
+///
+/// Publish a job event to any admin clients that are tailing events.
+/// This does nothing if no-one is listening
+///
+pub(crate) fn publish(event: &str, job: &Job) {
+    if EVENTS.receiver_count() == 0 {
+        return;
+    }
+
+    let _ = EVENTS.send(JobEvent {
+        timestamp: Utc::now(),
+        event: event.to_owned(),
+        job: job.clone(),
+    });
+}
+
+async fn peer_statuses() -> Vec<PeerStatus> {
+    let mut peers: BTreeMap<String, PeerStatus> = BTreeMap::new();
+
+    for peer in agent::real_peers().await {
+        peers.insert(
+            peer.to_string(),
+            PeerStatus {
+                peer: peer.to_string(),
+                agent_type: agent::agent_type(&peer).await,
+                availability: None,
+            },
+        );
+    }
+
+    // peers that have disconnected are no longer registered, but
+    // still have a connection history
+    for availability in health::get_availability() {
+        let peer = availability.peer.clone();
+
+        peers
+            .entry(peer.clone())
+            .or_insert_with(|| PeerStatus {
+                peer,
+                agent_type: None,
+                availability: None,
+            })
+            .availability = Some(availability);
+    }
+
+    peers.into_values().collect()
+}
+
+async fn reconcile(project: &str, repair: bool) -> Result<ReconciliationReport, Error> {
+    let me = agent::name().await;
+
+    let job = Job::parse(
+        &format!(
+            "{}.{} reconcile {}{}",
+            ADMIN_SENDER,
+            me,
+            project,
+            match repair {
+                true => " repair",
+                false => "",
+            }
+        ),
+        false,
+    )?
+    .pending()?;
+
+    tracing::warn!("Admin socket requested: {}", job.instruction());
+
+    let job = handler::invoke_runner(Envelope::new(&me, ADMIN_SENDER, ADMIN_ZONE, &job)).await?;
+
+    if let Err(e) = audit::record(ADMIN_SENDER, ADMIN_ZONE, &job).await {
+        tracing::error!("Could not record job {} in the audit log: {}", job.id(), e);
+    }
+
+    if let Some(error) = job.error_message() {
+        return Err(Error::Call(error));
+    }
+
+    job.result::<ReconciliationReport>()?.ok_or_else(|| {
+        Error::Call(format!(
+            "No reconciliation report was returned for {}",
+            project
+        ))
+    })
+}
+
+async fn handle(request: AdminRequest) -> Result<AdminResponse, Error> {
+    match request {
+        AdminRequest::Peers => Ok(AdminResponse::Peers {
+            peers: peer_statuses().await,
+        }),
+        AdminRequest::Health => Ok(AdminResponse::Health {
+            health: Box::new(health::collect_health(ADMIN_SENDER, Vec::new()).await?),
+        }),
+        AdminRequest::Diagnostics { destination } => Ok(AdminResponse::Diagnostics {
+            report: Box::new(diagnostics::collect_diagnostics(&destination).await?),
+        }),
+        AdminRequest::Board { destination } => Ok(AdminResponse::Board {
+            dump: Box::new(diagnostics::collect_board_dump(&destination).await?),
+        }),
+        AdminRequest::Reconcile { project, repair } => Ok(AdminResponse::Reconciled {
+            report: Box::new(reconcile(&project, repair).await?),
+        }),
+        AdminRequest::Restart { restart_type } => {
+            tracing::warn!("Admin socket requested a {} restart", restart_type);
+            Ok(AdminResponse::Done {
+                message: restart::handle_restart_request(ADMIN_SENDER, &restart_type, "").await?,
+            })
+        }
+        AdminRequest::RotateKey { peer, zone } => Ok(AdminResponse::Done {
+            message: keyrotation::rotate(&peer, &zone).await?,
+        }),
+        AdminRequest::Events => Err(Error::Bug(
+            "Events requests are streamed, not handled".to_owned(),
+        )),
+        AdminRequest::Handover => Ok(AdminResponse::Handover {
+            boards: handover::hand_over().await?,
+        }),
+    }
+}
+
+async fn write_response(stream: &mut UnixStream, response: &AdminResponse) -> Result<(), Error> {
+    let mut line = serde_json::to_string(response)?;
+    line.push('\n');
+    stream
+        .write_all(line.as_bytes())
+        .await
+        .context("Could not write to the admin socket")?;
+    Ok(())
+}
+
+async fn stream_events(stream: &mut UnixStream) -> Result<(), Error> {
+    let mut events = EVENTS.subscribe();
+
+    loop {
+        match events.recv().await {
+            Ok(event) => {
+                write_response(
+                    stream,
+                    &AdminResponse::Event {
+                        event: Box::new(event),
+                    },
+                )
+                .await?
+            }
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                write_response(
+                    stream,
+                    &AdminResponse::Error {
+                        error: format!("Missed {} job events", missed),
+                    },
+                )
+                .await?
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+async fn serve_connection(stream: UnixStream) -> Result<(), Error> {
+    let mut stream = BufReader::new(stream);
+    let mut line = String::new();
+
+    stream
+        .read_line(&mut line)
+        .await
+        .context("Could not read from the admin socket")?;
+
+    let mut stream = stream.into_inner();
+
+    let request: AdminRequest = match serde_json::from_str(line.trim()) {
+        Ok(request) => request,
+        Err(e) => {
+            return write_response(
+                &mut stream,
+                &AdminResponse::Error {
+                    error: format!("Could not parse admin request: {}", e),
+                },
+            )
+            .await;
+        }
+    };
+
+    tracing::debug!("Admin request: {:?}", request);
+
+    if request == AdminRequest::Events {
+        return stream_events(&mut stream).await;
+    }
+
+    let response = match handle(request).await {
+        Ok(response) => response,
+        Err(e) => AdminResponse::Error {
+            error: e.to_string(),
+        },
+    };
+
+    write_response(&mut stream, &response).await?;
+
+    // the new instance takes over once this instance has handed over
+    if matches!(response, AdminResponse::Handover { .. }) {
+        tracing::warn!("Handed over to the new instance - exiting");
+        systemd::notify("STOPPING=1");
+        std::process::exit(0);
+    }
+
+    Ok(())
+}
+
+///
+/// Send a single `request` to the agent listening on the admin socket
+/// at `socket`, returning its response
+///
+pub(crate) async fn send_request(
+    socket: &Path,
+    request: &AdminRequest,
+) -> Result<AdminResponse, Error> {
+    let mut stream = UnixStream::connect(socket)
+        .await
+        .with_context(|| format!("Could not connect to admin socket {}", socket.display()))?;
+
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    stream
+        .write_all(line.as_bytes())
+        .await
+        .context("Could not write to the admin socket")?;
+
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .await
+        .context("Could not read from the admin socket")?;
+
+    if line.trim().is_empty() {
+        return Err(Error::Unavailable(
+            "The agent closed the admin socket without responding".to_owned(),
+        ));
+    }
+
+    Ok(serde_json::from_str(line.trim())?)
+}
+
+///
+/// Listen on the admin socket at `socket`, serving requests until the
+/// agent exits. The socket can only be used by the user running the
+/// agent. An error is returned if another agent is already listening
+/// on this socket.
+///
+pub(crate) async fn serve(socket: &Path) -> Result<(), Error> {
+    if socket.exists() {
+        if UnixStream::connect(socket).await.is_ok() {
+            return Err(Error::Misconfigured(format!(
+                "Another agent is already listening on the admin socket {}",
+                socket.display()
+            )));
+        }
+
+        // left over from an agent that did not exit cleanly
+        std::fs::remove_file(socket)
+            .with_context(|| format!("Could not remove stale admin socket {}", socket.display()))?;
+    }
+
+    let listener = UnixListener::bind(socket)
+        .with_context(|| format!("Could not listen on admin socket {}", socket.display()))?;
+
+    std::fs::set_permissions(socket, std::fs::Permissions::from_mode(0o600)).with_context(
+        || {
+            format!(
+                "Could not set permissions of admin socket {}",
+                socket.display()
+            )
+        },
+    )?;
+
+    tracing::info!("Listening for op-admin on {}", socket.display());
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = serve_connection(stream).await {
+                            tracing::warn!("Admin socket connection failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    tracing::error!("Could not accept admin socket connection: {}", e);
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admin_request_round_trip() {
+        let requests = vec![
+            AdminRequest::Peers,
+            AdminRequest::Board {
+                destination: "provider.cluster".to_owned(),
+            },
+            AdminRequest::Reconcile {
+                project: "project.portal".to_owned(),
+                repair: true,
+            },
+            AdminRequest::Restart {
+                restart_type: "soft".to_owned(),
+            },
+            AdminRequest::RotateKey {
+                peer: "cluster".to_owned(),
+                zone: Some("default".to_owned()),
+            },
+            AdminRequest::Handover,
+        ];
+
+        for request in requests {
+            #[allow(clippy::unwrap_used)]
+            let line = serde_json::to_string(&request).unwrap();
+            assert!(!line.contains('\n'));
+
+            #[allow(clippy::unwrap_used)]
+            let parsed: AdminRequest = serde_json::from_str(&line).unwrap();
+            assert_eq!(parsed, request);
+        }
+
+        assert_eq!(
+            default_socket(Path::new("/etc/openportal/slurm-config.toml")),
+            PathBuf::from("/etc/openportal/slurm-config.sock")
+        );
+    }
+}
@@ -21,6 +21,7 @@ pub enum Type {
     Account,
     Filesystem,
     Scheduler,
+    Monitor,
     Virtual,
 }
 
@@ -35,6 +36,7 @@ impl std::fmt::Display for Type {
             Type::Account => write!(f, "account"),
             Type::Filesystem => write!(f, "filesystem"),
             Type::Scheduler => write!(f, "scheduler"),
+            Type::Monitor => write!(f, "monitor"),
             Type::Virtual => write!(f, "virtual"),
         }
     }
@@ -52,7 +54,9 @@ pub mod bridge {
 }
 
 pub mod custom {
+    pub use crate::agent_core::process_args;
     pub use crate::agent_core::Config;
+    pub use crate::agent_core::Defaults;
     pub use crate::custom::run;
 }
 
@@ -70,6 +74,13 @@ pub mod instance {
     pub use crate::instance::run;
 }
 
+pub mod monitor {
+    pub use crate::agent_core::process_args;
+    pub use crate::agent_core::Config;
+    pub use crate::agent_core::Defaults;
+    pub use crate::monitor::run;
+}
+
 pub mod platform {
     pub use crate::agent_core::process_args;
     pub use crate::agent_core::Config;
@@ -400,11 +411,13 @@ pub async fn all_peers() -> Vec<Peer> {
 }
 
 ///
-/// Return all real, non-virtual registered peers
+/// Return all real, non-virtual registered peers (this excludes
+/// any mock agents)
 ///
 pub async fn real_peers() -> Vec<Peer> {
-    let registrar = REGISTRAR.read().await;
-    registrar
+    let peers: Vec<Peer> = REGISTRAR
+        .read()
+        .await
         .peers
         .iter()
         .filter_map(|(peer, agent_type)| {
@@ -414,7 +427,22 @@ pub async fn real_peers() -> Vec<Peer> {
                 None
             }
         })
-        .collect()
+        .collect();
+
+    #[cfg(feature = "mock")]
+    let peers = {
+        let mut real = Vec::with_capacity(peers.len());
+
+        for peer in peers {
+            if !crate::mock::is_mock(&peer).await {
+                real.push(peer);
+            }
+        }
+
+        real
+    };
+
+    peers
 }
 
 ///
@@ -621,7 +649,14 @@ pub async fn is_self(peer: &Peer) -> bool {
 /// To return only non-self virtual agents, use
 /// is_virtual(peer) && !is_self(peer)
 ///
+/// Mock agents are also virtual, as they are run in this process
+///
 pub async fn is_virtual(peer: &Peer) -> bool {
+    #[cfg(feature = "mock")]
+    if crate::mock::is_mock(peer).await {
+        return true;
+    }
+
     let registrar = REGISTRAR.read().await;
 
     match peer.name() {
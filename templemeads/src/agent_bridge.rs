@@ -2,10 +2,14 @@
 // SPDX-License-Identifier: MIT
 
 use crate::agent::Type as AgentType;
+use crate::agent_core::replay_store;
 use crate::bridge_server::{
     save as save_bridge_invite, spawn, Config as BridgeConfig, Defaults as BridgeDefaults,
     Invite as BridgeInvite,
 };
+use crate::bridgeallowlist;
+use crate::bridgetls::TlsConfig;
+use crate::config::load as load_with_env;
 use crate::error::Error;
 use crate::handler::{process_message, set_my_service_details};
 use crate::runnable::AsyncRunnable;
@@ -123,6 +127,10 @@ pub async fn process_args(defaults: &Defaults) -> Result<Option<Config>, Error>
     let args = Args::parse();
     let defaults = defaults.clone();
 
+    // choose the crypto mode before any keys are generated or loaded, so
+    // that a misconfigured mode fails straight away
+    tracing::info!("Crypto mode: {}", paddington::crypto_mode()?);
+
     let config_file = match args.config_file {
         Some(path) => path,
         None => defaults.service.config_file(),
@@ -141,7 +149,9 @@ pub async fn process_args(defaults: &Defaults) -> Result<Option<Config>, Error>
             healthcheck_port,
             proxy_header,
             signal_url,
+            standby_signal_url,
             notification_url,
+            board_file,
             force,
         }) => {
             let local_healthcheck_port;
@@ -176,10 +186,16 @@ pub async fn process_args(defaults: &Defaults) -> Result<Option<Config>, Error>
                     &signal_url
                         .clone()
                         .unwrap_or_else(|| defaults.bridge.signal_url()),
+                    standby_signal_url,
                     &notification_url
                         .clone()
                         .unwrap_or_else(|| defaults.bridge.notification_url()),
-                ),
+                    Some(
+                        board_file
+                            .clone()
+                            .unwrap_or_else(|| config_file.with_file_name("bridge-board.json")),
+                    ),
+                )?,
                 agent: AgentType::Bridge,
             };
 
@@ -332,7 +348,16 @@ pub async fn process_args(defaults: &Defaults) -> Result<Option<Config>, Error>
 
             return Ok(None);
         }
-        Some(Commands::Bridge { config, regenerate }) => {
+        Some(Commands::Bridge {
+            config,
+            regenerate,
+            tls_cert,
+            tls_key,
+            tls_client_ca,
+            no_tls,
+            allow_ip,
+            disallow_ip,
+        }) => {
             if let Some(py_config_file) = config {
                 let config = load_config::<Config>(&config_file)?;
                 let py_config = BridgeInvite::parse(&config.bridge.url, &config.bridge.key);
@@ -346,19 +371,90 @@ pub async fn process_args(defaults: &Defaults) -> Result<Option<Config>, Error>
 
             if *regenerate {
                 let mut config = load_config::<Config>(&config_file)?;
-                config.bridge.key = Key::generate();
+                config.bridge.key = Key::generate()?;
                 save_config(&config, &config_file)?;
                 tracing::info!("API key regenerated.");
                 return Ok(None);
             }
 
+            if !allow_ip.is_empty() || !disallow_ip.is_empty() {
+                let allow = bridgeallowlist::parse(allow_ip)?;
+                let disallow = bridgeallowlist::parse(disallow_ip)?;
+
+                let mut config = load_config::<Config>(&config_file)?;
+
+                for ip in allow {
+                    if !config.bridge.allowed_ips.contains(&ip) {
+                        config.bridge.allowed_ips.push(ip);
+                    }
+                }
+
+                config
+                    .bridge
+                    .allowed_ips
+                    .retain(|ip| !disallow.contains(ip));
+                save_config(&config, &config_file)?;
+
+                match config.bridge.allowed_ips.is_empty() {
+                    true => tracing::info!("Every address can call the bridge API."),
+                    false => tracing::info!(
+                        "Only these addresses can call the bridge API: {}",
+                        config
+                            .bridge
+                            .allowed_ips
+                            .iter()
+                            .map(|ip| ip.to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                }
+
+                return Ok(None);
+            }
+
+            if *no_tls {
+                let mut config = load_config::<Config>(&config_file)?;
+                config.bridge.tls = None;
+                save_config(&config, &config_file)?;
+                tracing::info!("The bridge API will be served over plain HTTP.");
+                return Ok(None);
+            }
+
+            if let (Some(tls_cert), Some(tls_key)) = (tls_cert, tls_key) {
+                let tls = TlsConfig::new(tls_cert, tls_key, tls_client_ca.as_deref());
+
+                // make sure that the certificates can be loaded now, rather
+                // than when the bridge is next run
+                tls.server_config()?;
+
+                let mut config = load_config::<Config>(&config_file)?;
+                config.bridge.tls = Some(tls.clone());
+                save_config(&config, &config_file)?;
+
+                match tls.requires_client_certificate() {
+                    true => tracing::info!(
+                        "The bridge API will be served over TLS, requiring client certificates."
+                    ),
+                    false => tracing::info!("The bridge API will be served over TLS."),
+                }
+
+                return Ok(None);
+            }
+
             let _ = Args::command().print_help();
 
             return Ok(None);
         }
         Some(Commands::Run {}) => {
-            let config = load_config::<Config>(&config_file)?;
+            let config = load_with_env::<Config>(&config_file)?;
             tracing::info!("Loaded config from {}", &config_file.display());
+
+            // the nonces of received messages are saved, so that messages
+            // cannot be replayed to the bridge, even after a restart
+            if let Some(file) = replay_store("", &config_file) {
+                paddington::set_replay_store(&file, paddington::DEFAULT_REPLAY_STORE_SIZE)?;
+            }
+
             return Ok(Some(config));
         }
         _ => {
@@ -520,6 +616,13 @@ enum Commands {
         )]
         signal_url: Option<String>,
 
+        #[arg(
+            long,
+            short = 'S',
+            help = "Standby URL to call to signal new jobs if the signal URL does not respond. Can be passed multiple times; URLs are tried in order"
+        )]
+        standby_signal_url: Vec<String>,
+
         #[arg(
             long,
             short = 'N',
@@ -527,6 +630,13 @@ enum Commands {
         )]
         notification_url: Option<String>,
 
+        #[arg(
+            long,
+            short = 'B',
+            help = "File in which to save the bridge board so that jobs waiting for the web portal survive a restart (defaults to bridge-board.json next to the config file)"
+        )]
+        board_file: Option<PathBuf>,
+
         #[arg(long, short = 'f', help = "Force reinitialisation")]
         force: bool,
     },
@@ -546,6 +656,46 @@ enum Commands {
             help = "Re-generate the API key used by bridge clients to connect to the service. Note you will need to generate a new configuration file for any Python clients."
         )]
         regenerate: bool,
+
+        #[arg(
+            long,
+            requires = "tls_key",
+            help = "PEM file containing the certificate (chain) with which to serve the bridge API over HTTPS"
+        )]
+        tls_cert: Option<PathBuf>,
+
+        #[arg(
+            long,
+            requires = "tls_cert",
+            help = "PEM file containing the private key of the TLS certificate"
+        )]
+        tls_key: Option<PathBuf>,
+
+        #[arg(
+            long,
+            requires = "tls_cert",
+            help = "PEM file containing the CA certificate(s) that clients must present a certificate signed by (mutual TLS)"
+        )]
+        tls_client_ca: Option<PathBuf>,
+
+        #[arg(
+            long,
+            conflicts_with = "tls_cert",
+            help = "Stop serving the bridge API over TLS"
+        )]
+        no_tls: bool,
+
+        #[arg(
+            long,
+            help = "IP address or CIDR range to allow to call the bridge API. Can be passed multiple times. If none are allowed, every address can call the API"
+        )]
+        allow_ip: Vec<String>,
+
+        #[arg(
+            long,
+            help = "IP address or CIDR range to remove from those allowed to call the bridge API. Can be passed multiple times"
+        )]
+        disallow_ip: Vec<String>,
     },
 
     /// Run the service
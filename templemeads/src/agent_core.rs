@@ -1,21 +1,35 @@
 // SPDX-FileCopyrightText: © 2024 Christopher Woods <Christopher.Woods@bristol.ac.uk>
 // SPDX-License-Identifier: MIT
 
+use crate::admin;
 use crate::agent::Type as AgentType;
+use crate::audit;
+use crate::chaos;
+use crate::circuitbreaker;
+use crate::config::{
+    apply_options, check_options, env_secrets, load as load_with_env, watch as watch_config_file,
+};
+use crate::configcheck::ConfigCheck;
 use crate::error::Error;
+use crate::exec;
+use crate::handover;
+use crate::health;
+use crate::secrets::{self, SecretStores};
 
 use anyhow::Context;
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use clap::{CommandFactory, Parser, Subcommand};
 use paddington::config::{
     load as load_config, save as save_config, Defaults as ServiceDefaults, ServiceConfig,
 };
 use paddington::invite::{load as load_invite, save as save_invite};
-use secrecy::SecretString;
+use paddington::Key;
+use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::IpAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 // Configuration
 
@@ -75,10 +89,32 @@ where
         }
     }
 
+    ///
+    /// Check that the secret with the passed key, if it is held in the
+    /// config file, can be decrypted
+    ///
+    pub fn check_secret(&self, key: &str, check: &mut ConfigCheck) {
+        if let Some(value) = self.extras.get(key) {
+            if let Err(e) = self.service.decrypt::<String>(value) {
+                check.error(key, &format!("Could not decrypt the secret: {}", e));
+            }
+        }
+    }
+
     pub fn secret(&self, key: &str) -> Option<SecretString> {
+        // secrets fetched from an external secret store take precedence,
+        // as these are refreshed if the secret is rotated
+        if let Some(secret) = secrets::get(key) {
+            return Some(secret);
+        }
+
         match self.extras.get(key) {
             Some(value) => match self.service.decrypt::<String>(value) {
-                Ok(secret) => Some(secret.into()),
+                Ok(secret) => {
+                    // make sure that the secret never appears in logs or errors
+                    paddington::redact::register_secret(&secret);
+                    Some(secret.into())
+                }
                 Err(e) => {
                     tracing::error!("Failed to decrypt secret for key '{}': {:?}", key, e);
                     None
@@ -107,6 +143,12 @@ where
     }
 }
 
+///
+/// Function that checks the options specific to an agent, recording
+/// every problem that it finds
+///
+pub type ConfigCheckFn<T> = fn(&Config<T>, &mut ConfigCheck);
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(bound(deserialize = "T: for<'de2> Deserialize<'de2>"))]
 pub struct Defaults<T = ()>
@@ -117,6 +159,9 @@ where
     pub agent: AgentType,
     pub agent_config: T,
     pub extras: HashMap<String, String>,
+
+    #[serde(skip)]
+    config_check: Option<ConfigCheckFn<T>>,
 }
 
 impl<T> Defaults<T>
@@ -147,9 +192,18 @@ where
             agent: agent.unwrap_or(AgentType::Portal),
             agent_config: T::default(),
             extras: HashMap::new(),
+            config_check: None,
         }
     }
 
+    ///
+    /// Set the function that checks the options specific to this
+    /// agent when the agent is run with `--check-config`
+    ///
+    pub fn set_config_check(&mut self, check: ConfigCheckFn<T>) {
+        self.config_check = Some(check);
+    }
+
     pub fn add_extra(&mut self, key: &str, value: &str) {
         self.extras.insert(key.to_string(), value.to_string());
     }
@@ -182,11 +236,20 @@ where
     let args = Args::parse();
     let defaults = defaults.clone();
 
+    // choose the crypto mode before any keys are generated or loaded, so
+    // that a misconfigured mode fails straight away
+    tracing::info!("Crypto mode: {}", paddington::crypto_mode()?);
+
     let config_file = match args.config_file {
         Some(path) => path,
         None => defaults.service.config_file(),
     };
 
+    if args.check_config {
+        check_config(&config_file, &defaults)?;
+        return Ok(None);
+    }
+
     // see if we need to initialise the config directory
     match &args.command {
         Some(Commands::Init {
@@ -257,6 +320,7 @@ where
             remove,
             zone,
             rotate,
+            expires,
         }) => {
             if *list {
                 let config = load_config::<Config<T>>(&config_file)?;
@@ -281,6 +345,9 @@ where
                     &ip.clone().unwrap_or_else(|| "".to_string()),
                     zone,
                 )?;
+                config
+                    .service
+                    .set_invitation_expiry(client, zone, expiry(expires)?)?;
 
                 save_config(&config, &config_file)?;
                 save_invite(
@@ -377,22 +444,88 @@ where
 
             return Ok(None);
         }
+        Some(Commands::Invitation {
+            list,
+            revoke,
+            regenerate,
+            zone,
+            expires,
+        }) => {
+            if *list {
+                let config = load_config::<Config<T>>(&config_file)?;
+                for client in config.service.clients() {
+                    println!("{}", client.invitation_status());
+                }
+                return Ok(None);
+            }
+
+            if let Some(client) = revoke {
+                let mut config = load_config::<Config<T>>(&config_file)?;
+                config.service.remove_client(client, zone)?;
+                save_config(&config, &config_file)?;
+                tracing::info!(
+                    "Invitation for '{}' revoked. Restart the agent to disconnect it.",
+                    client
+                );
+                return Ok(None);
+            }
+
+            if let Some(client) = regenerate {
+                let mut config = load_config::<Config<T>>(&config_file)?;
+                let invite =
+                    config
+                        .service
+                        .regenerate_invitation(client, zone, expiry(expires)?)?;
+
+                save_config(&config, &config_file)?;
+                save_invite(
+                    &invite,
+                    &PathBuf::from(format!("./invite_{}_{}.toml", invite.name(), invite.zone())),
+                )?;
+
+                tracing::info!("Invitation for '{}' regenerated.", client);
+                return Ok(None);
+            }
+
+            let _ = Args::command().print_help();
+
+            return Ok(None);
+        }
         Some(Commands::Encryption {
             simple,
             environment,
+            command,
+            seal,
+            unseal,
         }) => {
             let mut config = load_config::<Config<T>>(&config_file)?;
 
-            match environment {
-                Some(env) => {
+            // unseal first, so that the scheme can be switched to one
+            // that cannot be used for sealing
+            if *unseal {
+                config.service.set_sealed(false)?;
+                tracing::info!("Peer keys will be stored in plain text in the config file.");
+            }
+
+            match (environment, command) {
+                (Some(env), _) => {
                     config.service.set_environment_encryption(env)?;
                 }
-                None => {
+                (None, Some(command)) => {
+                    config.service.set_command_encryption(command)?;
+                }
+                (None, None) => {
                     if *simple {
                         config.service.set_simple_encryption()?;
                     }
                 }
             }
+
+            if *seal {
+                config.service.set_sealed(true)?;
+                tracing::info!("Peer keys will be encrypted in the config file.");
+            }
+
             save_config(&config, &config_file)?;
             return Ok(None);
         }
@@ -409,15 +542,192 @@ where
             save_config(&config, &config_file)?;
             return Ok(None);
         }
+        Some(Commands::RotateKey { peer, zone }) => {
+            let config = load_with_env::<Config<T>>(&config_file)?;
+
+            // the keys are rotated by the running agent, so that the
+            // new keys reach the peer over the existing connection
+            let Some(socket) = admin_socket(&config.option("admin-socket", ""), &config_file)
+            else {
+                return Err(Error::Misconfigured(
+                    "The admin socket is disabled, so keys cannot be rotated on the running \
+                     agent. Use 'client --rotate' or 'server --rotate' instead."
+                        .to_owned(),
+                ));
+            };
+
+            let request = admin::AdminRequest::RotateKey {
+                peer: peer.clone(),
+                zone: zone.clone(),
+            };
+
+            match admin::send_request(&socket, &request).await {
+                Ok(admin::AdminResponse::Done { message }) => tracing::info!("{}", message),
+                Ok(admin::AdminResponse::Error { error }) => return Err(Error::Call(error)),
+                Ok(response) => {
+                    return Err(Error::Bug(format!(
+                        "Unexpected response to key rotation: {:?}",
+                        response
+                    )))
+                }
+                Err(e) => {
+                    return Err(Error::Unavailable(format!(
+                        "Could not reach the running agent: {}. If it is not running, use \
+                         'client --rotate' or 'server --rotate' instead.",
+                        e
+                    )))
+                }
+            }
+
+            return Ok(None);
+        }
         Some(Commands::Run {
             one_shot_commands,
             repeat,
             sender,
             zone,
+            handover,
         }) => {
-            let mut config = load_config::<Config<T>>(&config_file)?;
+            let mut config = load_with_env::<Config<T>>(&config_file)?;
             tracing::info!("Loaded config from {}", &config_file.display());
 
+            // secrets set in the environment are held encrypted, like
+            // those set with the 'secret' command
+            for (key, value) in env_secrets(&config.extras) {
+                let value = config.service.encrypt(&value)?;
+                config.extras.insert(key, value);
+            }
+
+            // every external command run by the agent (including those
+            // that fetch secrets) is limited by the same policy
+            exec::set_policy(exec::Policy::from_options(
+                &config.option("command-timeout", ""),
+                &config.option("command-max-output", ""),
+                &config.option("command-env", ""),
+            )?);
+
+            // calls to third-party services fail fast once they are down
+            circuitbreaker::set_policy(circuitbreaker::Policy::from_options(
+                &config.option("circuit-breaker-threshold", ""),
+                &config.option("circuit-breaker-cooldown", ""),
+            )?);
+
+            // faults can be injected to test resilience (all off by default)
+            chaos::set_faults(chaos::Faults::from_options(&chaos_options(&config))?);
+
+            // mock agents (for integration testing) are run in-process
+            check_mock_agents(&config.option("mock-agents", ""))?;
+
+            #[cfg(feature = "mock")]
+            for mock in crate::mock::parse(&config.option("mock-agents", ""))? {
+                mock.register().await;
+            }
+
+            // secrets can also be fetched from external secret stores
+            secrets::load(
+                secrets::parse_sources(&config.option("secret-sources", ""))?,
+                SecretStores {
+                    vault_addr: match config.option("vault-addr", "").trim() {
+                        "" => std::env::var("VAULT_ADDR").ok(),
+                        addr => Some(addr.to_owned()),
+                    },
+                    vault_token: match std::env::var("VAULT_TOKEN") {
+                        Ok(token) => Some(token.into()),
+                        Err(_) => config.secret("vault-token"),
+                    },
+                    aws_command: config.option("aws-command", "aws"),
+                },
+                health::parse_duration(&config.option("secret-refresh-interval", "1h"))?,
+                matches!(
+                    config
+                        .option("secret-rotation-restart", "false")
+                        .trim()
+                        .to_lowercase()
+                        .as_str(),
+                    "true" | "yes" | "1"
+                ),
+            )
+            .await?;
+
+            // every agent (except the bridge) shares some options, such as
+            // the log level, alert rules and resource limits, which can be
+            // changed by reloading the config file on SIGHUP
+            apply_options(&config.extras).await?;
+            watch_config_file(&config_file).await?;
+
+            // messages sent outside the replay window are rejected, and the
+            // nonces of those within it are saved, so that messages cannot
+            // be replayed to the agent, even after a restart
+            paddington::set_replay_window(
+                config
+                    .option(
+                        "replay-window",
+                        &paddington::DEFAULT_REPLAY_WINDOW.to_string(),
+                    )
+                    .trim()
+                    .parse()
+                    .unwrap_or(paddington::DEFAULT_REPLAY_WINDOW),
+            )?;
+
+            match replay_store(&config.option("replay-store", ""), &config_file) {
+                Some(file) => paddington::set_replay_store(
+                    &file,
+                    config
+                        .option(
+                            "replay-store-size",
+                            &paddington::DEFAULT_REPLAY_STORE_SIZE.to_string(),
+                        )
+                        .trim()
+                        .parse()
+                        .unwrap_or(paddington::DEFAULT_REPLAY_STORE_SIZE),
+                )?,
+                None => tracing::warn!(
+                    "The replay store is disabled - replayed messages are only rejected \
+                     until the agent restarts."
+                ),
+            }
+
+            // a new instance can take over from the running instance over
+            // its admin socket, e.g. after an upgrade
+            if *handover {
+                let Some(socket) = admin_socket(&config.option("admin-socket", ""), &config_file)
+                else {
+                    return Err(Error::Misconfigured(
+                        "Cannot hand over, as the admin socket is disabled".to_owned(),
+                    ));
+                };
+
+                handover::take_over(&config.service, &socket).await?;
+            }
+
+            // operators can manage the running agent with op-admin over
+            // a local socket (one-shot runs leave it to the running agent)
+            if one_shot_commands.is_none() {
+                match admin_socket(&config.option("admin-socket", ""), &config_file) {
+                    Some(socket) => admin::serve(&socket).await?,
+                    None => tracing::info!("The admin socket is disabled."),
+                }
+            }
+
+            // instructions that change things can be recorded in a
+            // signed, hash-chained audit log
+            match config.option("audit-log", "").trim() {
+                "" => {}
+                path => {
+                    let Some(key) = config.secret("audit-key") else {
+                        return Err(Error::Misconfigured(
+                            "The 'audit-key' secret must be set to sign the audit log".to_owned(),
+                        ));
+                    };
+
+                    audit::open(
+                        &PathBuf::from(path),
+                        Key::from_password(key.expose_secret())?,
+                    )
+                    .await?;
+                }
+            }
+
             if let Some(one_shot_commands) = one_shot_commands {
                 let repeat = repeat.unwrap_or(1);
                 let mut one_shot_commands = one_shot_commands.clone();
@@ -441,12 +751,227 @@ where
     Ok(None)
 }
 
+///
+/// Check the whole of the passed config file without running the agent,
+/// printing every problem that is found against the field that it is
+/// in. An error is returned if there were any problems
+///
+fn check_config<T>(config_file: &Path, defaults: &Defaults<T>) -> Result<(), Error>
+where
+    T: Serialize + for<'de> Deserialize<'de> + Clone + std::fmt::Debug + Default,
+{
+    // a file that cannot be parsed stops the check here, with the
+    // line and field of the problem
+    let mut config = load_with_env::<Config<T>>(config_file)?;
+
+    let mut check = ConfigCheck::new();
+
+    check.check_url("service.url", &config.service.url());
+
+    if config.service.healthcheck_port() == Some(config.service.port()) {
+        check.error(
+            "service.healthcheck_port",
+            "The health check port must be different to the service port",
+        );
+    }
+
+    for server in config.service.servers() {
+        let field = format!("servers.{}", server.name());
+
+        match server.url().trim() {
+            "" => check.error(&field, "The server has no URL"),
+            url => check.check_url(&field, url),
+        }
+    }
+
+    // secrets set in the environment are encrypted, as they are when
+    // the agent is run
+    for (key, value) in env_secrets(&config.extras) {
+        if let Some(value) = check.check(&key, config.service.encrypt(&value)) {
+            config.extras.insert(key, value);
+        }
+    }
+
+    let sources = check
+        .check(
+            "secret-sources",
+            secrets::parse_sources(&config.option("secret-sources", "")),
+        )
+        .unwrap_or_default();
+
+    check.check(
+        "secret-refresh-interval",
+        health::parse_duration(&config.option("secret-refresh-interval", "1h")),
+    );
+    check.check_url("vault-addr", &config.option("vault-addr", ""));
+    config.check_secret("vault-token", &mut check);
+
+    if !config.option("audit-log", "").trim().is_empty() {
+        if !config.extras.contains_key("audit-key")
+            && !sources.iter().any(|(key, _)| key == "audit-key")
+        {
+            check.error(
+                "audit-key",
+                "The 'audit-key' secret must be set to sign the audit log",
+            );
+        }
+
+        config.check_secret("audit-key", &mut check);
+    }
+
+    check.check_number::<usize>("replay-store-size", &config.option("replay-store-size", ""));
+    check.check_number::<u64>("replay-window", &config.option("replay-window", ""));
+
+    check.check(
+        "command-timeout",
+        exec::Policy::from_options(
+            &config.option("command-timeout", ""),
+            &config.option("command-max-output", ""),
+            &config.option("command-env", ""),
+        ),
+    );
+
+    check.check(
+        "circuit-breaker-threshold",
+        circuitbreaker::Policy::from_options(
+            &config.option("circuit-breaker-threshold", ""),
+            &config.option("circuit-breaker-cooldown", ""),
+        ),
+    );
+
+    check.check(
+        "chaos-message-delay",
+        chaos::Faults::from_options(&chaos_options(&config)),
+    );
+
+    check.check(
+        "mock-agents",
+        check_mock_agents(&config.option("mock-agents", "")),
+    );
+
+    check_options(&config.extras, &mut check);
+
+    // the services that agents connect to are given by options
+    // named '<service>-url'
+    let mut urls: Vec<(&String, &String)> = config
+        .extras
+        .iter()
+        .filter(|(key, _)| key.ends_with("-url"))
+        .collect();
+    urls.sort_unstable();
+
+    for (key, url) in urls {
+        check.check_url(key, url);
+    }
+
+    if let Some(check_agent) = defaults.config_check {
+        check_agent(&config, &mut check);
+    }
+
+    for issue in check.issues() {
+        println!("{}", issue);
+    }
+
+    match check.is_empty() {
+        true => {
+            println!("No problems found in {}", config_file.display());
+            Ok(())
+        }
+        false => Err(Error::InvalidConfig(format!(
+            "Found {} problem(s) in {}",
+            check.issues().len(),
+            config_file.display()
+        ))),
+    }
+}
+
+///
+/// Return when an invitation expires, from a duration such as '7d'
+///
+fn expiry(expires: &Option<String>) -> Result<Option<DateTime<Utc>>, Error> {
+    match expires {
+        Some(expires) => {
+            let seconds = health::parse_duration(expires)?;
+
+            chrono::Duration::try_seconds(i64::try_from(seconds).unwrap_or(i64::MAX))
+                .and_then(|duration| Utc::now().checked_add_signed(duration))
+                .map(Some)
+                .ok_or_else(|| Error::Parse(format!("Invalid invitation expiry '{}'", expires)))
+        }
+        None => Ok(None),
+    }
+}
+
+///
+/// Return the path of the replay store from the value of the
+/// `replay-store` option, or None if the store is disabled. This
+/// defaults to the config file with a `.replay.json` extension
+///
+pub(crate) fn replay_store(option: &str, config_file: &Path) -> Option<PathBuf> {
+    match option.trim() {
+        "none" => None,
+        "" => Some(config_file.with_extension("replay.json")),
+        file => Some(PathBuf::from(file)),
+    }
+}
+
+///
+/// Return the values of the `chaos-*` fault injection options
+///
+fn chaos_options<T>(config: &Config<T>) -> Vec<(&'static str, String)>
+where
+    T: Serialize + for<'de> Deserialize<'de> + Clone + std::fmt::Debug + Default,
+{
+    chaos::OPTIONS
+        .iter()
+        .map(|option| (*option, config.option(option, "")))
+        .collect()
+}
+
+///
+/// Check the value of the `mock-agents` option. Mock agents can only be
+/// used if templemeads is built with the `mock` feature
+///
+#[cfg(feature = "mock")]
+fn check_mock_agents(option: &str) -> Result<(), Error> {
+    crate::mock::parse(option).map(|_| ())
+}
+
+#[cfg(not(feature = "mock"))]
+fn check_mock_agents(option: &str) -> Result<(), Error> {
+    match option.trim().is_empty() {
+        true => Ok(()),
+        false => Err(Error::Incompatible(
+            "Cannot use 'mock-agents' as this agent was not built with the 'mock' feature"
+                .to_owned(),
+        )),
+    }
+}
+
+///
+/// Return the path of the admin socket from the value of the
+/// `admin-socket` option, or None if the socket is disabled
+///
+fn admin_socket(option: &str, config_file: &Path) -> Option<PathBuf> {
+    match option.trim() {
+        "none" => None,
+        "" => Some(admin::default_socket(config_file)),
+        socket => Some(PathBuf::from(socket)),
+    }
+}
+
 #[derive(Parser)]
 #[command(version = version(), about, long_about = None)]
 struct Args {
     #[arg(long, short = 'c', help = "Path to the configuration file")]
     config_file: Option<std::path::PathBuf>,
 
+    #[arg(
+        long,
+        help = "Check the configuration file, reporting every problem found, without running the agent"
+    )]
+    check_config: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -488,6 +1013,43 @@ enum Commands {
             help = "Name of the client whose keys are being rotated"
         )]
         rotate: Option<String>,
+
+        #[arg(
+            long,
+            short = 'e',
+            help = "Expire the invitation if it is not used within this time (e.g. 7d)"
+        )]
+        expires: Option<String>,
+    },
+
+    /// Listing, revoking and regenerating the invitations given to clients
+    Invitation {
+        #[arg(long, short = 'l', help = "List the invitations given to all clients")]
+        list: bool,
+
+        #[arg(
+            long,
+            short = 'r',
+            help = "Name of a client whose invitation should be revoked"
+        )]
+        revoke: Option<String>,
+
+        #[arg(
+            long,
+            short = 'g',
+            help = "Name of a client whose invitation should be replaced by a new one"
+        )]
+        regenerate: Option<String>,
+
+        #[arg(long, short = 'z', help = "The communication zone of the client")]
+        zone: Option<String>,
+
+        #[arg(
+            long,
+            short = 'e',
+            help = "Expire the regenerated invitation if it is not used within this time (e.g. 7d)"
+        )]
+        expires: Option<String>,
     },
 
     /// Adding and removing servers
@@ -602,6 +1164,42 @@ enum Commands {
             help = "Use the value of the specified environment variable as the encryption password."
         )]
         environment: Option<String>,
+
+        #[arg(
+            long,
+            help = "Use the output of the specified shell command (e.g. a KMS decrypt call) as the encryption password."
+        )]
+        command: Option<String>,
+
+        #[arg(
+            long,
+            conflicts_with = "unseal",
+            help = "Encrypt the servers and clients (including their keys) in the config file."
+        )]
+        seal: bool,
+
+        #[arg(
+            long,
+            help = "Store the servers and clients (including their keys) in plain text in the config file."
+        )]
+        unseal: bool,
+    },
+
+    /// Rotate the keys used to connect to a peer, via the running agent
+    RotateKey {
+        #[arg(
+            long,
+            short = 'p',
+            help = "Name of the peer whose keys should be rotated"
+        )]
+        peer: String,
+
+        #[arg(
+            long,
+            short = 'z',
+            help = "The zone of the peer. Only needed if there are several peers with this name"
+        )]
+        zone: Option<String>,
     },
 
     /// Run the service
@@ -630,5 +1228,11 @@ enum Commands {
             help = "The zone to use for the one-shot command(s) (default: one-shot)."
         )]
         zone: Option<String>,
+        #[arg(
+            long,
+            conflicts_with = "one_shot_commands",
+            help = "Take over the listening port and boards from the running instance of this agent, e.g. after an upgrade."
+        )]
+        handover: bool,
     },
 }
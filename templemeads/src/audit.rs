@@ -0,0 +1,344 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Append-only audit log of the instructions that change things
+//!
+//! Every job with an instruction that needs at least the operator role
+//! (i.e. that creates, changes or removes something) is recorded once it
+//! has finished. Each entry holds the hash of the entry before it, and
+//! is signed with the agent's audit key, so that any entry that is
+//! changed, removed or reordered is detected when the log is verified.
+
+use crate::error::Error;
+use crate::grammar::DateRange;
+use crate::job::Job;
+use crate::role::Role;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use paddington::{digest, SecretKey, Signature};
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+/// The previous hash of the first entry of an audit log
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+///
+/// The details of an instruction that was run, as recorded in the
+/// audit log. This is the part of each entry that is hashed
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Position of the entry in the log, starting from 0
+    pub sequence: u64,
+    /// When the instruction finished
+    pub timestamp: DateTime<Utc>,
+    /// The peer that sent the instruction
+    pub sender: String,
+    /// The zone of the peer that sent the instruction
+    pub zone: String,
+    /// The ID of the job
+    pub job: Uuid,
+    /// The full instruction, including all of its arguments
+    pub instruction: String,
+    /// Whether the instruction completed or errored
+    pub succeeded: bool,
+    /// The hash of the result (or error message) of the job
+    pub result_hash: String,
+    /// The hash of the entry before this one
+    pub previous_hash: String,
+}
+
+///
+/// An entry of the audit log - the record, its hash, and the signature
+/// of the hash made with the agent's audit key
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    #[serde(flatten)]
+    pub record: AuditRecord,
+    pub hash: String,
+    pub signature: Signature,
+}
+
+impl AuditEntry {
+    fn new(record: AuditRecord, key: &SecretKey) -> Result<Self, Error> {
+        let hash = digest(&record)?;
+        let signature = key.expose_secret().sign(&hash)?;
+
+        Ok(Self {
+            record,
+            hash,
+            signature,
+        })
+    }
+}
+
+///
+/// Verify that the passed entries form an unbroken, correctly signed
+/// chain, starting from the first entry of the log
+///
+pub fn verify(entries: &[AuditEntry], key: &SecretKey) -> Result<(), Error> {
+    let mut previous_hash = GENESIS_HASH.to_owned();
+
+    for (sequence, entry) in entries.iter().enumerate() {
+        let problem = if entry.record.sequence != sequence as u64 {
+            Some("it is out of sequence")
+        } else if entry.record.previous_hash != previous_hash {
+            Some("it does not follow the entry before it")
+        } else if digest(&entry.record)? != entry.hash {
+            Some("its hash does not match its contents")
+        } else if key
+            .expose_secret()
+            .verify(&entry.hash, &entry.signature)
+            .is_err()
+        {
+            Some("its signature is not valid")
+        } else {
+            None
+        };
+
+        if let Some(problem) = problem {
+            return Err(Error::InvalidState(format!(
+                "The audit log failed verification at entry {}: {}",
+                sequence, problem
+            )));
+        }
+
+        previous_hash = entry.hash.clone();
+    }
+
+    Ok(())
+}
+
+struct AuditLog {
+    path: PathBuf,
+    key: SecretKey,
+    sequence: u64,
+    previous_hash: String,
+}
+
+/// The audit log of this agent, if it keeps one
+static AUDIT_LOG: Lazy<Mutex<Option<AuditLog>>> = Lazy::new(|| Mutex::new(None));
+
+fn read_entries(path: &Path) -> Result<Vec<AuditEntry>, Error> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read audit log {}", path.display()))?;
+
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(i, line)| {
+            serde_json::from_str(line).map_err(|e| {
+                Error::Parse(format!(
+                    "Could not parse entry {} of audit log {}: {}",
+                    i,
+                    path.display(),
+                    e
+                ))
+            })
+        })
+        .collect()
+}
+
+///
+/// Start recording to the audit log at `path`, signing entries with
+/// `key`. Any existing log is verified first, and new entries are
+/// appended to it. An error is returned if the existing log has been
+/// tampered with, or was signed with a different key
+///
+pub(crate) async fn open(path: &Path, key: SecretKey) -> Result<(), Error> {
+    let entries = read_entries(path)?;
+    verify(&entries, &key)?;
+
+    let (sequence, previous_hash) = match entries.last() {
+        Some(entry) => (entry.record.sequence + 1, entry.hash.clone()),
+        None => (0, GENESIS_HASH.to_owned()),
+    };
+
+    tracing::info!(
+        "Recording audit log to {} ({} existing entries)",
+        path.display(),
+        sequence
+    );
+
+    *AUDIT_LOG.lock().await = Some(AuditLog {
+        path: path.to_path_buf(),
+        key,
+        sequence,
+        previous_hash,
+    });
+
+    Ok(())
+}
+
+///
+/// Record the passed finished job, sent by `sender` in `zone`, in the
+/// audit log. Only jobs whose instruction needs at least the operator
+/// role are recorded, and nothing is recorded if there is no audit log
+///
+pub(crate) async fn record(sender: &str, zone: &str, job: &Job) -> Result<(), Error> {
+    if job.instruction().required_role() < Role::Operator {
+        return Ok(());
+    }
+
+    let mut log = AUDIT_LOG.lock().await;
+
+    let Some(log) = log.as_mut() else {
+        return Ok(());
+    };
+
+    let result = match job.error_message() {
+        Some(message) => message,
+        None => job.result_json()?,
+    };
+
+    let entry = AuditEntry::new(
+        AuditRecord {
+            sequence: log.sequence,
+            timestamp: Utc::now(),
+            sender: sender.to_owned(),
+            zone: zone.to_owned(),
+            job: job.id(),
+            instruction: job.instruction().to_string(),
+            succeeded: !job.is_error(),
+            result_hash: digest(&result)?,
+            previous_hash: log.previous_hash.clone(),
+        },
+        &log.key,
+    )?;
+
+    let mut line = serde_json::to_string(&entry)?;
+    line.push('\n');
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(&log.path)
+        .with_context(|| format!("Could not open audit log {}", log.path.display()))?;
+
+    file.write_all(line.as_bytes())
+        .and_then(|_| file.sync_data())
+        .with_context(|| format!("Could not write to audit log {}", log.path.display()))?;
+
+    log.sequence += 1;
+    log.previous_hash = entry.hash;
+
+    Ok(())
+}
+
+///
+/// Verify the audit log, and return the entries recorded within the
+/// passed date range, as lines of JSON. The entries before the range
+/// are not returned, but the first returned entry holds the hash of the
+/// entry before it, so that the export can be checked against a later one
+///
+pub(crate) async fn export(date_range: &DateRange) -> Result<String, Error> {
+    let log = AUDIT_LOG.lock().await;
+
+    let Some(log) = log.as_ref() else {
+        return Err(Error::Misconfigured(
+            "This agent does not keep an audit log".to_owned(),
+        ));
+    };
+
+    let entries = read_entries(&log.path)?;
+    verify(&entries, &log.key)?;
+
+    let start = date_range.start_time().and_utc();
+    let end = date_range.end_time().and_utc();
+
+    let mut export = String::new();
+
+    for entry in entries
+        .iter()
+        .filter(|entry| entry.record.timestamp >= start && entry.record.timestamp < end)
+    {
+        export.push_str(&serde_json::to_string(entry)?);
+        export.push('\n');
+    }
+
+    Ok(export)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use paddington::Key;
+
+    fn record(sequence: u64, previous_hash: &str) -> AuditRecord {
+        AuditRecord {
+            sequence,
+            timestamp: Utc::now(),
+            sender: "portal".to_owned(),
+            zone: "default".to_owned(),
+            job: Uuid::new_v4(),
+            instruction: "add_user user.project.portal".to_owned(),
+            succeeded: true,
+            result_hash: digest("null").unwrap_or_default(),
+            previous_hash: previous_hash.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_audit_chain() {
+        let key = Key::generate().unwrap_or_else(|e| {
+            unreachable!("Cannot generate key: {}", e);
+        });
+
+        let mut entries: Vec<AuditEntry> = Vec::new();
+
+        for sequence in 0..3 {
+            let previous_hash = match entries.last() {
+                Some(entry) => entry.hash.clone(),
+                None => GENESIS_HASH.to_owned(),
+            };
+
+            entries.push(
+                AuditEntry::new(record(sequence, &previous_hash), &key)
+                    .unwrap_or_else(|e| unreachable!("Cannot create entry: {}", e)),
+            );
+        }
+
+        assert!(verify(&entries, &key).is_ok());
+
+        // entries survive a round trip through JSON
+        let line = serde_json::to_string(&entries[1])
+            .unwrap_or_else(|e| unreachable!("Cannot serialise entry: {}", e));
+        let parsed: AuditEntry = serde_json::from_str(&line)
+            .unwrap_or_else(|e| unreachable!("Cannot parse entry: {}", e));
+        assert_eq!(parsed, entries[1]);
+
+        // a different key cannot verify the log
+        let other = Key::generate().unwrap_or_else(|e| {
+            unreachable!("Cannot generate key: {}", e);
+        });
+        assert!(verify(&entries, &other).is_err());
+
+        // changing an entry is detected
+        let mut changed = entries.clone();
+        changed[1].record.instruction = "remove_user user.project.portal".to_owned();
+        assert!(verify(&changed, &key).is_err());
+
+        // as is rehashing it without the key
+        changed[1].hash = digest(&changed[1].record).unwrap_or_default();
+        assert!(verify(&changed, &key).is_err());
+
+        // removing an entry is detected
+        let mut removed = entries.clone();
+        removed.remove(1);
+        assert!(verify(&removed, &key).is_err());
+    }
+}
@@ -0,0 +1,437 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Batches of users
+//!
+//! The `add_users`, `remove_users`, `add_local_users` and
+//! `remove_local_users` instructions act on a batch of users in a single
+//! project, so that onboarding (or offboarding) a whole project is one
+//! job per hop, rather than one job per user. The cluster agent handles
+//! the batch itself. Account, filesystem and scheduler agents that call
+//! `set_native` handle the batch in their runner (e.g. adding users
+//! concurrently, or with a single command), while the others run each
+//! user in turn through their runner as the equivalent single-user
+//! instruction, so that every agent supports batches. Either way, the
+//! batch completes with a `BatchResult` that holds the mapping of each
+//! user that succeeded and the error of each user that failed, so that
+//! only the failed users need to be sent again.
+
+use futures::stream::{self, StreamExt};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use ts_rs::TS;
+
+use crate::agent;
+use crate::agent::Type as AgentType;
+use crate::error::Error;
+use crate::grammar::{Instruction, NamedType, UserIdentifier, UserMapping};
+use crate::job::{Envelope, Job};
+use crate::runnable::AsyncRunnable;
+
+/// The lifetime of a job that is not a batch, in minutes
+const DEFAULT_LIFETIME: i64 = 2;
+
+/// The extra lifetime given to a batch job for each of its users,
+/// in seconds
+const LIFETIME_PER_USER: i64 = 5;
+
+///
+/// Return the number of users in the passed batch instruction (also
+/// when this is submitted to another agent), or None if this is not
+/// a batch instruction
+///
+pub fn batch_size(instruction: &Instruction) -> Option<usize> {
+    match instruction {
+        Instruction::AddUsers(users) | Instruction::RemoveUsers(users) => Some(users.len()),
+        Instruction::AddLocalUsers(users) | Instruction::RemoveLocalUsers(users) => {
+            Some(users.len())
+        }
+        Instruction::Submit(_, instruction) => batch_size(instruction),
+        _ => None,
+    }
+}
+
+///
+/// Return the lifetime of a new job that runs the passed instruction.
+/// Batch jobs are given longer, depending on the number of users
+///
+pub fn lifetime(instruction: &Instruction) -> chrono::Duration {
+    let lifetime = chrono::Duration::minutes(DEFAULT_LIFETIME);
+
+    match batch_size(instruction) {
+        Some(size) => lifetime + chrono::Duration::seconds(LIFETIME_PER_USER * size as i64),
+        None => lifetime,
+    }
+}
+
+///
+/// Return the single-user instructions that make up the passed batch
+/// instruction, or None if this is not a batch instruction
+///
+pub(crate) fn expand(instruction: &Instruction) -> Option<Vec<Instruction>> {
+    match instruction {
+        Instruction::AddUsers(users) => {
+            Some(users.iter().cloned().map(Instruction::AddUser).collect())
+        }
+        Instruction::RemoveUsers(users) => {
+            Some(users.iter().cloned().map(Instruction::RemoveUser).collect())
+        }
+        Instruction::AddLocalUsers(users) => Some(
+            users
+                .iter()
+                .cloned()
+                .map(Instruction::AddLocalUser)
+                .collect(),
+        ),
+        Instruction::RemoveLocalUsers(users) => Some(
+            users
+                .iter()
+                .cloned()
+                .map(Instruction::RemoveLocalUser)
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+impl NamedType for BatchResult {
+    fn type_name() -> &'static str {
+        "BatchResult"
+    }
+}
+
+/// A user in a batch for whom the instruction failed
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, TS)]
+#[ts(export)]
+pub struct BatchFailure {
+    /// The user who failed
+    #[ts(as = "String")]
+    pub user: UserIdentifier,
+    /// Why the instruction failed for this user
+    pub error: String,
+}
+
+impl std::fmt::Display for BatchFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.user, self.error)
+    }
+}
+
+/// The result of a batch instruction, for each of its users
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, TS)]
+#[ts(export)]
+pub struct BatchResult {
+    /// The mappings of the users for whom the instruction succeeded
+    #[ts(as = "Vec<String>")]
+    pub succeeded: Vec<UserMapping>,
+    /// The users for whom the instruction failed
+    pub failed: Vec<BatchFailure>,
+}
+
+impl std::fmt::Display for BatchResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} succeeded, {} failed",
+            self.succeeded.len(),
+            self.failed.len()
+        )?;
+
+        for failure in &self.failed {
+            write!(f, "\n  {}", failure)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl BatchResult {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///
+    /// Record that the instruction succeeded for the user with the
+    /// passed mapping
+    ///
+    pub fn add_success(&mut self, mapping: UserMapping) {
+        self.succeeded.push(mapping);
+    }
+
+    ///
+    /// Record that the instruction failed for the passed user
+    ///
+    pub fn add_failure(&mut self, user: &UserIdentifier, error: impl std::fmt::Display) {
+        self.failed.push(BatchFailure {
+            user: user.clone(),
+            error: error.to_string(),
+        });
+    }
+
+    ///
+    /// Return whether the instruction succeeded for every user
+    ///
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    ///
+    /// Return the users for whom the instruction failed
+    ///
+    pub fn failed_users(&self) -> Vec<UserIdentifier> {
+        self.failed.iter().map(|f| f.user.clone()).collect()
+    }
+}
+
+///
+/// The users in a batch instruction - user identifiers for account
+/// agents, and user mappings for filesystem and scheduler agents
+///
+pub trait BatchUser {
+    fn batch_user(&self) -> &UserIdentifier;
+}
+
+impl BatchUser for UserIdentifier {
+    fn batch_user(&self) -> &UserIdentifier {
+        self
+    }
+}
+
+impl BatchUser for UserMapping {
+    fn batch_user(&self) -> &UserIdentifier {
+        self.user()
+    }
+}
+
+///
+/// Run `f` for each of the passed users, at most `max_concurrent` at a
+/// time, returning the mapping of each user for whom it succeeded and
+/// the error of each user for whom it failed. The users are run within
+/// the current task, so anything recorded against the task (e.g. the
+/// changes of a dry run) is kept
+///
+pub async fn run_each<T, F, Fut>(users: Vec<T>, max_concurrent: usize, f: F) -> BatchResult
+where
+    T: BatchUser,
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = Result<UserMapping, Error>>,
+{
+    let results = stream::iter(users)
+        .map(|user| {
+            let identifier = user.batch_user().clone();
+            let result = f(user);
+            async move { (identifier, result.await) }
+        })
+        .buffered(max_concurrent.max(1))
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut batch = BatchResult::new();
+
+    for (user, result) in results {
+        match result {
+            Ok(mapping) => batch.add_success(mapping),
+            Err(e) => batch.add_failure(&user, e),
+        }
+    }
+
+    batch
+}
+
+static NATIVE: Lazy<AtomicBool> = Lazy::new(|| AtomicBool::new(false));
+
+///
+/// Set whether this agent's runner handles the batch instructions
+/// itself, completing them with a `BatchResult`. By default, each
+/// user of a batch is run in turn as the single-user instruction
+///
+pub fn set_native(native: bool) {
+    NATIVE.store(native, Ordering::SeqCst);
+}
+
+///
+/// Return whether this agent's runner handles the batch instructions
+/// itself
+///
+pub fn is_native() -> bool {
+    NATIVE.load(Ordering::SeqCst)
+}
+
+///
+/// Return the user acted on by the passed part of a batch
+///
+fn part_user(part: &Instruction) -> Result<UserIdentifier, Error> {
+    match part {
+        Instruction::AddUser(user) | Instruction::RemoveUser(user) => Ok(user.clone()),
+        Instruction::AddLocalUser(mapping) | Instruction::RemoveLocalUser(mapping) => {
+            Ok(mapping.user().clone())
+        }
+        _ => Err(Error::Bug(format!("{} is not part of a batch", part))),
+    }
+}
+
+///
+/// Return the mapping of the user acted on by the passed finished part
+/// of a batch. The account agent returns the mapping, while the local
+/// instructions already contain it
+///
+fn mapping(part: &Job) -> Result<UserMapping, Error> {
+    match part.instruction() {
+        Instruction::AddLocalUser(mapping) | Instruction::RemoveLocalUser(mapping) => Ok(mapping),
+        _ => part.result::<UserMapping>()?.ok_or_else(|| {
+            Error::Run(format!(
+                "No user mapping was returned by: {}",
+                part.instruction()
+            ))
+        }),
+    }
+}
+
+///
+/// Run the job in the passed envelope using the runner. If the job is a
+/// batch, the recipient is an account, filesystem or scheduler agent,
+/// and the runner does not handle batches itself (`native` is false),
+/// then each user is run in turn as its single-user instruction, and
+/// the job completes with the result for each user
+///
+pub(crate) async fn run(
+    runner: &AsyncRunnable,
+    envelope: Envelope,
+    native: bool,
+) -> Result<Job, Error> {
+    let job = envelope.job();
+
+    let parts = match expand(&job.instruction()) {
+        Some(parts) if !native => parts,
+        _ => return runner(envelope).await,
+    };
+
+    let recipient = envelope.recipient();
+
+    match agent::agent_type(&recipient).await {
+        Some(AgentType::Account) | Some(AgentType::Filesystem) | Some(AgentType::Scheduler) => {}
+        _ => return runner(envelope).await,
+    }
+
+    let sender = envelope.sender();
+    let total = parts.len();
+
+    tracing::info!(
+        "Running {} as a batch of {} users",
+        job.instruction().command(),
+        total
+    );
+
+    let mut batch = BatchResult::new();
+
+    for part in parts {
+        let user = part_user(&part)?;
+        let part = job.with_instruction(part);
+
+        let result = runner(Envelope::new(
+            recipient.name(),
+            sender.name(),
+            recipient.zone(),
+            &part,
+        ))
+        .await;
+
+        match result {
+            Ok(result) if !result.is_error() => match mapping(&result) {
+                Ok(mapping) => batch.add_success(mapping),
+                Err(e) => batch.add_failure(&user, e),
+            },
+            Ok(result) => batch.add_failure(&user, result.error_message().unwrap_or_default()),
+            Err(e) => batch.add_failure(&user, e),
+        }
+    }
+
+    if !batch.is_complete() {
+        tracing::error!(
+            "{} of {} users in the batch failed: {}",
+            batch.failed.len(),
+            total,
+            batch
+                .failed
+                .iter()
+                .map(|f| f.to_string())
+                .collect::<Vec<_>>()
+                .join("; ")
+        );
+    }
+
+    job.completed(batch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch() {
+        let instruction = Instruction::parse("add_users a.project.portal b.project.portal")
+            .unwrap_or_else(|e| unreachable!("Cannot parse instruction: {}", e));
+
+        assert_eq!(batch_size(&instruction), Some(2));
+        assert_eq!(lifetime(&instruction), chrono::Duration::seconds(130));
+
+        let parts = expand(&instruction)
+            .unwrap_or_else(|| unreachable!("Cannot expand a batch instruction"));
+        assert_eq!(
+            parts
+                .iter()
+                .map(|part| part.to_string())
+                .collect::<Vec<String>>(),
+            vec!["add_user a.project.portal", "add_user b.project.portal"]
+        );
+
+        let instruction = Instruction::parse(
+            "submit portal.cluster remove_local_users a.project.portal:a:project",
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot parse instruction: {}", e));
+
+        assert_eq!(batch_size(&instruction), Some(1));
+        assert!(expand(&instruction).is_none());
+
+        let instruction = Instruction::parse("add_user a.project.portal")
+            .unwrap_or_else(|e| unreachable!("Cannot parse instruction: {}", e));
+
+        assert_eq!(batch_size(&instruction), None);
+        assert_eq!(lifetime(&instruction), chrono::Duration::minutes(2));
+        assert!(expand(&instruction).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_run_each() {
+        let mappings = ["a.project.portal:a:project", "b.project.portal:b:project"]
+            .iter()
+            .map(|mapping| {
+                UserMapping::parse(mapping)
+                    .unwrap_or_else(|e| unreachable!("Cannot parse mapping: {}", e))
+            })
+            .collect::<Vec<UserMapping>>();
+
+        let result = run_each(mappings.clone(), 2, |mapping| async move {
+            match mapping.local_user() {
+                "a" => Err(Error::Failed("No space left on device".to_owned())),
+                _ => Ok(mapping),
+            }
+        })
+        .await;
+
+        assert!(!result.is_complete());
+        assert_eq!(result.succeeded, vec![mappings[1].clone()]);
+        assert_eq!(result.failed_users(), vec![mappings[0].user().clone()]);
+        assert_eq!(result.to_string().lines().count(), 2);
+
+        let json = serde_json::to_string(&result)
+            .unwrap_or_else(|e| unreachable!("Cannot serialise result: {}", e));
+        assert_eq!(
+            serde_json::from_str::<BatchResult>(&json)
+                .unwrap_or_else(|e| unreachable!("Cannot deserialise result: {}", e)),
+            result
+        );
+    }
+}
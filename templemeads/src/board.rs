@@ -7,7 +7,9 @@ use tokio::sync::oneshot;
 use uuid::Uuid;
 
 use crate::agent::Peer;
+use crate::chaos;
 use crate::command::Command as ControlCommand;
+use crate::destination::Position;
 use crate::error::Error;
 use crate::job::Job;
 
@@ -111,6 +113,34 @@ impl Board {
         }
     }
 
+    ///
+    /// Return the peer with which this board is shared
+    ///
+    pub fn peer(&self) -> &Peer {
+        &self.peer
+    }
+
+    ///
+    /// Return all of the jobs on this board, ordered from the
+    /// oldest to the newest
+    ///
+    pub fn jobs(&self) -> Vec<Job> {
+        let mut jobs: Vec<Job> = self.jobs.values().cloned().collect();
+        jobs.sort_by_key(|job| job.created());
+        jobs
+    }
+
+    ///
+    /// Return the jobs of all of the commands that are queued waiting
+    /// for the connection to the peer to re-open
+    ///
+    pub fn queued_jobs(&self) -> Vec<Job> {
+        self.queued_commands
+            .iter()
+            .filter_map(|command| command.job())
+            .collect()
+    }
+
     ///
     /// Return job statistics for this board
     ///
@@ -254,6 +284,10 @@ impl Board {
                 // the next step would re-queue the job if there was
                 // a problem, and job changes are idempotent (i.e.
                 // it doesn't matter if this happens twice)
+                if job.is_pending() {
+                    job = chaos::expire(job);
+                }
+
                 self.jobs.insert(job.id(), job.clone());
                 state = JobAddState::Added;
             }
@@ -647,6 +681,39 @@ impl Board {
         // Return the errored jobs so the caller can send updates back upstream
         errored_jobs
     }
+
+    ///
+    /// Remove the unfinished jobs that this agent (called `my_name`)
+    /// received from the peer, returning them. This is used when taking
+    /// over the board from a previous instance of this agent, which can
+    /// no longer run them. The peer puts them again when it next syncs
+    /// its board, so that they are run by this instance. Finished jobs,
+    /// jobs this agent sent to the peer, and queued commands are kept.
+    ///
+    pub fn remove_unfinished_received_jobs(&mut self, my_name: &str) -> Vec<Job> {
+        let peer_name = self.peer.name().to_owned();
+
+        let unfinished: Vec<Job> = self
+            .jobs
+            .values()
+            .filter(|job| {
+                !job.is_finished()
+                    && matches!(
+                        job.destination().position(my_name, &peer_name),
+                        Position::Downstream | Position::Destination
+                    )
+            })
+            .cloned()
+            .collect();
+
+        for job in unfinished.iter() {
+            self.jobs.remove(&job.id());
+            self.waiters.remove(&job.id());
+            self.duplicates.remove(&job.id());
+        }
+
+        unfinished
+    }
 }
 
 ///
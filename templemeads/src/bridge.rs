@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: MIT
 
 use crate::agent;
+use crate::batch;
 use crate::command::Command;
 use crate::destination::Destination;
 use crate::error::Error;
@@ -89,13 +90,29 @@ pub async fn notify(command: &str) -> Result<(), Error> {
 }
 
 pub async fn run(command: &str) -> Result<Job, Error> {
-    tracing::info!("Received command: {}", command);
+    submit(command, false).await
+}
+
+///
+/// Run the passed command as a dry run. Every agent that the job passes
+/// through describes the changes it would make, rather than making them,
+/// and these are returned in the job's `changes`
+///
+pub async fn dry_run(command: &str) -> Result<Job, Error> {
+    submit(command, true).await
+}
+
+async fn submit(command: &str, dry_run: bool) -> Result<Job, Error> {
+    match dry_run {
+        true => tracing::info!("Received command (dry run): {}", command),
+        false => tracing::info!("Received command: {}", command),
+    }
 
     let my_name = agent::name().await;
 
     match agent::portal(5).await {
         Some(portal) => {
-            let job = Job::parse(command, true)?;
+            let job = Job::parse(command, true)?.with_dry_run(dry_run);
 
             if job.destination().first() == my_name {
                 // we can send this job straight to the portal if the
@@ -147,8 +164,11 @@ pub async fn run(command: &str) -> Result<Job, Error> {
             // use a longer duration for this job so that there is plenty of
             // time for the portal to collect the result - in reality, the
             // actual job on the system will have a much shorter lifetime,
-            // e.g. 1 minute
-            let job = job.set_lifetime(chrono::Duration::minutes(5));
+            // e.g. 1 minute. This is 3 minutes longer than the job that
+            // the portal submits, so 5 minutes unless this is a batch
+            let lifetime = chrono::Duration::minutes(3) + batch::lifetime(&job.instruction());
+
+            let job = job.set_lifetime(lifetime).with_dry_run(dry_run);
 
             Ok(job.put(&portal).await?)
         }
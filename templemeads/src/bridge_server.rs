@@ -2,11 +2,16 @@
 // SPDX-License-Identifier: MIT
 
 use crate::agent;
-use crate::bridge::{notify as bridge_notify, run as bridge_run, status as bridge_status};
+use crate::bridge::{
+    dry_run as bridge_dry_run, notify as bridge_notify, run as bridge_run, status as bridge_status,
+};
+use crate::bridgeallowlist;
+use crate::bridgeboard::{SignalPolicy, DEFAULT_BOARD_HIGH_WATER_MARK, DEFAULT_BOARD_WARNING_MARK};
 use crate::bridgestate::get as get_board;
+use crate::bridgetls::TlsConfig;
 use crate::command::Command;
 use crate::destination::Destinations;
-use crate::diagnostics::collect_diagnostics;
+use crate::diagnostics::{collect_board_dump, collect_diagnostics};
 use crate::error::Error;
 use crate::grammar::PortalIdentifier;
 use crate::health::collect_health;
@@ -17,19 +22,26 @@ use crate::notificationstate;
 use anyhow::{Context, Result};
 use axum::{
     body::Bytes,
-    extract::{Json, State},
+    extract::{ConnectInfo, Json, Request, State},
     http::header::HeaderMap,
     http::StatusCode,
+    middleware::{self, Next},
     response::{IntoResponse, Response},
     routing::{get, post},
     Router,
 };
 use chrono::{DateTime, Duration, Utc};
-use paddington::{Key, SecretKey};
+use paddington::config::IpOrRange;
+use paddington::{Key, SecretKey, Signature};
 use secrecy::ExposeSecret;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::{collections::HashMap, net::IpAddr, path, sync::Arc};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    path,
+    sync::Arc,
+};
 use tokio::{net::TcpListener, sync::Mutex};
 use url::Url;
 use uuid::Uuid;
@@ -86,6 +98,80 @@ pub fn sign_api_call(
     Ok(format!("OpenPortal {}", signature))
 }
 
+///
+/// Return the string that is signed for a signal for the passed id
+/// at the passed timestamp. Any other query parameters sent with the
+/// signal (e.g. `event` and `instruction`) are appended, sorted by name,
+/// as `\n<name>=<value>`, so that they cannot be changed without
+/// invalidating the signature.
+///
+fn signal_string(timestamp: i64, id: &Uuid, params: &[(&str, &str)]) -> String {
+    let mut params = params.to_vec();
+    params.sort();
+
+    let mut call_string = format!("signal\n{}\n{}", timestamp, id);
+
+    for (name, value) in params {
+        call_string.push_str(&format!("\n{}={}", name, value));
+    }
+
+    call_string
+}
+
+///
+/// Return the signature for a signal sent by the bridge to the web portal
+/// for the passed id (job or notification) and other query parameters
+/// at the passed unix timestamp, signed with the passed key.
+///
+/// The web portal receives the timestamp and signature as the `timestamp`
+/// and `signature` query parameters, and should check them using
+/// `verify_signal` before acting on the signal.
+///
+pub fn sign_signal(
+    key: &SecretKey,
+    timestamp: i64,
+    id: &Uuid,
+    params: &[(&str, &str)],
+) -> Result<String, anyhow::Error> {
+    let signature = key
+        .expose_secret()
+        .sign(signal_string(timestamp, id, params))?;
+    Ok(signature.to_string())
+}
+
+///
+/// Verify that the passed signature was generated by `sign_signal` for
+/// the passed id, other query parameters and timestamp, using the passed
+/// key. This also checks that the timestamp is within `max_age_seconds`
+/// of now, so that old signals cannot be replayed.
+///
+pub fn verify_signal(
+    key: &SecretKey,
+    timestamp: i64,
+    id: &Uuid,
+    params: &[(&str, &str)],
+    signature: &str,
+    max_age_seconds: i64,
+) -> Result<(), Error> {
+    let now = Utc::now().timestamp();
+
+    if (now - timestamp).abs() > max_age_seconds {
+        return Err(Error::Unauthorized(format!(
+            "Signal timestamp {} is outside the acceptable window of {} seconds",
+            timestamp, max_age_seconds
+        )));
+    }
+
+    let signature = Signature::from_string(signature)
+        .map_err(|e| Error::Unauthorized(format!("Invalid signal signature: {}", e)))?;
+
+    key.expose_secret()
+        .verify(signal_string(timestamp, id, params), &signature)
+        .map_err(|_| Error::Unauthorized(format!("Signal signature is invalid for {}", id)))?;
+
+    Ok(())
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Config {
     pub url: Url,
@@ -93,7 +179,29 @@ pub struct Config {
     pub port: u16,
     pub key: SecretKey,
     pub signal_url: Option<Url>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub standby_signal_urls: Vec<Url>,
     pub notification_url: Option<Url>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub board_file: Option<path::PathBuf>,
+    #[serde(default = "default_board_warning_mark")]
+    pub board_warning_mark: usize,
+    #[serde(default = "default_board_high_water_mark")]
+    pub board_high_water_mark: usize,
+    #[serde(default)]
+    pub signal_policy: SignalPolicy,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_ips: Vec<IpOrRange>,
+}
+
+fn default_board_warning_mark() -> usize {
+    DEFAULT_BOARD_WARNING_MARK
+}
+
+fn default_board_high_water_mark() -> usize {
+    DEFAULT_BOARD_HIGH_WATER_MARK
 }
 
 fn create_webserver_url(url: &str) -> Result<Url, Error> {
@@ -142,8 +250,16 @@ fn create_signal_url(signal_url: &str) -> Result<Option<Url>, Error> {
 }
 
 impl Config {
-    pub fn new(url: &str, ip: IpAddr, port: u16, signal_url: &str, notification_url: &str) -> Self {
-        Self {
+    pub fn new(
+        url: &str,
+        ip: IpAddr,
+        port: u16,
+        signal_url: &str,
+        standby_signal_urls: &[String],
+        notification_url: &str,
+        board_file: Option<path::PathBuf>,
+    ) -> Result<Self, Error> {
+        Ok(Self {
             url: create_webserver_url(url).unwrap_or_else(|e| {
                 tracing::error!(
                     "Could not parse URL: {} because '{}'. Using http://localhost:{port} instead.",
@@ -155,7 +271,7 @@ impl Config {
             }),
             ip,
             port,
-            key: Key::generate(),
+            key: Key::generate()?,
             signal_url: create_signal_url(signal_url).unwrap_or_else(|e| {
                 tracing::error!(
                     "Could not parse signal URL: {} because '{}'. Using None",
@@ -164,6 +280,19 @@ impl Config {
                 );
                 None
             }),
+            standby_signal_urls: standby_signal_urls
+                .iter()
+                .filter_map(|standby| {
+                    create_signal_url(standby).unwrap_or_else(|e| {
+                        tracing::error!(
+                            "Could not parse standby signal URL: {} because '{}'. Ignoring",
+                            standby,
+                            e
+                        );
+                        None
+                    })
+                })
+                .collect(),
             notification_url: create_signal_url(notification_url).unwrap_or_else(|e| {
                 tracing::error!(
                     "Could not parse notification URL: {} because '{}'. Using None",
@@ -172,7 +301,25 @@ impl Config {
                 );
                 None
             }),
-        }
+            board_file,
+            board_warning_mark: DEFAULT_BOARD_WARNING_MARK,
+            board_high_water_mark: DEFAULT_BOARD_HIGH_WATER_MARK,
+            signal_policy: SignalPolicy::default(),
+            tls: None,
+            allowed_ips: Vec::new(),
+        })
+    }
+
+    ///
+    /// Return all of the signal URLs in the order in which they should
+    /// be tried, i.e. the primary signal URL followed by the standbys
+    ///
+    pub fn signal_urls(&self) -> Vec<Url> {
+        self.signal_url
+            .iter()
+            .chain(self.standby_signal_urls.iter())
+            .cloned()
+            .collect()
     }
 }
 
@@ -310,6 +457,30 @@ fn extract_client_ip(headers: &HeaderMap) -> IpAddr {
     })
 }
 
+///
+/// Middleware that refuses calls from addresses that are not on the
+/// allowlist. This checks the address of the connection itself, not
+/// the (forgeable) forwarding headers
+///
+async fn check_allowed_ip(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if !bridgeallowlist::is_allowed(&addr.ip()) {
+        tracing::warn!(
+            "Refusing call from {}, which is not on the allowlist",
+            addr.ip()
+        );
+        return Err(AppError(
+            anyhow::anyhow!("{} is not allowed to call the bridge", addr.ip()),
+            Some(StatusCode::FORBIDDEN),
+        ));
+    }
+
+    Ok(next.run(request).await)
+}
+
 ///
 /// Verify the headers for the request - this checks the API key, rate limiting, and nonce
 /// The body parameter should be the raw request body bytes (empty for GET requests)
@@ -659,12 +830,51 @@ async fn diagnostics(
     Ok(Json(json!(result)))
 }
 
+//
+// Board dump endpoint for the web API. This returns the full contents
+// of the boards of the specified agent, for debugging stuck pipelines
+//
+#[tracing::instrument(skip_all)]
+async fn dump_board(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Result<Json<serde_json::Value>, AppError> {
+    verify_headers(&state, &headers, "post", "dump_board", &body).await?;
+
+    let payload: DiagnosticsRequest = serde_json::from_slice(&body)?;
+
+    tracing::info!("Board dump request - destination: {}", payload.destination);
+
+    let dump = match collect_board_dump(&payload.destination).await {
+        Ok(dump) => dump,
+        Err(e) => {
+            tracing::error!(
+                "Error collecting board dump from {}: {:?}",
+                payload.destination,
+                e
+            );
+            let mut result = HashMap::new();
+            result.insert("status".to_string(), json!("error"));
+            return Ok(Json(json!(result)));
+        }
+    };
+
+    let mut result = HashMap::new();
+    result.insert("status".to_string(), json!("ok"));
+    result.insert("dump".to_string(), json!(dump));
+
+    Ok(Json(json!(result)))
+}
+
 //
 // Struct to represent the requests to the 'run' endpoint
 //
 #[derive(Deserialize, Debug)]
 struct RunRequest {
     command: String,
+    #[serde(default)]
+    dry_run: bool,
 }
 
 //
@@ -684,7 +894,12 @@ async fn run(
 
     tracing::debug!("Running command: {}", payload.command);
 
-    match bridge_run(&payload.command).await {
+    let result = match payload.dry_run {
+        true => bridge_dry_run(&payload.command).await,
+        false => bridge_run(&payload.command).await,
+    };
+
+    match result {
         Ok(job) => Ok(Json(job)),
         Err(e) => {
             tracing::error!("Error running command: {:?}", e);
@@ -1134,11 +1349,120 @@ async fn remove_offerings(
     }
 }
 
+///
+/// Return the allowlist as strings, e.g. for a web API response
+///
+fn allowed_ips_to_strings(allowed_ips: &[IpOrRange]) -> Vec<String> {
+    allowed_ips.iter().map(|ip| ip.to_string()).collect()
+}
+
+///
+/// Return the addresses allowed to call the bridge web API. An empty
+/// list means that every address is allowed
+///
+#[tracing::instrument(skip_all)]
+async fn get_allowed_ips(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<String>>, AppError> {
+    verify_headers(&state, &headers, "get", "get_allowed_ips", &[]).await?;
+
+    Ok(Json(allowed_ips_to_strings(&bridgeallowlist::get())))
+}
+
+///
+/// Replace the addresses allowed to call the bridge web API
+///
+#[tracing::instrument(skip_all)]
+async fn sync_allowed_ips(
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Result<Json<Vec<String>>, AppError> {
+    verify_headers(&state, &headers, "post", "sync_allowed_ips", &body).await?;
+
+    let ips = bridgeallowlist::parse(&serde_json::from_slice::<Vec<String>>(&body)?)?;
+
+    Ok(Json(allowed_ips_to_strings(&bridgeallowlist::sync(
+        &addr.ip(),
+        ips,
+    )?)))
+}
+
+///
+/// Add addresses to those allowed to call the bridge web API
+///
+#[tracing::instrument(skip_all)]
+async fn add_allowed_ips(
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Result<Json<Vec<String>>, AppError> {
+    verify_headers(&state, &headers, "post", "add_allowed_ips", &body).await?;
+
+    let ips = bridgeallowlist::parse(&serde_json::from_slice::<Vec<String>>(&body)?)?;
+
+    Ok(Json(allowed_ips_to_strings(&bridgeallowlist::add(
+        &addr.ip(),
+        ips,
+    )?)))
+}
+
+///
+/// Remove addresses from those allowed to call the bridge web API
+///
+#[tracing::instrument(skip_all)]
+async fn remove_allowed_ips(
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Result<Json<Vec<String>>, AppError> {
+    verify_headers(&state, &headers, "post", "remove_allowed_ips", &body).await?;
+
+    let ips = bridgeallowlist::parse(&serde_json::from_slice::<Vec<String>>(&body)?)?;
+
+    Ok(Json(allowed_ips_to_strings(&bridgeallowlist::remove(
+        &addr.ip(),
+        ips,
+    )?)))
+}
+
 ///
 /// Function spawned to run the API server in a background thread
 ///
 async fn run_server(app: Router, listener: TcpListener) -> Result<()> {
-    match axum::serve(listener, app).await {
+    match axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    {
+        Ok(_) => {
+            tracing::info!("Server ran successfully");
+        }
+        Err(e) => {
+            tracing::error!("Error starting server: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Function spawned to run the API server over TLS in a background thread
+///
+async fn run_tls_server(
+    app: Router,
+    addr: std::net::SocketAddr,
+    config: axum_server::tls_rustls::RustlsConfig,
+) -> Result<()> {
+    match axum_server::bind_rustls(addr, config)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+    {
         Ok(_) => {
             tracing::info!("Server ran successfully");
         }
@@ -1165,6 +1489,7 @@ pub async fn spawn(config: Config) -> Result<(), Error> {
         .route("/health", get(health))
         .route("/restart", post(restart))
         .route("/diagnostics", post(diagnostics))
+        .route("/dump_board", post(dump_board))
         .route("/run", post(run))
         .route("/notify", post(notify))
         .route("/status", post(status))
@@ -1177,11 +1502,54 @@ pub async fn spawn(config: Config) -> Result<(), Error> {
         .route("/add_offerings", post(add_offerings))
         .route("/get_offerings", get(get_offerings))
         .route("/remove_offerings", post(remove_offerings))
+        .route("/get_allowed_ips", get(get_allowed_ips))
+        .route("/sync_allowed_ips", post(sync_allowed_ips))
+        .route("/add_allowed_ips", post(add_allowed_ips))
+        .route("/remove_allowed_ips", post(remove_allowed_ips))
+        .layer(middleware::from_fn(check_allowed_ip))
         .with_state(state);
 
+    // the allowlist can be changed while the bridge runs
+    bridgeallowlist::set(config.allowed_ips.clone());
+
+    if !config.allowed_ips.is_empty() {
+        tracing::info!(
+            "Only allowing calls to the bridge API from: {}",
+            allowed_ips_to_strings(&config.allowed_ips).join(", ")
+        );
+    }
+
+    let addr = std::net::SocketAddr::new(config.ip, config.port);
+
+    // serve HTTPS (optionally requiring client certificates) if TLS
+    // is configured, otherwise plain HTTP
+    if let Some(tls) = &config.tls {
+        let rustls_config =
+            axum_server::tls_rustls::RustlsConfig::from_config(Arc::new(tls.server_config()?));
+
+        if config.url.scheme() != "https" {
+            tracing::warn!(
+                "The bridge serves HTTPS, but its URL {} is not https - clients will not connect",
+                config.url
+            );
+        }
+
+        tracing::info!(
+            "Serving the bridge API over TLS on {}{}",
+            addr,
+            match tls.requires_client_certificate() {
+                true => " - client certificates are required",
+                false => "",
+            }
+        );
+
+        tokio::spawn(run_tls_server(app, addr, rustls_config));
+
+        return Ok(());
+    }
+
     // create a TCP listener on the specified port
-    let listener =
-        tokio::net::TcpListener::bind(&std::net::SocketAddr::new(config.ip, config.port)).await?;
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
 
     // spawn a new task to run the web server to listen for requests
     tokio::spawn(run_server(app, listener));
@@ -1219,7 +1587,9 @@ mod tests {
 
     #[test]
     fn test_sign_api_call() {
-        let key = Key::generate();
+        let key = Key::generate().unwrap_or_else(|e| {
+            unreachable!("Cannot generate key: {}", e);
+        });
         let date = Utc::now();
         let protocol = "get";
         let function = "health";
@@ -1249,7 +1619,9 @@ mod tests {
 
     #[test]
     fn test_sign_api_call_with_body() {
-        let key = Key::generate();
+        let key = Key::generate().unwrap_or_else(|e| {
+            unreachable!("Cannot generate key: {}", e);
+        });
         let date = Utc::now();
         let protocol = "post";
         let function = "run";
@@ -1278,4 +1650,62 @@ mod tests {
             assert_eq!(signed, expected);
         }
     }
+
+    #[test]
+    fn test_sign_verify_signal() {
+        let key = Key::generate().unwrap_or_else(|e| {
+            unreachable!("Cannot generate key: {}", e);
+        });
+        let id = Uuid::new_v4();
+        let timestamp = Utc::now().timestamp();
+
+        let signature = sign_signal(&key, timestamp, &id, &[]).unwrap_or_default();
+
+        assert!(verify_signal(&key, timestamp, &id, &[], &signature, 60).is_ok());
+
+        // wrong id, wrong key, or stale timestamp must all be rejected
+        assert!(verify_signal(&key, timestamp, &Uuid::new_v4(), &[], &signature, 60).is_err());
+        let other = Key::generate().unwrap_or_else(|e| {
+            unreachable!("Cannot generate key: {}", e);
+        });
+        assert!(verify_signal(&other, timestamp, &id, &[], &signature, 60).is_err());
+
+        let old = timestamp - 120;
+        let old_signature = sign_signal(&key, old, &id, &[]).unwrap_or_default();
+        assert!(verify_signal(&key, old, &id, &[], &old_signature, 60).is_err());
+    }
+
+    #[test]
+    fn test_sign_verify_signal_params() {
+        let key = Key::generate().unwrap_or_else(|e| {
+            unreachable!("Cannot generate key: {}", e);
+        });
+        let id = Uuid::new_v4();
+        let timestamp = Utc::now().timestamp();
+
+        let params = [("event", "expired"), ("instruction", "add_user a.b.c")];
+        let signature = sign_signal(&key, timestamp, &id, &params).unwrap_or_default();
+
+        assert!(verify_signal(&key, timestamp, &id, &params, &signature, 60).is_ok());
+
+        // the order of the parameters does not matter
+        let reversed = [("instruction", "add_user a.b.c"), ("event", "expired")];
+        assert!(verify_signal(&key, timestamp, &id, &reversed, &signature, 60).is_ok());
+
+        // forged, added or dropped parameters must all be rejected
+        let forged = [("event", "expired"), ("instruction", "remove_user a.b.c")];
+        assert!(verify_signal(&key, timestamp, &id, &forged, &signature, 60).is_err());
+
+        let added = [
+            ("event", "expired"),
+            ("instruction", "add_user a.b.c"),
+            ("extra", "1"),
+        ];
+        assert!(verify_signal(&key, timestamp, &id, &added, &signature, 60).is_err());
+        assert!(verify_signal(&key, timestamp, &id, &[], &signature, 60).is_err());
+
+        // and parameters cannot be added to a plain signal
+        let plain = sign_signal(&key, timestamp, &id, &[]).unwrap_or_default();
+        assert!(verify_signal(&key, timestamp, &id, &params, &plain, 60).is_err());
+    }
 }
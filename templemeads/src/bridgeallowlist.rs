@@ -0,0 +1,211 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! The source addresses that are allowed to call the bridge web API
+//!
+//! The allowlist starts as the `allowed_ips` in the bridge config, and
+//! can be changed while the bridge is running via the web API, so that
+//! web portal frontends can be added and removed (e.g. by autoscaling)
+//! without restarting the bridge. An empty allowlist allows every address.
+
+use crate::error::Error;
+
+use once_cell::sync::Lazy;
+use paddington::config::IpOrRange;
+use std::net::IpAddr;
+use std::sync::RwLock;
+
+/// The addresses currently allowed to call the bridge web API
+static ALLOWED_IPS: Lazy<RwLock<Vec<IpOrRange>>> = Lazy::new(|| RwLock::new(Vec::new()));
+
+///
+/// Parse the passed IP addresses or CIDR ranges, e.g. "10.0.0.12" or
+/// "10.0.1.0/24"
+///
+pub fn parse(ips: &[String]) -> Result<Vec<IpOrRange>, Error> {
+    let mut parsed = Vec::new();
+
+    for ip in ips {
+        let ip = IpOrRange::new(ip.trim())?;
+
+        if !parsed.contains(&ip) {
+            parsed.push(ip);
+        }
+    }
+
+    Ok(parsed)
+}
+
+///
+/// Return the addresses that are allowed to call the bridge web API.
+/// An empty list means that every address is allowed
+///
+pub fn get() -> Vec<IpOrRange> {
+    match ALLOWED_IPS.read() {
+        Ok(allowed) => allowed.clone(),
+        Err(e) => {
+            tracing::error!("Failed to lock the bridge allowlist: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+///
+/// Return whether the passed address is allowed to call the bridge web API
+///
+pub fn is_allowed(addr: &IpAddr) -> bool {
+    match ALLOWED_IPS.read() {
+        Ok(allowed) => allowed.is_empty() || allowed.iter().any(|ip| ip.matches(addr)),
+        Err(e) => {
+            // refuse everything rather than allow every address
+            tracing::error!("Failed to lock the bridge allowlist: {}", e);
+            false
+        }
+    }
+}
+
+///
+/// Update the allowlist with the passed function. The update is refused
+/// if it would stop `caller` (the address making the change) from
+/// calling the bridge web API, so that the allowlist cannot be locked
+/// from the outside
+///
+fn update(
+    caller: &IpAddr,
+    change: impl FnOnce(&mut Vec<IpOrRange>) -> Result<(), Error>,
+) -> Result<Vec<IpOrRange>, Error> {
+    let mut allowed = ALLOWED_IPS
+        .write()
+        .map_err(|e| Error::Bug(format!("Failed to lock the bridge allowlist: {}", e)))?;
+
+    let mut updated = allowed.clone();
+    change(&mut updated)?;
+
+    if !updated.is_empty() && !updated.iter().any(|ip| ip.matches(caller)) {
+        return Err(Error::InvalidState(format!(
+            "The allowlist would no longer allow {}, which is changing it",
+            caller
+        )));
+    }
+
+    tracing::info!(
+        "Bridge allowlist changed by {}: [{}]",
+        caller,
+        updated
+            .iter()
+            .map(|ip| ip.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    *allowed = updated.clone();
+
+    Ok(updated)
+}
+
+///
+/// Set the addresses that are allowed to call the bridge web API, e.g.
+/// from the bridge config when the bridge starts
+///
+pub fn set(ips: Vec<IpOrRange>) {
+    match ALLOWED_IPS.write() {
+        Ok(mut allowed) => *allowed = ips,
+        Err(e) => tracing::error!("Failed to lock the bridge allowlist: {}", e),
+    }
+}
+
+///
+/// Replace the allowlist with the passed addresses, on behalf of `caller`
+///
+pub fn sync(caller: &IpAddr, ips: Vec<IpOrRange>) -> Result<Vec<IpOrRange>, Error> {
+    update(caller, |allowed| {
+        *allowed = ips;
+        Ok(())
+    })
+}
+
+///
+/// Add the passed addresses to the allowlist, on behalf of `caller`
+///
+pub fn add(caller: &IpAddr, ips: Vec<IpOrRange>) -> Result<Vec<IpOrRange>, Error> {
+    update(caller, |allowed| {
+        for ip in ips {
+            if !allowed.contains(&ip) {
+                allowed.push(ip);
+            }
+        }
+
+        Ok(())
+    })
+}
+
+///
+/// Remove the passed addresses from the allowlist, on behalf of `caller`.
+/// This cannot empty the allowlist, as that would allow every address -
+/// use `sync` with an empty list to do that
+///
+pub fn remove(caller: &IpAddr, ips: Vec<IpOrRange>) -> Result<Vec<IpOrRange>, Error> {
+    update(caller, |allowed| {
+        allowed.retain(|ip| !ips.contains(ip));
+
+        match allowed.is_empty() {
+            true => Err(Error::InvalidState(
+                "Cannot remove every address from the allowlist, as an empty allowlist \
+                 allows every address"
+                    .to_owned(),
+            )),
+            false => Ok(()),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse()
+            .unwrap_or_else(|e| unreachable!("Cannot parse IP address: {}", e))
+    }
+
+    fn ips(ips: &[&str]) -> Vec<IpOrRange> {
+        parse(&ips.iter().map(|ip| ip.to_string()).collect::<Vec<_>>())
+            .unwrap_or_else(|e| unreachable!("Cannot parse allowlist: {}", e))
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let admin = ip("10.0.0.1");
+
+        set(Vec::new());
+        assert!(is_allowed(&ip("192.168.1.1")));
+
+        assert!(parse(&["not an ip".to_owned()]).is_err());
+        assert_eq!(ips(&["10.0.0.1", "10.0.0.1"]).len(), 1);
+
+        // cannot lock out the caller
+        assert!(sync(&admin, ips(&["10.0.1.0/24"])).is_err());
+        assert!(is_allowed(&ip("192.168.1.1")));
+
+        assert!(sync(&admin, ips(&["10.0.0.1", "10.0.1.0/24"])).is_ok());
+        assert!(is_allowed(&admin));
+        assert!(is_allowed(&ip("10.0.1.42")));
+        assert!(!is_allowed(&ip("192.168.1.1")));
+
+        assert!(add(&admin, ips(&["192.168.1.1"])).is_ok());
+        assert!(is_allowed(&ip("192.168.1.1")));
+
+        assert!(remove(&admin, ips(&["10.0.1.0/24", "192.168.1.1"])).is_ok());
+        assert!(!is_allowed(&ip("10.0.1.42")));
+        assert_eq!(get(), ips(&["10.0.0.1"]));
+
+        // cannot empty the allowlist by removing, or remove the caller
+        assert!(remove(&admin, ips(&["10.0.0.1"])).is_err());
+        assert!(add(&admin, ips(&["10.0.2.0/24"])).is_ok());
+        assert!(remove(&admin, ips(&["10.0.0.1"])).is_err());
+        assert!(remove(&ip("10.0.2.7"), ips(&["10.0.0.1"])).is_ok());
+
+        assert!(sync(&ip("10.0.2.7"), Vec::new()).is_ok());
+        assert!(is_allowed(&ip("192.168.1.1")));
+    }
+}
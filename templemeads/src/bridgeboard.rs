@@ -1,24 +1,111 @@
 // SPDX-FileCopyrightText: © 2025 Christopher Woods <Christopher.Woods@bristol.ac.uk>
 // SPDX-License-Identifier: MIT
 
+use anyhow::Context;
+use paddington::SecretKey;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use tokio::sync::oneshot;
 use url::Url;
 use uuid::Uuid;
 
 use crate::board::{Listener, Waiter};
 use crate::error::Error;
-use crate::job::Job;
+use crate::health;
+use crate::job::{Envelope, Job};
+
+const BACKPRESSURE_WARNING: &str = "bridge_board";
+
+/// Default number of unfinished jobs at which a health warning is raised
+pub const DEFAULT_BOARD_WARNING_MARK: usize = 500;
+
+/// Default number of unfinished jobs at which new jobs are rejected
+pub const DEFAULT_BOARD_HIGH_WATER_MARK: usize = 1000;
+
+// the maximum number of expired jobs held waiting to be reported
+const MAX_EXPIRED_QUEUE: usize = 1000;
+
+///
+/// The policy used when signalling the web portal that a job is
+/// waiting on the board. Each attempt tries every signal URL in turn,
+/// waiting `interval_seconds` between attempts, and giving up after
+/// `attempts` attempts or once `deadline_seconds` have passed. If
+/// `background_seconds` is non-zero then, rather than failing the job,
+/// the bridge keeps retrying in the background for up to that long
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SignalPolicy {
+    pub attempts: u32,
+    pub interval_seconds: u64,
+    pub deadline_seconds: u64,
+    pub background_seconds: u64,
+}
+
+impl Default for SignalPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            interval_seconds: 2,
+            deadline_seconds: 300,
+            background_seconds: 0,
+        }
+    }
+}
+
+impl SignalPolicy {
+    ///
+    /// Return whether or not failed signals should continue to be
+    /// retried in the background
+    ///
+    pub fn retries_in_background(&self) -> bool {
+        self.background_seconds > 0
+    }
+}
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct BridgeBoard {
     jobs: HashMap<Uuid, Job>,
 
-    signal_url: Option<Url>,
+    // the envelopes the jobs arrived in, so that the results can be
+    // returned to the sender if the bridge is restarted
+    #[serde(default)]
+    envelopes: HashMap<Uuid, Envelope>,
+
+    signal_urls: Vec<Url>,
+
+    // how to retry signals that are not acknowledged
+    #[serde(skip)]
+    signal_policy: SignalPolicy,
+
+    // the signal URL that acknowledged the signal for each job
+    #[serde(default)]
+    acknowledged_by: HashMap<Uuid, Url>,
 
     notification_url: Option<Url>,
 
+    // the key used to sign signals - never serialised
+    #[serde(skip)]
+    signal_key: Option<SecretKey>,
+
+    // the file to which the board is saved whenever it changes
+    #[serde(skip)]
+    board_file: Option<PathBuf>,
+
+    // the number of unfinished jobs at which to warn, and at which
+    // to reject new jobs, if the web portal stops consuming them
+    #[serde(skip)]
+    warning_mark: Option<usize>,
+
+    #[serde(skip)]
+    high_water_mark: Option<usize>,
+
+    // jobs that expired without an answer from the web portal,
+    // waiting to be reported to the web portal
+    #[serde(skip)]
+    expired_jobs: Vec<Job>,
+
     // do not serialise or clone the waiters
     #[serde(skip)]
     waiters: HashMap<Uuid, Vec<Listener>>,
@@ -29,8 +116,16 @@ impl Clone for BridgeBoard {
     fn clone(&self) -> Self {
         Self {
             jobs: self.jobs.clone(),
-            signal_url: self.signal_url.clone(),
+            envelopes: self.envelopes.clone(),
+            signal_urls: self.signal_urls.clone(),
+            signal_policy: self.signal_policy,
+            acknowledged_by: self.acknowledged_by.clone(),
             notification_url: self.notification_url.clone(),
+            signal_key: self.signal_key.clone(),
+            board_file: self.board_file.clone(),
+            warning_mark: self.warning_mark,
+            high_water_mark: self.high_water_mark,
+            expired_jobs: self.expired_jobs.clone(),
             waiters: HashMap::new(),
         }
     }
@@ -40,8 +135,16 @@ impl BridgeBoard {
     pub fn new() -> Self {
         Self {
             jobs: HashMap::new(),
-            signal_url: None,
+            envelopes: HashMap::new(),
+            signal_urls: Vec::new(),
+            signal_policy: SignalPolicy::default(),
+            acknowledged_by: HashMap::new(),
             notification_url: None,
+            signal_key: None,
+            board_file: None,
+            warning_mark: None,
+            high_water_mark: None,
+            expired_jobs: Vec::new(),
             waiters: HashMap::new(),
         }
     }
@@ -105,6 +208,10 @@ impl BridgeBoard {
     /// It is an error to attempt to add a job that is already
     /// present on the board
     ///
+    /// If the number of unfinished jobs has reached the high-water mark
+    /// then the job is rejected with a PortalUnavailable error, as this
+    /// means that the web portal is not consuming jobs
+    ///
     pub fn add(&mut self, job: &Job) -> Result<Waiter, Error> {
         match self.jobs.get_mut(&job.id()) {
             Some(j) => {
@@ -114,8 +221,29 @@ impl BridgeBoard {
                 )))
             }
             None => {
+                if let Some(high_water_mark) = self.high_water_mark {
+                    let unfinished = self.num_unfinished_jobs();
+
+                    if unfinished >= high_water_mark {
+                        let message = format!(
+                            "Bridge board is full ({} unfinished jobs, high-water mark {}). \
+                             The web portal does not appear to be fetching jobs.",
+                            unfinished, high_water_mark
+                        );
+                        health::set_warning(BACKPRESSURE_WARNING, &message);
+
+                        return Err(Error::PortalUnavailable(format!(
+                            "Web portal unavailable - rejecting job {}: {}",
+                            job.id(),
+                            message
+                        )));
+                    }
+                }
+
                 // add the job to the board
                 self.jobs.insert(job.id(), job.clone());
+                self.update_backpressure_warning();
+                self.save();
             }
         }
 
@@ -124,6 +252,33 @@ impl BridgeBoard {
         self.get_waiter(job)
     }
 
+    ///
+    /// Add the job in the passed envelope to our board, recording the
+    /// envelope so that the result can be returned to the sender even
+    /// if the bridge is restarted. This returns a waiter that can be used
+    /// to wait until the job is completed or errored
+    ///
+    pub fn add_envelope(&mut self, envelope: &Envelope) -> Result<Waiter, Error> {
+        let job = envelope.job();
+
+        if !self.jobs.contains_key(&job.id()) {
+            self.envelopes.insert(job.id(), envelope.clone());
+        }
+
+        self.add(&job)
+    }
+
+    ///
+    /// Return the envelopes of all of the unfinished jobs on the board
+    ///
+    pub fn unfinished_envelopes(&self) -> Vec<Envelope> {
+        self.jobs
+            .values()
+            .filter(|job| !job.is_finished() && !job.is_expired())
+            .filter_map(|job| self.envelopes.get(&job.id()).cloned())
+            .collect()
+    }
+
     ///
     /// Update the passed job on our board
     ///
@@ -143,6 +298,9 @@ impl BridgeBoard {
                             }
                         }
                     }
+
+                    self.update_backpressure_warning();
+                    self.save();
                 }
             }
             None => {
@@ -172,8 +330,15 @@ impl BridgeBoard {
             }
         }
 
+        self.acknowledged_by.remove(&job.id());
+        self.envelopes.remove(&job.id());
         let removed = self.jobs.remove(&job.id()).is_some();
 
+        if removed {
+            self.update_backpressure_warning();
+            self.save();
+        }
+
         Ok(removed)
     }
 
@@ -207,9 +372,11 @@ impl BridgeBoard {
     }
 
     ///
-    /// Remove all expired jobs from the board
+    /// Remove all expired jobs from the board, returning the jobs that
+    /// were removed. These are also queued so that the expiry can be
+    /// reported to the web portal (see take_expired_jobs)
     ///
-    pub fn remove_expired_jobs(&mut self) {
+    pub fn remove_expired_jobs(&mut self) -> Vec<Job> {
         let expired_jobs: Vec<Uuid> = self
             .jobs
             .iter()
@@ -229,17 +396,74 @@ impl BridgeBoard {
             })
             .collect();
 
-        for job_id in expired_jobs.iter() {
-            let _ = self.jobs.remove(job_id);
+        let expired_jobs: Vec<Job> = expired_jobs
+            .iter()
+            .filter_map(|job_id| {
+                let _ = self.acknowledged_by.remove(job_id);
+                let _ = self.envelopes.remove(job_id);
+                self.jobs.remove(job_id)
+            })
+            .collect();
+
+        if !expired_jobs.is_empty() {
+            self.expired_jobs.extend(expired_jobs.iter().cloned());
+
+            if self.expired_jobs.len() > MAX_EXPIRED_QUEUE {
+                let excess = self.expired_jobs.len() - MAX_EXPIRED_QUEUE;
+                self.expired_jobs.drain(0..excess);
+            }
+
+            self.update_backpressure_warning();
+            self.save();
         }
+
+        expired_jobs
     }
 
-    pub fn set_signal_url(&mut self, url: Url) {
-        self.signal_url = Some(url);
+    ///
+    /// Take the queue of jobs that have expired since this was last
+    /// called, so that their expiry can be reported to the web portal
+    ///
+    pub fn take_expired_jobs(&mut self) -> Vec<Job> {
+        std::mem::take(&mut self.expired_jobs)
+    }
+
+    ///
+    /// Set the signal URLs, in the order in which they should be tried
+    ///
+    pub fn set_signal_urls(&mut self, urls: Vec<Url>) {
+        self.signal_urls = urls;
+    }
+
+    pub fn signal_urls(&self) -> Vec<Url> {
+        self.signal_urls.clone()
+    }
+
+    pub fn set_signal_policy(&mut self, policy: SignalPolicy) {
+        self.signal_policy = policy;
     }
 
-    pub fn signal_url(&self) -> Option<Url> {
-        self.signal_url.clone()
+    pub fn signal_policy(&self) -> SignalPolicy {
+        self.signal_policy
+    }
+
+    ///
+    /// Record that the signal for the passed job was acknowledged
+    /// by the passed signal URL
+    ///
+    pub fn set_acknowledged_by(&mut self, job: &Uuid, url: &Url) {
+        if self.jobs.contains_key(job) {
+            self.acknowledged_by.insert(*job, url.clone());
+            self.save();
+        }
+    }
+
+    ///
+    /// Return the signal URL that acknowledged the signal for the
+    /// passed job, if any
+    ///
+    pub fn acknowledged_by(&self, job: &Uuid) -> Option<Url> {
+        self.acknowledged_by.get(job).cloned()
     }
 
     pub fn set_notification_url(&mut self, url: Url) {
@@ -249,4 +473,122 @@ impl BridgeBoard {
     pub fn notification_url(&self) -> Option<Url> {
         self.notification_url.clone()
     }
+
+    ///
+    /// Set the key used to sign the signals sent to the web portal
+    ///
+    pub fn set_signal_key(&mut self, key: SecretKey) {
+        self.signal_key = Some(key);
+    }
+
+    pub fn signal_key(&self) -> Option<SecretKey> {
+        self.signal_key.clone()
+    }
+
+    ///
+    /// Set the number of unfinished jobs at which a health warning is
+    /// raised, and the number at which new jobs are rejected
+    ///
+    pub fn set_high_water_marks(&mut self, warning_mark: usize, high_water_mark: usize) {
+        self.warning_mark = Some(warning_mark);
+        self.high_water_mark = Some(high_water_mark);
+        self.update_backpressure_warning();
+    }
+
+    fn num_unfinished_jobs(&self) -> usize {
+        self.jobs.values().filter(|job| !job.is_finished()).count()
+    }
+
+    ///
+    /// Raise or clear the health warning depending on how many
+    /// unfinished jobs are waiting for the web portal
+    ///
+    fn update_backpressure_warning(&self) {
+        let Some(warning_mark) = self.warning_mark else {
+            return;
+        };
+
+        let unfinished = self.num_unfinished_jobs();
+
+        if unfinished >= warning_mark {
+            health::set_warning(
+                BACKPRESSURE_WARNING,
+                &format!(
+                    "Bridge board has {} unfinished jobs (warning mark {}, high-water mark {}). \
+                     The web portal may not be fetching jobs.",
+                    unfinished,
+                    warning_mark,
+                    self.high_water_mark.unwrap_or(warning_mark)
+                ),
+            );
+        } else {
+            health::clear_warning(BACKPRESSURE_WARNING);
+        }
+    }
+
+    ///
+    /// Set the file to which this board is saved whenever it changes.
+    /// If the file already exists then the jobs (and their envelopes)
+    /// are loaded from it, so that jobs are not lost if the bridge
+    /// is restarted.
+    ///
+    pub fn set_board_file(&mut self, board_file: &Path) -> Result<(), Error> {
+        if board_file.try_exists()? {
+            let json = std::fs::read_to_string(board_file)
+                .with_context(|| format!("Could not read bridge board file: {:?}", board_file))?;
+
+            let saved: BridgeBoard = serde_json::from_str(&json)
+                .with_context(|| format!("Could not parse bridge board file: {:?}", board_file))?;
+
+            tracing::info!(
+                "Loaded {} job(s) from bridge board file {:?}",
+                saved.jobs.len(),
+                board_file
+            );
+
+            self.jobs = saved.jobs;
+            self.envelopes = saved.envelopes;
+            self.acknowledged_by = saved.acknowledged_by;
+        }
+
+        self.board_file = Some(board_file.to_path_buf());
+
+        // remove anything that expired while we were down, and
+        // make sure the file is writable
+        self.remove_expired_jobs();
+        self.save();
+
+        Ok(())
+    }
+
+    ///
+    /// Save the board to the board file (if set). This writes to a
+    /// temporary file first so that a crash mid-write cannot corrupt
+    /// the saved board. Errors are logged rather than returned, as
+    /// failing to save should not stop the bridge from working.
+    ///
+    fn save(&self) {
+        let Some(board_file) = &self.board_file else {
+            return;
+        };
+
+        let json = match serde_json::to_string(self) {
+            Ok(json) => json,
+            Err(e) => {
+                tracing::error!("Could not serialise bridge board: {}", e);
+                return;
+            }
+        };
+
+        let tmp_file = board_file.with_extension("tmp");
+
+        if let Err(e) = std::fs::write(&tmp_file, json) {
+            tracing::error!("Could not write bridge board file {:?}: {}", tmp_file, e);
+            return;
+        }
+
+        if let Err(e) = std::fs::rename(&tmp_file, board_file) {
+            tracing::error!("Could not save bridge board file {:?}: {}", board_file, e);
+        }
+    }
 }
@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: MIT
 
 use crate::bridgeboard::BridgeBoard;
+use crate::diagnostics;
 use crate::error::Error;
 
 use anyhow::Result;
@@ -57,5 +58,15 @@ async fn clean_board() {
         }
     };
 
-    state.write().await.remove_expired_jobs();
+    let expired_jobs = state.write().await.remove_expired_jobs();
+
+    for job in expired_jobs {
+        tracing::warn!(
+            "Bridge board job {} ({}) expired without a response from the web portal",
+            job.id(),
+            job.instruction()
+        );
+
+        diagnostics::record_expired_job(&job).await;
+    }
 }
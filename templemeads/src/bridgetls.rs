@@ -0,0 +1,177 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! TLS, and optionally mutual TLS, for the bridge web API
+//!
+//! The bridge web API normally serves plain HTTP, and relies on the HMAC
+//! signature of each call (and a reverse proxy) for its security. Some
+//! deployments require TLS with client certificates on every internal
+//! HTTP hop, so the bridge can serve HTTPS itself, and can require that
+//! every client presents a certificate signed by a trusted CA. Calls
+//! must still be signed with the bridge key.
+
+use crate::error::Error;
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+///
+/// The certificate and key that the bridge serves HTTPS with, plus the
+/// CA (if any) that client certificates must be signed by
+///
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TlsConfig {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_ca_file: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    pub fn new(cert_file: &Path, key_file: &Path, client_ca_file: Option<&Path>) -> Self {
+        Self {
+            cert_file: cert_file.to_path_buf(),
+            key_file: key_file.to_path_buf(),
+            client_ca_file: client_ca_file.map(|file| file.to_path_buf()),
+        }
+    }
+
+    ///
+    /// Return whether clients must present a certificate (mutual TLS)
+    ///
+    pub fn requires_client_certificate(&self) -> bool {
+        self.client_ca_file.is_some()
+    }
+
+    ///
+    /// Load the certificates and key, and return the rustls config that
+    /// the bridge web API should be served with
+    ///
+    pub fn server_config(&self) -> Result<ServerConfig, Error> {
+        let provider = Arc::new(paddington::tls_provider()?);
+
+        let certs = load_certificates(&self.cert_file)?;
+
+        let key = PrivateKeyDer::from_pem_file(&self.key_file).map_err(|e| {
+            Error::InvalidConfig(format!(
+                "Could not load the TLS key from {}: {}",
+                self.key_file.display(),
+                e
+            ))
+        })?;
+
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| Error::InvalidConfig(format!("Could not configure TLS: {}", e)))?;
+
+        let builder = match &self.client_ca_file {
+            Some(client_ca_file) => {
+                let mut roots = RootCertStore::empty();
+
+                for cert in load_certificates(client_ca_file)? {
+                    roots.add(cert).map_err(|e| {
+                        Error::InvalidConfig(format!(
+                            "Invalid client CA certificate in {}: {}",
+                            client_ca_file.display(),
+                            e
+                        ))
+                    })?;
+                }
+
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .build()
+                        .map_err(|e| {
+                            Error::InvalidConfig(format!(
+                                "Could not verify client certificates against {}: {}",
+                                client_ca_file.display(),
+                                e
+                            ))
+                        })?;
+
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let mut config = builder.with_single_cert(certs, key).map_err(|e| {
+            Error::InvalidConfig(format!(
+                "Invalid TLS certificate or key in {} / {}: {}",
+                self.cert_file.display(),
+                self.key_file.display(),
+                e
+            ))
+        })?;
+
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Ok(config)
+    }
+}
+
+///
+/// Load all of the PEM certificates in the passed file, returning an
+/// error if there are none
+///
+fn load_certificates(file: &Path) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let certs = CertificateDer::pem_file_iter(file)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| {
+            Error::InvalidConfig(format!(
+                "Could not load certificates from {}: {}",
+                file.display(),
+                e
+            ))
+        })?;
+
+    if certs.is_empty() {
+        return Err(Error::InvalidConfig(format!(
+            "No certificates found in {}",
+            file.display()
+        )));
+    }
+
+    Ok(certs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tls_config() {
+        let tls = TlsConfig::new(
+            Path::new("/nonexistent/bridge.pem"),
+            Path::new("/nonexistent/bridge.key"),
+            None,
+        );
+
+        assert!(!tls.requires_client_certificate());
+
+        let line = toml::to_string(&tls)
+            .unwrap_or_else(|e| unreachable!("Cannot serialise TLS config: {}", e));
+        assert!(!line.contains("client_ca_file"));
+
+        let mtls = TlsConfig::new(
+            Path::new("/nonexistent/bridge.pem"),
+            Path::new("/nonexistent/bridge.key"),
+            Some(Path::new("/nonexistent/clients.pem")),
+        );
+
+        assert!(mtls.requires_client_certificate());
+
+        let parsed: TlsConfig = toml::from_str(
+            &toml::to_string(&mtls)
+                .unwrap_or_else(|e| unreachable!("Cannot serialise TLS config: {}", e)),
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot parse TLS config: {}", e));
+        assert_eq!(parsed, mtls);
+
+        assert!(matches!(mtls.server_config(), Err(Error::InvalidConfig(_))));
+    }
+}
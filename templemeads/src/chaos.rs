@@ -0,0 +1,220 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Fault injection for resilience testing
+//!
+//! The `chaos-*` options give the probabilities with which faults are
+//! injected, so that the retry, expiry and deduplication logic can be
+//! tested under failure before it has to cope with it in production.
+//! Messages to peers can be delayed, dropped or duplicated (this is done
+//! by paddington), new jobs can be artificially expired as they are
+//! added to a board, and jobs can be errored rather than run. Every
+//! probability defaults to zero, in which case nothing is injected.
+
+use once_cell::sync::Lazy;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use paddington::{chance, check_probability, MessageFaults};
+
+use crate::error::Error;
+use crate::health::parse_duration;
+use crate::job::Job;
+
+/// The default longest delay of a delayed message
+const DEFAULT_MAX_DELAY: u64 = 5;
+
+///
+/// The probabilities with which faults are injected into jobs
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JobFaults {
+    /// The probability that a new job is expired as it is added to a board
+    pub expire: f64,
+    /// The probability that a job errors rather than being run
+    pub error: f64,
+}
+
+impl JobFaults {
+    ///
+    /// Return whether any faults will be injected
+    ///
+    pub fn is_enabled(&self) -> bool {
+        self.expire > 0.0 || self.error > 0.0
+    }
+}
+
+///
+/// All of the faults to inject, read from the `chaos-*` options
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Faults {
+    pub messages: MessageFaults,
+    pub jobs: JobFaults,
+}
+
+fn parse_probability(option: &str, value: &str) -> Result<f64, Error> {
+    match value.trim() {
+        "" => Ok(0.0),
+        value => {
+            let probability = value.parse::<f64>().map_err(|_| {
+                Error::Parse(format!(
+                    "Invalid {} '{}' - this should be a probability between 0 and 1",
+                    option, value
+                ))
+            })?;
+
+            check_probability(probability)?;
+
+            Ok(probability)
+        }
+    }
+}
+
+impl Faults {
+    ///
+    /// Create the faults from the `chaos-*` options, passed as (name, value)
+    /// pairs. Empty options are zero, apart from `chaos-message-max-delay`
+    /// (a duration, e.g. "5s"), which defaults to 5 seconds
+    ///
+    pub fn from_options(options: &[(&str, String)]) -> Result<Self, Error> {
+        let mut faults = Faults::default();
+        let mut max_delay = Duration::from_secs(DEFAULT_MAX_DELAY);
+
+        for (option, value) in options {
+            match *option {
+                "chaos-message-delay" => faults.messages.delay = parse_probability(option, value)?,
+                "chaos-message-drop" => faults.messages.drop = parse_probability(option, value)?,
+                "chaos-message-duplicate" => {
+                    faults.messages.duplicate = parse_probability(option, value)?
+                }
+                "chaos-message-max-delay" => {
+                    if !value.trim().is_empty() {
+                        max_delay = Duration::from_secs(parse_duration(value)?);
+                    }
+                }
+                "chaos-job-expire" => faults.jobs.expire = parse_probability(option, value)?,
+                "chaos-job-error" => faults.jobs.error = parse_probability(option, value)?,
+                _ => {
+                    return Err(Error::Parse(format!(
+                        "Unknown fault injection option '{}'",
+                        option
+                    )))
+                }
+            }
+        }
+
+        faults.messages.max_delay = max_delay;
+
+        Ok(faults)
+    }
+}
+
+/// The names of the options that configure fault injection
+pub const OPTIONS: [&str; 6] = [
+    "chaos-message-delay",
+    "chaos-message-max-delay",
+    "chaos-message-drop",
+    "chaos-message-duplicate",
+    "chaos-job-expire",
+    "chaos-job-error",
+];
+
+static JOB_FAULTS: Lazy<RwLock<JobFaults>> = Lazy::new(|| RwLock::new(JobFaults::default()));
+
+///
+/// Set the faults injected into messages and jobs by this agent
+///
+pub fn set_faults(faults: Faults) {
+    if faults.jobs.is_enabled() {
+        tracing::warn!(
+            "Injecting faults into jobs: expire={}, error={}",
+            faults.jobs.expire,
+            faults.jobs.error
+        );
+    }
+
+    paddington::set_message_faults(faults.messages);
+
+    match JOB_FAULTS.write() {
+        Ok(mut f) => *f = faults.jobs,
+        Err(e) => tracing::error!("Could not set the job faults: {}", e),
+    }
+}
+
+///
+/// Return the faults injected into jobs by this agent
+///
+pub fn job_faults() -> JobFaults {
+    match JOB_FAULTS.read() {
+        Ok(faults) => faults.clone(),
+        Err(e) => {
+            tracing::error!("Could not read the job faults: {}", e);
+            JobFaults::default()
+        }
+    }
+}
+
+///
+/// Return the passed new job, expired if a fault is injected
+///
+pub(crate) fn expire(job: Job) -> Job {
+    match chance(job_faults().expire) {
+        true => {
+            tracing::warn!("Fault injection: expiring job {}", job);
+            job.set_lifetime(chrono::Duration::zero())
+        }
+        false => job,
+    }
+}
+
+///
+/// Return the error to return instead of running the passed job, if a
+/// fault is injected
+///
+pub(crate) fn error(job: &Job) -> Option<Error> {
+    match chance(job_faults().error) {
+        true => {
+            tracing::warn!("Fault injection: erroring job {}", job);
+            Some(Error::Run(format!(
+                "Fault injection: job {} was not run",
+                job.id()
+            )))
+        }
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(values: &[&str; 6]) -> Vec<(&'static str, String)> {
+        OPTIONS
+            .iter()
+            .zip(values.iter())
+            .map(|(option, value)| (*option, value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_faults() {
+        let faults = Faults::from_options(&options(&["", "", "", "", "", ""]))
+            .unwrap_or_else(|e| unreachable!("Cannot parse faults: {}", e));
+        assert!(!faults.messages.is_enabled());
+        assert!(!faults.jobs.is_enabled());
+        assert_eq!(faults.messages.max_delay, Duration::from_secs(5));
+
+        let faults = Faults::from_options(&options(&["0.1", "2m", "0", "0.5", "1", "0.25"]))
+            .unwrap_or_else(|e| unreachable!("Cannot parse faults: {}", e));
+        assert_eq!(faults.messages.delay, 0.1);
+        assert_eq!(faults.messages.max_delay, Duration::from_secs(120));
+        assert_eq!(faults.messages.duplicate, 0.5);
+        assert_eq!(faults.jobs.expire, 1.0);
+        assert_eq!(faults.jobs.error, 0.25);
+
+        assert!(Faults::from_options(&options(&["2", "", "", "", "", ""])).is_err());
+        assert!(Faults::from_options(&options(&["", "", "", "", "", "often"])).is_err());
+        assert!(Faults::from_options(&[("chaos-monkey", "1".to_owned())]).is_err());
+    }
+}
@@ -0,0 +1,442 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Circuit breakers for calls to third-party services (e.g. slurmrestd,
+//! FreeIPA or the signal URL)
+//!
+//! Each service has a named breaker. The breaker is closed while calls
+//! succeed. After `circuit-breaker-threshold` consecutive failures it
+//! opens, and calls fail fast with `Error::UpstreamUnavailable` rather
+//! than waiting for the service to time out. Once the
+//! `circuit-breaker-cooldown` has passed (or a background health probe
+//! of the service has succeeded) the breaker is half-open, and a single
+//! call is let through as a probe. The breaker closes if the probe
+//! succeeds, and opens again if it fails.
+//!
+//! Only failures of the service itself (e.g. it could not be reached,
+//! or it returned a server error) should be recorded. Application
+//! errors, such as a request for a user that doesn't exist, show that
+//! the service is working.
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::health::{parse_duration, BreakerStatus};
+
+/// The default number of consecutive failures that opens a breaker
+pub const DEFAULT_THRESHOLD: u32 = 5;
+
+/// The default time (in seconds) that a breaker stays open before
+/// a probe call is let through
+pub const DEFAULT_COOLDOWN: u64 = 30;
+
+///
+/// The thresholds used by every circuit breaker in this agent
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    pub threshold: u32,
+    pub cooldown: Duration,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            threshold: DEFAULT_THRESHOLD,
+            cooldown: Duration::from_secs(DEFAULT_COOLDOWN),
+        }
+    }
+}
+
+impl Policy {
+    ///
+    /// Create the policy from the `circuit-breaker-threshold` (a number
+    /// of consecutive failures) and `circuit-breaker-cooldown` (a
+    /// duration, e.g. "30s") options. Empty options keep their defaults
+    ///
+    pub fn from_options(threshold: &str, cooldown: &str) -> Result<Self, Error> {
+        let mut policy = Policy::default();
+
+        if !threshold.trim().is_empty() {
+            policy.threshold = match threshold.trim().parse::<u32>() {
+                Ok(threshold) if threshold > 0 => threshold,
+                _ => {
+                    return Err(Error::Parse(format!(
+                        "Invalid circuit breaker threshold '{}' - this should be a number of failures greater than zero",
+                        threshold
+                    )))
+                }
+            };
+        }
+
+        if !cooldown.trim().is_empty() {
+            policy.cooldown = Duration::from_secs(parse_duration(cooldown)?.max(1));
+        }
+
+        Ok(policy)
+    }
+}
+
+static POLICY: Lazy<RwLock<Policy>> = Lazy::new(|| RwLock::new(Policy::default()));
+
+///
+/// Set the policy used by every circuit breaker in this agent
+///
+pub fn set_policy(policy: Policy) {
+    match POLICY.write() {
+        Ok(mut p) => *p = policy,
+        Err(e) => tracing::error!("Could not set the circuit breaker policy: {}", e),
+    }
+}
+
+///
+/// Return the policy used by every circuit breaker in this agent
+///
+pub fn policy() -> Policy {
+    match POLICY.read() {
+        Ok(policy) => policy.clone(),
+        Err(e) => {
+            tracing::error!("Could not read the circuit breaker policy: {}", e);
+            Policy::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl std::fmt::Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            State::Closed => write!(f, "closed"),
+            State::Open => write!(f, "open"),
+            State::HalfOpen => write!(f, "half-open"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Breaker {
+    state: State,
+    failures: u32,
+    last_error: String,
+    opened_at: Option<DateTime<Utc>>,
+    probe_started: Option<DateTime<Utc>>,
+    rejected: u64,
+}
+
+impl Default for Breaker {
+    fn default() -> Self {
+        Breaker {
+            state: State::Closed,
+            failures: 0,
+            last_error: String::new(),
+            opened_at: None,
+            probe_started: None,
+            rejected: 0,
+        }
+    }
+}
+
+///
+/// The state of every breaker, keyed by name. This uses a std Mutex
+/// so that breakers can be used from synchronous code
+///
+static BREAKERS: Lazy<Mutex<HashMap<String, Breaker>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn elapsed(since: DateTime<Utc>, cooldown: Duration) -> bool {
+    Utc::now().signed_duration_since(since).num_milliseconds() >= cooldown.as_millis() as i64
+}
+
+///
+/// A handle to the named circuit breaker. Handles are cheap to create,
+/// and all handles with the same name share the same state
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircuitBreaker {
+    name: String,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &str) -> Self {
+        match BREAKERS.lock() {
+            Ok(mut breakers) => {
+                breakers.entry(name.to_owned()).or_default();
+            }
+            Err(e) => tracing::error!("Could not lock circuit breakers: {}", e),
+        }
+
+        CircuitBreaker {
+            name: name.to_owned(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    ///
+    /// Return whether a call to the service can be made now. This
+    /// returns `Error::UpstreamUnavailable` if the breaker is open, or
+    /// if it is half-open and another call is already probing the
+    /// service. Every call that is let through must be followed by
+    /// a call to `success` or `failure`
+    ///
+    pub fn check(&self) -> Result<(), Error> {
+        let cooldown = policy().cooldown;
+
+        let mut breakers = match BREAKERS.lock() {
+            Ok(breakers) => breakers,
+            Err(e) => {
+                // never block calls because of a bug in the breaker
+                tracing::error!("Could not lock circuit breakers: {}", e);
+                return Ok(());
+            }
+        };
+
+        let breaker = breakers.entry(self.name.clone()).or_default();
+
+        let allowed = match breaker.state {
+            State::Closed => true,
+            State::Open => breaker.opened_at.is_none_or(|t| elapsed(t, cooldown)),
+            // a probe that never reported back is replaced after the cooldown
+            State::HalfOpen => breaker.probe_started.is_none_or(|t| elapsed(t, cooldown)),
+        };
+
+        if !allowed {
+            breaker.rejected += 1;
+
+            return Err(Error::UpstreamUnavailable(format!(
+                "{} is unavailable - its circuit breaker opened after {} consecutive failures. Last error: {}",
+                self.name, breaker.failures, breaker.last_error
+            )));
+        }
+
+        if breaker.state != State::Closed {
+            tracing::info!("Circuit breaker {} is half-open - probing", self.name);
+            breaker.state = State::HalfOpen;
+            breaker.probe_started = Some(Utc::now());
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Record that a call to the service succeeded, closing the breaker
+    ///
+    pub fn success(&self) {
+        match BREAKERS.lock() {
+            Ok(mut breakers) => {
+                let breaker = breakers.entry(self.name.clone()).or_default();
+
+                if breaker.state != State::Closed {
+                    tracing::info!("Circuit breaker {} closed - the service is back", self.name);
+                }
+
+                breaker.state = State::Closed;
+                breaker.failures = 0;
+                breaker.opened_at = None;
+                breaker.probe_started = None;
+            }
+            Err(e) => tracing::error!("Could not lock circuit breakers: {}", e),
+        }
+    }
+
+    ///
+    /// Record that a call to the service failed because of `error`.
+    /// This opens the breaker if this was the probe call, or if the
+    /// threshold of consecutive failures has been reached
+    ///
+    pub fn failure(&self, error: &impl std::fmt::Display) {
+        let threshold = policy().threshold;
+
+        match BREAKERS.lock() {
+            Ok(mut breakers) => {
+                let breaker = breakers.entry(self.name.clone()).or_default();
+
+                breaker.failures = breaker.failures.saturating_add(1);
+                breaker.last_error = paddington::redact::redact(error);
+
+                let opens = match breaker.state {
+                    State::Closed => breaker.failures >= threshold,
+                    State::HalfOpen => true,
+                    State::Open => false,
+                };
+
+                if opens {
+                    tracing::warn!(
+                        "Circuit breaker {} opened after {} consecutive failures: {}",
+                        self.name,
+                        breaker.failures,
+                        breaker.last_error
+                    );
+
+                    breaker.state = State::Open;
+                    breaker.opened_at = Some(Utc::now());
+                    breaker.probe_started = None;
+                }
+            }
+            Err(e) => tracing::error!("Could not lock circuit breakers: {}", e),
+        }
+    }
+
+    ///
+    /// Record that a background health probe of the service succeeded.
+    /// This doesn't close the breaker, as the probe may not exercise
+    /// the same path as real calls, but lets the next call through
+    /// as a probe without waiting for the cooldown
+    ///
+    pub fn probe_succeeded(&self) {
+        match BREAKERS.lock() {
+            Ok(mut breakers) => {
+                let breaker = breakers.entry(self.name.clone()).or_default();
+
+                if breaker.state == State::Open {
+                    tracing::info!(
+                        "Circuit breaker {} is half-open - the health probe succeeded",
+                        self.name
+                    );
+
+                    breaker.state = State::HalfOpen;
+                    breaker.probe_started = None;
+                }
+            }
+            Err(e) => tracing::error!("Could not lock circuit breakers: {}", e),
+        }
+    }
+
+    ///
+    /// Return whether the breaker is currently open or half-open
+    ///
+    pub fn is_open(&self) -> bool {
+        match BREAKERS.lock() {
+            Ok(breakers) => breakers
+                .get(&self.name)
+                .is_some_and(|b| b.state != State::Closed),
+            Err(e) => {
+                tracing::error!("Could not lock circuit breakers: {}", e);
+                false
+            }
+        }
+    }
+}
+
+///
+/// Return the status of every circuit breaker in this agent, sorted
+/// by name
+///
+pub fn statuses() -> Vec<BreakerStatus> {
+    match BREAKERS.lock() {
+        Ok(breakers) => {
+            let mut statuses: Vec<_> = breakers
+                .iter()
+                .map(|(name, breaker)| BreakerStatus {
+                    name: name.clone(),
+                    state: breaker.state.to_string(),
+                    failures: breaker.failures,
+                    last_error: breaker.last_error.clone(),
+                    opened_at: breaker.opened_at,
+                    rejected: breaker.rejected,
+                })
+                .collect();
+
+            statuses.sort_by(|a, b| a.name.cmp(&b.name));
+            statuses
+        }
+        Err(e) => {
+            tracing::error!("Could not lock circuit breakers: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(name: &str) -> BreakerStatus {
+        statuses()
+            .into_iter()
+            .find(|s| s.name == name)
+            .unwrap_or_else(|| unreachable!("No status for breaker {}", name))
+    }
+
+    #[test]
+    fn test_policy() {
+        let policy = Policy::from_options("", "").unwrap_or_else(|e| unreachable!("{}", e));
+        assert_eq!(policy, Policy::default());
+
+        let policy = Policy::from_options("3", "2m").unwrap_or_else(|e| unreachable!("{}", e));
+        assert_eq!(policy.threshold, 3);
+        assert_eq!(policy.cooldown, Duration::from_secs(120));
+
+        assert!(Policy::from_options("0", "").is_err());
+        assert!(Policy::from_options("many", "").is_err());
+        assert!(Policy::from_options("", "soon").is_err());
+    }
+
+    #[test]
+    fn test_breaker() {
+        // uses the default policy, as the policy is global
+        let breaker = CircuitBreaker::new("test_breaker");
+
+        assert!(breaker.check().is_ok());
+        assert_eq!(status("test_breaker").state, "closed");
+
+        // failures below the threshold leave the breaker closed,
+        // and a success resets the count
+        for _ in 1..DEFAULT_THRESHOLD {
+            breaker.failure(&"connection refused");
+        }
+
+        assert!(breaker.check().is_ok());
+        breaker.success();
+        assert_eq!(status("test_breaker").failures, 0);
+
+        for _ in 0..DEFAULT_THRESHOLD {
+            breaker.failure(&"connection refused");
+        }
+
+        let s = status("test_breaker");
+        assert_eq!(s.state, "open");
+        assert_eq!(s.last_error, "connection refused");
+        assert!(s.opened_at.is_some());
+        assert!(breaker.is_open());
+
+        // calls fail fast while the breaker is open
+        match breaker.check() {
+            Err(Error::UpstreamUnavailable(_)) => {}
+            other => unreachable!("Expected UpstreamUnavailable, got {:?}", other),
+        }
+
+        assert_eq!(status("test_breaker").rejected, 1);
+
+        // a successful health probe lets a single call through
+        breaker.probe_succeeded();
+        assert_eq!(status("test_breaker").state, "half-open");
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_err());
+
+        // a failed probe opens the breaker again
+        breaker.failure(&"connection refused");
+        assert_eq!(status("test_breaker").state, "open");
+
+        // a successful probe call closes it
+        breaker.probe_succeeded();
+        assert!(breaker.check().is_ok());
+        breaker.success();
+
+        let s = status("test_breaker");
+        assert_eq!(s.state, "closed");
+        assert!(s.opened_at.is_none());
+        assert!(!breaker.is_open());
+    }
+}
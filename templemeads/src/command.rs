@@ -5,7 +5,7 @@ use crate::agent::Type as AgentType;
 use crate::agent::{self, Peer};
 use crate::board::SyncState;
 use crate::destination::Destination;
-use crate::diagnostics::DiagnosticsReport;
+use crate::diagnostics::{BoardDump, DiagnosticsReport};
 use crate::error::Error;
 use crate::health::HealthInfo;
 use crate::job::Job;
@@ -13,12 +13,33 @@ use crate::notification::Notification;
 use crate::virtual_agent::send as send_to_virtual;
 
 use anyhow::Result;
+use paddington::invite::Invite;
 use paddington::message::Message;
 use paddington::received as received_from_peer;
 use paddington::send as send_to_peer;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+///
+/// A JSON-serialised invitation holding the new keys for a connection.
+/// The keys are not shown when it is debug printed
+///
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+#[serde(transparent)]
+pub struct RotatedInvite(String);
+
+impl std::fmt::Debug for RotatedInvite {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "RotatedInvite([REDACTED])")
+    }
+}
+
+impl RotatedInvite {
+    pub fn invite(&self) -> Result<Invite, Error> {
+        Ok(serde_json::from_str(&self.0)?)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum Command {
     Error {
@@ -51,7 +72,10 @@ pub enum Command {
         health: Box<HealthInfo>,
     },
     Restart {
-        /// Type of restart: "soft" (networking only), "hard" (terminate process), etc.
+        /// Type of restart: "soft" (networking only), "hard" (terminate process)
+        /// or "reload" (re-read the config file), optionally followed by
+        /// "@HH:MM" to schedule it or "@now" to ignore maintenance windows.
+        /// "cancel" cancels a scheduled restart
         restart_type: String,
         /// Dot-separated destination path (e.g., "brics.aip2.clusters")
         /// Empty string means restart self
@@ -65,9 +89,24 @@ pub enum Command {
     DiagnosticsResponse {
         report: Box<DiagnosticsReport>,
     },
+    DumpBoardRequest {
+        /// Dot-separated destination path (e.g., "brics.aip2.clusters")
+        /// Empty string means request from self
+        destination: String,
+    },
+    DumpBoardResponse {
+        dump: Box<BoardDump>,
+    },
     Notify {
         notification: Notification,
     },
+    RotateKeys {
+        /// The new keys for the connection. This is only ever sent
+        /// over the existing (encrypted) connection to the peer
+        invite: RotatedInvite,
+    },
+    KeysRotated,
+    RequestKeyRotation,
 }
 
 impl std::fmt::Display for Command {
@@ -105,7 +144,16 @@ impl std::fmt::Display for Command {
             Command::DiagnosticsResponse { report } => {
                 write!(f, "DiagnosticsResponse: {}", report)
             }
+            Command::DumpBoardRequest { destination } => {
+                write!(f, "DumpBoardRequest: destination={}", destination)
+            }
+            Command::DumpBoardResponse { dump } => {
+                write!(f, "DumpBoardResponse: {}", dump)
+            }
             Command::Notify { notification } => write!(f, "Notify: {}", notification),
+            Command::RotateKeys { invite: _ } => write!(f, "RotateKeys"),
+            Command::KeysRotated => write!(f, "KeysRotated"),
+            Command::RequestKeyRotation => write!(f, "RequestKeyRotation"),
         }
     }
 }
@@ -178,6 +226,32 @@ impl Command {
         }
     }
 
+    pub fn dump_board_request(destination: &str) -> Self {
+        Self::DumpBoardRequest {
+            destination: destination.to_owned(),
+        }
+    }
+
+    pub fn dump_board_response(dump: BoardDump) -> Self {
+        Self::DumpBoardResponse {
+            dump: Box::new(dump),
+        }
+    }
+
+    pub fn rotate_keys(invite: &Invite) -> Result<Self, Error> {
+        Ok(Self::RotateKeys {
+            invite: RotatedInvite(serde_json::to_string(invite)?),
+        })
+    }
+
+    pub fn keys_rotated() -> Self {
+        Self::KeysRotated
+    }
+
+    pub fn request_key_rotation() -> Self {
+        Self::RequestKeyRotation
+    }
+
     pub fn notify(notification: &Notification) -> Self {
         Self::Notify {
             notification: notification.clone(),
@@ -241,7 +315,12 @@ impl Command {
             } => None,
             Command::DiagnosticsRequest { destination: _ } => None,
             Command::DiagnosticsResponse { report: _ } => None,
+            Command::DumpBoardRequest { destination: _ } => None,
+            Command::DumpBoardResponse { dump: _ } => None,
             Command::Notify { notification: _ } => None,
+            Command::RotateKeys { invite: _ } => None,
+            Command::KeysRotated => None,
+            Command::RequestKeyRotation => None,
         }
     }
 
@@ -265,7 +344,12 @@ impl Command {
             } => None,
             Command::DiagnosticsRequest { destination: _ } => None,
             Command::DiagnosticsResponse { report: _ } => None,
+            Command::DumpBoardRequest { destination: _ } => None,
+            Command::DumpBoardResponse { dump: _ } => None,
             Command::Notify { notification: _ } => None,
+            Command::RotateKeys { invite: _ } => None,
+            Command::KeysRotated => None,
+            Command::RequestKeyRotation => None,
         }
     }
 
@@ -289,7 +373,12 @@ impl Command {
             } => None,
             Command::DiagnosticsRequest { destination: _ } => None,
             Command::DiagnosticsResponse { report: _ } => None,
+            Command::DumpBoardRequest { destination: _ } => None,
+            Command::DumpBoardResponse { dump: _ } => None,
             Command::Notify { notification } => Some(notification.destination().clone()),
+            Command::RotateKeys { invite: _ } => None,
+            Command::KeysRotated => None,
+            Command::RequestKeyRotation => None,
         }
     }
 }
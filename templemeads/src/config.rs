@@ -1,10 +1,52 @@
 // SPDX-FileCopyrightText: © 2024 Christopher Woods <Christopher.Woods@bristol.ac.uk>
 // SPDX-License-Identifier: MIT
 
+use tracing_subscriber::layer::Layered;
 use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::Registry;
+use tracing_subscriber::{reload, EnvFilter};
 
-use crate::diagnostics::RingBufferLayer;
+use crate::configcheck::ConfigCheck;
+use crate::destination::Destination;
+use crate::diagnostics::{self, RingBufferLayer};
+use crate::error::Error;
+use crate::health;
+use crate::logsinks::{self, Redacting, SinkLayer};
+use crate::maintenance::{self, MaintenanceWindows};
+use crate::portalquota::{self, PortalQuotas};
+use crate::role::{self, PeerRoles};
+use crate::systeminfo::{self, ResourceLimits};
+use crate::watchdog;
 
+use once_cell::sync::{Lazy, OnceCell};
+use paddington::config::ServiceConfig;
+use serde::de::DeserializeOwned;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+
+/// Handle used to change the log filter while the agent is running
+type FilterHandle = reload::Handle<EnvFilter, Layered<Vec<SinkLayer>, Registry>>;
+
+static LOG_FILTER: OnceCell<FilterHandle> = OnceCell::new();
+
+///
+/// Start logging to stdout. The level is set by RUST_LOG (default INFO),
+/// and the format by RUST_LOG_FORMAT, which can be "json", "pretty" or
+/// (the default) plain text. In JSON format, every line logged while a
+/// job is processed has a "span" object holding the job_id, instruction,
+/// destination and peer, so that the logs of a job can be followed
+/// across agents in e.g. Loki or Elasticsearch.
+///
+/// Logs can also be written to rotated files, syslog or Loki, as set
+/// by the RUST_LOG_FILE, RUST_LOG_SYSLOG and RUST_LOG_LOKI environment
+/// variables (see the logsinks module).
+///
+/// Secrets are redacted from every log line, wherever it is written.
+///
+/// The level can be changed later by the `log-level` config option,
+/// which can be reloaded while the agent is running.
+///
 pub fn initialise_tracing() {
     // make sure that we default to "INFO" if the RUST_LOG environment variable is not set
     match std::env::var("RUST_LOG") {
@@ -14,17 +56,1075 @@ pub fn initialise_tracing() {
         }
     }
 
-    let base = tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::from_default_env())
-        .with(RingBufferLayer);
-
     let format = std::env::var("RUST_LOG_FORMAT")
         .map(|s| s.to_lowercase())
         .unwrap_or_default();
 
+    let (filter, handle) = reload::Layer::new(EnvFilter::from_default_env());
+    let _ = LOG_FILTER.set(handle);
+
+    let base = tracing_subscriber::registry()
+        .with(logsinks::from_env(&format))
+        .with(filter)
+        .with(RingBufferLayer);
+
+    // secrets are redacted from every line written to stdout
+    let stdout = tracing_subscriber::fmt::layer().with_writer(Redacting(std::io::stdout));
+
     match format.as_str() {
-        "json" => base.with(tracing_subscriber::fmt::layer().json()).init(),
-        "pretty" => base.with(tracing_subscriber::fmt::layer().pretty()).init(),
-        _ => base.with(tracing_subscriber::fmt::layer()).init(),
+        "json" => base
+            .with(stdout.json().with_current_span(true).with_span_list(false))
+            .init(),
+        "pretty" => base.with(stdout.pretty()).init(),
+        _ => base.with(stdout).init(),
+    }
+}
+
+///
+/// Set the log filter, e.g. "debug" or "info,templemeads=debug".
+/// An empty level restores the filter set by RUST_LOG
+///
+fn set_log_level(level: &str) -> Result<(), Error> {
+    let filter = match level.trim() {
+        "" => EnvFilter::from_default_env(),
+        level => EnvFilter::try_new(level)
+            .map_err(|e| Error::Parse(format!("Invalid log-level '{}': {}", level, e)))?,
+    };
+
+    match LOG_FILTER.get() {
+        Some(handle) => handle
+            .reload(filter)
+            .map_err(|e| Error::Misconfigured(format!("Could not change the log level: {}", e))),
+        None => {
+            tracing::debug!("Logging was not initialised, so the log level cannot be changed");
+            Ok(())
+        }
+    }
+}
+
+///
+/// The config options shared by every agent (except the bridge) that
+/// are applied again when the config file is reloaded. They are grouped
+/// by the subsystem that is reconfigured when any of them change
+///
+const RELOADABLE: &[(&str, &[&str])] = &[
+    ("log", &["log-level"]),
+    (
+        "diagnostics",
+        &["diagnostics-interval", "diagnostics-retention"],
+    ),
+    (
+        "slow-jobs",
+        &[
+            "slow-job-threshold",
+            "slow-job-thresholds",
+            "slow-job-samples",
+        ],
+    ),
+    ("alerts", &["alert-rules", "alert-destination"]),
+    (
+        "limits",
+        &[
+            "memory-soft-limit",
+            "memory-hard-limit",
+            "cpu-soft-limit",
+            "cpu-hard-limit",
+        ],
+    ),
+    ("watchdog", &["watchdog-deadline"]),
+    ("roles", &["peer-roles", "default-role"]),
+    (
+        "quotas",
+        &[
+            "portal-max-projects",
+            "portal-max-users",
+            "portal-max-allocation",
+        ],
+    ),
+    ("maintenance", &["maintenance-windows", "drain-timeout"]),
+];
+
+fn option(extras: &HashMap<String, String>, key: &str, default: &str) -> String {
+    match extras.get(key) {
+        Some(value) => value.clone(),
+        None => default.to_owned(),
+    }
+}
+
+///
+/// Configure the named group of reloadable options from the
+/// passed extra config options
+///
+async fn apply_group(group: &str, extras: &HashMap<String, String>) -> Result<(), Error> {
+    match group {
+        "log" => set_log_level(&option(extras, "log-level", "")),
+        "diagnostics" => {
+            // every agent keeps a rolling history of its diagnostics counts
+            diagnostics::set_history(
+                option(
+                    extras,
+                    "diagnostics-interval",
+                    &diagnostics::DEFAULT_HISTORY_INTERVAL.to_string(),
+                )
+                .parse()
+                .unwrap_or(diagnostics::DEFAULT_HISTORY_INTERVAL),
+                option(
+                    extras,
+                    "diagnostics-retention",
+                    &diagnostics::DEFAULT_HISTORY_RETENTION.to_string(),
+                )
+                .parse()
+                .unwrap_or(diagnostics::DEFAULT_HISTORY_RETENTION),
+            )
+            .await;
+            Ok(())
+        }
+        "slow-jobs" => {
+            // every agent tracks its slowest jobs, with configurable
+            // thresholds for each type of instruction
+            diagnostics::set_slow_jobs(
+                health::parse_duration(&option(
+                    extras,
+                    "slow-job-threshold",
+                    &diagnostics::DEFAULT_SLOW_JOB_THRESHOLD.to_string(),
+                ))?,
+                diagnostics::parse_slow_job_thresholds(&option(extras, "slow-job-thresholds", ""))?,
+                option(
+                    extras,
+                    "slow-job-samples",
+                    &diagnostics::DEFAULT_SLOW_JOB_SAMPLES.to_string(),
+                )
+                .parse()
+                .unwrap_or(diagnostics::DEFAULT_SLOW_JOB_SAMPLES),
+            );
+            Ok(())
+        }
+        "alerts" => {
+            // every agent can raise alerts when its health crosses a threshold
+            let alert_rules = health::parse_alert_rules(&option(extras, "alert-rules", ""))?;
+
+            let alert_destination = match option(extras, "alert-destination", "").trim() {
+                "" => None,
+                destination => Some(Destination::parse(destination)?),
+            };
+
+            health::spawn_alert_monitor(alert_rules, alert_destination);
+            Ok(())
+        }
+        "limits" => {
+            // every agent can limit the memory and CPU that it uses
+            systeminfo::set_limits(ResourceLimits {
+                memory_soft: systeminfo::parse_memory_limit(&option(
+                    extras,
+                    "memory-soft-limit",
+                    "",
+                ))?,
+                memory_hard: systeminfo::parse_memory_limit(&option(
+                    extras,
+                    "memory-hard-limit",
+                    "",
+                ))?,
+                cpu_soft: systeminfo::parse_cpu_limit(&option(extras, "cpu-soft-limit", ""))?,
+                cpu_hard: systeminfo::parse_cpu_limit(&option(extras, "cpu-hard-limit", ""))?,
+            });
+            Ok(())
+        }
+        "watchdog" => {
+            // every agent can soft restart itself if it stops making progress
+            watchdog::spawn(
+                option(extras, "watchdog-deadline", "0")
+                    .parse()
+                    .unwrap_or(0),
+            );
+            Ok(())
+        }
+        "roles" => {
+            // every agent limits the instructions that each peer can send
+            role::set_peer_roles(PeerRoles::parse(
+                &option(extras, "peer-roles", ""),
+                &option(extras, "default-role", ""),
+            )?);
+            Ok(())
+        }
+        "quotas" => {
+            // portal and cluster agents cap what each portal can create
+            portalquota::set_portal_quotas(PortalQuotas::parse(
+                &option(extras, "portal-max-projects", ""),
+                &option(extras, "portal-max-users", ""),
+                &option(extras, "portal-max-allocation", ""),
+            )?);
+            Ok(())
+        }
+        "maintenance" => {
+            // every agent can limit soft and hard restarts to maintenance windows
+            maintenance::set_windows(
+                MaintenanceWindows::parse(&option(extras, "maintenance-windows", ""))?,
+                health::parse_duration(&option(
+                    extras,
+                    "drain-timeout",
+                    &maintenance::DEFAULT_DRAIN_TIMEOUT.to_string(),
+                ))?,
+            );
+            Ok(())
+        }
+        _ => Err(Error::Bug(format!("Unknown config group '{}'", group))),
+    }
+}
+
+///
+/// Apply all of the reloadable options shared by every agent
+/// (except the bridge) from the passed extra config options
+///
+pub(crate) async fn apply_options(extras: &HashMap<String, String>) -> Result<(), Error> {
+    for (group, _) in RELOADABLE {
+        apply_group(group, extras).await?;
+    }
+
+    Ok(())
+}
+
+///
+/// Check all of the reloadable options shared by every agent, recording
+/// every problem against its option. Numbers that cannot be parsed are
+/// reported here, even though they fall back to their defaults when
+/// the options are applied
+///
+pub(crate) fn check_options(extras: &HashMap<String, String>, check: &mut ConfigCheck) {
+    let log_level = option(extras, "log-level", "");
+
+    if !log_level.trim().is_empty() {
+        check.check("log-level", EnvFilter::try_new(log_level.trim()));
+    }
+
+    check.check_number::<u64>(
+        "diagnostics-interval",
+        &option(extras, "diagnostics-interval", ""),
+    );
+    check.check_number::<u64>(
+        "diagnostics-retention",
+        &option(extras, "diagnostics-retention", ""),
+    );
+    check.check(
+        "slow-job-threshold",
+        health::parse_duration(&option(
+            extras,
+            "slow-job-threshold",
+            &diagnostics::DEFAULT_SLOW_JOB_THRESHOLD.to_string(),
+        )),
+    );
+    check.check(
+        "slow-job-thresholds",
+        diagnostics::parse_slow_job_thresholds(&option(extras, "slow-job-thresholds", "")),
+    );
+    check.check_number::<usize>("slow-job-samples", &option(extras, "slow-job-samples", ""));
+    check.check(
+        "alert-rules",
+        health::parse_alert_rules(&option(extras, "alert-rules", "")),
+    );
+
+    match option(extras, "alert-destination", "").trim() {
+        "" => {}
+        destination => {
+            check.check("alert-destination", Destination::parse(destination));
+        }
+    }
+
+    for key in ["memory-soft-limit", "memory-hard-limit"] {
+        check.check(
+            key,
+            systeminfo::parse_memory_limit(&option(extras, key, "")),
+        );
+    }
+
+    for key in ["cpu-soft-limit", "cpu-hard-limit"] {
+        check.check(key, systeminfo::parse_cpu_limit(&option(extras, key, "")));
+    }
+
+    check.check_number::<u64>(
+        "watchdog-deadline",
+        &option(extras, "watchdog-deadline", ""),
+    );
+    check.check(
+        "peer-roles",
+        PeerRoles::parse(&option(extras, "peer-roles", ""), ""),
+    );
+    check.check(
+        "default-role",
+        PeerRoles::parse("", &option(extras, "default-role", "")),
+    );
+    check.check(
+        "portal-max-projects",
+        PortalQuotas::parse(&option(extras, "portal-max-projects", ""), "", ""),
+    );
+    check.check(
+        "portal-max-users",
+        PortalQuotas::parse("", &option(extras, "portal-max-users", ""), ""),
+    );
+    check.check(
+        "portal-max-allocation",
+        PortalQuotas::parse("", "", &option(extras, "portal-max-allocation", "")),
+    );
+    check.check(
+        "maintenance-windows",
+        MaintenanceWindows::parse(&option(extras, "maintenance-windows", "")),
+    );
+    check.check(
+        "drain-timeout",
+        health::parse_duration(&option(
+            extras,
+            "drain-timeout",
+            &maintenance::DEFAULT_DRAIN_TIMEOUT.to_string(),
+        )),
+    );
+}
+
+///
+/// The result of reloading the config file, reporting whether each
+/// changed option was applied
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReloadReport {
+    /// Changed options that were applied to the running agent
+    pub reloaded: Vec<String>,
+    /// Changed options that only take effect when the agent is restarted
+    pub requires_restart: Vec<String>,
+    /// Changed options that could not be applied, with the reason
+    pub failed: Vec<(String, String)>,
+}
+
+impl ReloadReport {
+    ///
+    /// Return whether no options were changed
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.reloaded.is_empty() && self.requires_restart.is_empty() && self.failed.is_empty()
+    }
+}
+
+impl std::fmt::Display for ReloadReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no options changed");
+        }
+
+        let mut parts = Vec::new();
+
+        if !self.reloaded.is_empty() {
+            parts.push(format!("reloaded {}", self.reloaded.join(", ")));
+        }
+
+        if !self.requires_restart.is_empty() {
+            parts.push(format!(
+                "restart needed for {}",
+                self.requires_restart.join(", ")
+            ));
+        }
+
+        if !self.failed.is_empty() {
+            parts.push(format!(
+                "failed {}",
+                self.failed
+                    .iter()
+                    .map(|(field, reason)| format!("{} ({})", field, reason))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+
+        write!(f, "{}", parts.join("; "))
+    }
+}
+
+///
+/// Prefix of the environment variables that override fields of the
+/// config file. The rest of the name is the path to the field, with
+/// "__" between sections, e.g. OPENPORTAL__SERVICE__URL or
+/// OPENPORTAL__EXTRAS__SLURM_SERVER
+///
+pub const ENV_PREFIX: &str = "OPENPORTAL__";
+
+///
+/// Section of the environment overrides that holds secret extras,
+/// e.g. OPENPORTAL__SECRETS__FREEIPA_PASSWORD. These are encrypted
+/// as they are added to the extras
+///
+const ENV_SECRETS: &str = "SECRETS";
+
+///
+/// Return the overrides (path and value) set in the environment,
+/// sorted by path
+///
+fn env_overrides() -> Vec<(String, String)> {
+    let mut overrides: Vec<(String, String)> = std::env::vars_os()
+        .filter_map(|(name, value)| {
+            let path = name.to_str()?.strip_prefix(ENV_PREFIX)?.to_owned();
+            Some((path, value.into_string().ok()?))
+        })
+        .collect();
+
+    overrides.sort();
+    overrides
+}
+
+///
+/// Return the name of the field in the table that the section of an
+/// environment variable refers to. Field names match ignoring case,
+/// with "-" matching "_". New extras use "-", as is conventional
+///
+fn field_name(table: &toml::Table, segment: &str, is_extra: bool) -> String {
+    match table
+        .keys()
+        .find(|key| key.to_uppercase().replace('-', "_") == segment)
+    {
+        Some(key) => key.clone(),
+        None => match is_extra {
+            true => segment.to_lowercase().replace('_', "-"),
+            false => segment.to_lowercase(),
+        },
+    }
+}
+
+///
+/// Convert the value of an environment variable to the type of the
+/// field that it overrides. Extras are always strings, while values
+/// for new fields are parsed as TOML if possible (e.g. numbers)
+///
+fn override_value(
+    existing: Option<&toml::Value>,
+    value: &str,
+    is_extra: bool,
+) -> Option<toml::Value> {
+    let parsed = || {
+        toml::from_str::<toml::Table>(&format!("value = {}", value))
+            .ok()
+            .and_then(|mut table| table.remove("value"))
+    };
+
+    match (existing, is_extra) {
+        (_, true) | (Some(toml::Value::String(_)), false) => {
+            Some(toml::Value::String(value.to_owned()))
+        }
+        (Some(existing), false) => parsed().filter(|parsed| parsed.same_type(existing)),
+        (None, false) => Some(parsed().unwrap_or_else(|| toml::Value::String(value.to_owned()))),
+    }
+}
+
+///
+/// Apply the overrides (path and value) to the parsed config file,
+/// returning the names of the overridden fields. Secrets are skipped,
+/// as they are applied once the config has been loaded
+///
+fn apply_overrides(
+    table: &mut toml::Table,
+    overrides: &[(String, String)],
+) -> Result<Vec<String>, Error> {
+    let mut applied = Vec::new();
+
+    for (path, value) in overrides {
+        let segments: Vec<&str> = path.split("__").collect();
+
+        if segments.iter().any(|segment| segment.is_empty()) {
+            return Err(Error::Parse(format!(
+                "Invalid config override {}{}",
+                ENV_PREFIX, path
+            )));
+        }
+
+        if segments[0] == ENV_SECRETS {
+            continue;
+        }
+
+        let in_extras = segments[0] == "EXTRAS";
+        let (last, sections) = match segments.split_last() {
+            Some(split) => split,
+            None => continue,
+        };
+
+        let mut current = &mut *table;
+        let mut names = Vec::new();
+
+        for segment in sections {
+            let name = field_name(current, segment, false);
+            names.push(name.clone());
+
+            current = match current
+                .entry(name)
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            {
+                toml::Value::Table(section) => section,
+                _ => {
+                    return Err(Error::Parse(format!(
+                        "Cannot override {}{} as '{}' is not a section",
+                        ENV_PREFIX,
+                        path,
+                        names.join(".")
+                    )))
+                }
+            };
+        }
+
+        let is_extra = in_extras && !sections.is_empty();
+        let name = field_name(current, last, is_extra);
+        names.push(name.clone());
+
+        // the value is not included in errors as it may be secret
+        match override_value(current.get(&name), value, is_extra) {
+            Some(value) => {
+                current.insert(name, value);
+            }
+            None => {
+                return Err(Error::Parse(format!(
+                    "The value of {}{} is not the right type for '{}'",
+                    ENV_PREFIX,
+                    path,
+                    names.join(".")
+                )))
+            }
+        }
+
+        applied.push(names.join("."));
+    }
+
+    Ok(applied)
+}
+
+///
+/// Return the secret extras (key and plain text value) that are set
+/// in the environment, using the names of any matching existing extras
+///
+pub(crate) fn env_secrets(extras: &HashMap<String, String>) -> Vec<(String, String)> {
+    let existing: toml::Table = extras
+        .keys()
+        .map(|key| (key.clone(), toml::Value::Boolean(true)))
+        .collect();
+
+    env_overrides()
+        .into_iter()
+        .filter_map(|(path, value)| {
+            let key = path.strip_prefix(ENV_SECRETS)?.strip_prefix("__")?;
+            Some((field_name(&existing, key, true), value))
+        })
+        .collect()
+}
+
+///
+/// Read and parse the config file, as it is on disk
+///
+fn read_table(config_file: &Path) -> Result<toml::Table, Error> {
+    let text = std::fs::read_to_string(config_file)?;
+
+    toml::from_str(&text).map_err(|e| {
+        Error::Parse(format!(
+            "Could not parse config file {}: {}",
+            config_file.display(),
+            e
+        ))
+    })
+}
+
+///
+/// Read and parse the config file, applying any overrides that are
+/// set in OPENPORTAL__ environment variables
+///
+fn load_table(config_file: &Path) -> Result<toml::Table, Error> {
+    let mut table = read_table(config_file)?;
+
+    for field in apply_overrides(&mut table, &env_overrides())? {
+        tracing::info!("Config field '{}' is set by the environment", field);
+    }
+
+    Ok(table)
+}
+
+///
+/// Load the config file, applying any overrides that are set in
+/// OPENPORTAL__ environment variables, so that agents can be configured
+/// in containers without templating their config files
+///
+pub fn load<C>(config_file: &Path) -> Result<C, Error>
+where
+    C: DeserializeOwned,
+{
+    toml::Value::Table(load_table(config_file)?)
+        .try_into()
+        .map_err(|e| {
+            Error::Parse(format!(
+                "Could not load config file {}: {}",
+                config_file.display(),
+                e
+            ))
+        })
+}
+
+///
+/// The fields of a config file, keyed by name. Extra options are
+/// keyed by their own name, fields of the service section by
+/// "service.<field>", and all other fields by their name
+///
+type Fields = BTreeMap<String, toml::Value>;
+
+///
+/// Split a config file into its fields, also returning the extra options
+///
+fn parse_fields(table: toml::Table) -> (Fields, HashMap<String, String>) {
+    let mut fields = Fields::new();
+    let mut extras = HashMap::new();
+
+    for (key, value) in table {
+        match (key.as_str(), value) {
+            ("extras", toml::Value::Table(options)) => {
+                for (option, value) in options {
+                    if let Some(value) = value.as_str() {
+                        extras.insert(option.clone(), value.to_owned());
+                    }
+
+                    fields.insert(option, value);
+                }
+            }
+            ("service", toml::Value::Table(service)) => {
+                for (field, value) in service {
+                    fields.insert(format!("service.{}", field), value);
+                }
+            }
+            (_, value) => {
+                fields.insert(key, value);
+            }
+        }
+    }
+
+    (fields, extras)
+}
+
+///
+/// Return the names of the fields that differ between the two configs
+///
+fn changed_fields(old: &Fields, new: &Fields) -> Vec<String> {
+    let mut changed: Vec<String> = old
+        .keys()
+        .chain(new.keys())
+        .filter(|field| old.get(*field) != new.get(*field))
+        .cloned()
+        .collect();
+
+    changed.sort();
+    changed.dedup();
+    changed
+}
+
+///
+/// Return the group of reloadable options that contains this field,
+/// or None if changing the field needs a restart
+///
+fn reloadable_group(field: &str) -> Option<&'static str> {
+    RELOADABLE
+        .iter()
+        .find(|(_, options)| options.contains(&field))
+        .map(|(group, _)| *group)
+}
+
+struct WatchedConfig {
+    config_file: PathBuf,
+    /// The fields when the agent started
+    initial: Fields,
+    /// The fields when the config was last loaded
+    current: Fields,
+}
+
+static WATCHED: Lazy<Mutex<Option<WatchedConfig>>> = Lazy::new(|| Mutex::new(None));
+
+///
+/// Return the config file that this agent was started with, or None
+/// if the agent is not running from a config file
+///
+pub async fn config_file() -> Option<PathBuf> {
+    WATCHED
+        .lock()
+        .await
+        .as_ref()
+        .map(|watched| watched.config_file.clone())
+}
+
+///
+/// Remember the config file of this agent so that it can be reloaded,
+/// and reload it whenever the agent receives SIGHUP
+///
+pub(crate) async fn watch(config_file: &Path) -> Result<(), Error> {
+    let (fields, _) = parse_fields(load_table(config_file)?);
+
+    *WATCHED.lock().await = Some(WatchedConfig {
+        config_file: config_file.to_path_buf(),
+        initial: fields.clone(),
+        current: fields,
+    });
+
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            tracing::error!(
+                "Could not listen for SIGHUP - config reload disabled: {}",
+                e
+            );
+            return Ok(());
+        }
+    };
+
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            tracing::info!("Received SIGHUP - reloading config");
+
+            if let Err(e) = reload().await {
+                tracing::error!("Could not reload config: {}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+///
+/// Reload the config file of this agent, applying any changed options
+/// that can be changed while the agent is running. Options that need
+/// a restart are reported, and raise a health warning until the agent
+/// is restarted (or the option is changed back)
+///
+pub async fn reload() -> Result<ReloadReport, Error> {
+    let mut watched = WATCHED.lock().await;
+
+    let Some(watched) = watched.as_mut() else {
+        return Err(Error::Misconfigured(
+            "This agent does not support reloading its config".to_owned(),
+        ));
+    };
+
+    let (fields, extras) = parse_fields(load_table(&watched.config_file)?);
+
+    let changed = changed_fields(&watched.current, &fields);
+
+    let mut report = ReloadReport::default();
+    let mut groups: Vec<&str> = Vec::new();
+
+    for field in &changed {
+        match reloadable_group(field) {
+            Some(group) => {
+                if !groups.contains(&group) {
+                    groups.push(group);
+                }
+            }
+            None => report.requires_restart.push(field.clone()),
+        }
+    }
+
+    for group in groups {
+        let fields_in_group = changed
+            .iter()
+            .filter(|field| reloadable_group(field) == Some(group))
+            .cloned();
+
+        match apply_group(group, &extras).await {
+            Ok(()) => report.reloaded.extend(fields_in_group),
+            Err(e) => report
+                .failed
+                .extend(fields_in_group.map(|field| (field, e.to_string()))),
+        }
+    }
+
+    for field in &report.reloaded {
+        tracing::info!("Reloaded config option '{}'", field);
+    }
+
+    for field in &report.requires_restart {
+        tracing::warn!(
+            "Config option '{}' has changed, but needs a restart to take effect",
+            field
+        );
+    }
+
+    for (field, reason) in &report.failed {
+        tracing::error!("Could not reload config option '{}': {}", field, reason);
+    }
+
+    let pending: Vec<String> = changed_fields(&watched.initial, &fields)
+        .into_iter()
+        .filter(|field| reloadable_group(field).is_none())
+        .collect();
+
+    match pending.is_empty() {
+        true => health::clear_warning("config:restart"),
+        false => health::set_warning(
+            "config:restart",
+            &format!(
+                "Config options changed that need a restart to take effect: {}",
+                pending.join(", ")
+            ),
+        ),
+    }
+
+    watched.current = fields;
+
+    tracing::info!(
+        "Reloaded config from {}: {}",
+        watched.config_file.display(),
+        report
+    );
+
+    Ok(report)
+}
+
+/// The fields of the service section that hold the peers and their keys
+const PEER_FIELDS: &[&str] = &["service.servers", "service.clients", "service.sealed_peers"];
+
+fn service_from(table: &toml::Table) -> Result<ServiceConfig, Error> {
+    table
+        .get("service")
+        .cloned()
+        .ok_or_else(|| Error::Parse("The config file has no service section".to_owned()))?
+        .try_into()
+        .map_err(|e| Error::Parse(format!("Could not read the service section: {}", e)))
+}
+
+///
+/// Return the service section of this agent's config file, as it is
+/// on disk (without any environment overrides)
+///
+pub(crate) async fn service() -> Result<ServiceConfig, Error> {
+    let watched = WATCHED.lock().await;
+
+    let Some(watched) = watched.as_ref() else {
+        return Err(Error::Misconfigured(
+            "This agent does not know its config file".to_owned(),
+        ));
+    };
+
+    service_from(&read_table(&watched.config_file)?)
+}
+
+///
+/// Change the service section of this agent's config file while the
+/// agent is running, e.g. to save rotated peer keys. The rest of the
+/// file is left as it is. As the running agent already uses the changed
+/// peers, they are not reported as needing a restart on reload
+///
+pub(crate) async fn update_service<F, R>(update: F) -> Result<R, Error>
+where
+    F: FnOnce(&mut ServiceConfig) -> Result<R, Error>,
+{
+    let mut watched = WATCHED.lock().await;
+
+    let Some(watched) = watched.as_mut() else {
+        return Err(Error::Misconfigured(
+            "This agent cannot change its config file while it is running".to_owned(),
+        ));
+    };
+
+    let mut table = read_table(&watched.config_file)?;
+    let mut service = service_from(&table)?;
+
+    let result = update(&mut service)?;
+
+    table.insert(
+        "service".to_owned(),
+        toml::Value::try_from(&service)
+            .map_err(|e| Error::Parse(format!("Could not write the service section: {}", e)))?,
+    );
+
+    paddington::config::save(&table, &watched.config_file)?;
+
+    let (fields, _) = parse_fields(table);
+
+    for snapshot in [&mut watched.initial, &mut watched.current] {
+        for field in PEER_FIELDS {
+            match fields.get(*field) {
+                Some(value) => snapshot.insert(field.to_string(), value.clone()),
+                None => snapshot.remove(*field),
+            };
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_fields() {
+        let old = r#"
+            agent = "Instance"
+            partition = "gpu"
+
+            [service]
+            name = "cluster"
+            port = 8042
+
+            [extras]
+            log-level = "info"
+            slow-job-threshold = "10s"
+        "#;
+
+        let new = r#"
+            agent = "Instance"
+            partition = "cpu"
+
+            [service]
+            name = "cluster"
+            port = 8043
+
+            [extras]
+            log-level = "debug"
+            watchdog-deadline = "300"
+        "#;
+
+        #[allow(clippy::unwrap_used)]
+        let (old, _) = parse_fields(toml::from_str(old).unwrap());
+        #[allow(clippy::unwrap_used)]
+        let (new, extras) = parse_fields(toml::from_str(new).unwrap());
+
+        assert_eq!(extras.get("log-level"), Some(&"debug".to_owned()));
+
+        let changed = changed_fields(&old, &new);
+
+        assert_eq!(
+            changed,
+            vec![
+                "log-level",
+                "partition",
+                "service.port",
+                "slow-job-threshold",
+                "watchdog-deadline"
+            ]
+        );
+
+        assert_eq!(reloadable_group("log-level"), Some("log"));
+        assert_eq!(reloadable_group("slow-job-threshold"), Some("slow-jobs"));
+        assert_eq!(reloadable_group("watchdog-deadline"), Some("watchdog"));
+        assert_eq!(reloadable_group("peer-roles"), Some("roles"));
+        assert_eq!(reloadable_group("portal-max-users"), Some("quotas"));
+        assert_eq!(reloadable_group("drain-timeout"), Some("maintenance"));
+        assert_eq!(reloadable_group("partition"), None);
+        assert_eq!(reloadable_group("service.port"), None);
+    }
+
+    #[test]
+    fn test_check_options() {
+        let invalid = [
+            ("log-level", "info,templemeads=loud"),
+            ("diagnostics-interval", "often"),
+            ("diagnostics-retention", "-1"),
+            ("slow-job-threshold", "10y"),
+            ("slow-job-thresholds", "add_user"),
+            ("slow-job-samples", "many"),
+            ("alert-rules", "slow_jobs"),
+            ("alert-destination", "portal"),
+            ("memory-soft-limit", "-5%"),
+            ("memory-hard-limit", "lots"),
+            ("cpu-soft-limit", "0"),
+            ("cpu-hard-limit", "half"),
+            ("watchdog-deadline", "5m"),
+            ("peer-roles", "portal"),
+            ("default-role", "owner"),
+            ("portal-max-projects", "waldur"),
+            ("portal-max-users", "waldur:many"),
+            ("portal-max-allocation", "waldur:100"),
+            ("maintenance-windows", "Funday 02:00-04:00"),
+            ("drain-timeout", "soon"),
+        ];
+
+        // every reloadable option must be checked
+        let mut options: Vec<&str> = RELOADABLE
+            .iter()
+            .flat_map(|(_, options)| options.iter().copied())
+            .collect();
+        let mut checked: Vec<&str> = invalid.iter().map(|(option, _)| *option).collect();
+        options.sort_unstable();
+        checked.sort_unstable();
+        assert_eq!(options, checked);
+
+        let mut check = ConfigCheck::new();
+        check_options(&HashMap::new(), &mut check);
+        assert!(check.is_empty());
+
+        for (option, value) in invalid {
+            let extras = HashMap::from([(option.to_owned(), value.to_owned())]);
+
+            let mut check = ConfigCheck::new();
+            check_options(&extras, &mut check);
+
+            let fields: Vec<String> = check.issues().iter().map(|issue| issue.field()).collect();
+            assert_eq!(
+                fields,
+                vec![option],
+                "{} = '{}' was not reported",
+                option,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply_overrides() {
+        let config = r#"
+            agent = "Instance"
+
+            [service]
+            name = "cluster"
+            url = "ws://localhost:8046"
+            port = 8046
+
+            [extras]
+            slurm-server = "localhost"
+        "#;
+
+        #[allow(clippy::unwrap_used)]
+        let mut table: toml::Table = toml::from_str(config).unwrap();
+
+        let overrides = vec![
+            ("SERVICE__URL".to_owned(), "wss://cluster:443".to_owned()),
+            ("SERVICE__PORT".to_owned(), "443".to_owned()),
+            ("SERVICE__HEATHCHECK_PORT".to_owned(), "8080".to_owned()),
+            ("EXTRAS__SLURM_SERVER".to_owned(), "slurm".to_owned()),
+            ("EXTRAS__SLOW_JOB_SAMPLES".to_owned(), "5".to_owned()),
+            ("SECRETS__SLURM_TOKEN".to_owned(), "secret".to_owned()),
+        ];
+
+        #[allow(clippy::unwrap_used)]
+        let applied = apply_overrides(&mut table, &overrides).unwrap();
+
+        assert_eq!(
+            applied,
+            vec![
+                "service.url",
+                "service.port",
+                "service.heathcheck_port",
+                "extras.slurm-server",
+                "extras.slow-job-samples"
+            ]
+        );
+
+        let (fields, extras) = parse_fields(table.clone());
+
+        assert_eq!(
+            fields.get("service.url"),
+            Some(&toml::Value::String("wss://cluster:443".to_owned()))
+        );
+        assert_eq!(fields.get("service.port"), Some(&toml::Value::Integer(443)));
+        assert_eq!(
+            fields.get("service.heathcheck_port"),
+            Some(&toml::Value::Integer(8080))
+        );
+        assert_eq!(extras.get("slurm-server"), Some(&"slurm".to_owned()));
+        assert_eq!(extras.get("slow-job-samples"), Some(&"5".to_owned()));
+        assert!(!extras.contains_key("slurm-token"));
+
+        // values must have the type of the field that they override
+        let overrides = vec![("SERVICE__PORT".to_owned(), "https".to_owned())];
+        assert!(apply_overrides(&mut table, &overrides).is_err());
+
+        // only sections can have fields
+        let overrides = vec![("AGENT__NAME".to_owned(), "cluster".to_owned())];
+        assert!(apply_overrides(&mut table, &overrides).is_err());
+
+        let overrides = vec![("SERVICE____URL".to_owned(), "x".to_owned())];
+        assert!(apply_overrides(&mut table, &overrides).is_err());
     }
 }
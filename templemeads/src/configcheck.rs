@@ -0,0 +1,175 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Validation of a whole config file before an agent is run
+//!
+//! Running an agent with `--check-config` checks every part of its
+//! config file that can be checked without connecting to anything, and
+//! reports all of the problems that it finds against the field that
+//! each is in, rather than stopping at the first problem, or only
+//! finding it deep inside the running agent when the option is used.
+
+use crate::error::Error;
+
+///
+/// A problem found in a config file, together with the field (e.g.
+/// `service.url`, `slurm-default-node` or `user_volumes.home`) that
+/// it is in
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigIssue {
+    field: String,
+    message: String,
+}
+
+impl ConfigIssue {
+    pub fn field(&self) -> String {
+        self.field.clone()
+    }
+
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+}
+
+impl std::fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+///
+/// Collects all of the problems found while checking a config file
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigCheck {
+    issues: Vec<ConfigIssue>,
+}
+
+impl ConfigCheck {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///
+    /// Record a problem with the passed field
+    ///
+    pub fn error(&mut self, field: &str, message: &str) {
+        self.issues.push(ConfigIssue {
+            field: field.to_owned(),
+            message: message.to_owned(),
+        });
+    }
+
+    ///
+    /// Record the error (if any) of the passed result against the
+    /// passed field, returning the value if there was no error
+    ///
+    pub fn check<R, E: std::fmt::Display>(
+        &mut self,
+        field: &str,
+        result: Result<R, E>,
+    ) -> Option<R> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.error(field, &e.to_string());
+                None
+            }
+        }
+    }
+
+    ///
+    /// Check that the passed field, if it is set, is a valid URL
+    ///
+    pub fn check_url(&mut self, field: &str, url: &str) {
+        let url = url.trim();
+
+        if url.is_empty() {
+            return;
+        }
+
+        if let Err(e) = url::Url::parse(url) {
+            self.error(field, &format!("Invalid URL '{}': {}", url, e));
+        }
+    }
+
+    ///
+    /// Check that the passed field, if it is set, is a number of type R
+    ///
+    pub fn check_number<R: std::str::FromStr>(&mut self, field: &str, value: &str) {
+        let value = value.trim();
+
+        if !value.is_empty() && value.parse::<R>().is_err() {
+            self.error(field, &format!("Invalid number '{}'", value));
+        }
+    }
+
+    pub fn issues(&self) -> &[ConfigIssue] {
+        &self.issues
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    ///
+    /// Return an error listing all of the problems found, if there
+    /// were any
+    ///
+    pub fn into_result(self) -> Result<(), Error> {
+        match self.issues.is_empty() {
+            true => Ok(()),
+            false => Err(Error::InvalidConfig(
+                self.issues
+                    .iter()
+                    .map(|issue| issue.to_string())
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_check() {
+        let mut check = ConfigCheck::new();
+
+        assert_eq!(check.check("port", "8042".parse::<u16>()), Some(8042));
+        check.check_url("waldur-url", "https://waldur.example.com/api/");
+        check.check_url("ldap-url", "");
+        check.check_number::<u64>("diagnostics-interval", "60");
+        assert!(check.is_empty());
+        assert!(check.clone().into_result().is_ok());
+
+        assert_eq!(check.check("port", "eighty".parse::<u16>()), None);
+        check.check_url("waldur-url", "waldur.example.com");
+        check.check_number::<u64>("diagnostics-interval", "-1");
+        check.error(
+            "user_volumes.home",
+            "User volume must have at least one root directory",
+        );
+
+        let fields: Vec<String> = check.issues().iter().map(|issue| issue.field()).collect();
+        assert_eq!(
+            fields,
+            vec![
+                "port",
+                "waldur-url",
+                "diagnostics-interval",
+                "user_volumes.home"
+            ]
+        );
+
+        assert_eq!(
+            check.issues()[3].to_string(),
+            "user_volumes.home: User volume must have at least one root directory"
+        );
+
+        assert!(check.into_result().is_err());
+    }
+}
@@ -4,6 +4,7 @@
 use crate::agent::{Peer, Type as AgentType};
 use crate::command::Command;
 use crate::error::Error;
+use crate::health;
 use crate::job;
 
 use anyhow::Result;
@@ -22,6 +23,7 @@ pub async fn process_control_message(
         } => {
             let peer = Peer::new(&agent, &zone);
             tracing::info!("Connected to agent: {}", peer);
+            health::record_connected(&peer).await;
             Command::register(
                 agent_type,
                 env!("CARGO_PKG_NAME"),
@@ -46,6 +48,7 @@ pub async fn process_control_message(
         ControlCommand::Disconnected { agent, zone } => {
             let peer = Peer::new(&agent, &zone);
             tracing::info!("Disconnected from agent: {}", peer);
+            health::record_disconnected(&peer).await;
         }
         ControlCommand::Error { error } => {
             tracing::error!("Received error: {}", error);
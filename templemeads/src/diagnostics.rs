@@ -6,14 +6,19 @@
 //! This module provides real-time tracking of job failures, slow executions,
 //! expirations, and other diagnostic information useful for remote troubleshooting.
 
+use crate::admin;
 use crate::agent;
 use crate::command::Command;
+use crate::error::Error;
 use crate::grammar::NamedType;
+use crate::health;
 use crate::job::Job;
+use crate::state;
 use chrono::{DateTime, Utc};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
 use tokio::sync::RwLock;
 use ts_rs::TS;
@@ -30,6 +35,27 @@ const MAX_EXPIRED_JOBS: usize = 200;
 /// Maximum number of log entries to retain in the ring buffer
 const MAX_LOG_ENTRIES: usize = 500;
 
+/// Maximum number of watchdog restarts to track
+const MAX_WATCHDOG_RESTARTS: usize = 50;
+
+/// Default threshold (in seconds) above which a job is slow
+pub const DEFAULT_SLOW_JOB_THRESHOLD: u64 = 10;
+
+/// Default number of the slowest jobs of each instruction type to track
+pub const DEFAULT_SLOW_JOB_SAMPLES: usize = 20;
+
+/// Default length (in seconds) of each interval of the diagnostics history
+pub const DEFAULT_HISTORY_INTERVAL: u64 = 300;
+
+/// Default number of hours of diagnostics history to retain
+pub const DEFAULT_HISTORY_RETENTION: u64 = 24;
+
+/// Length (in seconds) of each interval of the diagnostics history
+static HISTORY_INTERVAL: AtomicU64 = AtomicU64::new(DEFAULT_HISTORY_INTERVAL);
+
+/// Number of hours of diagnostics history to retain
+static HISTORY_RETENTION: AtomicU64 = AtomicU64::new(DEFAULT_HISTORY_RETENTION);
+
 /// Job statistics totals for all time
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, TS)]
 #[ts(export)]
@@ -80,6 +106,37 @@ pub struct DiagnosticsReport {
     /// Notification send/receive/failure totals
     #[serde(default)]
     pub notification_statistics: NotificationStatistics,
+    /// Job and notification counts in each interval of the retained
+    /// history (oldest first, ending with the current interval)
+    #[serde(default)]
+    pub history: Vec<DiagnosticsCounts>,
+    /// Soft restarts performed automatically by the watchdog (most recent first)
+    #[serde(default)]
+    pub watchdog_restarts: Vec<WatchdogRestart>,
+}
+
+/// Job and notification counts for one interval of the diagnostics history
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, TS)]
+#[ts(export)]
+pub struct DiagnosticsCounts {
+    /// Start of the interval
+    pub start: DateTime<Utc>,
+    /// End of the interval
+    pub end: DateTime<Utc>,
+    /// Number of jobs that completed successfully
+    pub completed: usize,
+    /// Number of jobs that failed
+    pub failed: usize,
+    /// Number of jobs that expired
+    pub expired: usize,
+    /// Number of slow jobs (>10s duration)
+    pub slow: usize,
+    /// Number of notifications received
+    pub notifications_received: usize,
+    /// Number of notifications sent
+    pub notifications_sent: usize,
+    /// Number of notifications that failed to deliver
+    pub notifications_failed: usize,
 }
 
 /// Entry for a failed job
@@ -160,12 +217,56 @@ pub struct LogEntry {
     pub message: String,
 }
 
+/// Record of a soft restart performed automatically by the watchdog
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, TS)]
+#[ts(export)]
+pub struct WatchdogRestart {
+    /// When the restart was performed
+    pub restarted_at: DateTime<Utc>,
+    /// Why the watchdog decided that the agent was wedged
+    pub reason: String,
+}
+
+/// Snapshot of every job on the board shared with one peer
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, TS)]
+#[ts(export)]
+pub struct BoardSnapshot {
+    /// Name of the peer that shares this board
+    pub peer: String,
+    /// Zone of the peer that shares this board
+    pub zone: String,
+    /// Every job on the board (oldest first), including its state
+    /// and its created, changed and expires timestamps
+    pub jobs: Vec<Job>,
+    /// Jobs that are queued waiting for the connection to the peer to re-open
+    pub queued: Vec<Job>,
+}
+
+/// Full dump of the contents of an agent's boards, used to debug
+/// stuck pipelines
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, TS)]
+#[ts(export)]
+pub struct BoardDump {
+    /// Agent name
+    pub agent_name: String,
+    /// When this dump was generated
+    pub generated_at: DateTime<Utc>,
+    /// The boards shared with each of the agent's peers
+    pub boards: Vec<BoardSnapshot>,
+}
+
 impl NamedType for DiagnosticsReport {
     fn type_name() -> &'static str {
         "DiagnosticsReport"
     }
 }
 
+impl NamedType for DiagnosticsCounts {
+    fn type_name() -> &'static str {
+        "DiagnosticsCounts"
+    }
+}
+
 impl NamedType for FailedJobEntry {
     fn type_name() -> &'static str {
         "FailedJobEntry"
@@ -190,6 +291,12 @@ impl NamedType for RunningJobEntry {
     }
 }
 
+impl NamedType for WatchdogRestart {
+    fn type_name() -> &'static str {
+        "WatchdogRestart"
+    }
+}
+
 impl NamedType for LogEntry {
     fn type_name() -> &'static str {
         "LogEntry"
@@ -233,6 +340,35 @@ struct RunningJobData {
     count: usize,
 }
 
+impl DiagnosticsCounts {
+    fn new(start: DateTime<Utc>) -> Self {
+        Self {
+            start,
+            end: start + chrono::Duration::seconds(history_interval() as i64),
+            completed: 0,
+            failed: 0,
+            expired: 0,
+            slow: 0,
+            notifications_received: 0,
+            notifications_sent: 0,
+            notifications_failed: 0,
+        }
+    }
+}
+
+/// Return the length (in seconds) of each interval of the history
+fn history_interval() -> u64 {
+    HISTORY_INTERVAL.load(Ordering::Relaxed).max(1)
+}
+
+/// Return the number of completed intervals of history to retain
+fn history_length() -> usize {
+    let retention = HISTORY_RETENTION
+        .load(Ordering::Relaxed)
+        .saturating_mul(3600);
+    (retention / history_interval()).max(1) as usize
+}
+
 /// Global diagnostics tracker
 struct DiagnosticsTracker {
     /// Failed jobs, deduplicated by destination+instruction
@@ -256,6 +392,10 @@ struct DiagnosticsTracker {
     total_notifications_received: usize,
     total_notifications_sent: usize,
     total_notifications_failed: usize,
+    /// Counts of the completed intervals of the history, oldest first
+    history: VecDeque<DiagnosticsCounts>,
+    /// Counts of the current interval of the history
+    current: DiagnosticsCounts,
 }
 
 impl DiagnosticsTracker {
@@ -274,15 +414,40 @@ impl DiagnosticsTracker {
             total_notifications_received: 0,
             total_notifications_sent: 0,
             total_notifications_failed: 0,
+            history: VecDeque::new(),
+            current: DiagnosticsCounts::new(Utc::now()),
         }
     }
 
+    /// Move on to the interval of the history that contains `now`,
+    /// dropping intervals that are older than the retention period
+    fn roll_history(&mut self, now: DateTime<Utc>) {
+        let max_intervals = history_length();
+
+        while now >= self.current.end {
+            let next = DiagnosticsCounts::new(self.current.end);
+            self.history
+                .push_back(std::mem::replace(&mut self.current, next));
+
+            while self.history.len() > max_intervals {
+                self.history.pop_front();
+            }
+        }
+    }
+
+    /// Return the counts of the current interval of the history
+    fn current_counts(&mut self) -> &mut DiagnosticsCounts {
+        self.roll_history(Utc::now());
+        &mut self.current
+    }
+
     fn record_failed_job(&mut self, job: &Job, error_message: String) {
         let key = JobKey::from_job(job);
         let now = Utc::now();
 
         // Increment total failed jobs counter
         self.total_jobs_failed += 1;
+        self.current_counts().failed += 1;
 
         if let Some(data) = self.failed_jobs.get_mut(&key) {
             // Update existing entry
@@ -312,20 +477,51 @@ impl DiagnosticsTracker {
     }
 
     fn record_slow_job(&mut self, job: &Job, duration_ms: f64) {
-        if duration_ms < SLOW_JOB_THRESHOLD_MS {
+        let instruction = job.instruction().to_string();
+
+        if !is_slow_job(&instruction, duration_ms) {
             return;
         }
 
         // Increment total slow jobs counter
         self.total_jobs_slow += 1;
+        self.current_counts().slow += 1;
 
         let entry = SlowJobEntry {
             destination: job.destination().to_string(),
-            instruction: job.instruction().to_string(),
+            instruction,
             duration_ms,
             completed_at: Utc::now(),
         };
 
+        // Only keep a sample of the slowest jobs of each instruction type,
+        // so that a flood of one type (e.g. usage reports) cannot evict
+        // genuinely anomalous jobs of other types
+        let samples = slow_job_samples();
+
+        if samples > 0 {
+            let this_type = instruction_type(&entry.instruction);
+
+            let same_type: Vec<usize> = self
+                .slowest_jobs
+                .iter()
+                .enumerate()
+                .filter(|(_, e)| instruction_type(&e.instruction) == this_type)
+                .map(|(i, _)| i)
+                .collect();
+
+            if same_type.len() >= samples {
+                // the list is sorted, so the last is the fastest of this type
+                if let Some(&fastest) = same_type.last() {
+                    if self.slowest_jobs[fastest].duration_ms >= duration_ms {
+                        return;
+                    }
+
+                    self.slowest_jobs.remove(fastest);
+                }
+            }
+        }
+
         // Insert and keep sorted by duration (descending)
         self.slowest_jobs.push(entry);
         self.slowest_jobs.sort_by(|a, b| {
@@ -345,6 +541,7 @@ impl DiagnosticsTracker {
 
         // Increment total expired jobs counter
         self.total_jobs_expired += 1;
+        self.current_counts().expired += 1;
 
         if let Some(data) = self.expired_jobs.get_mut(&key) {
             // Update existing entry
@@ -501,6 +698,19 @@ impl DiagnosticsTracker {
 
         let notification_statistics = self.get_notification_statistics();
 
+        let mut history: Vec<DiagnosticsCounts> = self.history.iter().cloned().collect();
+        history.push(self.current.clone());
+
+        let watchdog_restarts = get_watchdog_restarts();
+
+        if let Some(last) = watchdog_restarts.first() {
+            warnings.push(format!(
+                "Watchdog has soft restarted this agent {} time(s), most recently at {}",
+                watchdog_restarts.len(),
+                last.restarted_at.format("%Y-%m-%d %H:%M:%S UTC")
+            ));
+        }
+
         DiagnosticsReport {
             agent_name: agent_name.to_string(),
             generated_at: now,
@@ -511,6 +721,8 @@ impl DiagnosticsTracker {
             warnings,
             recent_logs: get_recent_logs(0),
             notification_statistics,
+            history,
+            watchdog_restarts,
         }
     }
 
@@ -578,7 +790,7 @@ impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for RingBufferLayer {
             timestamp: Utc::now(),
             level: meta.level().to_string(),
             target: meta.target().to_string(),
-            message: visitor.message,
+            message: paddington::redact::redact_str(&visitor.message),
         };
 
         if let Ok(mut buf) = LOG_BUFFER.lock() {
@@ -605,23 +817,144 @@ pub fn get_recent_logs(max: usize) -> Vec<LogEntry> {
     }
 }
 
-static SLOW_JOB_THRESHOLD_MS: f64 = 10000.0; // 10 seconds
+/// Global record of watchdog restarts. This is kept separately from the
+/// tracker so that it is not cleared by the soft restart itself
+static WATCHDOG_RESTARTS: Lazy<Mutex<VecDeque<WatchdogRestart>>> =
+    Lazy::new(|| Mutex::new(VecDeque::new()));
+
+/// Record that the watchdog has soft restarted this agent
+pub fn record_watchdog_restart(reason: &str) {
+    if let Ok(mut restarts) = WATCHDOG_RESTARTS.lock() {
+        restarts.push_front(WatchdogRestart {
+            restarted_at: Utc::now(),
+            reason: reason.to_owned(),
+        });
+        restarts.truncate(MAX_WATCHDOG_RESTARTS);
+    }
+}
+
+/// Return all of the tracked watchdog restarts, most recent first
+pub fn get_watchdog_restarts() -> Vec<WatchdogRestart> {
+    match WATCHDOG_RESTARTS.lock() {
+        Ok(restarts) => restarts.iter().cloned().collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Thresholds above which jobs are slow, and how many slow jobs
+/// of each instruction type to track
+struct SlowJobSettings {
+    /// Threshold (in seconds) for instruction types without their own
+    threshold: u64,
+    /// Thresholds (in seconds) for specific instruction types
+    thresholds: HashMap<String, u64>,
+    /// Number of the slowest jobs of each instruction type to track (0 is unlimited)
+    samples: usize,
+}
+
+static SLOW_JOB_SETTINGS: Lazy<Mutex<SlowJobSettings>> = Lazy::new(|| {
+    Mutex::new(SlowJobSettings {
+        threshold: DEFAULT_SLOW_JOB_THRESHOLD,
+        thresholds: HashMap::new(),
+        samples: DEFAULT_SLOW_JOB_SAMPLES,
+    })
+});
+
+/// Return the instruction type (e.g. "add_user") of the passed instruction
+fn instruction_type(instruction: &str) -> &str {
+    instruction.split_whitespace().next().unwrap_or_default()
+}
+
+/// Return whether a job running `instruction` that took `duration_ms` is slow
+fn is_slow_job(instruction: &str, duration_ms: f64) -> bool {
+    let threshold = match SLOW_JOB_SETTINGS.lock() {
+        Ok(settings) => settings
+            .thresholds
+            .get(instruction_type(instruction))
+            .copied()
+            .unwrap_or(settings.threshold),
+        Err(_) => DEFAULT_SLOW_JOB_THRESHOLD,
+    };
+
+    duration_ms > (threshold as f64) * 1000.0
+}
+
+/// Return the number of the slowest jobs of each instruction type to track
+fn slow_job_samples() -> usize {
+    match SLOW_JOB_SETTINGS.lock() {
+        Ok(settings) => settings.samples,
+        Err(_) => DEFAULT_SLOW_JOB_SAMPLES,
+    }
+}
+
+///
+/// Parse the per-instruction slow job thresholds, written as a
+/// comma-separated list of `instruction=duration`, e.g.
+/// "get_usage_report=2m, add_user=5s". Returns the thresholds in seconds.
+///
+pub fn parse_slow_job_thresholds(thresholds: &str) -> Result<HashMap<String, u64>, Error> {
+    let mut parsed = HashMap::new();
+
+    for threshold in thresholds.split(',') {
+        let threshold = threshold.trim();
+
+        if threshold.is_empty() {
+            continue;
+        }
+
+        let (instruction, duration) = threshold.split_once('=').ok_or_else(|| {
+            Error::Parse(format!(
+                "Invalid slow job threshold '{}'. It should be 'instruction=duration'",
+                threshold
+            ))
+        })?;
+
+        let instruction = instruction.trim();
+
+        if instruction.is_empty() || instruction.contains(char::is_whitespace) {
+            return Err(Error::Parse(format!(
+                "Invalid instruction type '{}' in slow job threshold '{}'",
+                instruction, threshold
+            )));
+        }
+
+        parsed.insert(instruction.to_owned(), health::parse_duration(duration)?);
+    }
+
+    Ok(parsed)
+}
+
+///
+/// Set the threshold (in seconds) above which a job is slow, any
+/// thresholds for specific instruction types, and the number of the
+/// slowest jobs of each instruction type to track (0 is unlimited)
+///
+pub fn set_slow_jobs(threshold: u64, thresholds: HashMap<String, u64>, samples: usize) {
+    if let Ok(mut settings) = SLOW_JOB_SETTINGS.lock() {
+        settings.threshold = threshold;
+        settings.thresholds = thresholds;
+        settings.samples = samples;
+    }
+}
 
 /// Record a failed job
 pub async fn record_failed_job(job: &Job, error_message: String) {
+    admin::publish("failed", job);
     let mut tracker = DIAGNOSTICS.write().await;
     tracker.record_failed_job(job, error_message);
 }
 
 /// Record a completed (successful) job
-pub async fn record_completed_job(_job: &Job) {
+pub async fn record_completed_job(job: &Job) {
+    admin::publish("completed", job);
     let mut tracker = DIAGNOSTICS.write().await;
     tracker.total_jobs_completed += 1;
+    tracker.current_counts().completed += 1;
 }
 
 /// Record a slow job completion
 pub async fn record_slow_job(job: &Job, duration_ms: f64) {
-    if duration_ms > SLOW_JOB_THRESHOLD_MS {
+    if is_slow_job(&job.instruction().to_string(), duration_ms) {
         let mut tracker = DIAGNOSTICS.write().await;
         tracker.record_slow_job(job, duration_ms);
     }
@@ -629,12 +962,14 @@ pub async fn record_slow_job(job: &Job, duration_ms: f64) {
 
 /// Record an expired job
 pub async fn record_expired_job(job: &Job) {
+    admin::publish("expired", job);
     let mut tracker = DIAGNOSTICS.write().await;
     tracker.record_expired_job(job);
 }
 
 /// Record when a job starts running
 pub async fn record_job_started(job: &Job) {
+    admin::publish("started", job);
     let mut tracker = DIAGNOSTICS.write().await;
     tracker.record_job_started(job);
 }
@@ -647,10 +982,25 @@ pub async fn record_job_finished(job: &Job) {
 
 /// Generate a diagnostics report
 pub async fn generate_report(agent_name: &str) -> DiagnosticsReport {
-    let tracker = DIAGNOSTICS.read().await;
+    let mut tracker = DIAGNOSTICS.write().await;
+    tracker.roll_history(Utc::now());
     tracker.generate_report(agent_name)
 }
 
+///
+/// Set the length (in seconds) of each interval of the diagnostics
+/// history, and the number of hours of history to retain. Any history
+/// that has already been recorded is discarded.
+///
+pub async fn set_history(interval: u64, retention_hours: u64) {
+    HISTORY_INTERVAL.store(interval.max(1), Ordering::Relaxed);
+    HISTORY_RETENTION.store(retention_hours, Ordering::Relaxed);
+
+    let mut tracker = DIAGNOSTICS.write().await;
+    tracker.history.clear();
+    tracker.current = DiagnosticsCounts::new(Utc::now());
+}
+
 /// Get job statistics totals
 pub async fn get_job_statistics() -> JobStatistics {
     let tracker = DIAGNOSTICS.read().await;
@@ -659,22 +1009,30 @@ pub async fn get_job_statistics() -> JobStatistics {
 
 /// Record a notification received by this agent (inbound from the network)
 pub async fn increment_notification_received() {
-    DIAGNOSTICS.write().await.total_notifications_received += 1;
+    let mut tracker = DIAGNOSTICS.write().await;
+    tracker.total_notifications_received += 1;
+    tracker.current_counts().notifications_received += 1;
 }
 
 /// Record a notification successfully sent (delivered to next hop or web portal)
 pub async fn increment_notification_sent() {
-    DIAGNOSTICS.write().await.total_notifications_sent += 1;
+    let mut tracker = DIAGNOSTICS.write().await;
+    tracker.total_notifications_sent += 1;
+    tracker.current_counts().notifications_sent += 1;
 }
 
 /// Record a notification that failed to deliver after all retries
 pub async fn increment_notification_failed() {
-    DIAGNOSTICS.write().await.total_notifications_failed += 1;
+    let mut tracker = DIAGNOSTICS.write().await;
+    tracker.total_notifications_failed += 1;
+    tracker.current_counts().notifications_failed += 1;
 }
 
 /// Bulk-increment the failed notification counter (e.g. when clearing a full queue)
 pub async fn add_notifications_failed(count: usize) {
-    DIAGNOSTICS.write().await.total_notifications_failed += count;
+    let mut tracker = DIAGNOSTICS.write().await;
+    tracker.total_notifications_failed += count;
+    tracker.current_counts().notifications_failed += count;
 }
 
 /// Clear all diagnostics data (used during soft restart)
@@ -715,47 +1073,51 @@ pub async fn get_cached_diagnostics(agent_name: &str) -> Option<DiagnosticsRepor
 }
 
 ///
-/// Wait for a diagnostics response from a specific agent
+/// Wait for a response from a specific agent to arrive in the passed cache
 ///
-/// Polls the cache until the agent has an updated diagnostics report since baseline_time,
-/// or until the timeout expires.
+/// Polls the cache until the agent has an entry that was generated since
+/// baseline_time, or until the timeout expires.
 ///
 /// Parameters:
+/// - `cache`: The cache into which responses are placed as they arrive
 /// - `agent_name`: The name of the agent we're waiting for a response from
-/// - `baseline_time`: Only accept reports generated after this time
+/// - `baseline_time`: Only accept responses generated after this time
 /// - `timeout`: How long to wait before giving up
+/// - `generated_at`: Returns when a cached response was generated
 ///
-/// Returns the cached report if found, None otherwise.
+/// Returns the cached response if found, None otherwise.
 ///
-async fn wait_for_diagnostics_response(
+async fn wait_for_response<T: Clone>(
+    cache: &RwLock<HashMap<String, T>>,
     agent_name: &str,
     baseline_time: DateTime<Utc>,
     timeout: std::time::Duration,
-) -> Option<DiagnosticsReport> {
+    generated_at: impl Fn(&T) -> DateTime<Utc>,
+) -> Option<T> {
     let start = tokio::time::Instant::now();
     let deadline = start + timeout;
 
     loop {
-        // Check if we have an updated diagnostics report since baseline
-        let cache = DIAGNOSTICS_CACHE.read().await;
-        if let Some(report) = cache.get(agent_name) {
-            if report.generated_at > baseline_time {
+        // Check if we have an updated response since baseline
+        let entries = cache.read().await;
+        if let Some(response) = entries.get(agent_name) {
+            if generated_at(response) > baseline_time {
                 tracing::debug!(
-                    "Diagnostics response received from {} in {:?}",
+                    "Response received from {} in {:?}",
                     agent_name,
                     start.elapsed()
                 );
-                let result = report.clone();
-                drop(cache);
+                let result = response.clone();
+                drop(entries);
                 return Some(result);
             }
         }
-        drop(cache);
+        drop(entries);
 
         // Check if we've exceeded timeout
         if tokio::time::Instant::now() >= deadline {
             tracing::debug!(
-                "Diagnostics request timeout: no response from {} within {:?}",
+                "Request timeout: no response from {} within {:?}",
                 agent_name,
                 timeout
             );
@@ -768,17 +1130,33 @@ async fn wait_for_diagnostics_response(
 }
 
 ///
-/// Collect diagnostics from a specific agent or self
+/// How a request addressed to a dot-separated destination path
+/// should be handled by this agent
 ///
-/// This function sends a diagnostics request to the specified destination and waits
-/// for the response (up to 5 seconds). If no destination is provided, generates a report for self.
+enum Route {
+    /// This agent is the target of the request
+    Local,
+    /// The request has been forwarded to `peer` with the remaining path,
+    /// and the response will be generated by the agent called `target`
+    Forwarded {
+        peer: agent::Peer,
+        remaining_path: String,
+        target: String,
+    },
+}
+
+///
+/// Work out whether this agent is the target of a request addressed to
+/// `destination`. If it isn't, then wait for the next peer in the path
+/// to be ready, and return that peer, the remaining path and the name
+/// of the agent that will ultimately respond. The caller is responsible
+/// for sending the request on to the peer.
 ///
 /// Parameters:
 /// - `destination`: Dot-separated path to target agent (e.g., "provider.cluster"), empty means self
+/// - `request`: Name of the type of request, used in log and error messages
 ///
-/// Returns the diagnostics report or an error if the request fails or times out.
-///
-pub async fn collect_diagnostics(destination: &str) -> Result<DiagnosticsReport, anyhow::Error> {
+async fn route(destination: &str, request: &str) -> Result<Route, anyhow::Error> {
     let my_name = agent::get_self(None).await.name().to_owned();
 
     // Parse the destination path
@@ -788,7 +1166,7 @@ pub async fn collect_diagnostics(destination: &str) -> Result<DiagnosticsReport,
         destination.split('.').collect()
     };
 
-    // Check if we are the target for this diagnostics request
+    // Check if we are the target for this request
     let is_target = if destination_parts.is_empty() {
         // Empty destination means request from the agent that received the request
         true
@@ -806,167 +1184,282 @@ pub async fn collect_diagnostics(destination: &str) -> Result<DiagnosticsReport,
     };
 
     if is_target {
-        let report = generate_report(&my_name).await;
+        return Ok(Route::Local);
+    }
 
-        tracing::debug!("Diagnostics report: {}", report);
+    // Check if this agent is allowed to forward requests
+    // Leaf nodes (like FreeIPA or Filesystem) have cascade_health=false and should not forward
+    if !agent::should_cascade_health().await {
+        tracing::warn!(
+            "Cannot forward {} request - this agent is a leaf node (cascade disabled)",
+            request
+        );
+        return Err(anyhow::anyhow!(
+            "Leaf node agents cannot forward {} requests",
+            request
+        ));
+    }
 
-        Ok(report)
-    } else {
-        // Check if this agent is allowed to forward diagnostics requests
-        // Leaf nodes (like FreeIPA or Filesystem) have cascade_health=false and should not forward
-        if !agent::should_cascade_health().await {
-            tracing::warn!(
-                "Cannot forward diagnostics request - this agent is a leaf node (cascade disabled)"
-            );
+    // We need to forward the request to the next peer in the path
+    // Parse the next hop, which may include a zone specifier (name@zone)
+    let next_hop = destination_parts[0];
+    let (next_peer_name, zone_filter) = if next_hop.contains('@') {
+        let parts: Vec<&str> = next_hop.split('@').collect();
+        if parts.len() == 2 {
+            (parts[0], Some(parts[1]))
+        } else {
+            tracing::error!("Invalid format for agent specification: {}", next_hop);
             return Err(anyhow::anyhow!(
-                "Leaf node agents cannot forward diagnostics requests"
+                "Invalid format '{}' - use 'name' or 'name@zone'",
+                next_hop
             ));
         }
+    } else {
+        (next_hop, None)
+    };
 
-        // We need to forward the diagnostics request to the next peer in the path
-        // Parse the next hop, which may include a zone specifier (name@zone)
-        let next_hop = destination_parts[0];
-        let (next_peer_name, zone_filter) = if next_hop.contains('@') {
-            let parts: Vec<&str> = next_hop.split('@').collect();
-            if parts.len() == 2 {
-                (parts[0], Some(parts[1]))
-            } else {
-                tracing::error!("Invalid format for agent specification: {}", next_hop);
-                return Err(anyhow::anyhow!(
-                    "Invalid format '{}' - use 'name' or 'name@zone'",
-                    next_hop
-                ));
-            }
-        } else {
-            (next_hop, None)
-        };
+    let remaining_path = destination_parts[1..].join(".");
 
-        let remaining_path = destination_parts[1..].join(".");
+    tracing::debug!(
+        "Forwarding {} request to {} (remaining path: {})",
+        request,
+        next_peer_name,
+        remaining_path
+    );
 
-        tracing::debug!(
-            "Forwarding diagnostics request to {} (remaining path: {})",
-            next_peer_name,
-            remaining_path
-        );
+    // Find the peer to forward to
+    let all_peers = agent::all_peers().await;
+    let next_peer = if let Some(required_zone) = zone_filter {
+        // Find peer with matching name AND zone
+        all_peers
+            .iter()
+            .find(|p| p.name() == next_peer_name && p.zone() == required_zone)
+    } else {
+        // Find first peer with matching name (any zone)
+        all_peers.iter().find(|p| p.name() == next_peer_name)
+    };
 
-        // Find the peer to forward to
-        let all_peers = agent::all_peers().await;
-        let next_peer = if let Some(required_zone) = zone_filter {
-            // Find peer with matching name AND zone
-            all_peers
-                .iter()
-                .find(|p| p.name() == next_peer_name && p.zone() == required_zone)
+    let Some(next_peer) = next_peer else {
+        let error_msg = if let Some(zone) = zone_filter {
+            format!(
+                "Cannot find peer {} in zone {} to forward {} request to",
+                next_peer_name, zone, request
+            )
         } else {
-            // Find first peer with matching name (any zone)
-            all_peers.iter().find(|p| p.name() == next_peer_name)
+            format!(
+                "Cannot find peer {} to forward {} request to",
+                next_peer_name, request
+            )
         };
+        tracing::error!("{}", error_msg);
+        return Err(anyhow::anyhow!("{}", error_msg));
+    };
 
-        if let Some(next_peer) = next_peer {
-            // Security: If we're a portal, don't forward to other portals
-            if agent::my_agent_type().await == agent::Type::Portal {
-                if let Some(peer_type) = agent::agent_type(next_peer).await {
-                    if peer_type == agent::Type::Portal {
-                        tracing::error!(
-                            "Cannot forward diagnostics to portal {} - portals do not share diagnostics with other portals",
-                            next_peer_name
-                        );
-                        return Err(anyhow::anyhow!(
-                            "Portals cannot forward diagnostics requests to other portals"
-                        ));
-                    }
-                }
+    // Security: If we're a portal, don't forward to other portals
+    if agent::my_agent_type().await == agent::Type::Portal {
+        if let Some(peer_type) = agent::agent_type(next_peer).await {
+            if peer_type == agent::Type::Portal {
+                tracing::error!(
+                    "Cannot forward {} to portal {} - portals do not share {} with other portals",
+                    request,
+                    next_peer_name,
+                    request
+                );
+                return Err(anyhow::anyhow!(
+                    "Portals cannot forward {} requests to other portals",
+                    request
+                ));
             }
+        }
+    }
 
-            // Wait for the peer to be ready
-            agent::wait_for(next_peer, 30).await?;
+    // Wait for the peer to be ready
+    agent::wait_for(next_peer, 30).await?;
 
-            // Record baseline time before sending request
-            let baseline_time = Utc::now();
+    // Extract the ultimate target agent name from the destination path
+    // This is the agent that will actually generate the response
+    // For "cluster.filesystem", the ultimate target is "filesystem"
+    // For "cluster", the ultimate target is "cluster"
+    let ultimate_target = if remaining_path.is_empty() {
+        // Next peer is the ultimate target
+        next_peer_name
+    } else {
+        // Find the last component in the remaining path
+        remaining_path
+            .split('.')
+            .next_back()
+            .unwrap_or(next_peer_name)
+    };
 
-            // Forward the diagnostics command with the updated destination
-            let diagnostics_cmd = Command::diagnostics_request(&remaining_path);
-            diagnostics_cmd.send_to(next_peer).await?;
+    // Extract just the name part if it includes @zone
+    let ultimate_target_name = if ultimate_target.contains('@') {
+        ultimate_target.split('@').next().unwrap_or(ultimate_target)
+    } else {
+        ultimate_target
+    };
 
-            tracing::debug!(
-                "Forwarded diagnostics request to {} in zone {}, waiting for response...",
-                next_peer_name,
-                next_peer.zone()
-            );
+    let target = ultimate_target_name.to_owned();
 
-            // Extract the ultimate target agent name from the destination path
-            // This is the agent that will actually generate the report
-            // For "cluster.filesystem", the ultimate target is "filesystem"
-            // For "cluster", the ultimate target is "cluster"
-            let ultimate_target = if remaining_path.is_empty() {
-                // Next peer is the ultimate target
-                next_peer_name
-            } else {
-                // Find the last component in the remaining path
-                remaining_path
-                    .split('.')
-                    .next_back()
-                    .unwrap_or(next_peer_name)
-            };
-
-            // Extract just the name part if it includes @zone
-            let ultimate_target_name = if ultimate_target.contains('@') {
-                ultimate_target.split('@').next().unwrap_or(ultimate_target)
-            } else {
-                ultimate_target
-            };
-
-            // Wait for the response (with 500ms timeout, or use cached if available)
-            let report = wait_for_diagnostics_response(
-                ultimate_target_name,
-                baseline_time,
-                std::time::Duration::from_millis(500),
-            )
-            .await;
+    Ok(Route::Forwarded {
+        peer: next_peer.clone(),
+        remaining_path,
+        target,
+    })
+}
 
-            if let Some(report) = report {
-                tracing::debug!(
-                    "Received diagnostics response from {}",
-                    ultimate_target_name,
-                );
-                Ok(report)
-            } else {
-                // Check if we have any cached report (even if old)
-                if let Some(cached_report) = get_cached_diagnostics(ultimate_target_name).await {
-                    tracing::warn!(
-                        "Timeout waiting for fresh diagnostics from {}, returning cached response (age: {}s)",
-                        ultimate_target_name,
-                        Utc::now().signed_duration_since(cached_report.generated_at).num_seconds(),
-                    );
-                    Ok(cached_report)
-                } else {
-                    tracing::warn!(
-                        "No diagnostics response received from {} and no cached data available",
-                        ultimate_target_name
-                    );
-                    Err(anyhow::anyhow!(
-                        "No diagnostics response received from {}",
-                        ultimate_target_name
-                    ))
-                }
-            }
+///
+/// Collect diagnostics from a specific agent or self
+///
+/// This function sends a diagnostics request to the specified destination and waits
+/// for the response (up to 500ms). If no destination is provided, generates a report for self.
+///
+/// Parameters:
+/// - `destination`: Dot-separated path to target agent (e.g., "provider.cluster"), empty means self
+///
+/// Returns the diagnostics report or an error if the request fails or times out.
+///
+pub async fn collect_diagnostics(destination: &str) -> Result<DiagnosticsReport, anyhow::Error> {
+    let (next_peer, remaining_path, target) = match route(destination, "diagnostics").await? {
+        Route::Local => {
+            let my_name = agent::get_self(None).await.name().to_owned();
+            let report = generate_report(&my_name).await;
+
+            tracing::debug!("Diagnostics report: {}", report);
+
+            return Ok(report);
+        }
+        Route::Forwarded {
+            peer,
+            remaining_path,
+            target,
+        } => (peer, remaining_path, target),
+    };
+
+    // Record baseline time before sending request
+    let baseline_time = Utc::now();
+
+    // Forward the diagnostics command with the updated destination
+    let diagnostics_cmd = Command::diagnostics_request(&remaining_path);
+    diagnostics_cmd.send_to(&next_peer).await?;
+
+    tracing::debug!(
+        "Forwarded diagnostics request to {} in zone {}, waiting for response...",
+        next_peer.name(),
+        next_peer.zone()
+    );
+
+    // Wait for the response (with 500ms timeout, or use cached if available)
+    let report = wait_for_response(
+        &DIAGNOSTICS_CACHE,
+        &target,
+        baseline_time,
+        std::time::Duration::from_millis(500),
+        |report| report.generated_at,
+    )
+    .await;
+
+    if let Some(report) = report {
+        tracing::debug!("Received diagnostics response from {}", target);
+        Ok(report)
+    } else {
+        // Check if we have any cached report (even if old)
+        if let Some(cached_report) = get_cached_diagnostics(&target).await {
+            tracing::warn!(
+                "Timeout waiting for fresh diagnostics from {}, returning cached response (age: {}s)",
+                target,
+                Utc::now().signed_duration_since(cached_report.generated_at).num_seconds(),
+            );
+            Ok(cached_report)
         } else {
-            let error_msg = if let Some(zone) = zone_filter {
-                format!(
-                    "Cannot find peer {} in zone {} to forward diagnostics request to",
-                    next_peer_name, zone
-                )
-            } else {
-                format!(
-                    "Cannot find peer {} to forward diagnostics request to",
-                    next_peer_name
-                )
-            };
-            tracing::error!("{}", error_msg);
-            Err(anyhow::anyhow!("{}", error_msg))
+            tracing::warn!(
+                "No diagnostics response received from {} and no cached data available",
+                target
+            );
+            Err(anyhow::anyhow!(
+                "No diagnostics response received from {}",
+                target
+            ))
         }
     }
 }
 
+///
+/// Global cache of board dump responses from agents
+/// Maps agent_name -> BoardDump
+///
+static BOARD_DUMP_CACHE: Lazy<RwLock<HashMap<String, BoardDump>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+///
+/// Store a board dump response in the global cache
+///
+pub async fn cache_board_dump_response(agent_name: String, dump: BoardDump) {
+    let mut cache = BOARD_DUMP_CACHE.write().await;
+    cache.insert(agent_name.clone(), dump);
+
+    tracing::debug!("Cached board dump response for agent: {}", agent_name);
+}
+
+/// Generate a dump of the contents of all of this agent's boards
+pub async fn generate_board_dump(agent_name: &str) -> BoardDump {
+    BoardDump {
+        agent_name: agent_name.to_owned(),
+        generated_at: Utc::now(),
+        boards: state::board_snapshots().await,
+    }
+}
+
+///
+/// Collect a dump of the boards of a specific agent or self
+///
+/// This works in the same way as `collect_diagnostics`, except that
+/// the dump is only useful if it is live, so an error is returned
+/// rather than an old cached dump if no response arrives in time.
+///
+/// Parameters:
+/// - `destination`: Dot-separated path to target agent (e.g., "provider.cluster"), empty means self
+///
+pub async fn collect_board_dump(destination: &str) -> Result<BoardDump, anyhow::Error> {
+    let (next_peer, remaining_path, target) = match route(destination, "board dump").await? {
+        Route::Local => {
+            let my_name = agent::get_self(None).await.name().to_owned();
+            let dump = generate_board_dump(&my_name).await;
+
+            tracing::debug!("Board dump: {}", dump);
+
+            return Ok(dump);
+        }
+        Route::Forwarded {
+            peer,
+            remaining_path,
+            target,
+        } => (peer, remaining_path, target),
+    };
+
+    let baseline_time = Utc::now();
+
+    Command::dump_board_request(&remaining_path)
+        .send_to(&next_peer)
+        .await?;
+
+    tracing::debug!(
+        "Forwarded board dump request to {} in zone {}, waiting for response...",
+        next_peer.name(),
+        next_peer.zone()
+    );
+
+    // boards can be large, so allow longer than for diagnostics
+    wait_for_response(
+        &BOARD_DUMP_CACHE,
+        &target,
+        baseline_time,
+        std::time::Duration::from_secs(2),
+        |dump| dump.generated_at,
+    )
+    .await
+    .ok_or_else(|| anyhow::anyhow!("No board dump response received from {}", target))
+}
+
 impl DiagnosticsReport {
     /// Return log entries in chronological order (oldest first).
     ///
@@ -995,6 +1488,22 @@ impl DiagnosticsReport {
         result
     }
 
+    /// Return the job and notification counts of each interval of the
+    /// history in chronological order (oldest first), e.g. to see whether
+    /// failures are increasing or were a one-off spike.
+    ///
+    /// - `minutes`: only return the intervals that overlap the last
+    ///   `minutes` before the report was generated; `0` means all.
+    pub fn history(&self, minutes: u64) -> Vec<DiagnosticsCounts> {
+        let since = self.generated_at - chrono::Duration::minutes(minutes as i64);
+
+        self.history
+            .iter()
+            .filter(|c| minutes == 0 || c.end > since)
+            .cloned()
+            .collect()
+    }
+
     /// Format diagnostics report as a human-readable string
     pub fn to_pretty_string(&self) -> String {
         let mut output = String::new();
@@ -1147,6 +1656,34 @@ impl DiagnosticsReport {
         ));
         output.push_str("│  │\n");
 
+        // History section (most recent intervals only)
+        output.push_str(&format!(
+            "│  ┌─ History ({} interval{}, showing up to 12)\n",
+            self.history.len(),
+            if self.history.len() == 1 { "" } else { "s" }
+        ));
+        if self.history.is_empty() {
+            output.push_str("│  │  No history recorded\n");
+        } else {
+            let skip = self.history.len().saturating_sub(12);
+            for counts in self.history.iter().skip(skip) {
+                output.push_str(&format!("│  │  {}\n", counts));
+            }
+        }
+        output.push_str("│  │\n");
+
+        // Watchdog restarts section (if any)
+        if !self.watchdog_restarts.is_empty() {
+            output.push_str(&format!(
+                "│  ┌─ Watchdog Restarts ({})\n",
+                self.watchdog_restarts.len()
+            ));
+            for restart in &self.watchdog_restarts {
+                output.push_str(&format!("│  │  {}\n", restart));
+            }
+            output.push_str("│  │\n");
+        }
+
         output.push_str("└─\n");
 
         output
@@ -1168,6 +1705,112 @@ impl std::fmt::Display for DiagnosticsReport {
     }
 }
 
+impl BoardDump {
+    /// Format the board dump as a human-readable string
+    pub fn to_pretty_string(&self) -> String {
+        let mut output = String::new();
+
+        output.push_str(&format!("┌─ Board Dump: {}\n", self.agent_name));
+        output.push_str(&format!(
+            "│  Generated: {}\n",
+            self.generated_at.format("%Y-%m-%d %H:%M:%S UTC")
+        ));
+        output.push_str("│\n");
+
+        if self.boards.is_empty() {
+            output.push_str("│  No boards\n");
+        }
+
+        for board in &self.boards {
+            output.push_str(&format!(
+                "│  ┌─ Board {}@{} ({} job{}, {} queued)\n",
+                board.peer,
+                board.zone,
+                board.jobs.len(),
+                if board.jobs.len() == 1 { "" } else { "s" },
+                board.queued.len()
+            ));
+
+            for job in &board.jobs {
+                output.push_str(&format!(
+                    "│  │  {} {} {} [{}]\n",
+                    job.id(),
+                    job.destination(),
+                    job.instruction(),
+                    job.state()
+                ));
+                output.push_str(&format!(
+                    "│  │     Created: {}, Changed: {}, Expires: {}\n",
+                    job.created().format("%Y-%m-%d %H:%M:%S"),
+                    job.changed().format("%Y-%m-%d %H:%M:%S"),
+                    job.expires().format("%Y-%m-%d %H:%M:%S")
+                ));
+            }
+
+            for job in &board.queued {
+                output.push_str(&format!(
+                    "│  │  {} {} {} [queued]\n",
+                    job.id(),
+                    job.destination(),
+                    job.instruction()
+                ));
+            }
+
+            output.push_str("│  │\n");
+        }
+
+        output.push_str("└─\n");
+
+        output
+    }
+}
+
+impl std::fmt::Display for BoardDump {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let num_jobs: usize = self.boards.iter().map(|b| b.jobs.len()).sum();
+
+        write!(
+            f,
+            "BoardDump for {} - {} board{}, {} job{} (generated {})",
+            self.agent_name,
+            self.boards.len(),
+            if self.boards.len() == 1 { "" } else { "s" },
+            num_jobs,
+            if num_jobs == 1 { "" } else { "s" },
+            self.generated_at.format("%Y-%m-%d %H:%M:%S UTC")
+        )
+    }
+}
+
+impl std::fmt::Display for DiagnosticsCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} - {}: {} completed, {} failed, {} expired, {} slow, notifications {} received, {} sent, {} failed",
+            self.start.format("%Y-%m-%d %H:%M:%S"),
+            self.end.format("%H:%M:%S"),
+            self.completed,
+            self.failed,
+            self.expired,
+            self.slow,
+            self.notifications_received,
+            self.notifications_sent,
+            self.notifications_failed
+        )
+    }
+}
+
+impl std::fmt::Display for WatchdogRestart {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {}",
+            self.restarted_at.format("%Y-%m-%d %H:%M:%S"),
+            self.reason
+        )
+    }
+}
+
 impl std::fmt::Display for FailedJobEntry {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
@@ -1275,3 +1918,33 @@ fn format_duration(seconds: i64) -> String {
         format!("{}s", secs)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_slow_job_thresholds() {
+        #[allow(clippy::unwrap_used)]
+        let thresholds = parse_slow_job_thresholds("get_usage_report=2m, add_user=5").unwrap();
+
+        assert_eq!(thresholds.len(), 2);
+        assert_eq!(thresholds.get("get_usage_report"), Some(&120));
+        assert_eq!(thresholds.get("add_user"), Some(&5));
+
+        #[allow(clippy::unwrap_used)]
+        let empty = parse_slow_job_thresholds("").unwrap();
+        assert!(empty.is_empty());
+
+        assert!(parse_slow_job_thresholds("add_user").is_err());
+        assert!(parse_slow_job_thresholds("add_user=soon").is_err());
+        assert!(parse_slow_job_thresholds("add user=5s").is_err());
+    }
+
+    #[test]
+    fn test_instruction_type() {
+        assert_eq!(instruction_type("add_user alice.proj.portal"), "add_user");
+        assert_eq!(instruction_type("get_projects"), "get_projects");
+        assert_eq!(instruction_type(""), "");
+    }
+}
@@ -0,0 +1,157 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Dry runs of jobs
+//!
+//! A job that is marked as a dry run is run by every agent that it
+//! passes through, but none of them make any changes. Instead, each
+//! agent records a description of the changes that it would have made,
+//! and these are returned with the job. Every job that an agent sends
+//! while running a dry run is also a dry run, so that a whole workflow
+//! (e.g. portal → cluster → FreeIPA, Slurm and the filesystem) can be
+//! rehearsed end to end, and the changes reported by downstream agents
+//! are added to those of the job that sent them.
+//!
+//! Agents that make changes (the account, filesystem and scheduler
+//! agents) must call `set_supported` to say that they honour dry runs,
+//! and then call `record` before each change, skipping the change if it
+//! returns true. Dry runs of instructions that change anything are
+//! refused by agents that have not called `set_supported`.
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::agent::Type as AgentType;
+
+struct DryRun {
+    agent: String,
+    changes: Vec<String>,
+}
+
+tokio::task_local! {
+    /// The changes recorded by the dry run job being run by this task
+    static DRY_RUN: RefCell<DryRun>;
+}
+
+static SUPPORTED: AtomicBool = AtomicBool::new(false);
+
+///
+/// Say whether or not this agent's runner honours dry runs, by
+/// recording the changes it would make rather than making them
+///
+pub fn set_supported(supported: bool) {
+    SUPPORTED.store(supported, Ordering::SeqCst);
+}
+
+///
+/// Return whether this agent can run dry runs of instructions that
+/// change anything. Agents that only route jobs to other agents can
+/// always run them
+///
+pub fn is_supported(agent_type: &AgentType) -> bool {
+    match agent_type {
+        AgentType::Account | AgentType::Filesystem | AgentType::Scheduler => {
+            SUPPORTED.load(Ordering::SeqCst)
+        }
+        _ => true,
+    }
+}
+
+///
+/// Return whether the job being run by this task is a dry run
+///
+pub fn is_dry_run() -> bool {
+    DRY_RUN.try_with(|_| ()).is_ok()
+}
+
+///
+/// Record a change that the job being run by this task would make.
+/// Returns true if the job is a dry run, in which case the caller must
+/// skip the change
+///
+pub fn record(change: &str) -> bool {
+    DRY_RUN
+        .try_with(|dry_run| {
+            let mut dry_run = dry_run.borrow_mut();
+            tracing::info!("Dry run: {} would {}", dry_run.agent, change);
+            let change = format!("{}: {}", dry_run.agent, change);
+            dry_run.changes.push(change);
+        })
+        .is_ok()
+}
+
+///
+/// Add the changes reported by a finished downstream dry run job to
+/// the changes of the job being run by this task. Changes that have
+/// already been added are skipped, as a job can be waited on more
+/// than once
+///
+pub(crate) fn merge(changes: &[String]) {
+    let _ = DRY_RUN.try_with(|dry_run| {
+        let mut dry_run = dry_run.borrow_mut();
+
+        for change in changes {
+            if !dry_run.changes.contains(change) {
+                dry_run.changes.push(change.clone());
+            }
+        }
+    });
+}
+
+///
+/// Run the passed future as a dry run on the named agent, returning its
+/// output together with all of the changes that it would have made
+///
+pub(crate) async fn run<F: Future>(agent: &str, future: F) -> (F::Output, Vec<String>) {
+    DRY_RUN
+        .scope(
+            RefCell::new(DryRun {
+                agent: agent.to_owned(),
+                changes: Vec::new(),
+            }),
+            async move {
+                let output = future.await;
+                let changes =
+                    DRY_RUN.with(|dry_run| std::mem::take(&mut dry_run.borrow_mut().changes));
+                (output, changes)
+            },
+        )
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        assert!(!is_dry_run());
+        assert!(!record("add user"));
+
+        let (output, changes) = run("freeipa", async {
+            assert!(is_dry_run());
+            assert!(record("add user alice"));
+
+            merge(&["slurm: add account project".to_owned()]);
+            merge(&["slurm: add account project".to_owned()]);
+
+            42
+        })
+        .await;
+
+        assert_eq!(output, 42);
+        assert_eq!(
+            changes,
+            vec![
+                "freeipa: add user alice".to_owned(),
+                "slurm: add account project".to_owned()
+            ]
+        );
+
+        assert!(!is_dry_run());
+
+        assert!(is_supported(&AgentType::Portal));
+        assert!(!is_supported(&AgentType::Account));
+    }
+}
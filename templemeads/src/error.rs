@@ -4,127 +4,139 @@
 use anyhow::Error as AnyError;
 use thiserror::Error;
 
+// all messages are redacted, as errors are often created by formatting
+// config, requests or responses that may contain secrets
+
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     AddrParse(#[from] std::net::AddrParseError),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     Any(#[from] AnyError),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     IO(#[from] std::io::Error),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     Paddington(#[from] paddington::Error),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     SerdeJson(#[from] serde_json::Error),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     UrlParse(#[from] url::ParseError),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     Bug(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     Call(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     ConfigExists(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     Delivery(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     Incompatible(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     IncompleteCode(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     InvalidBoard(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     InvalidConfig(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     InvalidInstruction(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     InvalidPeer(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     InvalidState(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     Duplicate(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     Failed(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     Expired(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     Locked(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     Login(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     Misconfigured(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     MissingAgent(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     MissingProject(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     MissingUser(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     NoPortal(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     InvalidPortal(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
+    PortalUnavailable(String),
+
+    #[error("{}", paddington::redact::redact(.0))]
     NotFound(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     Parse(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     PeerEdit(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     Run(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     State(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     NotReady(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     Timeout(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     Unknown(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     UnknownInstruction(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
+    Unauthorized(String),
+
+    #[error("{}", paddington::redact::redact(.0))]
     UnmanagedUser(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     UnmanagedGroup(String),
 
-    #[error("{0}")]
+    #[error("{}", paddington::redact::redact(.0))]
     Unavailable(String),
+
+    #[error("{}", paddington::redact::redact(.0))]
+    UpstreamUnavailable(String),
 }
 
 // implement into a paddington::Error
@@ -0,0 +1,50 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Running external commands (e.g. sacctmgr, lfs or the token command)
+//!
+//! Commands are run by `paddington::exec`, so that paddington can also
+//! use it (e.g. for the command that returns the encryption password).
+//! It is re-exported here, so that agents do not need to depend on
+//! paddington to run commands.
+
+pub use paddington::exec::*;
+
+use crate::error::Error;
+
+impl From<Failure> for Error {
+    fn from(failure: Failure) -> Self {
+        match failure.reason {
+            Reason::Empty => Error::Misconfigured(failure.to_string()),
+            Reason::Timeout(_) => Error::Timeout(failure.to_string()),
+            _ => Error::Call(failure.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_failure() {
+        let failure = Command::new("sleep")
+            .arg("5")
+            .timeout(Duration::from_millis(100))
+            .run()
+            .await
+            .err()
+            .unwrap_or_else(|| unreachable!("Command should have timed out"));
+
+        assert!(matches!(Error::from(failure), Error::Timeout(_)));
+
+        let failure = Command::from_parts::<&str>(&[])
+            .run()
+            .await
+            .err()
+            .unwrap_or_else(|| unreachable!("Empty command should fail"));
+
+        assert!(matches!(Error::from(failure), Error::Misconfigured(_)));
+    }
+}
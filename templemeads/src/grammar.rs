@@ -3,6 +3,7 @@
 
 use crate::destination::{Destination, Destinations};
 use crate::error::Error;
+use crate::role::Role;
 use crate::storage::{QuotaLimit, Volume};
 use crate::usagereport::Usage;
 
@@ -1597,6 +1598,12 @@ impl NamedType for Node {
     }
 }
 
+impl NamedType for Vec<Node> {
+    fn type_name() -> &'static str {
+        "Vec<Node>"
+    }
+}
+
 ///
 /// Details about an allocation to a project. This combines the
 /// size of the allocation plus the units of that allocation
@@ -2863,6 +2870,155 @@ impl std::fmt::Display for AwardDetails {
 /// New code should use AwardDetails directly.
 pub type ProjectDetails = AwardDetails;
 
+/// Details about a user that are held by a portal, and which are
+/// synchronised to the user's account. As for AwardDetails, all data
+/// is held as "option", so that only the fields that are set are
+/// updated.
+///
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct UserDetails {
+    /// The email address of the user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+
+    /// The name of the user, as it should be displayed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    display_name: Option<String>,
+
+    /// The user's preferred login shell (e.g. "/bin/bash")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    shell: Option<String>,
+}
+
+impl NamedType for UserDetails {
+    fn type_name() -> &'static str {
+        "UserDetails"
+    }
+}
+
+impl UserDetails {
+    pub fn new() -> Self {
+        Self {
+            email: None,
+            display_name: None,
+            shell: None,
+        }
+    }
+
+    pub fn parse(json: &str) -> Result<Self, Error> {
+        let details = UserDetails::from_json(json)?;
+
+        // make sure that the values are validated in the same way
+        // as when they are set
+        let mut validated = UserDetails::new();
+
+        if let Some(email) = details.email() {
+            validated.set_email(&email)?;
+        }
+
+        if let Some(display_name) = details.display_name() {
+            validated.set_display_name(&display_name)?;
+        }
+
+        if let Some(shell) = details.shell() {
+            validated.set_shell(&shell)?;
+        }
+
+        Ok(validated)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        serde_json::from_str(json).map_err(|e| Error::Parse(e.to_string()))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    ///
+    /// Return whether no details have been set
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.email.is_none() && self.display_name.is_none() && self.shell.is_none()
+    }
+
+    pub fn email(&self) -> Option<String> {
+        self.email.clone()
+    }
+
+    pub fn set_email(&mut self, email: &str) -> Result<(), Error> {
+        let email = email.trim();
+
+        match email.split_once('@') {
+            Some((user, domain))
+                if !user.is_empty()
+                    && domain.contains('.')
+                    && !email.chars().any(|c| c.is_whitespace()) =>
+            {
+                self.email = Some(email.to_string());
+                Ok(())
+            }
+            _ => Err(Error::Parse(format!("Invalid email address: '{}'", email))),
+        }
+    }
+
+    pub fn clear_email(&mut self) {
+        self.email = None;
+    }
+
+    pub fn display_name(&self) -> Option<String> {
+        self.display_name.clone()
+    }
+
+    pub fn set_display_name(&mut self, display_name: &str) -> Result<(), Error> {
+        let display_name = display_name.trim();
+
+        if display_name.is_empty() || display_name.chars().any(|c| c.is_control()) {
+            return Err(Error::Parse(format!(
+                "Invalid display name: '{}'",
+                display_name
+            )));
+        }
+
+        self.display_name = Some(display_name.to_string());
+        Ok(())
+    }
+
+    pub fn clear_display_name(&mut self) {
+        self.display_name = None;
+    }
+
+    pub fn shell(&self) -> Option<String> {
+        self.shell.clone()
+    }
+
+    pub fn set_shell(&mut self, shell: &str) -> Result<(), Error> {
+        let shell = shell.trim();
+
+        if !shell.starts_with('/')
+            || !shell
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '-' | '.'))
+        {
+            return Err(Error::Parse(format!("Invalid shell: '{}'", shell)));
+        }
+
+        self.shell = Some(shell.to_string());
+        Ok(())
+    }
+
+    pub fn clear_shell(&mut self) {
+        self.shell = None;
+    }
+}
+
+impl std::fmt::Display for UserDetails {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_json())
+    }
+}
+
 ///
 /// Enum of all of the instructions that can be sent to agents
 ///
@@ -2914,6 +3070,14 @@ pub enum Instruction {
     /// An instruction to remove a user
     RemoveUser(UserIdentifier),
 
+    /// An instruction to add a batch of users, who must all be in
+    /// the same project
+    AddUsers(Vec<UserIdentifier>),
+
+    /// An instruction to remove a batch of users, who must all be in
+    /// the same project
+    RemoveUsers(Vec<UserIdentifier>),
+
     /// An instruction to block a user from logging in without removing their
     /// account, home directory, or scheduler configuration
     BlockUser(UserIdentifier),
@@ -2957,6 +3121,14 @@ pub enum Instruction {
     /// An instruction to remove a local user
     RemoveLocalUser(UserMapping),
 
+    /// An instruction to add a batch of local users, who must all be
+    /// in the same project
+    AddLocalUsers(Vec<UserMapping>),
+
+    /// An instruction to remove a batch of local users, who must all be
+    /// in the same project
+    RemoveLocalUsers(Vec<UserMapping>),
+
     /// An instruction to add a local project
     AddLocalProject(ProjectMapping),
 
@@ -2966,11 +3138,13 @@ pub enum Instruction {
     /// An instruction to get a local project report
     GetLocalUsageReport(ProjectMapping, DateRange),
 
-    /// An instruction to get the limit of a local project
-    GetLocalLimit(ProjectMapping),
+    /// An instruction to get the limit of a local project, either
+    /// for the whole project or (if given) for a single partition
+    GetLocalLimit(ProjectMapping, Option<String>),
 
-    /// An instruction to set the limit of a local project
-    SetLocalLimit(ProjectMapping, Usage),
+    /// An instruction to set the limit of a local project, either
+    /// for the whole project or (if given) for a single partition
+    SetLocalLimit(ProjectMapping, Usage, Option<String>),
 
     /// An instruction to clear the quota of a local project on a volume
     ClearLocalProjectQuota(ProjectMapping, Volume),
@@ -3075,6 +3249,368 @@ pub enum Instruction {
 
     /// An instruction to get the list of offerings from an agent
     GetOfferings(),
+
+    /// An instruction to purge recycled directories that are older
+    /// than the retention period of their volume. If the flag is true
+    /// then this is a dry run that only reports what would be purged
+    PurgeRecycled(bool),
+
+    /// An instruction to reset the ownership, permissions and ACLs of
+    /// the directories of a project and its users to those they were
+    /// given when they were created
+    RepairPermissions(ProjectMapping),
+
+    /// An instruction to create (or update) the Slurm QOS of a project
+    /// from the QOS defined for the passed project class, and to make
+    /// it the default QOS of the project's account
+    SetLocalQos(ProjectMapping, ProjectTemplate),
+
+    /// An instruction to get the name of the default QOS of a project
+    GetLocalQos(ProjectMapping),
+
+    /// An instruction to create a Slurm reservation of the passed
+    /// number of nodes for a project, covering the passed dates. The
+    /// final date range is the project's allocation window, which the
+    /// reservation must lie within
+    CreateLocalReservation(ProjectMapping, DateRange, u32, DateRange),
+
+    /// An instruction to remove the reservation of a project that
+    /// covers the passed dates
+    RemoveLocalReservation(ProjectMapping, DateRange),
+
+    /// An instruction to get the jobs that a project has queued or
+    /// running on the scheduler
+    GetLocalJobQueue(ProjectMapping),
+
+    /// An instruction to get the jobs that a project has queued or
+    /// running on a cluster
+    GetJobQueue(ProjectIdentifier),
+
+    /// An instruction to submit the batch script at the passed path
+    /// on a cluster as the passed user, returning the scheduler job id
+    SubmitJob(UserIdentifier, String),
+
+    /// An instruction to submit the batch script at the passed path
+    /// as the passed local user, charged to their project's account,
+    /// returning the scheduler job id
+    SubmitLocalJob(UserMapping, String),
+
+    /// An instruction to compare the scheduler's accounts and
+    /// associations for a project against the mappings reported by
+    /// the account agent. If the flag is true then missing accounts
+    /// and associations are repaired
+    Reconcile(ProjectIdentifier, bool),
+
+    /// An instruction to compare the scheduler's account and
+    /// associations for the passed local project against the passed
+    /// local users of that project. If the flag is true then the
+    /// missing account and associations are repaired
+    ReconcileLocal(ProjectMapping, bool, Vec<UserMapping>),
+
+    /// An instruction to get the distinct types of compute node that
+    /// the scheduler manages, read from the scheduler's node inventory
+    GetLocalNodes,
+
+    /// An instruction to add the passed SSH public key to a user
+    AddUserSSHKey(UserIdentifier, String),
+
+    /// An instruction to remove the passed SSH public key from a user
+    RemoveUserSSHKey(UserIdentifier, String),
+
+    /// An instruction to get the SSH public keys of a user
+    GetUserSSHKeys(UserIdentifier),
+
+    /// An instruction to add a user to the passed secondary group
+    AddUserToGroup(UserIdentifier, String),
+
+    /// An instruction to remove a user from the passed secondary group
+    RemoveUserFromGroup(UserIdentifier, String),
+
+    /// An instruction to activate a staged user, e.g. once they have
+    /// completed the onboarding steps required by the portal
+    ActivateUser(UserIdentifier),
+
+    /// An instruction to update the details (e.g. email address, display
+    /// name and login shell) of a user's account
+    UpdateUser(UserIdentifier, UserDetails),
+
+    /// An instruction to issue a new OTP token to a user
+    AddUserOTPToken(UserIdentifier),
+
+    /// An instruction to revoke the OTP token with the passed ID from a user
+    RemoveUserOTPToken(UserIdentifier, String),
+
+    /// An instruction to get the IDs of the OTP tokens of a user
+    GetUserOTPTokens(UserIdentifier),
+
+    /// An instruction to ask whether a user is protected, i.e. is
+    /// never modified by OpenPortal, and if so, why
+    GetUserProtection(UserIdentifier),
+
+    /// An instruction to ask whether the group for a project is
+    /// protected, i.e. is never modified by OpenPortal, and if so, why
+    GetProjectProtection(ProjectIdentifier),
+
+    /// An instruction to export the entries of an agent's audit log
+    /// that were recorded between the passed dates
+    ExportAuditLog(DateRange),
+}
+
+///
+/// Parse the optional Slurm partition argument of a limit instruction.
+/// Partition names can only contain alphanumeric characters,
+/// underscores and dashes
+///
+fn parse_partition(partition: Option<&str>) -> Result<Option<String>, Error> {
+    match partition {
+        None => Ok(None),
+        Some(partition) => {
+            if partition.is_empty()
+                || !partition
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+            {
+                return Err(Error::Parse(format!(
+                    "Invalid partition '{}' - can only contain alphanumeric characters, underscores and dashes",
+                    partition
+                )));
+            }
+
+            Ok(Some(partition.to_string()))
+        }
+    }
+}
+
+///
+/// Return whether or not the passed string is a valid path to a job
+/// script. This must be absolute, and cannot move up out of a directory
+///
+fn is_job_script_path(path: &str) -> bool {
+    path.starts_with('/')
+        && path.len() > 1
+        && !path.ends_with('/')
+        && !path.split('/').any(|part| part == "..")
+}
+
+///
+/// Return whether or not the passed string is a valid name for a
+/// secondary group, i.e. a lowercase Unix group name
+///
+fn is_group_name(group: &str) -> bool {
+    !group.is_empty()
+        && group.len() <= 64
+        && !group.starts_with(['-', '.'])
+        && group
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-".contains(c))
+}
+
+///
+/// Return whether or not the passed string is a valid ID for an OTP
+/// token (FreeIPA uses a UUID by default)
+///
+fn is_otp_token_id(token: &str) -> bool {
+    !token.is_empty()
+        && token.len() <= 64
+        && !token.starts_with(['-', '.'])
+        && token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+}
+
+///
+/// The types of SSH public key that can be added to a user
+///
+const SSH_KEY_TYPES: [&str; 7] = [
+    "ssh-ed25519",
+    "ssh-rsa",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "sk-ssh-ed25519@openssh.com",
+    "sk-ecdsa-sha2-nistp256@openssh.com",
+];
+
+///
+/// Decode the passed standard (padded) base64 string, returning None
+/// if it is not valid base64
+///
+fn decode_base64(data: &str) -> Option<Vec<u8>> {
+    if data.is_empty() || !data.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let padding = data.bytes().rev().take_while(|b| *b == b'=').count();
+
+    if padding > 2 {
+        return None;
+    }
+
+    let mut decoded = Vec::with_capacity(data.len() / 4 * 3);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for byte in data[..data.len() - padding].bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Some(decoded)
+}
+
+///
+/// Parse and validate the passed SSH public key, which should be in the
+/// OpenSSH `authorized_keys` form of `<type> <base64 key> [<comment>]`.
+/// The key data must decode, and must be for the stated type of key.
+/// This returns the key with its whitespace normalised
+///
+pub fn parse_ssh_public_key(key: &str) -> Result<String, Error> {
+    let parts: Vec<&str> = key.split_whitespace().collect();
+
+    if parts.len() < 2 {
+        return Err(Error::Parse(format!(
+            "Invalid SSH public key '{}' - this should be '<type> <key> [<comment>]'",
+            key
+        )));
+    }
+
+    let key_type = parts[0];
+
+    if !SSH_KEY_TYPES.contains(&key_type) {
+        return Err(Error::Parse(format!(
+            "Invalid SSH public key - unsupported key type '{}'. Supported types are {}",
+            key_type,
+            SSH_KEY_TYPES.join(", ")
+        )));
+    }
+
+    let data = decode_base64(parts[1]).ok_or_else(|| {
+        Error::Parse(format!(
+            "Invalid SSH public key - the key data for '{}' is not valid base64",
+            key_type
+        ))
+    })?;
+
+    // the key data starts with the length-prefixed name of the key type
+    let embedded_type = match data.get(..4) {
+        Some(length) => {
+            let length = u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize;
+            data.get(4..4 + length)
+        }
+        None => None,
+    };
+
+    if embedded_type != Some(key_type.as_bytes()) {
+        return Err(Error::Parse(format!(
+            "Invalid SSH public key - the key data is not for a '{}' key",
+            key_type
+        )));
+    }
+
+    Ok(parts.join(" "))
+}
+
+///
+/// Check the users of a batch instruction - there must be at least one,
+/// none can be listed twice, and all must be in the same project
+///
+fn check_batch<T>(
+    command: &str,
+    users: &[T],
+    project: fn(&T) -> ProjectIdentifier,
+) -> Result<(), Error>
+where
+    T: PartialEq + std::fmt::Display,
+{
+    let first = match users.first() {
+        Some(user) => project(user),
+        None => {
+            tracing::error!("{} failed to parse: no users were given", command);
+            return Err(Error::Parse(format!(
+                "{} failed to parse: no users were given",
+                command
+            )));
+        }
+    };
+
+    for (i, user) in users.iter().enumerate() {
+        if project(user) != first {
+            tracing::error!(
+                "{} failed to parse: user {} is not in project {}",
+                command,
+                user,
+                first
+            );
+            return Err(Error::Parse(format!(
+                "{} failed to parse: user {} is not in project {}. All of the \
+                 users in a batch must be in the same project",
+                command, user, first
+            )));
+        }
+
+        if users[..i].contains(user) {
+            tracing::error!("{} failed to parse: user {} is repeated", command, user);
+            return Err(Error::Parse(format!(
+                "{} failed to parse: user {} is repeated",
+                command, user
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Parse the users of a batch instruction, e.g. 'add_users'
+///
+fn parse_users(command: &str, users: &[&str]) -> Result<Vec<UserIdentifier>, Error> {
+    let users = users
+        .iter()
+        .filter(|user| !user.is_empty())
+        .map(|user| UserIdentifier::parse(user))
+        .collect::<Result<Vec<UserIdentifier>, Error>>()
+        .map_err(|e| {
+            tracing::error!("{} failed to parse: {}", command, e);
+            Error::Parse(format!("{} failed to parse: {}", command, e))
+        })?;
+
+    check_batch(command, &users, |user| user.project_identifier())?;
+
+    Ok(users)
+}
+
+///
+/// Parse the user mappings of a batch instruction, e.g. 'add_local_users'
+///
+fn parse_user_mappings(command: &str, users: &[&str]) -> Result<Vec<UserMapping>, Error> {
+    let users = users
+        .iter()
+        .filter(|user| !user.is_empty())
+        .map(|user| UserMapping::parse(user))
+        .collect::<Result<Vec<UserMapping>, Error>>()
+        .map_err(|e| {
+            tracing::error!("{} failed to parse: {}", command, e);
+            Error::Parse(format!("{} failed to parse: {}", command, e))
+        })?;
+
+    check_batch(command, &users, |user| user.user().project_identifier())?;
+
+    Ok(users)
 }
 
 impl Instruction {
@@ -3275,7 +3811,23 @@ impl Instruction {
                     )))
                 }
             },
-            "block_user" => match UserIdentifier::parse(&parts[1..].join(" ")) {
+            "add_users" => Ok(Instruction::AddUsers(parse_users(
+                "add_users",
+                &parts[1..],
+            )?)),
+            "remove_users" => Ok(Instruction::RemoveUsers(parse_users(
+                "remove_users",
+                &parts[1..],
+            )?)),
+            "add_local_users" => Ok(Instruction::AddLocalUsers(parse_user_mappings(
+                "add_local_users",
+                &parts[1..],
+            )?)),
+            "remove_local_users" => Ok(Instruction::RemoveLocalUsers(parse_user_mappings(
+                "remove_local_users",
+                &parts[1..],
+            )?)),
+            "block_user" | "suspend_user" => match UserIdentifier::parse(&parts[1..].join(" ")) {
                 Ok(user) => Ok(Instruction::BlockUser(user)),
                 Err(_) => {
                     tracing::error!("block_user failed to parse: {}", &parts[1..].join(" "));
@@ -3285,16 +3837,18 @@ impl Instruction {
                     )))
                 }
             },
-            "unblock_user" => match UserIdentifier::parse(&parts[1..].join(" ")) {
-                Ok(user) => Ok(Instruction::UnblockUser(user)),
-                Err(_) => {
-                    tracing::error!("unblock_user failed to parse: {}", &parts[1..].join(" "));
-                    Err(Error::Parse(format!(
-                        "unblock_user failed to parse: {}",
-                        &parts[1..].join(" ")
-                    )))
+            "unblock_user" | "reactivate_user" => {
+                match UserIdentifier::parse(&parts[1..].join(" ")) {
+                    Ok(user) => Ok(Instruction::UnblockUser(user)),
+                    Err(_) => {
+                        tracing::error!("unblock_user failed to parse: {}", &parts[1..].join(" "));
+                        Err(Error::Parse(format!(
+                            "unblock_user failed to parse: {}",
+                            &parts[1..].join(" ")
+                        )))
+                    }
                 }
-            },
+            }
             "is_blocked_user" => match UserIdentifier::parse(&parts[1..].join(" ")) {
                 Ok(user) => Ok(Instruction::IsBlockedUser(user)),
                 Err(_) => {
@@ -3647,7 +4201,7 @@ impl Instruction {
                 }
             }
             "set_local_limit" => {
-                if parts.len() < 3 {
+                if parts.len() < 3 || parts.len() > 4 {
                     tracing::error!("set_local_limit failed to parse: {}", &parts[1..].join(" "));
                     return Err(Error::Parse(format!(
                         "set_local_limit failed to parse: {}",
@@ -3656,8 +4210,12 @@ impl Instruction {
                 }
 
                 match ProjectMapping::parse(parts[1]) {
-                    Ok(mapping) => match Usage::parse(parts[2]) {
-                        Ok(usage) => Ok(Instruction::SetLocalLimit(mapping, usage)),
+                    Ok(mapping) => match Usage::parse(parts[2])
+                        .and_then(|usage| Ok((usage, parse_partition(parts.get(3).copied())?)))
+                    {
+                        Ok((usage, partition)) => {
+                            Ok(Instruction::SetLocalLimit(mapping, usage, partition))
+                        }
                         Err(e) => {
                             tracing::error!(
                                 "set_local_limit failed to parse '{}': {}",
@@ -3686,7 +4244,7 @@ impl Instruction {
                 }
             }
             "get_local_limit" => {
-                if parts.len() < 2 {
+                if parts.len() < 2 || parts.len() > 3 {
                     tracing::error!("get_local_limit failed to parse: {}", &parts[1..].join(" "));
                     return Err(Error::Parse(format!(
                         "get_local_limit failed to parse: {}",
@@ -3694,8 +4252,10 @@ impl Instruction {
                     )));
                 }
 
-                match ProjectMapping::parse(parts[1]) {
-                    Ok(mapping) => Ok(Instruction::GetLocalLimit(mapping)),
+                match ProjectMapping::parse(parts[1])
+                    .and_then(|mapping| Ok((mapping, parse_partition(parts.get(2).copied())?)))
+                {
+                    Ok((mapping, partition)) => Ok(Instruction::GetLocalLimit(mapping, partition)),
                     Err(e) => {
                         tracing::error!(
                             "get_local_limit failed to parse '{}': {}",
@@ -4592,27 +5152,595 @@ impl Instruction {
                 }
             },
             "get_offerings" => Ok(Instruction::GetOfferings()),
-            _ => {
-                tracing::error!("Invalid instruction: {}", s);
-                Err(Error::Parse(format!("Invalid instruction: {}", s)))
-            }
-        }
-    }
+            "purge_recycled" => match parts.get(1) {
+                None => Ok(Instruction::PurgeRecycled(false)),
+                Some(&"dry_run") => Ok(Instruction::PurgeRecycled(true)),
+                Some(_) => {
+                    tracing::error!("purge_recycled failed to parse: {}", &parts[1..].join(" "));
+                    Err(Error::Parse(format!(
+                        "purge_recycled failed to parse: {}. Expected 'purge_recycled [dry_run]'",
+                        &parts[1..].join(" ")
+                    )))
+                }
+            },
+            "repair_permissions" => match ProjectMapping::parse(&parts[1..].join(" ")) {
+                Ok(mapping) => Ok(Instruction::RepairPermissions(mapping)),
+                Err(_) => {
+                    tracing::error!(
+                        "repair_permissions failed to parse: {}",
+                        &parts[1..].join(" ")
+                    );
+                    Err(Error::Parse(format!(
+                        "repair_permissions failed to parse: {}",
+                        &parts[1..].join(" ")
+                    )))
+                }
+            },
+            "set_local_qos" => {
+                if parts.len() < 3 {
+                    tracing::error!("set_local_qos failed to parse: {}", &parts[1..].join(" "));
+                    return Err(Error::Parse(format!(
+                        "set_local_qos failed to parse: {}",
+                        &parts[1..].join(" ")
+                    )));
+                }
 
-    pub fn command(&self) -> String {
-        match self {
-            Instruction::Submit(_, _) => "submit".to_string(),
-            Instruction::CreateProject(_, _) => "create_project".to_string(),
-            Instruction::UpdateProject(_, _) => "update_project".to_string(),
-            Instruction::GetProject(_) => "get_project".to_string(),
-            Instruction::GetProjects(_) => "get_projects".to_string(),
-            Instruction::GetAward(_) => "get_award".to_string(),
-            Instruction::GetAwards(_) => "get_awards".to_string(),
-            Instruction::AddProject(_) => "add_project".to_string(),
-            Instruction::RemoveProject(_) => "remove_project".to_string(),
+                match ProjectMapping::parse(parts[1]) {
+                    Ok(mapping) => match ProjectTemplate::parse(parts[2]) {
+                        Ok(class) => Ok(Instruction::SetLocalQos(mapping, class)),
+                        Err(e) => {
+                            tracing::error!(
+                                "set_local_qos failed to parse '{}': {}",
+                                &parts[1..].join(" "),
+                                e
+                            );
+                            Err(Error::Parse(format!(
+                                "set_local_qos failed to parse '{}': {}",
+                                &parts[1..].join(" "),
+                                e
+                            )))
+                        }
+                    },
+                    Err(e) => {
+                        tracing::error!(
+                            "set_local_qos failed to parse '{}': {}",
+                            &parts[1..].join(" "),
+                            e
+                        );
+                        Err(Error::Parse(format!(
+                            "set_local_qos failed to parse '{}': {}",
+                            &parts[1..].join(" "),
+                            e
+                        )))
+                    }
+                }
+            }
+            "get_local_qos" => match ProjectMapping::parse(&parts[1..].join(" ")) {
+                Ok(mapping) => Ok(Instruction::GetLocalQos(mapping)),
+                Err(_) => {
+                    tracing::error!("get_local_qos failed to parse: {}", &parts[1..].join(" "));
+                    Err(Error::Parse(format!(
+                        "get_local_qos failed to parse: {}",
+                        &parts[1..].join(" ")
+                    )))
+                }
+            },
+            "create_local_reservation" => {
+                if parts.len() != 5 {
+                    tracing::error!(
+                        "create_local_reservation failed to parse: {}",
+                        &parts[1..].join(" ")
+                    );
+                    return Err(Error::Parse(format!(
+                        "create_local_reservation failed to parse: {}. This should be \
+                         '<project_mapping> <dates> <nodes> <allocation_window>'",
+                        &parts[1..].join(" ")
+                    )));
+                }
+
+                let parsed = ProjectMapping::parse(parts[1]).and_then(|mapping| {
+                    let dates = DateRange::parse(parts[2])?;
+                    let window = DateRange::parse(parts[4])?;
+
+                    let nodes = match parts[3].parse::<u32>() {
+                        Ok(nodes) if nodes > 0 => nodes,
+                        _ => {
+                            return Err(Error::Parse(format!(
+                                "Invalid number of nodes '{}' - this must be a positive integer",
+                                parts[3]
+                            )))
+                        }
+                    };
+
+                    Ok(Instruction::CreateLocalReservation(
+                        mapping, dates, nodes, window,
+                    ))
+                });
+
+                match parsed {
+                    Ok(instruction) => Ok(instruction),
+                    Err(e) => {
+                        tracing::error!(
+                            "create_local_reservation failed to parse '{}': {}",
+                            &parts[1..].join(" "),
+                            e
+                        );
+                        Err(Error::Parse(format!(
+                            "create_local_reservation failed to parse '{}': {}",
+                            &parts[1..].join(" "),
+                            e
+                        )))
+                    }
+                }
+            }
+            "remove_local_reservation" => {
+                if parts.len() != 3 {
+                    tracing::error!(
+                        "remove_local_reservation failed to parse: {}",
+                        &parts[1..].join(" ")
+                    );
+                    return Err(Error::Parse(format!(
+                        "remove_local_reservation failed to parse: {}",
+                        &parts[1..].join(" ")
+                    )));
+                }
+
+                match ProjectMapping::parse(parts[1]) {
+                    Ok(mapping) => match DateRange::parse(parts[2]) {
+                        Ok(dates) => Ok(Instruction::RemoveLocalReservation(mapping, dates)),
+                        Err(e) => {
+                            tracing::error!(
+                                "remove_local_reservation failed to parse '{}': {}",
+                                &parts[1..].join(" "),
+                                e
+                            );
+                            Err(Error::Parse(format!(
+                                "remove_local_reservation failed to parse '{}': {}",
+                                &parts[1..].join(" "),
+                                e
+                            )))
+                        }
+                    },
+                    Err(e) => {
+                        tracing::error!(
+                            "remove_local_reservation failed to parse '{}': {}",
+                            &parts[1..].join(" "),
+                            e
+                        );
+                        Err(Error::Parse(format!(
+                            "remove_local_reservation failed to parse '{}': {}",
+                            &parts[1..].join(" "),
+                            e
+                        )))
+                    }
+                }
+            }
+            "get_local_job_queue" => match ProjectMapping::parse(&parts[1..].join(" ")) {
+                Ok(mapping) => Ok(Instruction::GetLocalJobQueue(mapping)),
+                Err(_) => {
+                    tracing::error!(
+                        "get_local_job_queue failed to parse: {}",
+                        &parts[1..].join(" ")
+                    );
+                    Err(Error::Parse(format!(
+                        "get_local_job_queue failed to parse: {}",
+                        &parts[1..].join(" ")
+                    )))
+                }
+            },
+            "get_job_queue" => match ProjectIdentifier::parse(&parts[1..].join(" ")) {
+                Ok(project) => Ok(Instruction::GetJobQueue(project)),
+                Err(_) => {
+                    tracing::error!("get_job_queue failed to parse: {}", &parts[1..].join(" "));
+                    Err(Error::Parse(format!(
+                        "get_job_queue failed to parse: {}",
+                        &parts[1..].join(" ")
+                    )))
+                }
+            },
+            "submit_job" => {
+                if parts.len() != 3 || !is_job_script_path(parts[2]) {
+                    tracing::error!("submit_job failed to parse: {}", &parts[1..].join(" "));
+                    return Err(Error::Parse(format!(
+                        "submit_job failed to parse: {}. This should be '<user_id> <script_path>', \
+                         where the script path is absolute",
+                        &parts[1..].join(" ")
+                    )));
+                }
+
+                match UserIdentifier::parse(parts[1]) {
+                    Ok(user) => Ok(Instruction::SubmitJob(user, parts[2].to_string())),
+                    Err(e) => {
+                        tracing::error!(
+                            "submit_job failed to parse '{}': {}",
+                            &parts[1..].join(" "),
+                            e
+                        );
+                        Err(Error::Parse(format!(
+                            "submit_job failed to parse '{}': {}",
+                            &parts[1..].join(" "),
+                            e
+                        )))
+                    }
+                }
+            }
+            "submit_local_job" => {
+                if parts.len() != 3 || !is_job_script_path(parts[2]) {
+                    tracing::error!(
+                        "submit_local_job failed to parse: {}",
+                        &parts[1..].join(" ")
+                    );
+                    return Err(Error::Parse(format!(
+                        "submit_local_job failed to parse: {}. This should be \
+                         '<user_mapping> <script_path>', where the script path is absolute",
+                        &parts[1..].join(" ")
+                    )));
+                }
+
+                match UserMapping::parse(parts[1]) {
+                    Ok(mapping) => Ok(Instruction::SubmitLocalJob(mapping, parts[2].to_string())),
+                    Err(e) => {
+                        tracing::error!(
+                            "submit_local_job failed to parse '{}': {}",
+                            &parts[1..].join(" "),
+                            e
+                        );
+                        Err(Error::Parse(format!(
+                            "submit_local_job failed to parse '{}': {}",
+                            &parts[1..].join(" "),
+                            e
+                        )))
+                    }
+                }
+            }
+            "reconcile" => {
+                let repair = match parts.get(2) {
+                    None => false,
+                    Some(&"repair") if parts.len() == 3 => true,
+                    Some(_) => {
+                        tracing::error!("reconcile failed to parse: {}", &parts[1..].join(" "));
+                        return Err(Error::Parse(format!(
+                            "reconcile failed to parse: {}. Expected 'reconcile <project_id> [repair]'",
+                            &parts[1..].join(" ")
+                        )));
+                    }
+                };
+
+                match ProjectIdentifier::parse(parts.get(1).unwrap_or(&"")) {
+                    Ok(project) => Ok(Instruction::Reconcile(project, repair)),
+                    Err(e) => {
+                        tracing::error!(
+                            "reconcile failed to parse '{}': {}",
+                            &parts[1..].join(" "),
+                            e
+                        );
+                        Err(Error::Parse(format!(
+                            "reconcile failed to parse '{}': {}",
+                            &parts[1..].join(" "),
+                            e
+                        )))
+                    }
+                }
+            }
+            "reconcile_local" => {
+                let (repair, users) = match parts.get(2) {
+                    Some(&"repair") => (true, &parts[3..]),
+                    _ => (false, &parts[parts.len().min(2)..]),
+                };
+
+                let mapping = match ProjectMapping::parse(parts.get(1).unwrap_or(&"")) {
+                    Ok(mapping) => mapping,
+                    Err(e) => {
+                        tracing::error!(
+                            "reconcile_local failed to parse '{}': {}",
+                            &parts[1..].join(" "),
+                            e
+                        );
+                        return Err(Error::Parse(format!(
+                            "reconcile_local failed to parse '{}': {}. Expected \
+                             'reconcile_local <project_mapping> [repair] [<user_mapping> ...]'",
+                            &parts[1..].join(" "),
+                            e
+                        )));
+                    }
+                };
+
+                let mut user_mappings = Vec::new();
+
+                for user in users {
+                    match UserMapping::parse(user) {
+                        Ok(user) if user.user().project_identifier() == *mapping.project() => {
+                            user_mappings.push(user)
+                        }
+                        Ok(user) => {
+                            tracing::error!(
+                                "reconcile_local failed to parse: user {} is not in project {}",
+                                user,
+                                mapping.project()
+                            );
+                            return Err(Error::Parse(format!(
+                                "reconcile_local failed to parse: user {} is not in project {}",
+                                user,
+                                mapping.project()
+                            )));
+                        }
+                        Err(e) => {
+                            tracing::error!(
+                                "reconcile_local failed to parse '{}': {}",
+                                &parts[1..].join(" "),
+                                e
+                            );
+                            return Err(Error::Parse(format!(
+                                "reconcile_local failed to parse '{}': {}",
+                                &parts[1..].join(" "),
+                                e
+                            )));
+                        }
+                    }
+                }
+
+                Ok(Instruction::ReconcileLocal(mapping, repair, user_mappings))
+            }
+            "export_audit_log" => {
+                if parts.len() > 2 {
+                    tracing::error!(
+                        "export_audit_log failed to parse: {}",
+                        &parts[1..].join(" ")
+                    );
+                    return Err(Error::Parse(format!(
+                        "export_audit_log failed to parse: {}. Expected 'export_audit_log [date_range]'",
+                        &parts[1..].join(" ")
+                    )));
+                }
+
+                match DateRange::parse(parts.get(1).cloned().unwrap_or("this_month")) {
+                    Ok(date_range) => Ok(Instruction::ExportAuditLog(date_range)),
+                    Err(e) => {
+                        tracing::error!(
+                            "export_audit_log failed to parse '{}': {}",
+                            &parts[1..].join(" "),
+                            e
+                        );
+                        Err(Error::Parse(format!(
+                            "export_audit_log failed to parse '{}': {}",
+                            &parts[1..].join(" "),
+                            e
+                        )))
+                    }
+                }
+            }
+            "get_local_nodes" => match parts.len() {
+                1 => Ok(Instruction::GetLocalNodes),
+                _ => {
+                    tracing::error!("get_local_nodes failed to parse: {}", &parts[1..].join(" "));
+                    Err(Error::Parse(format!(
+                        "get_local_nodes failed to parse: {}. Expected no arguments",
+                        &parts[1..].join(" ")
+                    )))
+                }
+            },
+            "add_user_ssh_key" | "remove_user_ssh_key" => {
+                if parts.len() < 3 {
+                    tracing::error!("{} failed to parse: {}", parts[0], &parts[1..].join(" "));
+                    return Err(Error::Parse(format!(
+                        "{} failed to parse: {}. This should be '<user_id> <ssh_public_key>'",
+                        parts[0],
+                        &parts[1..].join(" ")
+                    )));
+                }
+
+                let user = match UserIdentifier::parse(parts[1]) {
+                    Ok(user) => user,
+                    Err(e) => {
+                        tracing::error!(
+                            "{} failed to parse '{}': {}",
+                            parts[0],
+                            &parts[1..].join(" "),
+                            e
+                        );
+                        return Err(Error::Parse(format!(
+                            "{} failed to parse '{}': {}",
+                            parts[0],
+                            &parts[1..].join(" "),
+                            e
+                        )));
+                    }
+                };
+
+                let key = match parse_ssh_public_key(&parts[2..].join(" ")) {
+                    Ok(key) => key,
+                    Err(e) => {
+                        tracing::error!("{} failed to parse: {}", parts[0], e);
+                        return Err(Error::Parse(format!("{} failed to parse: {}", parts[0], e)));
+                    }
+                };
+
+                match parts[0] {
+                    "add_user_ssh_key" => Ok(Instruction::AddUserSSHKey(user, key)),
+                    _ => Ok(Instruction::RemoveUserSSHKey(user, key)),
+                }
+            }
+            "get_user_ssh_keys" => match UserIdentifier::parse(&parts[1..].join(" ")) {
+                Ok(user) => Ok(Instruction::GetUserSSHKeys(user)),
+                Err(_) => {
+                    tracing::error!(
+                        "get_user_ssh_keys failed to parse: {}",
+                        &parts[1..].join(" ")
+                    );
+                    Err(Error::Parse(format!(
+                        "get_user_ssh_keys failed to parse: {}",
+                        &parts[1..].join(" ")
+                    )))
+                }
+            },
+            "add_user_to_group" | "remove_user_from_group" => {
+                if parts.len() != 3 || !is_group_name(parts[2]) {
+                    tracing::error!("{} failed to parse: {}", parts[0], &parts[1..].join(" "));
+                    return Err(Error::Parse(format!(
+                        "{} failed to parse: {}. This should be '<user_id> <group>'",
+                        parts[0],
+                        &parts[1..].join(" ")
+                    )));
+                }
+
+                match UserIdentifier::parse(parts[1]) {
+                    Ok(user) => match parts[0] {
+                        "add_user_to_group" => {
+                            Ok(Instruction::AddUserToGroup(user, parts[2].to_string()))
+                        }
+                        _ => Ok(Instruction::RemoveUserFromGroup(user, parts[2].to_string())),
+                    },
+                    Err(e) => {
+                        tracing::error!(
+                            "{} failed to parse '{}': {}",
+                            parts[0],
+                            &parts[1..].join(" "),
+                            e
+                        );
+                        Err(Error::Parse(format!(
+                            "{} failed to parse '{}': {}",
+                            parts[0],
+                            &parts[1..].join(" "),
+                            e
+                        )))
+                    }
+                }
+            }
+            "activate_user" => match UserIdentifier::parse(&parts[1..].join(" ")) {
+                Ok(user) => Ok(Instruction::ActivateUser(user)),
+                Err(_) => {
+                    tracing::error!("activate_user failed to parse: {}", &parts[1..].join(" "));
+                    Err(Error::Parse(format!(
+                        "activate_user failed to parse: {}",
+                        &parts[1..].join(" ")
+                    )))
+                }
+            },
+            "update_user" => match UserIdentifier::parse(parts[1]) {
+                Ok(user) => match UserDetails::parse(&parts[2..].join(" ")) {
+                    Ok(details) => Ok(Instruction::UpdateUser(user, details)),
+                    Err(e) => {
+                        tracing::error!(
+                            "update_user failed to parse: {}: {}",
+                            &parts[2..].join(" "),
+                            e
+                        );
+                        Err(Error::Parse(format!(
+                            "update_user failed to parse: {}: {}",
+                            &parts[2..].join(" "),
+                            e
+                        )))
+                    }
+                },
+                Err(_) => {
+                    tracing::error!("update_user failed to parse: {}", &parts[1..].join(" "));
+                    Err(Error::Parse(format!(
+                        "update_user failed to parse: {}",
+                        &parts[1..].join(" ")
+                    )))
+                }
+            },
+            "add_user_otp_token" => match UserIdentifier::parse(&parts[1..].join(" ")) {
+                Ok(user) => Ok(Instruction::AddUserOTPToken(user)),
+                Err(_) => {
+                    tracing::error!(
+                        "add_user_otp_token failed to parse: {}",
+                        &parts[1..].join(" ")
+                    );
+                    Err(Error::Parse(format!(
+                        "add_user_otp_token failed to parse: {}",
+                        &parts[1..].join(" ")
+                    )))
+                }
+            },
+            "remove_user_otp_token" => {
+                if parts.len() != 3 || !is_otp_token_id(parts[2]) {
+                    tracing::error!(
+                        "remove_user_otp_token failed to parse: {}",
+                        &parts[1..].join(" ")
+                    );
+                    return Err(Error::Parse(format!(
+                        "remove_user_otp_token failed to parse: {}. This should be '<user_id> <token_id>'",
+                        &parts[1..].join(" ")
+                    )));
+                }
+
+                match UserIdentifier::parse(parts[1]) {
+                    Ok(user) => Ok(Instruction::RemoveUserOTPToken(user, parts[2].to_string())),
+                    Err(_) => {
+                        tracing::error!(
+                            "remove_user_otp_token failed to parse: {}",
+                            &parts[1..].join(" ")
+                        );
+                        Err(Error::Parse(format!(
+                            "remove_user_otp_token failed to parse: {}",
+                            &parts[1..].join(" ")
+                        )))
+                    }
+                }
+            }
+            "get_user_otp_tokens" => match UserIdentifier::parse(&parts[1..].join(" ")) {
+                Ok(user) => Ok(Instruction::GetUserOTPTokens(user)),
+                Err(_) => {
+                    tracing::error!(
+                        "get_user_otp_tokens failed to parse: {}",
+                        &parts[1..].join(" ")
+                    );
+                    Err(Error::Parse(format!(
+                        "get_user_otp_tokens failed to parse: {}",
+                        &parts[1..].join(" ")
+                    )))
+                }
+            },
+            "get_user_protection" => match UserIdentifier::parse(&parts[1..].join(" ")) {
+                Ok(user) => Ok(Instruction::GetUserProtection(user)),
+                Err(_) => {
+                    tracing::error!(
+                        "get_user_protection failed to parse: {}",
+                        &parts[1..].join(" ")
+                    );
+                    Err(Error::Parse(format!(
+                        "get_user_protection failed to parse: {}",
+                        &parts[1..].join(" ")
+                    )))
+                }
+            },
+            "get_project_protection" => match ProjectIdentifier::parse(&parts[1..].join(" ")) {
+                Ok(project) => Ok(Instruction::GetProjectProtection(project)),
+                Err(_) => {
+                    tracing::error!(
+                        "get_project_protection failed to parse: {}",
+                        &parts[1..].join(" ")
+                    );
+                    Err(Error::Parse(format!(
+                        "get_project_protection failed to parse: {}",
+                        &parts[1..].join(" ")
+                    )))
+                }
+            },
+            _ => {
+                tracing::error!("Invalid instruction: {}", s);
+                Err(Error::Parse(format!("Invalid instruction: {}", s)))
+            }
+        }
+    }
+
+    pub fn command(&self) -> String {
+        match self {
+            Instruction::Submit(_, _) => "submit".to_string(),
+            Instruction::CreateProject(_, _) => "create_project".to_string(),
+            Instruction::UpdateProject(_, _) => "update_project".to_string(),
+            Instruction::GetProject(_) => "get_project".to_string(),
+            Instruction::GetProjects(_) => "get_projects".to_string(),
+            Instruction::GetAward(_) => "get_award".to_string(),
+            Instruction::GetAwards(_) => "get_awards".to_string(),
+            Instruction::AddProject(_) => "add_project".to_string(),
+            Instruction::RemoveProject(_) => "remove_project".to_string(),
             Instruction::GetUsers(_) => "get_users".to_string(),
             Instruction::AddUser(_) => "add_user".to_string(),
             Instruction::RemoveUser(_) => "remove_user".to_string(),
+            Instruction::AddUsers(_) => "add_users".to_string(),
+            Instruction::RemoveUsers(_) => "remove_users".to_string(),
             Instruction::BlockUser(_) => "block_user".to_string(),
             Instruction::UnblockUser(_) => "unblock_user".to_string(),
             Instruction::IsBlockedUser(_) => "is_blocked_user".to_string(),
@@ -4626,11 +5754,13 @@ impl Instruction {
             Instruction::GetProjectDirs(_) => "get_project_dirs".to_string(),
             Instruction::AddLocalUser(_) => "add_local_user".to_string(),
             Instruction::RemoveLocalUser(_) => "remove_local_user".to_string(),
+            Instruction::AddLocalUsers(_) => "add_local_users".to_string(),
+            Instruction::RemoveLocalUsers(_) => "remove_local_users".to_string(),
             Instruction::AddLocalProject(_) => "add_local_project".to_string(),
             Instruction::RemoveLocalProject(_) => "remove_local_project".to_string(),
             Instruction::GetLocalUsageReport(_, _) => "get_local_usage_report".to_string(),
-            Instruction::GetLocalLimit(_) => "get_local_limit".to_string(),
-            Instruction::SetLocalLimit(_, _) => "set_local_limit".to_string(),
+            Instruction::GetLocalLimit(_, _) => "get_local_limit".to_string(),
+            Instruction::SetLocalLimit(_, _, _) => "set_local_limit".to_string(),
             Instruction::GetLocalProjectQuota(_, _) => "get_local_project_quota".to_string(),
             Instruction::ClearLocalProjectQuota(_, _) => "clear_local_project_quota".to_string(),
             Instruction::SetLocalProjectQuota(_, _, _) => "set_local_project_quota".to_string(),
@@ -4665,6 +5795,159 @@ impl Instruction {
             Instruction::AddOfferings(_) => "add_offerings".to_string(),
             Instruction::RemoveOfferings(_) => "remove_offerings".to_string(),
             Instruction::GetOfferings() => "get_offerings".to_string(),
+            Instruction::PurgeRecycled(_) => "purge_recycled".to_string(),
+            Instruction::RepairPermissions(_) => "repair_permissions".to_string(),
+            Instruction::SetLocalQos(_, _) => "set_local_qos".to_string(),
+            Instruction::GetLocalQos(_) => "get_local_qos".to_string(),
+            Instruction::CreateLocalReservation(_, _, _, _) => {
+                "create_local_reservation".to_string()
+            }
+            Instruction::RemoveLocalReservation(_, _) => "remove_local_reservation".to_string(),
+            Instruction::GetLocalJobQueue(_) => "get_local_job_queue".to_string(),
+            Instruction::GetJobQueue(_) => "get_job_queue".to_string(),
+            Instruction::SubmitJob(_, _) => "submit_job".to_string(),
+            Instruction::SubmitLocalJob(_, _) => "submit_local_job".to_string(),
+            Instruction::Reconcile(_, _) => "reconcile".to_string(),
+            Instruction::ReconcileLocal(_, _, _) => "reconcile_local".to_string(),
+            Instruction::GetLocalNodes => "get_local_nodes".to_string(),
+            Instruction::AddUserSSHKey(_, _) => "add_user_ssh_key".to_string(),
+            Instruction::RemoveUserSSHKey(_, _) => "remove_user_ssh_key".to_string(),
+            Instruction::GetUserSSHKeys(_) => "get_user_ssh_keys".to_string(),
+            Instruction::AddUserToGroup(_, _) => "add_user_to_group".to_string(),
+            Instruction::RemoveUserFromGroup(_, _) => "remove_user_from_group".to_string(),
+            Instruction::ActivateUser(_) => "activate_user".to_string(),
+            Instruction::UpdateUser(_, _) => "update_user".to_string(),
+            Instruction::AddUserOTPToken(_) => "add_user_otp_token".to_string(),
+            Instruction::RemoveUserOTPToken(_, _) => "remove_user_otp_token".to_string(),
+            Instruction::GetUserOTPTokens(_) => "get_user_otp_tokens".to_string(),
+            Instruction::GetUserProtection(_) => "get_user_protection".to_string(),
+            Instruction::GetProjectProtection(_) => "get_project_protection".to_string(),
+            Instruction::ExportAuditLog(_) => "export_audit_log".to_string(),
+        }
+    }
+
+    ///
+    /// Return the role that a peer needs to send this instruction
+    ///
+    pub fn required_role(&self) -> Role {
+        match self {
+            // the role needed to submit an instruction is the role
+            // needed for the instruction itself
+            Instruction::Submit(_, instruction) => instruction.required_role(),
+
+            // instructions that only read information
+            Instruction::GetProject(_)
+            | Instruction::GetProjects(_)
+            | Instruction::GetAward(_)
+            | Instruction::GetAwards(_)
+            | Instruction::GetUsers(_)
+            | Instruction::IsProtectedUser(_)
+            | Instruction::IsExistingUser(_)
+            | Instruction::IsExistingProject(_)
+            | Instruction::IsBlockedUser(_)
+            | Instruction::IsBlockedProject(_)
+            | Instruction::GetUserMapping(_)
+            | Instruction::GetProjectMapping(_)
+            | Instruction::GetHomeDir(_)
+            | Instruction::GetUserDirs(_)
+            | Instruction::GetProjectDirs(_)
+            | Instruction::GetLocalUsageReport(_, _)
+            | Instruction::GetLocalLimit(_, _)
+            | Instruction::GetLocalProjectQuota(_, _)
+            | Instruction::GetLocalProjectQuotas(_)
+            | Instruction::GetLocalUserQuota(_, _)
+            | Instruction::GetLocalUserQuotas(_)
+            | Instruction::GetLocalHomeDir(_)
+            | Instruction::GetLocalUserDirs(_)
+            | Instruction::GetLocalProjectDirs(_)
+            | Instruction::GetLocalStorageReport(_, _)
+            | Instruction::GetStorageReport(_, _)
+            | Instruction::GetStorageReports(_, _)
+            | Instruction::GetUsageReport(_, _)
+            | Instruction::GetUsageReports(_, _)
+            | Instruction::GetLimit(_)
+            | Instruction::GetProjectQuota(_, _)
+            | Instruction::GetProjectQuotas(_)
+            | Instruction::GetUserQuota(_, _)
+            | Instruction::GetUserQuotas(_)
+            | Instruction::GetOfferings()
+            | Instruction::GetLocalQos(_)
+            | Instruction::GetLocalJobQueue(_)
+            | Instruction::GetJobQueue(_)
+            | Instruction::GetLocalNodes
+            | Instruction::GetUserSSHKeys(_)
+            | Instruction::GetUserOTPTokens(_)
+            | Instruction::GetUserProtection(_)
+            | Instruction::GetProjectProtection(_)
+            | Instruction::Reconcile(_, false)
+            | Instruction::ReconcileLocal(_, false, _)
+            | Instruction::PurgeRecycled(true) => Role::ReadOnly,
+
+            // instructions that repair or permanently remove data
+            Instruction::Reconcile(_, true)
+            | Instruction::ReconcileLocal(_, true, _)
+            | Instruction::PurgeRecycled(false)
+            | Instruction::RepairPermissions(_)
+            | Instruction::ExportAuditLog(_) => Role::Admin,
+
+            // instructions that manage projects, users and their resources
+            Instruction::CreateProject(_, _)
+            | Instruction::UpdateProject(_, _)
+            | Instruction::AddProject(_)
+            | Instruction::RemoveProject(_)
+            | Instruction::AddUser(_)
+            | Instruction::RemoveUser(_)
+            | Instruction::AddUsers(_)
+            | Instruction::RemoveUsers(_)
+            | Instruction::BlockUser(_)
+            | Instruction::UnblockUser(_)
+            | Instruction::BlockProject(_)
+            | Instruction::UnblockProject(_)
+            | Instruction::AddLocalUser(_)
+            | Instruction::RemoveLocalUser(_)
+            | Instruction::AddLocalUsers(_)
+            | Instruction::RemoveLocalUsers(_)
+            | Instruction::AddLocalProject(_)
+            | Instruction::RemoveLocalProject(_)
+            | Instruction::SetLocalLimit(_, _, _)
+            | Instruction::ClearLocalProjectQuota(_, _)
+            | Instruction::SetLocalProjectQuota(_, _, _)
+            | Instruction::ClearLocalUserQuota(_, _)
+            | Instruction::SetLocalUserQuota(_, _, _)
+            | Instruction::UpdateHomeDir(_, _)
+            | Instruction::SetLimit(_, _)
+            | Instruction::ClearProjectQuota(_, _)
+            | Instruction::SetProjectQuota(_, _, _)
+            | Instruction::ClearUserQuota(_, _)
+            | Instruction::SetUserQuota(_, _, _)
+            | Instruction::SyncOfferings(_)
+            | Instruction::AddOfferings(_)
+            | Instruction::RemoveOfferings(_)
+            | Instruction::SetLocalQos(_, _)
+            | Instruction::CreateLocalReservation(_, _, _, _)
+            | Instruction::RemoveLocalReservation(_, _)
+            | Instruction::SubmitJob(_, _)
+            | Instruction::SubmitLocalJob(_, _)
+            | Instruction::AddUserSSHKey(_, _)
+            | Instruction::RemoveUserSSHKey(_, _)
+            | Instruction::AddUserToGroup(_, _)
+            | Instruction::RemoveUserFromGroup(_, _)
+            | Instruction::ActivateUser(_)
+            | Instruction::UpdateUser(_, _)
+            | Instruction::AddUserOTPToken(_)
+            | Instruction::RemoveUserOTPToken(_, _) => Role::Operator,
+        }
+    }
+
+    ///
+    /// Return whether this instruction changes anything on the agents
+    /// that run it. Only the instructions that need more than the
+    /// read-only role change anything, except for exporting the audit log
+    ///
+    pub fn is_mutating(&self) -> bool {
+        match self {
+            Instruction::ExportAuditLog(_) => false,
+            _ => self.required_role() > Role::ReadOnly,
         }
     }
 
@@ -4688,6 +5971,9 @@ impl Instruction {
             Instruction::GetUsers(project) => vec![project.to_string()],
             Instruction::AddUser(user) => vec![user.to_string()],
             Instruction::RemoveUser(user) => vec![user.to_string()],
+            Instruction::AddUsers(users) | Instruction::RemoveUsers(users) => {
+                users.iter().map(|user| user.to_string()).collect()
+            }
             Instruction::BlockUser(user) => vec![user.to_string()],
             Instruction::UnblockUser(user) => vec![user.to_string()],
             Instruction::IsBlockedUser(user) => vec![user.to_string()],
@@ -4701,14 +5987,23 @@ impl Instruction {
             Instruction::GetUserDirs(user) => vec![user.to_string()],
             Instruction::AddLocalUser(mapping) => vec![mapping.to_string()],
             Instruction::RemoveLocalUser(mapping) => vec![mapping.to_string()],
+            Instruction::AddLocalUsers(mappings) | Instruction::RemoveLocalUsers(mappings) => {
+                mappings.iter().map(|mapping| mapping.to_string()).collect()
+            }
             Instruction::AddLocalProject(mapping) => vec![mapping.to_string()],
             Instruction::RemoveLocalProject(mapping) => vec![mapping.to_string()],
             Instruction::GetLocalUsageReport(mapping, date_range) => {
                 vec![mapping.to_string(), date_range.to_string()]
             }
-            Instruction::GetLocalLimit(mapping) => vec![mapping.to_string()],
-            Instruction::SetLocalLimit(mapping, usage) => {
-                vec![mapping.to_string(), usage.seconds().to_string()]
+            Instruction::GetLocalLimit(mapping, partition) => {
+                let mut args = vec![mapping.to_string()];
+                args.extend(partition.clone());
+                args
+            }
+            Instruction::SetLocalLimit(mapping, usage, partition) => {
+                let mut args = vec![mapping.to_string(), usage.seconds().to_string()];
+                args.extend(partition.clone());
+                args
             }
             Instruction::GetLocalProjectQuota(mapping, volume) => {
                 vec![mapping.to_string(), volume.to_string()]
@@ -4782,6 +6077,61 @@ impl Instruction {
             Instruction::AddOfferings(offerings) => vec![offerings.to_string()],
             Instruction::RemoveOfferings(offerings) => vec![offerings.to_string()],
             Instruction::GetOfferings() => vec![],
+            Instruction::PurgeRecycled(dry_run) => match dry_run {
+                true => vec!["dry_run".to_string()],
+                false => vec![],
+            },
+            Instruction::RepairPermissions(mapping) => vec![mapping.to_string()],
+            Instruction::SetLocalQos(mapping, class) => {
+                vec![mapping.to_string(), class.to_string()]
+            }
+            Instruction::GetLocalQos(mapping) => vec![mapping.to_string()],
+            Instruction::CreateLocalReservation(mapping, dates, nodes, window) => {
+                vec![
+                    mapping.to_string(),
+                    dates.to_string(),
+                    nodes.to_string(),
+                    window.to_string(),
+                ]
+            }
+            Instruction::RemoveLocalReservation(mapping, dates) => {
+                vec![mapping.to_string(), dates.to_string()]
+            }
+            Instruction::GetLocalJobQueue(mapping) => vec![mapping.to_string()],
+            Instruction::GetJobQueue(project) => vec![project.to_string()],
+            Instruction::SubmitJob(user, script) => vec![user.to_string(), script.clone()],
+            Instruction::SubmitLocalJob(mapping, script) => {
+                vec![mapping.to_string(), script.clone()]
+            }
+            Instruction::Reconcile(project, repair) => match repair {
+                true => vec![project.to_string(), "repair".to_string()],
+                false => vec![project.to_string()],
+            },
+            Instruction::ReconcileLocal(mapping, repair, users) => {
+                let mut arguments = vec![mapping.to_string()];
+
+                if *repair {
+                    arguments.push("repair".to_string());
+                }
+
+                arguments.extend(users.iter().map(|user| user.to_string()));
+
+                arguments
+            }
+            Instruction::GetLocalNodes => vec![],
+            Instruction::ExportAuditLog(date_range) => vec![date_range.to_string()],
+            Instruction::AddUserSSHKey(user, key) => vec![user.to_string(), key.clone()],
+            Instruction::RemoveUserSSHKey(user, key) => vec![user.to_string(), key.clone()],
+            Instruction::GetUserSSHKeys(user) => vec![user.to_string()],
+            Instruction::AddUserToGroup(user, group) => vec![user.to_string(), group.clone()],
+            Instruction::RemoveUserFromGroup(user, group) => vec![user.to_string(), group.clone()],
+            Instruction::ActivateUser(user) => vec![user.to_string()],
+            Instruction::UpdateUser(user, details) => vec![user.to_string(), details.to_string()],
+            Instruction::AddUserOTPToken(user) => vec![user.to_string()],
+            Instruction::RemoveUserOTPToken(user, token) => vec![user.to_string(), token.clone()],
+            Instruction::GetUserOTPTokens(user) => vec![user.to_string()],
+            Instruction::GetUserProtection(user) => vec![user.to_string()],
+            Instruction::GetProjectProtection(project) => vec![project.to_string()],
         }
     }
 }
@@ -4807,6 +6157,9 @@ impl std::fmt::Display for Instruction {
             Instruction::GetUsers(project) => write!(f, "get_users {}", project),
             Instruction::AddUser(user) => write!(f, "add_user {}", user),
             Instruction::RemoveUser(user) => write!(f, "remove_user {}", user),
+            Instruction::AddUsers(_) | Instruction::RemoveUsers(_) => {
+                write!(f, "{} {}", self.command(), self.arguments().join(" "))
+            }
             Instruction::BlockUser(user) => write!(f, "block_user {}", user),
             Instruction::UnblockUser(user) => write!(f, "unblock_user {}", user),
             Instruction::IsBlockedUser(user) => write!(f, "is_blocked_user {}", user),
@@ -4819,6 +6172,9 @@ impl std::fmt::Display for Instruction {
             }
             Instruction::AddLocalUser(mapping) => write!(f, "add_local_user {}", mapping),
             Instruction::RemoveLocalUser(mapping) => write!(f, "remove_local_user {}", mapping),
+            Instruction::AddLocalUsers(_) | Instruction::RemoveLocalUsers(_) => {
+                write!(f, "{} {}", self.command(), self.arguments().join(" "))
+            }
             Instruction::UpdateHomeDir(user, homedir) => {
                 write!(f, "update_homedir {} {}", user, homedir)
             }
@@ -4839,10 +6195,20 @@ impl std::fmt::Display for Instruction {
             Instruction::GetUsageReports(portal, date_range) => {
                 write!(f, "get_usage_reports {} {}", portal, date_range)
             }
-            Instruction::GetLocalLimit(mapping) => write!(f, "get_local_limit {}", mapping),
-            Instruction::SetLocalLimit(mapping, usage) => {
-                write!(f, "set_local_limit {} {}", mapping, usage.seconds())
-            }
+            Instruction::GetLocalLimit(mapping, partition) => match partition {
+                Some(partition) => write!(f, "get_local_limit {} {}", mapping, partition),
+                None => write!(f, "get_local_limit {}", mapping),
+            },
+            Instruction::SetLocalLimit(mapping, usage, partition) => match partition {
+                Some(partition) => write!(
+                    f,
+                    "set_local_limit {} {} {}",
+                    mapping,
+                    usage.seconds(),
+                    partition
+                ),
+                None => write!(f, "set_local_limit {} {}", mapping, usage.seconds()),
+            },
             Instruction::SetLimit(project, usage) => {
                 write!(f, "set_limit {} {}", project, usage.seconds())
             }
@@ -4915,6 +6281,76 @@ impl std::fmt::Display for Instruction {
             Instruction::AddOfferings(offerings) => write!(f, "add_offerings {}", offerings),
             Instruction::RemoveOfferings(offerings) => write!(f, "remove_offerings {}", offerings),
             Instruction::GetOfferings() => write!(f, "get_offerings"),
+            Instruction::PurgeRecycled(dry_run) => match dry_run {
+                true => write!(f, "purge_recycled dry_run"),
+                false => write!(f, "purge_recycled"),
+            },
+            Instruction::RepairPermissions(mapping) => {
+                write!(f, "repair_permissions {}", mapping)
+            }
+            Instruction::SetLocalQos(mapping, class) => {
+                write!(f, "set_local_qos {} {}", mapping, class)
+            }
+            Instruction::GetLocalQos(mapping) => write!(f, "get_local_qos {}", mapping),
+            Instruction::CreateLocalReservation(mapping, dates, nodes, window) => {
+                write!(
+                    f,
+                    "create_local_reservation {} {} {} {}",
+                    mapping, dates, nodes, window
+                )
+            }
+            Instruction::RemoveLocalReservation(mapping, dates) => {
+                write!(f, "remove_local_reservation {} {}", mapping, dates)
+            }
+            Instruction::GetLocalJobQueue(mapping) => write!(f, "get_local_job_queue {}", mapping),
+            Instruction::GetJobQueue(project) => write!(f, "get_job_queue {}", project),
+            Instruction::SubmitJob(user, script) => write!(f, "submit_job {} {}", user, script),
+            Instruction::SubmitLocalJob(mapping, script) => {
+                write!(f, "submit_local_job {} {}", mapping, script)
+            }
+            Instruction::Reconcile(project, repair) => match repair {
+                true => write!(f, "reconcile {} repair", project),
+                false => write!(f, "reconcile {}", project),
+            },
+            Instruction::ReconcileLocal(mapping, repair, users) => {
+                write!(f, "reconcile_local {}", mapping)?;
+
+                if *repair {
+                    write!(f, " repair")?;
+                }
+
+                for user in users {
+                    write!(f, " {}", user)?;
+                }
+
+                Ok(())
+            }
+            Instruction::GetLocalNodes => write!(f, "get_local_nodes"),
+            Instruction::ExportAuditLog(date_range) => {
+                write!(f, "export_audit_log {}", date_range)
+            }
+            Instruction::AddUserSSHKey(user, key) => write!(f, "add_user_ssh_key {} {}", user, key),
+            Instruction::RemoveUserSSHKey(user, key) => {
+                write!(f, "remove_user_ssh_key {} {}", user, key)
+            }
+            Instruction::GetUserSSHKeys(user) => write!(f, "get_user_ssh_keys {}", user),
+            Instruction::AddUserToGroup(user, group) => {
+                write!(f, "add_user_to_group {} {}", user, group)
+            }
+            Instruction::RemoveUserFromGroup(user, group) => {
+                write!(f, "remove_user_from_group {} {}", user, group)
+            }
+            Instruction::ActivateUser(user) => write!(f, "activate_user {}", user),
+            Instruction::UpdateUser(user, details) => write!(f, "update_user {} {}", user, details),
+            Instruction::AddUserOTPToken(user) => write!(f, "add_user_otp_token {}", user),
+            Instruction::RemoveUserOTPToken(user, token) => {
+                write!(f, "remove_user_otp_token {} {}", user, token)
+            }
+            Instruction::GetUserOTPTokens(user) => write!(f, "get_user_otp_tokens {}", user),
+            Instruction::GetUserProtection(user) => write!(f, "get_user_protection {}", user),
+            Instruction::GetProjectProtection(project) => {
+                write!(f, "get_project_protection {}", project)
+            }
         }
     }
 }
@@ -5006,6 +6442,429 @@ mod tests {
             instruction,
             Instruction::UpdateHomeDir(user.clone(), "/home/user".to_string())
         );
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse("purge_recycled").unwrap();
+        assert_eq!(instruction, Instruction::PurgeRecycled(false));
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse("purge_recycled dry_run").unwrap();
+        assert_eq!(instruction, Instruction::PurgeRecycled(true));
+        assert_eq!(instruction.to_string(), "purge_recycled dry_run");
+
+        assert!(Instruction::parse("purge_recycled now").is_err());
+
+        #[allow(clippy::unwrap_used)]
+        let instruction =
+            Instruction::parse("repair_permissions project.portal:local_group").unwrap();
+        assert_eq!(
+            instruction,
+            Instruction::RepairPermissions(mapping.project())
+        );
+        assert_eq!(
+            instruction.to_string(),
+            "repair_permissions project.portal:local_group"
+        );
+
+        #[allow(clippy::unwrap_used)]
+        let instruction =
+            Instruction::parse("set_local_qos project.portal:local_group gpu-large").unwrap();
+        assert_eq!(
+            instruction,
+            Instruction::SetLocalQos(
+                mapping.project(),
+                #[allow(clippy::unwrap_used)]
+                ProjectTemplate::parse("gpu-large").unwrap()
+            )
+        );
+        assert_eq!(
+            instruction.to_string(),
+            "set_local_qos project.portal:local_group gpu-large"
+        );
+
+        assert!(Instruction::parse("set_local_qos project.portal:local_group").is_err());
+
+        #[allow(clippy::unwrap_used)]
+        let instruction =
+            Instruction::parse("set_local_limit project.portal:local_group 3600").unwrap();
+        assert_eq!(
+            instruction,
+            Instruction::SetLocalLimit(mapping.project(), Usage::new(3600), None)
+        );
+
+        #[allow(clippy::unwrap_used)]
+        let instruction =
+            Instruction::parse("set_local_limit project.portal:local_group 3600 gpu").unwrap();
+        assert_eq!(
+            instruction,
+            Instruction::SetLocalLimit(
+                mapping.project(),
+                Usage::new(3600),
+                Some("gpu".to_string())
+            )
+        );
+        assert_eq!(
+            instruction.to_string(),
+            "set_local_limit project.portal:local_group 3600 gpu"
+        );
+
+        #[allow(clippy::unwrap_used)]
+        let instruction =
+            Instruction::parse("get_local_limit project.portal:local_group gpu").unwrap();
+        assert_eq!(
+            instruction,
+            Instruction::GetLocalLimit(mapping.project(), Some("gpu".to_string()))
+        );
+
+        assert!(
+            Instruction::parse("set_local_limit project.portal:local_group 3600 gpu/a").is_err()
+        );
+        assert!(
+            Instruction::parse("get_local_limit project.portal:local_group gpu extra").is_err()
+        );
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse("get_local_qos project.portal:local_group").unwrap();
+        assert_eq!(instruction, Instruction::GetLocalQos(mapping.project()));
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse(
+            "create_local_reservation project.portal:local_group 2026-03-02:2026-03-06 4 2026-01-01:2026-12-31",
+        )
+        .unwrap();
+        #[allow(clippy::unwrap_used)]
+        let dates = DateRange::parse("2026-03-02:2026-03-06").unwrap();
+        #[allow(clippy::unwrap_used)]
+        let window = DateRange::parse("2026-01-01:2026-12-31").unwrap();
+        assert_eq!(
+            instruction,
+            Instruction::CreateLocalReservation(mapping.project(), dates.clone(), 4, window)
+        );
+        assert_eq!(
+            instruction.to_string(),
+            "create_local_reservation project.portal:local_group 2026-03-02:2026-03-06 4 2026-01-01:2026-12-31"
+        );
+
+        assert!(Instruction::parse(
+            "create_local_reservation project.portal:local_group 2026-03-02:2026-03-06 0 2026-01-01:2026-12-31"
+        )
+        .is_err());
+        assert!(Instruction::parse(
+            "create_local_reservation project.portal:local_group 2026-03-02:2026-03-06 4"
+        )
+        .is_err());
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse(
+            "remove_local_reservation project.portal:local_group 2026-03-02:2026-03-06",
+        )
+        .unwrap();
+        assert_eq!(
+            instruction,
+            Instruction::RemoveLocalReservation(mapping.project(), dates)
+        );
+
+        #[allow(clippy::unwrap_used)]
+        let instruction =
+            Instruction::parse("get_local_job_queue project.portal:local_group").unwrap();
+        assert_eq!(
+            instruction,
+            Instruction::GetLocalJobQueue(mapping.project())
+        );
+        assert_eq!(
+            instruction.to_string(),
+            "get_local_job_queue project.portal:local_group"
+        );
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse("get_job_queue project.portal").unwrap();
+        assert_eq!(
+            instruction,
+            Instruction::GetJobQueue(mapping.project().project().clone())
+        );
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse(
+            "submit_local_job user.project.portal:local_user:local_group /home/local_user/run.sh",
+        )
+        .unwrap();
+        assert_eq!(
+            instruction,
+            Instruction::SubmitLocalJob(mapping.clone(), "/home/local_user/run.sh".to_string())
+        );
+        assert_eq!(
+            instruction.to_string(),
+            "submit_local_job user.project.portal:local_user:local_group /home/local_user/run.sh"
+        );
+
+        #[allow(clippy::unwrap_used)]
+        let instruction =
+            Instruction::parse("submit_job user.project.portal /home/local_user/run.sh").unwrap();
+        assert_eq!(
+            instruction,
+            Instruction::SubmitJob(user.clone(), "/home/local_user/run.sh".to_string())
+        );
+
+        assert!(Instruction::parse("submit_job user.project.portal run.sh").is_err());
+        assert!(Instruction::parse("submit_job user.project.portal /home/../etc/run.sh").is_err());
+        assert!(Instruction::parse("submit_job user.project.portal /home/run.sh extra").is_err());
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse(
+            "reconcile_local project.portal:local_group repair user.project.portal:local_user:local_group",
+        )
+        .unwrap();
+        assert_eq!(
+            instruction,
+            Instruction::ReconcileLocal(mapping.project(), true, vec![mapping.clone()])
+        );
+        assert_eq!(
+            instruction.to_string(),
+            "reconcile_local project.portal:local_group repair user.project.portal:local_user:local_group"
+        );
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse("reconcile_local project.portal:local_group").unwrap();
+        assert_eq!(
+            instruction,
+            Instruction::ReconcileLocal(mapping.project(), false, vec![])
+        );
+
+        assert!(Instruction::parse(
+            "reconcile_local project.portal:local_group user.other.portal:local_user:local_group"
+        )
+        .is_err());
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse("reconcile project.portal repair").unwrap();
+        assert!(matches!(instruction, Instruction::Reconcile(_, true)));
+        assert_eq!(instruction.to_string(), "reconcile project.portal repair");
+
+        assert!(Instruction::parse("reconcile project.portal now").is_err());
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse("get_local_nodes").unwrap();
+        assert_eq!(instruction, Instruction::GetLocalNodes);
+        assert_eq!(instruction.to_string(), "get_local_nodes");
+
+        assert!(Instruction::parse("get_local_nodes all").is_err());
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse("export_audit_log 2026-01-01:2026-01-31").unwrap();
+        assert!(matches!(instruction, Instruction::ExportAuditLog(_)));
+        assert_eq!(
+            instruction.to_string(),
+            "export_audit_log 2026-01-01:2026-01-31"
+        );
+
+        assert!(Instruction::parse("export_audit_log this_month all").is_err());
+
+        #[allow(clippy::unwrap_used)]
+        let other = UserIdentifier::parse("other.project.portal").unwrap();
+        #[allow(clippy::unwrap_used)]
+        let other_mapping = UserMapping::new(&other, "other_user", "local_group").unwrap();
+
+        #[allow(clippy::unwrap_used)]
+        let instruction =
+            Instruction::parse("add_users user.project.portal other.project.portal").unwrap();
+        assert_eq!(
+            instruction,
+            Instruction::AddUsers(vec![user.clone(), other.clone()])
+        );
+        assert_eq!(
+            instruction.to_string(),
+            "add_users user.project.portal other.project.portal"
+        );
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse("remove_users user.project.portal").unwrap();
+        assert_eq!(instruction, Instruction::RemoveUsers(vec![user.clone()]));
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse(
+            "add_local_users user.project.portal:local_user:local_group other.project.portal:other_user:local_group",
+        )
+        .unwrap();
+        assert_eq!(
+            instruction,
+            Instruction::AddLocalUsers(vec![mapping.clone(), other_mapping.clone()])
+        );
+        assert_eq!(
+            instruction.to_string(),
+            "add_local_users user.project.portal:local_user:local_group other.project.portal:other_user:local_group"
+        );
+
+        #[allow(clippy::unwrap_used)]
+        let instruction =
+            Instruction::parse("remove_local_users user.project.portal:local_user:local_group")
+                .unwrap();
+        assert_eq!(
+            instruction,
+            Instruction::RemoveLocalUsers(vec![mapping.clone()])
+        );
+
+        assert!(Instruction::parse("add_users").is_err());
+        assert!(Instruction::parse("add_users user.project.portal user.project.portal").is_err());
+        assert!(Instruction::parse("add_users user.project.portal user.other.portal").is_err());
+        assert!(Instruction::parse("remove_local_users user.project.portal").is_err());
+
+        let key = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl user@host";
+
+        #[allow(clippy::unwrap_used)]
+        let instruction =
+            Instruction::parse(&format!("add_user_ssh_key user.project.portal {}", key)).unwrap();
+        assert!(matches!(instruction, Instruction::AddUserSSHKey(_, ref k) if k == key));
+        assert_eq!(
+            instruction.to_string(),
+            format!("add_user_ssh_key user.project.portal {}", key)
+        );
+
+        #[allow(clippy::unwrap_used)]
+        let instruction =
+            Instruction::parse(&format!("remove_user_ssh_key user.project.portal {}", key))
+                .unwrap();
+        assert!(matches!(instruction, Instruction::RemoveUserSSHKey(_, _)));
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse("get_user_ssh_keys user.project.portal").unwrap();
+        assert!(matches!(instruction, Instruction::GetUserSSHKeys(_)));
+        assert_eq!(
+            instruction.to_string(),
+            "get_user_ssh_keys user.project.portal"
+        );
+
+        #[allow(clippy::unwrap_used)]
+        let instruction =
+            Instruction::parse("add_user_to_group user.project.portal matlab-users").unwrap();
+        assert!(
+            matches!(instruction, Instruction::AddUserToGroup(_, ref g) if g == "matlab-users")
+        );
+        assert_eq!(
+            instruction.to_string(),
+            "add_user_to_group user.project.portal matlab-users"
+        );
+
+        #[allow(clippy::unwrap_used)]
+        let instruction =
+            Instruction::parse("remove_user_from_group user.project.portal matlab-users").unwrap();
+        assert!(matches!(
+            instruction,
+            Instruction::RemoveUserFromGroup(_, _)
+        ));
+
+        assert!(Instruction::parse("add_user_to_group user.project.portal").is_err());
+        assert!(Instruction::parse("add_user_to_group user.project.portal Admins").is_err());
+        assert!(Instruction::parse("add_user_to_group user.project.portal a b").is_err());
+
+        assert!(Instruction::parse("add_user_ssh_key user.project.portal").is_err());
+        assert!(
+            Instruction::parse("add_user_ssh_key user.project.portal ssh-dss AAAAB3Nz").is_err()
+        );
+        assert!(
+            Instruction::parse("add_user_ssh_key user.project.portal ssh-ed25519 not-base64!")
+                .is_err()
+        );
+
+        // the key data is for an ed25519 key, not an rsa key
+        assert!(Instruction::parse(
+            "add_user_ssh_key user.project.portal ssh-rsa AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl"
+        )
+        .is_err());
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse("activate_user user.project.portal").unwrap();
+        assert!(matches!(instruction, Instruction::ActivateUser(_)));
+        assert_eq!(instruction.to_string(), "activate_user user.project.portal");
+        assert!(Instruction::parse("activate_user user").is_err());
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse(
+            r#"update_user user.project.portal {"email": "user@example.com", "shell": "/bin/bash"}"#,
+        )
+        .unwrap();
+        assert!(
+            matches!(instruction, Instruction::UpdateUser(_, ref d) if d.email() == Some("user@example.com".to_string()) && d.display_name().is_none())
+        );
+        assert_eq!(
+            instruction.to_string(),
+            r#"update_user user.project.portal {"email":"user@example.com","shell":"/bin/bash"}"#
+        );
+        assert!(
+            Instruction::parse(r#"update_user user.project.portal {"email": "user"}"#).is_err()
+        );
+        assert!(
+            Instruction::parse(r#"update_user user.project.portal {"shell": "bash"}"#).is_err()
+        );
+        assert!(
+            Instruction::parse(r#"update_user user.project.portal {"display_name": " "}"#).is_err()
+        );
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse("add_user_otp_token user.project.portal").unwrap();
+        assert!(matches!(instruction, Instruction::AddUserOTPToken(_)));
+        assert_eq!(
+            instruction.to_string(),
+            "add_user_otp_token user.project.portal"
+        );
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse(
+            "remove_user_otp_token user.project.portal 6b0b3c7e-2c1f-4a5e-9d3b-0f4d2a1c8e77",
+        )
+        .unwrap();
+        assert!(matches!(instruction, Instruction::RemoveUserOTPToken(_, _)));
+        assert_eq!(
+            instruction.to_string(),
+            "remove_user_otp_token user.project.portal 6b0b3c7e-2c1f-4a5e-9d3b-0f4d2a1c8e77"
+        );
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse("get_user_otp_tokens user.project.portal").unwrap();
+        assert!(matches!(instruction, Instruction::GetUserOTPTokens(_)));
+
+        assert!(Instruction::parse("remove_user_otp_token user.project.portal").is_err());
+        assert!(Instruction::parse("remove_user_otp_token user.project.portal a/b").is_err());
+        assert!(Instruction::parse("remove_user_otp_token user.project.portal a b").is_err());
+
+        // suspend and reactivate are only aliases of block and unblock,
+        // so they round-trip as block_user and unblock_user
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse("suspend_user user.project.portal").unwrap();
+        assert!(matches!(instruction, Instruction::BlockUser(_)));
+        assert_eq!(instruction.to_string(), "block_user user.project.portal");
+        #[allow(clippy::unwrap_used)]
+        let reparsed = Instruction::parse(&instruction.to_string()).unwrap();
+        assert_eq!(reparsed, instruction);
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse("reactivate_user user.project.portal").unwrap();
+        assert!(matches!(instruction, Instruction::UnblockUser(_)));
+        assert_eq!(instruction.to_string(), "unblock_user user.project.portal");
+        #[allow(clippy::unwrap_used)]
+        let reparsed = Instruction::parse(&instruction.to_string()).unwrap();
+        assert_eq!(reparsed, instruction);
+
+        assert!(Instruction::parse("suspend_user").is_err());
+        assert!(Instruction::parse("reactivate_user not-a-user").is_err());
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse("get_user_protection user.project.portal").unwrap();
+        assert!(matches!(instruction, Instruction::GetUserProtection(_)));
+        assert_eq!(
+            instruction.to_string(),
+            "get_user_protection user.project.portal"
+        );
+
+        #[allow(clippy::unwrap_used)]
+        let instruction = Instruction::parse("get_project_protection project.portal").unwrap();
+        assert!(matches!(instruction, Instruction::GetProjectProtection(_)));
+        assert_eq!(
+            instruction.to_string(),
+            "get_project_protection project.portal"
+        );
+
+        assert!(Instruction::parse("get_user_protection project.portal").is_err());
+        assert!(Instruction::parse("get_project_protection portal").is_err());
     }
 
     #[test]
@@ -3,17 +3,29 @@
 
 use crate::agent;
 use crate::agent::{Peer, Type as AgentType};
+use crate::audit;
+use crate::batch;
+use crate::chaos;
 use crate::command::Command;
 use crate::control_message::process_control_message;
 use crate::destination::Position;
 use crate::diagnostics;
+use crate::dryrun;
 use crate::error::Error;
+use crate::grammar::Instruction;
 use crate::health;
-use crate::job::{sync_from_peer, Envelope, Status};
+use crate::invitation;
+use crate::job::{sync_from_peer, Envelope, Job, Status};
 use crate::jobtiming;
+use crate::keyrotation;
+use crate::maintenance;
 use crate::notification::{default_notify_runner, AsyncNotifyRunnable, NotificationEnvelope};
 use crate::restart;
+use crate::role;
 use crate::runnable::{default_runner, AsyncRunnable};
+use crate::systemd;
+use crate::systeminfo;
+use crate::watchdog;
 
 use anyhow::Result;
 use once_cell::sync::Lazy;
@@ -23,6 +35,7 @@ use std::boxed::Box;
 use std::collections::HashSet;
 use std::sync::{Arc, Mutex};
 use tokio::sync::RwLock;
+use tracing::Instrument;
 
 #[derive(Debug, Clone)]
 struct ServiceDetails {
@@ -69,6 +82,9 @@ pub async fn set_my_service_details(
         service_details.runner = runner;
     }
 
+    // tell systemd (if it runs this agent) once the agent is ready
+    systemd::spawn();
+
     Ok(())
 }
 
@@ -88,6 +104,64 @@ pub async fn invoke_notify_runner(envelope: NotificationEnvelope) -> Result<()>
     Ok(())
 }
 
+///
+/// Run a job directly on this agent's registered runner, in the same
+/// way as one-shot commands. Used by the admin socket.
+///
+pub(crate) async fn invoke_runner(envelope: Envelope) -> Result<Job, Error> {
+    let runner = SERVICE_DETAILS.read().await.runner;
+    runner(envelope).await
+}
+
+///
+/// Run the job in the envelope with the passed runner. Dry run jobs are
+/// run in a scope that records the changes they would make, which are
+/// returned with the finished job. Dry runs of instructions that change
+/// anything are refused by agents that cannot honour them
+///
+async fn run_job(runner: &AsyncRunnable, envelope: Envelope) -> Result<Job, Error> {
+    if let Some(error) = chaos::error(&envelope.job()) {
+        return Err(error);
+    }
+
+    // jobs sent to mock agents are run by the mock
+    #[cfg(feature = "mock")]
+    let is_mock = crate::mock::is_mock(&envelope.recipient()).await;
+    #[cfg(not(feature = "mock"))]
+    let is_mock = false;
+    #[cfg(feature = "mock")]
+    let mock_runner: AsyncRunnable = crate::mock::mock_runner;
+    #[cfg(feature = "mock")]
+    let runner = match is_mock {
+        true => &mock_runner,
+        false => runner,
+    };
+
+    let job = envelope.job();
+
+    // mocks and dry runs always run a batch one user at a time
+    let native = batch::is_native() && !is_mock && !job.is_dry_run();
+
+    if !job.is_dry_run() {
+        return batch::run(runner, envelope, native).await;
+    }
+
+    let recipient = envelope.recipient();
+
+    if job.instruction().is_mutating() && !dryrun::is_supported(&agent::my_agent_type().await) {
+        return Err(Error::Incompatible(format!(
+            "{} does not support dry runs, so cannot rehearse: {}",
+            recipient.name(),
+            job.instruction()
+        )));
+    }
+
+    let (result, changes) =
+        dryrun::run(recipient.name(), batch::run(runner, envelope, native)).await;
+
+    Ok(result?.with_changes(changes))
+}
+
 ///
 /// This is the main function that processes a command sent via the OpenPortal system
 /// This will either route the command to the right place, or if the command has reached
@@ -157,6 +231,16 @@ async fn process_command(
                 version
             );
             agent::register_peer(&Peer::new(sender, zone), agent, engine, version).await;
+
+            let used_by = format!("{} agent ({} {})", agent, engine, version);
+
+            if let Err(e) = invitation::record_use(sender, zone, &used_by).await {
+                tracing::warn!(
+                    "Could not record the use of the invitation for {}: {}",
+                    sender,
+                    e
+                );
+            }
         }
         Command::Update { job } => {
             if job.is_expired() {
@@ -209,6 +293,15 @@ async fn process_command(
 
             tracing::debug!("Put job: {:?} to {} from {}", job, recipient, peer,);
 
+            // the sender must have the role needed for the instruction,
+            // whether the job is run here or passed further downstream
+            if let Err(e) = role::authorize(&peer, &job.instruction()) {
+                tracing::warn!("Refusing job {} from {}: {}", job.id(), peer, e);
+                let job = job.errored(&e.to_string())?;
+                let _ = job.update(&peer).await?;
+                return Ok(());
+            }
+
             // update the sender's board with the received job
             let mut job = match job.received(&peer).await {
                 Ok(job) => job,
@@ -276,49 +369,87 @@ async fn process_command(
                                     job.instruction()
                                 );
 
-                                // Start timing the job execution
-                                let start_time = std::time::Instant::now();
+                                // Don't start new jobs while over the hard resource
+                                // limits, or while draining ahead of a scheduled restart
+                                let waited = match systeminfo::wait_for_resources(&job).await {
+                                    Ok(()) => maintenance::wait_while_draining(&job).await,
+                                    Err(e) => Err(e),
+                                };
 
-                                // Record job started for diagnostics
-                                diagnostics::record_job_started(&job).await;
+                                if let Err(e) = waited {
+                                    // the job expired or was held over a restart, so
+                                    // tell the sender rather than leave it waiting
+                                    tracing::error!("Not running job {}: {}", job.id(), e);
+                                    job = job.errored(&e.to_string())?;
+                                    diagnostics::record_failed_job(&job, e.to_string()).await;
+                                } else {
+                                    // Start timing the job execution
+                                    let start_time = std::time::Instant::now();
+
+                                    // Record job started for diagnostics and the watchdog
+                                    diagnostics::record_job_started(&job).await;
+                                    watchdog::job_started();
+
+                                    job = match job.instruction() {
+                                        // the audit log is kept by every agent, so
+                                        // is exported here rather than by the runner
+                                        Instruction::ExportAuditLog(date_range) => {
+                                            match audit::export(&date_range).await {
+                                                Ok(log) => job.completed(log)?,
+                                                Err(e) => job.errored(&e.to_string())?,
+                                            }
+                                        }
+                                        _ => match run_job(
+                                            runner,
+                                            Envelope::new(recipient, sender, zone, &job),
+                                        )
+                                        .await
+                                        {
+                                            Ok(job) => job,
+                                            Err(e) => {
+                                                tracing::error!("Error running job: {}", e);
+                                                job.errored(&e.to_string())?
+                                            }
+                                        },
+                                    };
+
+                                    if let Err(e) = audit::record(sender, zone, &job).await {
+                                        tracing::error!(
+                                            "Could not record job {} in the audit log: {}",
+                                            job.id(),
+                                            e
+                                        );
+                                    }
 
-                                job = match runner(Envelope::new(recipient, sender, zone, &job))
-                                    .await
-                                {
-                                    Ok(job) => job,
-                                    Err(e) => {
-                                        tracing::error!("Error running job: {}", e);
-                                        job.errored(&e.to_string())?
+                                    // Record the job execution time
+                                    let duration = start_time.elapsed();
+                                    let duration_ms = duration.as_secs_f64() * 1000.0;
+                                    jobtiming::record_job_time(duration_ms);
+
+                                    // Record job finished for diagnostics and the watchdog
+                                    diagnostics::record_job_finished(&job).await;
+                                    watchdog::job_finished();
+
+                                    // Track failures and slow jobs
+                                    if job.is_expired() {
+                                        diagnostics::record_expired_job(&job).await;
+                                    } else if job.is_error() {
+                                        let error_msg = job
+                                            .error_message()
+                                            .unwrap_or_else(|| "Unknown error".to_string());
+                                        diagnostics::record_failed_job(&job, error_msg).await;
+                                        diagnostics::record_slow_job(&job, duration_ms).await;
+                                    } else {
+                                        diagnostics::record_completed_job(&job).await;
+                                        diagnostics::record_slow_job(&job, duration_ms).await;
                                     }
-                                };
 
-                                // Record the job execution time
-                                let duration = start_time.elapsed();
-                                let duration_ms = duration.as_secs_f64() * 1000.0;
-                                jobtiming::record_job_time(duration_ms);
-
-                                // Record job finished for diagnostics
-                                diagnostics::record_job_finished(&job).await;
-
-                                // Track failures and slow jobs
-                                if job.is_expired() {
-                                    diagnostics::record_expired_job(&job).await;
-                                } else if job.is_error() {
-                                    let error_msg = job
-                                        .error_message()
-                                        .unwrap_or_else(|| "Unknown error".to_string());
-                                    diagnostics::record_failed_job(&job, error_msg).await;
-                                    diagnostics::record_slow_job(&job, duration_ms).await;
-                                } else {
-                                    diagnostics::record_completed_job(&job).await;
-                                    diagnostics::record_slow_job(&job, duration_ms).await;
+                                    tracing::debug!(
+                                        "Job {} completed in {:.2}ms",
+                                        job.id(),
+                                        duration_ms
+                                    );
                                 }
-
-                                tracing::debug!(
-                                    "Job {} completed in {:.2}ms",
-                                    job.id(),
-                                    duration_ms
-                                );
                             }
                         }
                     }
@@ -511,6 +642,51 @@ async fn process_command(
             diagnostics::cache_diagnostics_response(report.agent_name.clone(), *report.clone())
                 .await;
         }
+        Command::DumpBoardRequest { destination } => {
+            tracing::debug!(
+                "Received board dump request from {} (destination: {})",
+                sender,
+                destination
+            );
+
+            // Security: Portals must not dump their boards to other portals
+            // to prevent information leakage between sites
+            let my_type = agent::my_agent_type().await;
+            let sender_peer = Peer::new(sender, zone);
+
+            if my_type == agent::Type::Portal {
+                if let Some(sender_type) = agent::agent_type(&sender_peer).await {
+                    if sender_type == agent::Type::Portal {
+                        tracing::warn!(
+                            "Ignoring board dump request from portal {} - portals do not share boards with other portals",
+                            sender
+                        );
+                        return Ok(());
+                    }
+                }
+            }
+
+            let dump = diagnostics::collect_board_dump(destination).await?;
+
+            tracing::debug!("Board dump: {}", dump);
+
+            let response = Command::dump_board_response(dump);
+            response.send_to(&sender_peer).await?;
+        }
+        Command::DumpBoardResponse { dump } => {
+            tracing::debug!("Received board dump response from {}", dump.agent_name);
+
+            diagnostics::cache_board_dump_response(dump.agent_name.clone(), *dump.clone()).await;
+        }
+        Command::RotateKeys { invite } => {
+            keyrotation::received_keys(sender, zone, invite).await?;
+        }
+        Command::KeysRotated => {
+            keyrotation::keys_confirmed(sender, zone).await?;
+        }
+        Command::RequestKeyRotation => {
+            keyrotation::rotation_requested(sender, zone).await?;
+        }
         Command::Notify { notification } => {
             diagnostics::increment_notification_received().await;
             tracing::debug!(
@@ -611,6 +787,40 @@ async fn process_command(
     Ok(())
 }
 
+///
+/// Return the span in which the command is processed. Every log line
+/// written while the command is processed, including by the agent's
+/// runner, carries the span's fields. As a job keeps its id as it moves
+/// between agents, this lets the JSON logs (RUST_LOG_FORMAT=json) of
+/// every agent that handled a job be correlated.
+///
+fn command_span(recipient: &str, sender: &str, zone: &str, command: &Command) -> tracing::Span {
+    let peer = Peer::new(sender, zone);
+
+    // spans are created at error level so that their fields are kept
+    // whatever the value of RUST_LOG
+    match command {
+        Command::Put { job } | Command::Update { job } | Command::Delete { job } => {
+            tracing::error_span!(
+                "job",
+                agent = recipient,
+                peer = %peer,
+                job_id = %job.id(),
+                instruction = %job.instruction(),
+                destination = %job.destination()
+            )
+        }
+        Command::Notify { notification } => tracing::error_span!(
+            "notification",
+            agent = recipient,
+            peer = %peer,
+            notification_id = %notification.id(),
+            destination = %notification.destination()
+        ),
+        _ => tracing::error_span!("command", agent = recipient, peer = %peer),
+    }
+}
+
 async_message_handler! {
     ///
     /// Message handler for most templemeads agents
@@ -692,6 +902,8 @@ async_message_handler! {
                     }
                 }
 
+                let span = command_span(&recipient, &sender, &zone, &command);
+
                 process_command(
                     &recipient,
                     &sender,
@@ -700,6 +912,7 @@ async_message_handler! {
                     &service_info.runner,
                     &service_info.notify_runner,
                 )
+                .instrument(span)
                 .await?;
 
                 Ok(())
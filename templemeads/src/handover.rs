@@ -0,0 +1,129 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Handing over from a running instance of an agent to a new instance
+//!
+//! An agent run with `run --handover` takes over from the instance that
+//! is already running with the same config file, e.g. after the binary
+//! has been upgraded. The new instance starts listening on the service
+//! port (if the running instance shares it), then asks the running
+//! instance over its admin socket to hand over. The running instance stops
+//! accepting connections, drains its running jobs, and replies with its
+//! boards before exiting. The new instance loads those boards, so that
+//! finished and in-flight jobs are not lost, and its peers reconnect to
+//! it straight away rather than finding the port closed.
+
+use crate::admin::{self, AdminRequest, AdminResponse};
+use crate::board::Board;
+use crate::error::Error;
+use crate::maintenance;
+use crate::state;
+
+use paddington::config::ServiceConfig;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::net::UnixStream;
+
+/// How long to wait for the previous instance to exit after handing over
+const EXIT_TIMEOUT: u64 = 30;
+
+/// Whether this instance is already handing over
+static HANDING_OVER: AtomicBool = AtomicBool::new(false);
+
+///
+/// Hand over to a new instance of this agent. This stops accepting new
+/// connections, drains the running jobs, and returns the boards that
+/// the new instance should take over. The agent should exit once the
+/// boards have been sent
+///
+pub(crate) async fn hand_over() -> Result<Vec<Board>, Error> {
+    if HANDING_OVER.swap(true, Ordering::Relaxed) {
+        return Err(Error::InvalidState(
+            "This agent is already handing over to a new instance".to_owned(),
+        ));
+    }
+
+    tracing::warn!("Handing over to a new instance of this agent");
+
+    paddington::stop_listening();
+    maintenance::drain("handover").await;
+
+    let boards = state::export_boards().await;
+
+    tracing::info!("Handing over {} board(s)", boards.len());
+
+    Ok(boards)
+}
+
+///
+/// Take over from the instance of this agent that is listening on the
+/// admin socket at `socket`, loading the boards that it hands over.
+/// This returns once the previous instance has exited
+///
+pub(crate) async fn take_over(service: &ServiceConfig, socket: &Path) -> Result<(), Error> {
+    // listen before the previous instance stops, so that connections
+    // are queued for this instance rather than refused. Every agent
+    // shares its port, so this only fails if the port is held by another
+    // user's process, in which case this instance starts listening once
+    // the previous instance has exited
+    if !service.clients().is_empty() {
+        match paddington::listen(service) {
+            Ok(()) => {}
+            Err(paddington::Error::IO(e)) if e.kind() == std::io::ErrorKind::AddrInUse => {
+                tracing::warn!(
+                    "The service port is in use by a process that does not share it, so \
+                     connections will be refused until the running agent has handed over"
+                );
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    tracing::info!(
+        "Asking the agent on {} to hand over to this instance",
+        socket.display()
+    );
+
+    let boards = match admin::send_request(socket, &AdminRequest::Handover).await? {
+        AdminResponse::Handover { boards } => boards,
+        AdminResponse::Error { error } => {
+            return Err(Error::Unavailable(format!(
+                "The running agent could not hand over: {}",
+                error
+            )))
+        }
+        response => {
+            return Err(Error::Bug(format!(
+                "Unexpected response to a handover request: {:?}",
+                response
+            )))
+        }
+    };
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(EXIT_TIMEOUT);
+
+    while UnixStream::connect(socket).await.is_ok() {
+        if std::time::Instant::now() >= deadline {
+            return Err(Error::Unavailable(format!(
+                "The previous agent did not exit within {}s of handing over",
+                EXIT_TIMEOUT
+            )));
+        }
+
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    let name = service.name();
+
+    for board in boards {
+        tracing::info!(
+            "Taking over the board shared with {} ({} jobs)",
+            board.peer(),
+            board.jobs().len()
+        );
+
+        state::import_board(board, &name).await;
+    }
+
+    Ok(())
+}
@@ -7,10 +7,15 @@
 //! across the agent network.
 
 use crate::agent::{self, Peer, Type as AgentType};
+use crate::board::BoardJobStats;
+use crate::circuitbreaker;
 use crate::command::Command;
+use crate::destination::Destination;
 use crate::diagnostics;
+use crate::error::Error;
 use crate::grammar::NamedType;
 use crate::jobtiming;
+use crate::notification::{self, NotificationEvent};
 use crate::state;
 use crate::systeminfo;
 
@@ -53,6 +58,9 @@ pub struct HealthInfo {
     pub queued_jobs: usize,
     /// Number of active worker tasks processing messages
     pub worker_count: usize,
+    /// Number of replayed messages rejected since the agent started
+    #[serde(default)]
+    pub replays_rejected: u64,
     /// Memory usage of this agent process in bytes
     pub memory_bytes: u64,
     /// CPU usage of this agent process (percentage, 0.0-100.0)
@@ -89,13 +97,64 @@ pub struct HealthInfo {
     pub engine: String,
     /// Engine version
     pub version: String,
+    /// Crypto mode used by this agent ("standard" or "fips")
+    #[serde(default)]
+    pub crypto_mode: String,
     /// Time when this health response was received/cached
     pub last_updated: DateTime<Utc>,
+    /// Active warnings raised by this agent (e.g. a backed-up bridge board)
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    /// Status of the external services that this agent depends on
+    /// (e.g. slurmrestd, FreeIPA, volume mounts or the signal URL)
+    #[serde(default)]
+    pub dependencies: Vec<DependencyStatus>,
+    /// State of the circuit breakers protecting calls to third-party
+    /// services (e.g. slurmrestd, FreeIPA or the signal URL)
+    #[serde(default)]
+    pub breakers: Vec<BreakerStatus>,
+    /// Connection availability of each of this agent's peers over
+    /// rolling 24 hour, 7 day and 30 day windows
+    #[serde(default)]
+    pub availability: Vec<PeerAvailability>,
     /// Nested health information from downstream peers
     #[serde(default)]
     pub peers: HashMap<String, Box<HealthInfo>>,
 }
 
+/// Status of an external service that an agent depends on
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, TS)]
+#[ts(export)]
+pub struct DependencyStatus {
+    /// Name of the dependency, e.g. "slurmrestd:https://slurm:6820"
+    pub name: String,
+    /// Whether the dependency passed its last check
+    pub healthy: bool,
+    /// Details of the last check (e.g. why it failed)
+    pub detail: String,
+    /// Time when the dependency was last checked
+    pub last_checked: DateTime<Utc>,
+}
+
+/// State of a circuit breaker protecting calls to a third-party service
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, TS)]
+#[ts(export)]
+pub struct BreakerStatus {
+    /// Name of the breaker, e.g. "slurmrestd"
+    pub name: String,
+    /// "closed" (calls are made), "open" (calls fail fast) or
+    /// "half-open" (a probe call is let through)
+    pub state: String,
+    /// Number of consecutive failed calls
+    pub failures: u32,
+    /// The error from the last failed call
+    pub last_error: String,
+    /// Time when the breaker last opened, if it is not closed
+    pub opened_at: Option<DateTime<Utc>>,
+    /// Number of calls rejected because the breaker was open
+    pub rejected: u64,
+}
+
 impl HealthInfo {
     pub fn new(
         name: &str,
@@ -123,6 +182,7 @@ impl HealthInfo {
             inflight_jobs: 0,
             queued_jobs: 0,
             worker_count: 0,
+            replays_rejected: 0,
             memory_bytes: 0,
             cpu_percent: 0.0,
             system_memory_total: 0,
@@ -141,7 +201,18 @@ impl HealthInfo {
             uptime_seconds,
             engine: engine.to_owned(),
             version: version.to_owned(),
+            crypto_mode: match paddington::crypto_mode() {
+                Ok(mode) => mode.to_string(),
+                Err(e) => {
+                    tracing::error!("Could not get the crypto mode: {}", e);
+                    "unknown".to_owned()
+                }
+            },
             last_updated: current_time,
+            warnings: Vec::new(),
+            dependencies: Vec::new(),
+            breakers: Vec::new(),
+            availability: Vec::new(),
             peers: HashMap::new(),
         }
     }
@@ -227,6 +298,14 @@ impl HealthInfo {
         // Worker count
         output.push_str(&format!("{}│  Workers: {}\n", prefix, self.worker_count));
 
+        // Replayed messages (which could mean an attack, so always flagged)
+        if self.replays_rejected > 0 {
+            output.push_str(&format!(
+                "{}│  Replays Rejected: {} ⚠️\n",
+                prefix, self.replays_rejected
+            ));
+        }
+
         // Job statistics with warnings for high counts
         let pending_warning = if self.pending_jobs > 100 {
             " ⚠️"
@@ -343,6 +422,15 @@ impl HealthInfo {
             prefix, self.engine, self.version
         ));
 
+        // Crypto mode (not reported by older agents)
+        if !self.crypto_mode.is_empty() {
+            output.push_str(&format!(
+                "{}│  Crypto: {}
+",
+                prefix, self.crypto_mode
+            ));
+        }
+
         // Last updated timestamp
         let age = Utc::now()
             .signed_duration_since(self.last_updated)
@@ -359,6 +447,56 @@ impl HealthInfo {
             age_str
         ));
 
+        // Active warnings raised by the agent
+        for warning in &self.warnings {
+            output.push_str(&format!("{}│  ⚠️  {}\n", prefix, warning));
+        }
+
+        // External services that the agent depends on
+        for dependency in &self.dependencies {
+            let status = match dependency.healthy {
+                true => "ok",
+                false => "FAILED ⚠️",
+            };
+
+            output.push_str(&format!(
+                "{}│  Dependency {}: {} ({})\n",
+                prefix, dependency.name, status, dependency.detail
+            ));
+        }
+
+        // Circuit breakers that are failing calls fast
+        for breaker in self.breakers.iter().filter(|b| b.state != "closed") {
+            output.push_str(&format!(
+                "{}│  Circuit breaker {}: {} ⚠️ ({} failures, {} rejected calls, last error: {})\n",
+                prefix,
+                breaker.name,
+                breaker.state,
+                breaker.failures,
+                breaker.rejected,
+                breaker.last_error
+            ));
+        }
+
+        // Connection availability of each peer
+        for link in &self.availability {
+            let status = match link.connected {
+                true => "up",
+                false => "DOWN ⚠️",
+            };
+
+            output.push_str(&format!(
+                "{}│  Link {}: {} (24h {:.2}%, 7d {:.2}%, 30d {:.2}%, {} disconnections)\n",
+                prefix,
+                link.peer,
+                status,
+                link.availability_24h,
+                link.availability_7d,
+                link.availability_30d,
+                link.disconnections_30d
+            ));
+        }
+
         // Peer health information (recursively formatted)
         if !self.peers.is_empty() {
             output.push_str(&format!("{}│  Peers: {}\n", prefix, self.peers.len()));
@@ -486,6 +624,756 @@ impl std::fmt::Display for HealthInfo {
     }
 }
 
+///
+/// Active warnings raised by this agent, keyed by the subsystem
+/// that raised them. This uses a std Mutex so that warnings can be
+/// raised from synchronous code (e.g. while holding a board lock)
+///
+static WARNINGS: Lazy<std::sync::Mutex<HashMap<String, String>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+///
+/// Raise (or replace) the health warning for the passed key
+///
+pub fn set_warning(key: &str, message: &str) {
+    match WARNINGS.lock() {
+        Ok(mut warnings) => {
+            if warnings.get(key).map(|m| m.as_str()) != Some(message) {
+                tracing::warn!("Health warning [{}]: {}", key, message);
+                warnings.insert(key.to_owned(), message.to_owned());
+            }
+        }
+        Err(e) => {
+            tracing::error!("Could not lock health warnings: {}", e);
+        }
+    }
+}
+
+///
+/// Clear the health warning for the passed key (if any)
+///
+pub fn clear_warning(key: &str) {
+    match WARNINGS.lock() {
+        Ok(mut warnings) => {
+            if warnings.remove(key).is_some() {
+                tracing::info!("Health warning [{}] cleared", key);
+            }
+        }
+        Err(e) => {
+            tracing::error!("Could not lock health warnings: {}", e);
+        }
+    }
+}
+
+///
+/// Return all of the active health warnings for this agent
+///
+pub fn get_warnings() -> Vec<String> {
+    match WARNINGS.lock() {
+        Ok(warnings) => {
+            let mut keys: Vec<_> = warnings.keys().collect();
+            keys.sort();
+            keys.iter()
+                .filter_map(|key| warnings.get(*key).cloned())
+                .collect()
+        }
+        Err(e) => {
+            tracing::error!("Could not lock health warnings: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+///
+/// The status of each external service that this agent depends on,
+/// keyed by the name of the dependency. This uses a std Mutex so that
+/// it can be updated from synchronous code
+///
+static DEPENDENCIES: Lazy<std::sync::Mutex<HashMap<String, DependencyStatus>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+///
+/// Record the result of checking the named external dependency.
+/// `detail` should say why the check failed, or describe the
+/// healthy dependency
+///
+pub fn set_dependency(name: &str, healthy: bool, detail: &str) {
+    match DEPENDENCIES.lock() {
+        Ok(mut dependencies) => {
+            let changed = dependencies
+                .get(name)
+                .map(|d| d.healthy != healthy)
+                .unwrap_or(!healthy);
+
+            if changed {
+                match healthy {
+                    true => tracing::info!("Dependency {} is healthy: {}", name, detail),
+                    false => tracing::warn!("Dependency {} is unhealthy: {}", name, detail),
+                }
+            }
+
+            dependencies.insert(
+                name.to_owned(),
+                DependencyStatus {
+                    name: name.to_owned(),
+                    healthy,
+                    detail: detail.to_owned(),
+                    last_checked: Utc::now(),
+                },
+            );
+        }
+        Err(e) => {
+            tracing::error!("Could not lock health dependencies: {}", e);
+        }
+    }
+}
+
+///
+/// Stop reporting the named dependency (e.g. because it has been
+/// removed from the agent's configuration)
+///
+pub fn remove_dependency(name: &str) {
+    match DEPENDENCIES.lock() {
+        Ok(mut dependencies) => {
+            dependencies.remove(name);
+        }
+        Err(e) => {
+            tracing::error!("Could not lock health dependencies: {}", e);
+        }
+    }
+}
+
+///
+/// Return the status of all of the external dependencies of this
+/// agent, sorted by name
+///
+pub fn get_dependencies() -> Vec<DependencyStatus> {
+    match DEPENDENCIES.lock() {
+        Ok(dependencies) => {
+            let mut dependencies: Vec<_> = dependencies.values().cloned().collect();
+            dependencies.sort_by(|a, b| a.name.cmp(&b.name));
+            dependencies
+        }
+        Err(e) => {
+            tracing::error!("Could not lock health dependencies: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+///
+/// How often (in seconds) the alert rules are checked
+///
+const ALERT_CHECK_INTERVAL: u64 = 15;
+
+///
+/// The health metrics of this agent that can be watched by an alert rule
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlertMetric {
+    PendingJobs,
+    RunningJobs,
+    QueuedJobs,
+    InflightJobs,
+    ErroredJobs,
+    ExpiredJobs,
+    WorkerCount,
+    PeerDisconnected,
+}
+
+impl AlertMetric {
+    pub fn parse(metric: &str) -> Result<Self, Error> {
+        match metric.trim() {
+            "pending_jobs" => Ok(Self::PendingJobs),
+            "running_jobs" => Ok(Self::RunningJobs),
+            "queued_jobs" => Ok(Self::QueuedJobs),
+            "inflight_jobs" => Ok(Self::InflightJobs),
+            "errored_jobs" => Ok(Self::ErroredJobs),
+            "expired_jobs" => Ok(Self::ExpiredJobs),
+            "worker_count" => Ok(Self::WorkerCount),
+            "peer_disconnected" => Ok(Self::PeerDisconnected),
+            other => Err(Error::Parse(format!(
+                "Unknown alert metric '{}'. This must be one of pending_jobs, running_jobs, \
+                 queued_jobs, inflight_jobs, errored_jobs, expired_jobs, worker_count \
+                 or peer_disconnected",
+                other
+            ))),
+        }
+    }
+
+    ///
+    /// Return the current value of this metric, or None for metrics
+    /// (like peer_disconnected) that are not a single number
+    ///
+    fn value(&self, stats: &BoardJobStats) -> Option<u64> {
+        match self {
+            Self::PendingJobs => Some(stats.pending as u64),
+            Self::RunningJobs => Some(stats.running as u64),
+            Self::QueuedJobs => Some(stats.queued as u64),
+            Self::InflightJobs => Some(stats.in_flight as u64),
+            Self::ErroredJobs => Some(stats.errored as u64),
+            Self::ExpiredJobs => Some(stats.expired as u64),
+            Self::WorkerCount => Some(paddington::worker_count() as u64),
+            Self::PeerDisconnected => None,
+        }
+    }
+}
+
+impl std::fmt::Display for AlertMetric {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::PendingJobs => write!(f, "pending_jobs"),
+            Self::RunningJobs => write!(f, "running_jobs"),
+            Self::QueuedJobs => write!(f, "queued_jobs"),
+            Self::InflightJobs => write!(f, "inflight_jobs"),
+            Self::ErroredJobs => write!(f, "errored_jobs"),
+            Self::ExpiredJobs => write!(f, "expired_jobs"),
+            Self::WorkerCount => write!(f, "worker_count"),
+            Self::PeerDisconnected => write!(f, "peer_disconnected"),
+        }
+    }
+}
+
+///
+/// Parse a duration such as "90s", "10m", "2h" or "1d". A number
+/// without a unit is a number of seconds
+///
+pub(crate) fn parse_duration(duration: &str) -> Result<u64, Error> {
+    Ok(paddington::config::parse_duration(duration)?)
+}
+
+fn format_duration(seconds: u64) -> String {
+    if seconds == 0 {
+        "0s".to_owned()
+    } else if seconds.is_multiple_of(24 * 60 * 60) {
+        format!("{}d", seconds / (24 * 60 * 60))
+    } else if seconds.is_multiple_of(60 * 60) {
+        format!("{}h", seconds / (60 * 60))
+    } else if seconds.is_multiple_of(60) {
+        format!("{}m", seconds / 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+///
+/// A rule that raises an alert when a health metric of this agent
+/// stays above a threshold for longer than a duration, written as
+///
+///   pending_jobs > 100 for 10m
+///   peer_disconnected > 5m
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertRule {
+    metric: AlertMetric,
+    threshold: u64,
+    duration: u64,
+}
+
+impl AlertRule {
+    pub fn parse(rule: &str) -> Result<Self, Error> {
+        let (metric, rest) = rule.split_once('>').ok_or_else(|| {
+            Error::Parse(format!(
+                "Invalid alert rule '{}'. It should be '<metric> > <threshold> [for <duration>]'",
+                rule.trim()
+            ))
+        })?;
+
+        let metric = AlertMetric::parse(metric)?;
+        let rest = rest.trim();
+
+        if metric == AlertMetric::PeerDisconnected {
+            // the threshold of a disconnection is how long it has lasted
+            return Ok(Self {
+                metric,
+                threshold: 0,
+                duration: parse_duration(rest)?,
+            });
+        }
+
+        let (threshold, duration) = match rest.split_once(" for ") {
+            Some((threshold, duration)) => (threshold, parse_duration(duration)?),
+            None => (rest, 0),
+        };
+
+        let threshold = threshold.trim().parse::<u64>().map_err(|_| {
+            Error::Parse(format!(
+                "Invalid threshold '{}' in alert rule '{}'",
+                threshold.trim(),
+                rule.trim()
+            ))
+        })?;
+
+        Ok(Self {
+            metric,
+            threshold,
+            duration,
+        })
+    }
+
+    pub fn metric(&self) -> AlertMetric {
+        self.metric
+    }
+
+    pub fn threshold(&self) -> u64 {
+        self.threshold
+    }
+
+    ///
+    /// Return the number of seconds that the threshold must be
+    /// exceeded before the alert is raised
+    ///
+    pub fn duration(&self) -> u64 {
+        self.duration
+    }
+}
+
+impl std::fmt::Display for AlertRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (self.metric, self.duration) {
+            (AlertMetric::PeerDisconnected, duration) => {
+                write!(f, "{} > {}", self.metric, format_duration(duration))
+            }
+            (metric, 0) => write!(f, "{} > {}", metric, self.threshold),
+            (metric, duration) => write!(
+                f,
+                "{} > {} for {}",
+                metric,
+                self.threshold,
+                format_duration(duration)
+            ),
+        }
+    }
+}
+
+///
+/// Parse the comma-separated list of alert rules
+///
+pub fn parse_alert_rules(rules: &str) -> Result<Vec<AlertRule>, Error> {
+    rules
+        .split(',')
+        .filter(|rule| !rule.trim().is_empty())
+        .map(AlertRule::parse)
+        .collect()
+}
+
+///
+/// The time at which each peer of this agent was disconnected,
+/// keyed by the peer's name and zone
+///
+static DISCONNECTED: Lazy<RwLock<HashMap<String, DateTime<Utc>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+///
+/// Record that the peer has connected to this agent
+///
+pub async fn record_connected(peer: &Peer) {
+    DISCONNECTED.write().await.remove(&peer.to_string());
+    record_availability(peer, true);
+}
+
+///
+/// Record that the peer has disconnected from this agent
+///
+pub async fn record_disconnected(peer: &Peer) {
+    DISCONNECTED
+        .write()
+        .await
+        .entry(peer.to_string())
+        .or_insert_with(Utc::now);
+    record_availability(peer, false);
+}
+
+/// Rolling windows (in seconds) over which connection availability is
+/// reported. Outages that ended before the longest window are forgotten
+const AVAILABILITY_WINDOW_24H: i64 = 24 * 60 * 60;
+const AVAILABILITY_WINDOW_7D: i64 = 7 * AVAILABILITY_WINDOW_24H;
+const AVAILABILITY_WINDOW_30D: i64 = 30 * AVAILABILITY_WINDOW_24H;
+
+///
+/// The connection history of a single peer, from which its
+/// availability over a rolling window is calculated
+///
+#[derive(Debug, Clone)]
+struct ConnectionHistory {
+    /// When the peer first connected to this agent
+    tracked_since: DateTime<Utc>,
+    /// When the peer last connected, or None if it is disconnected
+    connected_since: Option<DateTime<Utc>>,
+    /// When the current outage started, if the peer is disconnected
+    down_since: Option<DateTime<Utc>>,
+    /// The start and end of each completed outage, oldest first
+    outages: std::collections::VecDeque<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+impl ConnectionHistory {
+    fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            tracked_since: now,
+            connected_since: Some(now),
+            down_since: None,
+            outages: std::collections::VecDeque::new(),
+        }
+    }
+
+    fn connected(&mut self, now: DateTime<Utc>) {
+        if self.connected_since.is_some() {
+            return;
+        }
+
+        if let Some(down_since) = self.down_since.take() {
+            if down_since < now {
+                self.outages.push_back((down_since, now));
+            }
+        }
+
+        self.connected_since = Some(now);
+        self.prune(now);
+    }
+
+    fn disconnected(&mut self, now: DateTime<Utc>) {
+        if self.connected_since.take().is_some() {
+            self.down_since = Some(now);
+        }
+
+        self.prune(now);
+    }
+
+    fn prune(&mut self, now: DateTime<Utc>) {
+        let cutoff = now - chrono::Duration::seconds(AVAILABILITY_WINDOW_30D);
+
+        while let Some((_, end)) = self.outages.front() {
+            if *end >= cutoff {
+                break;
+            }
+
+            self.outages.pop_front();
+        }
+    }
+
+    ///
+    /// Return the start of the window of `window` seconds ending now,
+    /// limited to the time that this peer has been tracked
+    ///
+    fn window_start(&self, window: i64, now: DateTime<Utc>) -> DateTime<Utc> {
+        std::cmp::max(now - chrono::Duration::seconds(window), self.tracked_since)
+    }
+
+    ///
+    /// Return the percentage of the last `window` seconds (or of the
+    /// time since tracking started, if shorter) that the peer was connected
+    ///
+    fn availability(&self, window: i64, now: DateTime<Utc>) -> f64 {
+        let start = self.window_start(window, now);
+        let observed = (now - start).num_milliseconds();
+
+        if observed <= 0 {
+            return match self.connected_since {
+                Some(_) => 100.0,
+                None => 0.0,
+            };
+        }
+
+        let current = self.down_since.map(|down_since| (down_since, now));
+
+        let downtime: i64 = self
+            .outages
+            .iter()
+            .copied()
+            .chain(current)
+            .map(|(down, up)| {
+                let down = std::cmp::max(down, start);
+                let up = std::cmp::min(up, now);
+
+                std::cmp::max((up - down).num_milliseconds(), 0)
+            })
+            .sum();
+
+        100.0 * (observed - downtime) as f64 / observed as f64
+    }
+
+    ///
+    /// Return the number of times the peer disconnected in the
+    /// last `window` seconds
+    ///
+    fn disconnections(&self, window: i64, now: DateTime<Utc>) -> usize {
+        let start = self.window_start(window, now);
+
+        self.outages
+            .iter()
+            .map(|(down, _)| *down)
+            .chain(self.down_since)
+            .filter(|down| *down > start)
+            .count()
+    }
+}
+
+///
+/// The connection availability of a peer of this agent over
+/// rolling 24 hour, 7 day and 30 day windows
+///
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, TS)]
+#[ts(export)]
+pub struct PeerAvailability {
+    /// Name and zone of the peer, e.g. "cluster@brics"
+    pub peer: String,
+    /// Whether the peer is currently connected
+    pub connected: bool,
+    /// When the peer first connected since this agent started.
+    /// Availability is only measured from this point
+    pub tracked_since: DateTime<Utc>,
+    /// When the peer last connected, if it is connected
+    pub connected_since: Option<DateTime<Utc>>,
+    /// Number of disconnections in the last 30 days
+    pub disconnections_30d: usize,
+    /// Percentage of the last 24 hours that the peer was connected
+    pub availability_24h: f64,
+    /// Percentage of the last 7 days that the peer was connected
+    pub availability_7d: f64,
+    /// Percentage of the last 30 days that the peer was connected
+    pub availability_30d: f64,
+}
+
+impl PeerAvailability {
+    fn new(peer: &str, history: &ConnectionHistory, now: DateTime<Utc>) -> Self {
+        Self {
+            peer: peer.to_owned(),
+            connected: history.connected_since.is_some(),
+            tracked_since: history.tracked_since,
+            connected_since: history.connected_since,
+            disconnections_30d: history.disconnections(AVAILABILITY_WINDOW_30D, now),
+            availability_24h: history.availability(AVAILABILITY_WINDOW_24H, now),
+            availability_7d: history.availability(AVAILABILITY_WINDOW_7D, now),
+            availability_30d: history.availability(AVAILABILITY_WINDOW_30D, now),
+        }
+    }
+}
+
+///
+/// The connection history of each peer of this agent, keyed by
+/// the peer's name and zone. This uses a std Mutex so that it can
+/// be read from synchronous code
+///
+static CONNECTIONS: Lazy<std::sync::Mutex<HashMap<String, ConnectionHistory>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+///
+/// Record a change in the connection state of the peer. Peers are
+/// only tracked from the first time they connect
+///
+fn record_availability(peer: &Peer, connected: bool) {
+    let now = Utc::now();
+
+    match CONNECTIONS.lock() {
+        Ok(mut connections) => match connections.get_mut(&peer.to_string()) {
+            Some(history) => match connected {
+                true => history.connected(now),
+                false => history.disconnected(now),
+            },
+            None => {
+                if connected {
+                    connections.insert(peer.to_string(), ConnectionHistory::new(now));
+                }
+            }
+        },
+        Err(e) => {
+            tracing::error!("Could not lock connection history: {}", e);
+        }
+    }
+}
+
+///
+/// Return the connection availability of each peer of this agent,
+/// sorted by peer
+///
+pub fn get_availability() -> Vec<PeerAvailability> {
+    let now = Utc::now();
+
+    match CONNECTIONS.lock() {
+        Ok(connections) => {
+            let mut availability: Vec<_> = connections
+                .iter()
+                .map(|(peer, history)| PeerAvailability::new(peer, history, now))
+                .collect();
+            availability.sort_by(|a, b| a.peer.cmp(&b.peer));
+            availability
+        }
+        Err(e) => {
+            tracing::error!("Could not lock connection history: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+///
+/// Tracks which alert rules are breached, and which alerts have
+/// been raised
+///
+struct AlertMonitor {
+    rules: Vec<AlertRule>,
+    destination: Option<Destination>,
+    /// When each (not yet raised) breach started, keyed by alert
+    breached: HashMap<String, DateTime<Utc>>,
+    /// The message of each raised alert, keyed by alert
+    raised: HashMap<String, String>,
+}
+
+impl AlertMonitor {
+    ///
+    /// Check all of the rules, raising alerts for those breached for
+    /// longer than their duration, and clearing alerts whose breach
+    /// has ended
+    ///
+    async fn check(&mut self) {
+        let now = Utc::now();
+        let stats = state::aggregate_job_stats().await;
+        let disconnected = DISCONNECTED.read().await.clone();
+
+        // the alerts that should be raised now, with their messages
+        let mut alerts = HashMap::new();
+        let mut breached = HashMap::new();
+
+        for rule in &self.rules {
+            let duration = chrono::Duration::seconds(rule.duration as i64);
+
+            match rule.metric.value(&stats) {
+                Some(value) => {
+                    if value <= rule.threshold {
+                        continue;
+                    }
+
+                    let key = format!("alert:{}", rule);
+
+                    let since = self.breached.get(&key).cloned().unwrap_or(now);
+                    breached.insert(key.clone(), since);
+
+                    if now - since >= duration {
+                        let message = match rule.duration {
+                            0 => format!("{} is above {}", rule.metric, rule.threshold),
+                            d => format!(
+                                "{} has been above {} for more than {}",
+                                rule.metric,
+                                rule.threshold,
+                                format_duration(d)
+                            ),
+                        };
+
+                        alerts.insert(key, message);
+                    }
+                }
+                None => {
+                    for (peer, since) in &disconnected {
+                        if now - *since >= duration {
+                            alerts.insert(
+                                format!("alert:{}:{}", rule, peer),
+                                format!(
+                                    "{} has been disconnected for more than {}",
+                                    peer,
+                                    format_duration(rule.duration)
+                                ),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        self.breached = breached;
+
+        let me = agent::name().await;
+
+        for (key, message) in &alerts {
+            if !self.raised.contains_key(key) {
+                set_warning(key, message);
+
+                if let Some(destination) = &self.destination {
+                    notification::send(
+                        destination,
+                        NotificationEvent::HealthAlert(format!("{}: {}", me, message)),
+                    )
+                    .await;
+                }
+            }
+        }
+
+        for (key, message) in &self.raised {
+            if !alerts.contains_key(key) {
+                clear_warning(key);
+
+                if let Some(destination) = &self.destination {
+                    notification::send(
+                        destination,
+                        NotificationEvent::HealthAlertCleared(format!("{}: {}", me, message)),
+                    )
+                    .await;
+                }
+            }
+        }
+
+        self.raised = alerts;
+    }
+}
+
+///
+/// The running alert monitor task, which is replaced if the alert
+/// rules are reconfigured
+///
+static ALERT_TASK: std::sync::Mutex<Option<tokio::task::JoinHandle<()>>> =
+    std::sync::Mutex::new(None);
+
+///
+/// Spawn a background task that regularly checks the alert rules.
+/// Raised alerts are added to this agent's health warnings, and, if
+/// a destination is passed, are also sent as health_alert notifications
+/// along that destination (with health_alert_cleared sent when the
+/// alert is cleared). Any previously spawned monitor is replaced.
+///
+pub fn spawn_alert_monitor(rules: Vec<AlertRule>, destination: Option<Destination>) {
+    let mut task = match ALERT_TASK.lock() {
+        Ok(task) => task,
+        Err(e) => {
+            tracing::error!("Could not lock the alert monitor task: {}", e);
+            return;
+        }
+    };
+
+    // replace any existing monitor, resetting the alerts that it raised
+    if let Some(previous) = task.take() {
+        previous.abort();
+
+        match WARNINGS.lock() {
+            Ok(mut warnings) => warnings.retain(|key, _| !key.starts_with("alert:")),
+            Err(e) => tracing::error!("Could not lock health warnings: {}", e),
+        }
+    }
+
+    if rules.is_empty() {
+        return;
+    }
+
+    for rule in &rules {
+        tracing::info!("Alerting when {}", rule);
+    }
+
+    *task = Some(tokio::spawn(async move {
+        let mut monitor = AlertMonitor {
+            rules,
+            destination,
+            breached: HashMap::new(),
+            raised: HashMap::new(),
+        };
+
+        let mut ticker =
+            tokio::time::interval(tokio::time::Duration::from_secs(ALERT_CHECK_INTERVAL));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            monitor.check().await;
+        }
+    }));
+}
+
 ///
 /// Global cache of health responses from agents
 /// Maps agent_name -> HealthInfo (with last_updated timestamp inside)
@@ -571,6 +1459,9 @@ pub async fn collect_health(
     // Get the worker count from paddington
     health.worker_count = paddington::worker_count();
 
+    // Get the number of replayed messages rejected by paddington
+    health.replays_rejected = paddington::replays_rejected().values().sum();
+
     // Collect system information (memory, CPU, etc.)
     let sysinfo = systeminfo::collect();
     health.memory_bytes = sysinfo.memory_bytes;
@@ -593,6 +1484,18 @@ pub async fn collect_health(
     health.total_expired = diagnostics_stats.total_expired;
     health.total_slow = diagnostics_stats.total_slow;
 
+    // Any warnings raised by subsystems of this agent
+    health.warnings = get_warnings();
+
+    // The last checked status of the agent's external dependencies
+    health.dependencies = get_dependencies();
+
+    // The state of the circuit breakers for third-party services
+    health.breakers = circuitbreaker::statuses();
+
+    // How available the connection to each peer has been
+    health.availability = get_availability();
+
     // Cascade health check to downstream peers (if enabled for this agent)
     // Leaf nodes (like FreeIPA or Filesystem) have cascade_health=false
     if agent::should_cascade_health().await {
@@ -820,3 +1723,81 @@ async fn wait_for_health_updates(
         tokio::time::sleep(std::time::Duration::from_millis(10)).await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alert_rule_parse_display_roundtrip() {
+        let cases = vec![
+            "pending_jobs > 100 for 10m",
+            "queued_jobs > 5",
+            "errored_jobs > 0 for 90s",
+            "peer_disconnected > 5m",
+            "peer_disconnected > 1d",
+        ];
+        for case in cases {
+            #[allow(clippy::unwrap_used)]
+            let rule = AlertRule::parse(case).unwrap();
+            assert_eq!(rule.to_string(), case);
+        }
+    }
+
+    #[test]
+    fn test_alert_rule_parse() {
+        #[allow(clippy::unwrap_used)]
+        let rules = parse_alert_rules("pending_jobs>100 for 600, peer_disconnected > 2h").unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].metric(), AlertMetric::PendingJobs);
+        assert_eq!(rules[0].threshold(), 100);
+        assert_eq!(rules[0].duration(), 600);
+        assert_eq!(rules[1].metric(), AlertMetric::PeerDisconnected);
+        assert_eq!(rules[1].duration(), 7200);
+
+        assert!(AlertRule::parse("pending_jobs 100").is_err());
+        assert!(AlertRule::parse("unknown_metric > 1").is_err());
+        assert!(AlertRule::parse("pending_jobs > lots").is_err());
+        assert!(AlertRule::parse("peer_disconnected > 5w").is_err());
+    }
+
+    #[test]
+    fn test_connection_availability() {
+        let hours = chrono::Duration::hours;
+        let start = Utc::now() - hours(48);
+
+        let mut history = ConnectionHistory::new(start);
+
+        // up for the whole of the tracked period
+        assert_eq!(
+            history.availability(AVAILABILITY_WINDOW_24H, start + hours(1)),
+            100.0
+        );
+
+        // down for 6 hours during the last day, then back up
+        history.disconnected(start + hours(30));
+        history.connected(start + hours(36));
+
+        let now = start + hours(48);
+        assert_eq!(history.availability(AVAILABILITY_WINDOW_24H, now), 75.0);
+        assert_eq!(history.disconnections(AVAILABILITY_WINDOW_24H, now), 1);
+
+        // the 7 and 30 day windows only cover the 48 tracked hours
+        assert_eq!(history.availability(AVAILABILITY_WINDOW_7D, now), 87.5);
+        assert_eq!(history.availability(AVAILABILITY_WINDOW_30D, now), 87.5);
+
+        // a current outage counts up to now
+        history.disconnected(start + hours(42));
+        assert_eq!(history.availability(AVAILABILITY_WINDOW_24H, now), 50.0);
+        assert_eq!(history.disconnections(AVAILABILITY_WINDOW_30D, now), 2);
+
+        // repeated disconnections do not restart the outage
+        history.disconnected(start + hours(45));
+        assert_eq!(history.availability(AVAILABILITY_WINDOW_24H, now), 50.0);
+
+        // outages older than 30 days are forgotten
+        history.connected(start + hours(48));
+        history.prune(start + hours(48) + chrono::Duration::days(31));
+        assert!(history.outages.is_empty());
+    }
+}
@@ -0,0 +1,45 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Tracking of the invitations given to clients
+//!
+//! The first time that a client connects, the agent that used its
+//! invitation is recorded in the config file, so that operators can
+//! see which invitations are stale and revoke them.
+
+use crate::config;
+use crate::error::Error;
+
+///
+/// Record that the client `sender` in `zone` has connected, described
+/// by `used_by`. Nothing is written if this was already recorded, or if
+/// `sender` is not one of our clients
+///
+pub(crate) async fn record_use(sender: &str, zone: &str, used_by: &str) -> Result<(), Error> {
+    let service = config::service().await?;
+
+    let Some(client) = service
+        .clients()
+        .into_iter()
+        .find(|client| client.name() == sender && client.zone() == zone)
+    else {
+        return Ok(());
+    };
+
+    if client.used().is_some() {
+        return Ok(());
+    }
+
+    if config::update_service(|service| Ok(service.record_invitation_use(sender, zone, used_by)?))
+        .await?
+    {
+        tracing::info!(
+            "Invitation for {} in zone {} used by {}",
+            sender,
+            zone,
+            used_by
+        );
+    }
+
+    Ok(())
+}
@@ -2,9 +2,11 @@
 // SPDX-License-Identifier: MIT
 
 use crate::agent::Peer;
+use crate::batch;
 use crate::board::{JobAddState, SyncState, Waiter};
 use crate::command::Command as ControlCommand;
 use crate::destination::{Destination, Position};
+use crate::dryrun;
 use crate::error::Error;
 use crate::grammar::{Instruction, NamedType};
 use crate::state;
@@ -133,6 +135,7 @@ impl Command {
                 Instruction::UpdateHomeDir(user, _) => Some(user),
                 Instruction::GetUserMapping(user) => Some(user),
                 Instruction::IsProtectedUser(user) => Some(user),
+                Instruction::GetUserProtection(user) => Some(user),
                 Instruction::IsExistingUser(user) => Some(user),
                 Instruction::GetHomeDir(user) => Some(user),
                 Instruction::GetLocalHomeDir(user) => Some(user.user().clone()),
@@ -146,6 +149,18 @@ impl Command {
                 Instruction::GetLocalUserQuotas(user) => Some(user.user().clone()),
                 Instruction::GetUserDirs(user) => Some(user),
                 Instruction::GetLocalUserDirs(user) => Some(user.user().clone()),
+                Instruction::SubmitJob(user, _) => Some(user),
+                Instruction::SubmitLocalJob(user, _) => Some(user.user().clone()),
+                Instruction::AddUserSSHKey(user, _) => Some(user),
+                Instruction::RemoveUserSSHKey(user, _) => Some(user),
+                Instruction::GetUserSSHKeys(user) => Some(user),
+                Instruction::AddUserToGroup(user, _) => Some(user),
+                Instruction::RemoveUserFromGroup(user, _) => Some(user),
+                Instruction::ActivateUser(user) => Some(user),
+                Instruction::UpdateUser(user, _) => Some(user),
+                Instruction::AddUserOTPToken(user) => Some(user),
+                Instruction::RemoveUserOTPToken(user, _) => Some(user),
+                Instruction::GetUserOTPTokens(user) => Some(user),
                 _ => None,
             };
 
@@ -171,13 +186,31 @@ impl Command {
                 Instruction::AddLocalProject(project) => Some(project.project().clone()),
                 Instruction::RemoveLocalProject(project) => Some(project.project().clone()),
                 Instruction::IsExistingProject(project) => Some(project),
+                Instruction::GetProjectProtection(project) => Some(project),
                 Instruction::GetUsers(project) => Some(project),
+                // every user in a batch is in the same project
+                Instruction::AddUsers(users) | Instruction::RemoveUsers(users) => {
+                    users.first().map(|user| user.project_identifier())
+                }
+                Instruction::AddLocalUsers(users) | Instruction::RemoveLocalUsers(users) => {
+                    users.first().map(|user| user.user().project_identifier())
+                }
                 Instruction::RemoveProject(project) => Some(project),
                 Instruction::GetUsageReport(project, _) => Some(project),
                 Instruction::GetLocalUsageReport(project, _) => Some(project.project().clone()),
                 Instruction::GetProjectMapping(project) => Some(project),
-                Instruction::GetLocalLimit(project) => Some(project.project().clone()),
-                Instruction::SetLocalLimit(project, _) => Some(project.project().clone()),
+                Instruction::GetLocalLimit(project, _) => Some(project.project().clone()),
+                Instruction::SetLocalLimit(project, _, _) => Some(project.project().clone()),
+                Instruction::GetLocalQos(project) => Some(project.project().clone()),
+                Instruction::SetLocalQos(project, _) => Some(project.project().clone()),
+                Instruction::CreateLocalReservation(project, _, _, _) => {
+                    Some(project.project().clone())
+                }
+                Instruction::RemoveLocalReservation(project, _) => Some(project.project().clone()),
+                Instruction::GetJobQueue(project) => Some(project),
+                Instruction::GetLocalJobQueue(project) => Some(project.project().clone()),
+                Instruction::Reconcile(project, _) => Some(project),
+                Instruction::ReconcileLocal(project, _, _) => Some(project.project().clone()),
                 Instruction::GetLimit(project) => Some(project),
                 Instruction::SetLimit(project, _) => Some(project),
                 Instruction::GetProjectDirs(project) => Some(project),
@@ -301,6 +334,10 @@ pub struct Job {
     #[serde(default)]
     #[ts(as = "Option<String>")]
     forwarded_for: Option<Destination>,
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(default)]
+    changes: Vec<String>,
     #[serde(skip)]
     #[ts(skip)]
     board: Option<Peer>,
@@ -331,6 +368,7 @@ impl Job {
         tracing::debug!("Parsing command: {:?}", command);
 
         let now = Utc::now();
+        let command = Command::parse(command, check_portal)?;
 
         Ok(Self {
             id: Uuid::new_v4(),
@@ -339,14 +377,17 @@ impl Job {
             // settled on 2 minutes as this makes the interface with the
             // user portal more responsive - any task that takes longer
             // than 2 minutes can have its lifetime changed using the
-            // set_lifetime method
-            expires: now + chrono::Duration::minutes(2),
+            // set_lifetime method. Batches of users are given longer,
+            // depending on their size
+            expires: now + batch::lifetime(&command.instruction),
             version: 1,
-            command: Command::parse(command, check_portal)?,
+            command,
             state: Status::Created,
             result: None,
             result_type: None,
             forwarded_for: None,
+            dry_run: false,
+            changes: Vec::new(),
             board: None,
         })
     }
@@ -387,6 +428,8 @@ impl Job {
             result: self.result.clone(),
             result_type: self.result_type.clone(),
             forwarded_for: self.forwarded_for.clone(),
+            dry_run: self.dry_run,
+            changes: self.changes.clone(),
             board: self.board.clone(),
         }
     }
@@ -438,6 +481,45 @@ impl Job {
         }
     }
 
+    ///
+    /// Return whether this job is a dry run, in which case the agents
+    /// that run it describe the changes they would make, rather than
+    /// making them
+    ///
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn with_dry_run(self, dry_run: bool) -> Self {
+        Self { dry_run, ..self }
+    }
+
+    ///
+    /// Return the changes that this dry run job would have made, on
+    /// every agent that it passed through
+    ///
+    pub fn changes(&self) -> &[String] {
+        &self.changes
+    }
+
+    pub fn with_changes(self, changes: Vec<String>) -> Self {
+        Self { changes, ..self }
+    }
+
+    ///
+    /// Return a copy of this job that runs the passed instruction
+    /// instead. This is used to run each part of a batch instruction
+    ///
+    pub(crate) fn with_instruction(&self, instruction: Instruction) -> Self {
+        Self {
+            command: Command {
+                destination: self.command.destination(),
+                instruction,
+            },
+            ..self.clone()
+        }
+    }
+
     pub fn increment_version(&self) -> Self {
         Self {
             id: self.id,
@@ -450,6 +532,8 @@ impl Job {
             result: self.result.clone(),
             result_type: self.result_type.clone(),
             forwarded_for: self.forwarded_for.clone(),
+            dry_run: self.dry_run,
+            changes: self.changes.clone(),
             board: self.board.clone(),
         }
     }
@@ -505,6 +589,8 @@ impl Job {
                 result: self.result.clone(),
                 result_type: self.result_type.clone(),
                 forwarded_for: self.forwarded_for.clone(),
+                dry_run: self.dry_run,
+                changes: self.changes.clone(),
                 board: self.board.clone(),
             }),
             Status::Pending => Ok(self.clone()),
@@ -517,6 +603,7 @@ impl Job {
     pub fn is_duplicate_of(&self, job: &Job) -> bool {
         self.command.destination().last() == job.command.destination().last()
             && self.command.instruction() == job.command.instruction()
+            && self.dry_run == job.dry_run
             && job.is_pending()
             && !job.is_expired()
             && self.is_pending()
@@ -548,6 +635,8 @@ impl Job {
                 result: job.id.to_string().into(),
                 result_type: None,
                 forwarded_for: self.forwarded_for.clone(),
+                dry_run: self.dry_run,
+                changes: self.changes.clone(),
                 board: self.board.clone(),
             }),
             _ => Err(Error::InvalidState(
@@ -569,6 +658,8 @@ impl Job {
                 result: progress,
                 result_type: None,
                 forwarded_for: self.forwarded_for.clone(),
+                dry_run: self.dry_run,
+                changes: self.changes.clone(),
                 board: self.board.clone(),
             }),
             _ => Err(Error::InvalidState(
@@ -597,6 +688,8 @@ impl Job {
                 result: other.result.clone(),
                 result_type: other.result_type.clone(),
                 forwarded_for: self.forwarded_for.clone(),
+                dry_run: self.dry_run,
+                changes: other.changes.clone(),
                 board: self.board.clone(),
             }),
             _ => Err(Error::InvalidState(
@@ -618,6 +711,8 @@ impl Job {
                 result: None,
                 result_type: Some("None".to_string()),
                 forwarded_for: self.forwarded_for.clone(),
+                dry_run: self.dry_run,
+                changes: self.changes.clone(),
                 board: self.board.clone(),
             }),
             _ => Err(Error::InvalidState(
@@ -643,6 +738,8 @@ impl Job {
                 result: Some(serde_json::to_string(&result)?),
                 result_type: Some(T::type_name().to_string()),
                 forwarded_for: self.forwarded_for.clone(),
+                dry_run: self.dry_run,
+                changes: self.changes.clone(),
                 board: self.board.clone(),
             }),
             _ => Err(Error::InvalidState(
@@ -664,6 +761,8 @@ impl Job {
                 result: Some(message.to_owned()),
                 result_type: Some("Error".to_string()),
                 forwarded_for: self.forwarded_for.clone(),
+                dry_run: self.dry_run,
+                changes: self.changes.clone(),
                 board: self.board.clone(),
             }),
             _ => Err(Error::InvalidState(
@@ -840,6 +939,11 @@ impl Job {
         // transition the job to pending, recording where it was sent
         let mut job = self.pending()?;
 
+        // jobs sent while running a dry run are also dry runs
+        if dryrun::is_dry_run() {
+            job.dry_run = true;
+        }
+
         // get a RwLock to the board from the shared state
         let board = match state::get(peer).await {
             Ok(b) => b.board().await,
@@ -1287,6 +1391,11 @@ impl Job {
             job = job._wait().await?;
         }
 
+        // the changes of a downstream dry run are part of this one
+        if job.dry_run {
+            dryrun::merge(&job.changes);
+        }
+
         Ok(job)
     }
 
@@ -1331,7 +1440,16 @@ impl Job {
         }
 
         // wait for the job to finish
-        waiter.try_result(timeout_ms).await
+        let job = waiter.try_result(timeout_ms).await?;
+
+        // the changes of a downstream dry run are part of this one
+        if let Some(job) = &job {
+            if job.dry_run && job.is_finished() {
+                dryrun::merge(&job.changes);
+            }
+        }
+
+        Ok(job)
     }
 }
 
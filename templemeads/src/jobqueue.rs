@@ -0,0 +1,135 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Snapshots of the jobs that a project has queued or running on a
+//! scheduler, so that portals can show users why their jobs are pending
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::grammar::{NamedType, ProjectIdentifier};
+
+impl NamedType for JobQueue {
+    fn type_name() -> &'static str {
+        "JobQueue"
+    }
+}
+
+impl NamedType for Vec<JobQueue> {
+    fn type_name() -> &'static str {
+        "Vec<JobQueue>"
+    }
+}
+
+/// A single job in the scheduler's queue
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, TS)]
+#[ts(export)]
+pub struct QueuedJob {
+    /// The scheduler's id for the job (e.g. "1234" or "1234_7" for
+    /// an element of an array job)
+    pub job_id: String,
+    /// The local username of the user who submitted the job
+    pub user: String,
+    /// The partition the job was submitted to
+    pub partition: String,
+    /// The state of the job, e.g. "PENDING" or "RUNNING"
+    pub state: String,
+    /// The reason the job is in this state, e.g. "Priority" or
+    /// "AssocGrpGRESMinutes". This is "None" for running jobs
+    pub reason: String,
+    /// The number of nodes requested (or allocated, once running)
+    pub nodes: u64,
+    /// The number of CPUs requested (or allocated, once running)
+    pub cpus: u64,
+    /// The number of GPUs requested (or allocated, once running)
+    pub gpus: u64,
+    /// When the job was submitted
+    pub submit_time: DateTime<Utc>,
+    /// When the job started, or the scheduler's estimate of when
+    /// a pending job will start (if it has one)
+    pub start_time: Option<DateTime<Utc>>,
+    /// How long the job has waited (or did wait) to start, in seconds
+    pub wait_seconds: u64,
+}
+
+impl QueuedJob {
+    pub fn is_pending(&self) -> bool {
+        self.state == "PENDING"
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state == "RUNNING"
+    }
+}
+
+/// The queued and running jobs of a single project
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, TS)]
+#[ts(export)]
+pub struct JobQueue {
+    /// The project that the jobs belong to
+    #[ts(as = "String")]
+    pub project: ProjectIdentifier,
+    /// When this snapshot of the queue was taken
+    pub generated_at: DateTime<Utc>,
+    /// The jobs, in the order returned by the scheduler
+    pub jobs: Vec<QueuedJob>,
+}
+
+impl std::fmt::Display for JobQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{} at {}", self.project, self.generated_at)?;
+
+        for job in &self.jobs {
+            writeln!(
+                f,
+                "  {} {} {} {} ({}) | nodes: {} cpus: {} gpus: {} | waited: {}s",
+                job.job_id,
+                job.user,
+                job.partition,
+                job.state,
+                job.reason,
+                job.nodes,
+                job.cpus,
+                job.gpus,
+                job.wait_seconds
+            )?;
+        }
+
+        write!(
+            f,
+            "Pending: {} | Running: {}",
+            self.num_pending(),
+            self.num_running()
+        )
+    }
+}
+
+impl JobQueue {
+    pub fn new(project: &ProjectIdentifier, jobs: Vec<QueuedJob>) -> Self {
+        Self {
+            project: project.clone(),
+            generated_at: Utc::now(),
+            jobs,
+        }
+    }
+
+    pub fn num_pending(&self) -> usize {
+        self.jobs.iter().filter(|j| j.is_pending()).count()
+    }
+
+    pub fn num_running(&self) -> usize {
+        self.jobs.iter().filter(|j| j.is_running()).count()
+    }
+
+    ///
+    /// Return the jobs of the passed local user
+    ///
+    pub fn user_jobs(&self, user: &str) -> Vec<QueuedJob> {
+        self.jobs
+            .iter()
+            .filter(|j| j.user == user)
+            .cloned()
+            .collect()
+    }
+}
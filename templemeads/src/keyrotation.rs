@@ -0,0 +1,190 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Rotation of the keys used to connect to a peer
+//!
+//! The keys are always generated by the side that has the peer as a
+//! client. That side saves the new keys, keeps accepting the old keys,
+//! and sends the new keys to the peer over the existing connection.
+//! The peer saves them and confirms, after which the old keys are
+//! dropped and the peer reconnects using the new keys.
+
+use crate::agent::Peer;
+use crate::command::{Command, RotatedInvite};
+use crate::config;
+use crate::error::Error;
+
+fn in_zone(peer_zone: &str, zone: &Option<String>) -> bool {
+    match zone {
+        Some(zone) => zone == peer_zone,
+        None => true,
+    }
+}
+
+///
+/// Rotate the keys used to connect to the peer called `name`. The zone
+/// only needs to be given if there are several peers with that name.
+/// If the peer is a client then new keys are generated here and sent
+/// to it. If the peer is a server then it is asked to rotate the keys
+///
+pub(crate) async fn rotate(name: &str, zone: &Option<String>) -> Result<String, Error> {
+    let service = config::service().await?;
+
+    let clients: Vec<String> = service
+        .clients()
+        .iter()
+        .filter(|client| client.name() == name && in_zone(&client.zone(), zone))
+        .map(|client| client.zone())
+        .collect();
+
+    let servers: Vec<String> = service
+        .servers()
+        .iter()
+        .filter(|server| server.name() == name && in_zone(&server.zone(), zone))
+        .map(|server| server.zone())
+        .collect();
+
+    if clients.len() + servers.len() > 1 {
+        return Err(Error::Misconfigured(format!(
+            "There are several peers called '{}'. Please specify the zone.",
+            name
+        )));
+    }
+
+    if let Some(zone) = clients.first() {
+        let (invite, previous) = config::update_service(|service| {
+            let client = service
+                .clients()
+                .into_iter()
+                .find(|client| client.name() == name && client.zone() == *zone)
+                .ok_or_else(|| {
+                    Error::NotFound(format!("Client '{}' not found in zone {}", name, zone))
+                })?;
+
+            let invite = service.rotate_client_keys(name, &Some(zone.clone()))?;
+
+            Ok((invite, (client.inner_key(), client.outer_key())))
+        })
+        .await?;
+
+        paddington::config::set_rotated_keys(
+            name,
+            zone,
+            &invite.inner_key(),
+            &invite.outer_key(),
+            Some(previous),
+        )?;
+
+        if let Err(e) = Command::rotate_keys(&invite)?
+            .send_to(&Peer::new(name, zone))
+            .await
+        {
+            return Err(Error::Unavailable(format!(
+                "Saved new keys for '{}', but could not send them to it: {}. \
+                 It can still connect with its old keys until this agent restarts.",
+                name, e
+            )));
+        }
+
+        tracing::info!("Sent rotated keys to client {} in zone {}", name, zone);
+
+        return Ok(format!(
+            "Sent new keys to '{}'. The old keys are dropped once it confirms.",
+            name
+        ));
+    }
+
+    if let Some(zone) = servers.first() {
+        Command::request_key_rotation()
+            .send_to(&Peer::new(name, zone))
+            .await?;
+
+        tracing::info!("Asked server {} in zone {} to rotate keys", name, zone);
+
+        return Ok(format!("Asked '{}' to send new keys.", name));
+    }
+
+    Err(Error::NotFound(format!(
+        "There is no peer called '{}'",
+        name
+    )))
+}
+
+///
+/// Save the new keys that our server `sender` sent for its connection,
+/// and confirm that they have been received
+///
+pub(crate) async fn received_keys(
+    sender: &str,
+    zone: &str,
+    invite: &RotatedInvite,
+) -> Result<(), Error> {
+    let invite = invite.invite()?;
+
+    if invite.name() != sender || invite.zone() != zone {
+        return Err(Error::InvalidPeer(format!(
+            "{} in zone {} sent keys for {} in zone {}",
+            sender,
+            zone,
+            invite.name(),
+            invite.zone()
+        )));
+    }
+
+    config::update_service(|service| Ok(service.rotate_server_keys(&invite)?)).await?;
+
+    paddington::config::set_rotated_keys(
+        sender,
+        zone,
+        &invite.inner_key(),
+        &invite.outer_key(),
+        None,
+    )?;
+
+    tracing::info!("Saved rotated keys from server {} in zone {}", sender, zone);
+
+    Command::keys_rotated()
+        .send_to(&Peer::new(sender, zone))
+        .await
+}
+
+///
+/// Our client `sender` has saved its new keys, so stop accepting its
+/// old keys and disconnect it, so that it reconnects with the new keys
+///
+pub(crate) async fn keys_confirmed(sender: &str, zone: &str) -> Result<(), Error> {
+    paddington::config::accept_only_rotated_keys(sender, zone)?;
+
+    tracing::info!(
+        "Client {} in zone {} confirmed its rotated keys - reconnecting",
+        sender,
+        zone
+    );
+
+    paddington::disconnect(sender, zone).await?;
+
+    Ok(())
+}
+
+///
+/// Our client `sender` has asked for the keys of its connection to be
+/// rotated. This is only honoured if it is one of our clients
+///
+pub(crate) async fn rotation_requested(sender: &str, zone: &str) -> Result<(), Error> {
+    let service = config::service().await?;
+
+    if !service
+        .clients()
+        .iter()
+        .any(|client| client.name() == sender && client.zone() == zone)
+    {
+        return Err(Error::InvalidPeer(format!(
+            "{} in zone {} asked for a key rotation, but is not a client",
+            sender, zone
+        )));
+    }
+
+    rotate(sender, &Some(zone.to_owned())).await?;
+
+    Ok(())
+}
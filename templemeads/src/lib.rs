@@ -6,37 +6,63 @@ mod account;
 mod agent_bridge;
 mod agent_core;
 mod bridge_server;
+mod bridgeallowlist;
 mod bridgeboard;
 mod bridgestate;
+mod bridgetls;
 mod control_message;
 mod custom;
 mod error;
 mod filesystem;
 mod handler;
+mod handover;
 mod instance;
+mod invitation;
 mod jobtiming;
+mod keyrotation;
+mod logsinks;
+mod maintenance;
+mod monitor;
 mod notificationstate;
 mod platform;
 mod portal;
 mod provider;
 mod restart;
 mod scheduler;
+mod secrets;
+mod systemd;
 mod systeminfo;
 mod virtual_agent;
+mod watchdog;
 
 // public API
+pub mod admin;
 pub mod agent;
+pub mod audit;
+pub mod batch;
 pub mod board;
 pub mod bridge;
+pub mod chaos;
+pub mod circuitbreaker;
 pub mod command;
 pub mod config;
+pub mod configcheck;
 pub mod destination;
 pub mod diagnostics;
+pub mod dryrun;
 pub use error::Error;
+pub mod exec;
 pub mod grammar;
 pub mod health;
 pub mod job;
+pub mod jobqueue;
+#[cfg(feature = "mock")]
+pub mod mock;
 pub mod notification;
+pub mod portalquota;
+pub mod protection;
+pub mod reconcile;
+pub mod role;
 pub mod runnable;
 pub mod state;
 pub mod storage;
@@ -45,6 +71,9 @@ pub mod usagereport;
 
 pub mod server {
     pub use crate::bridge_server::sign_api_call;
+    pub use crate::bridge_server::sign_signal;
+    pub use crate::bridge_server::verify_signal;
+    pub use crate::bridgeboard::SignalPolicy;
     pub use crate::bridgestate::get as get_board;
     pub use crate::notificationstate::add as add_pending_notification;
     pub use crate::notificationstate::enqueue as enqueue_notification;
@@ -64,12 +93,15 @@ pub use handler::set_notify_runner;
 mod tests {
     use crate::agent::Type as AgentType;
     use crate::diagnostics::{
-        DiagnosticsReport, ExpiredJobEntry, FailedJobEntry, JobStatistics, LogEntry,
-        RunningJobEntry, SlowJobEntry,
+        BoardDump, BoardSnapshot, DiagnosticsCounts, DiagnosticsReport, ExpiredJobEntry,
+        FailedJobEntry, JobStatistics, LogEntry, RunningJobEntry, SlowJobEntry, WatchdogRestart,
     };
-    use crate::grammar::{AwardDetails, Link, MembershipControl, Note};
-    use crate::health::HealthInfo;
+    use crate::grammar::{AwardDetails, Link, MembershipControl, Note, UserDetails};
+    use crate::health::{BreakerStatus, DependencyStatus, HealthInfo, PeerAvailability};
     use crate::job::{Job, Status};
+    use crate::jobqueue::{JobQueue, QueuedJob};
+    use crate::protection::ProtectionStatus;
+    use crate::reconcile::ReconciliationReport;
     use crate::storage::{Quota, Volume};
     use crate::storagereport::{ProjectStorageReport, StorageReport};
     use crate::usagereport::{
@@ -85,12 +117,19 @@ mod tests {
         Job::export_all().expect("Could not export Job");
         JobStatistics::export_all().expect("Could not export JobStatistics");
         DiagnosticsReport::export_all().expect("Could not export DiagnosticsReport");
+        DiagnosticsCounts::export_all().expect("Could not export DiagnosticsCounts");
         FailedJobEntry::export_all().expect("Could not export FailedJobEntry");
         SlowJobEntry::export_all().expect("Could not export SlowJobEntry");
         ExpiredJobEntry::export_all().expect("Could not export ExpiredJobEntry");
         RunningJobEntry::export_all().expect("Could not export RunningJobEntry");
         LogEntry::export_all().expect("Could not export LogEntry");
+        BoardDump::export_all().expect("Could not export BoardDump");
+        BoardSnapshot::export_all().expect("Could not export BoardSnapshot");
+        WatchdogRestart::export_all().expect("Could not export WatchdogRestart");
         HealthInfo::export_all().expect("Could not export HealthInfo");
+        DependencyStatus::export_all().expect("Could not export DependencyStatus");
+        BreakerStatus::export_all().expect("Could not export BreakerStatus");
+        PeerAvailability::export_all().expect("Could not export PeerAvailability");
         Volume::export_all().expect("Could not export Volume");
         Quota::export_all().expect("Could not export Quota");
         Usage::export_all().expect("Could not export Usage");
@@ -104,5 +143,10 @@ mod tests {
         Note::export_all().expect("Could not export Note");
         MembershipControl::export_all().expect("Could not export MembershipControl");
         AwardDetails::export_all().expect("Could not export AwardDetails");
+        UserDetails::export_all().expect("Could not export UserDetails");
+        QueuedJob::export_all().expect("Could not export QueuedJob");
+        JobQueue::export_all().expect("Could not export JobQueue");
+        ReconciliationReport::export_all().expect("Could not export ReconciliationReport");
+        ProtectionStatus::export_all().expect("Could not export ProtectionStatus");
     }
 }
@@ -0,0 +1,549 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Optional log sinks, so that agents running on cluster login nodes
+//! can send their logs to the site's logging without wrapping stdout.
+//!
+//! Each sink is configured by an environment variable, as logging is
+//! started before the agent's configuration is read:
+//!
+//! - `RUST_LOG_FILE` - write logs to this file, rotated every
+//!   `RUST_LOG_FILE_ROTATION` (minutely, hourly, daily or never), keeping
+//!   the last `RUST_LOG_FILE_KEEP` files
+//! - `RUST_LOG_SYSLOG` - send logs to syslog, either to a local socket
+//!   (e.g. "unix:/dev/log") or over UDP (e.g. "udp:loghost:514"), using
+//!   the `RUST_LOG_SYSLOG_FACILITY` facility
+//! - `RUST_LOG_LOKI` - push logs as JSON to the Loki server at this URL,
+//!   labelled with the agent and any `RUST_LOG_LOKI_LABELS`
+//!
+//! Every sink (and stdout) writes through `Redacting`, so that secrets
+//! are scrubbed from log lines wherever they are sent.
+
+use chrono::Utc;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::net::UdpSocket;
+use std::os::unix::net::UnixDatagram;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{Level, Metadata};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::registry::Registry;
+use tracing_subscriber::Layer;
+
+/// A log sink layer that can be added to the registry
+pub type SinkLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// The default number of rotated log files to keep
+const DEFAULT_FILE_KEEP: usize = 7;
+
+/// How often (in seconds) log lines are pushed to Loki
+const LOKI_PUSH_INTERVAL: u64 = 2;
+
+/// The most log lines held while waiting to push them to Loki. The
+/// oldest lines are dropped if Loki cannot be reached for a long time
+const MAX_LOKI_QUEUE: usize = 10_000;
+
+///
+/// Wraps a writer so that any secrets are redacted from each log line
+/// before it is written
+///
+#[derive(Debug, Clone)]
+pub struct Redacting<M>(pub M);
+
+///
+/// The writer made by `Redacting` for each log line
+///
+pub struct RedactingWriter<W>(W);
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // the fmt layer formats each event in full before writing it,
+        // so a whole line is redacted at once
+        let line = String::from_utf8_lossy(buf);
+        self.0
+            .write_all(paddington::redact::redact_str(&line).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for Redacting<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter(self.0.make_writer())
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        RedactingWriter(self.0.make_writer_for(meta))
+    }
+}
+
+///
+/// Return the name of this agent's executable, e.g. "op-slurm", which
+/// is used to tag syslog lines and label Loki streams
+///
+fn process_name() -> String {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.file_stem().map(|s| s.to_string_lossy().to_string()))
+        .unwrap_or_else(|| "openportal".to_owned())
+}
+
+///
+/// Return all of the log sinks that are configured in the environment.
+/// Problems are printed to stderr, as logging has not yet started
+///
+pub fn from_env(format: &str) -> Vec<SinkLayer> {
+    let mut sinks = Vec::new();
+
+    if let Ok(path) = std::env::var("RUST_LOG_FILE") {
+        match file_sink(&path, format) {
+            Ok(sink) => sinks.push(sink),
+            Err(e) => eprintln!("Could not log to file '{}': {}", path, e),
+        }
+    }
+
+    if let Ok(address) = std::env::var("RUST_LOG_SYSLOG") {
+        match syslog_sink(&address) {
+            Ok(sink) => sinks.push(sink),
+            Err(e) => eprintln!("Could not log to syslog at '{}': {}", address, e),
+        }
+    }
+
+    if let Ok(url) = std::env::var("RUST_LOG_LOKI") {
+        match loki_sink(&url) {
+            Ok(sink) => sinks.push(sink),
+            Err(e) => eprintln!("Could not log to Loki at '{}': {}", url, e),
+        }
+    }
+
+    sinks
+}
+
+///
+/// Log to rotated files. The lines use the same format as stdout
+/// (except that "pretty" is written as plain text)
+///
+fn file_sink(path: &str, format: &str) -> Result<SinkLayer, String> {
+    let path = std::path::PathBuf::from(path.trim());
+
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => std::path::PathBuf::from("."),
+    };
+
+    let prefix = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .ok_or_else(|| "the path must include a file name".to_owned())?;
+
+    let suffix = path
+        .extension()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "log".to_owned());
+
+    let rotation = match std::env::var("RUST_LOG_FILE_ROTATION")
+        .unwrap_or_default()
+        .to_lowercase()
+        .as_str()
+    {
+        "minutely" => Rotation::MINUTELY,
+        "hourly" => Rotation::HOURLY,
+        "daily" | "" => Rotation::DAILY,
+        "never" => Rotation::NEVER,
+        other => {
+            return Err(format!(
+            "invalid RUST_LOG_FILE_ROTATION '{}'. This must be minutely, hourly, daily or never",
+            other
+        ))
+        }
+    };
+
+    let keep = std::env::var("RUST_LOG_FILE_KEEP")
+        .ok()
+        .and_then(|k| k.trim().parse::<usize>().ok())
+        .unwrap_or(DEFAULT_FILE_KEEP)
+        .max(1);
+
+    let appender = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(prefix)
+        .filename_suffix(suffix)
+        .max_log_files(keep)
+        .build(&directory)
+        .map_err(|e| e.to_string())?;
+
+    let layer = tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .with_writer(Redacting(appender));
+
+    Ok(match format {
+        "json" => layer
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+        _ => layer.boxed(),
+    })
+}
+
+///
+/// Where syslog messages are sent
+///
+#[derive(Debug)]
+enum SyslogSocket {
+    Unix(UnixDatagram, String),
+    Udp(UdpSocket, String),
+}
+
+impl SyslogSocket {
+    fn connect(address: &str) -> Result<Self, String> {
+        let address = address.trim();
+
+        match address.split_once(':') {
+            Some(("udp", target)) => {
+                let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?;
+                Ok(SyslogSocket::Udp(socket, target.to_owned()))
+            }
+            Some(("unix", path)) => {
+                let socket = UnixDatagram::unbound().map_err(|e| e.to_string())?;
+                Ok(SyslogSocket::Unix(socket, path.to_owned()))
+            }
+            _ => Err("this should be unix:<socket path> or udp:<host>:<port>".to_owned()),
+        }
+    }
+
+    fn send(&self, message: &[u8]) {
+        // there is nowhere to report a failure to log, so failures
+        // are ignored (syslog over UDP is lossy anyway)
+        let _ = match self {
+            SyslogSocket::Unix(socket, path) => socket.send_to(message, path),
+            SyslogSocket::Udp(socket, target) => socket.send_to(message, target.as_str()),
+        };
+    }
+}
+
+///
+/// Writes each log line as a single RFC 3164 syslog message
+///
+#[derive(Debug, Clone)]
+struct SyslogWriter {
+    socket: Arc<SyslogSocket>,
+    facility: u8,
+    hostname: String,
+    tag: String,
+}
+
+///
+/// A single syslog message, which is sent when it is dropped
+///
+struct SyslogLine {
+    writer: SyslogWriter,
+    severity: u8,
+    buffer: Vec<u8>,
+}
+
+impl Write for SyslogLine {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SyslogLine {
+    fn drop(&mut self) {
+        let line = String::from_utf8_lossy(&self.buffer);
+        let line = line.trim_end();
+
+        if line.is_empty() {
+            return;
+        }
+
+        let message = format!(
+            "<{}>{} {} {}[{}]: {}",
+            self.writer.facility * 8 + self.severity,
+            Utc::now().format("%b %e %H:%M:%S"),
+            self.writer.hostname,
+            self.writer.tag,
+            std::process::id(),
+            line
+        );
+
+        self.writer.socket.send(message.as_bytes());
+    }
+}
+
+impl<'a> MakeWriter<'a> for SyslogWriter {
+    type Writer = SyslogLine;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        SyslogLine {
+            writer: self.clone(),
+            severity: 6,
+            buffer: Vec::new(),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        let severity = match *meta.level() {
+            Level::ERROR => 3,
+            Level::WARN => 4,
+            Level::INFO => 6,
+            Level::DEBUG | Level::TRACE => 7,
+        };
+
+        SyslogLine {
+            writer: self.clone(),
+            severity,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+///
+/// Log to syslog. Syslog adds its own timestamp, so this is not
+/// included in the message
+///
+fn syslog_sink(address: &str) -> Result<SinkLayer, String> {
+    let facility = match std::env::var("RUST_LOG_SYSLOG_FACILITY")
+        .unwrap_or_default()
+        .to_lowercase()
+        .as_str()
+    {
+        "user" => 1,
+        "daemon" | "" => 3,
+        "auth" => 4,
+        "local0" => 16,
+        "local1" => 17,
+        "local2" => 18,
+        "local3" => 19,
+        "local4" => 20,
+        "local5" => 21,
+        "local6" => 22,
+        "local7" => 23,
+        other => {
+            return Err(format!(
+            "invalid RUST_LOG_SYSLOG_FACILITY '{}'. This must be user, daemon, auth or local0-7",
+            other
+        ))
+        }
+    };
+
+    let writer = SyslogWriter {
+        socket: Arc::new(SyslogSocket::connect(address)?),
+        facility,
+        hostname: sysinfo::System::host_name().unwrap_or_else(|| "localhost".to_owned()),
+        tag: process_name(),
+    };
+
+    Ok(tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .without_time()
+        .with_writer(Redacting(writer))
+        .boxed())
+}
+
+/// The log lines waiting to be pushed to Loki, as (level, timestamp in
+/// nanoseconds, line)
+type LokiQueue = Arc<Mutex<Vec<(String, String, String)>>>;
+
+///
+/// Queues each log line to be pushed to Loki
+///
+#[derive(Debug, Clone)]
+struct LokiWriter {
+    queue: LokiQueue,
+}
+
+///
+/// A single Loki log line, which is queued when it is dropped
+///
+struct LokiLine {
+    queue: LokiQueue,
+    level: String,
+    buffer: Vec<u8>,
+}
+
+impl Write for LokiLine {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LokiLine {
+    fn drop(&mut self) {
+        let line = String::from_utf8_lossy(&self.buffer).trim_end().to_owned();
+
+        if line.is_empty() {
+            return;
+        }
+
+        let timestamp = Utc::now()
+            .timestamp_nanos_opt()
+            .unwrap_or_default()
+            .to_string();
+
+        if let Ok(mut queue) = self.queue.lock() {
+            if queue.len() >= MAX_LOKI_QUEUE {
+                queue.remove(0);
+            }
+
+            queue.push((self.level.clone(), timestamp, line));
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for LokiWriter {
+    type Writer = LokiLine;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LokiLine {
+            queue: self.queue.clone(),
+            level: "info".to_owned(),
+            buffer: Vec::new(),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        LokiLine {
+            queue: self.queue.clone(),
+            level: meta.level().to_string().to_lowercase(),
+            buffer: Vec::new(),
+        }
+    }
+}
+
+///
+/// Parse the comma-separated "key=value" Loki labels
+///
+fn parse_labels(labels: &str) -> Result<BTreeMap<String, String>, String> {
+    labels
+        .split(',')
+        .map(|label| label.trim())
+        .filter(|label| !label.is_empty())
+        .map(|label| match label.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                Ok((key.trim().to_owned(), value.trim().to_owned()))
+            }
+            _ => Err(format!(
+                "invalid Loki label '{}'. This should be key=value",
+                label
+            )),
+        })
+        .collect()
+}
+
+///
+/// Push all of the queued lines to Loki, with a stream for each level
+///
+fn push_to_loki(
+    client: &reqwest::blocking::Client,
+    url: &str,
+    labels: &BTreeMap<String, String>,
+    queue: &LokiQueue,
+) {
+    let lines = match queue.lock() {
+        Ok(mut queue) => std::mem::take(&mut *queue),
+        Err(_) => return,
+    };
+
+    if lines.is_empty() {
+        return;
+    }
+
+    let mut streams: BTreeMap<String, Vec<[String; 2]>> = BTreeMap::new();
+
+    for (level, timestamp, line) in lines {
+        streams.entry(level).or_default().push([timestamp, line]);
+    }
+
+    let streams: Vec<_> = streams
+        .into_iter()
+        .map(|(level, values)| {
+            let mut stream = labels.clone();
+            stream.insert("level".to_owned(), level);
+            json!({"stream": stream, "values": values})
+        })
+        .collect();
+
+    // errors cannot be logged (this would only queue more lines), so
+    // they are written to stderr
+    match client.post(url).json(&json!({"streams": streams})).send() {
+        Ok(response) if !response.status().is_success() => {
+            eprintln!("Loki rejected pushed logs: {}", response.status());
+        }
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Could not push logs to Loki: {}", e);
+        }
+    }
+}
+
+///
+/// Push logs to Loki as JSON lines (so that the job correlation span
+/// is kept). Lines are pushed in the background every few seconds
+///
+fn loki_sink(url: &str) -> Result<SinkLayer, String> {
+    let mut url = url::Url::parse(url.trim()).map_err(|e| e.to_string())?;
+
+    if !url.path().ends_with("/loki/api/v1/push") {
+        url = url.join("/loki/api/v1/push").map_err(|e| e.to_string())?;
+    }
+
+    let mut labels = parse_labels(&std::env::var("RUST_LOG_LOKI_LABELS").unwrap_or_default())?;
+    labels
+        .entry("agent".to_owned())
+        .or_insert_with(process_name);
+
+    let queue = LokiQueue::default();
+    let writer = LokiWriter {
+        queue: queue.clone(),
+    };
+
+    // a plain thread (with a blocking client) is used so that this works
+    // with or without a tokio runtime
+    std::thread::Builder::new()
+        .name("loki-push".to_owned())
+        .spawn(move || {
+            let client = match reqwest::blocking::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+            {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Could not create the client to push logs to Loki: {}", e);
+                    return;
+                }
+            };
+
+            loop {
+                std::thread::sleep(Duration::from_secs(LOKI_PUSH_INTERVAL));
+                push_to_loki(&client, url.as_str(), &labels, &queue);
+            }
+        })
+        .map_err(|e| e.to_string())?;
+
+    Ok(tracing_subscriber::fmt::layer()
+        .json()
+        .with_current_span(true)
+        .with_span_list(false)
+        .with_ansi(false)
+        .with_writer(Redacting(writer))
+        .boxed())
+}
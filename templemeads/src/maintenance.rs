@@ -0,0 +1,493 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Maintenance windows and scheduled restarts
+//!
+//! Soft and hard restarts disrupt every peer of an agent, so they can be
+//! limited to maintenance windows (e.g. "Sat-Sun 02:00-04:00"), given in
+//! the local time of the agent. A restart requested outside a window is
+//! scheduled for the start of the next one. When a scheduled restart is
+//! due, the agent first drains - it pauses new jobs and waits for the
+//! running jobs to finish - before restarting.
+
+use crate::error::Error;
+use crate::health;
+use crate::job::Job;
+use crate::restart;
+use crate::watchdog;
+
+use chrono::{Datelike, Duration, Local, NaiveDateTime, NaiveTime, Weekday};
+use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+
+/// Default time (in seconds) to wait for running jobs to finish
+pub const DEFAULT_DRAIN_TIMEOUT: u64 = 300;
+
+///
+/// A window, on some or all days of the week, in which disruptive
+/// operations can take place. A window whose end is before its start
+/// runs past midnight into the next day
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaintenanceWindow {
+    days: Vec<Weekday>,
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+pub(crate) fn parse_time(time: &str) -> Result<NaiveTime, Error> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| {
+        Error::Parse(format!(
+            "Invalid time '{}'. It must be given as HH:MM",
+            time
+        ))
+    })
+}
+
+fn parse_weekday(day: &str) -> Result<Weekday, Error> {
+    day.trim()
+        .parse()
+        .map_err(|_| Error::Parse(format!("Invalid day of the week '{}'", day)))
+}
+
+impl MaintenanceWindow {
+    ///
+    /// Parse a window such as "02:00-04:00" (every day), "Sat 02:00-04:00"
+    /// or "Mon-Fri 23:00-01:00"
+    ///
+    pub fn parse(window: &str) -> Result<Self, Error> {
+        let window = window.trim();
+
+        let (days, times) = match window.rsplit_once(' ') {
+            Some((days, times)) => (days.trim(), times),
+            None => ("", window),
+        };
+
+        let Some((start, end)) = times.split_once('-') else {
+            return Err(Error::Parse(format!(
+                "Invalid maintenance window '{}'. It must be '[days] HH:MM-HH:MM'",
+                window
+            )));
+        };
+
+        let start = parse_time(start)?;
+        let end = parse_time(end)?;
+
+        if start == end {
+            return Err(Error::Parse(format!(
+                "Invalid maintenance window '{}'. It must end at a different time to when it starts",
+                window
+            )));
+        }
+
+        let days = match days.split_once('-') {
+            None if days.is_empty() => Vec::new(),
+            Some((first, last)) => {
+                let mut day = parse_weekday(first)?;
+                let last = parse_weekday(last)?;
+
+                let mut days = vec![day];
+
+                while day != last {
+                    day = day.succ();
+                    days.push(day);
+                }
+
+                days
+            }
+            None => vec![parse_weekday(days)?],
+        };
+
+        Ok(Self { days, start, end })
+    }
+
+    fn is_on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    ///
+    /// Return the start and end of the occurrence of this window that
+    /// starts on the day of `time`, if the window is open on that day
+    ///
+    fn occurrence(&self, time: NaiveDateTime) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        let date = time.date();
+
+        if !self.is_on(date.weekday()) {
+            return None;
+        }
+
+        let start = date.and_time(self.start);
+
+        let end = match self.end > self.start {
+            true => date.and_time(self.end),
+            false => (date + Duration::days(1)).and_time(self.end),
+        };
+
+        Some((start, end))
+    }
+
+    ///
+    /// Return whether the passed local time is within this window
+    ///
+    pub fn contains(&self, time: NaiveDateTime) -> bool {
+        // the window may have started on the day before
+        [time - Duration::days(1), time].iter().any(|day| {
+            self.occurrence(*day)
+                .is_some_and(|(start, end)| start <= time && time < end)
+        })
+    }
+
+    ///
+    /// Return the next time after the passed local time that this
+    /// window opens
+    ///
+    pub fn next_start(&self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        (0..=7)
+            .filter_map(|days| self.occurrence(time + Duration::days(days)))
+            .map(|(start, _)| start)
+            .find(|start| *start > time)
+    }
+}
+
+///
+/// The maintenance windows of this agent. If there are none then
+/// disruptive operations can take place at any time
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MaintenanceWindows {
+    windows: Vec<MaintenanceWindow>,
+}
+
+impl MaintenanceWindows {
+    ///
+    /// Parse the comma-separated windows from the `maintenance-windows`
+    /// option, e.g. "Sat-Sun 02:00-04:00, Wed 01:00-02:00"
+    ///
+    pub fn parse(windows: &str) -> Result<Self, Error> {
+        Ok(Self {
+            windows: windows
+                .split(',')
+                .filter(|window| !window.trim().is_empty())
+                .map(MaintenanceWindow::parse)
+                .collect::<Result<Vec<_>, _>>()?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    ///
+    /// Return whether disruptive operations can take place at the passed
+    /// local time
+    ///
+    pub fn is_open(&self, time: NaiveDateTime) -> bool {
+        self.is_empty() || self.windows.iter().any(|window| window.contains(time))
+    }
+
+    ///
+    /// Return the earliest time, from the passed local time onwards, at
+    /// which disruptive operations can take place
+    ///
+    pub fn next_open(&self, time: NaiveDateTime) -> NaiveDateTime {
+        if self.is_open(time) {
+            return time;
+        }
+
+        self.windows
+            .iter()
+            .filter_map(|window| window.next_start(time))
+            .min()
+            .unwrap_or(time)
+    }
+}
+
+/// The maintenance windows of this agent
+static WINDOWS: Lazy<RwLock<MaintenanceWindows>> =
+    Lazy::new(|| RwLock::new(MaintenanceWindows::default()));
+
+/// Time (in seconds) to wait for running jobs to finish before restarting
+static DRAIN_TIMEOUT: Mutex<u64> = Mutex::new(DEFAULT_DRAIN_TIMEOUT);
+
+/// Whether this agent is draining ahead of a restart
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+/// Number of scheduled restarts that have been performed
+static RESTARTS: AtomicUsize = AtomicUsize::new(0);
+
+///
+/// A restart that will take place at a later time
+///
+struct ScheduledRestart {
+    restart_type: String,
+    at: NaiveDateTime,
+    task: tokio::task::JoinHandle<()>,
+}
+
+/// The restart that is scheduled, if any
+static SCHEDULED: Mutex<Option<ScheduledRestart>> = Mutex::new(None);
+
+///
+/// Set the maintenance windows of this agent, and the time (in seconds)
+/// to wait for running jobs to finish before a scheduled restart
+///
+pub fn set_windows(windows: MaintenanceWindows, drain_timeout: u64) {
+    match WINDOWS.write() {
+        Ok(mut current) => *current = windows,
+        Err(e) => tracing::error!("Failed to lock maintenance windows: {}", e),
+    }
+
+    match DRAIN_TIMEOUT.lock() {
+        Ok(mut current) => *current = drain_timeout,
+        Err(e) => tracing::error!("Failed to lock drain timeout: {}", e),
+    }
+}
+
+///
+/// Return the local time at which the next maintenance window opens,
+/// or None if disruptive operations can take place now
+///
+pub(crate) fn next_window() -> Option<NaiveDateTime> {
+    let now = Local::now().naive_local();
+
+    match WINDOWS.read() {
+        Ok(windows) => match windows.is_open(now) {
+            true => None,
+            false => Some(windows.next_open(now)),
+        },
+        Err(e) => {
+            tracing::error!("Failed to lock maintenance windows: {}", e);
+            None
+        }
+    }
+}
+
+///
+/// Return the next local time, from now, that is the passed time of day
+///
+pub(crate) fn next_time_of_day(time: NaiveTime) -> NaiveDateTime {
+    let now = Local::now().naive_local();
+    let today = now.date().and_time(time);
+
+    match today > now {
+        true => today,
+        false => today + Duration::days(1),
+    }
+}
+
+///
+/// Wait until this agent has stopped draining before running the
+/// passed job. This returns an error if the job expires while waiting,
+/// or if the agent restarted, as the restart has cancelled the job
+///
+pub(crate) async fn wait_while_draining(job: &Job) -> Result<(), Error> {
+    if !DRAINING.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+
+    tracing::warn!("Pausing job {} while draining for a restart", job.id());
+
+    let restarts = RESTARTS.load(Ordering::Relaxed);
+
+    while DRAINING.load(Ordering::Relaxed) {
+        job.assert_is_not_expired()?;
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+
+    if RESTARTS.load(Ordering::Relaxed) != restarts {
+        return Err(Error::Unavailable(format!(
+            "Job {} was held while the agent restarted",
+            job.id()
+        )));
+    }
+
+    Ok(())
+}
+
+///
+/// Return whether this agent is draining ahead of a restart
+///
+pub(crate) fn is_draining() -> bool {
+    DRAINING.load(Ordering::Relaxed)
+}
+
+fn stop_draining() {
+    DRAINING.store(false, Ordering::Relaxed);
+    health::clear_warning("maintenance");
+}
+
+///
+/// Pause new jobs, and wait for the running jobs to finish, or for the
+/// drain timeout to pass, whichever comes first
+///
+pub(crate) async fn drain(restart_type: &str) {
+    let timeout = match DRAIN_TIMEOUT.lock() {
+        Ok(timeout) => *timeout,
+        Err(e) => {
+            tracing::error!("Failed to lock drain timeout: {}", e);
+            DEFAULT_DRAIN_TIMEOUT
+        }
+    };
+
+    DRAINING.store(true, Ordering::Relaxed);
+    health::set_warning(
+        "maintenance",
+        &format!(
+            "Draining for a {} restart - new jobs are paused",
+            restart_type
+        ),
+    );
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(timeout);
+
+    while watchdog::running() > 0 {
+        if std::time::Instant::now() >= deadline {
+            tracing::warn!(
+                "{} job(s) still running after draining for {}s - restarting anyway",
+                watchdog::running(),
+                timeout
+            );
+            return;
+        }
+
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+
+    tracing::info!("Drained all running jobs");
+}
+
+///
+/// Schedule a restart of the passed type at the passed local time,
+/// replacing any restart that is already scheduled. The agent drains
+/// before it restarts
+///
+pub(crate) fn schedule(restart_type: &str, at: NaiveDateTime) -> Result<(), Error> {
+    let mut scheduled = SCHEDULED
+        .lock()
+        .map_err(|e| Error::Bug(format!("Failed to lock scheduled restart: {}", e)))?;
+
+    if let Some(previous) = scheduled.take() {
+        tracing::info!(
+            "Replacing the {} restart scheduled for {}",
+            previous.restart_type,
+            previous.at
+        );
+        previous.task.abort();
+        stop_draining();
+    }
+
+    let delay = (at - Local::now().naive_local())
+        .to_std()
+        .unwrap_or_default();
+
+    let task_type = restart_type.to_owned();
+
+    let task = tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+
+        tracing::warn!("Scheduled {} restart is due - draining", task_type);
+        drain(&task_type).await;
+
+        RESTARTS.fetch_add(1, Ordering::Relaxed);
+
+        if let Err(e) = restart::restart_now(&task_type).await {
+            tracing::error!("Scheduled {} restart failed: {}", task_type, e);
+        }
+
+        stop_draining();
+
+        if let Ok(mut scheduled) = SCHEDULED.lock() {
+            *scheduled = None;
+        }
+    });
+
+    tracing::warn!("Scheduled a {} restart for {}", restart_type, at);
+
+    *scheduled = Some(ScheduledRestart {
+        restart_type: restart_type.to_owned(),
+        at,
+        task,
+    });
+
+    Ok(())
+}
+
+///
+/// Cancel the scheduled restart, returning a description of the
+/// restart that was cancelled, if there was one
+///
+pub(crate) fn cancel() -> Result<Option<String>, Error> {
+    let mut scheduled = SCHEDULED
+        .lock()
+        .map_err(|e| Error::Bug(format!("Failed to lock scheduled restart: {}", e)))?;
+
+    let Some(previous) = scheduled.take() else {
+        return Ok(None);
+    };
+
+    previous.task.abort();
+    stop_draining();
+
+    tracing::warn!(
+        "Cancelled the {} restart scheduled for {}",
+        previous.restart_type,
+        previous.at
+    );
+
+    Ok(Some(format!(
+        "{} restart scheduled for {}",
+        previous.restart_type,
+        previous.at.format("%Y-%m-%d %H:%M")
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(day: u32, time: &str) -> NaiveDateTime {
+        // 2026-06-01 is a Monday
+        let date = NaiveDate::from_ymd_opt(2026, 6, day)
+            .unwrap_or_else(|| unreachable!("Invalid test date"));
+        let time = parse_time(time).unwrap_or_else(|e| unreachable!("Invalid test time: {}", e));
+
+        date.and_time(time)
+    }
+
+    #[test]
+    fn test_maintenance_windows() {
+        let windows = MaintenanceWindows::parse("Sat-Sun 02:00-04:00, Wed 23:00-01:00")
+            .unwrap_or_else(|e| unreachable!("Cannot parse windows: {}", e));
+
+        // Saturday 6th and Sunday 7th
+        assert!(windows.is_open(at(6, "02:00")));
+        assert!(windows.is_open(at(7, "03:59")));
+        assert!(!windows.is_open(at(6, "04:00")));
+        assert!(!windows.is_open(at(1, "03:00")));
+
+        // the Wednesday window runs past midnight into Thursday
+        assert!(windows.is_open(at(3, "23:30")));
+        assert!(windows.is_open(at(4, "00:30")));
+        assert!(!windows.is_open(at(4, "23:30")));
+
+        assert_eq!(windows.next_open(at(1, "12:00")), at(3, "23:00"));
+        assert_eq!(windows.next_open(at(4, "01:00")), at(6, "02:00"));
+        assert_eq!(windows.next_open(at(6, "03:00")), at(6, "03:00"));
+
+        // no windows means always open
+        let windows = MaintenanceWindows::parse("")
+            .unwrap_or_else(|e| unreachable!("Cannot parse windows: {}", e));
+        assert!(windows.is_open(at(1, "12:00")));
+
+        let windows = MaintenanceWindows::parse("02:00-03:00")
+            .unwrap_or_else(|e| unreachable!("Cannot parse windows: {}", e));
+        assert_eq!(windows.next_open(at(1, "12:00")), at(2, "02:00"));
+
+        assert!(MaintenanceWindows::parse("Sat").is_err());
+        assert!(MaintenanceWindows::parse("Someday 02:00-03:00").is_err());
+        assert!(MaintenanceWindows::parse("02:00-02:00").is_err());
+        assert!(MaintenanceWindows::parse("2am-3am").is_err());
+    }
+}
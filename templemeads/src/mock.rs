@@ -0,0 +1,686 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! In-process mock agents for integration testing
+//!
+//! Mock account, scheduler and filesystem agents run inside the agent
+//! that hosts them, in place of real FreeIPA, Slurm or filesystem
+//! agents. They are registered as peers of their mock type, so jobs are
+//! routed to them as normal, but each job is handled in-process by the
+//! mock rather than being sent over the network. This lets a whole
+//! workflow (e.g. portal → cluster → account, scheduler and filesystem)
+//! be exercised without any real services.
+//!
+//! By default, mocks keep track of the users and projects that have
+//! been added and removed, and answer queries about them. The response
+//! to any instruction can be scripted, and failures and delays can be
+//! injected to test how the rest of the system copes. Mocks are only
+//! available if templemeads is built with the `mock` feature.
+
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+use crate::agent::{self, Peer, Type as AgentType};
+use crate::async_runnable;
+use crate::dryrun;
+use crate::error::Error;
+use crate::grammar::{Instruction, ProjectIdentifier, ProjectMapping, UserIdentifier, UserMapping};
+use crate::handler::set_my_service_details;
+use crate::job::{Envelope, Job};
+
+/// The zone of mock agents created from the `mock-agents` option
+pub const DEFAULT_ZONE: &str = "mock";
+
+///
+/// A scripted response to a job, which returns the job completed
+/// with its result (or an error)
+///
+pub type Response = Arc<dyn Fn(&Job) -> Result<Job, Error> + Send + Sync>;
+
+#[derive(Debug, Clone)]
+struct Failure {
+    error: String,
+    // the number of jobs that still have to fail, or None if they all do
+    remaining: Option<usize>,
+}
+
+///
+/// A mock account, scheduler or filesystem agent. Create it, script its
+/// responses and failures, and then register it with the agent that
+/// will host it
+///
+#[derive(Clone)]
+pub struct MockAgent {
+    peer: Peer,
+    agent_type: AgentType,
+    responses: HashMap<String, Response>,
+    failures: HashMap<String, Failure>,
+    delay: Duration,
+    stage_users: bool,
+}
+
+impl std::fmt::Debug for MockAgent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("MockAgent")
+            .field("peer", &self.peer)
+            .field("agent_type", &self.agent_type)
+            .field("responses", &self.responses.keys().collect::<Vec<_>>())
+            .field("failures", &self.failures)
+            .field("delay", &self.delay)
+            .field("stage_users", &self.stage_users)
+            .finish()
+    }
+}
+
+impl MockAgent {
+    ///
+    /// Create a mock agent called `name` in `zone`, of the passed type.
+    /// Only account, scheduler and filesystem agents can be mocked
+    ///
+    pub fn new(name: &str, zone: &str, agent_type: &AgentType) -> Result<Self, Error> {
+        match agent_type {
+            AgentType::Account | AgentType::Scheduler | AgentType::Filesystem => {}
+            _ => {
+                return Err(Error::Incompatible(format!(
+                    "Cannot create a mock {} agent. Only account, scheduler \
+                     and filesystem agents can be mocked",
+                    agent_type
+                )))
+            }
+        }
+
+        if name.trim().is_empty() || zone.trim().is_empty() {
+            return Err(Error::Parse(format!(
+                "Invalid mock agent name '{}' or zone '{}'",
+                name, zone
+            )));
+        }
+
+        Ok(MockAgent {
+            peer: Peer::new(name.trim(), zone.trim()),
+            agent_type: agent_type.clone(),
+            responses: HashMap::new(),
+            failures: HashMap::new(),
+            delay: Duration::ZERO,
+            stage_users: false,
+        })
+    }
+
+    ///
+    /// Create a mock account agent (e.g. in place of FreeIPA)
+    ///
+    pub fn account(name: &str, zone: &str) -> Result<Self, Error> {
+        Self::new(name, zone, &AgentType::Account)
+    }
+
+    ///
+    /// Create a mock scheduler agent (e.g. in place of Slurm)
+    ///
+    pub fn scheduler(name: &str, zone: &str) -> Result<Self, Error> {
+        Self::new(name, zone, &AgentType::Scheduler)
+    }
+
+    ///
+    /// Create a mock filesystem agent
+    ///
+    pub fn filesystem(name: &str, zone: &str) -> Result<Self, Error> {
+        Self::new(name, zone, &AgentType::Filesystem)
+    }
+
+    pub fn peer(&self) -> &Peer {
+        &self.peer
+    }
+
+    pub fn agent_type(&self) -> &AgentType {
+        &self.agent_type
+    }
+
+    ///
+    /// Script the response to every job with the passed command
+    /// (e.g. "add_user"), replacing the default response
+    ///
+    pub fn respond<F>(mut self, command: &str, response: F) -> Self
+    where
+        F: Fn(&Job) -> Result<Job, Error> + Send + Sync + 'static,
+    {
+        self.responses
+            .insert(command.trim().to_owned(), Arc::new(response));
+        self
+    }
+
+    ///
+    /// Make every job with the passed command fail with `error`
+    ///
+    pub fn fail(mut self, command: &str, error: &str) -> Self {
+        self.failures.insert(
+            command.trim().to_owned(),
+            Failure {
+                error: error.to_owned(),
+                remaining: None,
+            },
+        );
+        self
+    }
+
+    ///
+    /// Make the next `times` jobs with the passed command fail with
+    /// `error`, after which they succeed again
+    ///
+    pub fn fail_times(mut self, command: &str, times: usize, error: &str) -> Self {
+        self.failures.insert(
+            command.trim().to_owned(),
+            Failure {
+                error: error.to_owned(),
+                remaining: Some(times),
+            },
+        );
+        self
+    }
+
+    ///
+    /// Wait for `delay` before responding to every job
+    ///
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    ///
+    /// Add new users as staged users (as the FreeIPA agent does with the
+    /// `stage-users` option). Staged users do not exist, and are not
+    /// listed in their project, until they are activated with
+    /// `activate_user`
+    ///
+    pub fn stage_users(mut self) -> Self {
+        self.stage_users = true;
+        self
+    }
+
+    ///
+    /// Register this mock with the agent in this process, so that jobs
+    /// can be sent to it. This replaces any mock with the same name and
+    /// zone, and returns the peer to use to reach the mock
+    ///
+    pub async fn register(self) -> Peer {
+        let peer = self.peer.clone();
+
+        agent::register_peer(&peer, &self.agent_type, "mock", env!("CARGO_PKG_VERSION")).await;
+
+        tracing::info!("Registered mock {} agent {}", self.agent_type, peer);
+
+        MOCKS.write().await.insert(
+            peer.clone(),
+            Mock {
+                agent: self,
+                records: Records::default(),
+            },
+        );
+
+        peer
+    }
+}
+
+///
+/// The users and projects that a mock knows about, and the
+/// instructions it has been sent
+///
+#[derive(Debug, Default)]
+struct Records {
+    calls: Vec<String>,
+    users: HashMap<UserIdentifier, UserMapping>,
+    staged: HashSet<UserIdentifier>,
+    projects: HashMap<ProjectIdentifier, ProjectMapping>,
+}
+
+#[derive(Debug)]
+struct Mock {
+    agent: MockAgent,
+    records: Records,
+}
+
+static MOCKS: Lazy<RwLock<HashMap<Peer, Mock>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+///
+/// Parse the value of the `mock-agents` option, which is a comma-separated
+/// list of `name:type` pairs (e.g. "freeipa:account,slurm:scheduler"),
+/// into the mocks to register. Each mock is in the "mock" zone
+///
+pub fn parse(mocks: &str) -> Result<Vec<MockAgent>, Error> {
+    mocks
+        .split(',')
+        .map(|mock| mock.trim())
+        .filter(|mock| !mock.is_empty())
+        .map(|mock| match mock.split_once(':') {
+            Some((name, "account")) => MockAgent::account(name, DEFAULT_ZONE),
+            Some((name, "scheduler")) => MockAgent::scheduler(name, DEFAULT_ZONE),
+            Some((name, "filesystem")) => MockAgent::filesystem(name, DEFAULT_ZONE),
+            _ => Err(Error::Parse(format!(
+                "Invalid mock agent '{}'. This should be 'name:type', where \
+                 type is one of account, scheduler or filesystem",
+                mock
+            ))),
+        })
+        .collect()
+}
+
+///
+/// Set the name and type of the agent that hosts the mocks. This is
+/// only needed by tests that call an agent's functions directly,
+/// without starting the agent itself
+///
+pub async fn host(name: &str, agent_type: &AgentType) -> Result<(), Error> {
+    set_my_service_details(name, agent_type, None, false).await?;
+    Ok(())
+}
+
+///
+/// Return whether or not the passed peer is a registered mock agent
+///
+pub async fn is_mock(peer: &Peer) -> bool {
+    MOCKS.read().await.contains_key(peer)
+}
+
+///
+/// Return the instructions that have been sent to the passed mock,
+/// in the order they were received
+///
+pub async fn calls(peer: &Peer) -> Vec<String> {
+    MOCKS
+        .read()
+        .await
+        .get(peer)
+        .map(|mock| mock.records.calls.clone())
+        .unwrap_or_default()
+}
+
+///
+/// Remove the passed mock, so that it can no longer be reached
+///
+pub async fn unregister(peer: &Peer) {
+    if MOCKS.write().await.remove(peer).is_some() {
+        agent::remove(peer).await;
+    }
+}
+
+async_runnable! {
+    ///
+    /// Runnable function that is called in place of the agent's runner
+    /// for every job that is sent to a mock agent
+    ///
+    pub async fn mock_runner(envelope: Envelope) -> Result<Job, Error>
+    {
+        let job = envelope.job();
+        let peer = envelope.recipient();
+        let command = job.instruction().command();
+
+        // take what is needed, so that the lock isn't held while responding
+        let (agent_type, response, failure, delay, stage_users) = {
+            let mut mocks = MOCKS.write().await;
+
+            let mock = mocks
+                .get_mut(&peer)
+                .ok_or_else(|| Error::MissingAgent(format!("{} is not a mock agent", peer)))?;
+
+            mock.records.calls.push(job.instruction().to_string());
+
+            let failure = mock.agent.failures.get_mut(&command).and_then(|failure| {
+                match failure.remaining.as_mut() {
+                    None => Some(failure.error.clone()),
+                    Some(0) => None,
+                    Some(remaining) => {
+                        *remaining -= 1;
+                        Some(failure.error.clone())
+                    }
+                }
+            });
+
+            (
+                mock.agent.agent_type.clone(),
+                mock.agent.responses.get(&command).cloned(),
+                failure,
+                mock.agent.delay,
+                mock.agent.stage_users,
+            )
+        };
+
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+
+        if let Some(error) = failure {
+            tracing::info!("Mock {} injecting failure for {}: {}", peer, job.instruction(), error);
+            return Err(Error::Call(error));
+        }
+
+        // nothing is recorded during a dry run, apart from the change
+        let dry_run = job.instruction().is_mutating()
+            && dryrun::record(&job.instruction().to_string());
+
+        if let Some(response) = response {
+            return response(&job);
+        }
+
+        let mut mocks = MOCKS.write().await;
+
+        let records = &mut mocks
+            .get_mut(&peer)
+            .ok_or_else(|| Error::MissingAgent(format!("{} is not a mock agent", peer)))?
+            .records;
+
+        match agent_type {
+            AgentType::Account => account_response(&job, records, dry_run, stage_users),
+            AgentType::Scheduler => scheduler_response(&job, records, dry_run),
+            _ => filesystem_response(&job, records, dry_run),
+        }
+    }
+}
+
+fn unsupported(job: &Job, agent_type: &str) -> Result<Job, Error> {
+    Err(Error::InvalidInstruction(format!(
+        "Mock {} agents have no default response to {}. Use 'respond' to \
+         script one.",
+        agent_type,
+        job.instruction()
+    )))
+}
+
+fn missing_user(user: &UserIdentifier) -> Error {
+    Error::MissingUser(format!("User {} does not exist in the mock", user))
+}
+
+fn missing_project(project: &ProjectIdentifier) -> Error {
+    Error::MissingProject(format!("Project {} does not exist in the mock", project))
+}
+
+///
+/// The default responses of a mock account agent. Users are given the
+/// local account 'username.project' in the group 'project'
+///
+fn account_response(
+    job: &Job,
+    records: &mut Records,
+    dry_run: bool,
+    stage_users: bool,
+) -> Result<Job, Error> {
+    match job.instruction() {
+        Instruction::GetProjects(portal) => job.completed(
+            records
+                .projects
+                .values()
+                .filter(|mapping| mapping.project().portal_identifier() == portal)
+                .cloned()
+                .collect::<Vec<ProjectMapping>>(),
+        ),
+        Instruction::AddProject(project) => {
+            let mapping = ProjectMapping::new(&project, &project.project())?;
+
+            if !dry_run {
+                records.projects.insert(project, mapping.clone());
+            }
+
+            job.completed(mapping)
+        }
+        Instruction::RemoveProject(project) => {
+            let mapping = records
+                .projects
+                .get(&project)
+                .cloned()
+                .ok_or_else(|| missing_project(&project))?;
+
+            if !dry_run {
+                records.projects.remove(&project);
+                records
+                    .users
+                    .retain(|user, _| user.project_identifier() != project);
+                records
+                    .staged
+                    .retain(|user| user.project_identifier() != project);
+            }
+
+            job.completed(mapping)
+        }
+        Instruction::GetProjectMapping(project) => job.completed(
+            records
+                .projects
+                .get(&project)
+                .cloned()
+                .ok_or_else(|| missing_project(&project))?,
+        ),
+        Instruction::IsExistingProject(project) => {
+            job.completed(records.projects.contains_key(&project))
+        }
+        // staged users are not listed until they are activated
+        Instruction::GetUsers(project) => job.completed(
+            records
+                .users
+                .values()
+                .filter(|mapping| mapping.user().project_identifier() == project)
+                .filter(|mapping| !records.staged.contains(mapping.user()))
+                .cloned()
+                .collect::<Vec<UserMapping>>(),
+        ),
+        Instruction::AddUser(user) => {
+            let mapping = UserMapping::new(
+                &user,
+                &format!("{}.{}", user.username(), user.project()),
+                &user.project(),
+            )?;
+
+            if !dry_run {
+                if stage_users && !records.users.contains_key(&user) {
+                    records.staged.insert(user.clone());
+                }
+
+                records.users.insert(user, mapping.clone());
+            }
+
+            job.completed(mapping)
+        }
+        Instruction::ActivateUser(user) => {
+            let mapping = records
+                .users
+                .get(&user)
+                .cloned()
+                .ok_or_else(|| missing_user(&user))?;
+
+            if !dry_run {
+                records.staged.remove(&user);
+            }
+
+            job.completed(mapping)
+        }
+        Instruction::RemoveUser(user) => {
+            let mapping = records
+                .users
+                .get(&user)
+                .cloned()
+                .ok_or_else(|| missing_user(&user))?;
+
+            if !dry_run {
+                records.users.remove(&user);
+                records.staged.remove(&user);
+            }
+
+            job.completed(mapping)
+        }
+        Instruction::GetUserMapping(user) => job.completed(
+            records
+                .users
+                .get(&user)
+                .cloned()
+                .ok_or_else(|| missing_user(&user))?,
+        ),
+        Instruction::IsExistingUser(user) => {
+            job.completed(records.users.contains_key(&user) && !records.staged.contains(&user))
+        }
+        Instruction::UpdateHomeDir(user, homedir) => {
+            if !records.users.contains_key(&user) {
+                return Err(missing_user(&user));
+            }
+
+            job.completed(homedir)
+        }
+        Instruction::IsProtectedUser(_) => job.completed(false),
+        _ => unsupported(job, "account"),
+    }
+}
+
+///
+/// Record the local users and projects added to or removed from a mock
+/// scheduler or filesystem agent
+///
+fn local_response(job: &Job, records: &mut Records, dry_run: bool) -> Option<Result<Job, Error>> {
+    match job.instruction() {
+        Instruction::AddLocalProject(mapping) => {
+            if !dry_run {
+                records.projects.insert(mapping.project().clone(), mapping);
+            }
+        }
+        Instruction::RemoveLocalProject(mapping) => {
+            if !dry_run {
+                records.projects.remove(mapping.project());
+            }
+        }
+        Instruction::AddLocalUser(mapping) => {
+            if !dry_run {
+                records.users.insert(mapping.user().clone(), mapping);
+            }
+        }
+        Instruction::RemoveLocalUser(mapping) => {
+            if !dry_run {
+                records.users.remove(mapping.user());
+            }
+        }
+        _ => return None,
+    }
+
+    Some(job.completed_none())
+}
+
+///
+/// The default responses of a mock scheduler agent
+///
+fn scheduler_response(job: &Job, records: &mut Records, dry_run: bool) -> Result<Job, Error> {
+    match local_response(job, records, dry_run) {
+        Some(result) => result,
+        None => unsupported(job, "scheduler"),
+    }
+}
+
+///
+/// The default responses of a mock filesystem agent. Users have the
+/// home directory '/home/local_user', and projects have the directory
+/// '/projects/local_group'
+///
+fn filesystem_response(job: &Job, records: &mut Records, dry_run: bool) -> Result<Job, Error> {
+    if let Some(result) = local_response(job, records, dry_run) {
+        return result;
+    }
+
+    match job.instruction() {
+        Instruction::GetLocalHomeDir(mapping) => {
+            job.completed(format!("/home/{}", mapping.local_user()))
+        }
+        Instruction::GetLocalProjectDirs(mapping) => {
+            job.completed(vec![format!("/projects/{}", mapping.local_group())])
+        }
+        _ => unsupported(job, "filesystem"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn run(command: &str) -> Result<Job, Error> {
+        let job = Job::parse(command, false)?;
+        let peer = Peer::new(&job.destination().last(), "test");
+
+        job.put(&peer).await?.wait().await
+    }
+
+    #[tokio::test]
+    async fn test_mock_agents() {
+        assert!(parse("freeipa:account, slurm:scheduler,filesystem:filesystem").is_ok());
+        assert!(parse("freeipa:portal").is_err());
+        assert!(parse("freeipa").is_err());
+        assert!(MockAgent::new("cluster", "test", &AgentType::Instance).is_err());
+
+        host("cluster", &AgentType::Instance)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot set service details: {}", e));
+
+        let account = MockAgent::account("freeipa", "test")
+            .unwrap_or_else(|e| unreachable!("Cannot create mock: {}", e))
+            .fail_times("add_project", 1, "FreeIPA is down")
+            .register()
+            .await;
+
+        MockAgent::filesystem("filesystem", "test")
+            .unwrap_or_else(|e| unreachable!("Cannot create mock: {}", e))
+            .respond("get_local_home_dir", |job| {
+                job.completed("/scratch/alice".to_owned())
+            })
+            .register()
+            .await;
+
+        assert!(is_mock(&account).await);
+        assert_eq!(agent::account(0).await, Some(account.clone()));
+
+        let job = run("cluster.freeipa add_project project.portal")
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot run job: {}", e));
+        assert!(job.is_error());
+
+        let job = run("cluster.freeipa add_project project.portal")
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot run job: {}", e));
+
+        let mapping = job
+            .result::<ProjectMapping>()
+            .unwrap_or_else(|e| unreachable!("Cannot get result: {}", e))
+            .unwrap_or_else(|| unreachable!("No result"));
+        assert_eq!(mapping.local_group(), "project");
+
+        let job = run("cluster.freeipa add_user alice.project.portal")
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot run job: {}", e));
+
+        let mapping = job
+            .result::<UserMapping>()
+            .unwrap_or_else(|e| unreachable!("Cannot get result: {}", e))
+            .unwrap_or_else(|| unreachable!("No result"));
+        assert_eq!(mapping.local_user(), "alice.project");
+
+        let job = run(&format!(
+            "cluster.filesystem get_local_home_dir {}",
+            mapping
+        ))
+        .await
+        .unwrap_or_else(|e| unreachable!("Cannot run job: {}", e));
+
+        assert_eq!(
+            job.result::<String>()
+                .unwrap_or_else(|e| unreachable!("Cannot get result: {}", e)),
+            Some("/scratch/alice".to_owned())
+        );
+
+        let job = run("cluster.freeipa get_users project.portal")
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot run job: {}", e));
+
+        assert_eq!(
+            job.result::<Vec<UserMapping>>()
+                .unwrap_or_else(|e| unreachable!("Cannot get result: {}", e)),
+            Some(vec![mapping])
+        );
+
+        assert_eq!(calls(&account).await.len(), 4);
+
+        unregister(&account).await;
+        assert!(!is_mock(&account).await);
+    }
+}
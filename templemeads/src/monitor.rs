@@ -0,0 +1,44 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use crate::agent::Type as AgentType;
+use crate::agent_core::Config;
+use crate::error::Error;
+use crate::handler::{process_message, set_my_service_details};
+use crate::runnable::AsyncRunnable;
+
+use serde::{Deserialize, Serialize};
+
+///
+/// Run the monitor service. Monitor agents watch the health of the
+/// agents they connect to, so health checks are cascaded to their peers
+///
+pub async fn run<T>(config: Config<T>, runner: AsyncRunnable) -> Result<(), Error>
+where
+    T: Serialize + for<'de> Deserialize<'de> + Clone + std::fmt::Debug + Default,
+{
+    if config.service().name().is_empty() {
+        return Err(Error::Misconfigured("Service name is empty".to_string()));
+    }
+
+    if config.agent() != AgentType::Monitor {
+        return Err(Error::Misconfigured(
+            "Service agent is not a Monitor".to_string(),
+        ));
+    }
+
+    // pass the service details onto the handler
+    set_my_service_details(
+        &config.service().name(),
+        &config.agent(),
+        Some(runner),
+        true,
+    )
+    .await?;
+
+    // run the Monitor OpenPortal agent
+    paddington::set_handler(process_message).await?;
+    paddington::run(config.service()).await?;
+
+    Ok(())
+}
@@ -6,6 +6,7 @@ use crate::agent::Peer;
 use crate::command::Command;
 use crate::destination::Destination;
 use crate::diagnostics;
+use crate::dryrun;
 use crate::error::Error;
 use crate::grammar::{ProjectIdentifier, UserIdentifier};
 use crate::handler::invoke_notify_runner;
@@ -41,6 +42,11 @@ pub enum NotificationEvent {
     ProjectBlocked(ProjectIdentifier),
     /// All users in a project were unblocked
     ProjectUnblocked(ProjectIdentifier),
+    /// A project consumed all of its allocation, so its account was blocked
+    AllocationExhausted(ProjectIdentifier),
+    /// A project whose allocation was exhausted has allocation available
+    /// again (e.g. because it was increased), so its account was unblocked
+    AllocationRestored(ProjectIdentifier),
     /// An award (project) was created or registered in the web portal
     AwardAdded(ProjectIdentifier),
     /// An award (project) was removed from the web portal
@@ -51,6 +57,11 @@ pub enum NotificationEvent {
     AwardAccepted(ProjectIdentifier),
     /// An award was rejected by the receiving portal
     AwardRejected(ProjectIdentifier),
+    /// An alert rule was breached on the sending agent (the argument
+    /// describes the alert)
+    HealthAlert(String),
+    /// A previously raised health alert has cleared
+    HealthAlertCleared(String),
     /// Infrastructure-only: used by the bridge agent to ask the portal to forward
     /// an inner notification southbound, stripping the bridge from the path.
     /// Analogous to `Instruction::Submit` for Jobs. Not accepted by `parse()`.
@@ -75,11 +86,15 @@ impl NotificationEvent {
             "project_changed" => Ok(Self::ProjectChanged(ProjectIdentifier::parse(rest)?)),
             "project_blocked" => Ok(Self::ProjectBlocked(ProjectIdentifier::parse(rest)?)),
             "project_unblocked" => Ok(Self::ProjectUnblocked(ProjectIdentifier::parse(rest)?)),
+            "allocation_exhausted" => Ok(Self::AllocationExhausted(ProjectIdentifier::parse(rest)?)),
+            "allocation_restored" => Ok(Self::AllocationRestored(ProjectIdentifier::parse(rest)?)),
             "award_added" => Ok(Self::AwardAdded(ProjectIdentifier::parse(rest)?)),
             "award_removed" => Ok(Self::AwardRemoved(ProjectIdentifier::parse(rest)?)),
             "award_changed" => Ok(Self::AwardChanged(ProjectIdentifier::parse(rest)?)),
             "award_accepted" => Ok(Self::AwardAccepted(ProjectIdentifier::parse(rest)?)),
             "award_rejected" => Ok(Self::AwardRejected(ProjectIdentifier::parse(rest)?)),
+            "health_alert" => Ok(Self::HealthAlert(rest.to_owned())),
+            "health_alert_cleared" => Ok(Self::HealthAlertCleared(rest.to_owned())),
             "forward" => Err(Error::Parse(
                 "NotificationEvent::Forward is an infrastructure-only event and cannot be parsed from a string".to_owned(),
             )),
@@ -104,11 +119,15 @@ impl fmt::Display for NotificationEvent {
             Self::ProjectChanged(p) => write!(f, "project_changed {}", p),
             Self::ProjectBlocked(p) => write!(f, "project_blocked {}", p),
             Self::ProjectUnblocked(p) => write!(f, "project_unblocked {}", p),
+            Self::AllocationExhausted(p) => write!(f, "allocation_exhausted {}", p),
+            Self::AllocationRestored(p) => write!(f, "allocation_restored {}", p),
             Self::AwardAdded(p) => write!(f, "award_added {}", p),
             Self::AwardRemoved(p) => write!(f, "award_removed {}", p),
             Self::AwardChanged(p) => write!(f, "award_changed {}", p),
             Self::AwardAccepted(p) => write!(f, "award_accepted {}", p),
             Self::AwardRejected(p) => write!(f, "award_rejected {}", p),
+            Self::HealthAlert(a) => write!(f, "health_alert {}", a),
+            Self::HealthAlertCleared(a) => write!(f, "health_alert_cleared {}", a),
             Self::Forward(n) => write!(f, "forward [{}]", n),
         }
     }
@@ -208,6 +227,11 @@ pub type AsyncNotifyRunnable =
 /// sends a `Notification` carrying `event` to it. Fire-and-forget: failures
 /// are logged and counted but not propagated to the caller.
 pub async fn send(destination: &Destination, event: NotificationEvent) {
+    // dry runs describe the notifications they would send
+    if dryrun::record(&format!("send notification '{}' to {}", event, destination)) {
+        return;
+    }
+
     let my_name = agent::name().await;
     let notification = Notification::new(destination.clone(), event);
 
@@ -332,6 +356,21 @@ mod tests {
             "project_changed myproject.brics",
             "project_blocked myproject.brics",
             "project_unblocked myproject.brics",
+            "allocation_exhausted myproject.brics",
+            "allocation_restored myproject.brics",
+        ];
+        for case in cases {
+            #[allow(clippy::unwrap_used)]
+            let event = NotificationEvent::parse(case).unwrap();
+            assert_eq!(event.to_string(), case);
+        }
+    }
+
+    #[test]
+    fn test_health_alert_notification_events() {
+        let cases = vec![
+            "health_alert cluster: pending_jobs has been above 100 for more than 10m",
+            "health_alert_cleared cluster: pending_jobs has been above 100 for more than 10m",
         ];
         for case in cases {
             #[allow(clippy::unwrap_used)]
@@ -0,0 +1,318 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Caps on the resources that each connected portal can create
+//!
+//! A portal or cluster agent can limit the number of projects that each
+//! portal may create, the number of users in each of those projects, and
+//! the total allocation that may be granted to them. Instructions that
+//! would exceed a cap are rejected, so that a misconfigured upstream
+//! portal cannot provision resources without bound.
+
+use crate::error::Error;
+use crate::grammar::Allocation;
+
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+///
+/// A cap for each named portal, plus an optional cap for any portal
+/// that is not named (given as `*`)
+///
+#[derive(Debug, Clone, PartialEq)]
+struct Caps<T> {
+    portals: HashMap<String, T>,
+    default: Option<T>,
+}
+
+impl<T> Default for Caps<T> {
+    fn default() -> Self {
+        Self {
+            portals: HashMap::new(),
+            default: None,
+        }
+    }
+}
+
+impl<T: Clone> Caps<T> {
+    ///
+    /// Parse a comma-separated list of `portal:cap` pairs, e.g.
+    /// "waldur:100, *:10", using `parse` to parse each cap
+    ///
+    fn parse(
+        caps: &str,
+        option: &str,
+        parse: impl Fn(&str) -> Result<T, Error>,
+    ) -> Result<Self, Error> {
+        let mut parsed = Self::default();
+
+        for pair in caps.split(',') {
+            let pair = pair.trim();
+
+            if pair.is_empty() {
+                continue;
+            }
+
+            let Some((portal, cap)) = pair.split_once(':') else {
+                return Err(Error::Parse(format!(
+                    "Invalid {} '{}'. It must be 'portal:cap'",
+                    option, pair
+                )));
+            };
+
+            let portal = portal.trim();
+
+            if portal.is_empty() {
+                return Err(Error::Parse(format!(
+                    "Invalid {} '{}'. The portal name is missing",
+                    option, pair
+                )));
+            }
+
+            let cap = parse(cap.trim())?;
+
+            match portal {
+                "*" => parsed.default = Some(cap),
+                portal => {
+                    parsed.portals.insert(portal.to_owned(), cap);
+                }
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    fn get(&self, portal: &str) -> Option<T> {
+        self.portals.get(portal).or(self.default.as_ref()).cloned()
+    }
+}
+
+fn parse_count(count: &str) -> Result<usize, Error> {
+    count
+        .parse()
+        .map_err(|_| Error::Parse(format!("Invalid cap '{}'. It must be a number", count)))
+}
+
+///
+/// The caps that apply to a single portal. A cap of `None` means
+/// that there is no limit
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PortalQuota {
+    portal: String,
+    max_projects: Option<usize>,
+    max_users: Option<usize>,
+    max_allocation: Option<Allocation>,
+}
+
+impl PortalQuota {
+    pub fn portal(&self) -> String {
+        self.portal.clone()
+    }
+
+    pub fn max_projects(&self) -> Option<usize> {
+        self.max_projects
+    }
+
+    pub fn max_users(&self) -> Option<usize> {
+        self.max_users
+    }
+
+    pub fn max_allocation(&self) -> Option<Allocation> {
+        self.max_allocation.clone()
+    }
+
+    ///
+    /// Return an error if the portal would have more than its maximum
+    /// number of projects once it has `projects` projects
+    ///
+    pub fn check_projects(&self, projects: usize) -> Result<(), Error> {
+        match self.max_projects {
+            Some(max_projects) if projects > max_projects => Err(Error::Unauthorized(format!(
+                "Portal {} cannot have more than {} projects",
+                self.portal, max_projects
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    ///
+    /// Return an error if the project `project` would have more than
+    /// the maximum number of users once it has `users` users
+    ///
+    pub fn check_users(&self, project: &str, users: usize) -> Result<(), Error> {
+        match self.max_users {
+            Some(max_users) if users > max_users => Err(Error::Unauthorized(format!(
+                "Project {} of portal {} cannot have more than {} users",
+                project, self.portal, max_users
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    ///
+    /// Return an error if the total of the passed allocations, which
+    /// are all of the allocations granted to the portal's projects,
+    /// would be more than the maximum allocation. Allocations in
+    /// different units to the maximum cannot be checked, so are rejected
+    ///
+    pub fn check_allocations(&self, allocations: &[Allocation]) -> Result<(), Error> {
+        let Some(max_allocation) = &self.max_allocation else {
+            return Ok(());
+        };
+
+        let mut total = 0.0;
+
+        for allocation in allocations {
+            let Some(size) = allocation.size() else {
+                continue;
+            };
+
+            if allocation.units() != max_allocation.units() {
+                return Err(Error::Unauthorized(format!(
+                    "The allocation {} for portal {} cannot be checked against its \
+                     maximum allocation of {}, as the units are different",
+                    allocation, self.portal, max_allocation
+                )));
+            }
+
+            total += size;
+        }
+
+        match max_allocation.size() {
+            Some(max_size) if total > max_size => Err(Error::Unauthorized(format!(
+                "Portal {} cannot be granted more than {} in total (requested {} {})",
+                self.portal,
+                max_allocation,
+                total,
+                max_allocation.units().unwrap_or_default()
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+///
+/// The caps for all of the portals connected to this agent
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PortalQuotas {
+    max_projects: Caps<usize>,
+    max_users: Caps<usize>,
+    max_allocation: Caps<Allocation>,
+}
+
+impl PortalQuotas {
+    ///
+    /// Parse the caps from the `portal-max-projects`, `portal-max-users`
+    /// and `portal-max-allocation` options. Each is a comma-separated
+    /// list of `portal:cap` pairs, where a portal of `*` gives the cap for
+    /// any portal that is not listed, e.g. "waldur:100, *:10" or
+    /// "waldur:50000 NHR". An empty option means there is no cap
+    ///
+    pub fn parse(max_projects: &str, max_users: &str, max_allocation: &str) -> Result<Self, Error> {
+        Ok(Self {
+            max_projects: Caps::parse(max_projects, "portal-max-projects", parse_count)?,
+            max_users: Caps::parse(max_users, "portal-max-users", parse_count)?,
+            max_allocation: Caps::parse(
+                max_allocation,
+                "portal-max-allocation",
+                Allocation::parse,
+            )?,
+        })
+    }
+
+    ///
+    /// Return the caps that apply to the passed portal
+    ///
+    pub fn quota(&self, portal: &str) -> PortalQuota {
+        PortalQuota {
+            portal: portal.to_owned(),
+            max_projects: self.max_projects.get(portal),
+            max_users: self.max_users.get(portal),
+            max_allocation: self.max_allocation.get(portal),
+        }
+    }
+}
+
+/// The caps for the portals connected to this agent
+static PORTAL_QUOTAS: Lazy<RwLock<PortalQuotas>> =
+    Lazy::new(|| RwLock::new(PortalQuotas::default()));
+
+///
+/// Set the caps for the portals connected to this agent
+///
+pub fn set_portal_quotas(quotas: PortalQuotas) {
+    match PORTAL_QUOTAS.write() {
+        Ok(mut current) => *current = quotas,
+        Err(e) => tracing::error!("Failed to lock portal quotas: {}", e),
+    }
+}
+
+///
+/// Return the caps that apply to the passed portal
+///
+pub fn quota(portal: &str) -> PortalQuota {
+    match PORTAL_QUOTAS.read() {
+        Ok(quotas) => quotas.quota(portal),
+        Err(e) => {
+            // refuse everything rather than allow unbounded creation
+            tracing::error!("Failed to lock portal quotas: {}", e);
+            PortalQuota {
+                portal: portal.to_owned(),
+                max_projects: Some(0),
+                max_users: Some(0),
+                max_allocation: Allocation::from_size_and_units(0.0, "NHR").ok(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allocation(allocation: &str) -> Allocation {
+        Allocation::parse(allocation)
+            .unwrap_or_else(|e| unreachable!("Cannot parse allocation: {}", e))
+    }
+
+    #[test]
+    fn test_portal_quotas() {
+        let quotas = PortalQuotas::parse("waldur:2, *:1", "waldur:3", "waldur:100 NHR")
+            .unwrap_or_else(|e| unreachable!("Cannot parse portal quotas: {}", e));
+
+        let waldur = quotas.quota("waldur");
+        assert_eq!(waldur.max_projects(), Some(2));
+        assert_eq!(waldur.max_users(), Some(3));
+        assert_eq!(waldur.max_allocation(), Some(allocation("100 NHR")));
+
+        let other = quotas.quota("other");
+        assert_eq!(other.max_projects(), Some(1));
+        assert_eq!(other.max_users(), None);
+        assert_eq!(other.max_allocation(), None);
+
+        assert!(waldur.check_projects(2).is_ok());
+        assert!(waldur.check_projects(3).is_err());
+        assert!(waldur.check_users("project", 3).is_ok());
+        assert!(waldur.check_users("project", 4).is_err());
+        assert!(other.check_users("project", 1000).is_ok());
+
+        assert!(waldur
+            .check_allocations(&[allocation("60 NHR"), allocation("40 node hours")])
+            .is_ok());
+        assert!(waldur
+            .check_allocations(&[allocation("60 NHR"), allocation("41 NHR")])
+            .is_err());
+        assert!(waldur.check_allocations(&[allocation("1 GPUh")]).is_err());
+        assert!(waldur.check_allocations(&[Allocation::default()]).is_ok());
+        assert!(other.check_allocations(&[allocation("1 GPUh")]).is_ok());
+
+        assert!(PortalQuotas::parse("", "", "").is_ok_and(|q| q == PortalQuotas::default()));
+        assert!(PortalQuotas::parse("waldur", "", "").is_err());
+        assert!(PortalQuotas::parse(":1", "", "").is_err());
+        assert!(PortalQuotas::parse("waldur:many", "", "").is_err());
+        assert!(PortalQuotas::parse("", "", "waldur:100").is_err());
+    }
+}
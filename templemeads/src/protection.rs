@@ -0,0 +1,254 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! The policy used by account agents to decide which users and groups
+//! are protected, i.e. must never be created, modified or removed by
+//! OpenPortal, together with the status returned when an agent is
+//! asked whether (and why) an identity is protected
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+use wildmatch::WildMatch;
+
+use crate::error::Error;
+use crate::grammar::NamedType;
+
+impl NamedType for ProtectionStatus {
+    fn type_name() -> &'static str {
+        "ProtectionStatus"
+    }
+}
+
+///
+/// A policy of protected account names and numeric ids. Names
+/// may contain the wildcards `*` and `?`, e.g. `svc-*`, while
+/// ids are given as inclusive ranges, e.g. `0-999`
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProtectionPolicy {
+    names: Vec<String>,
+    patterns: Vec<String>,
+    id_ranges: Vec<(u32, u32)>,
+}
+
+impl ProtectionPolicy {
+    ///
+    /// Parse the policy from a comma-separated list of names and
+    /// patterns, plus a comma-separated list of ids and id ranges,
+    /// e.g. `parse("root,admin,svc-*", "0-999,65534")`
+    ///
+    pub fn parse(names: &str, ids: &str) -> Result<Self, Error> {
+        let mut policy = ProtectionPolicy::default();
+
+        for name in names.split(',').map(|n| n.trim()).filter(|n| !n.is_empty()) {
+            if name.contains('*') || name.contains('?') {
+                policy.patterns.push(name.to_string());
+            } else {
+                policy.names.push(name.to_string());
+            }
+        }
+
+        for range in ids.split(',').map(|r| r.trim()).filter(|r| !r.is_empty()) {
+            let parse_id = |id: &str| {
+                id.trim().parse::<u32>().map_err(|e| {
+                    Error::Parse(format!("Invalid id '{}' in range '{}': {}", id, range, e))
+                })
+            };
+
+            let (start, end) = match range.split_once('-') {
+                Some((start, end)) => (parse_id(start)?, parse_id(end)?),
+                None => {
+                    let id = parse_id(range)?;
+                    (id, id)
+                }
+            };
+
+            if start > end {
+                return Err(Error::Parse(format!(
+                    "Invalid id range '{}': the start is greater than the end",
+                    range
+                )));
+            }
+
+            policy.id_ranges.push((start, end));
+        }
+
+        Ok(policy)
+    }
+
+    ///
+    /// Return whether or not this policy protects nothing
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.patterns.is_empty() && self.id_ranges.is_empty()
+    }
+
+    ///
+    /// Return the reason why the account with the passed name and
+    /// (if known) numeric id is protected by this policy, or None
+    /// if it is not protected
+    ///
+    pub fn reason(&self, name: &str, id: Option<u32>) -> Option<String> {
+        if self.names.iter().any(|n| n == name) {
+            return Some(format!("'{}' is an explicitly protected name", name));
+        }
+
+        if let Some(pattern) = self
+            .patterns
+            .iter()
+            .find(|p| WildMatch::new(p).matches(name))
+        {
+            return Some(format!(
+                "'{}' matches the protected pattern '{}'",
+                name, pattern
+            ));
+        }
+
+        if let Some(id) = id {
+            if let Some((start, end)) = self
+                .id_ranges
+                .iter()
+                .find(|(start, end)| id >= *start && id <= *end)
+            {
+                return Some(format!(
+                    "id {} is in the protected range {}-{}",
+                    id, start, end
+                ));
+            }
+        }
+
+        None
+    }
+
+    ///
+    /// Return whether or not the account with the passed name and
+    /// (if known) numeric id is protected by this policy
+    ///
+    pub fn is_protected(&self, name: &str, id: Option<u32>) -> bool {
+        self.reason(name, id).is_some()
+    }
+}
+
+impl std::fmt::Display for ProtectionPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let names = self
+            .names
+            .iter()
+            .chain(self.patterns.iter())
+            .cloned()
+            .collect::<Vec<String>>();
+
+        let ranges = self
+            .id_ranges
+            .iter()
+            .map(|(start, end)| match start == end {
+                true => start.to_string(),
+                false => format!("{}-{}", start, end),
+            })
+            .collect::<Vec<String>>();
+
+        write!(f, "names=[{}] ids=[{}]", names.join(","), ranges.join(","))
+    }
+}
+
+/// Whether or not an identity is protected in an account agent, and why
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, TS)]
+#[ts(export)]
+pub struct ProtectionStatus {
+    /// The identity (user or project identifier) that was checked
+    pub identity: String,
+    /// The local name of the account in the account agent, if it exists
+    pub local_name: Option<String>,
+    /// Whether or not the identity is protected
+    pub protected: bool,
+    /// Why the identity is protected
+    pub reason: Option<String>,
+}
+
+impl ProtectionStatus {
+    ///
+    /// Return the status of an identity that is not protected
+    ///
+    pub fn unprotected(identity: &str, local_name: Option<&str>) -> Self {
+        Self {
+            identity: identity.to_string(),
+            local_name: local_name.map(|n| n.to_string()),
+            protected: false,
+            reason: None,
+        }
+    }
+
+    ///
+    /// Return the status of an identity that is protected for the
+    /// passed reason
+    ///
+    pub fn protected(identity: &str, local_name: Option<&str>, reason: &str) -> Self {
+        Self {
+            identity: identity.to_string(),
+            local_name: local_name.map(|n| n.to_string()),
+            protected: true,
+            reason: Some(reason.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for ProtectionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match &self.local_name {
+            Some(local_name) => format!("{} ({})", self.identity, local_name),
+            None => self.identity.clone(),
+        };
+
+        match (&self.protected, &self.reason) {
+            (true, Some(reason)) => write!(f, "{} is protected: {}", name, reason),
+            (true, None) => write!(f, "{} is protected", name),
+            (false, _) => write!(f, "{} is not protected", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy() {
+        #[allow(clippy::unwrap_used)]
+        let policy = ProtectionPolicy::parse("root, admin,svc-*,tmp?", "0-999, 65534").unwrap();
+
+        assert!(!policy.is_empty());
+        assert!(policy.is_protected("root", None));
+        assert!(policy.is_protected("admin", Some(150000)));
+        assert!(policy.is_protected("svc-backup", None));
+        assert!(policy.is_protected("tmp1", None));
+        assert!(!policy.is_protected("tmp12", None));
+        assert!(policy.is_protected("alice", Some(500)));
+        assert!(policy.is_protected("alice", Some(65534)));
+        assert!(!policy.is_protected("alice", Some(1000)));
+        assert!(!policy.is_protected("alice", None));
+
+        assert_eq!(
+            policy.reason("svc-backup", None),
+            Some("'svc-backup' matches the protected pattern 'svc-*'".to_string())
+        );
+
+        assert_eq!(
+            policy.reason("alice", Some(0)),
+            Some("id 0 is in the protected range 0-999".to_string())
+        );
+
+        assert_eq!(
+            policy.to_string(),
+            "names=[root,admin,svc-*,tmp?] ids=[0-999,65534]"
+        );
+
+        #[allow(clippy::unwrap_used)]
+        let policy = ProtectionPolicy::parse("", "").unwrap();
+        assert!(policy.is_empty());
+        assert!(!policy.is_protected("root", Some(0)));
+
+        assert!(ProtectionPolicy::parse("", "1000-999").is_err());
+        assert!(ProtectionPolicy::parse("", "abc").is_err());
+        assert!(ProtectionPolicy::parse("", "-5").is_err());
+    }
+}
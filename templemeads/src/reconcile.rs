@@ -0,0 +1,175 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! The drift between the accounts and associations that a scheduler
+//! (or account agent) holds for a project and the mappings that are
+//! expected, so that problems are found before a user's job is rejected
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::grammar::{NamedType, ProjectIdentifier};
+
+impl NamedType for ReconciliationReport {
+    fn type_name() -> &'static str {
+        "ReconciliationReport"
+    }
+}
+
+impl NamedType for Vec<ReconciliationReport> {
+    fn type_name() -> &'static str {
+        "Vec<ReconciliationReport>"
+    }
+}
+
+/// The result of reconciling a single project against the scheduler
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, TS)]
+#[ts(export)]
+pub struct ReconciliationReport {
+    /// The project that was reconciled
+    #[ts(as = "String")]
+    pub project: ProjectIdentifier,
+    /// When the project was reconciled
+    pub generated_at: DateTime<Utc>,
+    /// Whether the project's account was missing from the scheduler
+    pub missing_account: bool,
+    /// The local usernames of mapped users who have no association
+    /// with the project's account
+    pub missing_users: Vec<String>,
+    /// The local usernames of users who are associated with the
+    /// project's account, but who are not mapped to the project.
+    /// These are only reported, and are never removed
+    pub unexpected_users: Vec<String>,
+    /// The local usernames of mapped users whose home directory in the
+    /// account agent differs from the one expected by the filesystem
+    #[serde(default)]
+    pub wrong_homedirs: Vec<String>,
+    /// Whether missing accounts and associations were repaired
+    pub repaired: bool,
+}
+
+impl std::fmt::Display for ReconciliationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{} at {}", self.project, self.generated_at)?;
+
+        if self.missing_account {
+            writeln!(f, "  missing account")?;
+        }
+
+        if !self.missing_users.is_empty() {
+            writeln!(f, "  missing users: {}", self.missing_users.join(", "))?;
+        }
+
+        if !self.unexpected_users.is_empty() {
+            writeln!(
+                f,
+                "  unexpected users: {}",
+                self.unexpected_users.join(", ")
+            )?;
+        }
+
+        if !self.wrong_homedirs.is_empty() {
+            writeln!(
+                f,
+                "  wrong home directories: {}",
+                self.wrong_homedirs.join(", ")
+            )?;
+        }
+
+        match (self.has_drift(), self.repaired) {
+            (false, _) => write!(f, "No drift"),
+            (true, true) => write!(f, "Drift found - repaired"),
+            (true, false) => write!(f, "Drift found - not repaired"),
+        }
+    }
+}
+
+impl ReconciliationReport {
+    pub fn new(project: &ProjectIdentifier) -> Self {
+        Self {
+            project: project.clone(),
+            generated_at: Utc::now(),
+            missing_account: false,
+            missing_users: Vec::new(),
+            unexpected_users: Vec::new(),
+            wrong_homedirs: Vec::new(),
+            repaired: false,
+        }
+    }
+
+    ///
+    /// Return whether the scheduler differs in any way from the mappings
+    ///
+    pub fn has_drift(&self) -> bool {
+        self.missing_account
+            || !self.missing_users.is_empty()
+            || !self.unexpected_users.is_empty()
+            || !self.wrong_homedirs.is_empty()
+    }
+
+    ///
+    /// Merge the passed report (e.g. from the account agent) for the same
+    /// project into this report. The merged report only counts as repaired
+    /// if the drift in both reports was repaired
+    ///
+    pub fn merge(&mut self, other: &ReconciliationReport) {
+        let repaired =
+            (self.repaired || !self.has_drift()) && (other.repaired || !other.has_drift());
+
+        self.missing_account |= other.missing_account;
+
+        for (users, other_users) in [
+            (&mut self.missing_users, &other.missing_users),
+            (&mut self.unexpected_users, &other.unexpected_users),
+            (&mut self.wrong_homedirs, &other.wrong_homedirs),
+        ] {
+            users.extend(other_users.iter().cloned());
+            users.sort();
+            users.dedup();
+        }
+
+        self.repaired = repaired && self.has_drift();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        #[allow(clippy::unwrap_used)]
+        let project = ProjectIdentifier::parse("project.portal").unwrap();
+
+        let mut scheduler = ReconciliationReport::new(&project);
+        scheduler.missing_users = vec!["bob".to_string()];
+        scheduler.repaired = true;
+
+        let mut account = ReconciliationReport::new(&project);
+        account.missing_users = vec!["alice".to_string(), "bob".to_string()];
+        account.wrong_homedirs = vec!["alice".to_string()];
+
+        let mut report = scheduler.clone();
+        report.merge(&account);
+        assert_eq!(report.missing_users, vec!["alice", "bob"]);
+        assert_eq!(report.wrong_homedirs, vec!["alice"]);
+        assert!(report.has_drift());
+        assert!(!report.repaired);
+
+        account.repaired = true;
+        let mut report = scheduler.clone();
+        report.merge(&account);
+        assert!(report.repaired);
+
+        // merging a report without drift keeps the repair status
+        let mut report = scheduler.clone();
+        report.merge(&ReconciliationReport::new(&project));
+        assert!(report.repaired);
+
+        let mut report = ReconciliationReport::new(&project);
+        report.merge(&ReconciliationReport::new(&project));
+        assert!(!report.has_drift());
+        assert!(!report.repaired);
+    }
+}
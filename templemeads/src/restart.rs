@@ -7,7 +7,10 @@
 
 use crate::agent;
 use crate::command::Command;
+use crate::config;
 use crate::diagnostics;
+use crate::maintenance;
+use crate::systemd;
 
 ///
 /// Perform a soft restart by disconnecting all peers and clearing boards
@@ -17,7 +20,7 @@ use crate::diagnostics;
 /// - Disconnects from each peer
 /// - Clears all job boards (cancels in-flight jobs)
 ///
-async fn perform_soft_restart() -> Result<(), anyhow::Error> {
+pub(crate) async fn perform_soft_restart() -> Result<(), anyhow::Error> {
     // Acquire the RAII guard to block new connections
     // The guard will automatically clear the flag when this function exits (even on panic)
     let _guard = paddington::SoftRestartGuard::new();
@@ -152,27 +155,71 @@ async fn perform_soft_restart() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+///
+/// Perform a restart of the passed type ("soft", "reload" or "hard")
+/// straight away. A hard restart does not return
+///
+pub(crate) async fn restart_now(restart_type: &str) -> Result<String, anyhow::Error> {
+    match restart_type {
+        "soft" => {
+            tracing::warn!("Performing soft restart - disconnecting all peers and clearing boards");
+            match perform_soft_restart().await {
+                Ok(_) => {
+                    tracing::info!("Soft restart completed successfully");
+                    Ok("soft restart complete".to_owned())
+                }
+                Err(e) => {
+                    tracing::error!("Soft restart failed: {} - falling back to hard restart", e);
+                    tracing::warn!(
+                        "Performing hard restart due to soft restart failure - terminating process"
+                    );
+                    // Exit the process - supervisor should restart it
+                    std::process::exit(1);
+                }
+            }
+        }
+        "reload" => {
+            tracing::warn!("Reloading config file");
+            config::reload().await?;
+            Ok("reload restart complete".to_owned())
+        }
+        "hard" => {
+            tracing::warn!("Performing hard restart - terminating process");
+            systemd::notify("STOPPING=1");
+            // Exit the process - supervisor should restart it
+            std::process::exit(0);
+        }
+        _ => {
+            tracing::error!("Unknown restart type: {}", restart_type);
+            Err(anyhow::anyhow!("Unknown restart type: {}", restart_type))
+        }
+    }
+}
+
 ///
 /// Handle a restart request from another agent
 ///
 /// This function:
 /// - Checks if this agent is the destination for the restart
-/// - If destination matches, performs the restart (soft or hard)
+/// - If destination matches, performs the restart (soft or hard), or
+///   schedules it for a given time or the next maintenance window
 /// - If destination doesn't match, forwards to the next peer in the path
 ///
-/// Fire-and-forget: No acknowledgment is sent back to the requester
+/// Fire-and-forget: No acknowledgment is sent back to the requester.
+/// A description of what was done is returned to the caller
 ///
 /// Parameters:
 /// - `sender`: The agent that requested the restart
 /// - `zone`: The zone of the sender
-/// - `restart_type`: Type of restart ("soft", "hard", etc.)
+/// - `restart_type`: Type of restart ("soft", "hard", etc.), optionally
+///   followed by "@HH:MM" or "@now", or "cancel"
 /// - `destination`: Dot-separated path (e.g., "brics.aip2.clusters"), empty means restart self
 ///
 pub async fn handle_restart_request(
     sender: &str,
     restart_type: &str,
     destination: &str,
-) -> Result<(), anyhow::Error> {
+) -> Result<String, anyhow::Error> {
     let my_name = agent::name().await;
     let my_type = agent::my_agent_type().await;
 
@@ -188,7 +235,7 @@ pub async fn handle_restart_request(
                         "Ignoring restart request from portal {} - portals do not restart other portals",
                         sender
                     );
-                    return Ok(());
+                    return Ok("Ignored restart request from a portal".to_owned());
                 }
             }
         }
@@ -219,39 +266,46 @@ pub async fn handle_restart_request(
     };
 
     if is_target {
-        // We are the target - perform the restart
+        // We are the target - perform or schedule the restart
         tracing::warn!(
             "Received restart command from {} (type: {}) - this agent is the target",
             sender,
             restart_type
         );
 
-        match restart_type {
-            "soft" => {
-                tracing::warn!(
-                    "Performing soft restart - disconnecting all peers and clearing boards"
-                );
-                match perform_soft_restart().await {
-                    Ok(_) => {
-                        tracing::info!("Soft restart completed successfully");
-                        Ok(())
-                    }
-                    Err(e) => {
-                        tracing::error!(
-                            "Soft restart failed: {} - falling back to hard restart",
-                            e
-                        );
-                        tracing::warn!("Performing hard restart due to soft restart failure - terminating process");
-                        // Exit the process - supervisor should restart it
-                        std::process::exit(1);
-                    }
-                }
-            }
-            "hard" => {
-                tracing::warn!("Performing hard restart - terminating process");
-                // Exit the process - supervisor should restart it
-                std::process::exit(0);
+        let (restart_type, time) = match restart_type.split_once('@') {
+            Some((restart_type, time)) => (restart_type.trim(), Some(time.trim())),
+            None => (restart_type.trim(), None),
+        };
+
+        match (restart_type, time) {
+            ("cancel", _) => match maintenance::cancel()? {
+                Some(cancelled) => Ok(format!("Cancelled the {}", cancelled)),
+                None => Ok("No restart is scheduled".to_owned()),
+            },
+            ("reload", _) | (_, Some("now")) => restart_now(restart_type).await,
+            ("soft" | "hard", Some(time)) => {
+                // restart at the requested time, whether or not it is
+                // within a maintenance window
+                let at = maintenance::next_time_of_day(maintenance::parse_time(time)?);
+                maintenance::schedule(restart_type, at)?;
+                Ok(format!(
+                    "{} restart scheduled for {}",
+                    restart_type,
+                    at.format("%Y-%m-%d %H:%M")
+                ))
             }
+            ("soft" | "hard", None) => match maintenance::next_window() {
+                Some(at) => {
+                    maintenance::schedule(restart_type, at)?;
+                    Ok(format!(
+                        "Outside the maintenance windows - {} restart scheduled for {}",
+                        restart_type,
+                        at.format("%Y-%m-%d %H:%M")
+                    ))
+                }
+                None => restart_now(restart_type).await,
+            },
             _ => {
                 tracing::error!("Unknown restart type: {}", restart_type);
                 Err(anyhow::anyhow!("Unknown restart type: {}", restart_type))
@@ -334,7 +388,7 @@ pub async fn handle_restart_request(
                 next_peer_name,
                 next_peer.zone()
             );
-            Ok(())
+            Ok(format!("Forwarded restart to {}", next_peer_name))
         } else {
             let error_msg = if let Some(zone) = zone_filter {
                 format!(
@@ -0,0 +1,228 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use crate::agent::Peer;
+use crate::error::Error;
+use crate::grammar::Instruction;
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+///
+/// The role of a peer, which limits the instructions that it can send
+/// to this agent. Each role can do everything that the roles before it
+/// can do
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Role {
+    /// Can only send instructions that read information
+    ReadOnly,
+    /// Can also send instructions that manage projects, users and
+    /// their resources
+    Operator,
+    /// Can send any instruction, including those that repair or purge
+    Admin,
+}
+
+impl Role {
+    pub fn parse(role: &str) -> Result<Self, Error> {
+        match role.trim().to_lowercase().as_str() {
+            "read-only" | "readonly" => Ok(Role::ReadOnly),
+            "operator" => Ok(Role::Operator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(Error::Parse(format!(
+                "Invalid role '{}'. It must be read-only, operator or admin",
+                role
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Role::ReadOnly => write!(f, "read-only"),
+            Role::Operator => write!(f, "operator"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+///
+/// The roles assigned to peers, together with the role of any peer
+/// that is not assigned one
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerRoles {
+    roles: HashMap<String, Role>,
+    default: Role,
+}
+
+impl Default for PeerRoles {
+    fn default() -> Self {
+        Self {
+            roles: HashMap::new(),
+            default: Role::Admin,
+        }
+    }
+}
+
+impl PeerRoles {
+    ///
+    /// Parse the roles from the `peer-roles` option, a comma-separated
+    /// list of `peer:role` pairs, e.g. "portal:admin, waldur:operator",
+    /// and the `default-role` option. An empty default means admin
+    ///
+    pub fn parse(peer_roles: &str, default: &str) -> Result<Self, Error> {
+        let mut roles = HashMap::new();
+
+        for pair in peer_roles.split(',') {
+            let pair = pair.trim();
+
+            if pair.is_empty() {
+                continue;
+            }
+
+            let Some((peer, role)) = pair.split_once(':') else {
+                return Err(Error::Parse(format!(
+                    "Invalid peer role '{}'. It must be 'peer:role'",
+                    pair
+                )));
+            };
+
+            let peer = peer.trim();
+
+            if peer.is_empty() {
+                return Err(Error::Parse(format!(
+                    "Invalid peer role '{}'. The peer name is missing",
+                    pair
+                )));
+            }
+
+            roles.insert(peer.to_owned(), Role::parse(role)?);
+        }
+
+        let default = match default.trim() {
+            "" => Role::Admin,
+            default => Role::parse(default)?,
+        };
+
+        Ok(Self { roles, default })
+    }
+
+    ///
+    /// Return the role of the passed peer
+    ///
+    pub fn role(&self, peer: &Peer) -> Role {
+        self.roles.get(peer.name()).copied().unwrap_or(self.default)
+    }
+}
+
+/// The roles assigned to the peers of this agent
+static PEER_ROLES: Lazy<RwLock<PeerRoles>> = Lazy::new(|| RwLock::new(PeerRoles::default()));
+
+///
+/// Set the roles assigned to the peers of this agent
+///
+pub fn set_peer_roles(roles: PeerRoles) {
+    match PEER_ROLES.write() {
+        Ok(mut current) => *current = roles,
+        Err(e) => tracing::error!("Failed to lock peer roles: {}", e),
+    }
+}
+
+///
+/// Return the role of the passed peer
+///
+pub fn role(peer: &Peer) -> Role {
+    match PEER_ROLES.read() {
+        Ok(roles) => roles.role(peer),
+        Err(e) => {
+            tracing::error!("Failed to lock peer roles: {}", e);
+            Role::ReadOnly
+        }
+    }
+}
+
+///
+/// Return an error if the passed peer does not have the role needed
+/// to send the passed instruction to this agent
+///
+pub fn authorize(peer: &Peer, instruction: &Instruction) -> Result<(), Error> {
+    let role = role(peer);
+    let required = instruction.required_role();
+
+    if role < required {
+        return Err(Error::Unauthorized(format!(
+            "{} has the {} role, but {} needs the {} role",
+            peer.name(),
+            role,
+            instruction.command(),
+            required
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peer_roles() {
+        let roles = PeerRoles::parse("portal:admin, waldur:operator,metrics:read-only", "")
+            .unwrap_or_else(|e| unreachable!("Cannot parse peer roles: {}", e));
+
+        assert_eq!(roles.role(&Peer::new("portal", "default")), Role::Admin);
+        assert_eq!(roles.role(&Peer::new("waldur", "default")), Role::Operator);
+        assert_eq!(roles.role(&Peer::new("metrics", "default")), Role::ReadOnly);
+        assert_eq!(roles.role(&Peer::new("other", "default")), Role::Admin);
+
+        let roles = PeerRoles::parse("", "read-only")
+            .unwrap_or_else(|e| unreachable!("Cannot parse peer roles: {}", e));
+
+        assert_eq!(roles.role(&Peer::new("other", "default")), Role::ReadOnly);
+
+        assert!(PeerRoles::parse("portal", "").is_err());
+        assert!(PeerRoles::parse("portal:owner", "").is_err());
+        assert!(PeerRoles::parse(":admin", "").is_err());
+        assert!(PeerRoles::parse("", "owner").is_err());
+    }
+
+    #[test]
+    fn test_required_roles() {
+        let parse = |s: &str| {
+            Instruction::parse(s).unwrap_or_else(|e| unreachable!("Cannot parse {}: {}", s, e))
+        };
+
+        assert_eq!(
+            parse("get_project project.portal").required_role(),
+            Role::ReadOnly
+        );
+        assert_eq!(
+            parse("add_user user.project.portal").required_role(),
+            Role::Operator
+        );
+        assert_eq!(
+            parse("reconcile project.portal").required_role(),
+            Role::ReadOnly
+        );
+        assert_eq!(
+            parse("reconcile project.portal repair").required_role(),
+            Role::Admin
+        );
+        assert_eq!(
+            parse("submit portal.provider.cluster add_user user.project.portal").required_role(),
+            Role::Operator
+        );
+
+        assert!(!parse("get_project project.portal").is_mutating());
+        assert!(!parse("reconcile project.portal").is_mutating());
+        assert!(parse("reconcile project.portal repair").is_mutating());
+        assert!(parse("add_user user.project.portal").is_mutating());
+        assert!(parse("submit portal.provider.cluster add_user user.project.portal").is_mutating());
+    }
+}
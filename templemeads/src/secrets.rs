@@ -0,0 +1,461 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Secrets fetched from external secret stores
+//!
+//! Rather than storing a secret (encrypted) in the config file, an agent
+//! can fetch it at startup from one of these sources, set by the
+//! `secret-sources` option as a comma-separated list of `key=source`:
+//!
+//! - `vault:<path>#<field>` - a field of a HashiCorp Vault KV secret, read
+//!   from `vault-addr` (or VAULT_ADDR) using the VAULT_TOKEN environment
+//!   variable or the `vault-token` secret
+//! - `aws-sm:<secret-id>[#<field>]` - an AWS Secrets Manager secret (or a
+//!   field of a JSON secret), read using the `aws` command line tool
+//! - `systemd:<name>` - a systemd credential, read from
+//!   `$CREDENTIALS_DIRECTORY/<name>`
+//! - `file:<path>` - the contents of a file, e.g. a mounted Kubernetes secret
+//!
+//! The secrets are fetched again every `secret-refresh-interval`, so that
+//! `Config::secret` always returns the latest value of a rotated secret.
+
+use crate::error::Error;
+use crate::exec;
+use crate::health;
+
+use once_cell::sync::Lazy;
+use secrecy::{ExposeSecret, SecretString};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Timeout (in seconds) when fetching a secret
+const FETCH_TIMEOUT: u64 = 30;
+
+///
+/// Where a secret is fetched from
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum SecretSource {
+    Vault { path: String, field: String },
+    AwsSecretsManager { id: String, field: Option<String> },
+    Systemd { name: String },
+    File { path: String },
+}
+
+impl std::fmt::Display for SecretSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SecretSource::Vault { path, field } => write!(f, "vault:{}#{}", path, field),
+            SecretSource::AwsSecretsManager { id, field } => match field {
+                Some(field) => write!(f, "aws-sm:{}#{}", id, field),
+                None => write!(f, "aws-sm:{}", id),
+            },
+            SecretSource::Systemd { name } => write!(f, "systemd:{}", name),
+            SecretSource::File { path } => write!(f, "file:{}", path),
+        }
+    }
+}
+
+impl SecretSource {
+    ///
+    /// Parse a source, e.g. "vault:secret/data/openportal#password"
+    ///
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        let source = source.trim();
+
+        let (kind, location) = source.split_once(':').ok_or_else(|| {
+            Error::Parse(format!(
+                "Invalid secret source '{}' - expected 'kind:location'",
+                source
+            ))
+        })?;
+
+        let location = location.trim();
+
+        if location.is_empty() {
+            return Err(Error::Parse(format!(
+                "Invalid secret source '{}' - no location given",
+                source
+            )));
+        }
+
+        let (location, field) = match location.split_once('#') {
+            Some((location, field)) => (location.trim(), Some(field.trim().to_owned())),
+            None => (location, None),
+        };
+
+        match kind.trim() {
+            "vault" => match field {
+                Some(field) if !field.is_empty() => Ok(SecretSource::Vault {
+                    path: location.trim_matches('/').to_owned(),
+                    field,
+                }),
+                _ => Err(Error::Parse(format!(
+                    "Invalid secret source '{}' - Vault secrets need a field, e.g. 'vault:<path>#<field>'",
+                    source
+                ))),
+            },
+            "aws-sm" => Ok(SecretSource::AwsSecretsManager {
+                id: location.to_owned(),
+                field: field.filter(|field| !field.is_empty()),
+            }),
+            "systemd" => Ok(SecretSource::Systemd {
+                name: location.to_owned(),
+            }),
+            "file" => Ok(SecretSource::File {
+                path: location.to_owned(),
+            }),
+            kind => Err(Error::Parse(format!(
+                "Unknown secret store '{}' in '{}' - use vault, aws-sm, systemd or file",
+                kind, source
+            ))),
+        }
+    }
+}
+
+///
+/// Parse the comma-separated list of secret sources, e.g.
+/// "freeipa-password=vault:secret/data/freeipa#password", returning
+/// the key and source of each secret
+///
+pub fn parse_sources(sources: &str) -> Result<Vec<(String, SecretSource)>, Error> {
+    sources
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let (key, source) = entry.split_once('=').ok_or_else(|| {
+                Error::Parse(format!(
+                    "Invalid secret source '{}' - expected 'key=source'",
+                    entry.trim()
+                ))
+            })?;
+
+            Ok((key.trim().to_owned(), SecretSource::parse(source)?))
+        })
+        .collect()
+}
+
+///
+/// How to connect to the external secret stores
+///
+#[derive(Debug)]
+pub struct SecretStores {
+    /// Address of the Vault server, e.g. "https://vault:8200"
+    pub vault_addr: Option<String>,
+    /// Token used to authenticate to Vault
+    pub vault_token: Option<SecretString>,
+    /// The AWS command line tool
+    pub aws_command: String,
+}
+
+///
+/// The secrets fetched from external secret stores, keyed by name
+///
+static SECRETS: Lazy<std::sync::RwLock<HashMap<String, SecretString>>> =
+    Lazy::new(|| std::sync::RwLock::new(HashMap::new()));
+
+///
+/// Return the latest value of the named secret, if it was fetched
+/// from an external secret store
+///
+pub fn get(key: &str) -> Option<SecretString> {
+    match SECRETS.read() {
+        Ok(secrets) => secrets
+            .get(key)
+            .map(|secret| SecretString::from(secret.expose_secret().to_owned())),
+        Err(e) => {
+            tracing::error!("Could not read the fetched secrets: {}", e);
+            None
+        }
+    }
+}
+
+///
+/// Store the value of the secret, returning whether it has changed
+///
+fn set(key: &str, value: String) -> bool {
+    // make sure that the secret never appears in logs or errors
+    paddington::redact::register_secret(&value);
+
+    match SECRETS.write() {
+        Ok(mut secrets) => {
+            let changed = secrets
+                .get(key)
+                .map(|secret| secret.expose_secret() != value)
+                .unwrap_or(true);
+
+            secrets.insert(key.to_owned(), SecretString::from(value));
+            changed
+        }
+        Err(e) => {
+            tracing::error!("Could not store the fetched secret: {}", e);
+            false
+        }
+    }
+}
+
+///
+/// Read a secret from the passed file, removing the trailing newline
+///
+async fn read_file(path: &std::path::Path) -> Result<String, Error> {
+    let value = tokio::fs::read_to_string(path).await.map_err(|e| {
+        Error::Unavailable(format!(
+            "Could not read secret from {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+
+    Ok(value.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+///
+/// Fetch the field of a Vault KV secret. Both version 1 and 2 of the
+/// KV secrets engine are supported
+///
+async fn fetch_vault(stores: &SecretStores, path: &str, field: &str) -> Result<String, Error> {
+    let addr = stores.vault_addr.as_deref().ok_or_else(|| {
+        Error::Misconfigured("No Vault address - set vault-addr or VAULT_ADDR".to_owned())
+    })?;
+
+    let token = stores.vault_token.as_ref().ok_or_else(|| {
+        Error::Misconfigured(
+            "No Vault token - set VAULT_TOKEN or the vault-token secret".to_owned(),
+        )
+    })?;
+
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(FETCH_TIMEOUT))
+        .build()
+        .map_err(|e| Error::Unavailable(format!("Could not create Vault client: {}", e)))?;
+
+    let url = format!("{}/v1/{}", addr.trim_end_matches('/'), path);
+
+    let response = client
+        .get(&url)
+        .header("X-Vault-Token", token.expose_secret())
+        .send()
+        .await
+        .map_err(|e| Error::Unavailable(format!("Could not contact Vault at {}: {}", url, e)))?;
+
+    if !response.status().is_success() {
+        return Err(Error::Unavailable(format!(
+            "Vault returned {} for {}",
+            response.status(),
+            path
+        )));
+    }
+
+    let body: serde_json::Value = response.json().await.map_err(|e| {
+        Error::Parse(format!(
+            "Could not parse Vault response for {}: {}",
+            path, e
+        ))
+    })?;
+
+    // KV version 2 nests the secret in data.data
+    let data = match body["data"].get("data") {
+        Some(data) if data.is_object() => data,
+        _ => &body["data"],
+    };
+
+    match data.get(field).and_then(|value| value.as_str()) {
+        Some(value) => Ok(value.to_owned()),
+        None => Err(Error::NotFound(format!(
+            "Vault secret {} has no field '{}'",
+            path, field
+        ))),
+    }
+}
+
+///
+/// Fetch a secret (or a field of a JSON secret) from AWS Secrets Manager
+///
+async fn fetch_aws(
+    stores: &SecretStores,
+    id: &str,
+    field: &Option<String>,
+) -> Result<String, Error> {
+    let output = exec::Command::new(&stores.aws_command)
+        .args([
+            "secretsmanager",
+            "get-secret-value",
+            "--secret-id",
+            id,
+            "--query",
+            "SecretString",
+            "--output",
+            "text",
+        ])
+        .timeout(std::time::Duration::from_secs(FETCH_TIMEOUT))
+        .run()
+        .await
+        .map_err(|failure| match failure.reason {
+            exec::Reason::Timeout(_) => {
+                Error::Timeout(format!("Timed out fetching AWS secret {}", id))
+            }
+            _ => Error::Unavailable(format!("Could not fetch AWS secret {}: {}", id, failure)),
+        })?;
+
+    let value = output.trim_end_matches(['\r', '\n']).to_owned();
+
+    match field {
+        Some(field) => {
+            let secret: serde_json::Value = serde_json::from_str(&value)
+                .map_err(|_| Error::Parse(format!("AWS secret {} is not a JSON object", id)))?;
+
+            match secret.get(field).and_then(|value| value.as_str()) {
+                Some(value) => Ok(value.to_owned()),
+                None => Err(Error::NotFound(format!(
+                    "AWS secret {} has no field '{}'",
+                    id, field
+                ))),
+            }
+        }
+        None => Ok(value),
+    }
+}
+
+///
+/// Fetch the secret from its source
+///
+async fn fetch(stores: &SecretStores, source: &SecretSource) -> Result<String, Error> {
+    match source {
+        SecretSource::Vault { path, field } => fetch_vault(stores, path, field).await,
+        SecretSource::AwsSecretsManager { id, field } => fetch_aws(stores, id, field).await,
+        SecretSource::Systemd { name } => match std::env::var_os("CREDENTIALS_DIRECTORY") {
+            Some(directory) => read_file(&std::path::Path::new(&directory).join(name)).await,
+            None => Err(Error::Misconfigured(format!(
+                "Cannot read systemd credential '{}' as CREDENTIALS_DIRECTORY is not set",
+                name
+            ))),
+        },
+        SecretSource::File { path } => read_file(std::path::Path::new(path)).await,
+    }
+}
+
+///
+/// Fetch the secrets from their sources, returning an error if any
+/// cannot be fetched. They are then fetched again every `refresh`
+/// seconds (0 disables this). If `restart_on_rotation` is set then
+/// the agent exits when a secret changes, so that its supervisor can
+/// restart it with the new secret, else a health warning is raised
+///
+pub async fn load(
+    sources: Vec<(String, SecretSource)>,
+    stores: SecretStores,
+    refresh: u64,
+    restart_on_rotation: bool,
+) -> Result<(), Error> {
+    if sources.is_empty() {
+        return Ok(());
+    }
+
+    for (key, source) in &sources {
+        let value = fetch(&stores, source).await.map_err(|e| {
+            Error::Misconfigured(format!(
+                "Could not fetch secret '{}' from {}: {}",
+                key, source, e
+            ))
+        })?;
+
+        set(key, value);
+        health::set_dependency(&format!("secret:{}", key), true, &source.to_string());
+        tracing::info!("Fetched secret '{}' from {}", key, source);
+    }
+
+    if refresh == 0 {
+        return Ok(());
+    }
+
+    let stores = Arc::new(stores);
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(std::time::Duration::from_secs(refresh)).await;
+
+            for (key, source) in &sources {
+                let dependency = format!("secret:{}", key);
+
+                match fetch(&stores, source).await {
+                    Ok(value) => {
+                        health::set_dependency(&dependency, true, &source.to_string());
+
+                        if !set(key, value) {
+                            continue;
+                        }
+
+                        tracing::warn!("Secret '{}' has been rotated in {}", key, source);
+
+                        if restart_on_rotation {
+                            tracing::warn!(
+                                "Performing hard restart so that the rotated secret '{}' is used",
+                                key
+                            );
+                            std::process::exit(0);
+                        }
+
+                        health::set_warning(
+                            &dependency,
+                            &format!(
+                                "Secret '{}' was rotated - restart the agent if it only reads it at startup",
+                                key
+                            ),
+                        );
+                    }
+                    Err(e) => {
+                        // keep using the last value that was fetched
+                        tracing::error!(
+                            "Could not refresh secret '{}' from {}: {}",
+                            key,
+                            source,
+                            e
+                        );
+                        health::set_dependency(&dependency, false, &format!("{}: {}", source, e));
+                    }
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sources() {
+        #[allow(clippy::unwrap_used)]
+        let sources = parse_sources(
+            "freeipa-password=vault:secret/data/freeipa#password, \
+             waldur-token = aws-sm:prod/waldur#token, \
+             ldap-bind-password=systemd:ldap, harbor-password=file:/run/secrets/harbor",
+        )
+        .unwrap();
+
+        assert_eq!(sources.len(), 4);
+        assert_eq!(sources[0].0, "freeipa-password");
+        assert_eq!(
+            sources[0].1,
+            SecretSource::Vault {
+                path: "secret/data/freeipa".to_owned(),
+                field: "password".to_owned()
+            }
+        );
+        assert_eq!(sources[1].0, "waldur-token");
+        assert_eq!(sources[1].1.to_string(), "aws-sm:prod/waldur#token");
+        assert_eq!(sources[2].1.to_string(), "systemd:ldap");
+        assert_eq!(sources[3].1.to_string(), "file:/run/secrets/harbor");
+
+        #[allow(clippy::unwrap_used)]
+        let empty = parse_sources("").unwrap();
+        assert!(empty.is_empty());
+
+        assert!(parse_sources("freeipa-password").is_err());
+        assert!(parse_sources("key=vault:secret/data/freeipa").is_err());
+        assert!(parse_sources("key=keychain:freeipa").is_err());
+        assert!(parse_sources("key=file:").is_err());
+    }
+}
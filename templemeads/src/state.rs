@@ -5,6 +5,7 @@ use crate::agent;
 use crate::board;
 use crate::command::Command as ControlCommand;
 use crate::destination;
+use crate::diagnostics;
 use crate::error::Error;
 
 use anyhow::Result;
@@ -194,3 +195,69 @@ pub async fn aggregate_job_stats() -> board::BoardJobStats {
 
     totals
 }
+
+///
+/// Take a snapshot of the jobs on all boards, ordered by peer
+///
+pub async fn board_snapshots() -> Vec<diagnostics::BoardSnapshot> {
+    let states = STATES.read().await;
+    let mut snapshots = Vec::new();
+
+    for state in states.states.values() {
+        let board = state.board().await;
+        let board = board.read().await;
+        snapshots.push(diagnostics::BoardSnapshot {
+            peer: board.peer().name().to_owned(),
+            zone: board.peer().zone().to_owned(),
+            jobs: board.jobs(),
+            queued: board.queued_jobs(),
+        });
+    }
+
+    snapshots.sort_by(|a, b| (&a.peer, &a.zone).cmp(&(&b.peer, &b.zone)));
+
+    snapshots
+}
+
+///
+/// Return a copy of the boards shared with every peer, so that they can
+/// be handed over to a new instance of this agent
+///
+pub async fn export_boards() -> Vec<board::Board> {
+    let states = STATES.read().await;
+    let mut boards = Vec::new();
+
+    for state in states.states.values() {
+        let board = state.board().await;
+        boards.push(board.read().await.clone());
+    }
+
+    boards
+}
+
+///
+/// Import a board handed over by a previous instance of this agent,
+/// which is called `my_name`, replacing any board already shared with
+/// the same peer. Jobs received from the peer that were not finished
+/// are dropped, so that the peer puts them again when it reconnects
+///
+pub async fn import_board(mut board: board::Board, my_name: &str) {
+    let dropped = board.remove_unfinished_received_jobs(my_name);
+
+    if !dropped.is_empty() {
+        tracing::info!(
+            "Dropped {} unfinished job(s) handed over from {} - they will be put again",
+            dropped.len(),
+            board.peer()
+        );
+    }
+
+    let peer = board.peer().clone();
+
+    STATES.write().await.states.insert(
+        peer,
+        Arc::new(State {
+            board: Arc::new(RwLock::new(board)),
+        }),
+    );
+}
@@ -0,0 +1,226 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Integration with systemd's service supervision (sd_notify)
+//!
+//! When an agent is run by systemd with `Type=notify`, it tells systemd
+//! that it is ready once it has connected to all of its servers, and
+//! keeps the status line of the unit up to date with its job counts.
+//! If the unit sets `WatchdogSec`, the agent also pings the watchdog,
+//! but only while it can still collect its status, so that systemd
+//! restarts an agent that has hung rather than treating it as healthy.
+//! Nothing is sent if the agent is not run by systemd.
+
+use crate::agent;
+use crate::config;
+use crate::diagnostics;
+use crate::maintenance;
+use crate::watchdog;
+
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// How often to check whether the agent is ready
+const READY_CHECK_INTERVAL: u64 = 1;
+
+/// Whether the notifier has already been spawned
+static SPAWNED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(target_os = "linux")]
+fn send_abstract(socket: &UnixDatagram, name: &[u8], state: &str) -> std::io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+
+    let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    socket.send_to_addr(state.as_bytes(), &address)?;
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_abstract(_socket: &UnixDatagram, _name: &[u8], _state: &str) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Abstract notify sockets are only supported on Linux",
+    ))
+}
+
+///
+/// Send the passed state (newline-separated `KEY=value` assignments)
+/// to the notify socket at `path`. A path starting with `@` is an
+/// abstract socket
+///
+fn send(path: &OsStr, state: &str) -> std::io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+
+    if let Some(name) = path.as_bytes().strip_prefix(b"@") {
+        return send_abstract(&socket, name, state);
+    }
+
+    socket.send_to(state.as_bytes(), Path::new(path))?;
+
+    Ok(())
+}
+
+///
+/// Send the passed state to systemd, if this agent is run by systemd
+///
+pub(crate) fn notify(state: &str) {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    if let Err(e) = send(&path, state) {
+        tracing::warn!("Could not notify systemd of '{}': {}", state, e);
+    }
+}
+
+///
+/// Return the interval at which to ping the systemd watchdog, which is
+/// half of the watchdog timeout, or None if the watchdog is not enabled
+/// for this process
+///
+fn watchdog_interval() -> Option<std::time::Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+
+    match usec {
+        0 => None,
+        usec => Some(std::time::Duration::from_micros(usec / 2)),
+    }
+}
+
+///
+/// Return the status line of this agent, e.g. "3 peers connected,
+/// 2 jobs running, 1520 completed, 4 failed, 0 expired"
+///
+async fn status() -> String {
+    let peers = agent::real_peers().await.len();
+    let statistics = diagnostics::get_job_statistics().await;
+
+    let status = format!(
+        "{} peers connected, {} jobs running, {} completed, {} failed, {} expired",
+        peers,
+        watchdog::running(),
+        statistics.total_completed,
+        statistics.total_failed,
+        statistics.total_expired
+    );
+
+    match maintenance::is_draining() {
+        true => format!("Draining for a restart - {}", status),
+        false => status,
+    }
+}
+
+///
+/// Return the names of the servers of this agent that it has not yet
+/// connected to
+///
+async fn unconnected_servers() -> Vec<String> {
+    // the bridge does not watch its config file, and so has no servers
+    let Ok(service) = config::service().await else {
+        return Vec::new();
+    };
+
+    let peers = agent::real_peers().await;
+
+    service
+        .servers()
+        .iter()
+        .filter(|server| {
+            !peers
+                .iter()
+                .any(|peer| peer.name() == server.name() && peer.zone() == server.zone())
+        })
+        .map(|server| server.name())
+        .collect()
+}
+
+///
+/// Spawn the tasks that tell systemd when this agent is ready, keep its
+/// status up to date, and ping its watchdog. This does nothing if the
+/// agent is not run by systemd, or if the tasks are already running
+///
+pub(crate) fn spawn() {
+    if std::env::var_os("NOTIFY_SOCKET").is_none() || SPAWNED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+
+    tokio::spawn(async {
+        loop {
+            let unconnected = unconnected_servers().await;
+
+            if unconnected.is_empty() {
+                break;
+            }
+
+            notify(&format!(
+                "STATUS=Waiting to connect to {}",
+                unconnected.join(", ")
+            ));
+
+            tokio::time::sleep(std::time::Duration::from_secs(READY_CHECK_INTERVAL)).await;
+        }
+
+        tracing::info!("Notifying systemd that the agent is ready");
+        notify(&format!("READY=1\nSTATUS={}", status().await));
+    });
+
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+
+    tracing::info!(
+        "Pinging the systemd watchdog every {}ms",
+        interval.as_millis()
+    );
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+
+            // the status needs the agent's locks, so a hung agent can't
+            // collect it in time, and so stops pinging the watchdog
+            match tokio::time::timeout(interval, status()).await {
+                Ok(status) => notify(&format!("WATCHDOG=1\nSTATUS={}", status)),
+                Err(_) => tracing::error!(
+                    "Could not collect the agent status within {}ms - not pinging the systemd watchdog",
+                    interval.as_millis()
+                ),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send() {
+        let path = std::env::temp_dir().join(format!("openportal-notify-{}", uuid::Uuid::new_v4()));
+
+        let socket = UnixDatagram::bind(&path)
+            .unwrap_or_else(|e| unreachable!("Cannot bind notify socket: {}", e));
+
+        send(path.as_os_str(), "READY=1\nSTATUS=Ready")
+            .unwrap_or_else(|e| unreachable!("Cannot send to notify socket: {}", e));
+
+        let mut buffer = [0; 64];
+        let size = socket
+            .recv(&mut buffer)
+            .unwrap_or_else(|e| unreachable!("Cannot receive from notify socket: {}", e));
+
+        assert_eq!(&buffer[..size], b"READY=1\nSTATUS=Ready");
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -3,9 +3,16 @@
 
 //! System information collection using sysinfo crate
 //!
-//! This module provides functions for collecting system metrics like memory and CPU usage.
+//! This module provides functions for collecting system metrics like memory and CPU usage,
+//! and for enforcing soft and hard limits on the resources used by the agent.
+
+use crate::error::Error;
+use crate::health;
+use crate::job::Job;
+use crate::storage::StorageSize;
 
 use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
 use sysinfo::{Pid, ProcessesToUpdate, System};
 
@@ -26,6 +33,74 @@ pub struct SystemInfo {
     pub system_cpus: usize,
 }
 
+/// A limit on the memory used by this process
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemoryLimit {
+    /// A fixed number of bytes
+    Bytes(u64),
+    /// A percentage of the total system memory
+    Percent(f64),
+}
+
+impl MemoryLimit {
+    ///
+    /// Parse a memory limit, either as a size (e.g. "2GB") or as
+    /// a percentage of the total system memory (e.g. "80%")
+    ///
+    pub fn parse(limit: &str) -> Result<Self, Error> {
+        let limit = limit.trim();
+
+        match limit.strip_suffix('%') {
+            Some(percent) => match percent.trim().parse::<f64>() {
+                Ok(percent) if percent > 0.0 => Ok(Self::Percent(percent)),
+                _ => Err(Error::Parse(format!(
+                    "Invalid memory limit '{}'. The percentage must be a positive number",
+                    limit
+                ))),
+            },
+            None => Ok(Self::Bytes(StorageSize::parse(limit)?.as_bytes())),
+        }
+    }
+
+    /// Return the limit in bytes, given the total system memory
+    pub fn as_bytes(&self, system_memory_total: u64) -> u64 {
+        match self {
+            Self::Bytes(bytes) => *bytes,
+            Self::Percent(percent) => (system_memory_total as f64 * percent / 100.0) as u64,
+        }
+    }
+}
+
+impl std::fmt::Display for MemoryLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Bytes(bytes) => write!(f, "{}", StorageSize::from_bytes(*bytes)),
+            Self::Percent(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+/// Soft and hard limits on the resources used by this process. Crossing
+/// a soft limit raises a health warning. Crossing a hard limit also
+/// pauses the running of new jobs until usage falls below the limit.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResourceLimits {
+    /// Soft limit on memory usage
+    pub memory_soft: Option<MemoryLimit>,
+    /// Hard limit on memory usage
+    pub memory_hard: Option<MemoryLimit>,
+    /// Soft limit on CPU usage (percent, where 100 is one core)
+    pub cpu_soft: Option<f32>,
+    /// Hard limit on CPU usage (percent, where 100 is one core)
+    pub cpu_hard: Option<f32>,
+}
+
+/// The resource limits applied to this process
+static LIMITS: Lazy<Mutex<ResourceLimits>> = Lazy::new(|| Mutex::new(ResourceLimits::default()));
+
+/// Whether this process is currently over one of its hard limits
+static OVER_HARD_LIMIT: AtomicBool = AtomicBool::new(false);
+
 impl Default for SystemInfo {
     fn default() -> Self {
         Self {
@@ -118,12 +193,158 @@ pub fn initialize() {
     );
 }
 
+///
+/// Parse an optional CPU limit, given as a percentage (where 100 is one
+/// core). An empty string means there is no limit
+///
+pub fn parse_cpu_limit(limit: &str) -> Result<Option<f32>, Error> {
+    let limit = limit.trim();
+
+    if limit.is_empty() {
+        return Ok(None);
+    }
+
+    match limit.trim_end_matches('%').trim().parse::<f32>() {
+        Ok(percent) if percent > 0.0 => Ok(Some(percent)),
+        _ => Err(Error::Parse(format!(
+            "Invalid CPU limit '{}'. It must be a positive percentage",
+            limit
+        ))),
+    }
+}
+
+///
+/// Parse an optional memory limit. An empty string means there is no limit
+///
+pub fn parse_memory_limit(limit: &str) -> Result<Option<MemoryLimit>, Error> {
+    match limit.trim() {
+        "" => Ok(None),
+        limit => Ok(Some(MemoryLimit::parse(limit)?)),
+    }
+}
+
+///
+/// Set the soft and hard limits on the resources used by this process.
+/// These are checked each time the system monitor refreshes
+///
+pub fn set_limits(limits: ResourceLimits) {
+    match LIMITS.lock() {
+        Ok(mut current) => *current = limits,
+        Err(e) => tracing::error!("Failed to lock resource limits: {}", e),
+    }
+}
+
+///
+/// Return whether this process is currently over one of its hard
+/// resource limits, in which case new jobs should not be started
+///
+pub fn is_over_hard_limit() -> bool {
+    OVER_HARD_LIMIT.load(Ordering::Relaxed)
+}
+
+///
+/// Wait until this process is below its hard resource limits before
+/// running the passed job. This returns an error if the job expires
+/// while waiting
+///
+pub async fn wait_for_resources(job: &Job) -> Result<(), Error> {
+    if !is_over_hard_limit() {
+        return Ok(());
+    }
+
+    tracing::warn!(
+        "Pausing job {} until resource usage falls below the hard limit",
+        job.id()
+    );
+
+    while is_over_hard_limit() {
+        job.assert_is_not_expired()?;
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+
+    tracing::info!("Resuming job {} as resource usage has fallen", job.id());
+
+    Ok(())
+}
+
+///
+/// Compare the passed system information against the resource limits,
+/// raising or clearing health warnings, and pausing or resuming jobs
+///
+fn check_limits(info: &SystemInfo) {
+    let limits = match LIMITS.lock() {
+        Ok(limits) => limits.clone(),
+        Err(e) => {
+            tracing::error!("Failed to lock resource limits: {}", e);
+            return;
+        }
+    };
+
+    let memory_mb = info.memory_bytes as f64 / 1_048_576.0;
+
+    let over_memory = |limit: Option<MemoryLimit>| {
+        limit.filter(|l| info.memory_bytes > l.as_bytes(info.system_memory_total))
+    };
+
+    let over_cpu = |limit: Option<f32>| limit.filter(|l| info.cpu_percent > *l);
+
+    let memory_hard = over_memory(limits.memory_hard);
+    let cpu_hard = over_cpu(limits.cpu_hard);
+
+    match (memory_hard, over_memory(limits.memory_soft)) {
+        (Some(limit), _) => health::set_warning(
+            "resource:memory",
+            &format!(
+                "Memory usage {:.1}MB is over the hard limit of {} - new jobs are paused",
+                memory_mb, limit
+            ),
+        ),
+        (None, Some(limit)) => health::set_warning(
+            "resource:memory",
+            &format!(
+                "Memory usage {:.1}MB is over the soft limit of {}",
+                memory_mb, limit
+            ),
+        ),
+        (None, None) => health::clear_warning("resource:memory"),
+    }
+
+    match (cpu_hard, over_cpu(limits.cpu_soft)) {
+        (Some(limit), _) => health::set_warning(
+            "resource:cpu",
+            &format!(
+                "CPU usage {:.1}% is over the hard limit of {}% - new jobs are paused",
+                info.cpu_percent, limit
+            ),
+        ),
+        (None, Some(limit)) => health::set_warning(
+            "resource:cpu",
+            &format!(
+                "CPU usage {:.1}% is over the soft limit of {}%",
+                info.cpu_percent, limit
+            ),
+        ),
+        (None, None) => health::clear_warning("resource:cpu"),
+    }
+
+    let over_hard_limit = memory_hard.is_some() || cpu_hard.is_some();
+
+    if OVER_HARD_LIMIT.swap(over_hard_limit, Ordering::Relaxed) != over_hard_limit {
+        if over_hard_limit {
+            tracing::warn!("Over the hard resource limit - pausing new jobs");
+        } else {
+            tracing::info!("Back under the hard resource limits - resuming new jobs");
+        }
+    }
+}
+
 /// Spawn a background task that periodically refreshes system info and monitors resource usage
 ///
 /// This task:
 /// - Refreshes CPU data every 10 seconds for accurate measurements
 /// - Logs warnings if CPU usage exceeds 90%
 /// - Logs warnings if process memory usage exceeds 80% of total system memory
+/// - Applies the soft and hard resource limits set via `set_limits`
 /// - Initializes system info on first run
 ///
 /// Call this once at startup. The task runs indefinitely in the background.
@@ -139,6 +360,9 @@ pub fn spawn_monitor() {
             // Collect current system info
             let info = collect();
 
+            // Raise warnings, or pause jobs, if over the resource limits
+            check_limits(&info);
+
             // Check CPU usage
             if info.cpu_percent > 90.0 {
                 tracing::warn!("High CPU usage: {:.1}%", info.cpu_percent);
@@ -196,3 +420,29 @@ pub fn spawn_monitor() {
 
     tracing::info!("System info monitor started - refreshing every 10 seconds");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_limits() {
+        #[allow(clippy::unwrap_used)]
+        let limit = parse_memory_limit("2GB").unwrap();
+        assert_eq!(limit, Some(MemoryLimit::Bytes(2 * 1_073_741_824)));
+
+        #[allow(clippy::unwrap_used)]
+        let limit = parse_memory_limit("80%").unwrap();
+        assert_eq!(limit, Some(MemoryLimit::Percent(80.0)));
+        assert_eq!(limit.map(|l| l.as_bytes(1000)), Some(800));
+
+        assert!(matches!(parse_memory_limit(""), Ok(None)));
+        assert!(parse_memory_limit("lots").is_err());
+        assert!(parse_memory_limit("-5%").is_err());
+
+        assert!(matches!(parse_cpu_limit("150"), Ok(Some(l)) if l == 150.0));
+        assert!(matches!(parse_cpu_limit("90%"), Ok(Some(l)) if l == 90.0));
+        assert!(matches!(parse_cpu_limit(""), Ok(None)));
+        assert!(parse_cpu_limit("0").is_err());
+    }
+}
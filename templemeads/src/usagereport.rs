@@ -434,6 +434,10 @@ pub struct DailyProjectUsageReport {
     reports: HashMap<String, Usage>,
     #[serde(default)]
     components: HashMap<String, HashMap<String, Usage>>,
+    /// The usage (and its components) on each partition. Empty when
+    /// reading data from older instances.
+    #[serde(default)]
+    partitions: HashMap<String, DailyProjectUsageReport>,
     /// Per-user job counts. Empty when reading data from older instances.
     #[serde(default)]
     user_job_counts: HashMap<String, u64>,
@@ -447,6 +451,10 @@ pub struct DailyProjectUsageReport {
     /// Scalar total — equals sum of user_wait_seconds when populated.
     #[serde(default)]
     total_wait_seconds: u64,
+    /// Per-user energy consumed, in joules. Empty when energy is not
+    /// collected, or when reading data from older instances.
+    #[serde(default)]
+    user_energy: HashMap<String, u64>,
     is_complete: bool,
 }
 
@@ -496,6 +504,25 @@ impl std::fmt::Display for DailyProjectUsageReport {
     }
 }
 
+///
+/// Convert the passed number of joules to kWh
+///
+fn joules_to_kwh(joules: u64) -> f64 {
+    joules as f64 / 3_600_000.0
+}
+
+/// The prefix of the components that hold the time for which licenses
+/// were checked out, e.g. "license:matlab"
+pub const LICENSE_COMPONENT_PREFIX: &str = "license:";
+
+///
+/// Return the name of the component that holds the license-seconds of
+/// the passed license feature
+///
+pub fn license_component(feature: &str) -> String {
+    format!("{}{}", LICENSE_COMPONENT_PREFIX, feature)
+}
+
 /// Display adapter that formats all [`Usage`] values in a
 /// [`DailyProjectUsageReport`] in hours. Obtained via
 /// [`DailyProjectUsageReport::in_hours`].
@@ -611,6 +638,26 @@ impl DailyProjectUsageReport {
         self.total_wait_seconds += seconds;
     }
 
+    /// Add energy (in joules) consumed by the jobs of a specific user
+    pub fn add_energy(&mut self, user: &str, joules: u64) {
+        *self.user_energy.entry(user.to_string()).or_default() += joules;
+    }
+
+    /// Return the energy (in joules) consumed by the jobs of a specific user
+    pub fn user_energy_joules(&self, user: &str) -> u64 {
+        self.user_energy.get(user).copied().unwrap_or(0)
+    }
+
+    /// Return the energy (in joules) consumed by all jobs on this day
+    pub fn energy_joules(&self) -> u64 {
+        self.user_energy.values().sum()
+    }
+
+    /// Return the energy consumed by all jobs on this day, in kWh
+    pub fn energy_kwh(&self) -> f64 {
+        joules_to_kwh(self.energy_joules())
+    }
+
     pub fn num_jobs_for_user(&self, user: &str) -> u64 {
         self.user_job_counts.get(user).copied().unwrap_or(0)
     }
@@ -676,6 +723,66 @@ impl DailyProjectUsageReport {
         components
     }
 
+    ///
+    /// Return the total GPU time used on this day, summed over all
+    /// users. This is the "gpu" component, i.e. GPU-seconds rather
+    /// than node-seconds
+    ///
+    pub fn gpu_usage(&self) -> Usage {
+        self.components
+            .get("gpu")
+            .map(|reports| reports.values().cloned().sum())
+            .unwrap_or_default()
+    }
+
+    ///
+    /// Return the GPU time used by the passed local user on this day
+    ///
+    pub fn user_gpu_usage(&self, local_user: &str) -> Usage {
+        self.components
+            .get("gpu")
+            .and_then(|reports| reports.get(local_user).cloned())
+            .unwrap_or_default()
+    }
+
+    ///
+    /// Return the license features that were checked out on this day,
+    /// i.e. the components named "license:<feature>"
+    ///
+    pub fn licenses(&self) -> Vec<String> {
+        let mut licenses = self
+            .components
+            .keys()
+            .filter_map(|c| c.strip_prefix(LICENSE_COMPONENT_PREFIX))
+            .map(|f| f.to_owned())
+            .collect::<Vec<_>>();
+        licenses.sort();
+        licenses
+    }
+
+    ///
+    /// Return the time for which the license feature was checked out on
+    /// this day, summed over all users. A user holding two licenses for
+    /// an hour counts as two license-hours.
+    ///
+    pub fn license_usage(&self, feature: &str) -> Usage {
+        self.components
+            .get(&license_component(feature))
+            .map(|reports| reports.values().cloned().sum())
+            .unwrap_or_default()
+    }
+
+    ///
+    /// Return the time for which the passed local user had the license
+    /// feature checked out on this day
+    ///
+    pub fn user_license_usage(&self, feature: &str, local_user: &str) -> Usage {
+        self.components
+            .get(&license_component(feature))
+            .and_then(|reports| reports.get(local_user).cloned())
+            .unwrap_or_default()
+    }
+
     // disable the clippy field_reassign_with_default warning
     // It is more robust to create a default and then overwrite
     // the fields that need to change via a clone
@@ -693,6 +800,7 @@ impl DailyProjectUsageReport {
                 report.user_wait_seconds = self.user_wait_seconds.clone();
                 report.num_jobs = self.num_jobs;
                 report.total_wait_seconds = self.total_wait_seconds;
+                report.user_energy = self.user_energy.clone();
                 report.is_complete = self.is_complete;
 
                 report
@@ -703,6 +811,7 @@ impl DailyProjectUsageReport {
                 report.user_wait_seconds = self.user_wait_seconds.clone();
                 report.num_jobs = self.num_jobs;
                 report.total_wait_seconds = self.total_wait_seconds;
+                report.user_energy = self.user_energy.clone();
                 report.is_complete = self.is_complete;
 
                 report
@@ -710,6 +819,49 @@ impl DailyProjectUsageReport {
         }
     }
 
+    ///
+    /// Return the names of the partitions that were used on this day
+    ///
+    pub fn partitions(&self) -> Vec<String> {
+        let mut partitions = self.partitions.keys().cloned().collect::<Vec<_>>();
+        partitions.sort();
+        partitions
+    }
+
+    ///
+    /// Add the passed report as usage on the passed partition. This
+    /// only records the breakdown - the report should also be added
+    /// to this report so that it is included in the totals
+    ///
+    pub fn add_partition_report(&mut self, partition: &str, report: &DailyProjectUsageReport) {
+        let mut report = report.clone();
+
+        // partition reports are not themselves broken down by partition
+        report.partitions.clear();
+
+        *self.partitions.entry(partition.to_string()).or_default() += report;
+    }
+
+    ///
+    /// Return the total usage on the passed partition on this day
+    ///
+    pub fn partition_usage(&self, partition: &str) -> Usage {
+        self.partitions
+            .get(partition)
+            .map(|report| report.total_usage())
+            .unwrap_or_default()
+    }
+
+    ///
+    /// Return the report of the usage on the passed partition. This
+    /// has its own per-user usage, job counts and components
+    ///
+    pub fn get_partition(&self, partition: &str) -> DailyProjectUsageReport {
+        let mut report = self.partitions.get(partition).cloned().unwrap_or_default();
+        report.is_complete = self.is_complete;
+        report
+    }
+
     pub fn set_complete(&mut self) {
         self.is_complete = true;
     }
@@ -745,6 +897,10 @@ impl DailyProjectUsageReport {
             })
             .collect();
 
+        for report in self.partitions.values_mut() {
+            report.remap_local_users(string_map);
+        }
+
         let old_counts = std::mem::take(&mut self.user_job_counts);
         self.user_job_counts = old_counts
             .into_iter()
@@ -762,6 +918,15 @@ impl DailyProjectUsageReport {
                 (new_user, secs)
             })
             .collect();
+
+        let old_energy = std::mem::take(&mut self.user_energy);
+        self.user_energy = old_energy
+            .into_iter()
+            .map(|(user, joules)| {
+                let new_user = string_map.get(&user).cloned().unwrap_or(user);
+                (new_user, joules)
+            })
+            .collect();
     }
 }
 
@@ -782,6 +947,11 @@ impl std::ops::Add<DailyProjectUsageReport> for DailyProjectUsageReport {
             }
         }
 
+        // and for the partitions
+        for (partition, report) in other.partitions {
+            *new_report.partitions.entry(partition).or_default() += report;
+        }
+
         for (user, count) in &other.user_job_counts {
             *new_report.user_job_counts.entry(user.clone()).or_default() += count;
         }
@@ -791,6 +961,9 @@ impl std::ops::Add<DailyProjectUsageReport> for DailyProjectUsageReport {
                 .entry(user.clone())
                 .or_default() += secs;
         }
+        for (user, joules) in &other.user_energy {
+            *new_report.user_energy.entry(user.clone()).or_default() += joules;
+        }
         new_report.num_jobs = self.num_jobs + other.num_jobs;
         new_report.total_wait_seconds = self.total_wait_seconds + other.total_wait_seconds;
 
@@ -813,12 +986,20 @@ impl std::ops::AddAssign<DailyProjectUsageReport> for DailyProjectUsageReport {
             }
         }
 
+        // and for the partitions
+        for (partition, report) in other.partitions {
+            *self.partitions.entry(partition).or_default() += report;
+        }
+
         for (user, count) in &other.user_job_counts {
             *self.user_job_counts.entry(user.clone()).or_default() += count;
         }
         for (user, secs) in &other.user_wait_seconds {
             *self.user_wait_seconds.entry(user.clone()).or_default() += secs;
         }
+        for (user, joules) in &other.user_energy {
+            *self.user_energy.entry(user.clone()).or_default() += joules;
+        }
         self.num_jobs += other.num_jobs;
         self.total_wait_seconds += other.total_wait_seconds;
 
@@ -842,6 +1023,11 @@ impl std::ops::Mul<f64> for DailyProjectUsageReport {
             }
         }
 
+        // and the partition usage
+        for partition_report in new_report.partitions.values_mut() {
+            *partition_report = partition_report.clone() * rhs;
+        }
+
         new_report
     }
 }
@@ -862,6 +1048,11 @@ impl std::ops::Div<f64> for DailyProjectUsageReport {
             }
         }
 
+        // and the partition usage
+        for partition_report in new_report.partitions.values_mut() {
+            *partition_report = partition_report.clone() / rhs;
+        }
+
         new_report
     }
 }
@@ -974,7 +1165,31 @@ impl std::fmt::Display for ProjectUsageReport {
                 }
             }
         }
-        writeln!(f, "Total: {}", self.total_usage())
+        writeln!(f, "Total: {}", self.total_usage())?;
+
+        if !self.gpu_usage().is_zero() {
+            writeln!(f, "GPU total: {}", self.gpu_usage())?;
+        }
+
+        if self.energy_joules() > 0 {
+            writeln!(f, "Energy: {:.2} kWh", self.energy_kwh())?;
+        }
+
+        // only break down by partition if more than one was used
+        let partitions = self.partitions();
+
+        if partitions.len() > 1 {
+            for partition in partitions {
+                writeln!(
+                    f,
+                    "Partition {}: {}",
+                    partition,
+                    self.partition_usage(&partition)
+                )?;
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -1062,7 +1277,31 @@ impl std::fmt::Display for ProjectUsageReportHoursDisplay<'_> {
                 }
             }
         }
-        writeln!(f, "Total: {}", report.total_usage().in_hours())
+        writeln!(f, "Total: {}", report.total_usage().in_hours())?;
+
+        if !report.gpu_usage().is_zero() {
+            writeln!(f, "GPU total: {}", report.gpu_usage().in_hours())?;
+        }
+
+        if report.energy_joules() > 0 {
+            writeln!(f, "Energy: {:.2} kWh", report.energy_kwh())?;
+        }
+
+        // only break down by partition if more than one was used
+        let partitions = report.partitions();
+
+        if partitions.len() > 1 {
+            for partition in partitions {
+                writeln!(
+                    f,
+                    "Partition {}: {}",
+                    partition,
+                    report.partition_usage(&partition).in_hours()
+                )?;
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -1321,6 +1560,110 @@ impl ProjectUsageReport {
         self.reports.values().map(|r| r.num_jobs()).sum()
     }
 
+    ///
+    /// Return the total GPU time used by this project. This is reported
+    /// separately from the node time in `total_usage`, so that GPU
+    /// allocations can be billed in GPU-hours
+    ///
+    pub fn gpu_usage(&self) -> Usage {
+        self.reports.values().map(|r| r.gpu_usage()).sum()
+    }
+
+    ///
+    /// Return the GPU time used by the passed user of this project
+    ///
+    pub fn user_gpu_usage(&self, user: &UserIdentifier) -> Usage {
+        match self.users.get(user) {
+            Some(local_user) => self
+                .reports
+                .values()
+                .map(|r| r.user_gpu_usage(local_user))
+                .sum(),
+            None => Usage::default(),
+        }
+    }
+
+    ///
+    /// Return the license features that were checked out by this
+    /// project's users
+    ///
+    pub fn licenses(&self) -> Vec<String> {
+        let mut licenses = self
+            .reports
+            .values()
+            .flat_map(|r| r.licenses())
+            .collect::<std::collections::HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        licenses.sort();
+        licenses
+    }
+
+    ///
+    /// Return the time for which this project's users had the license
+    /// feature checked out, e.g. for license chargeback
+    ///
+    pub fn license_usage(&self, feature: &str) -> Usage {
+        self.reports
+            .values()
+            .map(|r| r.license_usage(feature))
+            .sum()
+    }
+
+    ///
+    /// Return the time for which the passed user of this project had the
+    /// license feature checked out
+    ///
+    pub fn user_license_usage(&self, feature: &str, user: &UserIdentifier) -> Usage {
+        match self.users.get(user) {
+            Some(local_user) => self
+                .reports
+                .values()
+                .map(|r| r.user_license_usage(feature, local_user))
+                .sum(),
+            None => Usage::default(),
+        }
+    }
+
+    ///
+    /// Return the energy (in joules) consumed by this project's jobs
+    ///
+    pub fn energy_joules(&self) -> u64 {
+        self.reports.values().map(|r| r.energy_joules()).sum()
+    }
+
+    ///
+    /// Return the energy consumed by this project's jobs, in kWh
+    ///
+    pub fn energy_kwh(&self) -> f64 {
+        joules_to_kwh(self.energy_joules())
+    }
+
+    ///
+    /// Return the energy consumed by the jobs of the passed user of
+    /// this project, in kWh
+    ///
+    pub fn user_energy_kwh(&self, user: &UserIdentifier) -> f64 {
+        match self.users.get(user) {
+            Some(local_user) => joules_to_kwh(
+                self.reports
+                    .values()
+                    .map(|r| r.user_energy_joules(local_user))
+                    .sum(),
+            ),
+            None => 0.0,
+        }
+    }
+
+    ///
+    /// Return the carbon emitted by this project's jobs, in kg of CO2e,
+    /// given the carbon intensity of the site's electricity in grams
+    /// of CO2e per kWh
+    ///
+    pub fn carbon_kg(&self, grams_per_kwh: f64) -> f64 {
+        self.energy_kwh() * grams_per_kwh / 1000.0
+    }
+
     pub fn total_wait_seconds(&self) -> u64 {
         self.reports.values().map(|r| r.total_wait_seconds()).sum()
     }
@@ -1463,6 +1806,52 @@ impl ProjectUsageReport {
         }
     }
 
+    ///
+    /// Return the names of all partitions used by this project
+    ///
+    pub fn partitions(&self) -> Vec<String> {
+        let mut partitions: std::collections::HashSet<String> = std::collections::HashSet::new();
+
+        for report in self.reports.values() {
+            for partition in report.partitions() {
+                partitions.insert(partition);
+            }
+        }
+
+        let mut partitions: Vec<String> = partitions.into_iter().collect();
+
+        partitions.sort();
+
+        partitions
+    }
+
+    ///
+    /// Return the total usage of this project on the passed partition
+    ///
+    pub fn partition_usage(&self, partition: &str) -> Usage {
+        self.reports
+            .values()
+            .map(|r| r.partition_usage(partition))
+            .sum()
+    }
+
+    ///
+    /// Return the report of this project's usage on the passed partition
+    ///
+    pub fn get_partition(&self, partition: &str) -> ProjectUsageReport {
+        let mut reports = HashMap::new();
+
+        for (date, daily_report) in &self.reports {
+            reports.insert(date.clone(), daily_report.get_partition(partition));
+        }
+
+        ProjectUsageReport {
+            project: self.project.clone(),
+            reports,
+            users: self.users.clone(),
+        }
+    }
+
     pub fn combine(reports: &[ProjectUsageReport]) -> Result<Self, Error> {
         if reports.is_empty() {
             return Err(Error::InvalidState("No reports to combine".to_string()));
@@ -1829,6 +2218,38 @@ impl UsageReport {
         }
     }
 
+    ///
+    /// Return the names of all partitions used by all projects
+    ///
+    pub fn partitions(&self) -> Vec<String> {
+        let mut partitions: std::collections::HashSet<String> = std::collections::HashSet::new();
+
+        for report in self.reports.values() {
+            for partition in report.partitions() {
+                partitions.insert(partition);
+            }
+        }
+
+        let mut partitions: Vec<String> = partitions.into_iter().collect();
+
+        partitions.sort();
+
+        partitions
+    }
+
+    pub fn get_partition(&self, partition: &str) -> UsageReport {
+        let mut reports = HashMap::new();
+
+        for (project, project_report) in &self.reports {
+            reports.insert(project.clone(), project_report.get_partition(partition));
+        }
+
+        UsageReport {
+            portal: self.portal.clone(),
+            reports,
+        }
+    }
+
     pub fn total_usage(&self) -> Usage {
         self.reports.values().map(|r| r.total_usage()).sum()
     }
@@ -0,0 +1,190 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+//! Watchdog that automatically soft restarts a wedged agent
+//!
+//! An agent is wedged if it has jobs running, but no job has started or
+//! finished for longer than the configured deadline. This is normally
+//! caused by a stuck event loop or worker pool, and so the watchdog
+//! performs a soft restart (as if one had been requested via the
+//! `restart` command) and records this in diagnostics.
+//!
+//! Progress is tracked using atomics rather than the diagnostics tracker
+//! so that the watchdog still works if a lock has become stuck.
+
+use crate::diagnostics;
+use crate::restart;
+
+use chrono::Utc;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+
+/// Number of jobs currently being run by this agent
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// Timestamp (seconds since the epoch) of when a job last started or finished
+static LAST_PROGRESS: AtomicI64 = AtomicI64::new(0);
+
+/// Maximum time (in seconds) between checks of the watchdog
+const MAX_CHECK_INTERVAL: u64 = 60;
+
+/// The running watchdog task, which is replaced if the watchdog is reconfigured
+static TASK: std::sync::Mutex<Option<tokio::task::JoinHandle<()>>> = std::sync::Mutex::new(None);
+
+///
+/// Record that this agent has started running a job
+///
+pub fn job_started() {
+    RUNNING.fetch_add(1, Ordering::Relaxed);
+    LAST_PROGRESS.store(Utc::now().timestamp(), Ordering::Relaxed);
+}
+
+///
+/// Record that this agent has finished running a job
+///
+pub fn job_finished() {
+    let _ = RUNNING.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |running| {
+        Some(running.saturating_sub(1))
+    });
+    LAST_PROGRESS.store(Utc::now().timestamp(), Ordering::Relaxed);
+}
+
+///
+/// Return the number of jobs that this agent is currently running
+///
+pub fn running() -> usize {
+    RUNNING.load(Ordering::Relaxed)
+}
+
+///
+/// Return the reason this agent is wedged, or None if it is not,
+/// given the deadline (in seconds) for making progress
+///
+fn check(deadline: u64) -> Option<String> {
+    let running = RUNNING.load(Ordering::Relaxed);
+
+    if running == 0 {
+        return None;
+    }
+
+    let stalled_for = Utc::now().timestamp() - LAST_PROGRESS.load(Ordering::Relaxed);
+
+    if stalled_for < deadline as i64 {
+        return None;
+    }
+
+    Some(format!(
+        "{} job(s) running but no job has started or finished for {}s (deadline {}s)",
+        running, stalled_for, deadline
+    ))
+}
+
+///
+/// Spawn the watchdog, which soft restarts this agent if it has
+/// not made progress within `deadline` seconds while jobs are running.
+/// A deadline of 0 disables the watchdog. Any previously spawned
+/// watchdog is stopped.
+///
+pub fn spawn(deadline: u64) {
+    let mut task = match TASK.lock() {
+        Ok(task) => task,
+        Err(e) => {
+            tracing::error!("Could not lock the watchdog task: {}", e);
+            return;
+        }
+    };
+
+    if let Some(previous) = task.take() {
+        previous.abort();
+    }
+
+    if deadline == 0 {
+        tracing::debug!("Watchdog is disabled");
+        return;
+    }
+
+    tracing::info!(
+        "Watchdog will soft restart this agent if it makes no progress for {}s",
+        deadline
+    );
+
+    LAST_PROGRESS.store(Utc::now().timestamp(), Ordering::Relaxed);
+
+    let interval = (deadline / 4).clamp(1, MAX_CHECK_INTERVAL);
+
+    *task = Some(tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+
+            let Some(reason) = check(deadline) else {
+                continue;
+            };
+
+            tracing::error!(
+                "Watchdog: agent is wedged - {} - performing soft restart",
+                reason
+            );
+
+            diagnostics::record_watchdog_restart(&reason);
+
+            // give the next restart a full deadline to make progress
+            LAST_PROGRESS.store(Utc::now().timestamp(), Ordering::Relaxed);
+
+            // the soft restart needs the same locks that may be stuck, so
+            // fall back to a hard restart if it can't complete in time
+            match tokio::time::timeout(
+                std::time::Duration::from_secs(deadline),
+                restart::perform_soft_restart(),
+            )
+            .await
+            {
+                Ok(Ok(_)) => {
+                    tracing::warn!("Watchdog: soft restart completed successfully");
+                }
+                Ok(Err(e)) => {
+                    tracing::error!(
+                        "Watchdog: soft restart failed: {} - falling back to hard restart",
+                        e
+                    );
+                    std::process::exit(1);
+                }
+                Err(_) => {
+                    tracing::error!(
+                        "Watchdog: soft restart did not complete within {}s - falling back to hard restart",
+                        deadline
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        // nothing running, so never wedged
+        RUNNING.store(0, Ordering::Relaxed);
+        LAST_PROGRESS.store(0, Ordering::Relaxed);
+        assert!(check(60).is_none());
+
+        // running, and recent progress
+        job_started();
+        assert!(check(60).is_none());
+
+        // running, but no progress for longer than the deadline
+        LAST_PROGRESS.store(Utc::now().timestamp() - 120, Ordering::Relaxed);
+        assert!(check(60).is_some());
+
+        // finishing the job counts as progress
+        job_finished();
+        assert!(check(60).is_none());
+        assert_eq!(RUNNING.load(Ordering::Relaxed), 0);
+
+        // never drops below zero
+        job_finished();
+        assert_eq!(RUNNING.load(Ordering::Relaxed), 0);
+    }
+}
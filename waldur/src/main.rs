@@ -0,0 +1,137 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use anyhow::Result;
+
+use templemeads::agent::custom::{process_args, run, Defaults};
+use templemeads::agent::Type as AgentType;
+use templemeads::async_runnable;
+use templemeads::grammar::PortalIdentifier;
+use templemeads::job::{Envelope, Job};
+use templemeads::notification::default_notify_runner;
+use templemeads::set_notify_runner;
+use templemeads::Error;
+
+mod orders;
+mod resources;
+mod sync;
+mod waldur;
+
+///
+/// Main function for the Waldur agent
+///
+/// This agent replaces the bridge (and the Python glue that sits behind
+/// it) for sites that use Waldur as their user portal. It connects to
+/// the portal as a bridge agent, and polls the Waldur REST API directly.
+/// Orders for the configured offerings are approved and carried out as
+/// OpenPortal jobs, the members of each resource's team are added to
+/// (and removed from) the project, and the project's usage is submitted
+/// back to Waldur.
+///
+#[tokio::main]
+async fn main() -> Result<()> {
+    // start tracing
+    templemeads::config::initialise_tracing();
+
+    // start system monitoring
+    templemeads::spawn_system_monitor();
+
+    // create the OpenPortal paddington defaults
+    let defaults: Defaults = Defaults::parse(
+        Some("waldur-bridge".to_owned()),
+        Some(
+            dirs::config_local_dir()
+                .unwrap_or(
+                    ".".parse()
+                        .expect("Could not parse fallback config directory."),
+                )
+                .join("openportal")
+                .join("waldur-config.toml"),
+        ),
+        Some("ws://localhost:8055".to_owned()),
+        Some("127.0.0.1".to_owned()),
+        Some(8055),
+        None,
+        None,
+        Some(AgentType::Bridge),
+    );
+
+    // now parse the command line arguments to get the service configuration
+    let config = match process_args(&defaults).await? {
+        Some(config) => config,
+        None => {
+            // Not running the service, so can safely exit
+            return Ok(());
+        }
+    };
+
+    let waldur_url = config.option("waldur-url", "");
+
+    if waldur_url.trim().is_empty() {
+        return Err(anyhow::anyhow!(
+            "No Waldur URL specified. Please set this in the waldur-url option."
+        ));
+    }
+
+    let waldur_token = match config.secret("waldur-token") {
+        Some(token) => token,
+        None => {
+            return Err(anyhow::anyhow!(
+                "No Waldur token specified. Please set this in the waldur-token option."
+            ));
+        }
+    };
+
+    waldur::initialise(&waldur_url, waldur_token)?;
+
+    // the Waldur offerings that are provided through OpenPortal
+    let offerings = sync::parse_offerings(&config.option("offerings", ""))?;
+
+    if offerings.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No offerings specified. Please set this in the offerings option."
+        ));
+    }
+
+    let settings = sync::Settings {
+        portal: PortalIdentifier::parse(&config.option("portal", "waldur"))?,
+        offerings,
+        component: config.option("component", "node_hours"),
+    };
+
+    // get the interval (in seconds) between processing orders
+    let poll_interval: u64 = config
+        .option("poll-interval", "60")
+        .parse()
+        .unwrap_or(60)
+        .max(10);
+
+    // get the interval (in seconds) between synchronising the teams and
+    // usage of the resources, or 0 to never synchronise
+    let resource_interval: u64 = config
+        .option("resource-interval", "3600")
+        .parse()
+        .unwrap_or(3600);
+
+    sync::spawn_sync(poll_interval, resource_interval, settings);
+
+    async_runnable! {
+        ///
+        /// Runnable function that will be called when a job is received
+        /// by the agent
+        ///
+        pub async fn waldur_runner(envelope: Envelope) -> Result<Job, templemeads::Error>
+        {
+            let job = envelope.job();
+
+            Err(Error::InvalidInstruction(
+                format!("Invalid instruction: {}. Waldur agents do not accept instructions", job.instruction()),
+            ))
+        }
+    }
+
+    set_notify_runner(default_notify_runner).await?;
+    run(config, waldur_runner).await?;
+
+    Ok(())
+}
@@ -0,0 +1,230 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use templemeads::grammar::ProjectIdentifier;
+use templemeads::usagereport::Usage;
+use templemeads::Error;
+
+use crate::sync::{self, Offering, Settings};
+use crate::waldur::{self, Order};
+
+///
+/// Set the project's limit from the order's limit of the component,
+/// if the order has one
+///
+async fn set_limit(
+    settings: &Settings,
+    offering: &Offering,
+    project: &ProjectIdentifier,
+    order: &Order,
+) -> Result<(), Error> {
+    if let Some(hours) = order.limits.get(&settings.component) {
+        let limit = Usage::from_hours(*hours);
+
+        tracing::info!("Setting the limit of {} to {}", project, limit);
+
+        sync::run::<serde_json::Value>(
+            &offering.destination,
+            &format!("set_limit {} {}", project, limit.seconds()),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+///
+/// Carry out the order in OpenPortal
+///
+async fn execute(settings: &Settings, offering: &Offering, order: &Order) -> Result<(), Error> {
+    let project =
+        sync::project_identifier(&settings.portal, &order.project_uuid, &order.project_slug)?;
+
+    match order.order_type.as_str() {
+        "Create" => {
+            tracing::info!("Adding {} to {}", project, offering.destination);
+
+            sync::run::<serde_json::Value>(
+                &offering.destination,
+                &format!("add_project {}", project),
+            )
+            .await?;
+
+            set_limit(settings, offering, &project, order).await?;
+
+            if let Some(resource) = &order.resource_uuid {
+                waldur::set_backend_id(resource, &project.to_string()).await?;
+            }
+        }
+        "Update" => {
+            set_limit(settings, offering, &project, order).await?;
+        }
+        "Terminate" => {
+            tracing::info!("Removing {} from {}", project, offering.destination);
+
+            sync::run::<serde_json::Value>(
+                &offering.destination,
+                &format!("remove_project {}", project),
+            )
+            .await?;
+        }
+        _ => {
+            return Err(Error::InvalidInstruction(format!(
+                "Unsupported order type '{}'",
+                order.order_type
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Carry out the order, recording its success or failure in Waldur
+///
+async fn complete(settings: &Settings, offering: &Offering, order: &Order) -> Result<(), Error> {
+    match execute(settings, offering, order).await {
+        Ok(()) => {
+            tracing::info!("{} order {} is done", order.order_type, order.uuid);
+            waldur::set_order_done(order).await
+        }
+        Err(e) => {
+            tracing::error!("{} order {} failed: {}", order.order_type, order.uuid, e);
+            waldur::set_order_erred(order, &e.to_string()).await
+        }
+    }
+}
+
+///
+/// Approve and carry out the offering's pending orders, and carry out
+/// any orders that were approved but not finished (e.g. because this
+/// agent was restarted). A failure with one order is logged, and does
+/// not stop the remaining orders from being processed.
+///
+pub async fn process(settings: &Settings, offering: &Offering) -> Result<(), Error> {
+    for order in waldur::get_orders(&offering.uuid, "executing").await? {
+        if let Err(e) = complete(settings, offering, &order).await {
+            tracing::error!(
+                "Could not record the result of {} order {}: {}",
+                order.order_type,
+                order.uuid,
+                e
+            );
+        }
+    }
+
+    for order in waldur::get_orders(&offering.uuid, "pending-provider").await? {
+        tracing::info!("Approving {} order {}", order.order_type, order.uuid);
+
+        if let Err(e) = waldur::approve_order(&order).await {
+            tracing::error!(
+                "Could not approve {} order {}: {}",
+                order.order_type,
+                order.uuid,
+                e
+            );
+            continue;
+        }
+
+        if let Err(e) = complete(settings, offering, &order).await {
+            tracing::error!(
+                "Could not record the result of {} order {}: {}",
+                order.order_type,
+                order.uuid,
+                e
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::waldur::tests::{fake_waldur, order, orders_page, requests, respond};
+    use templemeads::destination::Destination;
+    use templemeads::grammar::PortalIdentifier;
+
+    fn settings(offering: &Offering) -> Settings {
+        Settings {
+            portal: PortalIdentifier::parse("waldur")
+                .unwrap_or_else(|e| unreachable!("Cannot parse portal: {}", e)),
+            offerings: vec![offering.clone()],
+            component: "cpu".to_owned(),
+        }
+    }
+
+    fn offering(uuid: &str) -> Offering {
+        Offering {
+            uuid: uuid.to_owned(),
+            destination: Destination::parse("waldur.provider.cluster")
+                .unwrap_or_else(|e| unreachable!("Cannot parse destination: {}", e)),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process() {
+        fake_waldur();
+
+        let offering = offering("proc");
+        let settings = settings(&offering);
+
+        // orders that cannot be carried out are marked as erred, and a
+        // failure to record that does not stop the remaining orders
+        respond(
+            &orders_page("proc", "executing", 1),
+            200,
+            &format!(
+                "[{}, {}]",
+                order("e1", "Restore", "p1"),
+                order("e2", "Create", "")
+            ),
+        );
+
+        respond(
+            "POST /api/marketplace-orders/e1/set_state_erred/",
+            500,
+            "down",
+        );
+
+        respond(
+            &orders_page("proc", "pending-provider", 1),
+            200,
+            &format!(
+                "[{}, {}]",
+                order("n1", "Restore", "p1"),
+                order("n2", "Restore", "p2")
+            ),
+        );
+
+        respond(
+            "POST /api/marketplace-orders/n1/approve_by_provider/",
+            500,
+            "down",
+        );
+
+        assert!(process(&settings, &offering).await.is_ok());
+
+        assert_eq!(requests("/e1/set_state_erred/").len(), 1);
+        assert!(requests("/e1/set_state_erred/")[0].contains("Unsupported order type 'Restore'"));
+        assert_eq!(requests("/e2/set_state_erred/").len(), 1);
+
+        // an order that could not be approved is left for the next cycle
+        assert_eq!(requests("/n1/approve_by_provider/").len(), 1);
+        assert!(requests("/n1/set_state").is_empty());
+
+        assert_eq!(requests("/n2/approve_by_provider/").len(), 1);
+        assert_eq!(requests("/n2/set_state_erred/").len(), 1);
+        assert!(requests("/n2/set_state_done/").is_empty());
+
+        // failing to list the orders fails the cycle
+        let down = self::offering("down");
+        respond(&orders_page("down", "executing", 1), 503, "down");
+
+        assert!(matches!(
+            process(&settings, &down).await,
+            Err(Error::Call(_))
+        ));
+    }
+}
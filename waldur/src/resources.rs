@@ -0,0 +1,202 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use chrono::Datelike;
+use std::collections::HashSet;
+use templemeads::grammar::{Date, DateRange, ProjectIdentifier, UserIdentifier, UserMapping};
+use templemeads::usagereport::ProjectUsageReport;
+use templemeads::Error;
+
+use crate::sync::{self, Offering, Settings};
+use crate::waldur::{self, Resource};
+
+/// The number of days into a month during which the usage of the
+/// previous month is still submitted, so that its final days are included
+const PREVIOUS_MONTH_DAYS: u32 = 3;
+
+///
+/// Add and remove the project's users so that they match the members
+/// of the resource's team
+///
+async fn sync_team(
+    offering: &Offering,
+    project: &ProjectIdentifier,
+    resource: &Resource,
+) -> Result<(), Error> {
+    let mut members = HashSet::new();
+
+    for member in waldur::get_team(&resource.uuid).await? {
+        match UserIdentifier::parse(&format!("{}.{}", member.username, project)) {
+            Ok(user) => {
+                members.insert(user);
+            }
+            Err(e) => tracing::warn!(
+                "Ignoring team member '{}' of {}: {}",
+                member.username,
+                project,
+                e
+            ),
+        }
+    }
+
+    let users =
+        sync::run::<Vec<UserMapping>>(&offering.destination, &format!("get_users {}", project))
+            .await?
+            .unwrap_or_default()
+            .iter()
+            .map(|mapping| mapping.user().clone())
+            .collect::<HashSet<_>>();
+
+    for user in members.difference(&users) {
+        tracing::info!("Adding {} to {}", user, offering.destination);
+
+        if let Err(e) =
+            sync::run::<serde_json::Value>(&offering.destination, &format!("add_user {}", user))
+                .await
+        {
+            tracing::error!("Could not add {}: {}", user, e);
+        }
+    }
+
+    for user in users.difference(&members) {
+        tracing::info!("Removing {} from {}", user, offering.destination);
+
+        if let Err(e) =
+            sync::run::<serde_json::Value>(&offering.destination, &format!("remove_user {}", user))
+                .await
+        {
+            tracing::error!("Could not remove {}: {}", user, e);
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Submit the project's usage over the month to the resource's component
+///
+async fn submit_usage(
+    settings: &Settings,
+    offering: &Offering,
+    project: &ProjectIdentifier,
+    resource: &Resource,
+    month: &DateRange,
+) -> Result<(), Error> {
+    let report = sync::run::<ProjectUsageReport>(
+        &offering.destination,
+        &format!("get_usage_report {} {}", project, month),
+    )
+    .await?
+    .unwrap_or_else(|| ProjectUsageReport::new(project));
+
+    let hours = report.total_usage().hours();
+
+    tracing::info!(
+        "Submitting {:.3} hours of {} for {} over {}",
+        hours,
+        settings.component,
+        project,
+        month
+    );
+
+    waldur::set_usage(
+        &resource.uuid,
+        &settings.component,
+        hours,
+        month.start_date().date(),
+    )
+    .await
+}
+
+///
+/// Synchronise the team of each of the offering's resources, and
+/// submit their usage for the current (and, early in the month, the
+/// previous) month
+///
+pub async fn sync(settings: &Settings, offering: &Offering) -> Result<(), Error> {
+    let today = Date::today();
+
+    let mut months = vec![today.month()];
+
+    if today.date().day() <= PREVIOUS_MONTH_DAYS {
+        months.push(today.prev_month());
+    }
+
+    for resource in waldur::get_resources(&offering.uuid).await? {
+        let project = match sync::project_identifier(
+            &settings.portal,
+            &resource.project_uuid,
+            &resource.project_slug,
+        ) {
+            Ok(project) => project,
+            Err(e) => {
+                tracing::error!("Ignoring resource {}: {}", resource.uuid, e);
+                continue;
+            }
+        };
+
+        if let Err(e) = sync_team(offering, &project, &resource).await {
+            tracing::error!("Could not synchronise the team of {}: {}", project, e);
+        }
+
+        for month in &months {
+            if let Err(e) = submit_usage(settings, offering, &project, &resource, month).await {
+                tracing::error!(
+                    "Could not submit the usage of {} over {}: {}",
+                    project,
+                    month,
+                    e
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::waldur::tests::{fake_waldur, requests, resources_page, respond};
+    use templemeads::destination::Destination;
+    use templemeads::grammar::PortalIdentifier;
+
+    #[tokio::test]
+    async fn test_sync() {
+        fake_waldur();
+
+        let offering = Offering {
+            uuid: "quiet".to_owned(),
+            destination: Destination::parse("waldur.provider.cluster")
+                .unwrap_or_else(|e| unreachable!("Cannot parse destination: {}", e)),
+        };
+
+        let settings = Settings {
+            portal: PortalIdentifier::parse("waldur")
+                .unwrap_or_else(|e| unreachable!("Cannot parse portal: {}", e)),
+            offerings: vec![offering.clone()],
+            component: "cpu".to_owned(),
+        };
+
+        // an offering without resources has nothing to synchronise
+        assert!(sync(&settings, &offering).await.is_ok());
+        assert_eq!(requests(&resources_page("quiet")).len(), 1);
+
+        // resources without a valid project are skipped
+        respond(
+            &resources_page("quiet"),
+            200,
+            r#"[{"uuid": "r1", "project_uuid": "", "project_slug": null}]"#,
+        );
+
+        assert!(sync(&settings, &offering).await.is_ok());
+        assert!(requests("/r1/").is_empty());
+
+        respond(&resources_page("quiet"), 503, "down");
+
+        assert!(matches!(
+            sync(&settings, &offering).await,
+            Err(Error::Call(_))
+        ));
+    }
+}
@@ -0,0 +1,230 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use serde::de::DeserializeOwned;
+use templemeads::bridge;
+use templemeads::destination::Destination;
+use templemeads::grammar::{PortalIdentifier, ProjectIdentifier};
+use templemeads::Error;
+
+use crate::orders;
+use crate::resources;
+
+///
+/// A Waldur offering, and the OpenPortal destination (starting with the
+/// portal) of the cluster that provides it
+///
+#[derive(Clone, Debug)]
+pub struct Offering {
+    pub uuid: String,
+    pub destination: Destination,
+}
+
+///
+/// The settings used when synchronising Waldur with OpenPortal
+///
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// The portal identifier given to all Waldur projects
+    pub portal: PortalIdentifier,
+
+    /// The offerings that are provided through OpenPortal
+    pub offerings: Vec<Offering>,
+
+    /// The Waldur component that holds the limit and usage, in hours
+    pub component: String,
+}
+
+///
+/// Parse the comma-separated list of `uuid=destination` offering mappings
+///
+pub fn parse_offerings(offerings: &str) -> Result<Vec<Offering>, Error> {
+    offerings
+        .split(',')
+        .map(|o| o.trim())
+        .filter(|o| !o.is_empty())
+        .map(|o| match o.split_once('=') {
+            Some((uuid, destination)) => Ok(Offering {
+                uuid: uuid.trim().replace('-', ""),
+                destination: Destination::parse(destination.trim())?,
+            }),
+            None => Err(Error::Misconfigured(format!(
+                "Invalid offering mapping '{}'. It should be uuid=destination",
+                o
+            ))),
+        })
+        .collect()
+}
+
+/// The number of characters of the Waldur project's UUID that are added
+/// to its slug, so that projects whose slugs clash are kept apart
+const UUID_PREFIX_LENGTH: usize = 8;
+
+///
+/// Return the OpenPortal project that represents the Waldur project.
+/// This is the project's slug followed by the start of its UUID if it
+/// has a slug, otherwise its UUID.
+///
+pub fn project_identifier(
+    portal: &PortalIdentifier,
+    project_uuid: &str,
+    project_slug: &Option<String>,
+) -> Result<ProjectIdentifier, Error> {
+    let uuid = project_uuid.trim().replace('-', "");
+
+    if uuid.is_empty() {
+        return Err(Error::Parse("Waldur project has no UUID".to_owned()));
+    }
+
+    // slugs are only unique per customer, so they are qualified by the
+    // start of the (unique) UUID
+    let name = match project_slug {
+        Some(slug) if !slug.trim().is_empty() => format!(
+            "{}-{}",
+            slug.trim(),
+            uuid.chars().take(UUID_PREFIX_LENGTH).collect::<String>()
+        ),
+        _ => uuid,
+    };
+
+    // project identifiers cannot contain dots or spaces
+    let name = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect::<String>();
+
+    ProjectIdentifier::parse(&format!("{}.{}", name, portal))
+}
+
+///
+/// Submit the instruction to the agent at `destination` via the portal,
+/// waiting for, and returning, its result
+///
+pub async fn run<T>(destination: &Destination, instruction: &str) -> Result<Option<T>, Error>
+where
+    T: DeserializeOwned,
+{
+    let job = bridge::run(&format!("{} {}", destination, instruction)).await?;
+
+    job.wait().await?.result::<T>()
+}
+
+///
+/// Spawn a background task that processes Waldur's orders every
+/// `interval` seconds, and that synchronises the resources' teams and
+/// usage every `resource_interval` seconds (or never, if this is 0)
+///
+pub fn spawn_sync(interval: u64, resource_interval: u64, settings: Settings) {
+    tokio::spawn(async move {
+        let mut last_resource_sync: Option<std::time::Instant> = None;
+
+        loop {
+            for offering in &settings.offerings {
+                if let Err(e) = orders::process(&settings, offering).await {
+                    tracing::error!(
+                        "Failed to process the orders of offering {}: {}",
+                        offering.uuid,
+                        e
+                    );
+                }
+            }
+
+            let resources_due = resource_interval > 0
+                && last_resource_sync
+                    .map(|t| t.elapsed().as_secs() >= resource_interval)
+                    .unwrap_or(true);
+
+            if resources_due {
+                last_resource_sync = Some(std::time::Instant::now());
+
+                for offering in &settings.offerings {
+                    if let Err(e) = resources::sync(&settings, offering).await {
+                        tracing::error!(
+                            "Failed to synchronise the resources of offering {}: {}",
+                            offering.uuid,
+                            e
+                        );
+                    }
+                }
+            }
+
+            tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn portal() -> PortalIdentifier {
+        PortalIdentifier::parse("waldur")
+            .unwrap_or_else(|e| unreachable!("Cannot parse portal: {}", e))
+    }
+
+    #[test]
+    fn test_parse_offerings() {
+        let offerings =
+            parse_offerings(" 1234-abcd = waldur.provider.cluster , ,5678=waldur.provider.other")
+                .unwrap_or_else(|e| unreachable!("Cannot parse offerings: {}", e));
+
+        assert_eq!(offerings.len(), 2);
+        assert_eq!(offerings[0].uuid, "1234abcd");
+        assert_eq!(
+            offerings[0].destination.to_string(),
+            "waldur.provider.cluster"
+        );
+        assert_eq!(offerings[1].uuid, "5678");
+
+        assert!(parse_offerings("").is_ok_and(|o| o.is_empty()));
+
+        assert!(matches!(
+            parse_offerings("1234"),
+            Err(Error::Misconfigured(_))
+        ));
+    }
+
+    #[test]
+    fn test_project_identifier() {
+        let uuid = "1a2b3c4d-5e6f-7a8b-9c0d-1e2f3a4b5c6d";
+
+        assert!(
+            project_identifier(&portal(), uuid, &Some(" climate ".to_owned()))
+                .is_ok_and(|p| p.to_string() == "climate-1a2b3c4d.waldur")
+        );
+
+        // projects without a slug use their full UUID
+        assert!(project_identifier(&portal(), uuid, &None)
+            .is_ok_and(|p| p.to_string() == "1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d.waldur"));
+
+        assert!(project_identifier(&portal(), uuid, &Some("  ".to_owned()))
+            .is_ok_and(|p| p.to_string() == "1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d.waldur"));
+
+        // characters that are not allowed in identifiers are replaced
+        assert!(
+            project_identifier(&portal(), uuid, &Some("my.big project".to_owned()))
+                .is_ok_and(|p| p.to_string() == "my-big-project-1a2b3c4d.waldur")
+        );
+
+        // projects with the same slug in different customers are kept apart
+        let other = project_identifier(
+            &portal(),
+            "99999999-5e6f-7a8b-9c0d-1e2f3a4b5c6d",
+            &Some("climate".to_owned()),
+        )
+        .unwrap_or_else(|e| unreachable!("Cannot create identifier: {}", e));
+
+        assert_eq!(other.to_string(), "climate-99999999.waldur");
+
+        assert!(matches!(
+            project_identifier(&portal(), " - ", &Some("climate".to_owned())),
+            Err(Error::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,603 @@
+// SPDX-FileCopyrightText: © 2026 Christopher Woods <Christopher.Woods@bristol.ac.uk>
+// SPDX-License-Identifier: MIT
+
+use once_cell::sync::OnceCell;
+use secrecy::{ExposeSecret, SecretString};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use templemeads::Error;
+
+/// The number of items requested per page of a Waldur list
+const PAGE_SIZE: usize = 100;
+
+///
+/// A client for the Waldur REST API, authenticated with an API token
+///
+struct Waldur {
+    url: String,
+    token: SecretString,
+    client: reqwest::Client,
+}
+
+static WALDUR: OnceCell<Waldur> = OnceCell::new();
+
+///
+/// A marketplace order, as returned by /api/marketplace-orders/
+///
+#[derive(Deserialize, Clone, Debug)]
+pub struct Order {
+    pub uuid: String,
+    #[serde(rename = "type")]
+    pub order_type: String,
+    pub resource_uuid: Option<String>,
+    pub project_uuid: String,
+    #[serde(default)]
+    pub project_slug: Option<String>,
+    #[serde(default)]
+    pub limits: HashMap<String, f64>,
+}
+
+///
+/// A marketplace resource, as returned by
+/// /api/marketplace-provider-resources/
+///
+#[derive(Deserialize, Clone, Debug)]
+pub struct Resource {
+    pub uuid: String,
+    pub project_uuid: String,
+    #[serde(default)]
+    pub project_slug: Option<String>,
+}
+
+///
+/// A member of a resource's team
+///
+#[derive(Deserialize, Clone, Debug)]
+pub struct TeamMember {
+    pub username: String,
+}
+
+///
+/// Connect to the Waldur API at the passed URL (e.g.
+/// https://waldur.example.org/api), authenticating with the token
+///
+pub fn initialise(url: &str, token: SecretString) -> Result<(), Error> {
+    let url = url.trim().trim_end_matches('/');
+
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err(Error::Misconfigured(format!(
+            "Invalid Waldur URL '{}'. It must start with http:// or https://",
+            url
+        )));
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(60))
+        .build()
+        .map_err(|e| Error::Misconfigured(format!("Could not create HTTP client: {}", e)))?;
+
+    tracing::info!("Using the Waldur API at {}", url);
+
+    WALDUR
+        .set(Waldur {
+            url: url.to_owned(),
+            token,
+            client,
+        })
+        .map_err(|_| Error::InvalidState("Waldur client already initialised".to_owned()))
+}
+
+fn waldur() -> Result<&'static Waldur, Error> {
+    WALDUR
+        .get()
+        .ok_or_else(|| Error::InvalidState("Waldur client not initialised".to_owned()))
+}
+
+///
+/// Send the request, returning the response if Waldur reported success
+///
+async fn send(request: reqwest::RequestBuilder, path: &str) -> Result<reqwest::Response, Error> {
+    let waldur = waldur()?;
+
+    let response = request
+        .header(
+            reqwest::header::AUTHORIZATION,
+            format!("Token {}", waldur.token.expose_secret()),
+        )
+        .send()
+        .await
+        .map_err(|e| Error::Call(format!("Could not call Waldur {}: {}", path, e)))?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+
+        return Err(Error::Call(format!(
+            "Waldur {} returned {}: {}",
+            path, status, body
+        )));
+    }
+
+    Ok(response)
+}
+
+///
+/// Return all of the items of the Waldur list at `path`, following the
+/// pages until the list is exhausted
+///
+async fn get_all<T: DeserializeOwned>(path: &str, query: &[(&str, &str)]) -> Result<Vec<T>, Error> {
+    let waldur = waldur()?;
+    let url = format!("{}/{}/", waldur.url, path.trim_matches('/'));
+
+    let mut items = Vec::new();
+    let mut page = 1;
+
+    loop {
+        let page_string = page.to_string();
+        let page_size = PAGE_SIZE.to_string();
+
+        let request = waldur.client.get(&url).query(query).query(&[
+            ("page", page_string.as_str()),
+            ("page_size", page_size.as_str()),
+        ]);
+
+        let page_items: Vec<T> = send(request, path)
+            .await?
+            .json()
+            .await
+            .map_err(|e| Error::Parse(format!("Could not parse Waldur {}: {}", path, e)))?;
+
+        let count = page_items.len();
+        items.extend(page_items);
+
+        if count < PAGE_SIZE {
+            return Ok(items);
+        }
+
+        page += 1;
+    }
+}
+
+///
+/// POST the JSON body to `path`, ignoring the response
+///
+async fn post(path: &str, body: serde_json::Value) -> Result<(), Error> {
+    let waldur = waldur()?;
+    let url = format!("{}/{}/", waldur.url, path.trim_matches('/'));
+
+    send(waldur.client.post(&url).json(&body), path).await?;
+
+    Ok(())
+}
+
+///
+/// Return the orders for the offering that are in the passed state
+///
+pub async fn get_orders(offering: &str, state: &str) -> Result<Vec<Order>, Error> {
+    get_all(
+        "marketplace-orders",
+        &[("offering_uuid", offering), ("state", state)],
+    )
+    .await
+}
+
+///
+/// Approve the order, moving it into the executing state
+///
+pub async fn approve_order(order: &Order) -> Result<(), Error> {
+    post(
+        &format!("marketplace-orders/{}/approve_by_provider", order.uuid),
+        serde_json::json!({}),
+    )
+    .await
+}
+
+///
+/// Mark the order as successfully completed
+///
+pub async fn set_order_done(order: &Order) -> Result<(), Error> {
+    post(
+        &format!("marketplace-orders/{}/set_state_done", order.uuid),
+        serde_json::json!({}),
+    )
+    .await
+}
+
+///
+/// Mark the order as failed, recording the error message in Waldur
+///
+pub async fn set_order_erred(order: &Order, message: &str) -> Result<(), Error> {
+    post(
+        &format!("marketplace-orders/{}/set_state_erred", order.uuid),
+        serde_json::json!({ "error_message": message }),
+    )
+    .await
+}
+
+///
+/// Return the resources of the offering that are in the OK state
+///
+pub async fn get_resources(offering: &str) -> Result<Vec<Resource>, Error> {
+    get_all(
+        "marketplace-provider-resources",
+        &[("offering_uuid", offering), ("state", "OK")],
+    )
+    .await
+}
+
+///
+/// Record the OpenPortal project of the resource as its backend ID
+///
+pub async fn set_backend_id(resource: &str, backend_id: &str) -> Result<(), Error> {
+    post(
+        &format!("marketplace-provider-resources/{}/set_backend_id", resource),
+        serde_json::json!({ "backend_id": backend_id }),
+    )
+    .await
+}
+
+///
+/// Return the members of the resource's team
+///
+pub async fn get_team(resource: &str) -> Result<Vec<TeamMember>, Error> {
+    get_all(
+        &format!("marketplace-provider-resources/{}/team", resource),
+        &[],
+    )
+    .await
+}
+
+///
+/// Set the usage of the resource's component for the month containing
+/// `date`
+///
+pub async fn set_usage(
+    resource: &str,
+    component: &str,
+    amount: f64,
+    date: &chrono::NaiveDate,
+) -> Result<(), Error> {
+    post(
+        "marketplace-component-usages/set_usage",
+        serde_json::json!({
+            "resource": resource,
+            "date": date.format("%Y-%m-%d").to_string(),
+            "usages": [{
+                "type": component,
+                "amount": format!("{:.3}", amount),
+                "description": "Reported by OpenPortal",
+            }],
+        }),
+    )
+    .await
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::sync::{Mutex, Once};
+
+    static FAKE_WALDUR: Once = Once::new();
+
+    /// The status and body of a response of the fake Waldur server
+    type Response = (u16, String);
+
+    /// The responses of the fake Waldur server, keyed by "<method> <path>",
+    /// optionally followed by "?<query>"
+    static RESPONSES: Mutex<Option<HashMap<String, Response>>> = Mutex::new(None);
+
+    /// Every request received by the fake Waldur server, as
+    /// "<method> <path>[?<query>] <body>"
+    static REQUESTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    ///
+    /// Read a single request from the stream and answer it
+    ///
+    fn handle(stream: std::net::TcpStream) -> std::io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+
+        let mut length = 0;
+        let mut authorised = false;
+
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header)?;
+
+            let header = header.trim();
+
+            if header.is_empty() {
+                break;
+            }
+
+            let lower = header.to_lowercase();
+
+            if let Some(value) = lower.strip_prefix("content-length:") {
+                length = value.trim().parse().unwrap_or(0);
+            }
+
+            if lower == "authorization: token secret" {
+                authorised = true;
+            }
+        }
+
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body)?;
+
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default();
+        let target = parts.next().unwrap_or_default();
+        let path = target.split_once('?').map(|(p, _)| p).unwrap_or(target);
+
+        if let Ok(mut requests) = REQUESTS.lock() {
+            requests.push(format!(
+                "{} {} {}",
+                method,
+                target,
+                String::from_utf8_lossy(&body)
+            ));
+        }
+
+        let (status, response) = match authorised {
+            false => (401, String::new()),
+            true => RESPONSES
+                .lock()
+                .ok()
+                .and_then(|responses| {
+                    let responses = responses.as_ref()?;
+
+                    responses
+                        .get(&format!("{} {}", method, target))
+                        .or_else(|| responses.get(&format!("{} {}", method, path)))
+                        .cloned()
+                })
+                .unwrap_or(match method {
+                    "GET" => (200, "[]".to_owned()),
+                    _ => (200, String::new()),
+                }),
+        };
+
+        let mut stream = stream;
+
+        write!(
+            stream,
+            "HTTP/1.1 {} Status\r\ncontent-type: application/json\r\n\
+             content-length: {}\r\nconnection: close\r\n\r\n{}",
+            status,
+            response.len(),
+            response
+        )?;
+
+        stream.flush()
+    }
+
+    ///
+    /// Start a fake Waldur server, used by the tests of all modules, and
+    /// initialise the client to use it. The server runs on its own thread
+    /// so that it outlives the runtime of each test.
+    ///
+    pub(crate) fn fake_waldur() {
+        FAKE_WALDUR.call_once(|| {
+            let listener = match std::net::TcpListener::bind("127.0.0.1:0") {
+                Ok(listener) => listener,
+                Err(e) => unreachable!("Cannot bind: {}", e),
+            };
+
+            let url = match listener.local_addr() {
+                Ok(address) => format!("http://{}/api/", address),
+                Err(e) => unreachable!("No address: {}", e),
+            };
+
+            std::thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let _ = handle(stream);
+                }
+            });
+
+            initialise(&url, SecretString::from("secret"))
+                .unwrap_or_else(|e| unreachable!("Cannot initialise: {}", e));
+        });
+    }
+
+    ///
+    /// Make the fake server answer requests matching `request` (e.g.
+    /// "GET /api/marketplace-orders/") with the passed status and body
+    ///
+    pub(crate) fn respond(request: &str, status: u16, body: &str) {
+        if let Ok(mut responses) = RESPONSES.lock() {
+            responses
+                .get_or_insert_with(HashMap::new)
+                .insert(request.to_owned(), (status, body.to_owned()));
+        }
+    }
+
+    ///
+    /// Return the requests received by the fake server that contain `text`
+    ///
+    pub(crate) fn requests(text: &str) -> Vec<String> {
+        REQUESTS
+            .lock()
+            .map(|requests| {
+                requests
+                    .iter()
+                    .filter(|r| r.contains(text))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    ///
+    /// Return the JSON of an order of the passed type
+    ///
+    pub(crate) fn order(uuid: &str, order_type: &str, project_uuid: &str) -> String {
+        format!(
+            r#"{{"uuid": "{}", "type": "{}", "resource_uuid": null,
+                "project_uuid": "{}", "project_slug": null, "limits": {{}}}}"#,
+            uuid, order_type, project_uuid
+        )
+    }
+
+    ///
+    /// Return the request target of the page of the offering's orders
+    /// in the passed state
+    ///
+    pub(crate) fn orders_page(offering: &str, state: &str, page: usize) -> String {
+        format!(
+            "GET /api/marketplace-orders/?offering_uuid={}&state={}&page={}&page_size={}",
+            offering, state, page, PAGE_SIZE
+        )
+    }
+
+    ///
+    /// Return the request target of the first page of the offering's
+    /// resources
+    ///
+    pub(crate) fn resources_page(offering: &str) -> String {
+        format!(
+            "GET /api/marketplace-provider-resources/?offering_uuid={}&state=OK&page=1&page_size={}",
+            offering, PAGE_SIZE
+        )
+    }
+
+    #[test]
+    fn test_initialise() {
+        for invalid in ["", "waldur.example.org/api", "ftp://waldur.example.org"] {
+            assert!(matches!(
+                initialise(invalid, SecretString::from("secret")),
+                Err(Error::Misconfigured(_))
+            ));
+        }
+
+        fake_waldur();
+
+        assert!(matches!(
+            initialise(
+                "https://waldur.example.org/api",
+                SecretString::from("secret")
+            ),
+            Err(Error::InvalidState(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_orders() {
+        fake_waldur();
+
+        // a full page is followed by the next page
+        let page = (0..PAGE_SIZE)
+            .map(|i| order(&format!("o{}", i), "Create", "p1"))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        respond(
+            &orders_page("paged", "executing", 1),
+            200,
+            &format!("[{}]", page),
+        );
+        respond(
+            &orders_page("paged", "executing", 2),
+            200,
+            &format!("[{}]", order("last", "Terminate", "p2")),
+        );
+
+        let orders = get_orders("paged", "executing")
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot get orders: {}", e));
+
+        assert_eq!(orders.len(), PAGE_SIZE + 1);
+        assert_eq!(orders[0].uuid, "o0");
+        assert_eq!(orders[PAGE_SIZE].uuid, "last");
+        assert_eq!(orders[PAGE_SIZE].order_type, "Terminate");
+        assert!(requests(&orders_page("paged", "executing", 3)).is_empty());
+
+        assert!(get_orders("empty", "executing")
+            .await
+            .is_ok_and(|orders| orders.is_empty()));
+
+        respond(&orders_page("failing", "executing", 1), 500, "oops");
+
+        assert!(matches!(
+            get_orders("failing", "executing").await,
+            Err(Error::Call(_))
+        ));
+
+        respond(&orders_page("garbled", "executing", 1), 200, "{not json");
+
+        assert!(matches!(
+            get_orders("garbled", "executing").await,
+            Err(Error::Parse(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_post() {
+        fake_waldur();
+
+        let order: Order = serde_json::from_str(&order("abc", "Create", "p1"))
+            .unwrap_or_else(|e| unreachable!("Cannot parse order: {}", e));
+
+        approve_order(&order)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot approve: {}", e));
+
+        set_order_done(&order)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot set done: {}", e));
+
+        set_order_erred(&order, "it broke")
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot set erred: {}", e));
+
+        set_backend_id("res1", "proj.waldur")
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot set backend ID: {}", e));
+
+        let date = chrono::NaiveDate::from_ymd_opt(2026, 3, 1)
+            .unwrap_or_else(|| unreachable!("Invalid date"));
+
+        set_usage("res1", "cpu", 12.3456, &date)
+            .await
+            .unwrap_or_else(|e| unreachable!("Cannot set usage: {}", e));
+
+        assert_eq!(
+            requests("POST /api/marketplace-orders/abc/approve_by_provider/ {}").len(),
+            1
+        );
+        assert_eq!(
+            requests("POST /api/marketplace-orders/abc/set_state_done/ {}").len(),
+            1
+        );
+        assert_eq!(
+            requests(r#"/abc/set_state_erred/ {"error_message":"it broke"}"#).len(),
+            1
+        );
+        assert_eq!(
+            requests(r#"/res1/set_backend_id/ {"backend_id":"proj.waldur"}"#).len(),
+            1
+        );
+
+        let usage = requests("POST /api/marketplace-component-usages/set_usage/");
+        assert_eq!(usage.len(), 1);
+        assert!(usage[0].contains(r#""date":"2026-03-01""#));
+        assert!(usage[0].contains(r#""amount":"12.346""#));
+        assert!(usage[0].contains(r#""type":"cpu""#));
+        assert!(usage[0].contains(r#""resource":"res1""#));
+
+        respond(
+            "POST /api/marketplace-provider-resources/res2/set_backend_id/",
+            400,
+            "no",
+        );
+
+        assert!(matches!(
+            set_backend_id("res2", "proj.waldur").await,
+            Err(Error::Call(_))
+        ));
+    }
+}